@@ -0,0 +1,155 @@
+// Shell completion and man page support
+// Completion scripts call back into `moses` with COMPLETE=<shell> set, so device
+// arguments complete to the drives actually attached. Enumerating devices can take a
+// second or more (lsblk, WMI), so the last listing is cached briefly and reused while
+// the user is still pressing Tab.
+use clap::ValueEnum;
+use clap_complete::engine::{CompletionCandidate, PathCompleter, ValueCompleter};
+use clap_complete::env::Shells;
+use moses_core::{Device, DeviceManager};
+use moses_platform::PlatformDeviceManager;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Environment variable the completion scripts set when asking for candidates
+pub const COMPLETE_VAR: &str = "COMPLETE";
+/// How long a cached device listing is trusted
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+    Elvish,
+}
+
+impl Shell {
+    fn name(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Powershell => "powershell",
+            Shell::Elvish => "elvish",
+        }
+    }
+}
+
+/// Write the registration script for `shell`
+pub fn write_registration(shell: Shell, buf: &mut dyn std::io::Write) -> anyhow::Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell.name())
+        .ok_or_else(|| anyhow::anyhow!("Completions are not available for {}", shell.name()))?;
+    completer.write_registration(COMPLETE_VAR, "moses", "moses", "moses", buf)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDevice {
+    id: String,
+    name: String,
+    size: u64,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("moses").join("devices.json"))
+}
+
+/// Remember a device listing for the completer; failures are ignored
+pub fn cache_devices(devices: &[Device]) {
+    let Some(path) = cache_path() else {
+        return;
+    };
+    let cached: Vec<CachedDevice> = devices
+        .iter()
+        .map(|device| CachedDevice {
+            id: device.id.clone(),
+            name: device.name.clone(),
+            size: device.size,
+        })
+        .collect();
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Ok(text) = serde_json::to_string(&cached) {
+        let _ = std::fs::write(path, text);
+    }
+}
+
+fn read_cache() -> Option<Vec<CachedDevice>> {
+    let path = cache_path()?;
+    let age = std::fs::metadata(&path).ok()?.modified().ok()?;
+    if SystemTime::now().duration_since(age).unwrap_or(Duration::MAX) > CACHE_TTL {
+        return None;
+    }
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+fn enumerate() -> Vec<CachedDevice> {
+    if let Some(cached) = read_cache() {
+        return cached;
+    }
+
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread().enable_all().build() else {
+        return Vec::new();
+    };
+    let devices = runtime.block_on(PlatformDeviceManager.enumerate_devices()).unwrap_or_default();
+    cache_devices(&devices);
+    devices
+        .into_iter()
+        .map(|device| CachedDevice { id: device.id, name: device.name, size: device.size })
+        .collect()
+}
+
+/// Candidates for a device argument: attached drives, then image files
+pub fn complete_device(current: &OsStr) -> Vec<CompletionCandidate> {
+    let prefix = current.to_string_lossy();
+    let mut candidates: Vec<CompletionCandidate> = enumerate()
+        .into_iter()
+        .filter(|device| device.id.starts_with(prefix.as_ref()))
+        .map(|device| {
+            let help = format!("{} ({:.1} GB)", device.name, device.size as f64 / 1_073_741_824.0);
+            CompletionCandidate::new(device.id).help(Some(help.into()))
+        })
+        .collect();
+    candidates.extend(PathCompleter::any().complete(current));
+    candidates
+}
+
+/// Man pages for `moses` and each subcommand, as (file name, contents)
+pub fn man_pages(cmd: clap::Command) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut pages = Vec::new();
+    let mut page = Vec::new();
+    clap_mangen::Man::new(cmd.clone()).render(&mut page)?;
+    pages.push(("moses.1".to_string(), page));
+
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let name = format!("moses-{}", sub.get_name());
+        let mut page = Vec::new();
+        let sub = sub.clone()
+            .display_name(&name)
+            .bin_name(format!("moses {}", sub.get_name()));
+        clap_mangen::Man::new(sub).render(&mut page)?;
+        pages.push((format!("{}.1", name), page));
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_shell_has_a_registration() {
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            write_registration(*shell, &mut script).unwrap();
+            assert!(String::from_utf8(script).unwrap().contains("moses"));
+        }
+    }
+}
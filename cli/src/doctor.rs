@@ -0,0 +1,244 @@
+// `moses doctor`: checks the environment and says how to fix what's missing
+// Most "it doesn't work" reports come down to a missing tool, a missing FUSE/WinFsp
+// install or no permission to open raw devices, so each of those is checked here with
+// a concrete fix printed next to any problem.
+use crate::progress;
+use moses_core::{DeviceManager, FormatterRegistry};
+use moses_filesystems::mount_driver::{self, MountDriver};
+use moses_platform::environment;
+use moses_platform::PlatformDeviceManager;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into(), fix: None }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    fn print(&self) {
+        let label = match self.status {
+            Status::Ok => progress::success("[ ok ]"),
+            Status::Warning => progress::warning("[warn]"),
+            Status::Problem => progress::error("[fail]"),
+        };
+        println!("{} {}: {}", label, self.name, self.detail);
+        if let Some(fix) = &self.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+}
+
+/// Run every check and print the report; returns whether any problem was found
+pub async fn run(registry: &FormatterRegistry) -> bool {
+    let checks = vec![
+        check_elevation(),
+        check_mount_driver(mount_driver::detect()),
+        check_tools(registry),
+        check_worker().await,
+        check_device_access().await,
+    ];
+
+    for check in &checks {
+        check.print();
+    }
+
+    let problems = checks.iter().filter(|check| check.status == Status::Problem).count();
+    let warnings = checks.iter().filter(|check| check.status == Status::Warning).count();
+    println!();
+    match (problems, warnings) {
+        (0, 0) => println!("{}", progress::success("Everything looks good.")),
+        (0, _) => println!("{} warning(s); Moses works but some features are limited.", warnings),
+        _ => println!("{} problem(s) and {} warning(s) found.", problems, warnings),
+    }
+    problems > 0
+}
+
+fn check_elevation() -> Check {
+    let method = environment::elevation_method();
+    if environment::is_elevated() {
+        return Check::new("Privileges", Status::Ok, "running elevated");
+    }
+    match method {
+        Some(method) => Check::new(
+            "Privileges",
+            Status::Ok,
+            format!("not elevated; formatting will ask for elevation via {}", method),
+        ),
+        None => Check::new("Privileges", Status::Problem, "not elevated and no way to elevate was found")
+            .fix("install polkit (pkexec) or sudo, or run moses as root"),
+    }
+}
+
+fn check_mount_driver(driver: MountDriver) -> Check {
+    let name = "Mount driver";
+    if !driver.installed {
+        return Check::new(name, Status::Warning, format!("{} not found; `moses mount` is unavailable", driver.name))
+            .fix(format!("install {} from {}", driver.name, driver.download_url));
+    }
+
+    let version = driver.version.as_deref().unwrap_or("unknown version");
+    if !driver.meets_requirement() {
+        return Check::new(
+            name,
+            Status::Warning,
+            format!("{} {} is too old; mounting needs {} or newer", driver.name, version, driver.required_version),
+        )
+        .fix(format!("update {} from {}", driver.name, driver.download_url));
+    }
+    if MountDriver::compiled_in() {
+        Check::new(name, Status::Ok, format!("{} {}", driver.name, version))
+    } else {
+        Check::new(
+            name,
+            Status::Warning,
+            format!("{} {} installed, but this build has no mount support", driver.name, version),
+        )
+        .fix("use a build with the mount-unix or mount-windows feature")
+    }
+}
+
+fn check_tools(registry: &FormatterRegistry) -> Check {
+    let mut formatters = registry.list_formatters();
+    formatters.sort();
+
+    let missing: Vec<String> = formatters
+        .iter()
+        .flat_map(|name| {
+            environment::missing_tools(name)
+                .into_iter()
+                .map(move |tool| format!("{} needs {}", name, tool))
+        })
+        .collect();
+
+    if missing.is_empty() {
+        Check::new("External tools", Status::Ok, format!("all {} formatters ready", formatters.len()))
+    } else {
+        Check::new("External tools", Status::Warning, missing.join("; "))
+            .fix("install the packages listed with your package manager (or `moses tools --install <package>` if your tool manifest has them)")
+    }
+}
+
+/// The elevated worker ships next to the GUI; look there, then next to this binary
+fn find_worker() -> Option<PathBuf> {
+    let name = if cfg!(windows) { "moses-worker.exe" } else { "moses-worker" };
+    let beside_us = std::env::current_exe().ok()?.parent()?.join(name);
+    if beside_us.exists() {
+        return Some(beside_us);
+    }
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.exists())
+    })
+}
+
+async fn check_worker() -> Check {
+    let name = "Elevated worker";
+    let Some(worker) = find_worker() else {
+        return Check::new(name, Status::Warning, "moses-worker not found; the GUI cannot format drives")
+            .fix("reinstall Moses, or put moses-worker next to the moses binary");
+    };
+
+    // The worker refuses to start without elevation, so only then can we talk to it
+    if !environment::is_elevated() {
+        return Check::new(
+            name,
+            Status::Ok,
+            format!("found at {} (run doctor elevated to test the connection)", worker.display()),
+        );
+    }
+
+    match ping_worker(&worker).await {
+        Ok(()) => Check::new(name, Status::Ok, format!("{} responds", worker.display())),
+        Err(e) => Check::new(name, Status::Problem, format!("{} did not respond: {}", worker.display(), e))
+            .fix("check that a firewall or antivirus is not blocking localhost connections"),
+    }
+}
+
+/// Start the worker in socket mode and exchange a ping, as the GUI does
+async fn ping_worker(worker: &std::path::Path) -> anyhow::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let mut child = tokio::process::Command::new(worker)
+        .arg("--socket")
+        .arg(port.to_string())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let (mut stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .map_err(|_| anyhow::anyhow!("no connection within 5s"))??;
+    stream.write_all(b"{\"command\":\"Ping\"}\n").await?;
+
+    let mut reply = String::new();
+    tokio::time::timeout(Duration::from_secs(2), BufReader::new(&mut stream).read_line(&mut reply))
+        .await
+        .map_err(|_| anyhow::anyhow!("no reply to ping"))??;
+
+    let _ = stream.write_all(b"{\"command\":\"Shutdown\"}\n").await;
+    let _ = child.kill().await;
+
+    let reply: serde_json::Value = serde_json::from_str(&reply)?;
+    if reply["status"] == "Pong" {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("unexpected reply {}", reply))
+    }
+}
+
+async fn check_device_access() -> Check {
+    let name = "Raw device access";
+    let devices = match PlatformDeviceManager.enumerate_devices().await {
+        Ok(devices) => devices,
+        Err(e) => {
+            return Check::new(name, Status::Problem, format!("cannot list devices: {}", e))
+                .fix("run moses doctor elevated to see whether this is a permission problem")
+        }
+    };
+
+    let denied: Vec<String> = devices
+        .iter()
+        .filter(|device| moses_filesystems::utils::open_device_read(device).is_err())
+        .map(|device| device.id.clone())
+        .collect();
+
+    if denied.is_empty() {
+        return Check::new(name, Status::Ok, format!("all {} devices readable", devices.len()));
+    }
+
+    let detail = format!("{} of {} devices not readable ({})", denied.len(), devices.len(), denied.join(", "));
+    // Without elevation this is expected; formatting elevates on its own
+    if environment::is_elevated() {
+        return Check::new(name, Status::Problem, detail)
+            .fix("the devices may be held exclusively by another program or blocked by security software");
+    }
+    let fix = if cfg!(target_os = "linux") {
+        "add yourself to the disk group (sudo usermod -aG disk $USER) or run moses with sudo"
+    } else if cfg!(windows) {
+        "run moses from an Administrator terminal"
+    } else {
+        "run moses with sudo"
+    };
+    Check::new(name, Status::Warning, detail).fix(fix)
+}
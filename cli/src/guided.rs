@@ -0,0 +1,142 @@
+// Guided format - the step-by-step safety walkthrough behind `moses format --interactive`
+// Picking a drive by path is where formatting tools destroy the wrong disk, so the guided
+// mode lists every device with what is known about it, shows the simulation a page at a
+// time, and asks for the device's serial number (its path when it reports none) instead
+// of "yes": typing the serial means reading which drive is about to be erased.
+use moses_core::{AuditLog, Device, DeviceHistory};
+use moses_filesystems::disk_manager::risk;
+use std::io::{self, BufRead, Write};
+
+/// Report lines shown before the pager waits for Enter
+const PAGE_LINES: usize = 20;
+
+/// Prints the simulation report; in the guided mode it stops after every page until the
+/// user presses Enter, and `q` cancels the format
+pub struct Pager {
+    guided: bool,
+    shown: usize,
+    cancelled: bool,
+}
+
+impl Pager {
+    pub fn new(guided: bool) -> Self {
+        Self { guided, shown: 0, cancelled: false }
+    }
+
+    pub fn line(&mut self, text: impl std::fmt::Display) {
+        if self.cancelled {
+            return;
+        }
+        if self.guided && self.shown == PAGE_LINES {
+            if !wait_for_page() {
+                self.cancelled = true;
+                return;
+            }
+            self.shown = 0;
+        }
+        println!("{}", text);
+        self.shown += 1;
+    }
+
+    /// Wait under the last page as well; false if the user cancelled
+    pub fn finish(&mut self) -> bool {
+        if self.guided && !self.cancelled && self.shown > 0 && !wait_for_page() {
+            self.cancelled = true;
+        }
+        !self.cancelled
+    }
+}
+
+fn wait_for_page() -> bool {
+    print!("-- Enter to continue, q to cancel -- ");
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).is_ok_and(|read| read > 0 && !line.trim().eq_ignore_ascii_case("q"))
+}
+
+/// Let the user pick the device to format from a numbered list; None if they cancel
+pub fn choose_device(devices: &[Device]) -> io::Result<Option<Device>> {
+    if devices.is_empty() {
+        println!("No devices found.");
+        return Ok(None);
+    }
+    println!("Devices Moses can see:\n");
+    for (number, device) in devices.iter().enumerate() {
+        let note = if device.is_system {
+            " - system disk, cannot be formatted"
+        } else if device.is_write_protected {
+            " - write-protected"
+        } else {
+            ""
+        };
+        println!("  {}. {} ({}, {:.2} GB, {:?}){}", number + 1, device.name, device.id,
+            device.size as f64 / 1_073_741_824.0, device.device_type, note);
+    }
+    loop {
+        print!("\nNumber of the device to format (q to cancel): ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 || line.trim().eq_ignore_ascii_case("q") {
+            return Ok(None);
+        }
+        match line.trim().parse::<usize>().ok().and_then(|number| devices.get(number.wrapping_sub(1))) {
+            Some(device) if device.is_system => println!("{} is a system disk and cannot be formatted", device.name),
+            Some(device) if device.is_write_protected => println!("{} is write-protected", device.name),
+            Some(device) => return Ok(Some(device.clone())),
+            None => println!("Enter a number from 1 to {}", devices.len()),
+        }
+    }
+}
+
+/// Everything known about the device before it is erased
+pub fn print_device_details(device: &Device, peers: &[Device]) {
+    println!("Target device: {}", device.name);
+    println!("  Path: {}", device.id);
+    println!("  Size: {:.2} GB ({} bytes)", device.size as f64 / 1_073_741_824.0, device.size);
+    println!("  Type: {:?}", device.device_type);
+    println!("  Removable: {}", if device.is_removable { "Yes" } else { "No" });
+    println!("  Serial number: {}", serial(device).unwrap_or("none reported"));
+    if let Some(filesystem) = &device.filesystem {
+        println!("  Filesystem: {}", filesystem);
+    }
+    if !device.mount_points.is_empty() {
+        println!("  Mounted at: {:?}", device.mount_points);
+    }
+    let risk = risk::assess_device(device, peers);
+    println!("  Risk: {:?}", risk.level);
+    for reason in &risk.reasons {
+        println!("    - {}", reason.detail);
+    }
+    if let Some(entry) = DeviceHistory::global().entries(device).first() {
+        println!("  Last changed by Moses: {} ({}; it held {})",
+            entry.at.format("%Y-%m-%d %H:%M"), entry.operation, entry.before.summary());
+    }
+}
+
+/// Ask for the device's serial number, or its path when it reports none. Returns how the
+/// user confirmed, for the audit log, or None if what they typed does not match.
+pub fn confirm_device(device: &Device) -> io::Result<Option<String>> {
+    let (what, expected) = match serial(device) {
+        Some(serial) => ("serial number", serial.to_string()),
+        None => ("path", device.id.clone()),
+    };
+    print!("To erase {}, type its {} as shown above: ", device.name, what);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    if line.trim() == expected {
+        Ok(Some(format!("typed the device {}", what)))
+    } else {
+        println!("That is not the {} of {}.", what, device.name);
+        Ok(None)
+    }
+}
+
+/// Whether the user has never confirmed a format, so the guided mode is worth suggesting
+pub fn first_run() -> bool {
+    AuditLog::global().records().is_empty()
+}
+
+fn serial(device: &Device) -> Option<&str> {
+    device.serial.as_deref().map(str::trim).filter(|serial| !serial.is_empty())
+}
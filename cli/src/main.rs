@@ -1,45 +1,180 @@
-use clap::{Parser, Subcommand};
-use moses_core::{DeviceManager, FormatterRegistry, FormatterCategory};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::ArgValueCompleter;
+use moses_core::{
+    CachedFilesystemInfo, DeviceManager, DevicePowerState, FilesystemCache, FormatPreset, FormatStrategy, FormatterCategory, FormatterRegistry,
+    MosesConfig, PostOperationAction, ReleaseChannel,
+};
 use moses_platform::PlatformDeviceManager;
+use moses_daemon::{DeviceWatcher, EventDispatcher, MosesEvent};
 use moses_filesystems::register_builtin_formatters;
+use moses_filesystems::advisor::Goal;
+use moses_filesystems::FlashJournal;
+use moses_filesystems::disk_manager::selective::{self, SelectiveFormatReport, SelectiveFormatRequest};
+use moses_filesystems::disk_manager::seeding::{self, SeedStep};
+use moses_filesystems::partitioner::PartitionTableType;
 #[cfg(any(feature = "mount-windows", feature = "mount-unix"))]
 use moses_filesystems::mount::{get_mount_provider, MountOptions};
 use std::sync::Arc;
 
+mod completion;
+mod doctor;
+mod guided;
+mod progress;
+
 #[derive(Parser)]
 #[command(name = "moses")]
 #[command(about = "Cross-platform drive formatting tool", long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Don't show progress bars or spinners
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Plain output without colors
+    #[arg(long, global = true)]
+    no_color: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// List available drives
+    ///
+    /// Shows every disk the platform reports with its size, type, filesystem and mount
+    /// points. System disks are marked as protected and can never be formatted.
     List,
+    /// Show a drive's details and what Moses has done to it
+    ///
+    /// Before every format, clean or conversion Moses records the filesystem, label and
+    /// partition layout it found, keyed by the drive's serial number where available.
+    /// `--restore-layout` writes the most recently saved partition table back.
+    Info {
+        /// Device identifier or disk image path; `device:<id>@part(N)` for one partition
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Put back the partition table from before the last operation that replaced one
+        #[arg(long)]
+        restore_layout: bool,
+    },
     /// Format a drive
+    ///
+    /// Runs a simulation first, shows its warnings, then asks for confirmation before
+    /// erasing anything. System drives and write-protected media are refused.
+    ///
+    /// Examples:
+    ///   moses format /dev/sdb -f exfat
+    ///   moses format E: -f fat32 --after eject
+    ///   moses format E: -f exfat --keep /Photos --keep /Work
+    ///   moses format --image out.img --size 256M -f fat32
+    ///   moses format --image golden.img --size 1G -f ext4 --seed build-42
     Format {
         /// Device identifier
-        device: String,
+        #[arg(required_unless_present_any = ["image", "interactive"], add = ArgValueCompleter::new(completion::complete_device))]
+        device: Option<String>,
+        /// Walk through the format: pick the device from a list with its details, read the
+        /// simulation a page at a time and confirm by typing the device's serial number
+        #[arg(long, conflicts_with = "image")]
+        interactive: bool,
+        /// Write the filesystem into a new image file instead of a drive (uses the built-in formatter)
+        #[arg(long, requires = "size", conflicts_with_all = ["device", "acknowledge_members", "after", "strategy", "keep", "preserve_serial"])]
+        image: Option<std::path::PathBuf>,
+        /// Size of the image file (e.g. 256M, 2G)
+        #[arg(long, value_parser = parse_size, requires = "image")]
+        size: Option<u64>,
         /// Filesystem type (ext4, ntfs, fat32, exfat, etc.)
         #[arg(short, long)]
         filesystem: String,
+        /// Other disks of a striped/spanned/RAID volume you accept breaking (or the volume group name)
+        #[arg(long, value_delimiter = ',')]
+        acknowledge_members: Vec<String>,
+        /// What to do with the drive afterwards (eject, power-off, standby)
+        #[arg(long, value_parser = parse_post_action)]
+        after: Option<PostOperationAction>,
+        /// Native or system-tool implementation, where both exist (auto, prefer-native, prefer-system)
+        #[arg(long, default_value = "auto", value_parser = parse_strategy)]
+        strategy: FormatStrategy,
+        /// Layout preset (auto, generic, sd-card, flash); auto uses the SD Association layout on SD cards
+        /// and flash formats ext4 without a journal, aligned to the erase block
+        #[arg(long, default_value = "auto", value_parser = parse_preset)]
+        preset: FormatPreset,
+        /// Journal for the flash preset: none (default) or async (journal_async_commit)
+        #[arg(long, value_parser = parse_flash_journal)]
+        flash_journal: Option<FlashJournal>,
+        /// File or folder on the drive to keep: it is copied off, and back after the format
+        #[arg(long)]
+        keep: Vec<std::path::PathBuf>,
+        /// Host folder the kept items are held in during the format (default: the temp folder)
+        #[arg(long, requires = "keep")]
+        staging: Option<std::path::PathBuf>,
+        /// Give the new volume the serial number (and exFAT volume GUID) of the one on the drive now
+        #[arg(long)]
+        preserve_serial: bool,
+        /// Derive UUIDs and serials from this text and use a fixed timestamp, so the same seed
+        /// gives a bit-identical filesystem
+        #[arg(long, conflicts_with = "preserve_serial")]
+        seed: Option<String>,
+        /// Timestamp for --seed in seconds since 1970 (default: 2000-01-01)
+        #[arg(long, requires = "seed")]
+        timestamp: Option<u64>,
+        /// Finish the new volume with: autorun (autorun.inf with label and icon), no-index
+        /// (.metadata_never_index for macOS), ext-root (root directory owned by you)
+        #[arg(long, value_delimiter = ',')]
+        post_step: Vec<String>,
+        /// .ico file the autorun step copies to the drive and names in autorun.inf
+        #[arg(long, requires = "post_step")]
+        volume_icon: Option<std::path::PathBuf>,
+    },
+    /// Recommend a filesystem for what the drive will be used for
+    ///
+    /// Checks every formatter against the goals (which systems must write the drive, files
+    /// over 4 GB, Unix permissions, SD card layout) and the drive's size, then explains the
+    /// trade-offs of the best match and the alternatives, and prints the format command.
+    ///
+    /// Examples:
+    ///   moses advise E: --goal share-windows-mac --goal large-files
+    ///   moses advise /dev/sdb --goal linux-boot
+    Advise {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// share-windows-mac, everywhere, large-files, linux-boot, linux-only, windows-only or camera
+        #[arg(short, long = "goal", required = true, value_parser = parse_goal)]
+        goals: Vec<Goal>,
     },
     /// List available formatters
+    ///
+    /// Without a category, formatters are grouped by category. Use `moses format-info`
+    /// for limits, aliases and required tools of a single formatter.
     ListFormats {
         /// Filter by category (modern, legacy, historical, console, embedded, experimental)
         #[arg(short, long)]
         category: Option<String>,
+        /// Show what can be done with each filesystem (format, read, write, mount, check,
+        /// resize, label) on this machine instead
+        #[arg(long, conflicts_with = "category")]
+        matrix: bool,
+        /// Print the matrix as JSON
+        #[arg(long, requires = "matrix")]
+        json: bool,
     },
     /// Show detailed information about a formatter
+    ///
+    /// Prints the formatter's description, supported platforms, size limits and the
+    /// external tools it needs, if any.
     FormatInfo {
         /// Formatter name or alias
         name: String,
     },
     /// Mount a filesystem (reads any filesystem on any platform!)
+    ///
+    /// Needs a build with WinFsp (Windows) or FUSE (Linux/macOS) support.
+    ///
+    /// Example:
+    ///   moses mount /dev/sdb1 /mnt/ext4 --readonly
+    ///   moses mount device:/dev/sdb@part(2):/DCIM /mnt/photos
     Mount {
-        /// Source device (e.g., E:, /dev/sdb1)
+        /// Source: `device:<id>[@part(N)][:/path]`, `image:<file>`, `host:<folder>`, or a
+        /// bare device, image or folder (e.g., E:, /dev/sdb1)
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
         source: String,
         /// Mount point (e.g., M:, /mnt/ext4)
         target: String,
@@ -49,17 +184,1014 @@ enum Commands {
         /// Mount as read-only
         #[arg(short = 'r', long)]
         readonly: bool,
+        /// Follow links (symlinks, NTFS junctions) that point inside the volume instead of
+        /// showing them as links; links leading elsewhere are still shown as links
+        #[arg(long)]
+        follow_links: bool,
+        /// Mount an NTFS shadow copy (GUID or index from `moses snapshots`); always read-only
+        #[arg(long, value_name = "ID")]
+        snapshot: Option<String>,
+        /// On Linux, mount with the kernel's own driver (through a loop device for images)
+        /// when it has one for the filesystem; other filesystems still go through FUSE.
+        /// The mount stays after `moses mount` exits
+        #[arg(long, conflicts_with_all = ["follow_links", "snapshot"])]
+        kernel: bool,
     },
     /// Unmount a filesystem
+    ///
+    /// Releases a mount created with `moses mount`, including `--kernel` mounts.
     Unmount {
         /// Mount point to unmount
         target: String,
     },
+    /// Show a read-only hex dump of a device with structure annotations
+    ///
+    /// Known on-disk structures (MBR, GPT, boot sectors, superblocks) inside the dumped
+    /// range are annotated field by field. Nothing is ever written.
+    ///
+    /// Example:
+    ///   moses hexdump /dev/sdb --offset 0x400 --len 1024
+    Hexdump {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Byte offset to start at (decimal or 0x-prefixed hex)
+        #[arg(short, long, default_value = "0", value_parser = parse_number)]
+        offset: u64,
+        /// Number of bytes to dump
+        #[arg(short, long, default_value = "512", value_parser = parse_number)]
+        len: u64,
+    },
+    /// Identify and decode the on-disk structure at an offset
+    ///
+    /// Useful when a disk is not recognized: point it at an offset and it reports which
+    /// structure lives there and what each field means.
+    Explain {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Byte offset of the structure (decimal or 0x-prefixed hex)
+        #[arg(short, long, default_value = "0", value_parser = parse_number)]
+        offset: u64,
+    },
+    /// Work out what is on a drive whose filesystem isn't recognised
+    ///
+    /// Runs every detector, scores partial signatures, classifies the content and looks for
+    /// leftovers of earlier filesystems. `--quick` reads only the start of each volume;
+    /// `--deep` also sweeps every sector for volumes no partition table points to any more.
+    /// The expected time of each depth comes from a few timed reads of the drive.
+    Analyze {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Only the partition table and the first MBs of each volume
+        #[arg(long, conflicts_with = "deep")]
+        quick: bool,
+        /// Sweep the whole surface for filesystem signatures
+        #[arg(long)]
+        deep: bool,
+        /// Show how long each depth would take, without analyzing
+        #[arg(long)]
+        estimate: bool,
+    },
+    /// Chart which parts of a volume are in use
+    ///
+    /// Reads the filesystem's own allocation record (ext block bitmaps, the FAT, the exFAT
+    /// bitmap or NTFS $Bitmap) and draws it downsampled, marking where data is most scattered.
+    UsageMap {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Cells per line
+        #[arg(short, long, default_value = "64")]
+        width: usize,
+        /// Number of lines
+        #[arg(short, long, default_value = "16")]
+        rows: usize,
+    },
+    /// Erase filesystem and partition table signatures without cleaning the disk
+    ///
+    /// Only the magic bytes are cleared, so other tools stop detecting stale filesystems.
+    /// The original bytes are saved to a backup file that `--restore` puts back.
+    Wipefs {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Only list the signatures that would be erased
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the original bytes (default: moses-wipefs-<device>.bak)
+        #[arg(short, long)]
+        backup: Option<std::path::PathBuf>,
+        /// Do not write a backup file
+        #[arg(long, conflicts_with = "backup")]
+        no_backup: bool,
+        /// Restore signatures from a backup file instead of wiping
+        #[arg(long, conflicts_with_all = ["no_act", "backup", "no_backup"])]
+        restore: Option<std::path::PathBuf>,
+    },
+    /// List a folder on a drive or image without mounting it
+    ///
+    /// Reads the filesystem directly, so it works for read-only formats such as ISO
+    /// images as well as drives the OS cannot mount. Naming a file lists just that file.
+    ///
+    /// Examples:
+    ///   moses ls ubuntu.iso:/casper
+    ///   moses ls /dev/sdb1:/DCIM --long
+    Ls {
+        /// `device:<id>[@part(N)][:/path]` or `image:<file>[:/path]` (default: the root);
+        /// a bare device or image works too
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Filesystem to read as, instead of detecting it
+        #[arg(long)]
+        fs_type: Option<String>,
+        /// Show type, permissions and size, and where symbolic links point
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Copy a whole drive or partition into an image file
+    ///
+    /// Every byte is read into a new file (runs of zeros are left as holes), which can be
+    /// browsed with `moses ls` or written back later. An existing file is never overwritten.
+    /// A mounted volume keeps changing while it is copied; `--consistent` freezes it
+    /// (Linux) or images a shadow copy of it (Windows) for the length of the copy.
+    ///
+    /// Examples:
+    ///   moses image /dev/sdb --to card.img
+    ///   moses image E: --to backup.img --consistent
+    Image {
+        /// Device identifier (`@part(N)` for one partition)
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Image file to create
+        #[arg(long)]
+        to: std::path::PathBuf,
+        /// Hold a mounted volume still while it is copied
+        #[arg(long)]
+        consistent: bool,
+    },
+    /// Archive a directory tree from a drive without mounting it
+    ///
+    /// Reads the filesystem directly and streams the subtree into a tar, tar.gz, tar.zst
+    /// or zip archive (chosen by the output extension), keeping timestamps and
+    /// permissions. Handy for saving data before a reformat; the drive is never written.
+    ///
+    /// Globs without a `/` match file names anywhere (`*.jpg`); globs with one match the
+    /// path below the exported folder (`2024/**/*.raw`). Excluded folders are skipped
+    /// entirely. `--preview` prints what would be archived without writing anything.
+    ///
+    /// Examples:
+    ///   moses export /dev/sdb1:/DCIM --to photos.tar.zst
+    ///   moses export card.img --to card.zip --include '*.jpg' --newer-than 2024-01-01
+    Export {
+        /// `device:<id>[@part(N)][:/path]` or `image:<file>[:/path]` (default: the root);
+        /// a bare device or image works too
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Archive to create
+        #[arg(long, required_unless_present = "preview")]
+        to: Option<std::path::PathBuf>,
+        /// Filesystem to read as, instead of detecting it
+        #[arg(long)]
+        fs_type: Option<String>,
+        /// Only archive files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+        /// Leave out files and folders matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+        /// Leave out files larger than this (e.g. 500M, 2G)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only archive files modified on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        newer_than: Option<u64>,
+        /// Count matching files and bytes without creating the archive
+        #[arg(long)]
+        preview: bool,
+        /// Export from an NTFS shadow copy (GUID or index from `moses snapshots`)
+        #[arg(long, value_name = "ID")]
+        snapshot: Option<String>,
+    },
+    /// Record the SHA-256 of every file on a volume
+    ///
+    /// With --save the checksums are kept for the drive (found again by its serial), so
+    /// `moses verify` can later tell whether anything changed or rotted. Every file is read
+    /// in full; use `--background` and `--limit` to keep the machine usable meanwhile.
+    ///
+    /// Examples:
+    ///   moses hash /dev/sdb1 --save
+    ///   moses hash /dev/sdb1:/Archive --output archive-2024.json
+    ///   moses hash /dev/sdb1 --save --background --limit 50
+    Hash {
+        /// `device:<id>[@part(N)][:/path]` or `image:<file>[:/path]` (default: the root);
+        /// a bare device or image works too
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Filesystem to read as, instead of detecting it
+        #[arg(long)]
+        fs_type: Option<String>,
+        /// Keep the checksums in Moses's database for `moses verify`
+        #[arg(long)]
+        save: bool,
+        /// Also write the checksums to this JSON file
+        #[arg(long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+        /// Run at idle I/O priority so the machine stays usable
+        #[arg(long)]
+        background: bool,
+        /// Cap throughput in MB/s
+        #[arg(long, value_name = "MB/s")]
+        limit: Option<u64>,
+    },
+    /// Check a volume against checksums recorded by `moses hash`
+    ///
+    /// Reports files that are missing, were edited, were added, or changed without being
+    /// rewritten (same size and modification time), which points at silent corruption.
+    /// Exits with status 1 when a recorded file is not intact. Like `moses hash` it reads
+    /// every file, so `--background` and `--limit` apply here too.
+    Verify {
+        /// `device:<id>[@part(N)][:/path]` or `image:<file>[:/path]` (default: the root);
+        /// a bare device or image works too
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Filesystem to read as, instead of detecting it
+        #[arg(long)]
+        fs_type: Option<String>,
+        /// Compare against this file from `moses hash --output` instead of the database
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<std::path::PathBuf>,
+        /// Afterwards keep the current checksums as the new baseline
+        #[arg(long)]
+        update: bool,
+        /// Run at idle I/O priority so the machine stays usable
+        #[arg(long)]
+        background: bool,
+        /// Cap throughput in MB/s
+        #[arg(long, value_name = "MB/s")]
+        limit: Option<u64>,
+    },
+    /// Show or change a file's attributes on an unmounted drive, like attrib, chmod and chown
+    ///
+    /// FAT, exFAT and NTFS keep the read-only, hidden, system and archive bits; ext keeps a
+    /// Unix mode, owner and group. A mode without the owner's write bit makes a file
+    /// read-only on FAT, exFAT and NTFS. The old bytes are saved to an undo file first,
+    /// which `--undo` applies to put them back.
+    ///
+    /// Examples:
+    ///   moses attr card.img:/DCIM --set h
+    ///   moses attr /dev/sdb1:/home/pi --mode 750 --owner 1000:1000
+    Attr {
+        /// `device:<id>[@part(N)]:/path` or `image:<file>:/path`
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        source: String,
+        /// Filesystem to edit as, instead of detecting it
+        #[arg(long)]
+        fs_type: Option<String>,
+        /// Attributes to set: any of r(ead-only), h(idden), s(ystem), a(rchive)
+        #[arg(long, value_name = "RHSA")]
+        set: Option<String>,
+        /// Attributes to clear: any of r, h, s, a
+        #[arg(long, value_name = "RHSA")]
+        clear: Option<String>,
+        /// Permission bits in octal, e.g. 644
+        #[arg(long, value_parser = parse_mode)]
+        mode: Option<u32>,
+        /// New owner as UID, or UID:GID to change the group too
+        #[arg(long, value_name = "UID[:GID]")]
+        owner: Option<String>,
+        /// Only show the bytes that would change
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the undo record (default: moses-attr-<device>.json)
+        #[arg(long)]
+        undo_file: Option<std::path::PathBuf>,
+        /// Put back the bytes saved in an undo record
+        #[arg(long, conflicts_with_all = ["set", "clear", "mode", "owner", "undo_file"])]
+        undo: Option<std::path::PathBuf>,
+        /// Write even to a FAT or exFAT volume that is marked dirty
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the shadow copies (previous versions) of an NTFS volume
+    ///
+    /// Their files can be browsed with `moses mount --snapshot` or saved with
+    /// `moses export --snapshot`.
+    Snapshots {
+        /// NTFS volume or disk image
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+    },
+    /// Show or empty the recycle bins and trash folders of a drive
+    ///
+    /// Lists $RECYCLE.BIN, RECYCLER, .Trash-<uid> and .Trashes at the root of each volume
+    /// with the space they hold. `--purge` deletes their contents after confirmation,
+    /// which often frees enough space that a reformat isn't needed.
+    Trash {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Delete everything in the trash folders
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Rebuild a damaged FAT32, exFAT or NTFS boot sector from its backup copy
+    ///
+    /// FAT32 keeps a copy at sector 6, exFAT a whole backup boot region after the main
+    /// one and NTFS a copy in the volume's last sector. The backup is checked, the fields
+    /// that differ are listed, and after confirmation it is copied over the primary.
+    /// The replaced bytes are saved to a backup file that `--restore` puts back.
+    RescueBoot {
+        /// Volume or disk image
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Only show what would change
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the replaced bytes (default: moses-boot-<device>.bak)
+        #[arg(short, long)]
+        backup: Option<std::path::PathBuf>,
+        /// Do not write a backup file
+        #[arg(long, conflicts_with = "backup")]
+        no_backup: bool,
+        /// Undo an earlier rescue from its backup file
+        #[arg(long, conflicts_with_all = ["no_act", "backup", "no_backup"])]
+        restore: Option<std::path::PathBuf>,
+    },
+    /// Rebuild a lost partition table from the filesystems still on the disk
+    ///
+    /// Probes every MiB and cylinder boundary for FAT, exFAT, NTFS and ext volumes, sizes
+    /// each from its own metadata and, after confirmation, writes a table listing them.
+    /// The table it replaces is kept in the drive's history (`moses info --restore-layout`).
+    RebuildTable {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Table to write: mbr or gpt (default: MBR when the volumes fit in one)
+        #[arg(long, value_parser = parse_table_type)]
+        style: Option<PartitionTableType>,
+        /// Only list the volumes found
+        #[arg(short = 'n', long)]
+        no_act: bool,
+    },
+    /// Repair tools for ext2, ext3 and ext4 filesystems
+    Ext {
+        #[command(subcommand)]
+        command: ExtCommand,
+    },
+    /// Checks and label changes for FAT12, FAT16, FAT32 and exFAT volumes
+    Fat {
+        #[command(subcommand)]
+        command: FatCommand,
+    },
+    /// Sector size (LBA format) of NVMe namespaces
+    Nvme {
+        #[command(subcommand)]
+        command: NvmeCommand,
+    },
+    /// Wipe partition structures or the whole disk
+    ///
+    /// `quick` clears the partition tables and the first megabyte. `zero`, `random` and
+    /// `dod` (three passes) overwrite the whole disk and can take hours; use
+    /// `--background` and `--limit` to keep the machine usable meanwhile.
+    Clean {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Wipe method (quick, zero, dod, random)
+        #[arg(short, long, default_value = "quick")]
+        method: String,
+        /// Run at idle I/O priority so the machine stays usable
+        #[arg(long)]
+        background: bool,
+        /// Cap throughput in MB/s
+        #[arg(long, value_name = "MB/s")]
+        limit: Option<u64>,
+        /// Only list the sectors and partitions that would be overwritten
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Show or change settings (stored in the user's config directory)
+    ///
+    /// Without options the current settings are printed. Environment variables
+    /// MOSES_MAX_THREADS, MOSES_QUEUE_DEPTH, MOSES_BLOCK_SIZE and MOSES_IO_QUEUE_DEPTH
+    /// override them for a single run.
+    Config {
+        /// Maximum worker threads for parallel work (0 = one per CPU)
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Operations allowed in flight against one device
+        #[arg(long)]
+        queue_depth: Option<usize>,
+        /// Probe each device for the best block size before copies and wipes
+        #[arg(long)]
+        auto_tune: Option<bool>,
+        /// Block size for copies and wipes instead of the probed one (0 = probe)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        block_size: Option<u64>,
+        /// Reads in flight per device instead of the probed count (0 = probe)
+        #[arg(long)]
+        io_queue_depth: Option<usize>,
+        /// Release channel `moses update` and the app follow (stable or beta)
+        #[arg(long, value_parser = parse_channel)]
+        channel: Option<ReleaseChannel>,
+    },
+    /// Flush and release a removable drive so it can be unplugged
+    ///
+    /// Mounted volumes must be unmounted first on Linux; on Windows they are locked and
+    /// dismounted automatically.
+    Eject {
+        /// Device identifier
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Also cut power to the drive where the platform supports it
+        #[arg(long)]
+        power_off: bool,
+    },
+    /// Spin a drive down to standby, or wake it back up
+    ///
+    /// Uses hdparm (ATA) or sdparm (USB/SCSI) on Linux. Any access wakes the drive again.
+    Standby {
+        /// Device identifier
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Spin the drive up instead
+        #[arg(long)]
+        wake: bool,
+    },
+    /// Check the environment and suggest fixes for anything missing
+    ///
+    /// Checks privileges, the WinFsp/FUSE driver used for mounting, external tools that
+    /// formatters need, the elevated worker used by the GUI, and whether raw devices can
+    /// be opened. Exits with status 1 if a problem was found.
+    Doctor,
+    /// Show the external tools formatters use, or install one from the tool manifest
+    ///
+    /// Tools are looked up in the managed tools directory, a `tools` folder next to the
+    /// moses binary, then PATH. Packages listed in the tool manifest can be downloaded
+    /// into the managed directory; each download is checked against its SHA-256.
+    Tools {
+        /// Package to download and install (e.g. exfatprogs)
+        #[arg(long)]
+        install: Option<String>,
+    },
+    /// Check for a newer Moses and stage it to replace this one on the next start
+    ///
+    /// Releases come from the feed in the settings (`update.feed`) on the followed channel,
+    /// stable unless changed with `moses config --channel beta`. A download is only staged
+    /// when its SHA-256 and release signature check out; the next run of moses swaps it in.
+    ///
+    /// Examples:
+    ///   moses update --check
+    ///   moses update --channel beta
+    Update {
+        /// Only report whether a newer release is available
+        #[arg(long)]
+        check: bool,
+        /// Follow this channel for this run instead of the configured one (stable or beta)
+        #[arg(long, value_parser = parse_channel)]
+        channel: Option<ReleaseChannel>,
+    },
+    /// Show what Moses has run on this machine and how it went
+    ///
+    /// Formats, cleans and exports record the device type, the error code of a failure and
+    /// the bytes moved in a local file; nothing is ever sent anywhere. Useful for spotting a
+    /// card reader that keeps timing out or a stick that is much slower than the others.
+    Stats {
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+        /// Delete everything recorded so far
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Watch for device changes and report events to webhooks and MQTT
+    ///
+    /// Receivers are configured under "events" in the config file. Every attach and
+    /// detach is delivered as JSON; formats run with `moses format` also report
+    /// format_completed and error events. Runs until interrupted.
+    Serve {
+        /// Seconds between device scans (default from the config, 2)
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+    /// Print a shell completion script
+    ///
+    /// Device arguments complete to the drives currently attached. Load the script from
+    /// your shell's startup file, for example:
+    ///   bash:       source <(moses completions bash)
+    ///   zsh:        source <(moses completions zsh)
+    ///   fish:       moses completions fish | source
+    ///   PowerShell: moses completions powershell | Out-String | Invoke-Expression
+    Completions {
+        /// Shell to generate the script for
+        shell: completion::Shell,
+    },
+    /// Generate man pages
+    ///
+    /// Prints the main page to stdout, or writes moses.1 and one page per subcommand
+    /// into a directory.
+    Man {
+        /// Directory to write every page into
+        #[arg(long)]
+        out_dir: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExtCommand {
+    /// Replace a corrupted primary superblock with one of its backups
+    ///
+    /// mkfs stores copies of the superblock and group descriptors in group 1 and groups
+    /// that are powers of 3, 5 and 7. This lists the copies found and writes the chosen
+    /// one over the primary. The replaced bytes are saved to a backup file that
+    /// `--restore` puts back. Run `e2fsck -f` afterwards to fix the free counts.
+    RescueSuperblock {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Block group of the backup to use, instead of asking
+        #[arg(short, long)]
+        group: Option<u32>,
+        /// Only list the backup superblocks found
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the replaced bytes (default: moses-superblock-<device>.bak)
+        #[arg(short, long)]
+        backup: Option<std::path::PathBuf>,
+        /// Do not write a backup file
+        #[arg(long, conflicts_with = "backup")]
+        no_backup: bool,
+        /// Undo an earlier rescue from its backup file
+        #[arg(long, conflicts_with_all = ["group", "no_act", "backup", "no_backup"])]
+        restore: Option<std::path::PathBuf>,
+    },
+    /// Change the label or UUID of an ext filesystem in place, like tune2fs -L / -U
+    ///
+    /// The primary superblock and all its backups are edited together. The old bytes are
+    /// saved to an undo file first, which `--undo` applies to put them back.
+    Tune {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// New volume label, at most 16 bytes
+        #[arg(short = 'L', long)]
+        label: Option<String>,
+        /// New UUID, or "random"
+        #[arg(short = 'U', long)]
+        uuid: Option<String>,
+        /// Only show the bytes that would change
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the undo record (default: moses-tune-<device>.json)
+        #[arg(long)]
+        undo_file: Option<std::path::PathBuf>,
+        /// Put back the bytes saved in an undo record
+        #[arg(long, conflicts_with_all = ["label", "uuid", "undo_file"])]
+        undo: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum FatCommand {
+    /// Check a volume that was not cleanly unmounted and mark it clean
+    ///
+    /// A FAT driver marks a volume dirty while it is mounted, so one removed or powered
+    /// off mid-write keeps the mark, and Moses will not write to it. This reports the
+    /// marks, checks that the FAT copies agree and that no cluster chain points outside
+    /// the volume or into another chain, and when nothing is wrong clears the marks. The
+    /// old bytes are saved to an undo file first, which `--undo` applies to put them back.
+    Check {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Clear the marks without asking when the check finds nothing wrong
+        #[arg(long)]
+        mark_clean: bool,
+        /// Only check; leave the marks as they are
+        #[arg(short = 'n', long, conflicts_with = "mark_clean")]
+        no_act: bool,
+        /// Where to save the undo record (default: moses-fat-clean-<device>.json)
+        #[arg(long)]
+        undo_file: Option<std::path::PathBuf>,
+        /// Put back the bytes saved in an undo record
+        #[arg(long, conflicts_with_all = ["mark_clean", "no_act", "undo_file"])]
+        undo: Option<std::path::PathBuf>,
+    },
+    /// Show or change the volume label of a FAT or exFAT volume
+    ///
+    /// FAT keeps the label in the boot sector and as a root directory entry, and Windows
+    /// shows the entry, so both are written together (with the FAT32 backup boot sector).
+    /// Without a new label this shows both copies and whether they agree. The old bytes
+    /// are saved to an undo file first, which `--undo` applies to put them back.
+    Label {
+        /// Device identifier or disk image path
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// New label: at most 11 characters, stored upper case on FAT
+        label: Option<String>,
+        /// Remove the label
+        #[arg(long, conflicts_with = "label")]
+        clear: bool,
+        /// Only show the bytes that would change
+        #[arg(short = 'n', long)]
+        no_act: bool,
+        /// Where to save the undo record (default: moses-label-<device>.json)
+        #[arg(long)]
+        undo_file: Option<std::path::PathBuf>,
+        /// Put back the bytes saved in an undo record
+        #[arg(long, conflicts_with_all = ["label", "clear", "undo_file"])]
+        undo: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum NvmeCommand {
+    /// List the LBA formats (sector sizes) an NVMe namespace supports
+    LbaFormats {
+        /// NVMe namespace, e.g. /dev/nvme0n1
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+    },
+    /// Reformat an NVMe namespace to another sector size, ERASING EVERYTHING on it
+    ///
+    /// NVMe drives usually ship with 512-byte sectors (512e) and can be switched to
+    /// 4096-byte ones (4Kn), which suit the 4 KiB blocks of ext4 and NTFS. The switch is a
+    /// Format NVM command: every partition and file on the namespace is lost, and there is
+    /// no undo. Confirm by typing the drive's serial number. Linux only.
+    SetLbaFormat {
+        /// NVMe namespace, e.g. /dev/nvme0n1
+        #[arg(add = ArgValueCompleter::new(completion::complete_device))]
+        device: String,
+        /// Index of the LBA format, as `moses nvme lba-formats` lists them
+        #[arg(required_unless_present = "sector_size")]
+        format: Option<u8>,
+        /// Pick the best rated format with this sector size instead, e.g. 4096
+        #[arg(long, conflicts_with = "format")]
+        sector_size: Option<u32>,
+        /// Only show what would change
+        #[arg(short = 'n', long)]
+        no_act: bool,
+    },
+}
+
+/// Parse octal permission bits for `moses attr --mode`
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim().trim_start_matches("0o"), 8).ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("'{}' is not an octal mode such as 644 or 2775", s))
+}
+
+/// Parse a byte count with an optional K, M, G or T suffix (powers of 1024)
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(format!("Unknown size suffix in '{}' (use K, M, G or T)", s)),
+            };
+            (&s[..i], shift)
+        }
+        _ => (s, 0),
+    };
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Invalid size: {}", s))
+}
+
+/// Parse a YYYY-MM-DD date into the Unix timestamp of its start (UTC)
+fn parse_date(s: &str) -> Result<u64, String> {
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp().max(0) as u64)
+        .map_err(|_| format!("Invalid date '{}' (expected YYYY-MM-DD)", s))
+}
+
+/// Parse a decimal or 0x-prefixed hexadecimal number
+fn parse_number(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    };
+    parsed.map_err(|e| format!("Invalid number '{}': {}", s, e))
+}
+
+fn parse_channel(s: &str) -> Result<ReleaseChannel, String> {
+    s.parse().map_err(|e: moses_core::MosesError| e.to_string())
+}
+
+fn parse_table_type(s: &str) -> Result<PartitionTableType, String> {
+    match s.to_lowercase().as_str() {
+        "mbr" => Ok(PartitionTableType::MBR),
+        "gpt" => Ok(PartitionTableType::GPT),
+        _ => Err(format!("Unknown partition table '{}' (expected mbr or gpt)", s)),
+    }
+}
+
+fn parse_strategy(s: &str) -> Result<FormatStrategy, String> {
+    FormatStrategy::parse(s)
+        .ok_or_else(|| format!("Unknown strategy '{}' (expected auto, prefer-native or prefer-system)", s))
+}
+
+fn parse_preset(s: &str) -> Result<FormatPreset, String> {
+    FormatPreset::parse(s)
+        .ok_or_else(|| format!("Unknown preset '{}' (expected auto, generic, sd-card or flash)", s))
+}
+
+fn parse_goal(s: &str) -> Result<Goal, String> {
+    s.parse().map_err(|e: moses_core::MosesError| e.to_string())
+}
+
+fn parse_flash_journal(s: &str) -> Result<FlashJournal, String> {
+    FlashJournal::parse(s)
+        .ok_or_else(|| format!("Unknown flash journal mode '{}' (expected none or async)", s))
+}
+
+fn parse_post_action(s: &str) -> Result<PostOperationAction, String> {
+    PostOperationAction::parse(s)
+        .ok_or_else(|| format!("Unknown action '{}' (expected eject, power-off or standby)", s))
+}
+
+/// Filesystem of a device: platform report, then the shared cache, then a quick probe
+fn device_filesystem(device: &moses_core::Device) -> Option<String> {
+    if let Some(fs) = device.filesystem.as_ref().filter(|fs| fs.as_str() != "unknown") {
+        return Some(fs.clone());
+    }
+    
+    let cache = FilesystemCache::global();
+    if let Some(fs) = cache.filesystem(device) {
+        return Some(fs);
+    }
+    
+    // Probing needs read access to the raw device; quietly skip if we don't have it
+    let mut file = moses_filesystems::utils::open_device_read(device).ok()?;
+    let fs = moses_filesystems::detection::detect_filesystem(&mut file)
+        .ok()
+        .filter(|fs| fs.as_str() != "unknown")?;
+    cache.insert(device, 0, CachedFilesystemInfo::detected_now(fs.clone()));
+    Some(fs)
+}
+
+/// How long `format --keep` waits for the OS to mount the new volume before writing to it itself
+const REMOUNT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+fn print_selective_report(report: &SelectiveFormatReport) {
+    match &report.restore_error {
+        None => println!("Restored {} kept files ({:.2} MB)",
+            report.restored.files_copied, report.restored.bytes_copied as f64 / (1024.0 * 1024.0)),
+        Some(error) => {
+            eprintln!("{}", progress::error(&format!("The kept items could not be restored: {}", error)));
+            if !report.rolled_back {
+                eprintln!("{}", progress::warning("  Part of them may be on the new volume"));
+            }
+        }
+    }
+    if let Some(staging) = &report.staging {
+        println!("{}", progress::warning(&format!("The kept items are still in {}", staging.display())));
+    }
+}
+
+fn post_action_message(action: PostOperationAction) -> &'static str {
+    match action {
+        PostOperationAction::Eject => "ejected, safe to unplug",
+        PostOperationAction::PowerOff => "powered off, safe to unplug",
+        PostOperationAction::Standby => "spun down to standby",
+    }
+}
+
+/// Read-only filesystem operations for hashing a device
+fn open_checksum_ops(
+    device: &moses_core::Device,
+    fs_type: Option<&str>,
+) -> Result<Box<dyn moses_filesystems::FilesystemOps>, moses_core::MosesError> {
+    use moses_filesystems::{register_all_filesystems, FilesystemOpsRegistry};
+    
+    let mut ops_registry = FilesystemOpsRegistry::new();
+    register_all_filesystems(&mut ops_registry, false);
+    let fs = ops_registry.create_ops(device, fs_type)?;
+    for warning in fs.warnings() {
+        eprintln!("{}", progress::warning(&warning));
+    }
+    Ok(fs)
+}
+
+/// Filesystem operations for a `moses mount` source
+fn create_mount_ops(
+    source: &moses_filesystems::MountSource,
+    fs_type: Option<&str>,
+    readonly: bool,
+) -> Result<Box<dyn moses_filesystems::FilesystemOps>, moses_core::MosesError> {
+    use moses_filesystems::{register_all_filesystems, FilesystemOps, FilesystemOpsRegistry, HostFolderOps, MountSource, NtfsOps, SubfolderOps};
+    
+    let mut ops_registry = FilesystemOpsRegistry::new();
+    register_all_filesystems(&mut ops_registry, !readonly);
+    match source {
+        MountSource::Device(device) => ops_registry.create_ops(device, fs_type),
+        MountSource::DevicePath { device, base_path } => {
+            let inner_ops = ops_registry.create_ops(device, fs_type)?;
+            SubfolderOps::new(inner_ops, device, base_path.clone())
+                .map(|ops| Box::new(ops) as Box<dyn moses_filesystems::FilesystemOps>)
+        }
+        MountSource::HostPath(path) => HostFolderOps::new(path.clone())
+            .map(|ops| Box::new(ops) as Box<dyn moses_filesystems::FilesystemOps>),
+        MountSource::NtfsSnapshot { device, snapshot_id } => {
+            let mut ops = NtfsOps::snapshot(snapshot_id.clone());
+            ops.init(device)?;
+            Ok(Box::new(ops))
+        }
+    }
+}
+
+#[cfg(any(feature = "mount-windows", feature = "mount-unix"))]
+fn print_mount_event(event: &moses_filesystems::mount::MountEvent) {
+    use moses_filesystems::mount::MountEvent;
+    match event {
+        MountEvent::Lost { mount_point, reason, .. } => {
+            eprintln!("{}", progress::warning(&format!("Mount at {} lost: {}", mount_point.display(), reason)));
+        }
+        MountEvent::Remounted { mount_point, attempt } => {
+            println!("{}", progress::success(&format!("Remounted {} (attempt {})", mount_point.display(), attempt)));
+        }
+        MountEvent::RemountFailed { mount_point, attempt, error } => {
+            eprintln!("{}", progress::warning(&format!("Remount of {} failed (attempt {}): {}", mount_point.display(), attempt, error)));
+        }
+        MountEvent::GaveUp { mount_point, reason } => {
+            eprintln!("{}", progress::error(&format!("Mount at {} stays down: {}", mount_point.display(), reason)));
+        }
+    }
+}
+
+/// `moses fat check`: report the dirty marks of a FAT volume, look for damage and, when
+/// there is none, clear the marks if `mark_clean` or the user agrees. Returns whether the
+/// volume is clean afterwards.
+fn fat_check(
+    device: &moses_core::Device,
+    mark_clean: bool,
+    no_act: bool,
+    undo_file: Option<std::path::PathBuf>,
+) -> anyhow::Result<bool> {
+    use moses_filesystems::families::fat::common::dirty::{self, FatDirtyState};
+    use std::io::{self, BufRead, IsTerminal};
+    
+    let state = FatDirtyState::read_device(device)?;
+    if !state.is_dirty() {
+        println!("{} volume on {} is marked clean", state.filesystem, device.name);
+    }
+    for warning in state.warnings().iter().skip(1).filter(|warning| !warning.contains("moses fat check")) {
+        println!("{}", progress::warning(warning));
+    }
+    
+    if let Some(mismatch) = moses_filesystems::families::fat::common::label::read_device(device)?.mismatch() {
+        println!("{}", progress::warning(&mismatch));
+    }
+    
+    let problems = dirty::check_device(device)?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        println!("{}", progress::warning(&format!(
+            "Found {} problem(s) that Moses cannot repair; run chkdsk /f on Windows or fsck.fat / fsck.exfat on Linux",
+            problems.len(),
+        )));
+        return Ok(false);
+    }
+    println!("{}", progress::success("The FAT copies agree and every cluster chain stays on the volume"));
+    if !state.is_dirty() {
+        return Ok(true);
+    }
+    if no_act {
+        return Ok(false);
+    }
+    if !mark_clean {
+        if !io::stdin().is_terminal() {
+            println!("Run again with --mark-clean to clear the marks");
+            return Ok(false);
+        }
+        println!("Mark {} clean so it can be written to again? [y/N] ", device.name);
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        if !line.trim().eq_ignore_ascii_case("y") {
+            return Ok(false);
+        }
+    }
+    
+    let patch = dirty::plan_clean_device(device)?;
+    let undo_path = undo_file.unwrap_or_else(|| {
+        let name: String = device.name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        std::path::PathBuf::from(format!("moses-fat-clean-{}.json", name))
+    });
+    for line in patch.diff() {
+        println!("  {}", line);
+    }
+    dirty::apply_device(device, &patch, Some(&undo_path))?;
+    println!("{}", progress::success(&format!("Marked {} clean", device.name)));
+    println!("Undo record saved to {} (undo with --undo {})", undo_path.display(), undo_path.display());
+    Ok(true)
+}
+
+/// Find a device by id or name, falling back to treating the argument as a disk image file
+async fn resolve_device(manager: &PlatformDeviceManager, device: &str) -> anyhow::Result<moses_core::Device> {
+    let devices = manager.enumerate_devices().await?;
+    if let Some(found) = devices.iter().find(|d| d.id == device || d.name.contains(device)) {
+        return Ok(found.clone());
+    }
+    
+    let path = std::path::Path::new(device);
+    if path.is_file() {
+        return Ok(moses_core::Device::image_file(path)?);
+    }
+    
+    Err(anyhow::anyhow!("Device not found: {}", device))
+}
+
+/// Resolve a source argument (`device:<id>[@part(N)][:/path]`, `image:<file>`,
+/// `host:<path>` or the older bare forms) to what a command reads
+async fn resolve_source(manager: &PlatformDeviceManager, source: &str) -> anyhow::Result<moses_filesystems::MountSource> {
+    use moses_core::{SourceKind, SourceUri};
+    use moses_filesystems::MountSource;
+    
+    let uri = SourceUri::parse(source)?;
+    let device = match &uri.kind {
+        SourceKind::Host(path) if path.is_dir() => return Ok(MountSource::HostPath(path.clone())),
+        SourceKind::Host(path) => return Err(anyhow::anyhow!("Not a folder: {}", path.display())),
+        SourceKind::Device(id) => resolve_device(manager, id).await?,
+        SourceKind::Image(file) => moses_core::Device::image_file(file)?,
+    };
+    let device = match uri.partition {
+        None => device,
+        Some(_) if matches!(uri.kind, SourceKind::Image(_)) => return Err(anyhow::anyhow!(
+            "Partitions inside image files cannot be opened directly; attach the image with \
+             `losetup -P` (Linux) or `hdiutil attach` (macOS) and name the partition device",
+        )),
+        Some(number) => partition_device(manager, &device, number).await?,
+    };
+    Ok(if uri.is_root() {
+        MountSource::Device(device)
+    } else {
+        MountSource::DevicePath { device, base_path: std::path::PathBuf::from(&uri.path) }
+    })
+}
+
+/// Partition `number` (from 1) of a whole disk, as a device of its own
+async fn partition_device(manager: &PlatformDeviceManager, disk: &moses_core::Device, number: u32) -> anyhow::Result<moses_core::Device> {
+    let id = moses_core::partition_id(&disk.id, number, moses_core::PathStyle::current())
+        .ok_or_else(|| anyhow::anyhow!("{} is a volume, not a whole disk; name the disk to pick a partition", disk.id))?;
+    let partitions = manager.get_device_info(disk).await?.partitions;
+    let partition = partitions.iter()
+        .find(|partition| partition.id == id)
+        .or_else(|| partitions.get(number as usize - 1))
+        .ok_or_else(|| anyhow::anyhow!("{} has no partition {} ({} found)", disk.name, number, partitions.len()))?;
+    Ok(moses_core::Device {
+        id,
+        name: format!("{} partition {}", disk.name, number),
+        size: partition.size,
+        filesystem: partition.filesystem.clone(),
+        mount_points: partition.mount_point.iter().cloned().collect(),
+        // 0 means the platform could not tell where the partition starts
+        partition_offset: Some(partition.start_offset).filter(|&offset| offset > 0),
+        ..disk.clone()
+    })
+}
+
+/// A source that must name a device or image rather than a host folder; also returns the
+/// path inside the volume
+async fn resolve_device_source(manager: &PlatformDeviceManager, source: &str) -> anyhow::Result<(moses_core::Device, String)> {
+    use moses_filesystems::MountSource;
+    
+    match resolve_source(manager, source).await? {
+        MountSource::Device(device) => Ok((device, "/".to_string())),
+        MountSource::DevicePath { device, base_path } => Ok((device, base_path.to_string_lossy().into_owned())),
+        _ => Err(anyhow::anyhow!("{} is a host folder; name a device or image", source)),
+    }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn main() -> anyhow::Result<()> {
+    // Answer shell completion requests before anything else
+    clap_complete::CompleteEnv::with_factory(Cli::command).complete();
+    
+    // Swap in a build staged by `moses update` and run that instead
+    if let Some(update) = moses_filesystems::update::apply_staged_on_start(moses_filesystems::update::Component::Cli, env!("CARGO_PKG_VERSION")) {
+        eprintln!("Updated to Moses {}", update.version);
+        let status = std::process::Command::new(&update.target).args(std::env::args_os().skip(1)).status()?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    
+    // Size the runtime from the user's concurrency settings instead of one thread per CPU
+    let runtime = MosesConfig::global().concurrency.runtime()?;
+    runtime.block_on(run(Cli::parse()))
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    progress::init(cli.quiet, cli.no_color);
+    
     
     // Initialize formatter registry
     let mut registry = FormatterRegistry::new();
@@ -71,6 +1203,7 @@ async fn main() -> anyhow::Result<()> {
             let manager = PlatformDeviceManager;
             match manager.enumerate_devices().await {
                 Ok(devices) => {
+                    completion::cache_devices(&devices);
                     if devices.is_empty() {
                         println!("No devices found.");
                     } else {
@@ -82,6 +1215,12 @@ async fn main() -> anyhow::Result<()> {
                             println!("  Type: {:?}", device.device_type);
                             println!("  Removable: {}", if device.is_removable { "Yes" } else { "No" });
                             println!("  System: {}", if device.is_system { "Yes (⚠️ PROTECTED)" } else { "No" });
+                            if device.is_write_protected {
+                                println!("  Write-protected: Yes");
+                            }
+                            if let Some(filesystem) = device_filesystem(&device) {
+                                println!("  Filesystem: {}", filesystem);
+                            }
                             if !device.mount_points.is_empty() {
                                 println!("  Mounted at: {:?}", device.mount_points);
                             }
@@ -94,19 +1233,168 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        Commands::Format { device, filesystem } => {
+        Commands::Info { device, restore_layout } => {
+            use moses_core::DeviceHistory;
+            
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &device).await?;
+            if path != "/" {
+                return Err(anyhow::anyhow!("moses info describes a whole device or partition, not {}", path));
+            }
+            let history = DeviceHistory::global().entries(&target_device);
+            
+            println!("Device: {}", target_device.name);
+            println!("  Path: {}", target_device.id);
+            println!("  Size: {:.2} GB", target_device.size as f64 / 1_073_741_824.0);
+            if let Some(serial) = &target_device.serial {
+                println!("  Serial: {}", serial);
+            }
+            if let Some(filesystem) = device_filesystem(&target_device) {
+                println!("  Filesystem: {}", filesystem);
+            }
+            match history.first() {
+                Some(last) => {
+                    println!("  Last changed by Moses: {} ({})", last.at.format("%Y-%m-%d %H:%M UTC"), last.operation);
+                    println!("  Before that: {}", last.before.summary());
+                }
+                None => println!("  Moses has no history for this drive"),
+            }
+            if history.len() > 1 {
+                println!("\nHistory (newest first):");
+                for entry in &history {
+                    println!("  {}  {:<24} was {}", entry.at.format("%Y-%m-%d %H:%M"), entry.operation, entry.before.summary());
+                }
+            }
+            
+            if restore_layout {
+                let Some(entry) = history.iter().find(|entry| entry.before.table_sectors.is_some()) else {
+                    eprintln!("\nNo saved partition table for this drive.");
+                    return Ok(());
+                };
+                println!("\n{}", progress::warning(&format!(
+                    "This rewrites the partition table of {} as it was before \"{}\" on {}:",
+                    target_device.name, entry.operation, entry.at.format("%Y-%m-%d %H:%M UTC"),
+                )));
+                for partition in &entry.before.partitions {
+                    println!("  Partition {}: {} bytes at offset {} ({})", partition.number, partition.size,
+                        partition.start_offset, partition.filesystem.as_deref().unwrap_or("unknown"));
+                }
+                println!("Data written since then is not recovered. Type 'yes' to continue: ");
+                
+                use std::io::{self, BufRead};
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+                if line.trim() != "yes" {
+                    println!("Restore cancelled.");
+                    return Ok(());
+                }
+                match moses_filesystems::disk_manager::history::restore_layout(&target_device, &entry.before) {
+                    Ok(()) => println!("{}", progress::success("Partition table restored")),
+                    Err(e) => eprintln!("{}", progress::error(&format!("Restore failed: {}", e))),
+                }
+            }
+        }
+        Commands::Format { device, interactive, image, size, filesystem, acknowledge_members, after, strategy, preset, flash_journal, keep, staging, preserve_serial, seed, timestamp, post_step, volume_icon } => {
             // Check if formatter is available
-            let formatter = registry.get_formatter(&filesystem)
-                .ok_or_else(|| anyhow::anyhow!("Unknown filesystem type: '{}'. Use 'moses list-formats' to see available formats.", filesystem))?;
+            if !registry.is_supported(&filesystem) {
+                return Err(anyhow::anyhow!("Unknown filesystem type: '{}'. Use 'moses list-formats' to see available formats.", filesystem));
+            }
+            
+            // Create format options
+            let mut options = moses_core::FormatOptions {
+                filesystem_type: filesystem.clone(),
+                label: Some("MOSES_TEST".to_string()),
+                quick_format: true,
+                cluster_size: None,
+                enable_compression: false,
+                verify_after_format: false,
+                dry_run: false,
+                force: false,
+                additional_options: std::collections::HashMap::new(),
+            };
+            if !acknowledge_members.is_empty() {
+                options.additional_options.insert(
+                    moses_filesystems::disk_manager::ACKNOWLEDGE_MEMBERS_OPTION.to_string(),
+                    acknowledge_members.join(","),
+                );
+            }
+            if let Some(action) = after {
+                options.additional_options.insert(
+                    PostOperationAction::OPTION_KEY.to_string(),
+                    action.as_str().to_string(),
+                );
+            }
+            options.additional_options.insert(FormatPreset::OPTION_KEY.to_string(), preset.as_str().to_string());
+            if let Some(journal) = flash_journal {
+                options.additional_options.insert(FlashJournal::OPTION_KEY.to_string(), journal.as_str().to_string());
+            }
+            if preserve_serial {
+                options.additional_options.insert(
+                    moses_filesystems::volume_serial::PRESERVE_SERIAL_OPTION.to_string(),
+                    "true".to_string(),
+                );
+            }
+            if let Some(seed) = seed {
+                options.additional_options.insert(moses_filesystems::deterministic::SEED_OPTION.to_string(), seed);
+            }
+            if let Some(timestamp) = timestamp {
+                options.additional_options.insert(moses_filesystems::deterministic::TIME_OPTION.to_string(), timestamp.to_string());
+            }
+            if !post_step.is_empty() {
+                options.additional_options.insert(seeding::STEPS_OPTION.to_string(), post_step.join(","));
+            }
+            if let Some(icon) = volume_icon {
+                options.additional_options.insert(seeding::ICON_OPTION.to_string(), icon.display().to_string());
+            }
+            let seed_steps = SeedStep::from_options(&options)?;
             
+            // No drive: the built-in formatter writes into a new file
+            if let Some(image) = image {
+                let size = size.expect("clap requires --size with --image");
+                let formatter = registry.get_formatter(&filesystem)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown filesystem type: '{}'", filesystem))?;
+                if let Some(step) = seed_steps.iter().find(|step| step.writes_files()) {
+                    anyhow::bail!("The {} post-format step writes to a mounted volume, which an image file is not", step.as_str());
+                }
+                let result = progress::with_spinner(
+                    &format!("Writing a {:.2} MB {} image to {}", size as f64 / (1024.0 * 1024.0), filesystem.to_uppercase(), image.display()),
+                    moses_filesystems::image_target::format_image(formatter.as_ref(), &image, size, &options),
+                ).await;
+                match result {
+                    Ok(_) => println!("{}", progress::success(&format!("Image written to {}", image.display()))),
+                    Err(e) => eprintln!("{}", progress::error(&format!("Format failed: {}", e))),
+                }
+                return Ok(());
+            }
             // Get the device manager
             let manager = PlatformDeviceManager;
             
-            // Find the specified device
+            // Find the specified device; the guided mode offers a list when none was named
             let devices = manager.enumerate_devices().await?;
-            let target_device = devices.iter()
-                .find(|d| d.id == device || d.name.contains(&device))
-                .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device))?;
+            let chosen = match &device {
+                Some(device) => devices.iter()
+                    .find(|d| &d.id == device || d.name.contains(device.as_str()))
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device))?,
+                None => match guided::choose_device(&devices)? {
+                    Some(device) => device,
+                    None => {
+                        println!("Format cancelled.");
+                        return Ok(());
+                    }
+                },
+            };
+            let target_device = &chosen;
+            
+            // Native or system-tool implementation, depending on the strategy and the device
+            let selected = registry.select(&filesystem, target_device, strategy)
+                .ok_or_else(|| anyhow::anyhow!("Unknown filesystem type: '{}'", filesystem))?;
+            let formatter = selected.formatter.clone();
+            if preserve_serial && selected.system {
+                eprintln!("Error: the system {} formatter picks its own volume serial", filesystem);
+                eprintln!("  Re-run with --strategy prefer-native to keep the serial.");
+                return Ok(());
+            }
             
             // Safety check
             if target_device.is_system {
@@ -114,6 +1402,11 @@ async fn main() -> anyhow::Result<()> {
                 return Ok(());
             }
             
+            if let Err(e) = target_device.ensure_writable() {
+                eprintln!("Error: {}", e);
+                return Ok(());
+            }
+            
             // Check if formatter can handle this device
             if !formatter.can_format(target_device) {
                 eprintln!("Error: {} formatter cannot format this device", filesystem);
@@ -132,9 +1425,13 @@ async fn main() -> anyhow::Result<()> {
                 return Ok(());
             }
             
-            println!("Target device: {}", target_device.name);
-            println!("  Size: {:.2} GB", target_device.size as f64 / 1_073_741_824.0);
-            println!("  Type: {:?}", target_device.device_type);
+            if interactive {
+                guided::print_device_details(target_device, &devices);
+            } else {
+                println!("Target device: {}", target_device.name);
+                println!("  Size: {:.2} GB", target_device.size as f64 / 1_073_741_824.0);
+                println!("  Type: {:?}", target_device.device_type);
+            }
             
             // Show formatter info
             if let Some(meta) = registry.get_metadata(&filesystem) {
@@ -144,56 +1441,235 @@ async fn main() -> anyhow::Result<()> {
             }
             println!();
             
-            // Create format options
-            let options = moses_core::FormatOptions {
-                filesystem_type: filesystem.clone(),
-                label: Some("MOSES_TEST".to_string()),
-                quick_format: true,
-                cluster_size: None,
-                enable_compression: false,
-                verify_after_format: false,
-                dry_run: false,
-                force: false,
-                additional_options: std::collections::HashMap::new(),
-            };
+            // Formatting one member of a striped/spanned volume breaks all the others
+            if let Err(e) = moses_filesystems::disk_manager::ConflictDetector::verify_multi_device_acknowledged(
+                target_device, &devices, &options,
+            ) {
+                eprintln!("Error: {}", e);
+                eprintln!("  Re-run with --acknowledge-members <disks> to format anyway.");
+                return Ok(());
+            }
             
             // Run dry run first
             println!("Running simulation...");
-            let simulation = formatter.dry_run(target_device, &options).await?;
+            let mut simulation = formatter.dry_run(target_device, &options).await?;
+            simulation.strategy = Some(selected.describe());
+            simulation.warnings.extend(moses_filesystems::disk_manager::installations::installation_warnings(target_device));
+            simulation.contents = moses_filesystems::disk_manager::contents::summarize_contents(target_device);
+            simulation.lints.extend(moses_filesystems::lints::lint(target_device, &options));
+            moses_filesystems::disk_manager::plan_format(&mut simulation, false);
             
-            println!("\nSimulation Report:");
-            println!("  Estimated time: {:?}", simulation.estimated_time);
-            if !simulation.required_tools.is_empty() {
-                println!("  Required tools: {:?}", simulation.required_tools);
+            let mut pager = guided::Pager::new(interactive);
+            pager.line("\nSimulation Report:");
+            if let Some(strategy) = &simulation.strategy {
+                pager.line(format!("  Implementation: {}", strategy));
             }
-            if !simulation.warnings.is_empty() {
-                println!("  Warnings:");
-                for warning in &simulation.warnings {
-                    println!("    - {}", warning);
+            if preserve_serial {
+                match moses_filesystems::volume_serial::read_volume_serial(target_device) {
+                    Ok(Some(serial)) => pager.line(format!("  Volume serial: {} (kept)", serial.display())),
+                    _ => pager.line("  Volume serial: none found to keep; the format will stop before writing"),
+                }
+            }
+            pager.line(format!("  Estimated time: {:?}", simulation.total_time()));
+            pager.line("  Steps:");
+            for (number, step) in simulation.steps.iter().enumerate() {
+                pager.line(format!("    {}. {} (~{}s)", number + 1, step.description, step.estimated_time.as_secs().max(1)));
+            }
+            if !simulation.required_tools.is_empty() {
+                pager.line(format!("  Required tools: {:?}", simulation.required_tools));
+            }
+            if !simulation.warnings.is_empty() {
+                pager.line("  Warnings:");
+                for warning in &simulation.warnings {
+                    pager.line(format!("    - {}", warning));
+                }
+            }
+            for contents in &simulation.contents {
+                pager.line(format!("  Currently on {} ({}): {} files, {:.2} MB{}",
+                    contents.location, contents.filesystem, contents.total_files,
+                    contents.total_bytes as f64 / (1024.0 * 1024.0),
+                    if contents.complete { "" } else { " or more" }));
+                for dir in &contents.directories {
+                    pager.line(format!("    {:<30} {:>8} files {:>12.2} MB", dir.path, dir.files, dir.bytes as f64 / (1024.0 * 1024.0)));
+                }
+                for trash in &contents.trash {
+                    pager.line(format!("    {:<30} {:>8} files {:>12.2} MB (deleted items; `moses trash --purge` frees them)",
+                        trash.path, trash.files, trash.bytes as f64 / (1024.0 * 1024.0)));
+                }
+                if !contents.largest_files.is_empty() {
+                    pager.line("    Largest files:");
+                    for file in &contents.largest_files {
+                        pager.line(format!("      {:>12.2} MB  {}", file.size as f64 / (1024.0 * 1024.0), file.path));
+                    }
+                }
+            }
+            let selective = (!keep.is_empty()).then(|| SelectiveFormatRequest {
+                options: options.clone(),
+                keep: keep.clone(),
+                staging: staging.clone().unwrap_or_else(std::env::temp_dir),
+            });
+            if let Some(request) = &selective {
+                let check = selective::check(target_device, formatter.as_ref(), request).await;
+                pager.line(format!("  Kept across the format: {} files, {:.2} MB, staged in {}",
+                    check.kept.files, check.kept.bytes as f64 / (1024.0 * 1024.0), request.staging.display()));
+                if !check.problems.is_empty() {
+                    for problem in &check.problems {
+                        eprintln!("{}", progress::error(&format!("    {}", problem)));
+                    }
+                    eprintln!("Error: the kept items cannot be carried across this format");
+                    return Ok(());
+                }
+            }
+            if !simulation.lints.is_empty() {
+                pager.line("  Pre-flight checklist:");
+                for lint in &simulation.lints {
+                    let line = format!("    [{:?}] {} ({})", lint.severity, lint.message, lint.code);
+                    pager.line(match lint.severity {
+                        moses_core::LintSeverity::Error => progress::error(&line),
+                        moses_core::LintSeverity::Warning => progress::warning(&line),
+                        moses_core::LintSeverity::Info => line,
+                    });
+                    if let Some(fix) = &lint.fix {
+                        pager.line(format!("        fix: {}", fix));
+                    }
                 }
             }
             
-            println!("\nWARNING: This will ERASE ALL DATA on {}!", target_device.name);
-            println!("Type 'yes' to continue: ");
-            
-            use std::io::{self, BufRead};
-            let stdin = io::stdin();
-            let mut line = String::new();
-            stdin.lock().read_line(&mut line)?;
-            
-            if line.trim() != "yes" {
+            if !pager.finish() {
                 println!("Format cancelled.");
                 return Ok(());
             }
             
+            println!("\n{}", progress::warning(&format!("WARNING: This will ERASE ALL DATA on {}!", target_device.name)));
+            let confirmation = if interactive {
+                match guided::confirm_device(target_device)? {
+                    Some(confirmation) => format!("{} in the guided mode", confirmation),
+                    None => {
+                        println!("Format cancelled.");
+                        return Ok(());
+                    }
+                }
+            } else {
+                if guided::first_run() {
+                    println!("First format with Moses? `moses format --interactive` walks through it step by step.");
+                }
+                println!("Type 'yes' to continue: ");
+                
+                use std::io::{self, BufRead};
+                let stdin = io::stdin();
+                let mut line = String::new();
+                stdin.lock().read_line(&mut line)?;
+                
+                if line.trim() != "yes" {
+                    println!("Format cancelled.");
+                    return Ok(());
+                }
+                "typed yes".to_string()
+            };
+            let record = moses_core::AuditRecord::new(target_device, format!("format as {}", filesystem), confirmation);
+            if let Err(e) = moses_core::AuditLog::global().record(record) {
+                eprintln!("{}", progress::warning(&format!("Could not write the audit log: {}", e)));
+            }
+            
             println!("\nFormatting {} as {}...", target_device.name, filesystem.to_uppercase());
-            match formatter.format(target_device, &options).await {
-                Ok(_) => println!("Format completed successfully!"),
-                Err(e) => eprintln!("Format failed: {}", e),
+            let cache = FilesystemCache::global();
+            cache.invalidate(target_device);
+            moses_filesystems::disk_manager::history::record_before(target_device, format!("format as {}", filesystem));
+            let keep_awake = moses_platform::KeepAwake::acquire("Formatting a disk");
+            let started = std::time::Instant::now();
+            let result = match &selective {
+                Some(request) => progress::with_spinner(
+                    &format!("Formatting {} and restoring the kept items", target_device.name),
+                    selective::selective_format(
+                        target_device,
+                        formatter.as_ref(),
+                        request,
+                        &mut moses_platform::RemountTarget::new(PlatformDeviceManager, REMOUNT_TIMEOUT),
+                    ),
+                ).await.map(Some),
+                None => progress::with_spinner(
+                    &format!("Formatting {}", target_device.name),
+                    formatter.format(target_device, &options),
+                ).await.map(|()| None),
+            };
+            drop(keep_awake);
+            moses_core::LocalMetrics::global().record(moses_core::MetricRecord::new("format", target_device, &result, started.elapsed()));
+            let event = match &result {
+                Ok(_) => MosesEvent::FormatCompleted { device: target_device.clone(), filesystem: filesystem.clone() },
+                Err(e) => MosesEvent::Error {
+                    operation: "format".to_string(),
+                    device_id: Some(target_device.id.clone()),
+                    message: e.to_string(),
+                },
+            };
+            match result {
+                Ok(kept) => {
+                    println!("{}", progress::success("Format completed successfully!"));
+                    if let Some(report) = kept {
+                        print_selective_report(&report);
+                    }
+                    if let Some(note) = moses_filesystems::disk_manager::SignatureWiper::cleanup_after_format(target_device, &filesystem) {
+                        println!("{}", note);
+                    }
+                    cache.insert(target_device, 0, CachedFilesystemInfo::detected_now(filesystem.clone()));
+                    
+                    // The format itself succeeded, so failed seeding is only reported
+                    if seed_steps.iter().any(SeedStep::writes_files) {
+                        let mut target = moses_platform::RemountTarget::new(PlatformDeviceManager, REMOUNT_TIMEOUT);
+                        match seeding::seed_volume(target_device, &options, &mut target).await {
+                            Ok(written) => for path in written {
+                                println!("  Wrote {}", path.display());
+                            },
+                            Err(e) => eprintln!("{}", progress::warning(&format!("Post-format steps failed: {}", e))),
+                        }
+                    }
+                    
+                    if let Some(action) = after {
+                        match action.apply(&manager, target_device).await {
+                            Ok(()) => println!("{}: {}", target_device.name, post_action_message(action)),
+                            Err(e) => eprintln!("Post-format {} failed: {}", action.as_str(), e),
+                        }
+                    }
+                }
+                Err(e) => eprintln!("{}", progress::error(&format!("Format failed: {}", e))),
+            }
+            
+            // Let configured webhooks/MQTT brokers know how it went
+            for failure in EventDispatcher::new(MosesConfig::global().events.clone()).deliver(&event).await {
+                eprintln!("{}", progress::warning(&format!("Event not delivered: {}", failure)));
             }
         }
-        Commands::ListFormats { category } => {
+        Commands::Advise { device, goals } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            let advice = moses_filesystems::advisor::advise(&registry, &target_device, &goals);
+            print!("{}", advice.to_report());
+            if let Some(best) = &advice.recommended {
+                let preset = best.options.additional_options.get(FormatPreset::OPTION_KEY)
+                    .map(|preset| format!(" --preset {}", preset))
+                    .unwrap_or_default();
+                println!("
+Format with: moses format {} -f {}{}", device, best.filesystem, preset);
+            }
+        }
+        Commands::ListFormats { matrix: true, json, .. } => {
+            // The CLI formats in-process, so there is no worker to hand administrator work to
+            let matrix = moses_filesystems::capabilities::CapabilityMatrix::current(&moses_core::AvailabilityContext {
+                platform: moses_core::Platform::current(),
+                elevated: moses_platform::environment::is_elevated(),
+                worker_available: false,
+            });
+            if json {
+                println!("{}", serde_json::to_string_pretty(&matrix)?);
+            } else {
+                println!("{}", matrix.to_table());
+            }
+        }
+        Commands::ListFormats { category, .. } => {
             println!("Available Formatters:\n");
+            // Readers without a formatter, such as flash filesystems, are listed in their category too
+            let mut readers = moses_filesystems::FilesystemOpsRegistry::new();
+            moses_filesystems::register_all_filesystems(&mut readers, false);
             
             if let Some(cat_str) = category {
                 // Parse category
@@ -211,7 +1687,8 @@ async fn main() -> anyhow::Result<()> {
                 };
                 
                 let formatters = registry.list_by_category(cat.clone());
-                if formatters.is_empty() {
+                let read_only = readers.list_by_category(&cat);
+                if formatters.is_empty() && read_only.is_empty() {
                     println!("No formatters found in category: {:?}", cat);
                 } else {
                     for (name, meta) in formatters {
@@ -220,6 +1697,9 @@ async fn main() -> anyhow::Result<()> {
                             println!("    Aliases: {:?}", meta.aliases);
                         }
                     }
+                    for name in read_only {
+                        println!("  {} - read only (browse, mount, export)", name);
+                    }
                 }
             } else {
                 // List all formatters by category
@@ -234,7 +1714,8 @@ async fn main() -> anyhow::Result<()> {
                 
                 for cat in categories {
                     let formatters = registry.list_by_category(cat.clone());
-                    if !formatters.is_empty() {
+                    let read_only = readers.list_by_category(&cat);
+                    if !formatters.is_empty() || !read_only.is_empty() {
                         println!("{:?}:", cat);
                         for (name, meta) in formatters {
                             println!("  {} - {}", name, meta.description);
@@ -242,6 +1723,9 @@ async fn main() -> anyhow::Result<()> {
                                 println!("    Aliases: {:?}", meta.aliases);
                             }
                         }
+                        for name in read_only {
+                            println!("  {} - read only (browse, mount, export)", name);
+                        }
                         println!();
                     }
                 }
@@ -257,78 +1741,21 @@ async fn main() -> anyhow::Result<()> {
                 eprintln!("Use 'moses list-formats' to see available formatters.");
             }
         }
-        Commands::Mount { source, target, fs_type, readonly } => {
+        Commands::Mount { source, target, fs_type, readonly, follow_links, snapshot, kernel } => {
             println!("🔧 Moses Mount - Universal Filesystem Access");
             println!("================================================");
             
-            use moses_filesystems::{MountSource, HostFolderOps, SubfolderOps, FilesystemOpsRegistry, register_all_filesystems};
-            use std::path::PathBuf;
-            
-            // Intelligently determine what we're mounting
-            let mount_source = if source.contains(':') && !source.starts_with('/') {
-                // Windows drive letter (E:) or device with path (E:\Users)
-                if source.len() == 2 && source.ends_with(':') {
-                    // Just a drive letter like "E:"
-                    let manager = PlatformDeviceManager;
-                    let devices = manager.enumerate_devices().await?;
-                    let device = devices.iter()
-                        .find(|d| d.id == source || d.name.contains(&source))
-                        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", source))?;
-                    MountSource::Device(device.clone())
-                } else {
-                    // Path like "E:\Users" - treat as host folder on Windows
-                    let path = PathBuf::from(&source);
-                    if path.exists() {
-                        MountSource::HostPath(path)
-                    } else {
-                        return Err(anyhow::anyhow!("Path does not exist: {}", source));
-                    }
-                }
-            } else if source.starts_with('/') {
-                // Unix-style path
-                let path = PathBuf::from(&source);
-                if path.exists() && path.is_dir() {
-                    // It's a local directory
-                    MountSource::HostPath(path)
-                } else if source.contains(':') {
-                    // Format: /dev/sdb1:/home/user
-                    let parts: Vec<&str> = source.splitn(2, ':').collect();
-                    if parts.len() == 2 {
-                        let manager = PlatformDeviceManager;
-                        let devices = manager.enumerate_devices().await?;
-                        let device = devices.iter()
-                            .find(|d| d.id == parts[0])
-                            .ok_or_else(|| anyhow::anyhow!("Device not found: {}", parts[0]))?;
-                        MountSource::DevicePath {
-                            device: device.clone(),
-                            base_path: PathBuf::from(parts[1]),
-                        }
-                    } else {
-                        // Try as device
-                        let manager = PlatformDeviceManager;
-                        let devices = manager.enumerate_devices().await?;
-                        let device = devices.iter()
-                            .find(|d| d.id == source)
-                            .ok_or_else(|| anyhow::anyhow!("Device not found: {}", source))?;
-                        MountSource::Device(device.clone())
-                    }
-                } else {
-                    // Assume it's a device path
-                    let manager = PlatformDeviceManager;
-                    let devices = manager.enumerate_devices().await?;
-                    let device = devices.iter()
-                        .find(|d| d.id == source || d.name.contains(&source))
-                        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", source))?;
-                    MountSource::Device(device.clone())
-                }
-            } else {
-                // Try to find as a device name
-                let manager = PlatformDeviceManager;
-                let devices = manager.enumerate_devices().await?;
-                let device = devices.iter()
-                    .find(|d| d.name.contains(&source))
-                    .ok_or_else(|| anyhow::anyhow!("Source not found: {}", source))?;
-                MountSource::Device(device.clone())
+            use moses_filesystems::MountSource;
+            
+            let manager = PlatformDeviceManager;
+            let mount_source = resolve_source(&manager, &source).await?;
+            
+            // Shadow copies are history: they can be browsed but never written
+            let readonly = readonly || snapshot.is_some();
+            let mount_source = match (snapshot, mount_source) {
+                (None, source) => source,
+                (Some(snapshot_id), MountSource::Device(device)) => MountSource::NtfsSnapshot { device, snapshot_id },
+                (Some(_), _) => return Err(anyhow::anyhow!("--snapshot needs a whole NTFS volume as the source")),
             };
             
             // Display what we're mounting
@@ -342,40 +1769,45 @@ async fn main() -> anyhow::Result<()> {
                 MountSource::HostPath(path) => {
                     println!("Source: {} (host folder)", path.display());
                 }
+                MountSource::NtfsSnapshot { device, snapshot_id } => {
+                    println!("Source: {} (shadow copy {}, read-only)", device.name, snapshot_id);
+                }
             }
             println!("Target: {}", target);
             
             // Create filesystem operations based on mount source
-            let ops_result = match mount_source {
-                MountSource::Device(ref device) => {
-                    // Standard device mounting
-                    let mut ops_registry = FilesystemOpsRegistry::new();
-                    register_all_filesystems(&mut ops_registry, !readonly);
-                    ops_registry.create_ops(device, fs_type.as_deref())
-                }
-                MountSource::DevicePath { ref device, ref base_path } => {
-                    // Mount subfolder from device
-                    let mut ops_registry = FilesystemOpsRegistry::new();
-                    register_all_filesystems(&mut ops_registry, !readonly);
-                    match ops_registry.create_ops(device, fs_type.as_deref()) {
-                        Ok(inner_ops) => {
-                            SubfolderOps::new(inner_ops, device, base_path.clone())
-                                .map(|ops| Box::new(ops) as Box<dyn moses_filesystems::FilesystemOps>)
-                        }
-                        Err(e) => Err(e)
-                    }
-                }
-                MountSource::HostPath(ref path) => {
-                    // Mount host folder
-                    HostFolderOps::new(path.clone())
-                        .map(|ops| Box::new(ops) as Box<dyn moses_filesystems::FilesystemOps>)
-                }
-            };
+            let fs_type_arg = fs_type.clone();
+            let ops_result = create_mount_ops(&mount_source, fs_type_arg.as_deref(), readonly);
             
             match ops_result {
                 Ok(ops) => {
-                    let fs_type = ops.filesystem_type();
+                    let fs_type = ops.filesystem_type().to_string();
                     println!("Detected filesystem: {}", fs_type);
+                    for warning in ops.warnings() {
+                        println!("{}", progress::warning(&warning));
+                    }
+                    
+                    // A real kernel mount where the kernel has a driver, FUSE otherwise
+                    #[cfg(target_os = "linux")]
+                    if kernel {
+                        use moses_filesystems::kernel_mount::KernelMountPlan;
+                        match &mount_source {
+                            MountSource::Device(device) => match KernelMountPlan::new(device, &fs_type, std::path::Path::new(&target), readonly) {
+                                Ok(plan) => {
+                                    let mounted = plan.mount()?;
+                                    println!("\n✅ Mounted {} ({}) at {} with the kernel's {} driver", source, mounted, target, plan.kernel_type);
+                                    println!("The mount stays after this command exits; release it with `moses unmount {}`", target);
+                                    return Ok(());
+                                }
+                                Err(e) => println!("{}", progress::warning(&format!("{}; mounting through FUSE instead", e))),
+                            },
+                            _ => println!("{}", progress::warning("--kernel mounts whole devices and images only; mounting through FUSE instead")),
+                        }
+                    }
+                    #[cfg(not(target_os = "linux"))]
+                    if kernel {
+                        println!("{}", progress::warning("Kernel mounts are only available on Linux; mounting through FUSE instead"));
+                    }
                     
                     // Try to actually mount if the feature is available
                     #[cfg(any(feature = "mount-windows", feature = "mount-unix"))]
@@ -383,11 +1815,16 @@ async fn main() -> anyhow::Result<()> {
                         println!("\nAttempting to mount filesystem...");
                         
                         match get_mount_provider() {
-                            Ok(mut provider) => {
+                            Ok(provider) => {
                                 let mount_opts = MountOptions {
                                     readonly,
                                     mount_point: target.clone(),
-                                    filesystem_type: fs_type.clone(),
+                                    filesystem_type: Some(fs_type.clone()),
+                                    links: if follow_links {
+                                        moses_filesystems::LinkPolicy::Follow
+                                    } else {
+                                        moses_filesystems::LinkPolicy::Expose
+                                    },
                                     ..Default::default()
                                 };
                                 
@@ -395,6 +1832,7 @@ async fn main() -> anyhow::Result<()> {
                                 let mount_device = match &mount_source {
                                     MountSource::Device(device) => device.clone(),
                                     MountSource::DevicePath { device, .. } => device.clone(),
+                                    MountSource::NtfsSnapshot { device, .. } => device.clone(),
                                     MountSource::HostPath(path) => {
                                         // Create a virtual device for host path mounting
                                         moses_core::Device {
@@ -404,43 +1842,68 @@ async fn main() -> anyhow::Result<()> {
                                                 .to_string(),
                                             id: path.to_string_lossy().to_string(),
                                             size: 0, // Would need platform-specific code
-                                            device_type: moses_core::DeviceType::Fixed,
+                                            device_type: moses_core::DeviceType::Virtual,
                                             is_removable: false,
                                             is_system: false,
+                                            is_write_protected: false,
+                                            serial: None,
+                                            erase_block_size: None,
+                                            partition_offset: None,
                                             mount_points: vec![],
-                                            partitions: vec![],
+                                            filesystem: None,
                                         }
                                     }
                                 };
                                 
-                                match provider.mount(&mount_device, ops, &mount_opts) {
+                                // The watchdog remounts read-only mounts whose session dies
+                                let mut watchdog = moses_filesystems::mount::MountWatchdog::new(provider);
+                                let factory_source = mount_source.clone();
+                                let factory_fs_type = fs_type_arg.clone();
+                                let factory: moses_filesystems::mount::OpsFactory = Box::new(move || {
+                                    create_mount_ops(&factory_source, factory_fs_type.as_deref(), readonly)
+                                });
+                                match watchdog.mount(&mount_device, ops, &mount_opts, factory) {
                                     Ok(()) => {
                                         println!("\n✅ Successfully mounted {} at {}", source, target);
                                         println!("\nYou can now:");
                                         println!("  - Browse {} files in Windows Explorer", fs_type);
                                         println!("  - Use any Windows application to read the files");
                                         println!("  - Access the filesystem as if it were native!");
-                                        println!("\nTo unmount, run: moses unmount {}", target);
+                                        println!("\nThe mount stays up while this command runs; press Ctrl+C to unmount.");
+                                        
+                                        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(5));
+                                        loop {
+                                            tokio::select! {
+                                                _ = tokio::signal::ctrl_c() => break,
+                                                _ = ticker.tick() => {}
+                                            }
+                                            for event in watchdog.check() {
+                                                print_mount_event(&event);
+                                            }
+                                        }
+                                        for (mount_point, e) in watchdog.unmount_all() {
+                                            eprintln!("{}", progress::error(&format!("Failed to unmount {}: {}", mount_point.display(), e)));
+                                        }
+                                        println!("Unmounted {}", target);
                                     }
                                     Err(e) => {
                                         eprintln!("\n❌ Failed to mount: {}", e);
                                         eprintln!("\nMake sure:");
-                                        eprintln!("  1. WinFsp is installed (http://www.secfs.net/winfsp/)");
-                                        eprintln!("  2. You're running as administrator");
-                                        eprintln!("  3. The mount point {} is available", target);
+                                        eprintln!("  1. You're running as administrator");
+                                        eprintln!("  2. The mount point {} is available", target);
+                                        eprintln!("\nRun `moses doctor` to check the mount driver and permissions.");
                                     }
                                 }
                             }
                             Err(e) => {
                                 eprintln!("\n❌ Mount provider not available: {}", e);
-                                eprintln!("\nInstall WinFsp from: http://www.secfs.net/winfsp/");
                             }
                         }
                     }
                     
                     #[cfg(not(any(feature = "mount-windows", feature = "mount-unix")))]
                     {
-                        let _ = readonly;  // Unused in preview mode
+                        let _ = (readonly, follow_links);  // Unused in preview mode
                         // Get filesystem info for preview
                         if let Ok(info) = ops.statfs() {
                             println!("\nFilesystem Information:");
@@ -451,11 +1914,16 @@ async fn main() -> anyhow::Result<()> {
                             }
                         }
                         
-                        println!("\n⚠️  Mounting functionality requires WinFsp (Windows) or FUSE (Linux/macOS)");
-                        println!("This is a preview of the mounting capability.");
-                        println!("\nTo mount {} filesystems on Windows:", fs_type);
-                        println!("  1. Install WinFsp from http://www.secfs.net/winfsp/");
-                        println!("  2. Run: moses mount {} {}", source, target);
+                        let driver = moses_filesystems::mount_driver::detect();
+                        println!("\n⚠️  This build has no mount support; this is a preview of the filesystem.");
+                        println!("\nTo mount {} filesystems:", fs_type);
+                        if driver.meets_requirement() {
+                            println!("  1. {} {} is already installed", driver.name, driver.version.as_deref().unwrap_or("(unknown version)"));
+                        } else {
+                            println!("  1. Install {} {} or newer from {}", driver.name, driver.required_version, driver.download_url);
+                        }
+                        println!("  2. Use a build with the mount-unix or mount-windows feature");
+                        println!("  3. Run: moses mount {} {}", source, target);
                         println!("\nOnce mounted, you'll be able to:");
                         println!("  - Browse {} files in Windows Explorer", fs_type);
                         println!("  - Use any Windows application to read the files");
@@ -477,8 +1945,1251 @@ async fn main() -> anyhow::Result<()> {
         }
         Commands::Unmount { target } => {
             println!("Unmounting {}", target);
-            println!("⚠️  Unmount functionality requires WinFsp/FUSE integration");
-            println!("This feature is coming soon!");
+            #[cfg(target_os = "linux")]
+            if moses_filesystems::kernel_mount::is_kernel_mount(std::path::Path::new(&target)) {
+                moses_filesystems::kernel_mount::unmount(std::path::Path::new(&target))?;
+                println!("{}", progress::success(&format!("Unmounted {}", target)));
+                return Ok(());
+            }
+            println!("⚠️  Mounts made with `moses mount` belong to that command; press Ctrl+C in its terminal to unmount.");
+            println!("Unmounting from a separate command is coming soon!");
+        }
+        Commands::Clean { device, method, background, limit, dry_run } => {
+            use moses_filesystems::disk_manager::{BootCodeAction, CleanOptions, DiskCleaner, WipeMethod};
+            use moses_filesystems::throttle::IoThrottle;
+            
+            let wipe_method = match method.to_lowercase().as_str() {
+                "quick" => WipeMethod::Quick,
+                "zero" => WipeMethod::Zero,
+                "dod" => WipeMethod::DoD5220,
+                "random" => WipeMethod::Random,
+                _ => {
+                    eprintln!("Unknown wipe method: {} (expected quick, zero, dod or random)", method);
+                    return Ok(());
+                }
+            };
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let options = CleanOptions {
+                wipe_method,
+                zero_entire_disk: wipe_method != WipeMethod::Quick,
+                boot_code: BootCodeAction::Discard,
+                throttle: IoThrottle::new(limit, background),
+            };
+            
+            if dry_run {
+                let plan = DiskCleaner::dry_run(&target_device, &options);
+                println!("{} on {} (nothing will be written)", plan.operation, target_device.name);
+                println!("  Partition table: {}", plan.current_style.as_deref().unwrap_or("none"));
+                for partition in &plan.partitions_removed {
+                    println!("  Removes partition {}: offset {}, {} bytes", partition.number, partition.start, partition.size);
+                }
+                for write in &plan.writes {
+                    println!("  Writes {:?} to sectors {}..{}: {}", write.content, write.first_sector(),
+                        write.first_sector() + write.sector_count(), write.structure);
+                }
+                for signature in &plan.signatures_overwritten {
+                    println!("  Erases {} at offset {}", signature.description, signature.offset);
+                }
+                for warning in &plan.warnings {
+                    println!("{}", progress::warning(&format!("  {}", warning)));
+                }
+                println!("  Total: {} bytes written", plan.bytes_written);
+                return Ok(());
+            }
+            
+            if target_device.is_system {
+                eprintln!("Error: Cannot clean system drive!");
+                return Ok(());
+            }
+            if let Err(e) = target_device.ensure_writable() {
+                eprintln!("Error: {}", e);
+                return Ok(());
+            }
+            
+            println!("\n{}", progress::warning(&format!("WARNING: This will ERASE {} with the {:?} method!", target_device.name, wipe_method)));
+            if let Some(limit) = limit {
+                println!("  Throughput limited to {} MB/s", limit);
+            }
+            if background {
+                println!("  Running at idle I/O priority");
+            }
+            println!("Type 'yes' to continue: ");
+            
+            use std::io::{self, BufRead};
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Clean cancelled.");
+                return Ok(());
+            }
+            
+            let _keep_awake = moses_platform::KeepAwake::acquire("Wiping a disk");
+            let mut bar = progress::ProgressBar::new();
+            let mut bytes_done = 0;
+            let started = std::time::Instant::now();
+            let result = DiskCleaner::clean_with_progress(&target_device, &options, &mut |update| {
+                bytes_done = bytes_done.max(update.bytes_done);
+                bar.update(update);
+            });
+            bar.finish();
+            moses_core::LocalMetrics::global().record(
+                moses_core::MetricRecord::new("clean", &target_device, &result, started.elapsed()).with_bytes(bytes_done),
+            );
+            match result {
+                Ok(()) => println!("{}", progress::success(&format!("{} cleaned successfully", target_device.name))),
+                Err(e) => eprintln!("{}", progress::error(&format!("Clean failed: {}", e))),
+            }
+        }
+        Commands::Config { threads, queue_depth, auto_tune, block_size, io_queue_depth, channel } => {
+            let path = MosesConfig::default_path()
+                .ok_or_else(|| anyhow::anyhow!("No config directory on this system"))?;
+            // Edit the saved file, not the environment-adjusted global
+            let mut config = MosesConfig::load_from(&path)?;
+            
+            if threads.is_some() || queue_depth.is_some() || auto_tune.is_some() || block_size.is_some() || io_queue_depth.is_some() || channel.is_some() {
+                if let Some(threads) = threads {
+                    config.concurrency.max_threads = (threads > 0).then_some(threads);
+                }
+                if let Some(depth) = queue_depth {
+                    config.concurrency.queue_depth = depth.max(1);
+                }
+                if let Some(auto_tune) = auto_tune {
+                    config.io.auto_tune = auto_tune;
+                }
+                if let Some(size) = block_size {
+                    config.io.block_size = (size > 0).then_some(size as usize);
+                }
+                if let Some(depth) = io_queue_depth {
+                    config.io.queue_depth = (depth > 0).then_some(depth);
+                }
+                if let Some(channel) = channel {
+                    config.update.channel = channel;
+                }
+                config.save_to(&path)?;
+                println!("Saved {}", path.display());
+            }
+            
+            println!("Config file: {}", path.display());
+            match config.concurrency.max_threads {
+                Some(threads) => println!("  Threads: {}", threads),
+                None => println!("  Threads: {} (one per CPU)", config.concurrency.threads()),
+            }
+            println!("  Queue depth per device: {}", config.concurrency.queue_depth());
+            let probed = if config.io.auto_tune { "probed per device" } else { "default" };
+            match config.io.block_size {
+                Some(size) => println!("  I/O block size: {} KiB", size / 1024),
+                None => println!("  I/O block size: {}", probed),
+            }
+            match config.io.queue_depth {
+                Some(depth) => println!("  Reads in flight per device: {}", depth),
+                None => println!("  Reads in flight per device: {}", probed),
+            }
+            println!("  Release channel: {}", config.update.channel);
+            println!("\nOverride for a single run with {}, {}, {} and {}.",
+                moses_core::config::MAX_THREADS_ENV, moses_core::config::QUEUE_DEPTH_ENV,
+                moses_core::config::BLOCK_SIZE_ENV, moses_core::config::IO_QUEUE_DEPTH_ENV);
+        }
+        Commands::Doctor => {
+            if doctor::run(&registry).await {
+                std::process::exit(1);
+            }
+        }
+        Commands::Serve { interval } => {
+            let config = &MosesConfig::global().events;
+            if config.is_empty() {
+                println!("{}", progress::warning("No webhooks or MQTT brokers configured; events will only be printed."));
+            }
+            let dispatcher = EventDispatcher::new(config.clone());
+            let interval = std::time::Duration::from_secs(interval.unwrap_or(config.poll_interval_secs).max(1));
+            
+            let manager = PlatformDeviceManager;
+            let mut watcher = DeviceWatcher::new(manager.enumerate_devices().await?);
+            println!("Watching for device changes every {}s (Ctrl+C to stop)", interval.as_secs());
+            
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = ticker.tick() => {}
+                }
+                let events = match manager.enumerate_devices().await {
+                    Ok(devices) => watcher.update(devices),
+                    Err(e) => vec![MosesEvent::Error {
+                        operation: "device_scan".to_string(),
+                        device_id: None,
+                        message: e.to_string(),
+                    }],
+                };
+                for event in events {
+                    println!("{}", event.to_json());
+                    for failure in dispatcher.deliver(&event).await {
+                        eprintln!("{}", progress::warning(&format!("Event not delivered: {}", failure)));
+                    }
+                }
+            }
+        }
+        Commands::Tools { install } => {
+            use moses_filesystems::tools::{self, ToolManager};
+            
+            let manager = ToolManager::global();
+            if let Some(package) = install {
+                for path in manager.install_package(&package)? {
+                    println!("{} {}", progress::success("Installed"), path.display());
+                }
+                return Ok(());
+            }
+            
+            println!("Managed tools directory: {}", manager.dir().display());
+            println!("Manifest entries for {}: {}\n", tools::current_platform(),
+                manager.manifest().tools.iter().filter(|entry| entry.platform == tools::current_platform()).count());
+            
+            let mut names = registry.list_formatters();
+            names.sort();
+            let mut listed = false;
+            for name in names {
+                let Some(formatter) = registry.get_formatter(&name) else { continue };
+                for package in formatter.bundled_tools() {
+                    listed = true;
+                    for binary in tools::package_binaries(package) {
+                        let status = match manager.locate(binary) {
+                            Some(tool) => format!("{:?}: {}", tool.source, tool.path.display()),
+                            None if manager.manifest().entry(binary).is_some() => {
+                                format!("missing (moses tools --install {})", package)
+                            }
+                            None => progress::warning("missing"),
+                        };
+                        println!("{:<8} {:<12} {:<12} {}", name, package, binary, status);
+                    }
+                }
+            }
+            if !listed {
+                println!("No formatter on this platform needs external tools.");
+            }
+        }
+        Commands::Update { check, channel } => {
+            use moses_filesystems::update::{Component, Updater};
+            
+            let mut updater = Updater::from_config(Component::Cli, env!("CARGO_PKG_VERSION"))?;
+            if let Some(channel) = channel {
+                updater = updater.with_channel(channel);
+            }
+            if let Some(staged) = updater.staged() {
+                println!("Moses {} is staged and replaces this build on the next run", staged.version);
+            }
+            let result = updater.check()?;
+            let Some(update) = result.available else {
+                println!("Moses {} is the newest {} release", result.current_version, result.channel);
+                return Ok(());
+            };
+            println!("Moses {} is available on the {} channel (this is {})", update.version, update.channel, result.current_version);
+            if !update.notes.is_empty() {
+                println!("\n{}\n", update.notes.trim());
+            }
+            if check {
+                println!("Run `moses update` to download it.");
+                return Ok(());
+            }
+            println!("Downloading Moses {}...", update.version);
+            let staged = updater.stage(&update, &std::env::current_exe()?)?;
+            println!("{}", progress::success(&format!("Moses {} verified and staged; it is used from the next run", staged.version)));
+        }
+        Commands::Stats { json, reset } => {
+            let metrics = moses_core::LocalMetrics::global();
+            if reset {
+                metrics.clear()?;
+                println!("Local statistics cleared");
+                return Ok(());
+            }
+            let summary = metrics.summary();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                return Ok(());
+            }
+            let Some(since) = summary.since else {
+                println!("Nothing recorded yet");
+                return Ok(());
+            };
+            println!("Since {}", since.format("%Y-%m-%d %H:%M"));
+            println!("\nOperations:");
+            for (operation, stats) in &summary.operations {
+                println!("  {:<10} {:>6} run(s) {:>6} failed", operation, stats.runs, stats.failures);
+            }
+            if !summary.failures_by_code.is_empty() {
+                println!("\nFailures by error code:");
+                for (code, count) in &summary.failures_by_code {
+                    println!("  {:<24} {:>6}", code, count);
+                }
+            }
+            if !summary.throughput_by_device_type.is_empty() {
+                println!("\nAverage throughput:");
+                for (device_type, rate) in &summary.throughput_by_device_type {
+                    println!("  {:<12} {:>8.1} MB/s", device_type, rate);
+                }
+            }
+            if let Some(path) = metrics.path() {
+                println!("\nRecorded in {}", path.display());
+            }
+        }
+        Commands::Completions { shell } => {
+            completion::write_registration(shell, &mut std::io::stdout())?;
+        }
+        Commands::Man { out_dir } => {
+            let pages = completion::man_pages(Cli::command())?;
+            match out_dir {
+                Some(dir) => {
+                    std::fs::create_dir_all(&dir)?;
+                    for (name, page) in &pages {
+                        std::fs::write(dir.join(name), page)?;
+                    }
+                    println!("Wrote {} man pages to {}", pages.len(), dir.display());
+                }
+                None => {
+                    use std::io::Write;
+                    std::io::stdout().write_all(&pages[0].1)?;
+                }
+            }
+        }
+        Commands::Eject { device, power_off } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let action = if power_off { PostOperationAction::PowerOff } else { PostOperationAction::Eject };
+            match action.apply(&manager, &target_device).await {
+                Ok(()) => println!("{}: {}", target_device.name, post_action_message(action)),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        Commands::Standby { device, wake } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let state = if wake { DevicePowerState::Active } else { DevicePowerState::Standby };
+            match manager.set_power_state(&target_device, state).await {
+                Ok(()) if wake => println!("{} is spinning up", target_device.name),
+                Ok(()) => println!("{} is now in standby", target_device.name),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        Commands::Hexdump { device, offset, len } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let dump = moses_filesystems::hexview::hexdump_device(&target_device, offset, len as usize)?;
+            
+            println!("{} @ 0x{:X} ({} bytes)\n", target_device.name, dump.offset, dump.data.len());
+            for line in dump.format_lines() {
+                println!("{}", line);
+            }
+            
+            if !dump.annotations.is_empty() {
+                println!("\nAnnotations:");
+                for a in &dump.annotations {
+                    println!("  0x{:08X} +{:<3} {} {} = {}", a.offset, a.length, a.structure, a.field, a.value);
+                }
+            }
+        }
+        Commands::Explain { device, offset } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            match moses_filesystems::explain::explain_device_offset(&target_device, offset)? {
+                Some(explanation) => print!("{}", explanation.to_text()),
+                None => println!("No known structure at 0x{:X} on {}", offset, target_device.name),
+            }
+        }
+        Commands::Analyze { device, quick, deep, estimate } => {
+            use moses_core::progress::format_duration;
+            use moses_filesystems::diagnostics::{self, AnalysisDepth};
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            let depth = match (quick, deep) {
+                (true, _) => AnalysisDepth::Quick,
+                (_, true) => AnalysisDepth::Deep,
+                _ => AnalysisDepth::Standard,
+            };
+            
+            let estimates = diagnostics::estimate_filesystem_analysis(&target_device)?;
+            let eta = |secs: Option<u64>| secs
+                .map(|secs| format!("about {}", format_duration(std::time::Duration::from_secs(secs))))
+                .unwrap_or_else(|| "unknown".to_string());
+            if estimate {
+                println!("Analysis of {}:", target_device.name);
+                for e in &estimates {
+                    println!("  {:<9} {:>10.1} MB in {:>8} reads, {}", e.depth.as_str(), e.bytes as f64 / (1024.0 * 1024.0), e.reads, eta(e.eta_secs));
+                }
+                return Ok(());
+            }
+            if let Some(e) = estimates.iter().find(|e| e.depth == depth) {
+                println!("Running {} analysis of {} ({})", depth.as_str(), target_device.name, eta(e.eta_secs));
+            }
+            
+            let mut bar = progress::ProgressBar::new();
+            let result = diagnostics::analyze_unknown_filesystem_with_progress(&target_device, depth, &mut |p| {
+                bar.update(&p.update);
+                std::ops::ControlFlow::Continue(())
+            });
+            bar.finish();
+            print!("{}", result?.to_report());
+        }
+        Commands::UsageMap { device, width, rows } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let width = width.max(1);
+            let map = moses_filesystems::allocation::device_allocation_map(&target_device, width * rows.max(1))?;
+            let cell_bytes = map.units_per_cell * map.unit_size as u64;
+            println!("{} on {}, {} bytes per cell", map.filesystem, target_device.name, cell_bytes);
+            print!("{}", map.to_chart(width));
+            println!("Used: {:.2} of {:.2} GB ({:.1}%) in {} extents",
+                map.allocated_units as f64 * map.unit_size as f64 / 1_073_741_824.0,
+                map.total_units as f64 * map.unit_size as f64 / 1_073_741_824.0,
+                map.allocated_units as f64 * 100.0 / map.total_units.max(1) as f64,
+                map.extent_count);
+            if !map.hotspots.is_empty() {
+                println!("Fragmentation hotspots:");
+                for hotspot in &map.hotspots {
+                    println!("  line {} column {}: {} extents in {} cell(s) from offset 0x{:X}",
+                        hotspot.first_cell / width + 1, hotspot.first_cell % width + 1,
+                        hotspot.extents, hotspot.cells, hotspot.first_cell as u64 * cell_bytes);
+                }
+            }
+        }
+        Commands::Wipefs { device, no_act, backup, no_backup, restore } => {
+            use moses_filesystems::disk_manager::SignatureWiper;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            if target_device.is_system {
+                eprintln!("Error: Cannot wipe signatures on system drive!");
+                return Ok(());
+            }
+            
+            if let Some(backup_path) = restore {
+                let restored = SignatureWiper::restore(&target_device, &backup_path)?;
+                println!("Restored {} signature(s) on {} from {}", restored, target_device.name, backup_path.display());
+                return Ok(());
+            }
+            
+            let signatures = SignatureWiper::scan(&target_device)?;
+            if signatures.is_empty() {
+                println!("No signatures found on {}", target_device.name);
+                return Ok(());
+            }
+            
+            println!("{:<12} {:<10} {:<20} DESCRIPTION", "OFFSET", "TYPE", "MAGIC");
+            for sig in &signatures {
+                println!("0x{:<10X} {:<10} {:<20} {}", sig.offset, sig.filesystem, sig.magic.iter().map(|b| format!("{:02x}", b)).collect::<String>(), sig.description);
+            }
+            
+            if no_act {
+                return Ok(());
+            }
+            
+            let backup_path = if no_backup {
+                None
+            } else {
+                Some(backup.unwrap_or_else(|| {
+                    let name: String = target_device.name.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                        .collect();
+                    std::path::PathBuf::from(format!("moses-wipefs-{}.bak", name))
+                }))
+            };
+            
+            println!("\nWARNING: This will erase {} signature(s) on {}!", signatures.len(), target_device.name);
+            println!("Type 'yes' to continue: ");
+            
+            use std::io::{self, BufRead};
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Wipe cancelled.");
+                return Ok(());
+            }
+            
+            SignatureWiper::wipe(&target_device, &signatures, backup_path.as_deref())?;
+            println!("Erased {} signature(s) on {}", signatures.len(), target_device.name);
+            if let Some(path) = backup_path {
+                println!("Backup saved to {} (undo with --restore {})", path.display(), path.display());
+            }
+        }
+        Commands::Export { source, to, fs_type, include, exclude, max_size, newer_than, preview, snapshot } => {
+            use moses_filesystems::export::{export_tree, ArchiveFormat};
+            use moses_filesystems::transfer::TransferFilter;
+            use moses_filesystems::{register_all_filesystems, FilesystemOps, FilesystemOpsRegistry};
+            
+            let mut filter = TransferFilter { include, exclude, max_file_size: max_size, newer_than, ..Default::default() };
+            filter.compile()?;
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &source).await?;
+            if !preview {
+                let io = moses_filesystems::io_tuning::for_device(&target_device);
+                eprintln!("Reading {} with {}", target_device.name, io);
+                filter.block_size = Some(io.block_size as u32);
+            }
+            
+            let mut fs: Box<dyn moses_filesystems::FilesystemOps> = match snapshot {
+                Some(snapshot_id) => {
+                    let mut ops = moses_filesystems::NtfsOps::snapshot(snapshot_id);
+                    ops.init(&target_device)?;
+                    Box::new(ops)
+                }
+                None => {
+                    let mut ops_registry = FilesystemOpsRegistry::new();
+                    register_all_filesystems(&mut ops_registry, false);
+                    ops_registry.create_ops(&target_device, fs_type.as_deref())?
+                }
+            };
+            for warning in fs.warnings() {
+                eprintln!("{}", progress::warning(&warning));
+            }
+            
+            if preview {
+                let counts = moses_filesystems::transfer::preview_tree(fs.as_mut(), std::path::Path::new(&path), &filter)?;
+                println!("{} file(s) in {} folder(s), {:.1} MB would be archived",
+                    counts.files, counts.directories, counts.bytes as f64 / (1024.0 * 1024.0));
+                println!("{} file(s), {:.1} MB left out by the filters",
+                    counts.excluded_files, counts.excluded_bytes as f64 / (1024.0 * 1024.0));
+                return Ok(());
+            }
+            
+            // clap guarantees --to unless --preview was given
+            let to = to.unwrap_or_default();
+            let format = ArchiveFormat::from_path(&to).ok_or_else(|| {
+                anyhow::anyhow!("Unknown archive type for {} (use .tar, .tar.gz, .tar.zst or .zip)", to.display())
+            })?;
+            let out = std::fs::File::create(&to)?;
+            let started = std::time::Instant::now();
+            let result = progress::with_spinner(
+                &format!("Exporting {}:{}", target_device.name, path),
+                async { export_tree(fs.as_mut(), std::path::Path::new(&path), out, format, &filter) },
+            ).await;
+            let mut record = moses_core::MetricRecord::new("export", &target_device, &result, started.elapsed());
+            if let Ok(summary) = &result {
+                record = record.with_bytes(summary.bytes);
+            }
+            moses_core::LocalMetrics::global().record(record);
+            let summary = match result {
+                Ok(summary) => summary,
+                Err(e) => {
+                    // Don't leave a truncated archive that looks like a good backup
+                    let _ = std::fs::remove_file(&to);
+                    return Err(e.into());
+                }
+            };
+            
+            println!("{}", progress::success(&format!(
+                "Exported {} file(s) and {} folder(s), {:.1} MB, to {}",
+                summary.files, summary.directories, summary.bytes as f64 / (1024.0 * 1024.0), to.display()
+            )));
+            if summary.excluded_files > 0 {
+                println!("{} file(s) left out by the filters", summary.excluded_files);
+            }
+            if !summary.skipped.is_empty() {
+                println!("{}", progress::warning(&format!("Skipped {} symlink(s):", summary.skipped.len())));
+                for name in &summary.skipped {
+                    println!("  {}", name);
+                }
+            }
+        }
+        Commands::Ls { source, fs_type, long } => {
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &source).await?;
+            let mut fs = open_checksum_ops(&target_device, fs_type.as_deref())?;
+            let path = std::path::Path::new(&path);
+            let attrs = fs.stat(path)?;
+            let is_directory = attrs.is_directory;
+            let mut entries = if is_directory {
+                fs.readdir(path)?
+            } else {
+                let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                vec![moses_filesystems::DirectoryEntry { name, attributes: attrs }]
+            };
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            
+            for entry in entries {
+                let attrs = &entry.attributes;
+                if !long {
+                    println!("{}{}", entry.name, if attrs.is_directory { "/" } else { "" });
+                    continue;
+                }
+                let kind = if attrs.is_directory { 'd' } else if attrs.is_symlink { 'l' } else { '-' };
+                let target = if attrs.is_symlink {
+                    let link = if is_directory { path.join(&entry.name) } else { path.to_path_buf() };
+                    fs.readlink(&link).map(|target| format!(" -> {}", target.display())).unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                println!("{}{:04o} {:>12} {}{}", kind, attrs.permissions & 0o7777, attrs.size, entry.name, target);
+            }
+        }
+        Commands::Image { source, to, consistent } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &source).await?;
+            if !target_device.mount_points.is_empty() && !consistent {
+                println!("{}", progress::warning(&format!(
+                    "{} is mounted and may change while it is copied; use --consistent for a consistent image",
+                    target_device.name
+                )));
+            }
+            
+            let held = if consistent { Some(moses_platform::Quiesce::hold(&target_device, &to)?) } else { None };
+            let source_device = held.as_ref().map_or(&target_device, |held| held.source());
+            let io = moses_filesystems::io_tuning::for_device(source_device);
+            eprintln!("Reading {} with {}", target_device.name, io);
+            
+            let _keep_awake = moses_platform::KeepAwake::acquire("Imaging a disk");
+            let mut bar = progress::ProgressBar::new();
+            let started = std::time::Instant::now();
+            let result = moses_filesystems::imaging::image_device(source_device, &to, io.block_size, &mut |update| bar.update(update));
+            bar.finish();
+            // Thaw or drop the snapshot before reporting, whichever way the copy went
+            let released = held.map_or(Ok(()), |held| held.release());
+            let mut record = moses_core::MetricRecord::new("image", &target_device, &result, started.elapsed());
+            if let Ok(summary) = &result {
+                record = record.with_bytes(summary.bytes);
+            }
+            moses_core::LocalMetrics::global().record(record);
+            let summary = match (result, released) {
+                (Ok(summary), Ok(())) => summary,
+                (Err(e), Err(release_error)) => {
+                    eprintln!("{}", progress::error(&release_error.to_string()));
+                    return Err(e.into());
+                }
+                (Err(e), Ok(())) | (Ok(_), Err(e)) => return Err(e.into()),
+            };
+            
+            println!("{}", progress::success(&format!(
+                "Imaged {} to {}, {:.1} MB ({:.1} MB of zeros left sparse)",
+                target_device.name, to.display(),
+                summary.bytes as f64 / (1024.0 * 1024.0), summary.sparse_bytes as f64 / (1024.0 * 1024.0)
+            )));
+        }
+        Commands::Hash { source, fs_type, save, output, background, limit } => {
+            use moses_filesystems::checksums::{hash_tree, ChecksumDatabase};
+            use moses_filesystems::throttle::IoThrottle;
+            
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &source).await?;
+            let mut fs = open_checksum_ops(&target_device, fs_type.as_deref())?;
+            let manifest = progress::with_spinner(
+                &format!("Hashing {}:{}", target_device.name, path),
+                async { hash_tree(fs.as_mut(), &target_device, std::path::Path::new(&path), &IoThrottle::new(limit, background), &mut |_| {}) },
+            ).await?;
+            
+            if !save && output.is_none() {
+                print!("{}", manifest.to_sha256sum());
+            }
+            println!("{}", progress::success(&format!(
+                "Hashed {} file(s), {:.1} MB",
+                manifest.files.len(), manifest.total_bytes() as f64 / (1024.0 * 1024.0)
+            )));
+            for name in &manifest.unreadable {
+                println!("{}", progress::warning(&format!("Could not read {}", name)));
+            }
+            if let Some(output) = output {
+                std::fs::write(&output, serde_json::to_vec_pretty(&manifest)?)?;
+                println!("Checksums written to {}", output.display());
+            }
+            if save {
+                let saved = ChecksumDatabase::global()?.save(&target_device, &manifest)?;
+                println!("Checksums saved for {} ({}); check later with: moses verify {}",
+                    target_device.name, saved.display(), source);
+            }
+        }
+        Commands::Attr { source, fs_type, set, clear, mode, owner, no_act, undo_file, undo, force } => {
+            use moses_filesystems::{attributes, AttributeChanges};
+            use moses_core::MetadataPatch;
+            
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &source).await?;
+            let (owner, group) = match owner.as_deref().map(|owner| owner.split_once(':').unwrap_or((owner, ""))) {
+                None => (None, None),
+                Some((uid, gid)) => {
+                    let id = |text: &str| text.parse::<u32>().map_err(|_| anyhow::anyhow!("'{}' is not a numeric user or group id", text));
+                    (Some(id(uid)?), if gid.is_empty() { None } else { Some(id(gid)?) })
+                }
+            };
+            let changes = AttributeChanges {
+                mode,
+                owner,
+                group,
+                set_dos: set.as_deref().map(AttributeChanges::parse_dos).transpose()?.unwrap_or(0),
+                clear_dos: clear.as_deref().map(AttributeChanges::parse_dos).transpose()?.unwrap_or(0),
+            };
+            
+            let filesystem = fs_type.clone().or_else(|| device_filesystem(&target_device));
+            let (patch, undo_path) = match undo {
+                Some(undo) => (MetadataPatch::load(&undo)?, None),
+                None if changes.is_empty() => {
+                    let mut fs = open_checksum_ops(&target_device, fs_type.as_deref())?;
+                    let attrs = fs.stat(std::path::Path::new(&path))?;
+                    let id = |id: Option<u32>| id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string());
+                    println!("{} ({}, {} bytes)", path, if attrs.is_directory { "directory" } else { "file" }, attrs.size);
+                    println!("  mode {:04o}, owner {}, group {}", attrs.permissions & 0o7777, id(attrs.owner), id(attrs.group));
+                    return Ok(());
+                }
+                None => {
+                    let filesystem = filesystem.as_deref()
+                        .ok_or_else(|| anyhow::anyhow!("Could not detect the filesystem on {}; give --fs-type", target_device.name))?;
+                    let patch = attributes::plan_device(&target_device, filesystem, &path, &changes)?;
+                    let undo_path = undo_file.unwrap_or_else(|| {
+                        let name: String = target_device.name.chars()
+                            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                            .collect();
+                        std::path::PathBuf::from(format!("moses-attr-{}.json", name))
+                    });
+                    (patch, Some(undo_path))
+                }
+            };
+            
+            if patch.is_empty() {
+                println!("{} already has these attributes; nothing to write", path);
+                return Ok(());
+            }
+            println!("{} on {}:", patch.description, target_device.name);
+            for line in patch.diff() {
+                println!("  {}", line);
+            }
+            if no_act {
+                return Ok(());
+            }
+            
+            if let Some(filesystem) = &filesystem {
+                if let Err(e) = attributes::check_writable(&target_device, filesystem, force) {
+                    eprintln!("{}", progress::warning(&e.to_string()));
+                    // Like Windows' "scan and fix" prompt: check it now, then carry on if it is clean
+                    use std::io::{self, BufRead, IsTerminal};
+                    if !io::stdin().is_terminal() {
+                        return Ok(());
+                    }
+                    println!("Check {} now? [y/N] ", target_device.name);
+                    let mut line = String::new();
+                    io::stdin().lock().read_line(&mut line)?;
+                    if !line.trim().eq_ignore_ascii_case("y") || !fat_check(&target_device, false, false, None)? {
+                        return Ok(());
+                    }
+                }
+            }
+            attributes::apply_device(&target_device, &patch, undo_path.as_deref())?;
+            println!("{}", progress::success(&format!("Wrote {} range(s) to {}", patch.ranges.len(), target_device.name)));
+            if let Some(path) = undo_path {
+                println!("Undo record saved to {} (undo with --undo {})", path.display(), path.display());
+            }
+        }
+        Commands::Verify { source, fs_type, manifest, update, background, limit } => {
+            use moses_filesystems::checksums::{compare, hash_tree, load_manifest, ChecksumDatabase};
+            use moses_filesystems::throttle::IoThrottle;
+            
+            let manager = PlatformDeviceManager;
+            let (target_device, path) = resolve_device_source(&manager, &source).await?;
+            let database = ChecksumDatabase::global()?;
+            let stored = match &manifest {
+                Some(file) => load_manifest(file)?,
+                None => database.load(&target_device, &path)?.ok_or_else(|| anyhow::anyhow!(
+                    "No checksums saved for {}:{}; record them first with: moses hash {} --save",
+                    target_device.name, path, source
+                ))?,
+            };
+            println!("Comparing with checksums of {} made {}", stored.device, stored.created.format("%Y-%m-%d %H:%M UTC"));
+            
+            let mut fs = open_checksum_ops(&target_device, fs_type.as_deref())?;
+            let current = progress::with_spinner(
+                &format!("Hashing {}:{}", target_device.name, path),
+                async { hash_tree(fs.as_mut(), &target_device, std::path::Path::new(&path), &IoThrottle::new(limit, background), &mut |_| {}) },
+            ).await?;
+            let report = compare(&stored, &current);
+            
+            let sections = [
+                ("corrupted (content changed, size and date did not)", &report.corrupted),
+                ("unreadable", &report.unreadable),
+                ("missing", &report.missing),
+                ("modified", &report.modified),
+                ("added", &report.added),
+            ];
+            for (label, names) in sections {
+                if names.is_empty() {
+                    continue;
+                }
+                println!("\n{} file(s) {}:", names.len(), label);
+                for name in names {
+                    println!("  {}", name);
+                }
+            }
+            println!();
+            if report.is_intact() {
+                println!("{}", progress::success(&format!("All {} recorded file(s) are intact", report.checked)));
+            } else {
+                println!("{}", progress::error(&format!(
+                    "{} of {} recorded file(s) are not intact",
+                    report.checked - report.unchanged, report.checked
+                )));
+            }
+            if update {
+                database.save(&target_device, &current)?;
+                println!("Saved the current checksums as the new baseline");
+            }
+            if !report.is_intact() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Snapshots { device } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            let copies = moses_filesystems::list_device_shadow_copies(&target_device)?;
+            
+            if copies.is_empty() {
+                println!("No shadow copies on {}", target_device.name);
+                return Ok(());
+            }
+            println!("{} shadow cop{} on {}:\n", copies.len(), if copies.len() == 1 { "y" } else { "ies" }, target_device.name);
+            for copy in &copies {
+                let created = copy.created
+                    .and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(|| "unknown time".to_string());
+                println!("  {:>2}. {{{}}}  {}{}", copy.index, copy.id, created,
+                    copy.machine.as_ref().map(|m| format!("  on {}", m)).unwrap_or_default());
+            }
+            println!("\nBrowse one with: moses mount {} <mount point> --snapshot <number or id>", device);
+        }
+        Commands::Trash { device, purge } => {
+            use moses_filesystems::disk_manager::trash;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            let reports = trash::analyze_trash(&target_device);
+            if reports.is_empty() {
+                println!("No readable filesystem on {}", target_device.name);
+                return Ok(());
+            }
+            
+            let mut total = 0;
+            for report in &reports {
+                println!("{} ({}):", report.location, report.filesystem);
+                if report.folders.is_empty() {
+                    println!("  no trash folders");
+                }
+                for folder in &report.folders {
+                    println!("  {:<20} {:>8} files {:>12.2} MB", folder.path, folder.files, folder.bytes as f64 / (1024.0 * 1024.0));
+                }
+                total += report.total_bytes();
+            }
+            if !purge || total == 0 {
+                return Ok(());
+            }
+            
+            println!("\nWARNING: This permanently deletes {:.2} MB of deleted items on {}!", total as f64 / (1024.0 * 1024.0), target_device.name);
+            println!("Type 'yes' to continue: ");
+            
+            use std::io::{self, BufRead};
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Purge cancelled.");
+                return Ok(());
+            }
+            
+            let purged = trash::purge_trash(&target_device)?;
+            println!("{}", progress::success(&format!("Removed {} files ({:.2} MB) from the trash on {}",
+                purged.removed_files, purged.removed_bytes as f64 / (1024.0 * 1024.0), target_device.name)));
+            for failure in &purged.failures {
+                println!("{}", progress::warning(&format!("  not removed: {}", failure)));
+            }
+        }
+        Commands::RescueBoot { device, no_act, backup, no_backup, restore } => {
+            use moses_filesystems::disk_manager::boot_rescue;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            if target_device.is_system {
+                eprintln!("Error: Cannot rewrite the boot sector of a system drive!");
+                return Ok(());
+            }
+            
+            if let Some(backup_path) = restore {
+                let restored = boot_rescue::undo_device(&target_device, &backup_path)?;
+                println!("Put back {} range(s) on {} from {}", restored, target_device.name, backup_path.display());
+                return Ok(());
+            }
+            
+            let Some(found) = boot_rescue::scan_device(&target_device)? else {
+                println!("No valid FAT32, exFAT or NTFS backup boot sector found on {}", target_device.name);
+                return Ok(());
+            };
+            println!("Found a valid {} backup boot sector at 0x{:X} ({}-byte sectors)", found.kind, found.backup_offset, found.sector_size);
+            if !found.differs() {
+                println!("The primary boot sector matches it; nothing to do.");
+                return Ok(());
+            }
+            if found.primary_valid {
+                println!("{}", progress::warning("The primary boot sector also looks valid; it may have been changed on purpose (e.g. a new label)"));
+            }
+            
+            println!("\n{:<12} {:<28} {:<30} BACKUP", "OFFSET", "FIELD", "CURRENT");
+            for change in found.changes() {
+                println!("0x{:<10X} {:<28} {:<30} {}", change.offset, change.field, change.current, change.backup);
+            }
+            
+            if no_act {
+                return Ok(());
+            }
+            
+            let backup_path = if no_backup {
+                None
+            } else {
+                Some(backup.unwrap_or_else(|| {
+                    let name: String = target_device.name.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                        .collect();
+                    std::path::PathBuf::from(format!("moses-boot-{}.bak", name))
+                }))
+            };
+            
+            println!("\nWARNING: This overwrites the {} boot sector of {} with its backup!", found.kind, target_device.name);
+            println!("Type 'yes' to continue: ");
+            
+            use std::io::{self, BufRead};
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Rescue cancelled.");
+                return Ok(());
+            }
+            
+            boot_rescue::rescue_device(&target_device, &found, backup_path.as_deref())?;
+            println!("{}", progress::success(&format!("Restored the {} boot sector of {}", found.kind, target_device.name)));
+            if let Some(path) = backup_path {
+                println!("Backup saved to {} (undo with --restore {})", path.display(), path.display());
+            }
+        }
+        Commands::RebuildTable { device, style, no_act } => {
+            use moses_filesystems::disk_manager::table_rebuild;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            if target_device.is_system {
+                eprintln!("Error: Cannot rebuild the partition table of a system drive!");
+                return Ok(());
+            }
+            
+            let found = progress::with_spinner(
+                &format!("Scanning {} for filesystems", target_device.name),
+                async { table_rebuild::scan_device(&target_device) },
+            ).await?;
+            if found.is_empty() {
+                println!("No filesystems found on {}", target_device.name);
+                return Ok(());
+            }
+            
+            println!("{:<4} {:<10} {:<14} {:<14} SIZE", "#", "TYPE", "START SECTOR", "SECTORS");
+            for (i, partition) in found.iter().enumerate() {
+                println!("{:<4} {:<10} {:<14} {:<14} {:.2} GB", i + 1, partition.filesystem, partition.start / 512,
+                    partition.size.div_ceil(512), partition.size as f64 / 1_073_741_824.0);
+            }
+            
+            if no_act {
+                return Ok(());
+            }
+            
+            let table_type = style.unwrap_or_else(|| table_rebuild::default_table_type(&found));
+            let table_name = match table_type {
+                PartitionTableType::MBR => "MBR",
+                PartitionTableType::GPT => "GPT",
+            };
+            println!("\nWARNING: This replaces the partition table of {} with a new {} listing these {} volume(s)!", target_device.name, table_name, found.len());
+            println!("Type 'yes' to continue: ");
+            
+            use std::io::{self, BufRead};
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Rebuild cancelled.");
+                return Ok(());
+            }
+            
+            table_rebuild::rebuild_device(&target_device, &found, table_type)?;
+            println!("{}", progress::success(&format!("Wrote a {} table with {} partition(s) to {}", table_name, found.len(), target_device.name)));
+            println!("Undo with: moses info {} --restore-layout", device);
+        }
+        Commands::Ext { command: ExtCommand::RescueSuperblock { device, group, no_act, backup, no_backup, restore } } => {
+            use moses_filesystems::families::ext::rescue;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            if target_device.is_system {
+                eprintln!("Error: Cannot rewrite the superblock of a system drive!");
+                return Ok(());
+            }
+            
+            if let Some(backup_path) = restore {
+                let restored = rescue::undo_device(&target_device, &backup_path)?;
+                println!("Put back {} range(s) on {} from {}", restored, target_device.name, backup_path.display());
+                return Ok(());
+            }
+            
+            let (primary_valid, backups) = rescue::scan_device(&target_device)?;
+            if backups.is_empty() {
+                println!("No backup superblocks found on {}", target_device.name);
+                return Ok(());
+            }
+            if primary_valid {
+                println!("{}", progress::warning("The primary superblock looks valid; restoring a backup loses changes made since it was written"));
+            }
+            
+            println!("{:<4} {:<8} {:<14} {:<8} {:<22} MOUNTS", "#", "GROUP", "OFFSET", "BLOCK", "LAST WRITTEN");
+            for (i, sb) in backups.iter().enumerate() {
+                let written = chrono::DateTime::from_timestamp(sb.written() as i64, 0)
+                    .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                println!("{:<4} {:<8} 0x{:<12X} {:<8} {:<22} {}", i + 1, sb.group, sb.offset, sb.block_size, written, sb.mount_count());
+            }
+            
+            if no_act {
+                return Ok(());
+            }
+            
+            use std::io::{self, BufRead};
+            let chosen = match group {
+                Some(group) => backups.iter().find(|sb| sb.group == group)
+                    .ok_or_else(|| anyhow::anyhow!("No backup superblock in group {}", group))?,
+                None => {
+                    println!("\nBackup to restore (1-{}): ", backups.len());
+                    let mut line = String::new();
+                    io::stdin().lock().read_line(&mut line)?;
+                    match line.trim().parse::<usize>() {
+                        Ok(n) if (1..=backups.len()).contains(&n) => &backups[n - 1],
+                        _ => {
+                            println!("Rescue cancelled.");
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+            
+            let backup_path = if no_backup {
+                None
+            } else {
+                Some(backup.unwrap_or_else(|| {
+                    let name: String = target_device.name.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                        .collect();
+                    std::path::PathBuf::from(format!("moses-superblock-{}.bak", name))
+                }))
+            };
+            
+            println!("\nWARNING: This overwrites the superblock and group descriptors of {} with the copy in group {}!", target_device.name, chosen.group);
+            println!("Type 'yes' to continue: ");
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim() != "yes" {
+                println!("Rescue cancelled.");
+                return Ok(());
+            }
+            
+            rescue::restore_device(&target_device, chosen, backup_path.as_deref())?;
+            println!("{}", progress::success(&format!("Restored the superblock of {} from group {}", target_device.name, chosen.group)));
+            if let Some(path) = backup_path {
+                println!("Backup saved to {} (undo with --restore {})", path.display(), path.display());
+            }
+            
+            match rescue::check_device(&target_device) {
+                Ok(result) => {
+                    for error in &result.errors {
+                        println!("{}", progress::error(error));
+                    }
+                    for warning in &result.warnings {
+                        println!("{}", progress::warning(warning));
+                    }
+                    if result.is_valid {
+                        println!("{}", progress::success("The filesystem checks out"));
+                    }
+                }
+                Err(e) => eprintln!("{}", progress::error(&format!("Check failed: {}", e))),
+            }
+            println!("Run `e2fsck -f {}` to rebuild the free block and inode counts.", target_device.id);
+        }
+        Commands::Ext { command: ExtCommand::Tune { device, label, uuid, no_act, undo_file, undo } } => {
+            use moses_filesystems::families::ext::tune;
+            use moses_core::MetadataPatch;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let (patch, undo_path) = match undo {
+                Some(path) => (MetadataPatch::load(&path)?, None),
+                None => {
+                    if label.is_none() && uuid.is_none() {
+                        eprintln!("Nothing to change: give --label and/or --uuid");
+                        return Ok(());
+                    }
+                    let uuid = uuid.as_deref().map(tune::parse_uuid).transpose()?;
+                    let patch = tune::plan_device(&target_device, &tune::ExtTune { label, uuid })?;
+                    let undo_path = undo_file.unwrap_or_else(|| {
+                        let name: String = target_device.name.chars()
+                            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                            .collect();
+                        std::path::PathBuf::from(format!("moses-tune-{}.json", name))
+                    });
+                    (patch, Some(undo_path))
+                }
+            };
+            
+            if patch.is_empty() {
+                println!("{} already has these settings; nothing to write", target_device.name);
+                return Ok(());
+            }
+            println!("{} on {}:", patch.description, target_device.name);
+            for line in patch.diff() {
+                println!("  {}", line);
+            }
+            if no_act {
+                return Ok(());
+            }
+            
+            tune::apply_device(&target_device, &patch, undo_path.as_deref())?;
+            println!("{}", progress::success(&format!("Wrote {} range(s) to {}", patch.ranges.len(), target_device.name)));
+            if let Some(path) = undo_path {
+                println!("Undo record saved to {} (undo with --undo {})", path.display(), path.display());
+            }
+        }
+        Commands::Fat { command: FatCommand::Check { device, mark_clean, no_act, undo_file, undo } } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            if let Some(path) = undo {
+                let patch = moses_core::MetadataPatch::load(&path)?;
+                moses_filesystems::families::fat::common::dirty::apply_device(&target_device, &patch, None)?;
+                println!("Put back {} range(s) on {} from {}", patch.ranges.len(), target_device.name, path.display());
+                return Ok(());
+            }
+            fat_check(&target_device, mark_clean, no_act, undo_file)?;
+        }
+        Commands::Fat { command: FatCommand::Label { device, label, clear, no_act, undo_file, undo } } => {
+            use moses_filesystems::families::fat::common::label;
+            use moses_core::MetadataPatch;
+            
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let (patch, undo_path) = match undo {
+                Some(path) => (MetadataPatch::load(&path)?, None),
+                None if label.is_none() && !clear => {
+                    let labels = label::read_device(&target_device)?;
+                    println!("{} volume on {}", labels.filesystem, target_device.name);
+                    if let Some(boot_sector) = &labels.boot_sector {
+                        println!("  Boot sector:    {}", boot_sector);
+                    }
+                    println!("  Root directory: {}", labels.directory.as_deref().unwrap_or("(no label entry)"));
+                    match labels.mismatch() {
+                        Some(mismatch) => println!("{}", progress::warning(&mismatch)),
+                        None => println!("Label: {}", labels.effective().as_deref().unwrap_or("(none)")),
+                    }
+                    return Ok(());
+                }
+                None => {
+                    let patch = label::plan_device(&target_device, label.as_deref())?;
+                    let undo_path = undo_file.unwrap_or_else(|| {
+                        let name: String = target_device.name.chars()
+                            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                            .collect();
+                        std::path::PathBuf::from(format!("moses-label-{}.json", name))
+                    });
+                    (patch, Some(undo_path))
+                }
+            };
+            
+            if patch.is_empty() {
+                println!("{} already has this label; nothing to write", target_device.name);
+                return Ok(());
+            }
+            println!("{} on {}:", patch.description, target_device.name);
+            for line in patch.diff() {
+                println!("  {}", line);
+            }
+            if no_act {
+                return Ok(());
+            }
+            
+            label::apply_device(&target_device, &patch, undo_path.as_deref())?;
+            println!("{}", progress::success(&format!("Wrote {} range(s) to {}", patch.ranges.len(), target_device.name)));
+            if let Some(path) = undo_path {
+                println!("Undo record saved to {} (undo with --undo {})", path.display(), path.display());
+            }
+        }
+        Commands::Nvme { command: NvmeCommand::LbaFormats { device } } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let formats = manager.lba_formats(&target_device).await?;
+            println!("LBA formats of {}:", target_device.name);
+            for format in formats {
+                println!("  {}", format);
+            }
+        }
+        Commands::Nvme { command: NvmeCommand::SetLbaFormat { device, format, sector_size, no_act } } => {
+            let manager = PlatformDeviceManager;
+            let target_device = resolve_device(&manager, &device).await?;
+            
+            let formats = manager.lba_formats(&target_device).await?;
+            let target = match (format, sector_size) {
+                (Some(index), _) => *formats.iter().find(|format| format.index == index)
+                    .ok_or_else(|| anyhow::anyhow!("{} has no LBA format #{}", target_device.name, index))?,
+                (None, Some(size)) => moses_core::nvme::pick_format(&formats, size)?,
+                (None, None) => unreachable!("clap requires a format or --sector-size"),
+            };
+            let current = formats.iter().find(|format| format.in_use);
+            if target.in_use {
+                println!("{} already uses {}; nothing to change", target_device.name, target);
+                return Ok(());
+            }
+            println!("{}: {} -> {}", target_device.name,
+                current.map_or("unknown".to_string(), |format| format.to_string()), target);
+            if target.metadata_size > 0 {
+                println!("{}", progress::warning("This format carries metadata bytes per sector, which most operating systems cannot use"));
+            }
+            if no_act {
+                return Ok(());
+            }
+            if target_device.is_system {
+                eprintln!("Error: Cannot reformat the system drive!");
+                return Ok(());
+            }
+            
+            let peers = manager.enumerate_devices().await.unwrap_or_default();
+            println!();
+            guided::print_device_details(&target_device, &peers);
+            println!("\n{}", progress::warning(&format!(
+                "WARNING: Changing the sector size ERASES EVERYTHING on {}: every partition and file, with no undo.",
+                target_device.name,
+            )));
+            let Some(confirmation) = guided::confirm_device(&target_device)? else {
+                println!("LBA format change cancelled.");
+                return Ok(());
+            };
+            let target_device = manager.verify_unchanged(&target_device).await?;
+            let operation = format!("NVMe LBA format #{} ({} bytes)", target.index, target.data_size);
+            let record = moses_core::AuditRecord::new(&target_device, operation.clone(), confirmation);
+            if let Err(e) = moses_core::AuditLog::global().record(record) {
+                eprintln!("{}", progress::warning(&format!("Could not write the audit log: {}", e)));
+            }
+            moses_filesystems::disk_manager::history::record_before(&target_device, operation);
+            
+            progress::with_spinner(
+                &format!("Formatting {} with {}-byte sectors", target_device.name, target.data_size),
+                manager.set_lba_format(&target_device, target.index),
+            ).await?;
+            println!("{}", progress::success(&format!(
+                "{} now uses {}-byte sectors; partition and format it as usual", target_device.name, target.data_size,
+            )));
         }
     }
     
@@ -0,0 +1,167 @@
+// Terminal progress display for long CLI operations
+// On a TTY, progress is drawn as a single bar rewritten in place; when output is piped
+// or redirected it falls back to plain lines every 10% so logs stay readable.
+// `--quiet` hides progress entirely and `--no-color` (or NO_COLOR) drops the ANSI styling.
+use moses_core::progress::format_duration;
+use moses_core::ProgressUpdate;
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 30;
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Minimum time between redraws of the bar
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutputMode {
+    pub quiet: bool,
+    pub color: bool,
+    pub interactive: bool,
+}
+
+static MODE: OnceLock<OutputMode> = OnceLock::new();
+
+/// Decide how to draw output; call once after parsing arguments
+pub fn init(quiet: bool, no_color: bool) {
+    let interactive = std::io::stdout().is_terminal();
+    let color = interactive && !no_color && std::env::var_os("NO_COLOR").is_none();
+    let _ = MODE.set(OutputMode { quiet, color, interactive });
+}
+
+pub fn mode() -> OutputMode {
+    *MODE.get_or_init(|| OutputMode { quiet: false, color: false, interactive: false })
+}
+
+fn paint(code: &str, text: &str) -> String {
+    if mode().color {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn success(text: &str) -> String {
+    paint("32", text)
+}
+
+pub fn warning(text: &str) -> String {
+    paint("33", text)
+}
+
+pub fn error(text: &str) -> String {
+    paint("31;1", text)
+}
+
+/// Progress bar fed by `ProgressUpdate`s
+pub struct ProgressBar {
+    mode: OutputMode,
+    last_draw: Option<Instant>,
+    last_logged_decile: Option<u32>,
+    drawn: bool,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self {
+            mode: mode(),
+            last_draw: None,
+            last_logged_decile: None,
+            drawn: false,
+        }
+    }
+
+    pub fn update(&mut self, update: &ProgressUpdate) {
+        if self.mode.quiet {
+            return;
+        }
+
+        if !self.mode.interactive {
+            let decile = (update.percent / 10.0) as u32;
+            if self.last_logged_decile != Some(decile) {
+                self.last_logged_decile = Some(decile);
+                println!("{}: {}", update.operation, update.summary());
+            }
+            return;
+        }
+
+        let done = update.percent >= 100.0;
+        if !done && self.last_draw.is_some_and(|t| t.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let filled = ((update.percent / 100.0) * BAR_WIDTH as f32).round() as usize;
+        let bar = format!(
+            "{}{}",
+            paint("36", &"█".repeat(filled.min(BAR_WIDTH))),
+            "░".repeat(BAR_WIDTH - filled.min(BAR_WIDTH))
+        );
+        let rate = update.bytes_per_sec
+            .map(|rate| format!(" {:.1} MB/s", rate as f64 / (1024.0 * 1024.0)))
+            .unwrap_or_default();
+        let eta = update.eta_secs
+            .map(|eta| format!(" ETA {}", format_duration(Duration::from_secs(eta))))
+            .unwrap_or_default();
+
+        print!("\r\x1b[2K{} {:>5.1}% {}{}{}", bar, update.percent, update.phase, rate, eta);
+        let _ = std::io::stdout().flush();
+        self.drawn = true;
+    }
+
+    /// End the bar's line so later output starts cleanly
+    pub fn finish(&mut self) {
+        if self.drawn {
+            println!();
+            self.drawn = false;
+        }
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Run a future that has no byte-level progress, showing a spinner with elapsed time.
+/// The spinner runs as its own task because formatters block their thread while writing.
+pub async fn with_spinner<F: Future>(message: &str, future: F) -> F::Output {
+    let mode = mode();
+    if mode.quiet {
+        return future.await;
+    }
+    if !mode.interactive {
+        println!("{}...", message);
+        return future.await;
+    }
+
+    let started = Instant::now();
+    let label = message.to_string();
+    let spinner = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(REDRAW_INTERVAL);
+        for frame in SPINNER_FRAMES.iter().cycle() {
+            ticker.tick().await;
+            print!(
+                "\r\x1b[2K{} {} ({})",
+                paint("36", &frame.to_string()),
+                label,
+                format_duration(started.elapsed())
+            );
+            let _ = std::io::stdout().flush();
+        }
+    });
+
+    let output = future.await;
+    spinner.abort();
+    let _ = spinner.await;
+    println!("\r\x1b[2K{} ({})", message, format_duration(started.elapsed()));
+    output
+}
@@ -0,0 +1,143 @@
+// Audit log of confirmed destructive operations
+// Each time the user confirms a format, Moses appends one record saying what was about to
+// be erased and how the user agreed to it: the device and its serial, the operation, and
+// the confirmation given (a typed "yes", or the serial typed back in the interactive mode).
+// The device history keeps the last few layouts per device so they can be restored; this
+// log is append-only JSON lines, never rewritten, and readable with any text tool.
+use crate::Device;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// One confirmed operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub at: DateTime<Utc>,
+    /// e.g. "format as exfat"
+    pub operation: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub serial: Option<String>,
+    pub size: u64,
+    /// How the user confirmed, e.g. "typed yes" or "typed the serial number"
+    pub confirmation: String,
+    /// Account that ran Moses, from the environment
+    pub user: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(device: &Device, operation: impl Into<String>, confirmation: impl Into<String>) -> Self {
+        Self {
+            at: Utc::now(),
+            operation: operation.into(),
+            device_id: device.id.clone(),
+            device_name: device.name.clone(),
+            serial: device.serial.clone(),
+            size: device.size,
+            confirmation: confirmation.into(),
+            user: ["SUDO_USER", "USER", "USERNAME"].iter().find_map(|key| std::env::var(key).ok()),
+        }
+    }
+}
+
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    /// Used when there is no backing file
+    memory: Mutex<Vec<AuditRecord>>,
+}
+
+impl AuditLog {
+    /// In-memory log
+    pub fn new() -> Self {
+        Self { path: None, memory: Mutex::new(Vec::new()) }
+    }
+
+    /// Log backed by a JSON lines file
+    pub fn persistent(path: PathBuf) -> Self {
+        Self { path: Some(path), memory: Mutex::new(Vec::new()) }
+    }
+
+    /// Process-wide log stored in the user's data directory
+    pub fn global() -> &'static AuditLog {
+        static GLOBAL: OnceLock<AuditLog> = OnceLock::new();
+        GLOBAL.get_or_init(|| match dirs::data_local_dir() {
+            Some(dir) => Self::persistent(dir.join("moses").join("audit.jsonl")),
+            None => Self::new(),
+        })
+    }
+
+    /// Where the records are kept, if anywhere
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Append a record
+    pub fn record(&self, record: AuditRecord) -> std::io::Result<()> {
+        tracing::info!("Audit: {} of {} confirmed ({})", record.operation, record.device_id, record.confirmation);
+        let mut memory = self.memory.lock().map_err(|_| std::io::Error::other("audit log lock poisoned"))?;
+        let Some(path) = &self.path else {
+            memory.push(record);
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let line = serde_json::to_string(&record).map_err(std::io::Error::other)?;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", line)
+    }
+
+    /// Every record, oldest first; lines that do not parse are skipped
+    pub fn records(&self) -> Vec<AuditRecord> {
+        match &self.path {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|text| text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+                .unwrap_or_default(),
+            None => self.memory.lock().map(|records| records.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    #[test]
+    fn test_audit_log_appends() {
+        let path = std::env::temp_dir().join(format!("moses_audit_test_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let stick = Device {
+            id: "/dev/sdb".to_string(),
+            name: "stick".to_string(),
+            size: 8 * 1024 * 1024 * 1024,
+            device_type: DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: Some("4C530001".to_string()),
+            erase_block_size: None,
+            partition_offset: None,
+        };
+        let log = AuditLog::persistent(path.clone());
+        log.record(AuditRecord::new(&stick, "format as exfat", "typed yes")).unwrap();
+        log.record(AuditRecord::new(&stick, "format as fat32", "typed the serial number")).unwrap();
+
+        let records = AuditLog::persistent(path.clone()).records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].operation, "format as exfat");
+        assert_eq!(records[1].serial.as_deref(), Some("4C530001"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let _ = std::fs::remove_file(path);
+    }
+}
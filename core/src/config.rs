@@ -0,0 +1,471 @@
+// User configuration shared by the GUI, worker and CLI
+// Stored as JSON in the user's config directory. A missing or unreadable file means
+// defaults, and environment variables override single settings for one run.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// Overrides `concurrency.max_threads`
+pub const MAX_THREADS_ENV: &str = "MOSES_MAX_THREADS";
+/// Overrides `concurrency.queue_depth`
+pub const QUEUE_DEPTH_ENV: &str = "MOSES_QUEUE_DEPTH";
+/// Overrides `worker.sandbox_parsing` (1 or 0)
+pub const SANDBOX_PARSING_ENV: &str = "MOSES_SANDBOX_PARSING";
+/// Overrides `io.block_size` (bytes)
+pub const BLOCK_SIZE_ENV: &str = "MOSES_BLOCK_SIZE";
+/// Overrides `io.queue_depth`
+pub const IO_QUEUE_DEPTH_ENV: &str = "MOSES_IO_QUEUE_DEPTH";
+
+const DEFAULT_QUEUE_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MosesConfig {
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub worker: WorkerConfig,
+    #[serde(default)]
+    pub io: IoConfig,
+    #[serde(default)]
+    pub update: UpdateConfig,
+}
+
+/// Where `moses serve` and CLI formats report device and format events
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventsConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default)]
+    pub mqtt: Vec<MqttConfig>,
+    /// How often `moses serve` rescans devices for attach/detach
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_secs: u64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            mqtt: Vec::new(),
+            poll_interval_secs: default_poll_interval(),
+        }
+    }
+}
+
+impl EventsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty() && self.mqtt.is_empty()
+    }
+}
+
+/// JSON POSTed to a URL for each event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event names to send (`device_attached`, `format_completed`, ...); empty sends all
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Extra request headers, e.g. an authorization token
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Events published to `<topic_prefix>/<event name>` on an MQTT broker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_topic_prefix")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Event names to publish; empty publishes all
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+fn default_poll_interval() -> u64 {
+    2
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_topic_prefix() -> String {
+    "moses".to_string()
+}
+
+/// External tools that formatters shell out to (mkfs.fat, mkfs.exfat, ...)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolsConfig {
+    /// Download missing tools listed in the tool manifest instead of failing
+    #[serde(default)]
+    pub auto_download: bool,
+    /// Manifest location (file path or URL) replacing the one in the tools directory
+    #[serde(default)]
+    pub manifest: Option<String>,
+}
+
+/// Where new releases of Moses are looked for
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateConfig {
+    #[serde(default)]
+    pub channel: ReleaseChannel,
+    /// Release feed (file path or URL) replacing the one the build was made with
+    #[serde(default)]
+    pub feed: Option<String>,
+}
+
+/// Which releases to offer: stable only, or betas too
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl ReleaseChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Beta => "beta",
+        }
+    }
+
+    /// Whether a release published on `channel` is offered to followers of this one
+    pub fn includes(&self, channel: ReleaseChannel) -> bool {
+        channel <= *self
+    }
+}
+
+impl std::str::FromStr for ReleaseChannel {
+    type Err = crate::MosesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "stable" => Ok(Self::Stable),
+            "beta" => Ok(Self::Beta),
+            other => Err(crate::MosesError::InvalidInput(format!(
+                "Unknown release channel '{}' (expected stable or beta)",
+                other
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for ReleaseChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the elevated worker treats what it reads from disks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerConfig {
+    /// Parse on-disk structures in an unprivileged child process; the elevated worker
+    /// then only reads raw blocks on its behalf. Only analysis and detection run that
+    /// way, so browsing a drive through the worker is refused while this is on.
+    #[serde(default)]
+    pub sandbox_parsing: bool,
+}
+
+/// Block size and reads in flight for copies and full-disk passes; whatever is not set
+/// here is picked from a short read probe of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoConfig {
+    /// Probe each device before the first copy or full-disk pass of a session
+    #[serde(default = "default_auto_tune")]
+    pub auto_tune: bool,
+    /// Bytes per read or write request
+    #[serde(default)]
+    pub block_size: Option<usize>,
+    /// Reads in flight against one device
+    #[serde(default)]
+    pub queue_depth: Option<usize>,
+}
+
+fn default_auto_tune() -> bool {
+    true
+}
+
+impl Default for IoConfig {
+    fn default() -> Self {
+        Self { auto_tune: default_auto_tune(), block_size: None, queue_depth: None }
+    }
+}
+
+/// Limits for parallel work, so small machines aren't saturated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// Worker threads for parallel formatting, hashing and copying; `None` means one per CPU
+    #[serde(default)]
+    pub max_threads: Option<usize>,
+    /// Operations allowed in flight against a single device at once
+    #[serde(default = "default_queue_depth")]
+    pub queue_depth: usize,
+}
+
+fn default_queue_depth() -> usize {
+    DEFAULT_QUEUE_DEPTH
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_threads: None,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    /// Number of worker threads to use, never less than one
+    pub fn threads(&self) -> usize {
+        match self.max_threads {
+            Some(threads) => threads.max(1),
+            None => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    /// Per-device queue depth, never less than one
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.max(1)
+    }
+
+    /// Tokio runtime sized to the configured thread count
+    pub fn runtime(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        let threads = self.threads();
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .max_blocking_threads(threads * self.queue_depth())
+            .enable_all()
+            .build()
+    }
+}
+
+impl MosesConfig {
+    /// `<config dir>/moses/config.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("moses").join("config.json"))
+    }
+
+    /// Read a config file; a missing file gives the defaults
+    pub fn load_from(path: &Path) -> Result<Self, crate::MosesError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| {
+                crate::MosesError::InvalidInput(format!("Invalid config file {}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(crate::MosesError::IoError(e)),
+        }
+    }
+
+    /// Load the user's config with environment overrides applied; problems fall back to defaults
+    pub fn load() -> Self {
+        let mut config = match Self::default_path() {
+            Some(path) => Self::load_from(&path).unwrap_or_else(|e| {
+                tracing::warn!("Using default configuration: {}", e);
+                Self::default()
+            }),
+            None => Self::default(),
+        };
+        config.apply_env();
+        config
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), crate::MosesError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::MosesError::Other(format!("Failed to serialize config: {}", e)))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Save to the default location
+    pub fn save(&self) -> Result<(), crate::MosesError> {
+        let path = Self::default_path()
+            .ok_or_else(|| crate::MosesError::Other("No config directory on this system".to_string()))?;
+        self.save_to(&path)
+    }
+
+    /// Configuration loaded once per process
+    pub fn global() -> &'static MosesConfig {
+        static GLOBAL: OnceLock<MosesConfig> = OnceLock::new();
+        GLOBAL.get_or_init(Self::load)
+    }
+
+    fn apply_env(&mut self) {
+        if let Some(threads) = env_usize(MAX_THREADS_ENV) {
+            self.concurrency.max_threads = Some(threads);
+        }
+        if let Some(depth) = env_usize(QUEUE_DEPTH_ENV) {
+            self.concurrency.queue_depth = depth;
+        }
+        if let Some(size) = env_usize(BLOCK_SIZE_ENV) {
+            self.io.block_size = Some(size);
+        }
+        if let Some(depth) = env_usize(IO_QUEUE_DEPTH_ENV) {
+            self.io.queue_depth = Some(depth);
+        }
+        if let Ok(value) = std::env::var(SANDBOX_PARSING_ENV) {
+            self.worker.sandbox_parsing = matches!(value.trim(), "1" | "true" | "yes");
+        }
+    }
+}
+
+fn env_usize(name: &str) -> Option<usize> {
+    let value = std::env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            tracing::warn!("Ignoring {}={}: not a number", name, value);
+            None
+        }
+    }
+}
+
+/// Caps how many operations run against one device at a time
+pub struct DeviceQueues {
+    depth: usize,
+    /// Lower depths for single devices, e.g. from an I/O probe
+    device_depths: Mutex<HashMap<String, usize>>,
+    in_flight: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+/// A slot in a device's queue, given back when dropped
+pub struct DeviceSlot<'a> {
+    queues: &'a DeviceQueues,
+    device_id: String,
+}
+
+impl DeviceQueues {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            device_depths: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Queues sized from the global configuration
+    pub fn global() -> &'static DeviceQueues {
+        static GLOBAL: OnceLock<DeviceQueues> = OnceLock::new();
+        GLOBAL.get_or_init(|| Self::new(MosesConfig::global().concurrency.queue_depth()))
+    }
+
+    /// Allow at most `depth` operations on one device; never more than the configured depth
+    pub fn set_device_depth(&self, device_id: &str, depth: usize) {
+        let depth = depth.clamp(1, self.depth);
+        self.device_depths.lock().unwrap_or_else(|e| e.into_inner()).insert(device_id.to_string(), depth);
+        // Waiters re-check against the new depth
+        self.released.notify_all();
+    }
+
+    /// Operations allowed on the device at once
+    pub fn depth(&self, device_id: &str) -> usize {
+        self.device_depths.lock().unwrap_or_else(|e| e.into_inner()).get(device_id).copied().unwrap_or(self.depth)
+    }
+
+    /// Block until the device has a free slot
+    pub fn acquire(&self, device_id: &str) -> DeviceSlot<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        while in_flight.get(device_id).copied().unwrap_or(0) >= self.depth(device_id) {
+            in_flight = self.released.wait(in_flight).unwrap_or_else(|e| e.into_inner());
+        }
+        *in_flight.entry(device_id.to_string()).or_insert(0) += 1;
+        DeviceSlot { queues: self, device_id: device_id.to_string() }
+    }
+
+    /// Take a slot only if one is free right now
+    pub fn try_acquire(&self, device_id: &str) -> Option<DeviceSlot<'_>> {
+        let depth = self.depth(device_id);
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let count = in_flight.entry(device_id.to_string()).or_insert(0);
+        if *count >= depth {
+            return None;
+        }
+        *count += 1;
+        Some(DeviceSlot { queues: self, device_id: device_id.to_string() })
+    }
+}
+
+impl Drop for DeviceSlot<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.queues.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = in_flight.get_mut(&self.device_id) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.device_id);
+            }
+        }
+        self.queues.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip_and_defaults() {
+        let dir = std::env::temp_dir().join(format!("moses_config_test_{}", std::process::id()));
+        let path = dir.join("config.json");
+
+        assert_eq!(MosesConfig::load_from(&path).unwrap(), MosesConfig::default());
+
+        let mut config = MosesConfig::default();
+        config.concurrency.max_threads = Some(2);
+        config.save_to(&path).unwrap();
+        assert_eq!(MosesConfig::load_from(&path).unwrap(), config);
+
+        // Older files without the section still load
+        std::fs::write(&path, "{}").unwrap();
+        assert_eq!(MosesConfig::load_from(&path).unwrap().concurrency.queue_depth, DEFAULT_QUEUE_DEPTH);
+        assert!(!MosesConfig::load_from(&path).unwrap().worker.sandbox_parsing);
+        assert!(MosesConfig::load_from(&path).unwrap().io.auto_tune);
+        assert_eq!(MosesConfig::load_from(&path).unwrap().update.channel, ReleaseChannel::Stable);
+
+        std::fs::write(&path, r#"{"update": {"channel": "beta"}}"#).unwrap();
+        let channel = MosesConfig::load_from(&path).unwrap().update.channel;
+        assert!(channel.includes(ReleaseChannel::Stable) && channel.includes(ReleaseChannel::Beta));
+        assert!(!ReleaseChannel::Stable.includes(ReleaseChannel::Beta));
+        assert_eq!("Beta".parse::<ReleaseChannel>().unwrap(), channel);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_device_queue_depth() {
+        let queues = DeviceQueues::new(2);
+        let a = queues.acquire("/dev/sdx");
+        let _b = queues.acquire("/dev/sdx");
+        assert!(queues.try_acquire("/dev/sdx").is_none());
+        assert!(queues.try_acquire("/dev/sdy").is_some());
+
+        drop(a);
+        assert!(queues.try_acquire("/dev/sdx").is_some());
+
+        // A device can be held to fewer, never to more than the configured depth
+        queues.set_device_depth("/dev/sdy", 1);
+        let _c = queues.acquire("/dev/sdy");
+        assert!(queues.try_acquire("/dev/sdy").is_none());
+        queues.set_device_depth("/dev/sdy", 8);
+        assert_eq!(queues.depth("/dev/sdy"), 2);
+    }
+}
@@ -11,6 +11,95 @@ pub struct Device {
     pub is_removable: bool,
     pub is_system: bool,
     pub filesystem: Option<String>,
+    /// Media refuses writes (SD card lock switch, read-only device)
+    #[serde(default)]
+    pub is_write_protected: bool,
+    /// Hardware serial number, when the platform reports one; survives re-enumeration
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Flash erase block size in bytes, when the platform reports it (the preferred erase
+    /// size of eMMC/SD cards, or the discard granularity of other flash)
+    #[serde(default)]
+    pub erase_block_size: Option<u64>,
+    /// Byte offset on its disk, for a device that is one partition of a disk
+    #[serde(default)]
+    pub partition_offset: Option<u64>,
+}
+
+impl Device {
+    /// A device for a disk image file, so formatters and readers can target it like a drive
+    pub fn image_file(path: &std::path::Path) -> Result<Self, crate::MosesError> {
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(crate::MosesError::InvalidInput(format!("{} is not a regular file", path.display())));
+        }
+        // Device helpers treat relative ids as names under /dev, so use an absolute path
+        let absolute = path.canonicalize()?;
+        Ok(Self {
+            id: absolute.to_string_lossy().to_string(),
+            name: path.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+            size: metadata.len(),
+            device_type: DeviceType::Virtual,
+            mount_points: vec![],
+            is_removable: false,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        })
+    }
+
+    /// Whether the device is a disk image file rather than a drive
+    pub fn is_image_file(&self) -> bool {
+        self.device_type == DeviceType::Virtual && std::path::Path::new(&self.id).is_file()
+    }
+
+    /// Fail fast on write-protected media instead of erroring deep inside a format
+    pub fn ensure_writable(&self) -> Result<(), crate::MosesError> {
+        if self.is_write_protected {
+            return Err(crate::MosesError::WriteProtected(format!(
+                "{} refuses writes. Slide an SD card's lock switch to unlocked and reinsert it, or clear the device's read-only flag",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fail if `current`, the device as the platform reports it now, is not the hardware this
+    /// one was enumerated from: a different stick plugged in at the same path between
+    /// selection and format has another size or serial. A serial is only compared when
+    /// both sides have one, since not every platform reports it.
+    pub fn ensure_same_hardware(&self, current: &Device) -> Result<(), crate::MosesError> {
+        if self.size != current.size && self.size != 0 && current.size != 0 {
+            return Err(crate::MosesError::DeviceChanged(format!(
+                "{} was {} bytes when selected but is now {} bytes. Select the drive again before continuing",
+                self.id, self.size, current.size
+            )));
+        }
+        if let (Some(selected), Some(now)) = (&self.serial, &current.serial) {
+            if selected != now {
+                return Err(crate::MosesError::DeviceChanged(format!(
+                    "{} had serial {} when selected but now reports {}. Select the drive again before continuing",
+                    self.id, selected, now
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Flash with a simple controller (SD/eMMC cards, USB sticks), where journal writes and
+    /// misaligned allocation wear the media and cost speed. SSDs manage this themselves.
+    pub fn is_managed_flash(&self) -> bool {
+        match self.device_type {
+            DeviceType::SDCard => true,
+            DeviceType::USB => self.is_removable,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -37,11 +126,86 @@ pub struct DeviceInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Partition {
     pub id: String,
+    /// Byte offset on the disk
+    #[serde(default)]
+    pub start_offset: u64,
     pub size: u64,
     pub filesystem: Option<String>,
     pub mount_point: Option<PathBuf>,
 }
 
+/// Power state a drive can be asked to enter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DevicePowerState {
+    /// Spin up / wake the drive
+    Active,
+    /// Spin down until the next access
+    Standby,
+}
+
+/// What to do with a device once a format or wipe has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostOperationAction {
+    /// Flush and release the device so it can be unplugged
+    Eject,
+    /// Eject and cut power to the device where the platform supports it
+    PowerOff,
+    /// Spin the drive down
+    Standby,
+}
+
+impl PostOperationAction {
+    /// Key in `FormatOptions::additional_options` holding the action
+    pub const OPTION_KEY: &'static str = "post_action";
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().replace('-', "_").as_str() {
+            "eject" => Some(Self::Eject),
+            "power_off" | "poweroff" => Some(Self::PowerOff),
+            "standby" | "spin_down" => Some(Self::Standby),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eject => "eject",
+            Self::PowerOff => "power_off",
+            Self::Standby => "standby",
+        }
+    }
+
+    /// Read the action from format options; unknown values are rejected rather than ignored
+    pub fn from_options(options: &crate::FormatOptions) -> Result<Option<Self>, crate::MosesError> {
+        match options.additional_options.get(Self::OPTION_KEY) {
+            None => Ok(None),
+            Some(value) if value.is_empty() || value == "none" => Ok(None),
+            Some(value) => Self::parse(value).map(Some).ok_or_else(|| {
+                crate::MosesError::InvalidInput(format!(
+                    "Unknown post-operation action '{}' (expected eject, power_off or standby)",
+                    value
+                ))
+            }),
+        }
+    }
+
+    /// Carry out the action through the platform device manager.
+    /// The device is looked up again first since a format changes its volumes and mount points.
+    pub async fn apply(&self, manager: &dyn DeviceManager, device: &Device) -> Result<(), crate::MosesError> {
+        let current = match manager.get_device_by_id(&device.id).await {
+            Ok(Some(current)) => current,
+            _ => device.clone(),
+        };
+        match self {
+            Self::Eject => manager.eject(&current, false).await,
+            Self::PowerOff => manager.eject(&current, true).await,
+            Self::Standby => manager.set_power_state(&current, DevicePowerState::Standby).await,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum PermissionLevel {
     ReadOnly,
@@ -56,4 +220,94 @@ pub trait DeviceManager: Send + Sync {
     async fn get_device_info(&self, device: &Device) -> Result<DeviceInfo, crate::MosesError>;
     async fn is_safe_to_format(&self, device: &Device) -> Result<bool, crate::MosesError>;
     async fn check_permissions(&self, device: &Device) -> Result<PermissionLevel, crate::MosesError>;
-}
\ No newline at end of file
+
+    /// Flush caches and release the device so it can be unplugged safely.
+    /// With `power_off` the device is also powered down where the platform allows it.
+    async fn eject(&self, device: &Device, power_off: bool) -> Result<(), crate::MosesError> {
+        let _ = power_off;
+        Err(crate::MosesError::NotSupported(format!(
+            "Ejecting {} is not supported on this platform",
+            device.name
+        )))
+    }
+
+    /// Re-read `device` from the platform right before a destructive write and fail if it
+    /// is gone or is no longer the same hardware; returns the fresh device
+    async fn verify_unchanged(&self, device: &Device) -> Result<Device, crate::MosesError> {
+        let current = self.get_device_by_id(&device.id).await?
+            .ok_or_else(|| crate::MosesError::DeviceChanged(format!("{} is no longer connected", device.id)))?;
+        device.ensure_same_hardware(&current)?;
+        Ok(current)
+    }
+
+    /// Spin a drive down to standby or wake it back up
+    async fn set_power_state(&self, device: &Device, state: DevicePowerState) -> Result<(), crate::MosesError> {
+        Err(crate::MosesError::NotSupported(format!(
+            "Changing the power state of {} to {:?} is not supported on this platform",
+            device.name, state
+        )))
+    }
+
+    /// The LBA formats (sector sizes) an NVMe namespace can be switched between
+    async fn lba_formats(&self, device: &Device) -> Result<Vec<crate::LbaFormat>, crate::MosesError> {
+        Err(crate::MosesError::NotSupported(format!(
+            "Listing the LBA formats of {} is not supported on this platform",
+            device.name
+        )))
+    }
+
+    /// Reformat an NVMe namespace to the LBA format `index`, erasing everything on it
+    async fn set_lba_format(&self, device: &Device, index: u8) -> Result<(), crate::MosesError> {
+        let _ = index;
+        Err(crate::MosesError::NotSupported(format!(
+            "Changing the LBA format of {} is not supported on this platform",
+            device.name
+        )))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormatOptions;
+
+    #[test]
+    fn test_post_operation_action_from_options() {
+        let mut options = FormatOptions::default();
+        assert_eq!(PostOperationAction::from_options(&options).unwrap(), None);
+
+        options.additional_options.insert(PostOperationAction::OPTION_KEY.to_string(), "power-off".to_string());
+        assert_eq!(PostOperationAction::from_options(&options).unwrap(), Some(PostOperationAction::PowerOff));
+
+        options.additional_options.insert(PostOperationAction::OPTION_KEY.to_string(), "explode".to_string());
+        assert!(PostOperationAction::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_swapped_device_is_detected() {
+        let selected = Device {
+            id: "/dev/sdb".to_string(),
+            name: "USB stick".to_string(),
+            size: 16_000_000_000,
+            device_type: DeviceType::USB,
+            mount_points: Vec::new(),
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: Some("AA01".to_string()),
+            erase_block_size: None,
+            partition_offset: None,
+        };
+        assert!(selected.ensure_same_hardware(&selected.clone()).is_ok());
+
+        let resized = Device { size: 32_000_000_000, ..selected.clone() };
+        assert!(matches!(selected.ensure_same_hardware(&resized), Err(crate::MosesError::DeviceChanged(_))));
+
+        let other_stick = Device { serial: Some("BB02".to_string()), ..selected.clone() };
+        assert!(matches!(selected.ensure_same_hardware(&other_stick), Err(crate::MosesError::DeviceChanged(_))));
+
+        // No serial reported now: size alone decides
+        let no_serial = Device { serial: None, ..selected.clone() };
+        assert!(selected.ensure_same_hardware(&no_serial).is_ok());
+    }
+}
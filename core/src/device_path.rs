@@ -0,0 +1,204 @@
+// Device path resolution
+// A Device is identified by its id: `\\.\PHYSICALDRIVE1` on Windows, `/dev/sdb` on Linux,
+// `/dev/disk2` on macOS, or the path of a disk image. Everything that opens a device turns
+// that id into an OS path here, so readers, writers and formatters all reach the same
+// node. Mount points are deliberately not used: a drive letter or mount directory names
+// one volume, not the disk, and stops being valid as soon as the layout is rewritten.
+
+use crate::Device;
+
+/// Path conventions of an operating system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    Windows,
+    Linux,
+    MacOS,
+}
+
+impl PathStyle {
+    /// Conventions of the platform Moses was built for
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            PathStyle::Windows
+        } else if cfg!(target_os = "macos") {
+            PathStyle::MacOS
+        } else {
+            PathStyle::Linux
+        }
+    }
+}
+
+/// The OS path for a device id
+///
+/// - Windows: `PhysicalDrive1`, `\\.\PHYSICALDRIVE1` and `1` all give `\\.\PHYSICALDRIVE1`;
+///   a volume such as `E:` gives `\\.\E:`.
+/// - Linux: bare names get `/dev/` in front.
+/// - macOS: disks are opened through their raw `/dev/rdiskN` node, which skips the
+///   buffer cache and is many times faster for whole-disk access.
+///
+/// Anything else, such as a disk image path, is returned unchanged.
+pub fn resolve_device_path(id: &str, style: PathStyle) -> String {
+    match style {
+        PathStyle::Windows => {
+            let name = id.strip_prefix(r"\\.\").or_else(|| id.strip_prefix("//./")).unwrap_or(id);
+            if let Some(number) = physical_drive_number(id) {
+                return format!(r"\\.\PHYSICALDRIVE{}", number);
+            }
+            let bytes = name.trim_end_matches(['\\', '/']).as_bytes();
+            if bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+                return format!(r"\\.\{}", &name[..2]);
+            }
+            id.to_string()
+        }
+        PathStyle::Linux => {
+            if id.starts_with('/') || id.starts_with('.') || id.contains('/') {
+                id.to_string()
+            } else {
+                format!("/dev/{}", id)
+            }
+        }
+        PathStyle::MacOS => {
+            let name = id.strip_prefix("/dev/").unwrap_or(id);
+            let is_disk = name.strip_prefix("disk")
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+            if is_disk {
+                format!("/dev/r{}", name)
+            } else if !id.contains('/') && name.starts_with("rdisk") {
+                format!("/dev/{}", name)
+            } else {
+                id.to_string()
+            }
+        }
+    }
+}
+
+/// Disk number of a Windows physical drive id: `\\.\PHYSICALDRIVE2`, `PhysicalDrive2` or `2`
+pub fn physical_drive_number(id: &str) -> Option<u32> {
+    let name = id.strip_prefix(r"\\.\").or_else(|| id.strip_prefix("//./")).unwrap_or(id);
+    let number = match name.get(..13) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("PHYSICALDRIVE") => &name[13..],
+        _ => name,
+    };
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// The id of partition `number` (from 1) of the disk `id`, as the OS names its node:
+/// `/dev/sdb2` or `/dev/nvme0n1p2` on Linux, `/dev/disk2s2` on macOS and
+/// `\\.\Harddisk1Partition2` on Windows. None when `id` is not a whole disk.
+pub fn partition_id(id: &str, number: u32, style: PathStyle) -> Option<String> {
+    match style {
+        PathStyle::Windows => physical_drive_number(id).map(|disk| format!(r"\\.\Harddisk{}Partition{}", disk, number)),
+        PathStyle::Linux => {
+            let disk = resolve_device_path(id, style);
+            // Names ending in a digit (nvme0n1, mmcblk0, loop0) put a "p" before the number
+            let separator = if disk.ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+            disk.starts_with("/dev/").then(|| format!("{}{}{}", disk, separator, number))
+        }
+        PathStyle::MacOS => {
+            let name = id.strip_prefix("/dev/").unwrap_or(id);
+            let name = name.strip_prefix('r').filter(|name| name.starts_with("disk")).unwrap_or(name);
+            let is_disk = name.strip_prefix("disk").is_some_and(|rest| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit()));
+            is_disk.then(|| format!("/dev/{}s{}", name, number))
+        }
+    }
+}
+
+/// The partition number (from 1) in a partition id, the reverse of [`partition_id`].
+/// None for whole disks, volumes and image files.
+pub fn partition_number(id: &str, style: PathStyle) -> Option<u32> {
+    let digits = |text: &str| (!text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())).then(|| text.parse().ok()).flatten();
+    match style {
+        PathStyle::Windows => {
+            let name = id.strip_prefix(r"\\.\").or_else(|| id.strip_prefix("//./")).unwrap_or(id).to_ascii_lowercase();
+            let (disk, number) = name.strip_prefix("harddisk")?.split_once("partition")?;
+            digits(disk).and(digits(number))
+        }
+        PathStyle::Linux => {
+            let name = resolve_device_path(id, style).strip_prefix("/dev/")?.to_string();
+            let stem = name.trim_end_matches(|c: char| c.is_ascii_digit());
+            let number = digits(&name[stem.len()..])?;
+            // nvme0n1p2, mmcblk0p1, loop0p1; otherwise sdb1, vda2, xvdb1
+            match stem.strip_suffix('p') {
+                Some(disk) if disk.ends_with(|c: char| c.is_ascii_digit()) => Some(number),
+                _ => ["sd", "hd", "vd", "xvd"].iter()
+                    .any(|prefix| stem.len() > prefix.len() && stem.starts_with(prefix) && stem[prefix.len()..].bytes().all(|b| b.is_ascii_lowercase()))
+                    .then_some(number),
+            }
+        }
+        PathStyle::MacOS => {
+            let name = id.strip_prefix("/dev/").unwrap_or(id);
+            let name = name.strip_prefix('r').filter(|name| name.starts_with("disk")).unwrap_or(name);
+            let (disk, number) = name.strip_prefix("disk")?.split_once('s')?;
+            digits(disk).and(digits(number))
+        }
+    }
+}
+
+/// The path to open `device` at on this platform
+pub fn device_path(device: &Device) -> String {
+    resolve_device_path(&device.id, PathStyle::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_paths() {
+        let resolve = |id| resolve_device_path(id, PathStyle::Windows);
+        assert_eq!(resolve(r"\\.\physicaldrive1"), r"\\.\PHYSICALDRIVE1");
+        assert_eq!(resolve("PhysicalDrive12"), r"\\.\PHYSICALDRIVE12");
+        assert_eq!(resolve("3"), r"\\.\PHYSICALDRIVE3");
+        assert_eq!(resolve("E:"), r"\\.\E:");
+        assert_eq!(resolve(r"E:\"), r"\\.\E:");
+        assert_eq!(resolve(r"\\.\E:"), r"\\.\E:");
+        assert_eq!(resolve(r"C:\images\card.img"), r"C:\images\card.img");
+        assert_eq!(resolve(r"\\?\Volume{1234}"), r"\\?\Volume{1234}");
+        assert_eq!(physical_drive_number(r"\\.\PHYSICALDRIVE2"), Some(2));
+        assert_eq!(physical_drive_number("physicaldrive7"), Some(7));
+        assert_eq!(physical_drive_number(r"\\.\PHYSICALDRIVE"), None);
+        assert_eq!(physical_drive_number(r"\\.\E:"), None);
+        assert_eq!(partition_id("PhysicalDrive1", 2, PathStyle::Windows).as_deref(), Some(r"\\.\Harddisk1Partition2"));
+        assert_eq!(partition_id("E:", 1, PathStyle::Windows), None);
+        assert_eq!(partition_number(r"\\.\Harddisk1Partition2", PathStyle::Windows), Some(2));
+        assert_eq!(partition_number(r"\\.\PHYSICALDRIVE1", PathStyle::Windows), None);
+    }
+
+    #[test]
+    fn test_linux_paths() {
+        let resolve = |id| resolve_device_path(id, PathStyle::Linux);
+        assert_eq!(resolve("/dev/sdb"), "/dev/sdb");
+        assert_eq!(resolve("sdb"), "/dev/sdb");
+        assert_eq!(resolve("nvme0n1"), "/dev/nvme0n1");
+        assert_eq!(resolve("/home/me/card.img"), "/home/me/card.img");
+        assert_eq!(resolve("images/card.img"), "images/card.img");
+        assert_eq!(partition_id("sdb", 1, PathStyle::Linux).as_deref(), Some("/dev/sdb1"));
+        assert_eq!(partition_id("/dev/nvme0n1", 3, PathStyle::Linux).as_deref(), Some("/dev/nvme0n1p3"));
+        assert_eq!(partition_id("/home/me/card.img", 1, PathStyle::Linux), None);
+        assert_eq!(partition_number("/dev/sdb1", PathStyle::Linux), Some(1));
+        assert_eq!(partition_number("nvme0n1p3", PathStyle::Linux), Some(3));
+        assert_eq!(partition_number("/dev/mmcblk0p1", PathStyle::Linux), Some(1));
+        assert_eq!(partition_number("/dev/nvme0n1", PathStyle::Linux), None);
+        assert_eq!(partition_number("/dev/mmcblk0", PathStyle::Linux), None);
+        assert_eq!(partition_number("/dev/sdb", PathStyle::Linux), None);
+        assert_eq!(partition_number("/home/me/card1", PathStyle::Linux), None);
+    }
+
+    #[test]
+    fn test_macos_paths() {
+        let resolve = |id| resolve_device_path(id, PathStyle::MacOS);
+        assert_eq!(resolve("/dev/disk2"), "/dev/rdisk2");
+        assert_eq!(resolve("disk2s1"), "/dev/rdisk2s1");
+        assert_eq!(resolve("/dev/rdisk2"), "/dev/rdisk2");
+        assert_eq!(resolve("rdisk4"), "/dev/rdisk4");
+        assert_eq!(resolve("/Users/me/card.img"), "/Users/me/card.img");
+        assert_eq!(resolve("/dev/diskless"), "/dev/diskless");
+        assert_eq!(partition_id("/dev/rdisk2", 1, PathStyle::MacOS).as_deref(), Some("/dev/disk2s1"));
+        assert_eq!(partition_id("disk2s1", 1, PathStyle::MacOS), None);
+        assert_eq!(partition_number("/dev/rdisk2s1", PathStyle::MacOS), Some(1));
+        assert_eq!(partition_number("/dev/disk2", PathStyle::MacOS), None);
+    }
+}
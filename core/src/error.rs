@@ -26,6 +26,12 @@ pub enum MosesError {
     #[error("Device is not safe to format: {0}")]
     UnsafeDevice(String),
     
+    #[error("Device is write-protected: {0}")]
+    WriteProtected(String),
+    
+    #[error("Device changed since it was selected: {0}")]
+    DeviceChanged(String),
+    
     #[error("Safety violation: {0}")]
     SafetyViolation(String),
     
@@ -53,9 +59,45 @@ pub enum MosesError {
     #[error("External command failed: {0}")]
     External(String),
     
+    #[error("{driver} {required_version} or newer is required for mounting (installed: {installed}). Download it from {download_url}")]
+    MountDriverMissing {
+        driver: String,
+        required_version: String,
+        /// Installed version, or "none"
+        installed: String,
+        download_url: String,
+    },
+    
     #[error("Not supported: {0}")]
     NotSupported(String),
     
     #[error("Other error: {0}")]
     Other(String),
-}
\ No newline at end of file
+}
+impl MosesError {
+    /// Stable short name of the variant, used to group failures in the local metrics
+    pub fn code(&self) -> &'static str {
+        match self {
+            MosesError::DeviceNotFound(_) => "device_not_found",
+            MosesError::InsufficientPrivileges(_) => "insufficient_privileges",
+            MosesError::FormatError(_) | MosesError::Format(_) => "format_failed",
+            MosesError::PlatformNotSupported(_) => "platform_not_supported",
+            MosesError::ExternalToolMissing(_) | MosesError::ToolNotFound(_) => "tool_missing",
+            MosesError::UserCancelled => "cancelled",
+            MosesError::SimulationOnly(_) => "simulation_only",
+            MosesError::UnsafeDevice(_) => "unsafe_device",
+            MosesError::WriteProtected(_) => "write_protected",
+            MosesError::DeviceChanged(_) => "device_changed",
+            MosesError::SafetyViolation(_) => "safety_violation",
+            MosesError::IoError(_) => "io",
+            MosesError::SerializationError(_) => "serialization",
+            MosesError::Configuration(_) => "configuration",
+            MosesError::InvalidInput(_) => "invalid_input",
+            MosesError::Timeout(_) => "timeout",
+            MosesError::External(_) => "external_command",
+            MosesError::MountDriverMissing { .. } => "mount_driver_missing",
+            MosesError::NotSupported(_) => "not_supported",
+            MosesError::Other(_) => "other",
+        }
+    }
+}
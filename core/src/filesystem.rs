@@ -31,6 +31,68 @@ impl Default for FormatOptions {
     }
 }
 
+/// Layout preset applied on top of a formatter's defaults
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormatPreset {
+    /// SD Association layout on SD cards, the formatter's own defaults elsewhere
+    #[default]
+    Auto,
+    /// The formatter's own defaults, even on SD cards
+    Generic,
+    /// Cluster size, partition offset and alignment per the SD Association
+    /// File System Specification, as the SD Formatter tool lays cards out
+    SdCard,
+    /// Flash media: ext4 without a journal (or with asynchronous commits) and allocation
+    /// aligned to the erase block; FAT layouts as for `Auto`
+    Flash,
+}
+
+impl FormatPreset {
+    /// Key in `FormatOptions::additional_options` selecting the preset
+    pub const OPTION_KEY: &'static str = "preset";
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().replace('_', "-").as_str() {
+            "auto" => Some(Self::Auto),
+            "generic" | "none" => Some(Self::Generic),
+            "sd-card" | "sd" | "sdcard" => Some(Self::SdCard),
+            "flash" => Some(Self::Flash),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Generic => "generic",
+            Self::SdCard => "sd-card",
+            Self::Flash => "flash",
+        }
+    }
+
+    pub fn from_options(options: &FormatOptions) -> Result<Self, MosesError> {
+        match options.additional_options.get(Self::OPTION_KEY) {
+            None => Ok(Self::default()),
+            Some(value) => Self::parse(value).ok_or_else(|| {
+                MosesError::InvalidInput(format!(
+                    "Unknown preset '{}' (expected auto, generic, sd-card or flash)",
+                    value
+                ))
+            }),
+        }
+    }
+
+    /// Whether the SD card layout applies to `device`
+    pub fn uses_sd_layout(&self, device: &Device) -> bool {
+        match self {
+            Self::Auto | Self::Flash => device.device_type == crate::DeviceType::SDCard,
+            Self::Generic => false,
+            Self::SdCard => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationReport {
     pub device: Device,
@@ -40,6 +102,144 @@ pub struct SimulationReport {
     pub required_tools: Vec<String>,
     pub will_erase_data: bool,
     pub space_after_format: u64,
+    /// Which implementation will run and why, for filesystems with more than one
+    #[serde(default)]
+    pub strategy: Option<String>,
+    /// Pre-flight checklist of filesystem-specific problems with these options on this device
+    #[serde(default)]
+    pub lints: Vec<SafetyLint>,
+    /// What is on the device now, one entry per volume that could be read
+    #[serde(default)]
+    pub contents: Vec<ContentSummary>,
+    /// What Moses will do, in the order it runs, each with its expected duration
+    #[serde(default)]
+    pub steps: Vec<PlannedStep>,
+    /// An administrator prompt will appear before the device is written
+    #[serde(default)]
+    pub requires_elevation: bool,
+    /// Mounted volumes that will be dismounted and held locked during the format
+    #[serde(default)]
+    pub locked_volumes: Vec<String>,
+}
+
+/// What kind of work a [`PlannedStep`] does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Elevate,
+    Dismount,
+    PartitionTable,
+    Format,
+    Cleanup,
+    Verify,
+    Seed,
+    PostAction,
+}
+
+/// One step of the plan in a [`SimulationReport`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedStep {
+    pub kind: StepKind,
+    /// e.g. "Dismount and lock E:\\"
+    pub description: String,
+    pub estimated_time: std::time::Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LintSeverity {
+    /// Worth knowing, e.g. which operating systems can read the result
+    Info,
+    /// The format will work but the result may surprise the user
+    Warning,
+    /// The format is expected to fail or produce an unusable volume
+    Error,
+}
+
+/// One entry of the pre-flight checklist in a [`SimulationReport`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SafetyLint {
+    pub severity: LintSeverity,
+    /// Stable identifier, e.g. "fat32-large-volume"
+    pub code: String,
+    pub message: String,
+    /// Moses can resolve this by adjusting the format options
+    pub auto_fixable: bool,
+    /// What the fix does, or how the user can resolve it
+    #[serde(default)]
+    pub fix: Option<String>,
+}
+
+impl SafetyLint {
+    pub fn new(severity: LintSeverity, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            message: message.into(),
+            auto_fixable: false,
+            fix: None,
+        }
+    }
+
+    /// Describe how to resolve the problem by hand
+    pub fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    /// Describe the option change Moses makes when asked to fix this automatically
+    pub fn auto_fix(mut self, fix: impl Into<String>) -> Self {
+        self.auto_fixable = true;
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// Files on an existing volume, so a format is confirmed against what it will destroy
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentSummary {
+    /// Mount point, or the volume on the device itself
+    pub location: String,
+    pub filesystem: String,
+    pub total_files: u64,
+    pub total_bytes: u64,
+    /// Usage per top-level directory, largest first; files in the root count under "/"
+    pub directories: Vec<DirectoryUsage>,
+    /// Largest files found, largest first
+    pub largest_files: Vec<FileUsage>,
+    /// Recycle bins and trash folders, counted here instead of in `directories`
+    #[serde(default)]
+    pub trash: Vec<DirectoryUsage>,
+    /// False when the walk stopped at its entry limit and the totals are a lower bound
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryUsage {
+    pub path: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileUsage {
+    pub path: String,
+    pub size: u64,
+}
+
+impl SimulationReport {
+    /// Expected duration of the whole plan, or of the format alone when there is no plan
+    pub fn total_time(&self) -> std::time::Duration {
+        if self.steps.is_empty() {
+            self.estimated_time
+        } else {
+            self.steps.iter().map(|step| step.estimated_time).sum()
+        }
+    }
+
+    /// Lints serious enough that the format should not go ahead as configured
+    pub fn blocking_lints(&self) -> impl Iterator<Item = &SafetyLint> {
+        self.lints.iter().filter(|lint| lint.severity == LintSeverity::Error)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -73,6 +273,11 @@ pub trait FilesystemFormatter: Send + Sync {
     fn requires_external_tools(&self) -> bool;
     fn bundled_tools(&self) -> Vec<&'static str>;
     
+    /// Whether the external tools this formatter runs are installed
+    fn tools_available(&self) -> bool {
+        true
+    }
+    
     async fn format(
         &self,
         device: &Device,
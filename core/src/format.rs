@@ -22,6 +22,7 @@ impl FormatManager {
                 options.filesystem_type
             )))?;
         
+        device.ensure_writable()?;
         formatter.validate_options(options).await?;
         formatter.dry_run(device, options).await
     }
@@ -38,7 +39,13 @@ impl FormatManager {
                 options.filesystem_type
             )))?;
         
+        device.ensure_writable()?;
         formatter.validate_options(options).await?;
-        formatter.format(device, options).await
+        
+        let cache = crate::FilesystemCache::global();
+        cache.invalidate(device);
+        formatter.format(device, options).await?;
+        cache.insert(device, 0, crate::CachedFilesystemInfo::detected_now(options.filesystem_type.clone()));
+        Ok(())
     }
 }
\ No newline at end of file
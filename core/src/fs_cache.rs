@@ -0,0 +1,359 @@
+// Filesystem detection cache shared by the GUI, worker and CLI
+// Entries are keyed by device serial + byte offset of the volume on the disk and tagged
+// with the disk's change generation. The serial follows a stick to whatever path it is
+// plugged in at, and another stick at the same path never sees its entries; devices
+// without a serial fall back to their id. A disk and its partitions share a serial, so
+// they share one entry: any write or format through Moses, to the disk or to one of its
+// partitions, bumps the generation and hides everything older on that disk. A partition
+// whose offset the platform did not report is never cached. A TTL covers changes made
+// outside Moses.
+use crate::device_path::{partition_number, PathStyle};
+use crate::Device;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How long an entry is trusted without a write through Moses
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedFilesystemInfo {
+    pub filesystem: String,
+    pub partition_table: Option<String>,
+    pub partitions: Vec<PartitionInfo>,
+    pub detected_at: SystemTime,
+}
+
+impl CachedFilesystemInfo {
+    /// Info for a filesystem Moses just created or detected, without partition details
+    pub fn detected_now(filesystem: impl Into<String>) -> Self {
+        Self {
+            filesystem: filesystem.into(),
+            partition_table: None,
+            partitions: Vec::new(),
+            detected_at: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    pub number: u32,
+    pub filesystem: Option<String>,
+    pub size: u64,
+    pub start_offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VolumeEntry {
+    partition_offset: u64,
+    generation: u64,
+    info: CachedFilesystemInfo,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DeviceEntry {
+    generation: u64,
+    volumes: Vec<VolumeEntry>,
+}
+
+pub struct FilesystemCache {
+    devices: RwLock<HashMap<String, DeviceEntry>>,
+    ttl: Duration,
+    path: Option<PathBuf>,
+    /// Modification time of the backing file when we last read or wrote it
+    loaded_at: RwLock<Option<SystemTime>>,
+}
+
+impl FilesystemCache {
+    /// In-memory cache
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            devices: RwLock::new(HashMap::new()),
+            ttl,
+            path: None,
+            loaded_at: RwLock::new(None),
+        }
+    }
+
+    /// Cache backed by a JSON file, so short-lived processes like the CLI can share it
+    pub fn persistent(path: PathBuf, ttl: Duration) -> Self {
+        let cache = Self {
+            devices: RwLock::new(HashMap::new()),
+            ttl,
+            path: Some(path),
+            loaded_at: RwLock::new(None),
+        };
+        cache.reload_if_changed();
+        cache
+    }
+
+    /// Process-wide cache stored in the user's cache directory
+    pub fn global() -> &'static FilesystemCache {
+        static GLOBAL: OnceLock<FilesystemCache> = OnceLock::new();
+        GLOBAL.get_or_init(|| match Self::default_path() {
+            Some(path) => Self::persistent(path, DEFAULT_CACHE_TTL),
+            None => Self::new(DEFAULT_CACHE_TTL),
+        })
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("moses").join("filesystem_cache.json"))
+    }
+
+    /// The key `device` is cached under: its serial, shared by a disk and its partitions,
+    /// or its id when the platform reports no serial
+    fn key(device: &Device) -> String {
+        match device.serial.as_deref().map(str::trim).filter(|serial| !serial.is_empty()) {
+            Some(serial) => format!("serial:{}", serial),
+            None => format!("id:{}", device.id),
+        }
+    }
+
+    /// Offset on the disk of the volume at `partition_offset` within `device`, or None
+    /// for a partition device whose own offset is unknown
+    fn disk_offset(device: &Device, partition_offset: u64) -> Option<u64> {
+        match device.partition_offset {
+            Some(offset) => offset.checked_add(partition_offset),
+            None if partition_number(&device.id, PathStyle::current()).is_some() => None,
+            None => Some(partition_offset),
+        }
+    }
+
+    /// Get fresh cached info for the volume at `partition_offset` (0 for the whole device)
+    pub fn get(&self, device: &Device, partition_offset: u64) -> Option<CachedFilesystemInfo> {
+        let partition_offset = Self::disk_offset(device, partition_offset)?;
+        self.reload_if_changed();
+        let devices = self.devices.read().ok()?;
+        let device = devices.get(&Self::key(device))?;
+        device.volumes.iter()
+            .find(|v| v.partition_offset == partition_offset && v.generation == device.generation)
+            .filter(|v| self.is_fresh(&v.info))
+            .map(|v| v.info.clone())
+    }
+
+    /// Shorthand for the filesystem of the whole device
+    pub fn filesystem(&self, device: &Device) -> Option<String> {
+        self.get(device, 0).map(|info| info.filesystem)
+    }
+
+    /// Store detection results for the current generation of a device
+    pub fn insert(&self, device: &Device, partition_offset: u64, info: CachedFilesystemInfo) {
+        let Some(partition_offset) = Self::disk_offset(device, partition_offset) else {
+            tracing::debug!("Not caching filesystem info for {}: its offset on the disk is unknown", device.id);
+            return;
+        };
+        tracing::info!("Caching filesystem info for {} at offset {}: {}", device.id, partition_offset, info.filesystem);
+        self.reload_if_changed();
+        if let Ok(mut devices) = self.devices.write() {
+            let device = devices.entry(Self::key(device)).or_default();
+            let generation = device.generation;
+            device.volumes.retain(|v| v.partition_offset != partition_offset && v.generation == generation);
+            device.volumes.push(VolumeEntry { partition_offset, generation, info });
+        }
+        self.save();
+    }
+
+    /// Mark a device as changed by Moses; everything cached for its disk becomes invalid
+    pub fn invalidate(&self, device: &Device) {
+        tracing::info!("Invalidating filesystem cache for {}", device.id);
+        self.reload_if_changed();
+        if let Ok(mut devices) = self.devices.write() {
+            let device = devices.entry(Self::key(device)).or_default();
+            device.generation += 1;
+            device.volumes.clear();
+        }
+        self.save();
+    }
+
+    /// Current change generation of a device's disk
+    pub fn generation(&self, device: &Device) -> u64 {
+        self.reload_if_changed();
+        self.devices.read()
+            .ok()
+            .and_then(|devices| devices.get(&Self::key(device)).map(|d| d.generation))
+            .unwrap_or(0)
+    }
+
+    /// Drop every cached entry
+    pub fn clear(&self) {
+        tracing::info!("Clearing filesystem cache");
+        if let Ok(mut devices) = self.devices.write() {
+            devices.clear();
+        }
+        self.save();
+    }
+
+    /// Whether an entry is young enough to trust for changes made outside Moses
+    pub fn is_fresh(&self, info: &CachedFilesystemInfo) -> bool {
+        info.detected_at.elapsed().map(|age| age < self.ttl).unwrap_or(false)
+    }
+
+    fn file_mtime(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Pick up changes another process (e.g. the elevated worker) wrote to the backing file
+    fn reload_if_changed(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mtime = self.file_mtime();
+        if mtime.is_none() || self.loaded_at.read().map(|t| *t == mtime).unwrap_or(true) {
+            return;
+        }
+        let loaded: Option<HashMap<String, DeviceEntry>> = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+        if let (Some(loaded), Ok(mut devices)) = (loaded, self.devices.write()) {
+            *devices = loaded;
+        }
+        if let Ok(mut loaded_at) = self.loaded_at.write() {
+            *loaded_at = mtime;
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let Ok(devices) = self.devices.read() else {
+            return;
+        };
+        let result = serde_json::to_string(&*devices)
+            .map_err(std::io::Error::other)
+            .and_then(|json| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, json)
+            });
+        match result {
+            Ok(()) => {
+                if let Ok(mut loaded_at) = self.loaded_at.write() {
+                    *loaded_at = self.file_mtime();
+                }
+            }
+            Err(e) => tracing::warn!("Failed to save filesystem cache to {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    fn stick(id: &str, serial: Option<&str>) -> Device {
+        Device {
+            id: id.to_string(),
+            name: "USB stick".to_string(),
+            size: 16 << 30,
+            device_type: DeviceType::USB,
+            mount_points: Vec::new(),
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: serial.map(str::to_string),
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_invalidate_hides_old_generation() {
+        let cache = FilesystemCache::new(DEFAULT_CACHE_TTL);
+        let disk = stick("/dev/sdb", Some("AA01"));
+        cache.insert(&disk, 0, CachedFilesystemInfo::detected_now("exfat"));
+        cache.insert(&disk, 1048576, CachedFilesystemInfo::detected_now("ntfs"));
+        assert_eq!(cache.filesystem(&disk).as_deref(), Some("exfat"));
+
+        cache.invalidate(&disk);
+        assert_eq!(cache.generation(&disk), 1);
+        assert!(cache.get(&disk, 0).is_none());
+        assert!(cache.get(&disk, 1048576).is_none());
+
+        cache.insert(&disk, 0, CachedFilesystemInfo::detected_now("ext4"));
+        assert_eq!(cache.filesystem(&disk).as_deref(), Some("ext4"));
+    }
+
+    #[test]
+    fn test_keyed_by_serial() {
+        let cache = FilesystemCache::new(DEFAULT_CACHE_TTL);
+        cache.insert(&stick("/dev/sdb", Some("AA01")), 0, CachedFilesystemInfo::detected_now("exfat"));
+        // Another stick plugged in at the same path
+        assert!(cache.filesystem(&stick("/dev/sdb", Some("BB02"))).is_none());
+        // The same stick after re-enumeration
+        assert_eq!(cache.filesystem(&stick("/dev/sdc", Some("AA01"))).as_deref(), Some("exfat"));
+        // Without a serial the id is all there is
+        cache.insert(&stick("/dev/sdd", None), 0, CachedFilesystemInfo::detected_now("fat32"));
+        assert_eq!(cache.filesystem(&stick("/dev/sdd", Some(" "))).as_deref(), Some("fat32"));
+        assert!(cache.filesystem(&stick("/dev/sde", None)).is_none());
+    }
+
+    fn partition(id: &str, serial: Option<&str>, offset: Option<u64>) -> Device {
+        Device { partition_offset: offset, size: 8 << 30, ..stick(id, serial) }
+    }
+
+    #[test]
+    fn test_disk_and_partitions_invalidate_together() {
+        let cache = FilesystemCache::new(DEFAULT_CACHE_TTL);
+        let disk = stick("/dev/sdb", Some("AA01"));
+        let first = partition("/dev/sdb1", Some("AA01"), Some(1048576));
+        let second = partition("/dev/sdb2", Some("AA01"), Some(8 << 30));
+        cache.insert(&first, 0, CachedFilesystemInfo::detected_now("ntfs"));
+        cache.insert(&second, 0, CachedFilesystemInfo::detected_now("ext4"));
+        // The same volume seen through the disk
+        assert_eq!(cache.get(&disk, 1048576).map(|info| info.filesystem).as_deref(), Some("ntfs"));
+
+        // Formatting the disk evicts its partitions
+        cache.invalidate(&disk);
+        assert!(cache.filesystem(&first).is_none());
+        assert!(cache.filesystem(&second).is_none());
+
+        // Writing one partition evicts the disk and its other partitions
+        cache.insert(&disk, 0, CachedFilesystemInfo::detected_now("gpt"));
+        cache.insert(&second, 0, CachedFilesystemInfo::detected_now("ext4"));
+        cache.invalidate(&first);
+        assert!(cache.filesystem(&disk).is_none());
+        assert!(cache.filesystem(&second).is_none());
+        assert_eq!(cache.generation(&second), 2);
+    }
+
+    #[test]
+    fn test_partition_without_offset_is_not_cached() {
+        let cache = FilesystemCache::new(DEFAULT_CACHE_TTL);
+        let unknown = partition("/dev/sdb1", Some("AA01"), None);
+        cache.insert(&unknown, 0, CachedFilesystemInfo::detected_now("fat32"));
+        assert!(cache.filesystem(&unknown).is_none());
+        // Nor mistaken for the disk itself
+        assert!(cache.filesystem(&stick("/dev/sdb", Some("AA01"))).is_none());
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let cache = FilesystemCache::new(Duration::from_secs(60));
+        let disk = stick("/dev/sdb", Some("AA01"));
+        let mut info = CachedFilesystemInfo::detected_now("fat32");
+        info.detected_at = SystemTime::now() - Duration::from_secs(120);
+        cache.insert(&disk, 0, info);
+        assert!(cache.get(&disk, 0).is_none());
+    }
+
+    #[test]
+    fn test_persistence_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filesystem_cache.json");
+        let disk = stick("/dev/sdb", Some("AA01"));
+        {
+            let cache = FilesystemCache::persistent(path.clone(), DEFAULT_CACHE_TTL);
+            cache.insert(&disk, 0, CachedFilesystemInfo::detected_now("ext4"));
+        }
+        let reloaded = FilesystemCache::persistent(path, DEFAULT_CACHE_TTL);
+        assert_eq!(reloaded.filesystem(&disk).as_deref(), Some("ext4"));
+    }
+}
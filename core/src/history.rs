@@ -0,0 +1,227 @@
+// Per-device operation history
+// Before Moses formats, cleans or converts a disk it records what was there: filesystem,
+// label and partition layout. Entries are keyed by hardware serial where the platform
+// reports one, so a stick keeps its history when it comes back under another /dev name.
+// The store is a small JSON file shared by the GUI, worker and CLI; every access re-reads
+// it because the elevated worker writes entries the GUI then shows.
+use crate::fs_cache::PartitionInfo;
+use crate::Device;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// Entries kept per device; older ones are dropped
+pub const MAX_ENTRIES_PER_DEVICE: usize = 10;
+
+/// What a device looked like at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayoutSnapshot {
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+    /// "mbr", "gpt" or None for a superfloppy/blank device
+    pub partition_table: Option<String>,
+    pub partitions: Vec<PartitionInfo>,
+    /// Hex-encoded partition table sectors (LBA 0-33) so the layout can be put back
+    #[serde(default)]
+    pub table_sectors: Option<String>,
+}
+
+impl LayoutSnapshot {
+    /// One-line description, e.g. `ext4 "BACKUP"` or `gpt, 2 partitions`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(filesystem) = &self.filesystem {
+            match &self.label {
+                Some(label) => parts.push(format!("{} \"{}\"", filesystem, label)),
+                None => parts.push(filesystem.clone()),
+            }
+        }
+        if let Some(table) = &self.partition_table {
+            parts.push(format!("{}, {} partition(s)", table, self.partitions.len()));
+        }
+        if parts.is_empty() {
+            "blank or unrecognised".to_string()
+        } else {
+            parts.join("; ")
+        }
+    }
+}
+
+/// One destructive operation Moses ran against a device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    /// e.g. "format as fat32", "clean (Quick)", "convert to GPT"
+    pub operation: String,
+    /// Device path at the time, which may differ from the current one
+    pub device_id: String,
+    /// Layout just before the operation
+    pub before: LayoutSnapshot,
+}
+
+pub struct DeviceHistory {
+    path: Option<PathBuf>,
+    /// Used when there is no backing file
+    memory: Mutex<HashMap<String, Vec<HistoryEntry>>>,
+}
+
+impl DeviceHistory {
+    /// In-memory history
+    pub fn new() -> Self {
+        Self { path: None, memory: Mutex::new(HashMap::new()) }
+    }
+
+    /// History backed by a JSON file
+    pub fn persistent(path: PathBuf) -> Self {
+        Self { path: Some(path), memory: Mutex::new(HashMap::new()) }
+    }
+
+    /// Process-wide history stored in the user's data directory
+    pub fn global() -> &'static DeviceHistory {
+        static GLOBAL: OnceLock<DeviceHistory> = OnceLock::new();
+        GLOBAL.get_or_init(|| match dirs::data_local_dir() {
+            Some(dir) => Self::persistent(dir.join("moses").join("device_history.json")),
+            None => Self::new(),
+        })
+    }
+
+    /// Key a device is filed under: its serial, or its path and size when it has none
+    pub fn key(device: &Device) -> String {
+        match &device.serial {
+            Some(serial) => format!("serial:{}", serial),
+            None => format!("path:{}:{}", device.id, device.size),
+        }
+    }
+
+    /// Record an operation, newest first
+    pub fn record(&self, device: &Device, operation: impl Into<String>, before: LayoutSnapshot) {
+        let entry = HistoryEntry {
+            at: Utc::now(),
+            operation: operation.into(),
+            device_id: device.id.clone(),
+            before,
+        };
+        tracing::info!("Recording history for {}: {}", device.id, entry.operation);
+        self.update(|devices| {
+            let entries = devices.entry(Self::key(device)).or_default();
+            entries.insert(0, entry);
+            entries.truncate(MAX_ENTRIES_PER_DEVICE);
+        });
+    }
+
+    /// Everything recorded for a device, newest first
+    pub fn entries(&self, device: &Device) -> Vec<HistoryEntry> {
+        self.load().remove(&Self::key(device)).unwrap_or_default()
+    }
+
+    /// When Moses last changed the device
+    pub fn last_touched(&self, device: &Device) -> Option<DateTime<Utc>> {
+        self.entries(device).first().map(|entry| entry.at)
+    }
+
+    /// Forget a device's history
+    pub fn clear(&self, device: &Device) {
+        self.update(|devices| {
+            devices.remove(&Self::key(device));
+        });
+    }
+
+    fn load(&self) -> HashMap<String, Vec<HistoryEntry>> {
+        match &self.path {
+            Some(path) => std::fs::read_to_string(path)
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            None => self.memory.lock().map(|devices| devices.clone()).unwrap_or_default(),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut HashMap<String, Vec<HistoryEntry>>)) {
+        // Serialise read-modify-write within this process
+        let Ok(mut memory) = self.memory.lock() else {
+            return;
+        };
+        let Some(path) = &self.path else {
+            change(&mut memory);
+            return;
+        };
+        let mut devices = self.load();
+        change(&mut devices);
+        let result = serde_json::to_string(&devices)
+            .map_err(std::io::Error::other)
+            .and_then(|json| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, json)
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save device history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Default for DeviceHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    fn device(id: &str, serial: Option<&str>) -> Device {
+        Device {
+            id: id.to_string(),
+            name: "stick".to_string(),
+            size: 8 * 1024 * 1024 * 1024,
+            device_type: DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: serial.map(str::to_string),
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_history_follows_serial() {
+        let path = std::env::temp_dir().join(format!("moses_history_test_{}.json", std::process::id()));
+        let history = DeviceHistory::persistent(path.clone());
+        let before = LayoutSnapshot {
+            filesystem: Some("ext4".to_string()),
+            label: Some("BACKUP".to_string()),
+            ..Default::default()
+        };
+        history.record(&device("/dev/sdb", Some("4C530001")), "format as fat32", before.clone());
+
+        // Same stick, enumerated under another name
+        let entries = DeviceHistory::persistent(path.clone()).entries(&device("/dev/sdc", Some("4C530001")));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].before, before);
+        assert_eq!(entries[0].device_id, "/dev/sdb");
+        assert_eq!(before.summary(), "ext4 \"BACKUP\"");
+
+        assert!(history.entries(&device("/dev/sdb", None)).is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let history = DeviceHistory::new();
+        let stick = device("/dev/sdb", None);
+        for i in 0..MAX_ENTRIES_PER_DEVICE + 3 {
+            history.record(&stick, format!("op {}", i), LayoutSnapshot::default());
+        }
+        let entries = history.entries(&stick);
+        assert_eq!(entries.len(), MAX_ENTRIES_PER_DEVICE);
+        assert_eq!(entries[0].operation, format!("op {}", MAX_ENTRIES_PER_DEVICE + 2));
+    }
+}
@@ -1,20 +1,52 @@
+pub mod audit;
+pub mod config;
 pub mod device;
+pub mod device_path;
 pub mod error;
 pub mod filesystem;
 pub mod format;
+pub mod fs_cache;
+pub mod history;
+pub mod metadata_patch;
+pub mod metrics;
+pub mod nvme;
 pub mod registry;
 pub mod plugin;
+pub mod progress;
 pub mod safety;
 pub mod safety_extensions;
+pub mod source_uri;
 
 pub mod test_utils;
 
-pub use device::{Device, DeviceInfo, DeviceManager, DeviceType, PermissionLevel, Partition};
+pub use audit::{AuditLog, AuditRecord};
+pub use config::{
+    ConcurrencyConfig, DeviceQueues, EventsConfig, MosesConfig, MqttConfig, ToolsConfig, WebhookConfig,
+    IoConfig, ReleaseChannel, UpdateConfig, WorkerConfig,
+};
+pub use device::{
+    Device, DeviceInfo, DeviceManager, DevicePowerState, DeviceType, PermissionLevel, Partition,
+    PostOperationAction,
+};
+pub use device_path::{device_path, partition_id, partition_number, physical_drive_number, resolve_device_path, PathStyle};
 pub use error::MosesError;
-pub use filesystem::{FilesystemFormatter, FormatOptions, Platform, SimulationReport};
+pub use filesystem::{
+    ContentSummary, DirectoryUsage, FileUsage, FilesystemFormatter, FormatOptions, FormatPreset, LintSeverity,
+    PlannedStep, Platform, SafetyLint, SimulationReport, StepKind,
+};
 pub use format::FormatManager;
-pub use registry::{FormatterRegistry, FormatterMetadata, FormatterCategory, FormatterCapabilities, FormatterMetadataBuilder};
+pub use fs_cache::{FilesystemCache, CachedFilesystemInfo, PartitionInfo};
+pub use history::{DeviceHistory, HistoryEntry, LayoutSnapshot};
+pub use metadata_patch::{MetadataPatch, PatchRange};
+pub use metrics::{LocalMetrics, MetricRecord, MetricsSummary, OperationStats};
+pub use nvme::LbaFormat;
+pub use registry::{
+    AvailabilityContext, FormatStrategy, FormatterAvailability, FormatterCapabilities, FormatterCategory,
+    FormatterMetadata, FormatterMetadataBuilder, FormatterRegistry, RequiredPermission, SelectedFormatter,
+};
+pub use progress::{ProgressTracker, ProgressUpdate, ThroughputEstimator};
 pub use plugin::{MosesPlugin, FormatterPlugin, ScriptFormatter};
+pub use source_uri::{SourceKind, SourceUri};
 pub use safety::{SafetyCheck, SafetyValidation, SafeFormatter, RiskLevel};
 pub use safety_extensions::{
     LockedDevice, SafetyApproval, OsVerification, OsDeviceVerifier,
@@ -0,0 +1,270 @@
+// Metadata patches - small, undoable in-place edits of on-disk structures
+// Label, UUID and feature edits change a few bytes of a superblock or boot sector. Every
+// editor builds a MetadataPatch holding each range it changes with its old and new bytes.
+// A dry run prints the patch as a diff; a real run saves the inverse patch as an undo
+// record, checks that the device still holds the old bytes, and writes the ranges. If a
+// write fails partway the ranges already written are put back, so the device ends up
+// either fully patched or as it was. Undoing is applying the saved inverse the same way.
+use crate::MosesError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Marks a file as an undo record, so arbitrary JSON is not written to a disk
+const UNDO_KIND: &str = "moses-metadata-undo";
+
+/// One byte range a patch changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchRange {
+    /// What the bytes are, e.g. "primary superblock: volume name"
+    pub field: String,
+    pub offset: u64,
+    #[serde(with = "hex_bytes")]
+    pub before: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub after: Vec<u8>,
+}
+
+impl PatchRange {
+    /// The changed part as `offset: old -> new`, with unchanged bytes at either end trimmed
+    pub fn diff(&self) -> String {
+        let same = |(a, b): (&u8, &u8)| a == b;
+        let prefix = self.before.iter().zip(&self.after).take_while(|&pair| same(pair)).count();
+        let suffix = self.before[prefix..].iter().rev().zip(self.after[prefix..].iter().rev()).take_while(|&pair| same(pair)).count();
+        let end = self.before.len() - suffix;
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        format!(
+            "{} @ 0x{:X}: {} -> {}",
+            self.field, self.offset + prefix as u64, hex(&self.before[prefix..end]), hex(&self.after[prefix..end])
+        )
+    }
+}
+
+/// A set of in-place edits applied together
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataPatch {
+    kind: String,
+    /// e.g. "set ext4 label to BACKUP"
+    pub description: String,
+    pub device_id: String,
+    pub created: DateTime<Utc>,
+    pub ranges: Vec<PatchRange>,
+}
+
+impl MetadataPatch {
+    pub fn new(description: impl Into<String>, device_id: impl Into<String>) -> Self {
+        Self {
+            kind: UNDO_KIND.to_string(),
+            description: description.into(),
+            device_id: device_id.into(),
+            created: Utc::now(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Change the bytes at `offset` from `before` to `after`; identical bytes are left out
+    pub fn set(&mut self, field: impl Into<String>, offset: u64, before: Vec<u8>, after: Vec<u8>) -> Result<(), MosesError> {
+        if before.len() != after.len() {
+            return Err(MosesError::Other(format!(
+                "patch of {} bytes at 0x{:X} would replace {} bytes", after.len(), offset, before.len()
+            )));
+        }
+        if before != after {
+            self.ranges.push(PatchRange { field: field.into(), offset, before, after });
+        }
+        Ok(())
+    }
+
+    /// As [`set`](Self::set), reading the current bytes from `reader`
+    pub fn set_from<R: Read + Seek>(&mut self, reader: &mut R, field: impl Into<String>, offset: u64, after: Vec<u8>) -> Result<(), MosesError> {
+        let before = read_range(reader, offset, after.len())?;
+        self.set(field, offset, before, after)
+    }
+
+    /// Nothing would change
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// One line per changed range, for dry runs
+    pub fn diff(&self) -> Vec<String> {
+        self.ranges.iter().map(PatchRange::diff).collect()
+    }
+
+    /// The patch that puts the old bytes back
+    pub fn inverse(&self) -> Self {
+        Self {
+            description: format!("undo {}", self.description),
+            created: Utc::now(),
+            ranges: self.ranges.iter().rev().map(|range| PatchRange {
+                field: range.field.clone(),
+                offset: range.offset,
+                before: range.after.clone(),
+                after: range.before.clone(),
+            }).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Save as an undo record for [`load`](Self::load)
+    pub fn save(&self, path: &Path) -> Result<(), MosesError> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| MosesError::Other(e.to_string()))?;
+        std::fs::write(path, json)
+            .map_err(|e| MosesError::Other(format!("Failed to write undo record {}: {}", path.display(), e)))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MosesError> {
+        let json = std::fs::read(path)
+            .map_err(|e| MosesError::Other(format!("Failed to read undo record {}: {}", path.display(), e)))?;
+        serde_json::from_slice::<Self>(&json).ok()
+            .filter(|patch| patch.kind == UNDO_KIND)
+            .ok_or_else(|| MosesError::InvalidInput(format!("{} is not a moses undo record", path.display())))
+    }
+
+    /// Write the patch, first saving its inverse to `undo` when given
+    ///
+    /// Fails without writing if any range no longer holds its old bytes, since the patch was
+    /// then computed against a different state of the device.
+    pub fn apply<F: Read + Write + Seek>(&self, file: &mut F, undo: Option<&Path>) -> Result<(), MosesError> {
+        for range in &self.ranges {
+            if read_range(file, range.offset, range.before.len())? != range.before {
+                return Err(MosesError::Other(format!(
+                    "{} at 0x{:X} changed since the edit was planned; nothing was written", range.field, range.offset
+                )));
+            }
+        }
+        if let Some(path) = undo {
+            self.inverse().save(path)?;
+        }
+        for (index, range) in self.ranges.iter().enumerate() {
+            if let Err(e) = write_range(file, range.offset, &range.after) {
+                tracing::error!("Writing {} failed, putting back {} range(s): {}", range.field, index, e);
+                for written in self.ranges[..=index].iter().rev() {
+                    let _ = write_range(file, written.offset, &written.before);
+                }
+                let _ = file.flush();
+                return Err(e);
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+fn read_range<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+    let mut bytes = vec![0u8; len];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut bytes)
+        .map_err(|e| MosesError::Other(format!("Failed to read {} bytes at 0x{:X}: {}", len, offset, e)))?;
+    Ok(bytes)
+}
+
+fn write_range<W: Write + Seek>(writer: &mut W, offset: u64, bytes: &[u8]) -> Result<(), MosesError> {
+    writer.seek(SeekFrom::Start(offset))?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+/// Byte vectors as hex strings, so undo records stay readable
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if !text.len().is_multiple_of(2) {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..text.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Fails the `fail_at`th write, as a flaky USB connection would
+    struct FailingDisk {
+        inner: Cursor<Vec<u8>>,
+        writes: usize,
+        fail_at: usize,
+    }
+
+    impl Read for FailingDisk {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for FailingDisk {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            if self.writes == self.fail_at {
+                return Err(std::io::Error::other("device went away"));
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FailingDisk {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn patch(disk: &mut Cursor<Vec<u8>>) -> MetadataPatch {
+        let mut patch = MetadataPatch::new("rename", "disk.img");
+        patch.set_from(disk, "label", 16, b"NEW LABEL".to_vec()).unwrap();
+        patch.set_from(disk, "backup label", 64, b"NEW LABEL".to_vec()).unwrap();
+        patch.set_from(disk, "unchanged", 100, vec![0; 4]).unwrap();
+        patch
+    }
+
+    #[test]
+    fn test_apply_diff_and_undo() {
+        let mut disk = Cursor::new(vec![0u8; 128]);
+        disk.get_mut()[16..25].copy_from_slice(b"OLD LABEL");
+        disk.get_mut()[64..73].copy_from_slice(b"OLD LABEL");
+        let original = disk.get_ref().clone();
+        let patch = patch(&mut disk);
+        assert_eq!(patch.ranges.len(), 2, "unchanged ranges are left out");
+        assert_eq!(patch.diff()[0], "label @ 0x10: 4f 4c 44 -> 4e 45 57");
+
+        let dir = std::env::temp_dir().join(format!("moses_patch_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let undo_path = dir.join("undo.json");
+        patch.apply(&mut disk, Some(&undo_path)).unwrap();
+        assert_eq!(&disk.get_ref()[64..73], b"NEW LABEL");
+        assert!(patch.apply(&mut disk, None).is_err(), "old bytes are gone, so it is not applied twice");
+
+        let undo = MetadataPatch::load(&undo_path).unwrap();
+        undo.apply(&mut disk, None).unwrap();
+        assert_eq!(disk.get_ref(), &original);
+
+        std::fs::write(dir.join("other.json"), b"{\"kind\":\"something\"}").unwrap();
+        assert!(MetadataPatch::load(&dir.join("other.json")).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_failed_write_is_rolled_back() {
+        let mut image = Cursor::new(vec![0u8; 128]);
+        image.get_mut()[16..25].copy_from_slice(b"OLD LABEL");
+        let patch = patch(&mut image);
+        let original = image.get_ref().clone();
+        let mut disk = FailingDisk { inner: image, writes: 0, fail_at: 2 };
+        assert!(patch.apply(&mut disk, None).is_err());
+        assert_eq!(disk.inner.get_ref(), &original);
+    }
+}
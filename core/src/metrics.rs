@@ -0,0 +1,227 @@
+// Local operation metrics
+// Every format, clean and export Moses finishes adds one record here: what ran, on which
+// kind of device, whether it failed and with which error code, and how many bytes moved in
+// how long. Nothing leaves the machine; `moses stats` and the app's statistics page read
+// the same JSON lines file so a user, or support looking over their shoulder, can see that
+// a reader keeps timing out or that one stick is much slower than the others.
+use crate::{Device, DeviceType, MosesError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// One finished operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricRecord {
+    pub at: DateTime<Utc>,
+    /// e.g. "format", "clean", "export"
+    pub operation: String,
+    pub device_type: DeviceType,
+    /// `MosesError::code` of the failure, None when the operation succeeded
+    pub error_code: Option<String>,
+    /// Bytes read or written, when the operation knows
+    pub bytes: Option<u64>,
+    pub duration_ms: u64,
+}
+
+impl MetricRecord {
+    pub fn new<T>(operation: impl Into<String>, device: &Device, result: &Result<T, MosesError>, elapsed: Duration) -> Self {
+        Self {
+            at: Utc::now(),
+            operation: operation.into(),
+            device_type: device.device_type.clone(),
+            error_code: result.as_ref().err().map(|e| e.code().to_string()),
+            bytes: None,
+            duration_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+}
+
+/// Runs and failures of one operation
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OperationStats {
+    pub runs: u64,
+    pub failures: u64,
+}
+
+/// Everything recorded, grouped for display
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSummary {
+    /// Oldest record, None when nothing was recorded yet
+    pub since: Option<DateTime<Utc>>,
+    pub operations: BTreeMap<String, OperationStats>,
+    pub failures_by_code: BTreeMap<String, u64>,
+    /// Average MB/s of successful operations that moved data, keyed by device type
+    pub throughput_by_device_type: BTreeMap<String, f64>,
+}
+
+impl MetricsSummary {
+    pub fn from_records(records: &[MetricRecord]) -> Self {
+        let mut summary = MetricsSummary { since: records.iter().map(|record| record.at).min(), ..Default::default() };
+        // Total bytes over total time, so one tiny fast export does not skew the average
+        let mut transfers: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for record in records {
+            let stats = summary.operations.entry(record.operation.clone()).or_default();
+            stats.runs += 1;
+            match &record.error_code {
+                Some(code) => {
+                    stats.failures += 1;
+                    *summary.failures_by_code.entry(code.clone()).or_default() += 1;
+                }
+                None => if let Some(bytes) = record.bytes.filter(|_| record.duration_ms > 0) {
+                    let totals = transfers.entry(format!("{:?}", record.device_type)).or_default();
+                    totals.0 += bytes;
+                    totals.1 += record.duration_ms;
+                },
+            }
+        }
+        summary.throughput_by_device_type = transfers
+            .into_iter()
+            .map(|(device_type, (bytes, ms))| (device_type, bytes as f64 / (1024.0 * 1024.0) / (ms as f64 / 1000.0)))
+            .collect();
+        summary
+    }
+}
+
+pub struct LocalMetrics {
+    path: Option<PathBuf>,
+    /// Used when there is no backing file
+    memory: Mutex<Vec<MetricRecord>>,
+}
+
+impl LocalMetrics {
+    /// In-memory metrics
+    pub fn new() -> Self {
+        Self { path: None, memory: Mutex::new(Vec::new()) }
+    }
+
+    /// Metrics backed by a JSON lines file
+    pub fn persistent(path: PathBuf) -> Self {
+        Self { path: Some(path), memory: Mutex::new(Vec::new()) }
+    }
+
+    /// Process-wide metrics stored in the user's data directory
+    pub fn global() -> &'static LocalMetrics {
+        static GLOBAL: OnceLock<LocalMetrics> = OnceLock::new();
+        GLOBAL.get_or_init(|| match dirs::data_local_dir() {
+            Some(dir) => Self::persistent(dir.join("moses").join("metrics.jsonl")),
+            None => Self::new(),
+        })
+    }
+
+    /// Where the records are kept, if anywhere
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Append a record; metrics are best effort, so a failed write is only logged
+    pub fn record(&self, record: MetricRecord) {
+        let Ok(mut memory) = self.memory.lock() else {
+            return;
+        };
+        let Some(path) = &self.path else {
+            memory.push(record);
+            return;
+        };
+        let result = serde_json::to_string(&record)
+            .map_err(std::io::Error::other)
+            .and_then(|line| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", line)
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save metrics to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Every record, oldest first; lines that do not parse are skipped
+    pub fn records(&self) -> Vec<MetricRecord> {
+        match &self.path {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|text| text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+                .unwrap_or_default(),
+            None => self.memory.lock().map(|records| records.clone()).unwrap_or_default(),
+        }
+    }
+
+    pub fn summary(&self) -> MetricsSummary {
+        MetricsSummary::from_records(&self.records())
+    }
+
+    /// Forget everything recorded so far
+    pub fn clear(&self) -> std::io::Result<()> {
+        let mut memory = self.memory.lock().map_err(|_| std::io::Error::other("metrics lock poisoned"))?;
+        memory.clear();
+        match &self.path {
+            Some(path) => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for LocalMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(device_type: DeviceType) -> Device {
+        Device {
+            id: "/dev/sdb".to_string(),
+            name: "stick".to_string(),
+            size: 8 * 1024 * 1024 * 1024,
+            device_type,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_metrics_summary() {
+        let path = std::env::temp_dir().join(format!("moses_metrics_test_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let metrics = LocalMetrics::persistent(path.clone());
+        let usb = device(DeviceType::USB);
+        let ok: Result<(), MosesError> = Ok(());
+        let timeout: Result<(), MosesError> = Err(MosesError::Timeout("reader stopped answering".to_string()));
+        metrics.record(MetricRecord::new("clean", &usb, &ok, Duration::from_secs(2)).with_bytes(20 * 1024 * 1024));
+        metrics.record(MetricRecord::new("clean", &usb, &ok, Duration::from_secs(8)).with_bytes(80 * 1024 * 1024));
+        metrics.record(MetricRecord::new("format", &usb, &timeout, Duration::from_secs(1)));
+        metrics.record(MetricRecord::new("format", &device(DeviceType::SSD), &ok, Duration::from_secs(1)));
+
+        let summary = LocalMetrics::persistent(path.clone()).summary();
+        assert_eq!(summary.operations["format"], OperationStats { runs: 2, failures: 1 });
+        assert_eq!(summary.operations["clean"], OperationStats { runs: 2, failures: 0 });
+        assert_eq!(summary.failures_by_code["timeout"], 1);
+        assert_eq!(summary.throughput_by_device_type.len(), 1);
+        assert!((summary.throughput_by_device_type["USB"] - 10.0).abs() < 1e-9);
+
+        metrics.clear().unwrap();
+        assert!(metrics.records().is_empty());
+        assert_eq!(metrics.summary(), MetricsSummary::default());
+    }
+}
@@ -0,0 +1,143 @@
+// NVMe LBA formats - the sector sizes an NVMe namespace can be switched between
+// Most NVMe drives ship formatted with 512-byte sectors for compatibility (512e) and also
+// offer 4096-byte ones (4Kn), which suit the 4 KiB blocks of ext4 and NTFS better. The
+// formats are listed in the Identify Namespace data; switching between them is a Format NVM
+// command, which erases the whole namespace. The platform crate sends the commands; this
+// module reads and checks the data they exchange.
+use crate::MosesError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Size of the Identify Namespace data structure
+pub const IDENTIFY_NAMESPACE_SIZE: usize = 4096;
+/// Most formats a namespace can list (NLBAF is 0-based, up to 63)
+const MAX_FORMATS: usize = 64;
+const FORMATS_OFFSET: usize = 128;
+
+/// One LBA format a namespace supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LbaFormat {
+    /// Index to pass to Format NVM
+    pub index: u8,
+    /// Bytes of data per sector
+    pub data_size: u32,
+    /// Metadata bytes per sector; formats with metadata are for special setups
+    pub metadata_size: u16,
+    /// The drive's own rating: 0 best, 1 better, 2 good, 3 degraded
+    pub relative_performance: u8,
+    /// The namespace is formatted with this one now
+    pub in_use: bool,
+}
+
+impl LbaFormat {
+    pub fn performance_name(&self) -> &'static str {
+        match self.relative_performance {
+            0 => "best",
+            1 => "better",
+            2 => "good",
+            _ => "degraded",
+        }
+    }
+}
+
+impl fmt::Display for LbaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}: {} bytes", self.index, self.data_size)?;
+        if self.metadata_size > 0 {
+            write!(f, " + {} metadata", self.metadata_size)?;
+        }
+        write!(f, " ({} performance)", self.performance_name())?;
+        if self.in_use {
+            write!(f, " - in use")?;
+        }
+        Ok(())
+    }
+}
+
+/// The LBA formats in an Identify Namespace data structure
+pub fn parse_identify_namespace(data: &[u8]) -> Result<Vec<LbaFormat>, MosesError> {
+    if data.len() < IDENTIFY_NAMESPACE_SIZE {
+        return Err(MosesError::InvalidInput(format!(
+            "Identify Namespace data is {} bytes, expected {}", data.len(), IDENTIFY_NAMESPACE_SIZE,
+        )));
+    }
+    let count = (data[25] as usize + 1).min(MAX_FORMATS);
+    // FLBAS: bits 0-3 are the low bits of the format in use, bits 5-6 the high ones
+    let flbas = data[26];
+    let current = (flbas & 0x0F) | ((flbas >> 1) & 0x30);
+    let mut formats = Vec::with_capacity(count);
+    for index in 0..count {
+        let offset = FORMATS_OFFSET + index * 4;
+        let entry = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let shift = (entry >> 16) & 0xFF;
+        // LBADS of 0 marks an unused entry; below 9 is not a valid sector size
+        if !(9..32).contains(&shift) {
+            continue;
+        }
+        formats.push(LbaFormat {
+            index: index as u8,
+            data_size: 1 << shift,
+            metadata_size: (entry & 0xFFFF) as u16,
+            relative_performance: ((entry >> 24) & 0x3) as u8,
+            in_use: index as u8 == current,
+        });
+    }
+    if formats.is_empty() {
+        return Err(MosesError::Other("The namespace lists no usable LBA formats".to_string()));
+    }
+    Ok(formats)
+}
+
+/// Command dword 10 of a Format NVM that switches to `index` without a secure erase
+pub fn format_nvm_cdw10(index: u8) -> u32 {
+    let index = index as u32;
+    (index & 0x0F) | ((index & 0x30) << 8)
+}
+
+/// The format to switch to for a wanted sector size: without metadata, best rated first
+pub fn pick_format(formats: &[LbaFormat], data_size: u32) -> Result<LbaFormat, MosesError> {
+    formats.iter()
+        .filter(|format| format.data_size == data_size && format.metadata_size == 0)
+        .min_by_key(|format| format.relative_performance)
+        .copied()
+        .ok_or_else(|| MosesError::NotSupported(format!(
+            "The namespace offers no {}-byte format without metadata (it has {})",
+            data_size,
+            formats.iter().map(|format| format.data_size.to_string()).collect::<Vec<_>>().join(", "),
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify_namespace_formats() {
+        let mut data = vec![0u8; IDENTIFY_NAMESPACE_SIZE];
+        data[25] = 2; // three formats
+        data[26] = 0; // the first is in use
+        let entry = |metadata: u32, shift: u32, performance: u32| (metadata | (shift << 16) | (performance << 24)).to_le_bytes();
+        data[128..132].copy_from_slice(&entry(0, 9, 2));
+        data[132..136].copy_from_slice(&entry(0, 12, 0));
+        data[136..140].copy_from_slice(&entry(8, 12, 1));
+
+        let formats = parse_identify_namespace(&data).unwrap();
+        assert_eq!(formats.len(), 3);
+        assert_eq!(formats[0], LbaFormat { index: 0, data_size: 512, metadata_size: 0, relative_performance: 2, in_use: true });
+        assert_eq!(formats[1].data_size, 4096);
+        assert!(!formats[1].in_use);
+        assert_eq!(formats[2].to_string(), "#2: 4096 bytes + 8 metadata (better performance)");
+        assert_eq!(pick_format(&formats, 4096).unwrap().index, 1);
+        assert!(pick_format(&formats, 8192).is_err());
+        assert!(parse_identify_namespace(&data[..512]).is_err());
+
+        // Formats 16 and up keep their high bits apart from the low ones
+        data[25] = 17;
+        data[26] = 0x21; // format 17
+        data[128 + 17 * 4..128 + 18 * 4].copy_from_slice(&entry(0, 12, 0));
+        let formats = parse_identify_namespace(&data).unwrap();
+        assert_eq!(formats.iter().find(|format| format.in_use).unwrap().index, 17);
+        assert_eq!(format_nvm_cdw10(17), 0x1001);
+        assert_eq!(format_nvm_cdw10(1), 1);
+    }
+}
@@ -186,6 +186,12 @@ impl FilesystemFormatter for ScriptFormatter {
             required_tools: self.config.required_tools.clone(),
             will_erase_data: true,
             space_after_format: device.size * 95 / 100, // Estimate 95% usable
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
     
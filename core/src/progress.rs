@@ -0,0 +1,202 @@
+// Throughput-based time estimates for long operations
+// Each operation keeps an exponentially weighted moving average of bytes/sec, so the
+// estimate follows the device's real speed (which drops as a disk fills toward its
+// slower inner tracks) without jumping around on every sample. Phases that only report
+// a fraction done are converted to bytes against the operation's total.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the moving average
+const DEFAULT_SMOOTHING: f64 = 0.3;
+/// Samples closer together than this are merged, so bursts don't skew the rate
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Rolling bytes/sec estimate
+#[derive(Debug, Clone)]
+pub struct ThroughputEstimator {
+    smoothing: f64,
+    rate: Option<f64>,
+    last_sample: Option<(Instant, u64)>,
+}
+
+impl Default for ThroughputEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_SMOOTHING)
+    }
+}
+
+impl ThroughputEstimator {
+    /// `smoothing` is the weight of each new sample, between 0 and 1
+    pub fn new(smoothing: f64) -> Self {
+        Self {
+            smoothing: smoothing.clamp(0.01, 1.0),
+            rate: None,
+            last_sample: None,
+        }
+    }
+
+    /// Record that `bytes_done` bytes are complete so far
+    pub fn record(&mut self, bytes_done: u64) {
+        self.record_at(bytes_done, Instant::now());
+    }
+
+    pub fn record_at(&mut self, bytes_done: u64, now: Instant) {
+        let Some((last_time, last_bytes)) = self.last_sample else {
+            self.last_sample = Some((now, bytes_done));
+            return;
+        };
+
+        let elapsed = now.saturating_duration_since(last_time);
+        if elapsed < MIN_SAMPLE_INTERVAL {
+            return;
+        }
+
+        let sample = bytes_done.saturating_sub(last_bytes) as f64 / elapsed.as_secs_f64();
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + self.smoothing * (sample - rate),
+            None => sample,
+        });
+        self.last_sample = Some((now, bytes_done));
+    }
+
+    /// Smoothed throughput, once at least two samples are in
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Time left for `remaining_bytes` at the current rate
+    pub fn eta(&self, remaining_bytes: u64) -> Option<Duration> {
+        let rate = self.rate.filter(|&rate| rate > 0.0)?;
+        Some(Duration::from_secs_f64(remaining_bytes as f64 / rate))
+    }
+}
+
+/// One progress report, as sent to the CLI and the GUI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressUpdate {
+    pub operation: String,
+    pub phase: String,
+    /// 0-100
+    pub percent: f32,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub bytes_per_sec: Option<u64>,
+    pub eta_secs: Option<u64>,
+}
+
+impl ProgressUpdate {
+    /// e.g. `42.0% pass 1/3 - 85.3 MB/s, 3m 12s left`
+    pub fn summary(&self) -> String {
+        let mut text = format!("{:.1}% {}", self.percent, self.phase);
+        if let Some(rate) = self.bytes_per_sec {
+            text.push_str(&format!(" - {:.1} MB/s", rate as f64 / (1024.0 * 1024.0)));
+        }
+        if let Some(eta) = self.eta_secs {
+            text.push_str(&format!(", {} left", format_duration(Duration::from_secs(eta))));
+        }
+        text
+    }
+}
+
+/// Tracks one operation and turns its progress into `ProgressUpdate`s with an ETA
+#[derive(Debug, Clone)]
+pub struct ProgressTracker {
+    operation: String,
+    bytes_total: u64,
+    estimator: ThroughputEstimator,
+}
+
+impl ProgressTracker {
+    pub fn new(operation: impl Into<String>, bytes_total: u64) -> Self {
+        Self {
+            operation: operation.into(),
+            bytes_total,
+            estimator: ThroughputEstimator::default(),
+        }
+    }
+
+    /// Progress measured in bytes across the whole operation
+    pub fn update(&mut self, phase: impl Into<String>, bytes_done: u64) -> ProgressUpdate {
+        let bytes_done = bytes_done.min(self.bytes_total);
+        self.estimator.record(bytes_done);
+
+        let percent = if self.bytes_total > 0 {
+            (bytes_done as f64 / self.bytes_total as f64 * 100.0) as f32
+        } else {
+            0.0
+        };
+        ProgressUpdate {
+            operation: self.operation.clone(),
+            phase: phase.into(),
+            percent,
+            bytes_done,
+            bytes_total: self.bytes_total,
+            bytes_per_sec: self.estimator.bytes_per_sec().map(|rate| rate as u64),
+            eta_secs: self.estimator.eta(self.bytes_total - bytes_done).map(|eta| eta.as_secs()),
+        }
+    }
+
+    /// Progress for phases that only know how far along they are (0.0-1.0 of the operation)
+    pub fn update_fraction(&mut self, phase: impl Into<String>, fraction: f64) -> ProgressUpdate {
+        let bytes_done = (fraction.clamp(0.0, 1.0) * self.bytes_total as f64) as u64;
+        self.update(phase, bytes_done)
+    }
+
+    pub fn estimator(&self) -> &ThroughputEstimator {
+        &self.estimator
+    }
+}
+
+/// Compact human-readable duration: `45s`, `3m 12s`, `2h 05m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimator_converges_on_rate() {
+        let mut estimator = ThroughputEstimator::default();
+        let start = Instant::now();
+        // 10 MB/s for 10 seconds
+        for second in 0..=10u64 {
+            estimator.record_at(second * 10_000_000, start + Duration::from_secs(second));
+        }
+        let rate = estimator.bytes_per_sec().unwrap();
+        assert!((rate - 10_000_000.0).abs() < 1.0);
+        assert_eq!(estimator.eta(50_000_000), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_estimator_smooths_slowdown() {
+        let mut estimator = ThroughputEstimator::new(0.5);
+        let start = Instant::now();
+        estimator.record_at(0, start);
+        estimator.record_at(100, start + Duration::from_secs(1));
+        estimator.record_at(120, start + Duration::from_secs(2));
+        // Halfway between the old 100 B/s and the new 20 B/s
+        assert_eq!(estimator.bytes_per_sec(), Some(60.0));
+    }
+
+    #[test]
+    fn test_no_eta_without_samples() {
+        let mut tracker = ProgressTracker::new("wipe", 1000);
+        let update = tracker.update("pass 1/1", 100);
+        assert_eq!(update.eta_secs, None);
+        assert_eq!(update.percent, 10.0);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m 12s");
+        assert_eq!(format_duration(Duration::from_secs(7500)), "2h 05m");
+    }
+}
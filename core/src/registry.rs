@@ -1,4 +1,5 @@
-use crate::{FilesystemFormatter, Platform, MosesError};
+use crate::{Device, FilesystemFormatter, FormatOptions, Platform, MosesError};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -7,6 +8,70 @@ pub struct FormatterRegistry {
     formatters: HashMap<String, Arc<dyn FilesystemFormatter>>,
     metadata: HashMap<String, FormatterMetadata>,
     aliases: HashMap<String, String>, // alias -> canonical name
+    system_formatters: HashMap<String, Arc<dyn FilesystemFormatter>>, // canonical name -> system-tool implementation
+}
+
+/// Which implementation to use when a filesystem has both a native and a system-tool formatter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FormatStrategy {
+    /// Native unless it can't handle the device, then the system tool if installed
+    #[default]
+    Auto,
+    PreferNative,
+    PreferSystem,
+}
+
+impl FormatStrategy {
+    /// Key in `FormatOptions::additional_options` selecting the strategy for one format
+    pub const OPTION_KEY: &'static str = "strategy";
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().replace('_', "-").as_str() {
+            "auto" => Some(Self::Auto),
+            "native" | "prefer-native" => Some(Self::PreferNative),
+            "system" | "prefer-system" => Some(Self::PreferSystem),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::PreferNative => "prefer-native",
+            Self::PreferSystem => "prefer-system",
+        }
+    }
+
+    pub fn from_options(options: &FormatOptions) -> Result<Self, MosesError> {
+        match options.additional_options.get(Self::OPTION_KEY) {
+            None => Ok(Self::default()),
+            Some(value) => Self::parse(value).ok_or_else(|| {
+                MosesError::InvalidInput(format!(
+                    "Unknown strategy '{}' (expected auto, prefer-native or prefer-system)",
+                    value
+                ))
+            }),
+        }
+    }
+}
+
+/// A formatter chosen by `FormatterRegistry::select`
+#[derive(Clone)]
+pub struct SelectedFormatter {
+    pub formatter: Arc<dyn FilesystemFormatter>,
+    /// True when the system-tool implementation was chosen
+    pub system: bool,
+    pub strategy: FormatStrategy,
+    /// Why this implementation was chosen, for the simulation report
+    pub reason: String,
+}
+
+impl SelectedFormatter {
+    /// e.g. `system tool (auto: native formatter cannot handle this device)`
+    pub fn describe(&self) -> String {
+        let implementation = if self.system { "system tool" } else { "native" };
+        format!("{} ({}: {})", implementation, self.strategy.as_str(), self.reason)
+    }
 }
 
 impl FormatterRegistry {
@@ -15,7 +80,79 @@ impl FormatterRegistry {
             formatters: HashMap::new(),
             metadata: HashMap::new(),
             aliases: HashMap::new(),
+            system_formatters: HashMap::new(),
+        }
+    }
+    
+    /// Register a system-tool implementation for an already registered filesystem
+    pub fn register_system_alternative(
+        &mut self,
+        name: &str,
+        formatter: Arc<dyn FilesystemFormatter>,
+    ) -> Result<(), MosesError> {
+        let canonical_name = self.canonical_name(name).to_string();
+        if !self.formatters.contains_key(&canonical_name) {
+            return Err(MosesError::Configuration(
+                format!("Cannot add a system formatter for unregistered '{}'", name)
+            ));
         }
+        self.system_formatters.insert(canonical_name, formatter);
+        Ok(())
+    }
+    
+    /// System-tool implementation of a filesystem, if one is registered
+    pub fn get_system_formatter(&self, name: &str) -> Option<Arc<dyn FilesystemFormatter>> {
+        self.system_formatters.get(self.canonical_name(name)).cloned()
+    }
+    
+    /// Pick the native or system-tool formatter for a device according to the strategy
+    pub fn select(&self, name: &str, device: &Device, strategy: FormatStrategy) -> Option<SelectedFormatter> {
+        let native = self.get_formatter(name)?;
+        let selected = |formatter: &Arc<dyn FilesystemFormatter>, system: bool, reason: &str| SelectedFormatter {
+            formatter: formatter.clone(),
+            system,
+            strategy,
+            reason: reason.to_string(),
+        };
+        
+        let Some(system) = self.get_system_formatter(name) else {
+            return Some(selected(&native, false, "only implementation"));
+        };
+        let platform = Platform::current();
+        let native_usable = native.supported_platforms().contains(&platform) && native.can_format(device);
+        let system_usable = system.supported_platforms().contains(&platform)
+            && system.tools_available()
+            && system.can_format(device);
+        
+        Some(match strategy {
+            FormatStrategy::PreferSystem if system_usable => selected(&system, true, "requested"),
+            FormatStrategy::PreferSystem => selected(&native, false, "system tool unavailable for this device"),
+            FormatStrategy::PreferNative if native_usable || !system_usable => selected(&native, false, "requested"),
+            FormatStrategy::PreferNative => selected(&system, true, "native formatter cannot handle this device"),
+            FormatStrategy::Auto if native_usable => selected(&native, false, "native formatter handles this device"),
+            FormatStrategy::Auto if system_usable => selected(&system, true, "native formatter cannot handle this device"),
+            FormatStrategy::Auto => selected(&native, false, "no implementation accepts this device"),
+        })
+    }
+    
+    /// Every formatter with what it needs and whether it can be used right now
+    pub fn availability(&self, context: &AvailabilityContext) -> Vec<FormatterAvailability> {
+        let mut entries: Vec<FormatterAvailability> = self.metadata
+            .iter()
+            .filter_map(|(name, metadata)| {
+                let native = self.formatters.get(name)?;
+                let system = self.system_formatters.get(name).map(|f| f.as_ref());
+                Some(FormatterAvailability::new(name, metadata, native.as_ref(), system, context))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        entries
+    }
+    
+    fn canonical_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name)
+            .map(|s| s.as_str())
+            .unwrap_or(name)
     }
     
     /// Register a formatter with its metadata
@@ -23,7 +160,7 @@ impl FormatterRegistry {
         &mut self, 
         name: String, 
         formatter: Arc<dyn FilesystemFormatter>,
-        metadata: FormatterMetadata,
+        mut metadata: FormatterMetadata,
     ) -> Result<(), MosesError> {
         // Check for duplicate names
         if self.formatters.contains_key(&name) {
@@ -42,6 +179,11 @@ impl FormatterRegistry {
             self.aliases.insert(alias.clone(), name.clone());
         }
 
+        // Platforms default to what the formatter itself reports
+        if metadata.platform_support.is_empty() {
+            metadata.platform_support = formatter.supported_platforms();
+        }
+
         // Store formatter and metadata
         self.formatters.insert(name.clone(), formatter);
         self.metadata.insert(name, metadata);
@@ -117,8 +259,93 @@ impl Default for FormatterRegistry {
     }
 }
 
+/// What the caller can offer, used to decide whether each formatter is usable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AvailabilityContext {
+    pub platform: Platform,
+    /// The process already runs as Administrator/root
+    pub elevated: bool,
+    /// An elevated worker can be launched to do the formatting
+    pub worker_available: bool,
+}
+
+/// Which implementations exist on one platform
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PlatformSupport {
+    pub platform: Platform,
+    pub native: bool,
+    pub system_tool: bool,
+}
+
+/// A formatter as shown to the user, with the reasons it can't be used if any
+#[derive(Clone, Debug, Serialize)]
+pub struct FormatterAvailability {
+    /// Registry name to pass back when formatting
+    pub id: String,
+    #[serde(flatten)]
+    pub metadata: FormatterMetadata,
+    pub platform_matrix: Vec<PlatformSupport>,
+    pub available: bool,
+    /// Why the formatter is unavailable; empty when it can be used
+    pub reasons: Vec<String>,
+}
+
+impl FormatterAvailability {
+    fn new(
+        name: &str,
+        metadata: &FormatterMetadata,
+        native: &dyn FilesystemFormatter,
+        system: Option<&dyn FilesystemFormatter>,
+        context: &AvailabilityContext,
+    ) -> Self {
+        let native_platforms = native.supported_platforms();
+        let system_platforms = system.map(|f| f.supported_platforms()).unwrap_or_default();
+        let platform_matrix: Vec<PlatformSupport> = [Platform::Windows, Platform::MacOS, Platform::Linux]
+            .into_iter()
+            .map(|platform| PlatformSupport {
+                platform,
+                native: native_platforms.contains(&platform),
+                system_tool: system_platforms.contains(&platform),
+            })
+            .collect();
+
+        let mut reasons = Vec::new();
+        let native_here = native_platforms.contains(&context.platform);
+        let system_here = system_platforms.contains(&context.platform);
+        if !native_here && !system_here {
+            reasons.push(format!("Not supported on {:?}", context.platform));
+        } else {
+            let native_ready = native_here && native.tools_available();
+            let system_ready = system_here && system.is_some_and(|f| f.tools_available());
+            if !native_ready && !system_ready {
+                let tools = match system {
+                    Some(system) if !native_here => system.bundled_tools(),
+                    _ => native.bundled_tools(),
+                };
+                reasons.push(format!("Missing external tools: {}", tools.join(", ")));
+            }
+        }
+
+        if metadata.required_permission == RequiredPermission::Administrator && !context.elevated {
+            if !metadata.needs_elevated_worker {
+                reasons.push("Needs administrator privileges; restart Moses as administrator".to_string());
+            } else if !context.worker_available {
+                reasons.push("Needs administrator privileges, and the elevated worker is not installed".to_string());
+            }
+        }
+
+        Self {
+            id: name.to_string(),
+            metadata: metadata.clone(),
+            platform_matrix,
+            available: reasons.is_empty(),
+            reasons,
+        }
+    }
+}
+
 /// Metadata about a formatter
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct FormatterMetadata {
     pub name: String,
     pub description: String,
@@ -132,6 +359,20 @@ pub struct FormatterMetadata {
     pub version: String,
     pub author: String,
     pub capabilities: FormatterCapabilities,
+    /// Privileges needed to format a real device
+    pub required_permission: RequiredPermission,
+    /// Whether an unelevated GUI can hand the format to the elevated worker
+    pub needs_elevated_worker: bool,
+}
+
+/// Privileges a formatter needs to write to a device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum RequiredPermission {
+    /// Works as a normal user (e.g. image files only)
+    User,
+    /// Administrator or root, for raw device access
+    #[default]
+    Administrator,
 }
 
 impl Default for FormatterMetadata {
@@ -149,12 +390,14 @@ impl Default for FormatterMetadata {
             version: "1.0.0".to_string(),
             author: "Moses Team".to_string(),
             capabilities: FormatterCapabilities::default(),
+            required_permission: RequiredPermission::Administrator,
+            needs_elevated_worker: true,
         }
     }
 }
 
 /// Categories for organizing formatters
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub enum FormatterCategory {
     Modern,          // ext4, btrfs, zfs
     Legacy,          // fat32, ntfs
@@ -165,7 +408,7 @@ pub enum FormatterCategory {
 }
 
 /// Capabilities of a formatter
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct FormatterCapabilities {
     pub supports_labels: bool,
     pub max_label_length: Option<usize>,
@@ -229,6 +472,16 @@ impl FormatterMetadataBuilder {
         self
     }
 
+    pub fn permission(mut self, permission: RequiredPermission) -> Self {
+        self.metadata.required_permission = permission;
+        self
+    }
+
+    pub fn needs_elevated_worker(mut self, needs_worker: bool) -> Self {
+        self.metadata.needs_elevated_worker = needs_worker;
+        self
+    }
+
     pub fn capability(mut self, f: impl FnOnce(&mut FormatterCapabilities)) -> Self {
         f(&mut self.metadata.capabilities);
         self
@@ -237,4 +490,110 @@ impl FormatterMetadataBuilder {
     pub fn build(self) -> FormatterMetadata {
         self.metadata
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceType, SimulationReport};
+
+    /// Formatter that accepts devices up to `max_size`
+    struct SizedFormatter {
+        max_size: u64,
+        tools: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl FilesystemFormatter for SizedFormatter {
+        fn name(&self) -> &'static str {
+            "sized"
+        }
+        fn supported_platforms(&self) -> Vec<Platform> {
+            vec![Platform::current()]
+        }
+        fn can_format(&self, device: &Device) -> bool {
+            device.size <= self.max_size
+        }
+        fn requires_external_tools(&self) -> bool {
+            false
+        }
+        fn bundled_tools(&self) -> Vec<&'static str> {
+            vec![]
+        }
+        fn tools_available(&self) -> bool {
+            self.tools
+        }
+        async fn format(&self, _device: &Device, _options: &FormatOptions) -> Result<(), MosesError> {
+            Ok(())
+        }
+        async fn validate_options(&self, _options: &FormatOptions) -> Result<(), MosesError> {
+            Ok(())
+        }
+        async fn dry_run(&self, _device: &Device, _options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+            Err(MosesError::NotSupported("test formatter".to_string()))
+        }
+    }
+
+    fn device(size: u64) -> Device {
+        Device {
+            id: "mock://disk".to_string(),
+            name: "disk".to_string(),
+            size,
+            device_type: DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    fn registry(system_tools: bool) -> FormatterRegistry {
+        let mut registry = FormatterRegistry::new();
+        let native = Arc::new(SizedFormatter { max_size: 1000, tools: true });
+        registry.register("sized".to_string(), native, FormatterMetadataBuilder::new("sized").build()).unwrap();
+        let system = Arc::new(SizedFormatter { max_size: u64::MAX, tools: system_tools });
+        registry.register_system_alternative("sized", system).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_auto_falls_back_to_system_by_size() {
+        let registry = registry(true);
+        assert!(!registry.select("sized", &device(500), FormatStrategy::Auto).unwrap().system);
+        assert!(registry.select("sized", &device(5000), FormatStrategy::Auto).unwrap().system);
+        assert!(registry.select("sized", &device(500), FormatStrategy::PreferSystem).unwrap().system);
+    }
+
+    #[test]
+    fn test_availability_reasons() {
+        let registry = registry(false);
+        let context = AvailabilityContext {
+            platform: Platform::current(),
+            elevated: false,
+            worker_available: false,
+        };
+        let entry = &registry.availability(&context)[0];
+        assert!(!entry.available);
+        assert_eq!(entry.reasons.len(), 1);
+        assert!(entry.platform_matrix.iter().any(|support| support.native && support.system_tool));
+        assert_eq!(entry.metadata.platform_support, vec![Platform::current()]);
+
+        let context = AvailabilityContext { worker_available: true, ..context };
+        assert!(registry.availability(&context)[0].available);
+    }
+
+    #[test]
+    fn test_missing_tools_keep_native() {
+        let registry = registry(false);
+        let selected = registry.select("sized", &device(500), FormatStrategy::PreferSystem).unwrap();
+        assert!(!selected.system);
+        assert!(selected.describe().starts_with("native (prefer-system"));
+        assert_eq!(
+            FormatStrategy::parse("prefer_system"),
+            Some(FormatStrategy::PreferSystem)
+        );
+    }
+}
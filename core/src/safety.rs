@@ -344,7 +344,7 @@ impl<F: crate::FilesystemFormatter> crate::FilesystemFormatter for SafeFormatter
         check.assess_risk();
         
         // Only allow if risk is acceptable and inner formatter agrees
-        check.risk_assessment <= RiskLevel::Medium && self.inner.can_format(device)
+        !device.is_write_protected && check.risk_assessment <= RiskLevel::Medium && self.inner.can_format(device)
     }
     
     fn requires_external_tools(&self) -> bool {
@@ -360,6 +360,9 @@ impl<F: crate::FilesystemFormatter> crate::FilesystemFormatter for SafeFormatter
         device: &Device,
         options: &FormatOptions,
     ) -> Result<(), MosesError> {
+        // Write-protected media would only fail later with an I/O error
+        device.ensure_writable()?;
+        
         // ENFORCE: Safety check MUST be performed
         let mut safety_check = SafetyCheck::new(device, self.name());
         
@@ -436,6 +439,10 @@ mod tests {
             mount_points: vec![PathBuf::from("C:\\")],
             is_removable: false,
             is_system: true,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some("ntfs".to_string()),
         };
         
@@ -461,6 +468,10 @@ mod tests {
             mount_points: vec![],
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some("fat32".to_string()),
         };
         
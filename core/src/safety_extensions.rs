@@ -357,6 +357,10 @@ impl FormatterCertifier {
             device_type: DeviceType::HardDisk,
             is_removable: false,
             is_system: true,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             mount_points: vec![std::path::PathBuf::from("/")],
             filesystem: Some("ext4".to_string()),
         };
@@ -373,6 +377,10 @@ impl FormatterCertifier {
             device_type: DeviceType::HardDisk,
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             mount_points: vec![std::path::PathBuf::from("/boot")],
             filesystem: Some("ext4".to_string()),
         };
@@ -389,6 +397,10 @@ impl FormatterCertifier {
             device_type: DeviceType::USB,
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             mount_points: vec![],
             filesystem: None,
         };
@@ -408,6 +420,10 @@ impl FormatterCertifier {
             device_type: DeviceType::Unknown,
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             mount_points: vec![],
             filesystem: None,
         };
@@ -0,0 +1,202 @@
+// Source URIs - one syntax for naming what a command reads
+// `device:<id>[@part(N)][:/path]` is a disk or volume the platform enumerates,
+// `image:<file>[@part(N)][:/path]` a disk image file and `host:<path>` a folder on this
+// machine. Every command that takes a source (mount, export, hash, verify, attr, info)
+// parses it here, so the same argument means the same thing everywhere. Arguments without
+// a scheme keep working: an existing folder is a host folder, an existing file an image,
+// and anything else a device, with the older `<device>:/path` form for a path inside it.
+
+use crate::MosesError;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What a source URI points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceKind {
+    /// A device id or name as `moses list` shows it
+    Device(String),
+    /// A disk image file
+    Image(PathBuf),
+    /// A folder on this machine, read through the host's own filesystem
+    Host(PathBuf),
+}
+
+/// A parsed source: the device, image or folder, a partition on it, and a path inside
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceUri {
+    pub kind: SourceKind,
+    /// 1-based partition number; None for the whole device
+    pub partition: Option<u32>,
+    /// Absolute path inside the volume with `/` separators; "/" for the root
+    pub path: String,
+}
+
+impl SourceUri {
+    /// Parse a source argument, with or without a scheme
+    pub fn parse(text: &str) -> Result<Self, MosesError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(MosesError::InvalidInput("No source given".to_string()));
+        }
+        if let Some(path) = text.strip_prefix("host:") {
+            return Self::host(path);
+        }
+        if let Some(rest) = text.strip_prefix("device:") {
+            let (id, partition, path) = split_target(rest)?;
+            return Ok(Self { kind: SourceKind::Device(id.to_string()), partition, path });
+        }
+        if let Some(rest) = text.strip_prefix("image:") {
+            let (file, partition, path) = split_target(rest)?;
+            return Ok(Self { kind: SourceKind::Image(PathBuf::from(file)), partition, path });
+        }
+
+        // No scheme: what is on disk decides, then the legacy `<device>:/path` split
+        let path = Path::new(text);
+        if path.is_dir() {
+            return Self::host(text);
+        }
+        if path.is_file() {
+            return Ok(Self { kind: SourceKind::Image(path.to_path_buf()), partition: None, path: "/".to_string() });
+        }
+        if is_drive_path(text) {
+            return Err(MosesError::InvalidInput(format!("Path does not exist: {}", text)));
+        }
+        let (id, path) = match text.rfind(":/") {
+            Some(index) if index > 0 => (&text[..index], normalize(&text[index + 1..])?),
+            _ => (text, "/".to_string()),
+        };
+        let (id, partition) = split_partition(id)?;
+        Ok(Self { kind: SourceKind::Device(id.to_string()), partition, path })
+    }
+
+    fn host(path: &str) -> Result<Self, MosesError> {
+        if path.is_empty() {
+            return Err(MosesError::InvalidInput("host: needs a folder path".to_string()));
+        }
+        Ok(Self { kind: SourceKind::Host(PathBuf::from(path)), partition: None, path: "/".to_string() })
+    }
+
+    /// The whole volume rather than a folder inside it
+    pub fn is_root(&self) -> bool {
+        self.path == "/"
+    }
+}
+
+impl FromStr for SourceUri {
+    type Err = MosesError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+impl fmt::Display for SourceUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SourceKind::Host(path) => return write!(f, "host:{}", path.display()),
+            SourceKind::Device(id) => write!(f, "device:{}", id)?,
+            SourceKind::Image(file) => write!(f, "image:{}", file.display())?,
+        }
+        if let Some(number) = self.partition {
+            write!(f, "@part({})", number)?;
+        }
+        if !self.is_root() {
+            write!(f, ":{}", self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// A drive-letter path such as `E:\Users` or `E:/Users`; `E:` alone is a volume
+fn is_drive_path(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && matches!(bytes[2], b'/' | b'\\')
+}
+
+/// Split `<id>[@part(N)][:/path]`. The path starts at the first `:` followed by a slash,
+/// past a leading drive letter, so `E::/Photos` is E: and `C:\disk.img:/Photos` works.
+fn split_target(rest: &str) -> Result<(&str, Option<u32>, String), MosesError> {
+    let skip = if is_drive_path(rest) { 2 } else { 0 };
+    let split = rest[skip..].match_indices(':')
+        .map(|(index, _)| index + skip)
+        .find(|&index| rest[index + 1..].starts_with(['/', '\\']));
+    let (target, path) = match split {
+        Some(index) => (&rest[..index], normalize(&rest[index + 1..])?),
+        None => (rest, "/".to_string()),
+    };
+    let (target, partition) = split_partition(target)?;
+    if target.is_empty() {
+        return Err(MosesError::InvalidInput(format!("'{}' names no device or image", rest)));
+    }
+    Ok((target, partition, path))
+}
+
+/// Split a trailing `@part(N)` off a device id or image path
+fn split_partition(target: &str) -> Result<(&str, Option<u32>), MosesError> {
+    let Some(index) = target.rfind("@part(") else {
+        return Ok((target, None));
+    };
+    let number = target[index + 6..].strip_suffix(')')
+        .and_then(|number| number.parse::<u32>().ok())
+        .filter(|&number| number > 0)
+        .ok_or_else(|| MosesError::InvalidInput(format!(
+            "'{}' is not a partition; write @part(N) with N counting from 1", &target[index..],
+        )))?;
+    Ok((&target[..index], Some(number)))
+}
+
+/// `/`-separated absolute path with empty and `.` components dropped
+fn normalize(path: &str) -> Result<String, MosesError> {
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return Err(MosesError::InvalidInput(format!("'{}' leaves the volume root", path))),
+            part => parts.push(part),
+        }
+    }
+    Ok(format!("/{}", parts.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_uris() {
+        let device = |id: &str, partition, path: &str| SourceUri {
+            kind: SourceKind::Device(id.to_string()),
+            partition,
+            path: path.to_string(),
+        };
+        assert_eq!(SourceUri::parse("device:/dev/sdb").unwrap(), device("/dev/sdb", None, "/"));
+        assert_eq!(SourceUri::parse("device:/dev/sdb@part(2):/DCIM/100").unwrap(), device("/dev/sdb", Some(2), "/DCIM/100"));
+        assert_eq!(SourceUri::parse(r"device:\\.\PHYSICALDRIVE1@part(1):\Users\me").unwrap(),
+            device(r"\\.\PHYSICALDRIVE1", Some(1), "/Users/me"));
+        assert_eq!(SourceUri::parse("device:E:").unwrap(), device("E:", None, "/"));
+        assert_eq!(SourceUri::parse("device:E::/Photos").unwrap(), device("E:", None, "/Photos"));
+        assert_eq!(SourceUri::parse(r"image:C:\images\card.img@part(1):/DCIM").unwrap(), SourceUri {
+            kind: SourceKind::Image(PathBuf::from(r"C:\images\card.img")),
+            partition: Some(1),
+            path: "/DCIM".to_string(),
+        });
+        assert_eq!(SourceUri::parse("host:/home/me/photos").unwrap().kind, SourceKind::Host(PathBuf::from("/home/me/photos")));
+
+        // The forms commands took before the schemes
+        assert_eq!(SourceUri::parse("/dev/sdb1:/home/user").unwrap(), device("/dev/sdb1", None, "/home/user"));
+        assert_eq!(SourceUri::parse("/dev/sdb@part(1)").unwrap(), device("/dev/sdb", Some(1), "/"));
+        assert_eq!(SourceUri::parse("E:").unwrap(), device("E:", None, "/"));
+        assert_eq!(SourceUri::parse("Kingston").unwrap(), device("Kingston", None, "/"));
+        let dir = std::env::temp_dir();
+        assert_eq!(SourceUri::parse(dir.to_str().unwrap()).unwrap().kind, SourceKind::Host(dir));
+
+        // Printed in the canonical form, which parses back to the same source
+        for text in ["device:/dev/sdb@part(2):/DCIM/100", "image:/tmp/card.img", "host:/home/me"] {
+            assert_eq!(SourceUri::parse(text).unwrap().to_string(), text);
+        }
+        assert!(SourceUri::parse("device:/dev/sdb@part(0)").is_err());
+        assert!(SourceUri::parse("device:/dev/sdb:/../etc").is_err());
+        assert!(SourceUri::parse("image:").is_err());
+    }
+}
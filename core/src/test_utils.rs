@@ -78,6 +78,10 @@ impl MockDeviceManager {
                     mount_points: vec![PathBuf::from("/")],
                     is_removable: false,
                     is_system: true,
+                    is_write_protected: false,
+                    serial: None,
+                    erase_block_size: None,
+                    partition_offset: None,
                     filesystem: Some("ntfs".to_string()),
                 },
                 Device {
@@ -88,6 +92,10 @@ impl MockDeviceManager {
                     mount_points: vec![],
                     is_removable: true,
                     is_system: false,
+                    is_write_protected: false,
+                    serial: None,
+                    erase_block_size: None,
+                    partition_offset: None,
                     filesystem: Some("fat32".to_string()),
                 },
             ],
@@ -266,6 +274,12 @@ impl FilesystemFormatter for MockFormatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: device.size * 95 / 100,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
@@ -353,6 +367,10 @@ mod tests {
             mount_points: vec![],
             is_removable: false,
             is_system: true,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some("ntfs".to_string()),
         };
 
@@ -371,6 +389,10 @@ mod tests {
             mount_points: vec![PathBuf::from("C:")],
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some("ntfs".to_string()),
         };
 
@@ -389,6 +411,10 @@ mod tests {
             mount_points: vec![],
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some("fat32".to_string()),
         };
 
@@ -0,0 +1,140 @@
+// Events reported to external systems
+// Each event is wrapped with a timestamp and serialized as one flat JSON object, e.g.
+// {"event":"device_attached","timestamp":1700000000,"device":{...}}. The dispatcher fans
+// an event out to every configured webhook and MQTT broker that subscribed to its name.
+use moses_core::{Device, EventsConfig};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Give up on a slow receiver instead of holding up the caller
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MosesEvent {
+    DeviceAttached { device: Device },
+    DeviceDetached { device: Device },
+    FormatCompleted { device: Device, filesystem: String },
+    Error { operation: String, device_id: Option<String>, message: String },
+}
+
+impl MosesEvent {
+    /// Name used in the payload, in subscriptions and as the MQTT topic suffix
+    pub fn name(&self) -> &'static str {
+        match self {
+            MosesEvent::DeviceAttached { .. } => "device_attached",
+            MosesEvent::DeviceDetached { .. } => "device_detached",
+            MosesEvent::FormatCompleted { .. } => "format_completed",
+            MosesEvent::Error { .. } => "error",
+        }
+    }
+
+    /// JSON payload with the event name and a Unix timestamp
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Envelope<'a> {
+            timestamp: u64,
+            #[serde(flatten)]
+            event: &'a MosesEvent,
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        serde_json::to_string(&Envelope { timestamp, event: self }).unwrap_or_default()
+    }
+}
+
+/// `events` is a subscription list; an empty list means every event
+pub(crate) fn subscribed(events: &[String], event: &MosesEvent) -> bool {
+    events.is_empty() || events.iter().any(|name| name == event.name())
+}
+
+/// Sends events to the receivers in the user's configuration
+#[derive(Debug, Clone, Default)]
+pub struct EventDispatcher {
+    config: EventsConfig,
+}
+
+impl EventDispatcher {
+    pub fn new(config: EventsConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty()
+    }
+
+    /// Deliver to every subscribed receiver and wait for all of them. Failures are
+    /// logged and returned; one bad receiver never stops delivery to the others.
+    pub async fn deliver(&self, event: &MosesEvent) -> Vec<String> {
+        let payload = event.to_json();
+        let mut deliveries = tokio::task::JoinSet::new();
+
+        for hook in self.config.webhooks.iter().filter(|hook| subscribed(&hook.events, event)) {
+            let (hook, payload) = (hook.clone(), payload.clone());
+            deliveries.spawn(async move {
+                let target = hook.url.clone();
+                (target, crate::webhook::post(&hook, &payload).await)
+            });
+        }
+        for broker in self.config.mqtt.iter().filter(|broker| subscribed(&broker.events, event)) {
+            let (broker, payload, name) = (broker.clone(), payload.clone(), event.name());
+            deliveries.spawn(async move {
+                let target = format!("mqtt://{}:{}", broker.host, broker.port);
+                (target, crate::mqtt::publish(&broker, name, payload.as_bytes()).await)
+            });
+        }
+
+        let mut failures = Vec::new();
+        while let Some(joined) = deliveries.join_next().await {
+            let failure = match joined {
+                Ok((_, Ok(()))) => continue,
+                Ok((target, Err(e))) => format!("{}: {}", target, e),
+                Err(e) => format!("delivery task failed: {}", e),
+            };
+            tracing::warn!("Could not deliver {} event to {}", event.name(), failure);
+            failures.push(failure);
+        }
+        failures
+    }
+
+    /// Deliver in the background without waiting
+    pub fn emit(&self, event: MosesEvent) {
+        if self.is_empty() {
+            return;
+        }
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            dispatcher.deliver(&event).await;
+        });
+    }
+}
+
+/// Bound a delivery so an unreachable receiver can't stall the caller
+pub(crate) async fn with_timeout<T>(
+    future: impl std::future::Future<Output = Result<T, moses_core::MosesError>>,
+) -> Result<T, moses_core::MosesError> {
+    tokio::time::timeout(DELIVERY_TIMEOUT, future)
+        .await
+        .map_err(|_| moses_core::MosesError::Timeout(format!("no response within {}s", DELIVERY_TIMEOUT.as_secs())))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_and_subscriptions() {
+        let event = MosesEvent::Error {
+            operation: "format".to_string(),
+            device_id: Some("/dev/sdx".to_string()),
+            message: "boom".to_string(),
+        };
+        let payload: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(payload["event"], "error");
+        assert_eq!(payload["device_id"], "/dev/sdx");
+        assert!(payload["timestamp"].as_u64().unwrap() > 0);
+
+        assert!(subscribed(&[], &event));
+        assert!(subscribed(&["error".to_string()], &event));
+        assert!(!subscribed(&["device_attached".to_string()], &event));
+    }
+}
@@ -1,2 +1,10 @@
 // Daemon module for privileged operations
-// This will handle the actual formatting operations with elevated privileges
\ No newline at end of file
+// This will handle the actual formatting operations with elevated privileges.
+// For now it hosts `moses serve`: device watching and event delivery to webhooks and MQTT.
+pub mod events;
+pub mod mqtt;
+pub mod watcher;
+pub mod webhook;
+
+pub use events::{EventDispatcher, MosesEvent};
+pub use watcher::DeviceWatcher;
@@ -0,0 +1,113 @@
+// Minimal MQTT 3.1.1 publisher
+// Events are rare, so each one opens a connection, publishes at QoS 0 and disconnects.
+// That keeps this to the three packets needed instead of a full client with keep-alives.
+use moses_core::{MosesError, MqttConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xE0;
+const KEEP_ALIVE_SECS: u16 = 30;
+
+/// Publish `payload` to `<topic_prefix>/<event>`
+pub async fn publish(broker: &MqttConfig, event: &str, payload: &[u8]) -> Result<(), MosesError> {
+    let topic = format!("{}/{}", broker.topic_prefix.trim_end_matches('/'), event);
+    crate::events::with_timeout(send(broker, &topic, payload)).await
+}
+
+async fn send(broker: &MqttConfig, topic: &str, payload: &[u8]) -> Result<(), MosesError> {
+    let mut stream = TcpStream::connect((broker.host.as_str(), broker.port)).await?;
+    stream.write_all(&connect_packet(broker)).await?;
+
+    let mut ack = [0u8; 4];
+    stream.read_exact(&mut ack).await?;
+    if ack[0] != CONNACK || ack[3] != 0 {
+        return Err(MosesError::External(format!("MQTT broker refused the connection (code {})", ack[3])));
+    }
+
+    stream.write_all(&publish_packet(topic, payload)).await?;
+    stream.write_all(&[DISCONNECT, 0]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+fn connect_packet(broker: &MqttConfig) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    body.push(4); // protocol level 3.1.1
+    let flags_at = body.len();
+    body.push(0);
+    body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+
+    push_str(&mut body, &broker.client_id);
+    if let Some(username) = &broker.username {
+        flags |= 0x80;
+        push_str(&mut body, username);
+    }
+    if let Some(password) = &broker.password {
+        flags |= 0x40;
+        push_str(&mut body, password);
+    }
+    body[flags_at] = flags;
+    packet(CONNECT, body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload);
+    packet(PUBLISH, body)
+}
+
+fn push_str(buffer: &mut Vec<u8>, text: &str) {
+    buffer.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+/// Fixed header: packet type, then the body length as a variable-length integer
+fn packet(kind: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_encoding() {
+        // 321 bytes encode as 0xC1 0x02
+        let publish = publish_packet("moses/error", &[b'x'; 308]);
+        assert_eq!(&publish[..3], &[PUBLISH, 0xC1, 0x02]);
+        assert_eq!(publish.len(), 3 + 321);
+
+        let broker = MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            topic_prefix: "moses".to_string(),
+            client_id: "moses".to_string(),
+            username: Some("user".to_string()),
+            password: None,
+            events: vec![],
+        };
+        let connect = connect_packet(&broker);
+        assert_eq!(&connect[2..8], b"\x00\x04MQTT");
+        assert_eq!(connect[9], 0x82);
+    }
+}
@@ -0,0 +1,74 @@
+// Device attach/detach detection
+// Platforms have no common hotplug API, so the watcher rescans the device list on an
+// interval and reports the differences. Devices are matched by id.
+use crate::events::MosesEvent;
+use moses_core::Device;
+use std::collections::HashMap;
+
+pub struct DeviceWatcher {
+    known: HashMap<String, Device>,
+}
+
+impl DeviceWatcher {
+    /// Start from the devices present now, so they aren't reported as attached
+    pub fn new(initial: Vec<Device>) -> Self {
+        Self {
+            known: initial.into_iter().map(|device| (device.id.clone(), device)).collect(),
+        }
+    }
+
+    /// Events for the differences between the last scan and `current`
+    pub fn update(&mut self, current: Vec<Device>) -> Vec<MosesEvent> {
+        let mut current: HashMap<String, Device> =
+            current.into_iter().map(|device| (device.id.clone(), device)).collect();
+
+        let mut events: Vec<MosesEvent> = self.known
+            .iter()
+            .filter(|(id, _)| !current.contains_key(*id))
+            .map(|(_, device)| MosesEvent::DeviceDetached { device: device.clone() })
+            .collect();
+        events.extend(
+            current
+                .iter()
+                .filter(|(id, _)| !self.known.contains_key(*id))
+                .map(|(_, device)| MosesEvent::DeviceAttached { device: device.clone() }),
+        );
+
+        std::mem::swap(&mut self.known, &mut current);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::DeviceType;
+
+    fn device(id: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            name: id.to_string(),
+            size: 0,
+            device_type: DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_attach_and_detach() {
+        let mut watcher = DeviceWatcher::new(vec![device("/dev/sda"), device("/dev/sdb")]);
+        assert!(watcher.update(vec![device("/dev/sda"), device("/dev/sdb")]).is_empty());
+
+        let events = watcher.update(vec![device("/dev/sda"), device("/dev/sdc")]);
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().any(|event| matches!(event, MosesEvent::DeviceDetached { device } if device.id == "/dev/sdb")));
+        assert!(events.iter().any(|event| matches!(event, MosesEvent::DeviceAttached { device } if device.id == "/dev/sdc")));
+    }
+}
@@ -0,0 +1,42 @@
+// Webhook delivery
+// Like tool downloads, requests go through the system curl (bundled with Windows 10 and
+// later), which handles HTTPS and proxies without pulling a TLS stack into Moses.
+use moses_core::{MosesError, WebhookConfig};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// POST `payload` as JSON to the webhook's URL
+pub async fn post(hook: &WebhookConfig, payload: &str) -> Result<(), MosesError> {
+    crate::events::with_timeout(send(hook, payload)).await
+}
+
+async fn send(hook: &WebhookConfig, payload: &str) -> Result<(), MosesError> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--fail", "--silent", "--show-error", "--max-time", "10"])
+        .args(["--request", "POST", "--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-"]);
+    for (name, value) in &hook.headers {
+        command.arg("--header").arg(format!("{}: {}", name, value));
+    }
+    command
+        .arg(&hook.url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| MosesError::External(format!("Could not start curl: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(MosesError::External(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+}
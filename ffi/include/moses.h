@@ -0,0 +1,53 @@
+/*
+ * Moses C API: device enumeration, filesystem detection and read-only file access.
+ *
+ * Link against libmoses_ffi (moses_ffi.dll on Windows). Strings returned as `char *`
+ * are owned by the caller and must be released with moses_string_free(). On failure
+ * functions return NULL (or -1) and moses_last_error() describes the problem; the
+ * message is per thread and valid until the next call on that thread.
+ *
+ * `device` arguments accept a device id from moses_list_devices() (e.g. "/dev/sdb",
+ * "\\\\.\\PHYSICALDRIVE1") or the path of a disk image file.
+ */
+#ifndef MOSES_H
+#define MOSES_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Library version, e.g. "0.1.0". Static; do not free. */
+const char *moses_version(void);
+
+/* Message for the last failed call on this thread, or NULL. Do not free. */
+const char *moses_last_error(void);
+
+/* Release a string returned by this library. NULL is ignored. */
+void moses_string_free(char *text);
+
+/* JSON array of device objects (id, name, size, device_type, ...). */
+char *moses_list_devices(void);
+
+/* Filesystem on the device, e.g. "ext4", "fat32", "ntfs" or "unknown". */
+char *moses_detect(const char *device);
+
+/*
+ * JSON array of {"name", "size", "is_directory", "is_symlink", "modified"} for `path`.
+ * `filesystem` may be NULL to detect it.
+ */
+char *moses_list_directory(const char *device, const char *filesystem, const char *path);
+
+/*
+ * Copy the file at `path` to the local file `destination`.
+ * Returns the number of bytes copied, or -1 on error. `filesystem` may be NULL.
+ */
+int64_t moses_extract_file(const char *device, const char *filesystem, const char *path,
+                           const char *destination);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MOSES_H */
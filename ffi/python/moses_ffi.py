@@ -0,0 +1,90 @@
+"""Python bindings for the Moses C API (ffi/include/moses.h) using ctypes.
+
+    import moses_ffi
+    lib = moses_ffi.Moses()                      # or Moses("/path/to/libmoses_ffi.so")
+    for device in lib.list_devices():
+        print(device["id"], lib.detect(device["id"]))
+    print(lib.list_directory("disk.img", "/"))
+    lib.extract_file("disk.img", "/etc/hostname", "hostname")
+
+Build the library with `cargo build --release -p moses-ffi`.
+"""
+
+import ctypes
+import ctypes.util
+import json
+import os
+import sys
+
+
+class MosesError(Exception):
+    pass
+
+
+def _default_library_path():
+    names = {
+        "win32": "moses_ffi.dll",
+        "darwin": "libmoses_ffi.dylib",
+    }
+    name = names.get(sys.platform, "libmoses_ffi.so")
+    here = os.path.dirname(os.path.abspath(__file__))
+    for build in ("release", "debug"):
+        candidate = os.path.join(here, "..", "..", "target", build, name)
+        if os.path.exists(candidate):
+            return candidate
+    return ctypes.util.find_library("moses_ffi") or name
+
+
+class Moses:
+    def __init__(self, library_path=None):
+        self._lib = ctypes.CDLL(library_path or _default_library_path())
+        lib = self._lib
+
+        lib.moses_version.restype = ctypes.c_char_p
+        lib.moses_last_error.restype = ctypes.c_char_p
+        lib.moses_string_free.argtypes = [ctypes.c_void_p]
+        lib.moses_list_devices.restype = ctypes.c_void_p
+        lib.moses_detect.argtypes = [ctypes.c_char_p]
+        lib.moses_detect.restype = ctypes.c_void_p
+        lib.moses_list_directory.argtypes = [ctypes.c_char_p] * 3
+        lib.moses_list_directory.restype = ctypes.c_void_p
+        lib.moses_extract_file.argtypes = [ctypes.c_char_p] * 4
+        lib.moses_extract_file.restype = ctypes.c_int64
+
+    def _error(self):
+        message = self._lib.moses_last_error()
+        return MosesError(message.decode() if message else "unknown error")
+
+    def _take(self, pointer):
+        """Copy an owned result string and free it"""
+        if not pointer:
+            raise self._error()
+        try:
+            return ctypes.string_at(pointer).decode()
+        finally:
+            self._lib.moses_string_free(pointer)
+
+    @staticmethod
+    def _arg(value):
+        return None if value is None else os.fsencode(value)
+
+    def version(self):
+        return self._lib.moses_version().decode()
+
+    def list_devices(self):
+        return json.loads(self._take(self._lib.moses_list_devices()))
+
+    def detect(self, device):
+        return self._take(self._lib.moses_detect(self._arg(device)))
+
+    def list_directory(self, device, path="/", filesystem=None):
+        result = self._lib.moses_list_directory(self._arg(device), self._arg(filesystem), self._arg(path))
+        return json.loads(self._take(result))
+
+    def extract_file(self, device, path, destination, filesystem=None):
+        copied = self._lib.moses_extract_file(
+            self._arg(device), self._arg(filesystem), self._arg(path), self._arg(destination)
+        )
+        if copied < 0:
+            raise self._error()
+        return copied
@@ -0,0 +1,210 @@
+// C API over the moses facade, for tooling written in other languages
+// Results that are more than a number come back as JSON strings owned by the caller, which
+// keeps the ABI to plain pointers and integers. Failures return NULL (or -1) and leave a
+// message for `moses_last_error` on the calling thread. See include/moses.h.
+use moses::MosesError;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::Path;
+use std::sync::OnceLock;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning errors and panics into `on_error` plus a `moses_last_error` message
+fn guard<T>(on_error: T, f: impl FnOnce() -> Result<T, MosesError>) -> T {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            on_error
+        }
+        Err(_) => {
+            set_last_error("internal error (panic)".to_string());
+            on_error
+        }
+    }
+}
+
+/// # Safety
+/// `ptr` must be NULL or a valid NUL-terminated string
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, MosesError> {
+    if ptr.is_null() {
+        return Err(MosesError::InvalidInput(format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| MosesError::InvalidInput(format!("{} is not valid UTF-8", name)))
+}
+
+/// # Safety
+/// `ptr` must be NULL or a valid NUL-terminated string
+unsafe fn optional_str_arg<'a>(ptr: *const c_char, name: &str) -> Result<Option<&'a str>, MosesError> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        str_arg(ptr, name).map(Some)
+    }
+}
+
+fn into_c_string(text: String) -> Result<*mut c_char, MosesError> {
+    CString::new(text)
+        .map(CString::into_raw)
+        .map_err(|_| MosesError::Other("result contains a NUL byte".to_string()))
+}
+
+/// Device enumeration is async; callers of the C API are not
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, MosesError> {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    if RUNTIME.get().is_none() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let _ = RUNTIME.set(runtime);
+    }
+    Ok(RUNTIME.get().expect("runtime initialised above").block_on(future))
+}
+
+/// Version of the library, e.g. "0.1.0". Static; do not free.
+#[no_mangle]
+pub extern "C" fn moses_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Message for the last failed call on this thread, or NULL. Valid until the next call; do not free.
+#[no_mangle]
+pub extern "C" fn moses_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
+
+/// Free a string returned by this library
+///
+/// # Safety
+/// `text` must be NULL or a pointer returned by a `moses_*` function that documents the
+/// result as owned by the caller, and must not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn moses_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// JSON array of the disks attached to this machine
+#[no_mangle]
+pub extern "C" fn moses_list_devices() -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let devices = block_on(moses::devices::list())??;
+        into_c_string(serde_json::to_string(&devices)?)
+    })
+}
+
+/// Filesystem name on a device or image file, e.g. "ext4" or "unknown"
+///
+/// # Safety
+/// `device` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn moses_detect(device: *const c_char) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let device = block_on(moses::devices::open(str_arg(device, "device")?))??;
+        into_c_string(moses::detect::filesystem(&device)?)
+    })
+}
+
+/// JSON array of `{name, size, is_directory, is_symlink, modified}` for one directory
+///
+/// # Safety
+/// `device` and `path` must be valid NUL-terminated strings; `filesystem` may be NULL to detect it.
+#[no_mangle]
+pub unsafe extern "C" fn moses_list_directory(
+    device: *const c_char,
+    filesystem: *const c_char,
+    path: *const c_char,
+) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let device = block_on(moses::devices::open(str_arg(device, "device")?))??;
+        let mut fs = moses::read::open(&device, optional_str_arg(filesystem, "filesystem")?)?;
+        let entries: Vec<_> = fs.readdir(Path::new(str_arg(path, "path")?))?
+            .into_iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "name": entry.name,
+                    "size": entry.attributes.size,
+                    "is_directory": entry.attributes.is_directory,
+                    "is_symlink": entry.attributes.is_symlink,
+                    "modified": entry.attributes.modified,
+                })
+            })
+            .collect();
+        into_c_string(serde_json::to_string(&entries)?)
+    })
+}
+
+/// Copy one file out of a device or image to `destination`; returns the bytes copied or -1
+///
+/// # Safety
+/// `device`, `path` and `destination` must be valid NUL-terminated strings; `filesystem`
+/// may be NULL to detect it.
+#[no_mangle]
+pub unsafe extern "C" fn moses_extract_file(
+    device: *const c_char,
+    filesystem: *const c_char,
+    path: *const c_char,
+    destination: *const c_char,
+) -> i64 {
+    guard(-1, || {
+        let device = block_on(moses::devices::open(str_arg(device, "device")?))??;
+        let mut fs = moses::read::open(&device, optional_str_arg(filesystem, "filesystem")?)?;
+        let mut out = std::fs::File::create(str_arg(destination, "destination")?)?;
+        let copied = moses::read::extract(fs.as_mut(), Path::new(str_arg(path, "path")?), &mut out)?;
+        Ok(copied as i64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(text: &str) -> CString {
+        CString::new(text).unwrap()
+    }
+
+    unsafe fn take(text: *mut c_char) -> String {
+        assert!(!text.is_null(), "{:?}", CStr::from_ptr(moses_last_error()));
+        let owned = CStr::from_ptr(text).to_string_lossy().to_string();
+        moses_string_free(text);
+        owned
+    }
+
+    #[test]
+    fn test_read_image_through_c_api() {
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(1024 * 1024 * 1024).unwrap();
+        let device = moses::devices::from_image(image.path()).unwrap();
+        let options = moses::FormatOptions { filesystem_type: "ext4".to_string(), ..Default::default() };
+        block_on(moses::format::format(&device, &options)).unwrap().unwrap();
+
+        let path = c(&image.path().to_string_lossy());
+        unsafe {
+            assert_eq!(take(moses_detect(path.as_ptr())), "ext4");
+            let listing = take(moses_list_directory(path.as_ptr(), std::ptr::null(), c("/").as_ptr()));
+            assert!(serde_json::from_str::<serde_json::Value>(&listing).unwrap().is_array());
+        }
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        unsafe {
+            assert!(moses_detect(std::ptr::null()).is_null());
+            let message = CStr::from_ptr(moses_last_error()).to_string_lossy();
+            assert!(message.contains("device is NULL"));
+
+            let missing = c("/nonexistent/moses.img");
+            assert_eq!(moses_extract_file(missing.as_ptr(), std::ptr::null(), c("/a").as_ptr(), c("/tmp/a").as_ptr()), -1);
+        }
+    }
+}
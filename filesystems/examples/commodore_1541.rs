@@ -186,6 +186,12 @@ impl FilesystemFormatter for Commodore1541Formatter {
             required_tools,
             will_erase_data: true,
             space_after_format: 144_896, // Usable space after BAM and directory
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
@@ -0,0 +1,414 @@
+// Filesystem advisor - recommend a filesystem for what a drive will be used for
+// The user names one or more goals ("share between Windows and Mac", "Linux boot drive",
+// "files over 4 GB"). Each goal becomes requirements: which operating systems must be able
+// to write the volume without extra drivers, how large a single file can be, whether Unix
+// owners and permissions are kept. Every registered formatter is checked against them using
+// its FormatterMetadata (size range, largest file, permissions) and what each operating
+// system can do with the filesystem out of the box. The best match comes back with its
+// trade-offs and FormatOptions filled in, ready to simulate.
+use std::collections::HashMap;
+use std::str::FromStr;
+use moses_core::{Device, DeviceType, FormatOptions, FormatPreset, FormatterMetadata, FormatterRegistry, MosesError, Platform};
+use serde::{Serialize, Deserialize};
+
+/// Files of this size or more don't fit on FAT
+const LARGE_FILE: u64 = 4 * 1024 * 1024 * 1024;
+/// SD cards up to this size are SDHC and come with FAT32, larger ones are SDXC with exFAT
+const SDHC_LIMIT: u64 = 32 * 1024 * 1024 * 1024;
+/// Largest FAT32 volume Windows creates itself, though it reads larger ones
+const WINDOWS_FAT32_LIMIT: u64 = 32 * 1024 * 1024 * 1024;
+
+/// What the drive will be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Goal {
+    /// Read and written on both Windows and macOS
+    ShareWindowsMac,
+    /// Read by anything with a USB port, from all three desktop systems to TVs and car stereos
+    Everywhere,
+    /// Single files of 4 GB or more, such as disk images and long videos
+    LargeFiles,
+    /// Installing or running Linux from the drive
+    LinuxBoot,
+    /// Storage for Linux machines only
+    LinuxOnly,
+    /// Storage for Windows machines only
+    WindowsOnly,
+    /// A memory card for a camera, dashcam or drone
+    Camera,
+}
+
+impl Goal {
+    pub const ALL: [Goal; 7] = [
+        Goal::ShareWindowsMac, Goal::Everywhere, Goal::LargeFiles, Goal::LinuxBoot,
+        Goal::LinuxOnly, Goal::WindowsOnly, Goal::Camera,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Goal::ShareWindowsMac => "share-windows-mac",
+            Goal::Everywhere => "everywhere",
+            Goal::LargeFiles => "large-files",
+            Goal::LinuxBoot => "linux-boot",
+            Goal::LinuxOnly => "linux-only",
+            Goal::WindowsOnly => "windows-only",
+            Goal::Camera => "camera",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Goal::ShareWindowsMac => "Share between Windows and Mac",
+            Goal::Everywhere => "Use with any computer, TV, car stereo or console",
+            Goal::LargeFiles => "Store single files of 4 GB or more",
+            Goal::LinuxBoot => "Install or boot Linux from it",
+            Goal::LinuxOnly => "Storage for Linux only",
+            Goal::WindowsOnly => "Storage for Windows only",
+            Goal::Camera => "Memory card for a camera, dashcam or drone",
+        }
+    }
+}
+
+impl FromStr for Goal {
+    type Err = MosesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase().replace('_', "-");
+        Self::ALL.into_iter().find(|goal| goal.as_str() == s).ok_or_else(|| {
+            let known: Vec<&str> = Self::ALL.iter().map(|goal| goal.as_str()).collect();
+            MosesError::InvalidInput(format!("Unknown goal '{}' (expected one of {})", s, known.join(", ")))
+        })
+    }
+}
+
+/// What an operating system can do with a filesystem without extra drivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Access {
+    Unsupported,
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Out-of-the-box support for a filesystem on a current Windows, macOS or Linux
+pub fn native_access(filesystem: &str, platform: Platform) -> Access {
+    match (filesystem, platform) {
+        ("fat12" | "fat16" | "fat32" | "exfat", _) => Access::ReadWrite,
+        ("ntfs", Platform::MacOS) => Access::ReadOnly,
+        ("ntfs", _) => Access::ReadWrite,
+        ("ext2" | "ext3" | "ext4", Platform::Linux) => Access::ReadWrite,
+        _ => Access::Unsupported,
+    }
+}
+
+fn journaled(filesystem: &str) -> bool {
+    matches!(filesystem, "ext3" | "ext4" | "ntfs")
+}
+
+/// A filesystem that meets every goal, with why and at what cost
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recommendation {
+    pub filesystem: String,
+    pub description: String,
+    pub benefits: Vec<String>,
+    pub trade_offs: Vec<String>,
+    /// Ready for a simulation or format of the device
+    pub options: FormatOptions,
+}
+
+/// A filesystem that fails at least one goal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rejection {
+    pub filesystem: String,
+    pub reasons: Vec<String>,
+}
+
+/// Result of [`advise`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advice {
+    pub goals: Vec<Goal>,
+    /// `None` when no filesystem meets every goal
+    pub recommended: Option<Recommendation>,
+    /// Other filesystems that meet every goal, best first
+    pub alternatives: Vec<Recommendation>,
+    pub rejected: Vec<Rejection>,
+}
+
+impl Advice {
+    /// Human-readable advice for the CLI
+    pub fn to_report(&self) -> String {
+        let goals: Vec<&str> = self.goals.iter().map(|goal| goal.description()).collect();
+        let mut report = format!("Goals: {}\n", goals.join("; "));
+        match &self.recommended {
+            Some(best) => {
+                report.push_str(&format!("\nRecommended: {} - {}\n", best.filesystem, best.description));
+                push_recommendation(&mut report, best);
+            }
+            None => report.push_str("\nNo filesystem meets every goal; drop one and ask again.\n"),
+        }
+        for alternative in &self.alternatives {
+            report.push_str(&format!("\nAlternative: {}\n", alternative.filesystem));
+            push_recommendation(&mut report, alternative);
+        }
+        if !self.rejected.is_empty() {
+            report.push_str("\nNot suitable:\n");
+            for rejection in &self.rejected {
+                report.push_str(&format!("  {:<8} {}\n", rejection.filesystem, rejection.reasons.join("; ")));
+            }
+        }
+        report
+    }
+}
+
+fn push_recommendation(report: &mut String, recommendation: &Recommendation) {
+    for benefit in &recommendation.benefits {
+        report.push_str(&format!("  + {}\n", benefit));
+    }
+    for trade_off in &recommendation.trade_offs {
+        report.push_str(&format!("  - {}\n", trade_off));
+    }
+}
+
+/// What the goals ask of a filesystem
+#[derive(Debug, Default)]
+struct Requirements {
+    writable_on: Vec<Platform>,
+    /// TVs, car stereos and other appliances, which often read nothing but FAT
+    appliances: bool,
+    large_files: bool,
+    permissions: bool,
+    camera: bool,
+}
+
+impl Requirements {
+    fn from_goals(goals: &[Goal]) -> Self {
+        let mut requirements = Self::default();
+        for goal in goals {
+            let platforms: &[Platform] = match goal {
+                Goal::ShareWindowsMac => &[Platform::Windows, Platform::MacOS],
+                Goal::Everywhere => &[Platform::Windows, Platform::MacOS, Platform::Linux],
+                Goal::LinuxBoot | Goal::LinuxOnly => &[Platform::Linux],
+                Goal::WindowsOnly => &[Platform::Windows],
+                Goal::LargeFiles | Goal::Camera => &[],
+            };
+            for platform in platforms {
+                if !requirements.writable_on.contains(platform) {
+                    requirements.writable_on.push(*platform);
+                }
+            }
+            match goal {
+                Goal::Everywhere => requirements.appliances = true,
+                Goal::LargeFiles => requirements.large_files = true,
+                Goal::LinuxBoot => requirements.permissions = true,
+                Goal::Camera => requirements.camera = true,
+                _ => {}
+            }
+        }
+        requirements
+    }
+}
+
+/// Recommend a filesystem from `registry` for `device` and the goals
+pub fn advise(registry: &FormatterRegistry, device: &Device, goals: &[Goal]) -> Advice {
+    let requirements = Requirements::from_goals(goals);
+    let mut suitable = Vec::new();
+    let mut rejected = Vec::new();
+
+    let mut formatters = registry.list_with_metadata();
+    formatters.sort_by_key(|(name, _)| *name);
+    for (name, metadata) in formatters {
+        let reasons = unmet_requirements(name, metadata, device, &requirements);
+        if reasons.is_empty() {
+            suitable.push((score(name, device, &requirements), recommend(name, metadata, device, &requirements)));
+        } else {
+            rejected.push(Rejection { filesystem: name.to_string(), reasons });
+        }
+    }
+
+    suitable.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    let mut suitable = suitable.into_iter().map(|(_, recommendation)| recommendation);
+    Advice {
+        goals: goals.to_vec(),
+        recommended: suitable.next(),
+        alternatives: suitable.collect(),
+        rejected,
+    }
+}
+
+fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Windows => "Windows",
+        Platform::MacOS => "macOS",
+        Platform::Linux => "Linux",
+    }
+}
+
+fn gigabytes(bytes: u64) -> String {
+    format!("{} GB", (bytes + (1 << 29)) >> 30)
+}
+
+/// Why `name` can't be used for the goals; empty when it can
+fn unmet_requirements(name: &str, metadata: &FormatterMetadata, device: &Device, requirements: &Requirements) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if metadata.min_size.is_some_and(|min| device.size < min) {
+        reasons.push(format!("needs a drive of at least {} MB", metadata.min_size.unwrap() >> 20));
+    }
+    if metadata.max_size.is_some_and(|max| device.size > max) {
+        reasons.push(format!("volumes are limited to {}", gigabytes(metadata.max_size.unwrap())));
+    }
+    for &platform in &requirements.writable_on {
+        match native_access(name, platform) {
+            Access::ReadWrite => {}
+            Access::ReadOnly => reasons.push(format!("{} can only read it", platform_name(platform))),
+            Access::Unsupported => reasons.push(format!("{} can't read it without extra drivers", platform_name(platform))),
+        }
+    }
+    if requirements.large_files && metadata.capabilities.max_file_size.is_some_and(|max| max < LARGE_FILE) {
+        reasons.push("no single file can be 4 GB or larger".to_string());
+    }
+    if requirements.permissions && !metadata.capabilities.preserves_permissions {
+        reasons.push("doesn't keep Unix owners and permissions".to_string());
+    }
+    reasons
+}
+
+/// How well `name` suits the goals beyond meeting them; higher is better
+fn score(name: &str, device: &Device, requirements: &Requirements) -> i32 {
+    let mut score = match name {
+        "ext4" | "ntfs" => 30,
+        "exfat" => 20,
+        "fat32" => 15,
+        "ext3" => 10,
+        _ => 0,
+    };
+    // Cameras follow the SD specification: FAT32 on SDHC, exFAT on SDXC
+    if requirements.camera {
+        score += match (name, device.size <= SDHC_LIMIT) {
+            ("fat32", true) | ("exfat", false) => 40,
+            _ => 0,
+        };
+    }
+    if requirements.appliances {
+        score += match name {
+            "fat32" => 40,
+            "fat16" => 10,
+            _ => 0,
+        };
+    }
+    score
+}
+
+fn recommend(name: &str, metadata: &FormatterMetadata, device: &Device, requirements: &Requirements) -> Recommendation {
+    let mut benefits = Vec::new();
+    let mut trade_offs = Vec::new();
+
+    let access = |wanted: Access| -> Vec<&str> {
+        [Platform::Windows, Platform::MacOS, Platform::Linux].into_iter()
+            .filter(|&platform| native_access(name, platform) == wanted)
+            .map(platform_name)
+            .collect()
+    };
+    let writable = access(Access::ReadWrite);
+    if !writable.is_empty() {
+        benefits.push(format!("{} write it without extra drivers", writable.join(", ")));
+    }
+    let read_only = access(Access::ReadOnly);
+    if !read_only.is_empty() {
+        trade_offs.push(format!("{} can only read it", read_only.join(", ")));
+    }
+    let unsupported = access(Access::Unsupported);
+    if !unsupported.is_empty() {
+        trade_offs.push(format!("{} need extra drivers to read it", unsupported.join(", ")));
+    }
+
+    match metadata.capabilities.max_file_size {
+        Some(max) if max < LARGE_FILE => trade_offs.push(format!("files are limited to {} MB", (max + 1) >> 20)),
+        _ => benefits.push("no practical limit on file size".to_string()),
+    }
+    if journaled(name) {
+        benefits.push("journaled, so it recovers quickly when unplugged without ejecting".to_string());
+    } else {
+        trade_offs.push("no journal, so unplugging without ejecting can corrupt it".to_string());
+    }
+    if metadata.capabilities.preserves_permissions {
+        benefits.push("keeps Unix owners and permissions".to_string());
+    }
+    if requirements.appliances && name == "exfat" {
+        trade_offs.push("older TVs, car stereos and consoles only read FAT32".to_string());
+    }
+    if name == "fat32" && device.size > WINDOWS_FAT32_LIMIT {
+        trade_offs.push(format!("Windows only creates FAT32 volumes up to {}, though it reads this one", gigabytes(WINDOWS_FAT32_LIMIT)));
+    }
+
+    let mut additional_options = HashMap::from([("create_partition_table".to_string(), "true".to_string())]);
+    let preset = if requirements.camera {
+        Some(FormatPreset::SdCard)
+    } else if name.starts_with("ext") && matches!(device.device_type, DeviceType::USB | DeviceType::SDCard) {
+        Some(FormatPreset::Flash)
+    } else {
+        None
+    };
+    if let Some(preset) = preset {
+        benefits.push(format!("{} preset for this drive", preset.as_str()));
+        additional_options.insert(FormatPreset::OPTION_KEY.to_string(), preset.as_str().to_string());
+    }
+
+    Recommendation {
+        filesystem: name.to_string(),
+        description: metadata.description.clone(),
+        benefits,
+        trade_offs,
+        options: FormatOptions {
+            filesystem_type: name.to_string(),
+            additional_options,
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registration::builtin_registry;
+    use crate::test_helpers::create_test_device;
+
+    fn advise_for(size: u64, goals: &[&str]) -> Advice {
+        let goals: Vec<Goal> = goals.iter().map(|goal| goal.parse().unwrap()).collect();
+        advise(builtin_registry(), &create_test_device("disk", size), &goals)
+    }
+
+    fn recommended(advice: &Advice) -> &str {
+        advice.recommended.as_ref().map(|r| r.filesystem.as_str()).unwrap_or("none")
+    }
+
+    #[test]
+    fn test_goals_pick_filesystems() {
+        const GB: u64 = 1024 * 1024 * 1024;
+        assert_eq!(recommended(&advise_for(64 * GB, &["share-windows-mac"])), "exfat");
+        assert_eq!(recommended(&advise_for(16 * GB, &["everywhere"])), "fat32");
+        assert_eq!(recommended(&advise_for(64 * GB, &["linux-boot"])), "ext4");
+        assert_eq!(recommended(&advise_for(16 * GB, &["camera"])), "fat32");
+        assert_eq!(recommended(&advise_for(128 * GB, &["camera"])), "exfat");
+
+        let advice = advise_for(64 * GB, &["everywhere", "large_files"]);
+        let best = advice.recommended.as_ref().unwrap();
+        assert_eq!(best.filesystem, "exfat");
+        assert!(best.trade_offs.iter().any(|t| t.contains("only read FAT32")));
+        let fat32 = advice.rejected.iter().find(|r| r.filesystem == "fat32").unwrap();
+        assert_eq!(fat32.reasons, vec!["no single file can be 4 GB or larger"]);
+        assert_eq!(best.options.filesystem_type, "exfat");
+
+        assert!(advise_for(64 * GB, &["linux-boot", "share-windows-mac"]).recommended.is_none());
+        assert!("floppy".parse::<Goal>().is_err());
+    }
+
+    #[test]
+    fn test_options_are_prefilled() {
+        let mut card = create_test_device("card", 16 * 1024 * 1024 * 1024);
+        card.device_type = DeviceType::SDCard;
+        let advice = advise(builtin_registry(), &card, &[Goal::LinuxOnly]);
+        let best = advice.recommended.unwrap();
+        assert_eq!(best.filesystem, "ext4");
+        assert_eq!(best.options.additional_options.get(FormatPreset::OPTION_KEY).map(String::as_str), Some("flash"));
+        assert!(builtin_registry().get_formatter(&best.options.filesystem_type).is_some());
+    }
+}
@@ -0,0 +1,520 @@
+// Allocation maps - which parts of a volume hold data
+// Every family records its allocated space on disk: ext block bitmaps, the FAT, the exFAT
+// allocation bitmap and the NTFS $Bitmap file. These are read into one bitmap of allocation
+// units (blocks or clusters; the system area in front of a FAT or exFAT cluster heap counts
+// as used), which yields the allocated extents in disk order. The downsampled map and the
+// fragmentation hotspots the GUI block map and `moses usage-map` draw are built from those.
+
+use std::io::{Read, Seek, SeekFrom};
+use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::diagnostics::{read_at, read_partition_table, run_detectors};
+use crate::families::ext::ext4_native::core::{constants::EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER, structures::Ext4Superblock};
+use crate::families::ext::ext4_native::reader::{load_superblock, load_group_descriptors, group_desc_size};
+
+/// Group descriptor flag: the block bitmap was never initialized, nothing but metadata is used
+const EXT4_BG_BLOCK_UNINIT: u16 = 0x0002;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u64 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u64
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_exact_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize, what: &str) -> Result<Vec<u8>, MosesError> {
+    read_at(reader, offset, len)
+        .ok_or_else(|| MosesError::Other(format!("Failed to read {} at 0x{:X}", what, offset)))
+}
+
+/// A run of allocated units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extent {
+    pub start: u64,
+    pub length: u64,
+}
+
+/// One bit per allocation unit of a volume
+#[derive(Debug, Clone)]
+pub struct AllocationBitmap {
+    pub filesystem: String,
+    /// Bytes per unit (block or cluster)
+    pub unit_size: u32,
+    pub total_units: u64,
+    bits: Vec<u8>,
+}
+
+impl AllocationBitmap {
+    fn new(filesystem: &str, unit_size: u32, total_units: u64) -> Self {
+        Self {
+            filesystem: filesystem.to_string(),
+            unit_size,
+            total_units,
+            bits: vec![0u8; total_units.div_ceil(8) as usize],
+        }
+    }
+
+    fn set(&mut self, unit: u64) {
+        if unit < self.total_units {
+            self.bits[(unit / 8) as usize] |= 1 << (unit % 8);
+        }
+    }
+
+    fn set_range(&mut self, start: u64, length: u64) {
+        for unit in start..(start + length).min(self.total_units) {
+            self.set(unit);
+        }
+    }
+
+    /// Copy an on-disk little-endian bitmap covering units from `start`
+    fn copy_bits(&mut self, start: u64, bitmap: &[u8], count: u64) {
+        for bit in 0..count.min(bitmap.len() as u64 * 8) {
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) != 0 {
+                self.set(start + bit);
+            }
+        }
+    }
+
+    pub fn is_allocated(&self, unit: u64) -> bool {
+        unit < self.total_units && self.bits[(unit / 8) as usize] & (1 << (unit % 8)) != 0
+    }
+
+    pub fn allocated_units(&self) -> u64 {
+        // Bits past total_units are never set
+        self.bits.iter().map(|b| b.count_ones() as u64).sum()
+    }
+
+    /// Allocated extents in ascending order
+    pub fn extents(&self) -> AllocatedExtents<'_> {
+        AllocatedExtents { bitmap: self, next: 0 }
+    }
+}
+
+/// Iterator over the allocated extents of an [`AllocationBitmap`]
+pub struct AllocatedExtents<'a> {
+    bitmap: &'a AllocationBitmap,
+    next: u64,
+}
+
+impl Iterator for AllocatedExtents<'_> {
+    type Item = Extent;
+
+    fn next(&mut self) -> Option<Extent> {
+        let total = self.bitmap.total_units;
+        let mut unit = self.next;
+        while unit < total && !self.bitmap.is_allocated(unit) {
+            // Skip whole free bytes at once
+            if unit.is_multiple_of(8) && self.bitmap.bits[(unit / 8) as usize] == 0 {
+                unit += 8;
+            } else {
+                unit += 1;
+            }
+        }
+        if unit >= total {
+            self.next = total;
+            return None;
+        }
+        let start = unit;
+        while unit < total && self.bitmap.is_allocated(unit) {
+            if unit.is_multiple_of(8) && self.bitmap.bits[(unit / 8) as usize] == 0xFF && unit + 8 <= total {
+                unit += 8;
+            } else {
+                unit += 1;
+            }
+        }
+        self.next = unit;
+        Some(Extent { start, length: unit - start })
+    }
+}
+
+/// A volume inside a disk, with offsets relative to the volume start
+pub(crate) struct VolumeReader<'a, R> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) start: u64,
+}
+
+impl<R: Read> Read for VolumeReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for VolumeReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.start + offset),
+            other => other,
+        };
+        Ok(self.inner.seek(pos)?.saturating_sub(self.start))
+    }
+}
+
+/// Whether a group carries a superblock backup: all of them, or with sparse_super
+/// only groups 0, 1 and powers of 3, 5 and 7
+fn has_superblock_backup(sb: &Ext4Superblock, group: u64) -> bool {
+    if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER == 0 || group <= 1 {
+        return true;
+    }
+    [3u64, 5, 7].iter().any(|&base| {
+        let mut power = base;
+        while power < group {
+            power *= base;
+        }
+        power == group
+    })
+}
+
+fn ext_allocation<R: Read + Seek>(reader: &mut R, filesystem: &str) -> Result<AllocationBitmap, MosesError> {
+    let sb = load_superblock(reader)?;
+    let descriptors = load_group_descriptors(reader, &sb)?;
+    let block_size = sb.s_block_size();
+    let blocks = sb.s_blocks_count_lo as u64 | ((sb.s_blocks_count_hi as u64) << 32);
+    let per_group = sb.s_blocks_per_group as u64;
+    let first = sb.s_first_data_block as u64;
+
+    let gdt_blocks = (descriptors.len() * group_desc_size(&sb)).div_ceil(block_size as usize) as u64;
+    let inode_table_blocks = (sb.s_inodes_per_group as u64 * sb.s_inode_size as u64).div_ceil(block_size as u64);
+
+    let mut bitmap = AllocationBitmap::new(filesystem, block_size, blocks);
+    bitmap.set_range(0, first);
+    for (group, gd) in descriptors.iter().enumerate() {
+        let start = first + group as u64 * per_group;
+        let count = per_group.min(blocks - start);
+        if gd.bg_flags & EXT4_BG_BLOCK_UNINIT != 0 {
+            // No bitmap on disk: only a superblock backup and the group's own metadata are used
+            if has_superblock_backup(&sb, group as u64) {
+                bitmap.set_range(start, 1 + gdt_blocks + sb.s_reserved_gdt_blocks as u64);
+            }
+            bitmap.set(gd.bg_block_bitmap_lo as u64 | ((gd.bg_block_bitmap_hi as u64) << 32));
+            bitmap.set(gd.bg_inode_bitmap_lo as u64 | ((gd.bg_inode_bitmap_hi as u64) << 32));
+            let inode_table = gd.bg_inode_table_lo as u64 | ((gd.bg_inode_table_hi as u64) << 32);
+            bitmap.set_range(inode_table, inode_table_blocks);
+            continue;
+        }
+        let location = gd.bg_block_bitmap_lo as u64 | ((gd.bg_block_bitmap_hi as u64) << 32);
+        let bits = read_exact_at(reader, location * block_size as u64, block_size as usize, "block bitmap")?;
+        bitmap.copy_bits(start, &bits, count);
+    }
+    Ok(bitmap)
+}
+
+fn fat_allocation<R: Read + Seek>(reader: &mut R, filesystem: &str, boot: &[u8]) -> Result<AllocationBitmap, MosesError> {
+    let bytes_per_sector = u16_at(boot, 11);
+    let cluster_size = bytes_per_sector * boot[13] as u64;
+    let reserved = u16_at(boot, 14);
+    let fats = boot[16] as u64;
+    let root_sectors = (u16_at(boot, 17) * 32).div_ceil(bytes_per_sector.max(1));
+    let fat_sectors = match u16_at(boot, 22) { 0 => u32_at(boot, 36), n => n };
+    let total_sectors = match u16_at(boot, 19) { 0 => u32_at(boot, 32), n => n };
+    let data_start = reserved + fats * fat_sectors + root_sectors;
+    if cluster_size == 0 || total_sectors <= data_start {
+        return Err(MosesError::Other("Corrupt FAT boot sector: invalid geometry".to_string()));
+    }
+    let clusters = (total_sectors - data_start) / boot[13] as u64;
+
+    // The reserved sectors, FATs and root directory come first as used units
+    let system_units = (data_start * bytes_per_sector).div_ceil(cluster_size);
+    let mut bitmap = AllocationBitmap::new(filesystem, cluster_size as u32, system_units + clusters);
+    bitmap.set_range(0, system_units);
+
+    let fat_bytes = (fat_sectors * bytes_per_sector) as usize;
+    let fat = read_exact_at(reader, reserved * bytes_per_sector, fat_bytes, "FAT")?;
+    for cluster in 2..clusters + 2 {
+        let entry = if clusters < 4085 {
+            let at = (cluster + cluster / 2) as usize;
+            if at + 1 >= fat.len() { break; }
+            let pair = u16_at(&fat, at);
+            if cluster % 2 == 0 { pair & 0xFFF } else { pair >> 4 }
+        } else if clusters < 65525 {
+            let at = cluster as usize * 2;
+            if at + 2 > fat.len() { break; }
+            u16_at(&fat, at)
+        } else {
+            let at = cluster as usize * 4;
+            if at + 4 > fat.len() { break; }
+            u32_at(&fat, at) & 0x0FFF_FFFF
+        };
+        if entry != 0 {
+            bitmap.set(system_units + cluster - 2);
+        }
+    }
+    Ok(bitmap)
+}
+
+fn exfat_allocation<R: Read + Seek>(reader: &mut R, boot: &[u8]) -> Result<AllocationBitmap, MosesError> {
+    let sector_size = 1u64 << boot[108].min(12);
+    let cluster_size = sector_size << boot[109].min(25);
+    let heap = u32_at(boot, 88) * sector_size;
+    let clusters = u32_at(boot, 92);
+    let root = u32_at(boot, 96);
+    let cluster_offset = |cluster: u64| heap + cluster.saturating_sub(2) * cluster_size;
+
+    // The allocation bitmap is described by an entry of type 0x81 in the root directory
+    let root_dir = read_exact_at(reader, cluster_offset(root), cluster_size as usize, "root directory")?;
+    let (first, length) = root_dir.as_chunks::<32>().0.iter()
+        .take_while(|entry| entry[0] != 0)
+        .find(|entry| entry[0] == 0x81)
+        .map(|entry| (u32_at(entry, 20), u64_at(entry, 24)))
+        .ok_or_else(|| MosesError::Other("exFAT root directory has no allocation bitmap entry".to_string()))?;
+    let bits = read_exact_at(reader, cluster_offset(first), length.min(clusters.div_ceil(8)) as usize, "allocation bitmap")?;
+
+    let system_units = heap.div_ceil(cluster_size);
+    let mut bitmap = AllocationBitmap::new("exfat", cluster_size as u32, system_units + clusters);
+    bitmap.set_range(0, system_units);
+    bitmap.copy_bits(system_units, &bits, clusters);
+    Ok(bitmap)
+}
+
+fn ntfs_allocation<R: Read + Seek>(reader: &mut R, boot: &[u8]) -> Result<AllocationBitmap, MosesError> {
+    use crate::families::ntfs::ntfs::attributes::AttributeData;
+    use crate::families::ntfs::ntfs::mft::MftRecord;
+    use crate::families::ntfs::ntfs::structures::{ATTR_TYPE_DATA, MFT_RECORD_BITMAP};
+
+    let bytes_per_sector = u16_at(boot, 11);
+    // Values above 0x80 are negative powers of two
+    let sectors_per_cluster = match boot[13] { n if n > 0x80 => 1u64 << (256 - n as u64), n => n as u64 };
+    let cluster_size = bytes_per_sector * sectors_per_cluster;
+    if cluster_size == 0 {
+        return Err(MosesError::Other("Corrupt NTFS boot sector: invalid geometry".to_string()));
+    }
+    let clusters = u64_at(boot, 40) / sectors_per_cluster;
+    let record_size = match boot[64] as i8 {
+        n if n < 0 => 1u64 << (-(n as i64)),
+        n => n as u64 * cluster_size,
+    };
+
+    // $Bitmap sits among the first MFT records, which are contiguous at the start of the MFT
+    let mft = u64_at(boot, 48) * cluster_size;
+    let raw = read_exact_at(reader, mft + MFT_RECORD_BITMAP * record_size, record_size as usize, "$Bitmap record")?;
+    let mut record = MftRecord::parse(raw)?;
+    let bits = match record.find_attribute(ATTR_TYPE_DATA) {
+        Some(AttributeData::Data(bytes)) => bytes.clone(),
+        Some(AttributeData::DataRuns(runs)) => {
+            let mut bits = Vec::new();
+            for run in runs.clone() {
+                let length = (run.length * cluster_size) as usize;
+                match run.lcn {
+                    Some(lcn) => bits.extend(read_exact_at(reader, lcn * cluster_size, length, "$Bitmap data")?),
+                    None => bits.resize(bits.len() + length, 0),
+                }
+            }
+            bits
+        }
+        _ => return Err(MosesError::Other("$Bitmap has no data attribute".to_string())),
+    };
+
+    let mut bitmap = AllocationBitmap::new("ntfs", cluster_size as u32, clusters);
+    bitmap.copy_bits(0, &bits, clusters);
+    Ok(bitmap)
+}
+
+/// Read the allocation record of the volume starting at `offset`
+pub fn read_allocation<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<AllocationBitmap, MosesError> {
+    let boot = read_exact_at(reader, offset, 512, "boot sector")?;
+    let superblock = read_at(reader, offset + 1024, 1024);
+    let filesystem = run_detectors(&boot, superblock.as_deref())
+        .ok_or_else(|| MosesError::Other(format!("No supported filesystem at 0x{:X}", offset)))?;
+
+    let mut volume = VolumeReader { inner: reader, start: offset };
+    match filesystem.as_str() {
+        fs if fs.starts_with("ext") => ext_allocation(&mut volume, fs),
+        fs @ ("fat12" | "fat16" | "fat32") => fat_allocation(&mut volume, fs, &boot),
+        "exfat" => exfat_allocation(&mut volume, &boot),
+        "ntfs" => ntfs_allocation(&mut volume, &boot),
+        other => Err(MosesError::NotSupported(format!("No allocation map for {}", other))),
+    }
+}
+
+/// A run of map cells where allocated extents are unusually dense
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FragmentationHotspot {
+    pub first_cell: usize,
+    pub cells: usize,
+    /// Allocated extents starting in these cells
+    pub extents: u64,
+}
+
+/// Downsampled allocation map of a volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationMap {
+    pub filesystem: String,
+    pub unit_size: u32,
+    pub total_units: u64,
+    pub allocated_units: u64,
+    pub extent_count: u64,
+    /// Units each cell covers; the last cell may cover fewer
+    pub units_per_cell: u64,
+    /// Percentage of each cell's units in use, 0-100
+    pub cells: Vec<u8>,
+    /// Densest areas first
+    pub hotspots: Vec<FragmentationHotspot>,
+}
+
+/// Most hotspots reported
+const MAX_HOTSPOTS: usize = 5;
+/// A cell is a hotspot when this many times more extents start in it than on average
+const HOTSPOT_FACTOR: u64 = 4;
+
+impl AllocationMap {
+    /// Build a map of at most `cells` cells from the allocated extents
+    pub fn from_bitmap(bitmap: &AllocationBitmap, cells: usize) -> Self {
+        let total = bitmap.total_units;
+        let units_per_cell = total.div_ceil(cells.max(1) as u64).max(1);
+        let cell_count = total.div_ceil(units_per_cell) as usize;
+        let mut used = vec![0u64; cell_count];
+        let mut starts = vec![0u64; cell_count];
+        let mut extent_count = 0;
+
+        for extent in bitmap.extents() {
+            extent_count += 1;
+            starts[(extent.start / units_per_cell) as usize] += 1;
+            let mut unit = extent.start;
+            let end = extent.start + extent.length;
+            while unit < end {
+                let cell = unit / units_per_cell;
+                let cell_end = ((cell + 1) * units_per_cell).min(end);
+                used[cell as usize] += cell_end - unit;
+                unit = cell_end;
+            }
+        }
+
+        let cells_out = used.iter().enumerate().map(|(cell, &units)| {
+            let size = units_per_cell.min(total - cell as u64 * units_per_cell);
+            (units * 100).div_ceil(size) as u8
+        }).collect();
+
+        Self {
+            filesystem: bitmap.filesystem.clone(),
+            unit_size: bitmap.unit_size,
+            total_units: total,
+            allocated_units: used.iter().sum(),
+            extent_count,
+            units_per_cell,
+            cells: cells_out,
+            hotspots: find_hotspots(&starts, extent_count),
+        }
+    }
+
+    /// Compact usage chart, `width` cells per line: ' ' free through '█' full
+    pub fn to_chart(&self, width: usize) -> String {
+        const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+        let mut chart = String::new();
+        for row in self.cells.chunks(width.max(1)) {
+            chart.push('|');
+            chart.extend(row.iter().map(|&fill| match fill {
+                0 => SHADES[0],
+                100 => SHADES[4],
+                fill => SHADES[1 + (fill as usize * 3 / 100).min(2)],
+            }));
+            chart.push_str("|\n");
+        }
+        chart
+    }
+}
+
+/// Runs of cells where extents start at `HOTSPOT_FACTOR` times the average rate
+fn find_hotspots(starts: &[u64], extent_count: u64) -> Vec<FragmentationHotspot> {
+    let threshold = (extent_count * HOTSPOT_FACTOR).div_ceil(starts.len().max(1) as u64).max(2);
+    let mut hotspots: Vec<FragmentationHotspot> = Vec::new();
+    for (cell, &count) in starts.iter().enumerate() {
+        if count < threshold {
+            continue;
+        }
+        match hotspots.last_mut() {
+            Some(last) if last.first_cell + last.cells == cell => {
+                last.cells += 1;
+                last.extents += count;
+            }
+            _ => hotspots.push(FragmentationHotspot { first_cell: cell, cells: 1, extents: count }),
+        }
+    }
+    hotspots.sort_by(|a, b| b.extents.cmp(&a.extents).then(a.first_cell.cmp(&b.first_cell)));
+    hotspots.truncate(MAX_HOTSPOTS);
+    hotspots
+}
+
+/// Allocation map of the volume on a device (read-only). A partitioned disk is mapped
+/// through its first partition that holds a supported filesystem.
+pub fn device_allocation_map(device: &Device, cells: usize) -> Result<AllocationMap, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    let bitmap = match read_allocation(&mut reader, 0) {
+        Ok(bitmap) => bitmap,
+        Err(first_error) => {
+            let (_, partitions) = read_partition_table(&mut reader);
+            partitions.iter()
+                .find_map(|&(start, _)| read_allocation(&mut reader, start).ok())
+                .ok_or(first_error)?
+        }
+    };
+    Ok(AllocationMap::from_bitmap(&bitmap, cells))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// FAT16 volume of 4200 512-byte clusters: reserved 4, two 17-sector FATs, 32-sector root
+    fn fat16_volume(allocated: &[u32]) -> Vec<u8> {
+        let mut disk = vec![0u8; 4270 * 512];
+        let boot = &mut disk[..512];
+        boot[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&4u16.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+        boot[19..21].copy_from_slice(&4270u16.to_le_bytes());
+        boot[21] = 0xF8;
+        boot[22..24].copy_from_slice(&17u16.to_le_bytes());
+        boot[38] = 0x29;
+        boot[54..62].copy_from_slice(b"FAT16   ");
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        for &cluster in allocated {
+            let at = 4 * 512 + cluster as usize * 2;
+            disk[at..at + 2].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        }
+        disk
+    }
+
+    #[test]
+    fn test_fat_extents() {
+        let disk = fat16_volume(&[2, 3, 4, 10, 12]);
+        let bitmap = read_allocation(&mut Cursor::new(disk), 0).unwrap();
+        assert_eq!(bitmap.filesystem, "fat16");
+        assert_eq!(bitmap.unit_size, 512);
+        // 70 system sectors, then the clusters
+        assert_eq!(bitmap.total_units, 70 + 4200);
+        let extents: Vec<Extent> = bitmap.extents().collect();
+        assert_eq!(extents, vec![
+            Extent { start: 0, length: 73 },
+            Extent { start: 78, length: 1 },
+            Extent { start: 80, length: 1 },
+        ]);
+        assert_eq!(bitmap.allocated_units(), 75);
+    }
+
+    #[test]
+    fn test_map_and_hotspots() {
+        let mut bitmap = AllocationBitmap::new("ext4", 4096, 1000);
+        bitmap.set_range(0, 100);
+        // Every other unit in 500..540 gives 20 one-unit extents
+        for unit in (500..540).step_by(2) {
+            bitmap.set(unit);
+        }
+        let map = AllocationMap::from_bitmap(&bitmap, 10);
+        assert_eq!(map.units_per_cell, 100);
+        assert_eq!(map.cells, vec![100, 0, 0, 0, 0, 20, 0, 0, 0, 0]);
+        assert_eq!(map.allocated_units, 120);
+        assert_eq!(map.extent_count, 21);
+        assert_eq!(map.hotspots, vec![FragmentationHotspot { first_cell: 5, cells: 1, extents: 20 }]);
+        assert_eq!(map.to_chart(5), "|█    |\n|░    |\n");
+    }
+}
@@ -0,0 +1,59 @@
+// File attribute edits on unmounted volumes - attrib, chmod and chown for Moses
+// Each family plans the edit as a MetadataPatch of the bytes that hold the attributes
+// (a FAT or exFAT directory entry, an ext inode, an NTFS MFT record), so the edit needs no
+// writer for the filesystem and `moses attr -n` can show it before anything is written.
+// The filesystem ops and the mount providers use the same path through set_attributes,
+// which like `moses attr` without --force will not write to a FAT volume marked dirty.
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use moses_core::{Device, MetadataPatch, MosesError};
+use crate::device_reader::AlignedDeviceReader;
+use crate::families::{ext, fat, ntfs};
+use crate::ops::AttributeChanges;
+
+/// Patch that applies `changes` to `path` on a `filesystem` volume, for a dry run or
+/// [`apply_device`]
+pub fn plan_device(device: &Device, filesystem: &str, path: &str, changes: &AttributeChanges) -> Result<MetadataPatch, MosesError> {
+    if changes.is_empty() {
+        return Err(MosesError::InvalidInput("No attribute changes were given".to_string()));
+    }
+    let mut reader = AlignedDeviceReader::new(crate::utils::open_device_with_fallback(device)?);
+    reader.seek(SeekFrom::Start(0))?;
+    match filesystem.to_lowercase().as_str() {
+        "fat12" | "fat16" | "fat32" | "exfat" => fat::common::attrib::plan_attributes(&mut reader, &device.id, path, changes),
+        "ext2" | "ext3" | "ext4" => ext::attrib::plan_attributes(&mut reader, device, path, changes),
+        "ntfs" => ntfs::ntfs::attrib::plan_attributes(&mut reader, device, path, changes),
+        other => Err(MosesError::NotSupported(format!("Moses cannot change file attributes on {}", other))),
+    }
+}
+
+/// Write an attribute patch to a device, saving its undo record to `undo` first
+pub fn apply_device(device: &Device, patch: &MetadataPatch, undo: Option<&Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to edit file attributes on a system disk".to_string()));
+    }
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    patch.apply(&mut file, undo)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Refuse to write to a FAT or exFAT volume that is marked dirty, unless `force`d
+pub fn check_writable(device: &Device, filesystem: &str, force: bool) -> Result<(), MosesError> {
+    match filesystem.to_lowercase().as_str() {
+        "fat12" | "fat16" | "fat32" | "exfat" => fat::common::dirty::FatDirtyState::read_device(device)?.ensure_writable(force),
+        _ => Ok(()),
+    }
+}
+
+/// Plan and apply `changes` in one step, as the filesystem ops do; dirty volumes are refused
+pub fn set_attributes(device: &Device, filesystem: &str, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+    check_writable(device, filesystem, false)?;
+    let path = path.to_string_lossy().replace('\\', "/");
+    let patch = plan_device(device, filesystem, &path, changes)?;
+    if patch.is_empty() {
+        return Ok(());
+    }
+    apply_device(device, &patch, None)
+}
@@ -58,6 +58,10 @@ fn format_fat32(device_path: &str, volume_label: Option<&str>) -> Result<(), Box
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     };
     
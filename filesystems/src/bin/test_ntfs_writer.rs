@@ -32,6 +32,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     };
     formatter.format(&device, "TestVolume")?;
@@ -0,0 +1,229 @@
+// Capability matrix - what Moses can do with each filesystem, on each platform
+// Built at runtime from the formatter registry, the filesystem ops registry and the
+// mount driver of this build, so `moses list-formats --matrix` and the GUI's toggles
+// show what the code does rather than a hand-kept list that drifts as family support
+// lands. Offline tools that have no registry of their own (consistency checks, label
+// edits, resizing) are listed in OFFLINE_TOOLS next to the code that implements them.
+use moses_core::{AvailabilityContext, FormatterRegistry, Platform};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use crate::mount_driver::{self, MountDriver};
+use crate::ops::FilesystemOpsRegistry;
+
+/// Every platform Moses builds for
+const PLATFORMS: [Platform; 3] = [Platform::Windows, Platform::MacOS, Platform::Linux];
+
+/// Tools that work on an existing volume through plain file access, so on every platform
+const OFFLINE_TOOLS: &[(Operation, &[&str])] = &[
+    // families::ext::rescue::check_device, families::fat::common::dirty::check_device
+    (Operation::Check, &["exfat", "ext2", "ext3", "ext4", "fat16", "fat32"]),
+    // families::ext::tune, families::fat::common::label
+    (Operation::Label, &["exfat", "ext2", "ext3", "ext4", "fat16", "fat32"]),
+];
+
+/// Something Moses can do with a filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Format,
+    Read,
+    Write,
+    Mount,
+    Check,
+    Resize,
+    Label,
+}
+
+impl Operation {
+    pub const ALL: [Self; 7] = [Self::Format, Self::Read, Self::Write, Self::Mount, Self::Check, Self::Resize, Self::Label];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Format => "format",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Mount => "mount",
+            Self::Check => "check",
+            Self::Resize => "resize",
+            Self::Label => "label",
+        }
+    }
+}
+
+/// One operation on one filesystem
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capability {
+    pub operation: Operation,
+    /// Platforms Moses supports it on; empty when it does not exist at all
+    pub platforms: Vec<Platform>,
+    /// Whether it can be done on this machine right now
+    pub available: bool,
+    /// Why not, when the platform is supported but something is missing here
+    pub reason: Option<String>,
+}
+
+/// What Moses can do with one filesystem
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FilesystemCapabilities {
+    pub filesystem: String,
+    /// One entry per [`Operation`], in [`Operation::ALL`] order
+    pub capabilities: Vec<Capability>,
+}
+
+impl FilesystemCapabilities {
+    pub fn get(&self, operation: Operation) -> &Capability {
+        self.capabilities.iter().find(|capability| capability.operation == operation)
+            .expect("every operation has an entry")
+    }
+
+    /// Whether `operation` can be done here and now
+    pub fn can(&self, operation: Operation) -> bool {
+        self.get(operation).available
+    }
+}
+
+/// Filesystems by operations by platforms
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CapabilityMatrix {
+    /// The platform `available` refers to
+    pub platform: Platform,
+    pub filesystems: Vec<FilesystemCapabilities>,
+}
+
+impl CapabilityMatrix {
+    /// Build the matrix from the registries, with `context` deciding what is usable here
+    pub fn build(formatters: &FormatterRegistry, ops: &FilesystemOpsRegistry, mount: &MountDriver, context: &AvailabilityContext) -> Self {
+        let availability = formatters.availability(context);
+        let mut names: Vec<String> = availability.iter().map(|entry| entry.id.clone())
+            .chain(ops.supported_types())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let here = |platforms: &[Platform], reason: Option<String>| {
+            let supported_here = platforms.contains(&context.platform);
+            (supported_here && reason.is_none(), reason.filter(|_| supported_here))
+        };
+        let everywhere = |supported: bool| if supported { PLATFORMS.to_vec() } else { Vec::new() };
+        let mount_problem = if !MountDriver::compiled_in() {
+            Some("This build of Moses has no mount support".to_string())
+        } else if !mount.meets_requirement() {
+            Some(format!("{} {} or newer is not installed ({})", mount.name, mount.required_version, mount.download_url))
+        } else {
+            None
+        };
+
+        let filesystems = names.into_iter().map(|filesystem| {
+            let readable = ops.supported_types().contains(&filesystem);
+            let capabilities = Operation::ALL.iter().map(|&operation| {
+                let (platforms, reason) = match operation {
+                    Operation::Format => match availability.iter().find(|entry| entry.id == filesystem) {
+                        Some(entry) => (
+                            entry.platform_matrix.iter().filter(|support| support.native || support.system_tool)
+                                .map(|support| support.platform).collect(),
+                            (!entry.available).then(|| entry.reasons.join("; ")),
+                        ),
+                        None => (Vec::new(), None),
+                    },
+                    Operation::Read => (everywhere(readable), None),
+                    Operation::Write => (everywhere(ops.is_writable(&filesystem)), None),
+                    Operation::Mount => (everywhere(readable), mount_problem.clone()),
+                    _ => (everywhere(offline_tool(operation, &filesystem)), None),
+                };
+                let (available, reason) = here(&platforms, reason);
+                Capability { operation, platforms, available, reason }
+            }).collect();
+            FilesystemCapabilities { filesystem, capabilities }
+        }).collect();
+
+        Self { platform: context.platform, filesystems }
+    }
+
+    /// The matrix of the built-in formatters and readers on this machine
+    pub fn current(context: &AvailabilityContext) -> Self {
+        let mut ops = FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut ops, true);
+        Self::build(crate::builtin_registry(), &ops, &mount_driver::detect(), context)
+    }
+
+    pub fn get(&self, filesystem: &str) -> Option<&FilesystemCapabilities> {
+        self.filesystems.iter().find(|entry| entry.filesystem.eq_ignore_ascii_case(filesystem))
+    }
+
+    /// A text table: "yes" here, "other OS" elsewhere only, "setup" when something is
+    /// missing here (listed below the table), "-" not at all
+    pub fn to_table(&self) -> String {
+        let mut table = format!("{:<12}", "Filesystem");
+        for operation in Operation::ALL {
+            table.push_str(&format!("{:<10}", operation.as_str()));
+        }
+        table.push('\n');
+        // Missing drivers and tools hold back several filesystems at once, so each is listed once
+        let mut notes: BTreeMap<(Operation, &str), Vec<&str>> = BTreeMap::new();
+        for entry in &self.filesystems {
+            table.push_str(&format!("{:<12}", entry.filesystem));
+            for capability in &entry.capabilities {
+                let cell = if capability.available {
+                    "yes"
+                } else if let Some(reason) = &capability.reason {
+                    notes.entry((capability.operation, reason)).or_default().push(&entry.filesystem);
+                    "setup"
+                } else if capability.platforms.is_empty() {
+                    "-"
+                } else {
+                    "other OS"
+                };
+                table.push_str(&format!("{:<10}", cell));
+            }
+            table.push('\n');
+        }
+        for ((operation, reason), filesystems) in notes {
+            table.push_str(&format!("\n  {} ({}): {}", operation.as_str(), filesystems.join(", "), reason));
+        }
+        table
+    }
+}
+
+fn offline_tool(operation: Operation, filesystem: &str) -> bool {
+    OFFLINE_TOOLS.iter().any(|(tool, filesystems)| *tool == operation && filesystems.contains(&filesystem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driver(installed: bool) -> MountDriver {
+        MountDriver {
+            name: "FUSE",
+            installed,
+            version: Some("3.14".to_string()),
+            location: None,
+            required_version: "2.9",
+            download_url: "https://github.com/libfuse/libfuse",
+        }
+    }
+
+    #[test]
+    fn test_matrix_follows_registries() {
+        let context = AvailabilityContext { platform: Platform::Linux, elevated: true, worker_available: false };
+        let mut ops = FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut ops, true);
+        let matrix = CapabilityMatrix::build(crate::builtin_registry(), &ops, &driver(true), &context);
+
+        let ntfs = matrix.get("NTFS").unwrap();
+        assert!(ntfs.can(Operation::Read) && ntfs.can(Operation::Write));
+        let ext4 = matrix.get("ext4").unwrap();
+        assert!(ext4.can(Operation::Format) && ext4.can(Operation::Check) && ext4.can(Operation::Label));
+        assert!(!ext4.can(Operation::Write), "the ext reader is registered read-only");
+        assert!(matrix.filesystems.iter().all(|entry| entry.get(Operation::Resize).platforms.is_empty()), "nothing resizes yet");
+        assert_eq!(ext4.get(Operation::Mount).available, MountDriver::compiled_in());
+
+        let mut readonly = FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut readonly, false);
+        let matrix = CapabilityMatrix::build(crate::builtin_registry(), &readonly, &driver(false), &context);
+        let ntfs = matrix.get("ntfs").unwrap();
+        assert!(!ntfs.can(Operation::Write));
+        assert!(!ntfs.can(Operation::Mount) && ntfs.get(Operation::Mount).reason.is_some());
+        assert!(matrix.to_table().contains("mount (btrfs, exfat, ext2, ext3, ext4, fat16, fat32, hfsplus, iso9660, jffs2, ntfs, xfs): "));
+    }
+}
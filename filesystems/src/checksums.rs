@@ -0,0 +1,248 @@
+// Checksum database - detect silent corruption on archival drives
+// `moses hash` records the SHA-256 of every file on a volume, and with --save files the
+// manifest under the device (by hardware serial, as the device history does). A later
+// `moses verify` hashes the volume again and compares. A file whose content changed while
+// its size and modification time did not was not rewritten by anyone: that is bit rot or
+// a failing drive, and is reported apart from ordinary edits, additions and deletions.
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use moses_core::{Device, DeviceHistory, MosesError};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::ops::FilesystemOps;
+use crate::throttle::IoThrottle;
+use crate::transfer::{self, FileReader, Selection};
+
+/// What was recorded about one file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileChecksum {
+    pub size: u64,
+    pub modified: Option<u64>,
+    /// Lower-case hex SHA-256 of the content
+    pub sha256: String,
+}
+
+/// Checksums of every file below `root` on one volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChecksumManifest {
+    pub created: DateTime<Utc>,
+    /// Device name when the manifest was made, for display
+    pub device: String,
+    pub filesystem: String,
+    /// Folder the names are relative to
+    pub root: String,
+    /// Relative, `/`-separated name -> checksum, in name order
+    pub files: BTreeMap<String, FileChecksum>,
+    /// Files that could not be read and have no checksum
+    #[serde(default)]
+    pub unreadable: Vec<String>,
+}
+
+impl ChecksumManifest {
+    pub fn total_bytes(&self) -> u64 {
+        self.files.values().map(|file| file.size).sum()
+    }
+
+    /// One `sha256  name` line per file, as `sha256sum` prints them
+    pub fn to_sha256sum(&self) -> String {
+        self.files.iter().map(|(name, file)| format!("{}  {}\n", file.sha256, name)).collect()
+    }
+}
+
+/// Hash every file below `root`, reading no faster than `throttle` allows; `on_file` gets
+/// each name before it is read
+pub fn hash_tree(
+    fs: &mut dyn FilesystemOps,
+    device: &Device,
+    root: &Path,
+    throttle: &IoThrottle,
+    on_file: &mut dyn FnMut(&str),
+) -> Result<ChecksumManifest, MosesError> {
+    let mut manifest = ChecksumManifest {
+        created: Utc::now(),
+        device: device.name.clone(),
+        filesystem: fs.filesystem_type().to_string(),
+        root: root.to_string_lossy().to_string(),
+        files: BTreeMap::new(),
+        unreadable: Vec::new(),
+    };
+    let _io_priority = throttle.enter();
+    // One rate for the whole walk, handed from each file's reader to the next
+    let mut pace = Some(throttle.wrap(()));
+    transfer::walk(fs, root, "", &Selection::default(), &mut |fs, path, name, attributes| {
+        if attributes.is_directory || attributes.is_symlink {
+            return Ok(());
+        }
+        on_file(&name);
+        let mut reader = pace.take().unwrap_or_else(|| throttle.wrap(())).carry_to(FileReader::new(fs, path, attributes.size));
+        let hashed = sha256(&mut reader);
+        pace = Some(reader.carry_to(()));
+        match hashed {
+            Ok(sha256) => {
+                manifest.files.insert(name, FileChecksum { size: attributes.size, modified: attributes.modified, sha256 });
+            }
+            Err(e) => {
+                log::warn!("Could not hash {}: {}", name, e);
+                manifest.unreadable.push(name);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(manifest)
+}
+
+fn sha256(data: &mut dyn Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; transfer::CHUNK_SIZE as usize];
+    loop {
+        let count = data.read(&mut buffer)?;
+        if count == 0 {
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&buffer[..count]);
+    }
+}
+
+/// Differences between a stored manifest and the volume as it is now
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DriftReport {
+    pub checked: u64,
+    pub unchanged: u64,
+    /// Content changed but size and modification time did not: corruption
+    pub corrupted: Vec<String>,
+    /// Content changed along with the size or modification time: edited since
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+    pub added: Vec<String>,
+    /// In the stored manifest but unreadable now
+    pub unreadable: Vec<String>,
+}
+
+impl DriftReport {
+    /// Whether every recorded file is still there and readable with the same content
+    pub fn is_intact(&self) -> bool {
+        self.corrupted.is_empty() && self.modified.is_empty() && self.missing.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+/// Compare `current` against the `stored` manifest
+pub fn compare(stored: &ChecksumManifest, current: &ChecksumManifest) -> DriftReport {
+    let mut report = DriftReport::default();
+    for (name, before) in &stored.files {
+        report.checked += 1;
+        match current.files.get(name) {
+            Some(now) if now.sha256 == before.sha256 => report.unchanged += 1,
+            Some(now) if now.size == before.size && now.modified == before.modified => report.corrupted.push(name.clone()),
+            Some(_) => report.modified.push(name.clone()),
+            None if current.unreadable.contains(name) => report.unreadable.push(name.clone()),
+            None => report.missing.push(name.clone()),
+        }
+    }
+    report.added = current.files.keys()
+        .chain(&current.unreadable)
+        .filter(|name| !stored.files.contains_key(*name))
+        .cloned()
+        .collect();
+    report
+}
+
+/// Stored manifests, one JSON file per device and folder
+pub struct ChecksumDatabase {
+    dir: PathBuf,
+}
+
+impl ChecksumDatabase {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Database in the user's data directory
+    pub fn global() -> Result<Self, MosesError> {
+        let dir = dirs::data_local_dir()
+            .ok_or_else(|| MosesError::Other("No data directory to keep checksums in".to_string()))?;
+        Ok(Self::new(dir.join("moses").join("checksums")))
+    }
+
+    /// File a device's manifest for `root` is kept in; devices are keyed as in the
+    /// device history, so a drive is found again under another name
+    pub fn path(&self, device: &Device, root: &str) -> PathBuf {
+        let key = format!("{}-{}", DeviceHistory::key(device), root);
+        let file: String = key.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{}.json", file))
+    }
+
+    pub fn save(&self, device: &Device, manifest: &ChecksumManifest) -> Result<PathBuf, MosesError> {
+        let path = self.path(device, &manifest.root);
+        std::fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_vec(manifest).map_err(|e| MosesError::Other(e.to_string()))?;
+        std::fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, device: &Device, root: &str) -> Result<Option<ChecksumManifest>, MosesError> {
+        load_manifest(&self.path(device, root)).map(Some).or_else(|e| match e {
+            MosesError::IoError(io) if io.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            e => Err(e),
+        })
+    }
+}
+
+/// Read a manifest saved with `moses hash --output`
+pub fn load_manifest(path: &Path) -> Result<ChecksumManifest, MosesError> {
+    let json = std::fs::read(path)?;
+    serde_json::from_slice(&json)
+        .map_err(|e| MosesError::InvalidInput(format!("{} is not a checksum manifest: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+    use crate::test_helpers::create_test_device;
+
+    #[test]
+    fn test_verify_tells_corruption_from_edits() {
+        let volume = tempfile::tempdir().unwrap();
+        let root = volume.path();
+        std::fs::create_dir_all(root.join("Photos/2019")).unwrap();
+        for name in ["Photos/2019/a.jpg", "Photos/2019/b.jpg", "notes.txt", "gone.txt"] {
+            std::fs::write(root.join(name), format!("content of {}", name)).unwrap();
+        }
+        let device = create_test_device(root.to_str().unwrap(), 0);
+        let mut fs = HostFolderOps::new(root.to_path_buf()).unwrap();
+        let stored = hash_tree(&mut fs, &device, Path::new("/"), &IoThrottle::unlimited(), &mut |_| {}).unwrap();
+        assert_eq!(stored.files.len(), 4);
+        assert!(stored.to_sha256sum().contains("  Photos/2019/a.jpg\n"));
+
+        // A flipped bit keeps size and modification time
+        let rotted = root.join("Photos/2019/a.jpg");
+        let modified = std::fs::metadata(&rotted).unwrap().modified().unwrap();
+        let mut data = std::fs::read(&rotted).unwrap();
+        data[3] ^= 0x01;
+        std::fs::write(&rotted, data).unwrap();
+        std::fs::File::options().write(true).open(&rotted).unwrap().set_modified(modified).unwrap();
+        std::fs::write(root.join("notes.txt"), "edited, and longer than before").unwrap();
+        std::fs::remove_file(root.join("gone.txt")).unwrap();
+        std::fs::write(root.join("new.txt"), "new").unwrap();
+
+        let current = hash_tree(&mut fs, &device, Path::new("/"), &IoThrottle::unlimited(), &mut |_| {}).unwrap();
+        let report = compare(&stored, &current);
+        assert_eq!(report.corrupted, vec!["Photos/2019/a.jpg"]);
+        assert_eq!(report.modified, vec!["notes.txt"]);
+        assert_eq!(report.missing, vec!["gone.txt"]);
+        assert_eq!(report.added, vec!["new.txt"]);
+        assert_eq!((report.checked, report.unchanged), (4, 1));
+        assert!(!report.is_intact());
+        assert!(compare(&stored, &stored).is_intact());
+
+        let store = tempfile::tempdir().unwrap();
+        let database = ChecksumDatabase::new(store.path().join("checksums"));
+        assert!(database.load(&device, "/").unwrap().is_none());
+        database.save(&device, &stored).unwrap();
+        assert_eq!(database.load(&device, "/").unwrap(), Some(stored));
+    }
+}
@@ -1,6 +1,7 @@
 // Filesystem detection trait and utilities
 
 use moses_core::MosesError;
+use std::io::{Read, Seek, SeekFrom};
 
 /// Trait for filesystem-specific detection logic
 pub trait FilesystemDetector {
@@ -16,10 +17,8 @@ pub trait FilesystemDetector {
     fn detect(boot_sector: &[u8], ext_superblock: Option<&[u8]>) -> Option<String>;
 }
 
-/// Helper to read common detection data from a device
-pub fn read_detection_data(file: &mut std::fs::File) -> Result<(Vec<u8>, Option<Vec<u8>>), MosesError> {
-    use std::io::{Read, Seek, SeekFrom};
-    
+/// Helper to read common detection data from a device, or anything that reads like one
+pub fn read_detection_data<R: Read + Seek>(file: &mut R) -> Result<(Vec<u8>, Option<Vec<u8>>), MosesError> {
     // Read boot sector (first 512 bytes)
     let mut boot_sector = vec![0u8; 512];
     file.read_exact(&mut boot_sector)
@@ -45,7 +44,7 @@ pub fn read_detection_data(file: &mut std::fs::File) -> Result<(Vec<u8>, Option<
 }
 
 /// Detect filesystem type using all registered detectors
-pub fn detect_filesystem(file: &mut std::fs::File) -> Result<String, MosesError> {
+pub fn detect_filesystem<R: Read + Seek>(file: &mut R) -> Result<String, MosesError> {
     let (boot_sector, ext_superblock) = read_detection_data(file)?;
     
     // Try each filesystem detector
@@ -74,5 +73,30 @@ pub fn detect_filesystem(file: &mut std::fs::File) -> Result<String, MosesError>
         return Ok(fs);
     }
     
+    // HFS+ keeps its volume header at 1 KiB, where ext keeps its superblock
+    if ext_superblock.as_deref().is_some_and(crate::families::hfsplus::has_volume_header) {
+        return Ok("hfsplus".to_string());
+    }
+    
+    // XFS starts with its primary superblock
+    if crate::families::xfs::has_superblock(&boot_sector) {
+        return Ok("xfs".to_string());
+    }
+    
+    // btrfs keeps its superblock at 64 KiB, past the data read above
+    if crate::families::btrfs::has_superblock(file) {
+        return Ok("btrfs".to_string());
+    }
+    
+    // JFFS2 images start with a node, usually a clean marker
+    if crate::families::flash::jffs2::has_node(&boot_sector) {
+        return Ok("jffs2".to_string());
+    }
+    
+    // ISO9660 volume descriptors start at 32 KiB, past the system area
+    if crate::families::optical::iso9660::has_volume_descriptor(file) {
+        return Ok("iso9660".to_string());
+    }
+    
     Ok("unknown".to_string())
 }
\ No newline at end of file
@@ -0,0 +1,166 @@
+// Deterministic formats - bit-identical filesystems for golden-image pipelines
+// A format normally takes its UUIDs and volume serials from the clock or a random source
+// and stamps its metadata with the current time, so formatting the same image twice gives
+// two different files. With a deterministic seed every such identifier is derived from
+// the seed instead (SHA-256 of the seed and what the value is for), and every timestamp
+// is one fixed time, so the same seed, size and options produce the same bytes. Different
+// seeds still give different identifiers, which keeps images from one pipeline apart.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use moses_core::{FormatOptions, MosesError};
+use sha2::{Digest, Sha256};
+use crate::volume_serial::preserve_requested;
+
+/// Key in `FormatOptions::additional_options`: any non-empty text
+pub const SEED_OPTION: &str = "deterministic_seed";
+/// Key in `FormatOptions::additional_options`: the timestamp written in deterministic mode,
+/// in seconds since the Unix epoch
+pub const TIME_OPTION: &str = "deterministic_time";
+/// 2000-01-01 00:00:00 UTC, which every supported filesystem can store
+pub const DEFAULT_TIME: u64 = 946_684_800;
+
+/// Earliest time FAT can store (1980-01-01) and the last a 32-bit ext timestamp can
+const TIME_RANGE: std::ops::RangeInclusive<u64> = 315_532_800..=u32::MAX as u64;
+
+/// Where a formatter takes its identifiers and timestamps from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormatSeed {
+    seed: Option<String>,
+    time: u64,
+}
+
+impl FormatSeed {
+    pub fn from_options(options: &FormatOptions) -> Result<Self, MosesError> {
+        let seed = options.additional_options.get(SEED_OPTION).cloned();
+        let time = options.additional_options.get(TIME_OPTION);
+        match (&seed, time) {
+            (Some(seed), _) if seed.is_empty() => {
+                return Err(MosesError::InvalidInput(format!("{} must not be empty", SEED_OPTION)));
+            }
+            (None, Some(_)) => {
+                return Err(MosesError::InvalidInput(format!("{} needs {}", TIME_OPTION, SEED_OPTION)));
+            }
+            (Some(_), _) if preserve_requested(options)? => {
+                return Err(MosesError::InvalidInput(
+                    "A deterministic format derives its serial from the seed; it cannot also keep the current one".to_string()
+                ));
+            }
+            _ => {}
+        }
+        let time = match time {
+            None => DEFAULT_TIME,
+            Some(value) => value.trim().parse().ok()
+                .filter(|time| TIME_RANGE.contains(time))
+                .ok_or_else(|| MosesError::InvalidInput(format!(
+                    "{} '{}' is not valid: expected seconds since 1970, from 1980 to 2106", TIME_OPTION, value
+                )))?,
+        };
+        Ok(Self { seed, time })
+    }
+
+    /// Whether identifiers and timestamps come from the seed rather than the clock
+    pub fn is_deterministic(&self) -> bool {
+        self.seed.is_some()
+    }
+
+    fn derive(&self, purpose: &str) -> Option<[u8; 32]> {
+        let seed = self.seed.as_ref()?;
+        let mut hasher = Sha256::new();
+        hasher.update(seed.as_bytes());
+        hasher.update([0]);
+        hasher.update(purpose.as_bytes());
+        Some(hasher.finalize().into())
+    }
+
+    /// A version 4 style UUID for `purpose`, in deterministic mode
+    pub fn uuid(&self, purpose: &str) -> Option<[u8; 16]> {
+        let mut uuid: [u8; 16] = self.derive(purpose)?[..16].try_into().ok()?;
+        uuid[6] = (uuid[6] & 0x0F) | 0x40;
+        uuid[8] = (uuid[8] & 0x3F) | 0x80;
+        Some(uuid)
+    }
+
+    /// A 32-bit FAT or exFAT volume serial, in deterministic mode
+    pub fn serial32(&self, purpose: &str) -> Option<u32> {
+        self.derive(purpose).map(|hash| u32::from_le_bytes(hash[..4].try_into().unwrap()))
+    }
+
+    /// A 64-bit NTFS volume serial, in deterministic mode
+    pub fn serial64(&self, purpose: &str) -> Option<u64> {
+        self.derive(purpose).map(|hash| u64::from_le_bytes(hash[..8].try_into().unwrap()))
+    }
+
+    /// The time to stamp metadata with: the fixed time in deterministic mode, else now
+    pub fn now(&self) -> SystemTime {
+        match self.seed {
+            Some(_) => UNIX_EPOCH + Duration::from_secs(self.time),
+            None => SystemTime::now(),
+        }
+    }
+
+    /// [`now`](Self::now) in seconds since the Unix epoch
+    pub fn unix_time(&self) -> u64 {
+        self.now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::FilesystemFormatter;
+
+    fn options(filesystem: &str, seed: &str) -> FormatOptions {
+        FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            label: Some("GOLDEN".to_string()),
+            additional_options: [(SEED_OPTION.to_string(), seed.to_string())].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    async fn format_twice(formatter: &dyn FilesystemFormatter, filesystem: &str, size: u64) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut images = Vec::new();
+        for (name, seed) in [("a.img", "build-42"), ("b.img", "build-42"), ("c.img", "build-43")] {
+            let path = dir.path().join(name);
+            crate::image_target::format_image(formatter, &path, size, &options(filesystem, seed)).await.unwrap();
+            images.push(std::fs::read(&path).unwrap());
+        }
+        let c = images.pop().unwrap();
+        let b = images.pop().unwrap();
+        (images.pop().unwrap(), b, c)
+    }
+
+    #[test]
+    fn test_options() {
+        let seed = FormatSeed::from_options(&options("fat32", "build-42")).unwrap();
+        assert!(seed.is_deterministic());
+        assert_eq!(seed.unix_time(), DEFAULT_TIME);
+        assert_eq!(seed.uuid("ext uuid"), FormatSeed::from_options(&options("ext4", "build-42")).unwrap().uuid("ext uuid"));
+        assert_ne!(seed.uuid("ext uuid"), seed.uuid("journal uuid"));
+        assert_eq!(FormatSeed::from_options(&FormatOptions::default()).unwrap().uuid("ext uuid"), None);
+
+        let mut bad = options("fat32", "build-42");
+        bad.additional_options.insert(TIME_OPTION.to_string(), "12".to_string());
+        assert!(FormatSeed::from_options(&bad).is_err(), "before FAT can store it");
+        bad.additional_options.insert(TIME_OPTION.to_string(), "1700000000".to_string());
+        assert_eq!(FormatSeed::from_options(&bad).unwrap().unix_time(), 1_700_000_000);
+        bad.additional_options.insert(crate::volume_serial::PRESERVE_SERIAL_OPTION.to_string(), "true".to_string());
+        assert!(FormatSeed::from_options(&bad).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_same_bytes() {
+        for (formatter, filesystem, size) in [
+            (&crate::Ext4NativeFormatter as &dyn FilesystemFormatter, "ext4", 300 * 1024 * 1024),
+            (&crate::Fat16Formatter, "fat16", 64 * 1024 * 1024),
+            (&crate::Fat32Formatter, "fat32", 300 * 1024 * 1024),
+            (&crate::ExFatFormatter, "exfat", 64 * 1024 * 1024),
+            (&crate::NtfsFormatter, "ntfs", 64 * 1024 * 1024),
+        ] {
+            let (a, b, c) = format_twice(formatter, filesystem, size).await;
+            let differs = a.iter().zip(&b).position(|(x, y)| x != y);
+            assert_eq!(differs, None, "{} images from one seed differ at this offset", filesystem);
+            assert!(a != c, "{} images from different seeds are identical", filesystem);
+        }
+    }
+}
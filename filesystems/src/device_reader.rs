@@ -9,6 +9,12 @@ use log::{debug, trace};
 
 const SECTOR_SIZE: usize = 512;
 
+/// Anything an AlignedDeviceReader can read sectors from: a device handle, or a view
+/// onto one such as an NTFS shadow copy
+pub trait BlockSource: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> BlockSource for T {}
+
 /// A device reader that handles Windows sector alignment requirements automatically
 /// 
 /// Windows requires all reads from raw devices to be:
@@ -17,7 +23,7 @@ const SECTOR_SIZE: usize = 512;
 /// 
 /// This abstraction handles these requirements transparently
 pub struct AlignedDeviceReader {
-    file: File,
+    file: Box<dyn BlockSource>,
     /// Cache of sectors we've already read
     sector_cache: HashMap<u64, Vec<u8>>,
     /// Optional limit on cache size (in sectors)
@@ -27,8 +33,13 @@ pub struct AlignedDeviceReader {
 impl AlignedDeviceReader {
     /// Create a new aligned device reader
     pub fn new(file: File) -> Self {
+        Self::from_source(Box::new(file))
+    }
+    
+    /// Create a reader over any block source
+    pub fn from_source(source: Box<dyn BlockSource>) -> Self {
         Self {
-            file,
+            file: source,
             sector_cache: HashMap::new(),
             max_cache_sectors: 1000, // Default: cache up to 500KB
         }
@@ -37,7 +48,7 @@ impl AlignedDeviceReader {
     /// Create with a specific cache size limit
     pub fn with_cache_limit(file: File, max_sectors: usize) -> Self {
         Self {
-            file,
+            file: Box::new(file),
             sector_cache: HashMap::new(),
             max_cache_sectors: max_sectors,
         }
@@ -227,10 +238,28 @@ pub struct FileMetadata {
     pub compressed: bool,
     pub sparse: bool,
     pub reparse_point: Option<String>,
+    /// Target of a symbolic link, junction or mount point, as the filesystem stores it
+    #[serde(default)]
+    pub link_target: Option<String>,
     pub allocated_size: Option<u64>,
     pub created: Option<u64>,      // Timestamps as Unix epoch
     pub modified: Option<u64>,
     pub accessed: Option<u64>,
+    // DOS/Windows attribute flags; families without them leave these false
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub system: bool,
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Owner, on filesystems that record one per file (NTFS: the owner SID and its name)
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Simplified access control list, one line per entry, e.g. "Allow Users: Read"
+    #[serde(default)]
+    pub access: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,1020 @@
+// Heuristic analysis of devices whose filesystem we could not identify
+// Runs every family detector, scores partial signature matches, classifies
+// the content by entropy and looks for leftovers of earlier filesystems
+// (backup superblocks, backup boot sectors, old FAT copies).
+// The analysis runs volume by volume; after each stage the caller gets a progress update
+// with an ETA and what has been identified so far, and can stop it there. On a large GPT
+// disk that is many seeks across the whole device, so it is not a single blocking call.
+// Three depths trade time for thoroughness: quick reads the start of each volume, standard
+// adds the backup structures spread across it, and deep sweeps every sector for signatures
+// of volumes no partition table points to any more. A few timed reads give each depth an
+// estimated duration before it is started.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::time::Instant;
+use moses_core::{Device, MosesError, ProgressTracker, ProgressUpdate};
+use serde::{Serialize, Deserialize};
+use crate::detection::FilesystemDetector;
+use crate::device_reader::AlignedDeviceReader;
+
+/// Number of evenly spaced blocks sampled for entropy analysis
+const ENTROPY_SAMPLES: u64 = 64;
+const ENTROPY_SAMPLE_SIZE: usize = 4096;
+/// How far into a volume we look for stale FAT tables
+const FAT_SCAN_LIMIT: usize = 4 * 1024 * 1024;
+/// Bytes read to probe one volume's boot sector, superblock and signatures (for the ETA)
+const PROBE_BYTES: u64 = 16 * 1024;
+/// Bytes read for backup superblocks and boot sectors, before the FAT scan (for the ETA)
+const RESIDUAL_PROBE_BYTES: u64 = 3 * 5 * 1024 + 3 * 512;
+/// Separate reads behind `PROBE_BYTES` and `RESIDUAL_PROBE_BYTES`
+const PROBE_READS: u64 = 2 + SIGNATURES.len() as u64;
+const RESIDUAL_PROBE_READS: u64 = 3 * 5 + 3;
+/// How much of a volume a quick analysis samples for entropy
+const QUICK_SAMPLE_SPAN: u64 = 16 * 1024 * 1024;
+/// Bytes read at a time by the deep sweep
+const SWEEP_CHUNK: u64 = 1024 * 1024;
+/// Extra bytes read past each chunk so signatures far from their sector are still seen
+const SWEEP_OVERLAP: u64 = 0x10040 + 8;
+/// Chunks between progress updates of the deep sweep
+const SWEEP_REPORT_CHUNKS: u64 = 64;
+/// Hits the deep sweep records before it stops looking
+const SWEEP_HIT_LIMIT: usize = 1000;
+/// Reads timed to measure the device's speed for time estimates
+const THROUGHPUT_PROBES: u64 = 4;
+const THROUGHPUT_SAMPLE: usize = 4 * 1024 * 1024;
+
+/// How thoroughly an analysis reads the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisDepth {
+    /// Partition table, the start of each volume and the allocation tables in its first MBs
+    Quick,
+    /// Also backup superblocks and boot sectors, and content sampled across the device
+    #[default]
+    Standard,
+    /// Also a signature sweep of every sector, for volumes no table points to any more
+    Deep,
+}
+
+impl AnalysisDepth {
+    pub const ALL: [AnalysisDepth; 3] = [AnalysisDepth::Quick, AnalysisDepth::Standard, AnalysisDepth::Deep];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnalysisDepth::Quick => "quick",
+            AnalysisDepth::Standard => "standard",
+            AnalysisDepth::Deep => "deep",
+        }
+    }
+}
+
+impl FromStr for AnalysisDepth {
+    type Err = MosesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|depth| depth.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| MosesError::InvalidInput(format!("Unknown analysis depth '{}' (quick, standard or deep)", s)))
+    }
+}
+
+/// Read speed of a device, from a few timed reads spread across it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DeviceThroughput {
+    pub bytes_per_sec: f64,
+    /// Latency of one small read at a new position
+    pub secs_per_read: f64,
+}
+
+/// What an analysis at one depth will read, and how long that should take
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalysisEstimate {
+    pub depth: AnalysisDepth,
+    pub bytes: u64,
+    pub reads: u64,
+    /// `None` when the device's speed could not be measured
+    pub eta_secs: Option<u64>,
+}
+
+/// A filesystem candidate found on the device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilesystemCandidate {
+    pub filesystem: String,
+    /// 1.0 for a full detector match, lower for signature-only hits
+    pub confidence: f32,
+    /// Byte offset of the volume the candidate was found in
+    pub offset: u64,
+    pub evidence: Vec<String>,
+}
+
+/// Coarse classification of unrecognised content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentClass {
+    /// Every sample was zero - never written or zero-wiped
+    Zeroed,
+    /// Every sample was a single repeated byte - pattern-wiped
+    Filled(u8),
+    /// Uniformly high entropy - encrypted, compressed or random-wiped
+    HighEntropy,
+    /// A mix of structured and empty data
+    Structured,
+}
+
+impl ContentClass {
+    pub fn description(&self) -> String {
+        match self {
+            ContentClass::Zeroed => "zeroed (never written or wiped with zeros)".to_string(),
+            ContentClass::Filled(b) => format!("wiped with 0x{:02X} pattern", b),
+            ContentClass::HighEntropy => "high entropy (encrypted, compressed or random-wiped)".to_string(),
+            ContentClass::Structured => "structured data (unrecognised or damaged filesystem)".to_string(),
+        }
+    }
+}
+
+/// Entropy statistics over the sampled blocks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntropySummary {
+    pub samples: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Fraction of samples that were entirely zero
+    pub zero_fraction: f64,
+    pub class: ContentClass,
+}
+
+/// A leftover structure hinting at an earlier filesystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResidualSignature {
+    pub filesystem: String,
+    pub description: String,
+    pub offset: u64,
+}
+
+/// Partition summary, shaped like the entries of the analysis cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionSummary {
+    pub number: u32,
+    pub filesystem: Option<String>,
+    pub size: u64,
+    pub start_offset: u64,
+}
+
+/// Result of [`analyze_unknown_filesystem`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnknownFilesystemAnalysis {
+    /// Best full match, or "unknown"
+    pub filesystem: String,
+    pub partition_table: Option<String>,
+    pub partitions: Vec<PartitionSummary>,
+    /// All candidates, best first
+    pub candidates: Vec<FilesystemCandidate>,
+    pub entropy: Option<EntropySummary>,
+    pub residual_signatures: Vec<ResidualSignature>,
+    pub likely_prior_filesystem: Option<String>,
+    /// Problems with recognised filesystems, such as an unclean shutdown
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+impl UnknownFilesystemAnalysis {
+    /// Human-readable report for logs and the CLI
+    pub fn to_report(&self) -> String {
+        let mut report = format!("Filesystem: {}\n", self.filesystem);
+        if let Some(table) = &self.partition_table {
+            report.push_str(&format!("Partition table: {}\n", table));
+        }
+        for p in &self.partitions {
+            report.push_str(&format!("  Partition {}: offset 0x{:X}, {} bytes, {}\n",
+                p.number, p.start_offset, p.size, p.filesystem.as_deref().unwrap_or("unknown")));
+        }
+
+        if !self.candidates.is_empty() {
+            report.push_str("\nCandidates:\n");
+            for c in &self.candidates {
+                report.push_str(&format!("  {:<12} {:>3.0}% at 0x{:X} ({})\n",
+                    c.filesystem, c.confidence * 100.0, c.offset, c.evidence.join(", ")));
+            }
+        }
+
+        if let Some(e) = &self.entropy {
+            report.push_str(&format!("\nContent: {}\n", e.class.description()));
+            report.push_str(&format!("  entropy mean {:.2}, min {:.2}, max {:.2} bits/byte over {} samples ({:.0}% zero)\n",
+                e.mean, e.min, e.max, e.samples, e.zero_fraction * 100.0));
+        }
+
+        if !self.residual_signatures.is_empty() {
+            report.push_str("\nResidual signatures:\n");
+            for r in &self.residual_signatures {
+                report.push_str(&format!("  0x{:X}: {}\n", r.offset, r.description));
+            }
+        }
+        if let Some(prior) = &self.likely_prior_filesystem {
+            report.push_str(&format!("\nLikely prior filesystem: {}\n", prior));
+        }
+        if !self.warnings.is_empty() {
+            report.push_str("\nWarnings:\n");
+            for w in &self.warnings {
+                report.push_str(&format!("  {}\n", w));
+            }
+        }
+        report
+    }
+}
+
+/// Progress of a running analysis with everything found so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalysisProgress {
+    pub update: ProgressUpdate,
+    /// The analysis as it would read if it ended now
+    pub partial: UnknownFilesystemAnalysis,
+}
+
+/// Magic values of filesystems we can recognise but not necessarily handle
+pub(crate) struct Signature {
+    pub(crate) offset: u64,
+    pub(crate) magic: &'static [u8],
+    pub(crate) filesystem: &'static str,
+}
+
+pub(crate) const SIGNATURES: &[Signature] = &[
+    Signature { offset: 3, magic: b"NTFS    ", filesystem: "ntfs" },
+    Signature { offset: 3, magic: b"EXFAT   ", filesystem: "exfat" },
+    Signature { offset: 82, magic: b"FAT32   ", filesystem: "fat32" },
+    Signature { offset: 54, magic: b"FAT16   ", filesystem: "fat16" },
+    Signature { offset: 54, magic: b"FAT12   ", filesystem: "fat12" },
+    Signature { offset: 1080, magic: &[0x53, 0xEF], filesystem: "ext" },
+    Signature { offset: 3, magic: b"-FVE-FS-", filesystem: "bitlocker" },
+    Signature { offset: 0, magic: b"LUKS\xBA\xBE", filesystem: "luks" },
+    Signature { offset: 0, magic: b"XFSB", filesystem: "xfs" },
+    Signature { offset: 0x10040, magic: b"_BHRfS_M", filesystem: "btrfs" },
+    Signature { offset: 1024, magic: b"H+\x00\x04", filesystem: "hfsplus" },
+    Signature { offset: 1024, magic: b"HX\x00\x05", filesystem: "hfsplus" },
+    Signature { offset: 1024, magic: &[0x10, 0x20, 0xF5, 0xF2], filesystem: "f2fs" },
+    Signature { offset: 0x8001, magic: b"CD001", filesystem: "iso9660" },
+    Signature { offset: 4086, magic: b"SWAPSPACE2", filesystem: "linux-swap" },
+    Signature { offset: 512, magic: b"LABELONE", filesystem: "lvm2" },
+];
+
+pub(crate) fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Option<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// Shannon entropy in bits per byte
+fn shannon_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Run the exact family detectors against a volume
+pub(crate) fn run_detectors(boot_sector: &[u8], ext_superblock: Option<&[u8]>) -> Option<String> {
+    use crate::families::{ntfs::ntfs::NtfsDetector, fat::exfat::ExFatDetector,
+        fat::fat32::Fat32Detector, fat::fat16::Fat16Detector, ext::ext4_native::ExtDetector};
+
+    NtfsDetector::detect(boot_sector, ext_superblock)
+        .or_else(|| ExFatDetector::detect(boot_sector, ext_superblock))
+        .or_else(|| Fat32Detector::detect(boot_sector, ext_superblock))
+        .or_else(|| Fat16Detector::detect(boot_sector, ext_superblock))
+        .or_else(|| ExtDetector::detect(boot_sector, ext_superblock))
+}
+
+/// Detector and signature candidates for the volume starting at `offset`
+fn volume_candidates<R: Read + Seek>(reader: &mut R, offset: u64) -> Vec<FilesystemCandidate> {
+    let mut candidates = Vec::new();
+    let Some(boot_sector) = read_at(reader, offset, 512) else {
+        return candidates;
+    };
+    let ext_superblock = read_at(reader, offset + 1024, 512);
+    let has_boot_signature = boot_sector[510] == 0x55 && boot_sector[511] == 0xAA;
+
+    let exact = run_detectors(&boot_sector, ext_superblock.as_deref());
+    if let Some(fs) = &exact {
+        candidates.push(FilesystemCandidate {
+            filesystem: fs.clone(),
+            confidence: 1.0,
+            offset,
+            evidence: vec!["family detector match".to_string()],
+        });
+    }
+
+    for sig in SIGNATURES {
+        let Some(bytes) = read_at(reader, offset + sig.offset, sig.magic.len()) else {
+            continue;
+        };
+        if bytes != sig.magic {
+            continue;
+        }
+        if exact.as_deref().is_some_and(|fs| fs.starts_with(sig.filesystem) || sig.filesystem.starts_with(fs)) {
+            continue;
+        }
+
+        // Longer magics are less likely to be coincidence
+        let mut confidence: f32 = match sig.magic.len() {
+            0..=2 => 0.3,
+            3..=5 => 0.5,
+            _ => 0.6,
+        };
+        let mut evidence = vec![format!("signature at +0x{:X}", sig.offset)];
+        if sig.offset < 512 && has_boot_signature {
+            confidence += 0.2;
+            evidence.push("boot sector signature 0x55AA".to_string());
+        } else if sig.offset < 512 {
+            evidence.push("boot sector signature missing".to_string());
+        }
+        candidates.push(FilesystemCandidate {
+            filesystem: sig.filesystem.to_string(),
+            confidence,
+            offset,
+            evidence,
+        });
+    }
+
+    candidates
+}
+
+/// Sample the volume and classify its content by entropy, calling `on_sample` before each
+/// sample is read. `None` when `on_sample` stopped it.
+fn analyze_entropy<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    size: u64,
+    on_sample: &mut dyn FnMut(u64) -> ControlFlow<()>,
+) -> Option<Option<EntropySummary>> {
+    if size < ENTROPY_SAMPLE_SIZE as u64 {
+        return Some(None);
+    }
+    let samples = ENTROPY_SAMPLES.min(size / ENTROPY_SAMPLE_SIZE as u64).max(1);
+    let stride = (size - ENTROPY_SAMPLE_SIZE as u64) / samples.max(2).saturating_sub(1);
+
+    let mut entropies = Vec::new();
+    let mut zero_samples = 0usize;
+    let mut fill_byte: Option<Option<u8>> = None;
+
+    for i in 0..samples {
+        // Align to 4KiB so the reads stay cheap on raw devices
+        let pos = offset + (i * stride) / 4096 * 4096;
+        if on_sample(i).is_break() {
+            return None;
+        }
+        let Some(block) = read_at(reader, pos, ENTROPY_SAMPLE_SIZE) else {
+            continue;
+        };
+        let first = block[0];
+        let uniform = block.iter().all(|&b| b == first);
+        if uniform && first == 0 {
+            zero_samples += 1;
+        }
+        fill_byte = match fill_byte {
+            None if uniform => Some(Some(first)),
+            Some(Some(b)) if uniform && b == first => Some(Some(b)),
+            _ => Some(None),
+        };
+        entropies.push(shannon_entropy(&block));
+    }
+
+    if entropies.is_empty() {
+        return Some(None);
+    }
+    let count = entropies.len();
+    let mean = entropies.iter().sum::<f64>() / count as f64;
+    let min = entropies.iter().cloned().fold(f64::MAX, f64::min);
+    let max = entropies.iter().cloned().fold(0.0, f64::max);
+    let zero_fraction = zero_samples as f64 / count as f64;
+
+    let class = match fill_byte.flatten() {
+        Some(0) => ContentClass::Zeroed,
+        Some(b) => ContentClass::Filled(b),
+        None if min >= 7.0 && mean >= 7.5 => ContentClass::HighEntropy,
+        None => ContentClass::Structured,
+    };
+
+    Some(Some(EntropySummary { samples: count, mean, min, max, zero_fraction, class }))
+}
+
+/// Look for backup metadata and stale allocation tables left behind by earlier filesystems
+fn find_residual_signatures<R: Read + Seek>(reader: &mut R, offset: u64, size: u64) -> Vec<ResidualSignature> {
+    let mut found = Vec::new();
+    let end = offset + size;
+
+    // ext backup superblocks live in groups 1, 3, 5, 7, 9 (sparse_super)
+    for (log_block_size, block_size, blocks_per_group) in [(0u32, 1024u64, 8192u64), (1, 2048, 16384), (2, 4096, 32768)] {
+        for group in [1u64, 3, 5, 7, 9] {
+            let sb_offset = if block_size == 1024 {
+                offset + (group * blocks_per_group + 1) * 1024
+            } else {
+                offset + group * blocks_per_group * block_size
+            };
+            if sb_offset + 1024 > end {
+                break;
+            }
+            let Some(sb) = read_at(reader, sb_offset, 1024) else {
+                break;
+            };
+            let log = u32::from_le_bytes([sb[24], sb[25], sb[26], sb[27]]);
+            let block_group_nr = u16::from_le_bytes([sb[90], sb[91]]);
+            if sb[56] == 0x53 && sb[57] == 0xEF && log == log_block_size && block_group_nr as u64 == group {
+                found.push(ResidualSignature {
+                    filesystem: "ext".to_string(),
+                    description: format!("ext backup superblock (group {}, {} byte blocks)", group, block_size),
+                    offset: sb_offset,
+                });
+            }
+        }
+    }
+
+    // FAT32 keeps a backup boot sector at sector 6, exFAT a backup boot region at sector 12
+    if let Some(sector) = read_at(reader, offset + 6 * 512, 512) {
+        if &sector[82..90] == b"FAT32   " && sector[510] == 0x55 && sector[511] == 0xAA {
+            found.push(ResidualSignature {
+                filesystem: "fat32".to_string(),
+                description: "FAT32 backup boot sector".to_string(),
+                offset: offset + 6 * 512,
+            });
+        }
+    }
+    if let Some(sector) = read_at(reader, offset + 12 * 512, 512) {
+        if &sector[3..11] == b"EXFAT   " {
+            found.push(ResidualSignature {
+                filesystem: "exfat".to_string(),
+                description: "exFAT backup boot region".to_string(),
+                offset: offset + 12 * 512,
+            });
+        }
+    }
+
+    // NTFS stores its backup boot sector in the last sector of the volume
+    if size >= 1024 {
+        let last = end - 512;
+        if let Some(sector) = read_at(reader, last, 512) {
+            if &sector[3..11] == b"NTFS    " {
+                found.push(ResidualSignature {
+                    filesystem: "ntfs".to_string(),
+                    description: "NTFS backup boot sector".to_string(),
+                    offset: last,
+                });
+            }
+            if &sector[0..8] == b"EFI PART" {
+                found.push(ResidualSignature {
+                    filesystem: "gpt".to_string(),
+                    description: "GPT backup header".to_string(),
+                    offset: last,
+                });
+            }
+        }
+    }
+
+    found.extend(find_stale_fat_tables(reader, offset, size));
+    found
+}
+
+/// Old FAT tables in the first MBs of a volume, which start with the media byte followed
+/// by all-ones entries
+fn find_stale_fat_tables<R: Read + Seek>(reader: &mut R, offset: u64, size: u64) -> Vec<ResidualSignature> {
+    let mut found = Vec::new();
+    let scan_len = (FAT_SCAN_LIMIT as u64).min(size) as usize / 512 * 512;
+    if let Some(area) = read_at(reader, offset, scan_len) {
+        let mut fat_copies = 0;
+        for (i, sector) in area.as_chunks::<512>().0.iter().enumerate().skip(1) {
+            let media_ok = sector[0] == 0xF0 || sector[0] >= 0xF8;
+            let fs = if media_ok && sector[1..4] == [0xFF, 0xFF, 0x0F] && sector[4..8] == [0xFF, 0xFF, 0xFF, 0x0F] {
+                "fat32"
+            } else if media_ok && sector[1] == 0xFF && sector[2..4] == [0xFF, 0xFF] && sector[4..8] != [0, 0, 0, 0] {
+                "fat16"
+            } else {
+                continue;
+            };
+            found.push(ResidualSignature {
+                filesystem: fs.to_string(),
+                description: format!("{} allocation table copy", fs.to_uppercase()),
+                offset: offset + (i * 512) as u64,
+            });
+            fat_copies += 1;
+            if fat_copies == 2 {
+                break;
+            }
+        }
+    }
+
+    found
+}
+
+/// Whether a sector holding an ext superblock magic has the block geometry of a real one
+fn plausible_ext_superblock(sb: &[u8]) -> bool {
+    let log_block_size = u32::from_le_bytes(sb[24..28].try_into().unwrap());
+    let blocks_per_group = u32::from_le_bytes(sb[32..36].try_into().unwrap()) as u64;
+    log_block_size <= 6 && blocks_per_group == 8 * (1024u64 << log_block_size)
+}
+
+/// Look for filesystem signatures at every sector of the device, calling `on_chunk` with the
+/// bytes swept so far. Hits at `known` volume starts and `skip` offsets are left out.
+/// `None` when `on_chunk` stopped it.
+fn sweep_signatures<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+    known: &[u64],
+    skip: &[u64],
+    on_chunk: &mut dyn FnMut(u64, &[ResidualSignature]) -> ControlFlow<()>,
+) -> Option<Vec<ResidualSignature>> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while pos < size && found.len() < SWEEP_HIT_LIMIT {
+        let len = (SWEEP_CHUNK + SWEEP_OVERLAP).min(size - pos) as usize;
+        if let Some(buf) = read_at(reader, pos, len) {
+            for sector in (0..SWEEP_CHUNK.min(size - pos) as usize).step_by(512) {
+                let start = pos + sector as u64;
+                if known.contains(&start) {
+                    continue;
+                }
+                for sig in SIGNATURES {
+                    let at = sector + sig.offset as usize;
+                    if buf.get(at..at + sig.magic.len()) != Some(sig.magic) {
+                        continue;
+                    }
+                    let holder = sector + sig.offset as usize / 512 * 512;
+                    if sig.filesystem == "ext" && !buf.get(holder..holder + 512).is_some_and(plausible_ext_superblock) {
+                        continue;
+                    }
+                    let offset = pos + holder as u64;
+                    if !skip.contains(&offset) && !found.iter().any(|r: &ResidualSignature| r.offset == offset) {
+                        found.push(ResidualSignature {
+                            filesystem: sig.filesystem.to_string(),
+                            description: format!("{} signature found by surface sweep", sig.filesystem),
+                            offset,
+                        });
+                    }
+                }
+            }
+        }
+        pos += SWEEP_CHUNK;
+        if (pos / SWEEP_CHUNK).is_multiple_of(SWEEP_REPORT_CHUNKS) && on_chunk(pos.min(size), &found).is_break() {
+            return None;
+        }
+    }
+    Some(found)
+}
+
+/// Parse the partition table, returning its style and (start, size) of each partition
+pub(crate) fn read_partition_table<R: Read + Seek>(reader: &mut R) -> (Option<String>, Vec<(u64, u64)>) {
+    let Some(mbr) = read_at(reader, 0, 512) else {
+        return (None, Vec::new());
+    };
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return (None, Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    let mut protective = false;
+    for i in 0..4 {
+        let e = &mbr[446 + i * 16..446 + (i + 1) * 16];
+        let ptype = e[4];
+        let start = u32::from_le_bytes([e[8], e[9], e[10], e[11]]) as u64;
+        let count = u32::from_le_bytes([e[12], e[13], e[14], e[15]]) as u64;
+        // A boot sector's code can spill into the table area; require a sane status byte
+        if ptype == 0 || count == 0 || (e[0] != 0x00 && e[0] != 0x80) {
+            continue;
+        }
+        if ptype == 0xEE {
+            protective = true;
+        }
+        entries.push((start * 512, count * 512));
+    }
+
+    if protective {
+        let Some(header) = read_at(reader, 512, 512) else {
+            return (Some("gpt".to_string()), Vec::new());
+        };
+        if &header[0..8] != b"EFI PART" {
+            return (Some("gpt".to_string()), Vec::new());
+        }
+        let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let num_entries = u32::from_le_bytes(header[80..84].try_into().unwrap()).min(128);
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()).max(128) as usize;
+        let mut partitions = Vec::new();
+        if let Some(table) = read_at(reader, entries_lba * 512, num_entries as usize * entry_size) {
+            for entry in table.chunks_exact(entry_size) {
+                if entry[0..16].iter().all(|&b| b == 0) {
+                    continue;
+                }
+                let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+                let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+                if last >= first {
+                    partitions.push((first * 512, (last - first + 1) * 512));
+                }
+            }
+        }
+        return (Some("gpt".to_string()), partitions);
+    }
+
+    if entries.is_empty() {
+        (None, Vec::new())
+    } else {
+        (Some("mbr".to_string()), entries)
+    }
+}
+
+/// Bytes and reads of each stage of an analysis, for progress and time estimates
+struct AnalysisPlan {
+    /// Identification and leftover scan of each volume
+    volumes: Vec<u64>,
+    entropy: u64,
+    sweep: u64,
+    reads: u64,
+}
+
+impl AnalysisPlan {
+    fn new(depth: AnalysisDepth, size: u64, volumes: &[(u64, u64)]) -> Self {
+        let residual = if depth == AnalysisDepth::Quick { (0, 0) } else { (RESIDUAL_PROBE_BYTES, RESIDUAL_PROBE_READS) };
+        let per_volume: Vec<u64> = volumes.iter()
+            .map(|&(start, len)| PROBE_BYTES + residual.0 + volume_len(size, start, len).min(FAT_SCAN_LIMIT as u64))
+            .collect();
+        let chunks = if depth == AnalysisDepth::Deep { size.div_ceil(SWEEP_CHUNK) } else { 0 };
+        Self {
+            reads: volumes.len() as u64 * (PROBE_READS + residual.1 + 1) + ENTROPY_SAMPLES + chunks,
+            volumes: per_volume,
+            entropy: ENTROPY_SAMPLES * ENTROPY_SAMPLE_SIZE as u64,
+            sweep: if depth == AnalysisDepth::Deep { size + chunks.saturating_sub(1) * SWEEP_OVERLAP } else { 0 },
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        self.volumes.iter().sum::<u64>() + self.entropy + self.sweep
+    }
+}
+
+/// Length of a partition clipped to the end of the device
+fn volume_len(size: u64, start: u64, len: u64) -> u64 {
+    len.min(size.saturating_sub(start))
+}
+
+/// The volumes to analyze: each partition, or the whole device when there is no table
+fn analysis_volumes(size: u64, layout: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    if layout.is_empty() { vec![(0, size)] } else { layout.to_vec() }
+}
+
+/// Analyze a device image or raw device reader of `size` bytes
+pub fn analyze_unknown<R: Read + Seek>(reader: &mut R, size: u64) -> Result<UnknownFilesystemAnalysis, MosesError> {
+    analyze_unknown_with_progress(reader, size, AnalysisDepth::Standard, &mut |_| ControlFlow::Continue(()))
+}
+
+/// As [`analyze_unknown`] at the given depth, reporting progress and partial results after
+/// each stage. Returning `ControlFlow::Break` from `on_progress` stops the analysis with
+/// `UserCancelled`.
+pub fn analyze_unknown_with_progress<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+    depth: AnalysisDepth,
+    on_progress: &mut dyn FnMut(&AnalysisProgress) -> ControlFlow<()>,
+) -> Result<UnknownFilesystemAnalysis, MosesError> {
+    // Make sure the device is readable at all before running heuristics
+    read_at(reader, 0, 512)
+        .ok_or_else(|| MosesError::Other("Failed to read sector 0".to_string()))?;
+
+    let (partition_table, layout) = read_partition_table(reader);
+    let volumes = analysis_volumes(size, &layout);
+
+    // Progress is counted in bytes read, which is what the ETA follows
+    let plan = AnalysisPlan::new(depth, size, &volumes);
+    let mut tracker = ProgressTracker::new(format!("Filesystem analysis ({})", depth.as_str()), plan.bytes());
+    let mut done = 0u64;
+
+    let mut analysis = UnknownFilesystemAnalysis {
+        filesystem: "unknown".to_string(),
+        partition_table,
+        partitions: Vec::new(),
+        candidates: Vec::new(),
+        entropy: None,
+        residual_signatures: Vec::new(),
+        likely_prior_filesystem: None,
+        warnings: Vec::new(),
+    };
+    let mut report = |phase: String, done: u64, analysis: &UnknownFilesystemAnalysis| {
+        let progress = AnalysisProgress { update: tracker.update(phase, done), partial: analysis.clone() };
+        match on_progress(&progress) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(MosesError::UserCancelled),
+        }
+    };
+
+    let count = volumes.len();
+    for (i, &(start, len)) in volumes.iter().enumerate() {
+        let volume = if layout.is_empty() { "the device".to_string() } else { format!("partition {}/{}", i + 1, count) };
+        let found = volume_candidates(reader, start);
+        if found.iter().any(|c| c.confidence >= 1.0 && c.filesystem.starts_with("ext")) {
+            analysis.warnings.extend(ext_dirty_warnings(reader, start).into_iter().map(|w| {
+                if layout.is_empty() { w } else { format!("Partition {}: {}", i + 1, w) }
+            }));
+        }
+        if !layout.is_empty() {
+            analysis.partitions.push(PartitionSummary {
+                number: i as u32 + 1,
+                filesystem: found.iter().find(|c| c.confidence >= 1.0).map(|c| c.filesystem.clone()),
+                size: len,
+                start_offset: start,
+            });
+        }
+        analysis.candidates.extend(found);
+        summarize(&mut analysis);
+        done += PROBE_BYTES;
+        report(format!("identified {}", volume), done, &analysis)?;
+
+        let len = volume_len(size, start, len);
+        analysis.residual_signatures.extend(match depth {
+            AnalysisDepth::Quick => find_stale_fat_tables(reader, start, len),
+            _ => find_residual_signatures(reader, start, len),
+        });
+        summarize(&mut analysis);
+        done += plan.volumes[i] - PROBE_BYTES;
+        report(format!("scanned {} for leftovers", volume), done, &analysis)?;
+    }
+
+    if depth == AnalysisDepth::Deep {
+        let known: Vec<u64> = volumes.iter().map(|&(start, _)| start).collect();
+        let skip: Vec<u64> = analysis.residual_signatures.iter().map(|r| r.offset).collect();
+        let mut swept = |bytes: u64, found: &[ResidualSignature]| {
+            let mut partial = analysis.clone();
+            partial.residual_signatures.extend_from_slice(found);
+            summarize(&mut partial);
+            let phase = format!("sweeping for signatures, {} of {} MB", bytes / (1024 * 1024), size / (1024 * 1024));
+            match report(phase, done + bytes, &partial) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        };
+        let found = sweep_signatures(reader, size, &known, &skip, &mut swept).ok_or(MosesError::UserCancelled)?;
+        if found.len() >= SWEEP_HIT_LIMIT {
+            analysis.warnings.push(format!("Surface sweep stopped after {} signatures", SWEEP_HIT_LIMIT));
+        }
+        analysis.residual_signatures.extend(found);
+        summarize(&mut analysis);
+        done += plan.sweep;
+    }
+
+    // Entropy only tells us something when nothing was recognised
+    if analysis.filesystem == "unknown" {
+        let (start, len) = volumes[0];
+        let len = match depth {
+            AnalysisDepth::Quick => volume_len(size, start, len).min(QUICK_SAMPLE_SPAN),
+            _ => volume_len(size, start, len),
+        };
+        let mut sampled = |sample: u64| {
+            if !sample.is_multiple_of(8) {
+                return ControlFlow::Continue(());
+            }
+            let phase = format!("sampling content {}/{}", sample, ENTROPY_SAMPLES);
+            match report(phase, done + sample * ENTROPY_SAMPLE_SIZE as u64, &analysis) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        };
+        let entropy = analyze_entropy(reader, start, len, &mut sampled)
+            .ok_or(MosesError::UserCancelled)?;
+        analysis.entropy = entropy;
+    }
+    report("complete".to_string(), plan.bytes(), &analysis)?;
+
+    Ok(analysis)
+}
+
+/// Rank the candidates and derive the current and prior filesystem from what was found so far
+fn summarize(analysis: &mut UnknownFilesystemAnalysis) {
+    analysis.candidates.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
+
+    analysis.filesystem = analysis.candidates.iter()
+        .find(|c| c.confidence >= 1.0)
+        .map(|c| c.filesystem.clone())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // The prior filesystem is the one with the most leftovers that isn't current
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    for r in &analysis.residual_signatures {
+        if r.filesystem != "gpt" && !analysis.filesystem.starts_with(r.filesystem.as_str()) {
+            *votes.entry(r.filesystem.as_str()).or_default() += 1;
+        }
+    }
+    analysis.likely_prior_filesystem = votes.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(fs, _)| fs.to_string());
+}
+
+/// Unclean-shutdown warnings for the ext volume at `start`, from its superblock and MMP block
+fn ext_dirty_warnings<R: Read + Seek>(reader: &mut R, start: u64) -> Vec<String> {
+    use crate::families::ext::ext4_native::{core::structures::Ext4Superblock, reader::DirtyState};
+
+    let Some(raw) = read_at(reader, start + 1024, 1024) else {
+        return Vec::new();
+    };
+    let sb = unsafe { std::ptr::read_unaligned(raw.as_ptr() as *const Ext4Superblock) };
+    let block_size = 1024u64 << sb.s_log_block_size.min(6);
+    let mut state = DirtyState::from_superblock(&sb);
+    state.check_mmp(&sb, |block| {
+        read_at(reader, start.saturating_add(block.saturating_mul(block_size)), block_size as usize)
+            .ok_or_else(|| MosesError::Other("MMP block unreadable".to_string()))
+    });
+    state.warnings()
+}
+
+/// Time a few small reads spread across the device and one sequential read
+pub fn measure_throughput<R: Read + Seek>(reader: &mut R, size: u64) -> Option<DeviceThroughput> {
+    let started = Instant::now();
+    for i in 0..THROUGHPUT_PROBES {
+        read_at(reader, (size / THROUGHPUT_PROBES * i) / 4096 * 4096, 4096)?;
+    }
+    let secs_per_read = started.elapsed().as_secs_f64() / THROUGHPUT_PROBES as f64;
+
+    let len = THROUGHPUT_SAMPLE.min(size as usize / 512 * 512);
+    let started = Instant::now();
+    read_at(reader, (size - len as u64) / 2 / 4096 * 4096, len)?;
+    let transfer = (started.elapsed().as_secs_f64() - secs_per_read).max(f64::EPSILON);
+    Some(DeviceThroughput { bytes_per_sec: len as f64 / transfer, secs_per_read })
+}
+
+/// What an analysis reads at each depth, with durations from the measured throughput
+pub fn estimate_analysis<R: Read + Seek>(reader: &mut R, size: u64) -> Vec<AnalysisEstimate> {
+    let (_, layout) = read_partition_table(reader);
+    let volumes = analysis_volumes(size, &layout);
+    let throughput = measure_throughput(reader, size);
+    AnalysisDepth::ALL.into_iter()
+        .map(|depth| {
+            let plan = AnalysisPlan::new(depth, size, &volumes);
+            AnalysisEstimate {
+                depth,
+                bytes: plan.bytes(),
+                reads: plan.reads,
+                eta_secs: throughput.map(|t| {
+                    (plan.reads as f64 * t.secs_per_read + plan.bytes() as f64 / t.bytes_per_sec).ceil() as u64
+                }),
+            }
+        })
+        .collect()
+}
+
+fn open_for_analysis(device: &Device) -> Result<(AlignedDeviceReader, u64), MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = AlignedDeviceReader::new(file);
+
+    let size = if device.size > 0 {
+        device.size
+    } else {
+        reader.seek(SeekFrom::End(0))
+            .map_err(|e| MosesError::Other(format!("Failed to determine device size: {}", e)))?
+    };
+    Ok((reader, size))
+}
+
+/// Analyze a device whose filesystem could not be identified (read-only)
+pub fn analyze_unknown_filesystem(device: &Device) -> Result<UnknownFilesystemAnalysis, MosesError> {
+    analyze_unknown_filesystem_with_progress(device, AnalysisDepth::Standard, &mut |_| ControlFlow::Continue(()))
+}
+
+/// As [`analyze_unknown_filesystem`] at the given depth, with progress, partial results
+/// and cancellation as in [`analyze_unknown_with_progress`]
+pub fn analyze_unknown_filesystem_with_progress(
+    device: &Device,
+    depth: AnalysisDepth,
+    on_progress: &mut dyn FnMut(&AnalysisProgress) -> ControlFlow<()>,
+) -> Result<UnknownFilesystemAnalysis, MosesError> {
+    log::info!("Running {} heuristic filesystem analysis on {}", depth.as_str(), device.name);
+    let (mut reader, size) = open_for_analysis(device)?;
+    analyze_unknown_with_progress(&mut reader, size, depth, on_progress)
+}
+
+/// As [`estimate_analysis`] for a device
+pub fn estimate_filesystem_analysis(device: &Device) -> Result<Vec<AnalysisEstimate>, MosesError> {
+    let (mut reader, size) = open_for_analysis(device)?;
+    Ok(estimate_analysis(&mut reader, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_random_content_is_high_entropy() {
+        use rand::RngCore;
+        let mut disk = vec![0u8; 1024 * 1024];
+        rand::thread_rng().fill_bytes(&mut disk);
+        let analysis = analyze_unknown(&mut Cursor::new(disk), 1024 * 1024).unwrap();
+
+        assert_eq!(analysis.filesystem, "unknown");
+        assert_eq!(analysis.entropy.unwrap().class, ContentClass::HighEntropy);
+    }
+
+    #[test]
+    fn test_zeroed_disk_with_ext_backup_superblock() {
+        let mut disk = vec![0u8; 10 * 1024 * 1024];
+        // Group 1 backup with 1KiB blocks lives at block 8193
+        let sb = (8192 + 1) * 1024;
+        disk[sb + 56] = 0x53;
+        disk[sb + 57] = 0xEF;
+        disk[sb + 90] = 1;
+        let analysis = analyze_unknown(&mut Cursor::new(disk), 10 * 1024 * 1024).unwrap();
+
+        assert_eq!(analysis.filesystem, "unknown");
+        assert_eq!(analysis.likely_prior_filesystem.as_deref(), Some("ext"));
+        assert_eq!(analysis.residual_signatures[0].offset, sb as u64);
+    }
+
+    #[test]
+    fn test_progress_streams_partial_results_and_cancels() {
+        let mut disk = vec![0u8; 10 * 1024 * 1024];
+        let sb = (8192 + 1) * 1024;
+        disk[sb + 56] = 0x53;
+        disk[sb + 57] = 0xEF;
+        disk[sb + 90] = 1;
+
+        let mut updates = Vec::new();
+        let analysis = analyze_unknown_with_progress(&mut Cursor::new(disk.clone()), disk.len() as u64, AnalysisDepth::Standard, &mut |progress| {
+            updates.push(progress.clone());
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert!(updates.windows(2).all(|w| w[0].update.percent <= w[1].update.percent));
+        assert_eq!(updates.last().unwrap().update.percent, 100.0);
+        // The leftover superblock is reported as soon as the volume has been scanned
+        let scanned = updates.iter().position(|p| p.update.phase.starts_with("scanned")).unwrap();
+        assert_eq!(updates[scanned].partial.likely_prior_filesystem.as_deref(), Some("ext"));
+        assert!(updates[scanned].partial.entropy.is_none());
+        assert!(analysis.entropy.is_some());
+
+        let mut calls = 0;
+        let cancelled = analyze_unknown_with_progress(&mut Cursor::new(disk.clone()), disk.len() as u64, AnalysisDepth::Standard, &mut |_| {
+            calls += 1;
+            if calls == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert!(matches!(cancelled, Err(MosesError::UserCancelled)));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_depths_find_more_and_cost_more() {
+        let mut disk = vec![0u8; 80 * 1024 * 1024];
+        let sb = (8192 + 1) * 1024;
+        disk[sb + 56] = 0x53;
+        disk[sb + 57] = 0xEF;
+        disk[sb + 90] = 1;
+        // An NTFS boot sector no partition table points to, far past the standard offsets
+        let orphan = 70 * 1024 * 1024 + 3 * 512;
+        disk[orphan + 3..orphan + 11].copy_from_slice(b"NTFS    ");
+        let size = disk.len() as u64;
+
+        let run = |depth| analyze_unknown_with_progress(&mut Cursor::new(disk.clone()), size, depth, &mut |_| ControlFlow::Continue(())).unwrap();
+        let offsets = |analysis: &UnknownFilesystemAnalysis| analysis.residual_signatures.iter().map(|r| r.offset).collect::<Vec<_>>();
+        assert!(offsets(&run(AnalysisDepth::Quick)).is_empty());
+        assert_eq!(offsets(&run(AnalysisDepth::Standard)), vec![sb as u64]);
+        let deep = run(AnalysisDepth::Deep);
+        assert_eq!(offsets(&deep), vec![sb as u64, orphan as u64], "the backup superblock is not reported twice");
+        assert_eq!(deep.residual_signatures[1].filesystem, "ntfs");
+
+        let estimates = estimate_analysis(&mut Cursor::new(disk), size);
+        assert_eq!(estimates.iter().map(|e| e.depth).collect::<Vec<_>>(), AnalysisDepth::ALL);
+        assert!(estimates.windows(2).all(|w| w[0].bytes < w[1].bytes && w[0].eta_secs <= w[1].eta_secs));
+        assert!(estimates[2].bytes >= size);
+        assert!(estimates.iter().all(|e| e.eta_secs.is_some()));
+        assert_eq!("Deep".parse::<AnalysisDepth>().unwrap(), AnalysisDepth::Deep);
+        assert!("thorough".parse::<AnalysisDepth>().is_err());
+    }
+
+    #[test]
+    fn test_dirty_ext_volume_is_reported() {
+        let mut disk = vec![0u8; 1024 * 1024];
+        let sb = 1024;
+        disk[sb + 56] = 0x53;
+        disk[sb + 57] = 0xEF;
+        // s_state 0 (not cleanly unmounted) and INCOMPAT_RECOVER
+        disk[sb + 0x60] = 0x04;
+        let analysis = analyze_unknown(&mut Cursor::new(disk), 1024 * 1024).unwrap();
+
+        assert!(analysis.filesystem.starts_with("ext"));
+        assert_eq!(analysis.warnings[0], "Filesystem is dirty, data may be stale");
+        assert!(analysis.to_report().contains("journal"));
+    }
+
+    #[test]
+    fn test_partial_ntfs_signature() {
+        let mut disk = vec![0u8; 64 * 1024];
+        disk[3..11].copy_from_slice(b"NTFS    ");
+        let analysis = analyze_unknown(&mut Cursor::new(disk), 64 * 1024).unwrap();
+
+        let ntfs = analysis.candidates.iter().find(|c| c.filesystem == "ntfs").unwrap();
+        assert!(ntfs.confidence < 1.0);
+    }
+}
@@ -0,0 +1,185 @@
+// MBR boot code handling - preserve or reinstall the first 440 bytes of sector 0
+use std::io::{Read, Write, Seek, SeekFrom};
+use moses_core::MosesError;
+use serde::{Serialize, Deserialize};
+
+/// Size of the boot code area in the MBR (before the disk signature at 0x1B8)
+pub const BOOT_CODE_SIZE: usize = 440;
+
+/// What to do with the MBR boot code when cleaning or converting a disk
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BootCodeAction {
+    /// Let the operation overwrite the boot code (previous behaviour)
+    #[default]
+    Discard,
+    /// Keep whatever boot code was on the disk before the operation
+    Preserve,
+    /// Install the standard Moses boot stub
+    InstallStandard,
+}
+
+impl BootCodeAction {
+    /// Parse the string form used by the GUI and worker ("discard", "preserve", "standard")
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "discard" | "none" => Some(BootCodeAction::Discard),
+            "preserve" | "keep" => Some(BootCodeAction::Preserve),
+            "standard" | "install" => Some(BootCodeAction::InstallStandard),
+            _ => None,
+        }
+    }
+    
+    /// String form accepted by [`BootCodeAction::parse`]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootCodeAction::Discard => "discard",
+            BootCodeAction::Preserve => "preserve",
+            BootCodeAction::InstallStandard => "standard",
+        }
+    }
+}
+
+/// Minimal real-mode boot stub.
+///
+/// It does not chain-load anything; it prints a message through the BIOS
+/// teletype service and halts, which is what a non-system disk should do
+/// instead of executing whatever garbage is left in sector 0.
+const STANDARD_STUB_CODE: [u8; 31] = [
+    0xFA,             // cli
+    0x31, 0xC0,       // xor ax, ax
+    0x8E, 0xD8,       // mov ds, ax
+    0x8E, 0xD0,       // mov ss, ax
+    0xBC, 0x00, 0x7C, // mov sp, 0x7C00
+    0xFB,             // sti
+    0xBE, 0x1F, 0x7C, // mov si, 0x7C1F (message)
+    0xAC,             // .print: lodsb
+    0x84, 0xC0,       // test al, al
+    0x74, 0x09,       // jz .halt
+    0xB4, 0x0E,       // mov ah, 0x0E
+    0xBB, 0x07, 0x00, // mov bx, 0x0007
+    0xCD, 0x10,       // int 0x10
+    0xEB, 0xF2,       // jmp .print
+    0xF4,             // .halt: hlt
+    0xEB, 0xFD,       // jmp .halt
+];
+
+const STANDARD_STUB_MESSAGE: &[u8] = b"This disk is not bootable. Remove it and restart.\r\n\0";
+
+/// Build the standard boot stub, padded to the full boot code area
+pub fn standard_boot_stub() -> [u8; BOOT_CODE_SIZE] {
+    let mut code = [0u8; BOOT_CODE_SIZE];
+    code[..STANDARD_STUB_CODE.len()].copy_from_slice(&STANDARD_STUB_CODE);
+    let msg_start = STANDARD_STUB_CODE.len();
+    code[msg_start..msg_start + STANDARD_STUB_MESSAGE.len()].copy_from_slice(STANDARD_STUB_MESSAGE);
+    code
+}
+
+/// Read the existing boot code from sector 0.
+///
+/// Returns `None` if the sector carries no 0x55AA signature or the boot code
+/// area is empty - there is nothing worth preserving in that case.
+pub fn read_boot_code<R: Read + Seek>(reader: &mut R) -> Result<Option<[u8; BOOT_CODE_SIZE]>, MosesError> {
+    let mut sector = [0u8; 512];
+    reader.seek(SeekFrom::Start(0))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to MBR: {}", e)))?;
+    reader.read_exact(&mut sector)
+        .map_err(|e| MosesError::Other(format!("Failed to read MBR: {}", e)))?;
+
+    if sector[0x1FE] != 0x55 || sector[0x1FF] != 0xAA {
+        return Ok(None);
+    }
+
+    let mut code = [0u8; BOOT_CODE_SIZE];
+    code.copy_from_slice(&sector[..BOOT_CODE_SIZE]);
+
+    if code.iter().all(|&b| b == 0) {
+        Ok(None)
+    } else {
+        Ok(Some(code))
+    }
+}
+
+/// Write boot code into sector 0 without touching the disk signature or partition table
+pub fn write_boot_code<W: Write + Seek>(writer: &mut W, code: &[u8; BOOT_CODE_SIZE]) -> Result<(), MosesError> {
+    writer.seek(SeekFrom::Start(0))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to MBR: {}", e)))?;
+    writer.write_all(code)
+        .map_err(|e| MosesError::Other(format!("Failed to write boot code: {}", e)))?;
+    writer.flush()
+        .map_err(|e| MosesError::Other(format!("Failed to flush boot code: {}", e)))?;
+    Ok(())
+}
+
+/// Resolve the boot code to write after an operation, capturing it beforehand if needed.
+///
+/// Call before the destructive step; pass the result to [`restore_boot_code`] afterwards.
+pub fn capture_boot_code<R: Read + Seek>(
+    reader: &mut R,
+    action: BootCodeAction,
+) -> Result<Option<[u8; BOOT_CODE_SIZE]>, MosesError> {
+    match action {
+        BootCodeAction::Discard => Ok(None),
+        BootCodeAction::Preserve => {
+            let code = read_boot_code(reader)?;
+            if code.is_none() {
+                log::info!("No existing boot code found to preserve");
+            }
+            Ok(code)
+        }
+        BootCodeAction::InstallStandard => Ok(Some(standard_boot_stub())),
+    }
+}
+
+/// Write back boot code captured by [`capture_boot_code`]
+pub fn restore_boot_code<W: Write + Seek>(
+    writer: &mut W,
+    code: Option<[u8; BOOT_CODE_SIZE]>,
+) -> Result<(), MosesError> {
+    if let Some(code) = code {
+        log::info!("Writing MBR boot code ({} bytes)", BOOT_CODE_SIZE);
+        write_boot_code(writer, &code)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_standard_stub_layout() {
+        let stub = standard_boot_stub();
+        assert_eq!(stub[0], 0xFA);
+        // mov si must point at the message that follows the code
+        let msg_addr = u16::from_le_bytes([stub[12], stub[13]]) as usize;
+        assert_eq!(msg_addr - 0x7C00, STANDARD_STUB_CODE.len());
+        assert_eq!(&stub[STANDARD_STUB_CODE.len()..STANDARD_STUB_CODE.len() + 4], b"This");
+    }
+
+    #[test]
+    fn test_preserve_round_trip() {
+        let mut disk = vec![0u8; 4096];
+        disk[..BOOT_CODE_SIZE].fill(0x90);
+        disk[0x1FE] = 0x55;
+        disk[0x1FF] = 0xAA;
+        let mut cursor = Cursor::new(&mut disk);
+
+        let saved = capture_boot_code(&mut cursor, BootCodeAction::Preserve).unwrap();
+        assert!(saved.is_some());
+
+        // Simulate a wipe of the first sector
+        cursor.get_mut()[..512].fill(0);
+        restore_boot_code(&mut cursor, saved).unwrap();
+
+        assert!(disk[..BOOT_CODE_SIZE].iter().all(|&b| b == 0x90));
+        assert_eq!(disk[0x1FE], 0);
+    }
+
+    #[test]
+    fn test_no_signature_nothing_preserved() {
+        let mut disk = vec![0x90u8; 1024];
+        let mut cursor = Cursor::new(&mut disk);
+        assert!(capture_boot_code(&mut cursor, BootCodeAction::Preserve).unwrap().is_none());
+    }
+}
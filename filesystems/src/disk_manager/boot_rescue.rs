@@ -0,0 +1,464 @@
+// Boot region rescue - rebuild a damaged boot sector from the filesystem's own backup
+// FAT32 keeps a copy of its boot sector at sector 6 (BPB_BkBootSec), exFAT a full copy of
+// its 12-sector boot region right after the main one, and NTFS a copy of its boot sector
+// in the last sector of the volume. When the primary is overwritten (a stray dd, a bad
+// MBR tool) the volume no longer mounts although everything else is intact. The backup is
+// validated, compared field by field with what is there now, and copied over the primary;
+// the replaced bytes are saved first so the rescue can be undone.
+
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use moses_core::{Device, MosesError};
+use crate::hexview::{decode_field, FieldKind, StructureLayout, EXFAT_BOOT_SECTOR_LAYOUT,
+    FAT32_EXTENDED_BPB_LAYOUT, FAT_BOOT_SECTOR_LAYOUT, NTFS_BOOT_SECTOR_LAYOUT};
+
+const SECTOR_SIZES: [u64; 4] = [512, 1024, 2048, 4096];
+const FAT32_DEFAULT_BACKUP_SECTOR: u64 = 6;
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+/// Boot sector, extended boot sectors, OEM parameters, reserved and checksum sector
+const EXFAT_BOOT_REGION_SECTORS: u64 = 12;
+const BACKUP_HEADER: &str = "# moses boot rescue backup";
+
+/// Which filesystem's boot region was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootRegionKind {
+    Fat32,
+    Exfat,
+    Ntfs,
+}
+
+impl std::fmt::Display for BootRegionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BootRegionKind::Fat32 => "FAT32",
+            BootRegionKind::Exfat => "exFAT",
+            BootRegionKind::Ntfs => "NTFS",
+        })
+    }
+}
+
+/// Bytes a rescue replaces: what is on the device now and what the backup holds
+#[derive(Debug, Clone)]
+pub struct RescueRange {
+    pub offset: u64,
+    pub current: Vec<u8>,
+    pub backup: Vec<u8>,
+}
+
+/// A valid backup boot region and the writes that would restore it
+#[derive(Debug, Clone)]
+pub struct BootBackup {
+    pub kind: BootRegionKind,
+    pub sector_size: u64,
+    /// Where the backup was read from
+    pub backup_offset: u64,
+    /// Whether the primary passes the same checks as the backup
+    pub primary_valid: bool,
+    pub ranges: Vec<RescueRange>,
+}
+
+/// One difference between the primary and the backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub offset: u64,
+    /// Field name, or the sector and offset for bytes outside a known field
+    pub field: String,
+    pub current: String,
+    pub backup: String,
+}
+
+impl BootBackup {
+    /// Whether the rescue would change anything
+    pub fn differs(&self) -> bool {
+        self.ranges.iter().any(|range| range.current != range.backup)
+    }
+
+    /// Differences between the primary and the backup, named after the boot sector fields
+    pub fn changes(&self) -> Vec<FieldChange> {
+        let layouts: &[&StructureLayout] = match self.kind {
+            BootRegionKind::Fat32 => &[&FAT_BOOT_SECTOR_LAYOUT, &FAT32_EXTENDED_BPB_LAYOUT],
+            BootRegionKind::Exfat => &[&EXFAT_BOOT_SECTOR_LAYOUT],
+            BootRegionKind::Ntfs => &[&NTFS_BOOT_SECTOR_LAYOUT],
+        };
+        let mut changes = Vec::new();
+        for range in &self.ranges {
+            let mut named = vec![false; range.current.len()];
+            if range.offset == 0 {
+                for field in layouts.iter().flat_map(|layout| layout.fields) {
+                    let span = field.offset..field.offset + field.length;
+                    if span.end > range.current.len() || range.current[span.clone()] == range.backup[span.clone()] {
+                        continue;
+                    }
+                    named[span.clone()].fill(true);
+                    changes.push(FieldChange {
+                        offset: field.offset as u64,
+                        field: field.name.to_string(),
+                        current: decode_field(&range.current[span.clone()], field.kind),
+                        backup: decode_field(&range.backup[span], field.kind),
+                    });
+                }
+            }
+
+            // Anything else that differs, as runs of raw bytes
+            let mut i = 0;
+            while i < range.current.len() {
+                if named[i] || range.current[i] == range.backup[i] {
+                    i += 1;
+                    continue;
+                }
+                let start = i;
+                while i < range.current.len() && !named[i] && range.current[i] != range.backup[i] {
+                    i += 1;
+                }
+                let offset = range.offset + start as u64;
+                changes.push(FieldChange {
+                    offset,
+                    field: format!("sector {} +0x{:X}", offset / self.sector_size, offset % self.sector_size),
+                    current: decode_field(&range.current[start..i], FieldKind::Bytes),
+                    backup: decode_field(&range.backup[start..i], FieldKind::Bytes),
+                });
+            }
+        }
+        changes.sort_by_key(|change| change.offset);
+        changes
+    }
+}
+
+fn read_bytes<R: Read + Seek>(reader: &mut R, offset: u64, len: u64) -> Option<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn has_boot_signature(sector: &[u8]) -> bool {
+    sector.len() >= 512 && sector[510] == 0x55 && sector[511] == 0xAA
+}
+
+/// A FAT32 boot sector with a sane BPB for `sector_size`
+fn valid_fat32(sector: &[u8], sector_size: u64) -> bool {
+    has_boot_signature(sector)
+        && (sector[0] == 0xEB || sector[0] == 0xE9)
+        && &sector[82..87] == b"FAT32"
+        && u16_at(sector, 11) as u64 == sector_size
+        && sector[13].is_power_of_two()
+        && u16_at(sector, 14) > u16_at(sector, 50)
+        && (1..=2).contains(&sector[16])
+        && u32_at(sector, 36) > 0
+}
+
+/// An NTFS boot sector with a sane BPB for `sector_size`
+fn valid_ntfs(sector: &[u8], sector_size: u64) -> bool {
+    has_boot_signature(sector)
+        && &sector[3..11] == b"NTFS    "
+        && u16_at(sector, 11) as u64 == sector_size
+        && sector[13] != 0
+        && u64_at(sector, 40) > 0
+}
+
+/// A complete exFAT boot region whose checksum sector matches
+fn valid_exfat(region: &[u8], sector_size: u64) -> bool {
+    let ss = sector_size as usize;
+    if region.len() < EXFAT_BOOT_REGION_SECTORS as usize * ss || !has_boot_signature(region) {
+        return false;
+    }
+    if &region[3..11] != b"EXFAT   " || 1u64.checked_shl(region[108] as u32) != Some(sector_size) {
+        return false;
+    }
+    let checksum = exfat_boot_checksum(&region[..11 * ss]);
+    region[11 * ss..12 * ss].as_chunks::<4>().0.iter().all(|word| u32::from_le_bytes(*word) == checksum)
+}
+
+/// exFAT boot checksum over sectors 0-10, skipping VolumeFlags and PercentInUse
+fn exfat_boot_checksum(sectors: &[u8]) -> u32 {
+    sectors.iter().enumerate()
+        .filter(|(i, _)| !matches!(i, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+/// Find the backup boot region of the volume in a reader of `size` bytes
+pub fn find_boot_backup<R: Read + Seek>(reader: &mut R, size: u64) -> Option<BootBackup> {
+    find_ntfs(reader, size)
+        .or_else(|| find_exfat(reader, size))
+        .or_else(|| find_fat32(reader, size))
+}
+
+fn find_fat32<R: Read + Seek>(reader: &mut R, size: u64) -> Option<BootBackup> {
+    for sector_size in SECTOR_SIZES {
+        let primary = read_bytes(reader, 0, sector_size)?;
+        let primary_valid = valid_fat32(&primary, sector_size);
+        // A readable primary says where its backup is; otherwise assume mkfs's default
+        let backup_sector = if primary_valid { u16_at(&primary, 50) as u64 } else { FAT32_DEFAULT_BACKUP_SECTOR };
+        if backup_sector == 0 || (backup_sector + 2) * sector_size > size {
+            continue;
+        }
+        let Some(backup) = read_bytes(reader, backup_sector * sector_size, sector_size) else { continue };
+        if !valid_fat32(&backup, sector_size) {
+            continue;
+        }
+
+        let mut ranges = vec![RescueRange { offset: 0, current: primary, backup }];
+        // FSInfo's free count is only a hint, so a live one is kept; a destroyed one is restored too
+        let fsinfo_sector = u16_at(&ranges[0].backup, 48) as u64;
+        if fsinfo_sector != 0 && fsinfo_sector < backup_sector {
+            let current = read_bytes(reader, fsinfo_sector * sector_size, sector_size)?;
+            let saved = read_bytes(reader, (backup_sector + fsinfo_sector) * sector_size, sector_size)?;
+            if u32_at(&current, 0) != FSINFO_LEAD_SIGNATURE && u32_at(&saved, 0) == FSINFO_LEAD_SIGNATURE {
+                ranges.push(RescueRange { offset: fsinfo_sector * sector_size, current, backup: saved });
+            }
+        }
+        return Some(BootBackup {
+            kind: BootRegionKind::Fat32,
+            sector_size,
+            backup_offset: backup_sector * sector_size,
+            primary_valid,
+            ranges,
+        });
+    }
+    None
+}
+
+fn find_exfat<R: Read + Seek>(reader: &mut R, size: u64) -> Option<BootBackup> {
+    for sector_size in SECTOR_SIZES {
+        let len = EXFAT_BOOT_REGION_SECTORS * sector_size;
+        if 2 * len > size {
+            continue;
+        }
+        let backup = read_bytes(reader, len, len)?;
+        if !valid_exfat(&backup, sector_size) {
+            continue;
+        }
+        let current = read_bytes(reader, 0, len)?;
+        return Some(BootBackup {
+            kind: BootRegionKind::Exfat,
+            sector_size,
+            backup_offset: len,
+            primary_valid: valid_exfat(&current, sector_size),
+            ranges: vec![RescueRange { offset: 0, current, backup }],
+        });
+    }
+    None
+}
+
+fn find_ntfs<R: Read + Seek>(reader: &mut R, size: u64) -> Option<BootBackup> {
+    for sector_size in SECTOR_SIZES {
+        if size < 2 * sector_size {
+            continue;
+        }
+        let current = read_bytes(reader, 0, sector_size)?;
+        let primary_valid = valid_ntfs(&current, sector_size);
+        // The backup sits just past the sectors the boot sector counts, normally the
+        // device's last sector
+        let mut candidates = vec![size / sector_size * sector_size - sector_size];
+        if primary_valid {
+            candidates.insert(0, u64_at(&current, 40) * sector_size);
+        }
+        for offset in candidates {
+            if offset == 0 || offset + sector_size > size {
+                continue;
+            }
+            let Some(backup) = read_bytes(reader, offset, sector_size) else { continue };
+            if valid_ntfs(&backup, sector_size) && u64_at(&backup, 40) * sector_size <= offset {
+                return Some(BootBackup {
+                    kind: BootRegionKind::Ntfs,
+                    sector_size,
+                    backup_offset: offset,
+                    primary_valid,
+                    ranges: vec![RescueRange { offset: 0, current, backup }],
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Copy the backup over the primary. The bytes replaced are written to `undo` first, in
+/// the format [`undo_boot_rescue`] reads.
+pub fn rescue_boot_region<F: Write + Seek>(
+    file: &mut F,
+    backup: &BootBackup,
+    undo: Option<&mut dyn Write>,
+    device_id: &str,
+) -> Result<(), MosesError> {
+    if let Some(out) = undo {
+        let mut text = format!("{} of {} ({} from 0x{:X})\n", BACKUP_HEADER, device_id, backup.kind, backup.backup_offset);
+        for range in &backup.ranges {
+            text.push_str(&format!("{:#x} {}\n", range.offset, hex::encode(&range.current)));
+        }
+        out.write_all(text.as_bytes())
+            .map_err(|e| MosesError::Other(format!("Failed to write backup: {}", e)))?;
+        out.flush()?;
+    }
+
+    for range in &backup.ranges {
+        log::info!("Restoring {} boot bytes at 0x{:X} from 0x{:X}", backup.kind, range.offset, backup.backup_offset);
+        file.seek(SeekFrom::Start(range.offset))?;
+        file.write_all(&range.backup)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Put back what [`rescue_boot_region`] replaced; returns the number of ranges written
+pub fn undo_boot_rescue<F: Write + Seek, R: BufRead>(file: &mut F, saved: R) -> Result<usize, MosesError> {
+    let mut lines = saved.lines();
+    match lines.next() {
+        Some(Ok(header)) if header.starts_with(BACKUP_HEADER) => {}
+        _ => return Err(MosesError::InvalidInput("Not a moses boot rescue backup file".to_string())),
+    }
+    let mut ranges = Vec::new();
+    for line in lines {
+        let line = line?;
+        let Some((offset, bytes)) = line.split_once(' ') else { continue };
+        let offset = u64::from_str_radix(offset.trim_start_matches("0x"), 16)
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid offset '{}' in backup: {}", offset, e)))?;
+        let bytes = hex::decode(bytes.trim())
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid bytes in backup at 0x{:X}: {}", offset, e)))?;
+        ranges.push((offset, bytes));
+    }
+    for (offset, bytes) in &ranges {
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(bytes)?;
+    }
+    file.flush()?;
+    Ok(ranges.len())
+}
+
+/// Find the backup boot region of a device (read-only)
+pub fn scan_device(device: &Device) -> Result<Option<BootBackup>, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    let size = if device.size > 0 { device.size } else { reader.seek(SeekFrom::End(0))? };
+    Ok(find_boot_backup(&mut reader, size))
+}
+
+/// Restore a backup on a device, saving the replaced bytes to `undo_path` first
+pub fn rescue_device(device: &Device, backup: &BootBackup, undo_path: Option<&Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to rewrite the boot sector of a system disk".to_string()));
+    }
+    let mut undo = match undo_path {
+        Some(path) => Some(std::fs::File::create(path)
+            .map_err(|e| MosesError::Other(format!("Failed to create backup file {}: {}", path.display(), e)))?),
+        None => None,
+    };
+    let mut file = super::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    rescue_boot_region(&mut file, backup, undo.as_mut().map(|f| f as &mut dyn Write), &device.id)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Undo a rescue on a device from the saved bytes
+pub fn undo_device(device: &Device, saved: &Path) -> Result<usize, MosesError> {
+    let saved = std::fs::File::open(saved)
+        .map_err(|e| MosesError::Other(format!("Failed to open backup file {}: {}", saved.display(), e)))?;
+    let mut file = super::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    let count = undo_boot_rescue(&mut file, std::io::BufReader::new(saved))?;
+    file.sync_all()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn fat32_boot_sector() -> Vec<u8> {
+        let mut sector = vec![0u8; 512];
+        sector[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        sector[3..11].copy_from_slice(b"MSWIN4.1");
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        sector[13] = 8;
+        sector[14..16].copy_from_slice(&32u16.to_le_bytes());
+        sector[16] = 2;
+        sector[36..40].copy_from_slice(&1000u32.to_le_bytes());
+        sector[48..50].copy_from_slice(&1u16.to_le_bytes());
+        sector[50..52].copy_from_slice(&6u16.to_le_bytes());
+        sector[71..82].copy_from_slice(b"CAMERA     ");
+        sector[82..90].copy_from_slice(b"FAT32   ");
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        sector
+    }
+
+    #[test]
+    fn test_fat32_rescue_and_undo() {
+        let mut disk = vec![0u8; 64 * 512];
+        let boot = fat32_boot_sector();
+        disk[6 * 512..7 * 512].copy_from_slice(&boot);
+        disk[7 * 512..7 * 512 + 4].copy_from_slice(&FSINFO_LEAD_SIGNATURE.to_le_bytes());
+        // Primary overwritten by something else
+        disk[..512].copy_from_slice(&boot);
+        disk[71..82].copy_from_slice(b"GARBAGE    ");
+        disk[82..90].fill(0);
+        let damaged = disk.clone();
+
+        let mut file = Cursor::new(disk);
+        let backup = find_boot_backup(&mut file, 64 * 512).unwrap();
+        assert_eq!(backup.kind, BootRegionKind::Fat32);
+        assert!(!backup.primary_valid);
+        // The destroyed FSInfo comes back with the boot sector
+        assert_eq!(backup.ranges.len(), 2);
+        let changes = backup.changes();
+        assert!(changes.iter().any(|c| c.field == "volume_label" && c.backup == "\"CAMERA\""));
+        assert!(changes.iter().any(|c| c.field == "fs_type"));
+
+        let mut undo = Vec::new();
+        rescue_boot_region(&mut file, &backup, Some(&mut undo), "test").unwrap();
+        assert_eq!(&file.get_ref()[..512], &boot[..]);
+        assert!(!find_boot_backup(&mut file, 64 * 512).unwrap().differs());
+
+        assert_eq!(undo_boot_rescue(&mut file, Cursor::new(undo)).unwrap(), 2);
+        assert_eq!(file.get_ref(), &damaged);
+    }
+
+    #[test]
+    fn test_exfat_and_ntfs_backups() {
+        // exFAT: the backup region must carry a matching checksum sector
+        let mut region = vec![0u8; 12 * 512];
+        region[3..11].copy_from_slice(b"EXFAT   ");
+        region[108] = 9;
+        region[510] = 0x55;
+        region[511] = 0xAA;
+        let checksum = exfat_boot_checksum(&region[..11 * 512]);
+        for word in region[11 * 512..].chunks_mut(4) {
+            word.copy_from_slice(&checksum.to_le_bytes());
+        }
+        let mut disk = vec![0u8; 64 * 512];
+        disk[12 * 512..24 * 512].copy_from_slice(&region);
+        let backup = find_boot_backup(&mut Cursor::new(disk.clone()), 64 * 512).unwrap();
+        assert_eq!(backup.kind, BootRegionKind::Exfat);
+        assert_eq!(backup.ranges[0].backup.len(), 12 * 512);
+        disk[12 * 512 + 11 * 512] ^= 1;
+        assert!(find_boot_backup(&mut Cursor::new(disk), 64 * 512).is_none());
+
+        // NTFS: the backup is the last sector
+        let mut sector = vec![0u8; 512];
+        sector[3..11].copy_from_slice(b"NTFS    ");
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        sector[13] = 8;
+        sector[40..48].copy_from_slice(&63u64.to_le_bytes());
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        let mut disk = vec![0u8; 64 * 512];
+        disk[63 * 512..].copy_from_slice(&sector);
+        let backup = find_boot_backup(&mut Cursor::new(disk), 64 * 512).unwrap();
+        assert_eq!(backup.kind, BootRegionKind::Ntfs);
+        assert_eq!(backup.backup_offset, 63 * 512);
+        assert!(backup.changes().iter().any(|c| c.field == "oem_id"));
+    }
+}
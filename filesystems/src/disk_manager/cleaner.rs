@@ -1,8 +1,11 @@
 // Disk Cleaner - Safely wipe partition structures and data
 use std::fs::OpenOptions;
 use std::io::{Write, Seek, SeekFrom};
-use moses_core::{Device, MosesError};
+use moses_core::{Device, MosesError, ProgressTracker, ProgressUpdate};
 use serde::{Serialize, Deserialize};
+use super::boot_code::{self, BootCodeAction};
+use super::plan::{OperationPlan, PlannedWrite, WriteContent};
+use crate::throttle::IoThrottle;
 
 pub struct DiskCleaner;
 
@@ -10,6 +13,12 @@ pub struct DiskCleaner;
 pub struct CleanOptions {
     pub wipe_method: WipeMethod,
     pub zero_entire_disk: bool,
+    /// What to do with the MBR boot code (first 440 bytes) after wiping
+    #[serde(default)]
+    pub boot_code: BootCodeAction,
+    /// Background mode: rate cap and idle I/O priority so the machine stays usable
+    #[serde(default)]
+    pub throttle: IoThrottle,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -27,7 +36,58 @@ pub enum WipeMethod {
 impl DiskCleaner {
     /// Clean a disk according to the specified options
     pub fn clean(device: &Device, options: &CleanOptions) -> Result<(), MosesError> {
+        Self::clean_with_progress(device, options, &mut |_| {})
+    }
+    
+    /// Describe what `clean` would write and which partitions and signatures it would destroy
+    pub fn dry_run(device: &Device, options: &CleanOptions) -> OperationPlan {
+        OperationPlan::for_device(
+            device,
+            format!("Clean ({:?})", options.wipe_method),
+            Self::planned_writes(options.wipe_method, device.size),
+            options.boot_code,
+        )
+    }
+    
+    /// Byte ranges written by `run_wipe`, in order
+    pub(crate) fn planned_writes(method: WipeMethod, disk_size: u64) -> Vec<PlannedWrite> {
+        let pass = |name: &str, content| PlannedWrite::new(name, 0, disk_size, content);
+        match method {
+            WipeMethod::Quick => {
+                let mut writes = vec![
+                    PlannedWrite::new("MBR", 0, 512, WriteContent::Zeros),
+                    PlannedWrite::new("Primary GPT header", 512, 512, WriteContent::Zeros),
+                    PlannedWrite::new("Primary GPT partition entries", 1024, 32 * 512, WriteContent::Zeros),
+                ];
+                if disk_size > 33 * 512 {
+                    writes.push(PlannedWrite::new(
+                        "Backup GPT partition entries and header", disk_size - 33 * 512, 33 * 512, WriteContent::Zeros,
+                    ));
+                }
+                writes.push(PlannedWrite::new("First megabyte (boot loaders)", 0, 1024 * 1024, WriteContent::Zeros));
+                writes.push(PlannedWrite::new("First partition start (1 MiB)", 1024 * 1024, 64 * 1024, WriteContent::Zeros));
+                writes
+            }
+            WipeMethod::Zero => vec![pass("Entire disk", WriteContent::Zeros)],
+            WipeMethod::Random => vec![pass("Entire disk", WriteContent::Random)],
+            WipeMethod::DoD5220 => vec![
+                pass("Entire disk (pass 1/3)", WriteContent::Zeros),
+                pass("Entire disk (pass 2/3)", WriteContent::Pattern(0xFF)),
+                pass("Entire disk (pass 3/3)", WriteContent::Random),
+            ],
+        }
+    }
+    
+    /// Clean a disk, reporting progress (with throughput and ETA) during full-disk passes
+    pub fn clean_with_progress(
+        device: &Device,
+        options: &CleanOptions,
+        on_progress: &mut dyn FnMut(&ProgressUpdate),
+    ) -> Result<(), MosesError> {
         log::info!("Cleaning disk: {} with method {:?}", device.name, options.wipe_method);
+        if !options.throttle.is_unlimited() {
+            log::info!("Background mode: {:?}", options.throttle);
+        }
         
         // Safety check
         if device.is_system {
@@ -36,19 +96,35 @@ impl DiskCleaner {
             ));
         }
         
+        // Full-disk passes write in the block size the device was tuned for; tuning also
+        // sets the queue depth the slot below respects
+        let block_size = match options.wipe_method {
+            WipeMethod::Quick => crate::io_tuning::DEFAULT_BLOCK_SIZE,
+            _ => crate::io_tuning::for_device(device).block_size,
+        };
+        
+        // Respect the per-device queue depth when several operations target one disk
+        let _slot = moses_core::DeviceQueues::global().acquire(&device.id);
+        super::history::record_before(device, format!("clean ({:?})", options.wipe_method));
+        
         #[cfg(target_os = "windows")]
-        {
-            Self::clean_windows(device, options)
-        }
+        let result = Self::clean_windows(device, options, block_size, on_progress);
         
         #[cfg(not(target_os = "windows"))]
-        {
-            Self::clean_unix(device, options)
-        }
+        let result = Self::clean_unix(device, options, block_size, on_progress);
+        
+        // Even a failed clean may have written something
+        moses_core::FilesystemCache::global().invalidate(device);
+        result
     }
     
     #[cfg(target_os = "windows")]
-    fn clean_windows(device: &Device, options: &CleanOptions) -> Result<(), MosesError> {
+    fn clean_windows(
+        device: &Device,
+        options: &CleanOptions,
+        block_size: usize,
+        on_progress: &mut dyn FnMut(&ProgressUpdate),
+    ) -> Result<(), MosesError> {
         // First, try to dismount any volumes on this device
         // This is crucial for being able to write to the disk
         if !device.mount_points.is_empty() {
@@ -102,42 +178,73 @@ impl DiskCleaner {
         // Now open the physical device for cleaning
         // Use the same method as formatters
         use crate::utils::open_device_write;
-        let mut file = open_device_write(device)?;
-        
-        // Clean based on options
-        match options.wipe_method {
-            WipeMethod::Quick => Self::quick_clean(&mut file, device.size)?,
-            WipeMethod::Zero => Self::zero_wipe(&mut file, device.size)?,
-            WipeMethod::DoD5220 => Self::dod_wipe(&mut file, device.size)?,
-            WipeMethod::Random => Self::random_wipe(&mut file, device.size)?,
-        }
+        let _io_priority = options.throttle.enter();
+        let mut file = options.throttle.wrap(open_device_write(device)?);
+        let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
         
-        file.sync_all()
+        Self::run_wipe(&mut file, device.size, options.wipe_method, block_size, on_progress)?;
+        
+        boot_code::restore_boot_code(&mut file, saved_boot_code)?;
+        
+        file.get_ref().sync_all()
             .map_err(|e| MosesError::Other(format!("Failed to sync after clean: {}", e)))?;
         
         Ok(())
     }
     
     #[cfg(not(target_os = "windows"))]
-    fn clean_unix(device: &Device, options: &CleanOptions) -> Result<(), MosesError> {
-        let mut file = OpenOptions::new()
+    fn clean_unix(
+        device: &Device,
+        options: &CleanOptions,
+        block_size: usize,
+        on_progress: &mut dyn FnMut(&ProgressUpdate),
+    ) -> Result<(), MosesError> {
+        let file = OpenOptions::new()
+            .read(true)
             .write(true)
             .open(&device.id)
             .map_err(|e| MosesError::IoError(e))?;
+        let _io_priority = options.throttle.enter();
+        let mut file = options.throttle.wrap(file);
+        let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
         
-        match options.wipe_method {
-            WipeMethod::Quick => Self::quick_clean(&mut file, device.size)?,
-            WipeMethod::Zero => Self::zero_wipe(&mut file, device.size)?,
-            WipeMethod::DoD5220 => Self::dod_wipe(&mut file, device.size)?,
-            WipeMethod::Random => Self::random_wipe(&mut file, device.size)?,
-        }
+        Self::run_wipe(&mut file, device.size, options.wipe_method, block_size, on_progress)?;
         
-        file.sync_all()
+        boot_code::restore_boot_code(&mut file, saved_boot_code)?;
+        
+        file.get_ref().sync_all()
             .map_err(|e| MosesError::Other(format!("Failed to sync after clean: {}", e)))?;
         
         Ok(())
     }
     
+    fn run_wipe<W: Write + Seek>(
+        writer: &mut W,
+        disk_size: u64,
+        method: WipeMethod,
+        block_size: usize,
+        on_progress: &mut dyn FnMut(&ProgressUpdate),
+    ) -> Result<(), MosesError> {
+        let passes = match method {
+            WipeMethod::Quick => return Self::quick_clean(writer, disk_size),
+            WipeMethod::Zero | WipeMethod::Random => 1,
+            WipeMethod::DoD5220 => 3,
+        };
+        let mut progress = WipeProgress {
+            tracker: ProgressTracker::new(format!("{:?} wipe", method), disk_size * passes),
+            on_progress,
+            completed: 0,
+            phase: String::new(),
+        };
+        
+        match method {
+            WipeMethod::Quick => unreachable!(),
+            WipeMethod::Zero => Self::zero_wipe(writer, disk_size, block_size, &mut progress),
+            WipeMethod::DoD5220 => Self::dod_wipe(writer, disk_size, block_size, &mut progress),
+            WipeMethod::Random => Self::random_wipe(writer, disk_size, block_size, &mut progress),
+        }
+    }
+    
     /// Quick clean - just wipe critical sectors
     fn quick_clean<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
         let zero_buffer = vec![0u8; 512];
@@ -192,100 +299,130 @@ impl DiskCleaner {
     }
     
     /// Zero entire disk
-    fn zero_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
-        const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
-        let zero_buffer = vec![0u8; CHUNK_SIZE];
+    fn zero_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64, block_size: usize, progress: &mut WipeProgress) -> Result<(), MosesError> {
+        progress.start_pass("zeroing");
+        let zero_buffer = vec![0u8; block_size];
         
         writer.seek(SeekFrom::Start(0))
             .map_err(|e| MosesError::Other(format!("Failed to seek to start: {}", e)))?;
         
         let mut written = 0u64;
         while written < disk_size {
-            let to_write = std::cmp::min(CHUNK_SIZE as u64, disk_size - written);
+            let to_write = std::cmp::min(block_size as u64, disk_size - written);
             writer.write_all(&zero_buffer[..to_write as usize])
                 .map_err(|e| MosesError::Other(format!("Failed to write zeros at {}: {}", written, e)))?;
             written += to_write;
-            
-            // Progress callback would go here
-            if written % (100 * 1024 * 1024) == 0 {
-                log::info!("Zero wipe progress: {}MB / {}MB", 
-                    written / (1024 * 1024), 
-                    disk_size / (1024 * 1024));
-            }
+            progress.advance(written, disk_size);
         }
+        progress.finish_pass(disk_size);
         
         log::info!("Zero wipe completed - entire disk zeroed");
         Ok(())
     }
     
     /// DoD 5220.22-M standard - 3 passes
-    fn dod_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
+    fn dod_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64, block_size: usize, progress: &mut WipeProgress) -> Result<(), MosesError> {
         // Pass 1: Write zeros
         log::info!("DoD wipe pass 1/3: Writing zeros");
-        Self::zero_wipe(writer, disk_size)?;
+        Self::zero_wipe(writer, disk_size, block_size, progress)?;
         
         // Pass 2: Write ones (0xFF)
         log::info!("DoD wipe pass 2/3: Writing ones");
-        Self::pattern_wipe(writer, disk_size, 0xFF)?;
+        Self::pattern_wipe(writer, disk_size, 0xFF, block_size, progress)?;
         
         // Pass 3: Write random data
         log::info!("DoD wipe pass 3/3: Writing random data");
-        Self::random_wipe(writer, disk_size)?;
+        Self::random_wipe(writer, disk_size, block_size, progress)?;
         
         log::info!("DoD 5220.22-M wipe completed");
         Ok(())
     }
     
     /// Write random data
-    fn random_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
+    fn random_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64, block_size: usize, progress: &mut WipeProgress) -> Result<(), MosesError> {
+        progress.start_pass("writing random data");
         use rand::Rng;
-        const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
         
         writer.seek(SeekFrom::Start(0))
             .map_err(|e| MosesError::Other(format!("Failed to seek to start: {}", e)))?;
         
         let mut rng = rand::thread_rng();
-        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut buffer = vec![0u8; block_size];
         
         let mut written = 0u64;
         while written < disk_size {
             rng.fill(&mut buffer[..]);
-            let to_write = std::cmp::min(CHUNK_SIZE as u64, disk_size - written);
+            let to_write = std::cmp::min(block_size as u64, disk_size - written);
             writer.write_all(&buffer[..to_write as usize])
                 .map_err(|e| MosesError::Other(format!("Failed to write random at {}: {}", written, e)))?;
             written += to_write;
-            
-            if written % (100 * 1024 * 1024) == 0 {
-                log::info!("Random wipe progress: {}MB / {}MB", 
-                    written / (1024 * 1024), 
-                    disk_size / (1024 * 1024));
-            }
+            progress.advance(written, disk_size);
         }
+        progress.finish_pass(disk_size);
         
         log::info!("Random wipe completed");
         Ok(())
     }
     
     /// Write a repeating pattern
-    fn pattern_wipe<W: Write + Seek>(writer: &mut W, disk_size: u64, pattern: u8) -> Result<(), MosesError> {
-        const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
-        let buffer = vec![pattern; CHUNK_SIZE];
+    fn pattern_wipe<W: Write + Seek>(
+        writer: &mut W,
+        disk_size: u64,
+        pattern: u8,
+        block_size: usize,
+        progress: &mut WipeProgress,
+    ) -> Result<(), MosesError> {
+        progress.start_pass(format!("writing 0x{:02X} pattern", pattern));
+        let buffer = vec![pattern; block_size];
         
         writer.seek(SeekFrom::Start(0))
             .map_err(|e| MosesError::Other(format!("Failed to seek to start: {}", e)))?;
         
         let mut written = 0u64;
         while written < disk_size {
-            let to_write = std::cmp::min(CHUNK_SIZE as u64, disk_size - written);
+            let to_write = std::cmp::min(block_size as u64, disk_size - written);
             writer.write_all(&buffer[..to_write as usize])
                 .map_err(|e| MosesError::Other(format!("Failed to write pattern at {}: {}", written, e)))?;
             written += to_write;
+            progress.advance(written, disk_size);
         }
+        progress.finish_pass(disk_size);
         
         Ok(())
     }
 }
 
+/// Progress across all passes of a full-disk wipe
+struct WipeProgress<'a> {
+    tracker: ProgressTracker,
+    on_progress: &'a mut dyn FnMut(&ProgressUpdate),
+    /// Bytes written by earlier passes
+    completed: u64,
+    phase: String,
+}
+
+impl WipeProgress<'_> {
+    /// Report every this many bytes
+    const INTERVAL: u64 = 64 * 1024 * 1024;
+    
+    fn start_pass(&mut self, phase: impl Into<String>) {
+        self.phase = phase.into();
+    }
+    
+    fn advance(&mut self, pass_written: u64, pass_size: u64) {
+        if !pass_written.is_multiple_of(Self::INTERVAL) && pass_written != pass_size {
+            return;
+        }
+        let update = self.tracker.update(&self.phase, self.completed + pass_written);
+        log::info!("{}: {}", update.operation, update.summary());
+        (self.on_progress)(&update);
+    }
+    
+    fn finish_pass(&mut self, pass_size: u64) {
+        self.completed += pass_size;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -311,4 +448,18 @@ mod tests {
         // Check that first MB is zeroed
         assert!(buffer[..1024*1024].iter().all(|&b| b == 0));
     }
+    
+    #[test]
+    fn test_quick_clean_plan_matches_writes() {
+        let size = 4 * 1024 * 1024;
+        let before = vec![0xAB; size];
+        let mut after = before.clone();
+        DiskCleaner::quick_clean(&mut Cursor::new(&mut after), size as u64).unwrap();
+        
+        let writes = DiskCleaner::planned_writes(WipeMethod::Quick, size as u64);
+        super::super::plan::assert_covered(&before, &after, &writes);
+        // The backup GPT at the end of the disk is listed
+        assert!(writes.iter().any(|w| w.offset + w.length == size as u64));
+        assert_eq!(DiskCleaner::planned_writes(WipeMethod::DoD5220, size as u64).len(), 3);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,148 @@
+// Volume contents summary - what a format would destroy, in numbers
+// A device name and size are easy to mix up; "412 files, 38 GB, mostly in Photos" is not.
+// Each readable volume on the device is walked breadth-first and the walk stops after a
+// fixed number of entries, so the dry run of a full disk stays quick. A summary that was
+// cut short says so and its totals are a lower bound.
+use std::collections::HashMap;
+use std::path::Path;
+use moses_core::{ContentSummary, Device, DirectoryUsage, FileUsage};
+use crate::ops::FilesystemOps;
+use crate::walker::{walk_tree, WalkControl, WalkOptions, WalkOrder};
+use super::installations::existing_volumes;
+use super::trash::is_trash_name;
+
+/// Entries visited per volume before the summary is cut short
+pub const SUMMARY_ENTRY_LIMIT: usize = 20_000;
+/// Files listed in `largest_files`
+const LARGEST_FILES: usize = 10;
+/// Top-level directories listed in `directories`
+const TOP_DIRECTORIES: usize = 10;
+/// Bucket for files directly in the root
+const ROOT_BUCKET: &str = "/";
+/// Kernel filesystems mounted under the root of a running system, not part of the volume
+const PSEUDO_DIRECTORIES: &[&str] = &["proc", "sys", "dev", "run"];
+
+/// Summarize every readable volume on the device; unreadable volumes are left out
+pub fn summarize_contents(device: &Device) -> Vec<ContentSummary> {
+    existing_volumes(device, false).into_iter()
+        .map(|mut volume| {
+            let skip = if volume.location == "/" { PSEUDO_DIRECTORIES } else { &[] };
+            let mut summary = summarize(volume.ops.as_mut(), SUMMARY_ENTRY_LIMIT, skip);
+            summary.location = volume.location;
+            summary.filesystem = volume.filesystem;
+            summary
+        })
+        .collect()
+}
+
+/// Walk one volume, visiting at most `limit` entries and skipping the root entries in `skip`
+pub fn summarize(ops: &mut dyn FilesystemOps, limit: usize, skip: &[&str]) -> ContentSummary {
+    let mut summary = ContentSummary::default();
+    let mut buckets: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut largest: Vec<FileUsage> = Vec::new();
+    let options = WalkOptions {
+        order: WalkOrder::BreadthFirst,
+        max_entries: Some(limit as u64),
+        skip_unreadable: true,
+        ..Default::default()
+    };
+
+    let walk = walk_tree(ops, Path::new("/"), &options, |_, entry| {
+        if entry.depth == 1 && skip.contains(&entry.name) {
+            return Ok(WalkControl::SkipChildren);
+        }
+        if entry.attributes.is_symlink {
+            return Ok(WalkControl::Continue);
+        }
+        if entry.is_directory() {
+            buckets.entry(entry.top().to_string()).or_default();
+            return Ok(WalkControl::Continue);
+        }
+
+        let size = entry.attributes.size;
+        summary.total_files += 1;
+        summary.total_bytes += size;
+        let top = if entry.depth == 1 { ROOT_BUCKET } else { entry.top() };
+        let bucket = buckets.entry(top.to_string()).or_default();
+        bucket.0 += 1;
+        bucket.1 += size;
+        largest.push(FileUsage { path: entry.path.to_string_lossy().to_string(), size });
+        if largest.len() > LARGEST_FILES * 4 {
+            keep_largest(&mut largest);
+        }
+        Ok(WalkControl::Continue)
+    });
+    // Unreadable folders are skipped, so the walk itself cannot fail
+    summary.complete = walk.is_ok_and(|walk| !walk.stopped && walk.too_deep.is_empty());
+
+    keep_largest(&mut largest);
+    summary.largest_files = largest;
+    let (trash, mut directories): (Vec<DirectoryUsage>, Vec<DirectoryUsage>) = buckets.into_iter()
+        .map(|(path, (files, bytes))| DirectoryUsage { path, files, bytes })
+        .partition(|usage| is_trash_name(&usage.path));
+    directories.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.files.cmp(&a.files)).then(a.path.cmp(&b.path)));
+    directories.truncate(TOP_DIRECTORIES);
+    summary.directories = directories;
+    summary.trash = trash;
+    summary
+}
+
+fn keep_largest(files: &mut Vec<FileUsage>) {
+    files.sort_by(|a, b| b.size.cmp(&a.size).then(a.path.cmp(&b.path)));
+    files.truncate(LARGEST_FILES);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::ops::HostFolderOps;
+    use crate::test_helpers::create_test_device;
+
+    #[test]
+    fn test_summary_by_top_level_directory() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("Photos/2023")).unwrap();
+        fs::create_dir_all(root.path().join("Empty")).unwrap();
+        fs::write(root.path().join("Photos/2023/a.jpg"), vec![0u8; 3000]).unwrap();
+        fs::write(root.path().join("Photos/b.jpg"), vec![0u8; 2000]).unwrap();
+        fs::write(root.path().join("notes.txt"), vec![0u8; 10]).unwrap();
+
+        let mut device = create_test_device("disk", 1 << 30);
+        device.mount_points = vec![root.path().to_path_buf()];
+        device.filesystem = Some("exfat".to_string());
+        let summaries = summarize_contents(&device);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert!(summary.complete);
+        assert_eq!(summary.filesystem, "exfat");
+        assert_eq!((summary.total_files, summary.total_bytes), (3, 5010));
+        assert_eq!(summary.directories, vec![
+            DirectoryUsage { path: "Photos".to_string(), files: 2, bytes: 5000 },
+            DirectoryUsage { path: "/".to_string(), files: 1, bytes: 10 },
+            DirectoryUsage { path: "Empty".to_string(), files: 0, bytes: 0 },
+        ]);
+        assert_eq!(summary.largest_files[0], FileUsage { path: "/Photos/2023/a.jpg".to_string(), size: 3000 });
+        assert_eq!(summary.largest_files.len(), 3);
+    }
+
+    #[test]
+    fn test_summary_stops_at_the_limit() {
+        let root = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            fs::write(root.path().join(format!("{:02}.bin", i)), vec![0u8; i]).unwrap();
+        }
+        fs::create_dir(root.path().join("proc")).unwrap();
+        fs::write(root.path().join("proc/kcore"), vec![0u8; 100]).unwrap();
+
+        let mut ops = HostFolderOps::new(root.path().to_path_buf()).unwrap();
+        let summary = summarize(&mut ops, 20, &[]);
+        assert!(!summary.complete);
+        assert_eq!(summary.largest_files.len(), LARGEST_FILES);
+
+        let summary = summarize(&mut ops, 1000, PSEUDO_DIRECTORIES);
+        assert!(summary.complete);
+        assert_eq!(summary.total_files, 50);
+        assert_eq!(summary.largest_files[0].size, 49);
+    }
+}
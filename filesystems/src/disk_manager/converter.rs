@@ -3,6 +3,8 @@ use std::io::{Write, Read, Seek, SeekFrom};
 use moses_core::{Device, MosesError};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
+use super::boot_code::{self, BootCodeAction};
+use super::plan::{OperationPlan, PlannedWrite, WriteContent};
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum PartitionStyle {
@@ -11,12 +13,30 @@ pub enum PartitionStyle {
     Uninitialized,
 }
 
+/// Options controlling a partition style conversion
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConvertOptions {
+    /// What to do with the MBR boot code (first 440 bytes)
+    #[serde(default)]
+    pub boot_code: BootCodeAction,
+}
+
 pub struct PartitionStyleConverter;
 
 impl PartitionStyleConverter {
     /// Convert a disk to the specified partition style
     pub fn convert(device: &Device, target_style: PartitionStyle) -> Result<(), MosesError> {
-        log::info!("Converting {} to {:?} partition style", device.name, target_style);
+        Self::convert_with_options(device, target_style, &ConvertOptions::default())
+    }
+    
+    /// Convert a disk to the specified partition style with explicit options
+    pub fn convert_with_options(
+        device: &Device,
+        target_style: PartitionStyle,
+        options: &ConvertOptions,
+    ) -> Result<(), MosesError> {
+        log::info!("Converting {} to {:?} partition style (boot code: {:?})",
+            device.name, target_style, options.boot_code);
         
         // Safety check
         if device.is_system {
@@ -25,10 +45,65 @@ impl PartitionStyleConverter {
             ));
         }
         
+        // Removing the table is a quick clean, which records itself
+        if target_style != PartitionStyle::Uninitialized {
+            super::history::record_before(device, format!("convert to {:?}", target_style));
+        }
+        let result = match target_style {
+            PartitionStyle::MBR => Self::convert_to_mbr(device, options),
+            PartitionStyle::GPT => Self::convert_to_gpt(device, options),
+            PartitionStyle::Uninitialized => Self::make_uninitialized(device, options),
+        };
+        moses_core::FilesystemCache::global().invalidate(device);
+        result
+    }
+    
+    /// Describe what a conversion would write and which partitions it would remove
+    pub fn dry_run(device: &Device, target_style: PartitionStyle, options: &ConvertOptions) -> OperationPlan {
+        let operation = match target_style {
+            PartitionStyle::Uninitialized => "Remove partition table".to_string(),
+            style => format!("Convert to {:?}", style),
+        };
+        let mut plan = OperationPlan::for_device(device, operation, Self::planned_writes(target_style, device.size), options.boot_code);
+        if target_style == PartitionStyle::MBR {
+            let sector_size = crate::lints::logical_sector_size(device).unwrap_or(512);
+            plan.warnings.extend(crate::partitioner::mbr_capacity_warning(device.size, sector_size));
+        }
+        plan
+    }
+    
+    /// Byte ranges written by the conversion, in order
+    pub(crate) fn planned_writes(target_style: PartitionStyle, disk_size: u64) -> Vec<PlannedWrite> {
         match target_style {
-            PartitionStyle::MBR => Self::convert_to_mbr(device),
-            PartitionStyle::GPT => Self::convert_to_gpt(device),
-            PartitionStyle::Uninitialized => Self::make_uninitialized(device),
+            PartitionStyle::MBR => {
+                let mut writes = vec![
+                    PlannedWrite::new("MBR (new disk signature)", 0, 512, WriteContent::Structure),
+                    PlannedWrite::new("Primary GPT header", 512, 512, WriteContent::Zeros),
+                    PlannedWrite::new("Primary GPT partition entries", 1024, 32 * 512, WriteContent::Zeros),
+                ];
+                if disk_size > 33 * 512 {
+                    writes.push(PlannedWrite::new(
+                        "Backup GPT partition entries", disk_size - 33 * 512, 32 * 512, WriteContent::Zeros,
+                    ));
+                }
+                writes
+            }
+            PartitionStyle::GPT => {
+                let backup_lba = (disk_size / 512).saturating_sub(1);
+                vec![
+                    PlannedWrite::new("Protective MBR", 0, 512, WriteContent::Structure),
+                    PlannedWrite::new("Primary GPT header", 512, 512, WriteContent::Structure),
+                    PlannedWrite::new("Primary GPT partition entries (empty)", 1024, 128 * 128, WriteContent::Zeros),
+                    PlannedWrite::new(
+                        "Backup GPT partition entries (empty)", backup_lba.saturating_sub(32) * 512, 128 * 128, WriteContent::Zeros,
+                    ),
+                    PlannedWrite::new("Backup GPT header", backup_lba * 512, 512, WriteContent::Structure),
+                ]
+            }
+            PartitionStyle::Uninitialized => {
+                use super::cleaner::{DiskCleaner, WipeMethod};
+                DiskCleaner::planned_writes(WipeMethod::Quick, disk_size)
+            }
         }
     }
     
@@ -130,23 +205,26 @@ impl PartitionStyleConverter {
     }
     
     /// Convert to MBR partition table
-    fn convert_to_mbr(device: &Device) -> Result<(), MosesError> {
+    fn convert_to_mbr(device: &Device, options: &ConvertOptions) -> Result<(), MosesError> {
         log::info!("Converting {} to MBR partition table", device.name);
         
         #[cfg(target_os = "windows")]
         {
             use std::fs::OpenOptions;
             use std::os::windows::fs::OpenOptionsExt;
-            use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_WRITE};
+            use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE};
             
             let mut file = OpenOptions::new()
+                .read(true)
                 .write(true)
                 .custom_flags(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                .access_mode(GENERIC_WRITE)
+                .access_mode(GENERIC_READ | GENERIC_WRITE)
                 .open(&device.id)
                 .map_err(|e| MosesError::IoError(e))?;
             
+            let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
             Self::write_mbr_structure(&mut file, device.size)?;
+            boot_code::restore_boot_code(&mut file, saved_boot_code)?;
         }
         
         #[cfg(not(target_os = "windows"))]
@@ -154,11 +232,14 @@ impl PartitionStyleConverter {
             use std::fs::OpenOptions;
             
             let mut file = OpenOptions::new()
+                .read(true)
                 .write(true)
                 .open(&device.id)
                 .map_err(|e| MosesError::IoError(e))?;
             
+            let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
             Self::write_mbr_structure(&mut file, device.size)?;
+            boot_code::restore_boot_code(&mut file, saved_boot_code)?;
         }
         
         log::info!("Successfully converted to MBR");
@@ -213,23 +294,26 @@ impl PartitionStyleConverter {
     }
     
     /// Convert to GPT partition table
-    fn convert_to_gpt(device: &Device) -> Result<(), MosesError> {
+    fn convert_to_gpt(device: &Device, options: &ConvertOptions) -> Result<(), MosesError> {
         log::info!("Converting {} to GPT partition table", device.name);
         
         #[cfg(target_os = "windows")]
         {
             use std::fs::OpenOptions;
             use std::os::windows::fs::OpenOptionsExt;
-            use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_WRITE};
+            use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE};
             
             let mut file = OpenOptions::new()
+                .read(true)
                 .write(true)
                 .custom_flags(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                .access_mode(GENERIC_WRITE)
+                .access_mode(GENERIC_READ | GENERIC_WRITE)
                 .open(&device.id)
                 .map_err(|e| MosesError::IoError(e))?;
             
+            let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
             Self::write_gpt_structure(&mut file, device.size)?;
+            boot_code::restore_boot_code(&mut file, saved_boot_code)?;
         }
         
         #[cfg(not(target_os = "windows"))]
@@ -237,11 +321,14 @@ impl PartitionStyleConverter {
             use std::fs::OpenOptions;
             
             let mut file = OpenOptions::new()
+                .read(true)
                 .write(true)
                 .open(&device.id)
                 .map_err(|e| MosesError::IoError(e))?;
             
+            let saved_boot_code = boot_code::capture_boot_code(&mut file, options.boot_code)?;
             Self::write_gpt_structure(&mut file, device.size)?;
+            boot_code::restore_boot_code(&mut file, saved_boot_code)?;
         }
         
         log::info!("Successfully converted to GPT");
@@ -249,7 +336,7 @@ impl PartitionStyleConverter {
     }
     
     /// Write a clean GPT structure
-    fn write_gpt_structure<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
+    pub(crate) fn write_gpt_structure<W: Write + Seek>(writer: &mut W, disk_size: u64) -> Result<(), MosesError> {
         // Create protective MBR
         let mut mbr = vec![0u8; 512];
         
@@ -367,19 +454,21 @@ impl PartitionStyleConverter {
     }
     
     /// Make disk uninitialized (no partition table)
-    fn make_uninitialized(device: &Device) -> Result<(), MosesError> {
+    fn make_uninitialized(device: &Device, options: &ConvertOptions) -> Result<(), MosesError> {
         log::info!("Removing partition table from {}", device.name);
         
         // This is essentially a quick clean - just wipe critical sectors
         // Use the cleaner module for this
         use super::cleaner::{DiskCleaner, CleanOptions, WipeMethod};
         
-        let options = CleanOptions {
+        let clean_options = CleanOptions {
             wipe_method: WipeMethod::Quick,
             zero_entire_disk: false,
+            boot_code: options.boot_code,
+            throttle: Default::default(),
         };
         
-        DiskCleaner::clean(device, &options)
+        DiskCleaner::clean(device, &clean_options)
     }
 }
 
@@ -427,4 +516,59 @@ mod tests {
         // Check revision
         assert_eq!(&buffer[520..524], &[0x00, 0x00, 0x01, 0x00]);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_conversion_plans_match_writes() {
+        let size = 4 * 1024 * 1024u64;
+        let before = vec![0xAB; size as usize];
+        
+        let mut after = before.clone();
+        PartitionStyleConverter::write_mbr_structure(&mut Cursor::new(&mut after), size).unwrap();
+        let writes = PartitionStyleConverter::planned_writes(PartitionStyle::MBR, size);
+        crate::disk_manager::plan::assert_covered(&before, &after, &writes);
+        
+        let mut after = before.clone();
+        PartitionStyleConverter::write_gpt_structure(&mut Cursor::new(&mut after), size).unwrap();
+        let writes = PartitionStyleConverter::planned_writes(PartitionStyle::GPT, size);
+        crate::disk_manager::plan::assert_covered(&before, &after, &writes);
+        assert_eq!(writes.last().unwrap().offset, size - 512);
+    }
+    
+    #[test]
+    fn test_dry_run_reports_removed_partitions() {
+        // An MBR disk with one partition and a FAT boot sector inside it
+        let size = 4 * 1024 * 1024u64;
+        let mut disk = vec![0u8; size as usize];
+        disk[0x1BE + 4] = 0x0C;
+        disk[0x1BE + 8..0x1BE + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[0x1BE + 12..0x1BE + 16].copy_from_slice(&4096u32.to_le_bytes());
+        disk[0x1FE] = 0x55;
+        disk[0x1FF] = 0xAA;
+        
+        let device = Device {
+            id: "test".to_string(),
+            name: "test".to_string(),
+            size,
+            device_type: moses_core::DeviceType::Virtual,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+            filesystem: None,
+        };
+        let writes = PartitionStyleConverter::planned_writes(PartitionStyle::GPT, size);
+        let plan = OperationPlan::inspect(&mut Cursor::new(&disk), &device, "Convert to GPT".to_string(),
+            writes, BootCodeAction::Preserve);
+        
+        assert_eq!(plan.current_style.as_deref(), Some("mbr"));
+        assert_eq!(plan.partitions_removed.len(), 1);
+        assert_eq!(plan.partitions_removed[0].start, 2048 * 512);
+        assert!(plan.signatures_overwritten.iter().any(|s| s.offset == 0x1FE));
+        assert_eq!(plan.writes.last().unwrap().structure, "MBR boot code (restored)");
+        // Nothing was written
+        assert_eq!(disk[512], 0);
+    }
+}
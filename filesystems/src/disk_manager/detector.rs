@@ -1,9 +1,14 @@
 // Conflict Detector - Identify partition table conflicts and issues
 use std::io::{Read, Seek, SeekFrom};
-use moses_core::{Device, MosesError};
+use moses_core::{Device, FormatOptions, MosesError};
 use super::converter::PartitionStyle;
+use super::membership::{self, MultiDeviceVolume};
 use serde::{Serialize, Deserialize};
 
+/// Format option listing the disks the user agreed to break when formatting one
+/// member of a multi-device volume (comma separated device ids, or the group name)
+pub const ACKNOWLEDGE_MEMBERS_OPTION: &str = "acknowledge_multi_device_members";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskConflict {
     pub severity: ConflictSeverity,
@@ -24,6 +29,9 @@ pub struct ConflictReport {
     pub detected_style: PartitionStyle,
     pub conflicts: Vec<DiskConflict>,
     pub recommendations: Vec<String>,
+    /// Striped, spanned or mirrored volumes this disk is a member of
+    #[serde(default)]
+    pub multi_device_volumes: Vec<MultiDeviceVolume>,
 }
 
 pub struct ConflictDetector;
@@ -31,21 +39,26 @@ pub struct ConflictDetector;
 impl ConflictDetector {
     /// Analyze a disk for partition table conflicts
     pub fn analyze(device: &Device) -> Result<ConflictReport, MosesError> {
+        Self::analyze_with_peers(device, &[])
+    }
+    
+    /// Analyze a disk, also listing which of `peers` share a multi-device volume with it
+    pub fn analyze_with_peers(device: &Device, peers: &[Device]) -> Result<ConflictReport, MosesError> {
         log::info!("Analyzing {} for partition table conflicts", device.name);
         
         #[cfg(target_os = "windows")]
         {
-            Self::analyze_windows(device)
+            Self::analyze_windows(device, peers)
         }
         
         #[cfg(not(target_os = "windows"))]
         {
-            Self::analyze_unix(device)
+            Self::analyze_unix(device, peers)
         }
     }
     
     #[cfg(target_os = "windows")]
-    fn analyze_windows(device: &Device) -> Result<ConflictReport, MosesError> {
+    fn analyze_windows(device: &Device, peers: &[Device]) -> Result<ConflictReport, MosesError> {
         use std::fs::OpenOptions;
         use std::os::windows::fs::OpenOptionsExt;
         use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ};
@@ -57,11 +70,11 @@ impl ConflictDetector {
             .open(&device.id)
             .map_err(|e| MosesError::IoError(e))?;
         
-        Self::perform_analysis(&mut file, device)
+        Self::perform_analysis(&mut file, device, peers)
     }
     
     #[cfg(not(target_os = "windows"))]
-    fn analyze_unix(device: &Device) -> Result<ConflictReport, MosesError> {
+    fn analyze_unix(device: &Device, peers: &[Device]) -> Result<ConflictReport, MosesError> {
         use std::fs::OpenOptions;
         
         let mut file = OpenOptions::new()
@@ -69,10 +82,10 @@ impl ConflictDetector {
             .open(&device.id)
             .map_err(|e| MosesError::IoError(e))?;
         
-        Self::perform_analysis(&mut file, device)
+        Self::perform_analysis(&mut file, device, peers)
     }
     
-    fn perform_analysis<R: Read + Seek>(reader: &mut R, device: &Device) -> Result<ConflictReport, MosesError> {
+    fn perform_analysis<R: Read + Seek>(reader: &mut R, device: &Device, peers: &[Device]) -> Result<ConflictReport, MosesError> {
         let mut conflicts = Vec::new();
         let mut recommendations = Vec::new();
         
@@ -221,6 +234,17 @@ impl ConflictDetector {
             recommendations.push("Convert to GPT to use full disk capacity".to_string());
         }
         
+        // 8. Member of a striped/spanned/mirrored volume
+        let memberships = membership::read_memberships(reader, device.size);
+        let multi_device_volumes = membership::match_members(memberships, &device.id, peers);
+        for volume in &multi_device_volumes {
+            conflicts.push(Self::multi_device_conflict(volume));
+            recommendations.push(format!(
+                "Remove this disk from {} '{}' before formatting, or acknowledge the affected disks",
+                volume.kind.description(), volume.name()
+            ));
+        }
+        
         // Add general recommendations based on state
         if conflicts.is_empty() {
             match detected_style {
@@ -250,9 +274,57 @@ impl ConflictDetector {
             detected_style,
             conflicts,
             recommendations,
+            multi_device_volumes,
         })
     }
     
+    fn multi_device_conflict(volume: &MultiDeviceVolume) -> DiskConflict {
+        DiskConflict {
+            severity: ConflictSeverity::Critical,
+            description: format!("Disk is a member of {} '{}' (other members: {})",
+                volume.kind.description(), volume.name(), volume.members_text()),
+            resolution: "Formatting this disk alone destroys the volume on every other member".to_string(),
+        }
+    }
+    
+    /// Refuse to format one member of a multi-device volume unless the format options
+    /// acknowledge every other affected disk (see [`ACKNOWLEDGE_MEMBERS_OPTION`]).
+    ///
+    /// A device we can't read is let through; the format itself will fail or be checked elsewhere.
+    pub fn verify_multi_device_acknowledged(
+        device: &Device,
+        peers: &[Device],
+        options: &FormatOptions,
+    ) -> Result<(), MosesError> {
+        let volumes = match membership::find_multi_device_volumes(device, peers) {
+            Ok(volumes) => volumes,
+            Err(e) => {
+                log::warn!("Could not check multi-device membership of {}: {}", device.name, e);
+                return Ok(());
+            }
+        };
+        
+        let acknowledged: Vec<&str> = options.additional_options
+            .get(ACKNOWLEDGE_MEMBERS_OPTION)
+            .map(|list| list.split(',').map(str::trim).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        
+        let unacknowledged: Vec<String> = volumes.iter()
+            .filter(|v| !v.is_acknowledged(&acknowledged))
+            .map(|v| format!("{} '{}' (other members: {})", v.kind.description(), v.name(), v.members_text()))
+            .collect();
+        
+        if unacknowledged.is_empty() {
+            Ok(())
+        } else {
+            Err(MosesError::UnsafeDevice(format!(
+                "{} is a member of {}. Formatting it breaks the volume on the other disks; \
+                 list them in '{}' to proceed",
+                device.name, unacknowledged.join("; "), ACKNOWLEDGE_MEMBERS_OPTION
+            )))
+        }
+    }
+    
     /// Check if MBR has valid partitions
     fn check_mbr_partitions(mbr: &[u8]) -> bool {
         for i in 0..4 {
@@ -376,4 +448,46 @@ mod tests {
         let empty = vec![0u8; 512];
         assert!(!ConflictDetector::has_filesystem_at_start(&empty));
     }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_multi_device_acknowledgement() {
+        use moses_core::DeviceType;
+        
+        // Two members of the same md array as image files
+        let dir = tempfile::tempdir().unwrap();
+        let devices: Vec<Device> = ["a.img", "b.img"].iter().map(|name| {
+            let path = dir.path().join(name);
+            let mut image = vec![0u8; 64 * 1024];
+            image[4096..4100].copy_from_slice(&0xa92b_4efcu32.to_le_bytes());
+            image[4100..4104].copy_from_slice(&1u32.to_le_bytes());
+            image[4112..4128].copy_from_slice(&[0x5A; 16]);
+            image[4128..4135].copy_from_slice(b"host:md");
+            std::fs::write(&path, image).unwrap();
+            Device {
+                id: path.to_string_lossy().to_string(),
+                name: name.to_string(),
+                size: 64 * 1024,
+                device_type: DeviceType::Virtual,
+                mount_points: vec![],
+                is_removable: true,
+                is_system: false,
+                is_write_protected: false,
+                serial: None,
+                erase_block_size: None,
+                partition_offset: None,
+                filesystem: None,
+            }
+        }).collect();
+        
+        let report = ConflictDetector::analyze_with_peers(&devices[0], &devices).unwrap();
+        assert_eq!(report.multi_device_volumes.len(), 1);
+        assert_eq!(report.multi_device_volumes[0].other_members, vec![devices[1].id.clone()]);
+        assert!(report.conflicts.iter().any(|c| c.severity == ConflictSeverity::Critical));
+        
+        let mut options = FormatOptions::default();
+        assert!(ConflictDetector::verify_multi_device_acknowledged(&devices[0], &devices, &options).is_err());
+        options.additional_options.insert(ACKNOWLEDGE_MEMBERS_OPTION.to_string(), devices[1].id.clone());
+        assert!(ConflictDetector::verify_multi_device_acknowledged(&devices[0], &devices, &options).is_ok());
+    }
 }
\ No newline at end of file
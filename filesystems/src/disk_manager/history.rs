@@ -0,0 +1,166 @@
+// Device history - snapshot the layout before destructive operations and put it back
+// Snapshots keep the raw partition table sectors, so "restore" brings back the partition
+// entries and labels Moses saw, not the data: after a quick clean that is usually enough
+// to get the old volumes back, after a full format of the same region it is not.
+use std::io::{Read, Seek, SeekFrom, Write};
+use moses_core::{Device, DeviceHistory, LayoutSnapshot, MosesError, PartitionInfo};
+use crate::diagnostics::{read_at, read_partition_table, run_detectors};
+
+/// Bytes of partition table saved with a snapshot: MBR, GPT header and 32 sectors of entries
+const TABLE_BYTES: usize = 34 * 512;
+
+/// Filesystem the family detectors recognise at `offset`
+fn detect_at<R: Read + Seek>(reader: &mut R, offset: u64) -> Option<String> {
+    let boot_sector = read_at(reader, offset, 512)?;
+    let ext_superblock = read_at(reader, offset + 1024, 512);
+    run_detectors(&boot_sector, ext_superblock.as_deref())
+}
+
+/// Snapshot the layout from a reader, without labels
+pub fn capture_layout_reader<R: Read + Seek>(reader: &mut R) -> LayoutSnapshot {
+    let (partition_table, layout) = read_partition_table(reader);
+    let partitions = layout.iter()
+        .enumerate()
+        .map(|(i, &(start, size))| PartitionInfo {
+            number: i as u32 + 1,
+            filesystem: detect_at(reader, start),
+            size,
+            start_offset: start,
+        })
+        .collect();
+    let table_sectors = partition_table.as_ref()
+        .and_then(|_| read_at(reader, 0, TABLE_BYTES))
+        .map(hex::encode);
+    LayoutSnapshot {
+        filesystem: if partition_table.is_none() { detect_at(reader, 0) } else { None },
+        label: None,
+        partition_table,
+        partitions,
+        table_sectors,
+    }
+}
+
+/// Snapshot what is on the device now; an unreadable device gives an empty snapshot
+pub fn capture_layout(device: &Device) -> LayoutSnapshot {
+    let mut snapshot = match crate::utils::open_device_with_fallback(device) {
+        Ok(file) => capture_layout_reader(&mut crate::device_reader::AlignedDeviceReader::new(file)),
+        Err(e) => {
+            log::warn!("Could not read {} for its history snapshot: {}", device.id, e);
+            return LayoutSnapshot::default();
+        }
+    };
+    if let Some(filesystem) = &snapshot.filesystem {
+        let mut registry = crate::FilesystemOpsRegistry::new();
+        crate::register_all_filesystems(&mut registry, false);
+        snapshot.label = registry.create_ops(device, Some(filesystem))
+            .and_then(|ops| ops.statfs())
+            .ok()
+            .and_then(|info| info.volume_label)
+            .filter(|label| !label.trim().is_empty());
+    }
+    snapshot
+}
+
+/// Snapshot the device and file it in the global history under `operation`
+pub fn record_before(device: &Device, operation: impl Into<String>) {
+    DeviceHistory::global().record(device, operation, capture_layout(device));
+}
+
+/// Write the partition table saved in `snapshot` back to the device
+pub fn restore_layout(device: &Device, snapshot: &LayoutSnapshot) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Cannot restore the layout of a system disk".to_string()));
+    }
+    device.ensure_writable()?;
+    let sectors = snapshot.table_sectors.as_deref()
+        .ok_or_else(|| MosesError::InvalidInput("This snapshot has no partition table to restore".to_string()))?;
+    let sectors = hex::decode(sectors)
+        .map_err(|e| MosesError::InvalidInput(format!("Corrupt snapshot: {}", e)))?;
+
+    record_before(device, "restore previous layout");
+    log::info!("Restoring {} partition table on {}", snapshot.partition_table.as_deref().unwrap_or("saved"), device.id);
+
+    #[cfg(target_os = "windows")]
+    let mut file = crate::utils::open_device_write(device)?;
+    #[cfg(not(target_os = "windows"))]
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&device.id)?;
+
+    let result = restore_layout_writer(&mut file, device.size, &sectors)
+        .and_then(|_| file.sync_all().map_err(MosesError::IoError));
+    moses_core::FilesystemCache::global().invalidate(device);
+    result
+}
+
+/// Write saved table sectors, rebuilding the backup GPT at the end of the disk if there is one
+pub fn restore_layout_writer<W: Write + Seek>(writer: &mut W, disk_size: u64, sectors: &[u8]) -> Result<(), MosesError> {
+    if sectors.len() != TABLE_BYTES {
+        return Err(MosesError::InvalidInput(format!(
+            "Saved partition table is {} bytes, expected {}", sectors.len(), TABLE_BYTES
+        )));
+    }
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(sectors)?;
+
+    let header = &sectors[512..1024];
+    if &header[0..8] == b"EFI PART" {
+        let header_size = (u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize).clamp(92, 512);
+        let entry_count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as u64;
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as u64;
+        let entry_sectors = (entry_count * entry_size).div_ceil(512);
+        if entry_sectors > 32 {
+            return Err(MosesError::InvalidInput("Saved GPT has more entries than the snapshot holds".to_string()));
+        }
+        // The disk may have been resized since; the backup always lives in the last sector
+        let backup_lba = disk_size / 512 - 1;
+        let entries_lba = backup_lba - entry_sectors;
+
+        let mut backup = header.to_vec();
+        backup[16..20].copy_from_slice(&[0; 4]);
+        backup[24..32].copy_from_slice(&backup_lba.to_le_bytes());
+        backup[32..40].copy_from_slice(&1u64.to_le_bytes());
+        backup[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        let crc = crc32fast::hash(&backup[..header_size]);
+        backup[16..20].copy_from_slice(&crc.to_le_bytes());
+
+        let entries = &sectors[1024..1024 + (entry_sectors * 512) as usize];
+        writer.seek(SeekFrom::Start(entries_lba * 512))?;
+        writer.write_all(entries)?;
+        writer.write_all(&backup)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use super::super::converter::PartitionStyleConverter;
+
+    #[test]
+    fn test_snapshot_and_restore_gpt() {
+        let size = 4 * 1024 * 1024u64;
+        let mut disk = vec![0u8; size as usize];
+        PartitionStyleConverter::write_gpt_structure(&mut Cursor::new(&mut disk), size).unwrap();
+        let original = disk.clone();
+
+        let snapshot = capture_layout_reader(&mut Cursor::new(&disk));
+        assert_eq!(snapshot.partition_table.as_deref(), Some("gpt"));
+        assert!(snapshot.table_sectors.is_some());
+
+        // Wipe both copies of the table, then put them back
+        disk[..TABLE_BYTES].fill(0);
+        let end = disk.len();
+        disk[end - 33 * 512..].fill(0);
+        let sectors = hex::decode(snapshot.table_sectors.unwrap()).unwrap();
+        restore_layout_writer(&mut Cursor::new(&mut disk), size, &sectors).unwrap();
+        assert!(disk == original, "restored table differs from the original");
+    }
+
+    #[test]
+    fn test_snapshot_of_superfloppy() {
+        let snapshot = capture_layout_reader(&mut Cursor::new(vec![0u8; 64 * 1024]));
+        assert_eq!(snapshot, LayoutSnapshot::default());
+        assert_eq!(snapshot.summary(), "blank or unrecognised");
+    }
+}
@@ -0,0 +1,274 @@
+// Operating system detection - look inside the filesystem about to be overwritten
+// A dry run that says "this drive appears to contain a Windows 10 installation" stops the
+// classic wrong-disk mistake before anything is written. The existing volume is opened
+// read-only with our own readers (or through the host when it is mounted) and probed for
+// files only an installed system or a boot loader has. Paths are matched without regard
+// to case, since the marker files live on FAT and NTFS as often as on ext.
+use std::path::{Path, PathBuf};
+use moses_core::Device;
+use serde::{Serialize, Deserialize};
+use crate::ops::{FilesystemOps, FilesystemOpsRegistry, HostFolderOps};
+
+/// Boot loaders recognised by a file at a fixed path
+const BOOT_LOADERS: &[(&str, &str)] = &[
+    ("EFI/Microsoft/Boot/bootmgfw.efi", "Windows Boot Manager"),
+    ("bootmgr", "Windows Boot Manager"),
+    ("EFI/systemd/systemd-bootx64.efi", "systemd-boot"),
+    ("boot/grub/grub.cfg", "GRUB"),
+    ("boot/grub2/grub.cfg", "GRUB"),
+    ("ldlinux.sys", "SYSLINUX"),
+];
+/// EFI applications a distribution installs under EFI/<vendor>/
+const EFI_VENDOR_LOADERS: &[&str] = &["grubx64.efi", "grubaa64.efi", "shimx64.efi", "shimaa64.efi"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemKind {
+    Windows,
+    Linux,
+    MacOs,
+    /// A boot loader without the system it starts, e.g. on an EFI system partition
+    BootLoader,
+}
+
+/// An operating system or boot loader found on a volume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectedSystem {
+    pub kind: SystemKind,
+    /// "Windows 10", "Ubuntu 22.04.3 LTS", "Windows Boot Manager"
+    pub name: String,
+    /// The file that gave it away, relative to the volume's root
+    pub marker: String,
+    /// Where the volume was read: a mount point, or the device itself
+    pub location: String,
+}
+
+impl DetectedSystem {
+    /// Dry-run warning describing what formatting would destroy
+    pub fn warning(&self) -> String {
+        match self.kind {
+            SystemKind::BootLoader => format!(
+                "This drive appears to contain the {} boot loader ({} on {}); a computer that boots from it may no longer start",
+                self.name, self.marker, self.location
+            ),
+            _ => format!(
+                "This drive appears to contain a {} installation ({} on {})",
+                self.name, self.marker, self.location
+            ),
+        }
+    }
+}
+
+/// Every system and boot loader found on the device's mounted volumes and on the
+/// filesystem at its start. Volumes that can't be read are skipped.
+pub fn detect_installations(device: &Device) -> Vec<DetectedSystem> {
+    let mut found: Vec<DetectedSystem> = Vec::new();
+    for mut volume in existing_volumes(device, false) {
+        found.extend(probe(volume.ops.as_mut(), &volume.location));
+    }
+
+    let mut unique: Vec<DetectedSystem> = Vec::new();
+    for system in found {
+        if !unique.iter().any(|s| s.kind == system.kind && s.name == system.name) {
+            unique.push(system);
+        }
+    }
+    unique
+}
+
+/// A volume already on a device, open read-only
+pub(crate) struct ExistingVolume {
+    /// Mount point, or "the <filesystem> volume" when read from the device
+    pub location: String,
+    pub filesystem: String,
+    /// Read through the host's own driver rather than ours
+    pub mounted: bool,
+    pub ops: Box<dyn FilesystemOps>,
+}
+
+/// Open the volumes on the device: the mounted ones through the host, otherwise the
+/// filesystem at the start of the device with our own readers (and writers if `writable`)
+pub(crate) fn existing_volumes(device: &Device, writable: bool) -> Vec<ExistingVolume> {
+    let mut volumes = Vec::new();
+    for mount in &device.mount_points {
+        match HostFolderOps::new(mount.clone()) {
+            Ok(ops) => volumes.push(ExistingVolume {
+                location: mount.display().to_string(),
+                filesystem: device.filesystem.clone().unwrap_or_default(),
+                mounted: true,
+                ops: Box::new(ops),
+            }),
+            Err(e) => log::debug!("Skipping {}: {}", mount.display(), e),
+        }
+    }
+
+    if device.mount_points.is_empty() {
+        let mut registry = FilesystemOpsRegistry::new();
+        crate::register_all_filesystems(&mut registry, writable);
+        match registry.create_ops(device, None) {
+            Ok(ops) => {
+                let filesystem = ops.filesystem_type().to_string();
+                volumes.push(ExistingVolume { location: format!("the {} volume", filesystem), filesystem, mounted: false, ops });
+            }
+            Err(e) => log::debug!("No readable filesystem on {}: {}", device.id, e),
+        }
+    }
+    volumes
+}
+
+/// One dry-run warning per system found
+pub fn installation_warnings(device: &Device) -> Vec<String> {
+    detect_installations(device).iter().map(DetectedSystem::warning).collect()
+}
+
+/// Look for every known marker on one volume
+pub fn probe(ops: &mut dyn FilesystemOps, location: &str) -> Vec<DetectedSystem> {
+    let mut found = Vec::new();
+    let mut add = |kind, name: String, marker: &Path| found.push(DetectedSystem {
+        kind,
+        name,
+        marker: marker.to_string_lossy().trim_start_matches('/').to_string(),
+        location: location.to_string(),
+    });
+
+    if let Some(system32) = find_path(ops, "Windows/System32") {
+        add(SystemKind::Windows, windows_name(ops), &system32);
+    }
+    if let Some((marker, text)) = ["etc/os-release", "usr/lib/os-release"].iter()
+        .find_map(|path| find_path(ops, path).and_then(|p| read_text(ops, &p).map(|text| (p, text))))
+    {
+        add(SystemKind::Linux, os_release_name(&text), &marker);
+    }
+    if let Some(marker) = find_path(ops, "System/Library/CoreServices/SystemVersion.plist") {
+        let version = read_text(ops, &marker).and_then(|plist| plist_string(&plist, "ProductVersion"));
+        add(SystemKind::MacOs, version.map_or("macOS".to_string(), |v| format!("macOS {}", v)), &marker);
+    }
+
+    for (path, name) in BOOT_LOADERS {
+        if let Some(marker) = find_path(ops, path) {
+            add(SystemKind::BootLoader, name.to_string(), &marker);
+        }
+    }
+    if let Some(efi) = find_path(ops, "EFI") {
+        for vendor in ops.readdir(&efi).unwrap_or_default().into_iter().filter(|e| e.attributes.is_directory) {
+            let dir = efi.join(&vendor.name);
+            let entries = ops.readdir(&dir).unwrap_or_default();
+            if let Some(loader) = entries.iter().find(|e| EFI_VENDOR_LOADERS.iter().any(|l| e.name.eq_ignore_ascii_case(l))) {
+                add(SystemKind::BootLoader, format!("GRUB ({})", vendor.name), &dir.join(&loader.name));
+            }
+        }
+    }
+    found
+}
+
+/// Resolve `path` component by component, ignoring case; the path as stored if it exists
+fn find_path(ops: &mut dyn FilesystemOps, path: &str) -> Option<PathBuf> {
+    let mut current = PathBuf::from("/");
+    for component in path.split('/') {
+        let entries = ops.readdir(&current).ok()?;
+        let entry = entries.iter().find(|e| e.name.eq_ignore_ascii_case(component))?;
+        current = current.join(&entry.name);
+    }
+    Some(current)
+}
+
+fn read_text(ops: &mut dyn FilesystemOps, path: &Path) -> Option<String> {
+    let bytes = ops.read(path, 0, 16 * 1024).ok()?;
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Windows release from the servicing stack's version directory ("10.0.19041.1")
+fn windows_name(ops: &mut dyn FilesystemOps) -> String {
+    let Some(dir) = find_path(ops, "Windows/servicing/Version") else {
+        return "Windows".to_string();
+    };
+    ops.readdir(&dir).unwrap_or_default().iter()
+        .filter_map(|e| {
+            let mut parts = e.name.split('.').map(|p| p.parse::<u32>().ok());
+            Some((parts.next()??, parts.next()??, parts.next()??))
+        })
+        .max()
+        .map_or("Windows".to_string(), |version| match version {
+            (10, 0, build) if build >= 22000 => "Windows 11".to_string(),
+            (10, 0, _) => "Windows 10".to_string(),
+            (6, 3, _) => "Windows 8.1".to_string(),
+            (6, 2, _) => "Windows 8".to_string(),
+            (6, 1, _) => "Windows 7".to_string(),
+            (6, 0, _) => "Windows Vista".to_string(),
+            _ => "Windows".to_string(),
+        })
+}
+
+/// PRETTY_NAME, else NAME, from an os-release file
+fn os_release_name(text: &str) -> String {
+    let value = |key: &str| text.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        .map(|v| v.trim().trim_matches('"').trim_matches('\'').to_string())
+        .filter(|v| !v.is_empty());
+    value("PRETTY_NAME").or_else(|| value("NAME")).unwrap_or_else(|| "Linux".to_string())
+}
+
+/// The <string> following <key>`key`</key> in an XML property list
+fn plist_string(plist: &str, key: &str) -> Option<String> {
+    let after = &plist[plist.find(&format!("<key>{}</key>", key))?..];
+    let start = after.find("<string>")? + "<string>".len();
+    let end = after[start..].find("</string>")?;
+    Some(after[start..start + end].trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_helpers::create_test_device;
+
+    #[test]
+    fn test_windows_and_boot_loaders_on_a_mounted_volume() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("WINDOWS/system32")).unwrap();
+        fs::create_dir_all(root.path().join("WINDOWS/Servicing/version/10.0.19041.1")).unwrap();
+        fs::create_dir_all(root.path().join("WINDOWS/Servicing/version/10.0.19045.3570")).unwrap();
+        fs::create_dir_all(root.path().join("EFI/Microsoft/Boot")).unwrap();
+        fs::write(root.path().join("EFI/Microsoft/Boot/bootmgfw.efi"), b"MZ").unwrap();
+        fs::create_dir_all(root.path().join("EFI/ubuntu")).unwrap();
+        fs::write(root.path().join("EFI/ubuntu/shimx64.efi"), b"MZ").unwrap();
+
+        let mut device = create_test_device("disk", 1 << 30);
+        device.mount_points = vec![root.path().to_path_buf()];
+        let found = detect_installations(&device);
+        let names: Vec<&str> = found.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Windows 10", "Windows Boot Manager", "GRUB (ubuntu)"]);
+        assert_eq!(found[0].marker, "WINDOWS/system32");
+
+        let warning = found.iter().find(|s| s.kind == SystemKind::BootLoader).unwrap().warning();
+        assert!(warning.starts_with("This drive appears to contain the Windows Boot Manager"), "{}", warning);
+    }
+
+    #[test]
+    fn test_windows_release_and_linux_name() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("Windows/System32")).unwrap();
+        fs::create_dir_all(root.path().join("Windows/servicing/Version/10.0.22621.2428")).unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/os-release"), "NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\n").unwrap();
+
+        let mut device = create_test_device("disk", 1 << 30);
+        device.mount_points = vec![root.path().to_path_buf()];
+        assert_eq!(installation_warnings(&device), vec![
+            format!("This drive appears to contain a Windows 11 installation (Windows/System32 on {})", root.path().display()),
+            format!("This drive appears to contain a Ubuntu 22.04.3 LTS installation (etc/os-release on {})", root.path().display()),
+        ]);
+
+        assert_eq!(os_release_name("ID=arch\nNAME='Arch Linux'\n"), "Arch Linux");
+        assert_eq!(os_release_name(""), "Linux");
+        let plist = "<dict>\n<key>ProductName</key>\n<string>macOS</string>\n<key>ProductVersion</key>\n<string>14.2</string>\n</dict>";
+        assert_eq!(plist_string(plist, "ProductVersion").as_deref(), Some("14.2"));
+    }
+
+    #[test]
+    fn test_blank_device_has_no_installations() {
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(8 << 20).unwrap();
+        let device = create_test_device(image.path().to_str().unwrap(), 8 << 20);
+        assert!(detect_installations(&device).is_empty());
+    }
+}
@@ -0,0 +1,386 @@
+// Multi-device volume membership - Windows dynamic disks, LVM and Linux RAID
+// A device that belongs to a striped, spanned or mirrored volume can't be
+// formatted on its own without breaking the volume on every other member.
+// Membership is read from the on-disk metadata so it works for any device we
+// can read, and members are matched across devices by their group identifier.
+use std::io::{Read, Seek};
+use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::diagnostics::{read_at, read_partition_table};
+
+const SECTOR_SIZE: u64 = 512;
+
+/// GPT partition type of the Windows LDM metadata partition (5808C8AA-7E8F-42E0-85D2-E1E90434CFB3)
+const LDM_METADATA_GUID: [u8; 16] = [
+    0xAA, 0xC8, 0x08, 0x58, 0x8F, 0x7E, 0xE0, 0x42,
+    0x85, 0xD2, 0xE1, 0xE9, 0x04, 0x34, 0xCF, 0xB3,
+];
+/// MBR partition type of Windows dynamic disks
+const LDM_MBR_TYPE: u8 = 0x42;
+/// LVM metadata area header magic
+const LVM_MDA_MAGIC: &[u8] = b" LVM2 x[5A%r0N*>";
+/// Linux md superblock 1.x magic
+const MD_MAGIC: u32 = 0xa92b_4efc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MultiDeviceKind {
+    /// Windows dynamic disk (striped, spanned, mirrored or RAID-5 volumes)
+    WindowsDynamic,
+    /// LVM physical volume in a volume group
+    LvmVolumeGroup,
+    /// Linux software RAID (md) member
+    LinuxRaid,
+}
+
+impl MultiDeviceKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            MultiDeviceKind::WindowsDynamic => "Windows dynamic disk group",
+            MultiDeviceKind::LvmVolumeGroup => "LVM volume group",
+            MultiDeviceKind::LinuxRaid => "Linux RAID array",
+        }
+    }
+}
+
+/// Metadata found on one device saying it belongs to a multi-device volume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeMembership {
+    pub kind: MultiDeviceKind,
+    /// Identifier shared by every member (disk group GUID, VG UUID, array UUID)
+    pub group_id: String,
+    pub group_name: Option<String>,
+    /// Byte offset of the metadata's partition (0 for the whole device)
+    pub offset: u64,
+}
+
+/// A multi-device volume the device belongs to, with the other members we found
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiDeviceVolume {
+    pub kind: MultiDeviceKind,
+    pub group_id: String,
+    pub group_name: Option<String>,
+    /// Ids of the other enumerated devices in the same group
+    pub other_members: Vec<String>,
+}
+
+impl MultiDeviceVolume {
+    pub fn name(&self) -> &str {
+        self.group_name.as_deref().unwrap_or(&self.group_id)
+    }
+
+    /// Whether `acknowledged` (device ids or the group name/id) covers every affected disk.
+    ///
+    /// If no other member was found the group itself must be named.
+    pub fn is_acknowledged(&self, acknowledged: &[&str]) -> bool {
+        if self.other_members.is_empty() {
+            acknowledged.iter().any(|a| *a == self.group_id || Some(*a) == self.group_name.as_deref())
+        } else {
+            self.other_members.iter().all(|m| acknowledged.contains(&m.as_str()))
+        }
+    }
+
+    /// Human readable list of the other affected disks
+    pub fn members_text(&self) -> String {
+        if self.other_members.is_empty() {
+            "other members not found among enumerated devices".to_string()
+        } else {
+            self.other_members.join(", ")
+        }
+    }
+}
+
+/// Read every multi-device membership recorded on a device image or raw device
+pub fn read_memberships<R: Read + Seek>(reader: &mut R, size: u64) -> Vec<VolumeMembership> {
+    let (_, partitions) = read_partition_table(reader);
+    let mut starts = vec![0u64];
+    starts.extend(partitions.iter().map(|&(start, _)| start));
+
+    let mut found = Vec::new();
+    if let Some(ldm) = read_ldm(reader) {
+        found.push(ldm);
+    }
+    for (i, &start) in starts.iter().enumerate() {
+        let len = if i == 0 { size } else { partitions[i - 1].1.min(size.saturating_sub(start)) };
+        if let Some(lvm) = read_lvm(reader, start) {
+            found.push(lvm);
+        }
+        if let Some(md) = read_md(reader, start, len) {
+            found.push(md);
+        }
+    }
+
+    found.dedup_by(|a, b| a.kind == b.kind && a.group_id == b.group_id);
+    found
+}
+
+/// Read the memberships of a device
+pub fn device_memberships(device: &Device) -> Result<Vec<VolumeMembership>, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    Ok(read_memberships(&mut reader, device.size))
+}
+
+/// Group a device's memberships with the peers that share them.
+///
+/// Peers that can't be read are skipped; the device itself is ignored if present in `peers`.
+pub fn find_multi_device_volumes(device: &Device, peers: &[Device]) -> Result<Vec<MultiDeviceVolume>, MosesError> {
+    Ok(match_members(device_memberships(device)?, &device.id, peers))
+}
+
+/// Find the peers sharing each membership read from the device `device_id`
+pub fn match_members(memberships: Vec<VolumeMembership>, device_id: &str, peers: &[Device]) -> Vec<MultiDeviceVolume> {
+    if memberships.is_empty() {
+        return Vec::new();
+    }
+
+    let peer_memberships: Vec<(&Device, Vec<VolumeMembership>)> = peers.iter()
+        .filter(|p| p.id != device_id)
+        .filter_map(|p| match device_memberships(p) {
+            Ok(m) => Some((p, m)),
+            Err(e) => {
+                log::debug!("Skipping {} for membership check: {}", p.id, e);
+                None
+            }
+        })
+        .collect();
+    group_members(memberships, device_id, &peer_memberships)
+}
+
+/// Find the peers sharing each membership, from memberships already read off every peer
+pub fn group_members(
+    memberships: Vec<VolumeMembership>,
+    device_id: &str,
+    peer_memberships: &[(&Device, Vec<VolumeMembership>)],
+) -> Vec<MultiDeviceVolume> {
+    memberships.into_iter()
+        .map(|m| MultiDeviceVolume {
+            other_members: peer_memberships.iter()
+                .filter(|(p, _)| p.id != device_id)
+                .filter(|(_, pm)| pm.iter().any(|o| o.kind == m.kind && o.group_id == m.group_id))
+                .map(|(p, _)| p.id.clone())
+                .collect(),
+            kind: m.kind,
+            group_id: m.group_id,
+            group_name: m.group_name,
+        })
+        .collect()
+}
+
+/// Trim a fixed-size, NUL padded ASCII field
+fn ascii_field(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+/// Windows LDM: the PRIVHEAD sector carries the disk group GUID and name
+fn read_ldm<R: Read + Seek>(reader: &mut R) -> Option<VolumeMembership> {
+    let mbr = read_at(reader, 0, SECTOR_SIZE as usize)?;
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return None;
+    }
+
+    let privhead_lba = if (0..4).any(|i| mbr[446 + i * 16 + 4] == LDM_MBR_TYPE) {
+        // MBR dynamic disks keep the PRIVHEAD in the gap before the first partition
+        6
+    } else if (0..4).any(|i| mbr[446 + i * 16 + 4] == 0xEE) {
+        // GPT dynamic disks keep it in the last sector of the LDM metadata partition
+        ldm_metadata_last_lba(reader)?
+    } else {
+        return None;
+    };
+
+    let privhead = read_at(reader, privhead_lba * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+    if &privhead[0..8] != b"PRIVHEAD" {
+        return None;
+    }
+    let group_id = ascii_field(&privhead[0xB0..0xF0]);
+    if group_id.is_empty() {
+        return None;
+    }
+    let group_name = ascii_field(&privhead[0xF0..0x10F]);
+
+    Some(VolumeMembership {
+        kind: MultiDeviceKind::WindowsDynamic,
+        group_id,
+        group_name: (!group_name.is_empty()).then_some(group_name),
+        offset: 0,
+    })
+}
+
+fn ldm_metadata_last_lba<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let header = read_at(reader, SECTOR_SIZE, SECTOR_SIZE as usize)?;
+    if &header[0..8] != b"EFI PART" {
+        return None;
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().ok()?);
+    let num_entries = u32::from_le_bytes(header[80..84].try_into().ok()?).min(128) as usize;
+    let entry_size = (u32::from_le_bytes(header[84..88].try_into().ok()?) as usize).max(128);
+    let table = read_at(reader, entries_lba * SECTOR_SIZE, num_entries * entry_size)?;
+
+    table.chunks_exact(entry_size)
+        .find(|entry| entry[0..16] == LDM_METADATA_GUID)
+        .map(|entry| u64::from_le_bytes(entry[40..48].try_into().unwrap()))
+}
+
+/// LVM2: label in one of the first four sectors, pointing at the PV header and metadata area
+fn read_lvm<R: Read + Seek>(reader: &mut R, start: u64) -> Option<VolumeMembership> {
+    let (label_sector, label) = (0..4u64).find_map(|s| {
+        let sector = read_at(reader, start + s * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        (&sector[0..8] == b"LABELONE" && &sector[24..32] == b"LVM2 001").then_some((s, sector))
+    })?;
+
+    // PV header: uuid, device size, then zero-terminated data and metadata area lists
+    let pv_offset = u32::from_le_bytes(label[20..24].try_into().ok()?) as usize;
+    let pv = label.get(pv_offset..)?;
+    let mut pos = 40;
+    let mut lists = 0;
+    let mut mda_offset = None;
+    while lists < 2 && pos + 16 <= pv.len() {
+        let offset = u64::from_le_bytes(pv[pos..pos + 8].try_into().ok()?);
+        pos += 16;
+        if offset == 0 {
+            lists += 1;
+        } else if lists == 1 && mda_offset.is_none() {
+            mda_offset = Some(offset);
+        }
+    }
+    let pv_uuid = ascii_field(&pv[0..32]);
+
+    // An orphan PV has no VG metadata yet and isn't part of anything
+    let (group_name, group_id) = read_lvm_metadata(reader, start + mda_offset?)?;
+    log::debug!("LVM PV {} at sector {} belongs to VG {}", pv_uuid, label_sector, group_name);
+
+    Some(VolumeMembership {
+        kind: MultiDeviceKind::LvmVolumeGroup,
+        group_id,
+        group_name: Some(group_name),
+        offset: start,
+    })
+}
+
+/// Returns the VG name and UUID from the current metadata text
+fn read_lvm_metadata<R: Read + Seek>(reader: &mut R, mda_start: u64) -> Option<(String, String)> {
+    let header = read_at(reader, mda_start, SECTOR_SIZE as usize)?;
+    if &header[4..20] != LVM_MDA_MAGIC {
+        return None;
+    }
+    let text_offset = u64::from_le_bytes(header[40..48].try_into().ok()?);
+    let text_size = u64::from_le_bytes(header[48..56].try_into().ok()?);
+    if text_offset == 0 || text_size == 0 {
+        return None;
+    }
+    let text = read_at(reader, mda_start + text_offset, text_size.min(64 * 1024) as usize)?;
+    let text = String::from_utf8_lossy(&text);
+
+    let name = text.split_whitespace().next()?.to_string();
+    let id = text.lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("id = \""))
+        .and_then(|rest| rest.split('"').next())
+        .map(str::to_string)
+        .unwrap_or_else(|| name.clone());
+    Some((name, id))
+}
+
+/// Linux md superblock 1.1 (start), 1.2 (4 KiB in) or 1.0 (near the end)
+fn read_md<R: Read + Seek>(reader: &mut R, start: u64, len: u64) -> Option<VolumeMembership> {
+    let mut locations = vec![start, start + 4096];
+    if len >= 16 * 1024 {
+        locations.push(start + ((len - 8192) & !4095));
+    }
+
+    locations.into_iter().find_map(|offset| {
+        let sb = read_at(reader, offset, 256)?;
+        let magic = u32::from_le_bytes(sb[0..4].try_into().ok()?);
+        let major = u32::from_le_bytes(sb[4..8].try_into().ok()?);
+        if magic != MD_MAGIC || major != 1 {
+            return None;
+        }
+        let uuid: String = sb[16..32].iter().map(|b| format!("{:02x}", b)).collect();
+        let name = ascii_field(&sb[32..64]);
+        Some(VolumeMembership {
+            kind: MultiDeviceKind::LinuxRaid,
+            group_id: uuid,
+            group_name: (!name.is_empty()).then_some(name),
+            offset: start,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn ldm_disk(group: &str) -> Vec<u8> {
+        let mut disk = vec![0u8; 64 * 1024];
+        disk[446 + 4] = LDM_MBR_TYPE;
+        disk[446 + 8..446 + 12].copy_from_slice(&63u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&64u32.to_le_bytes());
+        disk[510] = 0x55;
+        disk[511] = 0xAA;
+        let ph = 6 * 512;
+        disk[ph..ph + 8].copy_from_slice(b"PRIVHEAD");
+        disk[ph + 0xB0..ph + 0xB0 + group.len()].copy_from_slice(group.as_bytes());
+        disk[ph + 0xF0..ph + 0xF6].copy_from_slice(b"HostDg");
+        disk
+    }
+
+    #[test]
+    fn test_ldm_membership() {
+        let group = "1b77da20-c717-11d0-a5be-00a0c91db73c";
+        let disk = ldm_disk(group);
+        let found = read_memberships(&mut Cursor::new(&disk), disk.len() as u64);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, MultiDeviceKind::WindowsDynamic);
+        assert_eq!(found[0].group_id, group);
+        assert_eq!(found[0].group_name.as_deref(), Some("HostDg"));
+    }
+
+    #[test]
+    fn test_lvm_membership() {
+        let mut disk = vec![0u8; 64 * 1024];
+        // Label in sector 1, PV header right after the label header
+        let label = 512;
+        disk[label..label + 8].copy_from_slice(b"LABELONE");
+        disk[label + 8..label + 16].copy_from_slice(&1u64.to_le_bytes());
+        disk[label + 20..label + 24].copy_from_slice(&32u32.to_le_bytes());
+        disk[label + 24..label + 32].copy_from_slice(b"LVM2 001");
+        let pv = label + 32;
+        disk[pv..pv + 32].copy_from_slice(b"abcdefghijklmnopqrstuvwxyz012345");
+        // Data area 1 MiB in, then terminator; metadata area at 4 KiB, then terminator
+        disk[pv + 40..pv + 48].copy_from_slice(&(1024u64 * 1024).to_le_bytes());
+        disk[pv + 72..pv + 80].copy_from_slice(&4096u64.to_le_bytes());
+        disk[pv + 80..pv + 88].copy_from_slice(&(1024u64 * 1024 - 4096).to_le_bytes());
+
+        let mda = 4096;
+        disk[mda + 4..mda + 20].copy_from_slice(LVM_MDA_MAGIC);
+        let text = b"vg_data {\nid = \"Xq3dC1-abcd-efgh\"\nseqno = 3\n}\n";
+        disk[mda + 40..mda + 48].copy_from_slice(&512u64.to_le_bytes());
+        disk[mda + 48..mda + 56].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        disk[mda + 512..mda + 512 + text.len()].copy_from_slice(text);
+
+        let found = read_memberships(&mut Cursor::new(&disk), disk.len() as u64);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, MultiDeviceKind::LvmVolumeGroup);
+        assert_eq!(found[0].group_id, "Xq3dC1-abcd-efgh");
+        assert_eq!(found[0].group_name.as_deref(), Some("vg_data"));
+    }
+
+    #[test]
+    fn test_md_membership_and_plain_disk() {
+        let mut disk = vec![0u8; 64 * 1024];
+        assert!(read_memberships(&mut Cursor::new(&disk), disk.len() as u64).is_empty());
+
+        let sb = 4096;
+        disk[sb..sb + 4].copy_from_slice(&MD_MAGIC.to_le_bytes());
+        disk[sb + 4..sb + 8].copy_from_slice(&1u32.to_le_bytes());
+        disk[sb + 16..sb + 32].copy_from_slice(&[0x11; 16]);
+        disk[sb + 32..sb + 39].copy_from_slice(b"host:md");
+        let found = read_memberships(&mut Cursor::new(&disk), disk.len() as u64);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, MultiDeviceKind::LinuxRaid);
+        assert_eq!(found[0].group_id, "11".repeat(16));
+        assert_eq!(found[0].group_name.as_deref(), Some("host:md"));
+    }
+}
@@ -1,13 +1,37 @@
 // Disk Management Module - Clean, Convert, and Prepare operations
 // These are lower-level than formatting - they prepare disks for formatting
 
+pub mod boot_code;
+pub mod boot_rescue;
 pub mod cleaner;
+pub mod contents;
 pub mod converter;
 pub mod detector;
+pub mod history;
+pub mod installations;
+pub mod membership;
+pub mod plan;
+pub mod risk;
+pub mod seeding;
+pub mod selective;
+pub mod table_rebuild;
+pub mod trash;
+pub mod wipefs;
 
+pub use boot_code::BootCodeAction;
+pub use boot_rescue::{BootBackup, BootRegionKind};
 pub use cleaner::{DiskCleaner, CleanOptions, WipeMethod};
-pub use converter::{PartitionStyleConverter, PartitionStyle};
-pub use detector::{ConflictDetector, DiskConflict, ConflictSeverity, ConflictReport};
+pub use converter::{PartitionStyleConverter, PartitionStyle, ConvertOptions};
+pub use detector::{ConflictDetector, DiskConflict, ConflictSeverity, ConflictReport, ACKNOWLEDGE_MEMBERS_OPTION};
+pub use installations::{DetectedSystem, SystemKind};
+pub use membership::{MultiDeviceKind, MultiDeviceVolume, VolumeMembership};
+pub use plan::{plan_format, OperationPlan, PlannedWrite, PlannedPartition, WriteContent};
+pub use risk::{DeviceRisk, RiskFactor, RiskReason};
+pub use seeding::SeedStep;
+pub use selective::{RestoreTarget, SelectiveFormatCheck, SelectiveFormatReport, SelectiveFormatRequest};
+pub use table_rebuild::FoundPartition;
+pub use trash::{PurgeReport, TrashReport};
+pub use wipefs::{SignatureWiper, FoundSignature};
 
 /// High-level disk preparation API
 pub struct DiskManager;
@@ -21,6 +45,12 @@ impl DiskManager {
     ) -> Result<PreparationReport, moses_core::MosesError> {
         let mut report = PreparationReport::default();
         
+        // An MBR leaves everything past 2^32 sectors unreachable; say how much before writing one
+        if target_style == PartitionStyle::MBR {
+            let sector_size = crate::lints::logical_sector_size(device).unwrap_or(512);
+            report.warnings.extend(crate::partitioner::mbr_capacity_warning(device.size, sector_size));
+        }
+        
         // 1. Detect current state and conflicts
         let conflicts = ConflictDetector::analyze(device)?;
         report.initial_state = conflicts.current_state.clone();
@@ -31,6 +61,8 @@ impl DiskManager {
             let clean_options = CleanOptions {
                 wipe_method: WipeMethod::Quick,
                 zero_entire_disk: false,
+                boot_code: BootCodeAction::Discard,
+                throttle: Default::default(),
             };
             
             DiskCleaner::clean(device, &clean_options)?;
@@ -52,6 +84,8 @@ impl DiskManager {
         let options = CleanOptions {
             wipe_method: WipeMethod::Quick,
             zero_entire_disk: false,
+            boot_code: BootCodeAction::Discard,
+            throttle: Default::default(),
         };
         DiskCleaner::clean(device, &options)
     }
@@ -61,6 +95,8 @@ impl DiskManager {
         let options = CleanOptions {
             wipe_method: WipeMethod::DoD5220,
             zero_entire_disk: true,
+            boot_code: BootCodeAction::Discard,
+            throttle: Default::default(),
         };
         DiskCleaner::clean(device, &options)
     }
@@ -73,4 +109,6 @@ pub struct PreparationReport {
     pub conflicts_found: Vec<DiskConflict>,
     pub cleaned: bool,
     pub final_style: Option<PartitionStyle>,
+    /// Problems the preparation did not stop for, e.g. space an MBR cannot reach
+    pub warnings: Vec<String>,
 }
\ No newline at end of file
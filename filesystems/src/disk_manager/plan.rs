@@ -0,0 +1,297 @@
+// Dry-run plans for disk_manager operations
+// Clean and convert are built from the same byte ranges their writers use, so the GUI
+// confirmation screen lists exactly what will be overwritten before anything is.
+// Formats get a step plan instead: the dismounts, table write, format and follow-up work
+// in the order they run, so the same screen can say what happens and how long it takes.
+use std::io::{Read, Seek};
+use std::time::Duration;
+use moses_core::{Device, FormatPreset, PlannedStep, PostOperationAction, SimulationReport, StepKind};
+use serde::{Serialize, Deserialize};
+use crate::partitioner::PartitionTableType;
+use super::boot_code::{BootCodeAction, BOOT_CODE_SIZE};
+use super::seeding::SeedStep;
+use super::wipefs::{FoundSignature, SignatureWiper};
+
+/// What a planned write puts on the disk
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WriteContent {
+    Zeros,
+    /// A repeated byte (DoD pass 2)
+    Pattern(u8),
+    Random,
+    /// A freshly generated on-disk structure (MBR, GPT header, boot code)
+    Structure,
+}
+
+/// One byte range the operation would write
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedWrite {
+    /// Human-readable name, e.g. "Primary GPT header"
+    pub structure: String,
+    pub offset: u64,
+    pub length: u64,
+    pub content: WriteContent,
+}
+
+impl PlannedWrite {
+    pub(crate) fn new(structure: &str, offset: u64, length: u64, content: WriteContent) -> Self {
+        Self { structure: structure.to_string(), offset, length, content }
+    }
+
+    /// First sector touched, in 512-byte units
+    pub fn first_sector(&self) -> u64 {
+        self.offset / 512
+    }
+
+    /// Number of 512-byte sectors touched
+    pub fn sector_count(&self) -> u64 {
+        (self.offset + self.length).div_ceil(512) - self.first_sector()
+    }
+
+    fn contains(&self, offset: u64) -> bool {
+        offset >= self.offset && offset < self.offset + self.length
+    }
+}
+
+/// A partition in the current table that the operation would remove
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedPartition {
+    pub number: u32,
+    pub start: u64,
+    pub size: u64,
+}
+
+/// Everything a clean or partition style conversion would modify, without writing anything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationPlan {
+    pub device: Device,
+    /// e.g. "Clean (Quick)" or "Convert to GPT"
+    pub operation: String,
+    /// Partition table found on the disk ("mbr", "gpt") or None if there is none
+    pub current_style: Option<String>,
+    /// Writes in the order they happen; full-disk wipes list one write per pass
+    pub writes: Vec<PlannedWrite>,
+    pub partitions_removed: Vec<PlannedPartition>,
+    /// Filesystem and partition table signatures inside the written ranges
+    pub signatures_overwritten: Vec<FoundSignature>,
+    pub boot_code: BootCodeAction,
+    pub bytes_written: u64,
+    pub will_erase_data: bool,
+    pub warnings: Vec<String>,
+}
+
+impl OperationPlan {
+    fn new(device: &Device, operation: String, mut writes: Vec<PlannedWrite>, boot_code: BootCodeAction) -> Self {
+        writes.extend(boot_code_write(boot_code));
+        Self {
+            device: device.clone(),
+            operation,
+            current_style: None,
+            bytes_written: writes.iter().map(|write| write.length).sum(),
+            writes,
+            partitions_removed: Vec::new(),
+            signatures_overwritten: Vec::new(),
+            boot_code,
+            will_erase_data: true,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Build a plan for `writes`, reading the current layout from `reader` to see what they destroy
+    pub(crate) fn inspect<R: Read + Seek>(
+        reader: &mut R,
+        device: &Device,
+        operation: String,
+        writes: Vec<PlannedWrite>,
+        boot_code: BootCodeAction,
+    ) -> Self {
+        let mut plan = Self::new(device, operation, writes, boot_code);
+        let (style, partitions) = crate::diagnostics::read_partition_table(reader);
+        plan.current_style = style;
+        plan.partitions_removed = partitions.into_iter()
+            .enumerate()
+            .map(|(i, (start, size))| PlannedPartition { number: i as u32 + 1, start, size })
+            .collect();
+        plan.signatures_overwritten = SignatureWiper::scan_reader(reader, device.size)
+            .into_iter()
+            .filter(|signature| plan.writes.iter().any(|write| write.contains(signature.offset)))
+            .collect();
+        if !plan.partitions_removed.is_empty() {
+            plan.warnings.push(format!("{} partition(s) will be removed", plan.partitions_removed.len()));
+        }
+        plan
+    }
+
+    /// Open the device read-only and build the plan; if it cannot be read only the writes are listed
+    pub(crate) fn for_device(
+        device: &Device,
+        operation: String,
+        writes: Vec<PlannedWrite>,
+        boot_code: BootCodeAction,
+    ) -> Self {
+        let opened = crate::utils::open_device_with_fallback(device)
+            .map(crate::device_reader::AlignedDeviceReader::new);
+        let mut plan = match opened {
+            Ok(mut reader) => Self::inspect(&mut reader, device, operation, writes, boot_code),
+            Err(e) => {
+                let mut plan = Self::new(device, operation, writes, boot_code);
+                plan.warnings.push(format!("Current partition layout could not be read: {}", e));
+                plan
+            }
+        };
+        if device.is_system {
+            plan.warnings.push("This is a system disk; the operation will be refused".to_string());
+        }
+        plan
+    }
+}
+
+/// Time allowed for the user to answer the administrator prompt
+const ELEVATION_TIME: Duration = Duration::from_secs(10);
+
+/// Time to dismount and lock one volume
+const DISMOUNT_TIME: Duration = Duration::from_secs(2);
+
+/// Time for small fixed writes and reads: a partition table, signature cleanup, read-back
+const SHORT_STEP_TIME: Duration = Duration::from_secs(1);
+
+/// Time to flush and release or spin down the device afterwards
+const POST_ACTION_TIME: Duration = Duration::from_secs(3);
+
+/// Fill in the step plan of a format simulation from its device, options and estimate
+///
+/// `requires_elevation` says whether an administrator prompt comes first; the caller knows
+/// whether the work runs in-process or in an elevated helper that is not running yet.
+pub fn plan_format(report: &mut SimulationReport, requires_elevation: bool) {
+    let device = &report.device;
+    let options = &report.options;
+    let filesystem = options.filesystem_type.to_lowercase();
+    let step = |kind, description: String, estimated_time| PlannedStep { kind, description, estimated_time };
+    let mut steps = Vec::new();
+
+    if requires_elevation {
+        steps.push(step(StepKind::Elevate, "Ask for administrator rights".to_string(), ELEVATION_TIME));
+    }
+    let locked_volumes: Vec<String> = device.mount_points.iter().map(|mount| mount.display().to_string()).collect();
+    for volume in &locked_volumes {
+        steps.push(step(StepKind::Dismount, format!("Dismount and lock {}", volume), DISMOUNT_TIME));
+    }
+    if let Some(table) = partition_table_written(device, options, &filesystem) {
+        steps.push(step(
+            StepKind::PartitionTable,
+            format!("Replace the partition table with {} holding one {} partition", match table {
+                PartitionTableType::MBR => "an MBR",
+                PartitionTableType::GPT => "a GPT",
+            }, filesystem),
+            SHORT_STEP_TIME,
+        ));
+    }
+    steps.push(step(
+        StepKind::Format,
+        format!("{} format as {}", if options.quick_format { "Quick" } else { "Full" }, filesystem),
+        report.estimated_time,
+    ));
+    steps.push(step(StepKind::Cleanup, "Erase leftover signatures of the old filesystem".to_string(), SHORT_STEP_TIME));
+    // Only debug builds wrap the formatters in postcondition checks
+    if cfg!(debug_assertions) {
+        steps.push(step(StepKind::Verify, "Read the new volume back and check it".to_string(), SHORT_STEP_TIME));
+    }
+    for seed_step in SeedStep::from_options(options).unwrap_or_default().into_iter().filter(SeedStep::writes_files) {
+        steps.push(step(StepKind::Seed, seed_step.describe().to_string(), SHORT_STEP_TIME));
+    }
+    if let Ok(Some(action)) = PostOperationAction::from_options(options) {
+        steps.push(step(StepKind::PostAction, format!("{} the device", action.as_str().replace('_', " ")), POST_ACTION_TIME));
+    }
+
+    report.steps = steps;
+    report.requires_elevation = requires_elevation;
+    report.locked_volumes = locked_volumes;
+}
+
+/// The partition table the formatter writes first, if any: FAT16 and FAT32 write one when
+/// asked to, and FAT32 and exFAT whenever they lay out an SD card. Only FAT32 without the
+/// SD layout or a DOS geometry honours a request for GPT; the others always write an MBR.
+pub(crate) fn partition_table_written(
+    device: &Device,
+    options: &moses_core::FormatOptions,
+    filesystem: &str,
+) -> Option<PartitionTableType> {
+    let requested = options.additional_options.get("create_partition_table").is_some_and(|value| value == "true");
+    let sd_layout = FormatPreset::from_options(options).is_ok_and(|preset| preset.uses_sd_layout(device));
+    if sd_layout && matches!(filesystem, "fat32" | "exfat") {
+        return Some(PartitionTableType::MBR);
+    }
+    let dos_geometry = options.additional_options.contains_key(crate::families::fat::common::dos_geometry::GEOMETRY_OPTION);
+    match filesystem {
+        "fat32" if requested && !dos_geometry => Some(PartitionTableType::from_options(options).unwrap_or(PartitionTableType::MBR)),
+        "fat16" | "fat32" if requested => Some(PartitionTableType::MBR),
+        _ => None,
+    }
+}
+
+/// The boot code write that follows the operation, if any
+fn boot_code_write(action: BootCodeAction) -> Option<PlannedWrite> {
+    match action {
+        BootCodeAction::Discard => None,
+        BootCodeAction::Preserve => Some(PlannedWrite::new(
+            "MBR boot code (restored)", 0, BOOT_CODE_SIZE as u64, WriteContent::Structure,
+        )),
+        BootCodeAction::InstallStandard => Some(PlannedWrite::new(
+            "MBR boot code (standard stub)", 0, BOOT_CODE_SIZE as u64, WriteContent::Structure,
+        )),
+    }
+}
+
+/// Assert that every byte changed between `before` and `after` lies inside a planned write
+#[cfg(test)]
+pub(crate) fn assert_covered(before: &[u8], after: &[u8], writes: &[PlannedWrite]) {
+    for (offset, (a, b)) in before.iter().zip(after).enumerate() {
+        if a != b {
+            assert!(
+                writes.iter().any(|write| write.contains(offset as u64)),
+                "byte {} changed but no planned write covers it", offset
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use moses_core::{FilesystemFormatter, FormatOptions};
+    use crate::test_helpers::create_test_device;
+
+    #[tokio::test]
+    async fn test_format_plan_lists_steps_in_order() {
+        let mut device = create_test_device("/dev/null", 128 * 1024 * 1024);
+        device.mount_points = vec!["/media/card".into()];
+        let options = FormatOptions {
+            filesystem_type: "fat32".to_string(),
+            quick_format: true,
+            additional_options: HashMap::from([
+                ("create_partition_table".to_string(), "true".to_string()),
+                (PostOperationAction::OPTION_KEY.to_string(), "eject".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let formatter = crate::builtin_registry().get_formatter("fat32").unwrap();
+        let mut report = formatter.dry_run(&device, &options).await.unwrap();
+        plan_format(&mut report, true);
+
+        let kinds: Vec<StepKind> = report.steps.iter().map(|step| step.kind).collect();
+        let mut expected = vec![StepKind::Elevate, StepKind::Dismount, StepKind::PartitionTable, StepKind::Format, StepKind::Cleanup];
+        if cfg!(debug_assertions) {
+            expected.push(StepKind::Verify);
+        }
+        expected.push(StepKind::PostAction);
+        assert_eq!(kinds, expected);
+        assert_eq!(report.locked_volumes, vec!["/media/card"]);
+        assert!(report.requires_elevation);
+        assert!(report.total_time() > report.estimated_time);
+
+        let mut whole_disk = formatter.dry_run(&device, &FormatOptions { filesystem_type: "fat32".to_string(), ..Default::default() }).await.unwrap();
+        plan_format(&mut whole_disk, false);
+        assert!(!whole_disk.steps.iter().any(|step| matches!(step.kind, StepKind::Elevate | StepKind::PartitionTable)));
+    }
+}
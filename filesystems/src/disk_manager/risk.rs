@@ -0,0 +1,413 @@
+// Device risk assessment - how much damage formatting a device would do
+// `is_system` is a single bit; the GUI also needs to know why a target is dangerous so it
+// can sort the device list, flag entries and ask for a second confirmation on the worst of
+// them. Every factor found adds its weight to the score and carries a reason the GUI can
+// show as is. Content checks read the device; when it can't be read (no elevation) only
+// what the platform reported is scored, and `content_checked` says so.
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use moses_core::{Device, RiskLevel};
+use serde::{Serialize, Deserialize};
+use crate::diagnostics::{read_at, read_partition_table};
+use super::installations::{self, SystemKind};
+use super::membership::{self, VolumeMembership};
+
+/// Writes newer than this count as recent activity
+pub const RECENT_ACTIVITY_SECS: u64 = 7 * 24 * 60 * 60;
+/// Mounted directory entries looked at before giving up on finding recent activity
+const ACTIVITY_SCAN_LIMIT: usize = 2000;
+
+/// Mount points the machine boots from
+const BOOT_MOUNTS: &[&str] = &["/boot", "/boot/efi", "/efi"];
+
+/// GPT partition types only created by an operating system installer
+const OS_PARTITION_TYPES: &[([u8; 16], &str)] = &[
+    // Windows Recovery Environment (DE94BBA4-06D1-4D40-A16A-BFD50179D6AC)
+    ([0xA4, 0xBB, 0x94, 0xDE, 0xD1, 0x06, 0x40, 0x4D, 0xA1, 0x6A, 0xBF, 0xD5, 0x01, 0x79, 0xD6, 0xAC], "Windows"),
+    // Linux root, x86-64 (4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709)
+    ([0xE3, 0xBC, 0x68, 0x4F, 0xCD, 0xE8, 0xB1, 0x4D, 0x96, 0xE7, 0xFB, 0xCA, 0xF9, 0x84, 0xB7, 0x09], "Linux"),
+    // Linux root, AArch64 (B921B045-1DF0-41C3-AF44-4C6F280D3FAE)
+    ([0x45, 0xB0, 0x21, 0xB9, 0xF0, 0x1D, 0xC3, 0x41, 0xAF, 0x44, 0x4C, 0x6F, 0x28, 0x0D, 0x3F, 0xAE], "Linux"),
+];
+/// MBR partition type of the hidden Windows Recovery Environment partition
+const WINDOWS_RE_MBR_TYPE: u8 = 0x27;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RiskFactor {
+    /// The platform reports the disk the running system lives on
+    SystemDisk,
+    /// The machine boots from this disk
+    BootDevice,
+    /// An operating system is installed on the disk
+    OsInstallation,
+    /// Files or filesystems on the disk were written recently
+    RecentActivity,
+    /// The disk is one member of an LVM, RAID or dynamic disk volume
+    MultiDeviceMember,
+}
+
+impl RiskFactor {
+    /// Points this factor adds to a device's score
+    pub fn weight(&self) -> u32 {
+        match self {
+            RiskFactor::SystemDisk => 100,
+            RiskFactor::BootDevice => 80,
+            RiskFactor::OsInstallation => 60,
+            RiskFactor::MultiDeviceMember => 50,
+            RiskFactor::RecentActivity => 20,
+        }
+    }
+
+    /// The risk level a device has at least when this factor is found
+    pub fn level(&self) -> RiskLevel {
+        match self {
+            RiskFactor::SystemDisk | RiskFactor::BootDevice => RiskLevel::Critical,
+            RiskFactor::OsInstallation | RiskFactor::MultiDeviceMember => RiskLevel::High,
+            RiskFactor::RecentActivity => RiskLevel::Medium,
+        }
+    }
+}
+
+/// One thing found that makes formatting a device dangerous
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskReason {
+    pub factor: RiskFactor,
+    pub weight: u32,
+    /// What was found, for display
+    pub detail: String,
+}
+
+/// Risk assessment of one enumerated device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceRisk {
+    pub device_id: String,
+    /// Sum of the weights of every reason; higher is more dangerous
+    pub score: u32,
+    pub level: RiskLevel,
+    pub reasons: Vec<RiskReason>,
+    /// The GUI must ask for a second, explicit confirmation before formatting
+    pub requires_extra_confirmation: bool,
+    /// False when the device could not be read and only platform flags were scored
+    pub content_checked: bool,
+}
+
+impl DeviceRisk {
+    fn new(device: &Device) -> Self {
+        let level = if device.is_removable && device.mount_points.is_empty() {
+            RiskLevel::Safe
+        } else {
+            RiskLevel::Low
+        };
+        Self {
+            device_id: device.id.clone(),
+            score: 0,
+            level,
+            reasons: Vec::new(),
+            requires_extra_confirmation: false,
+            content_checked: false,
+        }
+    }
+
+    fn add(&mut self, factor: RiskFactor, detail: String) {
+        self.score += factor.weight();
+        self.level = self.level.max(factor.level());
+        self.requires_extra_confirmation = self.level >= RiskLevel::High;
+        self.reasons.push(RiskReason { factor, weight: factor.weight(), detail });
+    }
+
+    pub fn has(&self, factor: RiskFactor) -> bool {
+        self.reasons.iter().any(|r| r.factor == factor)
+    }
+}
+
+/// Assess one device; `peers` are the other enumerated devices, used to name RAID/LVM partners
+pub fn assess_device(device: &Device, peers: &[Device]) -> DeviceRisk {
+    let mut all: Vec<Device> = vec![device.clone()];
+    all.extend(peers.iter().filter(|p| p.id != device.id).cloned());
+    assess_devices(&all).swap_remove(0)
+}
+
+/// Assess every enumerated device, in the order given. Each device is read once.
+pub fn assess_devices(devices: &[Device]) -> Vec<DeviceRisk> {
+    let now = unix_now();
+    let mut risks = Vec::with_capacity(devices.len());
+    let mut memberships = Vec::with_capacity(devices.len());
+    for device in devices {
+        let mut risk = DeviceRisk::new(device);
+        assess_platform(&mut risk, device, now);
+        assess_installations(&mut risk, device);
+        let found = match open(device) {
+            Ok(mut reader) => {
+                risk.content_checked = true;
+                assess_contents(&mut risk, &mut reader, device, now);
+                membership::read_memberships(&mut reader, device.size)
+            }
+            Err(e) => {
+                log::debug!("Scoring {} from platform flags only: {}", device.id, e);
+                Vec::new()
+            }
+        };
+        risks.push(risk);
+        memberships.push(found);
+    }
+
+    let everyone: Vec<(&Device, Vec<VolumeMembership>)> = devices.iter().zip(memberships.iter().cloned()).collect();
+    for ((device, found), risk) in devices.iter().zip(memberships).zip(risks.iter_mut()) {
+        for volume in membership::group_members(found, &device.id, &everyone) {
+            risk.add(RiskFactor::MultiDeviceMember, format!(
+                "Member of {} {} (other members: {})", volume.kind.description(), volume.name(), volume.members_text()
+            ));
+        }
+    }
+    risks
+}
+
+fn open(device: &Device) -> Result<crate::device_reader::AlignedDeviceReader, moses_core::MosesError> {
+    Ok(crate::device_reader::AlignedDeviceReader::new(crate::utils::open_device_read(device)?))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Factors known from what the platform reported and the mounted volumes
+fn assess_platform(risk: &mut DeviceRisk, device: &Device, now: u64) {
+    if device.is_system {
+        risk.add(RiskFactor::SystemDisk, "The running system is on this disk".to_string());
+    }
+    let boot_mounts: Vec<String> = device.mount_points.iter()
+        .map(|m| m.to_string_lossy().to_string())
+        .filter(|m| BOOT_MOUNTS.contains(&m.as_str()))
+        .collect();
+    if !boot_mounts.is_empty() {
+        risk.add(RiskFactor::BootDevice, format!("Boot files are mounted from this disk at {}", boot_mounts.join(", ")));
+    }
+
+    let newest = device.mount_points.iter()
+        .filter_map(|mount| newest_write(mount).map(|t| (t, mount)))
+        .max_by_key(|(t, _)| *t);
+    if let Some((written, mount)) = newest.filter(|(t, _)| now.saturating_sub(*t) < RECENT_ACTIVITY_SECS) {
+        risk.add(RiskFactor::RecentActivity, format!(
+            "Files under {} were modified {}", mount.display(), age_text(now.saturating_sub(written))
+        ));
+    }
+}
+
+/// Factors read off the device itself: partition types and ext superblocks
+fn assess_contents<R: Read + Seek>(risk: &mut DeviceRisk, reader: &mut R, device: &Device, now: u64) {
+    // Partition types and superblocks only stand in for an installation we couldn't open
+    if !risk.has(RiskFactor::OsInstallation) {
+        if let Some(os) = partition_os(reader) {
+            risk.add(RiskFactor::OsInstallation, format!("The partition table holds a {} system partition", os));
+        }
+    }
+
+    let (_, partitions) = read_partition_table(reader);
+    let mut starts = vec![0u64];
+    starts.extend(partitions.iter().map(|&(start, _)| start).filter(|&start| start < device.size.max(1)));
+    let superblocks: Vec<ExtTimes> = starts.iter().filter_map(|&start| read_ext_times(reader, start)).collect();
+
+    if !risk.has(RiskFactor::OsInstallation) {
+        if let Some(sb) = superblocks.iter().find(|sb| sb.last_mounted == "/") {
+            risk.add(RiskFactor::OsInstallation, format!(
+                "The ext filesystem at byte {} was last mounted as a Linux root", sb.offset
+            ));
+        }
+    }
+    if !risk.has(RiskFactor::RecentActivity) {
+        if let Some(sb) = superblocks.iter().filter(|sb| now.saturating_sub(sb.written) < RECENT_ACTIVITY_SECS).max_by_key(|sb| sb.written) {
+            risk.add(RiskFactor::RecentActivity, format!(
+                "The ext filesystem at byte {} was written {}", sb.offset, age_text(now.saturating_sub(sb.written))
+            ));
+        }
+    }
+}
+
+/// Systems found inside the mounted volumes or the filesystem at the start of the device
+fn assess_installations(risk: &mut DeviceRisk, device: &Device) {
+    for system in installations::detect_installations(device) {
+        if system.kind != SystemKind::BootLoader {
+            risk.add(RiskFactor::OsInstallation, format!("{} installation on {}", system.name, system.location));
+        }
+    }
+}
+
+/// Newest modification time among the first entries of a breadth-first walk
+fn newest_write(root: &Path) -> Option<u64> {
+    let mut newest = None;
+    let mut queue = std::collections::VecDeque::from([root.to_path_buf()]);
+    let mut seen = 0;
+    while let Some(dir) = queue.pop_front() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            seen += 1;
+            if seen > ACTIVITY_SCAN_LIMIT {
+                return newest;
+            }
+            // symlink_metadata keeps the walk on this volume's own entries
+            let Ok(meta) = entry.path().symlink_metadata() else { continue };
+            if let Some(secs) = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()) {
+                newest = newest.max(Some(secs));
+            }
+            if meta.is_dir() {
+                queue.push_back(entry.path());
+            }
+        }
+    }
+    newest
+}
+
+/// The OS a partition table was laid out for, from partition types only installers create
+fn partition_os<R: Read + Seek>(reader: &mut R) -> Option<&'static str> {
+    let mbr = read_at(reader, 0, 512)?;
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return None;
+    }
+    if (0..4).any(|i| mbr[446 + i * 16 + 4] == WINDOWS_RE_MBR_TYPE) {
+        return Some("Windows");
+    }
+    if !(0..4).any(|i| mbr[446 + i * 16 + 4] == 0xEE) {
+        return None;
+    }
+
+    let header = read_at(reader, 512, 512)?;
+    if &header[0..8] != b"EFI PART" {
+        return None;
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().ok()?);
+    let num_entries = u32::from_le_bytes(header[80..84].try_into().ok()?).min(128) as usize;
+    let entry_size = (u32::from_le_bytes(header[84..88].try_into().ok()?) as usize).max(128);
+    let table = read_at(reader, entries_lba * 512, num_entries * entry_size)?;
+    table.chunks_exact(entry_size)
+        .find_map(|entry| OS_PARTITION_TYPES.iter().find(|(guid, _)| entry[0..16] == *guid))
+        .map(|&(_, os)| os)
+}
+
+/// Times recorded in an ext superblock
+struct ExtTimes {
+    offset: u64,
+    /// Last mount or write, whichever is newer
+    written: u64,
+    last_mounted: String,
+}
+
+fn read_ext_times<R: Read + Seek>(reader: &mut R, offset: u64) -> Option<ExtTimes> {
+    let sb = read_at(reader, offset + 1024, 1024)?;
+    if u16::from_le_bytes([sb[0x38], sb[0x39]]) != 0xEF53 {
+        return None;
+    }
+    let mount_time = u32::from_le_bytes(sb[0x2C..0x30].try_into().unwrap()) as u64;
+    let write_time = u32::from_le_bytes(sb[0x30..0x34].try_into().unwrap()) as u64;
+    let name = &sb[0x88..0xC8];
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(ExtTimes {
+        offset,
+        written: mount_time.max(write_time),
+        last_mounted: String::from_utf8_lossy(&name[..end]).to_string(),
+    })
+}
+
+fn age_text(secs: u64) -> String {
+    match secs {
+        0..=3599 => "within the last hour".to_string(),
+        3600..=86_399 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::test_helpers::create_test_device;
+
+    fn ext_disk(last_mounted: &str, written: u64) -> Vec<u8> {
+        let mut disk = vec![0u8; 64 * 1024];
+        let sb = 1024;
+        disk[sb + 0x38..sb + 0x3A].copy_from_slice(&0xEF53u16.to_le_bytes());
+        disk[sb + 0x30..sb + 0x34].copy_from_slice(&(written as u32).to_le_bytes());
+        disk[sb + 0x88..sb + 0x88 + last_mounted.len()].copy_from_slice(last_mounted.as_bytes());
+        disk
+    }
+
+    #[test]
+    fn test_ext_root_written_recently() {
+        let now = 1_700_000_000;
+        let device = create_test_device("disk", 64 * 1024);
+        let mut risk = DeviceRisk::new(&device);
+        assess_contents(&mut risk, &mut Cursor::new(ext_disk("/", now - 7200)), &device, now);
+        assert!(risk.has(RiskFactor::OsInstallation));
+        assert!(risk.has(RiskFactor::RecentActivity));
+        assert_eq!(risk.score, 80);
+        assert_eq!(risk.level, RiskLevel::High);
+        assert!(risk.requires_extra_confirmation);
+        assert!(risk.reasons.iter().any(|r| r.detail.contains("2 hours ago")));
+
+        // A data volume written a month ago is no more than a plain fixed disk
+        let mut risk = DeviceRisk::new(&device);
+        assess_contents(&mut risk, &mut Cursor::new(ext_disk("/mnt/data", now - 30 * 86_400)), &device, now);
+        assert!(risk.reasons.is_empty());
+        assert_eq!(risk.level, RiskLevel::Low);
+        assert!(!risk.requires_extra_confirmation);
+    }
+
+    #[test]
+    fn test_windows_recovery_partition() {
+        let mut disk = vec![0u8; 64 * 1024];
+        disk[446 + 4] = 0xEE;
+        disk[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&127u32.to_le_bytes());
+        disk[510] = 0x55;
+        disk[511] = 0xAA;
+        disk[512..520].copy_from_slice(b"EFI PART");
+        disk[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+        disk[512 + 80..512 + 84].copy_from_slice(&4u32.to_le_bytes());
+        disk[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+        let entry = 1024 + 128;
+        disk[entry..entry + 16].copy_from_slice(&OS_PARTITION_TYPES[0].0);
+        disk[entry + 32..entry + 40].copy_from_slice(&34u64.to_le_bytes());
+        disk[entry + 40..entry + 48].copy_from_slice(&100u64.to_le_bytes());
+        assert_eq!(partition_os(&mut Cursor::new(&disk)), Some("Windows"));
+
+        disk[entry] ^= 0xFF;
+        assert_eq!(partition_os(&mut Cursor::new(&disk)), None);
+    }
+
+    #[test]
+    fn test_platform_flags_and_mounts() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("etc")).unwrap();
+        std::fs::write(root.path().join("etc/os-release"), "ID=test\n").unwrap();
+
+        let mut device = create_test_device("disk", 1 << 30);
+        device.is_system = true;
+        device.mount_points = vec![root.path().to_path_buf(), "/boot/efi".into()];
+        let mut risk = DeviceRisk::new(&device);
+        assess_platform(&mut risk, &device, unix_now());
+        assess_installations(&mut risk, &device);
+        let factors: Vec<RiskFactor> = risk.reasons.iter().map(|r| r.factor).collect();
+        assert_eq!(factors, vec![
+            RiskFactor::SystemDisk, RiskFactor::BootDevice, RiskFactor::RecentActivity, RiskFactor::OsInstallation,
+        ]);
+        assert_eq!(risk.level, RiskLevel::Critical);
+        assert_eq!(risk.score, 260);
+
+        let mut stick = create_test_device("stick", 1 << 30);
+        stick.is_removable = true;
+        let mut risk = DeviceRisk::new(&stick);
+        assess_platform(&mut risk, &stick, unix_now());
+        assert_eq!((risk.score, risk.level), (0, RiskLevel::Safe));
+    }
+
+    #[test]
+    fn test_unreadable_devices_are_scored_from_flags() {
+        let mut device = create_test_device("/nonexistent/moses-risk", 1 << 30);
+        device.is_system = true;
+        let risks = assess_devices(&[device, create_test_device("/nonexistent/other", 1 << 30)]);
+        assert_eq!(risks.len(), 2);
+        assert!(!risks[0].content_checked);
+        assert!(risks[0].has(RiskFactor::SystemDisk));
+        assert_eq!(risks[1].score, 0);
+    }
+}
@@ -0,0 +1,255 @@
+// Post-format seeding - the few files and settings a new volume usually gets next
+// A drive is rarely left exactly as the formatter made it: Windows users add an
+// autorun.inf so the drive shows its name and icon, camera cards and build disks get a
+// .metadata_never_index so macOS does not index them, and an ext4 stick formatted with
+// sudo needs its root directory handed to the user, who cannot write to it otherwise.
+// These are post-steps in FormatOptions. The ext root owner is set by the ext formatters
+// while they build the root directory; the files are written onto the new volume
+// afterwards through the same restore target the selective format uses.
+use std::path::{Path, PathBuf};
+use moses_core::{Device, FormatOptions, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::ops::FilesystemOps;
+use super::selective::RestoreTarget;
+
+/// Key in `FormatOptions::additional_options`: comma separated steps, e.g. "autorun,no_index"
+pub const STEPS_OPTION: &str = "post_format_steps";
+/// Key in `FormatOptions::additional_options`: host path of the .ico the autorun step uses
+pub const ICON_OPTION: &str = "volume_icon";
+
+/// Icons bigger than this are not drive icons
+const MAX_ICON_SIZE: u64 = 1024 * 1024;
+
+/// Something done to a volume right after it is formatted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedStep {
+    /// autorun.inf with the drive's label and icon, shown by Windows Explorer
+    Autorun,
+    /// .metadata_never_index, which keeps Spotlight off the volume
+    NoIndex,
+    /// ext root directory owned by the user who ran the format
+    ExtRoot,
+}
+
+impl SeedStep {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "autorun" => Some(Self::Autorun),
+            "no_index" | "noindex" => Some(Self::NoIndex),
+            "ext_root" => Some(Self::ExtRoot),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Autorun => "autorun",
+            Self::NoIndex => "no_index",
+            Self::ExtRoot => "ext_root",
+        }
+    }
+
+    /// For the step plan of a format simulation
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Autorun => "Write autorun.inf with the drive's label and icon",
+            Self::NoIndex => "Write .metadata_never_index so macOS does not index the volume",
+            Self::ExtRoot => "Give the root directory to the user running the format",
+        }
+    }
+
+    /// Whether the step writes files onto the new volume, rather than being part of the format
+    pub fn writes_files(&self) -> bool {
+        !matches!(self, Self::ExtRoot)
+    }
+
+    fn supports(&self, filesystem: &str) -> bool {
+        match self {
+            Self::Autorun => matches!(filesystem, "fat16" | "fat32" | "exfat" | "ntfs"),
+            Self::NoIndex => true,
+            Self::ExtRoot => matches!(filesystem, "ext2" | "ext3" | "ext4"),
+        }
+    }
+
+    /// Read the steps from format options, checking each suits the filesystem
+    pub fn from_options(options: &FormatOptions) -> Result<Vec<Self>, MosesError> {
+        let Some(value) = options.additional_options.get(STEPS_OPTION) else {
+            return Ok(Vec::new());
+        };
+        let filesystem = options.filesystem_type.to_lowercase();
+        let mut steps = Vec::new();
+        for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+            let step = Self::parse(name).ok_or_else(|| MosesError::InvalidInput(format!(
+                "Unknown post-format step '{}' (expected autorun, no_index or ext_root)", name.trim()
+            )))?;
+            if !step.supports(&filesystem) {
+                return Err(MosesError::InvalidInput(format!(
+                    "The {} post-format step does not apply to {}", step.as_str(), filesystem
+                )));
+            }
+            if !steps.contains(&step) {
+                steps.push(step);
+            }
+        }
+        if steps.contains(&Self::Autorun) {
+            let icon = icon_path(options)?;
+            if icon.is_none() && options.label.as_deref().is_none_or(str::is_empty) {
+                return Err(MosesError::InvalidInput(
+                    "The autorun step needs a label or a volume icon to put in autorun.inf".to_string()
+                ));
+            }
+        }
+        Ok(steps)
+    }
+}
+
+/// The icon for autorun.inf, checked to be an existing .ico file of sensible size
+fn icon_path(options: &FormatOptions) -> Result<Option<PathBuf>, MosesError> {
+    let Some(path) = options.additional_options.get(ICON_OPTION).map(PathBuf::from) else {
+        return Ok(None);
+    };
+    if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ico")) {
+        return Err(MosesError::InvalidInput(format!("The volume icon {} is not an .ico file", path.display())));
+    }
+    match std::fs::metadata(&path) {
+        Ok(meta) if meta.is_file() && meta.len() <= MAX_ICON_SIZE => Ok(Some(path)),
+        Ok(_) => Err(MosesError::InvalidInput(format!(
+            "The volume icon {} is not a file of at most {} KB", path.display(), MAX_ICON_SIZE / 1024
+        ))),
+        Err(e) => Err(MosesError::InvalidInput(format!("Cannot read the volume icon {}: {}", path.display(), e))),
+    }
+}
+
+/// Owner (uid, gid) the ext formatters give the root directory: with the ext_root step,
+/// the user behind sudo or else the one running the format; `None` leaves it to root
+pub fn ext_root_owner(options: &FormatOptions) -> Result<Option<(u32, u32)>, MosesError> {
+    if !SeedStep::from_options(options)?.contains(&SeedStep::ExtRoot) {
+        return Ok(None);
+    }
+    let from_env = |key: &str| std::env::var(key).ok().and_then(|value| value.parse::<u32>().ok());
+    let owner = match (from_env("SUDO_UID"), from_env("SUDO_GID")) {
+        (Some(uid), Some(gid)) => Some((uid, gid)),
+        _ => current_user(),
+    };
+    Ok(owner.filter(|&owner| owner != (0, 0)))
+}
+
+#[cfg(target_os = "linux")]
+fn current_user() -> Option<(u32, u32)> {
+    Some((nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_user() -> Option<(u32, u32)> {
+    None
+}
+
+/// Write the files of `steps` to a volume; returns the paths written
+pub fn seed(ops: &mut dyn FilesystemOps, steps: &[SeedStep], options: &FormatOptions) -> Result<Vec<PathBuf>, MosesError> {
+    let mut written = Vec::new();
+    for step in steps {
+        match step {
+            SeedStep::Autorun => {
+                let mut inf = String::from("[autorun]\r\n");
+                if let Some(label) = options.label.as_deref().filter(|label| !label.is_empty()) {
+                    inf.push_str(&format!("label={}\r\n", label));
+                }
+                if let Some(icon) = icon_path(options)? {
+                    let name = icon.file_name().map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "autorun.ico".to_string());
+                    let bytes = std::fs::read(&icon)
+                        .map_err(|e| MosesError::Other(format!("Failed to read {}: {}", icon.display(), e)))?;
+                    written.push(write_file(ops, &format!("/{}", name), &bytes)?);
+                    inf.push_str(&format!("icon={}\r\n", name));
+                }
+                written.push(write_file(ops, "/autorun.inf", inf.as_bytes())?);
+            }
+            SeedStep::NoIndex => written.push(write_file(ops, "/.metadata_never_index", &[])?),
+            SeedStep::ExtRoot => {}
+        }
+    }
+    if !written.is_empty() {
+        ops.sync()?;
+    }
+    Ok(written)
+}
+
+fn write_file(ops: &mut dyn FilesystemOps, path: &str, data: &[u8]) -> Result<PathBuf, MosesError> {
+    let path = Path::new(path);
+    ops.create(path, 0o644)?;
+    if !data.is_empty() {
+        ops.write(path, 0, data)?;
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Seed a freshly formatted device, opening its volume only when a step writes files
+pub async fn seed_volume(
+    device: &Device,
+    options: &FormatOptions,
+    target: &mut dyn RestoreTarget,
+) -> Result<Vec<PathBuf>, MosesError> {
+    let steps: Vec<SeedStep> = SeedStep::from_options(options)?.into_iter().filter(SeedStep::writes_files).collect();
+    if steps.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut ops = target.open(device).await?;
+    seed(ops.as_mut(), &steps, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+
+    fn options(filesystem: &str, steps: &str) -> FormatOptions {
+        FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            label: Some("PHOTOS".to_string()),
+            additional_options: [(STEPS_OPTION.to_string(), steps.to_string())].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_steps_from_options() {
+        assert_eq!(SeedStep::from_options(&options("fat32", "autorun, no-index,autorun")).unwrap(), vec![SeedStep::Autorun, SeedStep::NoIndex]);
+        assert!(SeedStep::from_options(&options("ext4", "autorun")).is_err(), "autorun is for Windows filesystems");
+        assert!(SeedStep::from_options(&options("fat32", "ext_root")).is_err());
+        assert!(SeedStep::from_options(&options("fat32", "thumbnails")).is_err());
+        assert_eq!(ext_root_owner(&options("ext4", "no_index")).unwrap(), None);
+
+        let mut unlabeled = options("exfat", "autorun");
+        unlabeled.label = None;
+        assert!(SeedStep::from_options(&unlabeled).is_err(), "nothing to put in autorun.inf");
+        unlabeled.additional_options.insert(ICON_OPTION.to_string(), "/nonexistent/drive.ico".to_string());
+        assert!(SeedStep::from_options(&unlabeled).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seed_writes_files() {
+        struct FolderTarget(PathBuf);
+
+        #[async_trait::async_trait]
+        impl RestoreTarget for FolderTarget {
+            async fn open(&mut self, _device: &Device) -> Result<Box<dyn FilesystemOps>, MosesError> {
+                Ok(Box::new(HostFolderOps::new(self.0.clone())?))
+            }
+        }
+
+        let volume = tempfile::tempdir().unwrap();
+        let icons = tempfile::tempdir().unwrap();
+        let icon = icons.path().join("camera.ico");
+        std::fs::write(&icon, [0, 0, 1, 0]).unwrap();
+        let mut options = options("fat32", "autorun,no_index");
+        options.additional_options.insert(ICON_OPTION.to_string(), icon.display().to_string());
+
+        let device = crate::test_helpers::create_test_device("unused", 0);
+        let written = seed_volume(&device, &options, &mut FolderTarget(volume.path().to_path_buf())).await.unwrap();
+        assert_eq!(written.len(), 3);
+        assert_eq!(std::fs::read_to_string(volume.path().join("autorun.inf")).unwrap(), "[autorun]\r\nlabel=PHOTOS\r\nicon=camera.ico\r\n");
+        assert_eq!(std::fs::read(volume.path().join("camera.ico")).unwrap(), [0, 0, 1, 0]);
+        assert!(volume.path().join(".metadata_never_index").exists());
+    }
+}
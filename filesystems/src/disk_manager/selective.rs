@@ -0,0 +1,412 @@
+// Selective format - wipe the filesystem but keep a few folders
+// "I want a clean drive but keep these two folders" as one job: the folders are copied
+// to a staging folder on the host, the device is formatted, and they are copied back.
+// Nothing is formatted until every kept file has been staged and the space checks pass.
+// If putting them back fails, whatever was restored is removed again so the new volume
+// is left clean rather than half-filled, and the staged copy is kept for the user.
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use moses_core::{Device, FilesystemFormatter, FormatOptions, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::ops::{FilesystemOps, HostFolderOps};
+use crate::transfer::{self, FilesystemLimits, TransferFilter, TransferPreview, TransferReport};
+use super::installations::existing_volumes;
+
+/// Slack on top of the kept bytes for directory entries and partly used clusters
+const SPACE_MARGIN_PERCENT: u64 = 5;
+
+/// What to keep across the format, and where to hold it meanwhile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectiveFormatRequest {
+    pub options: FormatOptions,
+    /// Files or folders on the device's first readable volume, e.g. `/Photos`
+    pub keep: Vec<PathBuf>,
+    /// Host folder the staging copy is made in; it must not be on the device
+    pub staging: PathBuf,
+}
+
+/// Pre-checks of a selective format; it only runs when `problems` is empty
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SelectiveFormatCheck {
+    /// What will be staged and restored
+    pub kept: TransferPreview,
+    /// Free space in the staging folder, when the platform reports it
+    pub staging_available: Option<u64>,
+    /// Usable space on the volume after the format
+    pub capacity: u64,
+    pub problems: Vec<String>,
+}
+
+/// Outcome of a selective format that got as far as formatting
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SelectiveFormatReport {
+    pub staged: TransferReport,
+    pub restored: TransferReport,
+    /// Why the kept items could not be put back; None when they all were
+    pub restore_error: Option<String>,
+    /// The restored part was removed from the new volume after a failed restore
+    pub rolled_back: bool,
+    /// Staging folder left on the host, holding the kept items; None once they are restored
+    pub staging: Option<PathBuf>,
+}
+
+/// Opens the freshly formatted volume for writing the kept items back
+#[async_trait]
+pub trait RestoreTarget: Send {
+    async fn open(&mut self, device: &Device) -> Result<Box<dyn FilesystemOps>, MosesError>;
+}
+
+/// Restores through the host mount the device has, or else through our own writers
+pub struct WritableVolume;
+
+#[async_trait]
+impl RestoreTarget for WritableVolume {
+    async fn open(&mut self, device: &Device) -> Result<Box<dyn FilesystemOps>, MosesError> {
+        let volume = existing_volumes(device, true).into_iter().next()
+            .ok_or_else(|| MosesError::NotSupported(format!("The new volume on {} could not be opened", device.name)))?;
+        if volume.ops.is_readonly() {
+            return Err(MosesError::NotSupported(format!(
+                "Moses cannot write to unmounted {} volumes; mount {} to restore the kept files",
+                volume.filesystem, device.name
+            )));
+        }
+        Ok(volume.ops)
+    }
+}
+
+/// Check that the kept items exist, fit both the staging folder and the new volume, and
+/// stay within the new filesystem's file size and naming limits
+pub async fn check(
+    device: &Device,
+    formatter: &dyn FilesystemFormatter,
+    request: &SelectiveFormatRequest,
+) -> SelectiveFormatCheck {
+    let mut check = SelectiveFormatCheck {
+        capacity: match formatter.dry_run(device, &request.options).await {
+            Ok(report) if report.space_after_format > 0 => report.space_after_format,
+            _ => device.size,
+        },
+        ..Default::default()
+    };
+    if request.keep.is_empty() {
+        check.problems.push("No files or folders were selected to keep".to_string());
+    }
+
+    match existing_volumes(device, false).into_iter().next() {
+        Some(mut volume) => {
+            let (found, missing): (Vec<PathBuf>, Vec<PathBuf>) = request.keep.iter()
+                .cloned()
+                .partition(|path| volume.ops.stat(path).is_ok());
+            for path in missing {
+                check.problems.push(format!("{} was not found on {}", path.display(), volume.location));
+            }
+            for path in &found {
+                match volume.ops.stats(path) {
+                    Ok(stats) => {
+                        check.kept.files += stats.files + stats.symlinks;
+                        check.kept.directories += stats.directories;
+                        check.kept.bytes += stats.total_size;
+                    }
+                    Err(e) => check.problems.push(format!("Could not measure {}: {}", path.display(), e)),
+                }
+            }
+            let limits = FilesystemLimits::for_filesystem(&request.options.filesystem_type);
+            match transfer::check_limits(volume.ops.as_mut(), &found, &TransferFilter::default(), &limits, Path::new("/")) {
+                Ok(violations) => check.problems.extend(violations.iter().map(|violation| format!("Cannot keep {}", violation))),
+                Err(e) => check.problems.push(format!("Could not check the kept items against {}: {}", limits.filesystem, e)),
+            }
+        }
+        None => check.problems.push(format!("No readable filesystem on {} to keep files from", device.name)),
+    }
+
+    let staging = request.staging.canonicalize().unwrap_or_else(|_| request.staging.clone());
+    if device.mount_points.iter().any(|mount| staging.starts_with(mount)) {
+        check.problems.push(format!("The staging folder {} is on the device being formatted", request.staging.display()));
+    }
+    let needed = with_margin(check.kept.bytes);
+    check.staging_available = available_space(&request.staging);
+    match check.staging_available {
+        Some(available) if available < needed => check.problems.push(format!(
+            "{} has {} MB free, the kept items need {} MB",
+            request.staging.display(), available / (1024 * 1024), needed.div_ceil(1024 * 1024)
+        )),
+        Some(_) => {}
+        None => log::warn!("Free space of {} is unknown; staging without a space check", request.staging.display()),
+    }
+    if check.capacity < needed {
+        check.problems.push(format!(
+            "The kept items need {} MB but the new volume holds {} MB",
+            needed.div_ceil(1024 * 1024), check.capacity / (1024 * 1024)
+        ));
+    }
+    check
+}
+
+/// Stage the kept items, format the device and restore them
+///
+/// Fails without touching the device when a check fails or any kept file cannot be
+/// staged. Once the device is formatted the job returns a report even if the restore
+/// failed, since the staged copy is then the only one left.
+pub async fn selective_format(
+    device: &Device,
+    formatter: &dyn FilesystemFormatter,
+    request: &SelectiveFormatRequest,
+    target: &mut dyn RestoreTarget,
+) -> Result<SelectiveFormatReport, MosesError> {
+    let check = check(device, formatter, request).await;
+    if !check.problems.is_empty() {
+        return Err(MosesError::InvalidInput(check.problems.join("; ")));
+    }
+
+    let staging = request.staging.join(format!(
+        "moses-keep-{}-{}",
+        device.name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+    let mut report = SelectiveFormatReport {
+        staged: stage(device, &request.keep, &staging)?,
+        ..Default::default()
+    };
+    log::info!("Staged {} files ({} bytes) in {}", report.staged.files_copied, report.staged.bytes_copied, staging.display());
+
+    if let Err(e) = formatter.format(device, &request.options).await {
+        log::error!("Format failed; the kept items are still staged in {}", staging.display());
+        return Err(MosesError::FormatError(format!("{} (the kept items are in {})", e, staging.display())));
+    }
+
+    let restored = match target.open(device).await {
+        Ok(mut ops) => restore(&staging, ops.as_mut()),
+        Err(e) => Err(e),
+    };
+    match restored {
+        Ok(restored) => {
+            report.restored = restored;
+            if let Err(e) = std::fs::remove_dir_all(&staging) {
+                log::warn!("Could not remove the staging folder {}: {}", staging.display(), e);
+                report.staging = Some(staging);
+            }
+        }
+        Err(e) => {
+            log::error!("Restoring the kept items failed: {}", e);
+            report.restore_error = Some(e.to_string());
+            report.rolled_back = match target.open(device).await {
+                Ok(mut ops) => roll_back(&staging, ops.as_mut()),
+                Err(_) => false,
+            };
+            report.staging = Some(staging);
+        }
+    }
+    Ok(report)
+}
+
+fn with_margin(bytes: u64) -> u64 {
+    bytes + bytes * SPACE_MARGIN_PERCENT / 100
+}
+
+/// Copy the kept items out; any file left behind aborts the job before the format
+fn stage(device: &Device, keep: &[PathBuf], staging: &Path) -> Result<TransferReport, MosesError> {
+    let mut volume = existing_volumes(device, false).into_iter().next()
+        .ok_or_else(|| MosesError::NotSupported(format!("No readable filesystem on {}", device.name)))?;
+    let staged = transfer::extract_to_directory(volume.ops.as_mut(), keep, staging, &TransferFilter::default());
+    match staged {
+        Ok(report) if report.errors.is_empty() => Ok(report),
+        Ok(report) => {
+            let _ = std::fs::remove_dir_all(staging);
+            Err(MosesError::Other(format!(
+                "{} kept items could not be copied, so nothing was formatted: {}",
+                report.errors.len(), report.errors.join("; ")
+            )))
+        }
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(staging);
+            Err(e)
+        }
+    }
+}
+
+/// Top-level entries of the staging folder, as copied from the device
+fn staged_entries(staging: &mut HostFolderOps) -> Result<Vec<PathBuf>, MosesError> {
+    let mut entries: Vec<PathBuf> = staging.readdir(Path::new("/"))?.into_iter()
+        .filter(|entry| entry.name != "." && entry.name != "..")
+        .map(|entry| Path::new("/").join(entry.name))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// Copy the staged items back and check every byte arrived
+fn restore(staging: &Path, target: &mut dyn FilesystemOps) -> Result<TransferReport, MosesError> {
+    let mut source = HostFolderOps::new(staging.to_path_buf())?;
+    let entries = staged_entries(&mut source)?;
+    let expected = transfer::preview(&mut source, &entries, &TransferFilter::default())?;
+    let restored = transfer::copy_to_filesystem(&mut source, &entries, target, Path::new("/"), &TransferFilter::default())?;
+    target.sync()?;
+    if !restored.errors.is_empty() {
+        return Err(MosesError::Other(restored.errors.join("; ")));
+    }
+    if (restored.files_copied, restored.bytes_copied) != (expected.files, expected.bytes) {
+        return Err(MosesError::Other(format!(
+            "restored {} files ({} bytes) of {} ({} bytes)",
+            restored.files_copied, restored.bytes_copied, expected.files, expected.bytes
+        )));
+    }
+    Ok(restored)
+}
+
+/// Remove what a failed restore left on the new volume; true when it is clean again
+fn roll_back(staging: &Path, target: &mut dyn FilesystemOps) -> bool {
+    let Ok(mut source) = HostFolderOps::new(staging.to_path_buf()) else {
+        return false;
+    };
+    let Ok(entries) = staged_entries(&mut source) else {
+        return false;
+    };
+    let mut clean = true;
+    for entry in entries {
+        clean &= remove_tree(target, &entry);
+    }
+    let _ = target.sync();
+    clean
+}
+
+fn remove_tree(ops: &mut dyn FilesystemOps, path: &Path) -> bool {
+    let attributes = match ops.stat(path) {
+        Ok(attributes) => attributes,
+        // Never restored
+        Err(_) => return true,
+    };
+    if !attributes.is_directory || attributes.is_symlink {
+        return ops.unlink(path).is_ok();
+    }
+    let mut clean = true;
+    for entry in ops.readdir(path).unwrap_or_default() {
+        if entry.name != "." && entry.name != ".." {
+            clean &= remove_tree(ops, &path.join(&entry.name));
+        }
+    }
+    clean && ops.rmdir(path).is_ok()
+}
+
+/// Bytes free to the current user in the folder at `path`
+#[cfg(target_os = "linux")]
+fn available_space(path: &Path) -> Option<u64> {
+    let stats = nix::sys::statvfs::statvfs(path).ok()?;
+    Some(stats.blocks_available() as u64 * stats.fragment_size() as u64)
+}
+
+/// Bytes free to the current user in the folder at `path`
+#[cfg(target_os = "windows")]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+    use winapi::um::winnt::ULARGE_INTEGER;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let ok = unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) };
+    (ok != 0).then(|| unsafe { *available.QuadPart() })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_helpers::create_test_device;
+
+    /// Stands in for the host mounting the new volume
+    struct FolderTarget(PathBuf);
+
+    #[async_trait]
+    impl RestoreTarget for FolderTarget {
+        async fn open(&mut self, _device: &Device) -> Result<Box<dyn FilesystemOps>, MosesError> {
+            Ok(Box::new(HostFolderOps::new(self.0.clone())?))
+        }
+    }
+
+    fn setup() -> (tempfile::TempDir, tempfile::NamedTempFile, Device) {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("Photos/2024")).unwrap();
+        fs::create_dir_all(source.path().join("Work")).unwrap();
+        fs::create_dir_all(source.path().join("Junk")).unwrap();
+        fs::write(source.path().join("Photos/2024/a.jpg"), vec![1u8; 3000]).unwrap();
+        fs::write(source.path().join("Work/plan.txt"), b"plan").unwrap();
+        fs::write(source.path().join("Junk/old.bin"), vec![0u8; 100]).unwrap();
+
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 * 1024 * 1024).unwrap();
+        let mut device = create_test_device(image.path().to_str().unwrap(), 64 * 1024 * 1024);
+        device.mount_points = vec![source.path().to_path_buf()];
+        (source, image, device)
+    }
+
+    fn request(staging: &Path) -> SelectiveFormatRequest {
+        SelectiveFormatRequest {
+            options: FormatOptions { filesystem_type: "fat32".to_string(), quick_format: true, ..Default::default() },
+            keep: vec![PathBuf::from("/Photos"), PathBuf::from("/Work")],
+            staging: staging.to_path_buf(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_selective_format_restores_kept_folders() {
+        let (_source, _image, device) = setup();
+        let staging = tempfile::tempdir().unwrap();
+        let fresh = tempfile::tempdir().unwrap();
+        let formatter = crate::registration::builtin_registry().get_formatter("fat32").unwrap();
+
+        let report = selective_format(&device, formatter.as_ref(), &request(staging.path()), &mut FolderTarget(fresh.path().to_path_buf()))
+            .await
+            .unwrap();
+        assert_eq!((report.staged.files_copied, report.staged.bytes_copied), (2, 3004));
+        assert_eq!(report.restored, report.staged);
+        assert!(report.restore_error.is_none() && report.staging.is_none());
+        assert_eq!(fs::read(fresh.path().join("Photos/2024/a.jpg")).unwrap().len(), 3000);
+        assert_eq!(fs::read(fresh.path().join("Work/plan.txt")).unwrap(), b"plan");
+        assert!(!fresh.path().join("Junk").exists());
+        assert_eq!(fs::read_dir(staging.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_restore_rolls_back_and_keeps_staging() {
+        let (_source, _image, device) = setup();
+        let staging = tempfile::tempdir().unwrap();
+        let fresh = tempfile::tempdir().unwrap();
+        // A file where the Work folder has to go, after Photos is already restored
+        fs::write(fresh.path().join("Work"), b"in the way").unwrap();
+        let formatter = crate::registration::builtin_registry().get_formatter("fat32").unwrap();
+
+        let report = selective_format(&device, formatter.as_ref(), &request(staging.path()), &mut FolderTarget(fresh.path().to_path_buf()))
+            .await
+            .unwrap();
+        assert!(report.restore_error.is_some());
+        let kept = report.staging.unwrap();
+        assert!(kept.join("Photos/2024/a.jpg").exists() && kept.join("Work/plan.txt").exists());
+        assert!(report.rolled_back);
+        assert!(!fresh.path().join("Photos").exists());
+    }
+
+    #[tokio::test]
+    async fn test_checks_refuse_before_formatting() {
+        let (source, image, device) = setup();
+        let formatter = crate::registration::builtin_registry().get_formatter("fat32").unwrap();
+        let staging = tempfile::tempdir().unwrap();
+
+        let mut bad = request(staging.path());
+        bad.keep.push(PathBuf::from("/Missing"));
+        fs::File::create(source.path().join("Photos/huge.bin")).unwrap().set_len(100 * 1024 * 1024).unwrap();
+        bad.staging = source.path().join("Junk");
+        let check = check(&device, formatter.as_ref(), &bad).await;
+        assert_eq!(check.problems.len(), 3, "{:?}", check.problems);
+        assert!(check.problems.iter().any(|p| p.contains("/Missing")));
+        assert!(check.problems.iter().any(|p| p.contains("on the device being formatted")));
+        assert!(check.problems.iter().any(|p| p.contains("new volume holds")));
+
+        assert!(selective_format(&device, formatter.as_ref(), &bad, &mut WritableVolume).await.is_err());
+        assert!(fs::read(image.path()).unwrap().iter().all(|&b| b == 0), "nothing may be written when a check fails");
+    }
+}
@@ -0,0 +1,246 @@
+// Partition table rebuild - find filesystems on a disk whose table is gone and list them again
+// Wiping or overwriting the first sectors loses the table but leaves every volume in place.
+// Volumes start on the boundaries partitioning tools use (every MiB, or every cylinder of
+// 255 * 63 sectors plus the old 63-sector first track), so only those offsets are probed
+// with the family detectors. Each hit's size comes from its own boot sector or superblock
+// and the scan resumes past its end, which also skips the backup copies inside it.
+
+use std::io::{Read, Seek, SeekFrom};
+use moses_core::{Device, MosesError};
+use crate::diagnostics::{read_at, run_detectors};
+use crate::partitioner::{create_partition_table, PartitionEntry, PartitionTableType};
+
+const SECTOR: u64 = 512;
+const MIB_SECTORS: u64 = 2048;
+const CYLINDER_SECTORS: u64 = 255 * 63;
+const TRACK_SECTORS: u64 = 63;
+/// Same extent history snapshots save, see `history::restore_layout_writer`
+const TABLE_BYTES: usize = 34 * 512;
+
+/// A filesystem found where a partition used to start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundPartition {
+    /// Byte offset of the volume
+    pub start: u64,
+    /// Byte length from the volume's own metadata
+    pub size: u64,
+    pub filesystem: String,
+}
+
+impl FoundPartition {
+    /// MBR partition type for the filesystem
+    pub fn mbr_type(&self) -> u8 {
+        match self.filesystem.as_str() {
+            "fat16" => 0x06,
+            "fat32" => 0x0C,
+            "ntfs" | "exfat" => 0x07,
+            _ => 0x83,
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u64 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u64
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Size in bytes a volume records for itself
+fn volume_size(filesystem: &str, boot_sector: &[u8], superblock: Option<&[u8]>) -> Option<u64> {
+    let size = match filesystem {
+        // The backup boot sector sits in the sector after the counted ones
+        "ntfs" => (u64_at(boot_sector, 40) + 1) * u16_at(boot_sector, 11),
+        "exfat" => u64_at(boot_sector, 72).checked_shl(boot_sector[108] as u32)?,
+        "fat32" | "fat16" | "fat12" => {
+            let sectors = match u16_at(boot_sector, 19) {
+                0 => u32_at(boot_sector, 32),
+                sectors => sectors,
+            };
+            sectors * u16_at(boot_sector, 11)
+        }
+        fs if fs.starts_with("ext") => {
+            let sb = superblock?;
+            let log_block_size = u32_at(sb, 24);
+            if log_block_size > 6 {
+                return None;
+            }
+            let mut blocks = u32_at(sb, 4);
+            // INCOMPAT_64BIT keeps the high half at 0x150
+            if u32_at(sb, 96) & 0x80 != 0 {
+                blocks |= u32_at(sb, 0x150) << 32;
+            }
+            blocks * (1024 << log_block_size)
+        }
+        _ => return None,
+    };
+    (size > 0).then_some(size)
+}
+
+/// Probe offsets in ascending sector order: the first track, cylinders and MiB boundaries
+fn next_candidate(after: u64) -> u64 {
+    let mib = (after / MIB_SECTORS + 1) * MIB_SECTORS;
+    let cylinder = if after < TRACK_SECTORS { TRACK_SECTORS } else { (after / CYLINDER_SECTORS + 1) * CYLINDER_SECTORS };
+    mib.min(cylinder)
+}
+
+/// Filesystems on a reader of `size` bytes, outside sector 0, in disk order
+pub fn scan_for_partitions<R: Read + Seek>(reader: &mut R, size: u64) -> Vec<FoundPartition> {
+    let mut found = Vec::new();
+    let mut sector = next_candidate(0);
+    while sector * SECTOR + 1536 <= size {
+        let offset = sector * SECTOR;
+        let hit = read_at(reader, offset, 512).and_then(|boot_sector| {
+            let superblock = read_at(reader, offset + 1024, 512);
+            let filesystem = run_detectors(&boot_sector, superblock.as_deref())?;
+            let length = volume_size(&filesystem, &boot_sector, superblock.as_deref())?;
+            Some((filesystem, length))
+        });
+        match hit {
+            // A size running past the disk is a stray signature, not a volume
+            Some((filesystem, length)) if offset + length <= size => {
+                log::info!("Found {} at sector {} ({} bytes)", filesystem, sector, length);
+                found.push(FoundPartition { start: offset, size: length, filesystem });
+                sector = next_candidate((offset + length).div_ceil(SECTOR) - 1);
+            }
+            _ => sector = next_candidate(sector),
+        }
+    }
+    found
+}
+
+/// MBR when the partitions fit in one, GPT otherwise
+pub fn default_table_type(partitions: &[FoundPartition]) -> PartitionTableType {
+    let fits_mbr = partitions.len() <= 4
+        && partitions.iter().all(|p| (p.start + p.size) / SECTOR <= u32::MAX as u64);
+    if fits_mbr { PartitionTableType::MBR } else { PartitionTableType::GPT }
+}
+
+/// The table sectors a rebuild writes, in the layout history snapshots use. Boot code in
+/// sector 0 is kept; for MBR the sectors after it are too, since boot loaders live there.
+pub fn plan_table<R: Read + Seek>(
+    reader: &mut R,
+    disk_size: u64,
+    partitions: &[FoundPartition],
+    table_type: PartitionTableType,
+) -> Result<Vec<u8>, MosesError> {
+    let entries: Vec<PartitionEntry> = partitions.iter()
+        .map(|p| PartitionEntry {
+            start_lba: p.start / SECTOR,
+            size_lba: p.size.div_ceil(SECTOR),
+            partition_type: p.mbr_type(),
+            name: format!("{} Volume", p.filesystem.to_uppercase()),
+        })
+        .collect();
+    let table = create_partition_table(disk_size, table_type, &entries)?;
+
+    let mut sectors = read_at(reader, 0, TABLE_BYTES).unwrap_or_else(|| vec![0u8; TABLE_BYTES]);
+    let boot_code = sectors[..440].to_vec();
+    let old_signature = u32_at(&sectors, 440);
+    sectors[..table.len()].copy_from_slice(&table);
+    sectors[..440].copy_from_slice(&boot_code);
+    if let PartitionTableType::MBR = table_type {
+        if old_signature != 0 {
+            sectors[440..444].copy_from_slice(&(old_signature as u32).to_le_bytes());
+        }
+        // A leftover GPT header would take precedence over the new MBR
+        if &sectors[512..520] == b"EFI PART" {
+            sectors[512..1024].fill(0);
+        }
+    }
+    Ok(sectors)
+}
+
+/// Scan a device for filesystems (read-only)
+pub fn scan_device(device: &Device) -> Result<Vec<FoundPartition>, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    let size = if device.size > 0 { device.size } else { reader.seek(SeekFrom::End(0))? };
+    Ok(scan_for_partitions(&mut reader, size))
+}
+
+/// Write a table listing `partitions`; the table it replaces goes into the device history
+pub fn rebuild_device(device: &Device, partitions: &[FoundPartition], table_type: PartitionTableType) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Cannot rebuild the partition table of a system disk".to_string()));
+    }
+    device.ensure_writable()?;
+    let sectors = {
+        let file = crate::utils::open_device_with_fallback(device)?;
+        plan_table(&mut crate::device_reader::AlignedDeviceReader::new(file), device.size, partitions, table_type)?
+    };
+
+    super::history::record_before(device, "rebuild partition table");
+    log::info!("Writing a {:?} table with {} partition(s) to {}", table_type, partitions.len(), device.id);
+    let mut file = super::SignatureWiper::open_for_write(device)?;
+    let result = super::history::restore_layout_writer(&mut file, device.size, &sectors)
+        .and_then(|_| file.sync_all().map_err(MosesError::IoError));
+    moses_core::FilesystemCache::global().invalidate(device);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::read_partition_table;
+    use std::io::Cursor;
+
+    const MIB: usize = 1024 * 1024;
+
+    /// 16 MiB disk with no table: FAT32 at 1 MiB (4 MiB), ext4 at 6 MiB (8 MiB)
+    fn wiped_disk() -> Vec<u8> {
+        let mut disk = vec![0u8; 16 * MIB];
+        let fat = &mut disk[MIB..MIB + 512];
+        fat[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        fat[11..13].copy_from_slice(&512u16.to_le_bytes());
+        fat[13] = 8;
+        fat[32..36].copy_from_slice(&(4 * 2048u32).to_le_bytes());
+        fat[82..90].copy_from_slice(b"FAT32   ");
+        fat[510] = 0x55;
+        fat[511] = 0xAA;
+        // Its backup boot sector must not be taken for a second volume
+        let copy = disk[MIB..MIB + 512].to_vec();
+        disk[MIB + 6 * 512..MIB + 7 * 512].copy_from_slice(&copy);
+
+        let sb = &mut disk[6 * MIB + 1024..6 * MIB + 2048];
+        sb[4..8].copy_from_slice(&(8 * 1024u32).to_le_bytes());
+        sb[24..28].copy_from_slice(&0u32.to_le_bytes());
+        sb[56..58].copy_from_slice(&0xEF53u16.to_le_bytes());
+        sb[96..100].copy_from_slice(&0x1000u32.to_le_bytes());
+        disk
+    }
+
+    #[test]
+    fn test_scan_finds_volumes() {
+        let disk = wiped_disk();
+        let found = scan_for_partitions(&mut Cursor::new(disk), 16 * MIB as u64);
+        assert_eq!(found, vec![
+            FoundPartition { start: MIB as u64, size: 4 * MIB as u64, filesystem: "fat32".to_string() },
+            FoundPartition { start: 6 * MIB as u64, size: 8 * MIB as u64, filesystem: "ext4".to_string() },
+        ]);
+        assert!(matches!(default_table_type(&found), PartitionTableType::MBR));
+    }
+
+    #[test]
+    fn test_rebuilt_tables_read_back() {
+        let disk = wiped_disk();
+        let size = disk.len() as u64;
+        let mut reader = Cursor::new(disk);
+        let found = scan_for_partitions(&mut reader, size);
+        let expected = vec![(MIB as u64, 4 * MIB as u64), (6 * MIB as u64, 8 * MIB as u64)];
+
+        for table_type in [PartitionTableType::MBR, PartitionTableType::GPT] {
+            let sectors = plan_table(&mut reader, size, &found, table_type).unwrap();
+            let mut disk = reader.get_ref().clone();
+            crate::disk_manager::history::restore_layout_writer(&mut Cursor::new(&mut disk), size, &sectors).unwrap();
+            let (style, layout) = read_partition_table(&mut Cursor::new(disk));
+            assert_eq!(style.as_deref(), Some(if matches!(table_type, PartitionTableType::MBR) { "mbr" } else { "gpt" }));
+            assert_eq!(layout, expected);
+        }
+    }
+}
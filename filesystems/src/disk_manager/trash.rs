@@ -0,0 +1,243 @@
+// Recycle bins and trash folders - space a user can get back without a reformat
+// Windows keeps deleted files in $RECYCLE.BIN (RECYCLER before Vista), Linux desktops in
+// .Trash-<uid> and macOS in .Trashes, all at the root of the volume they were deleted
+// from. A drive that is "full" is often mostly deleted items, so they are reported on
+// their own and can be purged through the writable ops layer: the host when the volume
+// is mounted, our own writers otherwise. The folders themselves and the per-user folders
+// directly inside them are kept, so the operating system finds its trash where it left it.
+use std::path::Path;
+use moses_core::{Device, DirectoryUsage, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::ops::FilesystemOps;
+use crate::walker::{walk_tree, WalkControl, WalkOptions, WalkOrder};
+use super::installations::existing_volumes;
+
+/// Entries counted per trash folder before its usage is reported as a lower bound
+const TRASH_ENTRY_LIMIT: u64 = 100_000;
+/// Folder settings Windows keeps in each recycle bin; left in place by a purge
+const KEEP_FILES: &[&str] = &["desktop.ini"];
+
+/// Whether a root directory of a volume is a recycle bin or trash folder
+pub fn is_trash_name(name: &str) -> bool {
+    name.eq_ignore_ascii_case("$RECYCLE.BIN")
+        || name.eq_ignore_ascii_case("RECYCLER")
+        || name == ".Trashes"
+        || name.strip_prefix(".Trash-").is_some_and(|uid| !uid.is_empty() && uid.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The trash folders of one volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashReport {
+    /// Mount point, or the volume on the device itself
+    pub location: String,
+    pub filesystem: String,
+    pub folders: Vec<DirectoryUsage>,
+}
+
+impl TrashReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.folders.iter().map(|f| f.bytes).sum()
+    }
+
+    pub fn total_files(&self) -> u64 {
+        self.folders.iter().map(|f| f.files).sum()
+    }
+}
+
+/// What a purge removed, and what it could not
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PurgeReport {
+    pub removed_files: u64,
+    pub removed_bytes: u64,
+    pub removed_directories: u64,
+    /// One entry per file or directory left behind, with the reason
+    pub failures: Vec<String>,
+}
+
+/// Report the trash folders of every readable volume on the device
+pub fn analyze_trash(device: &Device) -> Vec<TrashReport> {
+    existing_volumes(device, false).into_iter()
+        .map(|mut volume| TrashReport {
+            folders: find_trash(volume.ops.as_mut()),
+            location: volume.location,
+            filesystem: volume.filesystem,
+        })
+        .collect()
+}
+
+/// Trash folders at the root of one volume with what they hold
+pub fn find_trash(ops: &mut dyn FilesystemOps) -> Vec<DirectoryUsage> {
+    let root = ops.readdir(Path::new("/")).unwrap_or_default();
+    root.iter()
+        .filter(|e| e.attributes.is_directory && is_trash_name(&e.name))
+        .map(|e| {
+            let (files, bytes) = usage(ops, &Path::new("/").join(&e.name));
+            DirectoryUsage { path: e.name.clone(), files, bytes }
+        })
+        .collect()
+}
+
+fn usage(ops: &mut dyn FilesystemOps, dir: &Path) -> (u64, u64) {
+    let (mut files, mut bytes) = (0, 0);
+    let options = WalkOptions {
+        order: WalkOrder::BreadthFirst,
+        max_entries: Some(TRASH_ENTRY_LIMIT),
+        skip_unreadable: true,
+        ..Default::default()
+    };
+    let _ = walk_tree(ops, dir, &options, |_, entry| {
+        if !entry.attributes.is_directory && !entry.attributes.is_symlink {
+            files += 1;
+            bytes += entry.attributes.size;
+        }
+        Ok(WalkControl::Continue)
+    });
+    (files, bytes)
+}
+
+/// Empty every trash folder on the device's volumes
+pub fn purge_trash(device: &Device) -> Result<PurgeReport, MosesError> {
+    if device.is_system && device.mount_points.is_empty() {
+        return Err(MosesError::UnsafeDevice("Cannot write to an unmounted system disk".to_string()));
+    }
+    let volumes = existing_volumes(device, true);
+    if volumes.is_empty() {
+        return Err(MosesError::NotSupported(format!("No readable filesystem on {}", device.name)));
+    }
+    if device.mount_points.is_empty() {
+        device.ensure_writable()?;
+    }
+
+    let mut report = PurgeReport::default();
+    for mut volume in volumes {
+        // Our writers delete on NTFS only; other unmounted volumes have to be mounted first
+        if !volume.mounted && volume.ops.is_readonly() {
+            report.failures.push(format!(
+                "{}: deleting files is not supported on unmounted {} volumes; mount it and purge again",
+                volume.location, volume.filesystem
+            ));
+            continue;
+        }
+        let ops = volume.ops.as_mut();
+        for folder in find_trash(ops) {
+            log::info!("Purging {} on {} ({} files, {} bytes)", folder.path, volume.location, folder.files, folder.bytes);
+            purge_folder(ops, &Path::new("/").join(&folder.path), &mut report);
+        }
+        if let Err(e) = ops.sync() {
+            report.failures.push(format!("{}: {}", volume.location, e));
+        }
+    }
+    Ok(report)
+}
+
+/// Remove what a trash folder holds, keeping its per-user folders and desktop.ini
+pub fn purge_folder(ops: &mut dyn FilesystemOps, trash: &Path, report: &mut PurgeReport) {
+    for entry in ops.readdir(trash).unwrap_or_default() {
+        let path = trash.join(&entry.name);
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        if entry.attributes.is_directory && !entry.attributes.is_symlink {
+            remove_contents(ops, &path, report);
+        } else if !KEEP_FILES.iter().any(|keep| entry.name.eq_ignore_ascii_case(keep)) {
+            remove_file(ops, &path, entry.attributes.size, report);
+        }
+    }
+}
+
+fn remove_contents(ops: &mut dyn FilesystemOps, dir: &Path, report: &mut PurgeReport) {
+    let entries = match ops.readdir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.failures.push(format!("{}: {}", dir.display(), e));
+            return;
+        }
+    };
+    for entry in entries {
+        if entry.name == "." || entry.name == ".." || KEEP_FILES.iter().any(|keep| entry.name.eq_ignore_ascii_case(keep)) {
+            continue;
+        }
+        let path = dir.join(&entry.name);
+        if entry.attributes.is_directory && !entry.attributes.is_symlink {
+            remove_contents(ops, &path, report);
+            match ops.rmdir(&path) {
+                Ok(()) => report.removed_directories += 1,
+                Err(e) => report.failures.push(format!("{}: {}", path.display(), e)),
+            }
+        } else {
+            remove_file(ops, &path, entry.attributes.size, report);
+        }
+    }
+}
+
+fn remove_file(ops: &mut dyn FilesystemOps, path: &Path, size: u64, report: &mut PurgeReport) {
+    match ops.unlink(path) {
+        Ok(()) => {
+            report.removed_files += 1;
+            report.removed_bytes += size;
+        }
+        Err(e) => report.failures.push(format!("{}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_helpers::create_test_device;
+
+    fn volume_with_trash() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let sid = root.path().join("$RECYCLE.BIN/S-1-5-21-1000");
+        fs::create_dir_all(sid.join("$R4K2J9A.dir")).unwrap();
+        fs::write(sid.join("desktop.ini"), b"[.ShellClassInfo]").unwrap();
+        fs::write(sid.join("$I4K2J9A.jpg"), vec![0u8; 544]).unwrap();
+        fs::write(sid.join("$R4K2J9A.jpg"), vec![0u8; 4000]).unwrap();
+        fs::write(sid.join("$R4K2J9A.dir/inner.txt"), vec![0u8; 100]).unwrap();
+        fs::create_dir_all(root.path().join(".Trash-1000/files")).unwrap();
+        fs::create_dir_all(root.path().join(".Trash-1000/info")).unwrap();
+        fs::write(root.path().join(".Trash-1000/files/old.iso"), vec![0u8; 8000]).unwrap();
+        fs::create_dir_all(root.path().join("Documents")).unwrap();
+        fs::write(root.path().join("Documents/keep.txt"), vec![0u8; 50]).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_trash_names() {
+        assert!(is_trash_name("$Recycle.Bin"));
+        assert!(is_trash_name("RECYCLER"));
+        assert!(is_trash_name(".Trash-1000"));
+        assert!(is_trash_name(".Trashes"));
+        assert!(!is_trash_name(".Trash-"));
+        assert!(!is_trash_name(".Trash-abc"));
+        assert!(!is_trash_name("Trash"));
+    }
+
+    #[test]
+    fn test_trash_reported_separately_and_purged() {
+        let root = volume_with_trash();
+        let mut device = create_test_device("disk", 1 << 30);
+        device.mount_points = vec![root.path().to_path_buf()];
+
+        let reports = analyze_trash(&device);
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].total_files(), reports[0].total_bytes()), (5, 4544 + 100 + 17 + 8000));
+
+        let contents = super::super::contents::summarize_contents(&device);
+        let mut trash: Vec<&str> = contents[0].trash.iter().map(|t| t.path.as_str()).collect();
+        trash.sort();
+        assert_eq!(trash, vec!["$RECYCLE.BIN", ".Trash-1000"]);
+        assert_eq!(contents[0].directories.iter().map(|d| d.path.as_str()).collect::<Vec<_>>(), vec!["Documents"]);
+
+        let purged = purge_trash(&device).unwrap();
+        assert!(purged.failures.is_empty(), "{:?}", purged.failures);
+        assert_eq!((purged.removed_files, purged.removed_bytes, purged.removed_directories), (4, 4544 + 100 + 8000, 1));
+        let sid = root.path().join("$RECYCLE.BIN/S-1-5-21-1000");
+        assert_eq!(fs::read_dir(&sid).unwrap().count(), 1);
+        assert!(sid.join("desktop.ini").exists());
+        assert!(root.path().join(".Trash-1000/files").is_dir());
+        assert!(root.path().join(".Trash-1000/info").is_dir());
+        assert!(root.path().join("Documents/keep.txt").exists());
+        assert_eq!(analyze_trash(&device)[0].total_files(), 1);
+    }
+}
@@ -0,0 +1,359 @@
+// Signature Wiper - zero only the magic bytes of filesystems and partition tables
+// A lighter alternative to a clean: stale signatures stop confusing detection
+// (e.g. a leftover exFAT backup boot region after an ext4 format) while the
+// rest of the disk is left untouched. Every wipe can be undone from a backup.
+use std::io::{BufRead, Read, Write, Seek, SeekFrom};
+use std::path::Path;
+use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::diagnostics::SIGNATURES;
+
+const SECTOR_SIZE: u64 = 512;
+const BACKUP_HEADER: &str = "# moses wipefs backup";
+
+/// A magic value found on the device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoundSignature {
+    pub offset: u64,
+    pub magic: Vec<u8>,
+    pub filesystem: String,
+    pub description: String,
+}
+
+pub struct SignatureWiper;
+
+fn read_bytes<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Option<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+impl SignatureWiper {
+    /// Locate all known signatures on a device (read-only)
+    pub fn scan(device: &Device) -> Result<Vec<FoundSignature>, MosesError> {
+        let file = crate::utils::open_device_with_fallback(device)?;
+        let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+        Ok(Self::scan_reader(&mut reader, device.size))
+    }
+
+    /// Locate all known signatures in a reader of `size` bytes
+    pub fn scan_reader<R: Read + Seek>(reader: &mut R, size: u64) -> Vec<FoundSignature> {
+        Self::scan_region(reader, 0, size, true)
+    }
+
+    /// Locate signatures in the region starting at `start`; partition tables only when `tables` is set
+    fn scan_region<R: Read + Seek>(reader: &mut R, start: u64, size: u64, tables: bool) -> Vec<FoundSignature> {
+        let mut found = Vec::new();
+        let end = start + size;
+        let mut check = |reader: &mut R, offset: u64, magic: &[u8], filesystem: &str, description: String| {
+            if offset + magic.len() as u64 > end {
+                return;
+            }
+            if read_bytes(reader, offset, magic.len()).as_deref() == Some(magic) {
+                found.push(FoundSignature {
+                    offset,
+                    magic: magic.to_vec(),
+                    filesystem: filesystem.to_string(),
+                    description,
+                });
+            }
+        };
+
+        for sig in SIGNATURES {
+            check(reader, start + sig.offset, sig.magic, sig.filesystem, format!("{} magic", sig.filesystem));
+        }
+        check(reader, start + 510, &[0x55, 0xAA], "dos", "MBR / boot sector signature".to_string());
+
+        // Partition tables
+        if tables {
+            check(reader, start + 512, b"EFI PART", "gpt", "GPT primary header".to_string());
+            if size >= 2 * SECTOR_SIZE {
+                check(reader, end - SECTOR_SIZE, b"EFI PART", "gpt", "GPT backup header".to_string());
+            }
+        }
+
+        // Backup boot sectors that detection can fall back to
+        check(reader, start + 6 * SECTOR_SIZE + 82, b"FAT32   ", "fat32", "FAT32 backup boot sector".to_string());
+        check(reader, start + 12 * SECTOR_SIZE + 3, b"EXFAT   ", "exfat", "exFAT backup boot region".to_string());
+        if size >= 2 * SECTOR_SIZE {
+            check(reader, end - SECTOR_SIZE + 3, b"NTFS    ", "ntfs", "NTFS backup boot sector".to_string());
+        }
+
+        found.sort_by_key(|s| s.offset);
+        found.dedup_by_key(|s| s.offset);
+        found
+    }
+
+    /// Find signatures left over from earlier filesystems after formatting as `filesystem`.
+    ///
+    /// Anything that doesn't belong to the new filesystem or the partition table
+    /// currently in use would make other tools detect two filesystems at once.
+    pub fn find_stale_reader<R: Read + Seek>(reader: &mut R, size: u64, filesystem: &str) -> Vec<FoundSignature> {
+        let family = signature_family(filesystem);
+        let uses_boot_sector = family != "ext";
+
+        // A filesystem at offset 0 means the device is not partitioned, whatever sector 0 claims
+        let whole_device = Self::scan_region(reader, 0, size, false)
+            .iter()
+            .any(|s| s.filesystem == family);
+        let (table, partitions) = if whole_device {
+            (None, Vec::new())
+        } else {
+            crate::diagnostics::read_partition_table(reader)
+        };
+
+        let mut stale: Vec<FoundSignature> = Self::scan_region(reader, 0, size, true)
+            .into_iter()
+            .filter(|s| {
+                let in_use = match s.filesystem.as_str() {
+                    "dos" => table.is_some() || uses_boot_sector,
+                    "gpt" => table.as_deref() == Some("gpt"),
+                    fs => fs == family,
+                };
+                !in_use
+            })
+            .collect();
+
+        for (start, len) in partitions {
+            let len = len.min(size.saturating_sub(start));
+            stale.extend(Self::scan_region(reader, start, len, false)
+                .into_iter()
+                .filter(|s| !(s.filesystem == family || (s.filesystem == "dos" && uses_boot_sector))));
+        }
+
+        stale.sort_by_key(|s| s.offset);
+        stale.dedup_by_key(|s| s.offset);
+        stale
+    }
+
+    /// Zero stale signatures after a format and return what was removed
+    pub fn remove_stale_signatures(device: &Device, filesystem: &str) -> Result<Vec<FoundSignature>, MosesError> {
+        let stale = {
+            let file = crate::utils::open_device_with_fallback(device)?;
+            let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+            Self::find_stale_reader(&mut reader, device.size, filesystem)
+        };
+        if stale.is_empty() {
+            return Ok(stale);
+        }
+
+        log::info!("Removing {} stale signature(s) left on {} after formatting as {}",
+            stale.len(), device.name, filesystem);
+        let mut file = Self::open_for_write(device)?;
+        Self::wipe_signatures(&mut file, &stale)?;
+        file.sync_all()
+            .map_err(|e| MosesError::Other(format!("Failed to sync after wipe: {}", e)))?;
+        Ok(stale)
+    }
+
+    /// Run the post-format cleanup and describe it for the format result.
+    ///
+    /// Failures are reported but never fail the format itself.
+    pub fn cleanup_after_format(device: &Device, filesystem: &str) -> Option<String> {
+        match Self::remove_stale_signatures(device, filesystem) {
+            Ok(removed) if removed.is_empty() => None,
+            Ok(removed) => {
+                let list: Vec<String> = removed.iter()
+                    .map(|s| format!("{} at 0x{:X}", s.description, s.offset))
+                    .collect();
+                Some(format!("Removed {} stale signature(s): {}", removed.len(), list.join(", ")))
+            }
+            Err(e) => {
+                log::warn!("Stale signature cleanup failed on {}: {}", device.name, e);
+                Some(format!("Warning: stale signature cleanup failed: {}", e))
+            }
+        }
+    }
+
+    /// Zero the given signatures, saving the original bytes to `backup` first
+    pub fn wipe(device: &Device, signatures: &[FoundSignature], backup: Option<&Path>) -> Result<(), MosesError> {
+        log::info!("Wiping {} signature(s) on {}", signatures.len(), device.name);
+
+        if device.is_system {
+            return Err(MosesError::InvalidInput(
+                "Cannot wipe signatures on system disk - this would destroy your OS!".to_string()
+            ));
+        }
+
+        if let Some(path) = backup {
+            let mut file = std::fs::File::create(path)
+                .map_err(|e| MosesError::Other(format!("Failed to create backup file {}: {}", path.display(), e)))?;
+            write_backup(&mut file, &device.id, signatures)?;
+            file.sync_all()
+                .map_err(|e| MosesError::Other(format!("Failed to sync backup file: {}", e)))?;
+        }
+
+        let mut file = Self::open_for_write(device)?;
+        moses_core::FilesystemCache::global().invalidate(device);
+        Self::wipe_signatures(&mut file, signatures)?;
+        file.sync_all()
+            .map_err(|e| MosesError::Other(format!("Failed to sync after wipe: {}", e)))?;
+        Ok(())
+    }
+
+    /// Write back the bytes saved by [`SignatureWiper::wipe`]
+    pub fn restore(device: &Device, backup: &Path) -> Result<usize, MosesError> {
+        log::info!("Restoring signatures on {} from {}", device.name, backup.display());
+
+        let file = std::fs::File::open(backup)
+            .map_err(|e| MosesError::Other(format!("Failed to open backup file {}: {}", backup.display(), e)))?;
+        let entries = read_backup(std::io::BufReader::new(file))?;
+
+        let mut file = Self::open_for_write(device)?;
+        moses_core::FilesystemCache::global().invalidate(device);
+        for (offset, bytes) in &entries {
+            patch_bytes(&mut file, *offset, bytes)?;
+        }
+        file.sync_all()
+            .map_err(|e| MosesError::Other(format!("Failed to sync after restore: {}", e)))?;
+        Ok(entries.len())
+    }
+
+    /// Zero signatures through any writer, using whole-sector read-modify-write
+    pub fn wipe_signatures<F: Read + Write + Seek>(file: &mut F, signatures: &[FoundSignature]) -> Result<(), MosesError> {
+        for sig in signatures {
+            log::info!("Zeroing {} ({} bytes) at 0x{:X}", sig.description, sig.magic.len(), sig.offset);
+            patch_bytes(file, sig.offset, &vec![0u8; sig.magic.len()])?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn open_for_write(device: &Device) -> Result<std::fs::File, MosesError> {
+        #[cfg(target_os = "windows")]
+        {
+            crate::utils::open_device_write(device)
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&device.id)
+                .map_err(MosesError::IoError)
+        }
+    }
+}
+
+/// Map a formatter name onto the signature table's filesystem names
+fn signature_family(filesystem: &str) -> &str {
+    match filesystem {
+        "ext2" | "ext3" | "ext4" => "ext",
+        other => other,
+    }
+}
+
+/// Overwrite bytes at an arbitrary offset; raw devices only accept whole sectors
+fn patch_bytes<F: Read + Write + Seek>(file: &mut F, offset: u64, bytes: &[u8]) -> Result<(), MosesError> {
+    let start = offset / SECTOR_SIZE * SECTOR_SIZE;
+    let end = (offset + bytes.len() as u64).div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+    let mut sectors = read_bytes(file, start, (end - start) as usize)
+        .ok_or_else(|| MosesError::Other(format!("Failed to read sectors at 0x{:X}", start)))?;
+
+    let rel = (offset - start) as usize;
+    sectors[rel..rel + bytes.len()].copy_from_slice(bytes);
+
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to 0x{:X}: {}", start, e)))?;
+    file.write_all(&sectors)
+        .map_err(|e| MosesError::Other(format!("Failed to write sectors at 0x{:X}: {}", start, e)))?;
+    Ok(())
+}
+
+/// Write a backup as text: a header, then one "offset hex-bytes" line per signature
+pub fn write_backup<W: Write>(writer: &mut W, device_id: &str, signatures: &[FoundSignature]) -> Result<(), MosesError> {
+    let mut out = format!("{} of {}\n", BACKUP_HEADER, device_id);
+    for sig in signatures {
+        out.push_str(&format!("{:#x} {} {}\n", sig.offset, hex::encode(&sig.magic), sig.filesystem));
+    }
+    writer.write_all(out.as_bytes())
+        .map_err(|e| MosesError::Other(format!("Failed to write backup: {}", e)))
+}
+
+/// Parse a backup written by [`write_backup`]
+pub fn read_backup<R: BufRead>(reader: R) -> Result<Vec<(u64, Vec<u8>)>, MosesError> {
+    let mut lines = reader.lines();
+    match lines.next() {
+        Some(Ok(header)) if header.starts_with(BACKUP_HEADER) => {}
+        _ => return Err(MosesError::InvalidInput("Not a moses wipefs backup file".to_string())),
+    }
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let line = line.map_err(|e| MosesError::Other(format!("Failed to read backup: {}", e)))?;
+        let mut parts = line.split_whitespace();
+        let (Some(offset), Some(bytes)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let offset = u64::from_str_radix(offset.trim_start_matches("0x"), 16)
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid offset '{}' in backup: {}", offset, e)))?;
+        let bytes = hex::decode(bytes)
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid bytes in backup at 0x{:X}: {}", offset, e)))?;
+        entries.push((offset, bytes));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wipe_and_restore_round_trip() {
+        let size = 64 * 1024u64;
+        let mut disk = vec![0u8; size as usize];
+        disk[3..11].copy_from_slice(b"EXFAT   ");
+        disk[510] = 0x55;
+        disk[511] = 0xAA;
+        disk[12 * 512 + 3..12 * 512 + 11].copy_from_slice(b"EXFAT   ");
+        disk[4000] = 0x42; // unrelated data must survive
+        let original = disk.clone();
+        let mut cursor = Cursor::new(disk);
+
+        let found = SignatureWiper::scan_reader(&mut cursor, size);
+        let names: Vec<_> = found.iter().map(|s| s.description.as_str()).collect();
+        assert_eq!(names, vec!["exfat magic", "MBR / boot sector signature", "exFAT backup boot region"]);
+
+        let mut backup = Vec::new();
+        write_backup(&mut backup, "test.img", &found).unwrap();
+        SignatureWiper::wipe_signatures(&mut cursor, &found).unwrap();
+        assert!(SignatureWiper::scan_reader(&mut cursor, size).is_empty());
+        assert_eq!(cursor.get_ref()[4000], 0x42);
+
+        for (offset, bytes) in read_backup(Cursor::new(backup)).unwrap() {
+            patch_bytes(&mut cursor, offset, &bytes).unwrap();
+        }
+        assert_eq!(cursor.into_inner(), original);
+    }
+
+    #[test]
+    fn test_stale_signatures_after_ext4_format() {
+        let size = 1024 * 1024u64;
+        let mut disk = vec![0u8; size as usize];
+        // Fresh ext4 superblock...
+        disk[1024 + 56] = 0x53;
+        disk[1024 + 57] = 0xEF;
+        // ...on top of an old exFAT volume whose boot sector and backup survived
+        disk[3..11].copy_from_slice(b"EXFAT   ");
+        disk[510] = 0x55;
+        disk[511] = 0xAA;
+        disk[12 * 512 + 3..12 * 512 + 11].copy_from_slice(b"EXFAT   ");
+        let mut cursor = Cursor::new(disk);
+
+        let stale = SignatureWiper::find_stale_reader(&mut cursor, size, "ext4");
+        let offsets: Vec<u64> = stale.iter().map(|s| s.offset).collect();
+        assert_eq!(offsets, vec![3, 510, 12 * 512 + 3]);
+
+        // Seen from a fresh exFAT volume, only the ext superblock is stale
+        let stale = SignatureWiper::find_stale_reader(&mut cursor, size, "exfat");
+        let offsets: Vec<u64> = stale.iter().map(|s| s.offset).collect();
+        assert_eq!(offsets, vec![1024 + 56]);
+    }
+
+    #[test]
+    fn test_rejects_foreign_backup() {
+        assert!(read_backup(Cursor::new(b"0x0 55aa\n".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+// Structure-aware "explain this sector"
+// Given an offset, identify the on-disk structure that starts there and
+// pretty-print it field by field with validity flags. Field layouts come
+// from the hex viewer so both tools agree on names and offsets.
+
+use std::io::{Read, Seek, SeekFrom};
+use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::device_reader::AlignedDeviceReader;
+use crate::families::ext::ext4_native::core::constants::EXT4_SUPER_MAGIC;
+use crate::families::ntfs::ntfs::{MFT_RECORD_SIGNATURE, MFT_RECORD_BAD_SIGNATURE, NTFS_SIGNATURE};
+use crate::families::fat::exfat::reader::EXFAT_SIGNATURE;
+use crate::hexview::{self, FieldKind, StructureLayout, field};
+
+pub static MFT_RECORD_LAYOUT: StructureLayout = StructureLayout {
+    name: "MFT record",
+    size: 48,
+    fields: &[
+        field(0x00, 4, "signature", FieldKind::Ascii),
+        field(0x04, 2, "usa_offset", FieldKind::U16),
+        field(0x06, 2, "usa_count", FieldKind::U16),
+        field(0x08, 8, "lsn", FieldKind::U64),
+        field(0x10, 2, "sequence_number", FieldKind::U16),
+        field(0x12, 2, "link_count", FieldKind::U16),
+        field(0x14, 2, "attrs_offset", FieldKind::U16),
+        field(0x16, 2, "flags", FieldKind::U16),
+        field(0x18, 4, "bytes_used", FieldKind::U32),
+        field(0x1C, 4, "bytes_allocated", FieldKind::U32),
+        field(0x20, 8, "base_mft_record", FieldKind::U64),
+        field(0x28, 2, "next_attr_id", FieldKind::U16),
+        field(0x2C, 4, "mft_record_number", FieldKind::U32),
+    ],
+};
+
+pub static FAT_DIR_ENTRY_LAYOUT: StructureLayout = StructureLayout {
+    name: "FAT directory entry",
+    size: 32,
+    fields: &[
+        field(0, 8, "name", FieldKind::Ascii),
+        field(8, 3, "extension", FieldKind::Ascii),
+        field(11, 1, "attributes", FieldKind::U8),
+        field(12, 1, "nt_reserved", FieldKind::U8),
+        field(13, 1, "create_time_tenth", FieldKind::U8),
+        field(14, 2, "create_time", FieldKind::U16),
+        field(16, 2, "create_date", FieldKind::U16),
+        field(18, 2, "access_date", FieldKind::U16),
+        field(20, 2, "first_cluster_hi", FieldKind::U16),
+        field(22, 2, "write_time", FieldKind::U16),
+        field(24, 2, "write_date", FieldKind::U16),
+        field(26, 2, "first_cluster_lo", FieldKind::U16),
+        field(28, 4, "file_size", FieldKind::U32),
+    ],
+};
+
+/// A decoded field with an optional validity verdict
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExplainedField {
+    pub name: String,
+    pub offset: u64,
+    pub length: usize,
+    pub value: String,
+    /// `None` when the field has no constraint we can check
+    pub valid: Option<bool>,
+    pub note: Option<String>,
+}
+
+/// A structure identified at a given offset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructureExplanation {
+    pub structure: String,
+    pub offset: u64,
+    pub size: usize,
+    pub fields: Vec<ExplainedField>,
+    /// True when every checked field passed
+    pub valid: bool,
+}
+
+impl StructureExplanation {
+    fn from_layout(layout: &StructureLayout, offset: u64, raw: &[u8]) -> Self {
+        let fields = layout.fields.iter()
+            .filter(|f| f.offset + f.length <= raw.len())
+            .map(|f| ExplainedField {
+                name: f.name.to_string(),
+                offset: offset + f.offset as u64,
+                length: f.length,
+                value: hexview::decode_field(&raw[f.offset..f.offset + f.length], f.kind),
+                valid: None,
+                note: None,
+            })
+            .collect();
+        Self {
+            structure: layout.name.to_string(),
+            offset,
+            size: layout.size,
+            fields,
+            valid: true,
+        }
+    }
+
+    /// Record a validity verdict for a field
+    fn check(&mut self, name: &str, ok: bool, note: &str) {
+        if let Some(f) = self.fields.iter_mut().find(|f| f.name == name) {
+            f.valid = Some(ok);
+            if !ok {
+                f.note = Some(note.to_string());
+            }
+        }
+        if !ok {
+            self.valid = false;
+        }
+    }
+
+    /// Multi-line text rendering for the CLI and support logs
+    pub fn to_text(&self) -> String {
+        let mut out = format!(
+            "{} at 0x{:X} ({} bytes) - {}\n",
+            self.structure, self.offset, self.size,
+            if self.valid { "looks valid" } else { "INCONSISTENT" }
+        );
+        for f in &self.fields {
+            let flag = match f.valid {
+                Some(true) => "ok ",
+                Some(false) => "BAD",
+                None => "   ",
+            };
+            out.push_str(&format!("  [{}] +0x{:03X} {:<26} {}", flag, f.offset - self.offset, f.name, f.value));
+            if let Some(note) = &f.note {
+                out.push_str(&format!("  ({})", note));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn u16_at(raw: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([raw[off], raw[off + 1]])
+}
+
+fn u32_at(raw: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([raw[off], raw[off + 1], raw[off + 2], raw[off + 3]])
+}
+
+fn u64_at(raw: &[u8], off: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&raw[off..off + 8]);
+    u64::from_le_bytes(b)
+}
+
+fn explain_gpt_header(offset: u64, raw: &[u8]) -> StructureExplanation {
+    let mut e = StructureExplanation::from_layout(&hexview::GPT_HEADER_LAYOUT, offset, raw);
+    let header_size = u32_at(raw, 12) as usize;
+    e.check("revision", u32_at(raw, 8) == 0x0001_0000, "expected 1.0 (0x00010000)");
+    e.check("header_size", (92..=512).contains(&header_size), "must be between 92 and 512");
+    if (92..=raw.len()).contains(&header_size) {
+        let mut copy = raw[..header_size].to_vec();
+        copy[16..20].fill(0);
+        e.check("header_crc32", crc32fast::hash(&copy) == u32_at(raw, 16), "CRC32 mismatch");
+    }
+    e.check("current_lba", u64_at(raw, 24) == offset / 512, "does not match the LBA it was read from");
+    e.check("first_usable_lba", u64_at(raw, 40) <= u64_at(raw, 48), "first usable LBA is after last usable LBA");
+    e.check("partition_entry_size", u32_at(raw, 84) >= 128 && u32_at(raw, 84).is_power_of_two(),
+        "must be a power of two >= 128");
+    e
+}
+
+fn explain_ext_superblock(offset: u64, raw: &[u8]) -> StructureExplanation {
+    let mut e = StructureExplanation::from_layout(&hexview::EXT_SUPERBLOCK_LAYOUT, offset, raw);
+    let inode_size = u16_at(raw, 88);
+    e.check("s_log_block_size", u32_at(raw, 24) <= 6, "block size above 64KiB");
+    e.check("s_blocks_per_group", u32_at(raw, 32) > 0, "zero blocks per group");
+    e.check("s_inodes_per_group", u32_at(raw, 40) > 0, "zero inodes per group");
+    e.check("s_state", matches!(u16_at(raw, 58), 1..=7), "unknown state bits");
+    e.check("s_errors", matches!(u16_at(raw, 60), 1..=3), "unknown error behaviour");
+    e.check("s_rev_level", u32_at(raw, 76) <= 1, "unknown revision");
+    e.check("s_inode_size", u32_at(raw, 76) == 0 || (inode_size >= 128 && inode_size.is_power_of_two()),
+        "must be a power of two >= 128");
+    e.check("s_free_blocks_count_lo", u32_at(raw, 12) <= u32_at(raw, 4), "more free blocks than blocks");
+    e.check("s_free_inodes_count", u32_at(raw, 16) <= u32_at(raw, 0), "more free inodes than inodes");
+    e
+}
+
+fn explain_mft_record(offset: u64, raw: &[u8]) -> StructureExplanation {
+    let mut e = StructureExplanation::from_layout(&MFT_RECORD_LAYOUT, offset, raw);
+    let usa_offset = u16_at(raw, 0x04);
+    let attrs_offset = u16_at(raw, 0x14) as u32;
+    let used = u32_at(raw, 0x18);
+    let allocated = u32_at(raw, 0x1C);
+    e.check("signature", &raw[0..4] == MFT_RECORD_SIGNATURE, "record marked BAAD by chkdsk");
+    e.check("usa_offset", usa_offset.is_multiple_of(2) && (0x28..0x200).contains(&usa_offset),
+        "update sequence array out of range");
+    e.check("bytes_allocated", allocated.is_power_of_two() && (1024..=4096).contains(&allocated),
+        "record size should be 1024 or 4096");
+    e.check("bytes_used", used <= allocated, "used size exceeds allocated size");
+    e.check("attrs_offset", attrs_offset.is_multiple_of(8) && attrs_offset < used, "first attribute outside used area");
+    e.check("flags", u16_at(raw, 0x16) & !0x000F == 0, "unknown flag bits");
+    e
+}
+
+fn explain_boot_sector(layout: &'static StructureLayout, offset: u64, raw: &[u8]) -> StructureExplanation {
+    let mut e = StructureExplanation::from_layout(layout, offset, raw);
+    e.check("jump_boot", raw[0] == 0xEB || raw[0] == 0xE9, "no x86 jump instruction");
+    e.check("boot_signature", raw[510] == 0x55 && raw[511] == 0xAA, "missing 0x55AA");
+    if std::ptr::eq(layout, &hexview::EXFAT_BOOT_SECTOR_LAYOUT) {
+        e.check("bytes_per_sector_shift", (9..=12).contains(&raw[108]), "must be 9..12");
+        e.check("sectors_per_cluster_shift", raw[108] as u16 + raw[109] as u16 <= 25, "cluster size above 32MiB");
+        e.check("number_of_fats", raw[110] == 1 || raw[110] == 2, "must be 1 or 2");
+    } else {
+        let bps = u16_at(raw, 11);
+        e.check("bytes_per_sector", bps.is_power_of_two() && (512..=4096).contains(&bps), "must be 512..4096");
+        e.check("sectors_per_cluster", raw[13].is_power_of_two(), "must be a power of two");
+        if !std::ptr::eq(layout, &hexview::NTFS_BOOT_SECTOR_LAYOUT) {
+            e.check("num_fats", raw[16] >= 1, "no FAT copies");
+            e.check("media", raw[21] == 0xF0 || raw[21] >= 0xF8, "invalid media descriptor");
+        }
+    }
+    e
+}
+
+/// Check if a 32-byte record looks like a short-name FAT directory entry
+fn looks_like_fat_dir_entry(raw: &[u8]) -> bool {
+    let attr = raw[11];
+    if attr == 0x0F || attr & 0xC0 != 0 {
+        return false;
+    }
+    let first = raw[0];
+    if first == 0x00 || first == 0x20 {
+        return false;
+    }
+    let valid_char = |c: u8| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b' '
+        || b"!#$%&'()-@^_`{}~".contains(&c) || c >= 0x80;
+    (first == 0xE5 || first == 0x05 || valid_char(first)) && raw[1..11].iter().all(|&c| valid_char(c))
+}
+
+fn explain_fat_dir_entry(offset: u64, raw: &[u8]) -> StructureExplanation {
+    let mut e = StructureExplanation::from_layout(&FAT_DIR_ENTRY_LAYOUT, offset, raw);
+    let date_ok = |d: u16| d == 0 || ((1..=12).contains(&((d >> 5) & 0x0F)) && (d & 0x1F) >= 1);
+    let time_ok = |t: u16| (t >> 11) < 24 && ((t >> 5) & 0x3F) < 60 && (t & 0x1F) < 30;
+    e.check("attributes", raw[11] & 0xC0 == 0, "reserved attribute bits set");
+    e.check("create_time_tenth", raw[13] < 200, "must be below 200");
+    e.check("create_date", date_ok(u16_at(raw, 16)), "invalid date");
+    e.check("write_date", date_ok(u16_at(raw, 24)), "invalid date");
+    e.check("create_time", time_ok(u16_at(raw, 14)), "invalid time");
+    e.check("write_time", time_ok(u16_at(raw, 22)), "invalid time");
+    if raw[11] & 0x10 != 0 {
+        e.check("file_size", u32_at(raw, 28) == 0, "directories must have size 0");
+    }
+    e
+}
+
+/// Identify the structure starting at `offset` and decode it.
+///
+/// Returns `Ok(None)` if nothing recognisable starts there.
+pub fn explain_at<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<StructureExplanation>, MosesError> {
+    reader.seek(SeekFrom::Start(offset))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to {:#x}: {}", offset, e)))?;
+
+    // Read up to 1KiB; structures near the end of the device may be shorter
+    let mut raw = Vec::with_capacity(1024);
+    reader.take(1024).read_to_end(&mut raw)
+        .map_err(|e| MosesError::Other(format!("Failed to read at {:#x}: {}", offset, e)))?;
+
+    if raw.len() >= 92 && &raw[0..8] == b"EFI PART" {
+        return Ok(Some(explain_gpt_header(offset, &raw)));
+    }
+    if raw.len() >= 1024 && u16_at(&raw, 56) == EXT4_SUPER_MAGIC {
+        return Ok(Some(explain_ext_superblock(offset, &raw)));
+    }
+    if raw.len() >= 48 && (&raw[0..4] == MFT_RECORD_SIGNATURE || &raw[0..4] == MFT_RECORD_BAD_SIGNATURE) {
+        return Ok(Some(explain_mft_record(offset, &raw)));
+    }
+    if raw.len() >= 512 {
+        if &raw[3..11] == NTFS_SIGNATURE {
+            return Ok(Some(explain_boot_sector(&hexview::NTFS_BOOT_SECTOR_LAYOUT, offset, &raw)));
+        }
+        if raw[3..11] == EXFAT_SIGNATURE {
+            return Ok(Some(explain_boot_sector(&hexview::EXFAT_BOOT_SECTOR_LAYOUT, offset, &raw)));
+        }
+        if (raw[0] == 0xEB || raw[0] == 0xE9) && (&raw[82..87] == b"FAT32" || &raw[54..57] == b"FAT") {
+            return Ok(Some(explain_boot_sector(&hexview::FAT_BOOT_SECTOR_LAYOUT, offset, &raw)));
+        }
+        if offset == 0 && raw[510] == 0x55 && raw[511] == 0xAA {
+            return Ok(Some(StructureExplanation::from_layout(&hexview::MBR_LAYOUT, offset, &raw)));
+        }
+    }
+    if raw.len() >= 32 && offset.is_multiple_of(32) && looks_like_fat_dir_entry(&raw) {
+        return Ok(Some(explain_fat_dir_entry(offset, &raw[..32])));
+    }
+
+    Ok(None)
+}
+
+/// Explain the structure at `offset` on a device (read-only)
+pub fn explain_device_offset(device: &Device, offset: u64) -> Result<Option<StructureExplanation>, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = AlignedDeviceReader::new(file);
+    explain_at(&mut reader, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_explain_mft_record() {
+        let mut disk = vec![0u8; 4096];
+        let rec = &mut disk[1024..2048];
+        rec[0..4].copy_from_slice(b"FILE");
+        rec[0x04..0x06].copy_from_slice(&0x30u16.to_le_bytes());
+        rec[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        rec[0x16..0x18].copy_from_slice(&1u16.to_le_bytes());
+        rec[0x18..0x1C].copy_from_slice(&0x1A0u32.to_le_bytes());
+        rec[0x1C..0x20].copy_from_slice(&1024u32.to_le_bytes());
+        let mut cursor = Cursor::new(disk);
+
+        let e = explain_at(&mut cursor, 1024).unwrap().unwrap();
+        assert_eq!(e.structure, "MFT record");
+        assert!(e.valid, "{}", e.to_text());
+    }
+
+    #[test]
+    fn test_explain_bad_gpt_crc() {
+        let mut disk = vec![0u8; 2048];
+        let hdr = &mut disk[512..1024];
+        hdr[0..8].copy_from_slice(b"EFI PART");
+        hdr[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        hdr[12..16].copy_from_slice(&92u32.to_le_bytes());
+        hdr[24..32].copy_from_slice(&1u64.to_le_bytes());
+        hdr[84..88].copy_from_slice(&128u32.to_le_bytes());
+        hdr[16..20].copy_from_slice(&0xDEADBEEFu32.to_le_bytes());
+        let mut cursor = Cursor::new(disk);
+
+        let e = explain_at(&mut cursor, 512).unwrap().unwrap();
+        assert_eq!(e.structure, "GPT header");
+        assert!(!e.valid);
+        let crc = e.fields.iter().find(|f| f.name == "header_crc32").unwrap();
+        assert_eq!(crc.valid, Some(false));
+    }
+
+    #[test]
+    fn test_explain_fat_dir_entry() {
+        let mut disk = vec![0u8; 512];
+        disk[64..75].copy_from_slice(b"README  TXT");
+        disk[64 + 11] = 0x20;
+        let mut cursor = Cursor::new(disk);
+
+        let e = explain_at(&mut cursor, 64).unwrap().unwrap();
+        assert_eq!(e.structure, "FAT directory entry");
+        assert!(explain_at(&mut cursor, 128).unwrap().is_none());
+    }
+}
@@ -0,0 +1,262 @@
+// Archive export of a directory tree
+// Streams a subtree from any FilesystemOps reader into a tar (optionally gzip or zstd
+// compressed) or zip archive, so data can be saved before a reformat without mounting.
+// Files are selected with the transfer engine's filters. Nothing is written to the
+// source; symlinks are skipped because the ops trait cannot read link targets.
+use crate::ops::{FileAttributes, FilesystemOps};
+use crate::transfer::{walk, FileReader, Selection, TransferFilter};
+use moses_core::MosesError;
+use serde::{Deserialize, Serialize};
+use std::io::{Seek, Write};
+use std::path::Path;
+
+/// Archive container and compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Pick the format from an output file name (.tar, .tar.gz/.tgz, .tar.zst/.tzst, .zip)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// What ended up in the archive
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportSummary {
+    pub files: u64,
+    pub directories: u64,
+    pub bytes: u64,
+    /// Archive paths of entries left out (symlinks)
+    pub skipped: Vec<String>,
+    /// Files the filter left out
+    #[serde(default)]
+    pub excluded_files: u64,
+}
+
+/// Write the files `filter` selects under `root` to `out` as `format`; entry names are
+/// relative to `root`
+pub fn export_tree<W: Write + Seek>(
+    fs: &mut dyn FilesystemOps,
+    root: &Path,
+    out: W,
+    format: ArchiveFormat,
+    filter: &TransferFilter,
+) -> Result<ExportSummary, MosesError> {
+    let selection = filter.compile()?;
+    let chunk_size = filter.chunk_size();
+    let attributes = fs.stat(root)?;
+    if !attributes.is_directory {
+        return Err(MosesError::InvalidInput(format!("{} is not a directory", root.display())));
+    }
+    let mut summary = ExportSummary::default();
+    match format {
+        ArchiveFormat::Tar => {
+            write_tar(fs, root, &selection, chunk_size, out, &mut summary)?;
+        }
+        ArchiveFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+            write_tar(fs, root, &selection, chunk_size, encoder, &mut summary)?.finish()?;
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = zstd::Encoder::new(out, 3)?;
+            write_tar(fs, root, &selection, chunk_size, encoder, &mut summary)?.finish()?;
+        }
+        ArchiveFormat::Zip => write_zip(fs, root, &selection, chunk_size, out, &mut summary)?,
+    }
+    Ok(summary)
+}
+
+fn write_tar<W: Write>(
+    fs: &mut dyn FilesystemOps,
+    root: &Path,
+    selection: &Selection,
+    chunk_size: u32,
+    out: W,
+    summary: &mut ExportSummary,
+) -> Result<W, MosesError> {
+    let mut builder = tar::Builder::new(out);
+    let (excluded, _) = walk(fs, root, "", selection, &mut |fs, path, name, attributes| {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(mode(attributes));
+        header.set_mtime(attributes.modified.unwrap_or(0));
+        header.set_uid(attributes.owner.unwrap_or(0) as u64);
+        header.set_gid(attributes.group.unwrap_or(0) as u64);
+        if attributes.is_symlink {
+            summary.skipped.push(name);
+        } else if attributes.is_directory {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", name), std::io::empty())?;
+            summary.directories += 1;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(attributes.size);
+            builder.append_data(&mut header, &name, FileReader::new(fs, path, attributes.size).with_chunk_size(chunk_size))?;
+            summary.files += 1;
+            summary.bytes += attributes.size;
+        }
+        Ok(())
+    })?;
+    summary.excluded_files = excluded;
+    Ok(builder.into_inner()?)
+}
+
+fn write_zip<W: Write + Seek>(
+    fs: &mut dyn FilesystemOps,
+    root: &Path,
+    selection: &Selection,
+    chunk_size: u32,
+    out: W,
+    summary: &mut ExportSummary,
+) -> Result<(), MosesError> {
+    let mut archive = zip::ZipWriter::new(out);
+    let (excluded, _) = walk(fs, root, "", selection, &mut |fs, path, name, attributes| {
+        let mut options = zip::write::SimpleFileOptions::default()
+            .unix_permissions(mode(attributes))
+            .large_file(attributes.size >= u32::MAX as u64);
+        if let Some(modified) = attributes.modified.and_then(zip_time) {
+            options = options.last_modified_time(modified);
+        }
+        if attributes.is_symlink {
+            summary.skipped.push(name);
+        } else if attributes.is_directory {
+            archive.add_directory(name, options).map_err(zip_error)?;
+            summary.directories += 1;
+        } else {
+            archive.start_file(name, options).map_err(zip_error)?;
+            std::io::copy(&mut FileReader::new(fs, path, attributes.size).with_chunk_size(chunk_size), &mut archive)?;
+            summary.files += 1;
+            summary.bytes += attributes.size;
+        }
+        Ok(())
+    })?;
+    summary.excluded_files = excluded;
+    archive.finish().map_err(zip_error)?;
+    Ok(())
+}
+
+/// Unix mode for an entry, with sensible defaults when the filesystem has none (FAT, exFAT)
+fn mode(attributes: &FileAttributes) -> u32 {
+    match attributes.permissions & 0o7777 {
+        0 if attributes.is_directory => 0o755,
+        0 => 0o644,
+        permissions => permissions,
+    }
+}
+
+/// Zip timestamps are DOS dates: local fields, 1980-2107, two-second resolution
+fn zip_time(unix: u64) -> Option<zip::DateTime> {
+    use chrono::{Datelike, Timelike};
+    let time = chrono::DateTime::from_timestamp(unix as i64, 0)?.naive_utc();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+fn zip_error(e: zip::result::ZipError) -> MosesError {
+    MosesError::Other(format!("Failed to write zip archive: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+    use crate::transfer::CHUNK_SIZE;
+    use std::io::{Cursor, Read};
+
+    fn sample_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("photos/2024")).unwrap();
+        std::fs::write(dir.path().join("readme.txt"), b"hello").unwrap();
+        std::fs::write(dir.path().join("photos/2024/img.raw"), vec![7u8; 3 * CHUNK_SIZE as usize / 2]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_export_tar_zst() {
+        let dir = sample_tree();
+        let mut fs = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let summary = export_tree(&mut fs, Path::new("/photos"), &mut out, ArchiveFormat::TarZst, &TransferFilter::default()).unwrap();
+        assert_eq!(summary.files, 1);
+        assert_eq!(summary.directories, 1);
+
+        let decoded = zstd::decode_all(Cursor::new(out.into_inner())).unwrap();
+        let mut archive = tar::Archive::new(Cursor::new(decoded));
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            names.push(entry.path().unwrap().display().to_string());
+            if entry.header().entry_type().is_file() {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                assert_eq!(data.len(), 3 * CHUNK_SIZE as usize / 2);
+                assert!(entry.header().mtime().unwrap() > 0);
+            }
+        }
+        assert_eq!(names, ["2024/", "2024/img.raw"]);
+    }
+
+    #[test]
+    fn test_export_zip() {
+        let dir = sample_tree();
+        let mut fs = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let summary = export_tree(&mut fs, Path::new("/"), &mut out, ArchiveFormat::Zip, &TransferFilter::default()).unwrap();
+        assert_eq!((summary.files, summary.directories), (2, 2));
+        assert_eq!(summary.bytes, 5 + 3 * CHUNK_SIZE as u64 / 2);
+
+        let mut archive = zip::ZipArchive::new(out).unwrap();
+        let mut readme = String::new();
+        archive.by_name("readme.txt").unwrap().read_to_string(&mut readme).unwrap();
+        assert_eq!(readme, "hello");
+        assert!(archive.by_name("photos/2024/").unwrap().is_dir());
+    }
+
+    #[test]
+    fn test_export_with_filter() {
+        let dir = sample_tree();
+        let mut fs = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+        let filter = TransferFilter { include: vec!["*.txt".to_string()], ..Default::default() };
+        let mut out = Cursor::new(Vec::new());
+        let summary = export_tree(&mut fs, Path::new("/"), &mut out, ArchiveFormat::Tar, &filter).unwrap();
+        assert_eq!((summary.files, summary.directories, summary.excluded_files), (1, 0, 1));
+
+        out.set_position(0);
+        let names: Vec<String> = tar::Archive::new(out).entries().unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(names, ["readme.txt"]);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ArchiveFormat::from_path(Path::new("out.tar.zst")), Some(ArchiveFormat::TarZst));
+        assert_eq!(ArchiveFormat::from_path(Path::new("OUT.TGZ")), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_path(Path::new("backup.zip")), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::from_path(Path::new("backup.7z")), None);
+    }
+}
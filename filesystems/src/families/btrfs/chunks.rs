@@ -0,0 +1,103 @@
+// Btrfs chunk map - from logical addresses to places on this device
+// Every tree block and extent is addressed by a logical byte number. Chunks map ranges of
+// logical space to one or more stripes on the volume's devices. The superblock carries the
+// system chunks that hold the chunk tree, so the tree can be read; the chunk tree then
+// holds every other chunk. Only the device being read is open, so single, DUP and the
+// mirrored profiles read from their copy on it; striped profiles, and chunks whose copies
+// are all on other devices, cannot be read.
+use super::structures::{corrupt, Chunk, Key, BLOCK_GROUP_STRIPED, CHUNK_ITEM_KEY, KEY_SIZE};
+use moses_core::MosesError;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMap {
+    /// Chunks by their first logical address
+    chunks: BTreeMap<u64, Chunk>,
+    /// The device open for reading
+    devid: u64,
+}
+
+impl ChunkMap {
+    pub fn new(devid: u64) -> Self {
+        Self { chunks: BTreeMap::new(), devid }
+    }
+
+    /// The chunks in the superblock's system chunk array: key, chunk item, key, chunk item...
+    pub fn from_sys_chunk_array(devid: u64, array: &[u8]) -> Result<Self, MosesError> {
+        let mut map = Self::new(devid);
+        let mut at = 0;
+        while at < array.len() {
+            if at + KEY_SIZE > array.len() {
+                return Err(MosesError::Other("Corrupted btrfs system chunk array: truncated key".to_string()));
+            }
+            let key = Key::parse(&array[at..]);
+            if key.item_type != CHUNK_ITEM_KEY {
+                return Err(MosesError::Other(format!("Corrupted btrfs system chunk array: item type {}", key.item_type)));
+            }
+            let (chunk, size) = Chunk::parse(&array[at + KEY_SIZE..])?;
+            map.insert(key.offset, chunk);
+            at += KEY_SIZE + size;
+        }
+        if map.chunks.is_empty() {
+            return Err(MosesError::Other("Corrupted btrfs superblock: no system chunks".to_string()));
+        }
+        Ok(map)
+    }
+
+    pub fn insert(&mut self, logical: u64, chunk: Chunk) {
+        self.chunks.insert(logical, chunk);
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Where `logical` is on this device, and how many bytes from there on belong to the
+    /// same chunk
+    pub fn map(&self, logical: u64) -> Result<(u64, u64), MosesError> {
+        let (&start, chunk) = self.chunks.range(..=logical).next_back()
+            .filter(|(&start, chunk)| logical - start < chunk.length)
+            .ok_or_else(|| MosesError::Other(format!("Btrfs address {:#x} is in no chunk", logical)))?;
+        if chunk.chunk_type & BLOCK_GROUP_STRIPED != 0 {
+            return Err(MosesError::NotSupported(format!(
+                "Btrfs address {:#x} is in a striped (RAID0/10/5/6) chunk, which needs all its devices", logical
+            )));
+        }
+        let stripe = chunk.stripes.iter().find(|stripe| stripe.devid == self.devid)
+            .ok_or_else(|| MosesError::NotSupported(format!(
+                "Btrfs address {:#x} is stored on another device of this multi-device volume", logical
+            )))?;
+        let within = logical - start;
+        let physical = stripe.offset.checked_add(within)
+            .ok_or_else(|| corrupt(&format!("chunk at {:#x}: stripe offset {:#x} runs past the end of the device", start, stripe.offset)))?;
+        Ok((physical, chunk.length - within))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::structures::{Stripe, BLOCK_GROUP_RAID0};
+
+    #[test]
+    fn test_chunk_mapping() {
+        // A DUP metadata chunk at 1 MiB and a single data chunk on this device, plus a
+        // mirrored one whose copies are elsewhere
+        let mut map = ChunkMap::new(1);
+        let stripes = |pairs: &[(u64, u64)]| pairs.iter().map(|&(devid, offset)| Stripe { devid, offset }).collect();
+        map.insert(1 << 20, Chunk { length: 1 << 20, chunk_type: 4 | 32, stripes: stripes(&[(1, 8 << 20), (1, 9 << 20)]) });
+        map.insert(4 << 20, Chunk { length: 4 << 20, chunk_type: 1, stripes: stripes(&[(1, 16 << 20)]) });
+        map.insert(16 << 20, Chunk { length: 1 << 20, chunk_type: 1 | 16, stripes: stripes(&[(2, 0), (3, 0)]) });
+        map.insert(32 << 20, Chunk { length: 1 << 20, chunk_type: 1 | BLOCK_GROUP_RAID0, stripes: stripes(&[(1, 0)]) });
+
+        assert_eq!(map.map((1 << 20) + 100).unwrap(), ((8 << 20) + 100, (1 << 20) - 100));
+        assert_eq!(map.map(5 << 20).unwrap(), (17 << 20, 3 << 20));
+        assert!(map.map(2 << 20).is_err(), "between chunks");
+        assert!(matches!(map.map(16 << 20), Err(MosesError::NotSupported(_))));
+        assert!(matches!(map.map(32 << 20), Err(MosesError::NotSupported(_))));
+    }
+}
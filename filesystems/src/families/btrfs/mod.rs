@@ -0,0 +1,25 @@
+// Btrfs family - read-only access to btrfs volumes on any platform
+// Btrfs keeps everything in copy-on-write B-trees addressed by logical byte numbers, which
+// the chunk tree maps to places on the devices. The reader follows the superblock to the
+// chunk tree, the root tree and the filesystem trees, and lists and reads files there;
+// nothing is ever written.
+
+pub mod structures;
+pub mod chunks;
+pub mod reader;
+pub mod ops;
+
+pub use reader::BtrfsReader;
+pub use ops::{BtrfsOps, BtrfsDetector};
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// Whether the btrfs superblock magic is at 64 KiB; leaves the position at the start
+pub fn has_superblock<R: Read + Seek>(reader: &mut R) -> bool {
+    let mut magic = [0u8; 8];
+    let found = reader.seek(SeekFrom::Start(structures::SUPERBLOCK_OFFSET + structures::MAGIC_OFFSET as u64)).is_ok()
+        && reader.read_exact(&mut magic).is_ok()
+        && &magic == structures::MAGIC;
+    let _ = reader.seek(SeekFrom::Start(0));
+    found
+}
@@ -0,0 +1,133 @@
+// Btrfs FilesystemOps implementation for mounting and browsing, read-only
+use super::reader::{BtrfsReader, Location};
+use super::structures::{InodeItem, MAGIC, MAGIC_OFFSET, SUPERBLOCK_OFFSET};
+use crate::device_reader::FilesystemReader;
+use crate::ops::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct BtrfsOps {
+    reader: Mutex<Option<BtrfsReader>>,
+}
+
+impl BtrfsOps {
+    pub fn new() -> Self {
+        Self { reader: Mutex::new(None) }
+    }
+
+    /// Run `f` on the reader with `path` resolved
+    fn with_path<T>(&self, path: &Path, f: impl FnOnce(&mut BtrfsReader, Location) -> Result<T, MosesError>) -> Result<T, MosesError> {
+        let path = path.to_str().ok_or_else(|| MosesError::InvalidInput("Invalid path".to_string()))?;
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let location = reader.lookup(path)?;
+        f(reader, location)
+    }
+}
+
+impl Default for BtrfsOps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attributes(inode: &InodeItem) -> FileAttributes {
+    FileAttributes {
+        size: if inode.is_directory() { 0 } else { inode.size },
+        is_directory: inode.is_directory(),
+        is_file: inode.mode & 0o170000 == 0o100000,
+        is_symlink: inode.is_symlink(),
+        created: Some(inode.otime),
+        modified: Some(inode.mtime),
+        accessed: Some(inode.atime),
+        permissions: inode.mode & 0o7777,
+        owner: Some(inode.uid),
+        group: Some(inode.gid),
+    }
+}
+
+impl FilesystemOps for BtrfsOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        *self.reader.lock().unwrap() = Some(BtrfsReader::new(device.clone())?);
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        let reader = self.reader.lock().unwrap();
+        let reader = reader.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let superblock = reader.superblock();
+        let info = reader.get_info();
+        let free = superblock.total_bytes.saturating_sub(superblock.bytes_used);
+        Ok(FilesystemInfo {
+            total_space: superblock.total_bytes,
+            free_space: free,
+            available_space: free,
+            total_inodes: 0,
+            free_inodes: 0,
+            block_size: superblock.sectorsize,
+            fragment_size: superblock.sectorsize,
+            max_filename_length: 255,
+            filesystem_type: info.fs_type,
+            volume_label: info.label,
+            volume_uuid: Some(superblock.uuid()),
+            is_readonly: true,
+        })
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.with_path(path, |reader, location| Ok(attributes(&reader.inode(location)?)))
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.with_path(path, |reader, location| {
+            if !reader.inode(location)?.is_directory() {
+                return Err(MosesError::InvalidInput(format!("Not a directory: {}", path.display())));
+            }
+            Ok(reader.list(location)?.into_iter()
+                .map(|entry| DirectoryEntry { attributes: attributes(&entry.inode), name: entry.name })
+                .collect())
+        })
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.with_path(path, |reader, location| reader.read(location, offset, size as u64))
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.with_path(path, |_, location| Ok(location.id())).ok()
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.with_path(path, |reader, location| reader.read_link(location).map(PathBuf::from))
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn filesystem_type(&self) -> &str {
+        "btrfs"
+    }
+}
+
+/// Finds btrfs by the magic in its superblock at 64 KiB
+pub struct BtrfsDetector;
+
+impl crate::ops::FilesystemDetector for BtrfsDetector {
+    fn detect(&self, device: &Device) -> Result<Option<String>, MosesError> {
+        use crate::utils::{open_device_read, read_block};
+
+        let mut file = open_device_read(device)?;
+        match read_block(&mut file, SUPERBLOCK_OFFSET + MAGIC_OFFSET as u64, MAGIC.len()) {
+            Ok(magic) if magic == MAGIC => Ok(Some("btrfs".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
@@ -0,0 +1,648 @@
+// Btrfs reader - superblock to chunk tree to root tree to files
+// Opening a volume reads the superblock (or a mirror when the primary is damaged), maps the
+// system chunks it carries, reads the chunk tree for the rest of the chunk map, and finds
+// the default filesystem tree in the root tree. Paths are then resolved through DIR_INDEX
+// items, crossing into a subvolume's own tree where a directory entry points at one, and
+// file data is assembled from EXTENT_DATA items: inline, regular, holes and preallocated
+// space, compressed with zlib or zstd or not at all. Every tree block's checksum and
+// address are checked before it is used.
+use super::chunks::ChunkMap;
+use super::structures::*;
+use crate::device_reader::{AlignedDeviceReader, FileEntry, FileMetadata, FilesystemInfo, FilesystemReader};
+use log::{debug, info, warn};
+use moses_core::{Device, MosesError};
+use std::collections::HashMap;
+
+/// The first tree in the chunk tree, which holds every chunk item
+const FIRST_CHUNK_TREE_OBJECTID: u64 = 256;
+/// Tree blocks kept in memory; directory lookups revisit the same few nodes
+const NODE_CACHE_LIMIT: usize = 4096;
+
+/// The root node of a tree and the subvolume it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeRoot {
+    pub id: u64,
+    pub bytenr: u64,
+    pub level: u8,
+}
+
+/// An inode in a particular filesystem tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub tree: TreeRoot,
+    pub inode: u64,
+}
+
+impl Location {
+    /// A number for the inode unique across subvolumes
+    pub fn id(&self) -> u64 {
+        (self.tree.id << 48) | self.inode
+    }
+}
+
+/// One name in a directory listing
+#[derive(Debug, Clone)]
+pub struct BtrfsEntry {
+    pub name: String,
+    pub location: Location,
+    pub inode: InodeItem,
+    /// The entry is a subvolume or snapshot rather than a plain directory
+    pub subvolume: bool,
+}
+
+/// Read-only btrfs volume
+pub struct BtrfsReader {
+    reader: AlignedDeviceReader,
+    superblock: Superblock,
+    chunks: ChunkMap,
+    fs_tree: TreeRoot,
+    node_cache: HashMap<u64, Vec<u8>>,
+    /// Extents of the file read last, so reads in pieces do not walk its items each time
+    extent_cache: Option<(Location, Vec<(u64, FileExtent)>)>,
+}
+
+impl BtrfsReader {
+    pub fn new(device: Device) -> Result<Self, MosesError> {
+        info!("Opening btrfs filesystem on device: {}", device.name);
+        let file = crate::utils::open_device_with_fallback(&device)?;
+        Self::from_reader(AlignedDeviceReader::new(file))
+    }
+
+    pub fn from_reader(mut reader: AlignedDeviceReader) -> Result<Self, MosesError> {
+        let superblock = read_superblock(&mut reader)?;
+        if superblock.num_devices > 1 {
+            warn!("btrfs volume has {} devices; data stored only on the others cannot be read", superblock.num_devices);
+        }
+        let chunks = ChunkMap::from_sys_chunk_array(superblock.devid, &superblock.sys_chunk_array)?;
+        let mut btrfs = Self {
+            reader,
+            chunks,
+            fs_tree: TreeRoot { id: FS_TREE_OBJECTID, bytenr: 0, level: 0 },
+            node_cache: HashMap::new(),
+            extent_cache: None,
+            superblock,
+        };
+
+        let chunk_tree = TreeRoot { id: 3, bytenr: btrfs.superblock.chunk_root, level: btrfs.superblock.chunk_root_level };
+        let items = btrfs.items(chunk_tree, Key::new(FIRST_CHUNK_TREE_OBJECTID, CHUNK_ITEM_KEY, 0), Key::new(FIRST_CHUNK_TREE_OBJECTID, CHUNK_ITEM_KEY, u64::MAX))?;
+        for (key, data) in items {
+            let (chunk, _) = Chunk::parse(&data)?;
+            btrfs.chunks.insert(key.offset, chunk);
+        }
+        btrfs.fs_tree = btrfs.subvolume_root(FS_TREE_OBJECTID)?;
+        debug!("btrfs: {} chunks, filesystem tree at {:#x}", btrfs.chunks.len(), btrfs.fs_tree.bytenr);
+        Ok(btrfs)
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    /// The root of subvolume `id`'s tree, from its ROOT_ITEM in the root tree
+    fn subvolume_root(&mut self, id: u64) -> Result<TreeRoot, MosesError> {
+        let root_tree = TreeRoot { id: ROOT_TREE_OBJECTID, bytenr: self.superblock.root, level: self.superblock.root_level };
+        // Snapshots key their root item by the generation they were taken in; the last one counts
+        let (_, data) = self.items(root_tree, Key::new(id, ROOT_ITEM_KEY, 0), Key::new(id, ROOT_ITEM_KEY, u64::MAX))?
+            .pop()
+            .ok_or_else(|| MosesError::Other(format!("btrfs subvolume {} has no root item", id)))?;
+        let root = RootItem::parse(&data)?;
+        if root.root_dirid != FIRST_FREE_OBJECTID {
+            warn!("btrfs subvolume {} has its root directory at inode {}", id, root.root_dirid);
+        }
+        Ok(TreeRoot { id, bytenr: root.bytenr, level: root.level })
+    }
+
+    /// Read and check the tree block at `logical`
+    fn read_node(&mut self, logical: u64) -> Result<Vec<u8>, MosesError> {
+        if let Some(node) = self.node_cache.get(&logical) {
+            return Ok(node.clone());
+        }
+        let nodesize = self.superblock.nodesize as usize;
+        let (physical, remaining) = self.chunks.map(logical)?;
+        if remaining < nodesize as u64 {
+            return Err(MosesError::Other(format!("btrfs tree block {:#x} crosses the end of its chunk", logical)));
+        }
+        let node = self.reader.read_at(physical, nodesize)?;
+        if !checksum_matches(self.superblock.csum_type, &node) {
+            return Err(MosesError::Other(format!("Corrupted btrfs tree block {:#x}: checksum mismatch", logical)));
+        }
+        if NodeHeader::parse(&node).bytenr != logical {
+            return Err(MosesError::Other(format!("Corrupted btrfs tree block {:#x}: it says it is at {:#x}", logical, NodeHeader::parse(&node).bytenr)));
+        }
+        if self.node_cache.len() >= NODE_CACHE_LIMIT {
+            self.node_cache.clear();
+        }
+        self.node_cache.insert(logical, node.clone());
+        Ok(node)
+    }
+
+    /// Items of `tree` with keys from `min` to `max`, in key order
+    pub fn items(&mut self, tree: TreeRoot, min: Key, max: Key) -> Result<Vec<(Key, Vec<u8>)>, MosesError> {
+        let mut items = Vec::new();
+        self.collect(tree.bytenr, tree.level, min, max, &mut items)?;
+        Ok(items)
+    }
+
+    fn collect(&mut self, bytenr: u64, level: u8, min: Key, max: Key, items: &mut Vec<(Key, Vec<u8>)>) -> Result<(), MosesError> {
+        let node = self.read_node(bytenr)?;
+        let header = NodeHeader::parse(&node);
+        // Levels must fall by one on the way down, which also rules out loops
+        if header.level != level {
+            return Err(MosesError::Other(format!(
+                "Corrupted btrfs tree block {:#x}: level {}, expected {}", bytenr, header.level, level
+            )));
+        }
+        if level == 0 {
+            items.extend(leaf_items(&node, header.nritems)?.into_iter()
+                .filter(|(key, _)| (min..=max).contains(key))
+                .map(|(key, data)| (key, data.to_vec())));
+            return Ok(());
+        }
+        let pointers = key_pointers(&node, header.nritems)?;
+        for (i, &(first, child)) in pointers.iter().enumerate() {
+            if first > max {
+                break;
+            }
+            // Everything in this child sorts before the next child's first key
+            if pointers.get(i + 1).is_some_and(|&(next, _)| next <= min) {
+                continue;
+            }
+            self.collect(child, level - 1, min, max, items)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes at a logical address, following chunks across their boundaries
+    fn read_logical(&mut self, logical: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+        // Grown as pieces arrive, so a corrupt length fails on the read rather than the allocation
+        let mut data = Vec::with_capacity(len.min(MAX_EXTENT_SIZE as usize));
+        while data.len() < len {
+            let at = logical.checked_add(data.len() as u64)
+                .ok_or_else(|| corrupt(&format!("address {:#x} + {} runs past the end of the address space", logical, len)))?;
+            let (physical, remaining) = self.chunks.map(at)?;
+            let piece = (len - data.len()).min(remaining as usize);
+            data.extend(self.reader.read_at(physical, piece)?);
+        }
+        Ok(data)
+    }
+
+    pub fn root(&self) -> Location {
+        Location { tree: self.fs_tree, inode: FIRST_FREE_OBJECTID }
+    }
+
+    pub fn inode(&mut self, location: Location) -> Result<InodeItem, MosesError> {
+        let key = Key::new(location.inode, INODE_ITEM_KEY, 0);
+        let (_, data) = self.items(location.tree, key, key)?.pop()
+            .ok_or_else(|| MosesError::Other(format!("btrfs inode {} not found", location.inode)))?;
+        InodeItem::parse(&data)
+    }
+
+    /// The entries of the directory at `location`, in the order they were created
+    pub fn list(&mut self, location: Location) -> Result<Vec<BtrfsEntry>, MosesError> {
+        let items = self.items(location.tree, Key::new(location.inode, DIR_INDEX_KEY, 0), Key::new(location.inode, DIR_INDEX_KEY, u64::MAX))?;
+        let mut entries = Vec::with_capacity(items.len());
+        for (_, data) in items {
+            let item = DirItem::parse(&data)?;
+            let (target, subvolume) = self.follow(location, &item)?;
+            let inode = self.inode(target)?;
+            entries.push(BtrfsEntry { name: item.name, location: target, inode, subvolume });
+        }
+        Ok(entries)
+    }
+
+    /// What a directory entry in `parent` leads to
+    fn follow(&mut self, parent: Location, item: &DirItem) -> Result<(Location, bool), MosesError> {
+        if item.location.item_type == ROOT_ITEM_KEY {
+            let tree = self.subvolume_root(item.location.objectid)?;
+            Ok((Location { tree, inode: FIRST_FREE_OBJECTID }, true))
+        } else {
+            Ok((Location { tree: parent.tree, inode: item.location.objectid }, false))
+        }
+    }
+
+    /// Resolve a `/`-separated path from the root of the default subvolume
+    pub fn lookup(&mut self, path: &str) -> Result<Location, MosesError> {
+        let mut location = self.root();
+        for part in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            let items = self.items(location.tree, Key::new(location.inode, DIR_INDEX_KEY, 0), Key::new(location.inode, DIR_INDEX_KEY, u64::MAX))?;
+            let item = items.iter()
+                .map(|(_, data)| DirItem::parse(data))
+                .find(|item| item.as_ref().map_or(true, |item| item.name == part))
+                .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))??;
+            location = self.follow(location, &item)?.0;
+        }
+        Ok(location)
+    }
+
+    fn extents(&mut self, location: Location) -> Result<Vec<(u64, FileExtent)>, MosesError> {
+        if let Some((cached, extents)) = &self.extent_cache {
+            if *cached == location {
+                return Ok(extents.clone());
+            }
+        }
+        let items = self.items(location.tree, Key::new(location.inode, EXTENT_DATA_KEY, 0), Key::new(location.inode, EXTENT_DATA_KEY, u64::MAX))?;
+        let extents = items.iter()
+            .map(|(key, data)| Ok((key.offset, FileExtent::parse(data)?)))
+            .collect::<Result<Vec<_>, MosesError>>()?;
+        self.extent_cache = Some((location, extents.clone()));
+        Ok(extents)
+    }
+
+    /// Up to `size` bytes of the file at `location` from `offset`; gaps between extents read as zeros
+    pub fn read(&mut self, location: Location, offset: u64, size: u64) -> Result<Vec<u8>, MosesError> {
+        let file_size = self.inode(location)?.size;
+        let end = offset.saturating_add(size).min(file_size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        // Even a sparse file is not read whole into memory past the size of the volume
+        if end - offset > self.superblock.total_bytes {
+            return Err(corrupt(&format!(
+                "inode {}: {} bytes to read on a volume of {}", location.inode, end - offset, self.superblock.total_bytes
+            )));
+        }
+        let mut data = vec![0u8; (end - offset) as usize];
+        for (file_offset, extent) in self.extents(location)? {
+            let start = file_offset.max(offset);
+            let stop = file_offset.saturating_add(extent.covers()).min(end);
+            if start >= stop {
+                continue;
+            }
+            let within = start - file_offset;
+            let len = (stop - start) as usize;
+            let piece = match &extent {
+                FileExtent::Inline { compression, ram_bytes, data } => {
+                    let inline = decompress(*compression, data, (*ram_bytes).max(data.len() as u64))?;
+                    inline.get(within as usize..within as usize + len).map(<[u8]>::to_vec)
+                        .ok_or_else(|| MosesError::Other(format!("btrfs inode {}: inline extent is short", location.inode)))?
+                }
+                FileExtent::Regular { disk_bytenr: 0, .. } | FileExtent::Regular { prealloc: true, .. } => continue,
+                FileExtent::Regular { compression, disk_bytenr, disk_num_bytes, ram_bytes, offset: extent_offset, .. } if *compression != COMPRESS_NONE => {
+                    let stored = self.read_logical(*disk_bytenr, *disk_num_bytes as usize)?;
+                    let whole = decompress(*compression, &stored, *ram_bytes)?;
+                    let from = extent_offset.checked_add(within).map(|from| from as usize);
+                    from.and_then(|from| whole.get(from..from.checked_add(len)?)).map(<[u8]>::to_vec)
+                        .ok_or_else(|| MosesError::Other(format!("btrfs inode {}: compressed extent is short", location.inode)))?
+                }
+                FileExtent::Regular { disk_bytenr, offset: extent_offset, .. } => {
+                    let logical = disk_bytenr.checked_add(*extent_offset).and_then(|logical| logical.checked_add(within))
+                        .ok_or_else(|| corrupt(&format!("inode {}: extent at {:#x} + {:#x} overflows", location.inode, disk_bytenr, extent_offset)))?;
+                    self.read_logical(logical, len)?
+                }
+            };
+            let at = (start - offset) as usize;
+            data[at..at + len].copy_from_slice(&piece);
+        }
+        Ok(data)
+    }
+
+    /// Target of the symbolic link at `location`
+    pub fn read_link(&mut self, location: Location) -> Result<String, MosesError> {
+        let inode = self.inode(location)?;
+        if !inode.is_symlink() {
+            return Err(MosesError::InvalidInput(format!("btrfs inode {} is not a symbolic link", location.inode)));
+        }
+        Ok(String::from_utf8_lossy(&self.read(location, 0, inode.size)?).into_owned())
+    }
+
+    fn file_entry(&mut self, entry: BtrfsEntry) -> FileEntry {
+        let link_target = if entry.inode.is_symlink() { self.read_link(entry.location).ok() } else { None };
+        let is_directory = entry.inode.is_directory();
+        FileEntry {
+            name: entry.name,
+            is_directory,
+            size: if is_directory { 0 } else { entry.inode.size },
+            cluster: None,
+            metadata: FileMetadata {
+                link_target,
+                allocated_size: Some(entry.inode.nbytes),
+                created: Some(entry.inode.otime),
+                modified: Some(entry.inode.mtime),
+                accessed: Some(entry.inode.atime),
+                readonly: entry.inode.mode & 0o222 == 0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The primary superblock, or the newest valid mirror when the primary cannot be used
+fn read_superblock(reader: &mut AlignedDeviceReader) -> Result<Superblock, MosesError> {
+    let primary = reader.read_at(SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE).and_then(|data| Superblock::parse(&data));
+    let error = match primary {
+        Ok(superblock) => return Ok(superblock),
+        Err(e) => e,
+    };
+    let mirror = SUPERBLOCK_MIRRORS.iter()
+        .filter_map(|&offset| reader.read_at(offset, SUPERBLOCK_SIZE).ok())
+        .filter_map(|data| Superblock::parse(&data).ok())
+        .max_by_key(|superblock| superblock.generation);
+    match mirror {
+        Some(superblock) => {
+            warn!("btrfs primary superblock unusable ({}); using a mirror from generation {}", error, superblock.generation);
+            Ok(superblock)
+        }
+        None => Err(error),
+    }
+}
+
+impl FilesystemReader for BtrfsReader {
+    fn read_metadata(&mut self) -> Result<(), MosesError> {
+        // Already read in new()
+        Ok(())
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
+        let location = self.lookup(path)?;
+        if !self.inode(location)?.is_directory() {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", path)));
+        }
+        let entries = self.list(location)?;
+        Ok(entries.into_iter().map(|entry| self.file_entry(entry)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, MosesError> {
+        let location = self.lookup(path)?;
+        let inode = self.inode(location)?;
+        if inode.is_directory() {
+            return Err(MosesError::InvalidInput(format!("Is a directory: {}", path)));
+        }
+        self.read(location, 0, inode.size)
+    }
+
+    fn get_info(&self) -> FilesystemInfo {
+        FilesystemInfo {
+            fs_type: "btrfs".to_string(),
+            label: Some(self.superblock.label.clone()).filter(|label| !label.is_empty()),
+            total_bytes: self.superblock.total_bytes,
+            used_bytes: self.superblock.bytes_used,
+            cluster_size: Some(self.superblock.sectorsize),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ops::FilesystemOps;
+    use std::io::Write;
+    use std::path::Path;
+
+    const NODESIZE: usize = 4096;
+    const CHUNK_START: u64 = 1 << 20;
+    const DATA: u64 = CHUNK_START + 0x10000;
+
+    fn key_bytes(key: Key) -> Vec<u8> {
+        [&key.objectid.to_le_bytes()[..], &[key.item_type], &key.offset.to_le_bytes()].concat()
+    }
+
+    fn checksummed(mut block: Vec<u8>) -> Vec<u8> {
+        let crc = crc32c::crc32c(&block[CSUM_SIZE..]);
+        block[..4].copy_from_slice(&crc.to_le_bytes());
+        block
+    }
+
+    fn leaf(bytenr: u64, owner: u64, mut items: Vec<(Key, Vec<u8>)>) -> Vec<u8> {
+        items.sort_by_key(|(key, _)| *key);
+        let mut node = vec![0u8; NODESIZE];
+        node[0x30..0x38].copy_from_slice(&bytenr.to_le_bytes());
+        node[0x58..0x60].copy_from_slice(&owner.to_le_bytes());
+        node[0x60..0x64].copy_from_slice(&(items.len() as u32).to_le_bytes());
+        let mut end = NODESIZE - NODE_HEADER_SIZE;
+        for (i, (key, data)) in items.iter().enumerate() {
+            end -= data.len();
+            let at = NODE_HEADER_SIZE + i * LEAF_ITEM_SIZE;
+            node[at..at + KEY_SIZE].copy_from_slice(&key_bytes(*key));
+            node[at + 17..at + 21].copy_from_slice(&(end as u32).to_le_bytes());
+            node[at + 21..at + 25].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            node[NODE_HEADER_SIZE + end..NODE_HEADER_SIZE + end + data.len()].copy_from_slice(data);
+        }
+        checksummed(node)
+    }
+
+    fn inode(mode: u32, size: u64) -> Vec<u8> {
+        let mut item = vec![0u8; INODE_ITEM_SIZE];
+        item[16..24].copy_from_slice(&size.to_le_bytes());
+        item[24..32].copy_from_slice(&size.to_le_bytes());
+        item[40..44].copy_from_slice(&1u32.to_le_bytes());
+        item[44..48].copy_from_slice(&1000u32.to_le_bytes());
+        item[52..56].copy_from_slice(&mode.to_le_bytes());
+        item[136..144].copy_from_slice(&1_700_000_000u64.to_le_bytes());
+        item
+    }
+
+    fn dir_index(location: Key, file_type: u8, name: &str) -> Vec<u8> {
+        let mut item = key_bytes(location);
+        item.extend([0u8; 8]);
+        item.extend(0u16.to_le_bytes());
+        item.extend((name.len() as u16).to_le_bytes());
+        item.push(file_type);
+        item.extend(name.as_bytes());
+        item
+    }
+
+    fn inline_extent(compression: u8, ram_bytes: u64, data: &[u8]) -> Vec<u8> {
+        let mut item = vec![0u8; 21];
+        item[8..16].copy_from_slice(&ram_bytes.to_le_bytes());
+        item[16] = compression;
+        item[20] = FILE_EXTENT_INLINE;
+        item.extend(data);
+        item
+    }
+
+    fn regular_extent(compression: u8, disk_bytenr: u64, disk_num_bytes: u64, ram_bytes: u64, num_bytes: u64) -> Vec<u8> {
+        let mut item = vec![0u8; 53];
+        item[8..16].copy_from_slice(&ram_bytes.to_le_bytes());
+        item[16] = compression;
+        item[20] = FILE_EXTENT_REG;
+        item[21..29].copy_from_slice(&disk_bytenr.to_le_bytes());
+        item[29..37].copy_from_slice(&disk_num_bytes.to_le_bytes());
+        item[45..53].copy_from_slice(&num_bytes.to_le_bytes());
+        item
+    }
+
+    fn root_item(bytenr: u64) -> Vec<u8> {
+        let mut item = vec![0u8; 439];
+        item[..INODE_ITEM_SIZE].copy_from_slice(&inode(0o40755, 3));
+        item[168..176].copy_from_slice(&FIRST_FREE_OBJECTID.to_le_bytes());
+        item[176..184].copy_from_slice(&bytenr.to_le_bytes());
+        item
+    }
+
+    /// The single chunk covering the image from 1 MiB, at the same place on device 1
+    fn chunk_item(length: u64) -> Vec<u8> {
+        let mut item = vec![0u8; 48 + 32];
+        item[0..8].copy_from_slice(&length.to_le_bytes());
+        item[16..24].copy_from_slice(&65536u64.to_le_bytes());
+        item[24..32].copy_from_slice(&7u64.to_le_bytes());
+        item[44..46].copy_from_slice(&1u16.to_le_bytes());
+        item[48..56].copy_from_slice(&1u64.to_le_bytes());
+        item[56..64].copy_from_slice(&CHUNK_START.to_le_bytes());
+        item
+    }
+
+    pub(crate) fn big_file() -> Vec<u8> {
+        (0..8192u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A 4 MiB volume: hello.txt, docs/big.bin (plain and zstd extents), docs/link, and a
+    /// subvolume vol holding a zlib-compressed inside.txt
+    pub(crate) fn build_image(path: &Path) {
+        let size = 4u64 << 20;
+        let chunk = chunk_item(size - CHUNK_START);
+        let (chunk_tree, root_tree, fs_tree, subvol_tree) = (CHUNK_START, CHUNK_START + 0x1000, CHUNK_START + 0x2000, CHUNK_START + 0x3000);
+        let tail: Vec<u8> = b"compressed tail ".iter().copied().cycle().take(4096).collect();
+        let zstd_tail = zstd::encode_all(&tail[..], 3).unwrap();
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(b"inside").unwrap();
+        let zlib_inside = zlib.finish().unwrap();
+
+        let ino = |objectid| Key::new(objectid, INODE_ITEM_KEY, 0);
+        let index = |dir, n| Key::new(dir, DIR_INDEX_KEY, n);
+        let extent = |objectid, offset| Key::new(objectid, EXTENT_DATA_KEY, offset);
+        let blocks = [
+            leaf(chunk_tree, 3, vec![(Key::new(FIRST_CHUNK_TREE_OBJECTID, CHUNK_ITEM_KEY, CHUNK_START), chunk.clone())]),
+            leaf(root_tree, ROOT_TREE_OBJECTID, vec![
+                (Key::new(FS_TREE_OBJECTID, ROOT_ITEM_KEY, 0), root_item(fs_tree)),
+                (Key::new(256, ROOT_ITEM_KEY, 0), root_item(subvol_tree)),
+            ]),
+            leaf(fs_tree, FS_TREE_OBJECTID, vec![
+                (ino(256), inode(0o40755, 0)),
+                (index(256, 2), dir_index(ino(257), 1, "hello.txt")),
+                (index(256, 3), dir_index(ino(258), FT_DIR, "docs")),
+                (index(256, 4), dir_index(Key::new(256, ROOT_ITEM_KEY, u64::MAX), FT_DIR, "vol")),
+                (ino(257), inode(0o100644, 13)),
+                (extent(257, 0), inline_extent(COMPRESS_NONE, 13, b"Hello, btrfs\n")),
+                (ino(258), inode(0o40755, 0)),
+                (index(258, 2), dir_index(ino(259), 1, "big.bin")),
+                (index(258, 3), dir_index(ino(260), FT_SYMLINK, "link")),
+                (ino(259), inode(0o100644, 8192 + 4096)),
+                (extent(259, 0), regular_extent(COMPRESS_NONE, DATA, 8192, 8192, 8192)),
+                (extent(259, 8192), regular_extent(COMPRESS_ZSTD, DATA + 8192, zstd_tail.len() as u64, 4096, 4096)),
+                (ino(260), inode(0o120777, 12)),
+                (extent(260, 0), inline_extent(COMPRESS_NONE, 12, b"../hello.txt")),
+            ]),
+            leaf(subvol_tree, 256, vec![
+                (ino(256), inode(0o40755, 0)),
+                (index(256, 2), dir_index(ino(257), 1, "inside.txt")),
+                (ino(257), inode(0o100644, 6)),
+                (extent(257, 0), inline_extent(COMPRESS_ZLIB, 6, &zlib_inside)),
+            ]),
+        ];
+
+        let mut image = vec![0u8; size as usize];
+        for (i, block) in blocks.iter().enumerate() {
+            let at = CHUNK_START as usize + i * NODESIZE;
+            image[at..at + NODESIZE].copy_from_slice(block);
+        }
+        image[DATA as usize..DATA as usize + 8192].copy_from_slice(&big_file());
+        image[DATA as usize + 8192..DATA as usize + 8192 + zstd_tail.len()].copy_from_slice(&zstd_tail);
+
+        let mut sb = vec![0u8; SUPERBLOCK_SIZE];
+        sb[0x20..0x30].copy_from_slice(&[0x42; 16]);
+        sb[0x30..0x38].copy_from_slice(&SUPERBLOCK_OFFSET.to_le_bytes());
+        sb[MAGIC_OFFSET..MAGIC_OFFSET + 8].copy_from_slice(MAGIC);
+        sb[0x48..0x50].copy_from_slice(&7u64.to_le_bytes());
+        sb[0x50..0x58].copy_from_slice(&root_tree.to_le_bytes());
+        sb[0x58..0x60].copy_from_slice(&chunk_tree.to_le_bytes());
+        sb[0x70..0x78].copy_from_slice(&size.to_le_bytes());
+        sb[0x78..0x80].copy_from_slice(&(1u64 << 20).to_le_bytes());
+        sb[0x88..0x90].copy_from_slice(&1u64.to_le_bytes());
+        sb[0x90..0x94].copy_from_slice(&4096u32.to_le_bytes());
+        sb[0x94..0x98].copy_from_slice(&(NODESIZE as u32).to_le_bytes());
+        let array = [key_bytes(Key::new(FIRST_CHUNK_TREE_OBJECTID, CHUNK_ITEM_KEY, CHUNK_START)), chunk].concat();
+        sb[0xA0..0xA4].copy_from_slice(&(array.len() as u32).to_le_bytes());
+        sb[0xC9..0xD1].copy_from_slice(&1u64.to_le_bytes());
+        sb[0x12B..0x12B + 6].copy_from_slice(b"photos");
+        sb[0x32B..0x32B + array.len()].copy_from_slice(&array);
+        let sb = checksummed(sb);
+        image[SUPERBLOCK_OFFSET as usize..SUPERBLOCK_OFFSET as usize + SUPERBLOCK_SIZE].copy_from_slice(&sb);
+        std::fs::write(path, image).unwrap();
+    }
+
+    #[test]
+    fn test_read_btrfs_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("btrfs.img");
+        build_image(&path);
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+        let mut reader = BtrfsReader::new(device.clone()).unwrap();
+        assert_eq!(reader.get_info().label.as_deref(), Some("photos"));
+        let names: Vec<_> = reader.list_directory("/").unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["hello.txt", "docs", "vol"]);
+        assert_eq!(reader.read_file("/hello.txt").unwrap(), b"Hello, btrfs\n");
+        let big = reader.read_file("/docs/big.bin").unwrap();
+        assert_eq!(big[..8192], big_file()[..]);
+        assert!(big[8192..].starts_with(b"compressed tail compressed"));
+        assert_eq!(reader.read_file("/vol/inside.txt").unwrap(), b"inside");
+        let link = reader.list_directory("/docs").unwrap().into_iter().find(|entry| entry.name == "link").unwrap();
+        assert_eq!(link.metadata.link_target.as_deref(), Some("../hello.txt"));
+        assert!(reader.read_file("/missing").is_err());
+
+        // Through the ops registry, found by its superblock
+        let mut registry = crate::ops::FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut registry, false);
+        let mut ops = registry.create_ops(&device, None).unwrap();
+        assert_eq!(ops.filesystem_type(), "btrfs");
+        assert_eq!(ops.read(Path::new("/docs/big.bin"), 8190, 8).unwrap(), [big_file()[8190], big_file()[8191], b'c', b'o', b'm', b'p', b'r', b'e']);
+        assert_eq!(ops.readlink(Path::new("/docs/link")).unwrap(), Path::new("../hello.txt"));
+        assert!(ops.stat(Path::new("/docs/link")).unwrap().is_symlink);
+        assert_ne!(ops.directory_id(Path::new("/")), ops.directory_id(Path::new("/vol")), "subvolume roots are both inode 256");
+        assert_eq!(ops.statfs().unwrap().volume_uuid.as_deref(), Some("42424242-4242-4242-4242-424242424242"));
+
+        // A damaged tree block is reported, not read
+        let mut image = std::fs::read(&path).unwrap();
+        image[CHUNK_START as usize + 2 * NODESIZE + 200] ^= 0xFF;
+        std::fs::write(&path, image).unwrap();
+        let error = BtrfsReader::new(device).unwrap().list_directory("/").unwrap_err().to_string();
+        assert!(error.contains("checksum"), "{}", error);
+    }
+
+    /// Overwrite part of the item under `key` in the leaf at `bytenr` and fix the checksum
+    fn patch_item(image: &mut [u8], bytenr: u64, key: Key, at: usize, bytes: &[u8]) {
+        let block = &mut image[bytenr as usize..bytenr as usize + NODESIZE];
+        let nritems = le_u32(block, 0x60) as usize;
+        let item = (0..nritems).map(|i| NODE_HEADER_SIZE + i * LEAF_ITEM_SIZE)
+            .find(|&item| block[item..item + KEY_SIZE] == key_bytes(key)[..])
+            .unwrap();
+        let start = NODE_HEADER_SIZE + le_u32(block, item + 17) as usize + at;
+        block[start..start + bytes.len()].copy_from_slice(bytes);
+        let sealed = checksummed(block.to_vec());
+        block.copy_from_slice(&sealed);
+    }
+
+    #[test]
+    fn test_malformed_sizes_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("btrfs.img");
+        build_image(&path);
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+        let fs_tree = CHUNK_START + 0x2000;
+        let pristine = std::fs::read(&path).unwrap();
+        let cases: [(Key, &[(usize, u64)], &str); 4] = [
+            // An inode claiming an exabyte of (sparse) data
+            (Key::new(257, INODE_ITEM_KEY, 0), &[(16, 1 << 60)], "/hello.txt"),
+            // A compressed extent that would decompress to far more than btrfs ever writes
+            (Key::new(259, EXTENT_DATA_KEY, 8192), &[(8, 1 << 40)], "/docs/big.bin"),
+            // A piece of the file starting far past the end of its extent
+            (Key::new(259, EXTENT_DATA_KEY, 0), &[(37, u64::MAX - 4096)], "/docs/big.bin"),
+            // An extent at the top of the address space, so the piece's address wraps
+            (Key::new(259, EXTENT_DATA_KEY, 0), &[(21, u64::MAX - 100), (37, 4096), (45, 4096)], "/docs/big.bin"),
+        ];
+        for (key, patches, file) in cases {
+            let mut image = pristine.clone();
+            for &(at, value) in patches {
+                patch_item(&mut image, fs_tree, key, at, &value.to_le_bytes());
+            }
+            std::fs::write(&path, &image).unwrap();
+            let error = BtrfsReader::new(device.clone()).unwrap().read_file(file).unwrap_err().to_string();
+            assert!(error.contains("Corrupted btrfs"), "{:?} {:?}: {}", key, patches, error);
+        }
+
+        // A chunk whose stripe sits at the very end of the device
+        let mut map = ChunkMap::new(1);
+        map.insert(0, Chunk { length: 1 << 20, chunk_type: 1, stripes: vec![Stripe { devid: 1, offset: u64::MAX - 10 }] });
+        assert!(map.map(100).unwrap_err().to_string().contains("Corrupted btrfs"));
+    }
+}
@@ -0,0 +1,430 @@
+// Btrfs on-disk structures - superblock, tree nodes, keys and the items the reader uses
+// Everything is little-endian and packed. Structures are parsed field by field from byte
+// slices with their offsets checked, since every one of them comes straight off a disk
+// that may be damaged.
+use moses_core::MosesError;
+
+/// The primary superblock; copies sit at 64 MiB and 256 GiB
+pub const SUPERBLOCK_OFFSET: u64 = 0x10000;
+pub const SUPERBLOCK_MIRRORS: [u64; 2] = [64 << 20, 256 << 30];
+pub const SUPERBLOCK_SIZE: usize = 4096;
+pub const MAGIC: &[u8; 8] = b"_BHRfS_M";
+/// Offset of the magic within the superblock
+pub const MAGIC_OFFSET: usize = 0x40;
+const SYS_CHUNK_ARRAY_OFFSET: usize = 0x32B;
+const SYS_CHUNK_ARRAY_MAX: usize = 2048;
+const LABEL_OFFSET: usize = 0x12B;
+const LABEL_SIZE: usize = 256;
+/// Checksums cover everything after the 32-byte checksum field
+pub const CSUM_SIZE: usize = 32;
+
+pub const CSUM_TYPE_CRC32C: u16 = 0;
+
+/// Tree ids
+pub const ROOT_TREE_OBJECTID: u64 = 1;
+pub const FS_TREE_OBJECTID: u64 = 5;
+/// The root directory of every filesystem tree, and the first id subvolumes get
+pub const FIRST_FREE_OBJECTID: u64 = 256;
+
+/// Item types
+pub const INODE_ITEM_KEY: u8 = 1;
+pub const DIR_INDEX_KEY: u8 = 96;
+pub const EXTENT_DATA_KEY: u8 = 108;
+pub const ROOT_ITEM_KEY: u8 = 132;
+pub const CHUNK_ITEM_KEY: u8 = 228;
+
+/// Chunk profiles that put the data of a stripe on more than one device in pieces
+pub const BLOCK_GROUP_RAID0: u64 = 1 << 3;
+pub const BLOCK_GROUP_RAID10: u64 = 1 << 6;
+pub const BLOCK_GROUP_RAID5: u64 = 1 << 7;
+pub const BLOCK_GROUP_RAID6: u64 = 1 << 8;
+pub const BLOCK_GROUP_STRIPED: u64 = BLOCK_GROUP_RAID0 | BLOCK_GROUP_RAID10 | BLOCK_GROUP_RAID5 | BLOCK_GROUP_RAID6;
+
+/// Directory entry types
+pub const FT_DIR: u8 = 2;
+pub const FT_SYMLINK: u8 = 7;
+
+/// File extent types and compression
+pub const FILE_EXTENT_INLINE: u8 = 0;
+pub const FILE_EXTENT_REG: u8 = 1;
+pub const FILE_EXTENT_PREALLOC: u8 = 2;
+pub const COMPRESS_NONE: u8 = 0;
+pub const COMPRESS_ZLIB: u8 = 1;
+pub const COMPRESS_LZO: u8 = 2;
+pub const COMPRESS_ZSTD: u8 = 3;
+/// The largest extent btrfs writes, measured before compression
+pub const MAX_EXTENT_SIZE: u64 = 128 << 20;
+
+/// Node header: csum, fsid, bytenr, flags, chunk tree uuid, generation, owner, nritems, level
+pub const NODE_HEADER_SIZE: usize = 101;
+/// Key (17 bytes) then data offset and size
+pub const LEAF_ITEM_SIZE: usize = 25;
+/// Key then block pointer and generation
+pub const KEY_PTR_SIZE: usize = 33;
+pub const KEY_SIZE: usize = 17;
+
+pub(crate) fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn corrupt(what: &str) -> MosesError {
+    MosesError::Other(format!("Corrupted btrfs {}", what))
+}
+
+/// Every item in a tree is found by its key, ordered by objectid, then type, then offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    pub objectid: u64,
+    pub item_type: u8,
+    pub offset: u64,
+}
+
+impl Key {
+    pub const fn new(objectid: u64, item_type: u8, offset: u64) -> Self {
+        Self { objectid, item_type, offset }
+    }
+
+    pub fn parse(data: &[u8]) -> Self {
+        Self { objectid: le_u64(data, 0), item_type: data[8], offset: le_u64(data, 9) }
+    }
+}
+
+/// The superblock fields the reader uses
+#[derive(Debug, Clone)]
+pub struct Superblock {
+    pub fsid: [u8; 16],
+    pub generation: u64,
+    pub root: u64,
+    pub chunk_root: u64,
+    pub total_bytes: u64,
+    pub bytes_used: u64,
+    pub num_devices: u64,
+    pub sectorsize: u32,
+    pub nodesize: u32,
+    pub incompat_flags: u64,
+    pub csum_type: u16,
+    pub root_level: u8,
+    pub chunk_root_level: u8,
+    /// devid of the device this superblock was read from
+    pub devid: u64,
+    pub label: String,
+    pub sys_chunk_array: Vec<u8>,
+}
+
+impl Superblock {
+    /// Parse and check a superblock copy; `data` is the whole 4 KiB
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < SUPERBLOCK_SIZE || &data[MAGIC_OFFSET..MAGIC_OFFSET + 8] != MAGIC {
+            return Err(MosesError::Other("Not a btrfs filesystem".to_string()));
+        }
+        let csum_type = le_u16(data, 0xC4);
+        if !checksum_matches(csum_type, data) {
+            return Err(corrupt("superblock: checksum mismatch"));
+        }
+        let sectorsize = le_u32(data, 0x90);
+        let nodesize = le_u32(data, 0x94);
+        if !sectorsize.is_power_of_two() || !(4096..=65536).contains(&sectorsize)
+            || !nodesize.is_power_of_two() || !(sectorsize..=65536).contains(&nodesize) {
+            return Err(corrupt(&format!("superblock: sector size {}, node size {}", sectorsize, nodesize)));
+        }
+        let sys_chunk_array_size = le_u32(data, 0xA0) as usize;
+        if sys_chunk_array_size > SYS_CHUNK_ARRAY_MAX {
+            return Err(corrupt(&format!("superblock: system chunk array of {} bytes", sys_chunk_array_size)));
+        }
+        let label = &data[LABEL_OFFSET..LABEL_OFFSET + LABEL_SIZE];
+        let label_len = label.iter().position(|&b| b == 0).unwrap_or(LABEL_SIZE);
+        Ok(Self {
+            fsid: data[0x20..0x30].try_into().unwrap(),
+            generation: le_u64(data, 0x48),
+            root: le_u64(data, 0x50),
+            chunk_root: le_u64(data, 0x58),
+            total_bytes: le_u64(data, 0x70),
+            bytes_used: le_u64(data, 0x78),
+            num_devices: le_u64(data, 0x88),
+            sectorsize,
+            nodesize,
+            incompat_flags: le_u64(data, 0xBC),
+            csum_type,
+            root_level: data[0xC6],
+            chunk_root_level: data[0xC7],
+            devid: le_u64(data, 0xC9),
+            label: String::from_utf8_lossy(&label[..label_len]).into_owned(),
+            sys_chunk_array: data[SYS_CHUNK_ARRAY_OFFSET..SYS_CHUNK_ARRAY_OFFSET + sys_chunk_array_size].to_vec(),
+        })
+    }
+
+    pub fn uuid(&self) -> String {
+        uuid::Uuid::from_bytes(self.fsid).to_string()
+    }
+}
+
+/// Whether the checksum at the start of `block` covers the rest of it. Only crc32c, the
+/// default, is checked; volumes made with xxhash, sha256 or blake2 are read unchecked.
+pub fn checksum_matches(csum_type: u16, block: &[u8]) -> bool {
+    csum_type != CSUM_TYPE_CRC32C || crc32c::crc32c(&block[CSUM_SIZE..]).to_le_bytes() == block[..4]
+}
+
+/// Header of a tree node
+#[derive(Debug, Clone, Copy)]
+pub struct NodeHeader {
+    pub bytenr: u64,
+    pub owner: u64,
+    pub nritems: u32,
+    pub level: u8,
+}
+
+impl NodeHeader {
+    pub fn parse(node: &[u8]) -> Self {
+        Self { bytenr: le_u64(node, 0x30), owner: le_u64(node, 0x58), nritems: le_u32(node, 0x60), level: node[0x64] }
+    }
+}
+
+/// The items of a leaf, with their data
+pub fn leaf_items(node: &[u8], nritems: u32) -> Result<Vec<(Key, &[u8])>, MosesError> {
+    (0..nritems as usize).map(|i| {
+        let at = NODE_HEADER_SIZE + i * LEAF_ITEM_SIZE;
+        if at + LEAF_ITEM_SIZE > node.len() {
+            return Err(corrupt(&format!("leaf: {} items do not fit", nritems)));
+        }
+        let offset = NODE_HEADER_SIZE + le_u32(node, at + KEY_SIZE) as usize;
+        let size = le_u32(node, at + KEY_SIZE + 4) as usize;
+        let data = node.get(offset..offset + size)
+            .ok_or_else(|| corrupt(&format!("leaf: item {} data runs past the node", i)))?;
+        Ok((Key::parse(&node[at..]), data))
+    }).collect()
+}
+
+/// The child pointers of an internal node: first key below each child, and its address
+pub fn key_pointers(node: &[u8], nritems: u32) -> Result<Vec<(Key, u64)>, MosesError> {
+    (0..nritems as usize).map(|i| {
+        let at = NODE_HEADER_SIZE + i * KEY_PTR_SIZE;
+        if at + KEY_PTR_SIZE > node.len() {
+            return Err(corrupt(&format!("node: {} pointers do not fit", nritems)));
+        }
+        Ok((Key::parse(&node[at..]), le_u64(node, at + KEY_SIZE)))
+    }).collect()
+}
+
+/// One stripe of a chunk: where on which device its data is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stripe {
+    pub devid: u64,
+    pub offset: u64,
+}
+
+/// A chunk item: `length` bytes of logical address space stored on `stripes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub length: u64,
+    pub chunk_type: u64,
+    pub stripes: Vec<Stripe>,
+}
+
+const CHUNK_HEADER_SIZE: usize = 48;
+const STRIPE_SIZE: usize = 32;
+
+impl Chunk {
+    /// Parse a chunk item; returns it and the bytes it took
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), MosesError> {
+        if data.len() < CHUNK_HEADER_SIZE {
+            return Err(corrupt("chunk item: too short"));
+        }
+        let num_stripes = le_u16(data, 44) as usize;
+        let size = CHUNK_HEADER_SIZE + num_stripes * STRIPE_SIZE;
+        if num_stripes == 0 || data.len() < size {
+            return Err(corrupt(&format!("chunk item: {} stripes", num_stripes)));
+        }
+        let stripes = (0..num_stripes).map(|i| {
+            let at = CHUNK_HEADER_SIZE + i * STRIPE_SIZE;
+            Stripe { devid: le_u64(data, at), offset: le_u64(data, at + 8) }
+        }).collect();
+        Ok((Self { length: le_u64(data, 0), chunk_type: le_u64(data, 24), stripes }, size))
+    }
+}
+
+/// The inode fields the reader uses
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InodeItem {
+    pub size: u64,
+    pub nbytes: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub atime: u64,
+    pub ctime: u64,
+    pub mtime: u64,
+    pub otime: u64,
+}
+
+pub const INODE_ITEM_SIZE: usize = 160;
+
+impl InodeItem {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < INODE_ITEM_SIZE {
+            return Err(corrupt("inode item: too short"));
+        }
+        Ok(Self {
+            size: le_u64(data, 16),
+            nbytes: le_u64(data, 24),
+            nlink: le_u32(data, 40),
+            uid: le_u32(data, 44),
+            gid: le_u32(data, 48),
+            mode: le_u32(data, 52),
+            atime: le_u64(data, 112),
+            ctime: le_u64(data, 124),
+            mtime: le_u64(data, 136),
+            otime: le_u64(data, 148),
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & 0o170000 == 0o120000
+    }
+}
+
+/// Where a subvolume's tree is: from a root item
+#[derive(Debug, Clone, Copy)]
+pub struct RootItem {
+    pub root_dirid: u64,
+    pub bytenr: u64,
+    pub level: u8,
+}
+
+impl RootItem {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < 239 {
+            return Err(corrupt("root item: too short"));
+        }
+        Ok(Self { root_dirid: le_u64(data, 168), bytenr: le_u64(data, 176), level: data[238] })
+    }
+}
+
+/// One name in a directory, from a DIR_INDEX item
+#[derive(Debug, Clone)]
+pub struct DirItem {
+    /// The inode, or for a subvolume its ROOT_ITEM key
+    pub location: Key,
+    pub file_type: u8,
+    pub name: String,
+}
+
+const DIR_ITEM_HEADER: usize = 30;
+
+impl DirItem {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < DIR_ITEM_HEADER {
+            return Err(corrupt("directory item: too short"));
+        }
+        let data_len = le_u16(data, 25) as usize;
+        let name_len = le_u16(data, 27) as usize;
+        let name = data.get(DIR_ITEM_HEADER..DIR_ITEM_HEADER + name_len)
+            .filter(|_| DIR_ITEM_HEADER + name_len + data_len <= data.len())
+            .ok_or_else(|| corrupt("directory item: name runs past the item"))?;
+        Ok(Self { location: Key::parse(data), file_type: data[29], name: String::from_utf8_lossy(name).into_owned() })
+    }
+}
+
+/// A piece of a file: where bytes from `file_offset` on come from
+#[derive(Debug, Clone)]
+pub enum FileExtent {
+    /// Data stored in the item itself, possibly compressed
+    Inline { compression: u8, ram_bytes: u64, data: Vec<u8> },
+    /// Data in an extent elsewhere; `disk_bytenr` 0 is a hole. Compressed extents are
+    /// decompressed whole, then `offset`..`offset + num_bytes` of the result is this piece.
+    Regular { compression: u8, disk_bytenr: u64, disk_num_bytes: u64, ram_bytes: u64, offset: u64, num_bytes: u64, prealloc: bool },
+}
+
+const FILE_EXTENT_HEADER: usize = 21;
+
+impl FileExtent {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < FILE_EXTENT_HEADER {
+            return Err(corrupt("file extent: too short"));
+        }
+        let ram_bytes = le_u64(data, 8);
+        let compression = data[16];
+        if data[17] != 0 {
+            return Err(MosesError::NotSupported("Encrypted btrfs extents cannot be read".to_string()));
+        }
+        match data[20] {
+            FILE_EXTENT_INLINE => Ok(Self::Inline { compression, ram_bytes, data: data[FILE_EXTENT_HEADER..].to_vec() }),
+            extent_type @ (FILE_EXTENT_REG | FILE_EXTENT_PREALLOC) if data.len() >= FILE_EXTENT_HEADER + 32 => {
+                let extent = Self::Regular {
+                    compression,
+                    disk_bytenr: le_u64(data, 21),
+                    disk_num_bytes: le_u64(data, 29),
+                    ram_bytes,
+                    offset: le_u64(data, 37),
+                    num_bytes: le_u64(data, 45),
+                    prealloc: extent_type == FILE_EXTENT_PREALLOC,
+                };
+                // Holes may cover any length; extents with data are never larger than
+                // MAX_EXTENT_SIZE, and the piece a file uses lies within the extent
+                if let Self::Regular { disk_bytenr: 1.., disk_num_bytes, ram_bytes, offset, num_bytes, .. } = extent {
+                    if disk_num_bytes > MAX_EXTENT_SIZE || ram_bytes > MAX_EXTENT_SIZE
+                        || offset.checked_add(num_bytes).is_none_or(|end| end > ram_bytes) {
+                        return Err(corrupt(&format!(
+                            "file extent: {} bytes on disk, {} decompressed, {} from {} used",
+                            disk_num_bytes, ram_bytes, num_bytes, offset
+                        )));
+                    }
+                }
+                Ok(extent)
+            }
+            extent_type => Err(corrupt(&format!("file extent: type {} in {} bytes", extent_type, data.len()))),
+        }
+    }
+
+    /// Bytes of the file this piece covers
+    pub fn covers(&self) -> u64 {
+        match self {
+            Self::Inline { ram_bytes, .. } => *ram_bytes,
+            Self::Regular { num_bytes, .. } => *num_bytes,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        match self {
+            Self::Inline { compression, .. } | Self::Regular { compression, .. } => *compression != COMPRESS_NONE,
+        }
+    }
+}
+
+/// Decompress one extent's data to `ram_bytes`
+pub fn decompress(compression: u8, data: &[u8], ram_bytes: u64) -> Result<Vec<u8>, MosesError> {
+    if ram_bytes > MAX_EXTENT_SIZE {
+        return Err(corrupt(&format!("extent: {} bytes decompressed", ram_bytes)));
+    }
+    use std::io::Read;
+    let mut out = Vec::with_capacity(ram_bytes as usize);
+    match compression {
+        COMPRESS_NONE => out.extend_from_slice(data),
+        COMPRESS_ZLIB => {
+            flate2::read::ZlibDecoder::new(data).take(ram_bytes).read_to_end(&mut out)
+                .map_err(|e| corrupt(&format!("zlib extent: {}", e)))?;
+        }
+        COMPRESS_ZSTD => {
+            let decoder = zstd::stream::read::Decoder::new(data)
+                .map_err(|e| corrupt(&format!("zstd extent: {}", e)))?;
+            decoder.take(ram_bytes).read_to_end(&mut out)
+                .map_err(|e| corrupt(&format!("zstd extent: {}", e)))?;
+        }
+        COMPRESS_LZO => return Err(MosesError::NotSupported("LZO-compressed btrfs extents cannot be read yet".to_string())),
+        other => return Err(corrupt(&format!("extent: unknown compression {}", other))),
+    }
+    out.resize(ram_bytes as usize, 0);
+    Ok(out)
+}
@@ -0,0 +1,149 @@
+// In-place mode and owner edits of ext inodes - chmod and chown without mounting
+// The inode is found through the reader's path lookup and patched where it lies in the
+// inode table: the permission bits of i_mode, the owner and group (low halves in the
+// inode body, high halves in the Linux part of i_osd2), i_ctime, and with metadata_csum
+// the inode checksum. Nothing is allocated, so this works on any ext2/3/4 filesystem the
+// reader opens, and as a MetadataPatch it can be dry-run and undone.
+
+use moses_core::{Device, MetadataPatch, MosesError};
+use std::io::{Read, Seek};
+
+use super::ext4_native::core::{checksum::crc32c_ext4, constants::*, structures::Ext4Superblock};
+use super::ext4_native::ExtReader;
+use super::rescue::{read_bytes, PRIMARY_OFFSET, SUPERBLOCK_SIZE};
+use crate::ops::AttributeChanges;
+
+const MODE_OFFSET: usize = 0x00;
+const UID_OFFSET: usize = 0x02;
+const CTIME_OFFSET: usize = 0x0C;
+const GID_OFFSET: usize = 0x18;
+const GENERATION_OFFSET: usize = 0x64;
+const CHECKSUM_LO_OFFSET: usize = 0x7C;
+/// i_osd2 holds the high 16 bits of the owner, then of the group
+const UID_HIGH_OFFSET: usize = 0x78;
+const GID_HIGH_OFFSET: usize = 0x7A;
+const EXTRA_ISIZE_OFFSET: usize = 0x80;
+const CHECKSUM_HI_OFFSET: usize = 0x82;
+const PERMISSION_BITS: u32 = 0o7777;
+
+/// metadata_csum checksum of an inode, with the checksum fields taken as zero
+fn inode_checksum(sb: &Ext4Superblock, inode_num: u32, inode: &[u8]) -> u32 {
+    let seed = if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+        sb.s_checksum_seed
+    } else {
+        crc32c_ext4(&sb.s_uuid, !0)
+    };
+    let mut crc = crc32c_ext4(&inode_num.to_le_bytes(), seed);
+    crc = crc32c_ext4(&inode[GENERATION_OFFSET..GENERATION_OFFSET + 4], crc);
+    let mut bytes = inode.to_vec();
+    bytes[CHECKSUM_LO_OFFSET..CHECKSUM_LO_OFFSET + 2].fill(0);
+    if bytes.len() > CHECKSUM_HI_OFFSET + 2 {
+        bytes[CHECKSUM_HI_OFFSET..CHECKSUM_HI_OFFSET + 2].fill(0);
+    }
+    crc32c_ext4(&bytes, crc)
+}
+
+/// The patch that applies the mode and owner part of `changes` to `path` on the ext
+/// filesystem of `device`, read through `reader`
+pub fn plan_attributes<R: Read + Seek>(
+    reader: &mut R,
+    device: &Device,
+    path: &str,
+    changes: &AttributeChanges,
+) -> Result<MetadataPatch, MosesError> {
+    if changes.set_dos != 0 || changes.clear_dos != 0 {
+        return Err(MosesError::NotSupported(
+            "ext has no DOS attributes; use the mode to make a file read-only".to_string()
+        ));
+    }
+    if changes.mode.is_some_and(|mode| mode & !PERMISSION_BITS != 0) {
+        return Err(MosesError::InvalidInput(format!("Mode {:o} has bits beyond 7777", changes.mode.unwrap_or(0))));
+    }
+    let primary = read_bytes(reader, PRIMARY_OFFSET, SUPERBLOCK_SIZE)?;
+    let sb = unsafe { std::ptr::read_unaligned(primary.as_ptr() as *const Ext4Superblock) };
+    if sb.s_magic != EXT4_SUPER_MAGIC {
+        return Err(MosesError::InvalidInput(format!("{} has no ext2/3/4 superblock", device.id)));
+    }
+    if (changes.owner.is_some_and(|id| id > 0xFFFF) || changes.group.is_some_and(|id| id > 0xFFFF)) && sb.s_rev_level == 0 {
+        return Err(MosesError::InvalidInput("Revision 0 ext filesystems store 16-bit owners only".to_string()));
+    }
+
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+    let (inode_num, offset, inode_size) = ExtReader::new(device.clone())?.inode_location(&path)?;
+    let before = read_bytes(reader, offset, inode_size)?;
+    let mut after = before.clone();
+    let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    if let Some(mode) = changes.mode {
+        let mode = (u16_at(&before, MODE_OFFSET) as u32 & !PERMISSION_BITS) | mode;
+        after[MODE_OFFSET..MODE_OFFSET + 2].copy_from_slice(&(mode as u16).to_le_bytes());
+    }
+    for (id, low, high) in [(changes.owner, UID_OFFSET, UID_HIGH_OFFSET), (changes.group, GID_OFFSET, GID_HIGH_OFFSET)] {
+        if let Some(id) = id {
+            after[low..low + 2].copy_from_slice(&(id as u16).to_le_bytes());
+            after[high..high + 2].copy_from_slice(&((id >> 16) as u16).to_le_bytes());
+        }
+    }
+
+    let mut patch = MetadataPatch::new(format!("{} of {}", changes.describe(), path), &device.id);
+    if after == before {
+        return Ok(patch);
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    after[CTIME_OFFSET..CTIME_OFFSET + 4].copy_from_slice(&(now as u32).to_le_bytes());
+    let mut fields = vec![
+        ("mode", MODE_OFFSET, 2),
+        ("owner", UID_OFFSET, 2),
+        ("change time", CTIME_OFFSET, 4),
+        ("group", GID_OFFSET, 2),
+        ("owner and group high bits", UID_HIGH_OFFSET, 4),
+    ];
+    if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0 {
+        let checksum = inode_checksum(&sb, inode_num, &after);
+        after[CHECKSUM_LO_OFFSET..CHECKSUM_LO_OFFSET + 2].copy_from_slice(&(checksum as u16).to_le_bytes());
+        fields.push(("checksum", CHECKSUM_LO_OFFSET, 2));
+        if inode_size > EXTRA_ISIZE_OFFSET + 2 && u16_at(&after, EXTRA_ISIZE_OFFSET) >= 4 {
+            after[CHECKSUM_HI_OFFSET..CHECKSUM_HI_OFFSET + 2].copy_from_slice(&((checksum >> 16) as u16).to_le_bytes());
+            fields.push(("checksum high bits", CHECKSUM_HI_OFFSET, 2));
+        }
+    }
+    for (field, start, len) in fields {
+        patch.set(
+            format!("inode {}: {}", inode_num, field),
+            offset + start as u64,
+            before[start..start + len].to_vec(),
+            after[start..start + len].to_vec(),
+        )?;
+    }
+    Ok(patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::families::ext::ext4_native::core::verify::verify_ext_filesystem;
+    use moses_core::FormatOptions;
+
+    #[tokio::test]
+    async fn test_chmod_and_chown_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ext4.img");
+        let options = FormatOptions { filesystem_type: "ext4".to_string(), ..Default::default() };
+        let device = crate::image_target::format_image(&crate::Ext4NativeFormatter, &path, 300 * 1024 * 1024, &options).await.unwrap();
+        let mut disk = std::fs::File::options().read(true).write(true).open(&path).unwrap();
+
+        let changes = AttributeChanges { mode: Some(0o700), owner: Some(70000), group: Some(100), ..Default::default() };
+        let patch = plan_attributes(&mut disk, &device, "/", &changes).unwrap();
+        assert!(patch.ranges.iter().any(|range| range.field == "inode 2: owner and group high bits"));
+        patch.apply(&mut disk, None).unwrap();
+        let stat = ExtReader::new(device.clone()).unwrap().stat("/").unwrap();
+        assert_eq!(stat.mode, 0o040700);
+        assert_eq!((stat.uid, stat.gid), (70000 & 0xFFFF, 100), "the reader shows the low halves");
+        assert!(verify_ext_filesystem(&mut disk).unwrap().is_valid);
+        let again = AttributeChanges { mode: Some(0o700), ..Default::default() };
+        assert!(plan_attributes(&mut disk, &device, "/", &again).unwrap().is_empty());
+
+        let hidden = AttributeChanges { set_dos: crate::ops::dos_attributes::HIDDEN, ..Default::default() };
+        assert!(matches!(plan_attributes(&mut disk, &device, "/", &hidden), Err(MosesError::NotSupported(_))));
+        assert!(plan_attributes(&mut disk, &device, "/missing", &again).is_err());
+    }
+}
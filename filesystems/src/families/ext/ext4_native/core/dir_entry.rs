@@ -0,0 +1,212 @@
+// Directory entries - bounds-checked reading and writing of ext directory blocks
+// A directory block is a chain of variable-length entries, each giving the distance to the
+// next in rec_len. Every part of the ext code that walks a block (reader, writer, HTree
+// lookup, verification) goes through DirEntries, so a corrupted rec_len or name_len is an
+// error in one place instead of an out-of-bounds read in several, and the metadata_csum tail
+// that closes each block is recognised the same way everywhere.
+use moses_core::MosesError;
+
+/// inode (4), rec_len (2), name_len (1), file_type (1)
+pub const DIR_ENTRY_HEADER: usize = 8;
+/// The smallest rec_len a real entry can have (a one-byte name, padded)
+pub const MIN_REC_LEN: usize = 12;
+/// The fake entry ending each directory block on metadata_csum filesystems
+pub const CSUM_TAIL_SIZE: usize = 12;
+pub const CSUM_TAIL_FILE_TYPE: u8 = 0xDE;
+/// rec_len on disk for an entry spanning a whole 64 KiB block
+const MAX_REC_LEN: u16 = 0xFFFF;
+
+/// Bytes an entry with a `name_len`-byte name needs, padded to 4
+pub fn rec_len_for(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+/// rec_len as stored; 64 KiB blocks store a whole-block entry as 0 or 0xFFFF
+fn rec_len_from_disk(raw: u16, block_len: usize) -> usize {
+    if block_len >= 65536 && (raw == 0 || raw == MAX_REC_LEN) {
+        65536
+    } else {
+        raw as usize
+    }
+}
+
+fn rec_len_to_disk(rec_len: usize) -> u16 {
+    if rec_len >= 65536 { MAX_REC_LEN } else { rec_len as u16 }
+}
+
+/// One entry of a directory block, borrowed from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntryRef<'a> {
+    /// Byte offset of the entry in the block
+    pub offset: usize,
+    /// 0 for an unused entry
+    pub inode: u32,
+    pub rec_len: usize,
+    pub file_type: u8,
+    pub name: &'a [u8],
+}
+
+impl<'a> DirEntryRef<'a> {
+    pub fn is_used(&self) -> bool {
+        self.inode != 0
+    }
+
+    /// The metadata_csum tail, which holds the block checksum and is never a free slot
+    pub fn is_csum_tail(&self) -> bool {
+        self.inode == 0 && self.rec_len == CSUM_TAIL_SIZE && self.name.is_empty() && self.file_type == CSUM_TAIL_FILE_TYPE
+    }
+
+    pub fn is_dot_or_dotdot(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
+
+    /// Name for display; ext names are bytes, usually UTF-8
+    pub fn name_lossy(&self) -> String {
+        String::from_utf8_lossy(self.name).into_owned()
+    }
+
+    /// Bytes the entry itself occupies; the rest of rec_len is free. 0 when unused.
+    pub fn used_size(&self) -> usize {
+        if self.is_used() { rec_len_for(self.name.len()) } else { 0 }
+    }
+
+    /// Free bytes after the entry, where another could be placed
+    pub fn slack(&self) -> usize {
+        self.rec_len - self.used_size()
+    }
+}
+
+/// The entries of one directory block, in order. A malformed entry ends the walk with an
+/// error; entries before it are still yielded.
+pub struct DirEntries<'a> {
+    block: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+pub fn dir_entries(block: &[u8]) -> DirEntries<'_> {
+    DirEntries { block, offset: 0, done: false }
+}
+
+impl<'a> DirEntries<'a> {
+    fn parse(&self) -> Result<DirEntryRef<'a>, String> {
+        let block = self.block;
+        let offset = self.offset;
+        if offset + DIR_ENTRY_HEADER > block.len() {
+            return Err("header runs past the end of the block".to_string());
+        }
+        let inode = u32::from_le_bytes([block[offset], block[offset + 1], block[offset + 2], block[offset + 3]]);
+        let rec_len = rec_len_from_disk(u16::from_le_bytes([block[offset + 4], block[offset + 5]]), block.len());
+        let name_len = block[offset + 6] as usize;
+        let file_type = block[offset + 7];
+        if !rec_len.is_multiple_of(4) || rec_len < MIN_REC_LEN {
+            return Err(format!("rec_len {} is not a multiple of 4 of at least {}", rec_len, MIN_REC_LEN));
+        }
+        if offset + rec_len > block.len() {
+            return Err(format!("rec_len {} runs past the end of the {}-byte block", rec_len, block.len()));
+        }
+        if DIR_ENTRY_HEADER + name_len > rec_len {
+            return Err(format!("name_len {} does not fit in rec_len {}", name_len, rec_len));
+        }
+        let name = &block[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name_len];
+        if inode != 0 && name.is_empty() {
+            return Err(format!("entry for inode {} has no name", inode));
+        }
+        Ok(DirEntryRef { offset, inode, rec_len, file_type, name })
+    }
+}
+
+impl<'a> Iterator for DirEntries<'a> {
+    type Item = Result<DirEntryRef<'a>, MosesError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.block.len() {
+            return None;
+        }
+        match self.parse() {
+            Ok(entry) => {
+                self.offset += entry.rec_len;
+                Some(Ok(entry))
+            }
+            Err(problem) => {
+                self.done = true;
+                Some(Err(MosesError::Other(format!("Corrupted directory entry at offset {}: {}", self.offset, problem))))
+            }
+        }
+    }
+}
+
+/// Write an entry at `offset` spanning `rec_len` bytes
+pub fn write_entry(block: &mut [u8], offset: usize, inode: u32, rec_len: usize, name: &[u8], file_type: u8) -> Result<(), MosesError> {
+    if name.len() > 255 {
+        return Err(MosesError::InvalidInput(format!("Name is {} bytes; ext allows 255", name.len())));
+    }
+    if !rec_len.is_multiple_of(4) || rec_len < rec_len_for(name.len()) || offset + rec_len > block.len() {
+        return Err(MosesError::Other(format!(
+            "No room for a {}-byte name in {} bytes at offset {} of a {}-byte block", name.len(), rec_len, offset, block.len(),
+        )));
+    }
+    block[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+    block[offset + 4..offset + 6].copy_from_slice(&rec_len_to_disk(rec_len).to_le_bytes());
+    block[offset + 6] = name.len() as u8;
+    block[offset + 7] = file_type;
+    block[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name);
+    Ok(())
+}
+
+/// Change the inode an entry found by DirEntries points at; 0 marks it unused
+pub fn set_inode(block: &mut [u8], entry: usize, inode: u32) {
+    block[entry..entry + 4].copy_from_slice(&inode.to_le_bytes());
+}
+
+/// Change the length of an entry found by DirEntries
+pub fn set_rec_len(block: &mut [u8], entry: usize, rec_len: usize) {
+    block[entry + 4..entry + 6].copy_from_slice(&rec_len_to_disk(rec_len).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_block_walk() {
+        let mut block = vec![0u8; 1024];
+        write_entry(&mut block, 0, 2, 12, b".", 2).unwrap();
+        write_entry(&mut block, 12, 2, 12, b"..", 2).unwrap();
+        write_entry(&mut block, 24, 11, 1024 - 24 - CSUM_TAIL_SIZE, b"lost+found", 2).unwrap();
+        write_entry(&mut block, 1024 - CSUM_TAIL_SIZE, 0, CSUM_TAIL_SIZE, b"", CSUM_TAIL_FILE_TYPE).unwrap();
+
+        let entries: Vec<_> = dir_entries(&block).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries[0].is_dot_or_dotdot() && entries[1].is_dot_or_dotdot());
+        assert_eq!(entries[2].name_lossy(), "lost+found");
+        assert_eq!(entries[2].used_size(), 20);
+        assert_eq!(entries[2].slack(), 1024 - 24 - CSUM_TAIL_SIZE - 20);
+        assert!(entries[3].is_csum_tail() && !entries[2].is_csum_tail());
+
+        // Splitting an entry's slack off keeps the chain whole
+        set_rec_len(&mut block, 24, 20);
+        write_entry(&mut block, 44, 12, 1024 - 44 - CSUM_TAIL_SIZE, b"notes.txt", 1).unwrap();
+        let names: Vec<_> = dir_entries(&block).map(|entry| entry.unwrap().name_lossy()).collect();
+        assert_eq!(names, [".", "..", "lost+found", "notes.txt", ""]);
+        set_inode(&mut block, 44, 0);
+        assert!(!dir_entries(&block).nth(3).unwrap().unwrap().is_used());
+
+        // A zero rec_len, one past the block and a name longer than its entry are errors,
+        // after the entries before them
+        for (field, value) in [(4, 0u16), (4, 2000), (6, 40)] {
+            let mut bad = block.clone();
+            if field == 6 {
+                bad[24 + 6] = value as u8;
+                set_rec_len(&mut bad, 24, 20);
+            } else {
+                bad[24 + field..24 + field + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            let results: Vec<_> = dir_entries(&bad).collect();
+            assert_eq!(results.len(), 3);
+            assert!(results[..2].iter().all(Result::is_ok));
+            assert!(results[2].is_err());
+        }
+        assert!(write_entry(&mut block, 1020, 5, 12, b"x", 1).is_err());
+    }
+}
@@ -15,6 +15,9 @@ pub struct ExtFilesystemBuilder {
     device_size: u64,
     block_size: u32,
     label: Option<String>,
+    uuid: Option<[u8; 16]>,
+    created: Option<u32>,
+    root_owner: Option<(u32, u32)>,
 }
 
 impl ExtFilesystemBuilder {
@@ -25,6 +28,9 @@ impl ExtFilesystemBuilder {
             device_size,
             block_size: 4096,
             label: None,
+            uuid: None,
+            created: None,
+            root_owner: None,
         }
     }
     
@@ -35,6 +41,9 @@ impl ExtFilesystemBuilder {
             device_size,
             block_size: 4096,
             label: None,
+            uuid: None,
+            created: None,
+            root_owner: None,
         }
     }
     
@@ -45,6 +54,9 @@ impl ExtFilesystemBuilder {
             device_size,
             block_size: 4096,
             label: None,
+            uuid: None,
+            created: None,
+            root_owner: None,
         }
     }
     
@@ -60,6 +72,19 @@ impl ExtFilesystemBuilder {
         self
     }
     
+    /// Take the UUID and timestamps from `seed` in deterministic mode
+    pub fn seed(mut self, seed: &crate::deterministic::FormatSeed) -> Self {
+        self.uuid = seed.uuid("ext uuid");
+        self.created = seed.is_deterministic().then(|| seed.unix_time() as u32);
+        self
+    }
+    
+    /// Give the root directory to `owner` (uid, gid) instead of root
+    pub fn root_owner(mut self, owner: Option<(u32, u32)>) -> Self {
+        self.root_owner = owner;
+        self
+    }
+    
     /// Build FilesystemParams appropriate for this ext version
     pub fn build_params(&self) -> FilesystemParams {
         FilesystemParams {
@@ -71,6 +96,9 @@ impl ExtFilesystemBuilder {
             enable_checksums: self.config.use_metadata_csum,
             enable_64bit: self.config.use_64bit,
             enable_journal: self.config.has_journal,
+            uuid: self.uuid,
+            created: self.created,
+            root_owner: self.root_owner,
         }
     }
     
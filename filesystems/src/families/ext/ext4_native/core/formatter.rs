@@ -17,12 +17,12 @@ impl FilesystemFormatter for Ext4NativeFormatter {
     
     fn can_format(&self, device: &Device) -> bool {
         // Never format system drives
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
-        // Only format removable devices for extra safety
-        device.is_removable
+        // Only format removable devices (or image files) for extra safety
+        device.is_removable || device.is_image_file()
     }
     
     fn requires_external_tools(&self) -> bool {
@@ -58,6 +58,11 @@ impl FilesystemFormatter for Ext4NativeFormatter {
                 return Err(MosesError::Other("Label must be 16 characters or less".to_string()));
             }
         }
+        if let Some(block_size) = options.cluster_size.filter(|&size| size != 4096) {
+            return Err(MosesError::NotSupported(format!(
+                "ext4 block size {} is not supported; only 4096 byte blocks are", block_size
+            )));
+        }
         Ok(())
     }
     
@@ -66,6 +71,8 @@ impl FilesystemFormatter for Ext4NativeFormatter {
         device: &Device,
         options: &FormatOptions,
     ) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         // Validate options first
         self.validate_options(options).await?;
         
@@ -122,6 +129,14 @@ impl FilesystemFormatter for Ext4NativeFormatter {
             warnings.push("✔️ Post-format verification enabled - filesystem will be validated".to_string());
         }
         
+        let block_size = options.cluster_size.unwrap_or(4096);
+        if let Some(flash) = crate::families::ext::FlashTuning::from_options(device, options, block_size)? {
+            warnings.push(format!("💾 {}", flash.describe()));
+            if flash.journal == crate::families::ext::FlashJournal::Async {
+                warnings.push("⚠️ The native formatter writes no journal, so journal_async does not apply; use the system formatter to keep a journal".to_string());
+            }
+        }
+        
         Ok(SimulationReport {
             device: device.clone(),
             options: options.clone(),
@@ -130,6 +145,12 @@ impl FilesystemFormatter for Ext4NativeFormatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: usable_space,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
\ No newline at end of file
@@ -180,14 +180,7 @@ pub async fn format_device_ext_version(
     // Now write everything to disk (same as ext4)
     progress.start_step(4, "Opening device for writing");
     
-    #[cfg(target_os = "windows")]
-    let device_path = if device.id.starts_with(r"\\.\") {
-        device.id.clone()
-    } else {
-        format!(r"\\.\{}", device.id)
-    };
-    #[cfg(not(target_os = "windows"))]
-    let device_path = format!("/dev/{}", device.id);
+    let device_path = crate::utils::get_device_path(device);
     
     info!("Writing filesystem to device: {}", device_path);
     
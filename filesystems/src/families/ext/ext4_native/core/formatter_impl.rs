@@ -39,6 +39,7 @@ pub async fn format_device_with_progress(
     info!("Device size: {} bytes ({} GB)", device.size, device.size / (1024*1024*1024));
     info!("Cluster size: {:?}", options.cluster_size);
     
+    let seed = crate::deterministic::FormatSeed::from_options(options)?;
     let params = FilesystemParams {
         size_bytes: device.size,
         block_size: options.cluster_size.unwrap_or(4096) as u32,
@@ -48,6 +49,9 @@ pub async fn format_device_with_progress(
         enable_checksums: true,
         enable_64bit: true, // Always enable 64-bit like modern mkfs.ext4
         enable_journal: false,
+        uuid: seed.uuid("ext uuid"),
+        created: seed.is_deterministic().then(|| seed.unix_time() as u32),
+        root_owner: crate::disk_manager::seeding::ext_root_owner(options)?,
     };
     
     info!("Filesystem params created: block_size={}, size_bytes={}", 
@@ -70,6 +74,10 @@ pub async fn format_device_with_progress(
     // Create and initialize superblock
     let mut sb = Ext4Superblock::new();
     sb.init_minimal(&params, &layout);
+    if let Some(flash) = crate::families::ext::FlashTuning::from_options(device, options, params.block_size)? {
+        info!("{}", flash.describe());
+        flash.apply(&mut sb);
+    }
     
     progress.start_step(2, "Initializing block groups");
     
@@ -286,18 +294,7 @@ pub async fn format_device_with_progress(
     
     progress.start_step(4, "Opening device for writing");
     // Open device for writing
-    #[cfg(target_os = "windows")]
-    let device_path = if device.id.starts_with(r"\\.\") {
-        device.id.clone()
-    } else {
-        format!(r"\\.\{}", device.id)
-    };
-    #[cfg(not(target_os = "windows"))]
-    let device_path = if device.id.starts_with('/') {
-        device.id.clone()
-    } else {
-        format!("/dev/{}", device.id)
-    };
+    let device_path = crate::utils::get_device_path(device);
     
     info!("Formatting device - ID: '{}', Path: '{}'", device.id, device_path);
     
@@ -699,15 +696,7 @@ pub async fn format_device_with_verification(
     info!("Starting post-format verification");
     
     // Verify the filesystem
-    let device_path = if cfg!(target_os = "windows") {
-        if device.id.starts_with(r"\\.\") {
-            device.id.clone()
-        } else {
-            format!(r"\\.\{}", device.id)
-        }
-    } else {
-        format!("/dev/{}", device.id)
-    };
+    let device_path = crate::utils::get_device_path(device);
     
     match verify::verify_device(&device_path) {
         Ok(verification_result) => {
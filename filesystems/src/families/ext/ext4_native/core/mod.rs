@@ -5,6 +5,7 @@ pub mod bitmap;
 pub mod block_allocator;
 pub mod checksum;
 pub mod constants;
+pub mod dir_entry;
 pub mod endian;
 pub mod ext_config;
 pub mod ext_builder;
@@ -1,6 +1,8 @@
 // Progress reporting for ext4 formatting operations
 
 use std::sync::Arc;
+use std::time::Duration;
+use moses_core::ThroughputEstimator;
 
 /// Progress information for formatting operations
 #[derive(Debug, Clone)]
@@ -17,6 +19,10 @@ pub struct FormatProgress {
     pub bytes_written: u64,
     /// Total bytes to write (estimated)
     pub total_bytes: u64,
+    /// Smoothed write throughput, once measured
+    pub bytes_per_sec: Option<f64>,
+    /// Estimated time remaining
+    pub eta: Option<Duration>,
 }
 
 impl FormatProgress {
@@ -28,6 +34,8 @@ impl FormatProgress {
             percentage: 0.0,
             bytes_written: 0,
             total_bytes,
+            bytes_per_sec: None,
+            eta: None,
         }
     }
     
@@ -67,11 +75,15 @@ pub struct LoggingProgress;
 impl ProgressCallback for LoggingProgress {
     fn on_progress(&self, progress: &FormatProgress) {
         use log::info;
-        info!("Format progress: {:.1}% - Step {}/{}: {}", 
+        let eta = progress.eta
+            .map(|eta| format!(" ({} left)", moses_core::progress::format_duration(eta)))
+            .unwrap_or_default();
+        info!("Format progress: {:.1}% - Step {}/{}: {}{}", 
               progress.percentage,
               progress.current_step + 1,
               progress.total_steps,
-              progress.step_description);
+              progress.step_description,
+              eta);
     }
 }
 
@@ -105,6 +117,7 @@ where
 pub struct ProgressReporter {
     progress: FormatProgress,
     callback: Arc<dyn ProgressCallback>,
+    throughput: ThroughputEstimator,
 }
 
 impl ProgressReporter {
@@ -112,6 +125,7 @@ impl ProgressReporter {
         Self {
             progress: FormatProgress::new(total_steps, total_bytes),
             callback,
+            throughput: ThroughputEstimator::default(),
         }
     }
     
@@ -125,17 +139,30 @@ impl ProgressReporter {
     
     pub fn start_step(&mut self, step: usize, description: impl Into<String>) {
         self.progress.update_step(step, description);
+        self.update_estimate();
         self.callback.on_progress(&self.progress);
     }
     
     pub fn update_bytes(&mut self, bytes: u64) {
         self.progress.update_bytes(bytes);
+        self.update_estimate();
         self.callback.on_progress(&self.progress);
     }
     
+    /// Steps without byte counts are converted to bytes through the overall percentage
+    fn update_estimate(&mut self) {
+        let total = self.progress.total_bytes;
+        let done = ((self.progress.percentage as f64 / 100.0) * total as f64) as u64;
+        let done = done.max(self.progress.bytes_written).min(total);
+        self.throughput.record(done);
+        self.progress.bytes_per_sec = self.throughput.bytes_per_sec();
+        self.progress.eta = self.throughput.eta(total - done);
+    }
+    
     pub fn complete(&mut self) {
         self.progress.percentage = 100.0;
         self.progress.current_step = self.progress.total_steps;
+        self.progress.eta = Some(Duration::ZERO);
         self.callback.on_progress(&self.progress);
     }
 }
\ No newline at end of file
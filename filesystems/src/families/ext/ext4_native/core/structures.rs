@@ -2,7 +2,7 @@
 // CRITICAL: These structures must match the ext4 specification EXACTLY
 
 use static_assertions::assert_eq_size;
-use crate::families::ext::ext4_native::core::{constants::*, checksum, types::*};
+use crate::families::ext::ext4_native::core::{constants::*, checksum, dir_entry::write_entry, types::*};
 use std::io;
 
 /// EXT4 Superblock structure (1024 bytes)
@@ -137,11 +137,8 @@ impl Ext4Superblock {
     
     /// Initialize with minimal valid values for a new filesystem
     pub fn init_minimal(&mut self, params: &FilesystemParams, layout: &FilesystemLayout) {
-        // Get current time
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_else(|_| std::time::Duration::from_secs(0))
-            .as_secs() as u32;
+        // Creation time, fixed for deterministic formats
+        let now = params.created.unwrap_or_else(current_time);
         
         // CRITICAL: Magic number must be exactly this
         self.s_magic = EXT4_SUPER_MAGIC;
@@ -218,7 +215,7 @@ impl Ext4Superblock {
         }
         
         // UUID generation
-        self.s_uuid = Self::generate_uuid();
+        self.s_uuid = params.uuid.unwrap_or_else(Self::generate_uuid);
         
         // Volume label
         if let Some(ref label) = params.label {
@@ -581,6 +578,14 @@ pub struct Ext4Inode {
     /* 0xA0 */ pub i_reserved: [u8; 96], // Reserved space to reach 256 bytes
 }
 
+/// Seconds since the epoch, as ext timestamps store them
+fn current_time() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_else(|_| std::time::Duration::from_secs(0))
+        .as_secs() as u32
+}
+
 // Verify size (standard ext4 inode with extra space)
 assert_eq_size!(Ext4Inode, [u8; 256]);
 
@@ -592,10 +597,7 @@ impl Ext4Inode {
     
     /// Initialize as lost+found directory inode
     pub fn init_lost_found_dir(&mut self, params: &FilesystemParams) {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_else(|_| std::time::Duration::from_secs(0))
-            .as_secs() as u32;
+        let now = params.created.unwrap_or_else(current_time);
         
         self.i_mode = S_IFDIR | 0o700;  // Directory with mode 700
         self.i_uid = 0;
@@ -618,17 +620,18 @@ impl Ext4Inode {
     
     /// Initialize as root directory inode
     pub fn init_root_dir(&mut self, params: &FilesystemParams) {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_else(|_| std::time::Duration::from_secs(0))
-            .as_secs() as u32;
+        let now = params.created.unwrap_or_else(current_time);
         
         // Directory mode: drwxr-xr-x (755)
         self.i_mode = S_IFDIR | S_IRUSR | S_IWUSR | S_IXUSR | S_IRGRP | S_IXGRP | S_IROTH | S_IXOTH;
         
-        // Root owned
-        self.i_uid = 0;
-        self.i_gid = 0;
+        // Root owned unless the user running the format asked for it
+        let (uid, gid) = params.root_owner.unwrap_or((0, 0));
+        self.i_uid = uid as u16;
+        self.i_gid = gid as u16;
+        // High 16 bits of each live in the Linux part of i_osd2
+        self.i_osd2[4..6].copy_from_slice(&((uid >> 16) as u16).to_le_bytes());
+        self.i_osd2[6..8].copy_from_slice(&((gid >> 16) as u16).to_le_bytes());
         
         // Size is one block for directory entries
         self.i_size_lo = params.block_size;
@@ -781,105 +784,23 @@ impl Ext4Extent {
 
 /// Create root directory data block with lost+found entry
 pub fn create_root_directory_block(block_size: u32) -> Vec<u8> {
-    let mut data = vec![0u8; block_size as usize];
-    let mut offset = 0;
-    
-    // Entry 1: "." pointing to root inode (2)
-    let (dot_entry, dot_name) = Ext4DirEntry2::new(EXT4_ROOT_INO, ".", EXT4_FT_DIR);
-    
-    // Entry 2: ".." also pointing to root inode (2) since root is its own parent
-    let (dotdot_entry, dotdot_name) = Ext4DirEntry2::new(EXT4_ROOT_INO, "..", EXT4_FT_DIR);
-    
-    // Entry 3: "lost+found" pointing to inode 11
-    let (lf_entry, lf_name) = Ext4DirEntry2::new(EXT4_FIRST_INO as u32, "lost+found", EXT4_FT_DIR);
-    
-    // Calculate record lengths
-    // First entry is fixed size
-    let mut dot_entry_mut = dot_entry;
-    dot_entry_mut.rec_len = 12;  // Standard size for "." entry
-    
-    // Second entry is fixed size
-    let mut dotdot_entry_mut = dotdot_entry;
-    dotdot_entry_mut.rec_len = 12;  // Standard size for ".." entry
-    
-    // Third entry takes the rest of the block
-    let mut lf_entry_mut = lf_entry;
-    lf_entry_mut.rec_len = (block_size as u16) - 24;
-    
-    // Write "." entry
-    unsafe {
-        let entry_bytes = std::slice::from_raw_parts(
-            &dot_entry_mut as *const _ as *const u8,
-            std::mem::size_of::<Ext4DirEntry2>()
-        );
-        data[offset..offset + 8].copy_from_slice(entry_bytes);
-    }
-    data[offset + 8..offset + 9].copy_from_slice(&dot_name);
-    offset = 12;
-    
-    // Write ".." entry
-    unsafe {
-        let entry_bytes = std::slice::from_raw_parts(
-            &dotdot_entry_mut as *const _ as *const u8,
-            std::mem::size_of::<Ext4DirEntry2>()
-        );
-        data[offset..offset + 8].copy_from_slice(entry_bytes);
-    }
-    data[offset + 8..offset + 10].copy_from_slice(&dotdot_name);
-    offset = 24;
-    
-    // Write "lost+found" entry
-    unsafe {
-        let entry_bytes = std::slice::from_raw_parts(
-            &lf_entry_mut as *const _ as *const u8,
-            std::mem::size_of::<Ext4DirEntry2>()
-        );
-        data[offset..offset + 8].copy_from_slice(entry_bytes);
-    }
-    data[offset + 8..offset + 8 + lf_name.len()].copy_from_slice(&lf_name);
-    
+    let block_size = block_size as usize;
+    let mut data = vec![0u8; block_size];
+    // "." and ".." both point to the root, which is its own parent; lost+found takes the
+    // rest of the block. Every block size ext allows fits all three.
+    write_entry(&mut data, 0, EXT4_ROOT_INO, 12, b".", EXT4_FT_DIR).expect("root block holds \".\"");
+    write_entry(&mut data, 12, EXT4_ROOT_INO, 12, b"..", EXT4_FT_DIR).expect("root block holds \"..\"");
+    write_entry(&mut data, 24, EXT4_FIRST_INO, block_size - 24, b"lost+found", EXT4_FT_DIR)
+        .expect("root block holds lost+found");
     data
 }
 
 /// Create lost+found directory data block
 pub fn create_lost_found_directory_block(block_size: u32) -> Vec<u8> {
-    let mut data = vec![0u8; block_size as usize];
-    let mut offset = 0;
-    
-    // Entry 1: "." pointing to lost+found inode (11)
-    let (dot_entry, dot_name) = Ext4DirEntry2::new(EXT4_FIRST_INO as u32, ".", EXT4_FT_DIR);
-    
-    // Entry 2: ".." pointing to root inode (2)
-    let (dotdot_entry, dotdot_name) = Ext4DirEntry2::new(EXT4_ROOT_INO, "..", EXT4_FT_DIR);
-    
-    // Calculate record lengths
-    let mut dot_entry_mut = dot_entry;
-    dot_entry_mut.rec_len = 12;
-    
-    let mut dotdot_entry_mut = dotdot_entry;
-    dotdot_entry_mut.rec_len = (block_size as u16) - 12;
-    
-    // Write "." entry
-    unsafe {
-        let entry_bytes = std::slice::from_raw_parts(
-            &dot_entry_mut as *const _ as *const u8,
-            std::mem::size_of::<Ext4DirEntry2>()
-        );
-        data[offset..offset + 8].copy_from_slice(entry_bytes);
-    }
-    data[offset + 8..offset + 9].copy_from_slice(&dot_name);
-    offset = 12;
-    
-    // Write ".." entry
-    unsafe {
-        let entry_bytes = std::slice::from_raw_parts(
-            &dotdot_entry_mut as *const _ as *const u8,
-            std::mem::size_of::<Ext4DirEntry2>()
-        );
-        data[offset..offset + 8].copy_from_slice(entry_bytes);
-    }
-    data[offset + 8..offset + 10].copy_from_slice(&dotdot_name);
-    
+    let block_size = block_size as usize;
+    let mut data = vec![0u8; block_size];
+    write_entry(&mut data, 0, EXT4_FIRST_INO, 12, b".", EXT4_FT_DIR).expect("lost+found block holds \".\"");
+    write_entry(&mut data, 12, EXT4_ROOT_INO, block_size - 12, b"..", EXT4_FT_DIR).expect("lost+found block holds \"..\"");
     data
 }
 
@@ -15,6 +15,9 @@ fn test_free_blocks_calculation_no_overflow() {
         enable_checksums: true,
         enable_64bit: true,
         enable_journal: false,
+        uuid: None,
+        created: None,
+        root_owner: None,
     };
     
     let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -95,6 +98,9 @@ fn test_various_drive_sizes() {
             enable_checksums: true,
             enable_64bit: size_gb > 16,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -22,6 +22,9 @@ fn test_ext4_superblock_unchanged() {
         enable_checksums: true,
         enable_64bit: false,
         enable_journal: false,
+        uuid: None,
+        created: None,
+        root_owner: None,
     };
     
     let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -58,6 +61,9 @@ fn test_ext4_group_descriptor_unchanged() {
         enable_checksums: true,
         enable_64bit: false,
         enable_journal: false,
+        uuid: None,
+        created: None,
+        root_owner: None,
     };
     
     let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -200,6 +206,9 @@ fn test_ext4_golden_bytes() {
         enable_checksums: true,
         enable_64bit: false,
         enable_journal: false,
+        uuid: None,
+        created: None,
+        root_owner: None,
     };
     
     let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -16,6 +16,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -64,6 +67,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -156,6 +162,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -201,6 +210,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
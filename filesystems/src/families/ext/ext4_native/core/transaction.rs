@@ -402,6 +402,9 @@ impl TransactionManager {
         // Calculate checksum
         let _checksum = self.calculate_transaction_checksum(&transaction);
 
+        // Barrier: a commit record may only reach the disk after every block it covers
+        journal.flush_journal()?;
+
         // Write commit block
         let commit = JournalCommitBlock {
             sequence: journal.next_sequence,
@@ -95,6 +95,12 @@ pub struct FilesystemParams {
     pub enable_64bit: bool,
     /// Enable journal
     pub enable_journal: bool,
+    /// Filesystem UUID; `None` generates one
+    pub uuid: Option<[u8; 16]>,
+    /// Creation time in seconds since the epoch; `None` is now
+    pub created: Option<u32>,
+    /// Owner (uid, gid) of the root directory; `None` is root
+    pub root_owner: Option<(u32, u32)>,
 }
 
 impl Default for FilesystemParams {
@@ -108,6 +114,9 @@ impl Default for FilesystemParams {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false, // Not implemented yet
+            uuid: None,
+            created: None,
+            root_owner: None,
         }
     }
 }
@@ -137,6 +146,13 @@ impl FilesystemLayout {
             });
         }
         
+        // Groups are laid out for 4KiB blocks: 32768 blocks each, starting at block 0
+        if params.block_size != 4096 {
+            return Err(Ext4Error::UnsupportedFeature(format!(
+                "{} byte blocks (only 4096 byte blocks are supported)", params.block_size
+            )));
+        }
+        
         let total_blocks = params.size_bytes / params.block_size as u64;
         let blocks_per_group = EXT4_BLOCKS_PER_GROUP;
         let inodes_per_group = EXT4_INODES_PER_GROUP;
@@ -6,6 +6,7 @@ use crate::families::ext::ext4_native::core::{
     checksum::crc32c_ext4,
     alignment::AlignedBuffer,
     ext_config::ExtVersion,
+    dir_entry::dir_entries,
 };
 use log::{debug, info, warn, error};
 use std::io::{Read, Seek, SeekFrom};
@@ -280,6 +281,15 @@ pub fn verify_ext_filesystem<R: Read + Seek>(reader: &mut R) -> Result<Verificat
         result.add_info(format!("Volume label: '{}'", label));
     }
     
+    // Step 9: Walk the root directory's first block
+    let block_size = sb.s_block_size() as u64;
+    if (1024..=65536).contains(&block_size) {
+        match root_directory_block(reader, &sb, block_size, total_blocks) {
+            Ok(block) => verify_root_directory(&block, &mut result),
+            Err(problem) => result.add_warning(format!("Root directory not checked: {}", problem)),
+        }
+    }
+    
     // Final summary
     if result.is_valid {
         info!("Filesystem verification passed with {} warnings", result.warnings.len());
@@ -290,6 +300,62 @@ pub fn verify_ext_filesystem<R: Read + Seek>(reader: &mut R) -> Result<Verificat
     Ok(result)
 }
 
+/// Read the first data block of the root directory (inode 2)
+fn root_directory_block<R: Read + Seek>(reader: &mut R, sb: &Ext4Superblock, block_size: u64, total_blocks: u64) -> Result<Vec<u8>, String> {
+    let read_at = |reader: &mut R, offset: u64, length: usize| -> Result<Vec<u8>, String> {
+        let mut data = vec![0u8; length];
+        reader.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+        Ok(data)
+    };
+    let u32_at = |data: &[u8], offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+
+    // The root inode is the second in group 0's inode table
+    let gdt = read_at(reader, (sb.s_first_data_block as u64 + 1) * block_size, 32)?;
+    let inode_table = u32_at(&gdt, 8) as u64;
+    let inode_size = (sb.s_inode_size as usize).max(128);
+    let inode = read_at(reader, inode_table * block_size + (EXT4_ROOT_INO as u64 - 1) * inode_size as u64, 128)?;
+    let flags = u32_at(&inode, 0x20);
+    let i_block = &inode[0x28..0x28 + 60];
+
+    let first_block = if flags & EXT4_EXTENTS_FL != 0 {
+        let magic = u16::from_le_bytes([i_block[0], i_block[1]]);
+        let entries = u16::from_le_bytes([i_block[2], i_block[3]]);
+        let depth = u16::from_le_bytes([i_block[6], i_block[7]]);
+        if magic != EXT4_EXTENT_MAGIC || entries == 0 || depth != 0 {
+            return Err("its extent tree is not a single leaf".to_string());
+        }
+        ((u16::from_le_bytes([i_block[18], i_block[19]]) as u64) << 32) | u32_at(i_block, 20) as u64
+    } else {
+        u32_at(i_block, 0) as u64
+    };
+    if first_block == 0 || first_block >= total_blocks {
+        return Err(format!("its first block {} is outside the filesystem", first_block));
+    }
+    read_at(reader, first_block * block_size, block_size as usize)
+}
+
+/// Check the entries of the root directory's first block with the same iterator the
+/// reader and writer use
+fn verify_root_directory(block: &[u8], result: &mut VerificationResult) {
+    let mut names = Vec::new();
+    for entry in dir_entries(block) {
+        match entry {
+            Ok(entry) if entry.is_used() => names.push(entry.name_lossy()),
+            Ok(_) => {}
+            Err(e) => {
+                result.add_error(format!("Root directory: {}", e));
+                return;
+            }
+        }
+    }
+    if names.len() < 2 || names[0] != "." || names[1] != ".." {
+        result.add_error("Root directory does not start with \".\" and \"..\"".to_string());
+    } else {
+        result.add_info(format!("Root directory block holds {} entries", names.len()));
+    }
+}
+
 /// Verify ext4 filesystem on device (compatibility wrapper)
 pub fn verify_ext4_filesystem<R: Read + Seek>(reader: &mut R) -> Result<VerificationResult, Ext4Error> {
     verify_ext_filesystem(reader)
@@ -29,11 +29,7 @@ impl InodeJournalDevice {
         block_size: u32,
     ) -> Result<Self, MosesError> {
         // Open device for reading/writing
-        let device_path = if !device.mount_points.is_empty() {
-            device.mount_points[0].clone()
-        } else {
-            std::path::PathBuf::from(format!("/dev/{}", device.id))
-        };
+        let device_path = crate::utils::get_device_path(&device);
         
         let file = OpenOptions::new()
             .read(true)
@@ -389,6 +389,10 @@ impl Jbd2Journal {
                 }
             }
             
+            // Barrier: the descriptors and data must be on disk before the commit block,
+            // or replay after a power loss could apply blocks that were never written
+            device.sync()?;
+            
             // Write commit block
             let mut commit_data = vec![0u8; 4096];
             let commit_header = JournalHeader {
@@ -1,5 +1,5 @@
 // FilesystemOps implementation for ext2/ext3/ext4 filesystems
-use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo};
+use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo, AttributeChanges};
 use super::reader::{ExtReader, FileType};
 use super::writer::Ext4Writer;
 use super::journaled_writer::{JournaledExt4Writer, Ext4JournalingConfig};
@@ -175,6 +175,11 @@ impl FilesystemOps for Ext4Ops {
         Ok(result)
     }
     
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        let (inode_num, _, _) = self.reader.as_mut()?.inode_location(path.to_str()?).ok()?;
+        Some(inode_num as u64)
+    }
+    
     fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
         let reader = self.reader.as_mut()
             .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
@@ -300,6 +305,14 @@ impl FilesystemOps for Ext4Ops {
             .truncate(path, size)
     }
     
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let filesystem = self.filesystem_type().to_string();
+        crate::attributes::set_attributes(&self.device, &filesystem, path, changes)?;
+        // The reader caches inodes, which now hold the old mode and owner
+        self.reader = Some(ExtReader::new(self.device.clone())?);
+        Ok(())
+    }
+    
     fn sync(&mut self) -> Result<(), MosesError> {
         if self.write_enabled {
             if let Some(ref writer) = self.writer {
@@ -323,6 +336,10 @@ impl FilesystemOps for Ext4Ops {
         !self.write_enabled
     }
     
+    fn warnings(&self) -> Vec<String> {
+        self.reader.as_ref().map(|reader| reader.dirty_state().warnings()).unwrap_or_default()
+    }
+    
     fn filesystem_type(&self) -> &str {
         if let Some(ref reader) = self.reader {
             match reader.version {
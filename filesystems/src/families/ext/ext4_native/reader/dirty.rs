@@ -0,0 +1,234 @@
+// Unclean-shutdown state of an ext filesystem
+// A volume pulled out without unmounting can be left with a journal that still needs
+// replaying (INCOMPAT_RECOVER), inodes that were deleted or truncated while open still
+// chained on the orphan list (s_last_orphan), and a multi-mount protection block claiming
+// the volume is in use. The reader never writes, so it neither replays the journal nor
+// frees orphans: orphans are not linked from any directory and are simply never visited.
+// What it found is reported so callers can warn that recent changes may be missing.
+
+use moses_core::MosesError;
+use super::super::core::{constants::*, structures::{Ext4Inode, Ext4Superblock}};
+
+/// Magic at the start of the MMP block
+const EXT4_MMP_MAGIC: u32 = 0x004D_4D50;
+/// mmp_seq of a cleanly unmounted filesystem
+const EXT4_MMP_SEQ_CLEAN: u32 = 0xFF4D_4D50;
+/// mmp_seq while e2fsck is running
+const EXT4_MMP_SEQ_FSCK: u32 = 0xE24D_4D50;
+/// Orphans walked before giving up; real lists are short, corrupt ones can be any length
+const MAX_ORPHANS: usize = 100_000;
+
+/// What the multi-mount protection block says about the filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MmpStatus {
+    Clean,
+    /// Mounted, or left mounted, by this node
+    InUse { node: String },
+    /// e2fsck on this node was checking the filesystem
+    Fsck { node: String },
+    /// The MMP block could not be read or has a bad magic
+    Unreadable,
+}
+
+/// Signs that the filesystem was not cleanly unmounted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirtyState {
+    /// s_state lacks the "cleanly unmounted" bit
+    pub not_clean: bool,
+    /// The kernel flagged errors (EXT4_ERROR_FS) or counted some
+    pub errors: bool,
+    pub error_count: u32,
+    /// The journal holds transactions that were never replayed
+    pub needs_recovery: bool,
+    /// Orphan inodes, head of the list first
+    pub orphans: Vec<u32>,
+    /// The orphan list was cut short: too long, looping or pointing outside the inode table
+    pub orphans_truncated: bool,
+    /// None when the filesystem has no MMP
+    pub mmp: Option<MmpStatus>,
+}
+
+impl DirtyState {
+    /// Flags readable from the superblock alone; `walk_orphans` and `check_mmp` fill in the rest
+    pub fn from_superblock(sb: &Ext4Superblock) -> Self {
+        Self {
+            not_clean: sb.s_state & EXT4_VALID_FS == 0,
+            errors: sb.s_state & EXT4_ERROR_FS != 0 || sb.s_error_count > 0,
+            error_count: sb.s_error_count,
+            needs_recovery: sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_RECOVER != 0,
+            // Only the head is known until the list is walked
+            orphans: if sb.s_last_orphan != 0 { vec![sb.s_last_orphan] } else { Vec::new() },
+            orphans_truncated: sb.s_last_orphan != 0,
+            mmp: None,
+        }
+    }
+
+    /// Follow the orphan list; each orphan keeps the next one's number in i_dtime
+    pub fn walk_orphans<F>(&mut self, sb: &Ext4Superblock, mut read_inode: F)
+    where
+        F: FnMut(u32) -> Result<Ext4Inode, MosesError>,
+    {
+        self.orphans.clear();
+        self.orphans_truncated = false;
+        let mut next = sb.s_last_orphan;
+        while next != 0 {
+            if next > sb.s_inodes_count || self.orphans.contains(&next) || self.orphans.len() >= MAX_ORPHANS {
+                self.orphans_truncated = true;
+                break;
+            }
+            self.orphans.push(next);
+            match read_inode(next) {
+                Ok(inode) => next = inode.i_dtime,
+                Err(e) => {
+                    log::debug!("Cannot read orphan inode {}: {}", next, e);
+                    self.orphans_truncated = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Read the MMP block if the filesystem uses multi-mount protection
+    pub fn check_mmp<F>(&mut self, sb: &Ext4Superblock, mut read_block: F)
+    where
+        F: FnMut(u64) -> Result<Vec<u8>, MosesError>,
+    {
+        if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_MMP == 0 {
+            return;
+        }
+        let status = match read_block(sb.s_mmp_block) {
+            Ok(block) if block.len() >= 112 && u32::from_le_bytes(block[0..4].try_into().unwrap()) == EXT4_MMP_MAGIC => {
+                let seq = u32::from_le_bytes(block[4..8].try_into().unwrap());
+                // mmp_nodename: 64 bytes after mmp_seq and mmp_time
+                let node = String::from_utf8_lossy(&block[16..80]).trim_end_matches('\0').to_string();
+                match seq {
+                    EXT4_MMP_SEQ_CLEAN => MmpStatus::Clean,
+                    EXT4_MMP_SEQ_FSCK => MmpStatus::Fsck { node },
+                    _ => MmpStatus::InUse { node },
+                }
+            }
+            _ => MmpStatus::Unreadable,
+        };
+        self.mmp = Some(status);
+    }
+
+    /// Whether anything suggests the on-disk data is not the latest
+    pub fn is_dirty(&self) -> bool {
+        self.not_clean
+            || self.errors
+            || self.needs_recovery
+            || !self.orphans.is_empty()
+            || self.mmp.as_ref().is_some_and(|mmp| *mmp != MmpStatus::Clean)
+    }
+
+    /// One line per problem, for logs, the CLI and the UI
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.is_dirty() {
+            return warnings;
+        }
+        warnings.push("Filesystem is dirty, data may be stale".to_string());
+        if self.not_clean {
+            warnings.push("It was not cleanly unmounted (removed or powered off while in use)".to_string());
+        }
+        if self.needs_recovery {
+            warnings.push(
+                "The journal has changes that were never written in place; the most recent writes are \
+                 missing until Linux mounts it or e2fsck replays the journal".to_string(),
+            );
+        }
+        if !self.orphans.is_empty() {
+            warnings.push(format!(
+                "{}{} orphan inode(s), files deleted or truncated while open, still hold space; they are \
+                 hidden and freed by e2fsck or the next mount",
+                self.orphans.len(),
+                if self.orphans_truncated { "+" } else { "" },
+            ));
+        }
+        if self.errors {
+            warnings.push(match self.error_count {
+                0 => "The kernel marked the filesystem as having errors; run e2fsck".to_string(),
+                count => format!("The kernel recorded {} filesystem error(s); run e2fsck", count),
+            });
+        }
+        match &self.mmp {
+            Some(MmpStatus::InUse { node }) => warnings.push(format!(
+                "Multi-mount protection says {} has it mounted; if that machine is running, files can change while being read",
+                if node.is_empty() { "another machine" } else { node },
+            )),
+            Some(MmpStatus::Fsck { node }) => warnings.push(format!(
+                "e2fsck{} was interrupted while checking it", if node.is_empty() { String::new() } else { format!(" on {}", node) },
+            )),
+            Some(MmpStatus::Unreadable) => warnings.push("The multi-mount protection block is unreadable".to_string()),
+            Some(MmpStatus::Clean) | None => {}
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn orphan(next: u32) -> Ext4Inode {
+        Ext4Inode { i_dtime: next, ..Ext4Inode::new() }
+    }
+
+    fn clean_superblock() -> Ext4Superblock {
+        let mut sb = Ext4Superblock::new();
+        sb.s_state = EXT4_VALID_FS;
+        sb
+    }
+
+    #[test]
+    fn test_clean_filesystem() {
+        let sb = clean_superblock();
+        let state = DirtyState::from_superblock(&sb);
+        assert!(!state.is_dirty());
+        assert!(state.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_unplugged_filesystem() {
+        let mut sb = Ext4Superblock::new();
+        sb.s_inodes_count = 100;
+        sb.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_RECOVER;
+        sb.s_last_orphan = 12;
+        let inodes = HashMap::from([(12, orphan(40)), (40, orphan(0))]);
+
+        let mut state = DirtyState::from_superblock(&sb);
+        state.walk_orphans(&sb, |ino| inodes.get(&ino).copied().ok_or(MosesError::Other("missing".into())));
+        assert_eq!(state.orphans, [12, 40]);
+        assert!(!state.orphans_truncated);
+        let warnings = state.warnings();
+        assert_eq!(warnings[0], "Filesystem is dirty, data may be stale");
+        assert!(warnings.iter().any(|w| w.contains("journal")));
+        assert!(warnings.iter().any(|w| w.starts_with("2 orphan")));
+
+        // A looping list stops instead of spinning
+        let looping = HashMap::from([(12, orphan(40)), (40, orphan(12))]);
+        state.walk_orphans(&sb, |ino| Ok(looping[&ino]));
+        assert!(state.orphans_truncated);
+    }
+
+    #[test]
+    fn test_mmp_in_use() {
+        let mut sb = clean_superblock();
+        sb.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_MMP;
+        sb.s_mmp_block = 7;
+        let mut block = vec![0u8; 1024];
+        block[0..4].copy_from_slice(&EXT4_MMP_MAGIC.to_le_bytes());
+        block[4..8].copy_from_slice(&42u32.to_le_bytes());
+        block[16..22].copy_from_slice(b"server");
+
+        let mut state = DirtyState::from_superblock(&sb);
+        state.check_mmp(&sb, |n| { assert_eq!(n, 7); Ok(block.clone()) });
+        assert_eq!(state.mmp, Some(MmpStatus::InUse { node: "server".to_string() }));
+        assert!(state.warnings().iter().any(|w| w.contains("server has it mounted")));
+
+        block[4..8].copy_from_slice(&EXT4_MMP_SEQ_CLEAN.to_le_bytes());
+        state.check_mmp(&sb, |_| Ok(block.clone()));
+        assert!(!state.is_dirty());
+    }
+}
@@ -2,12 +2,17 @@
 // This allows reading ext filesystems on any platform!
 
 use moses_core::{Device, MosesError};
-use log::info;
+use log::{info, warn};
 use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+pub mod dirty;
+pub use dirty::{DirtyState, MmpStatus};
 
 use super::core::{
     structures::*,
     constants::*,
+    dir_entry::dir_entries,
     ext_config::ExtVersion,
 };
 
@@ -69,6 +74,8 @@ pub struct ExtReader {
     block_size: u32,
     inode_size: u32,
     pub version: ExtVersion,
+    /// Unclean-shutdown state found when opening
+    dirty: DirtyState,
     
     // Cache for performance
     inode_cache: HashMap<u32, Ext4Inode>,
@@ -113,81 +120,51 @@ impl ExtReader {
             ExtVersion::Ext4 => "ext4",
         });
         
-        // Validate magic
-        if superblock.s_magic != EXT4_SUPER_MAGIC {
-            return Err(MosesError::Other(format!(
-                "Invalid ext magic: 0x{:X}", superblock.s_magic
-            )));
-        }
-        
         let block_size = superblock.s_block_size();
         let inode_size = superblock.s_inode_size as u32;
         
         // Read group descriptors
-        let num_groups = ((superblock.s_blocks_count_lo as u64 
-                          | ((superblock.s_blocks_count_hi as u64) << 32))
-                          + superblock.s_blocks_per_group as u64 - 1)
-                         / superblock.s_blocks_per_group as u64;
-        
-        let mut group_descriptors = Vec::new();
-        let gdt_block = if block_size == 1024 { 2 } else { 1 };
+        let group_descriptors = load_group_descriptors(&mut crate::utils::open_device_read(&device)?, &superblock)?;
         
-        for i in 0..num_groups {
-            let gd = Self::read_group_descriptor(&device, &superblock, gdt_block, i as u32)?;
-            group_descriptors.push(gd);
-        }
-        
-        Ok(ExtReader {
+        let mut reader = ExtReader {
             device,
             superblock,
             group_descriptors,
             block_size,
             inode_size,
             version,
+            dirty: DirtyState::from_superblock(&superblock),
             inode_cache: HashMap::new(),
             block_cache: HashMap::new(),
-        })
+        };
+        reader.inspect_dirty_state();
+        Ok(reader)
     }
     
-    /// Read superblock from device
-    fn read_superblock(device: &Device) -> Result<Ext4Superblock, MosesError> {
-        use crate::utils::{open_device_read, read_block};
-        
-        let mut file = open_device_read(device)?;
-        
-        // Superblock is at offset 1024
-        let buffer = read_block(&mut file, 1024, 1024)?;
-        
-        // Parse superblock
-        let sb = unsafe {
-            std::ptr::read_unaligned(buffer.as_ptr() as *const Ext4Superblock)
-        };
-        
-        Ok(sb)
+    /// Walk the orphan list and MMP block of a filesystem that was not cleanly unmounted.
+    /// Nothing is repaired: orphans are unreachable from directories anyway, and an
+    /// unreplayed journal only means recent changes are missing.
+    fn inspect_dirty_state(&mut self) {
+        let sb = self.superblock;
+        let mut dirty = std::mem::take(&mut self.dirty);
+        if sb.s_last_orphan != 0 {
+            dirty.walk_orphans(&sb, |ino| self.read_inode(ino));
+        }
+        dirty.check_mmp(&sb, |block| self.read_block(block));
+        for warning in dirty.warnings() {
+            warn!("{}: {}", self.device.name, warning);
+        }
+        self.dirty = dirty;
     }
     
-    /// Read group descriptor
-    fn read_group_descriptor(
-        device: &Device,
-        sb: &Ext4Superblock,
-        gdt_block: u64,
-        group_index: u32,
-    ) -> Result<Ext4GroupDesc, MosesError> {
-        use crate::utils::{open_device_read, read_block};
-        
-        let mut file = open_device_read(device)?;
-        
-        let block_size = sb.s_block_size();
-        let gd_size = 64; // Size of group descriptor
-        let offset = (gdt_block * block_size as u64) + (group_index as u64 * gd_size);
-        
-        let buffer = read_block(&mut file, offset, 64)?;
-        
-        let gd = unsafe {
-            std::ptr::read_unaligned(buffer.as_ptr() as *const Ext4GroupDesc)
-        };
-        
-        Ok(gd)
+    /// Signs of an unclean shutdown found when the filesystem was opened
+    pub fn dirty_state(&self) -> &DirtyState {
+        &self.dirty
+    }
+    
+    /// Read superblock from device
+    fn read_superblock(device: &Device) -> Result<Ext4Superblock, MosesError> {
+        load_superblock(&mut crate::utils::open_device_read(device)?)
     }
     
     /// Read an inode by number
@@ -197,21 +174,7 @@ impl ExtReader {
             return Ok(*cached);
         }
         
-        if inode_num == 0 || inode_num > self.superblock.s_inodes_count {
-            return Err(MosesError::Other(format!("Invalid inode number: {}", inode_num)));
-        }
-        
-        // Calculate inode location
-        let inodes_per_group = self.superblock.s_inodes_per_group;
-        let group = (inode_num - 1) / inodes_per_group;
-        let index = (inode_num - 1) % inodes_per_group;
-        
-        let gd = &self.group_descriptors[group as usize];
-        let inode_table_block = gd.bg_inode_table_lo as u64 
-                               | ((gd.bg_inode_table_hi as u64) << 32);
-        
-        let inode_offset = inode_table_block * self.block_size as u64
-                          + index as u64 * self.inode_size as u64;
+        let inode_offset = self.inode_offset(inode_num)?;
         
         // Read inode from device
         use crate::utils::{open_device_read, read_block};
@@ -229,6 +192,30 @@ impl ExtReader {
         Ok(inode)
     }
     
+    /// Byte offset of an inode on the device
+    fn inode_offset(&self, inode_num: u32) -> Result<u64, MosesError> {
+        if inode_num == 0 || inode_num > self.superblock.s_inodes_count {
+            return Err(MosesError::Other(format!("Invalid inode number: {}", inode_num)));
+        }
+        
+        let inodes_per_group = self.superblock.s_inodes_per_group;
+        let group = (inode_num - 1) / inodes_per_group;
+        let index = (inode_num - 1) % inodes_per_group;
+        
+        let gd = self.group_descriptors.get(group as usize)
+            .ok_or_else(|| MosesError::Other(format!("No group descriptor for inode {}", inode_num)))?;
+        let inode_table_block = gd.bg_inode_table_lo as u64 
+                               | ((gd.bg_inode_table_hi as u64) << 32);
+        
+        Ok(inode_table_block * self.block_size as u64 + index as u64 * self.inode_size as u64)
+    }
+    
+    /// Inode number, byte offset and size of the inode at `path`, for in-place edits
+    pub fn inode_location(&mut self, path: &str) -> Result<(u32, u64, usize), MosesError> {
+        let inode_num = self.path_to_inode(path)?;
+        Ok((inode_num, self.inode_offset(inode_num)?, self.inode_size as usize))
+    }
+    
     /// Read a block by number
     pub fn read_block(&mut self, block_num: u64) -> Result<Vec<u8>, MosesError> {
         use crate::utils::{open_device_read, read_block};
@@ -264,51 +251,7 @@ impl ExtReader {
             return Err(MosesError::Other(format!("{} is not a directory", path)));
         }
         
-        let mut entries = Vec::new();
-        
-        // Read directory blocks
-        let blocks = self.get_inode_blocks(&inode)?;
-        
-        for block_num in blocks {
-            if block_num == 0 { continue; }
-            
-            let block_data = self.read_block(block_num)?;
-            let mut offset = 0;
-            
-            while offset < block_data.len() {
-                // Parse directory entry
-                let entry = unsafe {
-                    &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-                };
-                
-                if entry.inode == 0 {
-                    // Deleted or empty entry
-                    offset += entry.rec_len as usize;
-                    continue;
-                }
-                
-                // Get name
-                let name_bytes = unsafe {
-                    std::slice::from_raw_parts(
-                        block_data.as_ptr().add(offset + 8),
-                        entry.name_len as usize
-                    )
-                };
-                
-                let name = String::from_utf8_lossy(name_bytes).to_string();
-                
-                entries.push(DirEntry {
-                    name,
-                    inode: entry.inode,
-                    entry_type: FileType::from(entry.file_type),
-                });
-                
-                offset += entry.rec_len as usize;
-                if entry.rec_len == 0 { break; }
-            }
-        }
-        
-        Ok(entries)
+        self.directory_entries(&inode)
     }
     
     /// Get blocks for an inode (handles both extents and indirect blocks)
@@ -397,45 +340,29 @@ impl ExtReader {
             return Err(MosesError::Other("Not a directory".to_string()));
         }
         
+        self.directory_entries(&inode)
+    }
+    
+    /// The entries of a directory inode's blocks. A corrupted entry is logged and the rest of
+    /// its block skipped, so one bad block does not hide the rest of the directory.
+    fn directory_entries(&mut self, inode: &Ext4Inode) -> Result<Vec<DirEntry>, MosesError> {
         let mut entries = Vec::new();
-        let blocks = self.get_inode_blocks(&inode)?;
-        
-        for block_num in blocks {
+        for block_num in self.get_inode_blocks(inode)? {
             if block_num == 0 { continue; }
             
             let block_data = self.read_block(block_num)?;
-            let mut offset = 0;
-            
-            while offset < block_data.len() {
-                let entry = unsafe {
-                    &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-                };
-                
-                if entry.inode == 0 {
-                    offset += entry.rec_len as usize;
-                    continue;
+            for entry in dir_entries(&block_data) {
+                match entry {
+                    Ok(entry) if entry.is_used() => entries.push(DirEntry {
+                        name: entry.name_lossy(),
+                        inode: entry.inode,
+                        entry_type: FileType::from(entry.file_type),
+                    }),
+                    Ok(_) => {}
+                    Err(e) => warn!("Directory block {}: {}", block_num, e),
                 }
-                
-                let name_bytes = unsafe {
-                    std::slice::from_raw_parts(
-                        block_data.as_ptr().add(offset + 8),
-                        entry.name_len as usize
-                    )
-                };
-                
-                let name = String::from_utf8_lossy(name_bytes).to_string();
-                
-                entries.push(DirEntry {
-                    name,
-                    inode: entry.inode,
-                    entry_type: FileType::from(entry.file_type),
-                });
-                
-                offset += entry.rec_len as usize;
-                if entry.rec_len == 0 { break; }
             }
         }
-        
         Ok(entries)
     }
     
@@ -559,6 +486,57 @@ impl ExtReader {
     }
 }
 
+/// Read the primary superblock at byte 1024 and check it is one this code can walk
+pub(crate) fn load_superblock<R: Read + Seek>(reader: &mut R) -> Result<Ext4Superblock, MosesError> {
+    let mut buffer = [0u8; 1024];
+    reader.seek(SeekFrom::Start(1024))?;
+    reader.read_exact(&mut buffer)?;
+    let sb = unsafe {
+        std::ptr::read_unaligned(buffer.as_ptr() as *const Ext4Superblock)
+    };
+    
+    if sb.s_magic != EXT4_SUPER_MAGIC {
+        return Err(MosesError::Other(format!("Invalid ext magic: 0x{:X}", sb.s_magic)));
+    }
+    if sb.s_log_block_size > 6 || sb.s_blocks_per_group == 0 || sb.s_inodes_per_group == 0 {
+        return Err(MosesError::Other("Corrupt ext superblock: invalid block or group geometry".to_string()));
+    }
+    Ok(sb)
+}
+
+/// On-disk size of one group descriptor: `s_desc_size` with the 64bit feature, 32 otherwise
+pub(crate) fn group_desc_size(sb: &Ext4Superblock) -> usize {
+    if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 && sb.s_desc_size >= 64 {
+        sb.s_desc_size as usize
+    } else {
+        32
+    }
+}
+
+/// Read the group descriptor table that follows the primary superblock. 32-byte
+/// descriptors come back with their high halves zeroed.
+pub(crate) fn load_group_descriptors<R: Read + Seek>(
+    reader: &mut R,
+    sb: &Ext4Superblock,
+) -> Result<Vec<Ext4GroupDesc>, MosesError> {
+    let blocks = sb.s_blocks_count_lo as u64 | ((sb.s_blocks_count_hi as u64) << 32);
+    let data_blocks = blocks.saturating_sub(sb.s_first_data_block as u64);
+    let num_groups = data_blocks.div_ceil(sb.s_blocks_per_group as u64);
+    let desc_size = group_desc_size(sb);
+    let gdt_offset = (sb.s_first_data_block as u64 + 1) * sb.s_block_size() as u64;
+    
+    let mut table = vec![0u8; num_groups as usize * desc_size];
+    reader.seek(SeekFrom::Start(gdt_offset))?;
+    reader.read_exact(&mut table)?;
+    
+    Ok(table.chunks_exact(desc_size).map(|bytes| {
+        let mut raw = [0u8; std::mem::size_of::<Ext4GroupDesc>()];
+        let len = bytes.len().min(raw.len());
+        raw[..len].copy_from_slice(&bytes[..len]);
+        unsafe { std::ptr::read_unaligned(raw.as_ptr() as *const Ext4GroupDesc) }
+    }).collect())
+}
+
 /// Information about an ext filesystem
 #[derive(Debug)]
 pub struct ExtInfo {
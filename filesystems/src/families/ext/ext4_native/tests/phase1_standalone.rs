@@ -48,6 +48,9 @@ fn create_phase1_image(path: &str, size_bytes: u64) -> Result<(), String> {
         enable_checksums: true,
         enable_64bit: false, // Keep simple for Phase 1
         enable_journal: false,
+        uuid: None,
+        created: None,
+        root_owner: None,
     };
     
     // Calculate layout
@@ -23,6 +23,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: true,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
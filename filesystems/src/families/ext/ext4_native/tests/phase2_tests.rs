@@ -80,6 +80,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: false,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -113,7 +113,9 @@ mod tests {
     
     #[test]
     fn test_create_complete_filesystem() {
-        let image_path = "test_phase3.img";
+        // Written to a temporary directory so test runs leave nothing in the crate
+        let dir = tempfile::tempdir().unwrap();
+        let image_path = dir.path().join("test_phase3.img");
         let image_size = 100 * 1024 * 1024; // 100MB
         
         let params = FilesystemParams {
@@ -125,6 +127,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: false,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -208,7 +213,7 @@ mod tests {
         sb.update_checksum();
         
         // Now write everything to image file
-        let mut file = File::create(image_path).unwrap();
+        let mut file = File::create(&image_path).unwrap();
         
         // Write zeros for entire image
         let zeros = vec![0u8; 1024 * 1024];
@@ -298,7 +303,7 @@ mod tests {
         
         file.sync_all().unwrap();
         
-        println!("\nPhase 3 complete filesystem created: {}", image_path);
+        println!("\nPhase 3 complete filesystem created: {}", image_path.display());
         println!("  Superblock at block 0");
         println!("  Group descriptor at block 1");
         println!("  Block bitmap at block {}", gd.bg_block_bitmap_lo);
@@ -309,12 +314,5 @@ mod tests {
         println!("\nDirectory structure:");
         println!("  Root inode (2): links=3, extent 0 -> block {}", dir_data_block);
         println!("  Lost+found inode (11): links=2, extent 0 -> block {}", lf_data_block);
-        println!("\nTo validate:");
-        println!("  Linux: e2fsck -fn {}", image_path);
-        println!("  Linux: dumpe2fs {} 2>/dev/null | head -50", image_path);
-        println!("  Linux: debugfs -R 'ls -l' {}", image_path);
-        
-        // Keep the file for manual testing
-        println!("\nImage file kept at: {}", image_path);
     }
 }
\ No newline at end of file
@@ -30,20 +30,25 @@ mod tests {
             device_type: DeviceType::Unknown,
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             mount_points: vec![],
+            filesystem: None,
         };
         
         // Format options
         let options = FormatOptions {
-            filesystem: "ext4".to_string(),
+            filesystem_type: "ext4".to_string(),
             label: Some("TEST".to_string()),
             cluster_size: Some(4096),
             quick_format: true,
             enable_compression: false,
-            enable_encryption: false,
             verify_after_format: false,
-        dry_run: false,
-        force: false,
+            dry_run: false,
+            force: false,
+            additional_options: std::collections::HashMap::new(),
         };
         
         // Format the device
@@ -62,6 +67,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: false,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
@@ -117,6 +125,9 @@ mod tests {
             enable_checksums: true,
             enable_64bit: false,
             enable_journal: false,
+            uuid: None,
+            created: None,
+            root_owner: None,
         };
         
         let layout = FilesystemLayout::from_params(&params).unwrap();
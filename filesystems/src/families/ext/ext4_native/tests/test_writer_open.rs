@@ -0,0 +1,134 @@
+// Test that the writer loads real metadata and refuses features it cannot write
+
+#[cfg(test)]
+mod tests {
+    use crate::families::ext::ext4_native::core::{
+        structures::*,
+        constants::*,
+        formatter_impl::format_device,
+    };
+    use crate::families::ext::ext4_native::reader::{load_superblock, load_group_descriptors, ExtReader};
+    use crate::families::ext::ext4_native::writer::{Ext4Writer, check_write_support};
+    use moses_core::{Device, DeviceType, FormatOptions, MosesError};
+    use std::fs::{File, OpenOptions};
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+    use tempfile::NamedTempFile;
+
+    fn image_device(path: &str, size: u64) -> Device {
+        Device {
+            id: path.to_string(),
+            name: "test_image".to_string(),
+            size,
+            device_type: DeviceType::Unknown,
+            is_removable: true,
+            is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+            mount_points: vec![],
+            filesystem: None,
+        }
+    }
+
+    async fn formatted_image(size: u64) -> (NamedTempFile, Device) {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let device = image_device(image.path().to_str().unwrap(), size);
+        let options = FormatOptions {
+            filesystem_type: "ext4".to_string(),
+            label: Some("WRITER".to_string()),
+            cluster_size: Some(4096),
+            quick_format: true,
+            enable_compression: false,
+            verify_after_format: false,
+            dry_run: false,
+            force: false,
+            additional_options: std::collections::HashMap::new(),
+        };
+        format_device(&device, &options).await.unwrap();
+        (image, device)
+    }
+
+    #[tokio::test]
+    async fn test_writer_loads_formatted_metadata() {
+        let size = 256 * 1024 * 1024;
+        let (image, device) = formatted_image(size).await;
+
+        let mut file = File::open(image.path()).unwrap();
+        let sb = load_superblock(&mut file).unwrap();
+        let descriptors = load_group_descriptors(&mut file, &sb).unwrap();
+        assert_eq!(descriptors.len(), 2);
+        for (group, gd) in descriptors.iter().enumerate() {
+            let inode_table = gd.bg_inode_table_lo as u64 | ((gd.bg_inode_table_hi as u64) << 32);
+            let group_start = group as u64 * sb.s_blocks_per_group as u64;
+            assert!(inode_table >= group_start && inode_table < group_start + sb.s_blocks_per_group as u64,
+                    "group {} inode table at block {}", group, inode_table);
+        }
+
+        let info = ExtReader::new(device.clone()).unwrap().get_info();
+        assert_eq!(info.block_count, sb.s_blocks_count_lo as u64 | ((sb.s_blocks_count_hi as u64) << 32));
+
+        Ext4Writer::new(device).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_writer_rejects_unsupported_features() {
+        let size = 256 * 1024 * 1024;
+        let (image, device) = formatted_image(size).await;
+
+        let mut sb = load_superblock(&mut File::open(image.path()).unwrap()).unwrap();
+        sb.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_INLINE_DATA;
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&sb as *const _ as *const u8, std::mem::size_of::<Ext4Superblock>())
+        };
+        let mut file = OpenOptions::new().write(true).open(image.path()).unwrap();
+        file.seek(SeekFrom::Start(1024)).unwrap();
+        file.write_all(bytes).unwrap();
+        drop(file);
+
+        match Ext4Writer::new(device) {
+            Err(MosesError::NotSupported(msg)) => assert!(msg.contains("inline_data"), "{}", msg),
+            other => panic!("expected NotSupported, got {:?}", other.err()),
+        }
+
+        let mut sb = Ext4Superblock::new();
+        sb.s_feature_incompat = EXT4_FEATURE_INCOMPAT_EXTENTS | EXT4_FEATURE_INCOMPAT_RECOVER;
+        assert!(matches!(check_write_support(&sb), Err(MosesError::NotSupported(msg)) if msg.contains("recovery")));
+        sb.s_feature_incompat = EXT4_FEATURE_INCOMPAT_EXTENTS;
+        sb.s_feature_ro_compat = EXT4_FEATURE_RO_COMPAT_BIGALLOC | 0x8000;
+        assert!(matches!(check_write_support(&sb), Err(MosesError::NotSupported(msg)) if msg.contains("bigalloc, 0x8000")));
+        sb.s_feature_ro_compat = EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+        assert!(check_write_support(&sb).is_ok());
+    }
+
+    #[test]
+    fn test_32_byte_group_descriptors() {
+        // 1 KiB blocks, three groups, descriptors without the 64bit feature
+        let mut sb = Ext4Superblock::new();
+        sb.s_magic = EXT4_SUPER_MAGIC;
+        sb.s_log_block_size = 0;
+        sb.s_first_data_block = 1;
+        sb.s_blocks_per_group = 8192;
+        sb.s_inodes_per_group = 2048;
+        sb.s_blocks_count_lo = 1 + 2 * 8192 + 100;
+        sb.s_desc_size = 64;
+
+        let mut image = vec![0u8; 4096];
+        let sb_bytes = unsafe {
+            std::slice::from_raw_parts(&sb as *const _ as *const u8, std::mem::size_of::<Ext4Superblock>())
+        };
+        image[1024..2048].copy_from_slice(sb_bytes);
+        for group in 0..3u32 {
+            let at = 2048 + group as usize * 32;
+            image[at + 8..at + 12].copy_from_slice(&(group * 8192 + 5).to_le_bytes());
+        }
+
+        // A 64-byte stride would read group 1's table as group 0's high half
+        let mut reader = Cursor::new(image);
+        let sb = load_superblock(&mut reader).unwrap();
+        let descriptors = load_group_descriptors(&mut reader, &sb).unwrap();
+        let tables: Vec<(u32, u32)> = descriptors.iter().map(|gd| (gd.bg_inode_table_lo, gd.bg_inode_table_hi)).collect();
+        assert_eq!(tables, vec![(5, 0), (8197, 0), (16389, 0)]);
+    }
+}
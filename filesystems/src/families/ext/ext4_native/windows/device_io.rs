@@ -3,7 +3,7 @@
 
 #[cfg(target_os = "windows")]
 use winapi::{
-    um::fileapi::{CreateFileW, OPEN_EXISTING, SetFilePointerEx, WriteFile},
+    um::fileapi::{CreateFileW, FlushFileBuffers, OPEN_EXISTING, SetFilePointerEx, WriteFile},
     um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE, HANDLE, LARGE_INTEGER},
     um::handleapi::CloseHandle,
     um::errhandlingapi::GetLastError,
@@ -299,7 +299,13 @@ impl WindowsDeviceIO {
     
     /// Flush all pending writes
     pub fn flush(&self) -> Ext4Result<()> {
-        // Windows FILE_FLAG_WRITE_THROUGH should handle this
+        // Only the last open attempt uses FILE_FLAG_WRITE_THROUGH, so the drive cache
+        // has to be flushed explicitly
+        let ok = unsafe { FlushFileBuffers(self.handle) };
+        if ok == 0 {
+            let error = unsafe { GetLastError() };
+            return Err(Ext4Error::WindowsError(format!("FlushFileBuffers failed: {}", error)));
+        }
         Ok(())
     }
 }
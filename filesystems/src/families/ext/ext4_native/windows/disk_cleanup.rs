@@ -148,12 +148,7 @@ pub fn cleanup_disk_for_format(physical_drive_number: u32) -> Result<(), String>
 // Extract drive number from device path (e.g., \\.\PHYSICALDRIVE2 -> 2)
 #[cfg(target_os = "windows")]
 pub fn get_drive_number_from_path(device_path: &str) -> Option<u32> {
-    if let Some(pos) = device_path.to_uppercase().find("PHYSICALDRIVE") {
-        let number_str = &device_path[pos + 13..]; // Skip "PHYSICALDRIVE"
-        number_str.parse().ok()
-    } else {
-        None
-    }
+    moses_core::physical_drive_number(device_path)
 }
 
 #[cfg(not(target_os = "windows"))]
@@ -6,6 +6,7 @@ use crate::families::ext::ext4_native::core::{
     types::*,
     constants::*,
     transaction::TransactionHandle,
+    dir_entry::{dir_entries, rec_len_for, set_inode, set_rec_len, write_entry, MIN_REC_LEN},
 };
 use moses_core::MosesError;
 /// Directory entry with metadata
@@ -71,46 +72,9 @@ impl super::Ext4Writer {
         let blocks = self.get_extent_blocks(dir_inode)?;
         
         for block_num in blocks {
-            // Read block data
             let block_data = self.read_block(block_num)?;
-            
-            // Parse directory entries in block
-            let mut offset = 0;
-            while offset < self.block_size as usize {
-                if offset + std::mem::size_of::<Ext4DirEntry2>() > self.block_size as usize {
-                    break;
-                }
-                
-                let entry = unsafe {
-                    &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-                };
-                
-                // Check if entry is valid
-                if entry.inode == 0 {
-                    offset += entry.rec_len as usize;
-                    continue;
-                }
-                
-                // Extract name
-                let name_len = entry.name_len as usize;
-                if name_len > 0 && name_len <= 255 {
-                    let entry_name = unsafe {
-                        let name_ptr = block_data.as_ptr().add(offset + 8);
-                        std::str::from_utf8_unchecked(
-                            std::slice::from_raw_parts(name_ptr, name_len)
-                        )
-                    };
-                    
-                    if entry_name == name {
-                        return Ok(Some(DirectoryEntry {
-                            inode: entry.inode,
-                            name: entry_name.to_string(),
-                            file_type: entry.file_type,
-                        }));
-                    }
-                }
-                
-                offset += entry.rec_len as usize;
+            if let Some(found) = find_entry(&block_data, name)? {
+                return Ok(Some(found));
             }
         }
         
@@ -174,76 +138,32 @@ impl super::Ext4Writer {
         __transaction: &TransactionHandle,
     ) -> Result<bool, MosesError> {
         let mut block_data = self.read_block(block_num)?;
-        
-        let name_len = name.len();
-        let required_size = 8 + name_len;
-        let aligned_required = (required_size + 3) & !3;
-        
-        let mut offset = 0;
-        while offset < self.block_size as usize {
-            if offset + std::mem::size_of::<Ext4DirEntry2>() > self.block_size as usize {
+        let required = rec_len_for(name.len());
+        
+        // First entry with room after its own bytes; the checksum tail is never a slot
+        let mut slot = None;
+        for entry in dir_entries(&block_data) {
+            let entry = entry?;
+            if !entry.is_csum_tail() && entry.slack() >= required {
+                slot = Some((entry.offset, entry.rec_len, entry.used_size()));
                 break;
             }
-            
-            let entry = unsafe {
-                &mut *(block_data.as_mut_ptr().add(offset) as *mut Ext4DirEntry2)
-            };
-            
-            let rec_len = entry.rec_len as usize;
-            
-            // Calculate actual entry size
-            let actual_size = if entry.inode != 0 {
-                8 + entry.name_len as usize
-            } else {
-                0
-            };
-            let aligned_actual = (actual_size + 3) & !3;
-            
-            // Check if there's enough space
-            if rec_len >= aligned_actual + aligned_required {
-                // Split the entry
-                if entry.inode != 0 {
-                    // Adjust current entry's rec_len
-                    entry.rec_len = aligned_actual as u16;
-                    
-                    // Create new entry in the free space
-                    let new_offset = offset + aligned_actual;
-                    let new_entry = unsafe {
-                        &mut *(block_data.as_mut_ptr().add(new_offset) as *mut Ext4DirEntry2)
-                    };
-                    
-                    new_entry.inode = inode;
-                    new_entry.rec_len = (rec_len - aligned_actual) as u16;
-                    new_entry.name_len = name_len as u8;
-                    new_entry.file_type = file_type;
-                    
-                    // Copy name
-                    unsafe {
-                        let name_ptr = block_data.as_mut_ptr().add(new_offset + 8);
-                        name_ptr.copy_from_nonoverlapping(name.as_ptr(), name_len);
-                    }
-                } else {
-                    // Use empty entry
-                    entry.inode = inode;
-                    entry.name_len = name_len as u8;
-                    entry.file_type = file_type;
-                    
-                    // Copy name
-                    unsafe {
-                        let name_ptr = block_data.as_mut_ptr().add(offset + 8);
-                        name_ptr.copy_from_nonoverlapping(name.as_ptr(), name_len);
-                    }
-                }
-                
-                // Write block back
-                self.write_block(block_num, &block_data)?;
-                return Ok(true);
-            }
-            
-            offset += rec_len;
+        }
+        let Some((offset, rec_len, used)) = slot else {
+            return Ok(false);
+        };
+        
+        if used > 0 {
+            // Split: the current entry keeps its own bytes, the new one takes the rest
+            set_rec_len(&mut block_data, offset, used);
+            write_entry(&mut block_data, offset + used, inode, rec_len - used, name.as_bytes(), file_type)?;
+        } else {
+            // Reuse the unused entry in place
+            write_entry(&mut block_data, offset, inode, rec_len, name.as_bytes(), file_type)?;
         }
         
-        Ok(false)
+        self.write_block(block_num, &block_data)?;
+        Ok(true)
     }
     
     /// Remove a directory entry
@@ -259,50 +179,30 @@ impl super::Ext4Writer {
         for block_num in blocks {
             let mut block_data = self.read_block(block_num)?;
             
-            let mut offset = 0;
-            let mut prev_offset = None;
-            
-            while offset < self.block_size as usize {
-                let entry = unsafe {
-                    &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-                };
-                
-                if entry.inode != 0 && entry.name_len > 0 {
-                    let entry_name = unsafe {
-                        let name_ptr = block_data.as_ptr().add(offset + 8);
-                        std::str::from_utf8_unchecked(
-                            std::slice::from_raw_parts(name_ptr, entry.name_len as usize)
-                        )
-                    };
-                    
-                    if entry_name == name {
-                        let removed_inode = entry.inode;
-                        
-                        // Mark entry as deleted
-                        let entry_mut = unsafe {
-                            &mut *(block_data.as_mut_ptr().add(offset) as *mut Ext4DirEntry2)
-                        };
-                        
-                        if let Some(prev) = prev_offset {
-                            // Merge with previous entry
-                            let prev_entry = unsafe {
-                                &mut *(block_data.as_mut_ptr().add(prev) as *mut Ext4DirEntry2)
-                            };
-                            prev_entry.rec_len += entry_mut.rec_len;
-                        } else {
-                            // Just mark as deleted
-                            entry_mut.inode = 0;
-                        }
-                        
-                        self.write_block(block_num, &block_data)?;
-                        self.update_directory_mtime(dir_inode_num, _transaction)?;
-                        return Ok(removed_inode);
-                    }
+            let mut found = None;
+            let mut previous: Option<(usize, usize)> = None;
+            for entry in dir_entries(&block_data) {
+                let entry = entry?;
+                if entry.is_used() && entry.name == name.as_bytes() {
+                    found = Some((entry.offset, entry.rec_len, entry.inode, previous));
+                    break;
                 }
-                
-                prev_offset = Some(offset);
-                offset += entry.rec_len as usize;
+                previous = Some((entry.offset, entry.rec_len));
+            }
+            let Some((offset, rec_len, removed_inode, previous)) = found else {
+                continue;
+            };
+            
+            match previous {
+                // Merge into the previous entry
+                Some((previous, previous_len)) => set_rec_len(&mut block_data, previous, previous_len + rec_len),
+                // The first entry of a block has nothing before it; just mark it unused
+                None => set_inode(&mut block_data, offset, 0),
             }
+            
+            self.write_block(block_num, &block_data)?;
+            self.update_directory_mtime(dir_inode_num, _transaction)?;
+            return Ok(removed_inode);
         }
         
         Err(MosesError::Other(format!("Entry '{}' not found", name)))
@@ -313,43 +213,17 @@ impl super::Ext4Writer {
         let dir_inode = self.read_inode(dir_inode_num)?;
         let blocks = self.get_extent_blocks(&dir_inode)?;
         
-        let mut entry_count = 0;
-        
         for block_num in blocks {
             let block_data = self.read_block(block_num)?;
-            
-            let mut offset = 0;
-            while offset < self.block_size as usize {
-                if offset + std::mem::size_of::<Ext4DirEntry2>() > self.block_size as usize {
-                    break;
+            for entry in dir_entries(&block_data) {
+                let entry = entry?;
+                if entry.is_used() && !entry.is_dot_or_dotdot() {
+                    return Ok(false);
                 }
-                
-                let entry = unsafe {
-                    &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-                };
-                
-                if entry.inode != 0 {
-                    let name_len = entry.name_len as usize;
-                    if name_len > 0 && name_len <= 255 {
-                        let entry_name = unsafe {
-                            let name_ptr = block_data.as_ptr().add(offset + 8);
-                            std::str::from_utf8_unchecked(
-                                std::slice::from_raw_parts(name_ptr, name_len)
-                            )
-                        };
-                        
-                        // Skip . and .. entries
-                        if entry_name != "." && entry_name != ".." {
-                            entry_count += 1;
-                        }
-                    }
-                }
-                
-                offset += entry.rec_len as usize;
             }
         }
         
-        Ok(entry_count == 0)
+        Ok(true)
     }
     
     /// Create . and .. entries in a new directory block
@@ -360,28 +234,11 @@ impl super::Ext4Writer {
         parent_inode: u32,
         __transaction: &TransactionHandle,
     ) -> Result<(), MosesError> {
-        let mut block_data = vec![0u8; self.block_size as usize];
+        let block_size = self.block_size as usize;
+        let mut block_data = vec![0u8; block_size];
         
-        // Create "." entry
-        let dot_entry = unsafe {
-            &mut *(block_data.as_mut_ptr() as *mut Ext4DirEntry2)
-        };
-        dot_entry.inode = self_inode;
-        dot_entry.rec_len = 12; // 4 + 2 + 1 + 1 + 1 (padded to 12)
-        dot_entry.name_len = 1;
-        dot_entry.file_type = EXT4_FT_DIR;
-        block_data[8] = b'.';
-        
-        // Create ".." entry
-        let dotdot_entry = unsafe {
-            &mut *(block_data.as_mut_ptr().add(12) as *mut Ext4DirEntry2)
-        };
-        dotdot_entry.inode = parent_inode;
-        dotdot_entry.rec_len = (self.block_size - 12) as u16;
-        dotdot_entry.name_len = 2;
-        dotdot_entry.file_type = EXT4_FT_DIR;
-        block_data[20] = b'.';
-        block_data[21] = b'.';
+        write_entry(&mut block_data, 0, self_inode, MIN_REC_LEN, b".", EXT4_FT_DIR)?;
+        write_entry(&mut block_data, MIN_REC_LEN, parent_inode, block_size - MIN_REC_LEN, b"..", EXT4_FT_DIR)?;
         
         self.write_block(dir_block, &block_data)?;
         Ok(())
@@ -393,16 +250,11 @@ impl super::Ext4Writer {
         block_num: BlockNumber,
         __transaction: &TransactionHandle,
     ) -> Result<(), MosesError> {
-        let mut block_data = vec![0u8; self.block_size as usize];
+        let block_size = self.block_size as usize;
+        let mut block_data = vec![0u8; block_size];
         
-        // Create a single empty entry spanning the whole block
-        let entry = unsafe {
-            &mut *(block_data.as_mut_ptr() as *mut Ext4DirEntry2)
-        };
-        entry.inode = 0;
-        entry.rec_len = self.block_size as u16;
-        entry.name_len = 0;
-        entry.file_type = 0;
+        // A single unused entry spanning the whole block
+        write_entry(&mut block_data, 0, 0, block_size, b"", 0)?;
         
         self.write_block(block_num, &block_data)?;
         Ok(())
@@ -447,25 +299,15 @@ impl super::Ext4Writer {
     
     /// Create an HTree indexed directory root block
     pub fn create_htree_root(&mut self, parent_inode: u32) -> Result<Vec<u8>, MosesError> {
-        let mut block = vec![0u8; self.block_size as usize];
-        
-        // Create dot entry
-        let dot = Ext4DirEntry2 {
-            inode: parent_inode,
-            rec_len: 12,
-            name_len: 1,
-            file_type: EXT4_FT_DIR,
-        };
+        let block_size = self.block_size as usize;
+        let mut block = vec![0u8; block_size];
         
-        // Create dotdot entry
-        let dotdot = Ext4DirEntry2 {
-            inode: parent_inode, // Will be updated to actual parent
-            rec_len: self.block_size as u16 - 12,
-            name_len: 2,
-            file_type: EXT4_FT_DIR,
-        };
+        // "." and ".." (updated to the actual parent later); ".." spans the rest of the
+        // block so the index after it is invisible to a linear walk
+        write_entry(&mut block, 0, parent_inode, MIN_REC_LEN, b".", EXT4_FT_DIR)?;
+        write_entry(&mut block, MIN_REC_LEN, parent_inode, block_size - MIN_REC_LEN, b"..", EXT4_FT_DIR)?;
         
-        // Create DxRootInfo
+        // DxRootInfo right after the ".." name
         let dx_info = DxRootInfo {
             reserved_zero: 0,
             hash_version: 1, // DX_HASH_HALF_MD4
@@ -473,41 +315,12 @@ impl super::Ext4Writer {
             indirect_levels: 0,
             unused_flags: 0,
         };
-        
-        // Write dot entry
-        unsafe {
-            let dot_bytes = std::slice::from_raw_parts(
-                &dot as *const _ as *const u8,
-                std::mem::size_of::<Ext4DirEntry2>()
-            );
-            block[0..dot_bytes.len()].copy_from_slice(dot_bytes);
-        }
-        
-        // Write name for dot
-        block[8] = b'.';
-        
-        // Write dotdot entry at offset 12
-        unsafe {
-            let dotdot_bytes = std::slice::from_raw_parts(
-                &dotdot as *const _ as *const u8,
-                std::mem::size_of::<Ext4DirEntry2>()
-            );
-            block[12..12 + dotdot_bytes.len()].copy_from_slice(dotdot_bytes);
-        }
-        
-        // Write name for dotdot
-        block[20] = b'.';
-        block[21] = b'.';
-        
-        // Write DxRootInfo at appropriate offset within dotdot's space
-        let dx_info_offset = 24;
-        unsafe {
-            let info_bytes = std::slice::from_raw_parts(
-                &dx_info as *const _ as *const u8,
-                std::mem::size_of::<DxRootInfo>()
-            );
-            block[dx_info_offset..dx_info_offset + info_bytes.len()].copy_from_slice(info_bytes);
-        }
+        let dx_info_offset = 2 * MIN_REC_LEN;
+        block[dx_info_offset..dx_info_offset + 4].copy_from_slice(&{ dx_info.reserved_zero }.to_le_bytes());
+        block[dx_info_offset + 4] = dx_info.hash_version;
+        block[dx_info_offset + 5] = dx_info.info_length;
+        block[dx_info_offset + 6] = dx_info.indirect_levels;
+        block[dx_info_offset + 7] = dx_info.unused_flags;
         
         Ok(block)
     }
@@ -521,4 +334,19 @@ impl super::Ext4Writer {
     fn write_block(&mut self, block_num: BlockNumber, data: &[u8]) -> Result<(), MosesError> {
         self.write_block_to_disk(block_num, data)
     }
-}
\ No newline at end of file
+}
+
+/// The used entry called `name` in a directory block
+pub(super) fn find_entry(block: &[u8], name: &str) -> Result<Option<DirectoryEntry>, MosesError> {
+    for entry in dir_entries(block) {
+        let entry = entry?;
+        if entry.is_used() && entry.name == name.as_bytes() {
+            return Ok(Some(DirectoryEntry {
+                inode: entry.inode,
+                name: name.to_string(),
+                file_type: entry.file_type,
+            }));
+        }
+    }
+    Ok(None)
+}
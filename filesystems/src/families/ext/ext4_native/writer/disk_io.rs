@@ -115,7 +115,7 @@ impl Ext4Writer {
             let mut file = OpenOptions::new()
                 .read(true)
                 .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -129,7 +129,7 @@ impl Ext4Writer {
             
             let mut file = OpenOptions::new()
                 .read(true)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -143,7 +143,7 @@ impl Ext4Writer {
             
             let mut file = OpenOptions::new()
                 .read(true)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -163,6 +163,8 @@ impl Ext4Writer {
             )));
         }
         
+        self.refresh_mmp()?;
+        
         let offset = block_num * self.block_size as u64;
         
         // Platform-specific device I/O
@@ -176,7 +178,7 @@ impl Ext4Writer {
             let mut file = OpenOptions::new()
                 .write(true)
                 .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -190,7 +192,7 @@ impl Ext4Writer {
             
             let mut file = OpenOptions::new()
                 .write(true)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -204,7 +206,7 @@ impl Ext4Writer {
             
             let mut file = OpenOptions::new()
                 .write(true)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.seek(SeekFrom::Start(offset)).map_err(|e| MosesError::Other(e.to_string()))?;
@@ -355,7 +357,7 @@ impl Ext4Writer {
             
             let file = OpenOptions::new()
                 .write(true)
-                .open(&self.device.mount_points[0])
+                .open(crate::utils::get_device_path(&self.device))
                 .map_err(|e| MosesError::Other(e.to_string()))?;
             
             file.sync_all().map_err(|e| MosesError::Other(e.to_string()))?;
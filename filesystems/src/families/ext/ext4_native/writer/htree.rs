@@ -2,10 +2,11 @@
 // Implements hash-based directory indexing for fast lookups
 
 use super::*;
-use crate::families::ext::ext4_native::writer::directory::{DirectoryEntry, DxRootInfo, DxEntry};
+use crate::families::ext::ext4_native::writer::directory::{find_entry, DirectoryEntry, DxRootInfo, DxEntry};
 use crate::families::ext::ext4_native::core::{
     structures::*,
     types::*,
+    dir_entry::{dir_entries, rec_len_for},
 };
 use moses_core::MosesError;
 
@@ -52,20 +53,7 @@ impl Ext4Writer {
     
     /// Parse HTree root from block data
     fn parse_htree_root(&self, block_data: &[u8]) -> Result<HTreeRoot, MosesError> {
-        // Skip . and .. entries
-        let mut offset = 0;
-        
-        // Skip . entry
-        let dot_entry = unsafe {
-            &*(block_data.as_ptr() as *const Ext4DirEntry2)
-        };
-        offset += dot_entry.rec_len as usize;
-        
-        // Skip .. entry
-        let dotdot_entry = unsafe {
-            &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-        };
-        offset += dotdot_entry.rec_len as usize;
+        let offset = dx_root_info_offset(block_data)?;
         
         // Now we should have the dx_root_info
         if offset + std::mem::size_of::<DxRootInfo>() > block_data.len() {
@@ -221,22 +209,7 @@ impl Ext4Writer {
         }
         
         // Parse dx_entries after the root info
-        let mut offset = 0;
-        
-        // Skip . entry
-        let dot_entry = unsafe {
-            &*(root_data.as_ptr() as *const Ext4DirEntry2)
-        };
-        offset += dot_entry.rec_len as usize;
-        
-        // Skip .. entry
-        let dotdot_entry = unsafe {
-            &*(root_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-        };
-        offset += dotdot_entry.rec_len as usize;
-        
-        // Skip dx_root_info
-        offset += std::mem::size_of::<DxRootInfo>();
+        let mut offset = dx_root_info_offset(root_data)? + std::mem::size_of::<DxRootInfo>();
         
         // Now we have dx_entries
         let mut best_block = 0u64;
@@ -272,41 +245,24 @@ impl Ext4Writer {
         name: &str,
     ) -> Result<Option<DirectoryEntry>, MosesError> {
         let block_data = self.read_block_from_disk(leaf_block)?;
-        
-        let mut offset = 0;
-        while offset < self.block_size as usize {
-            if offset + std::mem::size_of::<Ext4DirEntry2>() > block_data.len() {
-                break;
-            }
-            
-            let entry = unsafe {
-                &*(block_data.as_ptr().add(offset) as *const Ext4DirEntry2)
-            };
-            
-            if entry.inode != 0 && entry.name_len > 0 {
-                let entry_name = unsafe {
-                    let name_ptr = block_data.as_ptr().add(offset + 8);
-                    std::str::from_utf8_unchecked(
-                        std::slice::from_raw_parts(name_ptr, entry.name_len as usize)
-                    )
-                };
-                
-                if entry_name == name {
-                    return Ok(Some(DirectoryEntry {
-                        inode: entry.inode,
-                        name: entry_name.to_string(),
-                        file_type: entry.file_type,
-                    }));
-                }
-            }
-            
-            offset += entry.rec_len as usize;
-        }
-        
-        Ok(None)
+        find_entry(&block_data, name)
     }
 }
 
+/// Where DxRootInfo starts: after the bytes "." and ".." use. The ".." rec_len spans the
+/// rest of the block, hiding the index from a linear walk, so it cannot be used to skip.
+fn dx_root_info_offset(block_data: &[u8]) -> Result<usize, MosesError> {
+    let mut entries = dir_entries(block_data);
+    let mut next = |expected: &[u8]| match entries.next() {
+        Some(Ok(entry)) if entry.name == expected => Ok(entry),
+        Some(Err(e)) => Err(e),
+        _ => Err(MosesError::Other("Invalid HTree root structure: it does not start with . and ..".to_string())),
+    };
+    next(b".")?;
+    let dotdot = next(b"..")?;
+    Ok(dotdot.offset + rec_len_for(dotdot.name.len()))
+}
+
 /// HTree root information
 struct HTreeRoot {
     hash_version: u8,
@@ -0,0 +1,307 @@
+// Multi-mount protection (MMP) for the ext4 writer
+// Filesystems with INCOMPAT_MMP keep one block that every mounter claims with a random
+// sequence number and keeps refreshing. Before writing we follow the kernel's protocol
+// (fs/ext4/mmp.c): a clean block is claimed at once; any other sequence must stay
+// unchanged for two check intervals, proving its owner is gone, before it is taken over.
+// The claim is confirmed by waiting again and checking nobody overwrote it. While writing,
+// the block is refreshed every interval, and finding a sequence we did not write means
+// another machine mounted the disk, so writing stops. Dropping the guard marks it clean.
+
+use moses_core::MosesError;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::families::ext::ext4_native::core::{
+    checksum::crc32c_ext4,
+    constants::*,
+    structures::Ext4Superblock,
+};
+
+const EXT4_MMP_MAGIC: u32 = 0x004D_4D50;
+const EXT4_MMP_SEQ_CLEAN: u32 = 0xFF4D_4D50;
+const EXT4_MMP_SEQ_FSCK: u32 = 0xE24D_4D50;
+/// Largest sequence number a mounter may use
+const EXT4_MMP_SEQ_MAX: u32 = 0xE24D_4D4F;
+/// Seconds; the kernel never checks more often than this
+const EXT4_MMP_MIN_CHECK_INTERVAL: u16 = 5;
+/// Offset of mmp_checksum, the last field of the 1 KiB structure
+const MMP_CHECKSUM_OFFSET: usize = 0x3FC;
+
+/// Holds the MMP block of a filesystem being written
+pub struct MmpGuard<D: Read + Write + Seek> {
+    device: D,
+    /// Byte offset of the MMP block
+    offset: u64,
+    block_size: usize,
+    interval: Duration,
+    /// Sequence number we last wrote
+    seq: u32,
+    last_refresh: Instant,
+    /// Seed for mmp_checksum when metadata checksums are enabled
+    csum_seed: Option<u32>,
+    device_name: String,
+    wait: fn(Duration),
+    released: bool,
+}
+
+impl<D: Read + Write + Seek> MmpGuard<D> {
+    /// Claim the MMP block of `sb`'s filesystem through `device`. None if the filesystem
+    /// has no MMP; an error if another machine has it mounted or e2fsck is running.
+    /// With MMP this waits twice the check interval (11 seconds or more), twice if the
+    /// block was not left clean.
+    pub fn claim(device: D, sb: &Ext4Superblock, device_name: &str) -> Result<Option<Self>, MosesError> {
+        Self::claim_with(device, sb, device_name, std::thread::sleep)
+    }
+
+    fn claim_with(device: D, sb: &Ext4Superblock, device_name: &str, wait: fn(Duration)) -> Result<Option<Self>, MosesError> {
+        if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_MMP == 0 {
+            return Ok(None);
+        }
+        let block_size = sb.s_block_size() as usize;
+        let csum_seed = (sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0).then(|| {
+            if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+                sb.s_checksum_seed
+            } else {
+                crc32c_ext4(&sb.s_uuid, !0)
+            }
+        });
+        let mut guard = Self {
+            device,
+            offset: sb.s_mmp_block * block_size as u64,
+            block_size,
+            interval: Duration::ZERO,
+            seq: 0,
+            last_refresh: Instant::now(),
+            csum_seed,
+            device_name: device_name.to_string(),
+            wait,
+            released: true,
+        };
+
+        let block = guard.read_block()?;
+        let (seq, node) = parse_block(&block)?;
+        let disk_interval = u16::from_le_bytes([block[112], block[113]]);
+        let interval = sb.s_mmp_interval.max(disk_interval).max(EXT4_MMP_MIN_CHECK_INTERVAL);
+        guard.interval = Duration::from_secs(interval as u64);
+        // Long enough for a live owner to have refreshed the block at least once
+        let settle = guard.interval * 2 + Duration::from_secs(1);
+
+        match seq {
+            EXT4_MMP_SEQ_CLEAN => {}
+            EXT4_MMP_SEQ_FSCK => {
+                return Err(MosesError::Other(format!("e2fsck is running on this filesystem ({})", describe_node(&node))));
+            }
+            seq if seq > EXT4_MMP_SEQ_MAX => {
+                return Err(MosesError::Other(format!("MMP block has an invalid sequence {:#x}; run e2fsck", seq)));
+            }
+            seq => {
+                log::info!("MMP block last updated by {}, waiting {:?} to see if it is still mounted", describe_node(&node), settle);
+                (guard.wait)(settle);
+                let (now, node) = parse_block(&guard.read_block()?)?;
+                if now != seq {
+                    return Err(in_use(&node));
+                }
+            }
+        }
+
+        guard.seq = rand::random::<u32>() % EXT4_MMP_SEQ_MAX + 1;
+        guard.write_seq()?;
+        (guard.wait)(settle);
+        let (now, node) = parse_block(&guard.read_block()?)?;
+        if now != guard.seq {
+            return Err(in_use(&node));
+        }
+        guard.released = false;
+        log::info!("Claimed MMP block {} (check interval {:?})", sb.s_mmp_block, guard.interval);
+        Ok(Some(guard))
+    }
+
+    /// Refresh the block if a check interval has passed since the last refresh
+    pub fn refresh_if_due(&mut self) -> Result<(), MosesError> {
+        if self.last_refresh.elapsed() >= self.interval {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Confirm we still own the block and bump its sequence
+    pub fn refresh(&mut self) -> Result<(), MosesError> {
+        let (seq, node) = parse_block(&self.read_block()?)?;
+        if seq != self.seq {
+            // Someone else owns it now; never mark it clean behind their back
+            self.released = true;
+            return Err(in_use(&node));
+        }
+        self.seq = if self.seq >= EXT4_MMP_SEQ_MAX { 1 } else { self.seq + 1 };
+        self.write_seq()
+    }
+
+    /// Mark the filesystem cleanly released
+    pub fn release(mut self) -> Result<(), MosesError> {
+        self.release_in_place()
+    }
+
+    fn release_in_place(&mut self) -> Result<(), MosesError> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+        let (seq, _) = parse_block(&self.read_block()?)?;
+        if seq != self.seq {
+            log::warn!("MMP block changed hands during the session; leaving it as is");
+            return Ok(());
+        }
+        self.seq = EXT4_MMP_SEQ_CLEAN;
+        self.write_seq()
+    }
+
+    fn read_block(&mut self) -> Result<Vec<u8>, MosesError> {
+        let mut block = vec![0u8; self.block_size];
+        self.device.seek(SeekFrom::Start(self.offset))?;
+        self.device.read_exact(&mut block)?;
+        Ok(block)
+    }
+
+    /// Write the block with our sequence number, this node's name and the current time
+    fn write_seq(&mut self) -> Result<(), MosesError> {
+        let mut block = vec![0u8; self.block_size];
+        block[0..4].copy_from_slice(&EXT4_MMP_MAGIC.to_le_bytes());
+        block[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        block[8..16].copy_from_slice(&now.to_le_bytes());
+        copy_name(&mut block[16..80], &node_name());
+        copy_name(&mut block[80..112], &self.device_name);
+        block[112..114].copy_from_slice(&(self.interval.as_secs() as u16).to_le_bytes());
+        if let Some(seed) = self.csum_seed {
+            let checksum = crc32c_ext4(&block[..MMP_CHECKSUM_OFFSET], seed);
+            block[MMP_CHECKSUM_OFFSET..MMP_CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+        }
+
+        self.device.seek(SeekFrom::Start(self.offset))?;
+        self.device.write_all(&block)?;
+        self.device.flush()?;
+        self.last_refresh = Instant::now();
+        Ok(())
+    }
+}
+
+impl<D: Read + Write + Seek> Drop for MmpGuard<D> {
+    fn drop(&mut self) {
+        if let Err(e) = self.release_in_place() {
+            log::warn!("Failed to release MMP block: {}", e);
+        }
+    }
+}
+
+/// Sequence number and node name of an MMP block
+fn parse_block(block: &[u8]) -> Result<(u32, String), MosesError> {
+    if u32::from_le_bytes(block[0..4].try_into().unwrap()) != EXT4_MMP_MAGIC {
+        return Err(MosesError::Other("MMP block has a bad magic number; run e2fsck".to_string()));
+    }
+    let seq = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    let node = String::from_utf8_lossy(&block[16..80]).trim_end_matches('\0').to_string();
+    Ok((seq, node))
+}
+
+fn in_use(node: &str) -> MosesError {
+    MosesError::Other(format!(
+        "Filesystem is in use by {}; writing now would corrupt it",
+        describe_node(node)
+    ))
+}
+
+fn describe_node(node: &str) -> &str {
+    if node.is_empty() { "another machine" } else { node }
+}
+
+/// This machine's name, as Linux records it in mmp_nodename
+fn node_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "moses".to_string())
+}
+
+/// NUL-padded copy that always leaves a terminating NUL
+fn copy_name(field: &mut [u8], name: &str) {
+    let len = name.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MMP_BLOCK: u64 = 3;
+
+    fn no_wait(_: Duration) {}
+
+    fn mmp_superblock() -> Ext4Superblock {
+        let mut sb = Ext4Superblock::new();
+        sb.s_feature_incompat |= EXT4_FEATURE_INCOMPAT_MMP;
+        sb.s_mmp_block = MMP_BLOCK;
+        sb.s_mmp_interval = 5;
+        sb
+    }
+
+    /// 1 KiB-block image whose MMP block holds `seq`
+    fn disk(seq: u32, node: &str) -> Cursor<Vec<u8>> {
+        let mut image = vec![0u8; 8 * 1024];
+        let at = MMP_BLOCK as usize * 1024;
+        image[at..at + 4].copy_from_slice(&EXT4_MMP_MAGIC.to_le_bytes());
+        image[at + 4..at + 8].copy_from_slice(&seq.to_le_bytes());
+        image[at + 16..at + 16 + node.len()].copy_from_slice(node.as_bytes());
+        Cursor::new(image)
+    }
+
+    fn seq_on_disk(image: &Cursor<Vec<u8>>) -> u32 {
+        let at = MMP_BLOCK as usize * 1024;
+        u32::from_le_bytes(image.get_ref()[at + 4..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_claim_refresh_release() {
+        let mut guard = MmpGuard::claim_with(disk(EXT4_MMP_SEQ_CLEAN, ""), &mmp_superblock(), "sdb1", no_wait)
+            .unwrap()
+            .unwrap();
+        let claimed = seq_on_disk(&guard.device);
+        assert!(claimed <= EXT4_MMP_SEQ_MAX);
+        assert_eq!(claimed, guard.seq);
+
+        guard.refresh().unwrap();
+        assert_eq!(seq_on_disk(&guard.device), claimed % EXT4_MMP_SEQ_MAX + 1);
+
+        // Another machine takes over: refreshing fails and the block is left to it
+        let at = MMP_BLOCK as usize * 1024;
+        guard.device.get_mut()[at + 4..at + 8].copy_from_slice(&77u32.to_le_bytes());
+        assert!(guard.refresh().is_err());
+        guard.release_in_place().unwrap();
+        assert_eq!(seq_on_disk(&guard.device), 77);
+    }
+
+    #[test]
+    fn test_release_marks_clean() {
+        let mut guard = MmpGuard::claim_with(disk(1234, "deadhost"), &mmp_superblock(), "sdb1", no_wait)
+            .unwrap()
+            .unwrap();
+        // A stale sequence that never changed was taken over
+        assert_ne!(guard.seq, 1234);
+        guard.release_in_place().unwrap();
+        assert_eq!(seq_on_disk(&guard.device), EXT4_MMP_SEQ_CLEAN);
+    }
+
+    #[test]
+    fn test_refuses_fsck_and_plain_filesystems() {
+        let err = MmpGuard::claim_with(disk(EXT4_MMP_SEQ_FSCK, "server"), &mmp_superblock(), "sdb1", no_wait)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("e2fsck"));
+
+        let plain = Ext4Superblock::new();
+        assert!(MmpGuard::claim_with(disk(0, ""), &plain, "sdb1", no_wait).unwrap().is_none());
+    }
+}
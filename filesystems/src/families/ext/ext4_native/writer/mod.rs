@@ -10,6 +10,8 @@ mod path_resolution;
 mod disk_io;
 mod indirect_blocks;
 mod htree;
+mod mmp;
+pub use mmp::MmpGuard;
 use moses_core::{Device, MosesError};
 use std::path::{Path, PathBuf};
 use std::collections::HashMap;
@@ -22,8 +24,10 @@ use crate::families::ext::ext4_native::core::{
     block_allocator::{BlockAllocator, AllocationHint},
     inode_allocator::InodeAllocator,
     transaction::{TransactionManager, TransactionHandle, MetadataUpdate, MetadataType},
+    dir_entry::{dir_entries, set_inode},
 };
 
+use crate::families::ext::ext4_native::reader::{load_superblock, load_group_descriptors, group_desc_size};
 use crate::families::ext::ext4_native::journal::{
     Jbd2Journal, JournalConfig, JournalMode,
     Transaction as JournalTransaction,
@@ -63,6 +67,77 @@ pub struct Ext4Writer {
     dirty_inodes: std::collections::HashSet<u32>,
     /// Set of dirty blocks that need to be written
     dirty_blocks: std::collections::HashSet<BlockNumber>,
+    /// Claim on the multi-mount protection block, released when the writer is dropped
+    mmp: Option<MmpGuard<std::fs::File>>,
+}
+
+/// Incompatible features the writer understands; any other bit means the on-disk format
+/// differs in ways it would corrupt
+const WRITE_INCOMPAT_SUPPORTED: u32 = EXT4_FEATURE_INCOMPAT_FILETYPE
+    | EXT4_FEATURE_INCOMPAT_EXTENTS
+    | EXT4_FEATURE_INCOMPAT_64BIT
+    | EXT4_FEATURE_INCOMPAT_MMP
+    | EXT4_FEATURE_INCOMPAT_FLEX_BG;
+
+/// Read-only compatible features the writer keeps consistent
+const WRITE_RO_COMPAT_SUPPORTED: u32 = EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER
+    | EXT4_FEATURE_RO_COMPAT_LARGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_BTREE_DIR
+    | EXT4_FEATURE_RO_COMPAT_HUGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_GDT_CSUM
+    | EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+
+const INCOMPAT_NAMES: &[(u32, &str)] = &[
+    (EXT4_FEATURE_INCOMPAT_COMPRESSION, "compression"),
+    (EXT4_FEATURE_INCOMPAT_RECOVER, "needs_recovery"),
+    (EXT4_FEATURE_INCOMPAT_JOURNAL_DEV, "journal_dev"),
+    (EXT4_FEATURE_INCOMPAT_META_BG, "meta_bg"),
+    (EXT4_FEATURE_INCOMPAT_EA_INODE, "ea_inode"),
+    (EXT4_FEATURE_INCOMPAT_DIRDATA, "dirdata"),
+    (EXT4_FEATURE_INCOMPAT_CSUM_SEED, "metadata_csum_seed"),
+    (EXT4_FEATURE_INCOMPAT_LARGEDIR, "large_dir"),
+    (EXT4_FEATURE_INCOMPAT_INLINE_DATA, "inline_data"),
+    (EXT4_FEATURE_INCOMPAT_ENCRYPT, "encrypt"),
+];
+
+const RO_COMPAT_NAMES: &[(u32, &str)] = &[
+    (EXT4_FEATURE_RO_COMPAT_QUOTA, "quota"),
+    (EXT4_FEATURE_RO_COMPAT_BIGALLOC, "bigalloc"),
+    (EXT4_FEATURE_RO_COMPAT_REPLICA, "replica"),
+    (EXT4_FEATURE_RO_COMPAT_READONLY, "read-only"),
+    (EXT4_FEATURE_RO_COMPAT_PROJECT, "project"),
+];
+
+/// Names of the bits in `flags` outside `supported`, hex for ones without a name
+fn unsupported_features(flags: u32, supported: u32, names: &[(u32, &str)]) -> Vec<String> {
+    (0..32).map(|bit| 1u32 << bit)
+        .filter(|&mask| flags & mask != 0 && supported & mask == 0)
+        .map(|mask| names.iter()
+            .find(|(flag, _)| *flag == mask)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| format!("0x{:X}", mask)))
+        .collect()
+}
+
+/// Refuse to open a filesystem for writing when it uses a feature the writer does not
+/// implement. Unknown read-only compatible features forbid writing just like incompatible ones.
+pub(crate) fn check_write_support(sb: &Ext4Superblock) -> Result<(), MosesError> {
+    if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_RECOVER != 0 {
+        return Err(MosesError::NotSupported(
+            "The filesystem journal needs recovery. Mount it on Linux or run e2fsck first".to_string()
+        ));
+    }
+    let mut unsupported = unsupported_features(sb.s_feature_incompat, WRITE_INCOMPAT_SUPPORTED, INCOMPAT_NAMES);
+    unsupported.extend(unsupported_features(sb.s_feature_ro_compat, WRITE_RO_COMPAT_SUPPORTED, RO_COMPAT_NAMES));
+    if !unsupported.is_empty() {
+        return Err(MosesError::NotSupported(format!(
+            "Writing to ext filesystems with these features is not supported: {}",
+            unsupported.join(", ")
+        )));
+    }
+    Ok(())
 }
 
 impl Ext4Writer {
@@ -103,25 +178,20 @@ impl Ext4Writer {
         let block_size = superblock.s_block_size();
         let gdt_block = if block_size == 1024 { 2 } else { 1 };
         
-        // Open device using mount point or device name
-        let device_path = if !device.mount_points.is_empty() {
-            device.mount_points[0].clone()
-        } else {
-            PathBuf::from(format!("/dev/{}", device.id))
-        };
+        let device_path = PathBuf::from(crate::utils::get_device_path(device));
         
         let mut file = File::open(&device_path)
             .map_err(|e| MosesError::Other(e.to_string()))?;
         
-        // Read group descriptor
-        let gd_size = std::mem::size_of::<Ext4GroupDesc>();
-        let gd_offset = gdt_block * block_size + group as u32 * gd_size as u32;
+        // Read group descriptor; 32-byte descriptors leave the high half zeroed
+        let gd_size = group_desc_size(superblock);
+        let gd_offset = gdt_block as u64 * block_size as u64 + group as u64 * gd_size as u64;
         
-        file.seek(SeekFrom::Start(gd_offset as u64))
+        file.seek(SeekFrom::Start(gd_offset))
             .map_err(|e| MosesError::Other(e.to_string()))?;
         
-        let mut gd_bytes = vec![0u8; gd_size];
-        file.read_exact(&mut gd_bytes)
+        let mut gd_bytes = vec![0u8; std::mem::size_of::<Ext4GroupDesc>()];
+        file.read_exact(&mut gd_bytes[..gd_size.min(std::mem::size_of::<Ext4GroupDesc>())])
             .map_err(|e| MosesError::Other(e.to_string()))?;
         
         let group_desc = unsafe {
@@ -161,6 +231,9 @@ impl Ext4Writer {
             return Err(MosesError::Other("Not an ext4 filesystem".to_string()));
         }
         
+        // Make sure no other machine has it mounted before anything is written
+        let mmp = MmpGuard::claim(crate::utils::open_device_write(&device)?, &superblock, &device.name)?;
+        
         // Read group descriptors
         let group_descriptors = Self::read_group_descriptors(&device, &superblock)?;
         
@@ -181,11 +254,7 @@ impl Ext4Writer {
         
         // Create transaction manager with device path
         let enable_journal = superblock.s_feature_compat & EXT4_FEATURE_COMPAT_HAS_JOURNAL != 0;
-        let device_path = if !device.mount_points.is_empty() {
-            Some(device.mount_points[0].to_string_lossy().to_string())
-        } else {
-            Some(format!("/dev/{}", device.id))
-        };
+        let device_path = Some(crate::utils::get_device_path(&device));
         let transaction_manager = TransactionManager::new(&superblock, enable_journal, device_path);
         
         // Replay journal if needed
@@ -243,6 +312,7 @@ impl Ext4Writer {
             block_cache: HashMap::new(),
             dirty_inodes: std::collections::HashSet::new(),
             dirty_blocks: std::collections::HashSet::new(),
+            mmp,
         };
         
         // Cache root directory
@@ -898,17 +968,14 @@ impl Ext4Writer {
         // Read first block which contains . and .. entries
         let mut block_data = self.read_block_from_disk(blocks[0])?;
         
-        // Skip . entry and update .. entry
-        let dot_entry = unsafe {
-            &*(block_data.as_ptr() as *const Ext4DirEntry2)
-        };
-        let dotdot_offset = dot_entry.rec_len as usize;
-        
-        let dotdot_entry = unsafe {
-            &mut *(block_data.as_mut_ptr().add(dotdot_offset) as *mut Ext4DirEntry2)
-        };
-        
-        dotdot_entry.inode = new_parent;
+        // ".." is the second entry of the first block
+        let dotdot = dir_entries(&block_data)
+            .nth(1)
+            .transpose()?
+            .filter(|entry| entry.name == b"..")
+            .map(|entry| entry.offset)
+            .ok_or_else(|| MosesError::Other(format!("Directory inode {} has no .. entry", dir_inode_num)))?;
+        set_inode(&mut block_data, dotdot, new_parent);
         
         // Write block back
         self.write_block_to_disk(blocks[0], &block_data)?;
@@ -1046,23 +1113,21 @@ impl Ext4Writer {
             use std::fs::OpenOptions;
             use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
             
-            if !self.device.mount_points.is_empty() {
-                let file = OpenOptions::new()
-                    .write(true)
-                    .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                    .open(&self.device.mount_points[0])
-                    .map_err(|e| MosesError::IoError(e))?;
-                
-                file.sync_all()
-                    .map_err(|e| MosesError::IoError(e))?;
-            }
+            let file = OpenOptions::new()
+                .write(true)
+                .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+                .open(crate::utils::get_device_path(&self.device))
+                .map_err(|e| MosesError::IoError(e))?;
+            
+            file.sync_all()
+                .map_err(|e| MosesError::IoError(e))?;
         }
         
         #[cfg(not(target_os = "windows"))]
         {
             use std::fs::OpenOptions;
             
-            let device_path = format!("/dev/{}", self.device.id);
+            let device_path = crate::utils::get_device_path(&self.device);
             let file = OpenOptions::new()
                 .write(true)
                 .open(&device_path)
@@ -1076,6 +1141,15 @@ impl Ext4Writer {
         Ok(())
     }
     
+    /// Keep the MMP claim alive during long write sessions; fails if another machine
+    /// has mounted the filesystem since it was claimed
+    fn refresh_mmp(&mut self) -> Result<(), MosesError> {
+        match &mut self.mmp {
+            Some(mmp) => mmp.refresh_if_due(),
+            None => Ok(()),
+        }
+    }
+    
     /// Checkpoint the journal to ensure all transactions are persisted
     pub fn checkpoint_journal(&mut self) -> Result<(), MosesError> {
         // The transaction manager handles checkpointing internally
@@ -1137,8 +1211,10 @@ impl Ext4Writer {
         Ok(())
     }
     
-    /// Write raw data to disk at specific offset
+    /// Write raw data to disk at specific offset and wait for it to reach stable media
     fn write_raw_to_disk(&mut self, offset: u64, data: &[u8]) -> Result<(), MosesError> {
+        self.refresh_mmp()?;
+        
         #[cfg(target_os = "windows")]
         {
             use std::os::windows::fs::OpenOptionsExt;
@@ -1146,18 +1222,17 @@ impl Ext4Writer {
             use std::io::{Write, Seek, SeekFrom};
             use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
             
-            if !self.device.mount_points.is_empty() {
-                let mut file = OpenOptions::new()
-                    .write(true)
-                    .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
-                    .open(&self.device.mount_points[0])
-                    .map_err(|e| MosesError::IoError(e))?;
-                
-                file.seek(SeekFrom::Start(offset))
-                    .map_err(|e| MosesError::IoError(e))?;
-                file.write_all(data)
-                    .map_err(|e| MosesError::IoError(e))?;
-            }
+            let mut file = OpenOptions::new()
+                .write(true)
+                .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+                .open(crate::utils::get_device_path(&self.device))
+                .map_err(|e| MosesError::IoError(e))?;
+            
+            file.seek(SeekFrom::Start(offset))
+                .map_err(|e| MosesError::IoError(e))?;
+            file.write_all(data)
+                .map_err(|e| MosesError::IoError(e))?;
+            crate::utils::flush_device(&file)?;
         }
         
         #[cfg(not(target_os = "windows"))]
@@ -1165,7 +1240,7 @@ impl Ext4Writer {
             use std::fs::OpenOptions;
             use std::io::{Write, Seek, SeekFrom};
             
-            let device_path = format!("/dev/{}", self.device.id);
+            let device_path = crate::utils::get_device_path(&self.device);
             let mut file = OpenOptions::new()
                 .write(true)
                 .open(&device_path)
@@ -1175,6 +1250,7 @@ impl Ext4Writer {
                 .map_err(|e| MosesError::IoError(e))?;
             file.write_all(data)
                 .map_err(|e| MosesError::IoError(e))?;
+            crate::utils::flush_device(&file)?;
         }
         
         Ok(())
@@ -1182,15 +1258,16 @@ impl Ext4Writer {
     
     // ... Additional helper methods would go here ...
     
-    /// Placeholder for reading superblock
-    fn read_superblock(_device: &Device) -> Result<Ext4Superblock, MosesError> {
-        // Would read from device at offset 1024
-        Ok(Ext4Superblock::new())
+    /// Read the primary superblock, refusing filesystems this writer would damage
+    fn read_superblock(device: &Device) -> Result<Ext4Superblock, MosesError> {
+        let superblock = load_superblock(&mut crate::utils::open_device_read(device)?)?;
+        check_write_support(&superblock)?;
+        Ok(superblock)
     }
     
-    /// Placeholder for reading group descriptors
-    fn read_group_descriptors(__device: &Device, _sb: &Ext4Superblock) -> Result<Vec<Ext4GroupDesc>, MosesError> {
-        Ok(Vec::new())
+    /// Read the group descriptor table
+    fn read_group_descriptors(device: &Device, sb: &Ext4Superblock) -> Result<Vec<Ext4GroupDesc>, MosesError> {
+        load_group_descriptors(&mut crate::utils::open_device_read(device)?, sb)
     }
     
     // Many more helper methods would be implemented here...
@@ -0,0 +1,179 @@
+// Flash-friendly ext4 tuning for the "flash" format preset
+// SD/eMMC cards and USB sticks have weak wear levelling: the journal rewrites the same few
+// megabytes on every commit, and allocations that straddle erase blocks force
+// read-modify-erase cycles. The preset drops the journal (or keeps it with asynchronous
+// commits), aligns allocation to the erase block through the RAID stride hints, and turns
+// on discard by default.
+
+use moses_core::{Device, FormatOptions, FormatPreset, MosesError};
+use super::ext4_native::core::structures::Ext4Superblock;
+
+/// s_default_mount_opts bit: mount with `discard`
+const EXT4_DEFM_DISCARD: u32 = 0x0400;
+
+/// Journal choice for the flash preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashJournal {
+    /// No journal at all; fewest writes, but a crash needs a full fsck
+    None,
+    /// Keep the journal, mounted with journal_async_commit to skip the commit-block flush.
+    /// The kernel only allows that with data=writeback, so that becomes the default too.
+    Async,
+}
+
+impl FlashJournal {
+    /// Key in `FormatOptions::additional_options` choosing the journal mode (none, async)
+    pub const OPTION_KEY: &'static str = "flash_journal";
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().replace('-', "_").as_str() {
+            "none" | "off" => Some(Self::None),
+            "async" | "journal_async" | "journal_async_commit" => Some(Self::Async),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Async => "async",
+        }
+    }
+}
+
+/// ext4 settings the flash preset applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashTuning {
+    pub journal: FlashJournal,
+    /// Blocks per erase block, 0 when the erase block size is unknown
+    pub stride: u32,
+    /// Blocks per full stripe; the erase block again, since there is a single device
+    pub stripe_width: u32,
+}
+
+impl FlashTuning {
+    /// Tuning for `device` when the flash preset is selected, None otherwise
+    pub fn from_options(device: &Device, options: &FormatOptions, block_size: u32) -> Result<Option<Self>, MosesError> {
+        if FormatPreset::from_options(options)? != FormatPreset::Flash {
+            return Ok(None);
+        }
+        let journal = match options.additional_options.get(FlashJournal::OPTION_KEY) {
+            None => FlashJournal::None,
+            Some(value) => FlashJournal::parse(value).ok_or_else(|| {
+                MosesError::InvalidInput(format!("Unknown flash journal mode '{}' (expected none or async)", value))
+            })?,
+        };
+        let stride = Self::stride_for(device.erase_block_size, block_size);
+        Ok(Some(Self { journal, stride, stripe_width: stride }))
+    }
+
+    /// Erase block in filesystem blocks; 0 if unknown, smaller than a block or not a multiple of one
+    fn stride_for(erase_block_size: Option<u64>, block_size: u32) -> u32 {
+        match erase_block_size {
+            Some(size) if block_size > 0 && size >= block_size as u64 && size % block_size as u64 == 0 => {
+                // s_raid_stride is 16 bits
+                (size / block_size as u64).min(u16::MAX as u64) as u32
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write the stride hints and discard default into a superblock being formatted. The
+    /// native formatter writes no journal, so the journal mode has nothing to set there.
+    pub fn apply(&self, sb: &mut Ext4Superblock) {
+        sb.s_raid_stride = self.stride as u16;
+        sb.s_raid_stripe_width = self.stripe_width;
+        sb.s_default_mount_opts |= EXT4_DEFM_DISCARD;
+    }
+
+    /// Extra mkfs.ext4 arguments
+    pub fn mkfs_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.journal == FlashJournal::None {
+            args.extend(["-O".to_string(), "^has_journal".to_string()]);
+        }
+        if self.stride > 0 {
+            args.extend(["-E".to_string(), format!("stride={},stripe_width={}", self.stride, self.stripe_width)]);
+        }
+        args
+    }
+
+    /// tune2fs arguments setting the default mount options mkfs.ext4 cannot
+    pub fn tune2fs_args(&self) -> Vec<String> {
+        match self.journal {
+            FlashJournal::None => vec!["-o".to_string(), "discard".to_string()],
+            FlashJournal::Async => vec![
+                "-o".to_string(), "discard,journal_data_writeback".to_string(),
+                "-E".to_string(), "mount_opts=journal_async_commit".to_string(),
+            ],
+        }
+    }
+
+    /// One line for the simulation report
+    pub fn describe(&self) -> String {
+        let journal = match self.journal {
+            FlashJournal::None => "no journal",
+            FlashJournal::Async => "journal with asynchronous commits",
+        };
+        if self.stride > 0 {
+            format!("Flash preset: {}, discard, allocation aligned to {} blocks (the erase block)", journal, self.stride)
+        } else {
+            format!("Flash preset: {}, discard; erase block size unknown, so allocation is not aligned to it", journal)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::DeviceType;
+
+    fn card(erase_block_size: Option<u64>) -> Device {
+        Device {
+            id: "/dev/mmcblk0".to_string(),
+            name: "card".to_string(),
+            size: 32 * 1024 * 1024 * 1024,
+            device_type: DeviceType::SDCard,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size,
+            partition_offset: None,
+        }
+    }
+
+    fn flash_options(journal: Option<&str>) -> FormatOptions {
+        let mut options = FormatOptions { filesystem_type: "ext4".to_string(), ..Default::default() };
+        options.additional_options.insert(FormatPreset::OPTION_KEY.to_string(), "flash".to_string());
+        if let Some(journal) = journal {
+            options.additional_options.insert(FlashJournal::OPTION_KEY.to_string(), journal.to_string());
+        }
+        options
+    }
+
+    #[test]
+    fn test_tuning_from_erase_block() {
+        let tuning = FlashTuning::from_options(&card(Some(4 * 1024 * 1024)), &flash_options(None), 4096).unwrap().unwrap();
+        assert_eq!(tuning, FlashTuning { journal: FlashJournal::None, stride: 1024, stripe_width: 1024 });
+        assert_eq!(tuning.mkfs_args(), ["-O", "^has_journal", "-E", "stride=1024,stripe_width=1024"]);
+
+        let unknown = FlashTuning::from_options(&card(None), &flash_options(Some("async")), 4096).unwrap().unwrap();
+        assert_eq!((unknown.journal, unknown.stride), (FlashJournal::Async, 0));
+        assert!(unknown.mkfs_args().is_empty());
+        assert!(unknown.tune2fs_args().contains(&"mount_opts=journal_async_commit".to_string()));
+
+        assert!(FlashTuning::from_options(&card(None), &FormatOptions::default(), 4096).unwrap().is_none());
+        assert!(FlashTuning::from_options(&card(None), &flash_options(Some("sometimes")), 4096).is_err());
+    }
+
+    #[test]
+    fn test_apply_to_superblock() {
+        let mut sb = Ext4Superblock::new();
+        FlashTuning { journal: FlashJournal::None, stride: 1024, stripe_width: 1024 }.apply(&mut sb);
+        assert_eq!((sb.s_raid_stride, sb.s_raid_stripe_width), (1024, 1024));
+        assert_ne!(sb.s_default_mount_opts & EXT4_DEFM_DISCARD, 0);
+    }
+}
@@ -1,5 +1,13 @@
 // pub mod common; // TODO: Add common ext family code
+pub mod attrib;
 pub mod ext4_native;
+pub mod flash;
+pub mod rescue;
+pub mod system_formatter;
+pub mod tune;
+
+pub use flash::{FlashJournal, FlashTuning};
+pub use system_formatter::Ext4SystemFormatter;
 
 // Unified ext2/ext3/ext4 formatter that reuses ext4_native implementation
 use moses_core::{Device, FormatOptions, MosesError, FilesystemFormatter, SimulationReport, Platform};
@@ -51,10 +59,12 @@ impl FilesystemFormatter for Ext2Formatter {
     }
     
     fn can_format(&self, device: &Device) -> bool {
-        !device.is_system && device.mount_points.is_empty()
+        !device.is_system && !device.is_write_protected && device.mount_points.is_empty()
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         Ok(SimulationReport {
             device: device.clone(),
             options: options.clone(),
@@ -67,6 +77,12 @@ impl FilesystemFormatter for Ext2Formatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: (device.size as f64 * 0.95) as u64, // ~95% usable
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
@@ -100,10 +116,12 @@ impl FilesystemFormatter for Ext3Formatter {
     }
     
     fn can_format(&self, device: &Device) -> bool {
-        !device.is_system && device.mount_points.is_empty()
+        !device.is_system && !device.is_write_protected && device.mount_points.is_empty()
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         Ok(SimulationReport {
             device: device.clone(),
             options: options.clone(),
@@ -112,6 +130,12 @@ impl FilesystemFormatter for Ext3Formatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: (device.size as f64 * 0.92) as u64, // ~92% usable (journal takes space)
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
@@ -159,7 +183,9 @@ async fn format_ext2_impl(
     // Create ext2 builder
     let builder = ExtFilesystemBuilder::ext2(device.size)
         .block_size(options.cluster_size.unwrap_or(4096) as u32)
-        .label(options.label.clone().unwrap_or_default());
+        .label(options.label.clone().unwrap_or_default())
+        .seed(&crate::deterministic::FormatSeed::from_options(options)?)
+        .root_owner(crate::disk_manager::seeding::ext_root_owner(options)?);
     
     // Use the generic formatter with ext2 parameters
     format_device_ext_version(device, options, builder, Arc::new(LoggingProgress)).await
@@ -182,7 +208,9 @@ async fn format_ext3_impl(
     // Create ext3 builder
     let builder = ExtFilesystemBuilder::ext3(device.size)
         .block_size(options.cluster_size.unwrap_or(4096) as u32)
-        .label(options.label.clone().unwrap_or_default());
+        .label(options.label.clone().unwrap_or_default())
+        .seed(&crate::deterministic::FormatSeed::from_options(options)?)
+        .root_owner(crate::disk_manager::seeding::ext_root_owner(options)?);
     
     // Use the generic formatter with ext3 parameters
     format_device_ext_version(device, options, builder, Arc::new(LoggingProgress)).await
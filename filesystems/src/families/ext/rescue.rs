@@ -0,0 +1,313 @@
+// Superblock rescue for the ext family
+// mkfs keeps copies of the superblock and group descriptor table in group 1 and in groups
+// that are powers of 3, 5 and 7 (in every group without sparse_super). When the primary
+// copy at byte 1024 is damaged the filesystem no longer opens, but a backup can be
+// written over it. Backups are found by probing where mkfs's default geometry puts them
+// (8 * block size blocks per group) for each block size. The bytes being replaced are
+// saved first so a rescue can be undone. A backup's free counts date from the last resize
+// or e2fsck, so the restored filesystem should still be checked with e2fsck -f.
+
+use moses_core::{Device, MosesError};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+
+use super::ext4_native::core::{
+    checksum::calculate_superblock_checksum,
+    constants::*,
+    structures::Ext4Superblock,
+    verify::{verify_ext_filesystem, VerificationResult},
+};
+
+pub(super) const PRIMARY_OFFSET: u64 = 1024;
+pub(super) const SUPERBLOCK_SIZE: usize = 1024;
+const BLOCK_SIZES: [u32; 4] = [1024, 2048, 4096, 65536];
+const BACKUP_HEADER: &str = "# moses ext superblock backup";
+
+/// A backup superblock found on a device
+#[derive(Debug, Clone)]
+pub struct BackupSuperblock {
+    /// Block group holding the copy
+    pub group: u32,
+    /// Byte offset of the copy
+    pub offset: u64,
+    pub block_size: u32,
+    pub superblock: Ext4Superblock,
+}
+
+impl BackupSuperblock {
+    fn group_count(&self) -> u64 {
+        let sb = &self.superblock;
+        let blocks = sb.s_blocks_count_lo as u64 | (sb.s_blocks_count_hi as u64) << 32;
+        (blocks - sb.s_first_data_block as u64).div_ceil(sb.s_blocks_per_group as u64)
+    }
+
+    /// Size of the group descriptor table, without blocks reserved for growth
+    fn gdt_len(&self) -> u64 {
+        let sb = &self.superblock;
+        let desc_size = if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 && sb.s_desc_size >= 64 {
+            sb.s_desc_size as u64
+        } else {
+            32
+        };
+        (self.group_count() * desc_size).div_ceil(self.block_size as u64) * self.block_size as u64
+    }
+
+    /// The backup table starts in the block after the backup superblock
+    fn gdt_offset(&self) -> u64 {
+        (self.offset / self.block_size as u64 + 1) * self.block_size as u64
+    }
+
+    /// Where the primary table starts
+    fn primary_gdt_offset(&self) -> u64 {
+        (self.superblock.s_first_data_block as u64 + 1) * self.block_size as u64
+    }
+
+    /// Last write time, Unix seconds
+    pub fn written(&self) -> u32 {
+        self.superblock.s_wtime
+    }
+
+    pub fn mount_count(&self) -> u16 {
+        self.superblock.s_mnt_count
+    }
+}
+
+/// Whether the primary superblock looks usable
+pub fn primary_is_valid<R: Read + Seek>(reader: &mut R) -> bool {
+    read_superblock(reader, PRIMARY_OFFSET).is_some_and(|sb| {
+        sb.s_magic == EXT4_SUPER_MAGIC && sb.s_log_block_size <= 6 && sb.s_blocks_per_group > 0 && sb.s_inodes_per_group > 0
+    })
+}
+
+/// Backup superblocks in a reader of `size` bytes, lowest group first
+pub fn find_backup_superblocks<R: Read + Seek>(reader: &mut R, size: u64) -> Vec<BackupSuperblock> {
+    let mut found = Vec::new();
+    for block_size in BLOCK_SIZES {
+        let blocks_per_group = 8 * block_size as u64;
+        let first_data_block = u64::from(block_size == 1024);
+        let group_bytes = blocks_per_group * block_size as u64;
+        for group in backup_groups(size / group_bytes) {
+            let offset = (group as u64 * blocks_per_group + first_data_block) * block_size as u64;
+            let Some(sb) = read_superblock(reader, offset) else { continue };
+            let matches = sb.s_magic == EXT4_SUPER_MAGIC
+                && sb.s_log_block_size <= 6
+                && 1024u32 << sb.s_log_block_size == block_size
+                && sb.s_blocks_per_group as u64 == blocks_per_group
+                // Revision 0 filesystems do not record the group
+                && (sb.s_rev_level == EXT4_GOOD_OLD_REV || sb.s_block_group_nr as u32 == group);
+            if matches {
+                found.push(BackupSuperblock { group, offset, block_size, superblock: sb });
+            }
+        }
+    }
+    found.sort_by_key(|backup| (backup.group, backup.block_size));
+    found
+}
+
+/// Group numbers below `groups` that hold backups with sparse_super: 1 and powers of 3, 5, 7
+pub(super) fn backup_groups(groups: u64) -> Vec<u32> {
+    let mut list = vec![1u64];
+    for base in [3u64, 5, 7] {
+        let mut power = base;
+        while power < groups {
+            list.push(power);
+            power *= base;
+        }
+    }
+    list.retain(|&group| group < groups);
+    list.sort_unstable();
+    list.dedup();
+    list.into_iter().map(|group| group as u32).collect()
+}
+
+fn read_superblock<R: Read + Seek>(reader: &mut R, offset: u64) -> Option<Ext4Superblock> {
+    let bytes = read_bytes(reader, offset, SUPERBLOCK_SIZE).ok()?;
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Ext4Superblock) })
+}
+
+pub(super) fn read_bytes<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)
+        .map_err(|e| MosesError::Other(format!("Failed to read {} bytes at 0x{:X}: {}", len, offset, e)))?;
+    Ok(buf)
+}
+
+/// Byte ranges a restore from `backup` rewrites, with their new contents
+pub fn plan_restore<R: Read + Seek>(reader: &mut R, backup: &BackupSuperblock) -> Result<Vec<(u64, Vec<u8>)>, MosesError> {
+    let mut sb = backup.superblock;
+    sb.s_block_group_nr = 0;
+    let mut sb_bytes = unsafe {
+        std::slice::from_raw_parts(&sb as *const Ext4Superblock as *const u8, SUPERBLOCK_SIZE)
+    }.to_vec();
+    if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0 {
+        let checksum = calculate_superblock_checksum(&sb_bytes, !0);
+        sb_bytes[0x3FC..0x400].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    let gdt = read_bytes(reader, backup.gdt_offset(), backup.gdt_len() as usize)?;
+    Ok(vec![(PRIMARY_OFFSET, sb_bytes), (backup.primary_gdt_offset(), gdt)])
+}
+
+/// Write `backup` over the primary superblock and group descriptors. The bytes replaced
+/// are written to `undo` first, in the format [`undo_restore`] reads.
+pub fn restore_superblock<F: Read + Write + Seek>(
+    file: &mut F,
+    backup: &BackupSuperblock,
+    undo: Option<&mut dyn Write>,
+    device_id: &str,
+) -> Result<(), MosesError> {
+    let writes = plan_restore(file, backup)?;
+    if let Some(out) = undo {
+        let mut text = format!("{} of {} (group {} restored)\n", BACKUP_HEADER, device_id, backup.group);
+        for (offset, data) in &writes {
+            let original = read_bytes(file, *offset, data.len())?;
+            text.push_str(&format!("{:#x} {}\n", offset, hex::encode(original)));
+        }
+        out.write_all(text.as_bytes())
+            .map_err(|e| MosesError::Other(format!("Failed to write backup: {}", e)))?;
+        out.flush()?;
+    }
+
+    for (offset, data) in &writes {
+        log::info!("Restoring {} bytes at 0x{:X} from group {}", data.len(), offset, backup.group);
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(data)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Put back what [`restore_superblock`] replaced; returns the number of ranges written
+pub fn undo_restore<F: Write + Seek, R: BufRead>(file: &mut F, saved: R) -> Result<usize, MosesError> {
+    let mut lines = saved.lines();
+    match lines.next() {
+        Some(Ok(header)) if header.starts_with(BACKUP_HEADER) => {}
+        _ => return Err(MosesError::InvalidInput("Not a moses ext superblock backup file".to_string())),
+    }
+    let mut ranges = Vec::new();
+    for line in lines {
+        let line = line?;
+        let Some((offset, bytes)) = line.split_once(' ') else { continue };
+        let offset = u64::from_str_radix(offset.trim_start_matches("0x"), 16)
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid offset '{}' in backup: {}", offset, e)))?;
+        let bytes = hex::decode(bytes.trim())
+            .map_err(|e| MosesError::InvalidInput(format!("Invalid bytes in backup at 0x{:X}: {}", offset, e)))?;
+        ranges.push((offset, bytes));
+    }
+    for (offset, bytes) in &ranges {
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(bytes)?;
+    }
+    file.flush()?;
+    Ok(ranges.len())
+}
+
+/// Backup superblocks on a device, and whether its primary superblock looks valid
+pub fn scan_device(device: &Device) -> Result<(bool, Vec<BackupSuperblock>), MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    let size = if device.size > 0 { device.size } else { reader.seek(SeekFrom::End(0))? };
+    Ok((primary_is_valid(&mut reader), find_backup_superblocks(&mut reader, size)))
+}
+
+/// Restore a backup on a device, saving the replaced bytes to `undo_path` first
+pub fn restore_device(device: &Device, backup: &BackupSuperblock, undo_path: Option<&std::path::Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to rewrite the superblock of a system disk".to_string()));
+    }
+    let mut undo = match undo_path {
+        Some(path) => Some(std::fs::File::create(path)
+            .map_err(|e| MosesError::Other(format!("Failed to create backup file {}: {}", path.display(), e)))?),
+        None => None,
+    };
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    restore_superblock(&mut file, backup, undo.as_mut().map(|f| f as &mut dyn Write), &device.id)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Undo a restore on a device from the saved bytes
+pub fn undo_device(device: &Device, saved: &std::path::Path) -> Result<usize, MosesError> {
+    let saved = std::fs::File::open(saved)
+        .map_err(|e| MosesError::Other(format!("Failed to open backup file {}: {}", saved.display(), e)))?;
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    let count = undo_restore(&mut file, std::io::BufReader::new(saved))?;
+    file.sync_all()?;
+    Ok(count)
+}
+
+/// Run the ext checker over a device, e.g. after a restore
+pub fn check_device(device: &Device) -> Result<VerificationResult, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    verify_ext_filesystem(&mut reader).map_err(|e| MosesError::Other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK: usize = 1024;
+    const GROUP_BLOCKS: usize = 8 * BLOCK;
+
+    /// 4-group filesystem with 1 KiB blocks and a backup in group 1 and 3
+    fn image() -> Vec<u8> {
+        let groups = 4;
+        let mut disk = vec![0u8; groups * GROUP_BLOCKS * BLOCK];
+        let mut sb = Ext4Superblock::new();
+        sb.s_magic = EXT4_SUPER_MAGIC;
+        sb.s_rev_level = EXT4_DYNAMIC_REV;
+        sb.s_blocks_count_lo = (groups * GROUP_BLOCKS) as u32;
+        sb.s_blocks_per_group = GROUP_BLOCKS as u32;
+        sb.s_inodes_per_group = 128;
+        sb.s_first_data_block = 1;
+        sb.s_wtime = 1_700_000_000;
+        for group in [1usize, 3] {
+            sb.s_block_group_nr = group as u16;
+            let block = group * GROUP_BLOCKS + 1;
+            let bytes = unsafe { std::slice::from_raw_parts(&sb as *const _ as *const u8, SUPERBLOCK_SIZE) };
+            disk[block * BLOCK..block * BLOCK + SUPERBLOCK_SIZE].copy_from_slice(bytes);
+            // Group descriptor table backup in the next block
+            disk[(block + 1) * BLOCK..(block + 2) * BLOCK].fill(0xD0 + group as u8);
+        }
+        // A primary that no longer has its magic
+        disk[BLOCK..2 * BLOCK].fill(0xFF);
+        disk
+    }
+
+    #[test]
+    fn test_finds_backups() {
+        let disk = image();
+        let size = disk.len() as u64;
+        let mut reader = Cursor::new(disk);
+        assert!(!primary_is_valid(&mut reader));
+
+        let backups = find_backup_superblocks(&mut reader, size);
+        assert_eq!(backups.iter().map(|b| b.group).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(backups[0].offset, (GROUP_BLOCKS as u64 + 1) * BLOCK as u64);
+        assert_eq!(backups[0].written(), 1_700_000_000);
+        assert_eq!(backup_groups(30), [1, 3, 5, 7, 9, 25, 27]);
+    }
+
+    #[test]
+    fn test_restore_and_undo() {
+        let disk = image();
+        let size = disk.len() as u64;
+        let mut file = Cursor::new(disk.clone());
+        let backup = find_backup_superblocks(&mut file, size).remove(0);
+
+        let mut undo = Vec::new();
+        restore_superblock(&mut file, &backup, Some(&mut undo), "test").unwrap();
+        assert!(primary_is_valid(&mut file));
+        let primary = read_superblock(&mut file, PRIMARY_OFFSET).unwrap();
+        assert_eq!(primary.s_block_group_nr, 0);
+        // The table came from group 1's copy
+        assert!(file.get_ref()[2 * BLOCK..3 * BLOCK].iter().all(|&b| b == 0xD1));
+
+        assert_eq!(undo_restore(&mut file, Cursor::new(undo)).unwrap(), 2);
+        assert_eq!(file.get_ref(), &disk);
+    }
+}
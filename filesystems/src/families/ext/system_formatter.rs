@@ -0,0 +1,147 @@
+// ext2/3/4 formatting through mkfs from e2fsprogs
+// Alternative to the native implementation on Linux, chosen through the registry's format
+// strategy. mkfs.ext4 picks its own defaults from /etc/mke2fs.conf; the flash preset
+// overrides the journal and stride and sets default mount options with tune2fs afterwards.
+
+use moses_core::{Device, FilesystemFormatter, FormatOptions, MosesError, Platform, SimulationReport};
+use super::flash::FlashTuning;
+use std::process::Command;
+use std::time::Duration;
+
+pub struct Ext4SystemFormatter;
+
+impl Ext4SystemFormatter {
+    const TOOL: &'static str = "mkfs.ext4";
+    const TUNE_TOOL: &'static str = "tune2fs";
+    /// mkfs.ext4's default block size for anything but tiny volumes
+    const BLOCK_SIZE: u32 = 4096;
+    
+    fn run(mut cmd: Command, tool: &str) -> Result<(), MosesError> {
+        let output = cmd.output()
+            .map_err(|e| MosesError::Other(format!("Failed to execute {}: {}", tool, e)))?;
+        
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("Permission denied") {
+                return Err(MosesError::InsufficientPrivileges(
+                    "Root privileges required. Try running with sudo".to_string()
+                ));
+            }
+            return Err(MosesError::FormatError(format!("{} failed: {}", tool, stderr)));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl FilesystemFormatter for Ext4SystemFormatter {
+    fn name(&self) -> &'static str {
+        "ext4-system"
+    }
+    
+    fn supported_platforms(&self) -> Vec<Platform> {
+        vec![Platform::Linux]
+    }
+    
+    fn can_format(&self, device: &Device) -> bool {
+        !device.is_system && !device.is_write_protected
+    }
+    
+    fn requires_external_tools(&self) -> bool {
+        true
+    }
+    
+    fn bundled_tools(&self) -> Vec<&'static str> {
+        vec!["e2fsprogs"]
+    }
+    
+    fn tools_available(&self) -> bool {
+        crate::tools::ToolManager::global().locate(Self::TOOL).is_some()
+    }
+    
+    async fn format(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
+        if !self.can_format(device) {
+            return Err(MosesError::UnsafeDevice(
+                "Cannot format this device - it is a system drive or write-protected".to_string()
+            ));
+        }
+        self.validate_options(options).await?;
+        let flash = FlashTuning::from_options(device, options, Self::BLOCK_SIZE)?;
+        
+        let tool = crate::tools::ToolManager::global().ensure("e2fsprogs", &[Self::TOOL])?;
+        let mut cmd = Command::new(&tool);
+        cmd.arg("-F"); // Don't ask for confirmation on whole disks
+        if let Some(ref label) = options.label {
+            cmd.arg("-L").arg(label);
+        }
+        if !options.quick_format {
+            cmd.arg("-c"); // Scan for bad blocks
+        }
+        if let Some(flash) = &flash {
+            cmd.arg("-b").arg(Self::BLOCK_SIZE.to_string());
+            cmd.args(flash.mkfs_args());
+        }
+        cmd.arg(&device.id);
+        Self::run(cmd, Self::TOOL)?;
+        
+        if let Some(flash) = &flash {
+            let tune = crate::tools::ToolManager::global().ensure("e2fsprogs", &[Self::TUNE_TOOL])?;
+            let mut cmd = Command::new(&tune);
+            cmd.args(flash.tune2fs_args()).arg(&device.id);
+            Self::run(cmd, Self::TUNE_TOOL)?;
+        }
+        
+        Ok(())
+    }
+    
+    async fn validate_options(&self, options: &FormatOptions) -> Result<(), MosesError> {
+        if let Some(ref label) = options.label {
+            if label.len() > 16 {
+                return Err(MosesError::InvalidInput(
+                    "ext4 labels are limited to 16 bytes".to_string()
+                ));
+            }
+        }
+        Ok(())
+    }
+    
+    async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
+        let mut warnings = vec![];
+        if !device.mount_points.is_empty() {
+            warnings.push(format!("Device is currently mounted at: {:?}", device.mount_points));
+        }
+        if !self.tools_available() {
+            warnings.push("mkfs.ext4 not found. Install the e2fsprogs package".to_string());
+        }
+        if !options.quick_format {
+            warnings.push("Full format selected - the device will be scanned for bad blocks".to_string());
+        }
+        let flash = FlashTuning::from_options(device, options, Self::BLOCK_SIZE)?;
+        if let Some(flash) = &flash {
+            warnings.push(flash.describe());
+        }
+        warnings.push("All data on this device will be permanently erased".to_string());
+        
+        Ok(SimulationReport {
+            device: device.clone(),
+            options: options.clone(),
+            estimated_time: Duration::from_secs(if options.quick_format { 10 } else { 10 + device.size / (50 * 1024 * 1024) }),
+            warnings,
+            required_tools: if flash.is_some() {
+                vec![Self::TOOL.to_string(), Self::TUNE_TOOL.to_string()]
+            } else {
+                vec![Self::TOOL.to_string()]
+            },
+            will_erase_data: true,
+            space_after_format: device.size * 95 / 100,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
+        })
+    }
+}
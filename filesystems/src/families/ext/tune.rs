@@ -0,0 +1,185 @@
+// In-place tuning of ext superblocks - the label and UUID edits of tune2fs -L / -U
+// The primary superblock and every backup copy get the same change, with the
+// metadata_csum checksum of each recomputed, as one MetadataPatch: a dry run prints the
+// bytes that would change, and a real run keeps an undo record and writes all copies or
+// none. Group descriptor and metadata checksums are seeded from the UUID unless the
+// csum_seed feature is set, so changing the UUID of such a filesystem would invalidate
+// every other checksum on it; that is refused instead of done halfway.
+
+use moses_core::{Device, MetadataPatch, MosesError};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::ext4_native::core::{
+    checksum::calculate_superblock_checksum,
+    constants::*,
+    structures::Ext4Superblock,
+};
+use super::rescue::{backup_groups, read_bytes, PRIMARY_OFFSET, SUPERBLOCK_SIZE};
+
+const UUID_OFFSET: usize = 0x68;
+const LABEL_OFFSET: usize = 0x78;
+const LABEL_LEN: usize = 16;
+const CHECKSUM_OFFSET: usize = 0x3FC;
+
+/// Fields to change; `None` leaves a field as it is
+#[derive(Debug, Clone, Default)]
+pub struct ExtTune {
+    pub label: Option<String>,
+    pub uuid: Option<[u8; 16]>,
+}
+
+impl ExtTune {
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(label) = &self.label {
+            parts.push(format!("set ext label to '{}'", label));
+        }
+        if let Some(uuid) = self.uuid {
+            parts.push(format!("set ext UUID to {}", uuid::Uuid::from_bytes(uuid)));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Parse a UUID for [`ExtTune::uuid`]; "random" makes a new one
+pub fn parse_uuid(text: &str) -> Result<[u8; 16], MosesError> {
+    if text.eq_ignore_ascii_case("random") {
+        return Ok(*uuid::Uuid::new_v4().as_bytes());
+    }
+    uuid::Uuid::parse_str(text)
+        .map(|uuid| *uuid.as_bytes())
+        .map_err(|e| MosesError::InvalidInput(format!("'{}' is not a UUID: {}", text, e)))
+}
+
+/// Byte offsets of the superblock copies: the primary, then each backup
+fn superblock_offsets(sb: &Ext4Superblock) -> Vec<(u32, u64)> {
+    let block_size = 1024u64 << sb.s_log_block_size;
+    let blocks = sb.s_blocks_count_lo as u64 | (sb.s_blocks_count_hi as u64) << 32;
+    let groups = (blocks - sb.s_first_data_block as u64).div_ceil(sb.s_blocks_per_group as u64);
+    let backups: Vec<u32> = if sb.s_feature_compat & EXT4_FEATURE_COMPAT_SPARSE_SUPER2 != 0 {
+        sb.s_backup_bgs.iter().copied().filter(|&group| group != 0 && (group as u64) < groups).collect()
+    } else if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER != 0 {
+        backup_groups(groups)
+    } else {
+        (1..groups as u32).collect()
+    };
+    let mut offsets = vec![(0, PRIMARY_OFFSET)];
+    offsets.extend(backups.into_iter().map(|group| {
+        (group, (group as u64 * sb.s_blocks_per_group as u64 + sb.s_first_data_block as u64) * block_size)
+    }));
+    offsets
+}
+
+/// The patch that applies `tune` to the ext filesystem in `reader`
+pub fn plan_tune<R: Read + Seek>(reader: &mut R, device_id: &str, tune: &ExtTune) -> Result<MetadataPatch, MosesError> {
+    let primary = read_bytes(reader, PRIMARY_OFFSET, SUPERBLOCK_SIZE)?;
+    let sb = unsafe { std::ptr::read_unaligned(primary.as_ptr() as *const Ext4Superblock) };
+    if sb.s_magic != EXT4_SUPER_MAGIC || sb.s_log_block_size > 6 || sb.s_blocks_per_group == 0 {
+        return Err(MosesError::InvalidInput(format!("{} has no ext2/3/4 superblock", device_id)));
+    }
+    if let Some(label) = &tune.label {
+        if label.len() > LABEL_LEN {
+            return Err(MosesError::InvalidInput(format!(
+                "ext labels are at most {} bytes; '{}' is {}", LABEL_LEN, label, label.len()
+            )));
+        }
+    }
+    let seeded = sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0;
+    let checksummed = sb.s_feature_ro_compat & (EXT4_FEATURE_RO_COMPAT_METADATA_CSUM | EXT4_FEATURE_RO_COMPAT_GDT_CSUM) != 0;
+    if tune.uuid.is_some_and(|uuid| uuid != sb.s_uuid) && checksummed && !seeded {
+        return Err(MosesError::InvalidInput(
+            "The metadata checksums of this filesystem are seeded from its UUID; changing it needs e2fsprogs (tune2fs -U) to rewrite them".to_string()
+        ));
+    }
+    let metadata_csum = sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0;
+
+    let mut patch = MetadataPatch::new(tune.describe(), device_id);
+    for (group, offset) in superblock_offsets(&sb) {
+        let before = if group == 0 { primary.clone() } else { read_bytes(reader, offset, SUPERBLOCK_SIZE)? };
+        let copy = unsafe { std::ptr::read_unaligned(before.as_ptr() as *const Ext4Superblock) };
+        if copy.s_magic != EXT4_SUPER_MAGIC {
+            log::warn!("No superblock copy in group {} at 0x{:X}; leaving it alone", group, offset);
+            continue;
+        }
+        let mut after = before.clone();
+        if let Some(label) = &tune.label {
+            let field = &mut after[LABEL_OFFSET..LABEL_OFFSET + LABEL_LEN];
+            field.fill(0);
+            field[..label.len()].copy_from_slice(label.as_bytes());
+        }
+        if let Some(uuid) = tune.uuid {
+            after[UUID_OFFSET..UUID_OFFSET + 16].copy_from_slice(&uuid);
+        }
+        if metadata_csum {
+            let checksum = calculate_superblock_checksum(&after, !0);
+            after[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+        }
+        let name = if group == 0 { "primary superblock".to_string() } else { format!("group {} superblock", group) };
+        for (field, start, end) in [("UUID", UUID_OFFSET, UUID_OFFSET + 16), ("volume name", LABEL_OFFSET, LABEL_OFFSET + LABEL_LEN), ("checksum", CHECKSUM_OFFSET, SUPERBLOCK_SIZE)] {
+            patch.set(format!("{}: {}", name, field), offset + start as u64, before[start..end].to_vec(), after[start..end].to_vec())?;
+        }
+    }
+    Ok(patch)
+}
+
+/// Patch for `tune` on a device, for a dry run or [`apply_device`]
+pub fn plan_device(device: &Device, tune: &ExtTune) -> Result<MetadataPatch, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+    plan_tune(&mut reader, &device.id, tune)
+}
+
+/// Write a patch to a device, saving its undo record to `undo` first
+pub fn apply_device(device: &Device, patch: &MetadataPatch, undo: Option<&Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to edit the superblock of a system disk".to_string()));
+    }
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    patch.apply(&mut file, undo)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::families::ext::ext4_native::core::verify::verify_ext_filesystem;
+    use moses_core::FormatOptions;
+
+    #[tokio::test]
+    async fn test_label_change_and_undo() {
+        // Eight groups of 4 KiB blocks, so there are backups in groups 1, 3, 5 and 7
+        let size = 1024 * 1024 * 1024;
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let device = crate::test_helpers::create_test_device(image.path().to_str().unwrap(), size);
+        let options = FormatOptions { filesystem_type: "ext4".to_string(), label: Some("OLD".to_string()), ..Default::default() };
+        moses_core::FilesystemFormatter::format(&crate::Ext4NativeFormatter, &device, &options).await.unwrap();
+        let mut disk = std::fs::File::options().read(true).write(true).open(image.path()).unwrap();
+        let superblocks = |disk: &mut std::fs::File| {
+            [PRIMARY_OFFSET, 32768 * 4096, 7 * 32768 * 4096].map(|offset| read_bytes(disk, offset, SUPERBLOCK_SIZE).unwrap())
+        };
+        let original = superblocks(&mut disk);
+
+        let tune = ExtTune { label: Some("BACKUP".to_string()), uuid: None };
+        let patch = plan_tune(&mut disk, "test", &tune).unwrap();
+        assert_eq!(patch.diff()[0], "primary superblock: volume name @ 0x478: 4f 4c 44 00 00 00 -> 42 41 43 4b 55 50");
+        assert!(patch.ranges.iter().any(|range| range.field.starts_with("group 7 superblock")), "backups change too");
+        assert!(plan_tune(&mut disk, "test", &ExtTune { label: Some("x".repeat(17)), uuid: None }).is_err());
+
+        let undo_dir = tempfile::tempdir().unwrap();
+        let undo = undo_dir.path().join("undo.json");
+        patch.apply(&mut disk, Some(&undo)).unwrap();
+        for copy in superblocks(&mut disk) {
+            assert_eq!(&copy[LABEL_OFFSET..LABEL_OFFSET + 7], b"BACKUP\0");
+        }
+        assert!(verify_ext_filesystem(&mut disk).unwrap().is_valid);
+        assert!(plan_tune(&mut disk, "test", &tune).unwrap().is_empty());
+
+        MetadataPatch::load(&undo).unwrap().apply(&mut disk, None).unwrap();
+        assert_eq!(superblocks(&mut disk), original);
+    }
+}
@@ -0,0 +1,227 @@
+// In-place attribute edits for FAT12/16/32 and exFAT - what attrib does
+// The read-only, hidden, system and archive bits live in the file's directory entry, so
+// changing them is a one-byte edit (plus the entry set checksum on exFAT) that needs none
+// of the cluster allocation a writer does. The entry is found by walking the directory
+// tree from the boot sector, and the edit is a MetadataPatch, so a dry run can show it
+// and a real run keeps an undo record like the other metadata editors.
+
+use moses_core::{MetadataPatch, MosesError};
+use std::io::{Read, Seek};
+use crate::families::fat::exfat::structures::{
+    calculate_entry_set_checksum, ExFatDirectoryEntry, EXFAT_ENTRY_FILE, EXFAT_ENTRY_FILE_NAME, EXFAT_ENTRY_STREAM,
+};
+use crate::ops::AttributeChanges;
+use super::directory::{attributes::*, lfn_checksum, parse_83_name};
+use super::volume::{u16_at, u32_at, Dir, Volume, ENTRY_SIZE};
+
+/// Stream extension flag: the data is contiguous and has no FAT chain
+const EXFAT_NO_FAT_CHAIN: u8 = 0x02;
+
+/// A directory entry found by [`Volume::find`]
+struct Entry {
+    /// Offset of the short entry (FAT) or the file entry (exFAT)
+    offset: u64,
+    /// The whole exFAT entry set
+    set: Vec<[u8; ENTRY_SIZE]>,
+    attributes: u16,
+    dir: Option<Dir>,
+}
+
+impl Volume {
+    /// The entry called `name` (case-insensitively, long or short name) in `dir`
+    fn find<R: Read + Seek>(&self, reader: &mut R, dir: Dir, name: &str) -> Result<Option<Entry>, MosesError> {
+        let slots = self.slots(reader, dir)?;
+        let wanted = name.to_lowercase();
+        if self.exfat {
+            return Ok(find_exfat(&slots, &wanted));
+        }
+        let mut long_name: Vec<(u8, u8, [u16; 13])> = Vec::new();
+        for (offset, slot) in slots {
+            match slot[0] {
+                0x00 => break,
+                0xE5 => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            if slot[11] & 0x3F == ATTR_LONG_NAME {
+                let mut chars = [0u16; 13];
+                for (i, at) in (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2)).enumerate() {
+                    chars[i] = u16_at(&slot, at);
+                }
+                long_name.push((slot[0] & 0x1F, slot[13], chars));
+                continue;
+            }
+            if slot[11] & ATTR_VOLUME_ID != 0 {
+                long_name.clear();
+                continue;
+            }
+            let short: [u8; 11] = slot[..11].try_into().unwrap();
+            let checksum = lfn_checksum(&short);
+            let long = (!long_name.is_empty() && long_name.iter().all(|&(_, sum, _)| sum == checksum)).then(|| {
+                long_name.sort_by_key(|&(sequence, _, _)| sequence);
+                let units: Vec<u16> = long_name.iter().flat_map(|(_, _, chars)| chars.iter().copied())
+                    .take_while(|&unit| unit != 0 && unit != 0xFFFF)
+                    .collect();
+                String::from_utf16_lossy(&units)
+            });
+            long_name.clear();
+            if long.is_some_and(|long| long.to_lowercase() == wanted) || parse_83_name(&short).to_lowercase() == wanted {
+                let cluster = (u16_at(&slot, 20) as u32) << 16 | u16_at(&slot, 26) as u32;
+                let is_dir = slot[11] & ATTR_DIRECTORY != 0;
+                return Ok(Some(Entry {
+                    offset,
+                    set: vec![slot],
+                    attributes: slot[11] as u16,
+                    dir: is_dir.then_some(if cluster == 0 { Dir::FixedRoot } else { Dir::Chain(cluster) }),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn find_exfat(slots: &[(u64, [u8; ENTRY_SIZE])], wanted: &str) -> Option<Entry> {
+    let mut index = 0;
+    while index < slots.len() {
+        let (offset, slot) = slots[index];
+        match slot[0] {
+            0x00 => break,
+            EXFAT_ENTRY_FILE => {}
+            _ => {
+                index += 1;
+                continue;
+            }
+        }
+        let count = slot[1] as usize;
+        let set: Vec<[u8; ENTRY_SIZE]> = slots.iter().skip(index).take(count + 1).map(|(_, slot)| *slot).collect();
+        index += count + 1;
+        if set.len() != count + 1 || count < 2 || set[1][0] != EXFAT_ENTRY_STREAM {
+            continue;
+        }
+        let stream = set[1];
+        let units: Vec<u16> = set[2..].iter().filter(|entry| entry[0] == EXFAT_ENTRY_FILE_NAME)
+            .flat_map(|entry| (2..32).step_by(2).map(|at| u16_at(entry, at)))
+            .take(stream[3] as usize)
+            .collect();
+        if String::from_utf16_lossy(&units).to_lowercase() != wanted {
+            continue;
+        }
+        let attributes = u16_at(&slot, 4);
+        let first = u32_at(&stream, 20);
+        let length = u64::from_le_bytes(stream[24..32].try_into().unwrap());
+        return Some(Entry {
+            offset,
+            attributes,
+            dir: (attributes & ATTR_DIRECTORY as u16 != 0).then(|| {
+                if stream[1] & EXFAT_NO_FAT_CHAIN != 0 { Dir::Contiguous(first, length) } else { Dir::Chain(first) }
+            }),
+            set,
+        });
+    }
+    None
+}
+
+/// The patch that applies the DOS attribute part of `changes` to `path` on the FAT or
+/// exFAT volume in `reader`
+pub fn plan_attributes<R: Read + Seek>(
+    reader: &mut R,
+    device_id: &str,
+    path: &str,
+    changes: &AttributeChanges,
+) -> Result<MetadataPatch, MosesError> {
+    let volume = Volume::read(reader)?;
+    let (set, clear) = changes.dos_bits(volume.name())?;
+    let components: Vec<&str> = path.split(['/', '\\']).filter(|part| !part.is_empty()).collect();
+    let Some((last, parents)) = components.split_last() else {
+        return Err(MosesError::InvalidInput(format!("The {} root directory has no attributes", volume.name())));
+    };
+
+    let mut dir = volume.root;
+    for part in parents {
+        dir = volume.find(reader, dir, part)?
+            .ok_or_else(|| MosesError::InvalidInput(format!("{} not found on {}", path, device_id)))?
+            .dir
+            .ok_or_else(|| MosesError::InvalidInput(format!("{} in {} is not a directory", part, path)))?;
+    }
+    let entry = volume.find(reader, dir, last)?
+        .ok_or_else(|| MosesError::InvalidInput(format!("{} not found on {}", path, device_id)))?;
+    let attributes = (entry.attributes | set as u16) & !(clear as u16);
+
+    let mut patch = MetadataPatch::new(format!("{} of {}", changes.describe(), path), device_id);
+    if volume.exfat {
+        let mut set = entry.set.clone();
+        set[0][4..6].copy_from_slice(&attributes.to_le_bytes());
+        let entries: Vec<ExFatDirectoryEntry> = set.iter().map(|slot| ExFatDirectoryEntry::from_bytes(*slot)).collect();
+        set[0][2..4].copy_from_slice(&calculate_entry_set_checksum(&entries).to_le_bytes());
+        patch.set(format!("{}: set checksum and attributes", path), entry.offset + 2, entry.set[0][2..6].to_vec(), set[0][2..6].to_vec())?;
+    } else {
+        patch.set(format!("{}: attributes", path), entry.offset + 11, vec![entry.attributes as u8], vec![attributes as u8])?;
+    }
+    Ok(patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::FormatOptions;
+    use std::io::SeekFrom;
+
+    #[tokio::test]
+    async fn test_fat_and_exfat_attributes() {
+        let hidden = AttributeChanges { set_dos: crate::ops::dos_attributes::HIDDEN, mode: Some(0o444), ..Default::default() };
+        let dir = tempfile::tempdir().unwrap();
+
+        // The exFAT formatter leaves a README in the root directory
+        let path = dir.path().join("exfat.img");
+        let options = FormatOptions { filesystem_type: "exfat".to_string(), ..Default::default() };
+        crate::image_target::format_image(&crate::ExFatFormatter, &path, 64 * 1024 * 1024, &options).await.unwrap();
+        let mut file = std::fs::File::options().read(true).write(true).open(&path).unwrap();
+        let name = crate::ExFatReader::new(crate::test_helpers::create_test_device(path.to_str().unwrap(), 0))
+            .and_then(|mut reader| reader.read_root()).unwrap()
+            .into_iter().find(|entry| !entry.is_directory).unwrap().name;
+        let patch = plan_attributes(&mut file, "exfat.img", &name.to_uppercase(), &hidden).unwrap();
+        assert_eq!(patch.ranges.len(), 1);
+        patch.apply(&mut file, None).unwrap();
+        let entry = crate::ExFatReader::new(crate::test_helpers::create_test_device(path.to_str().unwrap(), 0))
+            .and_then(|mut reader| reader.read_root()).unwrap()
+            .into_iter().find(|entry| entry.name == name).unwrap();
+        assert!(entry.metadata.hidden && entry.metadata.readonly);
+        assert!(plan_attributes(&mut file, "exfat.img", &name, &hidden).unwrap().is_empty(), "already set");
+        assert!(plan_attributes(&mut file, "exfat.img", "/", &hidden).is_err());
+
+        // A FAT16 root entry with a long name, written by hand
+        let path = dir.path().join("fat16.img");
+        let options = FormatOptions { filesystem_type: "fat16".to_string(), ..Default::default() };
+        crate::image_target::format_image(&crate::Fat16Formatter, &path, 64 * 1024 * 1024, &options).await.unwrap();
+        let mut file = std::fs::File::options().read(true).write(true).open(&path).unwrap();
+        let volume = Volume::read(&mut file).unwrap();
+        assert_eq!(volume.fat_bits, 16);
+        let short = *b"NOTES~1 TXT";
+        let mut lfn = [0u8; ENTRY_SIZE];
+        lfn[0] = 0x41;
+        lfn[11] = ATTR_LONG_NAME;
+        lfn[13] = lfn_checksum(&short);
+        for (i, at) in (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2)).enumerate() {
+            let unit = "notes.txt".encode_utf16().chain([0]).chain(std::iter::repeat(0xFFFF)).nth(i).unwrap();
+            lfn[at..at + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[..11].copy_from_slice(&short);
+        entry[11] = ATTR_ARCHIVE;
+        let free = volume.slots(&mut file, Dir::FixedRoot).unwrap().into_iter().find(|(_, slot)| slot[0] == 0).unwrap().0;
+        let mut both = lfn.to_vec();
+        both.extend_from_slice(&entry);
+        file.seek(SeekFrom::Start(free)).unwrap();
+        std::io::Write::write_all(&mut file, &both).unwrap();
+
+        let clear = AttributeChanges { clear_dos: crate::ops::dos_attributes::ARCHIVE, ..hidden };
+        let patch = plan_attributes(&mut file, "fat16.img", "/Notes.TXT", &clear).unwrap();
+        assert_eq!(patch.ranges[0].offset, free + 32 + 11);
+        assert_eq!(patch.ranges[0].after, [ATTR_HIDDEN | ATTR_READ_ONLY]);
+        assert!(plan_attributes(&mut file, "fat16.img", "/missing.txt", &clear).is_err());
+        let owner = AttributeChanges { owner: Some(1000), ..Default::default() };
+        assert!(plan_attributes(&mut file, "fat16.img", "/notes.txt", &owner).is_err(), "FAT has no owners");
+    }
+}
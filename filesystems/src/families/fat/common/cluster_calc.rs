@@ -97,6 +97,12 @@ pub fn calculate_fat16_params(total_sectors: u64) -> Result<FatParams, MosesErro
 /// Calculate FAT32 parameters
 /// Ensures cluster count is >= 65525
 pub fn calculate_fat32_params(total_sectors: u64) -> Result<FatParams, MosesError> {
+    if total_sectors > u32::MAX as u64 {
+        return Err(MosesError::Other(format!(
+            "Volume too large for FAT32 ({} sectors, at most {})", total_sectors, u32::MAX
+        )));
+    }
+    
     // For FAT32, we need at least 65525 clusters
     // Start with smaller cluster sizes to maximize cluster count
     let mut sectors_per_cluster = if total_sectors <= 532_480 {
@@ -107,10 +113,8 @@ pub fn calculate_fat32_params(total_sectors: u64) -> Result<FatParams, MosesErro
         16  // 8KB clusters for <= 16GB
     } else if total_sectors <= 67_108_864 {
         32  // 16KB clusters for <= 32GB
-    } else if total_sectors <= 0xFFFFFFFF {
-        64  // 32KB clusters for <= 2TB
     } else {
-        128 // 64KB clusters for > 2TB
+        64  // 32KB clusters for <= 2TB
     };
     
     // FAT32 typically uses 32 reserved sectors
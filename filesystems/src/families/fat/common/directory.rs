@@ -2,6 +2,8 @@
 // Provides common functionality for FAT16, FAT32, and exFAT
 
 use moses_core::MosesError;
+use crate::device_reader::FileMetadata;
+use super::timestamps::fat_datetime_to_unix;
 
 /// Common trait for directory entries across FAT variants
 pub trait FatDirectoryEntry {
@@ -129,6 +131,32 @@ pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
     sum
 }
 
+/// Listing metadata for a FAT/exFAT entry: attribute flags, DOS timestamps (a zero
+/// date means unset) and the size rounded up to whole clusters
+pub fn entry_metadata(
+    attributes: u8,
+    created: (u16, u16),
+    modified: (u16, u16),
+    accessed_date: u16,
+    size: u64,
+    bytes_per_cluster: u32,
+) -> FileMetadata {
+    let timestamp = |(date, time): (u16, u16)| {
+        (date != 0).then(|| fat_datetime_to_unix(date, time)).filter(|&unix| unix != 0)
+    };
+    FileMetadata {
+        allocated_size: (bytes_per_cluster > 0)
+            .then(|| size.div_ceil(bytes_per_cluster as u64) * bytes_per_cluster as u64),
+        created: timestamp(created),
+        modified: timestamp(modified),
+        accessed: timestamp((accessed_date, 0)),
+        readonly: attributes & attributes::ATTR_READ_ONLY != 0,
+        hidden: attributes & attributes::ATTR_HIDDEN != 0,
+        system: attributes & attributes::ATTR_SYSTEM != 0,
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,4 +182,17 @@ mod tests {
         assert!(needs_lfn("very_long_filename.txt"));  // too long
         assert!(needs_lfn("file.jpeg"));  // extension too long
     }
+    
+    #[test]
+    fn test_entry_metadata() {
+        // 2024-03-15 12:30:00
+        let date = (44 << 9) | (3 << 5) | 15;
+        let time = (12 << 11) | (30 << 5);
+        let meta = entry_metadata(attributes::ATTR_HIDDEN | attributes::ATTR_READ_ONLY, (0, 0), (date, time), date, 5000, 4096);
+        assert!(meta.hidden && meta.readonly && !meta.system);
+        assert_eq!(meta.allocated_size, Some(8192));
+        assert_eq!(meta.modified, Some(1710505800));
+        assert_eq!(meta.created, None);
+        assert_eq!(meta.accessed, Some(1710460800));
+    }
 }
\ No newline at end of file
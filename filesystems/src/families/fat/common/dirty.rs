@@ -0,0 +1,370 @@
+// Dirty and unsafely removed FAT and exFAT volumes - Moses' "scan and fix"
+// A FAT driver marks a volume in use while it has it mounted and clears the mark on a
+// clean unmount, so a stick pulled out mid-write keeps it: FAT16 and FAT32 in the high
+// bits of FAT[1] (set means clean) and Windows NT in bit 0 of the boot sector's
+// reserved byte, exFAT in the VolumeDirty bit of VolumeFlags. FAT16/32 also record I/O
+// errors in FAT[1] and exFAT media failures in VolumeFlags. Writing to such a volume can
+// compound whatever the interrupted write left behind, so the ops refuse writes until
+// `moses fat check` finds the structures consistent and clears the marks.
+
+use moses_core::{Device, MetadataPatch, MosesError};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::families::fat::exfat::structures::{EXFAT_VOLUME_FLAG_DIRTY, EXFAT_VOLUME_FLAG_MEDIA_FAILURE};
+use super::volume::{read_at, u16_at, u32_at, Volume};
+
+/// FAT[1] bit that is set while the volume is cleanly unmounted
+const FAT16_CLEAN_SHUTDOWN: u32 = 0x8000;
+const FAT32_CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+/// FAT[1] bit that is cleared when the driver hit a disk I/O error
+const FAT16_NO_IO_ERRORS: u32 = 0x4000;
+const FAT32_NO_IO_ERRORS: u32 = 0x0400_0000;
+/// The boot sector byte whose bit 0 Windows NT sets while the volume is dirty
+const FAT16_NT_FLAGS_OFFSET: u64 = 37;
+const FAT32_NT_FLAGS_OFFSET: u64 = 65;
+const NT_FLAG_DIRTY: u8 = 0x01;
+const EXFAT_VOLUME_FLAGS_OFFSET: u64 = 106;
+/// Bytes of the FAT compared and scanned at a time
+const CHUNK: usize = 1 << 20;
+
+/// Signs that a FAT or exFAT volume was not cleanly unmounted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FatDirtyState {
+    /// "FAT12", "FAT16", "FAT32" or "exFAT"
+    pub filesystem: &'static str,
+    /// The clean bit of FAT[1] is clear, or the boot sector or VolumeFlags say dirty
+    pub dirty: bool,
+    /// FAT[1] records that the last driver hit disk I/O errors
+    pub io_errors: bool,
+    /// exFAT MediaFailure: the last driver found sectors it could not read or write
+    pub media_failure: bool,
+}
+
+impl FatDirtyState {
+    /// The state recorded in the boot sector and FAT[1] of the volume in `reader`
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, MosesError> {
+        let volume = Volume::read(reader)?;
+        let filesystem = filesystem_name(&volume);
+        if volume.exfat {
+            let flags = u16_at(&read_at(reader, EXFAT_VOLUME_FLAGS_OFFSET, 2)?, 0);
+            return Ok(Self {
+                filesystem,
+                dirty: flags & EXFAT_VOLUME_FLAG_DIRTY != 0,
+                io_errors: false,
+                media_failure: flags & EXFAT_VOLUME_FLAG_MEDIA_FAILURE != 0,
+            });
+        }
+        let nt_dirty = read_at(reader, nt_flags_offset(&volume), 1)?[0] & NT_FLAG_DIRTY != 0;
+        let (clean, no_errors) = match fat1_flags(reader, &volume)? {
+            Some((fat1, clean_bit, no_errors_bit)) => (fat1 & clean_bit != 0, fat1 & no_errors_bit != 0),
+            None => (true, true),
+        };
+        Ok(Self { filesystem, dirty: nt_dirty || !clean, io_errors: !no_errors, media_failure: false })
+    }
+
+    /// The state of the volume on `device`
+    pub fn read_device(device: &Device) -> Result<Self, MosesError> {
+        Self::read(&mut open_device(device)?)
+    }
+
+    /// Whether anything says the volume needs checking before it is written to
+    pub fn is_dirty(&self) -> bool {
+        self.dirty || self.io_errors || self.media_failure
+    }
+
+    /// One line per problem, for logs, the CLI and the UI
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.is_dirty() {
+            return warnings;
+        }
+        warnings.push("Filesystem is dirty, data may be stale".to_string());
+        if self.dirty {
+            warnings.push(format!(
+                "This {} volume was not cleanly unmounted (removed or powered off while in use); \
+                 the FAT and directories may disagree", self.filesystem,
+            ));
+        }
+        if self.io_errors {
+            warnings.push("The last driver to mount it hit disk I/O errors".to_string());
+        }
+        if self.media_failure {
+            warnings.push("exFAT recorded media failures; the device may have bad sectors".to_string());
+        }
+        warnings.push("Moses will not write to it until `moses fat check` finds it consistent and marks it clean".to_string());
+        warnings
+    }
+
+    /// Refuse a write unless the volume is clean or the caller overrides the check
+    pub fn ensure_writable(&self, force: bool) -> Result<(), MosesError> {
+        if self.is_dirty() && !force {
+            return Err(MosesError::InvalidInput(format!(
+                "The {} volume is marked dirty; run `moses fat check` before writing to it, or override with --force",
+                self.filesystem,
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn filesystem_name(volume: &Volume) -> &'static str {
+    match (volume.exfat, volume.fat_bits) {
+        (true, _) => "exFAT",
+        (false, 12) => "FAT12",
+        (false, 16) => "FAT16",
+        _ => "FAT32",
+    }
+}
+
+fn nt_flags_offset(volume: &Volume) -> u64 {
+    if volume.fat_bits == 32 { FAT32_NT_FLAGS_OFFSET } else { FAT16_NT_FLAGS_OFFSET }
+}
+
+/// FAT[1] of the first FAT with its clean and no-error bits; FAT12 has none
+fn fat1_flags<R: Read + Seek>(reader: &mut R, volume: &Volume) -> Result<Option<(u32, u32, u32)>, MosesError> {
+    Ok(match volume.fat_bits {
+        16 => Some((u16_at(&read_at(reader, volume.fat_offset + 2, 2)?, 0) as u32, FAT16_CLEAN_SHUTDOWN, FAT16_NO_IO_ERRORS)),
+        32 => Some((u32_at(&read_at(reader, volume.fat_offset + 4, 4)?, 0), FAT32_CLEAN_SHUTDOWN, FAT32_NO_IO_ERRORS)),
+        _ => None,
+    })
+}
+
+fn open_device(device: &Device) -> Result<crate::device_reader::AlignedDeviceReader, MosesError> {
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(crate::utils::open_device_with_fallback(device)?);
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(reader)
+}
+
+/// Look for the damage an interrupted write leaves: FAT copies that differ, a FAT[0]
+/// that does not match the media, chain entries pointing outside the volume, clusters
+/// claimed by two chains and a root directory that cannot be read. Returns the problems
+/// found, none when the volume is consistent enough to mark clean.
+pub fn check_volume<R: Read + Seek>(reader: &mut R) -> Result<Vec<String>, MosesError> {
+    let volume = Volume::read(reader)?;
+    let mut problems = Vec::new();
+    let bits = volume.fat_bits as u64;
+    let entries = volume.clusters as u64 + 2;
+    let fat_bytes = (entries * bits).div_ceil(8).min(volume.fat_size);
+    if entries * bits > volume.fat_size * 8 {
+        problems.push(format!("The FAT is too small for {} clusters", volume.clusters));
+    }
+
+    // Bad cluster markers and end-of-chain values are the top of each entry's range
+    let (bad, mask) = match (volume.exfat, volume.fat_bits) {
+        (true, _) => (0xFFFF_FFF7, u32::MAX),
+        (false, 12) => (0xFF7, 0xFFF),
+        (false, 16) => (0xFFF7, 0xFFFF),
+        _ => (0x0FFF_FFF7, 0x0FFF_FFFF),
+    };
+    // FAT12 entries straddle bytes, so its FAT (at most 6 KiB) is read whole
+    let chunk = if volume.fat_bits == 12 { fat_bytes as usize } else { CHUNK };
+    let mut referenced = vec![0u64; (entries as usize).div_ceil(64)];
+    let (mut out_of_range, mut cross_linked) = (0u64, 0u64);
+    let mut copies_differ = vec![false; volume.fats as usize];
+    let mut start = 0u64;
+    while start < fat_bytes {
+        let len = (fat_bytes - start).min(chunk as u64) as usize;
+        let fat = read_at(reader, volume.fat_offset + start, len)?;
+        // exFAT keeps a second FAT only for TexFAT, where the inactive one may differ
+        if !volume.exfat {
+            for copy in 1..volume.fats {
+                let offset = volume.fat_offset + copy as u64 * volume.fat_size + start;
+                if !copies_differ[copy as usize] && read_at(reader, offset, len)? != fat {
+                    copies_differ[copy as usize] = true;
+                }
+            }
+        }
+        let first = start * 8 / bits;
+        let last = ((start + len as u64) * 8 / bits).min(entries);
+        for cluster in first.max(2)..last {
+            let at = (cluster * bits / 8 - start) as usize;
+            let value = match volume.fat_bits {
+                12 => {
+                    let pair = u16_at(&fat, at) as u32;
+                    if cluster & 1 == 1 { pair >> 4 } else { pair & 0xFFF }
+                }
+                16 => u16_at(&fat, at) as u32,
+                _ => u32_at(&fat, at) & mask,
+            };
+            if value == 0 || value >= bad {
+                continue;
+            }
+            if value < 2 || value as u64 >= entries {
+                out_of_range += 1;
+                continue;
+            }
+            let (word, bit) = (value as usize / 64, value % 64);
+            if referenced[word] & 1 << bit != 0 {
+                cross_linked += 1;
+            }
+            referenced[word] |= 1 << bit;
+        }
+        if start == 0 {
+            let fat0 = if volume.fat_bits == 16 { u16_at(&fat, 0) as u32 } else { u32_at(&fat, 0) & mask };
+            let media = read_at(reader, 21, 1)?[0] as u32;
+            let expected = if volume.exfat { 0xFFFF_FFF8 } else { (mask & !0xFF) | media };
+            if fat0 != expected {
+                problems.push(format!("FAT[0] is 0x{:X}, not the media marker 0x{:X}", fat0, expected));
+            }
+        }
+        start += len as u64;
+    }
+
+    for (copy, differs) in copies_differ.iter().enumerate() {
+        if *differs {
+            problems.push(format!("FAT copy {} differs from the first FAT", copy + 1));
+        }
+    }
+    if out_of_range > 0 {
+        problems.push(format!("{} FAT entries point outside the volume", out_of_range));
+    }
+    if cross_linked > 0 {
+        problems.push(format!("{} clusters are claimed by more than one chain", cross_linked));
+    }
+    if let Err(e) = volume.slots(reader, volume.root) {
+        problems.push(format!("The root directory cannot be read: {}", e));
+    }
+    Ok(problems)
+}
+
+/// [`check_volume`] on `device`
+pub fn check_device(device: &Device) -> Result<Vec<String>, MosesError> {
+    check_volume(&mut open_device(device)?)
+}
+
+/// The patch that marks the volume in `reader` clean: the clean and no-error bits of
+/// FAT[1] in every FAT copy, the boot sector's dirty bit, or exFAT's VolumeDirty and
+/// MediaFailure. VolumeFlags is left out of the exFAT boot checksum, so that stays valid.
+pub fn plan_clean<R: Read + Seek>(reader: &mut R, device_id: &str) -> Result<MetadataPatch, MosesError> {
+    let volume = Volume::read(reader)?;
+    let mut patch = MetadataPatch::new(format!("Mark the {} volume clean", filesystem_name(&volume)), device_id);
+    if volume.exfat {
+        let before = read_at(reader, EXFAT_VOLUME_FLAGS_OFFSET, 2)?;
+        let flags = u16_at(&before, 0) & !(EXFAT_VOLUME_FLAG_DIRTY | EXFAT_VOLUME_FLAG_MEDIA_FAILURE);
+        patch.set("boot sector: VolumeFlags", EXFAT_VOLUME_FLAGS_OFFSET, before, flags.to_le_bytes().to_vec())?;
+        return Ok(patch);
+    }
+
+    let offset = nt_flags_offset(&volume);
+    let before = read_at(reader, offset, 1)?;
+    patch.set("boot sector: dirty flag", offset, before.clone(), vec![before[0] & !NT_FLAG_DIRTY])?;
+    for copy in 0..volume.fats as u64 {
+        let (at, len, flags) = match volume.fat_bits {
+            16 => (2, 2, FAT16_CLEAN_SHUTDOWN | FAT16_NO_IO_ERRORS),
+            32 => (4, 4, FAT32_CLEAN_SHUTDOWN | FAT32_NO_IO_ERRORS),
+            _ => break,
+        };
+        let offset = volume.fat_offset + copy * volume.fat_size + at;
+        let before = read_at(reader, offset, len)?;
+        let after = (u32_at(&[before.as_slice(), &[0, 0]].concat(), 0) | flags).to_le_bytes()[..len].to_vec();
+        patch.set(format!("FAT {}: FAT[1] clean shutdown bits", copy + 1), offset, before, after)?;
+    }
+    Ok(patch)
+}
+
+/// [`plan_clean`] on `device`
+pub fn plan_clean_device(device: &Device) -> Result<MetadataPatch, MosesError> {
+    plan_clean(&mut open_device(device)?, &device.id)
+}
+
+/// Write a patch to a device, saving its undo record to `undo` first
+pub fn apply_device(device: &Device, patch: &MetadataPatch, undo: Option<&Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to mark a system disk clean".to_string()));
+    }
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    patch.apply(&mut file, undo)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::FormatOptions;
+    use std::io::Write;
+
+    async fn image(dir: &Path, filesystem: &str, size: u64) -> std::fs::File {
+        let path = dir.join(format!("{}.img", filesystem));
+        let options = FormatOptions { filesystem_type: filesystem.to_string(), ..Default::default() };
+        match filesystem {
+            "exfat" => crate::image_target::format_image(&crate::ExFatFormatter, &path, size, &options).await,
+            "fat16" => crate::image_target::format_image(&crate::Fat16Formatter, &path, size, &options).await,
+            _ => crate::image_target::format_image(&crate::Fat32Formatter, &path, size, &options).await,
+        }.unwrap();
+        std::fs::File::options().read(true).write(true).open(&path).unwrap()
+    }
+
+    fn poke(file: &mut std::fs::File, offset: u64, bytes: &[u8]) {
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dirty_fat16_is_marked_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = image(dir.path(), "fat16", 64 * 1024 * 1024).await;
+        let state = FatDirtyState::read(&mut file).unwrap();
+        assert_eq!((state.filesystem, state.is_dirty()), ("FAT16", false));
+        assert!(state.warnings().is_empty() && state.ensure_writable(false).is_ok());
+        assert!(check_volume(&mut file).unwrap().is_empty());
+
+        // Pulled out mid-write: both marks say dirty, and the driver saw I/O errors
+        let volume = Volume::read(&mut file).unwrap();
+        poke(&mut file, volume.fat_offset + 2, &0x3FFFu16.to_le_bytes());
+        poke(&mut file, FAT16_NT_FLAGS_OFFSET, &[NT_FLAG_DIRTY]);
+        let state = FatDirtyState::read(&mut file).unwrap();
+        assert!(state.dirty && state.io_errors);
+        assert_eq!(state.warnings()[0], "Filesystem is dirty, data may be stale");
+        assert!(state.ensure_writable(false).is_err() && state.ensure_writable(true).is_ok());
+        assert_eq!(check_volume(&mut file).unwrap(), ["FAT copy 2 differs from the first FAT"]);
+
+        let patch = plan_clean(&mut file, "fat16.img").unwrap();
+        assert_eq!(patch.ranges.len(), 2, "the second FAT copy is already clean");
+        patch.apply(&mut file, None).unwrap();
+        assert!(!FatDirtyState::read(&mut file).unwrap().is_dirty());
+        assert!(check_volume(&mut file).unwrap().is_empty());
+        assert!(plan_clean(&mut file, "fat16.img").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fat32_chain_damage_is_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = image(dir.path(), "fat32", 300 * 1024 * 1024).await;
+        assert!(!FatDirtyState::read(&mut file).unwrap().is_dirty());
+        let problems = check_volume(&mut file).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        let volume = Volume::read(&mut file).unwrap();
+        poke(&mut file, volume.fat_offset + 4, &0x07FF_FFFFu32.to_le_bytes());
+        assert!(FatDirtyState::read(&mut file).unwrap().dirty);
+        // Clusters 10 and 11 both point at 12, and 13 points past the end
+        for copy in 0..volume.fats as u64 {
+            let fat = volume.fat_offset + copy * volume.fat_size;
+            poke(&mut file, fat + 10 * 4, &[12, 0, 0, 0, 12, 0, 0, 0]);
+            poke(&mut file, fat + 13 * 4, &(volume.clusters + 2).to_le_bytes());
+        }
+        let problems = check_volume(&mut file).unwrap();
+        assert!(problems.contains(&"1 FAT entries point outside the volume".to_string()), "{:?}", problems);
+        assert!(problems.contains(&"1 clusters are claimed by more than one chain".to_string()), "{:?}", problems);
+    }
+
+    #[tokio::test]
+    async fn test_exfat_volume_dirty() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = image(dir.path(), "exfat", 64 * 1024 * 1024).await;
+        let state = FatDirtyState::read(&mut file).unwrap();
+        assert_eq!((state.filesystem, state.is_dirty()), ("exFAT", false));
+        assert!(check_volume(&mut file).unwrap().is_empty());
+
+        poke(&mut file, EXFAT_VOLUME_FLAGS_OFFSET, &(EXFAT_VOLUME_FLAG_DIRTY | EXFAT_VOLUME_FLAG_MEDIA_FAILURE).to_le_bytes());
+        let state = FatDirtyState::read(&mut file).unwrap();
+        assert!(state.dirty && state.media_failure && !state.io_errors);
+        plan_clean(&mut file, "exfat.img").unwrap().apply(&mut file, None).unwrap();
+        assert!(!FatDirtyState::read(&mut file).unwrap().is_dirty());
+        assert!(crate::ExFatReader::new(crate::test_helpers::create_test_device(
+            dir.path().join("exfat.img").to_str().unwrap(), 0,
+        )).is_ok(), "the boot checksum leaves VolumeFlags out");
+    }
+}
@@ -0,0 +1,147 @@
+// DOS-compatible geometry for the FAT formatters
+// DOS, old BIOSes and some embedded boot loaders address the volume by cylinder, head and
+// sector, so they trust the geometry in the BPB and partition entry, and some check the
+// media byte or only boot volumes whose OEM string they know. By default the formatters
+// write the modern values (255 heads, 63 sectors per track, media F8, "MSWIN4.1") and put
+// the partition at 1 MB. With any of the options below they write the requested values
+// instead, start the partition on the second track as FDISK did, and can pad the reserved
+// area so the first cluster starts on a track boundary.
+
+use moses_core::{FormatOptions, MosesError};
+use super::sd_spec::align_up;
+
+/// "HEADS/SECTORS" (e.g. "16/63") or "auto" for the geometry an LBA-assist BIOS reports
+pub const GEOMETRY_OPTION: &str = "dos_geometry";
+/// Media descriptor byte in hex, e.g. "F8"
+pub const MEDIA_OPTION: &str = "media_descriptor";
+/// Up to 8 printable ASCII characters, e.g. "MSDOS5.0"
+pub const OEM_OPTION: &str = "oem_name";
+/// "true" starts the data area on a track boundary
+pub const TRACK_ALIGN_OPTION: &str = "align_to_track";
+
+const MIB: u64 = 1024 * 1024;
+
+/// Geometry, media byte and OEM string written to a FAT volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DosCompatibility {
+    pub heads: u16,
+    pub sectors_per_track: u16,
+    pub media_descriptor: u8,
+    pub oem_name: [u8; 8],
+    pub align_to_track: bool,
+    /// A geometry was asked for, so the partition goes on the second track
+    pub dos_partition: bool,
+}
+
+impl DosCompatibility {
+    /// The values written without any compatibility option
+    pub fn modern(media_descriptor: u8) -> Self {
+        Self {
+            heads: 255,
+            sectors_per_track: 63,
+            media_descriptor,
+            oem_name: *b"MSWIN4.1",
+            align_to_track: false,
+            dos_partition: false,
+        }
+    }
+
+    /// Whether any compatibility option is set
+    pub fn requested(options: &FormatOptions) -> bool {
+        [GEOMETRY_OPTION, MEDIA_OPTION, OEM_OPTION, TRACK_ALIGN_OPTION]
+            .iter()
+            .any(|key| options.additional_options.contains_key(*key))
+    }
+
+    /// The values for a format with `options` of a disk of `disk_size` bytes; unset options
+    /// keep the modern values, with `default_media` as the media byte
+    pub fn from_options(options: &FormatOptions, disk_size: u64, default_media: u8) -> Result<Self, MosesError> {
+        let mut compat = Self::modern(default_media);
+        let get = |key: &str| options.additional_options.get(key).map(|value| value.trim());
+        let invalid = |key: &str, value: &str, expected: &str| {
+            MosesError::InvalidInput(format!("{} '{}' is not valid: expected {}", key, value, expected))
+        };
+
+        if let Some(value) = get(GEOMETRY_OPTION) {
+            (compat.heads, compat.sectors_per_track) = if value.eq_ignore_ascii_case("auto") {
+                lba_assist_geometry(disk_size)
+            } else {
+                value.split_once('/')
+                    .and_then(|(heads, sectors)| Some((heads.trim().parse().ok()?, sectors.trim().parse().ok()?)))
+                    .filter(|&(heads, sectors): &(u16, u16)| (1..=255).contains(&heads) && (1..=63).contains(&sectors))
+                    .ok_or_else(|| invalid(GEOMETRY_OPTION, value, "auto or HEADS/SECTORS with 1-255 heads and 1-63 sectors"))?
+            };
+            compat.dos_partition = true;
+        }
+        if let Some(value) = get(MEDIA_OPTION) {
+            compat.media_descriptor = u8::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+                .filter(|&media| media == 0xF0 || media >= 0xF8)
+                .ok_or_else(|| invalid(MEDIA_OPTION, value, "F0 or F8-FF"))?;
+        }
+        if let Some(value) = get(OEM_OPTION) {
+            if value.is_empty() || value.len() > 8 || !value.bytes().all(|b| (0x20..=0x7E).contains(&b)) {
+                return Err(invalid(OEM_OPTION, value, "1-8 printable ASCII characters"));
+            }
+            compat.oem_name = *b"        ";
+            compat.oem_name[..value.len()].copy_from_slice(value.as_bytes());
+        }
+        if let Some(value) = get(TRACK_ALIGN_OPTION) {
+            compat.align_to_track = value.parse().map_err(|_| invalid(TRACK_ALIGN_OPTION, value, "true or false"))?;
+        }
+        Ok(compat)
+    }
+
+    /// First sector of the partition: the second track with a DOS geometry, else 1 MB
+    pub fn partition_start(&self) -> u32 {
+        if self.dos_partition { self.sectors_per_track as u32 } else { 2048 }
+    }
+
+    /// Extra reserved sectors so that a data area starting `first_data_sector` sectors into
+    /// the disk starts on a track instead
+    pub fn track_padding(&self, first_data_sector: u64) -> u64 {
+        if self.align_to_track {
+            align_up(first_data_sector, self.sectors_per_track as u64) - first_data_sector
+        } else {
+            0
+        }
+    }
+}
+
+/// Heads and sectors per track a BIOS with LBA-assist translation reports for a disk
+pub fn lba_assist_geometry(disk_size: u64) -> (u16, u16) {
+    let heads = [(504, 16), (1008, 32), (2016, 64), (4032, 128)].iter()
+        .find(|&&(limit, _)| disk_size <= limit * MIB)
+        .map_or(255, |&(_, heads)| heads);
+    (heads, 63)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn options(pairs: &[(&str, &str)]) -> FormatOptions {
+        FormatOptions {
+            additional_options: pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let none = options(&[]);
+        assert!(!DosCompatibility::requested(&none));
+        assert_eq!(DosCompatibility::from_options(&none, 0, 0xF8).unwrap(), DosCompatibility::modern(0xF8));
+
+        let dos = options(&[(GEOMETRY_OPTION, "auto"), (MEDIA_OPTION, "f0"), (OEM_OPTION, "IBM  3.3"), (TRACK_ALIGN_OPTION, "true")]);
+        let compat = DosCompatibility::from_options(&dos, 1000 * MIB, 0xF8).unwrap();
+        assert_eq!((compat.heads, compat.sectors_per_track, compat.media_descriptor), (32, 63, 0xF0));
+        assert_eq!(&compat.oem_name, b"IBM  3.3");
+        assert_eq!(compat.partition_start(), 63);
+        assert_eq!(compat.track_padding(63 + 1 + 2 * 200 + 32), 63 - 496 % 63);
+
+        for bad in [(GEOMETRY_OPTION, "256/63"), (GEOMETRY_OPTION, "16x63"), (MEDIA_OPTION, "F3"), (OEM_OPTION, "TOO LONG!"), (TRACK_ALIGN_OPTION, "yes")] {
+            assert!(DosCompatibility::from_options(&options(&[bad]), 0, 0xF8).is_err(), "{:?}", bad);
+        }
+    }
+}
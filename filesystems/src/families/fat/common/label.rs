@@ -0,0 +1,322 @@
+// Volume labels of FAT12/16/32 and exFAT - the boot sector field and the root entry
+// FAT keeps the label twice: in the boot sector (BS_VolLab) and as a root directory entry
+// with the volume ID attribute. Windows shows and changes the entry, while DOS-era tools,
+// cameras and some firmware read the boot sector, so a volume whose copies differ has a
+// different name depending on who looks. Readers prefer the entry, as Windows does, and
+// `moses fat label` writes both copies (and the FAT32 backup boot sector) as one
+// MetadataPatch, so a label is never left half-changed. exFAT has only the root entry.
+
+use moses_core::{Device, MetadataPatch, MosesError};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::families::fat::exfat::structures::EXFAT_ENTRY_VOLUME_LABEL;
+use super::directory::attributes::{ATTR_LONG_NAME, ATTR_VOLUME_ID};
+use super::dirty::filesystem_name;
+use super::timestamps::unix_to_fat_datetime;
+use super::volume::{read_at, u16_at, Volume, ENTRY_SIZE};
+
+/// What the boot sector holds when the volume has no label
+pub const NO_NAME: &[u8; 11] = b"NO NAME    ";
+/// BS_VolLab, present when the extended boot signature at the offset before it is 0x29
+const FAT16_LABEL_OFFSET: u64 = 43;
+const FAT32_LABEL_OFFSET: u64 = 71;
+const FAT16_BOOT_SIGNATURE_OFFSET: usize = 38;
+const FAT32_BOOT_SIGNATURE_OFFSET: usize = 66;
+const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+/// BPB_BkBootSec: the sector of the FAT32 backup boot sector
+const FAT32_BACKUP_BOOT_OFFSET: usize = 50;
+/// Characters Windows refuses in a FAT label
+const FAT_LABEL_FORBIDDEN: &str = "\"*+,./:;<=>?[\\]|";
+const EXFAT_LABEL_UNITS: usize = 11;
+
+/// Both copies of a volume's label and where they live
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeLabels {
+    /// "FAT12", "FAT16", "FAT32" or "exFAT"
+    pub filesystem: &'static str,
+    /// The boot sector label, trimmed; None on exFAT and on boot sectors without the field
+    pub boot_sector: Option<String>,
+    /// The root directory label entry, trimmed; None when there is no entry
+    pub directory: Option<String>,
+    /// Offsets of the boot sector label field: the primary, then the FAT32 backup
+    boot_offsets: Vec<u64>,
+    /// Offset of the label entry, or of the first free root slot when there is none
+    entry_offset: Option<u64>,
+    entry_exists: bool,
+}
+
+impl VolumeLabels {
+    /// The label to show: the directory entry when there is one, as Windows does, else
+    /// the boot sector. "NO NAME" and blanks mean no label.
+    pub fn effective(&self) -> Option<String> {
+        match &self.directory {
+            Some(label) => named(label),
+            None => self.boot_sector.as_deref().and_then(named),
+        }
+    }
+
+    /// A description of the copies disagreeing, None when they agree
+    pub fn mismatch(&self) -> Option<String> {
+        let boot = self.boot_sector.as_deref()?;
+        let directory = self.directory.as_deref().and_then(named);
+        if named(boot) == directory {
+            return None;
+        }
+        Some(format!(
+            "The boot sector label '{}' differs from the root directory label '{}'; Windows shows '{}' (run moses fat label to write one to both)",
+            boot, self.directory.as_deref().unwrap_or(""), directory.as_deref().unwrap_or(""),
+        ))
+    }
+}
+
+fn named(label: &str) -> Option<String> {
+    let label = label.trim();
+    (!label.is_empty() && label != "NO NAME").then(|| label.to_string())
+}
+
+/// Read both labels of the FAT or exFAT volume in `reader`
+pub fn read_labels<R: Read + Seek>(reader: &mut R) -> Result<VolumeLabels, MosesError> {
+    let volume = Volume::read(reader)?;
+    let mut labels = VolumeLabels { filesystem: filesystem_name(&volume), ..Default::default() };
+    if !volume.exfat {
+        let bs = read_at(reader, 0, 512)?;
+        let (signature, field) = if volume.fat_bits == 32 {
+            (FAT32_BOOT_SIGNATURE_OFFSET, FAT32_LABEL_OFFSET)
+        } else {
+            (FAT16_BOOT_SIGNATURE_OFFSET, FAT16_LABEL_OFFSET)
+        };
+        if bs[signature] == EXTENDED_BOOT_SIGNATURE {
+            labels.boot_sector = Some(String::from_utf8_lossy(&bs[field as usize..field as usize + 11]).trim_end().to_string());
+            labels.boot_offsets.push(field);
+            let backup = u16_at(&bs, FAT32_BACKUP_BOOT_OFFSET) as u64;
+            if volume.fat_bits == 32 && backup != 0 && backup != 0xFFFF {
+                let offset = backup * u16_at(&bs, 11) as u64;
+                let copy = read_at(reader, offset, 512)?;
+                if copy[510..512] == [0x55, 0xAA] && copy[signature] == EXTENDED_BOOT_SIGNATURE {
+                    labels.boot_offsets.push(offset + field);
+                }
+            }
+        }
+    }
+
+    for (offset, slot) in volume.slots(reader, volume.root)? {
+        let free = if volume.exfat { slot[0] < 0x80 } else { slot[0] == 0x00 || slot[0] == 0xE5 };
+        if free {
+            labels.entry_offset.get_or_insert(offset);
+            if slot[0] == 0x00 {
+                break;
+            }
+            continue;
+        }
+        let label = if volume.exfat {
+            (slot[0] == EXFAT_ENTRY_VOLUME_LABEL).then(|| {
+                let units: Vec<u16> = (0..(slot[1] as usize).min(EXFAT_LABEL_UNITS)).map(|i| u16_at(&slot, 2 + i * 2)).collect();
+                String::from_utf16_lossy(&units)
+            })
+        } else {
+            (slot[11] & 0x3F != ATTR_LONG_NAME && slot[11] & ATTR_VOLUME_ID != 0)
+                .then(|| String::from_utf8_lossy(&slot[..11]).trim_end().to_string())
+        };
+        if let Some(label) = label {
+            labels.directory = Some(label);
+            labels.entry_offset = Some(offset);
+            labels.entry_exists = true;
+            break;
+        }
+    }
+    Ok(labels)
+}
+
+/// The 11 boot sector bytes for a FAT label: upper case, space padded, and only
+/// characters Windows accepts
+pub fn fat_label_bytes(label: &str) -> Result<[u8; 11], MosesError> {
+    let upper = label.to_uppercase();
+    if upper.len() > 11 {
+        return Err(MosesError::InvalidInput(format!("FAT labels are at most 11 bytes; '{}' has {}", label, upper.len())));
+    }
+    if upper.starts_with(' ') {
+        return Err(MosesError::InvalidInput("FAT labels cannot start with a space".to_string()));
+    }
+    if let Some(c) = upper.chars().find(|&c| !c.is_ascii() || c.is_ascii_control() || FAT_LABEL_FORBIDDEN.contains(c)) {
+        return Err(MosesError::InvalidInput(format!("FAT labels cannot contain '{}'", c)));
+    }
+    let mut bytes = [b' '; 11];
+    bytes[..upper.len()].copy_from_slice(upper.as_bytes());
+    Ok(bytes)
+}
+
+/// A root directory entry holding the label `name`, stamped with `timestamp`
+pub fn fat_label_entry(name: &[u8; 11], timestamp: u64) -> [u8; ENTRY_SIZE] {
+    let (date, time) = unix_to_fat_datetime(timestamp);
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = ATTR_VOLUME_ID;
+    for (at, value) in [(14, time), (16, date), (18, date), (22, time), (24, date)] {
+        entry[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    entry
+}
+
+fn exfat_label_entry(label: &str) -> Result<[u8; ENTRY_SIZE], MosesError> {
+    let units: Vec<u16> = label.encode_utf16().collect();
+    if units.len() > EXFAT_LABEL_UNITS {
+        return Err(MosesError::InvalidInput(format!("exFAT labels are at most {} characters", EXFAT_LABEL_UNITS)));
+    }
+    if label.chars().any(char::is_control) {
+        return Err(MosesError::InvalidInput("exFAT labels cannot contain control characters".to_string()));
+    }
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[0] = EXFAT_ENTRY_VOLUME_LABEL;
+    entry[1] = units.len() as u8;
+    for (i, unit) in units.iter().enumerate() {
+        entry[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    Ok(entry)
+}
+
+/// The patch that sets the label of the FAT or exFAT volume in `reader` to `label`, or
+/// removes it when None, in the boot sector and the root directory together
+pub fn plan_label<R: Read + Seek>(reader: &mut R, device_id: &str, label: Option<&str>) -> Result<MetadataPatch, MosesError> {
+    let labels = read_labels(reader)?;
+    let label = label.map(str::trim).filter(|label| !label.is_empty());
+    let description = match label {
+        Some(label) => format!("set {} label to '{}'", labels.filesystem, label),
+        None => format!("remove the {} label", labels.filesystem),
+    };
+    let mut patch = MetadataPatch::new(description, device_id);
+    let exfat = labels.filesystem == "exFAT";
+
+    let name = match label {
+        Some(label) if !exfat => Some(fat_label_bytes(label)?),
+        _ => None,
+    };
+    for (copy, &offset) in labels.boot_offsets.iter().enumerate() {
+        let field = if copy == 0 { "boot sector: volume label" } else { "backup boot sector: volume label" };
+        patch.set_from(reader, field, offset, name.as_ref().unwrap_or(NO_NAME).to_vec())?;
+    }
+
+    let entry = match label {
+        Some(label) if exfat => Some(exfat_label_entry(label)?),
+        Some(_) => name.map(|name| fat_label_entry(&name, now())),
+        None => None,
+    };
+    let Some(offset) = labels.entry_offset else {
+        if entry.is_some() {
+            return Err(MosesError::InvalidInput(format!(
+                "The {} root directory is full; delete a file from it to make room for the label", labels.filesystem,
+            )));
+        }
+        return Ok(patch);
+    };
+    let before = read_at(reader, offset, ENTRY_SIZE)?;
+    match (entry, labels.entry_exists) {
+        // Only the name changes in an entry that is already there
+        (Some(entry), true) if !exfat => patch.set("root directory: volume label entry", offset, before[..11].to_vec(), entry[..11].to_vec())?,
+        (Some(entry), _) => patch.set("root directory: volume label entry", offset, before, entry.to_vec())?,
+        // Deleted entries: 0xE5 on FAT, the in-use bit cleared on exFAT
+        (None, true) => {
+            let deleted = if exfat { before[0] & 0x7F } else { 0xE5 };
+            patch.set("root directory: volume label entry", offset, vec![before[0]], vec![deleted])?
+        }
+        (None, false) => {}
+    }
+    Ok(patch)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn open_device(device: &Device) -> Result<crate::device_reader::AlignedDeviceReader, MosesError> {
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(crate::utils::open_device_with_fallback(device)?);
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(reader)
+}
+
+/// [`read_labels`] on `device`
+pub fn read_device(device: &Device) -> Result<VolumeLabels, MosesError> {
+    read_labels(&mut open_device(device)?)
+}
+
+/// [`plan_label`] on `device`
+pub fn plan_device(device: &Device, label: Option<&str>) -> Result<MetadataPatch, MosesError> {
+    plan_label(&mut open_device(device)?, &device.id, label)
+}
+
+/// Write a patch to a device, saving its undo record to `undo` first
+pub fn apply_device(device: &Device, patch: &MetadataPatch, undo: Option<&Path>) -> Result<(), MosesError> {
+    if device.is_system {
+        return Err(MosesError::InvalidInput("Refusing to relabel a system disk".to_string()));
+    }
+    let mut file = crate::disk_manager::SignatureWiper::open_for_write(device)?;
+    moses_core::FilesystemCache::global().invalidate(device);
+    patch.apply(&mut file, undo)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::FormatOptions;
+
+    async fn image(dir: &Path, filesystem: &str, label: Option<&str>) -> std::fs::File {
+        let path = dir.join(format!("{}-{}.img", filesystem, label.unwrap_or("unlabelled")));
+        let options = FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            label: label.map(str::to_string),
+            ..Default::default()
+        };
+        match filesystem {
+            "exfat" => crate::image_target::format_image(&crate::ExFatFormatter, &path, 64 << 20, &options).await,
+            "fat16" => crate::image_target::format_image(&crate::Fat16Formatter, &path, 64 << 20, &options).await,
+            _ => crate::image_target::format_image(&crate::Fat32Formatter, &path, 300 << 20, &options).await,
+        }.unwrap();
+        std::fs::File::options().read(true).write(true).open(&path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_labels_written_to_both_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        for filesystem in ["fat16", "fat32", "exfat"] {
+            // The formatters write the label to both places, and nothing when there is none
+            let mut file = image(dir.path(), filesystem, Some("Photos")).await;
+            let labels = read_labels(&mut file).unwrap();
+            assert_eq!(labels.effective().as_deref(), Some(if filesystem == "exfat" { "Photos" } else { "PHOTOS" }), "{}", filesystem);
+            assert_eq!(labels.mismatch(), None, "{}", filesystem);
+            if filesystem == "fat32" {
+                assert_eq!(labels.boot_offsets.len(), 2, "the backup boot sector carries it too");
+            }
+
+            let patch = plan_label(&mut file, filesystem, Some("Travel 24")).unwrap();
+            patch.apply(&mut file, None).unwrap();
+            let labels = read_labels(&mut file).unwrap();
+            assert_eq!(labels.effective().unwrap().to_uppercase(), "TRAVEL 24", "{}", filesystem);
+            assert_eq!(labels.mismatch(), None, "{}", filesystem);
+            assert!(plan_label(&mut file, filesystem, Some("Travel 24")).unwrap().is_empty());
+
+            plan_label(&mut file, filesystem, None).unwrap().apply(&mut file, None).unwrap();
+            let labels = read_labels(&mut file).unwrap();
+            assert_eq!((labels.effective(), labels.directory.as_deref()), (None, None), "{}", filesystem);
+            assert_eq!(labels.mismatch(), None, "{}", filesystem);
+
+            let mut unlabelled = image(dir.path(), filesystem, None).await;
+            let labels = read_labels(&mut unlabelled).unwrap();
+            assert_eq!((labels.effective(), labels.mismatch()), (None, None), "{}", filesystem);
+        }
+
+        // A label only the boot sector carries is a mismatch, and the entry wins
+        let mut file = image(dir.path(), "fat16", Some("OLD")).await;
+        let labels = read_labels(&mut file).unwrap();
+        let mut patch = MetadataPatch::new("stale boot sector label", "fat16");
+        patch.set_from(&mut file, "boot sector", labels.boot_offsets[0], b"STALE      ".to_vec()).unwrap();
+        patch.apply(&mut file, None).unwrap();
+        let labels = read_labels(&mut file).unwrap();
+        assert_eq!(labels.effective().as_deref(), Some("OLD"));
+        assert!(labels.mismatch().unwrap().contains("'STALE'"));
+
+        assert!(fat_label_bytes("TWELVE CHARS").is_err());
+        assert!(fat_label_bytes("a/b").is_err());
+        assert_eq!(&fat_label_bytes("data").unwrap(), b"DATA       ");
+    }
+}
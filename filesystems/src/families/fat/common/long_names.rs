@@ -210,14 +210,14 @@ pub fn create_vfat_lfn_entries(long_name: &str, short_name: &[u8; 11]) -> Vec<[u
     entries
 }
 
-/// Hash function for exFAT filename (for name hash in stream entry)
+/// Hash function for exFAT filename (for name hash in stream entry), over the name
+/// mapped unit by unit through the up-case table the formatter writes
 pub fn exfat_name_hash(name: &str) -> u16 {
     let mut hash = 0u16;
-    let name_upper = name.to_uppercase();
     
-    for ch in name_upper.encode_utf16() {
-        hash = ((hash << 15) | (hash >> 1)) + (ch & 0xFF) as u16;
-        hash = ((hash << 15) | (hash >> 1)) + (ch >> 8) as u16;
+    for ch in name.encode_utf16().map(crate::families::fat::exfat::upcase::to_upper_case) {
+        hash = hash.rotate_right(1).wrapping_add(ch & 0xFF);
+        hash = hash.rotate_right(1).wrapping_add(ch >> 8);
     }
     
     hash
@@ -12,6 +12,12 @@ pub mod directory;
 pub mod cluster_io;
 pub mod timestamps;
 pub mod long_names;
+pub mod sd_spec;
+pub mod dos_geometry;
+pub mod attrib;
+pub mod dirty;
+pub mod label;
+mod volume;
 
 pub use constants::*;
 pub use boot_sector::*;
@@ -22,6 +28,8 @@ pub use fat_table::*;
 pub use directory::*;
 pub use cluster_io::*;
 pub use timestamps::*;
+pub use sd_spec::{SdLayout, calculate_fat32_sd_params};
+pub use dos_geometry::DosCompatibility;
 
 use std::time::SystemTime;
 
@@ -42,17 +50,17 @@ pub fn generate_volume_serial() -> u32 {
     }
 }
 
-/// Convert a string to FAT volume label format (11 bytes, space-padded)
+/// Convert a string to FAT volume label format (11 bytes, space-padded); "NO NAME" when
+/// there is no label, as Windows writes it
 pub fn format_volume_label(label: Option<&str>) -> [u8; 11] {
+    let Some(label) = label.filter(|label| !label.trim().is_empty()) else {
+        return *label::NO_NAME;
+    };
     let mut result = [0x20u8; 11]; // Space-padded
-    
-    if let Some(label) = label {
-        let label = label.to_uppercase();
-        let bytes = label.as_bytes();
-        let len = bytes.len().min(11);
-        result[..len].copy_from_slice(&bytes[..len]);
-    }
-    
+    let label = label.to_uppercase();
+    let bytes = label.as_bytes();
+    let len = bytes.len().min(11);
+    result[..len].copy_from_slice(&bytes[..len]);
     result
 }
 
@@ -0,0 +1,168 @@
+// SD Association card layout
+// Flash cards erase and program in boundary units (BU) far larger than a sector. The SD
+// File System Specification fixes the cluster size and the partition start per capacity
+// class and places the FAT so the cluster heap begins on a BU boundary; a card laid out
+// generically ends up with clusters straddling erase blocks and writes noticeably slower.
+
+use moses_core::MosesError;
+use super::cluster_calc::{FatParams, FatType};
+use super::constants::FAT32_MIN_CLUSTERS;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+
+/// Layout the SD specification prescribes for a card of a given capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdLayout {
+    /// Boundary unit in bytes; the partition and the cluster heap start on multiples of it
+    pub boundary_unit: u64,
+    /// Byte offset of the single MBR partition
+    pub partition_offset: u64,
+    /// Cluster size in bytes
+    pub cluster_size: u32,
+}
+
+impl SdLayout {
+    /// Pick the layout from the card's capacity (SDSC up to 2 GB, SDHC up to 32 GB, SDXC above)
+    pub fn for_card(card_size: u64) -> Self {
+        let (boundary_unit, cluster_size) = match card_size {
+            0..=8_388_608 => (8 * KIB, 8 * KIB),
+            8_388_609..=67_108_864 => (32 * KIB, 16 * KIB),
+            67_108_865..=268_435_456 => (64 * KIB, 16 * KIB),
+            268_435_457..=1_073_741_824 => (128 * KIB, 16 * KIB),
+            1_073_741_825..=2_147_483_648 => (128 * KIB, 32 * KIB),
+            // SDHC
+            2_147_483_649..=34_359_738_368 => (4 * MIB, 32 * KIB),
+            // SDXC: "64 GB" cards fall below 64 GiB
+            _ if card_size <= 64 * GIB => (16 * MIB, 128 * KIB),
+            _ => (32 * MIB, 128 * KIB),
+        };
+        Self {
+            boundary_unit,
+            partition_offset: boundary_unit,
+            cluster_size: cluster_size as u32,
+        }
+    }
+
+    pub fn boundary_unit_sectors(&self) -> u64 {
+        self.boundary_unit / 512
+    }
+
+    pub fn sectors_per_cluster(&self) -> u32 {
+        self.cluster_size / 512
+    }
+}
+
+/// Round `value` up to a multiple of `unit`
+pub fn align_up(value: u64, unit: u64) -> u64 {
+    value.div_ceil(unit) * unit
+}
+
+/// FAT32 parameters for an SD layout, with the reserved sectors chosen so the data area
+/// (reserved sectors plus both FATs) ends on a boundary unit. Returns the parameters and
+/// the reserved sector count.
+pub fn calculate_fat32_sd_params(total_sectors: u64, layout: &SdLayout) -> Result<(FatParams, u16), MosesError> {
+    let bu_sectors = layout.boundary_unit_sectors().max(1);
+    let mut sectors_per_cluster = layout.sectors_per_cluster().max(1) as u64;
+
+    // Small cards get the SD cluster size only if that still leaves FAT32 enough clusters
+    let (reserved_sectors, sectors_per_fat, total_clusters) = loop {
+        let (reserved_sectors, sectors_per_fat) = settle_fat32_reserved(total_sectors, sectors_per_cluster, bu_sectors);
+        let total_clusters = (total_sectors.saturating_sub(reserved_sectors + 2 * sectors_per_fat) / sectors_per_cluster)
+            .min((sectors_per_fat * 128).saturating_sub(2));
+        if total_clusters >= FAT32_MIN_CLUSTERS as u64 || sectors_per_cluster == 1 {
+            break (reserved_sectors, sectors_per_fat, total_clusters);
+        }
+        sectors_per_cluster /= 2;
+    };
+
+    if reserved_sectors > u16::MAX as u64 {
+        return Err(MosesError::Other(format!(
+            "SD layout needs {} reserved sectors, more than FAT32 allows", reserved_sectors
+        )));
+    }
+    if total_clusters < FAT32_MIN_CLUSTERS as u64 {
+        return Err(MosesError::Other(format!(
+            "Volume too small for FAT32 (only {} clusters, need at least {})",
+            total_clusters, FAT32_MIN_CLUSTERS
+        )));
+    }
+    if total_clusters > 0x0FFFFFFF {
+        return Err(MosesError::Other(format!(
+            "Too many clusters for FAT32: {} (max 268435455)", total_clusters
+        )));
+    }
+
+    Ok((
+        FatParams {
+            sectors_per_cluster: sectors_per_cluster as u8,
+            sectors_per_fat: sectors_per_fat as u32,
+            root_entries: 0,
+            total_clusters: total_clusters as u32,
+            fat_type: FatType::Fat32,
+        },
+        reserved_sectors as u16,
+    ))
+}
+
+/// Reserved sectors (at least 32) and FAT size such that both FATs end on a boundary unit.
+/// The two depend on each other; this settles in a few rounds.
+fn settle_fat32_reserved(total_sectors: u64, sectors_per_cluster: u64, bu_sectors: u64) -> (u64, u64) {
+    let mut reserved_sectors = 32u64;
+    let mut sectors_per_fat = 0u64;
+    for _ in 0..16 {
+        let clusters = total_sectors.saturating_sub(reserved_sectors + 2 * sectors_per_fat) / sectors_per_cluster;
+        let next_fat = ((clusters + 2) * 4).div_ceil(512);
+        let next_reserved = align_up(32 + 2 * next_fat, bu_sectors) - 2 * next_fat;
+        if (next_fat, next_reserved) == (sectors_per_fat, reserved_sectors) {
+            break;
+        }
+        (sectors_per_fat, reserved_sectors) = (next_fat, next_reserved);
+    }
+    (reserved_sectors, sectors_per_fat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_by_capacity() {
+        let sdhc = SdLayout::for_card(15_931_539_456);  // a "16 GB" card
+        assert_eq!((sdhc.boundary_unit, sdhc.partition_offset, sdhc.cluster_size), (4 * MIB, 4 * MIB, 32 * 1024));
+
+        let sdxc = SdLayout::for_card(63_864_569_856);  // a "64 GB" card
+        assert_eq!((sdxc.partition_offset, sdxc.cluster_size), (16 * MIB, 128 * 1024));
+
+        let large = SdLayout::for_card(127_865_454_592);
+        assert_eq!(large.partition_offset, 32 * MIB);
+
+        let sdsc = SdLayout::for_card(2_002_780_160);
+        assert_eq!((sdsc.boundary_unit, sdsc.cluster_size), (128 * KIB, 32 * 1024));
+    }
+
+    #[test]
+    fn test_fat32_data_area_on_boundary_unit() {
+        let card_size = 15_931_539_456u64;
+        let layout = SdLayout::for_card(card_size);
+        let total_sectors = (card_size - layout.partition_offset) / 512;
+        let (params, reserved) = calculate_fat32_sd_params(total_sectors, &layout).unwrap();
+
+        assert_eq!(params.sectors_per_cluster, 64);
+        assert!(reserved >= 32);
+        let data_start = reserved as u64 + 2 * params.sectors_per_fat as u64;
+        assert_eq!(data_start % layout.boundary_unit_sectors(), 0);
+        // The FAT covers every cluster
+        assert!(params.sectors_per_fat as u64 * 128 >= params.total_clusters as u64 + 2);
+        assert!(data_start + params.total_clusters as u64 * 64 <= total_sectors);
+    }
+
+    #[test]
+    fn test_small_card_keeps_fat32_cluster_minimum() {
+        let layout = SdLayout::for_card(256 * MIB);
+        let (params, _) = calculate_fat32_sd_params(256 * MIB / 512 - 128, &layout).unwrap();
+        assert!(params.total_clusters >= FAT32_MIN_CLUSTERS);
+        assert!(params.sectors_per_cluster < 32);
+    }
+}
@@ -75,9 +75,11 @@ pub struct ExFatTimestamp {
 impl ExFatTimestamp {
     /// Create from current system time
     pub fn now() -> Self {
-        let now = SystemTime::now();
-        let unix_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
-        
+        Self::from_unix(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+    }
+    
+    /// Create from seconds since the Unix epoch
+    pub fn from_unix(unix_secs: u64) -> Self {
         // Convert Unix epoch (1970) to Windows epoch (1601)
         // Difference is 11644473600 seconds
         const EPOCH_DIFF: u64 = 11644473600;
@@ -0,0 +1,162 @@
+// Where a FAT12/16/32 or exFAT volume keeps its FATs, directories and clusters
+// Read straight from the boot sector for the in-place editors and the dirty-volume
+// check, which work on the raw device and need none of the readers' caches.
+
+use moses_core::MosesError;
+use std::io::{Read, Seek, SeekFrom};
+
+pub(super) const ENTRY_SIZE: usize = 32;
+
+/// A directory's clusters
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Dir {
+    /// The fixed root directory of FAT12 and FAT16
+    FixedRoot,
+    /// A cluster chain in the FAT
+    Chain(u32),
+    /// exFAT contiguous clusters: first cluster and length in bytes
+    Contiguous(u32, u64),
+}
+
+/// Where a FAT or exFAT volume keeps its directories
+pub(super) struct Volume {
+    pub(super) exfat: bool,
+    /// 12, 16 or 32
+    pub(super) fat_bits: u32,
+    pub(super) fat_offset: u64,
+    /// Number of FAT copies and the length of each in bytes
+    pub(super) fats: u32,
+    pub(super) fat_size: u64,
+    pub(super) data_offset: u64,
+    pub(super) cluster_size: u64,
+    pub(super) clusters: u32,
+    /// Offset and length of the FAT12/16 fixed root directory
+    pub(super) fixed_root: (u64, u64),
+    pub(super) root: Dir,
+}
+
+pub(super) fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(super) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub(super) fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)
+        .map_err(|e| MosesError::Other(format!("Failed to read {} bytes at 0x{:X}: {}", len, offset, e)))?;
+    Ok(buf)
+}
+
+impl Volume {
+    pub(super) fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, MosesError> {
+        let bs = read_at(reader, 0, 512)?;
+        if &bs[3..11] == b"EXFAT   " {
+            let sector = 1u64 << bs[108].min(12);
+            return Ok(Self {
+                exfat: true,
+                fat_bits: 32,
+                fat_offset: u32_at(&bs, 80) as u64 * sector,
+                fats: bs[110] as u32,
+                fat_size: u32_at(&bs, 84) as u64 * sector,
+                data_offset: u32_at(&bs, 88) as u64 * sector,
+                cluster_size: sector << bs[109].min(25),
+                clusters: u32_at(&bs, 92),
+                fixed_root: (0, 0),
+                root: Dir::Chain(u32_at(&bs, 96)),
+            });
+        }
+
+        let bytes_per_sector = u16_at(&bs, 11) as u64;
+        let sectors_per_cluster = bs[13] as u64;
+        if !bytes_per_sector.is_power_of_two() || !(512..=4096).contains(&bytes_per_sector) || sectors_per_cluster == 0 {
+            return Err(MosesError::InvalidInput("No FAT or exFAT boot sector found".to_string()));
+        }
+        let reserved = u16_at(&bs, 14) as u64;
+        let fats = bs[16] as u64;
+        let root_sectors = (u16_at(&bs, 17) as u64 * ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let fat_size = match u16_at(&bs, 22) { 0 => u32_at(&bs, 36) as u64, size => size as u64 };
+        let total = match u16_at(&bs, 19) { 0 => u32_at(&bs, 32) as u64, total => total as u64 };
+        let data_start = reserved + fats * fat_size + root_sectors;
+        let clusters = (total.saturating_sub(data_start) / sectors_per_cluster) as u32;
+        let fat_bits = match clusters {
+            0..4085 => 12,
+            4085..65525 => 16,
+            _ => 32,
+        };
+        Ok(Self {
+            exfat: false,
+            fat_bits,
+            fat_offset: reserved * bytes_per_sector,
+            fats: fats as u32,
+            fat_size: fat_size * bytes_per_sector,
+            data_offset: data_start * bytes_per_sector,
+            cluster_size: sectors_per_cluster * bytes_per_sector,
+            clusters,
+            fixed_root: ((reserved + fats * fat_size) * bytes_per_sector, root_sectors * bytes_per_sector),
+            root: if fat_bits == 32 { Dir::Chain(u32_at(&bs, 44)) } else { Dir::FixedRoot },
+        })
+    }
+
+    pub(super) fn name(&self) -> &'static str {
+        if self.exfat { "exFAT" } else { "FAT" }
+    }
+
+    pub(super) fn cluster_offset(&self, cluster: u32) -> Result<u64, MosesError> {
+        if cluster < 2 || cluster - 2 >= self.clusters {
+            return Err(MosesError::Other(format!("Cluster {} is outside the volume", cluster)));
+        }
+        Ok(self.data_offset + (cluster - 2) as u64 * self.cluster_size)
+    }
+
+    /// The cluster after `cluster` in its chain, None at the end
+    pub(super) fn next_cluster<R: Read + Seek>(&self, reader: &mut R, cluster: u32) -> Result<Option<u32>, MosesError> {
+        let next = match self.fat_bits {
+            12 => {
+                let pair = u16_at(&read_at(reader, self.fat_offset + cluster as u64 * 3 / 2, 2)?, 0);
+                let value = if cluster & 1 == 1 { pair >> 4 } else { pair & 0xFFF } as u32;
+                (value < 0xFF7).then_some(value)
+            }
+            16 => {
+                let value = u16_at(&read_at(reader, self.fat_offset + cluster as u64 * 2, 2)?, 0) as u32;
+                (value < 0xFFF7).then_some(value)
+            }
+            _ => {
+                let value = u32_at(&read_at(reader, self.fat_offset + cluster as u64 * 4, 4)?, 0);
+                let value = if self.exfat { value } else { value & 0x0FFF_FFFF };
+                (value < if self.exfat { 0xFFFF_FFF7 } else { 0x0FFF_FFF7 }).then_some(value)
+            }
+        };
+        Ok(next.filter(|&next| next >= 2))
+    }
+
+    /// Every 32-byte slot of a directory with its offset
+    pub(super) fn slots<R: Read + Seek>(&self, reader: &mut R, dir: Dir) -> Result<Vec<(u64, [u8; ENTRY_SIZE])>, MosesError> {
+        let mut extents = Vec::new();
+        match dir {
+            Dir::FixedRoot => extents.push(self.fixed_root),
+            Dir::Contiguous(first, length) => extents.push((self.cluster_offset(first)?, length)),
+            Dir::Chain(first) => {
+                let mut cluster = Some(first);
+                while let Some(current) = cluster {
+                    if extents.len() > self.clusters as usize {
+                        return Err(MosesError::Other(format!("The cluster chain from {} loops", first)));
+                    }
+                    extents.push((self.cluster_offset(current)?, self.cluster_size));
+                    cluster = self.next_cluster(reader, current)?;
+                }
+            }
+        }
+        let mut slots = Vec::new();
+        for (offset, length) in extents {
+            let data = read_at(reader, offset, length as usize)?;
+            for (index, chunk) in data.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+                slots.push((offset + (index * ENTRY_SIZE) as u64, *chunk));
+            }
+        }
+        Ok(slots)
+    }
+}
@@ -73,6 +73,11 @@ impl DirectoryEntrySetBuilder {
         self
     }
     
+    pub fn accessed(mut self, timestamp: ExFatTimestamp) -> Self {
+        self.accessed = timestamp;
+        self
+    }
+    
     /// Build the complete directory entry set
     pub fn build(self) -> Vec<ExFatDirectoryEntry> {
         let mut entries = Vec::new();
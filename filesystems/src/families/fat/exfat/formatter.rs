@@ -103,33 +103,11 @@ impl ExFatFormatter {
     
     #[cfg(target_os = "linux")]
     async fn format_linux(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
-        // Check which exFAT tool is available
-        let mkfs_exfat_check = Command::new("which")
-            .arg("mkfs.exfat")
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
-        
-        let mkexfatfs_check = Command::new("which")
-            .arg("mkexfatfs")
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
-        
-        if !mkfs_exfat_check && !mkexfatfs_check {
-            return Err(MosesError::ExternalToolMissing(
-                "Neither mkfs.exfat nor mkexfatfs found. Install exfatprogs or exfat-utils package".to_string()
-            ));
-        }
-        
-        // Prefer mkfs.exfat (newer tool from exfatprogs)
-        let tool = if mkfs_exfat_check {
-            "mkfs.exfat"
-        } else {
-            "mkexfatfs"
-        };
+        // Prefer mkfs.exfat (newer tool from exfatprogs) over mkexfatfs from exfat-utils
+        let tool = crate::tools::ToolManager::global()
+            .ensure("exfatprogs or exfat-utils", &["mkfs.exfat", "mkexfatfs"])?;
         
-        let mut cmd = Command::new(tool);
+        let mut cmd = Command::new(&tool);
         
         // Add label if provided
         if let Some(ref label) = options.label {
@@ -142,7 +120,7 @@ impl ExFatFormatter {
         
         // Execute the format command
         let output = cmd.output()
-            .map_err(|e| MosesError::Other(format!("Failed to run {}: {}", tool, e)))?;
+            .map_err(|e| MosesError::Other(format!("Failed to run {}: {}", tool.display(), e)))?;
         
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -206,7 +184,7 @@ impl FilesystemFormatter for ExFatFormatter {
     
     fn can_format(&self, device: &Device) -> bool {
         // Never format system drives
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
@@ -244,6 +222,16 @@ impl FilesystemFormatter for ExFatFormatter {
         return vec![];
     }
     
+    fn tools_available(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return ["mkfs.exfat", "mkexfatfs"]
+            .iter()
+            .any(|tool| crate::tools::ToolManager::global().locate(tool).is_some());
+        
+        #[cfg(not(target_os = "linux"))]
+        return true; // format.com / diskutil ship with the OS
+    }
+    
     async fn format(
         &self,
         device: &Device,
@@ -310,6 +298,8 @@ impl FilesystemFormatter for ExFatFormatter {
         device: &Device,
         options: &FormatOptions,
     ) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         let mut warnings = vec![];
         
         if device.is_system {
@@ -368,6 +358,12 @@ impl FilesystemFormatter for ExFatFormatter {
             required_tools: self.bundled_tools().into_iter().map(String::from).collect(),
             will_erase_data: true,
             space_after_format: device.size * 99 / 100, // exFAT has minimal overhead ~1%
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
\ No newline at end of file
@@ -1,11 +1,14 @@
 // Native exFAT formatter implementation
 // Formats drives as exFAT without using external tools
 
-use moses_core::{Device, MosesError, FormatOptions, FilesystemFormatter, SimulationReport, Platform};
+use moses_core::{Device, MosesError, FormatOptions, FormatPreset, FilesystemFormatter, SimulationReport, Platform};
 use async_trait::async_trait;
 use std::io::{Write, Seek, SeekFrom};
 use log::info;
-use crate::families::fat::common::generate_volume_serial;
+use crate::families::fat::common::{generate_volume_serial, SdLayout};
+use crate::volume_serial::{serial_for_format, VolumeSerial};
+use crate::deterministic::FormatSeed;
+use crate::families::fat::common::sd_spec::align_up;
 use super::structures::*;
 use super::bitmap::ExFatBitmap;
 use super::upcase::generate_upcase_table;
@@ -13,14 +16,15 @@ use super::upcase::generate_upcase_table;
 pub struct ExFatNativeFormatter;
 
 impl ExFatNativeFormatter {
-    /// Calculate exFAT parameters based on volume size
-    fn calculate_params(total_bytes: u64) -> ExFatParams {
+    /// Calculate exFAT parameters based on volume size, or on the SD layout for SD cards
+    fn calculate_params(total_bytes: u64, sd_layout: Option<&SdLayout>) -> ExFatParams {
         // Determine optimal cluster size based on volume size
-        let sectors_per_cluster = match total_bytes {
-            0..=256_000_000 => 8,           // <= 256MB: 4KB clusters
-            256_000_001..=32_000_000_000 => 64,   // <= 32GB: 32KB clusters
-            32_000_000_001..=256_000_000_000 => 256, // <= 256GB: 128KB clusters
-            _ => 512,                        // > 256GB: 256KB clusters
+        let sectors_per_cluster = match (sd_layout, total_bytes) {
+            (Some(layout), _) => layout.sectors_per_cluster(),
+            (None, 0..=256_000_000) => 8,           // <= 256MB: 4KB clusters
+            (None, 256_000_001..=32_000_000_000) => 64,   // <= 32GB: 32KB clusters
+            (None, 32_000_000_001..=256_000_000_000) => 256, // <= 256GB: 128KB clusters
+            (None, _) => 512,                        // > 256GB: 256KB clusters
         };
         
         let bytes_per_sector = 512;
@@ -33,8 +37,9 @@ impl ExFatNativeFormatter {
         // - Data region
         
         // Use 128 sectors for boot region like Windows does (for alignment)
-        // This is larger than the minimum 24 sectors but provides better alignment
-        let boot_sectors = 128;
+        // This is larger than the minimum 24 sectors but provides better alignment.
+        // The SD layout puts the FAT in the second half of the first boundary unit.
+        let boot_sectors = sd_layout.map_or(128, |layout| (layout.boundary_unit_sectors() / 2).max(24));
         let total_clusters = ((total_sectors - boot_sectors) * bytes_per_sector as u64) / bytes_per_cluster as u64;
         
         // FAT size: 4 bytes per cluster
@@ -45,11 +50,19 @@ impl ExFatNativeFormatter {
         let bitmap_size = (total_clusters + 7) / 8;  // 1 bit per cluster
         let bitmap_clusters = (bitmap_size + bytes_per_cluster as u64 - 1) / bytes_per_cluster as u64;
         
-        let upcase_size = 128 * 1024;  // 128KB for Unicode upcase table
+        let upcase_size = generate_upcase_table().len() as u64;  // Compressed up-case table
         let upcase_clusters = (upcase_size + bytes_per_cluster as u64 - 1) / bytes_per_cluster as u64;
         
         let heap_clusters = bitmap_clusters + upcase_clusters;
-        let usable_clusters = total_clusters - heap_clusters - 1;  // -1 for root directory
+        
+        // SD cards start the cluster heap on a boundary unit
+        let cluster_heap_offset = match sd_layout {
+            Some(layout) => align_up(boot_sectors + fat_sectors, layout.boundary_unit_sectors().max(1)),
+            None => boot_sectors + fat_sectors,
+        };
+        // -1 for root directory; never more clusters than fit after the heap offset
+        let usable_clusters = (total_clusters - heap_clusters - 1)
+            .min((total_sectors - cluster_heap_offset) / sectors_per_cluster as u64);
         
         ExFatParams {
             bytes_per_sector: bytes_per_sector as u32,
@@ -58,13 +71,14 @@ impl ExFatNativeFormatter {
             total_clusters: total_clusters as u32,
             fat_offset: boot_sectors,
             fat_length: fat_sectors as u32,
-            cluster_heap_offset: boot_sectors + fat_sectors,
+            cluster_heap_offset,
             cluster_count: usable_clusters as u32,
             first_cluster_of_root: (heap_clusters + 2) as u32,  // After bitmap and upcase
             bitmap_start_cluster: 2,  // First data cluster
             bitmap_length: bitmap_clusters as u32,
             upcase_start_cluster: (2 + bitmap_clusters) as u32,
             upcase_length: upcase_clusters as u32,
+            upcase_size,
         }
     }
     
@@ -105,39 +119,34 @@ impl ExFatNativeFormatter {
         // First cluster of root directory (4 bytes) - offset 96
         boot[96..100].copy_from_slice(&params.first_cluster_of_root.to_le_bytes());
         
-        // Bitmap start cluster (4 bytes) - offset 100
-        boot[100..104].copy_from_slice(&params.bitmap_start_cluster.to_le_bytes());
-        
-        // Bitmap length in clusters (4 bytes) - offset 104
-        boot[104..108].copy_from_slice(&params.bitmap_length.to_le_bytes());
+        // Volume serial number (4 bytes) - offset 100
+        // The bitmap and up-case table are found through their root directory entries
+        boot[100..104].copy_from_slice(&volume_serial.to_le_bytes());
         
-        // Volume serial number (4 bytes) - offset 108
-        boot[108..112].copy_from_slice(&volume_serial.to_le_bytes());
+        // File system revision (2 bytes) - offset 104
+        boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());  // Version 1.00
         
-        // File system revision (2 bytes) - offset 112
-        boot[112..114].copy_from_slice(&0x0100u16.to_le_bytes());  // Version 1.00
-        
-        // Volume flags (2 bytes) - offset 114
+        // Volume flags (2 bytes) - offset 106
         // Bit 0: ActiveFAT (0 = first FAT, 1 = second FAT)
         // Bit 1: VolumeDirty (0 = clean, 1 = dirty)
         // Bit 2: MediaFailure (0 = no failures, 1 = failures reported)
         let volume_flags: u16 = 0x0000;  // Clean volume, first FAT active, no failures
-        boot[114..116].copy_from_slice(&volume_flags.to_le_bytes());
+        boot[106..108].copy_from_slice(&volume_flags.to_le_bytes());
         
-        // Bytes per sector shift (1 byte) - offset 116
-        boot[116] = params.bytes_per_sector.trailing_zeros() as u8;
+        // Bytes per sector shift (1 byte) - offset 108
+        boot[108] = params.bytes_per_sector.trailing_zeros() as u8;
         
-        // Sectors per cluster shift (1 byte) - offset 117
-        boot[117] = params.sectors_per_cluster.trailing_zeros() as u8;
+        // Sectors per cluster shift (1 byte) - offset 109
+        boot[109] = params.sectors_per_cluster.trailing_zeros() as u8;
         
-        // Number of FATs (1 byte) - offset 118
-        boot[118] = 1;  // exFAT typically uses 1 FAT
+        // Number of FATs (1 byte) - offset 110
+        boot[110] = 1;  // exFAT typically uses 1 FAT
         
-        // Drive select (1 byte) - offset 119
-        boot[119] = 0x80;  // Hard disk
+        // Drive select (1 byte) - offset 111
+        boot[111] = 0x80;  // Hard disk
         
-        // Percent in use (1 byte) - offset 120
-        boot[120] = 0;  // 0% used initially
+        // Percent in use (1 byte) - offset 112
+        boot[112] = 0;  // 0% used initially
         
         // Reserved (7 bytes) - offset 113
         // Already zero
@@ -158,8 +167,8 @@ impl ExFatNativeFormatter {
         
         // Process boot sector (sector 0)
         for i in 0..512 {
-            // Skip VolumeFlags (114-115), PercentInUse (120), and Bitmap fields (100-107)
-            if (100..=107).contains(&i) || i == 114 || i == 115 || i == 120 {
+            // Skip VolumeFlags (106-107) and PercentInUse (112)
+            if i == 106 || i == 107 || i == 112 {
                 continue;
             }
             // Official algorithm: if LSB is 1, add 0x80000000 after shift
@@ -185,8 +194,25 @@ impl ExFatNativeFormatter {
         checksum
     }
     
+    /// Version 4 style GUID derived from the volume serial
+    fn volume_guid_for(serial: u32) -> [u8; 16] {
+        // Format: XXXXXXXX-XXXX-4XXX-8XXX-XXXXXXXXXXXX (version 4 random UUID)
+        let mut guid = [0u8; 16];
+        guid[0..4].copy_from_slice(&serial.to_le_bytes());
+        guid[4..6].copy_from_slice(&[0x12, 0x34]);
+        guid[6] = 0x40 | (serial as u8 & 0x0F);  // Version 4
+        guid[7] = serial.wrapping_shr(8) as u8;
+        guid[8] = 0x80 | (serial.wrapping_shr(16) as u8 & 0x3F);  // Variant
+        guid[9] = serial.wrapping_shr(24) as u8;
+        // Fill remaining bytes
+        for (i, byte) in guid.iter_mut().enumerate().skip(10) {
+            *byte = ((serial.wrapping_mul(i as u32 + 1)) & 0xFF) as u8;
+        }
+        guid
+    }
+    
     /// Create root directory with volume label
-    fn create_root_directory(label: Option<&str>, params: &ExFatParams, upcase_checksum: u32) -> Vec<u8> {
+    fn create_root_directory(label: Option<&str>, params: &ExFatParams, upcase_checksum: u32, volume_guid: [u8; 16], created: u64) -> Vec<u8> {
         let mut entries = Vec::new();
         
         // Volume label entry (if provided)
@@ -217,22 +243,7 @@ impl ExFatNativeFormatter {
             guid_entry.volume_guid.set_checksum = 0;  // Not used for GUID entry
             guid_entry.volume_guid.flags = 0;
             
-            // Generate a random GUID using the volume serial as seed
-            use crate::families::fat::common::generate_volume_serial;
-            let serial = generate_volume_serial();
-            
-            // Create a simple GUID based on the serial number
-            // Format: XXXXXXXX-XXXX-4XXX-8XXX-XXXXXXXXXXXX (version 4 random UUID)
-            guid_entry.volume_guid.volume_guid[0..4].copy_from_slice(&serial.to_le_bytes());
-            guid_entry.volume_guid.volume_guid[4..6].copy_from_slice(&[0x12, 0x34]);
-            guid_entry.volume_guid.volume_guid[6] = 0x40 | (serial as u8 & 0x0F);  // Version 4
-            guid_entry.volume_guid.volume_guid[7] = serial.wrapping_shr(8) as u8;
-            guid_entry.volume_guid.volume_guid[8] = 0x80 | (serial.wrapping_shr(16) as u8 & 0x3F);  // Variant
-            guid_entry.volume_guid.volume_guid[9] = serial.wrapping_shr(24) as u8;
-            // Fill remaining bytes
-            for i in 10..16 {
-                guid_entry.volume_guid.volume_guid[i] = ((serial.wrapping_mul(i as u32 + 1)) & 0xFF) as u8;
-            }
+            guid_entry.volume_guid.volume_guid = volume_guid;
         }
         
         entries.extend_from_slice(&guid_entry.to_bytes());
@@ -250,15 +261,20 @@ impl ExFatNativeFormatter {
         upcase_entry.upcase.entry_type = EXFAT_ENTRY_UPCASE;
         upcase_entry.upcase.table_checksum = upcase_checksum;
         upcase_entry.upcase.first_cluster = params.upcase_start_cluster;
-        upcase_entry.upcase.data_length = 128 * 1024;  // 128KB
+        upcase_entry.upcase.data_length = params.upcase_size;
         entries.extend_from_slice(&upcase_entry.to_bytes());
         
         // Add a test file to demonstrate directory entry sets (optional)
         // This helps verify the filesystem is working
         if cfg!(debug_assertions) {
             use super::directory_entries::DirectoryEntrySetBuilder;
+            use crate::families::fat::common::timestamps::ExFatTimestamp;
             
+            let timestamp = ExFatTimestamp::from_unix(created);
             let test_file = DirectoryEntrySetBuilder::new_file("README.TXT")
+                .created(timestamp)
+                .modified(timestamp)
+                .accessed(timestamp)
                 .size(46)  // Small test message
                 .first_cluster(0)  // No cluster allocated (empty file)
                 .build();
@@ -284,9 +300,13 @@ impl ExFatNativeFormatter {
         volume_label: Option<&str>,
         write_offset: u64,
         partition_size: u64,
+        sd_layout: Option<&SdLayout>,
+        serial: Option<&VolumeSerial>,
+        created: u64,
     ) -> Result<(), MosesError> {
-        let params = Self::calculate_params(partition_size);
-        let volume_serial = generate_volume_serial();
+        let params = Self::calculate_params(partition_size, sd_layout);
+        let volume_serial = serial.map_or_else(generate_volume_serial, VolumeSerial::short);
+        let volume_guid = serial.and_then(|serial| serial.guid).unwrap_or_else(|| Self::volume_guid_for(volume_serial));
         
         info!("exFAT parameters: {} total sectors, {} sectors/cluster, {} total clusters",
               params.total_sectors, params.sectors_per_cluster, params.total_clusters);
@@ -345,16 +365,12 @@ impl ExFatNativeFormatter {
         fat_buffer.extend_from_slice(&0xFFFFFFF8u32.to_le_bytes());  // Entry 0: Media descriptor
         fat_buffer.extend_from_slice(&0xFFFFFFFFu32.to_le_bytes());  // Entry 1: End of chain
         
-        // Mark system clusters as allocated
-        // Cluster 2: Bitmap (contiguous allocation)
-        for _i in 0..params.bitmap_length {
-            fat_buffer.extend_from_slice(&0xFFFFFFFFu32.to_le_bytes());  // End of chain
-        }
-        
-        // Upcase table clusters (contiguous allocation)
-        let upcase_clusters = (128 * 1024) / (params.sectors_per_cluster * params.bytes_per_sector);
-        for _i in 0..upcase_clusters {
-            fat_buffer.extend_from_slice(&0xFFFFFFFFu32.to_le_bytes());  // End of chain
+        // Bitmap from cluster 2, then the upcase table, each one contiguous chain
+        for (first, length) in [(params.bitmap_start_cluster, params.bitmap_length), (params.upcase_start_cluster, params.upcase_length)] {
+            for cluster in first..first + length {
+                let next = if cluster + 1 == first + length { 0xFFFFFFFF } else { cluster + 1 };
+                fat_buffer.extend_from_slice(&next.to_le_bytes());
+            }
         }
         
         // Root directory cluster
@@ -378,8 +394,7 @@ impl ExFatNativeFormatter {
             bitmap.set_allocated(i);
         }
         // Upcase table clusters  
-        let upcase_clusters = (128 * 1024) / (params.sectors_per_cluster * params.bytes_per_sector);
-        for i in 0..upcase_clusters {
+        for i in 0..params.upcase_length {
             bitmap.set_allocated(params.bitmap_length + i);
         }
         // Root directory cluster
@@ -402,8 +417,7 @@ impl ExFatNativeFormatter {
         let upcase_table = generate_upcase_table();
         let upcase_checksum = super::upcase::calculate_upcase_checksum(&upcase_table);
         
-        // Upcase table is already 128KB which should be cluster-aligned
-        // But ensure it's padded if needed
+        // Pad the compressed table to whole clusters
         let mut upcase_data = upcase_table;
         while upcase_data.len() % cluster_size != 0 {
             upcase_data.push(0);
@@ -416,15 +430,14 @@ impl ExFatNativeFormatter {
         // 9. Write root directory
         let root_offset = bitmap_offset + 
             ((params.first_cluster_of_root - 2) as u64 * params.sectors_per_cluster as u64 * params.bytes_per_sector as u64);
-        let root_dir = Self::create_root_directory(volume_label, &params, upcase_checksum);
+        let root_dir = Self::create_root_directory(volume_label, &params, upcase_checksum, volume_guid, created);
         
         // Root directory is already padded to cluster size in create_root_directory
         file.seek(SeekFrom::Start(root_offset))?;
         file.write_all(&root_dir)?;
         info!("Wrote root directory");
         
-        file.flush()?;
-        Ok(())
+        crate::utils::flush_device(file)
     }
 }
 
@@ -438,8 +451,8 @@ impl FilesystemFormatter for ExFatNativeFormatter {
         vec![Platform::Windows, Platform::Linux, Platform::MacOS]
     }
     
-    fn can_format(&self, _device: &Device) -> bool {
-        true  // Can format any device
+    fn can_format(&self, device: &Device) -> bool {
+        !device.is_write_protected  // Can format any writable device
     }
     
     fn requires_external_tools(&self) -> bool {
@@ -458,18 +471,37 @@ impl FilesystemFormatter for ExFatNativeFormatter {
                 ));
             }
         }
+        FormatPreset::from_options(options)?;
+        FormatSeed::from_options(options)?;
         Ok(())
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
+        let mut warnings = vec!["All data on the device will be lost".to_string()];
+        if FormatPreset::from_options(options)?.uses_sd_layout(device) {
+            let layout = SdLayout::for_card(device.size);
+            warnings.push(format!(
+                "SD card layout: {} KB clusters, partition at {} KB, cluster heap aligned to {} KB boundary units",
+                layout.cluster_size / 1024, layout.partition_offset / 1024, layout.boundary_unit / 1024
+            ));
+        }
+        
         let report = SimulationReport {
             device: device.clone(),
             options: options.clone(),
             estimated_time: std::time::Duration::from_secs(5),
-            warnings: vec!["All data on the device will be lost".to_string()],
+            warnings,
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: device.size,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         };
         
         Ok(report)
@@ -480,16 +512,36 @@ impl FilesystemFormatter for ExFatNativeFormatter {
         
         info!("Starting native exFAT format of device: {}", device.name);
         
-        // For now, we'll format the whole device without partitioning
-        // TODO: Add partition table support in FormatOptions
-        let write_offset = 0u64;
-        let partition_size = device.size;
+        // Read before anything is written
+        // The serial and volume GUID are kept from the old volume or derived from a seed
+        let seed = FormatSeed::from_options(options)?;
+        let serial = serial_for_format(device, options)?.or_else(|| {
+            seed.serial32("volume serial").map(|value| VolumeSerial { value: value as u64, wide: false, guid: seed.uuid("volume guid") })
+        });
         
         // Open device for writing (uses physical drive path, not drive letter)
         let mut file = open_device_write(device)?;
         
+        // SD cards get an MBR partition on the first boundary unit; anything else is
+        // formatted whole, without partitioning
+        let sd_layout = FormatPreset::from_options(options)?
+            .uses_sd_layout(device)
+            .then(|| SdLayout::for_card(device.size));
+        let write_offset = match &sd_layout {
+            Some(layout) => {
+                use crate::partitioner::{create_mbr_partition_table_at, write_partition_table};
+                info!("Using SD card layout: {} byte clusters, partition at {} bytes, {} byte boundary unit",
+                      layout.cluster_size, layout.partition_offset, layout.boundary_unit);
+                let partition_table = create_mbr_partition_table_at(device, "exfat", (layout.partition_offset / 512) as u32)?;
+                write_partition_table(&mut file, &partition_table)?;
+                layout.partition_offset
+            }
+            None => 0,
+        };
+        let partition_size = device.size - write_offset;
+        
         // Format the partition/device as exFAT
-        Self::write_exfat_to_file(&mut file, options.label.as_deref(), write_offset, partition_size).await?;
+        Self::write_exfat_to_file(&mut file, options.label.as_deref(), write_offset, partition_size, sd_layout.as_ref(), serial.as_ref(), seed.unix_time()).await?;
         
         info!("Successfully formatted device as exFAT");
         Ok(())
@@ -511,4 +563,6 @@ struct ExFatParams {
     bitmap_length: u32,
     upcase_start_cluster: u32,
     upcase_length: u32,
+    /// Bytes of the compressed upcase table
+    upcase_size: u64,
 }
\ No newline at end of file
@@ -1,8 +1,9 @@
 // exFAT FilesystemOps implementation for mounting
-use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo};
+use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo, AttributeChanges};
 use crate::device_reader::FilesystemReader;
 use crate::ops_helpers::convert_filesystem_info;
 use super::reader_aligned::ExFatReaderAligned;
+use crate::families::fat::common::dirty::FatDirtyState;
 use moses_core::{Device, MosesError};
 use std::path::Path;
 use std::sync::Mutex;
@@ -11,6 +12,8 @@ use std::sync::Mutex;
 pub struct ExFatOps {
     reader: Mutex<Option<ExFatReaderAligned>>,
     device: Option<Device>,
+    /// Unclean-shutdown marks found at init
+    dirty: FatDirtyState,
 }
 
 impl ExFatOps {
@@ -18,6 +21,7 @@ impl ExFatOps {
         ExFatOps {
             reader: Mutex::new(None),
             device: None,
+            dirty: FatDirtyState::default(),
         }
     }
 }
@@ -31,6 +35,19 @@ impl FilesystemOps for ExFatOps {
         let reader = ExFatReaderAligned::new(device.clone())?;
         *self.reader.lock().unwrap() = Some(reader);
         self.device = Some(device.clone());
+        self.dirty = FatDirtyState::read_device(device).unwrap_or_default();
+        for warning in self.dirty.warnings() {
+            log::warn!("{}: {}", device.name, warning);
+        }
+        Ok(())
+    }
+    
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let device = self.device.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        crate::attributes::set_attributes(device, "exfat", path, changes)?;
+        // The reader caches directory entries, which now hold the old attributes
+        *self.reader.lock().unwrap() = Some(ExFatReaderAligned::new(device.clone())?);
         Ok(())
     }
     
@@ -144,4 +161,8 @@ impl FilesystemOps for ExFatOps {
         let end = std::cmp::min(start + size as usize, data.len());
         Ok(data[start..end].to_vec())
     }
+    
+    fn warnings(&self) -> Vec<String> {
+        self.dirty.warnings()
+    }
 }
\ No newline at end of file
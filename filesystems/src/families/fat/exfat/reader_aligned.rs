@@ -2,9 +2,11 @@
 // Simplified version that leverages AlignedDeviceReader
 
 use moses_core::{Device, MosesError};
-use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo, FileMetadata};
-use log::{info, debug};
+use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo};
+use crate::families::fat::common::entry_metadata;
+use log::{info, debug, warn};
 use std::collections::HashMap;
+use super::upcase::{upcase_map, validate_upcase, UPCASE_ENTRIES};
 
 // Re-use structures from the original reader
 use super::reader::{
@@ -27,6 +29,11 @@ pub struct ExFatReaderAligned {
     cluster_heap_offset: u64,
     root_cluster: u32,
     total_clusters: u32,
+    label: Option<String>,
+    /// The volume's up-case table, expanded; names are compared through it
+    upcase: Vec<u16>,
+    /// Why the volume's up-case table was not used, if it was not
+    upcase_problem: Option<String>,
     
     // Cache
     fat_cache: HashMap<u32, u32>,  // cluster -> next cluster
@@ -55,6 +62,15 @@ impl ExFatReaderAligned {
             return Err(MosesError::Other("Not an exFAT filesystem".to_string()));
         }
         
+        // Sectors are 512..4096 bytes and clusters at most 32 MiB
+        let sector_shift = boot_sector.bytes_per_sector_shift;
+        let cluster_shift = boot_sector.sectors_per_cluster_shift;
+        if !(9..=12).contains(&sector_shift) || sector_shift as u32 + cluster_shift as u32 > 25 {
+            return Err(MosesError::Other(format!(
+                "Invalid exFAT geometry: sector shift {}, cluster shift {}", sector_shift, cluster_shift
+            )));
+        }
+        
         // Copy values to avoid unaligned access
         let bytes_per_sector = 1u32 << boot_sector.bytes_per_sector_shift;
         let sectors_per_cluster = 1u32 << boot_sector.sectors_per_cluster_shift;
@@ -103,7 +119,12 @@ impl ExFatReaderAligned {
         info!("  Root cluster: {}", root_cluster);
         info!("  Total clusters: {}", cluster_count);
         
-        Ok(Self {
+        // The volume label and up-case table are entries in the first cluster of the root directory
+        let root_offset = cluster_heap_offset + (root_cluster.saturating_sub(2) as u64 * bytes_per_cluster as u64);
+        let root = reader.read_at(root_offset, bytes_per_cluster as usize).ok();
+        let label = root.as_deref().and_then(volume_label);
+        
+        let mut exfat = Self {
             _device: device,
             reader,
             _boot_sector: boot_sector,
@@ -115,9 +136,58 @@ impl ExFatReaderAligned {
             cluster_heap_offset,
             root_cluster,
             total_clusters: cluster_count,
+            label,
+            upcase: Vec::new(),
+            upcase_problem: None,
             fat_cache: HashMap::new(),
             dir_cache: HashMap::new(),
-        })
+        };
+        exfat.load_upcase(root.as_deref());
+        Ok(exfat)
+    }
+    
+    /// Read the up-case table and check it as Windows and Linux do. Like Linux, a missing or
+    /// damaged table is reported and the default one used, so the volume still reads.
+    fn load_upcase(&mut self, root: Option<&[u8]>) {
+        let table = match root.and_then(upcase_entry) {
+            Some((checksum, first_cluster, length)) => self.read_upcase(first_cluster, length)
+                .and_then(|table| validate_upcase(&table, checksum)),
+            None => Err(MosesError::Other("The root directory has no up-case table entry".to_string())),
+        };
+        match table {
+            Ok(table) => self.upcase = table,
+            Err(e) => {
+                warn!("{}; comparing names with the default up-case table", e);
+                self.upcase = upcase_map();
+                self.upcase_problem = Some(e.to_string());
+            }
+        }
+    }
+    
+    fn read_upcase(&mut self, first_cluster: u32, length: u64) -> Result<Vec<u8>, MosesError> {
+        if length == 0 || length > (UPCASE_ENTRIES * 2) as u64 {
+            return Err(MosesError::Other(format!("Up-case table is {} bytes", length)));
+        }
+        let clusters = length.div_ceil(self.bytes_per_cluster as u64) as usize;
+        let mut table = self.read_cluster_chain(first_cluster, Some(clusters))?;
+        if (table.len() as u64) < length {
+            return Err(MosesError::Other(format!(
+                "Up-case table chain holds {} of its {} bytes", table.len(), length
+            )));
+        }
+        table.truncate(length as usize);
+        Ok(table)
+    }
+    
+    /// Why the volume's up-case table was rejected, if it was
+    pub fn upcase_problem(&self) -> Option<&str> {
+        self.upcase_problem.as_deref()
+    }
+    
+    /// Whether two names are the same name on this volume
+    fn names_match(&self, a: &str, b: &str) -> bool {
+        let upper = |name: &str| name.encode_utf16().map(|unit| self.upcase[unit as usize]).collect::<Vec<_>>();
+        upper(a) == upper(b)
     }
     
     /// Read a cluster by number
@@ -259,7 +329,14 @@ impl ExFatReaderAligned {
                         is_directory: file_entry.file_attributes & 0x10 != 0,
                         size: stream_entry.data_length,
                         cluster: Some(stream_entry.first_cluster),
-                        metadata: FileMetadata::default(),
+                        metadata: entry_metadata(
+                            file_entry.file_attributes as u8,
+                            split_timestamp(file_entry.create_timestamp),
+                            split_timestamp(file_entry.last_modified_timestamp),
+                            split_timestamp(file_entry.last_accessed_timestamp).0,
+                            stream_entry.data_length,
+                            self.bytes_per_cluster,
+                        ),
                     });
                     
                     // Skip all the entries we just read
@@ -296,7 +373,7 @@ impl ExFatReaderAligned {
             let entries = self.parse_directory_entries(&data);
             
             let dir = entries.iter()
-                .find(|e| e.name.eq_ignore_ascii_case(part) && e.is_directory)
+                .find(|e| self.names_match(&e.name, part) && e.is_directory)
                 .ok_or_else(|| MosesError::Other(format!("Directory not found: {}", part)))?;
             
             current_cluster = dir.cluster.unwrap();
@@ -340,7 +417,7 @@ impl FilesystemReader for ExFatReaderAligned {
         
         // Find file
         let file = entries.iter()
-            .find(|e| e.name.eq_ignore_ascii_case(file_name) && !e.is_directory)
+            .find(|e| self.names_match(&e.name, file_name) && !e.is_directory)
             .ok_or_else(|| MosesError::Other(format!("File not found: {}", file_name)))?;
         
         // Read file data
@@ -363,7 +440,7 @@ impl FilesystemReader for ExFatReaderAligned {
         
         FilesystemInfo {
             fs_type: "exFAT".to_string(),
-            label: None, // Would need to read volume label from root directory
+            label: self.label.clone(),
             total_bytes,
             used_bytes: 0, // Would need to scan allocation bitmap
             cluster_size: Some(self.bytes_per_cluster),
@@ -371,5 +448,33 @@ impl FilesystemReader for ExFatReaderAligned {
     }
 }
 
+/// Text of the volume label entry (0x83): a character count then up to 11 UTF-16 units
+fn volume_label(root: &[u8]) -> Option<String> {
+    let entry = root.as_chunks::<32>().0.iter()
+        .take_while(|entry| entry[0] != 0x00)
+        .find(|entry| entry[0] == 0x83)?;
+    let count = (entry[1] as usize).min(11);
+    let units: Vec<u16> = entry[2..2 + count * 2].as_chunks::<2>().0.iter()
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// TableChecksum, FirstCluster and DataLength of the up-case table entry (0x82)
+fn upcase_entry(root: &[u8]) -> Option<(u32, u32, u64)> {
+    let entry = root.as_chunks::<32>().0.iter()
+        .take_while(|entry| entry[0] != 0x00)
+        .find(|entry| entry[0] == 0x82)?;
+    let checksum = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+    let first_cluster = u32::from_le_bytes(entry[20..24].try_into().unwrap());
+    let length = u64::from_le_bytes(entry[24..32].try_into().unwrap());
+    Some((checksum, first_cluster, length))
+}
+
+/// exFAT timestamps pack a DOS date in the high half and a DOS time in the low half
+fn split_timestamp(timestamp: u32) -> (u16, u16) {
+    ((timestamp >> 16) as u16, timestamp as u16)
+}
+
 // Re-export the structures that other modules might need
 pub use super::reader::ExFatFile;
\ No newline at end of file
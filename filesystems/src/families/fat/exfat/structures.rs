@@ -239,7 +239,7 @@ pub fn calculate_entry_set_checksum(entries: &[ExFatDirectoryEntry]) -> u16 {
             if i == 0 && (j == 2 || j == 3) {
                 continue;
             }
-            checksum = ((checksum << 15) | (checksum >> 1)) + byte as u16;
+            checksum = checksum.rotate_right(1).wrapping_add(byte as u16);
         }
     }
     
@@ -22,6 +22,10 @@ fn create_test_device(size: u64) -> Device {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -252,6 +256,25 @@ async fn validate_formatted_exfat(path: &str) -> Vec<String> {
 mod tests {
     use super::*;
     
+    /// First cluster and data length of the root directory entry of `entry_type`
+    /// (0x81 bitmap, 0x82 up-case)
+    fn root_entry_location(file: &mut File, vbr: &[u8], entry_type: u8) -> Option<(u32, u64)> {
+        let heap_offset = u32::from_le_bytes([vbr[88], vbr[89], vbr[90], vbr[91]]) as u64;
+        let root_cluster = u32::from_le_bytes([vbr[96], vbr[97], vbr[98], vbr[99]]) as u64;
+        let bytes_per_sector = 1u64 << vbr[108];
+        let cluster_size = bytes_per_sector << vbr[109];
+        
+        let mut root = vec![0u8; cluster_size as usize];
+        file.seek(SeekFrom::Start(heap_offset * bytes_per_sector + (root_cluster - 2) * cluster_size)).ok()?;
+        file.read_exact(&mut root).ok()?;
+        root.chunks(32)
+            .find(|entry| entry[0] == entry_type)
+            .map(|entry| (
+                u32::from_le_bytes([entry[20], entry[21], entry[22], entry[23]]),
+                u64::from_le_bytes(entry[24..32].try_into().unwrap()),
+            ))
+    }
+    
     #[tokio::test]
     async fn test_small_exfat() {
         // Test minimum exFAT size (about 512MB)
@@ -537,8 +560,8 @@ mod tests {
                 let mut vbr = vec![0u8; 128 * 512];
                 file.read_exact(&mut vbr).expect("Failed to read VBR");
                 
-               // Verify cluster size matches expected value (sectors_per_cluster shift at VBR offset 117)
-                let spc_shift = vbr[117];
+               // Verify cluster size matches expected value (sectors_per_cluster shift at VBR offset 109)
+                let spc_shift = vbr[109];
                 let actual_spc = 1u8 << spc_shift;
                 
                 assert_eq!(actual_spc, expected_spc as u8, "Cluster size mismatch for {} bytes", size);
@@ -602,19 +625,13 @@ mod tests {
         let mut vbr = vec![0u8; 128 * 512];
         file.read_exact(&mut vbr).expect("Failed to read VBR");
         
-       // Bitmap location is at VBR offset 100 (4 bytes) for start cluster
-        let bitmap_start = u32::from_le_bytes([
-            vbr[100], vbr[101], vbr[102], vbr[103],
-        ]);
+        // The bitmap is located through its root directory entry
+        let (bitmap_start, bitmap_length) = root_entry_location(&mut file, &vbr, 0x81)
+            .expect("Bitmap directory entry missing");
         
         // Bitmap should start at cluster 2 (after FAT)
         assert_eq!(bitmap_start, 2, "Bitmap should start at cluster 2");
         
-        // Bitmap length is at VBR offset 104 (4 bytes)
-        let bitmap_length = u32::from_le_bytes([
-            vbr[104], vbr[105], vbr[106], vbr[107],
-        ]);
-        
         assert!(bitmap_length > 0, "Bitmap length should be positive");
     }
     
@@ -647,10 +664,9 @@ mod tests {
         let mut vbr = vec![0u8; 128 * 512];
         file.read_exact(&mut vbr).expect("Failed to read VBR");
         
-        // Upcase table start is at VBR offset 116 (4 bytes)
-        let upcase_start = u32::from_le_bytes([
-            vbr[116], vbr[117], vbr[118], vbr[119],
-        ]);
+        // The up-case table is located through its root directory entry
+        let (upcase_start, upcase_length) = root_entry_location(&mut file, &vbr, 0x82)
+            .expect("Up-case table directory entry missing");
         
         // Upcase table should be after bitmap (cluster 3 or higher)
         assert!(
@@ -658,5 +674,22 @@ mod tests {
             "Upcase table should start at cluster 3 or higher, got {}",
             upcase_start
         );
+        assert_eq!(upcase_length, super::super::upcase::generate_upcase_table().len() as u64, "stored compressed");
+        
+        // The reader accepts the table and compares names through it
+        use crate::device_reader::FilesystemReader;
+        let mut reader = crate::ExFatReader::new(device.clone()).expect("Failed to open volume");
+        assert_eq!(reader.upcase_problem(), None);
+        assert!(reader.read_file("/readme.txt").is_ok());
+        
+        // A changed byte no longer matches TableChecksum
+        let heap_offset = u32::from_le_bytes([vbr[88], vbr[89], vbr[90], vbr[91]]) as u64;
+        let cluster_size = 512u64 << vbr[109];
+        let mut file = OpenOptions::new().write(true).open(&path).expect("Failed to open file");
+        file.seek(SeekFrom::Start(heap_offset * 512 + (upcase_start as u64 - 2) * cluster_size + 200)).unwrap();
+        file.write_all(&[0x5A]).unwrap();
+        drop(file);
+        let reader = crate::ExFatReader::new(device).expect("Failed to open volume");
+        assert!(reader.upcase_problem().unwrap().contains("checksum"));
     }
 }
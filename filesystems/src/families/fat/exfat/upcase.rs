@@ -1,112 +1,122 @@
-// exFAT Unicode upcase table generation
-// The upcase table is used for case-insensitive filename comparison
+// exFAT up-case table - generation, compression and checking
+// Names on exFAT compare without regard to case through the up-case table the volume carries,
+// and each name's hash is taken over its up-cased form. The table is written in the
+// compressed form the specification recommends: a run of characters that map to themselves
+// is stored as 0xFFFF followed by the run's length. TableChecksum in the up-case directory
+// entry covers the stored bytes; Windows and Linux check it, and some cameras and consoles
+// refuse a volume whose checksum or mandatory first 128 entries are wrong.
+use moses_core::MosesError;
+use std::sync::OnceLock;
 
-/// Generate the standard exFAT upcase table
-/// This table maps Unicode characters to their uppercase equivalents
-/// for case-insensitive filename comparison
+/// Characters the table maps, one per UTF-16 unit
+pub const UPCASE_ENTRIES: usize = 0x10000;
+/// Introduces a compressed run of characters that map to themselves
+const IDENTITY_RUN: u16 = 0xFFFF;
+/// Identity runs shorter than this are cheaper written out
+const MIN_COMPRESSED_RUN: usize = 3;
+/// The entries every table must have exactly: ASCII, with only a-z changed
+pub const MANDATORY_ENTRIES: usize = 128;
+
+/// The table the formatter writes, compressed
 pub fn generate_upcase_table() -> Vec<u8> {
-    let mut table = Vec::with_capacity(128 * 1024);
-    
-    // The upcase table contains 65536 entries (one for each UTF-16 code unit)
-    // Each entry is 2 bytes (UTF-16LE)
-    for i in 0u16..=0xFFFF {
-        let upper = to_upper_case(i);
-        table.extend_from_slice(&upper.to_le_bytes());
+    static TABLE: OnceLock<Vec<u8>> = OnceLock::new();
+    TABLE.get_or_init(|| compress_upcase(&upcase_map())).clone()
+}
+
+/// The uppercase form of every UTF-16 unit, indexed by the unit
+pub fn upcase_map() -> Vec<u16> {
+    (0..=0xFFFF).map(to_upper_case).collect()
+}
+
+/// The simple uppercase mapping of one UTF-16 unit. Units whose uppercase form is several
+/// characters or outside the BMP, and surrogates, map to themselves.
+pub fn to_upper_case(ch: u16) -> u16 {
+    let Some(c) = char::from_u32(ch as u32) else { return ch };
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) if (u as u32) <= 0xFFFF => u as u16,
+        _ => ch,
     }
-    
-    table
 }
 
-/// Convert a UTF-16 code unit to uppercase
-/// This implements the standard Unicode uppercase mapping
-fn to_upper_case(ch: u16) -> u16 {
-    match ch {
-        // ASCII lowercase to uppercase
-        0x0061..=0x007A => ch - 0x20,
-        
-        // Latin-1 Supplement lowercase
-        0x00E0..=0x00F6 | 0x00F8..=0x00FE => ch - 0x20,
-        
-        // Latin Extended-A
-        0x0100..=0x012F => {
-            if ch % 2 == 1 { ch - 1 } else { ch }
-        }
-        0x0132..=0x0137 => {
-            if ch % 2 == 1 { ch - 1 } else { ch }
+/// Store a full mapping compressed. A unit mapping to 0xFFFF other than 0xFFFF itself
+/// cannot be stored this way; no Unicode character uppercases to U+FFFF.
+pub fn compress_upcase(map: &[u16]) -> Vec<u8> {
+    let mut units = Vec::new();
+    let mut index = 0;
+    while index < map.len() {
+        let run = map[index..].iter().enumerate()
+            .take_while(|&(offset, &upper)| upper as usize == index + offset)
+            .take(IDENTITY_RUN as usize)
+            .count();
+        // A run marker at 0xFFFF would read as that unit's identity entry
+        if run >= MIN_COMPRESSED_RUN && index < IDENTITY_RUN as usize {
+            units.extend([IDENTITY_RUN, run as u16]);
+            index += run;
+        } else {
+            debug_assert!(map[index] != IDENTITY_RUN || index == IDENTITY_RUN as usize);
+            units.push(map[index]);
+            index += 1;
         }
-        0x0139..=0x0148 => {
-            if ch % 2 == 0 { ch - 1 } else { ch }
-        }
-        0x014A..=0x0177 => {
-            if ch % 2 == 1 { ch - 1 } else { ch }
+    }
+    units.iter().flat_map(|unit| unit.to_le_bytes()).collect()
+}
+
+/// Expand a table as stored on a volume, compressed or not, into the full mapping. Units the
+/// table does not reach map to themselves.
+pub fn expand_upcase(table: &[u8]) -> Result<Vec<u16>, MosesError> {
+    if !table.len().is_multiple_of(2) {
+        return Err(MosesError::Other(format!("Up-case table is {} bytes, not a whole number of units", table.len())));
+    }
+    let mut map: Vec<u16> = Vec::with_capacity(UPCASE_ENTRIES);
+    let mut units = table.as_chunks::<2>().0.iter().map(|unit| u16::from_le_bytes(*unit));
+    while let Some(unit) = units.next() {
+        if map.len() >= UPCASE_ENTRIES {
+            return Err(MosesError::Other(format!("Up-case table maps more than {} units", UPCASE_ENTRIES)));
         }
-        
-        // Latin Extended-B
-        0x0180..=0x01F5 => {
-            match ch {
-                0x0180 => 0x0243,
-                0x0183 => 0x0182,
-                0x0185 => 0x0184,
-                0x0188 => 0x0187,
-                0x018C => 0x018B,
-                0x0192 => 0x0191,
-                0x0195 => 0x01F6,
-                0x0199 => 0x0198,
-                0x019A => 0x023D,
-                0x019E => 0x0220,
-                0x01A1 | 0x01A3 | 0x01A5 | 0x01A8 | 0x01AD | 0x01B0 | 0x01B4 | 0x01B6 |
-                0x01B9 | 0x01BD | 0x01BF | 0x01C5 | 0x01C6 | 0x01C8 | 0x01C9 | 0x01CB |
-                0x01CC | 0x01CE | 0x01D0 | 0x01D2 | 0x01D4 | 0x01D6 | 0x01D8 | 0x01DA |
-                0x01DC | 0x01DD | 0x01DF | 0x01E1 | 0x01E3 | 0x01E5 | 0x01E7 | 0x01E9 |
-                0x01EB | 0x01ED | 0x01EF | 0x01F2 | 0x01F3 | 0x01F5 => ch - 1,
-                _ => ch,
+        if unit == IDENTITY_RUN && map.len() != IDENTITY_RUN as usize {
+            let run = units.next()
+                .ok_or_else(|| MosesError::Other("Up-case table ends inside a compressed run".to_string()))?;
+            let start = map.len();
+            if start + run as usize > UPCASE_ENTRIES {
+                return Err(MosesError::Other(format!("Up-case run of {} at 0x{:04X} passes 0xFFFF", run, start)));
             }
+            map.extend((start..start + run as usize).map(|unit| unit as u16));
+        } else {
+            map.push(unit);
         }
-        
-        // Greek lowercase
-        0x03B1..=0x03C9 => ch - 0x20,
-        
-        // Cyrillic lowercase
-        0x0430..=0x044F => ch - 0x20,
-        0x0450..=0x045F => ch - 0x50,
-        
-        // Special cases
-        0x00FF => 0x0178,  // ÿ -> Ÿ
-        0x0131 => 0x0049,  // ı -> I (Turkish)
-        0x017F => 0x0053,  // ſ -> S (long s)
-        0x0253 => 0x0181,  // ɓ -> Ɓ
-        0x0254 => 0x0186,  // ɔ -> Ɔ
-        0x0259 => 0x018F,  // ə -> Ə
-        0x025B => 0x0190,  // ɛ -> Ɛ
-        0x0260 => 0x0193,  // ɠ -> Ɠ
-        0x0263 => 0x0194,  // ɣ -> Ɣ
-        0x0268 => 0x0197,  // ɨ -> Ɨ
-        0x0269 => 0x0196,  // ɩ -> Ɩ
-        0x026F => 0x019C,  // ɯ -> Ɯ
-        0x0272 => 0x019D,  // ɲ -> Ɲ
-        0x0275 => 0x019F,  // ɵ -> Ɵ
-        0x0283 => 0x01A9,  // ʃ -> Ʃ
-        0x0288 => 0x01AE,  // ʈ -> Ʈ
-        0x028A => 0x01B1,  // ʊ -> Ʊ
-        0x028B => 0x01B2,  // ʋ -> Ʋ
-        0x0292 => 0x01B7,  // ʒ -> Ʒ
-        
-        // Default: character maps to itself
-        _ => ch,
     }
+    let start = map.len();
+    map.extend((start..UPCASE_ENTRIES).map(|unit| unit as u16));
+
+    if let Some(unit) = (0..MANDATORY_ENTRIES).find(|&unit| map[unit] != mandatory_upcase(unit as u16)) {
+        return Err(MosesError::Other(format!(
+            "Up-case table maps 0x{:02X} to 0x{:04X}; the first {} entries must be ASCII",
+            unit, map[unit], MANDATORY_ENTRIES
+        )));
+    }
+    Ok(map)
+}
+
+fn mandatory_upcase(unit: u16) -> u16 {
+    if (b'a' as u16..=b'z' as u16).contains(&unit) { unit - 0x20 } else { unit }
 }
 
-/// Calculate checksum for the upcase table (for validation)
+/// TableChecksum over the table as stored
 pub fn calculate_upcase_checksum(table: &[u8]) -> u32 {
-    let mut checksum = 0u32;
-    
-    // Process as 32-bit words
-    for chunk in table.chunks_exact(4) {
-        let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-        checksum = ((checksum << 31) | (checksum >> 1)).wrapping_add(value);
+    table.iter().fold(0u32, |checksum, &byte| checksum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+/// Check a table read from a volume against the TableChecksum of its directory entry, and
+/// expand it
+pub fn validate_upcase(table: &[u8], checksum: u32) -> Result<Vec<u16>, MosesError> {
+    let actual = calculate_upcase_checksum(table);
+    if actual != checksum {
+        return Err(MosesError::Other(format!(
+            "Up-case table checksum is 0x{:08X}; its directory entry says 0x{:08X}", actual, checksum
+        )));
     }
-    
-    checksum
+    expand_upcase(table)
 }
 
 /// Verify if a character needs case conversion
@@ -119,13 +129,13 @@ pub fn compare_filenames(name1: &[u16], name2: &[u16]) -> bool {
     if name1.len() != name2.len() {
         return false;
     }
-    
+
     for (&ch1, &ch2) in name1.iter().zip(name2.iter()) {
         if to_upper_case(ch1) != to_upper_case(ch2) {
             return false;
         }
     }
-    
+
     true
 }
 
@@ -142,7 +152,7 @@ pub fn utf16le_to_utf8(data: &[u16]) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_ascii_upcase() {
         assert_eq!(to_upper_case(b'a' as u16), b'A' as u16);
@@ -150,34 +160,61 @@ mod tests {
         assert_eq!(to_upper_case(b'A' as u16), b'A' as u16);
         assert_eq!(to_upper_case(b'0' as u16), b'0' as u16);
     }
-    
+
     #[test]
     fn test_latin1_upcase() {
         assert_eq!(to_upper_case(0x00E0), 0x00C0);  // à -> À
         assert_eq!(to_upper_case(0x00F1), 0x00D1);  // ñ -> Ñ
         assert_eq!(to_upper_case(0x00FF), 0x0178);  // ÿ -> Ÿ
+        assert_eq!(to_upper_case(0x00DF), 0x00DF);  // ß uppercases to two characters
     }
-    
+
     #[test]
     fn test_cyrillic_upcase() {
         assert_eq!(to_upper_case(0x0430), 0x0410);  // а -> А
         assert_eq!(to_upper_case(0x044F), 0x042F);  // я -> Я
     }
-    
+
     #[test]
     fn test_filename_comparison() {
         let name1 = utf8_to_utf16le("test.txt");
         let name2 = utf8_to_utf16le("TEST.TXT");
         assert!(compare_filenames(&name1, &name2));
-        
+
         let name3 = utf8_to_utf16le("файл.dat");
         let name4 = utf8_to_utf16le("ФАЙЛ.DAT");
         assert!(compare_filenames(&name3, &name4));
     }
-    
+
     #[test]
-    fn test_upcase_table_size() {
+    fn test_upcase_table_compression() {
         let table = generate_upcase_table();
-        assert_eq!(table.len(), 128 * 1024);  // Should be exactly 128KB
+        assert!(table.len() < 16 * 1024, "compressed table is {} bytes", table.len());
+        assert_eq!(table[..4], [0xFF, 0xFF, 0x61, 0x00], "0x00-0x60 are one identity run");
+        assert_eq!(expand_upcase(&table).unwrap(), upcase_map());
+
+        // An uncompressed table expands to itself
+        let full: Vec<u8> = upcase_map().iter().flat_map(|unit| unit.to_le_bytes()).collect();
+        assert_eq!(full.len(), 128 * 1024);
+        assert_eq!(expand_upcase(&full).unwrap(), upcase_map());
+    }
+
+    #[test]
+    fn test_upcase_checksum_and_validation() {
+        // Rotate right, then add the byte
+        assert_eq!(calculate_upcase_checksum(&[1, 0, 0, 0]), 0x2000_0000);
+        assert_eq!(calculate_upcase_checksum(&[1, 0]), 0x8000_0000);
+
+        let table = generate_upcase_table();
+        let checksum = calculate_upcase_checksum(&table);
+        assert!(validate_upcase(&table, checksum).is_ok());
+        assert!(validate_upcase(&table, checksum ^ 1).is_err());
+
+        // 'a' left mapping to itself breaks the mandatory entries
+        let mut lower = upcase_map();
+        lower[b'a' as usize] = b'a' as u16;
+        assert!(expand_upcase(&compress_upcase(&lower)).is_err());
+        assert!(expand_upcase(&[0xFF, 0xFF]).is_err(), "run without a length");
+        assert!(expand_upcase(&[0x00, 0x00, 0x01, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]).is_err(), "run past 0xFFFF");
     }
-}
\ No newline at end of file
+}
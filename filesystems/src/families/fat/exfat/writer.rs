@@ -601,9 +601,8 @@ impl ExFatWriter {
             warn!("Bitmap write-back not yet implemented");
         }
         
-        self.file.flush()
-            .map_err(|e| MosesError::IoError(e))?;
-        Ok(())
+        // Barrier, so a completed operation survives a power loss
+        crate::utils::flush_device(&self.file)
     }
 }
 
@@ -109,7 +109,7 @@ impl FilesystemFormatter for Fat16Formatter {
     
     fn can_format(&self, device: &Device) -> bool {
         // Don't format system drives
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
@@ -122,6 +122,8 @@ impl FilesystemFormatter for Fat16Formatter {
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         let (_sectors_per_cluster, sectors_per_fat, root_entries) = 
             Self::calculate_fat16_params(device.size)?;
         
@@ -141,6 +143,12 @@ impl FilesystemFormatter for Fat16Formatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: device.size - overhead,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
     
@@ -276,8 +284,7 @@ impl FilesystemFormatter for Fat16Formatter {
         file.write_all(&root_dir)
             .map_err(|e| MosesError::Other(format!("Failed to write root directory: {}", e)))?;
         
-        file.flush()
-            .map_err(|e| MosesError::Other(format!("Failed to flush: {}", e)))?;
+        crate::utils::flush_device(&file)?;
         
         info!("FAT16 format completed successfully");
         Ok(())
@@ -7,8 +7,10 @@ use std::io::{Write, Seek, SeekFrom};
 use log::{info, warn};
 use crate::families::fat::common::{
     Fat16BootSector, generate_volume_serial, format_volume_label,
-    init_fat16_table, write_fat_tables, get_media_descriptor
+    init_fat16_table, write_fat_tables, get_media_descriptor, DosCompatibility
 };
+use crate::volume_serial::serial_for_format;
+use crate::deterministic::FormatSeed;
 
 pub struct Fat16CompliantFormatter;
 
@@ -78,18 +80,21 @@ impl Fat16CompliantFormatter {
         sectors_per_fat: u16,
         root_entries: u16,
         hidden_sectors: u32,
+        reserved_sectors: u16,
+        compat: &DosCompatibility,
         volume_label: Option<&str>,
+        volume_serial: u32,
     ) -> Vec<u8> {
         // Create a proper FAT16 boot sector using the common structure
         let mut boot_sector = Fat16BootSector::new();
         
-        // Set OEM name to Windows 4.1 for compatibility
-        boot_sector.common_bpb.oem_name = *b"MSWIN4.1";
+        // MSWIN4.1 unless the compatibility options ask for another
+        boot_sector.common_bpb.oem_name = compat.oem_name;
         
         // Set BPB fields
         boot_sector.common_bpb.bytes_per_sector = 512;
         boot_sector.common_bpb.sectors_per_cluster = sectors_per_cluster;
-        boot_sector.common_bpb.reserved_sectors = 1;
+        boot_sector.common_bpb.reserved_sectors = reserved_sectors;
         boot_sector.common_bpb.num_fats = 2;
         boot_sector.common_bpb.root_entries = root_entries;
         
@@ -102,20 +107,20 @@ impl Fat16CompliantFormatter {
             boot_sector.common_bpb.total_sectors_32 = total_sectors as u32;
         }
         
-        // Media descriptor: 0xF0 for removable, 0xF8 for fixed
-        boot_sector.common_bpb.media_descriptor = get_media_descriptor(device.is_removable);
+        // Media descriptor: 0xF0 for removable, 0xF8 for fixed, unless one was asked for
+        boot_sector.common_bpb.media_descriptor = compat.media_descriptor;
         
         // FAT16-specific fields
         boot_sector.common_bpb.sectors_per_fat_16 = sectors_per_fat;
-        boot_sector.common_bpb.sectors_per_track = 63;
-        boot_sector.common_bpb.num_heads = 255;
+        boot_sector.common_bpb.sectors_per_track = compat.sectors_per_track;
+        boot_sector.common_bpb.num_heads = compat.heads;
         boot_sector.common_bpb.hidden_sectors = hidden_sectors;
         
         // Extended BPB fields
         boot_sector.extended_bpb.drive_number = if device.is_removable { 0x00 } else { 0x80 };
         boot_sector.extended_bpb.reserved = 0;
         boot_sector.extended_bpb.boot_signature = 0x29;
-        boot_sector.extended_bpb.volume_id = generate_volume_serial();
+        boot_sector.extended_bpb.volume_id = volume_serial;
         
         // Volume label
         boot_sector.extended_bpb.volume_label = format_volume_label(volume_label);
@@ -178,14 +183,19 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
             }
         }
         
+        DosCompatibility::from_options(options, 0, 0xF8)?;
+        FormatSeed::from_options(options)?;
+        
         Ok(())
     }
     
     fn can_format(&self, device: &Device) -> bool {
-        !device.is_system && device.size <= 4 * 1024 * 1024 * 1024 // Max 4GB for FAT16
+        !device.is_system && !device.is_write_protected && device.size <= 4 * 1024 * 1024 * 1024 // Max 4GB for FAT16
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         let (_sectors_per_cluster, sectors_per_fat, root_entries) = 
             Self::calculate_fat16_params(device.size, options.cluster_size)?;
         
@@ -193,24 +203,41 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         let root_dir_size = root_entries as u64 * 32;
         let overhead = 512 + fat_size + root_dir_size; // Boot sector + FATs + Root
         
+        let mut warnings = Vec::new();
+        if device.size > 2 * 1024 * 1024 * 1024 {
+            warnings.push("Volume larger than 2GB may have compatibility issues with FAT16".to_string());
+        }
+        if DosCompatibility::requested(options) {
+            let compat = DosCompatibility::from_options(options, device.size, get_media_descriptor(device.is_removable))?;
+            warnings.push(describe_compatibility(&compat));
+        }
+        
         Ok(SimulationReport {
             device: device.clone(),
             options: options.clone(),
             estimated_time: std::time::Duration::from_secs(2),
-            warnings: if device.size > 2 * 1024 * 1024 * 1024 {
-                vec!["Volume larger than 2GB may have compatibility issues with FAT16".to_string()]
-            } else {
-                vec![]
-            },
+            warnings,
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: device.size - overhead,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
     
     async fn format(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
         info!("Starting FAT16 compliant format for device: {}", device.name);
         
+        let seed = FormatSeed::from_options(options)?;
+        let volume_serial = serial_for_format(device, options)?
+            .map(|kept| kept.short())
+            .or_else(|| seed.serial32("volume serial"))
+            .unwrap_or_else(generate_volume_serial);
+        
         // Check if we should create a partition table
         let create_partition = options.additional_options
             .get("create_partition_table")
@@ -219,12 +246,19 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         
         info!("Partition table creation: {}", if create_partition { "enabled" } else { "disabled (direct format)" });
         
+        let compat = DosCompatibility::from_options(options, device.size, get_media_descriptor(device.is_removable))?;
+        if DosCompatibility::requested(options) {
+            info!("{}", describe_compatibility(&compat));
+        }
+        
         // Calculate parameters based on partition size
         let (partition_size, partition_offset, hidden_sectors) = if create_partition {
-            // Partition starts at sector 2048 (1MB offset) for alignment
-            let offset = 2048 * 512;
+            // Partition starts at sector 2048 (1MB offset) for alignment, or on the
+            // second track with a DOS geometry
+            let start = compat.partition_start();
+            let offset = start as u64 * 512;
             let size = device.size - offset;
-            (size, offset, 2048u32)
+            (size, offset, start)
         } else {
             (device.size, 0u64, 0u32)
         };
@@ -233,8 +267,13 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         let (sectors_per_cluster, sectors_per_fat, root_entries) = 
             Self::calculate_fat16_params(partition_size, options.cluster_size)?;
         
-        info!("FAT16 parameters: {} sectors, {} sectors/cluster, {} sectors/FAT, {} root entries",
-              total_sectors, sectors_per_cluster, sectors_per_fat, root_entries);
+        // Grow the reserved area so the first cluster starts on a track when asked to
+        let root_dir_sectors = (root_entries as u64 * 32).div_ceil(512);
+        let padding = compat.track_padding(hidden_sectors as u64 + 1 + 2 * sectors_per_fat as u64 + root_dir_sectors);
+        let reserved_sectors = 1 + padding as u16;
+        
+        info!("FAT16 parameters: {} sectors, {} sectors/cluster, {} sectors/FAT, {} root entries, {} reserved sectors",
+              total_sectors, sectors_per_cluster, sectors_per_fat, root_entries, reserved_sectors);
         
         // Create boot sector bytes
         let boot_sector_bytes = Self::create_boot_sector_bytes(
@@ -244,7 +283,10 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
             sectors_per_fat,
             root_entries,
             hidden_sectors,
+            reserved_sectors,
+            &compat,
             options.label.as_deref(),
+            volume_serial,
         );
         
         // Verify boot sector has correct data
@@ -265,13 +307,15 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         if create_partition {
             info!("Creating MBR partition table");
             
-            use crate::partitioner::{create_single_partition_table, PartitionTableType, write_partition_table};
+            use crate::partitioner::{
+                create_single_partition_table, create_mbr_partition_table_with_geometry, PartitionTableType, write_partition_table,
+            };
             
-            let partition_table = create_single_partition_table(
-                device,
-                PartitionTableType::MBR,
-                "fat16"
-            )?;
+            let partition_table = if compat.dos_partition {
+                create_mbr_partition_table_with_geometry(device, "fat16", hidden_sectors, compat.heads, compat.sectors_per_track)?
+            } else {
+                create_single_partition_table(device, PartitionTableType::MBR, "fat16")?
+            };
             
             // Write the partition table
             write_partition_table(&mut file, &partition_table)?;
@@ -297,20 +341,23 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         file.write_all(&boot_sector_bytes)
             .map_err(|e| MosesError::Other(format!("Failed to write boot sector: {}", e)))?;
         info!("Boot sector written successfully");
+        if padding > 0 {
+            file.write_all(&vec![0u8; padding as usize * 512])
+                .map_err(|e| MosesError::Other(format!("Failed to clear reserved sectors: {}", e)))?;
+        }
         
         // Create and initialize FAT tables using common helper
         let fat_size = sectors_per_fat as usize * 512;
         let mut fat = vec![0u8; fat_size];
         
-        // Use common FAT initialization
-        let media_descriptor = get_media_descriptor(device.is_removable);
-        init_fat16_table(&mut fat, media_descriptor);
+        // Use common FAT initialization; FAT[0] repeats the media descriptor
+        init_fat16_table(&mut fat, compat.media_descriptor);
         
         // Write FAT tables using common helper
         write_fat_tables(
             &mut file,
             &fat,
-            1,  // FAT starts at sector 1 (after boot sector)
+            (partition_offset / 512) + reserved_sectors as u64,  // FATs follow the reserved sectors
             sectors_per_fat as u32,
             2,  // Number of FATs
             512 // Bytes per sector
@@ -318,7 +365,7 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         
         // Initialize root directory with volume label
         use crate::families::fat::fat16::root_directory::create_root_directory_with_label;
-        let root_dir = create_root_directory_with_label(root_entries, options.label.as_deref());
+        let root_dir = create_root_directory_with_label(root_entries, options.label.as_deref(), seed.unix_time());
         file.write_all(&root_dir)
             .map_err(|e| MosesError::Other(format!("Failed to write root directory: {}", e)))?;
         
@@ -330,4 +377,14 @@ impl FilesystemFormatter for Fat16CompliantFormatter {
         info!("FAT16 compliant format completed successfully");
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// One line on the geometry a compatibility format writes, for logs and dry runs
+pub(crate) fn describe_compatibility(compat: &DosCompatibility) -> String {
+    format!(
+        "DOS compatibility: {} heads, {} sectors/track, media 0x{:02X}, OEM '{}'{}",
+        compat.heads, compat.sectors_per_track, compat.media_descriptor,
+        String::from_utf8_lossy(&compat.oem_name).trim_end(),
+        if compat.align_to_track { ", clusters aligned to tracks" } else { "" }
+    )
+}
@@ -1,10 +1,11 @@
 // FAT16 FilesystemOps implementation for mounting
-use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo};
+use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo, AttributeChanges};
 use crate::device_reader::FilesystemReader;
 use crate::ops_helpers::convert_filesystem_info;
 use super::reader::Fat16Reader;
 use super::writer::Fat16Writer;
 use super::file_ops::Fat16FileOps;
+use crate::families::fat::common::dirty::FatDirtyState;
 use moses_core::{Device, MosesError};
 use std::path::Path;
 use std::sync::Mutex;
@@ -15,6 +16,8 @@ pub struct Fat16Ops {
     writer: Mutex<Option<Fat16Writer>>,
     file_ops: Mutex<Option<Fat16FileOps>>,
     device: Option<Device>,
+    /// Unclean-shutdown marks found at init
+    dirty: FatDirtyState,
 }
 
 impl Fat16Ops {
@@ -24,6 +27,7 @@ impl Fat16Ops {
             writer: Mutex::new(None),
             file_ops: Mutex::new(None),
             device: None,
+            dirty: FatDirtyState::default(),
         }
     }
 }
@@ -47,6 +51,19 @@ impl FilesystemOps for Fat16Ops {
         // For now, we'll keep them separate and create file_ops on demand
         
         self.device = Some(device.clone());
+        self.dirty = FatDirtyState::read_device(device).unwrap_or_default();
+        for warning in self.dirty.warnings() {
+            log::warn!("{}: {}", device.name, warning);
+        }
+        Ok(())
+    }
+    
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let device = self.device.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        crate::attributes::set_attributes(device, "fat16", path, changes)?;
+        // The reader caches directory entries, which now hold the old attributes
+        *self.reader.lock().unwrap() = Some(Fat16Reader::new(device.clone())?);
         Ok(())
     }
     
@@ -160,4 +177,8 @@ impl FilesystemOps for Fat16Ops {
         let end = std::cmp::min(start + size as usize, data.len());
         Ok(data[start..end].to_vec())
     }
+    
+    fn warnings(&self) -> Vec<String> {
+        self.dirty.warnings()
+    }
 }
\ No newline at end of file
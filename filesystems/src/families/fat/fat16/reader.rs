@@ -1,8 +1,9 @@
 // FAT16 filesystem reader
 
 use moses_core::{Device, MosesError};
-use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo, FileMetadata};
+use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo};
 use crate::families::fat::common::{Fat16BootSector, FatDirEntry, FatAttributes};
+use crate::families::fat::common::entry_metadata;
 use log::{info, debug};
 use std::collections::HashMap;
 
@@ -25,6 +26,9 @@ pub struct Fat16Reader {
     first_data_sector: u32,
     total_clusters: u32,
     
+    /// The volume label, from the root directory entry when there is one
+    label: Option<String>,
+    
     // Cache
     fat_cache: HashMap<u16, u16>,
     dir_cache: HashMap<String, Vec<FileEntry>>,
@@ -83,6 +87,10 @@ impl Fat16Reader {
         info!("  First data sector: {}", first_data_sector);
         info!("  Total clusters: {}", total_clusters);
         
+        // Windows shows the root directory's label entry, so that copy wins over the boot sector's
+        let label = crate::families::fat::common::label::read_labels(&mut reader).ok()
+            .and_then(|labels| labels.effective());
+        
         Ok(Self {
             _device: device,
             reader,
@@ -95,6 +103,7 @@ impl Fat16Reader {
             root_dir_sectors,
             first_data_sector,
             total_clusters,
+            label,
             fat_cache: HashMap::new(),
             dir_cache: HashMap::new(),
         })
@@ -233,7 +242,14 @@ impl Fat16Reader {
                 is_directory: entry.attributes & FatAttributes::DIRECTORY != 0,
                 size: if entry.attributes & FatAttributes::DIRECTORY != 0 { 0 } else { entry.file_size as u64 },
                 cluster: Some(entry.first_cluster() as u32),
-                metadata: FileMetadata::default(),
+                metadata: entry_metadata(
+                    entry.attributes,
+                    (entry.creation_date, entry.creation_time),
+                    (entry.write_date, entry.write_time),
+                    entry.last_access_date,
+                    entry.file_size as u64,
+                    self.bytes_per_cluster,
+                ),
             });
             
             i += 32;
@@ -362,10 +378,9 @@ impl FilesystemReader for Fat16Reader {
     
     fn get_info(&self) -> FilesystemInfo {
         let total_bytes = self.total_clusters as u64 * self.bytes_per_cluster as u64;
-        
         FilesystemInfo {
             fs_type: "FAT16".to_string(),
-            label: None, // Would need to read volume label
+            label: self.label.clone(),
             total_bytes,
             used_bytes: 0, // Would need to scan FAT
             cluster_size: Some(self.bytes_per_cluster),
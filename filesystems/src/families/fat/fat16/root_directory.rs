@@ -1,6 +1,6 @@
 // FAT16 Root Directory Entry structures and creation
 
-use std::time::SystemTime;
+use crate::families::fat::common::unix_to_fat_datetime;
 
 /// FAT16 Directory Entry (32 bytes)
 #[repr(C, packed(1))]
@@ -54,8 +54,8 @@ impl Fat16DirEntry {
         entry
     }
     
-    /// Create a volume label entry
-    pub fn volume_label(label: Option<&str>) -> Self {
+    /// Create a volume label entry stamped with `timestamp` (seconds since the Unix epoch)
+    pub fn volume_label(label: Option<&str>, timestamp: u64) -> Self {
         let mut entry = Self {
             name: [b' '; 8],
             ext: [b' '; 3],
@@ -91,8 +91,8 @@ impl Fat16DirEntry {
             entry.name[..7].copy_from_slice(b"NO NAME");
         }
         
-        // Set creation date/time to current time
-        let (date, time) = get_dos_datetime();
+        // Set creation date/time
+        let (date, time) = unix_to_fat_datetime(timestamp);
         entry.creation_date = date;
         entry.creation_time = time;
         entry.write_date = date;
@@ -103,50 +103,15 @@ impl Fat16DirEntry {
     }
 }
 
-/// Convert current system time to DOS date/time format
-fn get_dos_datetime() -> (u16, u16) {
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default();
-    
-    // Simple conversion (not accounting for timezone)
-    // DOS epoch is January 1, 1980
-    let seconds_since_1980 = now.as_secs().saturating_sub(315532800); // Unix time for 1980-01-01
-    let days_since_1980 = (seconds_since_1980 / 86400) as u32;
-    
-    // Approximate date calculation (simplified, doesn't handle leap years perfectly)
-    let years = days_since_1980 / 365;
-    let remaining_days = days_since_1980 % 365;
-    let months = remaining_days / 30;
-    let days = (remaining_days % 30) + 1;
-    
-    // DOS date format: bits 15-9: year (0-127, from 1980), bits 8-5: month (1-12), bits 4-0: day (1-31)
-    let dos_date = ((years.min(127) as u16) << 9) | 
-                   (((months + 1).min(12) as u16) << 5) | 
-                   (days.min(31) as u16);
-    
-    // Time within the day
-    let seconds_today = seconds_since_1980 % 86400;
-    let hours = seconds_today / 3600;
-    let minutes = (seconds_today % 3600) / 60;
-    let seconds = (seconds_today % 60) / 2; // DOS stores seconds/2
-    
-    // DOS time format: bits 15-11: hours (0-23), bits 10-5: minutes (0-59), bits 4-0: seconds/2 (0-29)
-    let dos_time = ((hours.min(23) as u16) << 11) | 
-                   ((minutes.min(59) as u16) << 5) | 
-                   (seconds.min(29) as u16);
-    
-    (dos_date, dos_time)
-}
-
 /// Create a root directory with volume label
-pub fn create_root_directory_with_label(root_entries: u16, volume_label: Option<&str>) -> Vec<u8> {
+pub fn create_root_directory_with_label(root_entries: u16, volume_label: Option<&str>, timestamp: u64) -> Vec<u8> {
     let root_dir_size = root_entries as usize * 32;
     let mut root_dir = vec![0u8; root_dir_size];
     
-    // First entry is the volume label
-    if volume_label.is_some() || true { // Always create a volume label entry
-        let label_entry = Fat16DirEntry::volume_label(volume_label);
+    // First entry is the volume label; an unlabelled volume has none, only "NO NAME" in
+    // the boot sector, as Windows formats it
+    if volume_label.is_some_and(|label| !label.trim().is_empty()) {
+        let label_entry = Fat16DirEntry::volume_label(volume_label, timestamp);
         
         // Write the volume label entry at the beginning
         let entry_bytes = unsafe {
@@ -170,7 +135,7 @@ mod tests {
     
     #[test]
     fn test_volume_label_creation() {
-        let label_entry = Fat16DirEntry::volume_label(Some("TEST DISK"));
+        let label_entry = Fat16DirEntry::volume_label(Some("TEST DISK"), 0);
         
         assert_eq!(&label_entry.name, b"TEST DIS");
         assert_eq!(&label_entry.ext, b"K  ");
@@ -184,7 +149,7 @@ mod tests {
     
     #[test]
     fn test_short_label() {
-        let label_entry = Fat16DirEntry::volume_label(Some("MYDISK"));
+        let label_entry = Fat16DirEntry::volume_label(Some("MYDISK"), 0);
         
         assert_eq!(&label_entry.name, b"MYDISK  ");
         assert_eq!(&label_entry.ext, b"   ");
@@ -192,7 +157,7 @@ mod tests {
     
     #[test]
     fn test_root_directory_creation() {
-        let root_dir = create_root_directory_with_label(512, Some("TEST VOL"));
+        let root_dir = create_root_directory_with_label(512, Some("TEST VOL"), 0);
         
         assert_eq!(root_dir.len(), 512 * 32);
         
@@ -36,7 +36,7 @@ impl FilesystemFormatter for Fat16SystemFormatter {
     
     fn can_format(&self, device: &Device) -> bool {
         // Don't format system drives
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
@@ -49,6 +49,8 @@ impl FilesystemFormatter for Fat16SystemFormatter {
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         Ok(SimulationReport {
             device: device.clone(),
             options: options.clone(),
@@ -61,6 +63,12 @@ impl FilesystemFormatter for Fat16SystemFormatter {
             required_tools: vec!["format.com".to_string()],
             will_erase_data: true,
             space_after_format: device.size - (64 * 1024), // Approximate overhead
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
     
@@ -93,11 +101,7 @@ impl FilesystemFormatter for Fat16SystemFormatter {
                 });
             
             // Extract disk number from device ID
-            let disk_number = if let Some(num_str) = device.id.strip_prefix("\\\\.\\PHYSICALDRIVE") {
-                num_str.parse::<u32>().ok()
-            } else {
-                None
-            };
+            let disk_number = moses_core::physical_drive_number(&device.id);
             
             if let Some(disk_num) = disk_number {
                 if create_partition {
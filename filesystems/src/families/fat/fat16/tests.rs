@@ -22,6 +22,10 @@ fn create_test_device(size: u64) -> Device {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -553,4 +557,60 @@ mod tests {
         // Standard FAT16 uses 512 root entries
         assert_eq!(root_entries, 512, "Root entries should be 512 for standard FAT16");
     }
+    
+    #[tokio::test]
+    async fn test_dos_compatibility() {
+        // DOS geometry: partition on the second track, clusters aligned to tracks
+        let size = 200 * 1024 * 1024;
+        let temp_file = create_test_image(size).expect("Failed to create test image");
+        let path = temp_file.path().to_str().unwrap().to_string();
+        let mut device = create_test_device(size);
+        device.id = path.clone();
+        
+        let options = FormatOptions {
+            filesystem_type: "fat16".to_string(),
+            label: Some("DOS".to_string()),
+            additional_options: [
+                ("create_partition_table", "true"),
+                ("dos_geometry", "16/63"),
+                ("media_descriptor", "F8"),
+                ("oem_name", "MSDOS5.0"),
+                ("align_to_track", "true"),
+            ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        let formatter = super::Fat16Formatter;
+        formatter.format(&device, &options).await.expect("Format failed");
+        
+        let mut file = File::open(&path).expect("Failed to open file");
+        let mut mbr = [0u8; 512];
+        file.read_exact(&mut mbr).unwrap();
+        assert_eq!(u32::from_le_bytes(mbr[454..458].try_into().unwrap()), 63, "partition starts on the second track");
+        assert_eq!(&mbr[447..450], &[1, 1, 0], "CHS start is head 1, sector 1, cylinder 0");
+        
+        let mut boot_sector = [0u8; 512];
+        file.seek(SeekFrom::Start(63 * 512)).unwrap();
+        file.read_exact(&mut boot_sector).unwrap();
+        assert_eq!(&boot_sector[3..11], b"MSDOS5.0");
+        assert_eq!(boot_sector[21], 0xF8);
+        assert_eq!(u16::from_le_bytes([boot_sector[24], boot_sector[25]]), 63);
+        assert_eq!(u16::from_le_bytes([boot_sector[26], boot_sector[27]]), 16);
+        
+        let reserved = u16::from_le_bytes([boot_sector[14], boot_sector[15]]) as u64;
+        let sectors_per_fat = u16::from_le_bytes([boot_sector[22], boot_sector[23]]) as u64;
+        let root_sectors = u16::from_le_bytes([boot_sector[17], boot_sector[18]]) as u64 * 32 / 512;
+        assert_eq!((63 + reserved + 2 * sectors_per_fat + root_sectors) % 63, 0, "data area starts on a track");
+        
+        let mut fat = [0u8; 2];
+        file.seek(SeekFrom::Start((63 + reserved) * 512)).unwrap();
+        file.read_exact(&mut fat).unwrap();
+        assert_eq!(fat, [0xF8, 0xFF], "FAT[0] repeats the media byte");
+        
+        let report = crate::families::fat::fat16::Fat16Validator::validate(&path, Some(63)).unwrap();
+        assert!(report.is_valid, "{:?}", report.errors);
+        
+        let mut bad = options.clone();
+        bad.additional_options.insert("media_descriptor".to_string(), "F1".to_string());
+        assert!(formatter.validate_options(&bad).await.is_err());
+    }
 }
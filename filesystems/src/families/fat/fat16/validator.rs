@@ -6,6 +6,7 @@ use std::io::{Read, Seek, SeekFrom};
 use std::collections::HashMap;
 use moses_core::MosesError;
 use crate::families::fat::common::structures::Fat16BootSector;
+use crate::families::fat::common::label::read_labels;
 
 // ============================================================================
 // Format-time Parameter Validation (from validation.rs)
@@ -193,7 +194,13 @@ impl Fat16Validator {
         Self::calculate_cluster_info(&boot_sector, &mut report);
         
         // Validate FAT tables
-        Self::validate_fat_tables(&mut file, &boot_sector, &mut report)?;
+        Self::validate_fat_tables(&mut file, offset_bytes, &boot_sector, &mut report)?;
+        
+        // The boot sector label and the root directory label entry must agree
+        let mut volume = crate::allocation::VolumeReader { inner: &mut file, start: offset_bytes };
+        if let Some(mismatch) = read_labels(&mut volume).ok().and_then(|labels| labels.mismatch()) {
+            report.warnings.push(mismatch);
+        }
         
         Ok(report)
     }
@@ -398,6 +405,7 @@ impl Fat16Validator {
     
     fn validate_fat_tables(
         file: &mut File,
+        offset_bytes: u64,
         boot: &Fat16BootSector,
         report: &mut ValidationReport
     ) -> Result<(), std::io::Error> {
@@ -407,14 +415,14 @@ impl Fat16Validator {
         let sectors_per_fat = boot.common_bpb.sectors_per_fat_16;
         
         // Seek to first FAT
-        file.seek(SeekFrom::Start(reserved_sectors as u64 * 512))?;
+        file.seek(SeekFrom::Start(offset_bytes + reserved_sectors as u64 * 512))?;
         
         // Read first 4 bytes of FAT
         let mut fat_start = [0u8; 4];
         file.read_exact(&mut fat_start)?;
         
-        // First FAT entry should be F8 FF FF FF or F0 FF FF FF
-        let expected_first = if media_descriptor == 0xF8 { 0xF8 } else { 0xF0 };
+        // First FAT entry repeats the media descriptor: F8 FF FF FF, F0 FF FF FF, ...
+        let expected_first = media_descriptor;
         if fat_start[0] != expected_first {
             report.errors.push(format!(
                 "FAT[0] low byte 0x{:02X} doesn't match media descriptor 0x{:02X}",
@@ -434,7 +442,7 @@ impl Fat16Validator {
         // Count free clusters (simplified - just check for 0x0000 entries)
         let fat_size_bytes = sectors_per_fat as usize * 512;
         let mut fat_data = vec![0u8; fat_size_bytes];
-        file.seek(SeekFrom::Start(reserved_sectors as u64 * 512))?;
+        file.seek(SeekFrom::Start(offset_bytes + reserved_sectors as u64 * 512))?;
         file.read_exact(&mut fat_data)?;
         
         let mut free_count = 0u64;
@@ -112,19 +112,9 @@ impl Fat32Formatter {
     
     #[cfg(target_os = "linux")]
     async fn format_linux(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
-        // Check if mkfs.fat is available
-        let check = Command::new("which")
-            .arg("mkfs.fat")
-            .output()
-            .map_err(|e| MosesError::Other(format!("Failed to check for mkfs.fat: {}", e)))?;
-        
-        if !check.status.success() {
-            return Err(MosesError::ExternalToolMissing(
-                "mkfs.fat not found. Please install dosfstools package".to_string()
-            ));
-        }
+        let tool = crate::tools::ToolManager::global().ensure("dosfstools", &["mkfs.fat"])?;
         
-        let mut cmd = Command::new("mkfs.fat");
+        let mut cmd = Command::new(&tool);
         cmd.arg("-F").arg("32"); // FAT32
         
         if let Some(ref label) = options.label {
@@ -202,7 +192,7 @@ impl FilesystemFormatter for Fat32Formatter {
     
     fn can_format(&self, device: &Device) -> bool {
         // Never format system drives
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
@@ -249,6 +239,14 @@ impl FilesystemFormatter for Fat32Formatter {
         return vec![];
     }
     
+    fn tools_available(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return crate::tools::ToolManager::global().locate("mkfs.fat").is_some();
+        
+        #[cfg(not(target_os = "linux"))]
+        return true; // format.com / diskutil ship with the OS
+    }
+    
     async fn format(
         &self,
         device: &Device,
@@ -327,6 +325,8 @@ impl FilesystemFormatter for Fat32Formatter {
         device: &Device,
         options: &FormatOptions,
     ) -> Result<SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        
         let mut warnings = vec![];
         
         if device.is_system {
@@ -379,6 +379,12 @@ impl FilesystemFormatter for Fat32Formatter {
             required_tools: self.bundled_tools().into_iter().map(String::from).collect(),
             will_erase_data: true,
             space_after_format: device.size * 98 / 100, // FAT32 overhead ~2%
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 // Native FAT32 formatter implementation
 // Uses shared FAT components for maximum code reuse
 
-use moses_core::{Device, MosesError, FormatOptions, FilesystemFormatter, SimulationReport, Platform};
+use moses_core::{Device, MosesError, FormatOptions, FormatPreset, FilesystemFormatter, SimulationReport, Platform};
 use async_trait::async_trait;
 use std::io::{Write, Seek, SeekFrom};
 use log::info;
@@ -10,9 +10,12 @@ use log::warn;
 use crate::families::fat::common::{
     Fat32BootSector, generate_volume_serial, format_volume_label,
     init_fat32_table, get_media_descriptor,
-    calculate_fat32_params,
+    calculate_fat32_params, calculate_fat32_sd_params, SdLayout, DosCompatibility,
     FAT32_ROOT_CLUSTER, FAT32_FS_INFO_SECTOR, FAT32_BACKUP_BOOT_SECTOR
 };
+use crate::families::fat::common::label::{fat_label_entry, NO_NAME};
+use crate::volume_serial::serial_for_format;
+use crate::deterministic::FormatSeed;
 
 pub struct Fat32NativeFormatter;
 
@@ -49,13 +52,25 @@ impl Fat32NativeFormatter {
     
     async fn write_fat32_to_file(
         file: &mut std::fs::File,
-        volume_label: Option<&str>,
+        label_entry: Option<[u8; 32]>,
         write_offset: u64,
         partition_size: u64,
+        sd_layout: Option<&SdLayout>,
+        compat: &DosCompatibility,
+        volume_serial: u32,
     ) -> Result<(), MosesError> {
-        // Calculate FAT32 parameters
-        let total_sectors = partition_size / 512;
-        let fat_params = calculate_fat32_params(total_sectors)?;
+        // Calculate FAT32 parameters; the SD layout pads the reserved area so data starts on a boundary unit
+        // BPB_TotSec32 cannot count the last sector of an exactly 2TiB volume; it stays unused
+        let total_sectors = (partition_size / 512).min(u32::MAX as u64);
+        let (fat_params, reserved_sectors) = match sd_layout {
+            Some(layout) => calculate_fat32_sd_params(total_sectors, layout)?,
+            None => {
+                // FAT32 typically uses 32, padded to a track boundary in DOS compatibility mode
+                let fat_params = calculate_fat32_params(total_sectors)?;
+                let padding = compat.track_padding(write_offset / 512 + 32 + 2 * fat_params.sectors_per_fat as u64);
+                (fat_params, 32 + padding as u16)
+            }
+        };
         
         info!("FAT32 parameters: {} sectors, {} sectors/cluster, {} sectors/FAT, {} total clusters",
               total_sectors, fat_params.sectors_per_cluster, 
@@ -65,17 +80,17 @@ impl Fat32NativeFormatter {
         let mut boot_sector = Fat32BootSector::new();
         
         // Set common BPB fields
-        boot_sector.common_bpb.oem_name = *b"MSWIN4.1";
+        boot_sector.common_bpb.oem_name = compat.oem_name;
         boot_sector.common_bpb.bytes_per_sector = 512;
         boot_sector.common_bpb.sectors_per_cluster = fat_params.sectors_per_cluster;
-        boot_sector.common_bpb.reserved_sectors = 32;  // FAT32 typically uses 32
+        boot_sector.common_bpb.reserved_sectors = reserved_sectors;
         boot_sector.common_bpb.num_fats = 2;
         boot_sector.common_bpb.root_entries = 0;  // FAT32 has no fixed root
         boot_sector.common_bpb.total_sectors_16 = 0;
-        boot_sector.common_bpb.media_descriptor = get_media_descriptor(false);  // Fixed disk
+        boot_sector.common_bpb.media_descriptor = compat.media_descriptor;
         boot_sector.common_bpb.sectors_per_fat_16 = 0;  // Not used in FAT32
-        boot_sector.common_bpb.sectors_per_track = 63;
-        boot_sector.common_bpb.num_heads = 255;
+        boot_sector.common_bpb.sectors_per_track = compat.sectors_per_track;
+        boot_sector.common_bpb.num_heads = compat.heads;
         boot_sector.common_bpb.hidden_sectors = (write_offset / 512) as u32;
         boot_sector.common_bpb.total_sectors_32 = total_sectors as u32;
         
@@ -88,8 +103,8 @@ impl Fat32NativeFormatter {
         boot_sector.extended_bpb.backup_boot_sector = FAT32_BACKUP_BOOT_SECTOR;
         boot_sector.extended_bpb.drive_number = 0x80;  // Fixed disk
         boot_sector.extended_bpb.boot_signature = 0x29;
-        boot_sector.extended_bpb.volume_id = generate_volume_serial();
-        boot_sector.extended_bpb.volume_label = format_volume_label(volume_label);
+        boot_sector.extended_bpb.volume_id = volume_serial;
+        boot_sector.extended_bpb.volume_label = label_entry.map_or(*NO_NAME, |entry| entry[..11].try_into().unwrap());
         boot_sector.extended_bpb.fs_type = *b"FAT32   ";
         
         // Convert to bytes
@@ -142,14 +157,12 @@ impl Fat32NativeFormatter {
                 remaining -= chunk_size;
             }
             
-            // Initialize FAT with proper reserved entries using common helper
-            if fat_num == 0 {
-                // Only initialize the first FAT, then copy it
-                file.seek(SeekFrom::Start(this_fat_offset))?;
-                let mut first_sector = vec![0u8; 512];
-                init_fat32_table(&mut first_sector, get_media_descriptor(false), FAT32_ROOT_CLUSTER);
-                file.write_all(&first_sector)?;
-            }
+            // Initialize FAT with proper reserved entries using common helper; every copy
+            // gets them, as checkers treat FATs that differ as damage
+            file.seek(SeekFrom::Start(this_fat_offset))?;
+            let mut first_sector = vec![0u8; 512];
+            init_fat32_table(&mut first_sector, compat.media_descriptor, FAT32_ROOT_CLUSTER);
+            file.write_all(&first_sector)?;
         }
         
         info!("Wrote {} FAT32 tables", boot_sector.common_bpb.num_fats);
@@ -161,8 +174,12 @@ impl Fat32NativeFormatter {
         
         // Clear root directory cluster
         file.seek(SeekFrom::Start(root_dir_offset))?;
-        let empty_cluster = vec![0u8; boot_sector.common_bpb.sectors_per_cluster as usize * 512];
-        file.write_all(&empty_cluster)?;
+        let mut root_cluster = vec![0u8; boot_sector.common_bpb.sectors_per_cluster as usize * 512];
+        // The label goes in the root directory as well as the boot sector; Windows shows this copy
+        if let Some(entry) = label_entry {
+            root_cluster[..32].copy_from_slice(&entry);
+        }
+        file.write_all(&root_cluster)?;
         info!("Initialized root directory cluster");
         
         // Sync to disk
@@ -184,7 +201,7 @@ impl FilesystemFormatter for Fat32NativeFormatter {
     }
     
     fn can_format(&self, device: &Device) -> bool {
-        if device.is_system {
+        if device.is_system || device.is_write_protected {
             return false;
         }
         
@@ -212,11 +229,22 @@ impl FilesystemFormatter for Fat32NativeFormatter {
             }
         }
         
+        let preset = FormatPreset::from_options(options)?;
+        if preset == FormatPreset::SdCard && DosCompatibility::requested(options) {
+            return Err(MosesError::InvalidInput(
+                "The SD card layout sets its own geometry; it cannot be combined with DOS compatibility options".to_string()
+            ));
+        }
+        DosCompatibility::from_options(options, 0, get_media_descriptor(false))?;
+        FormatSeed::from_options(options)?;
+        
         Ok(())
     }
     
     async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
-        let fat_params = calculate_fat32_params(device.size / 512)?;
+        device.ensure_writable()?;
+        
+        let fat_params = calculate_fat32_params((device.size / 512).min(u32::MAX as u64))?;
         
         let fat_size = fat_params.sectors_per_fat as u64 * 512 * 2;  // 2 FATs
         let reserved_size = 32 * 512;  // 32 reserved sectors
@@ -224,6 +252,14 @@ impl FilesystemFormatter for Fat32NativeFormatter {
         
         let mut warnings = vec![];
         
+        if FormatPreset::from_options(options)?.uses_sd_layout(device) && !DosCompatibility::requested(options) {
+            let layout = SdLayout::for_card(device.size);
+            warnings.push(format!(
+                "SD card layout: {} KB clusters, partition at {} KB, data aligned to {} KB boundary units",
+                layout.cluster_size / 1024, layout.partition_offset / 1024, layout.boundary_unit / 1024
+            ));
+        }
+        
         // Windows 32GB limitation warning
         #[cfg(target_os = "windows")]
         {
@@ -233,6 +269,11 @@ impl FilesystemFormatter for Fat32NativeFormatter {
             }
         }
         
+        if DosCompatibility::requested(options) {
+            let compat = DosCompatibility::from_options(options, device.size, get_media_descriptor(false))?;
+            warnings.push(crate::families::fat::fat16::formatter_compliant::describe_compatibility(&compat));
+        }
+        
         if device.size < 260 * 1024 * 1024 {
             warnings.push("Volume may be too small for FAT32 (minimum ~260MB)".to_string());
         }
@@ -245,6 +286,12 @@ impl FilesystemFormatter for Fat32NativeFormatter {
             required_tools: vec![],
             will_erase_data: true,
             space_after_format: device.size - overhead,
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
     
@@ -259,6 +306,17 @@ impl FilesystemFormatter for Fat32NativeFormatter {
         
         info!("Starting native FAT32 format for device: {}", device.name);
         
+        // Read before the Windows cleanup below wipes the old volume
+        let seed = FormatSeed::from_options(options)?;
+        let volume_serial = serial_for_format(device, options)?
+            .map(|kept| kept.short())
+            .or_else(|| seed.serial32("volume serial"))
+            .unwrap_or_else(generate_volume_serial);
+        
+        let label_entry = options.label.as_deref()
+            .filter(|label| !label.trim().is_empty())
+            .map(|label| fat_label_entry(&format_volume_label(Some(label)), seed.unix_time()));
+        
         // On Windows, cleanup the disk first (dismount volumes)
         #[cfg(target_os = "windows")]
         {
@@ -271,8 +329,19 @@ impl FilesystemFormatter for Fat32NativeFormatter {
             }
         }
         
+        // Geometry, media byte and OEM name; the modern values unless DOS compatibility was asked for
+        let compat = DosCompatibility::from_options(options, device.size, get_media_descriptor(false))?;
+        
+        // SD cards follow the SD Association layout, which always has an MBR; a DOS geometry replaces it
+        let sd_layout = (FormatPreset::from_options(options)?.uses_sd_layout(device) && !DosCompatibility::requested(options))
+            .then(|| SdLayout::for_card(device.size));
+        if let Some(layout) = &sd_layout {
+            info!("Using SD card layout: {} byte clusters, partition at {} bytes, {} byte boundary unit",
+                  layout.cluster_size, layout.partition_offset, layout.boundary_unit);
+        }
+        
         // Check if we should create a partition table
-        let create_partition_table = options.additional_options
+        let create_partition_table = sd_layout.is_some() || options.additional_options
             .get("create_partition_table")
             .and_then(|v| v.parse::<bool>().ok())
             .unwrap_or(false);
@@ -281,31 +350,49 @@ impl FilesystemFormatter for Fat32NativeFormatter {
         let mut file = crate::utils::open_device_write(device)?;
         
         if create_partition_table {
-            info!("Creating MBR partition table for FAT32");
+            use crate::partitioner::{
+                create_single_partition_table, create_mbr_partition_table_at, create_mbr_partition_table_with_geometry,
+                PartitionTableType, write_gpt_backup, write_partition_table,
+            };
             
-            // Create MBR with FAT32 partition
-            use crate::partitioner::{create_single_partition_table, PartitionTableType, write_partition_table};
+            // The SD layout and DOS geometries are defined in terms of an MBR
+            let table_type = if sd_layout.is_none() && !compat.dos_partition {
+                PartitionTableType::from_options(options)?
+            } else {
+                PartitionTableType::MBR
+            };
+            info!("Creating {:?} partition table for FAT32", table_type);
             
-            let partition_table = create_single_partition_table(
-                device,
-                PartitionTableType::MBR,
-                "fat32"
-            )?;
+            let partition_table = match &sd_layout {
+                Some(layout) => create_mbr_partition_table_at(device, "fat32", (layout.partition_offset / 512) as u32)?,
+                None if compat.dos_partition => create_mbr_partition_table_with_geometry(
+                    device, "fat32", compat.partition_start(), compat.heads, compat.sectors_per_track,
+                )?,
+                None => create_single_partition_table(device, table_type, "fat32")?,
+            };
             
             // Write the partition table
             write_partition_table(&mut file, &partition_table)?;
+            if table_type == PartitionTableType::GPT {
+                write_gpt_backup(&mut file, &partition_table, device.size)?;
+            }
             file.sync_all().map_err(|e| MosesError::IoError(e))?;
             
-            // Write FAT32 at partition offset (typically 1MB)
-            let partition_offset = 1024 * 1024;  // 1MB aligned
-            let partition_size = device.size - partition_offset;
+            // Write FAT32 at partition offset (typically 1MB, the second track with a DOS geometry)
+            let partition_offset = sd_layout.map_or(compat.partition_start() as u64 * 512, |layout| layout.partition_offset);
+            // A GPT keeps its backup in the last 33 sectors
+            let table_tail = if table_type == PartitionTableType::GPT { 33 * 512 } else { 0 };
+            let partition_size = device.size - partition_offset - table_tail;
             
             // Use the same file handle to write FAT32
             Self::write_fat32_to_file(
                 &mut file,
-                options.label.as_deref(),
+                label_entry,
                 partition_offset,
                 partition_size,
+                sd_layout.as_ref(),
+                &compat,
+                volume_serial,
             ).await?;
         } else {
             // Write FAT32 directly to device (no partition table)
@@ -313,9 +400,12 @@ impl FilesystemFormatter for Fat32NativeFormatter {
             
             Self::write_fat32_to_file(
                 &mut file,
-                options.label.as_deref(),
+                label_entry,
                 0,
                 device.size,
+                None,
+                &compat,
+                volume_serial,
             ).await?;
         }
         
@@ -324,4 +414,90 @@ impl FilesystemFormatter for Fat32NativeFormatter {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_sd_layout_aligns_data_area() {
+        // 1GB card: 128KB boundary units, partition on the first one
+        let size = 1024 * 1024 * 1024;
+        let layout = SdLayout::for_card(size);
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let mut file = image.reopen().unwrap();
+
+        Fat32NativeFormatter::write_fat32_to_file(
+            &mut file, Some(fat_label_entry(b"SDCARD     ", 0)), layout.partition_offset, size - layout.partition_offset, Some(&layout), &DosCompatibility::modern(0xF8), 0x1234_5678,
+        ).await.unwrap();
+
+        let mut boot_sector = [0u8; 512];
+        file.seek(SeekFrom::Start(layout.partition_offset)).unwrap();
+        file.read_exact(&mut boot_sector).unwrap();
+        assert_eq!(&boot_sector[510..512], &[0x55, 0xAA]);
+        let reserved = u16::from_le_bytes([boot_sector[14], boot_sector[15]]) as u64;
+        let sectors_per_fat = u32::from_le_bytes(boot_sector[36..40].try_into().unwrap()) as u64;
+        let hidden = u32::from_le_bytes(boot_sector[28..32].try_into().unwrap()) as u64;
+        assert_eq!(hidden * 512, layout.partition_offset);
+        assert_eq!((hidden + reserved + 2 * sectors_per_fat) % layout.boundary_unit_sectors(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dos_geometry() {
+        let size = 600 * 1024 * 1024;
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let device = crate::test_helpers::create_test_device(image.path().to_str().unwrap(), size);
+        let mut options = FormatOptions {
+            filesystem_type: "fat32".to_string(),
+            additional_options: [("create_partition_table", "true"), ("dos_geometry", "auto"), ("align_to_track", "true")]
+                .iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        Fat32NativeFormatter.format(&device, &options).await.unwrap();
+
+        let mut file = image.reopen().unwrap();
+        let mut boot_sector = [0u8; 512];
+        file.seek(SeekFrom::Start(63 * 512)).unwrap();
+        file.read_exact(&mut boot_sector).unwrap();
+        assert_eq!(&boot_sector[510..512], &[0x55, 0xAA]);
+        // LBA-assist translation gives a 600MB disk 32 heads
+        assert_eq!(u16::from_le_bytes([boot_sector[24], boot_sector[25]]), 63);
+        assert_eq!(u16::from_le_bytes([boot_sector[26], boot_sector[27]]), 32);
+        let reserved = u16::from_le_bytes([boot_sector[14], boot_sector[15]]) as u64;
+        let sectors_per_fat = u32::from_le_bytes(boot_sector[36..40].try_into().unwrap()) as u64;
+        assert!(reserved >= 32);
+        assert_eq!((63 + reserved + 2 * sectors_per_fat) % 63, 0, "data area starts on a track");
+
+        options.additional_options.insert("preset".to_string(), "sd-card".to_string());
+        assert!(Fat32NativeFormatter.validate_options(&options).await.is_err());
+    }
+    #[tokio::test]
+    async fn test_gpt_partition_table() {
+        let size = 300 * 1024 * 1024;
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(size).unwrap();
+        let device = crate::test_helpers::create_test_device(image.path().to_str().unwrap(), size);
+        let options = FormatOptions {
+            filesystem_type: "fat32".to_string(),
+            additional_options: [("create_partition_table", "true"), (crate::partitioner::PARTITION_STYLE_OPTION, "gpt")]
+                .iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        Fat32NativeFormatter.format(&device, &options).await.unwrap();
+
+        let mut disk = Vec::new();
+        image.reopen().unwrap().read_to_end(&mut disk).unwrap();
+        assert_eq!(disk[446 + 4], 0xEE, "protective MBR");
+        assert_eq!(&disk[512..520], b"EFI PART");
+        let last = disk.len() - 512;
+        assert_eq!(&disk[last..last + 8], b"EFI PART", "backup header in the last sector");
+        assert_eq!(u64::from_le_bytes(disk[last + 24..last + 32].try_into().unwrap()), size / 512 - 1);
+        assert_eq!(disk[last - 32 * 512..last], disk[1024..1024 + 32 * 512], "backup entries");
+        assert_eq!(&disk[2048 * 512 + 510..2048 * 512 + 512], &[0x55, 0xAA]);
+        let total_sectors = u32::from_le_bytes(disk[2048 * 512 + 32..2048 * 512 + 36].try_into().unwrap()) as u64;
+        assert!(2048 + total_sectors <= size / 512 - 33, "the volume stays clear of the backup GPT");
+    }
+}
@@ -2,8 +2,9 @@
 // Handles Windows sector alignment automatically
 
 use moses_core::{Device, MosesError};
-use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo, FileMetadata};
+use crate::device_reader::{AlignedDeviceReader, FilesystemReader, FileEntry, FilesystemInfo};
 use crate::utils::open_device_read;
+use crate::families::fat::common::entry_metadata;
 use log::{info, debug};
 use std::collections::HashMap;
 
@@ -96,6 +97,9 @@ pub struct Fat32Reader {
     root_cluster: u32,
     total_clusters: u32,
     
+    /// The volume label, from the root directory entry when there is one
+    label: Option<String>,
+    
     // Cache
     fat_cache: HashMap<u32, u32>,  // cluster -> next cluster
     dir_cache: HashMap<String, Vec<FileEntry>>,
@@ -180,6 +184,10 @@ impl Fat32Reader {
             .to_string();
         info!("  Volume label: '{}'", volume_label);
         
+        // Windows shows the root directory's label entry, so that copy wins over the boot sector's
+        let label = crate::families::fat::common::label::read_labels(&mut reader).ok()
+            .and_then(|labels| labels.effective());
+        
         Ok(Fat32Reader {
             _device: device,
             reader,
@@ -192,6 +200,7 @@ impl Fat32Reader {
             data_start_byte,
             root_cluster: boot_sector.root_cluster,
             total_clusters,
+            label,
             fat_cache: HashMap::new(),
             dir_cache: HashMap::new(),
         })
@@ -366,7 +375,14 @@ impl Fat32Reader {
                 is_directory,
                 size: if is_directory { 0 } else { dir_entry.file_size as u64 },
                 cluster: Some(cluster),
-                metadata: FileMetadata::default(),
+                metadata: entry_metadata(
+                    dir_entry.attributes,
+                    (dir_entry.creation_date, dir_entry.creation_time),
+                    (dir_entry.write_date, dir_entry.write_time),
+                    dir_entry.last_access_date,
+                    if is_directory { 0 } else { dir_entry.file_size as u64 },
+                    self.bytes_per_cluster,
+                ),
             });
         }
         
@@ -400,10 +416,6 @@ impl FilesystemReader for Fat32Reader {
     }
     
     fn get_info(&self) -> FilesystemInfo {
-        let volume_label = String::from_utf8_lossy(&self.boot_sector.volume_label)
-            .trim()
-            .to_string();
-        
         let bytes_per_sector = self.boot_sector.bytes_per_sector as u64;
         let sectors_per_cluster = self.boot_sector.sectors_per_cluster as u64;
         let total_sectors = if self.boot_sector.total_sectors_16 != 0 {
@@ -420,11 +432,7 @@ impl FilesystemReader for Fat32Reader {
         
         FilesystemInfo {
             fs_type: "FAT32".to_string(),
-            label: if volume_label.is_empty() { 
-                None 
-            } else { 
-                Some(volume_label) 
-            },
+            label: self.label.clone(),
             total_bytes: total_size,
             used_bytes: used_size,
             cluster_size: Some(cluster_size as u32),
@@ -34,7 +34,7 @@ struct Fat32BootSector {
 impl Fat32ReaderEnhanced {
     /// Create a new FAT32 reader with enhanced error handling
     pub fn new(device: Device) -> MosesResult<Self> {
-        let device_path = device.mount_points[0].clone();
+        let device_path = std::path::PathBuf::from(crate::utils::get_device_path(&device));
         
         // Open device with proper error context
         let mut file = std::fs::OpenOptions::new()
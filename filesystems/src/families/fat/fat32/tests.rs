@@ -22,6 +22,10 @@ fn create_test_device(size: u64) -> Device {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -277,6 +281,21 @@ mod tests {
         ), "Should have no critical warnings");
     }
     
+    #[tokio::test]
+    async fn test_write_protected_device_rejected() {
+        let mut device = create_test_device(1024 * 1024 * 1024);
+        device.is_write_protected = true;
+        let formatter = Fat32Formatter;
+        
+        assert!(!formatter.can_format(&device), "Write-protected media must not be formattable");
+        let options = FormatOptions {
+            filesystem_type: "fat32".to_string(),
+            ..FormatOptions::default()
+        };
+        let err = formatter.dry_run(&device, &options).await.unwrap_err();
+        assert!(err.to_string().contains("write-protected"), "Unexpected error: {}", err);
+    }
+    
     #[tokio::test]
     async fn test_medium_fat32() {
         // Test typical FAT32 size (1GB)
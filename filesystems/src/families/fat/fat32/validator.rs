@@ -4,6 +4,7 @@
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::collections::HashMap;
+use crate::families::fat::common::label::read_labels;
 use crate::families::fat::common::{
     validator::{
         ValidationResult, ValidationStatus, FatValidator,
@@ -297,6 +298,15 @@ impl Fat32ComprehensiveValidator {
         file.read_exact(&mut fat_sample)?;
         let fat_validation = validator.validate_fat_entries(&fat_sample);
         
+        // The boot sector label and the root directory label entry must agree
+        let label_validation = match read_labels(&mut file) {
+            Ok(labels) => match labels.mismatch() {
+                Some(mismatch) => ValidationResult::Warning(mismatch),
+                None => ValidationResult::Pass("The boot sector and root directory labels agree".to_string()),
+            },
+            Err(e) => ValidationResult::Warning(format!("Could not read the volume label: {}", e)),
+        };
+        
         // Determine overall status
         let mut has_errors = false;
         let mut has_warnings = false;
@@ -316,6 +326,7 @@ impl Fat32ComprehensiveValidator {
         check_result(&cluster_validation);
         check_result(&fsinfo_validation);
         check_result(&fat_validation);
+        check_result(&label_validation);
         
         for result in specific_fields.values() {
             check_result(result);
@@ -336,6 +347,7 @@ impl Fat32ComprehensiveValidator {
             cluster_validation,
             fsinfo_validation,
             fat_validation,
+            label_validation,
             cluster_count,
         })
     }
@@ -349,5 +361,6 @@ pub struct Fat32ValidationReport {
     pub cluster_validation: ValidationResult,
     pub fsinfo_validation: ValidationResult,
     pub fat_validation: ValidationResult,
+    pub label_validation: ValidationResult,
     pub cluster_count: Option<u32>,
 }
\ No newline at end of file
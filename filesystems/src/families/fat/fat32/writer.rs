@@ -61,7 +61,7 @@ impl Fat32Writer {
         let mut file = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(&device.mount_points[0])
+            .open(crate::utils::get_device_path(&device))
             .map_err(|e| MosesError::IoError(e))?;
         
         // Read boot sector
@@ -539,9 +539,8 @@ impl Fat32Writer {
     /// Flush all pending writes
     pub fn flush(&mut self) -> MosesResult<()> {
         self.flush_fat()?;
-        self.file.flush()
-            .map_err(|e| MosesError::IoError(e))?;
-        Ok(())
+        // Barrier, so a completed operation survives a power loss
+        crate::utils::flush_device(&self.file)
     }
 }
 
@@ -0,0 +1,19 @@
+// JFFS2 - read-only access to the journalling flash filesystem of routers and IoT boards
+// A JFFS2 image is a log of nodes: directory entries naming an inode in a parent, and
+// inode nodes carrying metadata and a piece of file data. The reader scans the image for
+// nodes whose CRCs check out, keeps the newest version of each name and replays the data
+// nodes of a file in version order. Nodes are found wherever they are, so a full flash
+// dump with the JFFS2 partition somewhere inside it reads as well as the partition alone.
+
+pub mod structures;
+pub mod reader;
+pub mod ops;
+
+pub use reader::Jffs2Reader;
+pub use ops::{Jffs2Ops, Jffs2Detector};
+
+/// Whether a boot sector starts with a JFFS2 node of either byte order
+pub fn has_node(boot_sector: &[u8]) -> bool {
+    structures::NodeHeader::parse(boot_sector, structures::Endian::Little).is_some()
+        || structures::NodeHeader::parse(boot_sector, structures::Endian::Big).is_some()
+}
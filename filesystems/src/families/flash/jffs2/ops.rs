@@ -0,0 +1,131 @@
+// JFFS2 FilesystemOps implementation for mounting and browsing, read-only
+use super::reader::{placeholder, Jffs2Reader};
+use crate::device_reader::FilesystemReader;
+use crate::ops::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct Jffs2Ops {
+    reader: Mutex<Option<Jffs2Reader>>,
+}
+
+impl Jffs2Ops {
+    pub fn new() -> Self {
+        Self { reader: Mutex::new(None) }
+    }
+
+    /// Run `f` on the reader with `path` resolved to its inode number
+    fn with_path<T>(&self, path: &Path, f: impl FnOnce(&mut Jffs2Reader, u32) -> Result<T, MosesError>) -> Result<T, MosesError> {
+        let path = path.to_str().ok_or_else(|| MosesError::InvalidInput("Invalid path".to_string()))?;
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let ino = reader.lookup(path)?;
+        f(reader, ino)
+    }
+}
+
+impl Default for Jffs2Ops {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attributes(reader: &Jffs2Reader, ino: u32) -> FileAttributes {
+    let inode = reader.inode(ino).cloned().unwrap_or_else(|| placeholder(ino));
+    let is_directory = reader.is_directory(ino);
+    FileAttributes {
+        size: if is_directory { 0 } else { u64::from(inode.isize) },
+        is_directory,
+        is_file: inode.is_file(),
+        is_symlink: inode.is_symlink(),
+        created: Some(u64::from(inode.ctime)),
+        modified: Some(u64::from(inode.mtime)),
+        accessed: Some(u64::from(inode.atime)),
+        permissions: inode.mode & 0o7777,
+        owner: Some(u32::from(inode.uid)),
+        group: Some(u32::from(inode.gid)),
+    }
+}
+
+impl FilesystemOps for Jffs2Ops {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        *self.reader.lock().unwrap() = Some(Jffs2Reader::new(device.clone())?);
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        let reader = self.reader.lock().unwrap();
+        let reader = reader.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let info = reader.get_info();
+        let free = info.total_bytes.saturating_sub(info.used_bytes);
+        Ok(FilesystemInfo {
+            total_space: info.total_bytes,
+            free_space: free,
+            available_space: free,
+            total_inodes: 0,
+            free_inodes: 0,
+            block_size: 4096,
+            fragment_size: 4096,
+            max_filename_length: super::structures::MAX_NAME_LEN as u32,
+            filesystem_type: info.fs_type,
+            volume_label: None,
+            volume_uuid: None,
+            is_readonly: true,
+        })
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.with_path(path, |reader, ino| Ok(attributes(reader, ino)))
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.with_path(path, |reader, ino| {
+            Ok(reader.list(ino)?.into_iter()
+                .map(|entry| DirectoryEntry { attributes: attributes(reader, entry.ino), name: entry.name })
+                .collect())
+        })
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.with_path(path, |reader, ino| reader.read(ino, offset, u64::from(size)))
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.with_path(path, |_, ino| Ok(u64::from(ino))).ok()
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.with_path(path, |reader, ino| reader.read_link(ino).map(PathBuf::from))
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn filesystem_type(&self) -> &str {
+        "jffs2"
+    }
+}
+
+/// Finds JFFS2 by a node at the start of the device; dumps with the filesystem further
+/// in are opened by naming the type
+pub struct Jffs2Detector;
+
+impl crate::ops::FilesystemDetector for Jffs2Detector {
+    fn detect(&self, device: &Device) -> Result<Option<String>, MosesError> {
+        use crate::utils::{open_device_read, read_block};
+
+        let mut file = open_device_read(device)?;
+        match read_block(&mut file, 0, super::structures::HEADER_SIZE) {
+            Ok(header) if super::has_node(&header) => Ok(Some("jffs2".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
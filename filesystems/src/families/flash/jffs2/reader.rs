@@ -0,0 +1,520 @@
+// JFFS2 reader - scans the whole image once, then serves names and data from an index
+// Opening an image walks it in 4-byte steps looking for node headers whose CRC checks out;
+// erased flash (0xFF) and anything that is not JFFS2 is stepped over. Directory entries
+// keep only the newest version per name, so renames and deletions fall out of the scan.
+// Inode nodes are indexed with where their data sits and read again when a file is read:
+// each node covers a range of the file, and replaying them in version order, truncating to
+// each node's file size, gives the file's current contents.
+use super::structures::*;
+use crate::device_reader::{AlignedDeviceReader, FileEntry, FileMetadata, FilesystemInfo, FilesystemReader};
+use log::{info, warn};
+use moses_core::{Device, MosesError};
+use std::collections::{BTreeMap, HashMap};
+
+/// Bytes read per step of the scan
+const SCAN_WINDOW: usize = 1 << 20;
+/// Largest node the scan accepts; inode nodes hold at most one page of data
+const MAX_NODE_SIZE: u32 = 1 << 20;
+
+/// One name in a directory listing
+#[derive(Debug, Clone)]
+pub struct Jffs2Entry {
+    pub name: String,
+    pub ino: u32,
+    /// Newest metadata of the inode; None for names whose inode has no nodes left
+    pub inode: Option<InodeNode>,
+}
+
+/// An indexed inode node and where its data starts in the image
+#[derive(Debug, Clone)]
+struct DataNode {
+    node: InodeNode,
+    data_offset: u64,
+}
+
+/// Read-only JFFS2 image
+pub struct Jffs2Reader {
+    reader: AlignedDeviceReader,
+    endian: Endian,
+    image_size: u64,
+    /// Bytes taken by nodes still in use
+    used_bytes: u64,
+    /// Newest entry per name, by parent inode; removed names have `ino` 0
+    dirents: HashMap<u32, BTreeMap<String, Dirent>>,
+    /// Inode nodes in version order, by inode
+    inodes: HashMap<u32, Vec<DataNode>>,
+}
+
+impl Jffs2Reader {
+    pub fn new(device: Device) -> Result<Self, MosesError> {
+        info!("Opening JFFS2 filesystem on device: {}", device.name);
+        let file = crate::utils::open_device_with_fallback(&device)?;
+        // Block devices report no length of their own
+        let size = file.metadata().map(|meta| meta.len()).ok().filter(|&len| len > 0).unwrap_or(device.size);
+        Self::from_reader(AlignedDeviceReader::new(file), size)
+    }
+
+    pub fn from_reader(reader: AlignedDeviceReader, size: u64) -> Result<Self, MosesError> {
+        let mut jffs2 = Self {
+            reader,
+            endian: Endian::Little,
+            // The aligned reader only reads whole sectors
+            image_size: size / 512 * 512,
+            used_bytes: 0,
+            dirents: HashMap::new(),
+            inodes: HashMap::new(),
+        };
+        jffs2.scan()?;
+        Ok(jffs2)
+    }
+
+    fn scan(&mut self) -> Result<(), MosesError> {
+        let mut endian = None;
+        let mut window_start = 0u64;
+        let mut window = Vec::new();
+        let mut skipped = 0u64;
+        let mut pos = 0u64;
+        while pos + HEADER_SIZE as u64 <= self.image_size {
+            if pos + HEADER_SIZE as u64 > window_start + window.len() as u64 {
+                window_start = pos;
+                window = self.reader.read_at(pos, SCAN_WINDOW.min((self.image_size - pos) as usize))?;
+            }
+            let at = (pos - window_start) as usize;
+            let header = match endian {
+                Some(endian) => NodeHeader::parse(&window[at..], endian).map(|header| (endian, header)),
+                None => [Endian::Little, Endian::Big].into_iter()
+                    .find_map(|endian| NodeHeader::parse(&window[at..], endian).map(|header| (endian, header))),
+            };
+            let Some((node_endian, header)) = header.filter(|(_, header)| header.totlen <= MAX_NODE_SIZE) else {
+                pos += 4;
+                continue;
+            };
+            let end = pos + u64::from(header.totlen);
+            if end > self.image_size {
+                pos += 4;
+                continue;
+            }
+            if endian.is_none() {
+                info!("JFFS2 nodes are {:?}-endian, first at offset {:#x}", node_endian, pos);
+                endian = Some(node_endian);
+                self.endian = node_endian;
+            }
+            if !header.obsolete {
+                let node = if end <= window_start + window.len() as u64 {
+                    window[at..at + header.totlen as usize].to_vec()
+                } else {
+                    self.reader.read_at(pos, header.totlen as usize)?
+                };
+                match self.index(pos, &header, &node) {
+                    Ok(()) => self.used_bytes += u64::from(header.totlen),
+                    Err(e) => {
+                        warn!("Skipping JFFS2 node at offset {:#x}: {}", pos, e);
+                        skipped += 1;
+                    }
+                }
+            }
+            pos = (end + 3) & !3;
+        }
+        if endian.is_none() {
+            return Err(MosesError::Other("No JFFS2 nodes found".to_string()));
+        }
+        if skipped > 0 {
+            warn!("{} damaged JFFS2 node(s) were skipped; older versions of those files are shown", skipped);
+        }
+        for nodes in self.inodes.values_mut() {
+            nodes.sort_by_key(|data| data.node.version);
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, pos: u64, header: &NodeHeader, node: &[u8]) -> Result<(), MosesError> {
+        match header.nodetype {
+            NODETYPE_DIRENT => {
+                let dirent = Dirent::parse(node, self.endian)?;
+                let names = self.dirents.entry(dirent.pino).or_default();
+                match names.get(&dirent.name) {
+                    Some(existing) if existing.version >= dirent.version => {}
+                    _ => {
+                        names.insert(dirent.name.clone(), dirent);
+                    }
+                }
+            }
+            NODETYPE_INODE => {
+                let inode = InodeNode::parse(node, self.endian)?;
+                let data = node.get(INODE_SIZE..INODE_SIZE + inode.csize as usize)
+                    .ok_or_else(|| corrupt(&format!("inode {}: data runs past the node", inode.ino)))?;
+                if inode.compr != COMPR_ZERO && crc(data) != inode.data_crc {
+                    return Err(corrupt(&format!("inode {}: data checksum mismatch", inode.ino)));
+                }
+                self.inodes.entry(inode.ino).or_default()
+                    .push(DataNode { node: inode, data_offset: pos + INODE_SIZE as u64 });
+            }
+            // Clean markers, padding, summaries and extended attributes say nothing about files
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn root(&self) -> u32 {
+        ROOT_INO
+    }
+
+    /// Byte order of the image
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Newest metadata of inode `ino`
+    pub fn inode(&self, ino: u32) -> Option<&InodeNode> {
+        self.inodes.get(&ino).and_then(|nodes| nodes.last()).map(|data| &data.node)
+    }
+
+    pub fn is_directory(&self, ino: u32) -> bool {
+        match self.inode(ino) {
+            Some(inode) => inode.is_directory(),
+            None => ino == ROOT_INO,
+        }
+    }
+
+    /// The entries of directory `ino`, sorted by name
+    pub fn list(&self, ino: u32) -> Result<Vec<Jffs2Entry>, MosesError> {
+        if !self.is_directory(ino) {
+            return Err(MosesError::InvalidInput(format!("JFFS2 inode {} is not a directory", ino)));
+        }
+        Ok(self.dirents.get(&ino).into_iter().flat_map(|names| names.values())
+            .filter(|dirent| dirent.ino != 0)
+            .map(|dirent| Jffs2Entry { name: dirent.name.clone(), ino: dirent.ino, inode: self.inode(dirent.ino).cloned() })
+            .collect())
+    }
+
+    /// Resolve a `/`-separated path from the root directory
+    pub fn lookup(&self, path: &str) -> Result<u32, MosesError> {
+        let mut ino = ROOT_INO;
+        for part in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            if !self.is_directory(ino) {
+                return Err(MosesError::Other(format!("Path not found: {}", path)));
+            }
+            ino = self.dirents.get(&ino)
+                .and_then(|names| names.get(part))
+                .filter(|dirent| dirent.ino != 0)
+                .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))?
+                .ino;
+        }
+        Ok(ino)
+    }
+
+    /// Current size of file `ino`
+    pub fn size(&self, ino: u32) -> u64 {
+        self.inode(ino).map_or(0, |inode| u64::from(inode.isize))
+    }
+
+    /// Up to `size` bytes of file `ino` from `offset`
+    pub fn read(&mut self, ino: u32, offset: u64, size: u64) -> Result<Vec<u8>, MosesError> {
+        let end = offset.saturating_add(size).min(self.size(ino));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let nodes = self.inodes.get(&ino).cloned().unwrap_or_default();
+        let mut data = vec![0u8; (end - offset) as usize];
+        for DataNode { node, data_offset } in nodes {
+            let (node_start, node_end) = (u64::from(node.offset), u64::from(node.offset) + u64::from(node.dsize));
+            let (from, to) = (offset.max(node_start), end.min(node_end));
+            if from < to {
+                let stored = self.reader.read_at(data_offset, node.csize as usize)?;
+                let piece = decompress(node.compr, &stored, node.dsize as usize)
+                    .map_err(|e| match e {
+                        MosesError::NotSupported(what) => MosesError::NotSupported(format!("{} (inode {})", what, ino)),
+                        e => e,
+                    })?;
+                data[(from - offset) as usize..(to - offset) as usize]
+                    .copy_from_slice(&piece[(from - node_start) as usize..(to - node_start) as usize]);
+            }
+            // A truncation drops everything past the new size, even if a later write extends the file again
+            let cut = u64::from(node.isize).max(offset);
+            if cut < end {
+                data[(cut - offset) as usize..].fill(0);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Target of the symbolic link `ino`
+    pub fn read_link(&mut self, ino: u32) -> Result<String, MosesError> {
+        if !self.inode(ino).is_some_and(InodeNode::is_symlink) {
+            return Err(MosesError::InvalidInput(format!("JFFS2 inode {} is not a symbolic link", ino)));
+        }
+        let target = self.read(ino, 0, self.size(ino))?;
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    fn file_entry(&mut self, entry: Jffs2Entry) -> FileEntry {
+        let is_symlink = entry.inode.as_ref().is_some_and(InodeNode::is_symlink);
+        let link_target = if is_symlink { self.read_link(entry.ino).ok() } else { None };
+        let is_directory = self.is_directory(entry.ino);
+        let inode = entry.inode.unwrap_or_else(|| placeholder(entry.ino));
+        FileEntry {
+            name: entry.name,
+            is_directory,
+            size: if is_directory { 0 } else { u64::from(inode.isize) },
+            cluster: None,
+            metadata: FileMetadata {
+                link_target,
+                compressed: self.inodes.get(&entry.ino)
+                    .is_some_and(|nodes| nodes.iter().any(|data| !matches!(data.node.compr, COMPR_NONE | COMPR_COPY | COMPR_ZERO))),
+                created: Some(u64::from(inode.ctime)),
+                modified: Some(u64::from(inode.mtime)),
+                accessed: Some(u64::from(inode.atime)),
+                readonly: inode.mode & 0o222 == 0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Metadata for a name whose inode has no nodes: the root, or a file that lost them
+pub(crate) fn placeholder(ino: u32) -> InodeNode {
+    InodeNode {
+        ino,
+        version: 0,
+        mode: if ino == ROOT_INO { 0o040755 } else { 0o100644 },
+        uid: 0,
+        gid: 0,
+        isize: 0,
+        atime: 0,
+        mtime: 0,
+        ctime: 0,
+        offset: 0,
+        csize: 0,
+        dsize: 0,
+        compr: COMPR_NONE,
+        data_crc: 0,
+    }
+}
+
+impl FilesystemReader for Jffs2Reader {
+    fn read_metadata(&mut self) -> Result<(), MosesError> {
+        // Already scanned in new()
+        Ok(())
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
+        let ino = self.lookup(path)?;
+        if !self.is_directory(ino) {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", path)));
+        }
+        let entries = self.list(ino)?;
+        Ok(entries.into_iter().map(|entry| self.file_entry(entry)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, MosesError> {
+        let ino = self.lookup(path)?;
+        if self.is_directory(ino) {
+            return Err(MosesError::InvalidInput(format!("Is a directory: {}", path)));
+        }
+        self.read(ino, 0, self.size(ino))
+    }
+
+    fn get_info(&self) -> FilesystemInfo {
+        FilesystemInfo {
+            fs_type: "jffs2".to_string(),
+            label: None,
+            total_bytes: self.image_size,
+            used_bytes: self.used_bytes,
+            cluster_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::Path;
+
+    /// Builds images in either byte order, node by node
+    struct Image {
+        data: Vec<u8>,
+        endian: Endian,
+    }
+
+    impl Image {
+        fn new(endian: Endian) -> Self {
+            Self { data: Vec::new(), endian }
+        }
+
+        fn u16(&self, value: u16) -> [u8; 2] {
+            match self.endian {
+                Endian::Little => value.to_le_bytes(),
+                Endian::Big => value.to_be_bytes(),
+            }
+        }
+
+        fn u32(&self, value: u32) -> [u8; 4] {
+            match self.endian {
+                Endian::Little => value.to_le_bytes(),
+                Endian::Big => value.to_be_bytes(),
+            }
+        }
+
+        fn node(&mut self, nodetype: u16, body: Vec<u8>) -> usize {
+            let at = self.data.len();
+            let mut node = Vec::new();
+            node.extend_from_slice(&self.u16(MAGIC));
+            node.extend_from_slice(&self.u16(nodetype));
+            node.extend_from_slice(&self.u32((HEADER_SIZE + body.len()) as u32));
+            let hdr_crc = crc(&node);
+            node.extend_from_slice(&self.u32(hdr_crc));
+            node.extend_from_slice(&body);
+            self.data.extend_from_slice(&node);
+            while self.data.len() % 4 != 0 {
+                self.data.push(0xFF);
+            }
+            at
+        }
+
+        fn dirent(&mut self, pino: u32, version: u32, ino: u32, dtype: u8, name: &str) -> usize {
+            let mut fixed = Vec::new();
+            fixed.extend_from_slice(&self.u16(MAGIC));
+            fixed.extend_from_slice(&self.u16(NODETYPE_DIRENT));
+            fixed.extend_from_slice(&self.u32((DIRENT_SIZE + name.len()) as u32));
+            fixed.extend_from_slice(&self.u32(crc(&fixed)));
+            for value in [pino, version, ino, 1_600_000_000] {
+                fixed.extend_from_slice(&self.u32(value));
+            }
+            fixed.extend_from_slice(&[name.len() as u8, dtype, 0, 0]);
+            fixed.extend_from_slice(&self.u32(crc(&fixed)));
+            fixed.extend_from_slice(&self.u32(crc(name.as_bytes())));
+            let mut body = fixed[HEADER_SIZE..].to_vec();
+            body.extend_from_slice(name.as_bytes());
+            self.node(NODETYPE_DIRENT, body)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn inode(&mut self, ino: u32, version: u32, mode: u32, isize: u32, offset: u32, dsize: u32, compr: u8, data: &[u8]) -> usize {
+            let mut fixed = Vec::new();
+            fixed.extend_from_slice(&self.u16(MAGIC));
+            fixed.extend_from_slice(&self.u16(NODETYPE_INODE));
+            fixed.extend_from_slice(&self.u32((INODE_SIZE + data.len()) as u32));
+            fixed.extend_from_slice(&self.u32(crc(&fixed)));
+            fixed.extend_from_slice(&self.u32(ino));
+            fixed.extend_from_slice(&self.u32(version));
+            fixed.extend_from_slice(&self.u32(mode));
+            fixed.extend_from_slice(&self.u16(1000));
+            fixed.extend_from_slice(&self.u16(100));
+            for value in [isize, 1_600_000_001, 1_600_000_002, 1_600_000_003, offset, data.len() as u32, dsize] {
+                fixed.extend_from_slice(&self.u32(value));
+            }
+            fixed.extend_from_slice(&[compr, 0, 0, 0]);
+            fixed.extend_from_slice(&self.u32(crc(data)));
+            fixed.extend_from_slice(&self.u32(crc(&fixed[..60])));
+            let mut body = fixed[HEADER_SIZE..].to_vec();
+            body.extend_from_slice(data);
+            self.node(NODETYPE_INODE, body)
+        }
+
+        fn file(&mut self, ino: u32, version: u32, content: &[u8]) {
+            self.inode(ino, version, 0o100644, content.len() as u32, 0, content.len() as u32, COMPR_NONE, content);
+        }
+
+        /// Round up to a whole erase block of erased flash
+        fn erase_block(&mut self) {
+            let len = (self.data.len() / 0x1000 + 1) * 0x1000;
+            self.data.resize(len, 0xFF);
+        }
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn build_image(endian: Endian) -> Vec<u8> {
+        let mut image = Image::new(endian);
+        image.node(NODETYPE_CLEANMARKER, Vec::new());
+        image.dirent(ROOT_INO, 1, 2, DT_DIR, "etc");
+        image.inode(2, 1, 0o040755, 0, 0, 0, COMPR_NONE, &[]);
+        image.dirent(2, 2, 3, DT_REG, "config");
+        image.file(3, 1, b"old settings\n");
+        image.erase_block();
+        // Rewritten in place: the newer version wins
+        image.file(3, 2, b"wifi=on\n");
+        image.dirent(ROOT_INO, 3, 4, DT_LNK, "link");
+        image.inode(4, 1, 0o120777, 10, 0, 10, COMPR_NONE, b"etc/config");
+        // Two pages, the first zlib-compressed, then a truncation and a hole
+        let page = vec![b'x'; 4096];
+        image.dirent(ROOT_INO, 4, 5, DT_REG, "big.bin");
+        image.inode(5, 1, 0o100644, 4096, 0, 4096, COMPR_ZLIB, &zlib(&page));
+        image.inode(5, 2, 0o100644, 8192, 4096, 4096, COMPR_NONE, &vec![b'y'; 4096]);
+        image.inode(5, 3, 0o100644, 100, 0, 0, COMPR_NONE, &[]);
+        image.inode(5, 4, 0o100644, 5000, 4990, 10, COMPR_RTIME, &[b'z', 9]);
+        // Created, then deleted
+        image.dirent(ROOT_INO, 5, 6, DT_REG, "gone.txt");
+        image.file(6, 1, b"temporary");
+        image.dirent(ROOT_INO, 6, 0, DT_REG, "gone.txt");
+        // An obsolete node is skipped
+        let at = image.dirent(ROOT_INO, 7, 3, DT_REG, "stale");
+        let flag = match endian { Endian::Little => at + 3, Endian::Big => at + 2 };
+        image.data[flag] &= !0x20;
+        image.erase_block();
+        image.data
+    }
+
+    fn names(entries: Vec<FileEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_read_jffs2_image() {
+        let dir = tempfile::tempdir().unwrap();
+        for endian in [Endian::Big, Endian::Little] {
+            let path = dir.path().join(format!("{:?}.jffs2", endian));
+            std::fs::write(&path, build_image(endian)).unwrap();
+            let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+            let mut reader = Jffs2Reader::new(device.clone()).unwrap();
+            assert_eq!(reader.endian(), endian);
+            assert_eq!(names(reader.list_directory("/").unwrap()), ["big.bin", "etc", "link"]);
+            assert_eq!(reader.read_file("/etc/config").unwrap(), b"wifi=on\n");
+            let big = reader.read_file("/big.bin").unwrap();
+            assert_eq!(big.len(), 5000);
+            assert!(big[..100].iter().all(|&b| b == b'x'));
+            assert!(big[100..4990].iter().all(|&b| b == 0), "truncated data must not come back");
+            assert_eq!(&big[4990..], b"zzzzzzzzzz");
+            let root = reader.list_directory("/").unwrap();
+            let link = root.iter().find(|entry| entry.name == "link").unwrap();
+            assert_eq!(link.metadata.link_target.as_deref(), Some("etc/config"));
+            assert!(root.iter().find(|entry| entry.name == "etc").unwrap().is_directory);
+            assert!(reader.read_file("/gone.txt").is_err());
+            assert!(reader.read_file("/etc/config/x").is_err());
+
+            // Found by the sniffer and the ops registry
+            let mut file = std::fs::File::open(&path).unwrap();
+            assert_eq!(crate::detection::detect_filesystem(&mut file).unwrap(), "jffs2");
+            let mut registry = crate::ops::FilesystemOpsRegistry::new();
+            crate::ops_registry::register_all_filesystems(&mut registry, false);
+            let mut ops = registry.create_ops(&device, None).unwrap();
+            assert_eq!(ops.filesystem_type(), "jffs2");
+            assert_eq!(ops.read(Path::new("/big.bin"), 98, 4).unwrap(), b"xx\0\0");
+            assert_eq!(ops.readlink(Path::new("/link")).unwrap(), Path::new("etc/config"));
+            let attributes = ops.stat(Path::new("/etc/config")).unwrap();
+            assert_eq!((attributes.size, attributes.owner, attributes.permissions), (8, Some(1000), 0o644));
+        }
+
+        // Inside a larger firmware dump the nodes are still found
+        let mut dump = vec![0xA5; 0x2_0000];
+        dump.extend(build_image(Endian::Big));
+        let path = dir.path().join("firmware.bin");
+        std::fs::write(&path, &dump).unwrap();
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+        assert_eq!(Jffs2Reader::new(device).unwrap().read_file("/etc/config").unwrap(), b"wifi=on\n");
+
+        // A damaged data node falls back to the previous version of the file
+        let mut image = build_image(Endian::Little);
+        let newest = image.windows(8).position(|window| window == b"wifi=on\n").unwrap();
+        image[newest] ^= 0xFF;
+        std::fs::write(&path, &image).unwrap();
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+        assert_eq!(Jffs2Reader::new(device).unwrap().read_file("/etc/config").unwrap(), b"old settings\n");
+    }
+}
@@ -0,0 +1,278 @@
+// JFFS2 on-disk structures - node headers, directory entries, inode nodes and compressors
+// Every node starts with the same 12-byte header: magic, node type, total length and a CRC
+// of the first eight bytes. Images are written in the byte order of the board's CPU, so
+// big-endian MIPS routers and little-endian ARM boards both occur; the reader picks the
+// order from the first good node. JFFS2's CRC32 is the common polynomial without the usual
+// inversion before and after.
+use moses_core::MosesError;
+use std::io::Read;
+
+pub const MAGIC: u16 = 0x1985;
+pub const HEADER_SIZE: usize = 12;
+/// Set when a node is written and cleared in place when it becomes obsolete
+pub const NODE_ACCURATE: u16 = 0x2000;
+pub const NODETYPE_DIRENT: u16 = 0xE001;
+pub const NODETYPE_INODE: u16 = 0xE002;
+pub const NODETYPE_CLEANMARKER: u16 = 0x2003;
+pub const NODETYPE_PADDING: u16 = 0x2004;
+
+/// Fixed part of a directory entry node; the name follows
+pub const DIRENT_SIZE: usize = 40;
+/// Fixed part of an inode node; the (compressed) data follows
+pub const INODE_SIZE: usize = 68;
+/// The root directory has no inode node of its own
+pub const ROOT_INO: u32 = 1;
+/// Longest name a directory entry can hold
+pub const MAX_NAME_LEN: usize = 254;
+
+/// Compression of an inode node's data
+pub const COMPR_NONE: u8 = 0x00;
+pub const COMPR_ZERO: u8 = 0x01;
+pub const COMPR_RTIME: u8 = 0x02;
+pub const COMPR_RUBINMIPS: u8 = 0x03;
+pub const COMPR_COPY: u8 = 0x04;
+pub const COMPR_DYNRUBIN: u8 = 0x05;
+pub const COMPR_ZLIB: u8 = 0x06;
+pub const COMPR_LZO: u8 = 0x07;
+pub const COMPR_LZMA: u8 = 0x08;
+
+/// Directory entry file types (the DT_* values)
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn u16(self, data: &[u8], offset: usize) -> u16 {
+        let bytes = data[offset..offset + 2].try_into().unwrap();
+        match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn u32(self, data: &[u8], offset: usize) -> u32 {
+        let bytes = data[offset..offset + 4].try_into().unwrap();
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+pub(crate) fn corrupt(what: &str) -> MosesError {
+    MosesError::Other(format!("Corrupted JFFS2 {}", what))
+}
+
+/// CRC32 as JFFS2 computes it: seed 0, no final inversion
+pub fn crc(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new_with_initial(!0);
+    hasher.update(data);
+    !hasher.finalize()
+}
+
+/// The header every node starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeHeader {
+    /// Node type with the accurate bit set
+    pub nodetype: u16,
+    pub totlen: u32,
+    /// The accurate bit was cleared: a newer node replaced this one
+    pub obsolete: bool,
+}
+
+impl NodeHeader {
+    /// The header at the start of `data`, if there is one whose CRC checks out
+    pub fn parse(data: &[u8], endian: Endian) -> Option<Self> {
+        if data.len() < HEADER_SIZE || endian.u16(data, 0) != MAGIC {
+            return None;
+        }
+        let nodetype = endian.u16(data, 2);
+        let totlen = endian.u32(data, 4);
+        // The CRC was taken before the node was marked obsolete
+        let mut covered = [0u8; 8];
+        covered.copy_from_slice(&data[..8]);
+        let accurate = match endian {
+            Endian::Little => (nodetype | NODE_ACCURATE).to_le_bytes(),
+            Endian::Big => (nodetype | NODE_ACCURATE).to_be_bytes(),
+        };
+        covered[2..4].copy_from_slice(&accurate);
+        if crc(&covered) != endian.u32(data, 8) || (totlen as usize) < HEADER_SIZE {
+            return None;
+        }
+        Some(Self { nodetype: nodetype | NODE_ACCURATE, totlen, obsolete: nodetype & NODE_ACCURATE == 0 })
+    }
+}
+
+/// A name for inode `ino` in directory `pino`; `ino` 0 removes the name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    pub pino: u32,
+    pub version: u32,
+    pub ino: u32,
+    pub mctime: u32,
+    pub dtype: u8,
+    pub name: String,
+}
+
+impl Dirent {
+    pub fn parse(node: &[u8], endian: Endian) -> Result<Self, MosesError> {
+        if node.len() < DIRENT_SIZE {
+            return Err(corrupt("directory entry: node is short"));
+        }
+        if crc(&node[..32]) != endian.u32(node, 32) {
+            return Err(corrupt("directory entry: node checksum mismatch"));
+        }
+        let nsize = node[28] as usize;
+        let name = node.get(DIRENT_SIZE..DIRENT_SIZE + nsize)
+            .ok_or_else(|| corrupt("directory entry: name runs past the node"))?;
+        if crc(name) != endian.u32(node, 36) {
+            return Err(corrupt("directory entry: name checksum mismatch"));
+        }
+        if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+            return Err(corrupt("directory entry: invalid name"));
+        }
+        Ok(Self {
+            pino: endian.u32(node, 12),
+            version: endian.u32(node, 16),
+            ino: endian.u32(node, 20),
+            mctime: endian.u32(node, 24),
+            dtype: node[29],
+            name: String::from_utf8_lossy(name).into_owned(),
+        })
+    }
+}
+
+/// Metadata of an inode as of one version, with the piece of data written at that version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InodeNode {
+    pub ino: u32,
+    pub version: u32,
+    pub mode: u32,
+    pub uid: u16,
+    pub gid: u16,
+    /// File size after this node was written
+    pub isize: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+    /// Where in the file the data goes
+    pub offset: u32,
+    /// Stored and uncompressed length of the data
+    pub csize: u32,
+    pub dsize: u32,
+    pub compr: u8,
+    pub data_crc: u32,
+}
+
+impl InodeNode {
+    /// The fixed part of an inode node; the data is checked separately
+    pub fn parse(node: &[u8], endian: Endian) -> Result<Self, MosesError> {
+        if node.len() < INODE_SIZE {
+            return Err(corrupt("inode: node is short"));
+        }
+        if crc(&node[..60]) != endian.u32(node, 64) {
+            return Err(corrupt("inode: node checksum mismatch"));
+        }
+        Ok(Self {
+            ino: endian.u32(node, 12),
+            version: endian.u32(node, 16),
+            mode: endian.u32(node, 20),
+            uid: endian.u16(node, 24),
+            gid: endian.u16(node, 26),
+            isize: endian.u32(node, 28),
+            atime: endian.u32(node, 32),
+            mtime: endian.u32(node, 36),
+            ctime: endian.u32(node, 40),
+            offset: endian.u32(node, 44),
+            csize: endian.u32(node, 48),
+            dsize: endian.u32(node, 52),
+            compr: node[56],
+            data_crc: endian.u32(node, 60),
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+/// `dsize` bytes of data stored with compressor `compr`
+pub fn decompress(compr: u8, data: &[u8], dsize: usize) -> Result<Vec<u8>, MosesError> {
+    let mut out = match compr {
+        COMPR_NONE | COMPR_COPY => data.to_vec(),
+        COMPR_ZERO => return Ok(vec![0; dsize]),
+        COMPR_RTIME => rtime_decompress(data, dsize)?,
+        COMPR_ZLIB => {
+            // The kernel writes a zlib header and skips it, and the checksum, on the way back
+            if data.len() < 2 || data[0] & 0x0F != 8 || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0 {
+                return Err(corrupt("zlib data: bad header"));
+            }
+            let mut out = Vec::with_capacity(dsize);
+            flate2::read::DeflateDecoder::new(&data[2..]).take(dsize as u64).read_to_end(&mut out)
+                .map_err(|e| corrupt(&format!("zlib data: {}", e)))?;
+            out
+        }
+        COMPR_RUBINMIPS | COMPR_DYNRUBIN => return Err(MosesError::NotSupported("JFFS2 rubin compression".to_string())),
+        COMPR_LZO => return Err(MosesError::NotSupported("JFFS2 LZO compression".to_string())),
+        COMPR_LZMA => return Err(MosesError::NotSupported("JFFS2 LZMA compression".to_string())),
+        other => return Err(corrupt(&format!("inode data: unknown compression {:#x}", other))),
+    };
+    if out.len() < dsize {
+        return Err(corrupt(&format!("inode data: {} of {} bytes after decompression", out.len(), dsize)));
+    }
+    out.truncate(dsize);
+    Ok(out)
+}
+
+/// JFFS2's run-length scheme: each byte is followed by how many bytes to copy from where
+/// that byte value was last seen
+fn rtime_decompress(data: &[u8], dsize: usize) -> Result<Vec<u8>, MosesError> {
+    let mut positions = [0usize; 256];
+    let mut out = Vec::with_capacity(dsize);
+    let mut pairs = data.as_chunks::<2>().0.iter();
+    while out.len() < dsize {
+        let [value, repeat] = *pairs.next().ok_or_else(|| corrupt("rtime data: ends early"))?;
+        out.push(value);
+        let back = positions[value as usize];
+        positions[value as usize] = out.len();
+        // Byte by byte: the copy may overlap what it produces
+        for i in 0..repeat as usize {
+            let byte = out[back + i];
+            out.push(byte);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_and_rtime() {
+        // The standard check string without the pre- and post-inversion
+        assert_eq!(crc(b"123456789"), 0x2DFD_2D88);
+        // The second 'a' copies two bytes from just after the first one
+        assert_eq!(rtime_decompress(&[b'a', 0, b'a', 2, b'b', 0], 5).unwrap(), b"aaaab");
+        assert!(rtime_decompress(&[b'a', 0], 3).is_err());
+    }
+}
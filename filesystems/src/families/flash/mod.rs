@@ -0,0 +1,6 @@
+// Flash filesystem family - filesystems written straight to raw NOR/NAND flash
+// Routers, cameras and other embedded boards keep their writable partitions in flash
+// filesystems that have no fixed on-disk layout: they append nodes to erase blocks and the
+// newest version of each wins. Moses reads them from firmware dumps and image files.
+
+pub mod jffs2;
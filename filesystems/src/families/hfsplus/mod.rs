@@ -0,0 +1,18 @@
+// HFS+ family - read-only access to Mac OS Extended volumes on any platform
+// HFS+ keeps every folder and file in a catalog B-tree and the extents of fragmented
+// files in a second B-tree. The reader follows both to list and read files on HFS+ and
+// HFSX volumes, including those embedded in a classic HFS wrapper; nothing is ever written.
+
+pub mod structures;
+pub mod reader;
+pub mod ops;
+
+pub use reader::HfsPlusReader;
+pub use ops::{HfsPlusOps, HfsPlusDetector};
+
+/// Whether the bytes at 1 KiB are an HFS+ or HFSX volume header, or a classic HFS
+/// wrapper holding one
+pub fn has_volume_header(data: &[u8]) -> bool {
+    data.len() >= 4 && matches!(&data[..4], b"H+\x00\x04" | b"HX\x00\x05")
+        || structures::embedded_volume_offset(data).is_some()
+}
@@ -0,0 +1,153 @@
+// HFS+ FilesystemOps implementation for mounting and browsing, read-only
+use super::reader::{HfsPlusEntry, HfsPlusReader};
+use super::structures::*;
+use crate::device_reader::FilesystemReader;
+use crate::ops::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct HfsPlusOps {
+    reader: Mutex<Option<HfsPlusReader>>,
+}
+
+impl HfsPlusOps {
+    pub fn new() -> Self {
+        Self { reader: Mutex::new(None) }
+    }
+
+    /// Run `f` on the reader with `path` resolved to its catalog entry
+    fn with_path<T>(&self, path: &Path, f: impl FnOnce(&mut HfsPlusReader, HfsPlusEntry) -> Result<T, MosesError>) -> Result<T, MosesError> {
+        let path = path.to_str().ok_or_else(|| MosesError::InvalidInput("Invalid path".to_string()))?;
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let entry = reader.lookup(path)?;
+        f(reader, entry)
+    }
+}
+
+impl Default for HfsPlusOps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attributes(record: &CatalogRecord) -> FileAttributes {
+    let (common, size, is_symlink) = match record {
+        CatalogRecord::File(file) => (&file.common, file.data_fork.logical_size, file.is_symlink()),
+        CatalogRecord::Folder(folder) => (folder, 0, false),
+        CatalogRecord::Thread { .. } => unreachable!("lookups never end at a thread"),
+    };
+    let is_directory = record.is_folder();
+    // Volumes from before Mac OS X keep no permissions
+    let permissions = match common.bsd.mode & 0o7777 {
+        0 if is_directory => 0o755,
+        0 => 0o644,
+        mode => u32::from(mode),
+    };
+    FileAttributes {
+        size,
+        is_directory,
+        is_file: !is_directory && !is_symlink,
+        is_symlink,
+        created: mac_time(common.create_date),
+        modified: mac_time(common.modify_date),
+        accessed: mac_time(common.access_date),
+        permissions,
+        owner: Some(common.bsd.owner),
+        group: Some(common.bsd.group),
+    }
+}
+
+fn file_of(entry: HfsPlusEntry) -> Result<Box<CatalogFile>, MosesError> {
+    match entry.record {
+        CatalogRecord::File(file) => Ok(file),
+        _ => Err(MosesError::InvalidInput(format!("{} is a folder", entry.name))),
+    }
+}
+
+impl FilesystemOps for HfsPlusOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        *self.reader.lock().unwrap() = Some(HfsPlusReader::new(device.clone())?);
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        let reader = self.reader.lock().unwrap();
+        let reader = reader.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let header = reader.volume_header();
+        let info = reader.get_info();
+        let free = u64::from(header.free_blocks) * u64::from(header.block_size);
+        Ok(FilesystemInfo {
+            total_space: info.total_bytes,
+            free_space: free,
+            available_space: free,
+            // Catalog node IDs are 32 bits
+            total_inodes: u64::from(u32::MAX),
+            free_inodes: u64::from(u32::MAX - header.file_count.saturating_add(header.folder_count)),
+            block_size: header.block_size,
+            fragment_size: header.block_size,
+            max_filename_length: 255,
+            filesystem_type: info.fs_type,
+            volume_label: info.label,
+            volume_uuid: header.volume_id(),
+            is_readonly: true,
+        })
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.with_path(path, |_, entry| Ok(attributes(&entry.record)))
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.with_path(path, |reader, entry| {
+            let CatalogRecord::Folder(folder) = entry.record else {
+                return Err(MosesError::InvalidInput(format!("{} is not a folder", entry.name)));
+            };
+            Ok(reader.list(folder.id)?.into_iter()
+                .map(|child| DirectoryEntry { attributes: attributes(&child.record), name: child.name })
+                .collect())
+        })
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.with_path(path, |reader, entry| reader.read(&*file_of(entry)?, offset, size as u64))
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.with_path(path, |_, entry| Ok(entry.record.id().map(u64::from))).ok().flatten()
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.with_path(path, |reader, entry| reader.read_link(&*file_of(entry)?).map(PathBuf::from))
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn filesystem_type(&self) -> &str {
+        "hfsplus"
+    }
+}
+
+/// Finds HFS+ and HFSX by the volume header at 1 KiB, also inside a classic HFS wrapper
+pub struct HfsPlusDetector;
+
+impl crate::ops::FilesystemDetector for HfsPlusDetector {
+    fn detect(&self, device: &Device) -> Result<Option<String>, MosesError> {
+        use crate::utils::{open_device_read, read_block};
+
+        let mut file = open_device_read(device)?;
+        match read_block(&mut file, VOLUME_HEADER_OFFSET, 512) {
+            Ok(header) if super::has_volume_header(&header) => Ok(Some("hfsplus".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
@@ -0,0 +1,717 @@
+// HFS+ reader - volume header to catalog B-tree to files
+// Every folder and file is a record in the catalog B-tree, keyed by its parent folder's ID
+// and its name, so a folder's contents sit next to each other in the tree's leaves. A
+// listing descends to the first key of the folder and walks the leaves from there. Forks
+// list their first eight extents; the rest are found in the extents overflow B-tree, which
+// the catalog file itself may need on fragmented volumes. Hard links are resolved through
+// the hidden folders at the root that hold their targets, and volumes wrapped in a classic
+// HFS volume, as old Macs formatted them, are read through the wrapper.
+use super::structures::*;
+use crate::device_reader::{AlignedDeviceReader, FileEntry, FileMetadata, FilesystemInfo, FilesystemReader};
+use log::{info, warn};
+use moses_core::{Device, MosesError};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// B-tree nodes kept in memory; listings revisit the index nodes above their leaves
+const NODE_CACHE_LIMIT: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Tree {
+    Extents,
+    Catalog,
+}
+
+struct BTree {
+    header: BTreeHeader,
+    fork: ForkData,
+}
+
+/// The allocation blocks of the HFS+ volume, wherever it starts on the device
+struct Volume {
+    reader: AlignedDeviceReader,
+    /// Past the wrapper on volumes embedded in classic HFS
+    offset: u64,
+    block_size: u64,
+    total_blocks: u64,
+}
+
+impl Volume {
+    /// `len` bytes from byte `start` of the fork mapped by `extents`
+    fn read(&mut self, extents: &[ExtentDescriptor], start: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+        let end = start + len as u64;
+        let mut data = Vec::with_capacity(len);
+        let mut fork_start = 0u64;
+        for extent in extents {
+            let fork_end = fork_start + u64::from(extent.block_count) * self.block_size;
+            let (from, to) = (start.max(fork_start), end.min(fork_end));
+            if from < to {
+                if u64::from(extent.start_block) + u64::from(extent.block_count) > self.total_blocks {
+                    return Err(corrupt(&format!("extent at block {}: past the end of the volume", extent.start_block)));
+                }
+                let disk = self.offset + u64::from(extent.start_block) * self.block_size + (from - fork_start);
+                data.extend(self.reader.read_at(disk, (to - from) as usize)?);
+            }
+            if fork_end >= end {
+                break;
+            }
+            fork_start = fork_end;
+        }
+        if data.len() < len {
+            return Err(corrupt("fork: its extents end before its data"));
+        }
+        Ok(data)
+    }
+
+    fn open_tree(&mut self, fork: ForkData) -> Result<BTree, MosesError> {
+        let head = self.read(&fork.extents, 0, 512)?;
+        Ok(BTree { header: BTreeHeader::parse(&head)?, fork })
+    }
+}
+
+/// One name in a folder listing, with hard links replaced by what they link to
+#[derive(Debug, Clone)]
+pub struct HfsPlusEntry {
+    pub name: String,
+    pub record: CatalogRecord,
+}
+
+/// Read-only HFS+ or HFSX volume
+pub struct HfsPlusReader {
+    volume: Volume,
+    header: VolumeHeader,
+    root: HfsPlusEntry,
+    extents: BTree,
+    catalog: BTree,
+    node_cache: HashMap<(Tree, u32), Vec<u8>>,
+    /// Targets of hard links by their name in the hidden link folders, loaded on first use
+    link_targets: Option<HashMap<String, CatalogRecord>>,
+}
+
+impl HfsPlusReader {
+    pub fn new(device: Device) -> Result<Self, MosesError> {
+        info!("Opening HFS+ filesystem on device: {}", device.name);
+        let file = crate::utils::open_device_with_fallback(&device)?;
+        Self::from_reader(AlignedDeviceReader::new(file))
+    }
+
+    pub fn from_reader(mut reader: AlignedDeviceReader) -> Result<Self, MosesError> {
+        let mut offset = 0;
+        let mut data = reader.read_at(VOLUME_HEADER_OFFSET, 512)?;
+        if let Some(embedded) = embedded_volume_offset(&data) {
+            info!("HFS+ volume is wrapped in classic HFS; it starts at byte {}", embedded);
+            offset = embedded;
+            data = reader.read_at(offset + VOLUME_HEADER_OFFSET, 512)?;
+        }
+        let header = VolumeHeader::parse(&data)?;
+        if header.attributes & ATTR_JOURNALED != 0 && header.attributes & ATTR_UNMOUNTED == 0 {
+            warn!("HFS+ volume was not unmounted cleanly; changes still in its journal will not be seen");
+        }
+
+        let mut volume = Volume {
+            reader,
+            offset,
+            block_size: u64::from(header.block_size),
+            total_blocks: u64::from(header.total_blocks),
+        };
+        // The extents file never overflows; the catalog's header node is in its first extent
+        let extents = volume.open_tree(header.extents_file.clone())?;
+        let catalog = volume.open_tree(header.catalog_file.clone())?;
+        let mut this = Self {
+            volume,
+            header,
+            root: HfsPlusEntry { name: String::new(), record: CatalogRecord::Thread { parent_id: 0, name: String::new() } },
+            extents,
+            catalog,
+            node_cache: HashMap::new(),
+            link_targets: None,
+        };
+        let catalog_fork = this.catalog.fork.clone();
+        this.catalog.fork = this.full_fork(CATALOG_FILE_ID, DATA_FORK, &catalog_fork)?;
+
+        // The root folder is the one child of the root's parent, named after the volume
+        this.root = this.catalog_children(ROOT_PARENT_ID)?
+            .into_iter()
+            .find(|(_, record)| record.id() == Some(ROOT_FOLDER_ID) && record.is_folder())
+            .map(|(key, record)| HfsPlusEntry { name: key.name, record })
+            .ok_or_else(|| corrupt("catalog: no root folder"))?;
+        Ok(this)
+    }
+
+    pub fn volume_header(&self) -> &VolumeHeader {
+        &self.header
+    }
+
+    pub fn label(&self) -> &str {
+        &self.root.name
+    }
+
+    fn tree(&self, tree: Tree) -> &BTree {
+        match tree {
+            Tree::Extents => &self.extents,
+            Tree::Catalog => &self.catalog,
+        }
+    }
+
+    fn read_node(&mut self, tree: Tree, number: u32) -> Result<Vec<u8>, MosesError> {
+        if let Some(node) = self.node_cache.get(&(tree, number)) {
+            return Ok(node.clone());
+        }
+        let BTree { header, fork } = self.tree(tree);
+        if number >= header.total_nodes {
+            return Err(corrupt(&format!("{:?} B-tree: node {} of {}", tree, number, header.total_nodes)));
+        }
+        let size = header.node_size as usize;
+        let extents = fork.extents.clone();
+        let node = self.volume.read(&extents, u64::from(number) * size as u64, size)?;
+        if self.node_cache.len() >= NODE_CACHE_LIMIT {
+            self.node_cache.clear();
+        }
+        self.node_cache.insert((tree, number), node.clone());
+        Ok(node)
+    }
+
+    /// The leaf node where keys ordered by `compare` against the target start
+    fn find_leaf(&mut self, tree: Tree, compare: &dyn Fn(&[u8]) -> Result<Ordering, MosesError>) -> Result<Option<u32>, MosesError> {
+        let header = self.tree(tree).header.clone();
+        if header.root == 0 {
+            return Ok(None);
+        }
+        let (mut number, mut height) = (header.root, header.depth);
+        loop {
+            let node = self.read_node(tree, number)?;
+            let descriptor = NodeDescriptor::parse(&node);
+            // Heights fall by one on every step down, which also rules out loops
+            match descriptor.kind {
+                LEAF_NODE if height == 1 && descriptor.height == 1 => return Ok(Some(number)),
+                INDEX_NODE if height > 1 && u16::from(descriptor.height) == height => {
+                    let records = node_records(&node)?;
+                    let first = records.first().ok_or_else(|| corrupt(&format!("{:?} B-tree: empty index node {}", tree, number)))?;
+                    // The last key not after the target; before every key, the first child
+                    let mut child = index_pointer(first)?;
+                    for record in &records[1..] {
+                        if compare(record)? == Ordering::Greater {
+                            break;
+                        }
+                        child = index_pointer(record)?;
+                    }
+                    number = child;
+                    height -= 1;
+                }
+                kind => {
+                    return Err(corrupt(&format!(
+                        "{:?} B-tree: node {} of kind {} at height {}, expected {}", tree, number, kind, descriptor.height, height
+                    )));
+                }
+            }
+        }
+    }
+
+    /// The extents overflow record starting at `key`
+    fn find_extents(&mut self, key: ExtentKey) -> Result<Option<Vec<ExtentDescriptor>>, MosesError> {
+        let Some(number) = self.find_leaf(Tree::Extents, &|record| Ok(ExtentKey::parse(record)?.cmp(&key)))? else {
+            return Ok(None);
+        };
+        let node = self.read_node(Tree::Extents, number)?;
+        for record in node_records(&node)? {
+            if ExtentKey::parse(record)? == key {
+                let data = &record[key_end(record)?..];
+                if data.len() < EXTENTS_PER_RECORD * 8 {
+                    return Err(corrupt("extents overflow record: too short"));
+                }
+                return Ok(Some(parse_extents(data)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// A fork with all its extents, including those in the overflow file
+    fn full_fork(&mut self, file_id: u32, fork_type: u8, fork: &ForkData) -> Result<ForkData, MosesError> {
+        let mut full = fork.clone();
+        while full.mapped_blocks() < u64::from(fork.total_blocks) {
+            let start_block = full.mapped_blocks() as u32;
+            let missing = || corrupt(&format!("file {}: no extents from block {}", file_id, start_block));
+            if file_id == EXTENTS_FILE_ID {
+                return Err(missing());
+            }
+            let more = self.find_extents(ExtentKey { file_id, fork_type, start_block })?.ok_or_else(missing)?;
+            if more.is_empty() {
+                return Err(missing());
+            }
+            full.extents.extend(more);
+        }
+        Ok(full)
+    }
+
+    /// Every record whose parent is `parent`, threads left out
+    fn catalog_children(&mut self, parent: u32) -> Result<Vec<(CatalogKey, CatalogRecord)>, MosesError> {
+        // A folder's thread has an empty name, so its key comes before all its children's
+        let compare = |record: &[u8]| {
+            let key = CatalogKey::parse(record)?;
+            Ok(key.parent_id.cmp(&parent).then(if key.name.is_empty() { Ordering::Equal } else { Ordering::Greater }))
+        };
+        let Some(mut number) = self.find_leaf(Tree::Catalog, &compare)? else {
+            return Ok(Vec::new());
+        };
+        let mut children = Vec::new();
+        for _ in 0..self.catalog.header.total_nodes {
+            let node = self.read_node(Tree::Catalog, number)?;
+            let descriptor = NodeDescriptor::parse(&node);
+            if descriptor.kind != LEAF_NODE {
+                return Err(corrupt(&format!("catalog: leaf chain reaches node {} of kind {}", number, descriptor.kind)));
+            }
+            for record in node_records(&node)? {
+                let key = CatalogKey::parse(record)?;
+                match key.parent_id.cmp(&parent) {
+                    Ordering::Less => continue,
+                    Ordering::Greater => return Ok(children),
+                    Ordering::Equal => {}
+                }
+                let record = CatalogRecord::parse(&record[key_end(record)?..])?;
+                if !matches!(record, CatalogRecord::Thread { .. }) {
+                    children.push((key, record));
+                }
+            }
+            if descriptor.next == 0 {
+                return Ok(children);
+            }
+            number = descriptor.next;
+        }
+        Err(corrupt("catalog: leaf chain loops"))
+    }
+
+    fn link_targets(&mut self) -> Result<&HashMap<String, CatalogRecord>, MosesError> {
+        if self.link_targets.is_none() {
+            let mut targets = HashMap::new();
+            for (key, record) in self.catalog_children(ROOT_FOLDER_ID)? {
+                if let (FILE_LINK_DIR | FOLDER_LINK_DIR, CatalogRecord::Folder(folder)) = (key.name.as_str(), &record) {
+                    for (key, record) in self.catalog_children(folder.id)? {
+                        targets.insert(key.name, record);
+                    }
+                }
+            }
+            self.link_targets = Some(targets);
+        }
+        Ok(self.link_targets.as_ref().unwrap())
+    }
+
+    /// What a hard link points to; anything else as it is
+    fn resolve(&mut self, record: CatalogRecord) -> Result<CatalogRecord, MosesError> {
+        let name = match &record {
+            CatalogRecord::File(file) if file.is_file_link() => format!("iNode{}", file.common.bsd.special),
+            CatalogRecord::File(file) if file.is_folder_link() => format!("dir_{}", file.common.bsd.special),
+            _ => return Ok(record),
+        };
+        match self.link_targets()?.get(&name) {
+            Some(target) => Ok(target.clone()),
+            None => {
+                warn!("HFS+ hard link to {} has no target", name);
+                Ok(record)
+            }
+        }
+    }
+
+    /// The contents of folder `id`, without the hidden folders that hold hard link targets
+    pub fn list(&mut self, id: u32) -> Result<Vec<HfsPlusEntry>, MosesError> {
+        let mut entries = Vec::new();
+        for (key, record) in self.catalog_children(id)? {
+            if id == ROOT_FOLDER_ID && (key.name == FILE_LINK_DIR || key.name == FOLDER_LINK_DIR) {
+                continue;
+            }
+            entries.push(HfsPlusEntry { name: key.name, record: self.resolve(record)? });
+        }
+        Ok(entries)
+    }
+
+    /// Resolve a `/`-separated path from the root folder; names compare as the volume does
+    pub fn lookup(&mut self, path: &str) -> Result<HfsPlusEntry, MosesError> {
+        let case_sensitive = self.catalog.header.key_compare_type == KEY_COMPARE_BINARY;
+        let mut entry = self.root.clone();
+        for part in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            let CatalogRecord::Folder(folder) = &entry.record else {
+                return Err(MosesError::Other(format!("Path not found: {}", path)));
+            };
+            let id = folder.id;
+            entry = self.list(id)?
+                .into_iter()
+                .find(|child| child.name == part || (!case_sensitive && child.name.to_lowercase() == part.to_lowercase()))
+                .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))?;
+        }
+        Ok(entry)
+    }
+
+    /// Up to `size` bytes of a file's data fork from `offset`
+    pub fn read(&mut self, file: &CatalogFile, offset: u64, size: u64) -> Result<Vec<u8>, MosesError> {
+        if file.is_compressed() {
+            return Err(MosesError::NotSupported(format!(
+                "HFS+ file {} is stored compressed, which is not supported yet", file.common.id
+            )));
+        }
+        let end = offset.saturating_add(size).min(file.data_fork.logical_size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let fork = self.full_fork(file.common.id, DATA_FORK, &file.data_fork)?;
+        self.volume.read(&fork.extents, offset, (end - offset) as usize)
+    }
+
+    /// Target of a symbolic link, kept as its data
+    pub fn read_link(&mut self, file: &CatalogFile) -> Result<String, MosesError> {
+        if !file.is_symlink() {
+            return Err(MosesError::InvalidInput(format!("HFS+ file {} is not a symbolic link", file.common.id)));
+        }
+        let target = self.read(file, 0, file.data_fork.logical_size)?;
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    fn file_entry(&mut self, entry: HfsPlusEntry) -> FileEntry {
+        let (common, size, allocated, link_target, compressed) = match &entry.record {
+            CatalogRecord::File(file) => (
+                &file.common,
+                file.data_fork.logical_size,
+                Some(u64::from(file.data_fork.total_blocks) * self.volume.block_size),
+                if file.is_symlink() { self.read_link(file).ok() } else { None },
+                file.is_compressed(),
+            ),
+            CatalogRecord::Folder(folder) => (folder, 0, None, None, false),
+            CatalogRecord::Thread { .. } => unreachable!("listings leave threads out"),
+        };
+        FileEntry {
+            is_directory: entry.record.is_folder(),
+            size,
+            cluster: None,
+            metadata: FileMetadata {
+                compressed,
+                link_target,
+                allocated_size: allocated,
+                created: mac_time(common.create_date),
+                modified: mac_time(common.modify_date),
+                accessed: mac_time(common.access_date),
+                readonly: common.bsd.mode != 0 && common.bsd.mode & 0o222 == 0,
+                hidden: common.is_invisible() || entry.name.starts_with('.'),
+                ..Default::default()
+            },
+            name: entry.name,
+        }
+    }
+}
+
+impl FilesystemReader for HfsPlusReader {
+    fn read_metadata(&mut self) -> Result<(), MosesError> {
+        // Already read in new()
+        Ok(())
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
+        let CatalogRecord::Folder(folder) = self.lookup(path)?.record else {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", path)));
+        };
+        let entries = self.list(folder.id)?;
+        Ok(entries.into_iter().map(|entry| self.file_entry(entry)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, MosesError> {
+        let CatalogRecord::File(file) = self.lookup(path)?.record else {
+            return Err(MosesError::InvalidInput(format!("Is a directory: {}", path)));
+        };
+        self.read(&file, 0, file.data_fork.logical_size)
+    }
+
+    fn get_info(&self) -> FilesystemInfo {
+        let block_size = self.volume.block_size;
+        let total_blocks = u64::from(self.header.total_blocks);
+        FilesystemInfo {
+            fs_type: "hfsplus".to_string(),
+            label: Some(self.root.name.clone()).filter(|label| !label.is_empty()),
+            total_bytes: total_blocks * block_size,
+            used_bytes: total_blocks.saturating_sub(u64::from(self.header.free_blocks)) * block_size,
+            cluster_size: Some(self.header.block_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const BS: usize = 4096;
+    const NODE: usize = 1024;
+    const BLOCKS: usize = 64;
+    /// 2023-11-14, in seconds since 1904
+    const MAC_TIME: u32 = 3_782_844_800;
+    const FRAG_BLOCKS: [u32; 10] = [30, 32, 34, 36, 38, 40, 42, 44, 50, 52];
+
+    fn node(kind: i8, height: u8, next: u32, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut node = vec![0u8; NODE];
+        node[..4].copy_from_slice(&next.to_be_bytes());
+        node[8] = kind as u8;
+        node[9] = height;
+        node[10..12].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        let mut at = NODE_DESCRIPTOR_SIZE;
+        for (i, record) in records.iter().enumerate() {
+            node[NODE - 2 * (i + 1)..NODE - 2 * i].copy_from_slice(&(at as u16).to_be_bytes());
+            node[at..at + record.len()].copy_from_slice(record);
+            at += record.len();
+        }
+        let free = NODE - 2 * (records.len() + 1);
+        node[free..free + 2].copy_from_slice(&(at as u16).to_be_bytes());
+        node
+    }
+
+    fn header_node(depth: u16, root: u32, first_leaf: u32, max_key: u16, total_nodes: u32, compare: u8) -> Vec<u8> {
+        let mut record = vec![0u8; 106];
+        record[..2].copy_from_slice(&depth.to_be_bytes());
+        record[2..6].copy_from_slice(&root.to_be_bytes());
+        record[10..14].copy_from_slice(&first_leaf.to_be_bytes());
+        record[18..20].copy_from_slice(&(NODE as u16).to_be_bytes());
+        record[20..22].copy_from_slice(&max_key.to_be_bytes());
+        record[22..26].copy_from_slice(&total_nodes.to_be_bytes());
+        record[37] = compare;
+        record[38..42].copy_from_slice(&6u32.to_be_bytes());
+        node(HEADER_NODE, 0, 0, &[record])
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let mut data = (units.len() as u16).to_be_bytes().to_vec();
+        data.extend(units.iter().flat_map(|unit| unit.to_be_bytes()));
+        data
+    }
+
+    fn catalog_key(parent: u32, item: &str) -> Vec<u8> {
+        let name = name(item);
+        let mut key = ((4 + name.len()) as u16).to_be_bytes().to_vec();
+        key.extend(parent.to_be_bytes());
+        key.extend(name);
+        key
+    }
+
+    fn common(kind: u16, id: u32, mode: u16, size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size];
+        data[..2].copy_from_slice(&kind.to_be_bytes());
+        data[8..12].copy_from_slice(&id.to_be_bytes());
+        for at in [12, 16, 20, 24] {
+            data[at..at + 4].copy_from_slice(&MAC_TIME.to_be_bytes());
+        }
+        data[32..36].copy_from_slice(&501u32.to_be_bytes());
+        data[36..40].copy_from_slice(&20u32.to_be_bytes());
+        data[42..44].copy_from_slice(&mode.to_be_bytes());
+        data
+    }
+
+    fn folder(id: u32) -> Vec<u8> {
+        common(FOLDER_RECORD, id, 0o40755, 88)
+    }
+
+    fn thread(parent: u32, item: &str) -> Vec<u8> {
+        let mut data = FOLDER_THREAD_RECORD.to_be_bytes().to_vec();
+        data.extend([0, 0]);
+        data.extend(parent.to_be_bytes());
+        data.extend(name(item));
+        data
+    }
+
+    fn file(id: u32, mode: u16, size: u64, blocks: &[u32]) -> Vec<u8> {
+        let mut data = common(FILE_RECORD, id, mode, 248);
+        data[88..96].copy_from_slice(&size.to_be_bytes());
+        data[100..104].copy_from_slice(&(blocks.len() as u32).to_be_bytes());
+        for (i, block) in blocks.iter().take(EXTENTS_PER_RECORD).enumerate() {
+            data[104 + i * 8..108 + i * 8].copy_from_slice(&block.to_be_bytes());
+            data[108 + i * 8..112 + i * 8].copy_from_slice(&1u32.to_be_bytes());
+        }
+        data
+    }
+
+    fn hard_link(id: u32, kind: &[u8; 4], creator: &[u8; 4], target: u32) -> Vec<u8> {
+        let mut data = file(id, 0o100644, 0, &[]);
+        data[44..48].copy_from_slice(&target.to_be_bytes());
+        data[48..52].copy_from_slice(kind);
+        data[52..56].copy_from_slice(creator);
+        data
+    }
+
+    fn extents_record(file_id: u32, start_block: u32, extents: &[(u32, u32)]) -> Vec<u8> {
+        let mut record = 10u16.to_be_bytes().to_vec();
+        record.extend([DATA_FORK, 0]);
+        record.extend(file_id.to_be_bytes());
+        record.extend(start_block.to_be_bytes());
+        let mut descriptors = [0u8; EXTENTS_PER_RECORD * 8];
+        for (i, (start, count)) in extents.iter().enumerate() {
+            descriptors[i * 8..i * 8 + 4].copy_from_slice(&start.to_be_bytes());
+            descriptors[i * 8 + 4..i * 8 + 8].copy_from_slice(&count.to_be_bytes());
+        }
+        record.extend(descriptors);
+        record
+    }
+
+    fn fork(size: u64, extents: &[(u32, u32)], total_blocks: u32) -> Vec<u8> {
+        let mut data = vec![0u8; ForkData::SIZE];
+        data[..8].copy_from_slice(&size.to_be_bytes());
+        data[12..16].copy_from_slice(&total_blocks.to_be_bytes());
+        for (i, (start, count)) in extents.iter().enumerate() {
+            data[16 + i * 8..20 + i * 8].copy_from_slice(&start.to_be_bytes());
+            data[20 + i * 8..24 + i * 8].copy_from_slice(&count.to_be_bytes());
+        }
+        data
+    }
+
+    /// A 256 KiB volume with 1 KiB B-tree nodes: a two-level catalog whose last nodes are
+    /// in an overflow extent, a file in ten extents, a symlink, a file and a folder hard
+    /// link, a compressed file and a name with a slash in it
+    fn build_image() -> Vec<u8> {
+        let mut image = vec![0u8; BLOCKS * BS];
+        let mut put = |block: usize, offset: usize, data: &[u8]| {
+            let at = block * BS + offset;
+            image[at..at + data.len()].copy_from_slice(data);
+        };
+
+        let mut header = vec![0u8; 512];
+        header[..4].copy_from_slice(b"H+\x00\x04");
+        header[4..8].copy_from_slice(&ATTR_UNMOUNTED.to_be_bytes());
+        header[16..20].copy_from_slice(&MAC_TIME.to_be_bytes());
+        header[40..44].copy_from_slice(&(BS as u32).to_be_bytes());
+        header[44..48].copy_from_slice(&(BLOCKS as u32).to_be_bytes());
+        header[48..52].copy_from_slice(&30u32.to_be_bytes());
+        header[104..112].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        header[192..272].copy_from_slice(&fork(BS as u64, &[(2, 1)], 1));
+        header[272..352].copy_from_slice(&fork(4 * BS as u64, &[(4, 3)], 4));
+        put(0, VOLUME_HEADER_OFFSET as usize, &header);
+
+        // Extents overflow: the catalog's fourth block and frag.bin's last two
+        let overflow = [extents_record(CATALOG_FILE_ID, 3, &[(12, 1)]), extents_record(20, 8, &[(50, 1), (52, 1)])];
+        put(2, 0, &header_node(1, 1, 1, 10, 4, 0));
+        put(2, NODE, &node(LEAF_NODE, 1, 0, &overflow));
+
+        let mut records = vec![
+            (ROOT_PARENT_ID, "Macintosh HD".to_string(), folder(ROOT_FOLDER_ID)),
+            (ROOT_FOLDER_ID, String::new(), thread(ROOT_PARENT_ID, "Macintosh HD")),
+            (2, "Docs".into(), folder(16)),
+            (16, String::new(), thread(2, "Docs")),
+            (2, "hello.txt".into(), file(17, 0o100644, 12, &[20])),
+            (2, "link".into(), file(18, 0o120755, 9, &[21])),
+            (2, "frag.bin".into(), file(20, 0o100644, 10 * BS as u64 - 10, &FRAG_BLOCKS)),
+            (2, "hard".into(), hard_link(21, FILE_LINK_TYPE, FILE_LINK_CREATOR, 22)),
+            (2, "a/b".into(), file(23, 0o100644, 0, &[])),
+            (2, "Docs alias".into(), hard_link(26, FOLDER_LINK_TYPE, FOLDER_LINK_CREATOR, 27)),
+            (2, FILE_LINK_DIR.into(), folder(19)),
+            (19, String::new(), thread(2, FILE_LINK_DIR)),
+            (19, "iNode22".into(), file(22, 0o100644, 7, &[22])),
+            (2, FOLDER_LINK_DIR.into(), folder(28)),
+            (28, String::new(), thread(2, FOLDER_LINK_DIR)),
+            (28, "dir_27".into(), folder(27)),
+            (27, String::new(), thread(28, "dir_27")),
+            (27, "inner.txt".into(), file(29, 0o100644, 6, &[24])),
+            (16, "notes.txt".into(), file(24, 0o100444, 6, &[23])),
+            (16, "zip.txt".into(), file(25, 0o100644, 0, &[])),
+        ];
+        // zip.txt is compressed
+        records.last_mut().unwrap().2[41] = UF_COMPRESSED;
+        records.sort_by_key(|(parent, name, _)| (*parent, name.to_lowercase()));
+
+        // Leaves are numbered down from 15, so the last ones are in the overflow extent
+        let mut leaves: Vec<Vec<Vec<u8>>> = vec![Vec::new()];
+        let mut first_keys = Vec::new();
+        for (parent, name, data) in &records {
+            let mut record = catalog_key(*parent, name);
+            record.extend(data);
+            let leaf = leaves.last().unwrap();
+            let used: usize = leaf.iter().map(Vec::len).sum();
+            if NODE_DESCRIPTOR_SIZE + used + record.len() + 2 * (leaf.len() + 2) > NODE {
+                leaves.push(Vec::new());
+            }
+            if leaves.last().unwrap().is_empty() {
+                first_keys.push(catalog_key(*parent, name));
+            }
+            leaves.last_mut().unwrap().push(record);
+        }
+        assert!(leaves.len() >= 5 && leaves.len() <= 14, "{} leaves", leaves.len());
+        let number = |i: usize| 15 - i as u32;
+        let mut catalog = vec![0u8; 16 * NODE];
+        let mut put_node = |n: u32, data: &[u8]| catalog[n as usize * NODE..(n as usize + 1) * NODE].copy_from_slice(data);
+        put_node(0, &header_node(2, 1, number(0), 516, 16, 0xCF));
+        let index: Vec<Vec<u8>> = first_keys.iter().enumerate()
+            .map(|(i, key)| [key.clone(), number(i).to_be_bytes().to_vec()].concat())
+            .collect();
+        put_node(1, &node(INDEX_NODE, 2, 0, &index));
+        for (i, leaf) in leaves.iter().enumerate() {
+            let next = if i + 1 < leaves.len() { number(i + 1) } else { 0 };
+            put_node(number(i), &node(LEAF_NODE, 1, next, leaf));
+        }
+        put(4, 0, &catalog[..3 * BS]);
+        put(12, 0, &catalog[3 * BS..]);
+
+        put(20, 0, b"Hello, HFS+\n");
+        put(21, 0, b"hello.txt");
+        put(22, 0, b"shared\n");
+        put(23, 0, b"notes\n");
+        put(24, 0, b"inner\n");
+        for (i, block) in FRAG_BLOCKS.iter().enumerate() {
+            put(*block as usize, 0, &[i as u8 + 1; BS]);
+        }
+        image
+    }
+
+    fn names(entries: &[FileEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_read_hfsplus_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hfsplus.img");
+        std::fs::write(&path, build_image()).unwrap();
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+        let mut reader = HfsPlusReader::new(device.clone()).unwrap();
+        assert_eq!(reader.get_info().label.as_deref(), Some("Macintosh HD"));
+        let root = reader.list_directory("/").unwrap();
+        assert_eq!(names(&root), ["a:b", "Docs", "Docs alias", "frag.bin", "hard", "hello.txt", "link"]);
+        assert_eq!(root[5].metadata.modified, Some(1_700_000_000));
+        assert_eq!(root[6].metadata.link_target.as_deref(), Some("hello.txt"));
+        assert_eq!(names(&reader.list_directory("/Docs").unwrap()), ["notes.txt", "zip.txt"]);
+        assert_eq!(names(&reader.list_directory("/Docs alias").unwrap()), ["inner.txt"]);
+
+        assert_eq!(reader.read_file("/hello.txt").unwrap(), b"Hello, HFS+\n");
+        assert_eq!(reader.read_file("/HELLO.TXT").unwrap(), b"Hello, HFS+\n", "names compare case-insensitively");
+        assert_eq!(reader.read_file("/hard").unwrap(), b"shared\n");
+        assert_eq!(reader.read_file("/Docs alias/inner.txt").unwrap(), b"inner\n");
+        assert!(reader.list_directory("/Docs").unwrap()[0].metadata.readonly);
+        let frag = reader.read_file("/frag.bin").unwrap();
+        assert_eq!(frag.len(), 10 * BS - 10);
+        for (i, block) in frag.chunks(BS).enumerate() {
+            assert!(block.iter().all(|&b| b == i as u8 + 1), "block {}", i);
+        }
+        let error = reader.read_file("/Docs/zip.txt").unwrap_err();
+        assert!(matches!(error, MosesError::NotSupported(_)), "{}", error);
+        assert!(reader.read_file("/missing").is_err());
+
+        // Found by the sniffer and the ops registry
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(crate::detection::detect_filesystem(&mut file).unwrap(), "hfsplus");
+        let mut registry = crate::ops::FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut registry, false);
+        let mut ops = registry.create_ops(&device, None).unwrap();
+        assert_eq!(ops.filesystem_type(), "hfsplus");
+        assert_eq!(ops.read(Path::new("/frag.bin"), 9 * BS as u64 - 2, 4).unwrap(), [9, 9, 10, 10]);
+        assert_eq!(ops.readlink(Path::new("/link")).unwrap(), Path::new("hello.txt"));
+        assert_eq!(ops.stat(Path::new("/hard")).unwrap().size, 7);
+        assert_eq!(ops.directory_id(Path::new("/Docs")), Some(16));
+        let info = ops.statfs().unwrap();
+        assert_eq!(info.volume_uuid.as_deref(), Some("0102030405060708"));
+        assert_eq!(info.free_space, 30 * BS as u64);
+
+        // The same volume inside a classic HFS wrapper
+        let mut wrapped = vec![0u8; 16 * BS];
+        wrapped[1024..1026].copy_from_slice(SIGNATURE_HFS);
+        wrapped[1024 + 0x14..1024 + 0x18].copy_from_slice(&(BS as u32).to_be_bytes());
+        wrapped[1024 + 0x1C..1024 + 0x1E].copy_from_slice(&16u16.to_be_bytes());
+        wrapped[1024 + 0x7C..1024 + 0x7E].copy_from_slice(SIGNATURE_HFSPLUS);
+        wrapped[1024 + 0x7E..1024 + 0x80].copy_from_slice(&14u16.to_be_bytes());
+        wrapped.extend(build_image());
+        std::fs::write(&path, &wrapped).unwrap();
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(crate::detection::detect_filesystem(&mut file).unwrap(), "hfsplus");
+        assert_eq!(HfsPlusReader::new(device).unwrap().read_file("/hard").unwrap(), b"shared\n");
+    }
+}
@@ -0,0 +1,451 @@
+// HFS+ on-disk structures - volume header, fork data, B-tree nodes and catalog records
+// Everything is big-endian. Names are UTF-16 as Mac OS stored them, decomposed, with `/`
+// allowed since the classic Mac OS separator was `:`. Times count seconds from 1904.
+// Structures are parsed field by field from byte slices with their offsets checked, since
+// every one of them comes straight off a disk that may be damaged.
+use moses_core::MosesError;
+
+pub const VOLUME_HEADER_OFFSET: u64 = 1024;
+pub const SIGNATURE_HFSPLUS: &[u8; 2] = b"H+";
+/// HFSX: HFS+ that may compare names case-sensitively
+pub const SIGNATURE_HFSX: &[u8; 2] = b"HX";
+/// Classic HFS, which old Macs used as a wrapper around an embedded HFS+ volume
+pub const SIGNATURE_HFS: &[u8; 2] = b"BD";
+
+/// Volume attributes
+pub const ATTR_UNMOUNTED: u32 = 1 << 8;
+pub const ATTR_JOURNALED: u32 = 1 << 13;
+
+/// Catalog node IDs with fixed meanings
+pub const ROOT_PARENT_ID: u32 = 1;
+pub const ROOT_FOLDER_ID: u32 = 2;
+pub const EXTENTS_FILE_ID: u32 = 3;
+pub const CATALOG_FILE_ID: u32 = 4;
+
+/// Catalog record types
+pub const FOLDER_RECORD: u16 = 1;
+pub const FILE_RECORD: u16 = 2;
+pub const FOLDER_THREAD_RECORD: u16 = 3;
+pub const FILE_THREAD_RECORD: u16 = 4;
+const FOLDER_RECORD_SIZE: usize = 88;
+const FILE_RECORD_SIZE: usize = 248;
+
+/// B-tree node kinds
+pub const LEAF_NODE: i8 = -1;
+pub const INDEX_NODE: i8 = 0;
+pub const HEADER_NODE: i8 = 1;
+pub const NODE_DESCRIPTOR_SIZE: usize = 14;
+/// Name comparison of HFSX catalogs that compare names as stored
+pub const KEY_COMPARE_BINARY: u8 = 0xBC;
+
+/// Fork types in extents overflow keys
+pub const DATA_FORK: u8 = 0x00;
+pub const RESOURCE_FORK: u8 = 0xFF;
+pub const EXTENTS_PER_RECORD: usize = 8;
+
+/// BSD file modes
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFLNK: u16 = 0o120000;
+/// Owner flag of files whose data lives compressed in an extended attribute or resource fork
+pub const UF_COMPRESSED: u8 = 0x20;
+/// Finder flag of items hidden from the user
+const FINDER_INVISIBLE: u16 = 0x4000;
+
+/// Hard links are small files pointing into hidden folders at the root
+pub const FILE_LINK_TYPE: &[u8; 4] = b"hlnk";
+pub const FILE_LINK_CREATOR: &[u8; 4] = b"hfs+";
+pub const FOLDER_LINK_TYPE: &[u8; 4] = b"fdrp";
+pub const FOLDER_LINK_CREATOR: &[u8; 4] = b"MACS";
+pub const FILE_LINK_DIR: &str = "\0\0\0\0HFS+ Private Data";
+pub const FOLDER_LINK_DIR: &str = ".HFS+ Private Directory Data\r";
+
+/// Seconds between 1904-01-01 and 1970-01-01
+const MAC_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+pub(crate) fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn corrupt(what: &str) -> MosesError {
+    MosesError::Other(format!("Corrupted HFS+ {}", what))
+}
+
+/// Unix time of a Mac timestamp; unset and pre-1970 times have none
+pub fn mac_time(seconds: u32) -> Option<u64> {
+    u64::from(seconds).checked_sub(MAC_EPOCH_OFFSET).filter(|_| seconds != 0)
+}
+
+/// Byte offset of the HFS+ volume embedded in a classic HFS wrapper, from the wrapper's
+/// master directory block
+pub fn embedded_volume_offset(mdb: &[u8]) -> Option<u64> {
+    if mdb.len() < 0x84 || &mdb[..2] != SIGNATURE_HFS || &mdb[0x7C..0x7E] != SIGNATURE_HFSPLUS {
+        return None;
+    }
+    let block_size = u64::from(be_u32(mdb, 0x14));
+    let first_block_sector = u64::from(be_u16(mdb, 0x1C));
+    let start = u64::from(be_u16(mdb, 0x7E));
+    (block_size > 0 && block_size.is_multiple_of(512)).then(|| first_block_sector * 512 + start * block_size)
+}
+
+/// A run of allocation blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtentDescriptor {
+    pub start_block: u32,
+    pub block_count: u32,
+}
+
+/// Up to eight extent descriptors, as in fork data and extents overflow records
+pub fn parse_extents(data: &[u8]) -> Vec<ExtentDescriptor> {
+    data[..EXTENTS_PER_RECORD * 8].as_chunks::<8>().0.iter()
+        .map(|record| ExtentDescriptor { start_block: be_u32(record, 0), block_count: be_u32(record, 4) })
+        .take_while(|extent| extent.block_count > 0)
+        .collect()
+}
+
+/// Size and location of one fork of a file
+#[derive(Debug, Clone, Default)]
+pub struct ForkData {
+    pub logical_size: u64,
+    pub total_blocks: u32,
+    /// The first eight extents; larger forks continue in the extents overflow file
+    pub extents: Vec<ExtentDescriptor>,
+}
+
+impl ForkData {
+    pub const SIZE: usize = 80;
+
+    pub fn parse(data: &[u8]) -> Self {
+        Self {
+            logical_size: be_u64(data, 0),
+            total_blocks: be_u32(data, 12),
+            extents: parse_extents(&data[16..Self::SIZE]),
+        }
+    }
+
+    /// Blocks the listed extents cover
+    pub fn mapped_blocks(&self) -> u64 {
+        self.extents.iter().map(|extent| u64::from(extent.block_count)).sum()
+    }
+}
+
+/// The volume header at 1 KiB
+#[derive(Debug, Clone)]
+pub struct VolumeHeader {
+    pub signature: [u8; 2],
+    pub version: u16,
+    pub attributes: u32,
+    pub create_date: u32,
+    pub modify_date: u32,
+    pub file_count: u32,
+    pub folder_count: u32,
+    pub block_size: u32,
+    pub total_blocks: u32,
+    pub free_blocks: u32,
+    /// Finder information; the last eight bytes are the volume's 64-bit identifier
+    pub finder_info: [u8; 32],
+    pub extents_file: ForkData,
+    pub catalog_file: ForkData,
+}
+
+impl VolumeHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < 512 {
+            return Err(corrupt("volume header: too short"));
+        }
+        let signature = [data[0], data[1]];
+        let version = be_u16(data, 2);
+        if !matches!((&signature, version), (SIGNATURE_HFSPLUS, 4) | (SIGNATURE_HFSX, 5)) {
+            return Err(MosesError::Other(format!(
+                "Not an HFS+ volume (signature {:02x}{:02x}, version {})", signature[0], signature[1], version
+            )));
+        }
+        let block_size = be_u32(data, 40);
+        if !block_size.is_power_of_two() || block_size < 512 {
+            return Err(corrupt(&format!("volume header: block size {}", block_size)));
+        }
+        Ok(Self {
+            signature,
+            version,
+            attributes: be_u32(data, 4),
+            create_date: be_u32(data, 16),
+            modify_date: be_u32(data, 20),
+            file_count: be_u32(data, 32),
+            folder_count: be_u32(data, 36),
+            block_size,
+            total_blocks: be_u32(data, 44),
+            free_blocks: be_u32(data, 48),
+            finder_info: data[80..112].try_into().unwrap(),
+            extents_file: ForkData::parse(&data[192..272]),
+            catalog_file: ForkData::parse(&data[272..352]),
+        })
+    }
+
+    pub fn is_hfsx(&self) -> bool {
+        &self.signature == SIGNATURE_HFSX
+    }
+
+    /// The volume identifier Finder keeps, as hex; zero on volumes that never had one
+    pub fn volume_id(&self) -> Option<String> {
+        let id = &self.finder_info[24..32];
+        id.iter().any(|&b| b != 0).then(|| hex::encode(id))
+    }
+}
+
+/// The fixed start of every B-tree node
+#[derive(Debug, Clone, Copy)]
+pub struct NodeDescriptor {
+    pub next: u32,
+    pub kind: i8,
+    pub height: u8,
+    pub num_records: u16,
+}
+
+impl NodeDescriptor {
+    pub fn parse(node: &[u8]) -> Self {
+        Self { next: be_u32(node, 0), kind: node[8] as i8, height: node[9], num_records: be_u16(node, 10) }
+    }
+}
+
+/// The records of a node, found through the offset table at its end
+pub fn node_records(node: &[u8]) -> Result<Vec<&[u8]>, MosesError> {
+    let count = NodeDescriptor::parse(node).num_records as usize;
+    let table = (count + 1) * 2;
+    if NODE_DESCRIPTOR_SIZE + table > node.len() {
+        return Err(corrupt(&format!("B-tree node: {} records do not fit", count)));
+    }
+    let offset = |i: usize| be_u16(node, node.len() - 2 * (i + 1)) as usize;
+    let mut records = Vec::with_capacity(count);
+    for i in 0..count {
+        let (start, end) = (offset(i), offset(i + 1));
+        if start < NODE_DESCRIPTOR_SIZE || start > end || end > node.len() - table {
+            return Err(corrupt(&format!("B-tree node: record {} at {}..{}", i, start, end)));
+        }
+        records.push(&node[start..end]);
+    }
+    Ok(records)
+}
+
+/// The header record of a B-tree's node 0
+#[derive(Debug, Clone)]
+pub struct BTreeHeader {
+    pub depth: u16,
+    pub root: u32,
+    pub first_leaf: u32,
+    pub node_size: u16,
+    pub total_nodes: u32,
+    pub key_compare_type: u8,
+}
+
+impl BTreeHeader {
+    /// From the first bytes of node 0
+    pub fn parse(node: &[u8]) -> Result<Self, MosesError> {
+        if node.len() < NODE_DESCRIPTOR_SIZE + 106 || NodeDescriptor::parse(node).kind != HEADER_NODE {
+            return Err(corrupt("B-tree: no header node"));
+        }
+        let record = &node[NODE_DESCRIPTOR_SIZE..];
+        let node_size = be_u16(record, 18);
+        if !node_size.is_power_of_two() || node_size < 512 {
+            return Err(corrupt(&format!("B-tree: node size {}", node_size)));
+        }
+        Ok(Self {
+            depth: be_u16(record, 0),
+            root: be_u32(record, 2),
+            first_leaf: be_u32(record, 10),
+            node_size,
+            total_nodes: be_u32(record, 22),
+            key_compare_type: record[37],
+        })
+    }
+}
+
+/// Where the data after a record's key starts; keys carry their own length
+pub fn key_end(record: &[u8]) -> Result<usize, MosesError> {
+    let end = record.get(..2).map(|length| 2 + be_u16(length, 0) as usize);
+    match end {
+        // Data after a key starts on an even offset
+        Some(end) if end + end % 2 <= record.len() => Ok(end + end % 2),
+        _ => Err(corrupt("B-tree record: key longer than the record")),
+    }
+}
+
+/// Child node a pointer record in an index node leads to
+pub fn index_pointer(record: &[u8]) -> Result<u32, MosesError> {
+    let at = key_end(record)?;
+    record.get(at..at + 4).map(|pointer| be_u32(pointer, 0))
+        .ok_or_else(|| corrupt("B-tree index record: no child pointer"))
+}
+
+/// Catalog keys: the parent folder and the item's name in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogKey {
+    pub parent_id: u32,
+    pub name: String,
+}
+
+impl CatalogKey {
+    pub fn parse(record: &[u8]) -> Result<Self, MosesError> {
+        let end = key_end(record)?;
+        if end < 8 {
+            return Err(corrupt("catalog key: too short"));
+        }
+        Ok(Self { parent_id: be_u32(record, 2), name: parse_name(&record[6..end])? })
+    }
+}
+
+/// An HFSUniStr255: a length, then that many UTF-16 code units. Slashes become colons, as
+/// macOS shows them, since `/` separates paths here
+pub fn parse_name(data: &[u8]) -> Result<String, MosesError> {
+    let length = data.get(..2).map(|length| be_u16(length, 0) as usize).unwrap_or(usize::MAX);
+    let units = data.get(2..2 + length.saturating_mul(2))
+        .ok_or_else(|| corrupt("catalog name: longer than its record"))?;
+    let units: Vec<u16> = units.as_chunks::<2>().0.iter().map(|unit| u16::from_be_bytes(*unit)).collect();
+    Ok(String::from_utf16_lossy(&units).replace('/', ":"))
+}
+
+/// Extents overflow keys: which fork of which file, from which of its blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExtentKey {
+    pub file_id: u32,
+    pub fork_type: u8,
+    pub start_block: u32,
+}
+
+impl ExtentKey {
+    pub fn parse(record: &[u8]) -> Result<Self, MosesError> {
+        if record.len() < 12 || be_u16(record, 0) < 10 {
+            return Err(corrupt("extents overflow key: too short"));
+        }
+        Ok(Self { fork_type: record[2], file_id: be_u32(record, 4), start_block: be_u32(record, 8) })
+    }
+}
+
+/// Ownership and mode, where the volume records them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BsdInfo {
+    pub owner: u32,
+    pub group: u32,
+    pub owner_flags: u8,
+    pub mode: u16,
+    /// Link reference of hard links, device number of device files
+    pub special: u32,
+}
+
+impl BsdInfo {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            owner: be_u32(data, 0),
+            group: be_u32(data, 4),
+            owner_flags: data[9],
+            mode: be_u16(data, 10),
+            special: be_u32(data, 12),
+        }
+    }
+}
+
+/// What folder and file records share
+#[derive(Debug, Clone)]
+pub struct CatalogCommon {
+    pub id: u32,
+    pub create_date: u32,
+    pub modify_date: u32,
+    pub access_date: u32,
+    pub bsd: BsdInfo,
+    pub finder_flags: u16,
+}
+
+impl CatalogCommon {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            id: be_u32(data, 8),
+            create_date: be_u32(data, 12),
+            modify_date: be_u32(data, 16),
+            access_date: be_u32(data, 24),
+            bsd: BsdInfo::parse(&data[32..48]),
+            finder_flags: be_u16(data, 56),
+        }
+    }
+
+    pub fn is_invisible(&self) -> bool {
+        self.finder_flags & FINDER_INVISIBLE != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatalogFile {
+    pub common: CatalogCommon,
+    pub file_type: [u8; 4],
+    pub creator: [u8; 4],
+    pub data_fork: ForkData,
+    pub resource_fork: ForkData,
+}
+
+impl CatalogFile {
+    pub fn is_symlink(&self) -> bool {
+        self.common.bsd.mode & S_IFMT == S_IFLNK
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.common.bsd.owner_flags & UF_COMPRESSED != 0
+    }
+
+    pub fn is_file_link(&self) -> bool {
+        &self.file_type == FILE_LINK_TYPE && &self.creator == FILE_LINK_CREATOR
+    }
+
+    pub fn is_folder_link(&self) -> bool {
+        &self.file_type == FOLDER_LINK_TYPE && &self.creator == FOLDER_LINK_CREATOR
+    }
+}
+
+/// A catalog leaf record
+#[derive(Debug, Clone)]
+pub enum CatalogRecord {
+    Folder(CatalogCommon),
+    File(Box<CatalogFile>),
+    /// Points from an ID back to the item's parent and name
+    Thread { parent_id: u32, name: String },
+}
+
+impl CatalogRecord {
+    /// The data after a leaf record's key
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        let short = || corrupt("catalog record: shorter than its type");
+        match data.get(..2).map(|kind| be_u16(kind, 0)) {
+            Some(FOLDER_RECORD) if data.len() >= FOLDER_RECORD_SIZE => Ok(Self::Folder(CatalogCommon::parse(data))),
+            Some(FILE_RECORD) if data.len() >= FILE_RECORD_SIZE => Ok(Self::File(Box::new(CatalogFile {
+                common: CatalogCommon::parse(data),
+                file_type: data[48..52].try_into().unwrap(),
+                creator: data[52..56].try_into().unwrap(),
+                data_fork: ForkData::parse(&data[88..168]),
+                resource_fork: ForkData::parse(&data[168..248]),
+            }))),
+            Some(FOLDER_THREAD_RECORD | FILE_THREAD_RECORD) if data.len() >= 10 => Ok(Self::Thread {
+                parent_id: be_u32(data, 4),
+                name: parse_name(&data[8..])?,
+            }),
+            Some(FOLDER_RECORD | FILE_RECORD | FOLDER_THREAD_RECORD | FILE_THREAD_RECORD) => Err(short()),
+            kind => Err(corrupt(&format!("catalog record: type {:?}", kind))),
+        }
+    }
+
+    pub fn is_folder(&self) -> bool {
+        matches!(self, Self::Folder(_))
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Self::Folder(common) => Some(common.id),
+            Self::File(file) => Some(file.common.id),
+            Self::Thread { .. } => None,
+        }
+    }
+}
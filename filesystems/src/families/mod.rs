@@ -4,11 +4,14 @@
 pub mod fat;
 pub mod ext;
 pub mod ntfs;
+pub mod btrfs;
+pub mod xfs;
+pub mod hfsplus;
+pub mod flash;
+pub mod optical;
 
 // Future filesystem families
 // pub mod bsd;    // FFS/UFS family
-// pub mod flash;  // JFFS2/YAFFS/UBIFS
-// pub mod optical; // ISO9660/UDF
 
 use moses_core::MosesError;
 
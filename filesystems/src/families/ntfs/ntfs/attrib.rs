@@ -0,0 +1,188 @@
+// In-place DOS attribute edits of NTFS files - what attrib does, without a writer
+// The read-only, hidden, system and archive bits are kept in $STANDARD_INFORMATION,
+// with a copy in the flags of each resident $FILE_NAME of the record; both are patched.
+// The copy in the parent directory's index is left as it is, as Windows itself only
+// refreshes it when the entry is next rewritten. MFT records are protected by update
+// sequence fixups: the last two bytes of each 512-byte stride are kept in the update
+// sequence array, so a change to one of those bytes is made there instead.
+
+use moses_core::{Device, MetadataPatch, MosesError};
+use std::io::{Read, Seek, SeekFrom};
+
+use super::path_resolver::PathResolver;
+use super::reader::NtfsReader;
+use super::structures::*;
+use crate::ops::AttributeChanges;
+
+const FIXUP_STRIDE: usize = 512;
+/// file_attributes in the $STANDARD_INFORMATION value
+const SI_ATTRIBUTES_OFFSET: usize = 0x20;
+/// flags in the $FILE_NAME value
+const FN_FLAGS_OFFSET: usize = 0x38;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Where byte `index` of a record is stored on disk, relative to the record
+fn disk_index(record: &[u8], index: usize) -> usize {
+    match index % FIXUP_STRIDE {
+        tail @ 510.. => u16_at(record, 4) as usize + 2 + 2 * (index / FIXUP_STRIDE) + (tail - 510),
+        _ => index,
+    }
+}
+
+/// Offsets of the resident values of `attribute_type` in a record with fixups applied
+fn resident_values(record: &[u8], attribute_type: u32) -> Vec<(usize, usize)> {
+    let mut values = Vec::new();
+    let mut offset = u16_at(record, 0x14) as usize;
+    while offset + 0x18 <= record.len() {
+        let kind = u32_at(record, offset);
+        let length = u32_at(record, offset + 4) as usize;
+        if kind == ATTR_TYPE_END || length < 0x18 || offset + length > record.len() {
+            break;
+        }
+        if kind == attribute_type && record[offset + 8] == 0 {
+            let value = offset + u16_at(record, offset + 0x14) as usize;
+            let size = u32_at(record, offset + 0x10) as usize;
+            if value + size <= offset + length {
+                values.push((value, size));
+            }
+        }
+        offset += length;
+    }
+    values
+}
+
+/// The patch that applies the DOS attribute part of `changes` to `path` on the NTFS
+/// volume of `device`, read through `reader`
+pub fn plan_attributes<R: Read + Seek>(
+    reader: &mut R,
+    device: &Device,
+    path: &str,
+    changes: &AttributeChanges,
+) -> Result<MetadataPatch, MosesError> {
+    if changes.owner.is_some() || changes.group.is_some() {
+        return Err(MosesError::NotSupported(
+            "NTFS owners are Windows security identifiers, not Unix user and group ids".to_string()
+        ));
+    }
+    let (set, clear) = changes.dos_bits("NTFS")?;
+    let mut ntfs = NtfsReader::new(device.clone())?;
+    let record_num = PathResolver::new().resolve_path(&mut ntfs, path)?;
+    let (offset, size) = ntfs.mft_record_location(record_num)?;
+
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut disk = vec![0u8; size];
+    reader.read_exact(&mut disk)?;
+    let mut patch = MetadataPatch::new(format!("{} of {}", changes.describe(), path), &device.id);
+    patch_record(&mut patch, record_num, offset, &disk, set, clear)?;
+    Ok(patch)
+}
+
+/// Add the edits of the attribute bits of MFT record `record_num`, read from `offset` as
+/// `disk`, to `patch`
+fn patch_record(patch: &mut MetadataPatch, record_num: u64, offset: u64, disk: &[u8], set: u32, clear: u32) -> Result<(), MosesError> {
+    if &disk[..4] != b"FILE" {
+        return Err(MosesError::Other(format!("MFT record {} has no FILE signature", record_num)));
+    }
+    let usa_offset = u16_at(disk, 4) as usize;
+    let usa_count = u16_at(disk, 6) as usize;
+    if usa_count == 0 || (usa_count - 1) * FIXUP_STRIDE != disk.len() || usa_offset + 2 * usa_count > disk.len() {
+        return Err(MosesError::Other(format!("MFT record {} has a malformed update sequence array", record_num)));
+    }
+    let mut record = disk.to_vec();
+    for stride in 0..usa_count - 1 {
+        let end = (stride + 1) * FIXUP_STRIDE - 2;
+        if record[end..end + 2] != disk[usa_offset..usa_offset + 2] {
+            return Err(MosesError::Other(format!("MFT record {} fails its fixup check", record_num)));
+        }
+        let saved = usa_offset + 2 + 2 * stride;
+        record[end..end + 2].copy_from_slice(&disk[saved..saved + 2]);
+    }
+
+    let mut fields = Vec::new();
+    if let Some(&(value, size)) = resident_values(&record, ATTR_TYPE_STANDARD_INFORMATION).first() {
+        if size >= SI_ATTRIBUTES_OFFSET + 4 {
+            fields.push(("$STANDARD_INFORMATION attributes", value + SI_ATTRIBUTES_OFFSET));
+        }
+    }
+    if fields.is_empty() {
+        return Err(MosesError::Other(format!("MFT record {} has no $STANDARD_INFORMATION", record_num)));
+    }
+    for (value, size) in resident_values(&record, ATTR_TYPE_FILE_NAME) {
+        if size >= FN_FLAGS_OFFSET + 4 {
+            fields.push(("$FILE_NAME flags", value + FN_FLAGS_OFFSET));
+        }
+    }
+
+    for (field, at) in fields {
+        let attributes = (u32_at(&record, at) | set) & !clear;
+        // A field is split where a fixup tail byte moved to the update sequence array
+        let mut spans: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (index, byte) in (at..at + 4).zip(attributes.to_le_bytes()) {
+            let stored = disk_index(disk, index);
+            match spans.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == stored => bytes.push(byte),
+                _ => spans.push((stored, vec![byte])),
+            }
+        }
+        for (start, after) in spans {
+            let before = disk[start..start + after.len()].to_vec();
+            patch.set(format!("MFT record {}: {}", record_num, field), offset + start as u64, before, after)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::dos_attributes::{ARCHIVE, HIDDEN};
+
+    /// A 1024-byte record whose $STANDARD_INFORMATION attributes start on a fixup tail byte
+    fn record() -> Vec<u8> {
+        let mut disk = vec![0u8; 1024];
+        disk[..4].copy_from_slice(b"FILE");
+        disk[4..8].copy_from_slice(&[0x30, 0, 3, 0]);
+        let attribute = 510 - SI_ATTRIBUTES_OFFSET - 0x18;
+        disk[0x14..0x16].copy_from_slice(&(attribute as u16).to_le_bytes());
+        disk[attribute..attribute + 4].copy_from_slice(&ATTR_TYPE_STANDARD_INFORMATION.to_le_bytes());
+        disk[attribute + 4..attribute + 8].copy_from_slice(&0x60u32.to_le_bytes());
+        disk[attribute + 0x10..attribute + 0x14].copy_from_slice(&0x48u32.to_le_bytes());
+        disk[attribute + 0x14..attribute + 0x16].copy_from_slice(&0x18u16.to_le_bytes());
+        disk[attribute + 0x60..attribute + 0x64].copy_from_slice(&ATTR_TYPE_END.to_le_bytes());
+        // Archive is stored in the array; the sector ends hold the update sequence number
+        disk[0x30..0x36].copy_from_slice(&[1, 0, ARCHIVE as u8, 0, 0, 0]);
+        disk[510..512].copy_from_slice(&[1, 0]);
+        disk[1022..1024].copy_from_slice(&[1, 0]);
+        disk
+    }
+
+    #[test]
+    fn test_attribute_bits_on_a_fixup_tail() {
+        let mut patch = MetadataPatch::new("hide", "test");
+        patch_record(&mut patch, 40, 0x10000, &record(), HIDDEN, ARCHIVE).unwrap();
+        assert_eq!(patch.ranges.len(), 1);
+        assert_eq!(patch.ranges[0].field, "MFT record 40: $STANDARD_INFORMATION attributes");
+        assert_eq!((patch.ranges[0].offset, patch.ranges[0].after.as_slice()), (0x10032, [HIDDEN as u8, 0].as_slice()));
+
+        let mut disk = record();
+        disk[1022] = 2;
+        assert!(patch_record(&mut MetadataPatch::new("hide", "test"), 40, 0, &disk, HIDDEN, 0).is_err(), "torn write");
+        disk[..4].copy_from_slice(b"BAAD");
+        assert!(patch_record(&mut MetadataPatch::new("hide", "test"), 40, 0, &disk, HIDDEN, 0).is_err());
+    }
+
+    #[test]
+    fn test_owners_are_refused() {
+        let device = crate::test_helpers::create_test_device("/nonexistent", 0);
+        let owner = AttributeChanges { owner: Some(0), ..Default::default() };
+        let mut empty = std::io::Cursor::new(Vec::new());
+        assert!(matches!(plan_attributes(&mut empty, &device, "/", &owner), Err(MosesError::NotSupported(_))));
+    }
+}
@@ -21,7 +21,11 @@ impl NtfsBootSectorReader {
         // Open device
         let file = open_device_with_fallback(&device)?;
         let mut reader = AlignedDeviceReader::new(file);
-        
+        Self::from_reader(device, &mut reader)
+    }
+    
+    /// Read and parse the boot sector through an already open reader
+    pub fn from_reader(device: Device, reader: &mut AlignedDeviceReader) -> Result<Self, MosesError> {
         // Read first sector (512 bytes)
         let boot_data = reader.read_at(0, 512)?;
         
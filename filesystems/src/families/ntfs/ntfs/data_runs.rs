@@ -1,5 +1,5 @@
 // NTFS Data Run decoder
-// Phase 1.4: Decode runlists for non-resident attributes
+// Phase 1.4: Decode runlists for non-resident attributes, and encode them for the formatter
 
 use moses_core::MosesError;
 
@@ -62,6 +62,45 @@ pub fn decode_data_runs(data: &[u8]) -> Result<Vec<DataRun>, MosesError> {
     Ok(runs)
 }
 
+/// Encode runs as a runlist, terminator included. Lengths and offsets take the fewest
+/// bytes that hold them as signed values, as Windows writes them.
+pub fn encode_data_runs(runs: &[DataRun]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut prev_lcn = 0i64;
+    for run in runs {
+        let length = signed_le_bytes(run.length as i64);
+        let offset = match run.lcn {
+            Some(lcn) => {
+                let offset = signed_le_bytes(lcn as i64 - prev_lcn);
+                prev_lcn = lcn as i64;
+                offset
+            }
+            None => Vec::new(),
+        };
+        data.push(((offset.len() as u8) << 4) | length.len() as u8);
+        data.extend_from_slice(&length);
+        data.extend_from_slice(&offset);
+    }
+    data.push(0);
+    data
+}
+
+/// The shortest little-endian form of `value` that reads back with the same sign
+fn signed_le_bytes(value: i64) -> Vec<u8> {
+    let bytes = value.to_le_bytes();
+    let mut len = 8;
+    while len > 1 {
+        let top = bytes[len - 1];
+        let next_negative = bytes[len - 2] & 0x80 != 0;
+        if (top == 0 && !next_negative) || (top == 0xFF && next_negative) {
+            len -= 1;
+        } else {
+            break;
+        }
+    }
+    bytes[..len].to_vec()
+}
+
 /// Read little-endian bytes as unsigned integer
 fn read_le_bytes(bytes: &[u8]) -> u64 {
     let mut value = 0u64;
@@ -161,4 +200,21 @@ mod tests {
         assert_eq!(runs[0].lcn, Some(1000));
         assert_eq!(runs[1].lcn, Some(900));
     }
+    
+    #[test]
+    fn test_encode_round_trip() {
+        let runs = vec![
+            DataRun { lcn: Some(786432), length: 128 },
+            DataRun { lcn: None, length: 0x1_0000_0000 },
+            DataRun { lcn: Some(4), length: 3 },
+        ];
+        let data = encode_data_runs(&runs);
+        // 128 needs a second byte to stay positive
+        assert_eq!(&data[..6], &[0x32, 0x80, 0x00, 0x00, 0x00, 0x0C]);
+        let decoded = decode_data_runs(&data).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!((decoded[0].lcn, decoded[0].length), (Some(786432), 128));
+        assert_eq!((decoded[1].lcn, decoded[1].length), (None, 0x1_0000_0000));
+        assert_eq!((decoded[2].lcn, decoded[2].length), (Some(4), 3));
+    }
 }
\ No newline at end of file
@@ -1,14 +1,80 @@
-// NTFS Formatter - Phase 5: Create NTFS filesystems
-// This is a minimal NTFS formatter that creates a basic, valid NTFS volume
+// NTFS Formatter - native creation of NTFS 3.1 volumes
+// Lays a volume out the way Windows' own format does: $Boot at the start with a backup of
+// the boot sector in the device's last sector; an MFT holding the sixteen system files,
+// eight reserved records and the three $Extend files; a mirror of its first four records in
+// the middle of the volume; a $LogFile filled with 0xFF, which Windows takes as a clean,
+// empty log and initialises on first mount; and the $AttrDef, $Bitmap, $UpCase and $Secure
+// contents those records point at. Directory indexes are sorted with the volume's own
+// $UpCase table, and everything written follows from the options and the format seed.
 
 use moses_core::{Device, FormatOptions, MosesError, FilesystemFormatter};
 use crate::families::ntfs::ntfs::structures::*;
-use crate::families::ntfs::ntfs::mft_writer::MftRecordBuilder;
+use crate::families::ntfs::ntfs::data_runs::{encode_data_runs, DataRun};
+use crate::families::ntfs::ntfs::index::{INDEX_ENTRY_END, INDEX_ENTRY_NODE};
+use crate::families::ntfs::ntfs::upcase::{collate_names, upcase_bytes, upcase_table};
+use crate::volume_serial::serial_for_format;
+use crate::deterministic::FormatSeed;
 use log::{info, debug};
 use std::io::{Write, Seek, SeekFrom};
 use async_trait::async_trait;
 
+const SECTOR_SIZE: u64 = 512;
+const MFT_RECORD_SIZE: usize = 1024;
+const INDEX_BLOCK_SIZE: usize = 4096;
+/// Smallest device the formatter accepts
+const MIN_VOLUME_SIZE: u64 = 10 * 1024 * 1024;
+/// Largest cluster size; bigger ones need Windows 10 1709 or later
+const MAX_CLUSTER_SIZE: u64 = 64 * 1024;
+/// Cluster numbers in the bitmap and in Windows are 32-bit
+const MAX_CLUSTERS: u64 = u32::MAX as u64;
+/// Longest label, in UTF-16 units
+pub const MAX_LABEL_LENGTH: usize = 32;
+/// $Boot: the boot sector and the boot loader sectors after it
+const BOOT_SIZE: u64 = 8192;
+/// The MFT starts after the first 16 KiB, where Windows puts it on small volumes too
+const MFT_START: u64 = 16 * 1024;
+/// Records the MFT starts with; it grows as files are created
+const INITIAL_MFT_RECORDS: u64 = 64;
+/// Records $MFTMirr keeps a copy of
+const MIRRORED_RECORDS: u64 = 4;
+const ATTRDEF_SIZE: usize = 0xA00;
+/// $SDS keeps a copy of every 256 KiB block in the next one
+const SDS_BLOCK_SIZE: usize = 0x40000;
+/// Security id of the one descriptor every system file uses
+const SECURITY_ID: u32 = 0x100;
+/// Update sequence number stamped into every sector of a new record or index block
+const UPDATE_SEQUENCE: u16 = 1;
+const RECORD_USA_OFFSET: usize = 0x30;
+const INDEX_USA_OFFSET: usize = 0x28;
+/// Bytes written per call when filling $Bitmap and $LogFile
+const WRITE_CHUNK: u64 = 1024 * 1024;
+
+// The $Extend files, after the sixteen system records and eight reserved ones
+const MFT_RECORD_QUOTA: u64 = 24;
+const MFT_RECORD_OBJID: u64 = 25;
+const MFT_RECORD_REPARSE: u64 = 26;
+/// Records the format puts in use, reserved ones included
+const SYSTEM_RECORDS: u64 = 27;
+const MFT_RECORD_IS_VIEW_INDEX: u16 = 0x0008;
+
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x0002;
+const FILE_ATTRIBUTE_SYSTEM: u32 = 0x0004;
+const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x0020;
+/// In FILE_NAME: the file is a directory with a $I30 index
+const FILE_ATTRIBUTE_DIRECTORY_INDEX: u32 = 0x1000_0000;
+/// The file holds view indexes ($Secure and the $Extend files)
+const FILE_ATTRIBUTE_VIEW_INDEX: u32 = 0x2000_0000;
+
+const COLLATION_FILE_NAME: u32 = 0x01;
+const COLLATION_NTOFS_ULONG: u32 = 0x10;
+const COLLATION_NTOFS_SID: u32 = 0x11;
+const COLLATION_NTOFS_SECURITY_HASH: u32 = 0x12;
+const COLLATION_NTOFS_ULONGS: u32 = 0x13;
+/// INDEX_ROOT header flag: the entries continue in INDEX_ALLOCATION blocks
+const LARGE_INDEX: u32 = 0x01;
+
 /// NTFS Formatter implementation
+#[derive(Default)]
 pub struct NtfsFormatter;
 
 #[async_trait]
@@ -16,7 +82,7 @@ impl FilesystemFormatter for NtfsFormatter {
     fn name(&self) -> &'static str {
         "NTFS"
     }
-    
+
     fn supported_platforms(&self) -> Vec<moses_core::Platform> {
         vec![
             moses_core::Platform::Windows,
@@ -24,193 +90,940 @@ impl FilesystemFormatter for NtfsFormatter {
             moses_core::Platform::MacOS,
         ]
     }
-    
+
     fn can_format(&self, device: &Device) -> bool {
-        device.size >= 10 * 1024 * 1024 // Minimum 10MB
+        !device.is_write_protected && device.size >= MIN_VOLUME_SIZE
     }
-    
+
     fn requires_external_tools(&self) -> bool {
         false
     }
-    
+
     fn bundled_tools(&self) -> Vec<&'static str> {
         vec![]
     }
-    
-    async fn validate_options(&self, _options: &FormatOptions) -> Result<(), MosesError> {
+
+    async fn validate_options(&self, options: &FormatOptions) -> Result<(), MosesError> {
+        if let Some(label) = &options.label {
+            check_label(label)?;
+        }
+        if let Some(cluster_size) = options.cluster_size {
+            check_cluster_size(cluster_size as u64)?;
+        }
+        FormatSeed::from_options(options)?;
         Ok(())
     }
-    
+
     async fn dry_run(
         &self,
         device: &Device,
         options: &FormatOptions,
     ) -> Result<moses_core::SimulationReport, MosesError> {
+        device.ensure_writable()?;
+        let layout = Layout::new(device.size, options.cluster_size.map(u64::from))?;
+
         Ok(moses_core::SimulationReport {
             device: device.clone(),
             options: options.clone(),
-            estimated_time: std::time::Duration::from_secs(device.size / (1024 * 1024 * 1024)),
-            warnings: vec![],
+            estimated_time: std::time::Duration::from_secs(5),
+            warnings: vec![format!(
+                "NTFS with {} byte clusters and a {} MB $LogFile",
+                layout.cluster_size, layout.logfile_size / (1024 * 1024),
+            )],
             required_tools: vec![],
             will_erase_data: true,
-            space_after_format: device.size * 9 / 10, // Roughly 90% usable
+            space_after_format: layout.free_bytes(),
+            strategy: None,
+            lints: Vec::new(),
+            contents: Vec::new(),
+            steps: Vec::new(),
+            requires_elevation: false,
+            locked_volumes: Vec::new(),
         })
     }
-    
+
     async fn format(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
         info!("Starting NTFS format of device: {}", device.name);
-        
-        // Basic validation
-        if device.size < 10 * 1024 * 1024 {
+        format_device(device, options)
+    }
+}
+
+impl NtfsFormatter {
+    /// Create a new NTFS formatter instance
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Synchronous format with default options, for tools without an async runtime
+    pub fn format(&mut self, device: &Device, label: &str) -> Result<(), MosesError> {
+        let options = FormatOptions {
+            filesystem_type: "ntfs".to_string(),
+            label: Some(label.to_string()),
+            ..Default::default()
+        };
+        format_device(device, &options)
+    }
+}
+
+fn format_device(device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
+    // Worked out in full before the device is opened, so a bad option changes nothing
+    let volume = NtfsVolume::plan(device, options)?;
+    let layout = &volume.layout;
+    info!("NTFS parameters: {} clusters of {} bytes, MFT at cluster {}, mirror at {}",
+          layout.clusters, layout.cluster_size, layout.mft.lcn, layout.mft_mirror.lcn);
+
+    let mut file = crate::utils::open_device_write(device)?;
+    volume.write(&mut file)?;
+
+    // Flush all writes, including the drive cache
+    crate::utils::flush_device(&file)?;
+
+    info!("NTFS format completed successfully");
+    Ok(())
+}
+
+fn check_label(label: &str) -> Result<(), MosesError> {
+    let length = label.encode_utf16().count();
+    if length > MAX_LABEL_LENGTH {
+        return Err(MosesError::InvalidInput(format!(
+            "NTFS labels are at most {} characters; '{}' is {}", MAX_LABEL_LENGTH, label, length,
+        )));
+    }
+    Ok(())
+}
+
+fn check_cluster_size(cluster_size: u64) -> Result<(), MosesError> {
+    if !cluster_size.is_power_of_two() || !(SECTOR_SIZE..=MAX_CLUSTER_SIZE).contains(&cluster_size) {
+        return Err(MosesError::InvalidInput(format!(
+            "NTFS clusters are a power of two from {} to {} bytes, not {}", SECTOR_SIZE, MAX_CLUSTER_SIZE, cluster_size,
+        )));
+    }
+    Ok(())
+}
+
+/// The cluster size Windows picks: 4 KiB up to 16 TiB, then doubling with the volume
+fn default_cluster_size(volume_size: u64) -> u64 {
+    const TIB: u64 = 1 << 40;
+    match volume_size {
+        size if size <= 16 * TIB => 4096,
+        size if size <= 32 * TIB => 8192,
+        size if size <= 64 * TIB => 16384,
+        size if size <= 128 * TIB => 32768,
+        _ => 65536,
+    }
+}
+
+/// $LogFile size: 2 MiB up to 200 MiB, then a hundredth of the volume up to 64 MiB
+fn logfile_size(volume_size: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    if volume_size <= 200 * MIB {
+        2 * MIB
+    } else {
+        (volume_size / 100).min(64 * MIB)
+    }
+}
+
+/// A run of clusters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Extent {
+    lcn: u64,
+    clusters: u64,
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.lcn + self.clusters
+    }
+
+    fn runs(&self) -> Vec<DataRun> {
+        vec![DataRun { lcn: Some(self.lcn), length: self.clusters }]
+    }
+}
+
+/// Where each metadata file goes on a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    cluster_size: u64,
+    /// Sectors in the volume; the device's last sector, after them, holds the backup boot sector
+    volume_sectors: u64,
+    clusters: u64,
+    mft_records: u64,
+    bitmap_size: u64,
+    logfile_size: u64,
+    boot: Extent,
+    mft: Extent,
+    mft_bitmap: Extent,
+    mft_mirror: Extent,
+    root_index: Extent,
+    attrdef: Extent,
+    bitmap: Extent,
+    upcase: Extent,
+    sds: Extent,
+    logfile: Extent,
+}
+
+impl Layout {
+    fn new(device_size: u64, cluster_size: Option<u64>) -> Result<Self, MosesError> {
+        if device_size < MIN_VOLUME_SIZE {
             return Err(MosesError::InvalidInput("Device too small for NTFS (min 10MB)".to_string()));
         }
-        
-        // Open device for writing
-        let mut file = {
-            use std::fs::OpenOptions;
-            #[cfg(target_os = "windows")]
-            {
-                use std::os::windows::fs::OpenOptionsExt;
-                use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE, FILE_SHARE_READ};
-                
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .access_mode(GENERIC_READ | GENERIC_WRITE)
-                    .share_mode(FILE_SHARE_READ)
-                    .open(&device.mount_points[0])?
+        let cluster_size = match cluster_size {
+            Some(size) => {
+                check_cluster_size(size)?;
+                size
             }
-            #[cfg(not(target_os = "windows"))]
-            {
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&device.mount_points[0])?
+            None => default_cluster_size(device_size),
+        };
+        let volume_sectors = device_size / SECTOR_SIZE - 1;
+        let clusters = volume_sectors * SECTOR_SIZE / cluster_size;
+        if clusters > MAX_CLUSTERS {
+            return Err(MosesError::InvalidInput(format!(
+                "{} clusters of {} bytes are more than NTFS addresses; use larger clusters", clusters, cluster_size,
+            )));
+        }
+
+        let mft_size = (INITIAL_MFT_RECORDS * MFT_RECORD_SIZE as u64).next_multiple_of(cluster_size);
+        let mft_records = mft_size / MFT_RECORD_SIZE as u64;
+        let bitmap_size = clusters.div_ceil(8).next_multiple_of(8);
+        let logfile_size = logfile_size(volume_sectors * SECTOR_SIZE).next_multiple_of(cluster_size);
+        let mft_mirror = Extent {
+            lcn: clusters / 2,
+            clusters: (MIRRORED_RECORDS * MFT_RECORD_SIZE as u64).div_ceil(cluster_size),
+        };
+
+        // Everything else in order from the MFT on, around the mirror
+        let boot = Extent { lcn: 0, clusters: BOOT_SIZE.div_ceil(cluster_size) };
+        let mut next = boot.end().max(MFT_START.div_ceil(cluster_size));
+        let mut take = |size: u64| {
+            let length = size.div_ceil(cluster_size);
+            if next < mft_mirror.end() && next + length > mft_mirror.lcn {
+                next = mft_mirror.end();
             }
+            let extent = Extent { lcn: next, clusters: length };
+            next += length;
+            extent
         };
-        
-        // Calculate filesystem parameters
-        let bytes_per_sector = 512u16;
-        let sectors_per_cluster = calculate_sectors_per_cluster(device.size);
-        let bytes_per_cluster = bytes_per_sector as u32 * sectors_per_cluster as u32;
-        let total_sectors = device.size / bytes_per_sector as u64;
-        let total_clusters = total_sectors / sectors_per_cluster as u64;
-        
-        // MFT parameters
-        let mft_record_size = 1024u32; // Standard size
-        let mft_clusters = (total_clusters / 8).max(8192).min(65536); // 12.5% of disk, min 32MB, max 256MB
-        let _mft_zone_clusters = mft_clusters * 4; // Reserve 4x MFT size for growth
-        let mft_start_cluster = 4; // Start MFT at cluster 4 (after boot sector)
-        
-        info!("NTFS parameters: {} sectors, {} bytes/cluster, MFT at cluster {}",
-              total_sectors, bytes_per_cluster, mft_start_cluster);
-        
-        // Step 1: Write boot sector
-        write_boot_sector(&mut file, &device, options, 
-                         bytes_per_sector, sectors_per_cluster,
-                         total_sectors, mft_start_cluster)?;
-        
-        // Step 2: Create and write system MFT records
-        write_system_mft_records(&mut file, bytes_per_cluster, 
-                                mft_start_cluster, mft_record_size,
-                                total_clusters)?;
-        
-        // Step 3: Initialize bitmaps
-        initialize_bitmaps(&mut file, bytes_per_cluster, total_clusters, mft_clusters)?;
-        
-        // Step 4: Write backup boot sector
-        write_backup_boot_sector(&mut file, total_sectors, bytes_per_sector)?;
-        
-        // Flush all writes
-        file.flush()?;
-        
-        info!("NTFS format completed successfully");
-        Ok(())
+        let mft = take(mft_size);
+        let mft_bitmap = take(mft_bitmap_size(mft_records));
+        let root_index = take(INDEX_BLOCK_SIZE as u64);
+        let attrdef = take(ATTRDEF_SIZE as u64);
+        let bitmap = take(bitmap_size);
+        let upcase = take(upcase_size());
+        let sds = take(sds_size());
+        let logfile = take(logfile_size);
+        if next > clusters {
+            return Err(MosesError::InvalidInput(format!(
+                "A {} byte device is too small for the NTFS metadata", device_size,
+            )));
+        }
+
+        Ok(Self {
+            cluster_size,
+            volume_sectors,
+            clusters,
+            mft_records,
+            bitmap_size,
+            logfile_size,
+            boot,
+            mft,
+            mft_bitmap,
+            mft_mirror,
+            root_index,
+            attrdef,
+            bitmap,
+            upcase,
+            sds,
+            logfile,
+        })
+    }
+
+    fn offset(&self, extent: &Extent) -> u64 {
+        extent.lcn * self.cluster_size
+    }
+
+    fn bytes(&self, extent: &Extent) -> u64 {
+        extent.clusters * self.cluster_size
+    }
+
+    fn used_extents(&self) -> [Extent; 11] {
+        [
+            self.boot, self.mft, self.mft_bitmap, self.mft_mirror, self.root_index, self.attrdef,
+            self.bitmap, self.upcase, self.sds, self.logfile,
+            // The partial cluster at the end, if any, is never handed out
+            Extent { lcn: self.clusters, clusters: 0 },
+        ]
+    }
+
+    fn free_bytes(&self) -> u64 {
+        let used: u64 = self.used_extents().iter().map(|extent| extent.clusters).sum();
+        (self.clusters - used) * self.cluster_size
+    }
+
+    /// Bytes `start..start + length` of $Bitmap
+    fn bitmap_bytes(&self, start: u64, length: u64) -> Vec<u8> {
+        let mut bytes = vec![0u8; length as usize];
+        let (first, last) = (start * 8, (start + length) * 8);
+        for extent in self.used_extents() {
+            for cluster in extent.lcn.max(first)..extent.end().min(last) {
+                let bit = cluster - first;
+                bytes[(bit / 8) as usize] |= 1 << (bit % 8);
+            }
+        }
+        bytes
     }
 }
 
-/// Calculate appropriate sectors per cluster based on volume size
-fn calculate_sectors_per_cluster(volume_size: u64) -> u8 {
-    // Standard NTFS cluster sizes
-    match volume_size {
-        0..=512_000_000 => 1,           // <= 512MB: 512 bytes
-        ..=1_024_000_000 => 2,          // <= 1GB: 1KB
-        ..=2_147_483_648 => 4,          // <= 2GB: 2KB  
-        ..=8_589_934_592 => 8,          // <= 8GB: 4KB (most common)
-        ..=17_179_869_184 => 16,        // <= 16GB: 8KB
-        ..=34_359_738_368 => 32,        // <= 32GB: 16KB
-        ..=68_719_476_736 => 64,        // <= 64GB: 32KB
-        _ => 128,                        // > 64GB: 64KB
-    }
-}
-
-/// Write the NTFS boot sector
-fn write_boot_sector(
-    file: &mut std::fs::File,
-    _device: &Device,
-    _options: &FormatOptions,
-    bytes_per_sector: u16,
-    sectors_per_cluster: u8,
-    total_sectors: u64,
-    mft_start_cluster: u64,
-) -> Result<(), MosesError> {
-    debug!("Writing NTFS boot sector");
-    
-    let mft_record_size = 1024u32; // Standard MFT record size
-    
-    let boot_sector = NtfsBootSector {
-        jump: [0xEB, 0x52, 0x90],
-        oem_id: *b"NTFS    ",
-        bytes_per_sector,
-        sectors_per_cluster,
-        reserved_sectors: 0,
-        zero1: [0; 3],
-        unused1: 0,
-        media_descriptor: 0xF8,
-        zero2: 0,
-        sectors_per_track: 63,
-        num_heads: 255,
-        hidden_sectors: 0,
-        unused2: 0,
-        unused3: 0,
-        total_sectors,
-        mft_lcn: mft_start_cluster,
-        mftmirr_lcn: 2, // Usually at cluster 2
-        clusters_per_mft_record: if mft_record_size < bytes_per_sector as u32 * sectors_per_cluster as u32 {
-            // Negative value indicates size in bytes (2^(-n))
-            let mut size = mft_record_size;
-            let mut n = 0u8;
-            while size > 1 {
-                size >>= 1;
-                n += 1;
+fn mft_bitmap_size(mft_records: u64) -> u64 {
+    mft_records.div_ceil(8).next_multiple_of(8)
+}
+
+fn upcase_size() -> u64 {
+    (crate::families::ntfs::ntfs::upcase::UPCASE_ENTRIES * 2) as u64
+}
+
+/// $SDS: the one descriptor, then its copy in the mirror block
+fn sds_size() -> u64 {
+    (SDS_BLOCK_SIZE + SDS_HEADER_SIZE + security_descriptor().len()) as u64
+}
+
+/// Everything one format writes, worked out before the device is opened
+struct NtfsVolume {
+    layout: Layout,
+    serial: u64,
+    label: String,
+    /// FILETIME every system file is stamped with
+    time: u64,
+    upcase: Vec<u16>,
+}
+
+impl NtfsVolume {
+    fn plan(device: &Device, options: &FormatOptions) -> Result<Self, MosesError> {
+        let label = options.label.clone().unwrap_or_default();
+        check_label(&label)?;
+        let layout = Layout::new(device.size, options.cluster_size.map(u64::from))?;
+        let seed = FormatSeed::from_options(options)?;
+        let serial = serial_for_format(device, options)?
+            .map(|kept| kept.long())
+            .or_else(|| seed.serial64("volume serial"))
+            .unwrap_or_else(generate_serial_number);
+        Ok(Self { layout, serial, label, time: windows_time(&seed), upcase: upcase_table() })
+    }
+
+    fn write<W: Write + Seek>(&self, out: &mut W) -> Result<(), MosesError> {
+        let layout = &self.layout;
+        let (records, root_index) = self.mft_records()?;
+        let mft = records.concat();
+
+        debug!("Writing the MFT, its bitmap and its mirror");
+        write_at(out, layout.offset(&layout.mft), &mft)?;
+        let mut mft_bitmap = vec![0u8; layout.bytes(&layout.mft_bitmap) as usize];
+        for record in (0..16).chain(MFT_RECORD_QUOTA..SYSTEM_RECORDS) {
+            mft_bitmap[record as usize / 8] |= 1 << (record % 8);
+        }
+        write_at(out, layout.offset(&layout.mft_bitmap), &mft_bitmap)?;
+        let mut mirror = mft[..MIRRORED_RECORDS as usize * MFT_RECORD_SIZE].to_vec();
+        mirror.resize(layout.bytes(&layout.mft_mirror) as usize, 0);
+        write_at(out, layout.offset(&layout.mft_mirror), &mirror)?;
+
+        debug!("Writing the root index, $AttrDef, $UpCase and $Secure");
+        write_at(out, layout.offset(&layout.root_index), &root_index)?;
+        write_at(out, layout.offset(&layout.attrdef), &attribute_definitions())?;
+        write_at(out, layout.offset(&layout.upcase), &upcase_bytes(&self.upcase))?;
+        write_at(out, layout.offset(&layout.sds), &security_stream())?;
+
+        debug!("Writing the {} byte $Bitmap", layout.bitmap_size);
+        let bitmap_bytes = layout.bytes(&layout.bitmap);
+        let mut written = 0;
+        while written < bitmap_bytes {
+            let length = (bitmap_bytes - written).min(WRITE_CHUNK);
+            write_at(out, layout.offset(&layout.bitmap) + written, &layout.bitmap_bytes(written, length))?;
+            written += length;
+        }
+
+        // An all-0xFF log is one Windows has never written; it sets the log up on first mount
+        debug!("Writing the {} byte $LogFile", layout.logfile_size);
+        let mut written = 0;
+        while written < layout.logfile_size {
+            let length = (layout.logfile_size - written).min(WRITE_CHUNK);
+            write_at(out, layout.offset(&layout.logfile) + written, &vec![0xFF; length as usize])?;
+            written += length;
+        }
+
+        // The boot sectors last, so a format cut short does not leave a volume that mounts
+        debug!("Writing the boot sector and its backup");
+        let boot_sector = self.boot_sector();
+        write_at(out, layout.volume_sectors * SECTOR_SIZE, &boot_sector)?;
+        let mut boot = vec![0u8; layout.bytes(&layout.boot) as usize];
+        boot[..boot_sector.len()].copy_from_slice(&boot_sector);
+        write_at(out, 0, &boot)?;
+        Ok(())
+    }
+
+    fn boot_sector(&self) -> Vec<u8> {
+        let layout = &self.layout;
+        let boot_sector = NtfsBootSector {
+            jump: [0xEB, 0x52, 0x90],
+            oem_id: *NTFS_SIGNATURE,
+            bytes_per_sector: SECTOR_SIZE as u16,
+            sectors_per_cluster: (layout.cluster_size / SECTOR_SIZE) as u8,
+            reserved_sectors: 0,
+            zero1: [0; 3],
+            unused1: 0,
+            media_descriptor: 0xF8,
+            zero2: 0,
+            sectors_per_track: 63,
+            num_heads: 255,
+            hidden_sectors: 0,
+            unused2: 0,
+            // BIOS drive 0x80 and the extended boot signature 0x80
+            unused3: 0x0080_0080,
+            total_sectors: layout.volume_sectors,
+            mft_lcn: layout.mft.lcn,
+            mftmirr_lcn: layout.mft_mirror.lcn,
+            clusters_per_mft_record: size_in_clusters(MFT_RECORD_SIZE as u64, layout.cluster_size),
+            unused4: [0; 3],
+            clusters_per_index_buffer: size_in_clusters(INDEX_BLOCK_SIZE as u64, layout.cluster_size),
+            unused5: [0; 3],
+            volume_serial: self.serial,
+            checksum: 0,
+            bootstrap: [0; 426],
+            signature: 0xAA55,
+        };
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &boot_sector as *const _ as *const u8,
+                std::mem::size_of::<NtfsBootSector>()
+            )
+        };
+        bytes.to_vec()
+    }
+
+    fn standard_information(&self, attributes: u32, security_id: u32) -> Vec<u8> {
+        let mut value = vec![0u8; 0x48];
+        for time in 0..4 {
+            put_u64(&mut value, time * 8, self.time);
+        }
+        put_u32(&mut value, 0x20, attributes);
+        put_u32(&mut value, 0x34, security_id);
+        value
+    }
+
+    fn file_name(&self, parent: u64, name: &str, sizes: (u64, u64), attributes: u32) -> Vec<u8> {
+        let name = utf16_bytes(name);
+        let mut value = vec![0u8; 0x42 + name.len()];
+        put_u64(&mut value, 0x00, parent);
+        for time in 0..4 {
+            put_u64(&mut value, 0x08 + time * 8, self.time);
+        }
+        put_u64(&mut value, 0x28, sizes.0);
+        put_u64(&mut value, 0x30, sizes.1);
+        put_u32(&mut value, 0x38, attributes);
+        value[0x40] = (name.len() / 2) as u8;
+        value[0x41] = FILE_NAME_WIN32_AND_DOS;
+        value[0x42..].copy_from_slice(&name);
+        value
+    }
+
+    /// A system file's record with its $STANDARD_INFORMATION and $FILE_NAME; the
+    /// $FILE_NAME value comes back too, as the key of its entry in the parent's index.
+    /// `sizes` are the allocated and data sizes of the unnamed $DATA.
+    fn system_file(
+        &self,
+        number: u64,
+        name: &str,
+        parent: u64,
+        attributes: u32,
+        directory: bool,
+        sizes: (u64, u64),
+    ) -> Result<(FileRecord, Vec<u8>), MosesError> {
+        let mut flags = MFT_RECORD_IN_USE;
+        let mut name_attributes = attributes;
+        if directory {
+            flags |= MFT_RECORD_IS_DIRECTORY;
+            name_attributes |= FILE_ATTRIBUTE_DIRECTORY_INDEX;
+        }
+        if attributes & FILE_ATTRIBUTE_VIEW_INDEX != 0 {
+            flags |= MFT_RECORD_IS_VIEW_INDEX;
+        }
+        let mut record = FileRecord::new(number, flags);
+        record.resident(ATTR_TYPE_STANDARD_INFORMATION, "", &self.standard_information(attributes, SECURITY_ID), false)?;
+        let file_name = self.file_name(parent, name, sizes, name_attributes);
+        record.resident(ATTR_TYPE_FILE_NAME, "", &file_name, true)?;
+        record.set_link_count(1);
+        Ok((record, file_name))
+    }
+
+    /// Every record of the initial MFT in order, and the root directory's index block
+    fn mft_records(&self) -> Result<(Vec<Vec<u8>>, Vec<u8>), MosesError> {
+        let layout = &self.layout;
+        let cluster_size = layout.cluster_size;
+        let system = FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM;
+        let root = reference(MFT_RECORD_ROOT);
+        let extend = reference(MFT_RECORD_EXTEND);
+        let mut records: Vec<Option<Vec<u8>>> = vec![None; layout.mft_records as usize];
+        let mut root_entries = Vec::new();
+
+        // The files whose only content is one unnamed non-resident $DATA
+        let plain_files = [
+            (MFT_RECORD_MFTMIRR, "$MFTMirr", layout.mft_mirror, MIRRORED_RECORDS * MFT_RECORD_SIZE as u64),
+            (MFT_RECORD_LOGFILE, "$LogFile", layout.logfile, layout.logfile_size),
+            (MFT_RECORD_ATTRDEF, "$AttrDef", layout.attrdef, ATTRDEF_SIZE as u64),
+            (MFT_RECORD_BITMAP, "$Bitmap", layout.bitmap, layout.bitmap_size),
+            (MFT_RECORD_BOOT, "$Boot", layout.boot, BOOT_SIZE),
+            (MFT_RECORD_UPCASE, "$UpCase", layout.upcase, upcase_size()),
+        ];
+        for (number, name, extent, size) in plain_files {
+            let allocated = layout.bytes(&extent);
+            let (mut record, key) = self.system_file(number, name, root, system, false, (allocated, size))?;
+            record.non_resident(ATTR_TYPE_DATA, "", &extent.runs(), allocated, size)?;
+            records[number as usize] = Some(record.finish());
+            root_entries.push((name, number, key));
+        }
+
+        let mft_size = layout.bytes(&layout.mft);
+        let (mut record, key) = self.system_file(MFT_RECORD_MFT, "$MFT", root, system, false, (mft_size, mft_size))?;
+        record.non_resident(ATTR_TYPE_DATA, "", &layout.mft.runs(), mft_size, mft_size)?;
+        record.non_resident(
+            ATTR_TYPE_BITMAP, "", &layout.mft_bitmap.runs(), layout.bytes(&layout.mft_bitmap), mft_bitmap_size(layout.mft_records),
+        )?;
+        records[MFT_RECORD_MFT as usize] = Some(record.finish());
+        root_entries.push(("$MFT", MFT_RECORD_MFT, key));
+
+        let (mut record, key) = self.system_file(MFT_RECORD_VOLUME, "$Volume", root, system, false, (0, 0))?;
+        record.resident(ATTR_TYPE_VOLUME_NAME, "", &utf16_bytes(&self.label), false)?;
+        record.resident(ATTR_TYPE_VOLUME_INFORMATION, "", &volume_information(), false)?;
+        record.resident(ATTR_TYPE_DATA, "", &[], false)?;
+        records[MFT_RECORD_VOLUME as usize] = Some(record.finish());
+        root_entries.push(("$Volume", MFT_RECORD_VOLUME, key));
+
+        // Every cluster is listed as a sparse run of $Bad; bad ones would be real runs
+        let volume_bytes = layout.clusters * cluster_size;
+        let (mut record, key) = self.system_file(MFT_RECORD_BADCLUS, "$BadClus", root, system, false, (0, 0))?;
+        record.resident(ATTR_TYPE_DATA, "", &[], false)?;
+        record.non_resident(ATTR_TYPE_DATA, "$Bad", &[DataRun { lcn: None, length: layout.clusters }], volume_bytes, volume_bytes)?;
+        records[MFT_RECORD_BADCLUS as usize] = Some(record.finish());
+        root_entries.push(("$BadClus", MFT_RECORD_BADCLUS, key));
+
+        let descriptor = security_descriptor();
+        let header = sds_header(&descriptor);
+        let hash = security_hash(&descriptor);
+        let sdh_key = [hash.to_le_bytes(), SECURITY_ID.to_le_bytes()].concat();
+        // $SDH entries end in "II" padding, as Windows writes them
+        let sdh = [view_entry(&sdh_key, &header, &utf16_bytes("II")), end_entry(None)].concat();
+        let sii = [view_entry(&SECURITY_ID.to_le_bytes(), &header, &[]), end_entry(None)].concat();
+        let secure_attributes = system | FILE_ATTRIBUTE_VIEW_INDEX;
+        let (mut record, key) = self.system_file(MFT_RECORD_SECURE, "$Secure", root, secure_attributes, false, (0, 0))?;
+        record.non_resident(ATTR_TYPE_DATA, "$SDS", &layout.sds.runs(), layout.bytes(&layout.sds), sds_size())?;
+        record.resident(ATTR_TYPE_INDEX_ROOT, "$SDH", &index_root(0, COLLATION_NTOFS_SECURITY_HASH, cluster_size, &sdh, false), false)?;
+        record.resident(ATTR_TYPE_INDEX_ROOT, "$SII", &index_root(0, COLLATION_NTOFS_ULONG, cluster_size, &sii, false), false)?;
+        records[MFT_RECORD_SECURE as usize] = Some(record.finish());
+        root_entries.push(("$Secure", MFT_RECORD_SECURE, key));
+
+        // $Extend and the three view-index files in it
+        let mut extend_entries = Vec::new();
+        let empty = |collation| index_root(0, collation, cluster_size, &end_entry(None), false);
+        let quotas = [view_entry(&1u32.to_le_bytes(), &self.default_quota(), &[]), end_entry(None)].concat();
+        let extend_files = [
+            (MFT_RECORD_QUOTA, "$Quota", vec![
+                ("$O", empty(COLLATION_NTOFS_SID)),
+                ("$Q", index_root(0, COLLATION_NTOFS_ULONG, cluster_size, &quotas, false)),
+            ]),
+            (MFT_RECORD_OBJID, "$ObjId", vec![("$O", empty(COLLATION_NTOFS_ULONGS))]),
+            (MFT_RECORD_REPARSE, "$Reparse", vec![("$R", empty(COLLATION_NTOFS_ULONGS))]),
+        ];
+        for (number, name, indexes) in extend_files {
+            let attributes = system | FILE_ATTRIBUTE_ARCHIVE | FILE_ATTRIBUTE_VIEW_INDEX;
+            let (mut record, key) = self.system_file(number, name, extend, attributes, false, (0, 0))?;
+            for (index, value) in indexes {
+                record.resident(ATTR_TYPE_INDEX_ROOT, index, &value, false)?;
             }
-            (256u16 - n as u16) as i8
-        } else {
-            (mft_record_size / (bytes_per_sector as u32 * sectors_per_cluster as u32)) as i8
-        },
-        unused4: [0; 3],
-        clusters_per_index_buffer: 1,
-        unused5: [0; 3],
-        volume_serial: generate_serial_number(),
-        checksum: 0,
-        bootstrap: [0; 426],
-        signature: 0xAA55,
-    };
-    
-    // Volume label would be stored in the MFT $Volume record
-    // For now, we're creating a basic NTFS structure
-    
-    // Write boot sector at offset 0
-    file.seek(SeekFrom::Start(0))?;
-    let boot_bytes = unsafe {
-        std::slice::from_raw_parts(
-            &boot_sector as *const _ as *const u8,
-            std::mem::size_of::<NtfsBootSector>()
-        )
+            records[number as usize] = Some(record.finish());
+            extend_entries.push((name, number, key));
+        }
+        let entries = self.directory_entries(extend_entries, end_entry(None));
+        let (mut record, key) = self.system_file(MFT_RECORD_EXTEND, "$Extend", root, system, true, (0, 0))?;
+        record.resident(
+            ATTR_TYPE_INDEX_ROOT, "$I30", &index_root(ATTR_TYPE_FILE_NAME, COLLATION_FILE_NAME, cluster_size, &entries, false), false,
+        )?;
+        records[MFT_RECORD_EXTEND as usize] = Some(record.finish());
+        root_entries.push(("$Extend", MFT_RECORD_EXTEND, key));
+
+        // The root lists all of the above and itself, in one index block below a root
+        // holding only the end entry
+        let (mut record, key) = self.system_file(MFT_RECORD_ROOT, ".", root, system | FILE_ATTRIBUTE_ARCHIVE, true, (0, 0))?;
+        root_entries.push((".", MFT_RECORD_ROOT, key));
+        let root_index = index_block(&self.directory_entries(root_entries, end_entry(None)))?;
+        let mut root_index_padded = root_index.clone();
+        root_index_padded.resize(layout.bytes(&layout.root_index) as usize, 0);
+        record.resident(
+            ATTR_TYPE_INDEX_ROOT, "$I30", &index_root(ATTR_TYPE_FILE_NAME, COLLATION_FILE_NAME, cluster_size, &end_entry(Some(0)), true), false,
+        )?;
+        record.non_resident(
+            ATTR_TYPE_INDEX_ALLOCATION, "$I30", &layout.root_index.runs(), layout.bytes(&layout.root_index), INDEX_BLOCK_SIZE as u64,
+        )?;
+        record.resident(ATTR_TYPE_BITMAP, "$I30", &[1, 0, 0, 0, 0, 0, 0, 0], false)?;
+        records[MFT_RECORD_ROOT as usize] = Some(record.finish());
+
+        // Records 12-15 are reserved in use, the rest are free
+        for number in 12..16 {
+            let mut record = FileRecord::new(number, MFT_RECORD_IN_USE);
+            record.resident(ATTR_TYPE_STANDARD_INFORMATION, "", &self.standard_information(system, 0), false)?;
+            records[number as usize] = Some(record.finish());
+        }
+        let records = records.into_iter()
+            .enumerate()
+            .map(|(number, record)| record.unwrap_or_else(|| FileRecord::new(number as u64, 0).finish()))
+            .collect();
+        Ok((records, root_index_padded))
+    }
+
+    /// Index entries for `files` in collation order, then `end`
+    fn directory_entries(&self, mut files: Vec<(&str, u64, Vec<u8>)>, end: Vec<u8>) -> Vec<u8> {
+        let units = |name: &str| name.encode_utf16().collect::<Vec<_>>();
+        files.sort_by(|a, b| collate_names(&self.upcase, &units(a.0), &units(b.0)));
+        let mut entries: Vec<u8> = files.iter()
+            .flat_map(|(_, number, key)| file_name_entry(reference(*number), key))
+            .collect();
+        entries.extend(end);
+        entries
+    }
+
+    /// The $Q entry for owner id 1, which holds the volume's default limits: none
+    fn default_quota(&self) -> Vec<u8> {
+        const QUOTA_VERSION: u32 = 2;
+        const QUOTA_FLAG_DEFAULT_LIMITS: u32 = 0x01;
+        let mut data = vec![0u8; 48];
+        put_u32(&mut data, 0x00, QUOTA_VERSION);
+        put_u32(&mut data, 0x04, QUOTA_FLAG_DEFAULT_LIMITS);
+        put_u64(&mut data, 0x10, self.time);
+        put_u64(&mut data, 0x18, u64::MAX);
+        put_u64(&mut data, 0x20, u64::MAX);
+        data
+    }
+}
+
+/// An MFT record being put together. Attributes must be added in type order, then
+/// name order.
+struct FileRecord {
+    data: Vec<u8>,
+    /// Where the next attribute goes
+    used: usize,
+    next_instance: u16,
+}
+
+impl FileRecord {
+    fn new(number: u64, flags: u16) -> Self {
+        let usa_count = usa_count(MFT_RECORD_SIZE);
+        let first_attribute = align8(RECORD_USA_OFFSET + 2 * usa_count);
+        let mut data = vec![0u8; MFT_RECORD_SIZE];
+        data[0..4].copy_from_slice(MFT_RECORD_SIGNATURE);
+        put_u16(&mut data, 0x04, RECORD_USA_OFFSET as u16);
+        put_u16(&mut data, 0x06, usa_count as u16);
+        put_u16(&mut data, 0x10, sequence_number(number));
+        put_u16(&mut data, 0x14, first_attribute as u16);
+        put_u16(&mut data, 0x16, flags);
+        put_u32(&mut data, 0x1C, MFT_RECORD_SIZE as u32);
+        put_u32(&mut data, 0x2C, number as u32);
+        Self { data, used: first_attribute, next_instance: 0 }
+    }
+
+    fn set_link_count(&mut self, links: u16) {
+        put_u16(&mut self.data, 0x12, links);
+    }
+
+    fn append(&mut self, mut attribute: Vec<u8>) -> Result<(), MosesError> {
+        // Room is left for the end marker
+        if self.used + attribute.len() + 8 > MFT_RECORD_SIZE {
+            return Err(MosesError::Other(format!(
+                "Attribute 0x{:X} does not fit in MFT record {}", get_u32(&attribute, 0), get_u32(&self.data, 0x2C),
+            )));
+        }
+        put_u16(&mut attribute, 0x0E, self.next_instance);
+        self.next_instance += 1;
+        self.data[self.used..self.used + attribute.len()].copy_from_slice(&attribute);
+        self.used += attribute.len();
+        Ok(())
+    }
+
+    fn resident(&mut self, type_code: u32, name: &str, value: &[u8], indexed: bool) -> Result<(), MosesError> {
+        let name = utf16_bytes(name);
+        let value_offset = align8(0x18 + name.len());
+        let length = align8(value_offset + value.len());
+        let mut attribute = vec![0u8; length];
+        put_u32(&mut attribute, 0x00, type_code);
+        put_u32(&mut attribute, 0x04, length as u32);
+        attribute[0x09] = (name.len() / 2) as u8;
+        put_u16(&mut attribute, 0x0A, 0x18);
+        put_u32(&mut attribute, 0x10, value.len() as u32);
+        put_u16(&mut attribute, 0x14, value_offset as u16);
+        attribute[0x16] = indexed as u8;
+        attribute[0x18..0x18 + name.len()].copy_from_slice(&name);
+        attribute[value_offset..value_offset + value.len()].copy_from_slice(value);
+        self.append(attribute)
+    }
+
+    fn non_resident(&mut self, type_code: u32, name: &str, runs: &[DataRun], allocated: u64, size: u64) -> Result<(), MosesError> {
+        let name = utf16_bytes(name);
+        let runs_offset = align8(0x40 + name.len());
+        let encoded = encode_data_runs(runs);
+        let length = align8(runs_offset + encoded.len());
+        let clusters: u64 = runs.iter().map(|run| run.length).sum();
+        let mut attribute = vec![0u8; length];
+        put_u32(&mut attribute, 0x00, type_code);
+        put_u32(&mut attribute, 0x04, length as u32);
+        attribute[0x08] = 1;
+        attribute[0x09] = (name.len() / 2) as u8;
+        put_u16(&mut attribute, 0x0A, 0x40);
+        put_u64(&mut attribute, 0x18, clusters - 1);
+        put_u16(&mut attribute, 0x20, runs_offset as u16);
+        put_u64(&mut attribute, 0x28, allocated);
+        put_u64(&mut attribute, 0x30, size);
+        put_u64(&mut attribute, 0x38, size);
+        attribute[0x40..0x40 + name.len()].copy_from_slice(&name);
+        attribute[runs_offset..runs_offset + encoded.len()].copy_from_slice(&encoded);
+        self.append(attribute)
+    }
+
+    /// Close the record with the end marker and protect it with the update sequence
+    fn finish(mut self) -> Vec<u8> {
+        let used = self.used;
+        put_u32(&mut self.data, used, ATTR_TYPE_END);
+        put_u32(&mut self.data, 0x18, (used + 8) as u32);
+        put_u16(&mut self.data, 0x28, self.next_instance);
+        protect(&mut self.data, RECORD_USA_OFFSET);
+        self.data
+    }
+}
+
+/// The system files' sequence numbers are their record numbers, as Windows has them;
+/// $MFT and every other record start at 1
+fn sequence_number(number: u64) -> u16 {
+    match number {
+        1..=15 => number as u16,
+        _ => 1,
+    }
+}
+
+fn reference(number: u64) -> u64 {
+    ((sequence_number(number) as u64) << 48) | number
+}
+
+/// Entries in the update sequence array of a block: the sequence number, then one per sector
+fn usa_count(block_size: usize) -> usize {
+    block_size / SECTOR_SIZE as usize + 1
+}
+
+/// Apply the update sequence: the last two bytes of every sector move into the array and
+/// the sequence number takes their place, so a torn write shows as a mismatch
+fn protect(block: &mut [u8], usa_offset: usize) {
+    put_u16(block, usa_offset, UPDATE_SEQUENCE);
+    for sector in 0..block.len() / SECTOR_SIZE as usize {
+        let end = (sector + 1) * SECTOR_SIZE as usize - 2;
+        let slot = usa_offset + 2 + sector * 2;
+        block.copy_within(end..end + 2, slot);
+        put_u16(block, end, UPDATE_SEQUENCE);
+    }
+}
+
+/// Boot sector encoding of a record size: clusters per record, or when a record is
+/// smaller than a cluster, the negated log2 of its size in bytes
+fn size_in_clusters(size: u64, cluster_size: u64) -> i8 {
+    if size >= cluster_size {
+        (size / cluster_size) as i8
+    } else {
+        -(size.trailing_zeros() as i8)
+    }
+}
+
+/// An INDEX_ROOT value; `large` roots hold only an end entry pointing at block 0
+fn index_root(indexed_type: u32, collation: u32, cluster_size: u64, entries: &[u8], large: bool) -> Vec<u8> {
+    // Index blocks smaller than a cluster are counted in sectors
+    let block_size = INDEX_BLOCK_SIZE as u64;
+    let clusters_per_block = if block_size >= cluster_size { block_size / cluster_size } else { block_size / SECTOR_SIZE };
+    let index_length = 16 + entries.len() as u32;
+    let mut root = vec![0u8; 32 + entries.len()];
+    put_u32(&mut root, 0x00, indexed_type);
+    put_u32(&mut root, 0x04, collation);
+    put_u32(&mut root, 0x08, INDEX_BLOCK_SIZE as u32);
+    root[0x0C] = clusters_per_block as u8;
+    put_u32(&mut root, 0x10, 16);
+    put_u32(&mut root, 0x14, index_length);
+    put_u32(&mut root, 0x18, index_length);
+    put_u32(&mut root, 0x1C, if large { LARGE_INDEX } else { 0 });
+    root[32..].copy_from_slice(entries);
+    root
+}
+
+/// An INDX block holding `entries`, protected with the update sequence
+fn index_block(entries: &[u8]) -> Result<Vec<u8>, MosesError> {
+    const HEADER: usize = crate::families::ntfs::ntfs::index::INDEX_BLOCK_HEADER_OFFSET;
+    let usa_count = usa_count(INDEX_BLOCK_SIZE);
+    let entries_start = align8(INDEX_USA_OFFSET + 2 * usa_count);
+    if entries_start + entries.len() > INDEX_BLOCK_SIZE {
+        return Err(MosesError::Other("The root directory's entries do not fit in one index block".to_string()));
+    }
+    let mut block = vec![0u8; INDEX_BLOCK_SIZE];
+    block[0..4].copy_from_slice(b"INDX");
+    put_u16(&mut block, 0x04, INDEX_USA_OFFSET as u16);
+    put_u16(&mut block, 0x06, usa_count as u16);
+    put_u32(&mut block, HEADER, (entries_start - HEADER) as u32);
+    put_u32(&mut block, HEADER + 4, (entries_start - HEADER + entries.len()) as u32);
+    put_u32(&mut block, HEADER + 8, (INDEX_BLOCK_SIZE - HEADER) as u32);
+    block[entries_start..entries_start + entries.len()].copy_from_slice(entries);
+    protect(&mut block, INDEX_USA_OFFSET);
+    Ok(block)
+}
+
+/// A $I30 entry: the file's reference with its $FILE_NAME value as the key
+fn file_name_entry(reference: u64, key: &[u8]) -> Vec<u8> {
+    let length = align8(16 + key.len());
+    let mut entry = vec![0u8; length];
+    put_u64(&mut entry, 0x00, reference);
+    put_u16(&mut entry, 0x08, length as u16);
+    put_u16(&mut entry, 0x0A, key.len() as u16);
+    entry[16..16 + key.len()].copy_from_slice(key);
+    entry
+}
+
+/// A view index entry, which carries data where $I30 entries have a file reference
+fn view_entry(key: &[u8], data: &[u8], padding: &[u8]) -> Vec<u8> {
+    let data_offset = 16 + key.len();
+    let length = align8(data_offset + data.len() + padding.len());
+    let mut entry = vec![0u8; length];
+    put_u16(&mut entry, 0x00, data_offset as u16);
+    put_u16(&mut entry, 0x02, data.len() as u16);
+    put_u16(&mut entry, 0x08, length as u16);
+    put_u16(&mut entry, 0x0A, key.len() as u16);
+    entry[16..data_offset].copy_from_slice(key);
+    entry[data_offset..data_offset + data.len()].copy_from_slice(data);
+    entry[data_offset + data.len()..data_offset + data.len() + padding.len()].copy_from_slice(padding);
+    entry
+}
+
+/// The last entry of an index node, pointing at the block below it if there is one
+fn end_entry(child_vcn: Option<u64>) -> Vec<u8> {
+    let (length, flags) = match child_vcn {
+        Some(_) => (24, INDEX_ENTRY_END | INDEX_ENTRY_NODE),
+        None => (16, INDEX_ENTRY_END),
     };
-    file.write_all(boot_bytes)?;
-    
-    Ok(())
+    let mut entry = vec![0u8; length];
+    put_u16(&mut entry, 0x08, length as u16);
+    put_u16(&mut entry, 0x0C, flags);
+    if let Some(vcn) = child_vcn {
+        put_u64(&mut entry, 0x10, vcn);
+    }
+    entry
+}
+
+/// $VOLUME_INFORMATION: NTFS 3.1, no flags set
+fn volume_information() -> Vec<u8> {
+    let mut value = vec![0u8; 12];
+    value[8] = 3;
+    value[9] = 1;
+    value
+}
+
+/// Owner and group Administrators, with Everyone allowed full control that everything
+/// created below inherits
+fn security_descriptor() -> Vec<u8> {
+    const SE_DACL_PRESENT: u16 = 0x0004;
+    const SE_SELF_RELATIVE: u16 = 0x8000;
+    const FULL_CONTROL: u32 = 0x001F_01FF;
+    // Allowed, object and container inherit
+    const ACE_FLAGS: u8 = 0x03;
+    let everyone = [1u8, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+    let administrators = [1u8, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0x20, 0x02, 0, 0];
+
+    let mut ace = vec![0, ACE_FLAGS];
+    ace.extend_from_slice(&((8 + everyone.len()) as u16).to_le_bytes());
+    ace.extend_from_slice(&FULL_CONTROL.to_le_bytes());
+    ace.extend_from_slice(&everyone);
+    let mut dacl = vec![2, 0];
+    dacl.extend_from_slice(&((8 + ace.len()) as u16).to_le_bytes());
+    dacl.extend_from_slice(&1u16.to_le_bytes());
+    dacl.extend_from_slice(&[0, 0]);
+    dacl.extend_from_slice(&ace);
+
+    let dacl_offset = 20u32;
+    let owner_offset = dacl_offset + dacl.len() as u32;
+    let group_offset = owner_offset + administrators.len() as u32;
+    let mut descriptor = vec![1, 0];
+    descriptor.extend_from_slice(&(SE_SELF_RELATIVE | SE_DACL_PRESENT).to_le_bytes());
+    descriptor.extend_from_slice(&owner_offset.to_le_bytes());
+    descriptor.extend_from_slice(&group_offset.to_le_bytes());
+    descriptor.extend_from_slice(&0u32.to_le_bytes());
+    descriptor.extend_from_slice(&dacl_offset.to_le_bytes());
+    descriptor.extend_from_slice(&dacl);
+    descriptor.extend_from_slice(&administrators);
+    descriptor.extend_from_slice(&administrators);
+    descriptor
+}
+
+/// The hash $SDH indexes descriptors by
+fn security_hash(descriptor: &[u8]) -> u32 {
+    descriptor.as_chunks::<4>().0.iter().fold(0u32, |hash, word| {
+        u32::from_le_bytes(*word).wrapping_add(hash.rotate_left(3))
+    })
+}
+
+/// hash, id, offset in $SDS and length of a descriptor: what precedes it in $SDS and
+/// what $SDH and $SII map to
+const SDS_HEADER_SIZE: usize = 20;
+
+fn sds_header(descriptor: &[u8]) -> Vec<u8> {
+    let mut header = vec![0u8; SDS_HEADER_SIZE];
+    put_u32(&mut header, 0x00, security_hash(descriptor));
+    put_u32(&mut header, 0x04, SECURITY_ID);
+    put_u32(&mut header, 0x10, (SDS_HEADER_SIZE + descriptor.len()) as u32);
+    header
+}
+
+/// $Secure:$SDS, with the descriptor at the start and again in the mirror block
+fn security_stream() -> Vec<u8> {
+    let descriptor = security_descriptor();
+    let entry = [sds_header(&descriptor), descriptor].concat();
+    let mut stream = vec![0u8; sds_size() as usize];
+    stream[..entry.len()].copy_from_slice(&entry);
+    stream[SDS_BLOCK_SIZE..SDS_BLOCK_SIZE + entry.len()].copy_from_slice(&entry);
+    stream
+}
+
+/// $AttrDef: name, type, flags and size limits of every attribute type NTFS 3.1 knows
+fn attribute_definitions() -> Vec<u8> {
+    const INDEXABLE: u32 = 0x02;
+    const MUST_BE_RESIDENT: u32 = 0x40;
+    const LOG_NONRESIDENT: u32 = 0x80;
+    const ENTRY_SIZE: usize = 0xA0;
+    const UNLIMITED: i64 = -1;
+    let definitions: [(&str, u32, u32, i64, i64); 15] = [
+        ("$STANDARD_INFORMATION", ATTR_TYPE_STANDARD_INFORMATION, MUST_BE_RESIDENT, 0x30, 0x48),
+        ("$ATTRIBUTE_LIST", ATTR_TYPE_ATTRIBUTE_LIST, LOG_NONRESIDENT, 0, UNLIMITED),
+        ("$FILE_NAME", ATTR_TYPE_FILE_NAME, MUST_BE_RESIDENT | INDEXABLE, 0x44, 0x242),
+        ("$OBJECT_ID", ATTR_TYPE_OBJECT_ID, MUST_BE_RESIDENT, 0, 0x100),
+        ("$SECURITY_DESCRIPTOR", ATTR_TYPE_SECURITY_DESCRIPTOR, LOG_NONRESIDENT, 0, UNLIMITED),
+        ("$VOLUME_NAME", ATTR_TYPE_VOLUME_NAME, MUST_BE_RESIDENT, 2, 0x100),
+        ("$VOLUME_INFORMATION", ATTR_TYPE_VOLUME_INFORMATION, MUST_BE_RESIDENT, 0xC, 0xC),
+        ("$DATA", ATTR_TYPE_DATA, 0, 0, UNLIMITED),
+        ("$INDEX_ROOT", ATTR_TYPE_INDEX_ROOT, MUST_BE_RESIDENT, 0, UNLIMITED),
+        ("$INDEX_ALLOCATION", ATTR_TYPE_INDEX_ALLOCATION, LOG_NONRESIDENT, 0, UNLIMITED),
+        ("$BITMAP", ATTR_TYPE_BITMAP, LOG_NONRESIDENT, 0, UNLIMITED),
+        ("$REPARSE_POINT", ATTR_TYPE_REPARSE_POINT, LOG_NONRESIDENT, 0, 0x4000),
+        ("$EA_INFORMATION", ATTR_TYPE_EA_INFORMATION, MUST_BE_RESIDENT, 8, 8),
+        ("$EA", ATTR_TYPE_EA, 0, 0, 0x10000),
+        ("$LOGGED_UTILITY_STREAM", ATTR_TYPE_LOGGED_UTILITY_STREAM, LOG_NONRESIDENT, 0, 0x10000),
+    ];
+    let mut table = vec![0u8; ATTRDEF_SIZE];
+    for (entry, (name, type_code, flags, min, max)) in table.as_chunks_mut::<ENTRY_SIZE>().0.iter_mut().zip(definitions) {
+        let name = utf16_bytes(name);
+        entry[..name.len()].copy_from_slice(&name);
+        put_u32(entry, 0x80, type_code);
+        let collation = if type_code == ATTR_TYPE_FILE_NAME { COLLATION_FILE_NAME } else { 0 };
+        put_u32(entry, 0x88, collation);
+        put_u32(entry, 0x8C, flags);
+        put_u64(entry, 0x90, min as u64);
+        put_u64(entry, 0x98, max as u64);
+    }
+    table
 }
 
 /// Generate a random volume serial number
@@ -220,362 +1033,142 @@ fn generate_serial_number() -> u64 {
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_secs();
-    
+
     // Mix timestamp with a simple hash for uniqueness
     timestamp ^ (timestamp << 13) ^ (timestamp >> 7)
 }
 
-/// Write the system MFT records
-fn write_system_mft_records(
-    file: &mut std::fs::File,
-    bytes_per_cluster: u32,
-    mft_start_cluster: u64,
-    mft_record_size: u32,
-    total_clusters: u64,
-) -> Result<(), MosesError> {
-    info!("Writing system MFT records");
-    
-    let mft_offset = mft_start_cluster * bytes_per_cluster as u64;
-    
-    // Create system MFT records
-    let system_records = vec![
-        create_mft_record_0(mft_record_size, mft_start_cluster, bytes_per_cluster)?, // $MFT
-        create_mft_record_1(mft_record_size)?, // $MFTMirr
-        create_mft_record_2(mft_record_size)?, // $LogFile
-        create_mft_record_3(mft_record_size)?, // $Volume
-        create_mft_record_4(mft_record_size)?, // $AttrDef
-        create_mft_record_5(mft_record_size)?, // . (root directory)
-        create_mft_record_6(mft_record_size, total_clusters)?, // $Bitmap
-        create_mft_record_7(mft_record_size)?, // $Boot
-        create_mft_record_8(mft_record_size)?, // $BadClus
-        create_mft_record_9(mft_record_size)?, // $Secure
-        create_mft_record_10(mft_record_size)?, // $UpCase
-        create_mft_record_11(mft_record_size)?, // $Extend
-    ];
-    
-    // Write each MFT record
-    for (i, record) in system_records.iter().enumerate() {
-        let record_offset = mft_offset + (i as u64 * mft_record_size as u64);
-        file.seek(SeekFrom::Start(record_offset))?;
-        file.write_all(record)?;
-        debug!("Wrote MFT record {}", i);
-    }
-    
-    // Write additional reserved MFT records (12-15) as empty
-    for i in 12..16 {
-        let record_offset = mft_offset + (i * mft_record_size as u64);
-        file.seek(SeekFrom::Start(record_offset))?;
-        let empty_record = vec![0u8; mft_record_size as usize];
-        file.write_all(&empty_record)?;
-    }
-    
-    Ok(())
-}
+/// Time to stamp the system files with, in Windows FILETIME format
+fn windows_time(seed: &FormatSeed) -> u64 {
+    let unix_time = seed.unix_time();
 
-/// Create MFT record 0 ($MFT)
-fn create_mft_record_0(record_size: u32, mft_cluster: u64, bytes_per_cluster: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    // Calculate MFT size - typically starts with 16 records minimum
-    let initial_mft_records = 16u64;
-    let mft_size = initial_mft_records * record_size as u64;
-    let mft_clusters = (mft_size + bytes_per_cluster as u64 - 1) / bytes_per_cluster as u64;
-    
-    MftRecordBuilder::new(0, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)? // System + Hidden
-        .with_file_name(5, "$MFT", 3, current_time, current_time, current_time, 
-                       mft_size, mft_size, 0x06)?
-        .with_non_resident_data(mft_cluster, mft_clusters, bytes_per_cluster)? // Non-resident DATA attribute
-        .build()
-}
-
-/// Create MFT record 1 ($MFTMirr)
-fn create_mft_record_1(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(1, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$MFTMirr", 3, current_time, current_time, current_time, 0, 0, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 2 ($LogFile)
-fn create_mft_record_2(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(2, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$LogFile", 3, current_time, current_time, current_time, 0, 0, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 3 ($Volume)
-fn create_mft_record_3(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(3, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x16)? // System + Hidden + Volume
-        .with_file_name(5, "$Volume", 3, current_time, current_time, current_time, 0, 0, 0x16)?
-        .build()
-}
-
-/// Create MFT record 4 ($AttrDef)
-fn create_mft_record_4(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(4, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$AttrDef", 3, current_time, current_time, current_time, 0, 0, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 5 (root directory)
-fn create_mft_record_5(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(5, record_size)
-        .as_directory()
-        .with_standard_info(current_time, current_time, current_time, 0x10)? // Directory
-        .with_file_name(5, ".", 3, current_time, current_time, current_time, 0, 0, 0x10)?
-        .with_index_root(ATTR_TYPE_FILE_NAME)?
-        .build()
-}
-
-/// Create MFT record 6 ($Bitmap)
-fn create_mft_record_6(record_size: u32, total_clusters: u64) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    let bitmap_size = (total_clusters + 7) / 8;
-    
-    MftRecordBuilder::new(6, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$Bitmap", 3, current_time, current_time, current_time, 
-                       bitmap_size, bitmap_size, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 7 ($Boot)
-fn create_mft_record_7(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(7, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$Boot", 3, current_time, current_time, current_time, 8192, 8192, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 8 ($BadClus)
-fn create_mft_record_8(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(8, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$BadClus", 3, current_time, current_time, current_time, 0, 0, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 9 ($Secure)
-fn create_mft_record_9(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(9, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$Secure", 3, current_time, current_time, current_time, 0, 0, 0x06)?
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 10 ($UpCase)
-fn create_mft_record_10(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(10, record_size)
-        .as_file()
-        .with_standard_info(current_time, current_time, current_time, 0x06)?
-        .with_file_name(5, "$UpCase", 3, current_time, current_time, current_time, 
-                       131072, 131072, 0x06)? // 128KB upcase table
-        .with_empty_data()?
-        .build()
-}
-
-/// Create MFT record 11 ($Extend)
-fn create_mft_record_11(record_size: u32) -> Result<Vec<u8>, MosesError> {
-    let current_time = windows_time_now();
-    
-    MftRecordBuilder::new(11, record_size)
-        .as_directory()
-        .with_standard_info(current_time, current_time, current_time, 0x16)?
-        .with_file_name(5, "$Extend", 3, current_time, current_time, current_time, 0, 0, 0x16)?
-        .with_index_root(ATTR_TYPE_FILE_NAME)?
-        .build()
-}
-
-/// Get current time in Windows FILETIME format
-fn windows_time_now() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    
-    let unix_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    
     // Convert Unix time to Windows FILETIME (100ns intervals since 1601)
     // Unix epoch (1970) is 11644473600 seconds after Windows epoch (1601)
     (unix_time + 11644473600) * 10_000_000
 }
 
-/// Initialize cluster and MFT bitmaps
-fn initialize_bitmaps(
-    file: &mut std::fs::File,
-    bytes_per_cluster: u32,
-    total_clusters: u64,
-    mft_clusters: u64,
-) -> Result<(), MosesError> {
-    debug!("Initializing bitmaps");
-    
-    // Create cluster bitmap
-    let bitmap_size = ((total_clusters + 7) / 8) as usize;
-    let mut cluster_bitmap = vec![0u8; bitmap_size];
-    
-    // Mark system clusters as used (0-15 for boot sectors and system files)
-    for i in 0..16 {
-        let byte_idx = i / 8;
-        let bit_idx = i % 8;
-        cluster_bitmap[byte_idx] |= 1 << bit_idx;
-    }
-    
-    // Mark MFT clusters as used
-    let mft_start = 4;
-    for i in 0..mft_clusters {
-        let cluster = mft_start + i;
-        let byte_idx = (cluster / 8) as usize;
-        let bit_idx = (cluster % 8) as u8;
-        if byte_idx < cluster_bitmap.len() {
-            cluster_bitmap[byte_idx] |= 1 << bit_idx;
-        }
-    }
-    
-    // Write bitmap to a known location (we'd normally put this in $Bitmap's data)
-    // For now, write after MFT zone
-    let bitmap_offset = (16 + mft_clusters) * bytes_per_cluster as u64;
-    file.seek(SeekFrom::Start(bitmap_offset))?;
-    file.write_all(&cluster_bitmap)?;
-    
+fn write_at<W: Write + Seek>(out: &mut W, offset: u64, data: &[u8]) -> Result<(), MosesError> {
+    out.seek(SeekFrom::Start(offset))?;
+    out.write_all(data)?;
     Ok(())
 }
 
-/// Write backup boot sector at the end of the volume
-fn write_backup_boot_sector(
-    file: &mut std::fs::File,
-    total_sectors: u64,
-    bytes_per_sector: u16,
-) -> Result<(), MosesError> {
-    debug!("Writing backup boot sector");
-    
-    // Read the primary boot sector
-    file.seek(SeekFrom::Start(0))?;
-    let mut boot_sector = vec![0u8; bytes_per_sector as usize];
-    file.read_exact(&mut boot_sector)?;
-    
-    // Write it at the last sector
-    let backup_offset = (total_sectors - 1) * bytes_per_sector as u64;
-    file.seek(SeekFrom::Start(backup_offset))?;
-    file.write_all(&boot_sector)?;
-    
-    Ok(())
+fn align8(value: usize) -> usize {
+    value.next_multiple_of(8)
 }
 
-impl NtfsFormatter {
-    /// Create a new NTFS formatter instance
-    pub fn new() -> Self {
-        Self
+fn utf16_bytes(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn put_u16(buffer: &mut [u8], offset: usize, value: u16) {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut [u8], offset: usize, value: u64) {
+    buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::families::ntfs::ntfs::index::{parse_index_allocation, parse_index_root};
+    use crate::families::ntfs::ntfs::mft::apply_fixup;
+    use crate::families::ntfs::ntfs::NtfsReader;
+    use moses_core::FilesystemFormatter as _;
+
+    fn record(image: &[u8], layout: &Layout, number: usize) -> Vec<u8> {
+        let start = layout.offset(&layout.mft) as usize + number * MFT_RECORD_SIZE;
+        let mut data = image[start..start + MFT_RECORD_SIZE].to_vec();
+        apply_fixup(&mut data, RECORD_USA_OFFSET as u16, usa_count(MFT_RECORD_SIZE) as u16).unwrap();
+        data
     }
-    
-    /// Synchronous format method for testing
-    pub fn format(&mut self, device: &Device, label: &str) -> Result<(), MosesError> {
-        // Delegate to the same implementation as the async version
-        // but synchronously
-        self.format_impl(device, label)
-    }
-    
-    fn format_impl(&mut self, device: &Device, label: &str) -> Result<(), MosesError> {
-        // Open the device file
-        let mut file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&device.id)?;
-        
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout::new(64 << 20, None).unwrap();
+        assert_eq!(layout.cluster_size, 4096);
+        assert_eq!(layout.mft.lcn, 4);
+        assert_eq!(layout.mft_mirror.lcn, layout.clusters / 2);
+        let extents = layout.used_extents();
+        for (i, a) in extents.iter().enumerate() {
+            assert!(a.end() <= layout.clusters);
+            assert!(extents[i + 1..].iter().all(|b| a.end() <= b.lcn || b.end() <= a.lcn), "{:?} overlaps", a);
+        }
+        assert_eq!(size_in_clusters(1024, 4096), -10);
+        assert_eq!(size_in_clusters(4096, 512), 8);
+        assert!(Layout::new(64 << 20, Some(3000)).is_err());
+        assert!(Layout::new(1 << 20, None).is_err());
+        // Clusters are 32-bit; 512-byte ones run out at 2 TiB
+        assert!(Layout::new(4 << 40, Some(512)).is_err());
+        assert_eq!(Layout::new(20 << 40, None).unwrap().cluster_size, 8192);
+    }
+
+    #[tokio::test]
+    async fn test_format_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ntfs.img");
+        let size = 64u64 << 20;
+        let device = crate::image_target::create_image(&path, size).unwrap();
         let options = FormatOptions {
             filesystem_type: "ntfs".to_string(),
-            label: Some(label.to_string()),
-            cluster_size: None,
-            quick_format: false,
-            enable_compression: false,
-            verify_after_format: false,
-            dry_run: false,
-            force: false,
-            additional_options: std::collections::HashMap::new(),
+            label: Some("Données".to_string()),
+            ..Default::default()
         };
-        
-        // Reuse the same formatting logic
-        self.format_device(&mut file, device, &options)
-    }
-    
-    fn format_device(&self, file: &mut std::fs::File, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
-        info!("Formatting {} as NTFS", device.id);
-        
-        // Default parameters
-        let bytes_per_sector = 512u16;
-        let total_sectors = device.size / bytes_per_sector as u64;
-        let sectors_per_cluster = calculate_sectors_per_cluster(device.size);
-        let bytes_per_cluster = bytes_per_sector as u32 * sectors_per_cluster as u32;
-        let total_clusters = total_sectors / sectors_per_cluster as u64;
-        
-        // MFT parameters
-        let mft_record_size = 1024u32; // Standard size
-        let mft_clusters = (total_clusters / 8).max(8192).min(65536); // 12.5% of disk, min 32MB, max 256MB
-        let _mft_zone_clusters = mft_clusters * 4; // Reserve 4x MFT size for growth
-        let mft_start_cluster = 4; // Start MFT at cluster 4 (after boot sector)
-        
-        info!("NTFS parameters: {} sectors, {} bytes/cluster, MFT at cluster {}",
-              total_sectors, bytes_per_cluster, mft_start_cluster);
-        
-        // Step 1: Write boot sector
-        write_boot_sector(file, device, options, 
-                         bytes_per_sector, sectors_per_cluster,
-                         total_sectors, mft_start_cluster)?;
-        
-        // Step 2: Create and write system MFT records
-        write_system_mft_records(file, bytes_per_cluster, 
-                                mft_start_cluster, mft_record_size,
-                                total_clusters)?;
-        
-        // Step 3: Initialize bitmaps
-        initialize_bitmaps(file, bytes_per_cluster, total_clusters, mft_clusters)?;
-        
-        // Step 4: Write backup boot sector
-        write_backup_boot_sector(file, total_sectors, bytes_per_sector)?;
-        
-        // Flush all writes
-        file.flush()?;
-        
-        info!("NTFS format completed successfully");
-        Ok(())
+        NtfsFormatter.validate_options(&options).await.unwrap();
+        moses_core::FilesystemFormatter::format(&NtfsFormatter, &device, &options).await.unwrap();
+        let image = std::fs::read(&path).unwrap();
+        let layout = Layout::new(size, None).unwrap();
+
+        // The backup boot sector is the device's last sector
+        assert_eq!(&image[3..11], NTFS_SIGNATURE);
+        assert_eq!(image[..512], image[image.len() - 512..]);
+
+        // The mirror matches the first records, and every record passes its fixup
+        let mft = layout.offset(&layout.mft) as usize;
+        let mirror = layout.offset(&layout.mft_mirror) as usize;
+        assert_eq!(image[mft..mft + 4096], image[mirror..mirror + 4096]);
+        let mft_record = record(&image, &layout, 0);
+        assert_eq!(&mft_record[0..4], MFT_RECORD_SIGNATURE);
+        for number in 0..layout.mft_records as usize {
+            record(&image, &layout, number);
+        }
+
+        // The root's one index block lists the system files in collation order
+        let root = record(&image, &layout, MFT_RECORD_ROOT as usize);
+        let index_root_type = ATTR_TYPE_INDEX_ROOT.to_le_bytes();
+        let at = (0x38..MFT_RECORD_SIZE).step_by(8).find(|&i| root[i..i + 4] == index_root_type).unwrap();
+        let value = &root[at + get_u32(&root, at + 0x14) as usize & 0xFFFF..];
+        assert!(parse_index_root(value).unwrap().is_empty());
+        let block = layout.offset(&layout.root_index) as usize;
+        let entries = parse_index_allocation(&image[block..block + INDEX_BLOCK_SIZE], INDEX_BLOCK_SIZE as u32).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.file_name.as_str()).collect();
+        assert_eq!(names, [
+            "$AttrDef", "$BadClus", "$Bitmap", "$Boot", "$Extend", "$LogFile", "$MFT", "$MFTMirr",
+            "$Secure", "$UpCase", "$Volume", ".",
+        ]);
+        assert!(entries.iter().find(|entry| entry.file_name == "$Extend").unwrap().is_directory);
+
+        // $Bitmap marks exactly the metadata clusters
+        let bitmap = layout.offset(&layout.bitmap) as usize;
+        let used: u64 = image[bitmap..bitmap + layout.bitmap_size as usize].iter().map(|byte| byte.count_ones() as u64).sum();
+        assert_eq!(used * layout.cluster_size, layout.clusters * layout.cluster_size - layout.free_bytes());
+
+        let mut reader = NtfsReader::new(device.clone()).unwrap();
+        let info = reader.filesystem_info().unwrap();
+        assert_eq!(info.label.as_deref(), Some("Données"));
+        assert!(reader.list_directory("/").unwrap().is_empty(), "system files are not listed");
+        // The root's security id resolves through $Secure:$SDS
+        let descriptor = reader.read_security(MFT_RECORD_ROOT).unwrap().unwrap();
+        assert!(descriptor.owner_name().unwrap().contains("Administrators"));
+        assert!(descriptor.access_summary().iter().any(|line| line.contains("Everyone")));
     }
 }
-
-use std::io::Read;
\ No newline at end of file
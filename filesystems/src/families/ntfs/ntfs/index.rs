@@ -8,6 +8,9 @@ use log::trace;
 // Index header flags
 pub const INDEX_NODE: u32 = 0x01;  // This is an index node (has children)
 
+/// Where the index header sits in an INDX block
+pub const INDEX_BLOCK_HEADER_OFFSET: usize = 0x18;
+
 /// Index header structure (common to INDEX_ROOT and INDEX_ALLOCATION)
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
@@ -61,15 +64,16 @@ pub fn parse_index_root(data: &[u8]) -> Result<Vec<IndexEntry>, MosesError> {
         trace!("Index root for non-filename attribute: 0x{:X}", attribute_type);
     }
     
-    // Parse entries starting after the IndexRoot structure
-    let entries_offset = std::mem::size_of::<IndexRoot>() + root.header.entries_offset as usize;
-    let index_length = root.header.index_length as usize;
+    // Both offsets count from the index header, which follows the first 16 bytes
+    let header_offset = std::mem::offset_of!(IndexRoot, header);
+    let entries_offset = header_offset + root.header.entries_offset as usize;
+    let entries_end = header_offset + root.header.index_length as usize;
     
-    if entries_offset + index_length > data.len() {
+    if entries_offset > entries_end || entries_end > data.len() {
         return Err(MosesError::Other("Index entries beyond buffer".to_string()));
     }
     
-    parse_index_entries(&data[entries_offset..entries_offset + index_length])
+    parse_index_entries(&data[entries_offset..entries_end])
 }
 
 /// Parse an INDEX_ALLOCATION attribute (for large directories)
@@ -89,28 +93,28 @@ pub fn parse_index_allocation(data: &[u8], index_block_size: u32) -> Result<Vec<
             break;
         }
         
-        // Skip INDX header (similar to MFT record header)
-        // Structure: signature(4) + usa_offset(2) + usa_count(2) + lsn(8) + vcn(8)
-        let _header_size = 24;
-        
-        // Get index header (starts after INDX header + USA)
-        let usa_offset = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
-        let usa_count = u16::from_le_bytes([data[offset + 6], data[offset + 7]]) as usize;
-        let index_offset = offset + usa_offset + (usa_count * 2);
+        // Like MFT records, index blocks are protected by an update sequence array
+        let block_end = (offset + index_block_size as usize).min(data.len());
+        let mut block = data[offset..block_end].to_vec();
+        let usa_offset = u16::from_le_bytes([block[4], block[5]]);
+        let usa_count = u16::from_le_bytes([block[6], block[7]]);
+        crate::families::ntfs::ntfs::mft::apply_fixup(&mut block, usa_offset, usa_count)?;
         
-        if index_offset + std::mem::size_of::<IndexHeader>() > data.len() {
+        // The index header follows signature(4) + usa_offset(2) + usa_count(2) + lsn(8) + vcn(8)
+        let index_offset = INDEX_BLOCK_HEADER_OFFSET;
+        if index_offset + std::mem::size_of::<IndexHeader>() > block.len() {
             break;
         }
         
         let index_header = unsafe {
-            std::ptr::read_unaligned(&data[index_offset] as *const u8 as *const IndexHeader)
+            std::ptr::read_unaligned(&block[index_offset] as *const u8 as *const IndexHeader)
         };
         
         let entries_offset = index_offset + index_header.entries_offset as usize;
-        let entries_length = index_header.index_length as usize;
+        let entries_end = index_offset + index_header.index_length as usize;
         
-        if entries_offset + entries_length <= data.len() {
-            let entries = parse_index_entries(&data[entries_offset..entries_offset + entries_length])?;
+        if entries_offset <= entries_end && entries_end <= block.len() {
+            let entries = parse_index_entries(&block[entries_offset..entries_end])?;
             all_entries.extend(entries);
         }
         
@@ -127,6 +131,9 @@ pub struct IndexEntry {
     pub file_name: String,
     pub is_directory: bool,
     pub has_subnode: bool,
+    /// FILE_NAME key as stored in the index; Windows only refreshes its sizes and times
+    /// lazily, so they can lag the MFT record
+    pub file_name_attr: Option<FileNameAttr>,
 }
 
 /// Parse index entries from a buffer
@@ -180,6 +187,7 @@ fn parse_index_entries(data: &[u8]) -> Result<Vec<IndexEntry>, MosesError> {
                     file_name: name,
                     is_directory,
                     has_subnode: flags & INDEX_ENTRY_NODE != 0,
+                    file_name_attr: Some(file_name_attr),
                 });
             }
         }
@@ -39,12 +39,12 @@ impl IndexUpdater {
             std::ptr::read_unaligned(index_root_data.as_ptr() as *const IndexRoot)
         };
         
-        // Calculate where entries start
-        let header_size = std::mem::size_of::<IndexRoot>();
-        let entries_start = header_size + root.header.entries_offset as usize;
-        let entries_end = header_size + root.header.entries_offset as usize + root.header.index_length as usize;
+        // Entries start entries_offset and end index_length bytes after the index header
+        let header_offset = std::mem::offset_of!(IndexRoot, header);
+        let entries_start = header_offset + root.header.entries_offset as usize;
+        let entries_end = header_offset + root.header.index_length as usize;
         
-        if entries_end > index_root_data.len() {
+        if entries_start > entries_end || entries_end > index_root_data.len() {
             return Err(MosesError::Other("INDEX_ROOT entries beyond buffer".to_string()));
         }
         
@@ -254,8 +254,11 @@ impl IndexUpdater {
         
         // Copy original header with updated sizes
         let mut new_root = original_root;
-        new_root.header.index_length = entries_size as u32;
-        new_root.header.allocated_size = ((entries_size + 7) / 8 * 8) as u32; // Align to 8 bytes
+        // Entries go straight after the header; both sizes count from its start
+        let header_length = (header_size - std::mem::offset_of!(IndexRoot, header)) as u32;
+        new_root.header.entries_offset = header_length;
+        new_root.header.index_length = header_length + entries_size as u32;
+        new_root.header.allocated_size = header_length + entries_size as u32;
         
         unsafe {
             let root_bytes = std::slice::from_raw_parts(
@@ -49,6 +49,7 @@ impl IndexWriter {
                     file_name: file_name.to_string(),
                     is_directory,
                     has_subnode: false,
+                    file_name_attr: None,
                 };
                 
                 // Try to add to INDEX_ROOT (small directories)
@@ -146,7 +147,8 @@ impl IndexWriter {
     
     /// Create a new INDEX_ROOT for an empty directory
     pub fn create_empty_index_root(&self) -> Vec<u8> {
-        let mut buffer = vec![0u8; 64]; // Minimum INDEX_ROOT size
+        // The root, then an end entry of just a header
+        let mut buffer = vec![0u8; std::mem::size_of::<IndexRoot>() + std::mem::size_of::<IndexEntryHeader>()];
         
         // Create IndexRoot structure
         let root = IndexRoot {
@@ -157,7 +159,7 @@ impl IndexWriter {
             reserved: [0; 3],
             header: IndexHeader {
                 entries_offset: 16, // After the IndexHeader
-                index_length: 24,    // Just the end entry
+                index_length: 32,    // Offsets are from the header: it and the end entry
                 allocated_size: 32,
                 flags: 0,
             },
@@ -173,11 +175,11 @@ impl IndexWriter {
         }
         
         // Add end entry
-        let entry_offset = std::mem::size_of::<IndexRoot>() + root.header.entries_offset as usize;
+        let entry_offset = std::mem::offset_of!(IndexRoot, header) + root.header.entries_offset as usize;
         if entry_offset + std::mem::size_of::<IndexEntryHeader>() <= buffer.len() {
             let end_entry = IndexEntryHeader {
                 mft_reference: 0,
-                length: 24,
+                length: std::mem::size_of::<IndexEntryHeader>() as u16,
                 key_length: 0,
                 flags: INDEX_ENTRY_END,
                 reserved: 0,
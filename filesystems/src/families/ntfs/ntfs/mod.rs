@@ -9,11 +9,13 @@ pub mod mft;
 pub mod mft_writer;
 pub mod mft_updater;
 pub mod resident_data_writer;
+pub mod attrib;
 pub mod attributes;
 pub mod data_runs;
 pub mod index;
 pub mod index_writer;
 pub mod index_updater;
+pub mod upcase;
 pub mod path_resolver;
 pub mod resident_converter;
 // pub mod directory_creator;  // TODO: Fix lifetime issue
@@ -22,6 +24,8 @@ pub mod compression;
 pub mod sparse;
 pub mod attribute_list;
 pub mod reparse;
+pub mod security;
+pub mod shadow;
 pub mod reader;
 pub mod writer;
 pub mod writer_ops;
@@ -40,6 +44,7 @@ pub use writer::{NtfsWriter, NtfsWriteConfig};
 pub use formatter::NtfsFormatter;
 pub use ops::NtfsOps;
 pub use ops_rw_v2::NtfsRwOps;
+pub use shadow::{list_device_shadow_copies, ShadowCopy, ShadowVolume};
 pub use structures::*;
 pub use journaled_writer::{JournaledNtfsWriter, JournalingConfig};
 pub use logfile::{LogFileConfig, LogFileWriter, LogFileReader, LogFileRecovery};
\ No newline at end of file
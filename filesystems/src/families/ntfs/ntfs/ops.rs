@@ -1,16 +1,18 @@
 // NTFS FilesystemOps implementation for mounting
-use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo};
-use crate::device_reader::FilesystemReader;
+use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo, AttributeChanges};
+use crate::device_reader::{FileEntry, FilesystemReader};
 use crate::ops_helpers::convert_filesystem_info;
 use super::reader::NtfsReader;
 use moses_core::{Device, MosesError};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
 /// NTFS filesystem operations wrapper
 pub struct NtfsOps {
     reader: Mutex<Option<NtfsReader>>,
     device: Option<Device>,
+    /// Shadow copy to present instead of the live volume
+    snapshot: Option<String>,
 }
 
 impl NtfsOps {
@@ -18,6 +20,15 @@ impl NtfsOps {
         NtfsOps {
             reader: Mutex::new(None),
             device: None,
+            snapshot: None,
+        }
+    }
+    
+    /// Ops over a shadow copy of the volume, by GUID or index
+    pub fn snapshot(snapshot_id: impl Into<String>) -> Self {
+        NtfsOps {
+            snapshot: Some(snapshot_id.into()),
+            ..Self::new()
         }
     }
 }
@@ -28,12 +39,27 @@ impl FilesystemOps for NtfsOps {
     }
     
     fn init(&mut self, device: &Device) -> Result<(), MosesError> {
-        let reader = NtfsReader::new(device.clone())?;
+        let reader = match &self.snapshot {
+            Some(snapshot_id) => NtfsReader::open_snapshot(device.clone(), snapshot_id)?,
+            None => NtfsReader::new(device.clone())?,
+        };
         *self.reader.lock().unwrap() = Some(reader);
         self.device = Some(device.clone());
         Ok(())
     }
     
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        if self.snapshot.is_some() {
+            return Err(MosesError::NotSupported("Shadow copies are read-only".to_string()));
+        }
+        let device = self.device.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        crate::attributes::set_attributes(device, "ntfs", path, changes)?;
+        // The reader caches MFT records, which now hold the old attributes
+        *self.reader.lock().unwrap() = Some(NtfsReader::new(device.clone())?);
+        Ok(())
+    }
+    
     fn statfs(&self) -> Result<OpsFilesystemInfo, MosesError> {
         let reader = self.reader.lock().unwrap();
         let reader = reader.as_ref()
@@ -81,18 +107,7 @@ impl FilesystemOps for NtfsOps {
             .find(|e| e.name == file_name)
             .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path_str)))?;
         
-        Ok(FileAttributes {
-            size: entry.size,
-            is_directory: entry.is_directory,
-            is_file: !entry.is_directory,
-            is_symlink: false,
-            created: entry.metadata.created,
-            modified: entry.metadata.modified,
-            accessed: entry.metadata.accessed,
-            permissions: if entry.is_directory { 0o755 } else { 0o644 },
-            owner: None,
-            group: None,
-        })
+        Ok(entry_attributes(entry))
     }
     
     fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
@@ -105,20 +120,9 @@ impl FilesystemOps for NtfsOps {
         
         let entries = reader.list_directory(path_str)?;
         
-        Ok(entries.into_iter().map(|e| DirectoryEntry {
+        Ok(entries.iter().map(|e| DirectoryEntry {
             name: e.name.clone(),
-            attributes: FileAttributes {
-                size: e.size,
-                is_directory: e.is_directory,
-                is_file: !e.is_directory,
-                is_symlink: false,
-                created: e.metadata.created,
-                modified: e.metadata.modified,
-                accessed: e.metadata.accessed,
-                permissions: if e.is_directory { 0o755 } else { 0o644 },
-                owner: None,
-                group: None,
-            },
+            attributes: entry_attributes(e),
         }).collect())
     }
     
@@ -142,4 +146,85 @@ impl FilesystemOps for NtfsOps {
         let end = std::cmp::min(start + size as usize, data.len());
         Ok(data[start..end].to_vec())
     }
-}
\ No newline at end of file
+    
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        let path_str = path.to_str()
+            .ok_or_else(|| MosesError::Other("Invalid path".to_string()))?;
+        
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        
+        read_link(reader, path_str)
+    }
+}
+
+/// Target of the link at `path`, shared with the read-write ops
+pub(super) fn read_link(reader: &mut NtfsReader, path: &str) -> Result<PathBuf, MosesError> {
+    // Entries only live in the root until subdirectory navigation exists
+    let entries = reader.list_directory("/")?;
+    let file_name = path.trim_start_matches('/');
+    let entry = entries.iter()
+        .find(|e| e.name == file_name)
+        .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))?;
+    
+    entry.metadata.link_target.as_deref()
+        .map(link_path)
+        .ok_or_else(|| MosesError::InvalidInput(format!("{} is not a link", path)))
+}
+
+/// Ops attributes for a listing entry. Junctions, volume mount points and symbolic links
+/// are reported as symlinks (not directories) so the mount layer can expose or follow them.
+pub(super) fn entry_attributes(entry: &FileEntry) -> FileAttributes {
+    let link_target = entry.metadata.link_target.as_ref();
+    let is_symlink = link_target.is_some();
+    FileAttributes {
+        size: link_target.map_or(entry.size, |target| target.len() as u64),
+        is_directory: entry.is_directory && !is_symlink,
+        is_file: !entry.is_directory && !is_symlink,
+        is_symlink,
+        created: entry.metadata.created,
+        modified: entry.metadata.modified,
+        accessed: entry.metadata.accessed,
+        permissions: if is_symlink { 0o777 } else if entry.is_directory { 0o755 } else { 0o644 },
+        owner: None,
+        group: None,
+    }
+}
+
+/// A link target as `readlink` returns it. Relative targets get '/' separators so they
+/// resolve from the link's directory; absolute ones name a drive or volume the mount
+/// cannot know about, so they stay as Windows stores them.
+pub(super) fn link_path(target: &str) -> PathBuf {
+    if target.starts_with('\\') || target.contains(':') {
+        PathBuf::from(target)
+    } else {
+        PathBuf::from(target.replace('\\', "/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_reader::FileMetadata;
+
+    #[test]
+    fn test_junction_reported_as_symlink() {
+        let junction = FileEntry {
+            name: "Documents".to_string(),
+            is_directory: true,
+            size: 0,
+            cluster: Some(64),
+            metadata: FileMetadata {
+                reparse_point: Some("junction".to_string()),
+                link_target: Some("C:\\Users\\me\\Documents".to_string()),
+                ..Default::default()
+            },
+        };
+        let attrs = entry_attributes(&junction);
+        assert!(attrs.is_symlink && !attrs.is_directory && !attrs.is_file);
+
+        assert_eq!(link_path("C:\\Users\\me\\Documents"), PathBuf::from("C:\\Users\\me\\Documents"));
+        assert_eq!(link_path("..\\shared\\notes.txt"), PathBuf::from("../shared/notes.txt"));
+    }
+}
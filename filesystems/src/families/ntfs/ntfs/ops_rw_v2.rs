@@ -1,16 +1,17 @@
 // NTFS Read-Write FilesystemOps implementation
 // This version includes write support using NtfsWriter with high-level operations
 
-use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo};
+use crate::ops::{FilesystemOps, FileAttributes, DirectoryEntry, FilesystemInfo as OpsFilesystemInfo, AttributeChanges};
 use crate::device_reader::FilesystemReader;
 use crate::ops_helpers::convert_filesystem_info;
 use super::reader::NtfsReader;
+use super::ops::{entry_attributes, read_link};
 use super::writer::{NtfsWriter, NtfsWriteConfig};
 use super::path_resolver::PathResolver;
 use super::journaled_writer::{JournaledNtfsWriter, JournalingConfig};
 use super::structures::MFT_RECORD_ROOT;
 use moses_core::{Device, MosesError};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::collections::HashMap;
 use log::{info, debug};
@@ -176,18 +177,7 @@ impl FilesystemOps for NtfsRwOps {
             .find(|e| e.name == file_name)
             .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path_str)))?;
         
-        Ok(FileAttributes {
-            size: entry.size,
-            is_directory: entry.is_directory,
-            is_file: !entry.is_directory,
-            is_symlink: false,
-            created: entry.metadata.created,
-            modified: entry.metadata.modified,
-            accessed: entry.metadata.accessed,
-            permissions: if entry.is_directory { 0o755 } else { 0o644 },
-            owner: None,
-            group: None,
-        })
+        Ok(entry_attributes(entry))
     }
     
     fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
@@ -200,20 +190,9 @@ impl FilesystemOps for NtfsRwOps {
         
         let entries = reader.list_directory(path_str)?;
         
-        Ok(entries.into_iter().map(|e| DirectoryEntry {
+        Ok(entries.iter().map(|e| DirectoryEntry {
             name: e.name.clone(),
-            attributes: FileAttributes {
-                size: e.size,
-                is_directory: e.is_directory,
-                is_file: !e.is_directory,
-                is_symlink: false,
-                created: e.metadata.created,
-                modified: e.metadata.modified,
-                accessed: e.metadata.accessed,
-                permissions: if e.is_directory { 0o755 } else { 0o644 },
-                owner: None,
-                group: None,
-            },
+            attributes: entry_attributes(e),
         }).collect())
     }
     
@@ -238,6 +217,17 @@ impl FilesystemOps for NtfsRwOps {
         Ok(data[start..end].to_vec())
     }
     
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        let path_str = path.to_str()
+            .ok_or_else(|| MosesError::Other("Invalid path".to_string()))?;
+        
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        
+        read_link(reader, path_str)
+    }
+    
     // Write operations
     fn write(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<u32, MosesError> {
         if !self.write_enabled {
@@ -351,6 +341,15 @@ impl FilesystemOps for NtfsRwOps {
         Ok(())
     }
     
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let device = self.device.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        crate::attributes::set_attributes(device, "ntfs", path, changes)?;
+        // The reader caches MFT records, which now hold the old attributes
+        *self.reader.lock().unwrap() = Some(NtfsReader::new(device.clone())?);
+        Ok(())
+    }
+    
     fn sync(&mut self) -> Result<(), MosesError> {
         if let Some(writer) = self.writer.lock().unwrap().as_mut() {
             // If we had pending changes, we'd flush them here
@@ -8,6 +8,9 @@ use crate::families::ntfs::ntfs::mft::{MftReader, MftRecord};
 use crate::families::ntfs::ntfs::structures::*;
 use crate::families::ntfs::ntfs::attributes::AttributeData;
 use crate::families::ntfs::ntfs::data_runs::DataRun;
+use crate::families::ntfs::ntfs::reparse::{parse_reparse_point, ReparsePoint};
+use crate::families::ntfs::ntfs::security::{parse_sds, SecurityDescriptor};
+use crate::families::ntfs::ntfs::shadow::ShadowVolume;
 use log::{info, debug, trace};
 use std::collections::HashMap;
 
@@ -22,17 +25,19 @@ pub struct NtfsReader {
     mft_cache: HashMap<u64, MftRecord>,
     // Cache for MFT's own data runs (to read other MFT records)
     mft_data_runs: Option<Vec<DataRun>>,
+    // Security descriptors from $Secure:$SDS by security_id, read on first use
+    security_descriptors: Option<HashMap<u32, SecurityDescriptor>>,
+    // $Volume's VOLUME_NAME, read at open
+    volume_label: Option<String>,
 }
 
+/// Records below this are the system files, which Windows never lists
+const FIRST_USER_RECORD: u64 = 16;
+
 impl NtfsReader {
     pub fn new(device: Device) -> Result<Self, MosesError> {
         info!("Opening NTFS filesystem on device: {}", device.name);
         
-        // Phase 1.1 - Read and parse boot sector
-        let boot_reader = NtfsBootSectorReader::new(device.clone())?;
-        let boot_sector = *boot_reader.boot_sector();
-        boot_reader.sanity_check()?;
-        
         // Open device for reading (we'll open it twice - once for general reading, once for MFT)
         use crate::utils::open_device_with_fallback;
         let file = open_device_with_fallback(&device)?;
@@ -42,6 +47,32 @@ impl NtfsReader {
         let mft_file = open_device_with_fallback(&device)?;
         let mft_device_reader = AlignedDeviceReader::new(mft_file);
         
+        Self::from_readers(device, reader, mft_device_reader)
+    }
+    
+    /// Open the volume as it was when a shadow copy was taken (GUID or index, see
+    /// `ShadowCopy::matches`). Everything read comes from the snapshot, so the result is
+    /// read-only by nature.
+    pub fn open_snapshot(device: Device, snapshot_id: &str) -> Result<Self, MosesError> {
+        info!("Opening shadow copy {} of NTFS volume {}", snapshot_id, device.name);
+        
+        use crate::utils::open_device_with_fallback;
+        let snapshot = ShadowVolume::open(open_device_with_fallback(&device)?, snapshot_id)?;
+        let mft_snapshot = snapshot.try_clone()?;
+        
+        Self::from_readers(
+            device,
+            AlignedDeviceReader::from_source(Box::new(snapshot)),
+            AlignedDeviceReader::from_source(Box::new(mft_snapshot)),
+        )
+    }
+    
+    fn from_readers(device: Device, mut reader: AlignedDeviceReader, mft_device_reader: AlignedDeviceReader) -> Result<Self, MosesError> {
+        // Phase 1.1 - Read and parse boot sector
+        let boot_reader = NtfsBootSectorReader::from_reader(device.clone(), &mut reader)?;
+        let boot_sector = *boot_reader.boot_sector();
+        boot_reader.sanity_check()?;
+        
         // Phase 1.2 - Initialize MFT reader
         let mft_offset = boot_reader.mft_offset();
         let mft_record_size = boot_sector.mft_record_size();
@@ -61,10 +92,13 @@ impl NtfsReader {
             bytes_per_cluster,
             mft_cache: HashMap::new(),
             mft_data_runs: None,
+            security_descriptors: None,
+            volume_label: None,
         };
         
         // Phase 1.3 - Read MFT record 0 (the MFT itself)
         ntfs_reader.initialize_mft()?;
+        ntfs_reader.volume_label = ntfs_reader.read_volume_label();
         
         Ok(ntfs_reader)
     }
@@ -100,6 +134,16 @@ impl NtfsReader {
         Ok(())
     }
     
+    /// The label in $Volume's VOLUME_NAME; a volume without one has an empty value
+    fn read_volume_label(&mut self) -> Option<String> {
+        let mut volume = self.read_mft_record(MFT_RECORD_VOLUME)
+            .map_err(|e| debug!("Failed to read $Volume: {}", e))
+            .ok()?;
+        let AttributeData::Unknown(name) = volume.find_attribute(ATTR_TYPE_VOLUME_NAME)? else { return None };
+        let units: Vec<u16> = name.as_chunks::<2>().0.iter().map(|unit| u16::from_le_bytes(*unit)).collect();
+        Some(String::from_utf16_lossy(&units)).filter(|label| !label.is_empty())
+    }
+    
     /// Read an MFT record by number
     pub fn read_mft_record(&mut self, record_num: u64) -> Result<MftRecord, MosesError> {
         // Check cache first
@@ -122,6 +166,25 @@ impl NtfsReader {
         Ok(record)
     }
     
+    /// Byte offset and size of an MFT record on the volume, for in-place edits
+    pub fn mft_record_location(&self, record_num: u64) -> Result<(u64, usize), MosesError> {
+        let record_size = self.boot_sector.mft_record_size() as u64;
+        let cluster_size = self.bytes_per_cluster as u64;
+        let Some(runs) = &self.mft_data_runs else {
+            return Ok((self.boot_sector.mft_lcn * cluster_size + record_num * record_size, record_size as usize));
+        };
+        let mut position = record_num * record_size;
+        for run in runs {
+            let length = run.length * cluster_size;
+            if position < length {
+                let lcn = run.lcn.ok_or_else(|| MosesError::Other(format!("MFT record {} is in a sparse run", record_num)))?;
+                return Ok((lcn * cluster_size + position, record_size as usize));
+            }
+            position -= length;
+        }
+        Err(MosesError::Other(format!("MFT record {} is beyond the end of the MFT", record_num)))
+    }
+    
     /// Read data from cluster chains
     fn read_clusters(&mut self, runs: &[DataRun]) -> Result<Vec<u8>, MosesError> {
         let mut data = Vec::new();
@@ -145,6 +208,65 @@ impl NtfsReader {
         Ok(data)
     }
 
+    /// Parse the $REPARSE_POINT attribute of an MFT record, None if it has none
+    pub fn read_reparse_point(&mut self, record_num: u64) -> Result<Option<ReparsePoint>, MosesError> {
+        let mut record = self.read_mft_record(record_num)?;
+        let data = match record.find_attribute(ATTR_TYPE_REPARSE_POINT) {
+            Some(AttributeData::Unknown(data)) => data.clone(),
+            Some(AttributeData::DataRuns(runs)) => {
+                let runs = runs.clone();
+                self.read_clusters(&runs)?
+            }
+            _ => return Ok(None),
+        };
+        parse_reparse_point(&data).map(Some)
+    }
+
+    /// Security descriptor of an MFT record: its own $SECURITY_DESCRIPTOR on pre-3.0
+    /// volumes, otherwise the $Secure entry its STANDARD_INFORMATION refers to
+    pub fn read_security(&mut self, record_num: u64) -> Result<Option<SecurityDescriptor>, MosesError> {
+        let mut record = self.read_mft_record(record_num)?;
+        let own = match record.find_attribute(ATTR_TYPE_SECURITY_DESCRIPTOR) {
+            Some(AttributeData::Unknown(data)) => Some(data.clone()),
+            Some(AttributeData::DataRuns(runs)) => {
+                let runs = runs.clone();
+                Some(self.read_clusters(&runs)?)
+            }
+            _ => None,
+        };
+        if let Some(data) = own {
+            return SecurityDescriptor::parse(&data).map(Some);
+        }
+        
+        let security_id = match record.find_attribute(ATTR_TYPE_STANDARD_INFORMATION) {
+            Some(AttributeData::StandardInformation(info)) => info.security_id,
+            _ => return Ok(None),
+        };
+        if security_id == 0 {
+            return Ok(None);
+        }
+        if self.security_descriptors.is_none() {
+            let descriptors = self.read_secure_stream()?;
+            debug!("Loaded {} security descriptors from $Secure", descriptors.len());
+            self.security_descriptors = Some(descriptors);
+        }
+        Ok(self.security_descriptors.as_ref().and_then(|descriptors| descriptors.get(&security_id).cloned()))
+    }
+    
+    /// Parse $Secure:$SDS, the only data stream of $Secure
+    fn read_secure_stream(&mut self) -> Result<HashMap<u32, SecurityDescriptor>, MosesError> {
+        let mut record = self.read_mft_record(MFT_RECORD_SECURE)?;
+        let data = match record.find_attribute(ATTR_TYPE_DATA) {
+            Some(AttributeData::Data(data)) => data.clone(),
+            Some(AttributeData::DataRuns(runs)) => {
+                let runs = runs.clone();
+                self.read_clusters(&runs)?
+            }
+            _ => return Ok(HashMap::new()),
+        };
+        Ok(parse_sds(&data))
+    }
+    
     /// Public method to list directory contents
     pub fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
         <Self as FilesystemReader>::list_directory(self, path)
@@ -190,18 +312,12 @@ impl FilesystemReader for NtfsReader {
                 match crate::families::ntfs::ntfs::index::parse_index_root(&data) {
                     Ok(index_entries) => {
                         for entry in index_entries {
-                            // Skip . and .. entries
-                            if entry.file_name == "." || entry.file_name == ".." {
+                            // Skip . and .. entries and the system files
+                            if entry.file_name == "." || entry.file_name == ".." || entry.mft_reference < FIRST_USER_RECORD {
                                 continue;
                             }
                             
-                            entries.push(FileEntry {
-                                name: entry.file_name,
-                                is_directory: entry.is_directory,
-                                size: 0, // Would need to read the MFT record for size
-                                cluster: Some(entry.mft_reference as u32),
-                                metadata: FileMetadata::default(),
-                            });
+                            entries.push(index_file_entry(entry));
                         }
                     }
                     Err(e) => {
@@ -227,20 +343,14 @@ impl FilesystemReader for NtfsReader {
                     match crate::families::ntfs::ntfs::index::parse_index_allocation(&index_data, index_block_size) {
                         Ok(index_entries) => {
                             for entry in index_entries {
-                                // Skip . and .. entries
-                                if entry.file_name == "." || entry.file_name == ".." {
+                                // Skip . and .. entries and the system files
+                                if entry.file_name == "." || entry.file_name == ".." || entry.mft_reference < FIRST_USER_RECORD {
                                     continue;
                                 }
                                 
                                 // Avoid duplicates
                                 if !entries.iter().any(|e| e.name == entry.file_name) {
-                                    entries.push(FileEntry {
-                                        name: entry.file_name,
-                                        is_directory: entry.is_directory,
-                                        size: 0, // Would need to read the MFT record for size
-                                        cluster: Some(entry.mft_reference as u32),
-                                        metadata: FileMetadata::default(),
-                                    });
+                                    entries.push(index_file_entry(entry));
                                 }
                             }
                         }
@@ -253,24 +363,31 @@ impl FilesystemReader for NtfsReader {
             }
         }
         
-        // If we didn't find any entries through indexes, fall back to the basic approach
-        if entries.is_empty() && (path == "/" || path.is_empty()) {
-            // Add some known system files that should exist
-            entries.push(FileEntry {
-                name: "$MFT".to_string(),
-                is_directory: false,
-                size: 0,
-                cluster: Some(0),
-                metadata: FileMetadata::default(),
-            });
-            
-            entries.push(FileEntry {
-                name: "$Volume".to_string(),
-                is_directory: false,
-                size: 0,
-                cluster: Some(3),
-                metadata: FileMetadata::default(),
-            });
+        // The index only flags reparse points; their kind and target live in each file's
+        // own $REPARSE_POINT attribute
+        for entry in entries.iter_mut().filter(|e| e.metadata.reparse_point.is_some()) {
+            let Some(record_num) = entry.cluster else { continue };
+            match self.read_reparse_point(record_num as u64) {
+                Ok(Some(point)) => {
+                    entry.metadata.reparse_point = Some(point.kind().to_string());
+                    entry.metadata.link_target = point.link_target();
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Failed to read reparse point of {}: {}", entry.name, e),
+            }
+        }
+        
+        // Ownership is not in the index either
+        for entry in entries.iter_mut() {
+            let Some(record_num) = entry.cluster else { continue };
+            match self.read_security(record_num as u64) {
+                Ok(Some(descriptor)) => {
+                    entry.metadata.owner = descriptor.owner_name();
+                    entry.metadata.access = descriptor.access_summary();
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Failed to read security descriptor of {}: {}", entry.name, e),
+            }
         }
         
         Ok(entries)
@@ -359,10 +476,53 @@ impl FilesystemReader for NtfsReader {
 
         FilesystemInfo {
             fs_type: "ntfs".to_string(),
-            label: None,
+            label: self.volume_label.clone(),
             total_bytes,
             used_bytes: 0,  // TODO: Calculate from $Bitmap
             cluster_size: Some(self.bytes_per_cluster),
         }
     }
 }
+
+/// Listing entry from a directory index, using the sizes, times and flags cached in
+/// its FILE_NAME key rather than reading every MFT record
+fn index_file_entry(entry: crate::families::ntfs::ntfs::index::IndexEntry) -> FileEntry {
+    const FILE_ATTRIBUTE_READONLY: u32 = 0x0001;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x0002;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x0004;
+    const FILE_ATTRIBUTE_SPARSE_FILE: u32 = 0x0200;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x0400;
+    const FILE_ATTRIBUTE_COMPRESSED: u32 = 0x0800;
+    const FILE_ATTRIBUTE_ENCRYPTED: u32 = 0x4000;
+
+    let (size, metadata) = match entry.file_name_attr {
+        Some(attr) => {
+            let flags = attr.file_attributes;
+            let timestamp = |filetime: u64| (filetime != 0).then(|| filetime_to_unix(filetime));
+            let size = if entry.is_directory { 0 } else { attr.data_size };
+            (size, FileMetadata {
+                compressed: flags & FILE_ATTRIBUTE_COMPRESSED != 0,
+                sparse: flags & FILE_ATTRIBUTE_SPARSE_FILE != 0,
+                reparse_point: (flags & FILE_ATTRIBUTE_REPARSE_POINT != 0).then(|| "reparse point".to_string()),
+                link_target: None,
+                allocated_size: (!entry.is_directory).then_some(attr.allocated_size),
+                created: timestamp(attr.creation_time),
+                modified: timestamp(attr.modification_time),
+                accessed: timestamp(attr.access_time),
+                readonly: flags & FILE_ATTRIBUTE_READONLY != 0,
+                hidden: flags & FILE_ATTRIBUTE_HIDDEN != 0,
+                system: flags & FILE_ATTRIBUTE_SYSTEM != 0,
+                encrypted: flags & FILE_ATTRIBUTE_ENCRYPTED != 0,
+                ..Default::default()
+            })
+        }
+        None => (0, FileMetadata::default()),
+    };
+    FileEntry {
+        name: entry.file_name,
+        is_directory: entry.is_directory,
+        size,
+        cluster: Some(entry.mft_reference as u32),
+        metadata,
+    }
+}
@@ -61,6 +61,37 @@ pub enum ReparsePoint {
     },
 }
 
+impl ReparsePoint {
+    /// Short description for listings
+    pub fn kind(&self) -> &'static str {
+        match self {
+            // Junctions and volume mount points share a tag; only the target tells them apart
+            Self::MountPoint { substitute_name, .. } if is_volume_name(substitute_name) => "volume mount point",
+            Self::MountPoint { .. } => "junction",
+            Self::SymbolicLink { .. } => "symbolic link",
+            Self::AppExecLink { .. } => "app execution alias",
+            Self::Unknown { .. } => "reparse point",
+        }
+    }
+
+    /// Where the link points, in Windows syntax: a drive path or volume name for junctions
+    /// and absolute symlinks, a path relative to the link's directory for relative symlinks.
+    /// None for reparse points that are not links.
+    pub fn link_target(&self) -> Option<String> {
+        match self {
+            Self::MountPoint { substitute_name, .. } => Some(resolve_substitute_name(substitute_name)),
+            Self::SymbolicLink { substitute_name, is_relative: true, .. } => Some(substitute_name.clone()),
+            Self::SymbolicLink { substitute_name, .. } => Some(resolve_substitute_name(substitute_name)),
+            Self::AppExecLink { .. } | Self::Unknown { .. } => None,
+        }
+    }
+}
+
+/// Whether a substitute name names a volume (`\??\Volume{GUID}\`) rather than a path
+fn is_volume_name(substitute_name: &str) -> bool {
+    resolve_substitute_name(substitute_name).starts_with("Volume{")
+}
+
 /// Check if a file has a reparse point
 pub fn is_reparse_point(file_attributes: u32) -> bool {
     const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
@@ -274,6 +305,49 @@ mod tests {
         );
     }
     
+    fn reparse_buffer(tag: u32, fields: &[u16], flags: Option<u32>, substitute: &str, print: &str) -> Vec<u8> {
+        let utf16 = |s: &str| s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect::<Vec<u8>>();
+        let mut body = Vec::new();
+        for field in fields {
+            body.extend_from_slice(&field.to_le_bytes());
+        }
+        if let Some(flags) = flags {
+            body.extend_from_slice(&flags.to_le_bytes());
+        }
+        body.extend(utf16(substitute));
+        body.extend(utf16(print));
+        let mut data = tag.to_le_bytes().to_vec();
+        data.extend_from_slice(&(body.len() as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn test_link_targets() {
+        let sub = "\\??\\D:\\Data";
+        let junction = reparse_buffer(
+            IO_REPARSE_TAG_MOUNT_POINT,
+            &[0, sub.len() as u16 * 2, sub.len() as u16 * 2, 14],
+            None, sub, "D:\\Data",
+        );
+        let point = parse_reparse_point(&junction).unwrap();
+        assert_eq!(point.kind(), "junction");
+        assert_eq!(point.link_target().as_deref(), Some("D:\\Data"));
+
+        let symlink = reparse_buffer(IO_REPARSE_TAG_SYMLINK, &[0, 16, 16, 16], Some(1), "..\\notes", "..\\notes");
+        let point = parse_reparse_point(&symlink).unwrap();
+        assert_eq!(point.kind(), "symbolic link");
+        assert_eq!(point.link_target().as_deref(), Some("..\\notes"));
+
+        let volume = ReparsePoint::MountPoint {
+            substitute_name: "\\??\\Volume{0b1c2d3e-0000-0000-0000-100000000000}\\".to_string(),
+            print_name: String::new(),
+        };
+        assert_eq!(volume.kind(), "volume mount point");
+        assert!(ReparsePoint::Unknown { tag: 0x9000001A, data: vec![] }.link_target().is_none());
+    }
+
     #[test]
     fn test_reparse_tag_values() {
         // Verify known tag values
@@ -0,0 +1,362 @@
+// NTFS Security Descriptor Support
+// NTFS 3.0+ stores each distinct security descriptor once, in the $SDS stream of $Secure
+// (MFT record 9), and files refer to it by the security_id in STANDARD_INFORMATION; older
+// volumes give every file its own $SECURITY_DESCRIPTOR attribute. Both hold a
+// self-relative SECURITY_DESCRIPTOR. Only well-known SIDs can be named: account names
+// live in the SAM or Active Directory of the machine that wrote the volume, which is
+// rarely at hand when recovering a disk.
+
+use moses_core::MosesError;
+use std::collections::HashMap;
+use std::fmt;
+
+/// $SDS is written in 256 KiB blocks, each followed by a mirror copy
+const SDS_BLOCK_SIZE: usize = 0x40000;
+/// hash, security_id, offset, length
+const SDS_ENTRY_HEADER_SIZE: usize = 20;
+
+// ACE types summarised; object and callback ACEs are counted as "other"
+const ACCESS_ALLOWED_ACE_TYPE: u8 = 0x00;
+const ACCESS_DENIED_ACE_TYPE: u8 = 0x01;
+const INHERITED_ACE: u8 = 0x10;
+
+/// Security descriptor control flag: a DACL is present (absent means no restrictions)
+const SE_DACL_PRESENT: u16 = 0x0004;
+
+/// Security identifier, e.g. S-1-5-32-544
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid {
+    pub revision: u8,
+    pub authority: u64,
+    pub sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    /// Parse a binary SID, returning it and its length in bytes
+    pub fn parse(data: &[u8]) -> Option<(Self, usize)> {
+        if data.len() < 8 {
+            return None;
+        }
+        let count = data[1] as usize;
+        let len = 8 + count * 4;
+        if data.len() < len {
+            return None;
+        }
+        // The identifier authority is big-endian, the sub-authorities little-endian
+        let authority = data[2..8].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let sub_authorities = data[8..len]
+            .as_chunks::<4>().0
+            .iter()
+            .map(|chunk| u32::from_le_bytes(*chunk))
+            .collect();
+        Some((Self { revision: data[0], authority, sub_authorities }, len))
+    }
+
+    /// Name of a well-known SID, or a description of an account SID
+    pub fn name(&self) -> Option<String> {
+        let well_known = match (self.authority, self.sub_authorities.as_slice()) {
+            (1, [0]) => "Everyone",
+            (3, [0]) => "CREATOR OWNER",
+            (3, [1]) => "CREATOR GROUP",
+            (5, [7]) => "ANONYMOUS LOGON",
+            (5, [11]) => "Authenticated Users",
+            (5, [18]) => "SYSTEM",
+            (5, [19]) => "LOCAL SERVICE",
+            (5, [20]) => "NETWORK SERVICE",
+            (5, [32, 544]) => "Administrators",
+            (5, [32, 545]) => "Users",
+            (5, [32, 546]) => "Guests",
+            (5, [32, 547]) => "Power Users",
+            (5, [32, 551]) => "Backup Operators",
+            (5, [80, 956008885, 3418522649, 1831038044, 1853292631, 2271478464]) => "TrustedInstaller",
+            (15, [2, 1]) => "ALL APPLICATION PACKAGES",
+            (16, [4096]) => "Low Mandatory Level",
+            (16, [8192]) => "Medium Mandatory Level",
+            (16, [12288]) => "High Mandatory Level",
+            // Accounts of a particular machine or domain: only the relative ID says anything
+            (5, [21, .., rid]) => {
+                return Some(match *rid {
+                    500 => "Administrator account".to_string(),
+                    501 => "Guest account".to_string(),
+                    512 => "Domain Admins".to_string(),
+                    513 => "Domain Users".to_string(),
+                    rid if rid >= 1000 => format!("user account {}", rid),
+                    _ => return None,
+                });
+            }
+            _ => return None,
+        };
+        Some(well_known.to_string())
+    }
+
+    /// "Name (S-1-...)" when the SID has a name, the bare SID otherwise
+    pub fn display_name(&self) -> String {
+        match self.name() {
+            Some(name) => format!("{} ({})", name, self),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "S-{}-{}", self.revision, self.authority)?;
+        for sub in &self.sub_authorities {
+            write!(f, "-{}", sub)?;
+        }
+        Ok(())
+    }
+}
+
+/// One allow or deny entry of a DACL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ace {
+    pub allow: bool,
+    pub trustee: Sid,
+    pub mask: u32,
+    pub inherited: bool,
+}
+
+/// Parsed self-relative security descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityDescriptor {
+    pub owner: Option<Sid>,
+    pub group: Option<Sid>,
+    /// Allow/deny entries; None when there is no DACL, which grants everyone full access
+    pub dacl: Option<Vec<Ace>>,
+    /// ACEs of other types (object, callback, ...) left out of `dacl`
+    pub other_aces: usize,
+}
+
+impl SecurityDescriptor {
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < 20 || data[0] != 1 {
+            return Err(MosesError::Other("Invalid security descriptor".to_string()));
+        }
+        let control = u16::from_le_bytes([data[2], data[3]]);
+        let offset = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize;
+        let sid_at = |at: usize| (at != 0).then(|| data.get(at..).and_then(Sid::parse)).flatten().map(|(sid, _)| sid);
+
+        let owner = sid_at(offset(4));
+        let group = sid_at(offset(8));
+        let dacl_offset = offset(16);
+        let (dacl, other_aces) = if control & SE_DACL_PRESENT != 0 && dacl_offset != 0 {
+            let (aces, other) = parse_acl(data.get(dacl_offset..).unwrap_or_default())?;
+            (Some(aces), other)
+        } else {
+            (None, 0)
+        };
+        Ok(Self { owner, group, dacl, other_aces })
+    }
+
+    /// Owner for display, e.g. "Administrators (S-1-5-32-544)"
+    pub fn owner_name(&self) -> Option<String> {
+        self.owner.as_ref().map(Sid::display_name)
+    }
+
+    /// One line per ACE, e.g. "Allow Users: Read & execute (inherited)"
+    pub fn access_summary(&self) -> Vec<String> {
+        let Some(dacl) = &self.dacl else {
+            return vec!["Everyone: Full control (no access list)".to_string()];
+        };
+        if dacl.is_empty() && self.other_aces == 0 {
+            return vec!["No access granted (empty access list)".to_string()];
+        }
+        let mut lines: Vec<String> = dacl.iter().map(|ace| {
+            let trustee = ace.trustee.name().unwrap_or_else(|| ace.trustee.to_string());
+            format!(
+                "{} {}: {}{}",
+                if ace.allow { "Allow" } else { "Deny" },
+                trustee,
+                describe_mask(ace.mask),
+                if ace.inherited { " (inherited)" } else { "" },
+            )
+        }).collect();
+        if self.other_aces > 0 {
+            lines.push(format!("{} other entries (object or conditional)", self.other_aces));
+        }
+        lines
+    }
+}
+
+/// Parse an ACL into its allow/deny ACEs and a count of the rest
+fn parse_acl(data: &[u8]) -> Result<(Vec<Ace>, usize), MosesError> {
+    if data.len() < 8 {
+        return Err(MosesError::Other("Access control list too small".to_string()));
+    }
+    let ace_count = u16::from_le_bytes([data[4], data[5]]) as usize;
+    let mut aces = Vec::new();
+    let mut other = 0;
+    let mut pos = 8;
+    for _ in 0..ace_count {
+        let Some(header) = data.get(pos..pos + 4) else { break };
+        let (ace_type, flags) = (header[0], header[1]);
+        let size = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if size < 8 || pos + size > data.len() {
+            break;
+        }
+        let ace = &data[pos..pos + size];
+        match ace_type {
+            ACCESS_ALLOWED_ACE_TYPE | ACCESS_DENIED_ACE_TYPE => {
+                let mask = u32::from_le_bytes([ace[4], ace[5], ace[6], ace[7]]);
+                if let Some((trustee, _)) = Sid::parse(&ace[8..]) {
+                    aces.push(Ace {
+                        allow: ace_type == ACCESS_ALLOWED_ACE_TYPE,
+                        trustee,
+                        mask,
+                        inherited: flags & INHERITED_ACE != 0,
+                    });
+                }
+            }
+            _ => other += 1,
+        }
+        pos += size;
+    }
+    Ok((aces, other))
+}
+
+/// The permission names Explorer shows for the common access masks
+fn describe_mask(mask: u32) -> String {
+    const GENERIC_ALL: u32 = 0x1000_0000;
+    const GENERIC_EXECUTE: u32 = 0x2000_0000;
+    const GENERIC_WRITE: u32 = 0x4000_0000;
+    const GENERIC_READ: u32 = 0x8000_0000;
+    match mask {
+        0x001F_01FF => "Full control".to_string(),
+        0x0013_01BF => "Modify".to_string(),
+        0x0012_00A9 => "Read & execute".to_string(),
+        0x0012_0089 => "Read".to_string(),
+        0x0010_0116 => "Write".to_string(),
+        _ if mask & GENERIC_ALL != 0 => "Full control".to_string(),
+        _ if mask & (GENERIC_READ | GENERIC_WRITE | GENERIC_EXECUTE) != 0 => {
+            let generic: Vec<&str> = [(GENERIC_READ, "read"), (GENERIC_WRITE, "write"), (GENERIC_EXECUTE, "execute")]
+                .iter()
+                .filter(|(bit, _)| mask & bit != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            format!("Generic {}", generic.join(", "))
+        }
+        _ => format!("Special (0x{:08X})", mask),
+    }
+}
+
+/// Index the descriptors in a $Secure:$SDS stream by security_id
+pub fn parse_sds(data: &[u8]) -> HashMap<u32, SecurityDescriptor> {
+    let mut descriptors = HashMap::new();
+    let mut pos = 0;
+    while pos + SDS_ENTRY_HEADER_SIZE <= data.len() {
+        let field = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let security_id = field(pos + 4);
+        let offset = u64::from_le_bytes(data[pos + 8..pos + 16].try_into().unwrap());
+        let length = field(pos + 16) as usize;
+
+        // Entries record their own offset; anything else is padding or a mirror block
+        if security_id == 0 || offset != pos as u64 || length < SDS_ENTRY_HEADER_SIZE || pos + length > data.len() {
+            pos = (pos / SDS_BLOCK_SIZE + 1) * SDS_BLOCK_SIZE;
+            continue;
+        }
+        match SecurityDescriptor::parse(&data[pos + SDS_ENTRY_HEADER_SIZE..pos + length]) {
+            Ok(descriptor) => {
+                descriptors.insert(security_id, descriptor);
+            }
+            Err(e) => log::debug!("Skipping security descriptor {}: {}", security_id, e),
+        }
+        pos = (pos + length).div_ceil(16) * 16;
+    }
+    descriptors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sid_bytes(authority: u8, subs: &[u32]) -> Vec<u8> {
+        let mut data = vec![1, subs.len() as u8, 0, 0, 0, 0, 0, authority];
+        for sub in subs {
+            data.extend_from_slice(&sub.to_le_bytes());
+        }
+        data
+    }
+
+    fn ace_bytes(ace_type: u8, flags: u8, mask: u32, sid: &[u8]) -> Vec<u8> {
+        let mut data = vec![ace_type, flags];
+        data.extend_from_slice(&(8 + sid.len() as u16).to_le_bytes());
+        data.extend_from_slice(&mask.to_le_bytes());
+        data.extend_from_slice(sid);
+        data
+    }
+
+    /// Owner Administrators; Administrators full control, inherited Users read & execute
+    fn descriptor_bytes() -> Vec<u8> {
+        let owner = sid_bytes(5, &[32, 544]);
+        let aces = [
+            ace_bytes(ACCESS_ALLOWED_ACE_TYPE, 0, 0x001F_01FF, &owner),
+            ace_bytes(ACCESS_ALLOWED_ACE_TYPE, INHERITED_ACE, 0x0012_00A9, &sid_bytes(5, &[32, 545])),
+        ].concat();
+        let mut acl = vec![2, 0];
+        acl.extend_from_slice(&(8 + aces.len() as u16).to_le_bytes());
+        acl.extend_from_slice(&2u16.to_le_bytes());
+        acl.extend_from_slice(&[0, 0]);
+        acl.extend(aces);
+
+        let owner_offset = 20u32;
+        let dacl_offset = owner_offset + owner.len() as u32;
+        let mut data = vec![1, 0];
+        data.extend_from_slice(&(SE_DACL_PRESENT | 0x8000).to_le_bytes());
+        for offset in [owner_offset, 0, 0, dacl_offset] {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend(owner);
+        data.extend(acl);
+        data
+    }
+
+    #[test]
+    fn test_sid_names() {
+        let (sid, len) = Sid::parse(&sid_bytes(5, &[32, 544])).unwrap();
+        assert_eq!(len, 16);
+        assert_eq!(sid.to_string(), "S-1-5-32-544");
+        assert_eq!(sid.display_name(), "Administrators (S-1-5-32-544)");
+
+        let (user, _) = Sid::parse(&sid_bytes(5, &[21, 1004336348, 1177238915, 682003330, 1001])).unwrap();
+        assert_eq!(user.name().as_deref(), Some("user account 1001"));
+        let (unknown, _) = Sid::parse(&sid_bytes(9, &[7])).unwrap();
+        assert_eq!(unknown.display_name(), "S-1-9-7");
+    }
+
+    #[test]
+    fn test_descriptor_summary() {
+        let descriptor = SecurityDescriptor::parse(&descriptor_bytes()).unwrap();
+        assert_eq!(descriptor.owner_name().as_deref(), Some("Administrators (S-1-5-32-544)"));
+        assert_eq!(descriptor.access_summary(), [
+            "Allow Administrators: Full control",
+            "Allow Users: Read & execute (inherited)",
+        ]);
+
+        let open = SecurityDescriptor { owner: None, group: None, dacl: None, other_aces: 0 };
+        assert_eq!(open.access_summary(), ["Everyone: Full control (no access list)"]);
+    }
+
+    #[test]
+    fn test_sds_index() {
+        let descriptor = descriptor_bytes();
+        let mut sds = Vec::new();
+        for security_id in [0x100u32, 0x101] {
+            let offset = sds.len() as u64;
+            sds.extend_from_slice(&0xDEADBEEFu32.to_le_bytes());
+            sds.extend_from_slice(&security_id.to_le_bytes());
+            sds.extend_from_slice(&offset.to_le_bytes());
+            sds.extend_from_slice(&((SDS_ENTRY_HEADER_SIZE + descriptor.len()) as u32).to_le_bytes());
+            sds.extend_from_slice(&descriptor);
+            sds.resize(sds.len().div_ceil(16) * 16, 0);
+        }
+        // The mirror of the first block must not be mistaken for entries
+        sds.resize(SDS_BLOCK_SIZE, 0);
+        let mirror = sds[..64].to_vec();
+        sds.extend(mirror);
+
+        let index = parse_sds(&sds);
+        assert_eq!(index.len(), 2);
+        assert!(index[&0x101].owner.is_some());
+    }
+}
@@ -0,0 +1,459 @@
+// NTFS Volume Shadow Copy (VSS) support
+// Windows keeps previous versions of a volume as copy-on-write stores inside the volume
+// itself: before a block is overwritten, its old contents are copied into the store of the
+// newest snapshot. A volume header at 0x1E00 points to a catalog of stores, and each store
+// has a block list mapping original volume offsets to the saved copies. A snapshot is read
+// by looking each 16 KiB block up in its own store, then in every newer store, and finally
+// falling back to the live volume. Store bitmaps are not consulted, so blocks that were
+// free when the snapshot was taken read as whatever they hold now.
+
+use moses_core::{Device, MosesError};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// {3808876b-c176-4e48-b7ae-04046e6cc752}, as stored on disk
+const VSS_IDENTIFIER: [u8; 16] = [
+    0x6B, 0x87, 0x08, 0x38, 0x76, 0xC1, 0x48, 0x4E, 0xB7, 0xAE, 0x04, 0x04, 0x6E, 0x6C, 0xC7, 0x52,
+];
+const VOLUME_HEADER_OFFSET: u64 = 0x1E00;
+/// Catalogs, block lists and copied data all come in blocks of this size
+const BLOCK_SIZE: u64 = 0x4000;
+const BLOCK_HEADER_SIZE: usize = 128;
+const SECTOR_SIZE: usize = 512;
+
+const RECORD_TYPE_VOLUME_HEADER: u32 = 1;
+const RECORD_TYPE_CATALOG: u32 = 2;
+const RECORD_TYPE_BLOCK_LIST: u32 = 3;
+const RECORD_TYPE_STORE_HEADER: u32 = 4;
+
+const CATALOG_ENTRY_SIZE: usize = 128;
+const CATALOG_ENTRY_STORE_INFO: u64 = 2;
+const CATALOG_ENTRY_STORE_LOCATION: u64 = 3;
+
+const BLOCK_LIST_ENTRY_SIZE: usize = 32;
+const BLOCK_FLAG_FORWARDER: u32 = 0x01;
+const BLOCK_FLAG_OVERLAY: u32 = 0x02;
+const BLOCK_FLAG_NOT_USED: u32 = 0x04;
+
+/// Catalogs and block lists are linked lists on disk; a corrupt one must not loop forever
+const MAX_CHAIN_BLOCKS: usize = 1 << 20;
+
+/// One previous version of a volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowCopy {
+    /// Position on the volume, 1 for the oldest snapshot
+    pub index: usize,
+    /// Shadow copy GUID, as vssadmin shows it
+    pub id: String,
+    /// Shadow copy set GUID, shared by snapshots of several volumes taken together
+    pub set_id: String,
+    /// Creation time, Unix seconds
+    pub created: Option<u64>,
+    pub volume_size: u64,
+    /// Machine that took the snapshot
+    pub machine: Option<String>,
+    block_list_offset: u64,
+}
+
+impl ShadowCopy {
+    /// Whether `id` names this snapshot: its GUID (braces optional) or its index
+    pub fn matches(&self, id: &str) -> bool {
+        let id = id.trim().trim_start_matches('{').trim_end_matches('}');
+        id.eq_ignore_ascii_case(&self.id) || id.parse::<usize>() == Ok(self.index)
+    }
+}
+
+/// Shadow copies on an NTFS volume, oldest first
+pub fn list_device_shadow_copies(device: &Device) -> Result<Vec<ShadowCopy>, MosesError> {
+    let mut volume = crate::utils::open_device_with_fallback(device)?;
+    list_shadow_copies(&mut volume)
+}
+
+/// Shadow copies recorded in a volume's VSS catalog, oldest first. Empty when the volume
+/// has never had shadow copies.
+pub fn list_shadow_copies<R: Read + Seek>(volume: &mut R) -> Result<Vec<ShadowCopy>, MosesError> {
+    let header = read_at(volume, VOLUME_HEADER_OFFSET, SECTOR_SIZE)?;
+    if header[..16] != VSS_IDENTIFIER || u32_at(&header, 20) != RECORD_TYPE_VOLUME_HEADER {
+        return Ok(Vec::new());
+    }
+    let catalog_offset = u64_at(&header, 48);
+    if catalog_offset == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Each store has an information entry and a location entry, keyed by the store GUID
+    let mut infos: Vec<([u8; 16], u64, u64)> = Vec::new();
+    let mut locations: HashMap<[u8; 16], (u64, u64)> = HashMap::new();
+    for block in read_chain(volume, catalog_offset, RECORD_TYPE_CATALOG)? {
+        for entry in block[BLOCK_HEADER_SIZE..].as_chunks::<CATALOG_ENTRY_SIZE>().0 {
+            let store = guid_bytes(&entry[16..32]);
+            match u64_at(entry, 0) {
+                CATALOG_ENTRY_STORE_INFO => infos.push((store, u64_at(entry, 8), u64_at(entry, 48))),
+                CATALOG_ENTRY_STORE_LOCATION => {
+                    locations.insert(store, (u64_at(entry, 8), u64_at(entry, 32)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut copies = Vec::new();
+    for (store, volume_size, filetime) in infos {
+        let Some(&(block_list_offset, store_header_offset)) = locations.get(&store) else {
+            log::debug!("VSS store {} has no location entry", format_guid(&store));
+            continue;
+        };
+        let store_header = read_at(volume, store_header_offset, BLOCK_SIZE as usize)?;
+        check_block(&store_header, RECORD_TYPE_STORE_HEADER, store_header_offset)?;
+        let info = &store_header[BLOCK_HEADER_SIZE..];
+        copies.push(ShadowCopy {
+            index: 0,
+            id: format_guid(&info[16..32]),
+            set_id: format_guid(&info[32..48]),
+            created: (filetime != 0).then(|| super::structures::filetime_to_unix(filetime)),
+            volume_size,
+            machine: utf16_string(&info[64..]),
+            block_list_offset,
+        });
+    }
+    copies.sort_by_key(|copy| copy.created);
+    for (position, copy) in copies.iter_mut().enumerate() {
+        copy.index = position + 1;
+    }
+    Ok(copies)
+}
+
+/// Where a store keeps the old contents of one block
+#[derive(Debug, Clone, Copy)]
+enum StoredBlock {
+    /// A full copy at this volume offset
+    Copy(u64),
+    /// The block's old contents are those of another original offset
+    Forward(u64),
+}
+
+/// Block list of one store
+#[derive(Debug, Default)]
+struct StoreBlocks {
+    blocks: HashMap<u64, StoredBlock>,
+    /// Partial copies: data offset and a bitmap of the 512-byte sectors it holds
+    overlays: HashMap<u64, Vec<(u64, u32)>>,
+}
+
+/// A volume as it was when one snapshot was taken
+pub struct ShadowVolume<R> {
+    volume: R,
+    /// The snapshot's own store, then every newer one
+    stores: Arc<Vec<StoreBlocks>>,
+    size: u64,
+    pos: u64,
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> ShadowVolume<R> {
+    /// View `volume` as of the snapshot `snapshot` (GUID or index, see [`ShadowCopy::matches`])
+    pub fn open(mut volume: R, snapshot: &str) -> Result<Self, MosesError> {
+        let copies = list_shadow_copies(&mut volume)?;
+        let position = copies.iter().position(|copy| copy.matches(snapshot)).ok_or_else(|| {
+            MosesError::InvalidInput(format!(
+                "No shadow copy {} on this volume ({} found)", snapshot, copies.len()
+            ))
+        })?;
+        let stores = copies[position..]
+            .iter()
+            .map(|copy| read_block_list(&mut volume, copy.block_list_offset))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            volume,
+            stores: Arc::new(stores),
+            size: copies[position].volume_size,
+            pos: 0,
+            cached: None,
+        })
+    }
+
+    /// Size of the volume when the snapshot was taken
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Contents of the block at `offset` as of store `from`
+    fn resolve(&mut self, offset: u64, from: usize) -> io::Result<Vec<u8>> {
+        let stores = Arc::clone(&self.stores);
+        let mut found = None;
+        for (index, store) in stores.iter().enumerate().skip(from) {
+            if let Some(&stored) = store.blocks.get(&offset) {
+                found = Some((index, stored));
+                break;
+            }
+        }
+        let (mut data, last) = match found {
+            Some((index, StoredBlock::Copy(data_offset))) => {
+                (read_at(&mut self.volume, data_offset, BLOCK_SIZE as usize)?, index)
+            }
+            Some((index, StoredBlock::Forward(target))) => (self.resolve(target, index + 1)?, index),
+            None => (read_at(&mut self.volume, offset, BLOCK_SIZE as usize)?, stores.len().saturating_sub(1)),
+        };
+
+        // Partial copies in the stores consulted, oldest last so the oldest data wins
+        for store in stores[from.min(stores.len())..=last.min(stores.len().saturating_sub(1))].iter().rev() {
+            for &(data_offset, bitmap) in store.overlays.get(&offset).into_iter().flatten() {
+                let overlay = read_at(&mut self.volume, data_offset, BLOCK_SIZE as usize)?;
+                for sector in (0..32).filter(|sector| bitmap & (1 << sector) != 0) {
+                    let range = sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE;
+                    data[range.clone()].copy_from_slice(&overlay[range]);
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl ShadowVolume<File> {
+    /// A second handle on the same snapshot, sharing its block lists
+    pub fn try_clone(&self) -> Result<Self, MosesError> {
+        Ok(Self {
+            volume: self.volume.try_clone()?,
+            stores: Arc::clone(&self.stores),
+            size: self.size,
+            pos: 0,
+            cached: None,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for ShadowVolume<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block = self.pos / BLOCK_SIZE * BLOCK_SIZE;
+        if self.cached.as_ref().map(|(offset, _)| *offset) != Some(block) {
+            let data = self.resolve(block, 0)?;
+            self.cached = Some((block, data));
+        }
+        let data = &self.cached.as_ref().expect("block cached above").1;
+        let start = (self.pos - block) as usize;
+        let count = buf.len().min(data.len() - start).min((self.size - self.pos) as usize);
+        buf[..count].copy_from_slice(&data[start..start + count]);
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl<R: Read + Seek> Seek for ShadowVolume<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of snapshot"))?;
+        Ok(self.pos)
+    }
+}
+
+fn read_block_list<R: Read + Seek>(volume: &mut R, offset: u64) -> Result<StoreBlocks, MosesError> {
+    let mut store = StoreBlocks::default();
+    for block in read_chain(volume, offset, RECORD_TYPE_BLOCK_LIST)? {
+        for entry in block[BLOCK_HEADER_SIZE..].as_chunks::<BLOCK_LIST_ENTRY_SIZE>().0 {
+            let original = u64_at(entry, 0);
+            let relative = u64_at(entry, 8);
+            let data_offset = u64_at(entry, 16);
+            let flags = u32_at(entry, 24);
+            if entry.iter().all(|&b| b == 0) || flags & BLOCK_FLAG_NOT_USED != 0 {
+                continue;
+            }
+            if flags & BLOCK_FLAG_FORWARDER != 0 {
+                store.blocks.insert(original, StoredBlock::Forward(relative));
+            } else if flags & BLOCK_FLAG_OVERLAY != 0 {
+                store.overlays.entry(original).or_default().push((data_offset, u32_at(entry, 28)));
+            } else {
+                store.blocks.insert(original, StoredBlock::Copy(data_offset));
+            }
+        }
+    }
+    Ok(store)
+}
+
+/// Follow a linked list of 16 KiB blocks of one record type
+fn read_chain<R: Read + Seek>(volume: &mut R, first: u64, record_type: u32) -> Result<Vec<Vec<u8>>, MosesError> {
+    let mut blocks = Vec::new();
+    let mut offset = first;
+    while offset != 0 {
+        if blocks.len() >= MAX_CHAIN_BLOCKS {
+            return Err(MosesError::Other("VSS block chain does not end".to_string()));
+        }
+        let block = read_at(volume, offset, BLOCK_SIZE as usize)?;
+        check_block(&block, record_type, offset)?;
+        offset = u64_at(&block, 40);
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+fn check_block(block: &[u8], record_type: u32, offset: u64) -> Result<(), MosesError> {
+    if block[..16] != VSS_IDENTIFIER || u32_at(block, 20) != record_type {
+        return Err(MosesError::Other(format!(
+            "Corrupt VSS metadata at offset {:#x} (expected record type {})", offset, record_type
+        )));
+    }
+    Ok(())
+}
+
+/// Read `len` bytes, zero-filling past the end of the volume
+fn read_at<R: Read + Seek>(volume: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    volume.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len);
+    volume.by_ref().take(len as u64).read_to_end(&mut data)?;
+    data.resize(len, 0);
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn guid_bytes(data: &[u8]) -> [u8; 16] {
+    data[..16].try_into().unwrap()
+}
+
+/// Mixed-endian GUID text, lowercase without braces
+fn format_guid(data: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32_at(data, 0),
+        u16::from_le_bytes([data[4], data[5]]),
+        u16::from_le_bytes([data[6], data[7]]),
+        data[8], data[9], data[10], data[11], data[12], data[13], data[14], data[15],
+    )
+}
+
+/// Length-prefixed UTF-16 string, None when empty or malformed
+fn utf16_string(data: &[u8]) -> Option<String> {
+    let len = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let bytes = data.get(2..2 + len)?;
+    let units: Vec<u16> = bytes.as_chunks::<2>().0.iter().map(|&unit| u16::from_le_bytes(unit)).collect();
+    String::from_utf16(&units).ok().filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const VOLUME_SIZE: usize = 0x40000;
+    const CATALOG: u64 = 0x8000;
+
+    fn block_header(volume: &mut [u8], offset: u64, record_type: u32, next: u64) {
+        let at = offset as usize;
+        volume[at..at + 16].copy_from_slice(&VSS_IDENTIFIER);
+        volume[at + 16..at + 20].copy_from_slice(&1u32.to_le_bytes());
+        volume[at + 20..at + 24].copy_from_slice(&record_type.to_le_bytes());
+        volume[at + 40..at + 48].copy_from_slice(&next.to_le_bytes());
+    }
+
+    fn put_u64(volume: &mut [u8], at: usize, value: u64) {
+        volume[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Add a store to the catalog: its entries, header and block list entries
+    /// (original offset, data offset, flags, bitmap)
+    fn add_store(volume: &mut [u8], slot: usize, id: u8, filetime: u64, header: u64, list: u64, entries: &[(u64, u64, u32, u32)]) {
+        let info = CATALOG as usize + BLOCK_HEADER_SIZE + slot * 2 * CATALOG_ENTRY_SIZE;
+        let location = info + CATALOG_ENTRY_SIZE;
+        put_u64(volume, info, CATALOG_ENTRY_STORE_INFO);
+        put_u64(volume, info + 8, VOLUME_SIZE as u64);
+        volume[info + 16..info + 32].fill(id);
+        put_u64(volume, info + 48, filetime);
+        put_u64(volume, location, CATALOG_ENTRY_STORE_LOCATION);
+        put_u64(volume, location + 8, list);
+        volume[location + 16..location + 32].fill(id);
+        put_u64(volume, location + 32, header);
+
+        block_header(volume, header, RECORD_TYPE_STORE_HEADER, 0);
+        let store_info = header as usize + BLOCK_HEADER_SIZE;
+        volume[store_info + 16..store_info + 32].fill(id);
+        let machine: Vec<u8> = "PC".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        volume[store_info + 64..store_info + 66].copy_from_slice(&(machine.len() as u16).to_le_bytes());
+        volume[store_info + 66..store_info + 66 + machine.len()].copy_from_slice(&machine);
+
+        block_header(volume, list, RECORD_TYPE_BLOCK_LIST, 0);
+        for (i, &(original, data, flags, bitmap)) in entries.iter().enumerate() {
+            let at = list as usize + BLOCK_HEADER_SIZE + i * BLOCK_LIST_ENTRY_SIZE;
+            put_u64(volume, at, original);
+            put_u64(volume, at + 16, data);
+            volume[at + 24..at + 28].copy_from_slice(&flags.to_le_bytes());
+            volume[at + 28..at + 32].copy_from_slice(&bitmap.to_le_bytes());
+        }
+    }
+
+    /// Live blocks 0x20000 (0xB1) and 0x24000 (0xB2). The older store saved 0xA1 for the
+    /// first block; the newer saved 0xC2 for the second plus one sector of 0xD1 for the first.
+    fn volume_with_two_snapshots() -> Vec<u8> {
+        let mut volume = vec![0u8; VOLUME_SIZE];
+        volume[0x20000..0x24000].fill(0xB1);
+        volume[0x24000..0x28000].fill(0xB2);
+        volume[0x30000..0x34000].fill(0xA1);
+        volume[0x34000..0x38000].fill(0xC2);
+        volume[0x38000..0x3C000].fill(0xD1);
+
+        let header = VOLUME_HEADER_OFFSET as usize;
+        volume[header..header + 16].copy_from_slice(&VSS_IDENTIFIER);
+        volume[header + 20..header + 24].copy_from_slice(&RECORD_TYPE_VOLUME_HEADER.to_le_bytes());
+        put_u64(&mut volume, header + 48, CATALOG);
+        block_header(&mut volume, CATALOG, RECORD_TYPE_CATALOG, 0);
+
+        // Listed newest first, as Windows does
+        add_store(&mut volume, 0, 0x22, 133_000_000_000_000_000, 0xC000, 0x10000,
+            &[(0x24000, 0x34000, 0, 0), (0x20000, 0x38000, BLOCK_FLAG_OVERLAY, 0b1)]);
+        add_store(&mut volume, 1, 0x11, 132_000_000_000_000_000, 0x14000, 0x18000,
+            &[(0x20000, 0x30000, 0, 0)]);
+        volume
+    }
+
+    #[test]
+    fn test_list_shadow_copies() {
+        let copies = list_shadow_copies(&mut Cursor::new(volume_with_two_snapshots())).unwrap();
+        assert_eq!(copies.len(), 2);
+        assert_eq!(copies[0].id, "11111111-1111-1111-1111-111111111111");
+        assert_eq!((copies[0].index, copies[1].index), (1, 2));
+        assert!(copies[0].created < copies[1].created);
+        assert_eq!(copies[0].machine.as_deref(), Some("PC"));
+        assert!(copies[1].matches("{22222222-2222-2222-2222-222222222222}"));
+        assert!(copies[1].matches("2"));
+
+        assert!(list_shadow_copies(&mut Cursor::new(vec![0u8; VOLUME_SIZE])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_snapshot_blocks() {
+        let read = |snapshot: &str, offset: u64| {
+            let mut view = ShadowVolume::open(Cursor::new(volume_with_two_snapshots()), snapshot).unwrap();
+            let mut block = vec![0u8; BLOCK_SIZE as usize];
+            view.seek(SeekFrom::Start(offset)).unwrap();
+            view.read_exact(&mut block).unwrap();
+            block
+        };
+
+        // Newest snapshot: its own copy, its overlay over the live block
+        assert!(read("2", 0x24000).iter().all(|&b| b == 0xC2));
+        let overlaid = read("2", 0x20000);
+        assert!(overlaid[..512].iter().all(|&b| b == 0xD1));
+        assert!(overlaid[512..].iter().all(|&b| b == 0xB1));
+
+        // Oldest snapshot: its own full copy wins; unchanged since, the newer store's copy applies
+        assert!(read("1", 0x20000).iter().all(|&b| b == 0xA1));
+        assert!(read("1", 0x24000).iter().all(|&b| b == 0xC2));
+        // Never copied: the live volume
+        assert!(read("1", 0x28000).iter().all(|&b| b == 0));
+
+        assert!(ShadowVolume::open(Cursor::new(volume_with_two_snapshots()), "3").is_err());
+    }
+}
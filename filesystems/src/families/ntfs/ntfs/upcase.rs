@@ -0,0 +1,59 @@
+// NTFS $UpCase - the table file names are compared through
+// NTFS compares names without regard to case by mapping every UTF-16 unit through the
+// volume's own $UpCase file, 64K entries written when the volume is formatted. Windows
+// uses whatever table the volume carries, so the one thing that matters is that the
+// directory indexes written at format time are sorted with the same table.
+use std::cmp::Ordering;
+
+/// Entries in the table, one per UTF-16 unit
+pub const UPCASE_ENTRIES: usize = 0x10000;
+
+/// The simple uppercase mapping of every UTF-16 unit. Units whose uppercase form is
+/// several characters or outside the BMP, and surrogates, map to themselves.
+pub fn upcase_table() -> Vec<u16> {
+    (0..UPCASE_ENTRIES as u32)
+        .map(|unit| {
+            let Some(c) = char::from_u32(unit) else { return unit as u16 };
+            let mut upper = c.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(u), None) if (u as u32) < 0x10000 => u as u16,
+                _ => unit as u16,
+            }
+        })
+        .collect()
+}
+
+/// The table as $UpCase stores it
+pub fn upcase_bytes(table: &[u16]) -> Vec<u8> {
+    table.iter().flat_map(|unit| unit.to_le_bytes()).collect()
+}
+
+/// Order of two names in a file name index (COLLATION_FILE_NAME): by their uppercase
+/// forms, then by the names as written
+pub fn collate_names(table: &[u16], a: &[u16], b: &[u16]) -> Ordering {
+    let upper = |name: &[u16]| name.iter().map(|&unit| table[unit as usize]).collect::<Vec<_>>();
+    upper(a).cmp(&upper(b)).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upcase_table() {
+        let table = upcase_table();
+        assert_eq!(table.len(), UPCASE_ENTRIES);
+        assert_eq!(table['a' as usize], 'A' as u16);
+        assert_eq!(table['é' as usize], 'É' as u16);
+        assert_eq!(table['ж' as usize], 'Ж' as u16);
+        assert_eq!(table['ß' as usize], 'ß' as u16, "uppercases to two characters");
+        assert_eq!(table[0xD800], 0xD800);
+        assert_eq!(upcase_bytes(&table)[0xC2..0xC4], [0x41, 0]);
+
+        let name = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+        assert_eq!(collate_names(&table, &name("$MFT"), &name("$MFTMirr")), Ordering::Less);
+        assert_eq!(collate_names(&table, &name("$Volume"), &name(".")), Ordering::Less);
+        assert_eq!(collate_names(&table, &name("readme"), &name("README")), Ordering::Greater);
+        assert_eq!(collate_names(&table, &name("a"), &name("B")), Ordering::Less);
+    }
+}
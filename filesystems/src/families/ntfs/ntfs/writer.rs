@@ -131,14 +131,14 @@ impl NtfsWriter {
                     .write(true)
                     .access_mode(GENERIC_READ | GENERIC_WRITE)
                     .share_mode(FILE_SHARE_READ)
-                    .open(&device.mount_points[0])?
+                    .open(crate::utils::get_device_path(&device))?
             }
             #[cfg(not(target_os = "windows"))]
             {
                 OpenOptions::new()
                     .read(true)
                     .write(true)
-                    .open(&device.mount_points[0])?
+                    .open(crate::utils::get_device_path(&device))?
             }
         } else {
             // Dummy file handle for dry run mode
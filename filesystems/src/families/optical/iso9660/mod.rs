@@ -0,0 +1,24 @@
+// ISO9660 - read-only access to CD/DVD images and optical media, with Joliet and Rock Ridge
+// The volume descriptors at 32 KiB point to a primary directory tree with short upper-case
+// names and, on most discs, a Joliet tree with Unicode names for Windows. Rock Ridge adds
+// long names, Unix modes and symbolic links to the primary tree. The reader prefers Rock
+// Ridge, then Joliet, then the plain names; nothing is ever written.
+
+pub mod structures;
+pub mod reader;
+pub mod ops;
+
+pub use reader::Iso9660Reader;
+pub use ops::{Iso9660Ops, Iso9660Detector};
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// Whether the first volume descriptor carries the ISO9660 standard identifier
+pub fn has_volume_descriptor<R: Read + Seek>(file: &mut R) -> bool {
+    let mut id = [0u8; 5];
+    let found = file.seek(SeekFrom::Start(structures::VOLUME_DESCRIPTORS_OFFSET + 1)).is_ok()
+        && file.read_exact(&mut id).is_ok()
+        && &id == structures::STANDARD_ID;
+    let _ = file.seek(SeekFrom::Start(0));
+    found
+}
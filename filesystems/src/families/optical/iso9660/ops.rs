@@ -0,0 +1,133 @@
+// ISO9660 FilesystemOps implementation for mounting and browsing, read-only
+use super::reader::{Iso9660Entry, Iso9660Reader};
+use crate::device_reader::FilesystemReader;
+use crate::ops::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct Iso9660Ops {
+    reader: Mutex<Option<Iso9660Reader>>,
+}
+
+impl Iso9660Ops {
+    pub fn new() -> Self {
+        Self { reader: Mutex::new(None) }
+    }
+
+    /// Run `f` on the reader with `path` resolved to its entry
+    fn with_path<T>(&self, path: &Path, f: impl FnOnce(&mut Iso9660Reader, Iso9660Entry) -> Result<T, MosesError>) -> Result<T, MosesError> {
+        let path = path.to_str().ok_or_else(|| MosesError::InvalidInput("Invalid path".to_string()))?;
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let entry = reader.lookup(path)?;
+        f(reader, entry)
+    }
+}
+
+impl Default for Iso9660Ops {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attributes(entry: &Iso9660Entry) -> FileAttributes {
+    let rock_ridge = &entry.rock_ridge;
+    let is_symlink = entry.is_symlink();
+    // Without Rock Ridge everything is readable by everyone and nothing is writable
+    let default_mode = if entry.is_directory { 0o555 } else { 0o444 };
+    FileAttributes {
+        size: if entry.is_directory { 0 } else { entry.size },
+        is_directory: entry.is_directory,
+        is_file: !entry.is_directory && !is_symlink,
+        is_symlink,
+        created: rock_ridge.created.or(entry.recorded),
+        modified: rock_ridge.modified.or(entry.recorded),
+        accessed: rock_ridge.accessed,
+        permissions: rock_ridge.mode.map_or(default_mode, |mode| mode & 0o7777),
+        owner: rock_ridge.uid,
+        group: rock_ridge.gid,
+    }
+}
+
+impl FilesystemOps for Iso9660Ops {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        *self.reader.lock().unwrap() = Some(Iso9660Reader::new(device.clone())?);
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        let reader = self.reader.lock().unwrap();
+        let reader = reader.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let info = reader.get_info();
+        let block_size = info.cluster_size.unwrap_or(super::structures::SECTOR_SIZE as u32);
+        Ok(FilesystemInfo {
+            total_space: info.total_bytes,
+            free_space: 0,
+            available_space: 0,
+            total_inodes: 0,
+            free_inodes: 0,
+            block_size,
+            fragment_size: block_size,
+            max_filename_length: 255,
+            filesystem_type: info.fs_type,
+            volume_label: info.label,
+            volume_uuid: None,
+            is_readonly: true,
+        })
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.with_path(path, |_, entry| Ok(attributes(&entry)))
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.with_path(path, |reader, dir| {
+            Ok(reader.list(&dir)?.into_iter()
+                .map(|entry| DirectoryEntry { attributes: attributes(&entry), name: entry.name })
+                .collect())
+        })
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.with_path(path, |reader, entry| reader.read(&entry, offset, u64::from(size)))
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        // Directories are identified by where their records start
+        self.with_path(path, |_, entry| Ok(entry.extents[0].0)).ok()
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.with_path(path, |reader, entry| reader.read_link(&entry).map(PathBuf::from))
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn filesystem_type(&self) -> &str {
+        "iso9660"
+    }
+}
+
+/// Finds ISO9660 by the standard identifier of the first volume descriptor
+pub struct Iso9660Detector;
+
+impl crate::ops::FilesystemDetector for Iso9660Detector {
+    fn detect(&self, device: &Device) -> Result<Option<String>, MosesError> {
+        use crate::utils::{open_device_read, read_block};
+
+        let mut file = open_device_read(device)?;
+        match read_block(&mut file, super::structures::VOLUME_DESCRIPTORS_OFFSET, 6) {
+            Ok(header) if &header[1..6] == super::structures::STANDARD_ID => Ok(Some("iso9660".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
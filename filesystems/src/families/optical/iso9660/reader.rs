@@ -0,0 +1,612 @@
+// ISO9660 reader - volume descriptors to a directory tree, named by Rock Ridge or Joliet
+// Opening a volume reads the descriptors from sector 16 up to the terminator. When the
+// primary root's `.` record starts with a SUSP SP entry the primary tree is read with Rock
+// Ridge names, modes and links; otherwise a Joliet supplementary tree is used if there is
+// one, and the plain primary names if not. Files are one or more extents laid end to end;
+// Rock Ridge directories relocated out of deep trees are followed back to where they live.
+use super::structures::*;
+use crate::device_reader::{AlignedDeviceReader, FileEntry, FileMetadata, FilesystemInfo, FilesystemReader};
+use log::{info, warn};
+use moses_core::{Device, MosesError};
+use std::collections::HashMap;
+
+/// Directory listings kept in memory; path lookups revisit the same directories
+const DIRECTORY_CACHE_LIMIT: usize = 256;
+/// CE continuations followed per record before the chain is treated as damaged
+const MAX_CONTINUATIONS: usize = 16;
+
+/// Which names and metadata the reader shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Naming {
+    /// Primary tree with Rock Ridge entries, after skipping this many bytes of each area
+    RockRidge { skip: usize },
+    /// Joliet tree at this level
+    Joliet(u8),
+    /// Primary tree, upper-case 8.3 or ISO9660:1999 names
+    Plain,
+}
+
+/// One file or directory
+#[derive(Debug, Clone)]
+pub struct Iso9660Entry {
+    pub name: String,
+    pub is_directory: bool,
+    /// Byte offset and length of each extent, in file order
+    pub extents: Vec<(u64, u64)>,
+    pub size: u64,
+    pub recorded: Option<u64>,
+    pub hidden: bool,
+    pub interleaved: bool,
+    /// Rock Ridge metadata; default when the volume has none
+    pub rock_ridge: RockRidge,
+}
+
+impl Iso9660Entry {
+    pub fn is_symlink(&self) -> bool {
+        self.rock_ridge.is_symlink()
+    }
+}
+
+/// Read-only ISO9660 volume
+pub struct Iso9660Reader {
+    reader: AlignedDeviceReader,
+    primary: VolumeDescriptor,
+    naming: Naming,
+    root: Iso9660Entry,
+    /// An El Torito boot record is present
+    bootable: bool,
+    directory_cache: HashMap<u64, Vec<Iso9660Entry>>,
+}
+
+impl Iso9660Reader {
+    pub fn new(device: Device) -> Result<Self, MosesError> {
+        info!("Opening ISO9660 filesystem on device: {}", device.name);
+        let file = crate::utils::open_device_with_fallback(&device)?;
+        Self::from_reader(AlignedDeviceReader::new(file))
+    }
+
+    pub fn from_reader(mut reader: AlignedDeviceReader) -> Result<Self, MosesError> {
+        let mut primary = None;
+        let mut joliet: Option<VolumeDescriptor> = None;
+        let mut bootable = false;
+        for index in 0..MAX_VOLUME_DESCRIPTORS {
+            let sector = reader.read_at(VOLUME_DESCRIPTORS_OFFSET + index * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+            if &sector[1..6] != STANDARD_ID {
+                return Err(corrupt(&format!("volume descriptor {}: missing standard identifier", 16 + index)));
+            }
+            match sector[0] {
+                VD_TERMINATOR => break,
+                VD_BOOT_RECORD => bootable |= sector[7..].starts_with(EL_TORITO_ID),
+                VD_PRIMARY if primary.is_none() => primary = Some(VolumeDescriptor::parse(&sector)?),
+                VD_SUPPLEMENTARY => match VolumeDescriptor::parse(&sector) {
+                    Ok(descriptor) if descriptor.joliet > joliet.as_ref().and_then(|best| best.joliet) => joliet = Some(descriptor),
+                    Ok(_) => {}
+                    Err(e) => warn!("Ignoring supplementary volume descriptor {}: {}", 16 + index, e),
+                },
+                _ => {}
+            }
+        }
+        let primary = primary.ok_or_else(|| corrupt("volume: no primary volume descriptor"))?;
+
+        let mut iso = Self {
+            reader,
+            naming: Naming::Plain,
+            root: Self::root_entry(&primary),
+            primary,
+            bootable,
+            directory_cache: HashMap::new(),
+        };
+        // Rock Ridge announces itself in the root's `.` record
+        let first = iso.read_extent_bytes(iso.root.extents[0].0, iso.root.size.min(SECTOR_SIZE))?;
+        if let Some(skip) = DirRecord::parse(&first).ok().as_ref().and_then(susp_skip) {
+            iso.naming = Naming::RockRidge { skip };
+        } else if let Some(joliet) = joliet {
+            iso.naming = Naming::Joliet(joliet.joliet.unwrap_or(1));
+            iso.root = Self::root_entry(&joliet);
+        }
+        info!("ISO9660 volume \"{}\" read with {:?} names", iso.primary.volume_id, iso.naming);
+        Ok(iso)
+    }
+
+    fn root_entry(descriptor: &VolumeDescriptor) -> Iso9660Entry {
+        let block = u64::from(descriptor.block_size);
+        Iso9660Entry {
+            name: String::new(),
+            is_directory: true,
+            extents: vec![(u64::from(descriptor.root.extent) * block, u64::from(descriptor.root.size))],
+            size: u64::from(descriptor.root.size),
+            recorded: descriptor.root.recorded,
+            hidden: false,
+            interleaved: false,
+            rock_ridge: RockRidge::default(),
+        }
+    }
+
+    pub fn naming(&self) -> Naming {
+        self.naming
+    }
+
+    /// Whether the volume has an El Torito boot record, as installer images do
+    pub fn is_bootable(&self) -> bool {
+        self.bootable
+    }
+
+    pub fn volume_id(&self) -> &str {
+        &self.primary.volume_id
+    }
+
+    pub fn root(&self) -> &Iso9660Entry {
+        &self.root
+    }
+
+    fn block_size(&self) -> u64 {
+        u64::from(self.primary.block_size)
+    }
+
+    fn read_extent_bytes(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, MosesError> {
+        self.reader.read_at(offset, len as usize)
+    }
+
+    /// The SUSP entries of a record, following CE continuations
+    fn susp_entries(&mut self, record: &DirRecord, skip: usize) -> Vec<SuspEntry> {
+        let (mut entries, mut continuation) = parse_susp(record.system_use.get(skip..).unwrap_or_default());
+        for _ in 0..MAX_CONTINUATIONS {
+            let Some(next) = continuation.take() else {
+                break;
+            };
+            let offset = u64::from(next.block) * self.block_size() + u64::from(next.offset);
+            match self.reader.read_at(offset, (next.length as usize).min(SECTOR_SIZE as usize)) {
+                Ok(area) => {
+                    let (more, after) = parse_susp(&area);
+                    entries.extend(more);
+                    continuation = after;
+                }
+                Err(e) => {
+                    warn!("Unreadable Rock Ridge continuation at block {}: {}", next.block, e);
+                    break;
+                }
+            }
+        }
+        entries
+    }
+
+    /// Size of the directory at `block`, from its `.` record
+    fn directory_size(&mut self, block: u32) -> Result<u64, MosesError> {
+        let first = self.reader.read_at(u64::from(block) * self.block_size(), SECTOR_SIZE as usize)?;
+        let record = DirRecord::parse(&first)?;
+        if !record.is_self_or_parent() {
+            return Err(corrupt(&format!("relocated directory at block {}: no `.` record", block)));
+        }
+        Ok(u64::from(record.size))
+    }
+
+    /// The entries of directory `dir`, in the order they are stored, without `.` and `..`
+    pub fn list(&mut self, dir: &Iso9660Entry) -> Result<Vec<Iso9660Entry>, MosesError> {
+        if !dir.is_directory {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", dir.name)));
+        }
+        let (start, size) = dir.extents[0];
+        if let Some(entries) = self.directory_cache.get(&start) {
+            return Ok(entries.clone());
+        }
+        let data = self.reader.read_at(start, size as usize)?;
+        let mut entries: Vec<Iso9660Entry> = Vec::new();
+        // Set while the records of a multi-extent file are being collected
+        let mut continuing = false;
+        let mut at = 0;
+        while at < data.len() {
+            let len = data[at] as usize;
+            if len == 0 {
+                // Padding to the end of the sector
+                at = (at / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            let sector_end = (at / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            let record = DirRecord::parse(&data[at..sector_end.min(data.len())])
+                .map_err(|e| corrupt(&format!("directory at {:#x}: {}", start, e)))?;
+            at += len;
+            if record.is_self_or_parent() {
+                continue;
+            }
+            let extent = (u64::from(record.extent) * self.block_size(), u64::from(record.size));
+            if continuing {
+                if let Some(last) = entries.last_mut() {
+                    last.extents.push(extent);
+                    last.size += extent.1;
+                }
+                continuing = record.flags & FLAG_MULTI_EXTENT != 0;
+                continue;
+            }
+            continuing = record.flags & FLAG_MULTI_EXTENT != 0;
+            let entry = self.entry(record, extent)?;
+            if !entry.rock_ridge.relocated {
+                entries.push(entry);
+            }
+        }
+        if self.directory_cache.len() >= DIRECTORY_CACHE_LIMIT {
+            self.directory_cache.clear();
+        }
+        self.directory_cache.insert(start, entries.clone());
+        Ok(entries)
+    }
+
+    fn entry(&mut self, record: DirRecord, mut extent: (u64, u64)) -> Result<Iso9660Entry, MosesError> {
+        let mut is_directory = record.is_directory();
+        let (name, rock_ridge) = match self.naming {
+            Naming::RockRidge { skip } => {
+                let rock_ridge = RockRidge::from_entries(&self.susp_entries(&record, skip));
+                let plain = strip_version(&String::from_utf8_lossy(&record.name)).to_string();
+                (rock_ridge.name.clone().unwrap_or(plain), rock_ridge)
+            }
+            Naming::Joliet(_) => (strip_version(&ucs2_name(&record.name)).to_string(), RockRidge::default()),
+            Naming::Plain => (strip_version(&String::from_utf8_lossy(&record.name)).to_string(), RockRidge::default()),
+        };
+        // A placeholder file standing in for a directory that was moved to keep the tree shallow
+        if let Some(block) = rock_ridge.child_link {
+            is_directory = true;
+            extent = (u64::from(block) * self.block_size(), self.directory_size(block)?);
+        }
+        Ok(Iso9660Entry {
+            name,
+            is_directory,
+            size: if is_directory { extent.1 } else { u64::from(record.size) },
+            extents: vec![extent],
+            recorded: record.recorded,
+            hidden: record.flags & FLAG_HIDDEN != 0,
+            interleaved: record.interleaved,
+            rock_ridge,
+        })
+    }
+
+    /// Resolve a `/`-separated path from the root directory
+    pub fn lookup(&mut self, path: &str) -> Result<Iso9660Entry, MosesError> {
+        let mut entry = self.root.clone();
+        for part in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            if !entry.is_directory {
+                return Err(MosesError::Other(format!("Path not found: {}", path)));
+            }
+            let entries = self.list(&entry)?;
+            // Plain and Joliet names are matched without regard to case, as Windows does
+            let exact = !matches!(self.naming, Naming::Plain | Naming::Joliet(_));
+            entry = entries.into_iter()
+                .find(|child| if exact { child.name == part } else { child.name.eq_ignore_ascii_case(part) })
+                .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))?;
+        }
+        Ok(entry)
+    }
+
+    /// Target of a Rock Ridge symbolic link
+    pub fn read_link(&self, entry: &Iso9660Entry) -> Result<String, MosesError> {
+        entry.rock_ridge.symlink.clone()
+            .ok_or_else(|| MosesError::InvalidInput(format!("Not a symbolic link: {}", entry.name)))
+    }
+
+    /// Up to `size` bytes of `entry` from `offset`
+    pub fn read(&mut self, entry: &Iso9660Entry, offset: u64, size: u64) -> Result<Vec<u8>, MosesError> {
+        if entry.interleaved {
+            return Err(MosesError::NotSupported(format!("ISO9660 interleaved file {}", entry.name)));
+        }
+        let end = offset.saturating_add(size).min(entry.size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        let mut extent_start = 0u64;
+        for &(start, len) in &entry.extents {
+            let extent_end = extent_start + len;
+            let (from, to) = (offset.max(extent_start), end.min(extent_end));
+            if from < to {
+                data.extend(self.reader.read_at(start + (from - extent_start), (to - from) as usize)?);
+            }
+            extent_start = extent_end;
+        }
+        Ok(data)
+    }
+
+    fn file_entry(&self, entry: Iso9660Entry) -> FileEntry {
+        let rock_ridge = &entry.rock_ridge;
+        FileEntry {
+            is_directory: entry.is_directory,
+            size: if entry.is_directory { 0 } else { entry.size },
+            cluster: None,
+            metadata: FileMetadata {
+                link_target: rock_ridge.symlink.clone(),
+                allocated_size: Some(entry.extents.iter().map(|(_, len)| len.div_ceil(self.block_size()) * self.block_size()).sum()),
+                created: rock_ridge.created.or(entry.recorded),
+                modified: rock_ridge.modified.or(entry.recorded),
+                accessed: rock_ridge.accessed,
+                readonly: true,
+                hidden: entry.hidden,
+                ..Default::default()
+            },
+            name: entry.name,
+        }
+    }
+}
+
+impl FilesystemReader for Iso9660Reader {
+    fn read_metadata(&mut self) -> Result<(), MosesError> {
+        // Already read in new()
+        Ok(())
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
+        let dir = self.lookup(path)?;
+        if !dir.is_directory {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", path)));
+        }
+        let entries = self.list(&dir)?;
+        Ok(entries.into_iter().map(|entry| self.file_entry(entry)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, MosesError> {
+        let entry = self.lookup(path)?;
+        if entry.is_directory {
+            return Err(MosesError::InvalidInput(format!("Is a directory: {}", path)));
+        }
+        self.read(&entry, 0, entry.size)
+    }
+
+    fn get_info(&self) -> FilesystemInfo {
+        let total = u64::from(self.primary.volume_space_size) * self.block_size();
+        FilesystemInfo {
+            fs_type: "iso9660".to_string(),
+            label: Some(self.primary.volume_id.clone()).filter(|label| !label.is_empty()),
+            total_bytes: total,
+            used_bytes: total,
+            cluster_size: Some(u32::from(self.primary.block_size)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const SECTORS: usize = 32;
+    const ROOT: u32 = 20;
+    const DOCS: u32 = 21;
+    const JOLIET_ROOT: u32 = 22;
+    const JOLIET_DOCS: u32 = 23;
+    const README: u32 = 24;
+    const NOTES: u32 = 25;
+    const BIG_FIRST: u32 = 26;
+    const BIG_SECOND: u32 = 28;
+    /// Relocated directory (Rock Ridge CL/RE)
+    const DEEP: u32 = 29;
+    const DEEP_FILE: u32 = 30;
+    const RR_MOVED: u32 = 31;
+    const DATE: [u8; 7] = [124, 1, 2, 3, 4, 5, 0];
+
+    fn both_u16(value: u16) -> [u8; 4] {
+        let (le, be) = (value.to_le_bytes(), value.to_be_bytes());
+        [le[0], le[1], be[0], be[1]]
+    }
+
+    fn both_u32(value: u32) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&value.to_le_bytes());
+        bytes[4..].copy_from_slice(&value.to_be_bytes());
+        bytes
+    }
+
+    fn record(extent: u32, size: u32, flags: u8, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0u8; 33];
+        record[2..10].copy_from_slice(&both_u32(extent));
+        record[10..18].copy_from_slice(&both_u32(size));
+        record[18..25].copy_from_slice(&DATE);
+        record[25] = flags;
+        record[28..32].copy_from_slice(&both_u16(1));
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record.extend_from_slice(system_use);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn susp(signature: &[u8; 2], body: &[u8]) -> Vec<u8> {
+        let mut entry = vec![signature[0], signature[1], (4 + body.len()) as u8, 1];
+        entry.extend_from_slice(body);
+        entry
+    }
+
+    fn rr_name(name: &str) -> Vec<u8> {
+        let mut body = vec![0];
+        body.extend_from_slice(name.as_bytes());
+        susp(b"NM", &body)
+    }
+
+    fn rr_mode(mode: u32) -> Vec<u8> {
+        let mut body = Vec::new();
+        for value in [mode, 1, 1000, 100] {
+            body.extend_from_slice(&both_u32(value));
+        }
+        susp(b"PX", &body)
+    }
+
+    fn joliet(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    fn directory(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for record in records {
+            // Records never cross a sector boundary
+            if data.len() % 2048 + record.len() > 2048 {
+                data.resize((data.len() / 2048 + 1) * 2048, 0);
+            }
+            data.extend_from_slice(record);
+        }
+        data
+    }
+
+    fn descriptor(kind: u8, volume_id: &[u8], root: u32, root_size: u32, escape: &[u8]) -> Vec<u8> {
+        let mut sector = vec![0u8; 2048];
+        sector[0] = kind;
+        sector[1..6].copy_from_slice(STANDARD_ID);
+        sector[6] = 1;
+        sector[40..72].fill(b' ');
+        sector[40..40 + volume_id.len()].copy_from_slice(volume_id);
+        sector[80..88].copy_from_slice(&both_u32(SECTORS as u32));
+        sector[88..88 + escape.len()].copy_from_slice(escape);
+        sector[120..124].copy_from_slice(&both_u16(1));
+        sector[124..128].copy_from_slice(&both_u16(1));
+        sector[128..132].copy_from_slice(&both_u16(2048));
+        sector[156..190].copy_from_slice(&record(root, root_size, FLAG_DIRECTORY, &[0], &[]));
+        sector[813..830].copy_from_slice(b"2024010203040500\0");
+        sector
+    }
+
+    fn build_image(rock_ridge: bool) -> Vec<u8> {
+        let mut image = vec![0u8; SECTORS * 2048];
+        let mut put = |sector: u32, data: &[u8]| {
+            let at = sector as usize * 2048;
+            image[at..at + data.len()].copy_from_slice(data);
+        };
+        let rr = |entries: &[Vec<u8>]| if rock_ridge { entries.concat() } else { Vec::new() };
+
+        // Primary tree, with Rock Ridge when asked for
+        let sp = susp(b"SP", &[0xBE, 0xEF, 0]);
+        let mut link = vec![0];
+        for component in [&b"docs"[..], b"notes.txt"] {
+            link.extend_from_slice(&[0, component.len() as u8]);
+            link.extend_from_slice(component);
+        }
+        let root = directory(&[
+            record(ROOT, 2048, FLAG_DIRECTORY, &[0], &rr(&[sp, rr_mode(0o040755)])),
+            record(ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(README, 12, 0, b"README.MD;1", &rr(&[rr_name("readme.md"), rr_mode(0o100644)])),
+            record(DOCS, 2048, FLAG_DIRECTORY, b"DOCS", &rr(&[rr_name("docs"), rr_mode(0o040755)])),
+            record(BIG_FIRST, 4096, FLAG_MULTI_EXTENT, b"BIG.BIN;1", &rr(&[rr_name("big.bin"), rr_mode(0o100644)])),
+            record(BIG_SECOND, 6, 0, b"BIG.BIN;1", &[]),
+            record(0, 0, 0, b"LINK.;1", &rr(&[rr_name("link"), rr_mode(0o120777), susp(b"SL", &link)])),
+            record(0, 0, 0, b"DEEP", &rr(&[rr_name("deep"), rr_mode(0o040755), susp(b"CL", &both_u32(DEEP))])),
+            record(RR_MOVED, 2048, FLAG_DIRECTORY, b"RR_MOVED", &rr(&[rr_name("rr_moved")])),
+        ]);
+        put(ROOT, &root);
+        put(RR_MOVED, &directory(&[
+            record(RR_MOVED, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(DEEP, 2048, FLAG_DIRECTORY, b"DEEP", &rr(&[rr_name("deep"), susp(b"RE", &[])])),
+        ]));
+        put(DOCS, &directory(&[
+            record(DOCS, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(NOTES, 6, 0, b"NOTES.TXT;1", &rr(&[rr_name("notes.txt"), rr_mode(0o100600)])),
+        ]));
+        put(DEEP, &directory(&[
+            record(DEEP, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(DEEP_FILE, 5, 0, b"BOTTOM.TXT;1", &rr(&[rr_name("bottom.txt")])),
+        ]));
+
+        // Joliet tree
+        put(JOLIET_ROOT, &directory(&[
+            record(JOLIET_ROOT, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(JOLIET_ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(README, 12, FLAG_HIDDEN, &joliet("ReadMe.md;1"), &[]),
+            record(JOLIET_DOCS, 2048, FLAG_DIRECTORY, &joliet("Docs"), &[]),
+            record(BIG_FIRST, 4096, FLAG_MULTI_EXTENT, &joliet("Big.bin;1"), &[]),
+            record(BIG_SECOND, 6, 0, &joliet("Big.bin;1"), &[]),
+        ]));
+        put(JOLIET_DOCS, &directory(&[
+            record(JOLIET_DOCS, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(JOLIET_ROOT, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(NOTES, 6, 0, &joliet("Notes.txt;1"), &[]),
+        ]));
+
+        put(README, b"# Installer\n");
+        put(NOTES, b"notes\n");
+        put(BIG_FIRST, &[b'a'; 4096]);
+        put(BIG_SECOND, b"tail!\n");
+        put(DEEP_FILE, b"deep\n");
+
+        put(16, &descriptor(VD_PRIMARY, b"MOSES_TEST", ROOT, 2048, &[]));
+        let mut boot = vec![0u8; 2048];
+        boot[1..6].copy_from_slice(STANDARD_ID);
+        boot[6] = 1;
+        boot[7..7 + EL_TORITO_ID.len()].copy_from_slice(EL_TORITO_ID);
+        put(17, &boot);
+        put(18, &descriptor(VD_SUPPLEMENTARY, &joliet("Moses Test"), JOLIET_ROOT, 2048, b"%/E"));
+        let mut terminator = vec![0u8; 2048];
+        terminator[0] = VD_TERMINATOR;
+        terminator[1..6].copy_from_slice(STANDARD_ID);
+        put(19, &terminator);
+        image
+    }
+
+    fn names(entries: Vec<FileEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_read_rock_ridge_iso() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("installer.iso");
+        std::fs::write(&path, build_image(true)).unwrap();
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+        let mut reader = Iso9660Reader::new(device.clone()).unwrap();
+        assert_eq!(reader.naming(), Naming::RockRidge { skip: 0 });
+        assert!(reader.is_bootable());
+        assert_eq!(reader.get_info().label.as_deref(), Some("MOSES_TEST"));
+        assert_eq!(names(reader.list_directory("/").unwrap()), ["readme.md", "docs", "big.bin", "link", "deep", "rr_moved"]);
+        assert!(reader.list_directory("/rr_moved").unwrap().is_empty());
+        assert_eq!(reader.read_file("/readme.md").unwrap(), b"# Installer\n");
+        assert_eq!(reader.read_file("/docs/notes.txt").unwrap(), b"notes\n");
+        assert_eq!(reader.read_file("/deep/bottom.txt").unwrap(), b"deep\n");
+        let big = reader.read_file("/big.bin").unwrap();
+        assert_eq!(big.len(), 4102);
+        assert!(big[..4096].iter().all(|&b| b == b'a') && big.ends_with(b"tail!\n"));
+        assert!(reader.read_file("/README.MD").is_err(), "Rock Ridge names are case-sensitive");
+
+        let root = reader.list_directory("/").unwrap();
+        let link = root.iter().find(|entry| entry.name == "link").unwrap();
+        assert_eq!(link.metadata.link_target.as_deref(), Some("docs/notes.txt"));
+        assert_eq!(root[0].metadata.modified, Some(1_704_164_645));
+
+        // Found by the sniffer and the ops registry
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(crate::detection::detect_filesystem(&mut file).unwrap(), "iso9660");
+        let mut registry = crate::ops::FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut registry, false);
+        let mut ops = registry.create_ops(&device, None).unwrap();
+        assert_eq!(ops.filesystem_type(), "iso9660");
+        assert_eq!(ops.read(Path::new("/big.bin"), 4094, 4).unwrap(), b"aata");
+        assert_eq!(ops.readlink(Path::new("/link")).unwrap(), Path::new("docs/notes.txt"));
+        let notes = ops.stat(Path::new("/docs/notes.txt")).unwrap();
+        assert_eq!((notes.permissions, notes.owner, notes.size), (0o600, Some(1000), 6));
+        assert!(ops.stat(Path::new("/deep")).unwrap().is_directory);
+    }
+
+    #[test]
+    fn test_read_joliet_and_plain_iso() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("windows.iso");
+        let mut image = build_image(false);
+        std::fs::write(&path, &image).unwrap();
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+        let mut reader = Iso9660Reader::new(device.clone()).unwrap();
+        assert_eq!(reader.naming(), Naming::Joliet(3));
+        assert_eq!(names(reader.list_directory("/").unwrap()), ["ReadMe.md", "Docs", "Big.bin"]);
+        assert_eq!(reader.read_file("/docs/notes.TXT").unwrap(), b"notes\n");
+        assert!(reader.list_directory("/").unwrap()[0].metadata.hidden);
+        assert_eq!(reader.read_file("/Big.bin").unwrap().len(), 4102);
+
+        // Without the Joliet descriptor only the primary names are left
+        image[18 * 2048] = VD_TERMINATOR;
+        std::fs::write(&path, &image).unwrap();
+        let mut reader = Iso9660Reader::new(device).unwrap();
+        assert_eq!(reader.naming(), Naming::Plain);
+        assert_eq!(names(reader.list_directory("/").unwrap()), ["README.MD", "DOCS", "BIG.BIN", "LINK", "DEEP", "RR_MOVED"]);
+        assert_eq!(reader.read_file("/readme.md").unwrap(), b"# Installer\n");
+    }
+}
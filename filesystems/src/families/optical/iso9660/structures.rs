@@ -0,0 +1,383 @@
+// ISO9660 on-disk structures - volume descriptors, directory records and Rock Ridge entries
+// Numbers are stored twice, little-endian then big-endian; the little-endian copy is read.
+// Directory records never cross a sector boundary: a zero length byte means the rest of the
+// sector is padding. Rock Ridge entries follow the SUSP format in each record's system use
+// area: a two-letter signature, a length and a version, with CE entries continuing the area
+// in another block.
+use moses_core::MosesError;
+
+pub const SECTOR_SIZE: u64 = 2048;
+/// Volume descriptors start at sector 16
+pub const VOLUME_DESCRIPTORS_OFFSET: u64 = 16 * SECTOR_SIZE;
+pub const STANDARD_ID: &[u8; 5] = b"CD001";
+/// Descriptors read before giving up on finding the terminator
+pub const MAX_VOLUME_DESCRIPTORS: u64 = 64;
+
+/// Volume descriptor types
+pub const VD_BOOT_RECORD: u8 = 0;
+pub const VD_PRIMARY: u8 = 1;
+pub const VD_SUPPLEMENTARY: u8 = 2;
+pub const VD_TERMINATOR: u8 = 255;
+/// Boot system identifier of an El Torito boot record
+pub const EL_TORITO_ID: &[u8] = b"EL TORITO SPECIFICATION";
+
+/// Directory record flags
+pub const FLAG_HIDDEN: u8 = 0x01;
+pub const FLAG_DIRECTORY: u8 = 0x02;
+/// More records for the same file follow, each with one more extent
+pub const FLAG_MULTI_EXTENT: u8 = 0x80;
+pub const DIR_RECORD_MIN: usize = 34;
+
+/// Rock Ridge NM and SL flags; NM continuations simply append
+const NM_CURRENT: u8 = 0x02;
+const NM_PARENT: u8 = 0x04;
+const SL_CONTINUE: u8 = 0x01;
+const SL_CURRENT: u8 = 0x02;
+const SL_PARENT: u8 = 0x04;
+const SL_ROOT: u8 = 0x08;
+/// TF flags: which times follow, and whether they use the 17-byte format
+const TF_CREATION: u8 = 0x01;
+const TF_MODIFY: u8 = 0x02;
+const TF_ACCESS: u8 = 0x04;
+const TF_ATTRIBUTES: u8 = 0x08;
+const TF_LONG_FORM: u8 = 0x80;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFLNK: u32 = 0o120000;
+
+pub(crate) fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn corrupt(what: &str) -> MosesError {
+    MosesError::Other(format!("Corrupted ISO9660 {}", what))
+}
+
+/// Seconds since 1970 of the 7-byte directory record time: years since 1900, month, day,
+/// hour, minute, second and the offset from GMT in 15-minute steps
+pub fn record_time(data: &[u8]) -> Option<u64> {
+    let date = chrono::NaiveDate::from_ymd_opt(1900 + i32::from(data[0]), u32::from(data[1]), u32::from(data[2]))?;
+    let time = date.and_hms_opt(u32::from(data[3]), u32::from(data[4]), u32::from(data[5]))?;
+    let seconds = time.and_utc().timestamp() - i64::from(data[6] as i8) * 15 * 60;
+    u64::try_from(seconds).ok()
+}
+
+/// Seconds since 1970 of the 17-byte volume descriptor time: "YYYYMMDDHHMMSScc" in ASCII
+/// and the offset from GMT
+pub fn long_time(data: &[u8]) -> Option<u64> {
+    let digits = std::str::from_utf8(&data[..16]).ok()?;
+    let field = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u32>().ok();
+    let date = chrono::NaiveDate::from_ymd_opt(field(0..4)? as i32, field(4..6)?, field(6..8)?)?;
+    let time = date.and_hms_opt(field(8..10)?, field(10..12)?, field(12..14)?)?;
+    let seconds = time.and_utc().timestamp() - i64::from(data[16] as i8) * 15 * 60;
+    u64::try_from(seconds).ok()
+}
+
+/// Names stored as UCS-2 big-endian, as Joliet does
+pub fn ucs2_name(data: &[u8]) -> String {
+    let units: Vec<u16> = data.chunks(2).filter(|unit| unit.len() == 2).map(|unit| u16::from_be_bytes([unit[0], unit[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// A name without its `;1` version and the dot ISO9660 adds to names without an extension
+pub fn strip_version(name: &str) -> &str {
+    let name = name.rsplit_once(';').map_or(name, |(name, _)| name);
+    match name.strip_suffix('.') {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => name,
+    }
+}
+
+/// The volume descriptor fields the reader uses
+#[derive(Debug, Clone)]
+pub struct VolumeDescriptor {
+    pub kind: u8,
+    pub volume_id: String,
+    pub volume_space_size: u32,
+    pub block_size: u16,
+    pub root: DirRecord,
+    /// Joliet level (1-3) of a supplementary descriptor with Joliet escape sequences
+    pub joliet: Option<u8>,
+    pub created: Option<u64>,
+}
+
+impl VolumeDescriptor {
+    /// A primary or supplementary descriptor
+    pub fn parse(sector: &[u8]) -> Result<Self, MosesError> {
+        if sector.len() < SECTOR_SIZE as usize || &sector[1..6] != STANDARD_ID {
+            return Err(corrupt("volume descriptor: missing standard identifier"));
+        }
+        let kind = sector[0];
+        let joliet = match (kind, &sector[88..91]) {
+            (VD_SUPPLEMENTARY, [0x25, 0x2F, level]) => match level {
+                0x40 => Some(1),
+                0x43 => Some(2),
+                0x45 => Some(3),
+                _ => None,
+            },
+            _ => None,
+        };
+        let volume_id = match joliet {
+            Some(_) => ucs2_name(&sector[40..72]),
+            None => String::from_utf8_lossy(&sector[40..72]).into_owned(),
+        };
+        let block_size = le_u16(sector, 128);
+        if !block_size.is_power_of_two() || !(512..=SECTOR_SIZE as u16).contains(&block_size) {
+            return Err(corrupt(&format!("volume descriptor: logical block size {}", block_size)));
+        }
+        Ok(Self {
+            kind,
+            volume_id: volume_id.trim_end_matches([' ', '\0']).to_string(),
+            volume_space_size: le_u32(sector, 80),
+            block_size,
+            root: DirRecord::parse(&sector[156..190])?,
+            joliet,
+            created: long_time(&sector[813..830]),
+        })
+    }
+}
+
+/// One directory record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirRecord {
+    /// First logical block of the data, after any extended attribute record
+    pub extent: u32,
+    pub size: u32,
+    pub flags: u8,
+    pub recorded: Option<u64>,
+    /// Interleaved files store their data in units with gaps between them
+    pub interleaved: bool,
+    pub name: Vec<u8>,
+    pub system_use: Vec<u8>,
+}
+
+impl DirRecord {
+    /// The record at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        let len = *data.first().ok_or_else(|| corrupt("directory record: empty"))? as usize;
+        if len < DIR_RECORD_MIN || len > data.len() {
+            return Err(corrupt(&format!("directory record: length {}", len)));
+        }
+        let name_len = data[32] as usize;
+        if 33 + name_len > len {
+            return Err(corrupt("directory record: name runs past the record"));
+        }
+        // The name is padded to an even offset
+        let system_use_start = (33 + name_len + 1) & !1;
+        Ok(Self {
+            extent: le_u32(data, 2).saturating_add(u32::from(data[1])),
+            size: le_u32(data, 10),
+            flags: data[25],
+            recorded: record_time(&data[18..25]),
+            interleaved: data[26] != 0 || data[27] != 0,
+            name: data[33..33 + name_len].to_vec(),
+            system_use: data.get(system_use_start..len).unwrap_or_default().to_vec(),
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    /// The `.` and `..` records every directory starts with
+    pub fn is_self_or_parent(&self) -> bool {
+        matches!(self.name.as_slice(), [0] | [1])
+    }
+}
+
+/// One SUSP entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuspEntry {
+    pub signature: [u8; 2],
+    pub data: Vec<u8>,
+}
+
+/// Where a system use area continues (a CE entry)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Continuation {
+    pub block: u32,
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// The SUSP entries of a system use area, and where it continues if it does
+pub fn parse_susp(area: &[u8]) -> (Vec<SuspEntry>, Option<Continuation>) {
+    let mut entries = Vec::new();
+    let mut continuation = None;
+    let mut at = 0;
+    while at + 4 <= area.len() {
+        let len = area[at + 2] as usize;
+        if len < 4 || at + len > area.len() {
+            break;
+        }
+        let entry = SuspEntry { signature: [area[at], area[at + 1]], data: area[at..at + len].to_vec() };
+        match &entry.signature {
+            b"ST" => break,
+            b"CE" if len >= 28 => continuation = Some(Continuation {
+                block: le_u32(&entry.data, 4),
+                offset: le_u32(&entry.data, 12),
+                length: le_u32(&entry.data, 20),
+            }),
+            _ => entries.push(entry),
+        }
+        at += len;
+    }
+    (entries, continuation)
+}
+
+/// The bytes to skip at the start of every system use area, from the root's SP entry;
+/// None when the volume has no SUSP entries
+pub fn susp_skip(root_self: &DirRecord) -> Option<usize> {
+    let area = &root_self.system_use;
+    (area.len() >= 7 && &area[..2] == b"SP" && area[4..6] == [0xBE, 0xEF]).then(|| area[6] as usize)
+}
+
+/// What Rock Ridge says about one file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RockRidge {
+    pub name: Option<String>,
+    pub mode: Option<u32>,
+    pub nlink: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub symlink: Option<String>,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+    pub changed: Option<u64>,
+    /// A directory moved elsewhere to keep the tree within eight levels (CL)
+    pub child_link: Option<u32>,
+    /// The moved directory at its new place, hidden from listings (RE)
+    pub relocated: bool,
+}
+
+impl RockRidge {
+    pub fn from_entries(entries: &[SuspEntry]) -> Self {
+        let mut rr = Self::default();
+        let mut name = String::new();
+        let mut has_name = false;
+        let mut link = String::new();
+        let mut has_link = false;
+        // An SL component whose text continues in the next one
+        let mut link_continues = false;
+        for entry in entries {
+            let data = &entry.data;
+            match &entry.signature {
+                b"NM" if data.len() >= 5 => {
+                    if data[4] & (NM_CURRENT | NM_PARENT) == 0 {
+                        name.push_str(&String::from_utf8_lossy(&data[5..]));
+                        has_name = true;
+                    }
+                }
+                b"PX" if data.len() >= 36 => {
+                    rr.mode = Some(le_u32(data, 4));
+                    rr.nlink = Some(le_u32(data, 12));
+                    rr.uid = Some(le_u32(data, 20));
+                    rr.gid = Some(le_u32(data, 28));
+                }
+                b"SL" if data.len() >= 5 => {
+                    has_link = true;
+                    let mut at = 5;
+                    while at + 2 <= data.len() {
+                        let (flags, len) = (data[at], data[at + 1] as usize);
+                        let Some(content) = data.get(at + 2..at + 2 + len) else {
+                            break;
+                        };
+                        let component = if flags & SL_CURRENT != 0 {
+                            ".".to_string()
+                        } else if flags & SL_PARENT != 0 {
+                            "..".to_string()
+                        } else if flags & SL_ROOT != 0 {
+                            String::new()
+                        } else {
+                            String::from_utf8_lossy(content).into_owned()
+                        };
+                        if !link_continues && (!link.is_empty() || flags & SL_ROOT != 0) && !link.ends_with('/') {
+                            link.push('/');
+                        }
+                        link.push_str(&component);
+                        link_continues = flags & SL_CONTINUE != 0;
+                        at += 2 + len;
+                    }
+                }
+                b"TF" if data.len() >= 5 => {
+                    let flags = data[4];
+                    let width = if flags & TF_LONG_FORM != 0 { 17 } else { 7 };
+                    let mut at = 5;
+                    for (flag, slot) in [
+                        (TF_CREATION, &mut rr.created),
+                        (TF_MODIFY, &mut rr.modified),
+                        (TF_ACCESS, &mut rr.accessed),
+                        (TF_ATTRIBUTES, &mut rr.changed),
+                    ] {
+                        if flags & flag == 0 {
+                            continue;
+                        }
+                        let Some(stamp) = data.get(at..at + width) else {
+                            break;
+                        };
+                        *slot = if width == 17 { long_time(stamp) } else { record_time(stamp) };
+                        at += width;
+                    }
+                }
+                b"CL" if data.len() >= 8 => rr.child_link = Some(le_u32(data, 4)),
+                b"RE" => rr.relocated = true,
+                _ => {}
+            }
+        }
+        rr.name = has_name.then_some(name).filter(|name| !name.is_empty());
+        rr.symlink = has_link.then_some(link);
+        rr
+    }
+
+    pub fn is_directory(&self) -> Option<bool> {
+        self.mode.map(|mode| mode & S_IFMT == S_IFDIR)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) || self.symlink.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_and_times() {
+        assert_eq!(strip_version("README.TXT;1"), "README.TXT");
+        assert_eq!(strip_version("MAKEFILE.;1"), "MAKEFILE");
+        assert_eq!(strip_version("BOOT"), "BOOT");
+        assert_eq!(ucs2_name(&[0, b'A', 0x00, 0xE9]), "Aé");
+        // 2024-01-02 03:04:05 at GMT+1
+        assert_eq!(record_time(&[124, 1, 2, 3, 4, 5, 4]), Some(1_704_164_645 - 3600));
+        assert_eq!(long_time(b"2024010203040500\0"), Some(1_704_164_645));
+        assert_eq!(record_time(&[0; 7]), None);
+    }
+
+    #[test]
+    fn test_rock_ridge_symlink() {
+        // "/usr/../lib" split over two SL entries, the first ending inside a component
+        let sl = |flags: u8, components: &[(u8, &[u8])]| {
+            let mut data = vec![b'S', b'L', 0, 1, flags];
+            for (component_flags, content) in components {
+                data.extend_from_slice(&[*component_flags, content.len() as u8]);
+                data.extend_from_slice(content);
+            }
+            data[2] = data.len() as u8;
+            SuspEntry { signature: *b"SL", data }
+        };
+        let entries = [
+            sl(SL_CONTINUE, &[(SL_ROOT, b""), (0, b"usr"), (SL_PARENT, b""), (SL_CONTINUE, b"li")]),
+            sl(0, &[(0, b"b")]),
+        ];
+        let rr = RockRidge::from_entries(&entries);
+        assert_eq!(rr.symlink.as_deref(), Some("/usr/../lib"));
+        assert!(rr.is_symlink());
+    }
+}
@@ -0,0 +1,5 @@
+// Optical filesystem family - the filesystems of CDs, DVDs and the images made from them
+// ISO9660 is what bootable installer images are built on; Moses reads them so they can be
+// browsed, mounted and, later, written out to USB sticks.
+
+pub mod iso9660;
@@ -0,0 +1,17 @@
+// XFS family - read-only access to XFS volumes on any platform
+// XFS splits a volume into allocation groups, each starting with a copy of the superblock
+// and keeping its own inodes and free space. The reader follows the root inode through
+// directories to files in every on-disk form XFS uses, version 4 and 5 alike; nothing is
+// ever written.
+
+pub mod structures;
+pub mod reader;
+pub mod ops;
+
+pub use reader::XfsReader;
+pub use ops::{XfsOps, XfsDetector};
+
+/// Whether a boot sector is the start of an XFS superblock
+pub fn has_superblock(boot_sector: &[u8]) -> bool {
+    boot_sector.starts_with(structures::MAGIC)
+}
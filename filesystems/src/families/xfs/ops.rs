@@ -0,0 +1,130 @@
+// XFS FilesystemOps implementation for mounting and browsing, read-only
+use super::reader::XfsReader;
+use super::structures::{Inode, MAGIC};
+use crate::device_reader::FilesystemReader;
+use crate::ops::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct XfsOps {
+    reader: Mutex<Option<XfsReader>>,
+}
+
+impl XfsOps {
+    pub fn new() -> Self {
+        Self { reader: Mutex::new(None) }
+    }
+
+    /// Run `f` on the reader with `path` resolved to its inode number
+    fn with_path<T>(&self, path: &Path, f: impl FnOnce(&mut XfsReader, u64) -> Result<T, MosesError>) -> Result<T, MosesError> {
+        let path = path.to_str().ok_or_else(|| MosesError::InvalidInput("Invalid path".to_string()))?;
+        let mut reader = self.reader.lock().unwrap();
+        let reader = reader.as_mut()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let ino = reader.lookup(path)?;
+        f(reader, ino)
+    }
+}
+
+impl Default for XfsOps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attributes(inode: &Inode) -> FileAttributes {
+    FileAttributes {
+        size: if inode.is_directory() { 0 } else { inode.size },
+        is_directory: inode.is_directory(),
+        is_file: inode.is_file(),
+        is_symlink: inode.is_symlink(),
+        created: inode.crtime,
+        modified: Some(inode.mtime),
+        accessed: Some(inode.atime),
+        permissions: u32::from(inode.mode & 0o7777),
+        owner: Some(inode.uid),
+        group: Some(inode.gid),
+    }
+}
+
+impl FilesystemOps for XfsOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        *self.reader.lock().unwrap() = Some(XfsReader::new(device.clone())?);
+        Ok(())
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        let reader = self.reader.lock().unwrap();
+        let reader = reader.as_ref()
+            .ok_or_else(|| MosesError::Other("Filesystem not initialized".to_string()))?;
+        let superblock = reader.superblock();
+        let info = reader.get_info();
+        let free = superblock.fdblocks * u64::from(superblock.blocksize);
+        Ok(FilesystemInfo {
+            total_space: info.total_bytes,
+            free_space: free,
+            available_space: free,
+            total_inodes: superblock.icount,
+            free_inodes: superblock.ifree,
+            block_size: superblock.blocksize,
+            fragment_size: superblock.blocksize,
+            max_filename_length: 255,
+            filesystem_type: info.fs_type,
+            volume_label: info.label,
+            volume_uuid: Some(superblock.uuid()),
+            is_readonly: true,
+        })
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.with_path(path, |reader, ino| Ok(attributes(&reader.inode(ino)?)))
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.with_path(path, |reader, ino| {
+            Ok(reader.list(ino)?.into_iter()
+                .map(|entry| DirectoryEntry { attributes: attributes(&entry.inode), name: entry.name })
+                .collect())
+        })
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.with_path(path, |reader, ino| reader.read(ino, offset, size as u64))
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.with_path(path, |_, ino| Ok(ino)).ok()
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.with_path(path, |reader, ino| reader.read_link(ino).map(PathBuf::from))
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn filesystem_type(&self) -> &str {
+        "xfs"
+    }
+}
+
+/// Finds XFS by the magic at the start of its primary superblock
+pub struct XfsDetector;
+
+impl crate::ops::FilesystemDetector for XfsDetector {
+    fn detect(&self, device: &Device) -> Result<Option<String>, MosesError> {
+        use crate::utils::{open_device_read, read_block};
+
+        let mut file = open_device_read(device)?;
+        match read_block(&mut file, 0, MAGIC.len()) {
+            Ok(magic) if magic == MAGIC => Ok(Some("xfs".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
@@ -0,0 +1,608 @@
+// XFS reader - superblock to inodes to directories and files
+// Opening a volume reads the primary superblock, or the copy at the start of the second
+// allocation group when the primary is damaged. Inodes are found from the allocation
+// group, block and slot their numbers encode. File data is mapped through the inode's
+// extent list or, for fragmented files, its block map B+tree; holes and unwritten extents
+// read as zeros. Directories are read in all their forms: short-form inside the inode,
+// a single block, or data blocks with leaf and free-index blocks above them. On version 5
+// filesystems every inode and metadata block's CRC and owner are checked before use.
+use super::structures::*;
+use crate::device_reader::{AlignedDeviceReader, FileEntry, FileMetadata, FilesystemInfo, FilesystemReader};
+use log::{info, warn};
+use moses_core::{Device, MosesError};
+use std::collections::{BTreeSet, HashMap};
+
+/// Inodes kept in memory; path lookups revisit the same directories
+const INODE_CACHE_LIMIT: usize = 4096;
+/// Block map trees deeper than this are treated as damaged
+const MAX_BMAP_LEVELS: u16 = 9;
+
+/// One name in a directory listing
+#[derive(Debug, Clone)]
+pub struct XfsEntry {
+    pub name: String,
+    pub ino: u64,
+    pub inode: Inode,
+}
+
+/// Read-only XFS volume
+pub struct XfsReader {
+    reader: AlignedDeviceReader,
+    superblock: Superblock,
+    inode_cache: HashMap<u64, Inode>,
+    /// Extents of the file read last, so reads in pieces do not walk its block map each time
+    extent_cache: Option<(u64, Vec<Extent>)>,
+}
+
+impl XfsReader {
+    pub fn new(device: Device) -> Result<Self, MosesError> {
+        info!("Opening XFS filesystem on device: {}", device.name);
+        let file = crate::utils::open_device_with_fallback(&device)?;
+        Self::from_reader(AlignedDeviceReader::new(file))
+    }
+
+    pub fn from_reader(mut reader: AlignedDeviceReader) -> Result<Self, MosesError> {
+        let superblock = read_superblock(&mut reader)?;
+        if superblock.features_incompat & INCOMPAT_NEEDSREPAIR != 0 {
+            warn!("XFS filesystem is marked as needing repair; some files may not read correctly");
+        }
+        Ok(Self { reader, superblock, inode_cache: HashMap::new(), extent_cache: None })
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn root(&self) -> u64 {
+        self.superblock.rootino
+    }
+
+    pub fn inode(&mut self, ino: u64) -> Result<Inode, MosesError> {
+        if let Some(inode) = self.inode_cache.get(&ino) {
+            return Ok(inode.clone());
+        }
+        let offset = self.superblock.inode_offset(ino)?;
+        let data = self.reader.read_at(offset, self.superblock.inodesize as usize)?;
+        let inode = Inode::parse(&data, ino, self.superblock.has_crc())?;
+        if self.inode_cache.len() >= INODE_CACHE_LIMIT {
+            self.inode_cache.clear();
+        }
+        self.inode_cache.insert(ino, inode.clone());
+        Ok(inode)
+    }
+
+    fn read_block(&mut self, fsblock: u64) -> Result<Vec<u8>, MosesError> {
+        let offset = self.superblock.block_offset(fsblock)?;
+        self.reader.read_at(offset, self.superblock.blocksize as usize)
+    }
+
+    /// The data fork's extents in file order
+    fn extents(&mut self, ino: u64, inode: &Inode) -> Result<Vec<Extent>, MosesError> {
+        if let Some((cached, extents)) = &self.extent_cache {
+            if *cached == ino {
+                return Ok(extents.clone());
+            }
+        }
+        let mut extents = match inode.format {
+            FORMAT_EXTENTS => Extent::parse_all(&inode.fork, inode.nextents as usize)
+                .map_err(|e| corrupt(&format!("inode {}: {}", ino, e)))?,
+            FORMAT_BTREE => {
+                let mut extents = Vec::new();
+                self.walk_bmap(ino, parse_bmap_root(&inode.fork)?, &mut extents)?;
+                extents
+            }
+            _ => Vec::new(),
+        };
+        extents.sort_by_key(|extent| extent.offset);
+        self.extent_cache = Some((ino, extents.clone()));
+        Ok(extents)
+    }
+
+    fn walk_bmap(&mut self, ino: u64, node: BmapNode, extents: &mut Vec<Extent>) -> Result<(), MosesError> {
+        let (level, children) = match node {
+            BmapNode::Leaf(records) => {
+                extents.extend(records);
+                return Ok(());
+            }
+            BmapNode::Node { level, children } => (level, children),
+        };
+        if level > MAX_BMAP_LEVELS {
+            return Err(corrupt(&format!("block map of inode {}: {} levels", ino, level)));
+        }
+        for child in children {
+            let block = self.read_block(child)?;
+            let node = parse_bmap_block(&block, self.superblock.has_crc(), ino)?;
+            // Levels must fall by one on the way down, which also rules out loops
+            let child_level = match &node {
+                BmapNode::Leaf(_) => 0,
+                BmapNode::Node { level, .. } => *level,
+            };
+            if child_level + 1 != level {
+                return Err(corrupt(&format!(
+                    "block map of inode {}: level {} below level {}", ino, child_level, level
+                )));
+            }
+            self.walk_bmap(ino, node, extents)?;
+        }
+        Ok(())
+    }
+
+    /// `len` bytes from byte `start` of a file mapped by `extents`
+    fn read_mapped(&mut self, extents: &[Extent], start: u64, len: usize) -> Result<Vec<u8>, MosesError> {
+        let blocksize = u64::from(self.superblock.blocksize);
+        let end = start + len as u64;
+        let mut data = vec![0u8; len];
+        for extent in extents {
+            let extent_start = extent.offset.saturating_mul(blocksize);
+            let extent_end = extent.offset.saturating_add(extent.count).saturating_mul(blocksize);
+            let (from, to) = (start.max(extent_start), end.min(extent_end));
+            if from >= to || extent.unwritten {
+                continue;
+            }
+            // Extents never cross allocation groups; check both ends are in the same one
+            let first = self.superblock.block_offset(extent.block)?;
+            let last = self.superblock.block_offset(extent.block + extent.count - 1)?;
+            if last - first != (extent.count - 1) * blocksize {
+                return Err(corrupt(&format!("extent at block {:#x}: crosses an allocation group", extent.block)));
+            }
+            let piece = self.reader.read_at(first + (from - extent_start), (to - from) as usize)?;
+            let at = (from - start) as usize;
+            data[at..at + piece.len()].copy_from_slice(&piece);
+        }
+        Ok(data)
+    }
+
+    /// The names in directory `ino`, without `.` and `..`
+    fn dir_entries(&mut self, ino: u64, inode: &Inode) -> Result<Vec<DirEntry>, MosesError> {
+        let (has_crc, ftype) = (self.superblock.has_crc(), self.superblock.ftype);
+        match inode.format {
+            FORMAT_LOCAL => parse_shortform_dir(&inode.fork, inode.size, ftype)
+                .map_err(|e| corrupt(&format!("directory {}: {}", ino, e))),
+            FORMAT_EXTENTS | FORMAT_BTREE => {
+                let extents = self.extents(ino, inode)?;
+                let blocksize = u64::from(self.superblock.blocksize);
+                let dir_block = self.superblock.dir_block_size() as u64;
+                // Data blocks are everything mapped below the leaf area
+                let mut starts = BTreeSet::new();
+                for extent in &extents {
+                    let from = extent.offset.saturating_mul(blocksize) / dir_block * dir_block;
+                    let to = extent.offset.saturating_add(extent.count).saturating_mul(blocksize).min(DIR_LEAF_OFFSET);
+                    starts.extend((from..to).step_by(dir_block as usize));
+                }
+                let mut entries = Vec::new();
+                for start in starts {
+                    let block = self.read_mapped(&extents, start, dir_block as usize)?;
+                    entries.extend(parse_dir_block(&block, has_crc, ftype, ino)?);
+                }
+                Ok(entries)
+            }
+            format => Err(corrupt(&format!("directory {}: data fork format {}", ino, format))),
+        }
+    }
+
+    /// The entries of directory `ino`, in the order they are stored
+    pub fn list(&mut self, ino: u64) -> Result<Vec<XfsEntry>, MosesError> {
+        let inode = self.inode(ino)?;
+        if !inode.is_directory() {
+            return Err(MosesError::InvalidInput(format!("XFS inode {} is not a directory", ino)));
+        }
+        self.dir_entries(ino, &inode)?
+            .into_iter()
+            .map(|entry| Ok(XfsEntry { inode: self.inode(entry.ino)?, name: entry.name, ino: entry.ino }))
+            .collect()
+    }
+
+    /// Resolve a `/`-separated path from the root directory
+    pub fn lookup(&mut self, path: &str) -> Result<u64, MosesError> {
+        let mut ino = self.root();
+        for part in path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+            let inode = self.inode(ino)?;
+            if !inode.is_directory() {
+                return Err(MosesError::Other(format!("Path not found: {}", path)));
+            }
+            ino = self.dir_entries(ino, &inode)?
+                .into_iter()
+                .find(|entry| entry.name == part)
+                .ok_or_else(|| MosesError::Other(format!("Path not found: {}", path)))?
+                .ino;
+        }
+        Ok(ino)
+    }
+
+    /// Up to `size` bytes of file `ino` from `offset`
+    pub fn read(&mut self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>, MosesError> {
+        let inode = self.inode(ino)?;
+        if inode.is_realtime() {
+            return Err(MosesError::NotSupported(format!("XFS inode {} keeps its data on the realtime device", ino)));
+        }
+        let end = offset.saturating_add(size).min(inode.size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        match inode.format {
+            FORMAT_LOCAL => inode.fork.get(offset as usize..end as usize).map(<[u8]>::to_vec)
+                .ok_or_else(|| corrupt(&format!("inode {}: inline data is short", ino))),
+            FORMAT_EXTENTS | FORMAT_BTREE => {
+                let extents = self.extents(ino, &inode)?;
+                self.read_mapped(&extents, offset, (end - offset) as usize)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Target of the symbolic link `ino`
+    pub fn read_link(&mut self, ino: u64) -> Result<String, MosesError> {
+        let inode = self.inode(ino)?;
+        if !inode.is_symlink() {
+            return Err(MosesError::InvalidInput(format!("XFS inode {} is not a symbolic link", ino)));
+        }
+        let target = match inode.format {
+            FORMAT_LOCAL => inode.fork.get(..inode.size as usize).map(<[u8]>::to_vec)
+                .ok_or_else(|| corrupt(&format!("symbolic link {}: inline target is short", ino)))?,
+            _ => {
+                let mut target = Vec::new();
+                for extent in self.extents(ino, &inode)? {
+                    for block in extent.block..extent.block + extent.count {
+                        let data = self.read_block(block)?;
+                        target.extend_from_slice(symlink_block_data(&data, self.superblock.has_crc(), ino)?);
+                    }
+                }
+                target.truncate(inode.size as usize);
+                target
+            }
+        };
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    fn file_entry(&mut self, entry: XfsEntry) -> FileEntry {
+        let link_target = if entry.inode.is_symlink() { self.read_link(entry.ino).ok() } else { None };
+        let is_directory = entry.inode.is_directory();
+        FileEntry {
+            name: entry.name,
+            is_directory,
+            size: if is_directory { 0 } else { entry.inode.size },
+            cluster: None,
+            metadata: FileMetadata {
+                link_target,
+                allocated_size: Some(entry.inode.nblocks * u64::from(self.superblock.blocksize)),
+                created: entry.inode.crtime,
+                modified: Some(entry.inode.mtime),
+                accessed: Some(entry.inode.atime),
+                readonly: entry.inode.mode & 0o222 == 0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The primary superblock, or the copy in the second allocation group when the primary
+/// cannot be used
+fn read_superblock(reader: &mut AlignedDeviceReader) -> Result<Superblock, MosesError> {
+    let head = reader.read_at(0, 512)?;
+    let sectsize = if &head[..4] == MAGIC { be_u16(&head, 102).clamp(512, 32768) as usize } else { 512 };
+    let primary = reader.read_at(0, sectsize)?;
+    let error = match Superblock::parse(&primary) {
+        Ok(superblock) => return Ok(superblock),
+        Err(e) => e,
+    };
+    // Only the damaged primary says where the copy is
+    if &primary[..4] != MAGIC || be_u32(&primary, 88) < 2 {
+        return Err(error);
+    }
+    let ag_bytes = u64::from(be_u32(&primary, 84)) * u64::from(be_u32(&primary, 4));
+    let copy = reader.read_at(ag_bytes, sectsize).ok().and_then(|data| Superblock::parse(&data).ok());
+    match copy {
+        Some(superblock) if ag_bytes > 0 => {
+            warn!("XFS primary superblock unusable ({}); using the copy in allocation group 1", error);
+            Ok(superblock)
+        }
+        _ => Err(error),
+    }
+}
+
+impl FilesystemReader for XfsReader {
+    fn read_metadata(&mut self) -> Result<(), MosesError> {
+        // Already read in new()
+        Ok(())
+    }
+
+    fn list_directory(&mut self, path: &str) -> Result<Vec<FileEntry>, MosesError> {
+        let ino = self.lookup(path)?;
+        if !self.inode(ino)?.is_directory() {
+            return Err(MosesError::InvalidInput(format!("Not a directory: {}", path)));
+        }
+        let entries = self.list(ino)?;
+        Ok(entries.into_iter().map(|entry| self.file_entry(entry)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, MosesError> {
+        let ino = self.lookup(path)?;
+        let inode = self.inode(ino)?;
+        if inode.is_directory() {
+            return Err(MosesError::InvalidInput(format!("Is a directory: {}", path)));
+        }
+        self.read(ino, 0, inode.size)
+    }
+
+    fn get_info(&self) -> FilesystemInfo {
+        let blocksize = u64::from(self.superblock.blocksize);
+        FilesystemInfo {
+            fs_type: "xfs".to_string(),
+            label: Some(self.superblock.label.clone()).filter(|label| !label.is_empty()),
+            total_bytes: self.superblock.dblocks * blocksize,
+            used_bytes: self.superblock.dblocks.saturating_sub(self.superblock.fdblocks) * blocksize,
+            cluster_size: Some(self.superblock.blocksize),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const BS: usize = 4096;
+    const INODE_SIZE: usize = 512;
+    const AG_BLOCKS: u64 = 1024;
+    /// Inode numbers: slot 0 of block 8 in allocation group 0 is inode 64
+    const ROOT: u64 = 64;
+    const DOCS: u64 = 65;
+    const HELLO: u64 = 66;
+    const BIG: u64 = 67;
+    const LINK: u64 = 68;
+    const SPARSE: u64 = 69;
+    const MANY: u64 = 70;
+    const LONGLINK: u64 = 71;
+    const FAR: u64 = (1 << 13) | 64;
+
+    fn with_crc(mut block: Vec<u8>, offset: usize) -> Vec<u8> {
+        block[offset..offset + 4].fill(0);
+        let crc = crc32c::crc32c(&block);
+        block[offset..offset + 4].copy_from_slice(&crc.to_le_bytes());
+        block
+    }
+
+    fn extent(offset: u64, block: u64, count: u64, unwritten: bool) -> [u8; 16] {
+        let packed = (u128::from(unwritten) << 127) | (u128::from(offset) << 73) | (u128::from(block) << 21) | u128::from(count);
+        packed.to_be_bytes()
+    }
+
+    fn inode(ino: u64, mode: u16, format: u8, size: u64, nextents: u32, fork: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; INODE_SIZE];
+        data[..2].copy_from_slice(INODE_MAGIC);
+        data[2..4].copy_from_slice(&mode.to_be_bytes());
+        data[4] = 3;
+        data[5] = format;
+        data[8..12].copy_from_slice(&1000u32.to_be_bytes());
+        data[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        data[16..20].copy_from_slice(&1u32.to_be_bytes());
+        for time in [32, 40, 48, 144] {
+            data[time..time + 4].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        }
+        data[56..64].copy_from_slice(&size.to_be_bytes());
+        data[64..72].copy_from_slice(&size.div_ceil(BS as u64).to_be_bytes());
+        data[76..80].copy_from_slice(&nextents.to_be_bytes());
+        data[152..160].copy_from_slice(&ino.to_be_bytes());
+        data[INODE_CORE_V3..INODE_CORE_V3 + fork.len()].copy_from_slice(fork);
+        with_crc(data, INODE_CRC_OFFSET)
+    }
+
+    fn extents_inode(ino: u64, mode: u16, size: u64, extents: &[[u8; 16]]) -> Vec<u8> {
+        inode(ino, mode, FORMAT_EXTENTS, size, extents.len() as u32, &extents.concat())
+    }
+
+    /// A directory data block; block-form ones end in a leaf of `entries` slots and a tail
+    fn dir_block(magic: &[u8; 4], owner: u64, dots: bool, entries: &[(&str, u64, u8)]) -> Vec<u8> {
+        let mut block = vec![0u8; BS];
+        block[..4].copy_from_slice(magic);
+        block[40..48].copy_from_slice(&owner.to_be_bytes());
+        let mut all = Vec::new();
+        if dots {
+            all.extend([(".", owner, FT_DIR), ("..", ROOT, FT_DIR)]);
+        }
+        all.extend_from_slice(entries);
+        let mut at = DIR_HEADER_V5;
+        for (name, ino, ftype) in &all {
+            let length = (8 + 1 + name.len() + 1 + 2).next_multiple_of(8);
+            block[at..at + 8].copy_from_slice(&ino.to_be_bytes());
+            block[at + 8] = name.len() as u8;
+            block[at + 9..at + 9 + name.len()].copy_from_slice(name.as_bytes());
+            block[at + 9 + name.len()] = *ftype;
+            block[at + length - 2..at + length].copy_from_slice(&(at as u16).to_be_bytes());
+            at += length;
+        }
+        let end = if magic == DIR_BLOCK_MAGIC_V5 {
+            block[BS - 8..BS - 4].copy_from_slice(&(all.len() as u32).to_be_bytes());
+            BS - 8 - all.len() * 8
+        } else {
+            BS
+        };
+        block[at..at + 2].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        block[at + 2..at + 4].copy_from_slice(&((end - at) as u16).to_be_bytes());
+        block[end - 2..end].copy_from_slice(&(at as u16).to_be_bytes());
+        with_crc(block, 4)
+    }
+
+    fn shortform(parent: u32, entries: &[(&str, u32, u8)]) -> Vec<u8> {
+        let mut data = vec![entries.len() as u8, 0];
+        data.extend(parent.to_be_bytes());
+        for (i, (name, ino, ftype)) in entries.iter().enumerate() {
+            data.push(name.len() as u8);
+            data.extend((0x60 + i as u16 * 0x10).to_be_bytes());
+            data.extend(name.as_bytes());
+            data.push(*ftype);
+            data.extend(ino.to_be_bytes());
+        }
+        data
+    }
+
+    fn superblock() -> Vec<u8> {
+        let mut sb = vec![0u8; 512];
+        sb[..4].copy_from_slice(MAGIC);
+        sb[4..8].copy_from_slice(&(BS as u32).to_be_bytes());
+        sb[8..16].copy_from_slice(&(2 * AG_BLOCKS).to_be_bytes());
+        sb[32..48].copy_from_slice(&[0x58; 16]);
+        sb[56..64].copy_from_slice(&ROOT.to_be_bytes());
+        sb[84..88].copy_from_slice(&(AG_BLOCKS as u32).to_be_bytes());
+        sb[88..92].copy_from_slice(&2u32.to_be_bytes());
+        sb[100..102].copy_from_slice(&0xB4A5u16.to_be_bytes());
+        sb[102..104].copy_from_slice(&512u16.to_be_bytes());
+        sb[104..106].copy_from_slice(&(INODE_SIZE as u16).to_be_bytes());
+        sb[106..108].copy_from_slice(&8u16.to_be_bytes());
+        sb[108..115].copy_from_slice(b"servers");
+        sb[120..125].copy_from_slice(&[12, 9, 9, 3, 10]);
+        sb[128..136].copy_from_slice(&64u64.to_be_bytes());
+        sb[136..144].copy_from_slice(&50u64.to_be_bytes());
+        sb[144..152].copy_from_slice(&1900u64.to_be_bytes());
+        sb[200..204].copy_from_slice(&0x8Au32.to_be_bytes());
+        sb[216..220].copy_from_slice(&INCOMPAT_FTYPE.to_be_bytes());
+        with_crc(sb, SUPERBLOCK_CRC_OFFSET)
+    }
+
+    fn pattern() -> Vec<u8> {
+        (0..8192u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    const LONG_TARGET: &str = "docs/../many/one";
+    const BIG_SIZE: u64= 4 * BS as u64 - 100;
+
+    /// An 8 MiB v5 volume in two allocation groups: a short-form root, a block-form docs
+    /// directory with a B+tree-mapped file, a sparse file and both kinds of symlink, a
+    /// directory of data blocks, and a file in the second allocation group
+    fn build_image(path: &Path) {
+        let mut image = vec![0u8; 2 * AG_BLOCKS as usize * BS];
+        let mut put = |at: usize, data: &[u8]| image[at..at + data.len()].copy_from_slice(data);
+        let block = |agno: u64, agbno: u64| ((agno * AG_BLOCKS + agbno) as usize) * BS;
+        let slot = |ino: u64| {
+            let agno = ino >> 13;
+            block(agno, (ino & 0x1FFF) >> 3) + (ino as usize & 7) * INODE_SIZE
+        };
+
+        put(0, &superblock());
+        put(block(1, 0), &superblock());
+
+        let root = shortform(ROOT as u32, &[
+            ("hello.txt", HELLO as u32, FT_REG_FILE),
+            ("docs", DOCS as u32, FT_DIR),
+            ("many", MANY as u32, FT_DIR),
+            ("far.txt", FAR as u32, FT_REG_FILE),
+        ]);
+        put(slot(ROOT), &inode(ROOT, 0o40755, FORMAT_LOCAL, root.len() as u64, 0, &root));
+
+        put(slot(DOCS), &extents_inode(DOCS, 0o40755, BS as u64, &[extent(0, 20, 1, false)]));
+        put(block(0, 20), &dir_block(DIR_BLOCK_MAGIC_V5, DOCS, true, &[
+            ("big.bin", BIG, FT_REG_FILE),
+            ("link", LINK, FT_SYMLINK),
+            ("sparse.bin", SPARSE, FT_REG_FILE),
+            ("longlink", LONGLINK, FT_SYMLINK),
+        ]));
+
+        put(slot(HELLO), &extents_inode(HELLO, 0o100644, 11, &[extent(0, 30, 1, false)]));
+        put(block(0, 30), b"Hello, XFS\n");
+
+        // big.bin: a one-level block map tree whose leaf holds three extents
+        let mut fork = vec![0u8; INODE_SIZE - INODE_CORE_V3];
+        fork[..4].copy_from_slice(&[0, 1, 0, 1]);
+        let maxrecs = (fork.len() - 4) / 16;
+        fork[4 + maxrecs * 8..4 + maxrecs * 8 + 8].copy_from_slice(&40u64.to_be_bytes());
+        put(slot(BIG), &inode(BIG, 0o100644, FORMAT_BTREE, BIG_SIZE, 3, &fork));
+        let mut leaf = vec![0u8; BS];
+        leaf[..4].copy_from_slice(BMAP_MAGIC_V5);
+        leaf[6..8].copy_from_slice(&3u16.to_be_bytes());
+        leaf[8..24].fill(0xFF);
+        leaf[56..64].copy_from_slice(&BIG.to_be_bytes());
+        let records = [extent(0, 50, 2, false), extent(2, 52, 1, true), extent(3, 53, 1, false)].concat();
+        leaf[BMAP_HEADER_V5..BMAP_HEADER_V5 + records.len()].copy_from_slice(&records);
+        put(block(0, 40), &with_crc(leaf, 64));
+        put(block(0, 50), &pattern());
+        put(block(0, 52), &[0xEE; BS]);
+        put(block(0, 53), &b"tail".repeat(BS / 4));
+
+        put(slot(LINK), &inode(LINK, 0o120777, FORMAT_LOCAL, 12, 0, b"../hello.txt"));
+
+        put(slot(SPARSE), &extents_inode(SPARSE, 0o100644, 2 * BS as u64, &[extent(1, 56, 1, false)]));
+        put(block(0, 56), b"sparse");
+
+        let mut symlink = vec![0u8; BS];
+        symlink[..4].copy_from_slice(SYMLINK_MAGIC);
+        symlink[8..12].copy_from_slice(&(LONG_TARGET.len() as u32).to_be_bytes());
+        symlink[32..40].copy_from_slice(&LONGLINK.to_be_bytes());
+        symlink[SYMLINK_HEADER_V5..SYMLINK_HEADER_V5 + LONG_TARGET.len()].copy_from_slice(LONG_TARGET.as_bytes());
+        put(slot(LONGLINK), &extents_inode(LONGLINK, 0o120777, LONG_TARGET.len() as u64, &[extent(0, 70, 1, false)]));
+        put(block(0, 70), &with_crc(symlink, 12));
+
+        // many: two data blocks, and a leaf block far above them that listing skips
+        let leaf_block = DIR_LEAF_OFFSET / BS as u64;
+        put(slot(MANY), &extents_inode(MANY, 0o40755, 2 * BS as u64, &[extent(0, 60, 2, false), extent(leaf_block, 62, 1, false)]));
+        put(block(0, 60), &dir_block(DIR_DATA_MAGIC_V5, MANY, true, &[("one", HELLO, FT_REG_FILE), ("two", HELLO, FT_REG_FILE)]));
+        put(block(0, 61), &dir_block(DIR_DATA_MAGIC_V5, MANY, false, &[("three", HELLO, FT_REG_FILE)]));
+        put(block(0, 62), &[0xAB; BS]);
+
+        put(slot(FAR), &extents_inode(FAR, 0o100644, 9, &[extent(0, (1 << 10) | 30, 1, false)]));
+        put(block(1, 30), b"far away\n");
+        std::fs::write(path, image).unwrap();
+    }
+
+    fn names(entries: Vec<FileEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_read_xfs_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("xfs.img");
+        build_image(&path);
+        let device = crate::test_helpers::create_test_device(path.to_str().unwrap(), 0);
+
+        let mut reader = XfsReader::new(device.clone()).unwrap();
+        assert_eq!(reader.get_info().label.as_deref(), Some("servers"));
+        assert_eq!(names(reader.list_directory("/").unwrap()), ["hello.txt", "docs", "many", "far.txt"]);
+        assert_eq!(names(reader.list_directory("/docs").unwrap()), ["big.bin", "link", "sparse.bin", "longlink"]);
+        assert_eq!(names(reader.list_directory("/many").unwrap()), ["one", "two", "three"]);
+        assert_eq!(reader.read_file("/hello.txt").unwrap(), b"Hello, XFS\n");
+        assert_eq!(reader.read_file("/many/three").unwrap(), b"Hello, XFS\n");
+        assert_eq!(reader.read_file("/far.txt").unwrap(), b"far away\n");
+
+        let big = reader.read_file("/docs/big.bin").unwrap();
+        assert_eq!(big.len() as u64, BIG_SIZE);
+        assert_eq!(big[..8192], pattern()[..]);
+        assert!(big[8192..3 * BS].iter().all(|&b| b == 0), "unwritten extents read as zeros");
+        assert!(big[3 * BS..].starts_with(b"tailtail"));
+        let sparse = reader.read_file("/docs/sparse.bin").unwrap();
+        assert!(sparse[..BS].iter().all(|&b| b == 0) && sparse[BS..].starts_with(b"sparse"));
+
+        let docs = reader.list_directory("/docs").unwrap();
+        let target = |name: &str| docs.iter().find(|entry| entry.name == name).unwrap().metadata.link_target.clone();
+        assert_eq!(target("link").as_deref(), Some("../hello.txt"));
+        assert_eq!(target("longlink").as_deref(), Some(LONG_TARGET));
+        assert!(reader.read_file("/missing").is_err());
+        assert!(reader.read_file("/hello.txt/x").is_err());
+
+        // Found by the sniffer and the ops registry
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(crate::detection::detect_filesystem(&mut file).unwrap(), "xfs");
+        let mut registry = crate::ops::FilesystemOpsRegistry::new();
+        crate::ops_registry::register_all_filesystems(&mut registry, false);
+        let mut ops = registry.create_ops(&device, None).unwrap();
+        assert_eq!(ops.filesystem_type(), "xfs");
+        assert_eq!(ops.read(Path::new("/docs/big.bin"), 8190, 4).unwrap(), [pattern()[8190], pattern()[8191], 0, 0]);
+        assert_eq!(ops.readlink(Path::new("/docs/longlink")).unwrap(), Path::new(LONG_TARGET));
+        assert!(ops.stat(Path::new("/docs/link")).unwrap().is_symlink);
+        assert_eq!(ops.directory_id(Path::new("/docs")), Some(DOCS));
+        let info = ops.statfs().unwrap();
+        assert_eq!(info.volume_uuid.as_deref(), Some("58585858-5858-5858-5858-585858585858"));
+        assert_eq!(info.free_space, 1900 * BS as u64);
+
+        // A damaged primary superblock falls back to allocation group 1's copy
+        let mut image = std::fs::read(&path).unwrap();
+        image[150] ^= 0xFF;
+        std::fs::write(&path, &image).unwrap();
+        assert_eq!(XfsReader::new(device.clone()).unwrap().read_file("/far.txt").unwrap(), b"far away\n");
+
+        // A damaged directory block is reported, not read
+        image[20 * BS + 100] ^= 0xFF;
+        std::fs::write(&path, &image).unwrap();
+        let error = XfsReader::new(device).unwrap().list_directory("/docs").unwrap_err().to_string();
+        assert!(error.contains("checksum"), "{}", error);
+    }
+}
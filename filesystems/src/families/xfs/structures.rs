@@ -0,0 +1,528 @@
+// XFS on-disk structures - superblock, inodes, extents and directory blocks
+// Everything is big-endian except the CRCs, which v5 filesystems store little-endian.
+// Structures are parsed field by field from byte slices with their offsets checked, since
+// every one of them comes straight off a disk that may be damaged. Version 4 filesystems
+// have the same layout without the self-describing headers and CRCs that version 5 added.
+use moses_core::MosesError;
+
+pub const MAGIC: &[u8; 4] = b"XFSB";
+pub const INODE_MAGIC: &[u8; 2] = b"IN";
+pub const SUPERBLOCK_CRC_OFFSET: usize = 224;
+pub const INODE_CRC_OFFSET: usize = 100;
+
+/// Superblock version numbers (low nibble of sb_versionnum)
+pub const VERSION_4: u16 = 4;
+pub const VERSION_5: u16 = 5;
+/// Version 4 filesystems must use version 2 directories
+const VERSION_DIRV2BIT: u16 = 0x2000;
+/// Directory entries carry a file type (v4 features2, v5 incompat)
+const VERSION2_FTYPE: u32 = 0x200;
+pub const INCOMPAT_FTYPE: u32 = 1 << 0;
+pub const INCOMPAT_SPINODES: u32 = 1 << 1;
+pub const INCOMPAT_META_UUID: u32 = 1 << 2;
+pub const INCOMPAT_BIGTIME: u32 = 1 << 3;
+pub const INCOMPAT_NEEDSREPAIR: u32 = 1 << 4;
+pub const INCOMPAT_NREXT64: u32 = 1 << 5;
+pub const INCOMPAT_EXCHRANGE: u32 = 1 << 6;
+pub const INCOMPAT_PARENT: u32 = 1 << 7;
+/// Incompatible features that do not change what the reader parses
+const INCOMPAT_UNDERSTOOD: u32 = INCOMPAT_FTYPE | INCOMPAT_SPINODES | INCOMPAT_META_UUID | INCOMPAT_BIGTIME
+    | INCOMPAT_NEEDSREPAIR | INCOMPAT_NREXT64 | INCOMPAT_EXCHRANGE | INCOMPAT_PARENT;
+
+/// Inode data fork formats
+pub const FORMAT_DEV: u8 = 0;
+pub const FORMAT_LOCAL: u8 = 1;
+pub const FORMAT_EXTENTS: u8 = 2;
+pub const FORMAT_BTREE: u8 = 3;
+
+/// Inode core sizes: version 3 (v5 filesystems) and versions 1 and 2
+pub const INODE_CORE_V3: usize = 176;
+pub const INODE_CORE_V2: usize = 100;
+const DIFLAG_REALTIME: u16 = 1 << 0;
+const DIFLAG2_BIGTIME: u64 = 1 << 3;
+const DIFLAG2_NREXT64: u64 = 1 << 4;
+/// Big timestamps count nanoseconds from the smallest 32-bit time, 1901-12-13
+const BIGTIME_EPOCH_OFFSET: i64 = 1 << 31;
+
+/// Block map B+tree blocks
+pub const BMAP_MAGIC_V4: &[u8; 4] = b"BMAP";
+pub const BMAP_MAGIC_V5: &[u8; 4] = b"BMA3";
+pub const BMAP_HEADER_V4: usize = 24;
+pub const BMAP_HEADER_V5: usize = 72;
+const BMAP_CRC_OFFSET: usize = 64;
+const BMAP_OWNER_OFFSET: usize = 56;
+pub const EXTENT_SIZE: usize = 16;
+
+/// Directory data blocks: single-block directories and the data blocks of larger ones
+pub const DIR_BLOCK_MAGIC_V4: &[u8; 4] = b"XD2B";
+pub const DIR_DATA_MAGIC_V4: &[u8; 4] = b"XD2D";
+pub const DIR_BLOCK_MAGIC_V5: &[u8; 4] = b"XDB3";
+pub const DIR_DATA_MAGIC_V5: &[u8; 4] = b"XDD3";
+pub const DIR_HEADER_V4: usize = 16;
+pub const DIR_HEADER_V5: usize = 64;
+const DIR_CRC_OFFSET: usize = 4;
+const DIR_OWNER_OFFSET: usize = 40;
+/// Directory data lives below 32 GiB; leaf and free-index blocks are placed above it
+pub const DIR_LEAF_OFFSET: u64 = 1 << 35;
+const DIR_FREE_TAG: u16 = 0xFFFF;
+/// Block-form tail: leaf entry count and stale count
+const DIR_BLOCK_TAIL: usize = 8;
+const DIR_LEAF_ENTRY: usize = 8;
+
+/// Remote symlink blocks carry a header on v5 filesystems
+pub const SYMLINK_MAGIC: &[u8; 4] = b"XSLM";
+pub const SYMLINK_HEADER_V5: usize = 56;
+
+/// Directory entry file types
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_SYMLINK: u8 = 7;
+
+pub(crate) fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn corrupt(what: &str) -> MosesError {
+    MosesError::Other(format!("Corrupted XFS {}", what))
+}
+
+/// Whether the little-endian CRC32C at `offset` covers `block` with itself zeroed
+pub fn crc_matches(block: &[u8], offset: usize) -> bool {
+    let mut crc = crc32c::crc32c(&block[..offset]);
+    crc = crc32c::crc32c_append(crc, &[0; 4]);
+    crc = crc32c::crc32c_append(crc, &block[offset + 4..]);
+    crc.to_le_bytes() == block[offset..offset + 4]
+}
+
+/// The superblock fields the reader uses
+#[derive(Debug, Clone)]
+pub struct Superblock {
+    pub blocksize: u32,
+    pub dblocks: u64,
+    pub uuid: [u8; 16],
+    pub rootino: u64,
+    pub agblocks: u32,
+    pub agcount: u32,
+    pub version: u16,
+    pub sectsize: u16,
+    pub inodesize: u16,
+    pub label: String,
+    pub inopblog: u8,
+    pub agblklog: u8,
+    pub dirblklog: u8,
+    pub icount: u64,
+    pub ifree: u64,
+    pub fdblocks: u64,
+    pub features_incompat: u32,
+    /// Directory entries carry their file type
+    pub ftype: bool,
+}
+
+impl Superblock {
+    /// Parse and check a superblock copy; `data` holds at least one sector
+    pub fn parse(data: &[u8]) -> Result<Self, MosesError> {
+        if data.len() < 512 || &data[..4] != MAGIC {
+            return Err(MosesError::Other("Not an XFS filesystem".to_string()));
+        }
+        let versionnum = be_u16(data, 100);
+        let version = versionnum & 0xF;
+        let sectsize = be_u16(data, 102);
+        if !sectsize.is_power_of_two() || !(512..=32768).contains(&sectsize) || data.len() < sectsize as usize {
+            return Err(corrupt(&format!("superblock: sector size {}", sectsize)));
+        }
+        match version {
+            VERSION_5 => {
+                if !crc_matches(&data[..sectsize as usize], SUPERBLOCK_CRC_OFFSET) {
+                    return Err(corrupt("superblock: checksum mismatch"));
+                }
+            }
+            VERSION_4 if versionnum & VERSION_DIRV2BIT != 0 => {}
+            VERSION_4 => return Err(MosesError::NotSupported("XFS version 1 directories are not supported".to_string())),
+            _ => return Err(MosesError::NotSupported(format!("XFS superblock version {} is not supported", version))),
+        }
+
+        let blocksize = be_u32(data, 4);
+        let agblocks = be_u32(data, 84);
+        let agcount = be_u32(data, 88);
+        let inodesize = be_u16(data, 104);
+        let inopblock = be_u16(data, 106);
+        let (blocklog, inodelog, inopblog, agblklog, dirblklog) = (data[120], data[122], data[123], data[124], data[192]);
+        let power = |log: u8| 1u32.checked_shl(log.into()).unwrap_or(0);
+        let geometry_ok = (512..=65536).contains(&blocksize) && power(blocklog) == blocksize
+            && (256..=2048).contains(&inodesize) && power(inodelog) == u32::from(inodesize)
+            && power(inopblog) == u32::from(inopblock) && u32::from(inopblock) * u32::from(inodesize) == blocksize
+            && agcount > 0 && agblocks > 0 && agblklog < 32 && u64::from(agblocks) <= 1u64 << agblklog
+            && dirblklog <= 16 && u64::from(blocksize) << dirblklog <= 65536;
+        if !geometry_ok {
+            return Err(corrupt(&format!(
+                "superblock: block size {}, inode size {}, {} allocation groups of {} blocks",
+                blocksize, inodesize, agcount, agblocks
+            )));
+        }
+
+        let features2 = be_u32(data, 200);
+        let features_incompat = if version == VERSION_5 { be_u32(data, 216) } else { 0 };
+        if features_incompat & !INCOMPAT_UNDERSTOOD != 0 {
+            return Err(MosesError::NotSupported(format!(
+                "XFS filesystem uses unknown incompatible features {:#x}", features_incompat & !INCOMPAT_UNDERSTOOD
+            )));
+        }
+        let label = &data[108..120];
+        let label_len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+        Ok(Self {
+            blocksize,
+            dblocks: be_u64(data, 8),
+            uuid: data[32..48].try_into().unwrap(),
+            rootino: be_u64(data, 56),
+            agblocks,
+            agcount,
+            version,
+            sectsize,
+            inodesize,
+            label: String::from_utf8_lossy(&label[..label_len]).into_owned(),
+            inopblog,
+            agblklog,
+            dirblklog,
+            icount: be_u64(data, 128),
+            ifree: be_u64(data, 136),
+            fdblocks: be_u64(data, 144),
+            features_incompat,
+            ftype: if version == VERSION_5 { features_incompat & INCOMPAT_FTYPE != 0 } else { features2 & VERSION2_FTYPE != 0 },
+        })
+    }
+
+    /// Metadata blocks carry CRCs and owner fields
+    pub fn has_crc(&self) -> bool {
+        self.version == VERSION_5
+    }
+
+    pub fn uuid(&self) -> String {
+        uuid::Uuid::from_bytes(self.uuid).to_string()
+    }
+
+    /// Bytes in a directory block
+    pub fn dir_block_size(&self) -> usize {
+        (self.blocksize as usize) << self.dirblklog
+    }
+
+    /// Byte position of the start of allocation group `agno`
+    pub fn ag_offset(&self, agno: u64) -> u64 {
+        agno * u64::from(self.agblocks) * u64::from(self.blocksize)
+    }
+
+    /// Byte position of a filesystem block number (allocation group and block within it)
+    pub fn block_offset(&self, fsblock: u64) -> Result<u64, MosesError> {
+        let agno = fsblock >> self.agblklog;
+        let agbno = fsblock & ((1u64 << self.agblklog) - 1);
+        if agno >= u64::from(self.agcount) || agbno >= u64::from(self.agblocks) {
+            return Err(corrupt(&format!("block number {:#x}: outside the filesystem", fsblock)));
+        }
+        Ok(self.ag_offset(agno) + agbno * u64::from(self.blocksize))
+    }
+
+    /// Byte position of an inode, from the allocation group, block and slot its number encodes
+    pub fn inode_offset(&self, ino: u64) -> Result<u64, MosesError> {
+        let agino_bits = u32::from(self.agblklog) + u32::from(self.inopblog);
+        let agno = ino.checked_shr(agino_bits).unwrap_or(0);
+        let agino = ino & ((1u64 << agino_bits) - 1);
+        let agbno = agino >> self.inopblog;
+        let slot = agino & ((1u64 << self.inopblog) - 1);
+        if agno >= u64::from(self.agcount) || agbno >= u64::from(self.agblocks) {
+            return Err(corrupt(&format!("inode number {}: outside the filesystem", ino)));
+        }
+        Ok(self.ag_offset(agno) + agbno * u64::from(self.blocksize) + slot * u64::from(self.inodesize))
+    }
+}
+
+/// An inode's core fields and its data fork
+#[derive(Debug, Clone)]
+pub struct Inode {
+    pub mode: u16,
+    pub format: u8,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub crtime: Option<u64>,
+    pub size: u64,
+    pub nblocks: u64,
+    /// Extent records in the data fork (extents format)
+    pub nextents: u64,
+    pub flags: u16,
+    /// The data fork: inline data, extent records or the root of the block map tree
+    pub fork: Vec<u8>,
+}
+
+impl Inode {
+    /// Parse and check the inode `ino`; `data` is the whole inode
+    pub fn parse(data: &[u8], ino: u64, has_crc: bool) -> Result<Self, MosesError> {
+        let bad = |what: &str| corrupt(&format!("inode {}: {}", ino, what));
+        if data.len() < INODE_CORE_V3 || &data[..2] != INODE_MAGIC {
+            return Err(bad("bad magic"));
+        }
+        let version = data[4];
+        let core = match (version, has_crc) {
+            (3, true) => {
+                if !crc_matches(data, INODE_CRC_OFFSET) {
+                    return Err(bad("checksum mismatch"));
+                }
+                if be_u64(data, 152) != ino {
+                    return Err(bad(&format!("it says it is inode {}", be_u64(data, 152))));
+                }
+                INODE_CORE_V3
+            }
+            (1 | 2, false) => INODE_CORE_V2,
+            _ => return Err(bad(&format!("version {} on a version {} filesystem", version, if has_crc { 5 } else { 4 }))),
+        };
+
+        let flags2 = if version == 3 { be_u64(data, 120) } else { 0 };
+        let time = |offset: usize| -> u64 {
+            if flags2 & DIFLAG2_BIGTIME != 0 {
+                (be_u64(data, offset) / 1_000_000_000) as i64 - BIGTIME_EPOCH_OFFSET
+            } else {
+                i64::from(be_u32(data, offset) as i32)
+            }
+            .max(0) as u64
+        };
+        let nextents = if flags2 & DIFLAG2_NREXT64 != 0 { be_u64(data, 24) } else { u64::from(be_u32(data, 76)) };
+        let forkoff = data[82] as usize * 8;
+        let fork_end = if forkoff == 0 { data.len() } else { core + forkoff };
+        if fork_end > data.len() {
+            return Err(bad(&format!("attribute fork at {} bytes", forkoff)));
+        }
+        Ok(Self {
+            mode: be_u16(data, 2),
+            format: data[5],
+            uid: be_u32(data, 8),
+            gid: be_u32(data, 12),
+            nlink: if version == 1 { u32::from(be_u16(data, 6)) } else { be_u32(data, 16) },
+            atime: time(32),
+            mtime: time(40),
+            ctime: time(48),
+            crtime: (version == 3).then(|| time(144)),
+            size: be_u64(data, 56),
+            nblocks: be_u64(data, 64),
+            nextents,
+            flags: be_u16(data, 90),
+            fork: data[core..fork_end].to_vec(),
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & 0o170000 == 0o120000
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & 0o170000 == 0o100000
+    }
+
+    /// Data kept on the realtime device, which is not read
+    pub fn is_realtime(&self) -> bool {
+        self.flags & DIFLAG_REALTIME != 0
+    }
+}
+
+/// A run of file blocks mapped to filesystem blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// First file block
+    pub offset: u64,
+    /// First filesystem block
+    pub block: u64,
+    pub count: u64,
+    /// Allocated but never written; reads as zeros
+    pub unwritten: bool,
+}
+
+impl Extent {
+    /// Unpack a 128-bit record: flag, 54-bit file offset, 52-bit block, 21-bit length
+    pub fn parse(data: &[u8]) -> Self {
+        let packed = u128::from_be_bytes(data[..EXTENT_SIZE].try_into().unwrap());
+        Self {
+            unwritten: packed >> 127 != 0,
+            offset: ((packed >> 73) & ((1 << 54) - 1)) as u64,
+            block: ((packed >> 21) & ((1 << 52) - 1)) as u64,
+            count: (packed & ((1 << 21) - 1)) as u64,
+        }
+    }
+
+    pub fn parse_all(data: &[u8], count: usize) -> Result<Vec<Self>, MosesError> {
+        if count.checked_mul(EXTENT_SIZE).is_none_or(|size| size > data.len()) {
+            return Err(corrupt(&format!("extent list: {} records do not fit", count)));
+        }
+        Ok(data[..count * EXTENT_SIZE].as_chunks::<EXTENT_SIZE>().0.iter().map(|record| Self::parse(record)).collect())
+    }
+}
+
+/// A node of the block map B+tree: extent records in leaves, child blocks above them
+#[derive(Debug, Clone)]
+pub enum BmapNode {
+    Leaf(Vec<Extent>),
+    Node { level: u16, children: Vec<u64> },
+}
+
+/// The tree root kept in the inode's data fork: level, count, then keys and pointers
+/// sized to fill the fork
+pub fn parse_bmap_root(fork: &[u8]) -> Result<BmapNode, MosesError> {
+    if fork.len() < 4 {
+        return Err(corrupt("block map root: no room for its header"));
+    }
+    let (level, numrecs) = (be_u16(fork, 0), be_u16(fork, 2) as usize);
+    if level == 0 {
+        return Ok(BmapNode::Leaf(Extent::parse_all(&fork[4..], numrecs)?));
+    }
+    let maxrecs = (fork.len() - 4) / 16;
+    bmap_children(&fork[4..], level, numrecs, maxrecs)
+}
+
+/// A block of the block map B+tree below the root
+pub fn parse_bmap_block(block: &[u8], has_crc: bool, owner: u64) -> Result<BmapNode, MosesError> {
+    let (magic, header) = if has_crc { (BMAP_MAGIC_V5, BMAP_HEADER_V5) } else { (BMAP_MAGIC_V4, BMAP_HEADER_V4) };
+    if block.len() < header || &block[..4] != magic {
+        return Err(corrupt(&format!("block map of inode {}: bad magic", owner)));
+    }
+    if has_crc {
+        if !crc_matches(block, BMAP_CRC_OFFSET) {
+            return Err(corrupt(&format!("block map of inode {}: checksum mismatch", owner)));
+        }
+        if be_u64(block, BMAP_OWNER_OFFSET) != owner {
+            return Err(corrupt(&format!("block map of inode {}: block belongs to inode {}", owner, be_u64(block, BMAP_OWNER_OFFSET))));
+        }
+    }
+    let (level, numrecs) = (be_u16(block, 4), be_u16(block, 6) as usize);
+    let body = &block[header..];
+    if level == 0 {
+        return Ok(BmapNode::Leaf(Extent::parse_all(body, numrecs)?));
+    }
+    bmap_children(body, level, numrecs, body.len() / 16)
+}
+
+/// Child pointers of an interior node: `maxrecs` keys, then as many pointers
+fn bmap_children(body: &[u8], level: u16, numrecs: usize, maxrecs: usize) -> Result<BmapNode, MosesError> {
+    if numrecs > maxrecs {
+        return Err(corrupt(&format!("block map node: {} records where {} fit", numrecs, maxrecs)));
+    }
+    let pointers = maxrecs * 8;
+    Ok(BmapNode::Node { level, children: (0..numrecs).map(|i| be_u64(body, pointers + i * 8)).collect() })
+}
+
+/// One name in a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+}
+
+/// Entries of a short-form directory kept inside its inode; `.` and `..` are implicit
+pub fn parse_shortform_dir(fork: &[u8], size: u64, ftype: bool) -> Result<Vec<DirEntry>, MosesError> {
+    let data = fork.get(..size as usize).ok_or_else(|| corrupt("short-form directory: larger than its inode"))?;
+    let short = || corrupt("short-form directory: entries run past its end");
+    // Inode numbers are all 8 bytes once any entry needs more than 4
+    let (count, i8count) = (*data.first().ok_or_else(short)?, *data.get(1).ok_or_else(short)?);
+    let ino_size = if i8count == 0 { 4 } else { 8 };
+    let mut at = 2 + ino_size;
+    let mut result = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let namelen = *data.get(at).ok_or_else(short)? as usize;
+        let name_at = at + 3;
+        let ino_at = name_at + namelen + usize::from(ftype);
+        let ino = data.get(ino_at..ino_at + ino_size).ok_or_else(short)?;
+        result.push(DirEntry {
+            name: String::from_utf8_lossy(&data[name_at..name_at + namelen]).into_owned(),
+            ino: ino.iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b)),
+        });
+        at = ino_at + ino_size;
+    }
+    Ok(result)
+}
+
+/// Entries of a directory data block (or the single block of a block-form directory),
+/// without `.` and `..`
+pub fn parse_dir_block(block: &[u8], has_crc: bool, ftype: bool, owner: u64) -> Result<Vec<DirEntry>, MosesError> {
+    let bad = |what: String| corrupt(&format!("directory {}: {}", owner, what));
+    let (block_magic, data_magic, header) = if has_crc {
+        (DIR_BLOCK_MAGIC_V5, DIR_DATA_MAGIC_V5, DIR_HEADER_V5)
+    } else {
+        (DIR_BLOCK_MAGIC_V4, DIR_DATA_MAGIC_V4, DIR_HEADER_V4)
+    };
+    let block_form = &block[..4] == block_magic;
+    if !block_form && &block[..4] != data_magic {
+        return Err(bad("bad data block magic".to_string()));
+    }
+    if has_crc {
+        if !crc_matches(block, DIR_CRC_OFFSET) {
+            return Err(bad("checksum mismatch".to_string()));
+        }
+        if be_u64(block, DIR_OWNER_OFFSET) != owner {
+            return Err(bad(format!("data block belongs to inode {}", be_u64(block, DIR_OWNER_OFFSET))));
+        }
+    }
+
+    // A block-form directory keeps its hash leaf and a tail at the end of the block
+    let end = if block_form {
+        let leaf_count = be_u32(block, block.len() - DIR_BLOCK_TAIL) as usize;
+        leaf_count.checked_mul(DIR_LEAF_ENTRY)
+            .and_then(|leaf| block.len().checked_sub(DIR_BLOCK_TAIL + leaf))
+            .filter(|&end| end >= header)
+            .ok_or_else(|| bad(format!("{} leaf entries do not fit", leaf_count)))?
+    } else {
+        block.len()
+    };
+
+    let mut entries = Vec::new();
+    let mut at = header;
+    while at < end {
+        if at + 4 > end {
+            return Err(bad(format!("entry at {} runs past the data", at)));
+        }
+        if be_u16(block, at) == DIR_FREE_TAG {
+            let length = be_u16(block, at + 2) as usize;
+            if length == 0 || !length.is_multiple_of(8) || at + length > end {
+                return Err(bad(format!("free space of {} bytes at {}", length, at)));
+            }
+            at += length;
+            continue;
+        }
+        if at + 9 > end {
+            return Err(bad(format!("entry at {} runs past the data", at)));
+        }
+        let namelen = block[at + 8] as usize;
+        let length = (8 + 1 + namelen + usize::from(ftype) + 2).next_multiple_of(8);
+        if namelen == 0 || at + length > end {
+            return Err(bad(format!("entry at {} runs past the data", at)));
+        }
+        let name = &block[at + 9..at + 9 + namelen];
+        if name != b"." && name != b".." {
+            entries.push(DirEntry { name: String::from_utf8_lossy(name).into_owned(), ino: be_u64(block, at) });
+        }
+        at += length;
+    }
+    Ok(entries)
+}
+
+/// The target bytes in one block of a remote symlink
+pub fn symlink_block_data(block: &[u8], has_crc: bool, owner: u64) -> Result<&[u8], MosesError> {
+    if !has_crc {
+        return Ok(block);
+    }
+    if &block[..4] != SYMLINK_MAGIC || !crc_matches(block, 12) || be_u64(block, 32) != owner {
+        return Err(corrupt(&format!("symbolic link {}: bad block header or checksum", owner)));
+    }
+    let bytes = be_u32(block, 8) as usize;
+    block.get(SYMLINK_HEADER_V5..SYMLINK_HEADER_V5 + bytes)
+        .ok_or_else(|| corrupt(&format!("symbolic link {}: {} bytes do not fit its block", owner, bytes)))
+}
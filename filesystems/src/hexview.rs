@@ -0,0 +1,516 @@
+// Read-only hex viewer for diagnostics
+// Dumps raw bytes from a device and overlays field annotations for the
+// on-disk structures we know how to recognise (MBR, GPT, boot sectors, ext superblock)
+
+use std::io::{Read, Seek, SeekFrom};
+use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::device_reader::AlignedDeviceReader;
+
+/// Upper bound on a single dump so the GUI can't ask for gigabytes at once
+pub const MAX_HEXDUMP_LEN: usize = 1024 * 1024;
+
+/// How a field's bytes should be decoded for display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    Ascii,
+    Guid,
+    Bytes,
+}
+
+/// A single field in a structure layout, relative to the structure start
+#[derive(Debug, Clone, Copy)]
+pub struct FieldDef {
+    pub offset: usize,
+    pub length: usize,
+    pub name: &'static str,
+    pub kind: FieldKind,
+}
+
+/// Field layout of a known on-disk structure
+#[derive(Debug)]
+pub struct StructureLayout {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: &'static [FieldDef],
+}
+
+pub(crate) const fn field(offset: usize, length: usize, name: &'static str, kind: FieldKind) -> FieldDef {
+    FieldDef { offset, length, name, kind }
+}
+
+pub static MBR_LAYOUT: StructureLayout = StructureLayout {
+    name: "MBR",
+    size: 512,
+    fields: &[
+        field(0x000, 440, "boot_code", FieldKind::Bytes),
+        field(0x1B8, 4, "disk_signature", FieldKind::U32),
+        field(0x1BC, 2, "reserved", FieldKind::U16),
+        field(0x1BE, 1, "partition[0].status", FieldKind::U8),
+        field(0x1C2, 1, "partition[0].type", FieldKind::U8),
+        field(0x1C6, 4, "partition[0].start_lba", FieldKind::U32),
+        field(0x1CA, 4, "partition[0].sectors", FieldKind::U32),
+        field(0x1CE, 1, "partition[1].status", FieldKind::U8),
+        field(0x1D2, 1, "partition[1].type", FieldKind::U8),
+        field(0x1D6, 4, "partition[1].start_lba", FieldKind::U32),
+        field(0x1DA, 4, "partition[1].sectors", FieldKind::U32),
+        field(0x1DE, 1, "partition[2].status", FieldKind::U8),
+        field(0x1E2, 1, "partition[2].type", FieldKind::U8),
+        field(0x1E6, 4, "partition[2].start_lba", FieldKind::U32),
+        field(0x1EA, 4, "partition[2].sectors", FieldKind::U32),
+        field(0x1EE, 1, "partition[3].status", FieldKind::U8),
+        field(0x1F2, 1, "partition[3].type", FieldKind::U8),
+        field(0x1F6, 4, "partition[3].start_lba", FieldKind::U32),
+        field(0x1FA, 4, "partition[3].sectors", FieldKind::U32),
+        field(0x1FE, 2, "boot_signature", FieldKind::U16),
+    ],
+};
+
+pub static GPT_HEADER_LAYOUT: StructureLayout = StructureLayout {
+    name: "GPT header",
+    size: 92,
+    fields: &[
+        field(0, 8, "signature", FieldKind::Ascii),
+        field(8, 4, "revision", FieldKind::U32),
+        field(12, 4, "header_size", FieldKind::U32),
+        field(16, 4, "header_crc32", FieldKind::U32),
+        field(24, 8, "current_lba", FieldKind::U64),
+        field(32, 8, "backup_lba", FieldKind::U64),
+        field(40, 8, "first_usable_lba", FieldKind::U64),
+        field(48, 8, "last_usable_lba", FieldKind::U64),
+        field(56, 16, "disk_guid", FieldKind::Guid),
+        field(72, 8, "partition_entries_lba", FieldKind::U64),
+        field(80, 4, "num_partition_entries", FieldKind::U32),
+        field(84, 4, "partition_entry_size", FieldKind::U32),
+        field(88, 4, "partition_entries_crc32", FieldKind::U32),
+    ],
+};
+
+pub static FAT_BOOT_SECTOR_LAYOUT: StructureLayout = StructureLayout {
+    name: "FAT boot sector",
+    size: 512,
+    fields: &[
+        field(0, 3, "jump_boot", FieldKind::Bytes),
+        field(3, 8, "oem_name", FieldKind::Ascii),
+        field(11, 2, "bytes_per_sector", FieldKind::U16),
+        field(13, 1, "sectors_per_cluster", FieldKind::U8),
+        field(14, 2, "reserved_sectors", FieldKind::U16),
+        field(16, 1, "num_fats", FieldKind::U8),
+        field(17, 2, "root_entries", FieldKind::U16),
+        field(19, 2, "total_sectors_16", FieldKind::U16),
+        field(21, 1, "media", FieldKind::U8),
+        field(22, 2, "sectors_per_fat_16", FieldKind::U16),
+        field(24, 2, "sectors_per_track", FieldKind::U16),
+        field(26, 2, "num_heads", FieldKind::U16),
+        field(28, 4, "hidden_sectors", FieldKind::U32),
+        field(32, 4, "total_sectors_32", FieldKind::U32),
+        field(510, 2, "boot_signature", FieldKind::U16),
+    ],
+};
+
+pub static FAT32_EXTENDED_BPB_LAYOUT: StructureLayout = StructureLayout {
+    name: "FAT32 extended BPB",
+    size: 90,
+    fields: &[
+        field(36, 4, "sectors_per_fat_32", FieldKind::U32),
+        field(40, 2, "ext_flags", FieldKind::U16),
+        field(42, 2, "fs_version", FieldKind::U16),
+        field(44, 4, "root_cluster", FieldKind::U32),
+        field(48, 2, "fs_info_sector", FieldKind::U16),
+        field(50, 2, "backup_boot_sector", FieldKind::U16),
+        field(64, 1, "drive_number", FieldKind::U8),
+        field(66, 1, "boot_signature", FieldKind::U8),
+        field(67, 4, "volume_id", FieldKind::U32),
+        field(71, 11, "volume_label", FieldKind::Ascii),
+        field(82, 8, "fs_type", FieldKind::Ascii),
+    ],
+};
+
+pub static FAT16_EXTENDED_BPB_LAYOUT: StructureLayout = StructureLayout {
+    name: "FAT12/16 extended BPB",
+    size: 62,
+    fields: &[
+        field(36, 1, "drive_number", FieldKind::U8),
+        field(38, 1, "boot_signature", FieldKind::U8),
+        field(39, 4, "volume_id", FieldKind::U32),
+        field(43, 11, "volume_label", FieldKind::Ascii),
+        field(54, 8, "fs_type", FieldKind::Ascii),
+    ],
+};
+
+pub static EXFAT_BOOT_SECTOR_LAYOUT: StructureLayout = StructureLayout {
+    name: "exFAT boot sector",
+    size: 512,
+    fields: &[
+        field(0, 3, "jump_boot", FieldKind::Bytes),
+        field(3, 8, "file_system_name", FieldKind::Ascii),
+        field(64, 8, "partition_offset", FieldKind::U64),
+        field(72, 8, "volume_length", FieldKind::U64),
+        field(80, 4, "fat_offset", FieldKind::U32),
+        field(84, 4, "fat_length", FieldKind::U32),
+        field(88, 4, "cluster_heap_offset", FieldKind::U32),
+        field(92, 4, "cluster_count", FieldKind::U32),
+        field(96, 4, "first_cluster_of_root", FieldKind::U32),
+        field(100, 4, "volume_serial_number", FieldKind::U32),
+        field(104, 2, "file_system_revision", FieldKind::U16),
+        field(106, 2, "volume_flags", FieldKind::U16),
+        field(108, 1, "bytes_per_sector_shift", FieldKind::U8),
+        field(109, 1, "sectors_per_cluster_shift", FieldKind::U8),
+        field(110, 1, "number_of_fats", FieldKind::U8),
+        field(111, 1, "drive_select", FieldKind::U8),
+        field(112, 1, "percent_in_use", FieldKind::U8),
+        field(510, 2, "boot_signature", FieldKind::U16),
+    ],
+};
+
+pub static NTFS_BOOT_SECTOR_LAYOUT: StructureLayout = StructureLayout {
+    name: "NTFS boot sector",
+    size: 512,
+    fields: &[
+        field(0, 3, "jump_boot", FieldKind::Bytes),
+        field(3, 8, "oem_id", FieldKind::Ascii),
+        field(11, 2, "bytes_per_sector", FieldKind::U16),
+        field(13, 1, "sectors_per_cluster", FieldKind::U8),
+        field(21, 1, "media", FieldKind::U8),
+        field(28, 4, "hidden_sectors", FieldKind::U32),
+        field(40, 8, "total_sectors", FieldKind::U64),
+        field(48, 8, "mft_cluster", FieldKind::U64),
+        field(56, 8, "mft_mirror_cluster", FieldKind::U64),
+        field(64, 1, "clusters_per_mft_record", FieldKind::U8),
+        field(68, 1, "clusters_per_index_record", FieldKind::U8),
+        field(72, 8, "volume_serial_number", FieldKind::U64),
+        field(510, 2, "boot_signature", FieldKind::U16),
+    ],
+};
+
+pub static EXT_SUPERBLOCK_LAYOUT: StructureLayout = StructureLayout {
+    name: "ext superblock",
+    size: 1024,
+    fields: &[
+        field(0, 4, "s_inodes_count", FieldKind::U32),
+        field(4, 4, "s_blocks_count_lo", FieldKind::U32),
+        field(8, 4, "s_r_blocks_count_lo", FieldKind::U32),
+        field(12, 4, "s_free_blocks_count_lo", FieldKind::U32),
+        field(16, 4, "s_free_inodes_count", FieldKind::U32),
+        field(20, 4, "s_first_data_block", FieldKind::U32),
+        field(24, 4, "s_log_block_size", FieldKind::U32),
+        field(32, 4, "s_blocks_per_group", FieldKind::U32),
+        field(40, 4, "s_inodes_per_group", FieldKind::U32),
+        field(44, 4, "s_mtime", FieldKind::U32),
+        field(48, 4, "s_wtime", FieldKind::U32),
+        field(52, 2, "s_mnt_count", FieldKind::U16),
+        field(56, 2, "s_magic", FieldKind::U16),
+        field(58, 2, "s_state", FieldKind::U16),
+        field(60, 2, "s_errors", FieldKind::U16),
+        field(76, 4, "s_rev_level", FieldKind::U32),
+        field(84, 4, "s_first_ino", FieldKind::U32),
+        field(88, 2, "s_inode_size", FieldKind::U16),
+        field(92, 4, "s_feature_compat", FieldKind::U32),
+        field(96, 4, "s_feature_incompat", FieldKind::U32),
+        field(100, 4, "s_feature_ro_compat", FieldKind::U32),
+        field(104, 16, "s_uuid", FieldKind::Guid),
+        field(120, 16, "s_volume_name", FieldKind::Ascii),
+        field(254, 2, "s_desc_size", FieldKind::U16),
+        field(336, 4, "s_blocks_count_hi", FieldKind::U32),
+        field(1020, 4, "s_checksum", FieldKind::U32),
+    ],
+};
+
+/// A field annotation overlaid on the hex dump, with an absolute device offset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldAnnotation {
+    pub offset: u64,
+    pub length: usize,
+    pub structure: String,
+    pub field: String,
+    pub value: String,
+}
+
+/// Result of a hex dump request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HexDump {
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub annotations: Vec<FieldAnnotation>,
+}
+
+/// A known structure found on the device, together with its raw bytes
+#[derive(Debug)]
+pub struct DetectedStructure {
+    pub offset: u64,
+    pub layout: &'static StructureLayout,
+    pub raw: Vec<u8>,
+}
+
+impl DetectedStructure {
+    /// Annotations for every field of this structure
+    pub fn annotations(&self) -> Vec<FieldAnnotation> {
+        self.layout.fields.iter()
+            .filter(|f| f.offset + f.length <= self.raw.len())
+            .map(|f| FieldAnnotation {
+                offset: self.offset + f.offset as u64,
+                length: f.length,
+                structure: self.layout.name.to_string(),
+                field: f.name.to_string(),
+                value: decode_field(&self.raw[f.offset..f.offset + f.length], f.kind),
+            })
+            .collect()
+    }
+}
+
+/// Decode raw field bytes into a display string
+pub fn decode_field(bytes: &[u8], kind: FieldKind) -> String {
+    match kind {
+        FieldKind::U8 => format!("{} (0x{:02X})", bytes[0], bytes[0]),
+        FieldKind::U16 => {
+            let v = u16::from_le_bytes([bytes[0], bytes[1]]);
+            format!("{} (0x{:04X})", v, v)
+        }
+        FieldKind::U32 => {
+            let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            format!("{} (0x{:08X})", v, v)
+        }
+        FieldKind::U64 => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[..8]);
+            let v = u64::from_le_bytes(b);
+            format!("{} (0x{:016X})", v, v)
+        }
+        FieldKind::Ascii => {
+            let s: String = bytes.iter()
+                .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+                .collect();
+            format!("\"{}\"", s.trim_end_matches(['.', ' ']))
+        }
+        FieldKind::Guid => {
+            // Mixed-endian GUID as used by GPT; ext UUIDs are printed byte-wise
+            format!(
+                "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+                bytes[3], bytes[2], bytes[1], bytes[0], bytes[5], bytes[4], bytes[7], bytes[6],
+                bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15]
+            )
+        }
+        FieldKind::Bytes => {
+            let shown: Vec<String> = bytes.iter().take(16).map(|b| format!("{:02X}", b)).collect();
+            if bytes.len() > 16 {
+                format!("{} ... ({} bytes)", shown.join(" "), bytes.len())
+            } else {
+                shown.join(" ")
+            }
+        }
+    }
+}
+
+fn read_exact_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    reader.seek(SeekFrom::Start(offset)).ok()?;
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// Identify a volume boot record / superblock that starts at `base`
+fn detect_volume_structures<R: Read + Seek>(reader: &mut R, base: u64, found: &mut Vec<DetectedStructure>) {
+    if let Some(sector) = read_exact_at(reader, base, 512) {
+        let has_jump = sector[0] == 0xEB || sector[0] == 0xE9;
+        if &sector[3..11] == b"NTFS    " {
+            found.push(DetectedStructure { offset: base, layout: &NTFS_BOOT_SECTOR_LAYOUT, raw: sector });
+        } else if &sector[3..11] == b"EXFAT   " {
+            found.push(DetectedStructure { offset: base, layout: &EXFAT_BOOT_SECTOR_LAYOUT, raw: sector });
+        } else if has_jump && &sector[82..87] == b"FAT32" {
+            found.push(DetectedStructure { offset: base, layout: &FAT32_EXTENDED_BPB_LAYOUT, raw: sector.clone() });
+            found.push(DetectedStructure { offset: base, layout: &FAT_BOOT_SECTOR_LAYOUT, raw: sector });
+        } else if has_jump && &sector[54..57] == b"FAT" {
+            found.push(DetectedStructure { offset: base, layout: &FAT16_EXTENDED_BPB_LAYOUT, raw: sector.clone() });
+            found.push(DetectedStructure { offset: base, layout: &FAT_BOOT_SECTOR_LAYOUT, raw: sector });
+        }
+    }
+
+    if let Some(sb) = read_exact_at(reader, base + 1024, 1024) {
+        if u16::from_le_bytes([sb[56], sb[57]]) == 0xEF53 {
+            found.push(DetectedStructure { offset: base + 1024, layout: &EXT_SUPERBLOCK_LAYOUT, raw: sb });
+        }
+    }
+}
+
+/// Find the known structures on a device: partition tables plus the
+/// boot sector / superblock at the start of the device and of each partition
+pub fn detect_structures<R: Read + Seek>(reader: &mut R) -> Result<Vec<DetectedStructure>, MosesError> {
+    let mut found = Vec::new();
+
+    let sector0 = read_exact_at(reader, 0, 512)
+        .ok_or_else(|| MosesError::Other("Failed to read sector 0".to_string()))?;
+
+    let has_signature = sector0[0x1FE] == 0x55 && sector0[0x1FF] == 0xAA;
+    let is_vbr = &sector0[3..11] == b"NTFS    " || &sector0[3..11] == b"EXFAT   "
+        || &sector0[82..87] == b"FAT32" || &sector0[54..57] == b"FAT";
+
+    let mut partition_starts = Vec::new();
+
+    if has_signature && !is_vbr {
+        let mut protective = false;
+        for i in 0..4 {
+            let entry = 0x1BE + i * 16;
+            let ptype = sector0[entry + 4];
+            let start = u32::from_le_bytes([
+                sector0[entry + 8], sector0[entry + 9], sector0[entry + 10], sector0[entry + 11],
+            ]) as u64;
+            match ptype {
+                0x00 => {}
+                0xEE => protective = true,
+                0x05 | 0x0F | 0x85 => {} // extended partitions carry EBRs, not volumes
+                _ if start > 0 => partition_starts.push(start * 512),
+                _ => {}
+            }
+        }
+        found.push(DetectedStructure { offset: 0, layout: &MBR_LAYOUT, raw: sector0 });
+
+        if protective {
+            if let Some(header) = read_exact_at(reader, 512, 512) {
+                if &header[0..8] == b"EFI PART" {
+                    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+                    let count = u32::from_le_bytes(header[80..84].try_into().unwrap()).min(128) as usize;
+                    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+                    found.push(DetectedStructure { offset: 512, layout: &GPT_HEADER_LAYOUT, raw: header });
+
+                    if (128..=4096).contains(&entry_size) {
+                        if let Some(entries) = read_exact_at(reader, entries_lba * 512, count * entry_size) {
+                            for entry in entries.chunks(entry_size) {
+                                if entry[0..16].iter().any(|&b| b != 0) {
+                                    let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+                                    partition_starts.push(first_lba * 512);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        // Superfloppy: the volume starts at offset 0
+        partition_starts.push(0);
+    }
+
+    for start in partition_starts {
+        detect_volume_structures(reader, start, &mut found);
+    }
+
+    Ok(found)
+}
+
+/// Read `len` bytes at `offset` and annotate every known field overlapping that range
+pub fn hexdump<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<HexDump, MosesError> {
+    if len > MAX_HEXDUMP_LEN {
+        return Err(MosesError::InvalidInput(format!(
+            "Hex dump length {} exceeds maximum of {} bytes", len, MAX_HEXDUMP_LEN
+        )));
+    }
+
+    let data = read_exact_at(reader, offset, len)
+        .ok_or_else(|| MosesError::Other(format!("Failed to read {} bytes at offset {:#x}", len, offset)))?;
+
+    let end = offset + len as u64;
+    let annotations = detect_structures(reader)
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|s| s.annotations())
+        .filter(|a| a.offset < end && a.offset + a.length as u64 > offset)
+        .collect();
+
+    Ok(HexDump { offset, data, annotations })
+}
+
+/// Hex dump a device through the sector-aligned reader (read-only)
+pub fn hexdump_device(device: &Device, offset: u64, len: usize) -> Result<HexDump, MosesError> {
+    if device.size > 0 && offset >= device.size {
+        return Err(MosesError::InvalidInput(format!(
+            "Offset {:#x} is beyond the end of the device ({} bytes)", offset, device.size
+        )));
+    }
+    let len = if device.size > 0 {
+        len.min((device.size - offset) as usize)
+    } else {
+        len
+    };
+
+    let file = crate::utils::open_device_with_fallback(device)?;
+    let mut reader = AlignedDeviceReader::new(file);
+    hexdump(&mut reader, offset, len)
+}
+
+impl HexDump {
+    /// Classic 16-bytes-per-line rendering with an ASCII column.
+    /// Lines that start an annotated field are suffixed with the field name.
+    pub fn format_lines(&self) -> Vec<String> {
+        self.data.chunks(16).enumerate().map(|(i, chunk)| {
+            let line_offset = self.offset + (i * 16) as u64;
+            let mut line = format!("{:08X}: ", line_offset);
+            for j in 0..16 {
+                match chunk.get(j) {
+                    Some(b) => line.push_str(&format!("{:02X} ", b)),
+                    None => line.push_str("   "),
+                }
+            }
+            line.push_str(" |");
+            for &b in chunk {
+                line.push(if (0x20..0x7F).contains(&b) { b as char } else { '.' });
+            }
+            line.push('|');
+
+            let fields: Vec<&str> = self.annotations.iter()
+                .filter(|a| a.offset >= line_offset && a.offset < line_offset + 16)
+                .map(|a| a.field.as_str())
+                .collect();
+            if !fields.is_empty() {
+                line.push_str(&format!("  <- {}", fields.join(", ")));
+            }
+            line
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_mbr_annotations() {
+        let mut disk = vec![0u8; 64 * 1024];
+        disk[0x1BE + 4] = 0x0C;
+        disk[0x1BE + 8..0x1BE + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[0x1FE] = 0x55;
+        disk[0x1FF] = 0xAA;
+        let mut cursor = Cursor::new(disk);
+
+        let dump = hexdump(&mut cursor, 0x1B0, 0x50).unwrap();
+        assert_eq!(dump.data.len(), 0x50);
+        let start = dump.annotations.iter()
+            .find(|a| a.field == "partition[0].start_lba")
+            .unwrap();
+        assert_eq!(start.offset, 0x1C6);
+        assert!(start.value.starts_with("2048"));
+        // boot_code overlaps the start of the range too
+        assert!(dump.annotations.iter().any(|a| a.field == "boot_code"));
+    }
+
+    #[test]
+    fn test_ext_superblock_annotations() {
+        let mut disk = vec![0u8; 4096];
+        disk[1024 + 56..1024 + 58].copy_from_slice(&0xEF53u16.to_le_bytes());
+        let mut cursor = Cursor::new(disk);
+
+        let dump = hexdump(&mut cursor, 1024, 128).unwrap();
+        assert!(dump.annotations.iter().any(|a| a.structure == "ext superblock" && a.field == "s_magic"));
+        assert!(dump.format_lines()[3].contains("s_magic"));
+    }
+
+    #[test]
+    fn test_length_limit() {
+        let mut cursor = Cursor::new(vec![0u8; 512]);
+        assert!(hexdump(&mut cursor, 0, MAX_HEXDUMP_LEN + 1).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Formatting into a plain image file
+// Emulators, SD card flashing pipelines and CI want a freshly formatted filesystem image
+// without any drive attached. The formatters write to whatever file a Device points at, so
+// an image target is a sparse file of the requested size behind a Virtual device. As with
+// mkfs.fat -C, an existing file is never overwritten, and a failed format removes the
+// partial image instead of leaving something that looks usable.
+
+use moses_core::{Device, FilesystemFormatter, FormatOptions, MosesError};
+use std::path::Path;
+
+/// Create a sparse image file of `size` bytes and the device that targets it
+pub fn create_image(path: &Path, size: u64) -> Result<Device, MosesError> {
+    if size == 0 || !size.is_multiple_of(512) {
+        return Err(MosesError::InvalidInput(format!(
+            "Image size must be a non-zero multiple of 512 bytes, not {}", size
+        )));
+    }
+    let file = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => MosesError::InvalidInput(format!(
+                "{} already exists; images are only written to new files", path.display()
+            )),
+            _ => MosesError::Other(format!("Failed to create {}: {}", path.display(), e)),
+        })?;
+    if let Err(e) = file.set_len(size) {
+        let _ = std::fs::remove_file(path);
+        return Err(MosesError::Other(format!("Failed to size {} to {} bytes: {}", path.display(), size, e)));
+    }
+    Device::image_file(path)
+}
+
+/// Format a new image file of `size` bytes at `path` with `formatter`
+pub async fn format_image(
+    formatter: &dyn FilesystemFormatter,
+    path: &Path,
+    size: u64,
+    options: &FormatOptions,
+) -> Result<Device, MosesError> {
+    formatter.validate_options(options).await?;
+    let device = create_image(path, size)?;
+    let result = if formatter.can_format(&device) {
+        formatter.format(&device, options).await
+    } else {
+        Err(MosesError::InvalidInput(format!(
+            "The {} formatter cannot make a {} byte image", options.filesystem_type, size
+        )))
+    };
+    match result {
+        Ok(()) => Ok(device),
+        Err(e) => {
+            let _ = std::fs::remove_file(path);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+
+    #[tokio::test]
+    async fn test_format_fat32_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("card.img");
+        let options = FormatOptions { filesystem_type: "fat32".to_string(), label: Some("CI".to_string()), ..Default::default() };
+        let device = format_image(&crate::Fat32Formatter, &path, 256 * 1024 * 1024, &options).await.unwrap();
+        assert_eq!(device.size, 256 * 1024 * 1024);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), device.size);
+
+        let mut boot_sector = [0u8; 512];
+        let mut file = std::fs::File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_exact(&mut boot_sector).unwrap();
+        assert_eq!(&boot_sector[82..90], b"FAT32   ");
+        assert_eq!(&boot_sector[510..512], &[0x55, 0xAA]);
+
+        assert!(format_image(&crate::Fat32Formatter, &path, 256 * 1024 * 1024, &options).await.is_err(), "existing files are kept");
+        assert!(create_image(&dir.path().join("odd.img"), 1000).is_err());
+        let tiny = dir.path().join("tiny.img");
+        assert!(format_image(&crate::Fat32Formatter, &tiny, 512 * 1024, &options).await.is_err());
+        assert!(!tiny.exists(), "a failed format leaves no image behind");
+    }
+}
@@ -0,0 +1,121 @@
+// Imaging a drive or volume into a file
+// The reverse of writing an image out: every byte of the source is copied into a new file
+// that can later be browsed, mounted or written back. As with image targets, an existing
+// file is never overwritten and a failed copy removes the partial image instead of leaving
+// something that looks like a good backup. Blocks of zeros are skipped with a seek, so the
+// image of a mostly empty drive stays sparse.
+// A volume the OS has mounted keeps changing while it is copied; moses_platform::quiesce
+// holds it still (or snapshots it) for the length of the copy.
+
+use moses_core::{Device, MosesError, ProgressTracker, ProgressUpdate};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// What an imaging run copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageSummary {
+    /// Size of the image, which is the size of the source
+    pub bytes: u64,
+    /// Bytes left as holes because they were all zero
+    pub sparse_bytes: u64,
+}
+
+/// Copy `device` into a new image file at `to`, reading `block_size` bytes at a time
+pub fn image_device(
+    device: &Device,
+    to: &Path,
+    block_size: usize,
+    progress: &mut dyn FnMut(&ProgressUpdate),
+) -> Result<ImageSummary, MosesError> {
+    let mut source = crate::utils::open_device_read(device)?;
+    let size = match device.size {
+        0 => source.seek(SeekFrom::End(0))?,
+        size => size,
+    };
+    source.seek(SeekFrom::Start(0))?;
+
+    let mut out = File::options()
+        .write(true)
+        .create_new(true)
+        .open(to)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => MosesError::InvalidInput(format!(
+                "{} already exists; images are only written to new files", to.display()
+            )),
+            _ => MosesError::Other(format!("Failed to create {}: {}", to.display(), e)),
+        })?;
+    let operation = format!("Imaging {}", device.name);
+    let result = copy_image(&mut source, size, &mut out, block_size, &operation, progress)
+        .and_then(|summary| {
+            out.sync_all()?;
+            Ok(summary)
+        });
+    if result.is_err() {
+        drop(out);
+        let _ = std::fs::remove_file(to);
+    }
+    result
+}
+
+/// Copy `size` bytes of `source` into `out`, leaving all-zero blocks as holes
+fn copy_image<R: Read>(
+    source: &mut R,
+    size: u64,
+    out: &mut File,
+    block_size: usize,
+    operation: &str,
+    progress: &mut dyn FnMut(&ProgressUpdate),
+) -> Result<ImageSummary, MosesError> {
+    let mut tracker = ProgressTracker::new(operation, size);
+    let mut buffer = vec![0u8; block_size.max(512)];
+    let mut summary = ImageSummary { bytes: size, sparse_bytes: 0 };
+    let mut done = 0u64;
+    while done < size {
+        let len = buffer.len().min((size - done) as usize);
+        source.read_exact(&mut buffer[..len])
+            .map_err(|e| MosesError::Other(format!("Failed to read the source at offset {}: {}", done, e)))?;
+        if buffer[..len].iter().all(|&b| b == 0) {
+            out.seek(SeekFrom::Current(len as i64))?;
+            summary.sparse_bytes += len as u64;
+        } else {
+            out.write_all(&buffer[..len])?;
+        }
+        done += len as u64;
+        progress(&tracker.update("copying", done));
+    }
+    // A trailing hole is only a seek until the length is set
+    out.set_len(size)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_device_copies_and_keeps_holes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_path = dir.path().join("card.img");
+        let mut data = vec![0u8; 1 << 20];
+        data[..512].fill(0xAB);
+        data[700_000..700_010].copy_from_slice(b"moses data");
+        std::fs::write(&source_path, &data).unwrap();
+        let device = Device::image_file(&source_path).unwrap();
+
+        let to = dir.path().join("copy.img");
+        let mut updates = 0;
+        let summary = image_device(&device, &to, 64 * 1024, &mut |_| updates += 1).unwrap();
+        assert_eq!(std::fs::read(&to).unwrap(), data);
+        assert_eq!(summary.bytes, 1 << 20);
+        assert_eq!(summary.sparse_bytes, (1 << 20) - 2 * 64 * 1024);
+        assert_eq!(updates, 16);
+
+        // Never overwrites, and a failed copy leaves nothing behind
+        assert!(image_device(&device, &to, 64 * 1024, &mut |_| {}).is_err());
+        let short = Device { size: 2 << 20, ..device };
+        let partial = dir.path().join("partial.img");
+        assert!(image_device(&short, &partial, 64 * 1024, &mut |_| {}).is_err());
+        assert!(!partial.exists());
+    }
+}
@@ -0,0 +1,228 @@
+// I/O tuning - block size and reads in flight picked from a short read probe
+// A USB 2 stick manages about 30 MB/s whatever the block size and gains nothing from
+// parallel reads, while an NVMe drive needs large blocks and several reads in flight to get
+// near its speed. Before the first copy or full-disk pass on a device, Moses reads it for a
+// moment at a few block sizes, then with one, two and four readers, and keeps the smallest
+// settings that reach 90% of the best throughput seen. The result is cached per device for
+// the session and logged, and the `io` section of the configuration overrides either value.
+use moses_core::{Device, IoConfig, MosesConfig, MosesError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Block sizes the probe compares
+pub const BLOCK_SIZES: [usize; 4] = [64 * 1024, 256 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+/// Reader counts the probe compares
+pub const READERS: [usize; 3] = [1, 2, 4];
+/// Used when nothing was probed or configured; the size transfers always used
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+/// Settings within this share of the best throughput count as just as good
+const GOOD_ENOUGH: f64 = 0.9;
+/// Each measurement stops after this long or this many bytes per reader
+const MEASURE_TIME: Duration = Duration::from_millis(150);
+const MEASURE_BYTES: u64 = 16 * 1024 * 1024;
+/// Devices smaller than this are not worth probing
+const MIN_PROBE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Where the parameters came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TuningSource {
+    Probed,
+    Configured,
+    Default,
+}
+
+/// What a copy or full-disk pass uses on one device
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IoParameters {
+    /// Bytes per read or write request
+    pub block_size: usize,
+    /// Reads in flight against the device
+    pub queue_depth: usize,
+    /// Best read throughput the probe measured
+    pub read_mb_per_sec: Option<f64>,
+    pub source: TuningSource,
+}
+
+impl Default for IoParameters {
+    fn default() -> Self {
+        Self { block_size: DEFAULT_BLOCK_SIZE, queue_depth: 1, read_mb_per_sec: None, source: TuningSource::Default }
+    }
+}
+
+impl fmt::Display for IoParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} KiB blocks, {} in flight", self.block_size / 1024, self.queue_depth)?;
+        match (self.source, self.read_mb_per_sec) {
+            (TuningSource::Probed, Some(speed)) => write!(f, " (probed at {:.0} MB/s)", speed),
+            (TuningSource::Configured, Some(speed)) => write!(f, " (partly configured; probed at {:.0} MB/s)", speed),
+            (TuningSource::Configured, None) => write!(f, " (configured)"),
+            _ => write!(f, " (defaults)"),
+        }
+    }
+}
+
+/// One measurement of the probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProbeSample {
+    pub block_size: usize,
+    pub readers: usize,
+    pub mb_per_sec: f64,
+}
+
+/// Probe a device of `size` bytes through handles from `open`: block sizes with one
+/// reader, then reader counts at the chosen block size
+pub fn probe<R, F>(open: F, size: u64) -> Result<(IoParameters, Vec<ProbeSample>), MosesError>
+where
+    R: Read + Seek + Send,
+    F: Fn() -> Result<R, MosesError>,
+{
+    let mut samples = Vec::new();
+    // Every measurement reads its own part of the device, so none is served from the cache
+    // an earlier one filled
+    let regions = (BLOCK_SIZES.len() + READERS.len()) as u64 * READERS[READERS.len() - 1] as u64;
+    let mut region = 0u64;
+    let mut measure = |block_size: usize, readers: usize| -> Result<f64, MosesError> {
+        let handles = (0..readers).map(|_| open()).collect::<Result<Vec<_>, _>>()?;
+        let starts: Vec<u64> = (0..readers as u64)
+            .map(|reader| (region + reader) * (size / regions) / 4096 * 4096)
+            .collect();
+        region += readers as u64;
+        let speed = measure_throughput(handles, &starts, size / regions, block_size)?;
+        samples.push(ProbeSample { block_size, readers, mb_per_sec: speed });
+        Ok(speed)
+    };
+
+    let by_size = BLOCK_SIZES.iter()
+        .map(|&block_size| Ok((block_size, measure(block_size, 1)?)))
+        .collect::<Result<Vec<_>, MosesError>>()?;
+    let (block_size, single) = good_enough(&by_size);
+    let mut by_readers = vec![(1, single)];
+    for &readers in &READERS[1..] {
+        by_readers.push((readers, measure(block_size, readers)?));
+    }
+    let (queue_depth, _) = good_enough(&by_readers);
+    let best = by_size.iter().chain(&by_readers).map(|(_, speed)| *speed).fold(0.0, f64::max);
+    let parameters = IoParameters { block_size, queue_depth, read_mb_per_sec: Some(best), source: TuningSource::Probed };
+    Ok((parameters, samples))
+}
+
+/// The smallest setting reaching GOOD_ENOUGH of the best throughput
+fn good_enough(results: &[(usize, f64)]) -> (usize, f64) {
+    let best = results.iter().map(|(_, speed)| *speed).fold(0.0, f64::max);
+    results.iter()
+        .copied()
+        .find(|(_, speed)| *speed >= best * GOOD_ENOUGH)
+        .unwrap_or(results[0])
+}
+
+/// MB/s read by all handles together, each reading up to `span` bytes from its start
+fn measure_throughput<R: Read + Seek + Send>(handles: Vec<R>, starts: &[u64], span: u64, block_size: usize) -> Result<f64, MosesError> {
+    let limit = span.min(MEASURE_BYTES);
+    let started = Instant::now();
+    let read = std::thread::scope(|scope| {
+        let readers: Vec<_> = handles.into_iter().zip(starts).map(|(mut handle, &start)| scope.spawn(move || {
+            handle.seek(SeekFrom::Start(start))?;
+            let mut buffer = vec![0u8; block_size];
+            let began = Instant::now();
+            let mut read = 0u64;
+            while read < limit && began.elapsed() < MEASURE_TIME {
+                let count = handle.read(&mut buffer)?;
+                if count == 0 {
+                    break;
+                }
+                read += count as u64;
+            }
+            Ok::<u64, std::io::Error>(read)
+        })).collect();
+        readers.into_iter()
+            .map(|reader| reader.join().unwrap_or_else(|_| Err(std::io::Error::other("probe reader panicked"))))
+            .sum::<Result<u64, _>>()
+    })?;
+    let seconds = started.elapsed().as_secs_f64().max(1e-6);
+    Ok(read as f64 / seconds / 1_000_000.0)
+}
+
+/// Apply the configuration to probed parameters (or defaults when there are none); set
+/// values win, and the queue depth never exceeds `max_depth`
+pub fn apply_config(config: &IoConfig, probed: Option<IoParameters>, max_depth: usize) -> IoParameters {
+    let mut parameters = probed.unwrap_or_default();
+    if let Some(block_size) = config.block_size.filter(|&size| size >= 512) {
+        parameters.block_size = block_size / 512 * 512;
+        parameters.source = TuningSource::Configured;
+    }
+    if let Some(depth) = config.queue_depth.filter(|&depth| depth > 0) {
+        parameters.queue_depth = depth;
+        parameters.source = TuningSource::Configured;
+    }
+    parameters.queue_depth = parameters.queue_depth.clamp(1, max_depth.max(1));
+    parameters
+}
+
+/// Parameters for `device`, probing it the first time it is asked about in this process.
+/// The queue depth also becomes the device's limit in DeviceQueues.
+pub fn for_device(device: &Device) -> IoParameters {
+    static TUNED: OnceLock<Mutex<HashMap<String, IoParameters>>> = OnceLock::new();
+    let tuned = TUNED.get_or_init(Default::default);
+    if let Some(parameters) = tuned.lock().ok().and_then(|tuned| tuned.get(&device.id).copied()) {
+        return parameters;
+    }
+
+    let config = MosesConfig::global();
+    let fully_configured = config.io.block_size.is_some() && config.io.queue_depth.is_some();
+    let probed = if config.io.auto_tune && !fully_configured && device.size >= MIN_PROBE_SIZE {
+        match probe(|| crate::utils::open_device_read(device), device.size) {
+            Ok((parameters, samples)) => {
+                for sample in &samples {
+                    log::debug!("I/O probe of {}: {} KiB x {} -> {:.0} MB/s",
+                        device.name, sample.block_size / 1024, sample.readers, sample.mb_per_sec);
+                }
+                Some(parameters)
+            }
+            Err(e) => {
+                log::warn!("I/O probe of {} failed, using default block size: {}", device.name, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let parameters = apply_config(&config.io, probed, config.concurrency.queue_depth());
+    log::info!("I/O for {}: {}", device.name, parameters);
+    moses_core::DeviceQueues::global().set_device_depth(&device.id, parameters.queue_depth);
+    if let Ok(mut tuned) = tuned.lock() {
+        tuned.insert(device.id.clone(), parameters);
+    }
+    parameters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_probe_and_overrides() {
+        let disk = vec![0u8; 32 * 1024 * 1024];
+        let (parameters, samples) = probe(|| Ok(Cursor::new(disk.as_slice())), disk.len() as u64).unwrap();
+        assert_eq!(samples.len(), BLOCK_SIZES.len() + READERS.len() - 1);
+        assert!(BLOCK_SIZES.contains(&parameters.block_size));
+        assert!(READERS.contains(&parameters.queue_depth));
+        assert_eq!(parameters.source, TuningSource::Probed);
+
+        // The smallest setting close to the best wins
+        assert_eq!(good_enough(&[(64, 95.0), (256, 100.0), (1024, 101.0)]).0, 64);
+        assert_eq!(good_enough(&[(1, 30.0), (2, 60.0), (4, 62.0)]).0, 2);
+
+        let config = IoConfig { auto_tune: true, block_size: Some(128 * 1024 + 7), queue_depth: None };
+        let applied = apply_config(&config, Some(parameters), 2);
+        assert_eq!(applied.block_size, 128 * 1024);
+        assert_eq!(applied.source, TuningSource::Configured);
+        assert!(applied.queue_depth <= 2);
+        assert_eq!(apply_config(&IoConfig::default(), None, 4), IoParameters::default());
+    }
+}
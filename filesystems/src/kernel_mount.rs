@@ -0,0 +1,196 @@
+// Kernel mounts on Linux - the kernel's own driver instead of FUSE where it has one
+// ext2/3/4, NTFS (ntfs3), FAT (vfat) and exFAT all have in-kernel drivers on current
+// kernels, which are faster than a FUSE session and behave like any other disk: the
+// mount outlives `moses mount`, and the page cache, fsync and mmap work as expected.
+// A block device is mounted as it is; an image file is first attached to a loop device
+// with losetup. The loop device is detached straight after mounting, which the kernel
+// defers until the filesystem is unmounted (autoclear), so a plain umount cleans up.
+// Filesystems the running kernel has no driver for are left to FUSE.
+
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The kernel's name for a Moses filesystem type, and the directory of its module
+fn kernel_driver(filesystem: &str) -> Option<(&'static str, &'static str)> {
+    Some(match filesystem.to_lowercase().as_str() {
+        "ext2" => ("ext2", "ext4"),
+        "ext3" => ("ext3", "ext4"),
+        "ext4" => ("ext4", "ext4"),
+        "ntfs" => ("ntfs3", "ntfs3"),
+        "fat12" | "fat16" | "fat32" => ("vfat", "fat"),
+        "exfat" => ("exfat", "exfat"),
+        _ => return None,
+    })
+}
+
+/// Whether `/proc/filesystems` lists `kernel_type`
+fn listed(proc_filesystems: &str, kernel_type: &str) -> bool {
+    proc_filesystems.lines().any(|line| line.split_whitespace().last() == Some(kernel_type))
+}
+
+/// The kernel filesystem type to mount `filesystem` as, when the running kernel has a
+/// driver for it, loaded or as a module it can load on demand
+pub fn kernel_type(filesystem: &str) -> Option<&'static str> {
+    let (kernel_type, module) = kernel_driver(filesystem)?;
+    let loaded = std::fs::read_to_string("/proc/filesystems")
+        .is_ok_and(|filesystems| listed(&filesystems, kernel_type));
+    let loadable = || {
+        std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .is_ok_and(|release| Path::new("/lib/modules").join(release.trim()).join("kernel/fs").join(module).is_dir())
+    };
+    (loaded || loadable()).then_some(kernel_type)
+}
+
+/// How a volume will be mounted by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelMountPlan {
+    pub kernel_type: &'static str,
+    /// The device or image file holding the volume
+    pub source: PathBuf,
+    /// An image file, which needs a loop device
+    pub needs_loop: bool,
+    pub mount_point: PathBuf,
+    /// The `-o` options
+    pub options: Vec<String>,
+}
+
+impl KernelMountPlan {
+    /// Plan a kernel mount of the `filesystem` volume on `device`; NotSupported when the
+    /// kernel has no driver for it, so the caller can fall back to FUSE
+    pub fn new(device: &Device, filesystem: &str, mount_point: &Path, readonly: bool) -> Result<Self, MosesError> {
+        let kernel_type = kernel_type(filesystem).ok_or_else(|| MosesError::NotSupported(format!(
+            "The running kernel has no {} driver", filesystem,
+        )))?;
+        let source = PathBuf::from(&device.id);
+        let needs_loop = std::fs::metadata(&source)
+            .map_err(|e| MosesError::Other(format!("Cannot open {}: {}", source.display(), e)))?
+            .is_file();
+        // No Unix owners on disk: files belong to whoever asked for the mount, even through sudo
+        let from_env = |key: &str| std::env::var(key).ok().and_then(|value| value.parse::<u32>().ok());
+        let owner = (
+            from_env("SUDO_UID").unwrap_or_else(|| nix::unistd::getuid().as_raw()),
+            from_env("SUDO_GID").unwrap_or_else(|| nix::unistd::getgid().as_raw()),
+        );
+        Ok(Self::with_type(kernel_type, source, needs_loop, mount_point, readonly, owner))
+    }
+
+    fn with_type(kernel_type: &'static str, source: PathBuf, needs_loop: bool, mount_point: &Path, readonly: bool, (uid, gid): (u32, u32)) -> Self {
+        let mut mount_options = vec![if readonly { "ro" } else { "rw" }.to_string(), "nodev".to_string(), "nosuid".to_string()];
+        match kernel_type {
+            "vfat" | "exfat" | "ntfs3" => {
+                mount_options.push(format!("uid={}", uid));
+                mount_options.push(format!("gid={}", gid));
+                mount_options.push(if kernel_type == "vfat" { "utf8" } else { "iocharset=utf8" }.to_string());
+            }
+            _ => mount_options.push("errors=remount-ro".to_string()),
+        }
+        Self { kernel_type, source, needs_loop, mount_point: mount_point.to_path_buf(), options: mount_options }
+    }
+
+    /// Arguments for losetup that attach the image and print the loop device
+    pub fn losetup_args(&self) -> Option<Vec<String>> {
+        self.needs_loop.then(|| {
+            let mut args = vec!["--find".to_string(), "--show".to_string()];
+            if self.options.first().is_some_and(|option| option == "ro") {
+                args.push("--read-only".to_string());
+            }
+            args.push(self.source.to_string_lossy().to_string());
+            args
+        })
+    }
+
+    /// Arguments for mount, with `device` the block device to mount
+    pub fn mount_args(&self, device: &str) -> Vec<String> {
+        vec![
+            "-t".to_string(),
+            self.kernel_type.to_string(),
+            "-o".to_string(),
+            self.options.join(","),
+            device.to_string(),
+            self.mount_point.to_string_lossy().to_string(),
+        ]
+    }
+
+    /// Attach the loop device if one is needed and mount; returns the mounted block device
+    pub fn mount(&self) -> Result<String, MosesError> {
+        std::fs::create_dir_all(&self.mount_point)?;
+        let device = match self.losetup_args() {
+            Some(args) => run("losetup", &args)?,
+            None => self.source.to_string_lossy().to_string(),
+        };
+        let mounted = run("mount", &self.mount_args(&device));
+        if self.needs_loop {
+            // While mounted this only marks the loop device for release at unmount
+            if let Err(e) = run("losetup", &["--detach".to_string(), device.clone()]) {
+                log::warn!("Could not release {}: {}", device, e);
+            }
+        }
+        mounted.map(|_| device)
+    }
+}
+
+fn run(program: &str, args: &[String]) -> Result<String, MosesError> {
+    let output = Command::new(program).args(args).output()
+        .map_err(|e| MosesError::Other(format!("Cannot run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(MosesError::Other(format!(
+            "{} {} failed: {}", program, args.join(" "), String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The kernel filesystem type mounted at `mount_point`, from a `/proc/mounts` listing
+fn mounted_type(proc_mounts: &str, mount_point: &Path) -> Option<String> {
+    proc_mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // Spaces in mount points are escaped as \040
+        (fields.len() > 2 && Path::new(&fields[1].replace("\\040", " ")) == mount_point).then(|| fields[2].to_string())
+    })
+}
+
+/// Whether `mount_point` holds a kernel mount of the kind `moses mount --kernel` makes,
+/// rather than a FUSE mount or a system filesystem
+pub fn is_kernel_mount(mount_point: &Path) -> bool {
+    let mount_point = mount_point.canonicalize().unwrap_or_else(|_| mount_point.to_path_buf());
+    mount_point != Path::new("/") && std::fs::read_to_string("/proc/mounts").ok()
+        .and_then(|mounts| mounted_type(&mounts, &mount_point))
+        .is_some_and(|kind| ["ext2", "ext3", "ext4", "ntfs3", "vfat", "exfat"].contains(&kind.as_str()))
+}
+
+/// Unmount a kernel mount; an autoclear loop device beneath it goes with it
+pub fn unmount(mount_point: &Path) -> Result<(), MosesError> {
+    run("umount", &[mount_point.to_string_lossy().to_string()]).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_mount_plan() {
+        let listing = "nodev\tsysfs\n\text3\n\text2\n\text4\nnodev\tfuse\n\tvfat\n";
+        assert!(listed(listing, "ext4") && listed(listing, "vfat"));
+        assert!(!listed(listing, "ntfs3") && !listed(listing, "fuseblk"));
+        assert_eq!(kernel_driver("NTFS"), Some(("ntfs3", "ntfs3")));
+        assert_eq!(kernel_driver("fat16"), Some(("vfat", "fat")));
+        assert_eq!(kernel_driver("btrfs"), None);
+
+        let plan = KernelMountPlan::with_type("vfat", PathBuf::from("/tmp/stick.img"), true, Path::new("/mnt/usb"), true, (1000, 100));
+        assert_eq!(plan.losetup_args().unwrap(), ["--find", "--show", "--read-only", "/tmp/stick.img"]);
+        assert_eq!(plan.mount_args("/dev/loop3"), ["-t", "vfat", "-o", "ro,nodev,nosuid,uid=1000,gid=100,utf8", "/dev/loop3", "/mnt/usb"]);
+
+        let plan = KernelMountPlan::with_type("ext4", PathBuf::from("/dev/sdb1"), false, Path::new("/mnt/data"), false, (0, 0));
+        assert_eq!(plan.losetup_args(), None);
+        assert_eq!(plan.options, ["rw", "nodev", "nosuid", "errors=remount-ro"]);
+    }
+
+    #[test]
+    fn test_mounted_type() {
+        let mounts = "/dev/sdb1 /mnt/my\\040stick vfat rw 0 0\nmoses /mnt/ext fuse.moses ro 0 0\n";
+        assert_eq!(mounted_type(mounts, Path::new("/mnt/my stick")).as_deref(), Some("vfat"));
+        assert_eq!(mounted_type(mounts, Path::new("/mnt/ext")).as_deref(), Some("fuse.moses"));
+        assert_eq!(mounted_type(mounts, Path::new("/mnt")), None);
+    }
+}
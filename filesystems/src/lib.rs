@@ -3,13 +3,43 @@ pub mod families;
 
 // Filesystem modules are now organized in families
 pub mod registration;
+pub mod postconditions;
 pub mod utils;
 pub mod detection;
 pub mod device_reader;
 pub mod device_writer;
+pub mod diagnostics;
 pub mod diagnostics_improved;
+pub mod hexview;
+pub mod explain;
+pub mod allocation;
 pub mod partitioner;
 pub mod disk_manager;
+pub mod lints;
+pub mod export;
+pub mod transfer;
+pub mod preview;
+pub mod readonly;
+pub mod links;
+pub mod throttle;
+pub mod tools;
+pub mod update;
+pub mod volume_serial;
+pub mod advisor;
+pub mod checksums;
+pub mod deterministic;
+pub mod image_target;
+pub mod imaging;
+pub mod sandbox;
+pub mod capabilities;
+pub mod mount_driver;
+#[cfg(target_os = "linux")]
+pub mod kernel_mount;
+pub mod attributes;
+pub mod watch;
+pub mod remote_blocks;
+pub mod io_tuning;
+pub mod walker;
 // FAT common module now in families/fat/common
 pub mod ops;
 pub mod ops_helpers;
@@ -18,6 +48,10 @@ pub mod ops_registry;
 pub mod error_recovery;
 #[cfg(test)]
 pub mod test_helpers;
+#[cfg(test)]
+mod test_large_devices;
+#[cfg(test)]
+mod test_conformance;
 
 #[cfg(feature = "mount")]
 pub mod mount;
@@ -27,23 +61,32 @@ pub mod mount;
 pub use families::ext::ext4_native::{Ext4NativeFormatter, ExtReader, Ext4Ops};
 
 // Extended ext family support (ext2/ext3) using ext4_native base
-pub use families::ext::{Ext2Formatter, Ext3Formatter};
+pub use families::ext::{Ext2Formatter, Ext3Formatter, FlashJournal, FlashTuning};
 
 // Re-export formatters and readers
 // NTFS implementation - read and format support
 pub use families::ntfs::ntfs::{NtfsDetector, NtfsReader, NtfsFormatter, NtfsOps, NtfsRwOps};
+pub use families::ntfs::ntfs::{list_device_shadow_copies, ShadowCopy, ShadowVolume};
 pub use families::fat::fat16::{Fat16Formatter, Fat16Reader, Fat16Ops};
 pub use families::fat::fat32::{Fat32Formatter, Fat32Reader, Fat32Ops};
 pub use families::fat::exfat::{ExFatFormatter, ExFatReader, ExFatOps};
+pub use families::btrfs::{BtrfsReader, BtrfsOps};
+pub use families::xfs::{XfsReader, XfsOps};
+pub use families::hfsplus::{HfsPlusReader, HfsPlusOps};
+pub use families::flash::jffs2::{Jffs2Reader, Jffs2Ops};
+pub use families::optical::iso9660::{Iso9660Reader, Iso9660Ops};
 
 
 // Re-export registration functions
-pub use registration::{register_builtin_formatters, list_available_formatters, get_formatter_info};
+pub use registration::{
+    builtin_registry, get_formatter_info, list_available_formatters, register_builtin_formatters, resolve_formatter,
+};
 
 // Re-export filesystem operations
 pub use ops::{
     FilesystemOps, FilesystemOpsRegistry, FilesystemDetector, 
     FileAttributes, DirectoryEntry, FilesystemInfo, register_builtin_ops,
-    MountSource, SubfolderOps, HostFolderOps
+    MountSource, SubfolderOps, HostFolderOps, TreeStats, tree_stats, AttributeChanges, dos_attributes
 };
-pub use ops_registry::register_all_filesystems;
\ No newline at end of file
+pub use ops_registry::register_all_filesystems;
+pub use links::{FollowLinksOps, LinkPolicy};
\ No newline at end of file
@@ -0,0 +1,311 @@
+// Link policy for mounts
+// Filesystems report symbolic links, NTFS junctions and volume mount points as symlinks
+// and give their targets through `readlink`. Exposing them as links suits FUSE mounts,
+// but targets like "C:\Users\me" mean nothing to the host and WinFsp mounts cannot show
+// links at all. FollowLinksOps instead resolves links whose targets stay inside the
+// volume and presents them as what they point to; links leading off the volume (drive
+// paths, other volumes, absolute host paths) are still exposed as links.
+use crate::ops::{AttributeChanges, DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps};
+use moses_core::{Device, MosesError};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// Links followed while resolving one path before giving up, as Linux does for ELOOP
+const MAX_LINK_HOPS: usize = 40;
+
+/// How a mount presents links
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// Show links as links, with their targets as stored
+    #[default]
+    Expose,
+    /// Show links that resolve inside the volume as their targets
+    Follow,
+}
+
+/// Wraps ops so in-volume links are transparently followed
+pub struct FollowLinksOps {
+    inner: Box<dyn FilesystemOps>,
+}
+
+impl FollowLinksOps {
+    pub fn new(inner: Box<dyn FilesystemOps>) -> Self {
+        Self { inner }
+    }
+
+    /// Target of the link at `path` if it stays inside the volume
+    fn volume_target(&mut self, path: &Path) -> Option<PathBuf> {
+        let target = self.inner.readlink(path).ok()?;
+        // Absolute paths, drive letters and leftover Windows separators point off the volume
+        let text = target.to_string_lossy();
+        (target.is_relative() && !text.contains(['\\', ':'])).then_some(target)
+    }
+
+    /// `path` with each in-volume link along it replaced by its target. With `follow_last`
+    /// false the final component is kept, for operations on a link itself.
+    fn resolve(&mut self, path: &Path, follow_last: bool) -> Result<PathBuf, MosesError> {
+        let mut pending = components(path);
+        pending.reverse();
+        let mut resolved = PathBuf::from("/");
+        let mut hops = 0;
+
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                // Never above the volume root
+                resolved.pop();
+                continue;
+            }
+            let candidate = resolved.join(&name);
+            if !follow_last && pending.is_empty() {
+                return Ok(candidate);
+            }
+            let is_link = self.inner.stat(&candidate).is_ok_and(|attrs| attrs.is_symlink);
+            match is_link.then(|| self.volume_target(&candidate)).flatten() {
+                Some(target) => {
+                    hops += 1;
+                    if hops > MAX_LINK_HOPS {
+                        return Err(MosesError::Other(format!(
+                            "Too many levels of links resolving {}", path.display()
+                        )));
+                    }
+                    pending.extend(components(&target).into_iter().rev());
+                }
+                None => resolved = candidate,
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+/// Names and ".." steps of a path, without the root or "." steps
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+impl FilesystemOps for FollowLinksOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        self.inner.init(device)
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        self.inner.statfs()
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        let resolved = self.resolve(path, true)?;
+        self.inner.stat(&resolved)
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        let dir = self.resolve(path, true)?;
+        let mut entries = self.inner.readdir(&dir)?;
+        for entry in entries.iter_mut().filter(|entry| entry.attributes.is_symlink) {
+            let link = dir.join(&entry.name);
+            if let Ok(target) = self.resolve(&link, true) {
+                if target != link {
+                    if let Ok(attributes) = self.inner.stat(&target) {
+                        entry.attributes = attributes;
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        let resolved = self.resolve(path, true)?;
+        self.inner.read(&resolved, offset, size)
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        let resolved = self.resolve(path, true).ok()?;
+        self.inner.directory_id(&resolved)
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        // Only links that could not be followed are still visible as links
+        let resolved = self.resolve(path, false)?;
+        self.inner.readlink(&resolved)
+    }
+
+    fn write(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<u32, MosesError> {
+        let resolved = self.resolve(path, true)?;
+        self.inner.write(&resolved, offset, data)
+    }
+
+    fn create(&mut self, path: &Path, mode: u32) -> Result<(), MosesError> {
+        let resolved = self.resolve(path, false)?;
+        self.inner.create(&resolved, mode)
+    }
+
+    fn mkdir(&mut self, path: &Path, mode: u32) -> Result<(), MosesError> {
+        let resolved = self.resolve(path, false)?;
+        self.inner.mkdir(&resolved, mode)
+    }
+
+    fn unlink(&mut self, path: &Path) -> Result<(), MosesError> {
+        // Removes the link, not what it points to
+        let resolved = self.resolve(path, false)?;
+        self.inner.unlink(&resolved)
+    }
+
+    fn rmdir(&mut self, path: &Path) -> Result<(), MosesError> {
+        let resolved = self.resolve(path, false)?;
+        self.inner.rmdir(&resolved)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<(), MosesError> {
+        let from = self.resolve(from, false)?;
+        let to = self.resolve(to, false)?;
+        self.inner.rename(&from, &to)
+    }
+
+    fn truncate(&mut self, path: &Path, size: u64) -> Result<(), MosesError> {
+        let resolved = self.resolve(path, true)?;
+        self.inner.truncate(&resolved, size)
+    }
+
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let resolved = self.resolve(path, true)?;
+        self.inner.set_attributes(&resolved, changes)
+    }
+
+    fn sync(&mut self) -> Result<(), MosesError> {
+        self.inner.sync()
+    }
+
+    fn is_readonly(&self) -> bool {
+        self.inner.is_readonly()
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        self.inner.warnings()
+    }
+
+    fn filesystem_type(&self) -> &str {
+        self.inner.filesystem_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    enum Node {
+        Dir,
+        File(&'static [u8]),
+        Link(&'static str),
+    }
+
+    /// Just enough of a filesystem to hold links
+    struct LinkedOps(HashMap<PathBuf, Node>);
+
+    impl LinkedOps {
+        fn new() -> Self {
+            Self(HashMap::from([
+                (PathBuf::from("/"), Node::Dir),
+                (PathBuf::from("/data"), Node::Dir),
+                (PathBuf::from("/data/notes.txt"), Node::File(b"notes")),
+                (PathBuf::from("/docs"), Node::Link("data")),
+                (PathBuf::from("/data/up"), Node::Link("../docs/notes.txt")),
+                (PathBuf::from("/users"), Node::Link("C:\\Users")),
+                (PathBuf::from("/loop"), Node::Link("loop")),
+            ]))
+        }
+
+        fn node(&self, path: &Path) -> Result<&Node, MosesError> {
+            self.0.get(path).ok_or_else(|| MosesError::Other(format!("Path not found: {}", path.display())))
+        }
+    }
+
+    impl FilesystemOps for LinkedOps {
+        fn init(&mut self, _device: &Device) -> Result<(), MosesError> {
+            Ok(())
+        }
+
+        fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+            Err(MosesError::NotSupported("statfs".to_string()))
+        }
+
+        fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+            let (size, is_directory, is_symlink) = match self.node(path)? {
+                Node::Dir => (0, true, false),
+                Node::File(data) => (data.len() as u64, false, false),
+                Node::Link(target) => (target.len() as u64, false, true),
+            };
+            Ok(FileAttributes {
+                size,
+                is_directory,
+                is_file: !is_directory && !is_symlink,
+                is_symlink,
+                created: None,
+                modified: None,
+                accessed: None,
+                permissions: 0o644,
+                owner: None,
+                group: None,
+            })
+        }
+
+        fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+            let children: Vec<PathBuf> = self.0.keys()
+                .filter(|child| child.parent() == Some(path))
+                .cloned()
+                .collect();
+            children.into_iter().map(|child| Ok(DirectoryEntry {
+                name: child.file_name().unwrap().to_string_lossy().to_string(),
+                attributes: self.stat(&child)?,
+            })).collect()
+        }
+
+        fn read(&mut self, path: &Path, _offset: u64, _size: u32) -> Result<Vec<u8>, MosesError> {
+            match self.node(path)? {
+                Node::File(data) => Ok(data.to_vec()),
+                _ => Err(MosesError::Other("Not a file".to_string())),
+            }
+        }
+
+        fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+            match self.node(path)? {
+                Node::Link(target) => Ok(PathBuf::from(target)),
+                _ => Err(MosesError::InvalidInput("Not a link".to_string())),
+            }
+        }
+
+        fn filesystem_type(&self) -> &str {
+            "test"
+        }
+    }
+
+    #[test]
+    fn test_follows_links_inside_the_volume() {
+        let mut ops = FollowLinksOps::new(Box::new(LinkedOps::new()));
+
+        assert!(ops.stat(Path::new("/docs")).unwrap().is_directory);
+        assert_eq!(ops.read(Path::new("/docs/notes.txt"), 0, 64).unwrap(), b"notes");
+        // Relative to the link's directory, through another link
+        assert_eq!(ops.read(Path::new("/data/up"), 0, 64).unwrap(), b"notes");
+
+        let root = ops.readdir(Path::new("/")).unwrap();
+        let docs = root.iter().find(|entry| entry.name == "docs").unwrap();
+        assert!(docs.attributes.is_directory && !docs.attributes.is_symlink);
+    }
+
+    #[test]
+    fn test_exposes_links_it_cannot_follow() {
+        let mut ops = FollowLinksOps::new(Box::new(LinkedOps::new()));
+
+        // A drive path off the volume stays a link
+        assert!(ops.stat(Path::new("/users")).unwrap().is_symlink);
+        assert_eq!(ops.readlink(Path::new("/users")).unwrap(), PathBuf::from("C:\\Users"));
+
+        assert!(ops.stat(Path::new("/loop")).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+// Pre-flight safety lints for formatting
+// Filesystem-specific checks that turn "this will work, but..." knowledge into structured
+// SimulationReport entries the GUI can render as a checklist. Lints never block on their
+// own; callers decide what to do with Error severity entries.
+use moses_core::{Device, DeviceType, FormatOptions, FormatPreset, LintSeverity, SafetyLint};
+use crate::partitioner::{mbr_capacity_warning, PartitionTableType, PARTITION_STYLE_OPTION};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Characters Windows refuses in FAT volume labels
+const FAT_LABEL_FORBIDDEN: &[char] = &['"', '*', '+', ',', '.', '/', ':', ';', '<', '=', '>', '?', '[', '\\', ']', '|'];
+
+/// Check `options` against `device` and return every lint that applies
+pub fn lint(device: &Device, options: &FormatOptions) -> Vec<SafetyLint> {
+    lint_with_sector_size(device, options, logical_sector_size(device))
+}
+
+/// As [`lint`], with the device's logical sector size supplied by the caller
+pub fn lint_with_sector_size(device: &Device, options: &FormatOptions, sector_size: Option<u32>) -> Vec<SafetyLint> {
+    let filesystem = options.filesystem_type.to_lowercase();
+    let mut lints = Vec::new();
+
+    match filesystem.as_str() {
+        "fat16" => {
+            if device.size > 4 * GIB {
+                lints.push(SafetyLint::new(LintSeverity::Error, "fat16-too-large",
+                    "FAT16 volumes are limited to 4 GB; the rest of the device would be unusable")
+                    .with_fix("Choose FAT32 or exFAT"));
+            } else if device.size > 2 * GIB {
+                lints.push(SafetyLint::new(LintSeverity::Warning, "fat16-64k-clusters",
+                    "FAT16 above 2 GB needs 64 KB clusters, which only Windows NT-based systems can read"));
+            }
+            lints.push(file_size_limit("FAT16"));
+        }
+        "fat32" => {
+            if device.size > 2048 * GIB {
+                lints.push(SafetyLint::new(LintSeverity::Error, "fat32-too-large",
+                    "FAT32 cannot address more than 2 TB with 512-byte sectors")
+                    .with_fix("Choose exFAT or NTFS"));
+            } else if device.size > 32 * GIB {
+                lints.push(SafetyLint::new(LintSeverity::Warning, "fat32-large-volume",
+                    "Windows won't format FAT32 above 32 GB but Moses will; Explorer may be slow with large folders"));
+            }
+            // 65525 clusters is the FAT32 minimum; at 512-byte clusters that is about 32 MB
+            if device.size < 65525 * 512 {
+                lints.push(SafetyLint::new(LintSeverity::Error, "fat32-too-small",
+                    "The device is too small for FAT32's minimum of 65525 clusters")
+                    .with_fix("Choose FAT16"));
+            }
+            lints.push(file_size_limit("FAT32"));
+        }
+        "exfat" => {
+            lints.push(SafetyLint::new(LintSeverity::Info, "exfat-legacy-os",
+                "exFAT is not readable by Windows XP without update KB955704 or by macOS before 10.6.5"));
+        }
+        "ntfs" => {
+            lints.push(SafetyLint::new(LintSeverity::Info, "ntfs-macos-read-only",
+                "macOS mounts NTFS read-only and most cameras and consoles cannot read it"));
+        }
+        "ext2" | "ext3" | "ext4" => {
+            lints.push(SafetyLint::new(LintSeverity::Info, "ext-not-readable-on-windows",
+                format!("Windows and macOS cannot read {} without third-party drivers", filesystem)));
+        }
+        _ => {}
+    }
+
+    // The FAT, exFAT and NTFS formatters write 512-byte sector geometry
+    if sector_size.is_some_and(|size| size != 512) && matches!(filesystem.as_str(), "fat16" | "fat32" | "exfat" | "ntfs") {
+        lints.push(SafetyLint::new(LintSeverity::Error, "sector-size-mismatch",
+            format!("The device uses {}-byte sectors but the {} formatter assumes 512-byte sectors",
+                sector_size.unwrap_or_default(), filesystem))
+            .with_fix("Choose ext4, or format with the operating system's own tool"));
+    }
+
+    // A new MBR cannot reach past 2^32 sectors; FAT32 can write a GPT instead
+    if crate::disk_manager::plan::partition_table_written(device, options, &filesystem) == Some(PartitionTableType::MBR) {
+        if let Some(message) = mbr_capacity_warning(device.size, sector_size.unwrap_or(512)) {
+            let lint = SafetyLint::new(LintSeverity::Warning, "mbr-beyond-2tib", message);
+            // Whether the formatter would honour the fix, rather than keep its MBR
+            let with_gpt = FormatOptions {
+                additional_options: options.additional_options.clone().into_iter()
+                    .chain([(PARTITION_STYLE_OPTION.to_string(), "gpt".to_string())])
+                    .collect(),
+                ..options.clone()
+            };
+            lints.push(match crate::disk_manager::plan::partition_table_written(device, &with_gpt, &filesystem) {
+                Some(PartitionTableType::GPT) => lint.auto_fix("Convert to GPT: write a GPT partition table instead of an MBR"),
+                _ => lint.with_fix("Format without a partition table, or convert the disk to GPT first and format its partition"),
+            });
+        }
+    }
+
+    if let Some(label) = &options.label {
+        if let Some(max) = label_limit(&filesystem) {
+            if label.chars().count() > max {
+                lints.push(SafetyLint::new(LintSeverity::Error, "label-too-long",
+                    format!("{} labels are limited to {} characters", filesystem, max))
+                    .auto_fix(format!("Shorten the label to \"{}\"", truncate(label, max))));
+            }
+        }
+        if matches!(filesystem.as_str(), "fat16" | "fat32") && label != &fat_label(label) {
+            lints.push(SafetyLint::new(LintSeverity::Warning, "fat-label-characters",
+                "FAT labels are stored in upper case and cannot contain \" * + , . / : ; < = > ? [ \\ ] |")
+                .auto_fix(format!("Use \"{}\"", fat_label(label))));
+        }
+    }
+
+    if crate::volume_serial::preserve_requested(options).unwrap_or(false)
+        && !crate::volume_serial::PRESERVING_FILESYSTEMS.contains(&filesystem.as_str()) {
+        lints.push(SafetyLint::new(LintSeverity::Error, "serial-not-preserved",
+            format!("The {} formatter cannot keep the current volume serial", filesystem))
+            .with_fix("Choose FAT16, FAT32, exFAT or NTFS, or let the volume get a new serial"));
+    }
+
+    if let Some(cluster_size) = options.cluster_size {
+        if !cluster_size.is_power_of_two() || cluster_size < 512 {
+            lints.push(SafetyLint::new(LintSeverity::Error, "cluster-size-invalid",
+                format!("Cluster size {} is not a power of two of at least 512 bytes", cluster_size))
+                .auto_fix("Use the default cluster size for this volume"));
+        }
+    }
+
+    // Raw NAND has no translation layer: no wear levelling, no bad block remapping
+    if device.id.contains("mtdblock") || device.id.starts_with("/dev/mtd") {
+        lints.push(SafetyLint::new(LintSeverity::Error, "raw-nand",
+            "This is raw NAND flash (MTD) without a translation layer; block filesystems will wear out and corrupt it")
+            .with_fix("Use a flash filesystem such as UBIFS or JFFS2"));
+    } else if device.is_managed_flash() {
+        lints.extend(flash_lints(&filesystem, device, options));
+    }
+
+    // Cards laid out generically straddle erase blocks and write slower
+    if device.device_type == DeviceType::SDCard && matches!(filesystem.as_str(), "fat32" | "exfat")
+        && FormatPreset::from_options(options).is_ok_and(|preset| !preset.uses_sd_layout(device)) {
+        lints.push(SafetyLint::new(LintSeverity::Warning, "sd-card-generic-layout",
+            "This SD card will not get the SD Association layout, which cameras expect and which keeps writes aligned to the card's erase blocks")
+            .auto_fix("Use the SD card preset"));
+    }
+
+    lints
+}
+
+/// Adjust `options` to resolve every auto-fixable lint in `lints`
+pub fn apply_fixes(options: &FormatOptions, lints: &[SafetyLint]) -> FormatOptions {
+    let filesystem = options.filesystem_type.to_lowercase();
+    let mut fixed = options.clone();
+    for lint in lints.iter().filter(|lint| lint.auto_fixable) {
+        match lint.code.as_str() {
+            "label-too-long" => {
+                if let (Some(label), Some(max)) = (&fixed.label, label_limit(&filesystem)) {
+                    fixed.label = Some(truncate(label, max));
+                }
+            }
+            "fat-label-characters" => fixed.label = fixed.label.as_deref().map(fat_label),
+            "cluster-size-invalid" => fixed.cluster_size = None,
+            "mbr-beyond-2tib" => {
+                fixed.additional_options.insert(PARTITION_STYLE_OPTION.to_string(), "gpt".to_string());
+            }
+            "flash-ext4-defaults" => {
+                fixed.additional_options.insert(FormatPreset::OPTION_KEY.to_string(), FormatPreset::Flash.as_str().to_string());
+            }
+            "flash-small-blocks" => fixed.cluster_size = None,
+            "sd-card-generic-layout" => {
+                fixed.additional_options.insert(FormatPreset::OPTION_KEY.to_string(), FormatPreset::SdCard.as_str().to_string());
+            }
+            _ => {}
+        }
+    }
+    fixed
+}
+
+/// ext options that wear managed NAND (SD/eMMC cards, USB sticks) or waste its speed
+fn flash_lints(filesystem: &str, device: &Device, options: &FormatOptions) -> Vec<SafetyLint> {
+    let mut lints = Vec::new();
+    if !matches!(filesystem, "ext2" | "ext3" | "ext4") {
+        return lints;
+    }
+    let preset = FormatPreset::from_options(options).unwrap_or_default();
+    match filesystem {
+        "ext3" => lints.push(SafetyLint::new(LintSeverity::Warning, "flash-ext3-journal",
+            "ext3 always journals, rewriting the same blocks on every commit; that wears flash cards and sticks quickly")
+            .with_fix("Choose ext4 with the flash preset")),
+        "ext4" if preset != FormatPreset::Flash => lints.push(SafetyLint::new(LintSeverity::Warning, "flash-ext4-defaults",
+            "ext4's defaults (journal commits every few seconds, allocation not aligned to erase blocks) wear flash media and slow it down")
+            .auto_fix("Use the flash preset: no journal, discard, stride set from the erase block")),
+        "ext4" if device.erase_block_size.is_none() => lints.push(SafetyLint::new(LintSeverity::Info, "flash-erase-block-unknown",
+            "The device does not report its erase block size, so allocation cannot be aligned to it")),
+        _ => {}
+    }
+    if options.cluster_size.is_some_and(|size| size < 4096) {
+        lints.push(SafetyLint::new(LintSeverity::Warning, "flash-small-blocks",
+            "Blocks smaller than 4 KB split flash pages and multiply writes")
+            .auto_fix("Use 4 KB blocks"));
+    }
+    lints
+}
+
+fn file_size_limit(filesystem: &str) -> SafetyLint {
+    SafetyLint::new(LintSeverity::Info, "fat-file-size-limit",
+        format!("{} cannot store files of 4 GB or larger", filesystem))
+        .with_fix("Choose exFAT to store large files")
+}
+
+fn label_limit(filesystem: &str) -> Option<usize> {
+    match filesystem {
+        "fat16" | "fat32" => Some(11),
+        "exfat" => Some(15),
+        "ext2" | "ext3" | "ext4" => Some(16),
+        "ntfs" => Some(32),
+        _ => None,
+    }
+}
+
+fn truncate(label: &str, max: usize) -> String {
+    label.chars().take(max).collect()
+}
+
+fn fat_label(label: &str) -> String {
+    label.chars()
+        .map(|c| if FAT_LABEL_FORBIDDEN.contains(&c) { '_' } else { c.to_ascii_uppercase() })
+        .collect()
+}
+
+/// Logical sector size reported by the operating system, if it can be determined
+pub fn logical_sector_size(device: &Device) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    {
+        let name = std::path::Path::new(&device.id).file_name()?.to_str()?;
+        let block = std::path::Path::new("/sys/class/block").join(name);
+        // Partitions keep their queue parameters on the parent disk
+        [block.join("queue/logical_block_size"), block.join("../queue/logical_block_size")]
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .and_then(|size| size.trim().parse().ok())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = device;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(size: u64) -> Device {
+        Device {
+            id: "/nonexistent/moses-lint".to_string(),
+            name: "lint".to_string(),
+            size,
+            device_type: DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    fn options(filesystem: &str, label: Option<&str>) -> FormatOptions {
+        FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            label: label.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn codes(lints: &[SafetyLint]) -> Vec<&str> {
+        lints.iter().map(|lint| lint.code.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fat32_lints() {
+        let lints = lint_with_sector_size(&device(64 * GIB), &options("fat32", Some("my.stick")), Some(4096));
+        let codes = codes(&lints);
+        assert!(codes.contains(&"fat32-large-volume"));
+        assert!(codes.contains(&"sector-size-mismatch"));
+        assert!(codes.contains(&"fat-label-characters"));
+        assert!(!codes.contains(&"label-too-long"));
+
+        let small = lint_with_sector_size(&device(16 * 1024 * 1024), &options("fat32", None), Some(512));
+        assert_eq!(small[0].severity, LintSeverity::Error);
+        assert_eq!(small[0].code, "fat32-too-small");
+    }
+
+    #[test]
+    fn test_apply_fixes() {
+        let mut opts = options("exfat", Some("a-very-long-volume-label"));
+        opts.cluster_size = Some(3000);
+        let lints = lint_with_sector_size(&device(8 * GIB), &opts, None);
+        assert!(lints.iter().any(|lint| lint.code == "exfat-legacy-os" && !lint.auto_fixable));
+
+        let fixed = apply_fixes(&opts, &lints);
+        assert_eq!(fixed.label.as_deref(), Some("a-very-long-vol"));
+        assert_eq!(fixed.cluster_size, None);
+        let remaining = lint_with_sector_size(&device(8 * GIB), &fixed, None);
+        assert!(remaining.iter().all(|lint| !lint.auto_fixable));
+
+        let fat = options("fat32", Some("usb:drive"));
+        let fixed = apply_fixes(&fat, &lint_with_sector_size(&device(8 * GIB), &fat, None));
+        assert_eq!(fixed.label.as_deref(), Some("USB_DRIVE"));
+    }
+
+    #[test]
+    fn test_mbr_beyond_2tib() {
+        let disk = device(3 * 1024 * GIB);
+        let mut opts = options("fat32", None);
+        opts.additional_options.insert("create_partition_table".to_string(), "true".to_string());
+        let lints = lint_with_sector_size(&disk, &opts, Some(512));
+        let mbr = lints.iter().find(|lint| lint.code == "mbr-beyond-2tib").unwrap();
+        assert!(mbr.auto_fixable);
+        assert!(mbr.message.contains(&format!("{} bytes", 1024 * GIB)), "{}", mbr.message);
+
+        let fixed = apply_fixes(&opts, &lints);
+        assert_eq!(fixed.additional_options[PARTITION_STYLE_OPTION], "gpt");
+        assert!(!codes(&lint_with_sector_size(&disk, &fixed, Some(512))).contains(&"mbr-beyond-2tib"));
+        // With 4 KB sectors an MBR reaches 16 TiB; without a table there is no MBR at all
+        assert!(!codes(&lint_with_sector_size(&disk, &opts, Some(4096))).contains(&"mbr-beyond-2tib"));
+        assert!(!codes(&lint_with_sector_size(&disk, &options("fat32", None), Some(512))).contains(&"mbr-beyond-2tib"));
+    }
+
+    #[test]
+    fn test_serial_not_preserved() {
+        let mut opts = options("ext4", None);
+        opts.additional_options.insert(crate::volume_serial::PRESERVE_SERIAL_OPTION.to_string(), "true".to_string());
+        assert!(codes(&lint_with_sector_size(&device(8 * GIB), &opts, None)).contains(&"serial-not-preserved"));
+        opts.filesystem_type = "exfat".to_string();
+        assert!(!codes(&lint_with_sector_size(&device(8 * GIB), &opts, None)).contains(&"serial-not-preserved"));
+    }
+
+    #[test]
+    fn test_sd_card_generic_layout() {
+        let mut card = device(32 * GIB);
+        card.device_type = DeviceType::SDCard;
+        let mut opts = options("exfat", None);
+        assert!(!codes(&lint_with_sector_size(&card, &opts, None)).contains(&"sd-card-generic-layout"));
+
+        opts.additional_options.insert(FormatPreset::OPTION_KEY.to_string(), "generic".to_string());
+        let lints = lint_with_sector_size(&card, &opts, None);
+        assert!(codes(&lints).contains(&"sd-card-generic-layout"));
+        let fixed = apply_fixes(&opts, &lints);
+        assert_eq!(FormatPreset::from_options(&fixed).unwrap(), FormatPreset::SdCard);
+    }
+
+    #[test]
+    fn test_flash_lints() {
+        let mut stick = device(16 * GIB);
+        let mut opts = options("ext4", None);
+        opts.cluster_size = Some(1024);
+        let lints = lint_with_sector_size(&stick, &opts, None);
+        assert!(codes(&lints).contains(&"flash-ext4-defaults"));
+        assert!(codes(&lints).contains(&"flash-small-blocks"));
+
+        let fixed = apply_fixes(&opts, &lints);
+        assert_eq!(FormatPreset::from_options(&fixed).unwrap(), FormatPreset::Flash);
+        assert_eq!(fixed.cluster_size, None);
+        assert_eq!(codes(&lint_with_sector_size(&stick, &fixed, None)), ["ext-not-readable-on-windows", "flash-erase-block-unknown"]);
+
+        stick.erase_block_size = Some(4 * 1024 * 1024);
+        assert_eq!(codes(&lint_with_sector_size(&stick, &fixed, None)), ["ext-not-readable-on-windows"]);
+
+        // Fixed disks and SSDs manage their own flash
+        stick.is_removable = false;
+        assert!(!codes(&lint_with_sector_size(&stick, &opts, None)).contains(&"flash-ext4-defaults"));
+
+        stick.id = "/dev/mtdblock0".to_string();
+        assert!(codes(&lint_with_sector_size(&stick, &opts, None)).contains(&"raw-nand"));
+    }
+}
@@ -2,7 +2,7 @@
 // This bridges Moses FilesystemOps to FUSE API using the fuser crate
 
 use super::{MountOptions, MountProvider};
-use crate::ops::{FilesystemOps, FileAttributes};
+use crate::ops::{FilesystemOps, FileAttributes, AttributeChanges};
 use moses_core::{Device, MosesError};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
@@ -204,6 +204,28 @@ impl Filesystem for MosesFuseFilesystem {
         }
     }
     
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let path = match self.get_path_from_inode(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        
+        let mut ops = self.ops.lock().unwrap();
+        
+        match ops.readlink(&path) {
+            Ok(target) => {
+                reply.data(target.as_os_str().as_encoded_bytes());
+            }
+            Err(e) => {
+                log::error!("Failed to read link {:?}: {}", path, e);
+                reply.error(libc::EINVAL);
+            }
+        }
+    }
+    
     fn readdir(
         &mut self,
         _req: &Request,
@@ -349,6 +371,64 @@ impl Filesystem for MosesFuseFilesystem {
         }
     }
     
+    // chmod, chown and truncate
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let path = match self.get_path_from_inode(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        let changes = AttributeChanges { mode: mode.map(|mode| mode & 0o7777), owner: uid, group: gid, ..Default::default() };
+        if self.readonly && (size.is_some() || !changes.is_empty()) {
+            reply.error(libc::EROFS);
+            return;
+        }
+        
+        let mut ops = self.ops.lock().unwrap();
+        let result = (|| {
+            if !changes.is_empty() {
+                ops.set_attributes(&path, &changes)?;
+            }
+            if let Some(size) = size {
+                ops.truncate(&path, size)?;
+            }
+            Ok::<(), MosesError>(())
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to set attributes of {:?}: {}", path, e);
+            reply.error(match e {
+                MosesError::NotSupported(_) => libc::ENOTSUP,
+                MosesError::InvalidInput(_) => libc::EINVAL,
+                _ => libc::EIO,
+            });
+            return;
+        }
+        
+        match ops.stat(&path) {
+            Ok(attrs) => reply.attr(&Duration::from_secs(1), &convert_to_fuse_attr(&attrs, ino)),
+            Err(_) => reply.error(libc::ENOENT),
+        }
+    }
+    
     // Write operations - all return error for read-only filesystem
     fn write(
         &mut self,
@@ -424,10 +504,11 @@ impl MountProvider for FuseMount {
     fn mount(
         &mut self,
         device: &Device,
-        mut ops: Box<dyn FilesystemOps>,
+        ops: Box<dyn FilesystemOps>,
         options: &MountOptions,
     ) -> Result<(), MosesError> {
-        // Initialize the filesystem ops
+        // Initialize the filesystem ops, behind the read-only guard if requested
+        let mut ops = super::enforce_options(ops, device, options);
         ops.init(device)?;
         
         // Create the FUSE filesystem
@@ -529,7 +610,8 @@ impl MountProvider for FuseMount {
     }
     
     fn is_mounted(&self, mount_point: &Path) -> bool {
-        self.mounts.iter().any(|(path, _)| path == mount_point)
+        // mount2 returns when the session ends, whether by unmount or a crash
+        self.mounts.iter().any(|(path, handle)| path == mount_point && !handle.is_finished())
     }
 }
 
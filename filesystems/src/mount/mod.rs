@@ -7,6 +7,11 @@ pub mod winfsp;
 #[cfg(all(unix, feature = "mount-unix"))]
 pub mod fuse;
 
+pub mod watchdog;
+
+pub use watchdog::{MountEvent, MountWatchdog, OpsFactory};
+
+use crate::links::{FollowLinksOps, LinkPolicy};
 use crate::ops::FilesystemOps;
 use moses_core::{Device, MosesError};
 use std::path::Path;
@@ -22,6 +27,8 @@ pub struct MountOptions {
     pub allow_other: bool,
     pub direct_io: bool,
     pub max_read: Option<u32>,
+    /// Whether links (symlinks, NTFS junctions) are shown as links or followed
+    pub links: LinkPolicy,
 }
 
 impl Default for MountOptions {
@@ -35,10 +42,27 @@ impl Default for MountOptions {
             allow_other: false,
             direct_io: false,
             max_read: Some(128 * 1024), // 128KB default
+            links: LinkPolicy::Expose,
         }
     }
 }
 
+/// The ops a provider should mount: read-only mounts are wrapped in ReadOnlyOps so the
+/// guarantee does not depend on the provider honouring its own flag, writable ones in
+/// NotifyingOps so directory watches see their changes, and the link policy is applied
+/// beneath either
+pub fn enforce_options(ops: Box<dyn FilesystemOps>, device: &Device, options: &MountOptions) -> Box<dyn FilesystemOps> {
+    let ops: Box<dyn FilesystemOps> = match options.links {
+        LinkPolicy::Follow => Box::new(FollowLinksOps::new(ops)),
+        LinkPolicy::Expose => ops,
+    };
+    if options.readonly {
+        Box::new(crate::readonly::ReadOnlyOps::new(ops))
+    } else {
+        Box::new(crate::watch::NotifyingOps::new(ops, device))
+    }
+}
+
 /// Common mount interface
 pub trait MountProvider {
     /// Mount a filesystem
@@ -60,11 +84,13 @@ pub trait MountProvider {
 pub fn get_mount_provider() -> Result<Box<dyn MountProvider>, MosesError> {
     #[cfg(all(target_os = "windows", feature = "mount-windows"))]
     {
+        crate::mount_driver::detect().ensure_available()?;
         Ok(Box::new(winfsp::WinFspMount::new()?))
     }
     
     #[cfg(all(unix, feature = "mount-unix"))]
     {
+        crate::mount_driver::detect().ensure_available()?;
         Ok(Box::new(fuse::FuseMount::new()?))
     }
     
@@ -0,0 +1,333 @@
+// Mount supervision
+// A FUSE or WinFsp session can die without telling anyone: the provider thread exits,
+// the driver is restarted, or the device drops off the bus for a moment. The watchdog
+// health-checks every mount it owns, reports why a mount was lost and, for read-only
+// mounts, puts it back with fresh FilesystemOps. Writable mounts are never remounted
+// automatically; a write may have been cut off and the user should look first.
+use super::{MountOptions, MountProvider};
+use crate::ops::FilesystemOps;
+use moses_core::{Device, MosesError};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Time a mount point gets to answer a directory listing
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Remount attempts before the watchdog gives up on a mount
+pub const MAX_REMOUNTS: u32 = 3;
+/// A mount that stays healthy this long after a remount gets its attempts back
+pub const STABLE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Builds fresh ops for a remount; the old ones went down with the session
+pub type OpsFactory = Box<dyn Fn() -> Result<Box<dyn FilesystemOps>, MosesError>>;
+
+/// What happened to a supervised mount
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MountEvent {
+    Lost { mount_point: PathBuf, device_id: String, reason: String },
+    Remounted { mount_point: PathBuf, attempt: u32 },
+    RemountFailed { mount_point: PathBuf, attempt: u32, error: String },
+    /// The mount stays down; `reason` says why no further remount is tried
+    GaveUp { mount_point: PathBuf, reason: String },
+}
+
+struct Supervised {
+    device: Device,
+    options: MountOptions,
+    factory: OpsFactory,
+    remounts: u32,
+    last_remount: Option<Instant>,
+    /// Why the mount is down, while it is
+    down: Option<String>,
+    abandoned: bool,
+}
+
+impl Supervised {
+    fn mount_point(&self) -> PathBuf {
+        PathBuf::from(&self.options.mount_point)
+    }
+}
+
+/// Owns a mount provider and keeps its mounts alive; call `check` periodically
+pub struct MountWatchdog {
+    provider: Box<dyn MountProvider>,
+    mounts: Vec<Supervised>,
+    probe_timeout: Duration,
+}
+
+impl MountWatchdog {
+    pub fn new(provider: Box<dyn MountProvider>) -> Self {
+        Self { provider, mounts: Vec::new(), probe_timeout: PROBE_TIMEOUT }
+    }
+
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Mount `ops` and supervise the result; `factory` recreates the ops for a remount
+    pub fn mount(
+        &mut self,
+        device: &Device,
+        ops: Box<dyn FilesystemOps>,
+        options: &MountOptions,
+        factory: OpsFactory,
+    ) -> Result<(), MosesError> {
+        self.provider.mount(device, ops, options)?;
+        self.mounts.push(Supervised {
+            device: device.clone(),
+            options: options.clone(),
+            factory,
+            remounts: 0,
+            last_remount: None,
+            down: None,
+            abandoned: false,
+        });
+        Ok(())
+    }
+
+    /// Stop supervising and unmount
+    pub fn unmount(&mut self, mount_point: &Path) -> Result<(), MosesError> {
+        self.mounts.retain(|mount| mount.mount_point() != mount_point);
+        self.provider.unmount(mount_point)
+    }
+
+    /// Unmount everything, returning the mount points that failed to unmount
+    pub fn unmount_all(&mut self) -> Vec<(PathBuf, MosesError)> {
+        let mut failures = Vec::new();
+        for mount in std::mem::take(&mut self.mounts) {
+            let mount_point = mount.mount_point();
+            if self.provider.is_mounted(&mount_point) {
+                if let Err(e) = self.provider.unmount(&mount_point) {
+                    failures.push((mount_point, e));
+                }
+            }
+        }
+        failures
+    }
+
+    /// Mount points being supervised, including ones that are currently down
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        self.mounts.iter().map(Supervised::mount_point).collect()
+    }
+
+    /// Health-check every mount once, remounting read-only mounts that were lost
+    pub fn check(&mut self) -> Vec<MountEvent> {
+        let Self { provider, mounts, probe_timeout } = self;
+        let mut events = Vec::new();
+
+        for mount in mounts.iter_mut().filter(|mount| !mount.abandoned) {
+            let mount_point = mount.mount_point();
+            let Some(reason) = diagnose(provider.as_ref(), mount, *probe_timeout) else {
+                if mount.last_remount.is_some_and(|at| at.elapsed() >= STABLE_PERIOD) {
+                    mount.remounts = 0;
+                    mount.last_remount = None;
+                }
+                continue;
+            };
+
+            if mount.down.is_none() {
+                log::warn!("Mount at {} lost: {}", mount_point.display(), reason);
+                events.push(MountEvent::Lost {
+                    mount_point: mount_point.clone(),
+                    device_id: mount.device.id.clone(),
+                    reason: reason.clone(),
+                });
+            }
+            mount.down = Some(reason);
+
+            if !mount.options.readonly {
+                mount.abandoned = true;
+                events.push(give_up(&mount_point, "writable mounts are not remounted automatically".to_string()));
+                continue;
+            }
+            // A device that dropped off may come back; wait for it without spending attempts
+            if !device_present(&mount.device) {
+                continue;
+            }
+            if mount.remounts >= MAX_REMOUNTS {
+                mount.abandoned = true;
+                events.push(give_up(&mount_point, format!("{} remount attempts failed", MAX_REMOUNTS)));
+                continue;
+            }
+
+            mount.remounts += 1;
+            mount.last_remount = Some(Instant::now());
+            // Clear whatever is left of the dead session before mounting again
+            if provider.is_mounted(&mount_point) {
+                let _ = provider.unmount(&mount_point);
+            }
+            match (mount.factory)().and_then(|ops| provider.mount(&mount.device, ops, &mount.options)) {
+                Ok(()) => {
+                    log::info!("Remounted {} (attempt {})", mount_point.display(), mount.remounts);
+                    mount.down = None;
+                    events.push(MountEvent::Remounted { mount_point, attempt: mount.remounts });
+                }
+                Err(e) => {
+                    log::warn!("Remount of {} failed (attempt {}): {}", mount_point.display(), mount.remounts, e);
+                    events.push(MountEvent::RemountFailed {
+                        mount_point,
+                        attempt: mount.remounts,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        events
+    }
+}
+
+fn give_up(mount_point: &Path, reason: String) -> MountEvent {
+    log::error!("Giving up on mount at {}: {}", mount_point.display(), reason);
+    MountEvent::GaveUp { mount_point: mount_point.to_path_buf(), reason }
+}
+
+/// Why a mount is unhealthy, or None when it is fine
+fn diagnose(provider: &dyn MountProvider, mount: &Supervised, timeout: Duration) -> Option<String> {
+    let mount_point = mount.mount_point();
+    if !provider.is_mounted(&mount_point) {
+        return Some("the mount provider session ended".to_string());
+    }
+    if !device_present(&mount.device) {
+        return Some(format!("device {} is no longer present", mount.device.id));
+    }
+    probe(&mount_point, timeout).err()
+}
+
+/// List the mount point on a helper thread so a hung session cannot block the watchdog.
+/// A thread stuck in a dead FUSE mount is left behind; the kernel releases it on unmount.
+fn probe(mount_point: &Path, timeout: Duration) -> Result<(), String> {
+    let mut path = mount_point.to_path_buf();
+    // "M:" names the current directory on drive M, not its root
+    if mount_point.to_string_lossy().ends_with(':') {
+        path.push("\\");
+    }
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = std::fs::read_dir(&path).map(|mut entries| {
+            let _ = entries.next();
+        });
+        let _ = sender.send(result);
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("the mount point cannot be listed: {}", e)),
+        Err(_) => Err(format!("the mount point did not respond within {}s", timeout.as_secs())),
+    }
+}
+
+fn device_present(device: &Device) -> bool {
+    let path = Path::new(&device.id);
+    // Host folders mounted through HostFolderOps use their path as the id
+    if path.is_dir() {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        crate::utils::open_device_read(device).is_ok()
+    }
+    #[cfg(not(windows))]
+    {
+        path.exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+    use moses_core::DeviceType;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    /// Provider whose sessions the test can kill
+    #[derive(Clone, Default)]
+    struct FakeProvider {
+        mounted: Arc<Mutex<HashSet<PathBuf>>>,
+    }
+
+    impl MountProvider for FakeProvider {
+        fn mount(&mut self, _device: &Device, _ops: Box<dyn FilesystemOps>, options: &MountOptions) -> Result<(), MosesError> {
+            self.mounted.lock().unwrap().insert(PathBuf::from(&options.mount_point));
+            Ok(())
+        }
+
+        fn unmount(&mut self, mount_point: &Path) -> Result<(), MosesError> {
+            self.mounted.lock().unwrap().remove(mount_point);
+            Ok(())
+        }
+
+        fn is_mounted(&self, mount_point: &Path) -> bool {
+            self.mounted.lock().unwrap().contains(mount_point)
+        }
+    }
+
+    fn folder_device(dir: &Path) -> Device {
+        Device {
+            id: dir.to_string_lossy().to_string(),
+            name: "folder".to_string(),
+            size: 0,
+            device_type: DeviceType::Virtual,
+            mount_points: vec![],
+            is_removable: false,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        }
+    }
+
+    fn supervise(readonly: bool, factory: OpsFactory) -> (tempfile::TempDir, FakeProvider, MountWatchdog, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = FakeProvider::default();
+        let mut watchdog = MountWatchdog::new(Box::new(provider.clone()));
+        let options = MountOptions { readonly, mount_point: dir.path().to_string_lossy().to_string(), ..Default::default() };
+        let ops = Box::new(HostFolderOps::new(dir.path().to_path_buf()).unwrap());
+        watchdog.mount(&folder_device(dir.path()), ops, &options, factory).unwrap();
+        let mount_point = dir.path().to_path_buf();
+        (dir, provider, watchdog, mount_point)
+    }
+
+    #[test]
+    fn test_readonly_mount_is_remounted() {
+        let (_dir, provider, mut watchdog, mount_point) = supervise(true, Box::new(|| {
+            Ok(Box::new(HostFolderOps::new(std::env::temp_dir())?) as Box<dyn FilesystemOps>)
+        }));
+        assert!(watchdog.check().is_empty());
+
+        provider.mounted.lock().unwrap().clear();
+        let events = watchdog.check();
+        assert!(matches!(&events[0], MountEvent::Lost { reason, .. } if reason.contains("session ended")));
+        assert_eq!(events[1], MountEvent::Remounted { mount_point: mount_point.clone(), attempt: 1 });
+        assert!(provider.is_mounted(&mount_point));
+        assert!(watchdog.check().is_empty());
+    }
+
+    #[test]
+    fn test_watchdog_gives_up() {
+        // Writable mounts are reported but left alone
+        let (_dir, provider, mut watchdog, mount_point) = supervise(false, Box::new(|| panic!("remounted a writable mount")));
+        provider.mounted.lock().unwrap().clear();
+        let events = watchdog.check();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[1], MountEvent::GaveUp { .. }));
+        assert!(watchdog.check().is_empty());
+        assert_eq!(watchdog.mount_points(), [mount_point]);
+
+        // Read-only mounts get MAX_REMOUNTS attempts, and Lost is only reported once
+        let (_dir, provider, mut watchdog, _) = supervise(true, Box::new(|| Err(MosesError::Other("device busy".to_string()))));
+        provider.mounted.lock().unwrap().clear();
+        let mut events = Vec::new();
+        for _ in 0..=MAX_REMOUNTS {
+            events.extend(watchdog.check());
+        }
+        assert!(matches!(events[0], MountEvent::Lost { .. }));
+        assert_eq!(events.iter().filter(|e| matches!(e, MountEvent::RemountFailed { .. })).count(), MAX_REMOUNTS as usize);
+        assert!(matches!(events.last(), Some(MountEvent::GaveUp { .. })));
+        assert_eq!(events.len(), MAX_REMOUNTS as usize + 2);
+    }
+}
@@ -2,7 +2,7 @@
 // This bridges Moses FilesystemOps to WinFsp API
 
 use super::{MountOptions, MountProvider};
-use crate::ops::{FilesystemOps, FileAttributes};
+use crate::ops::{FilesystemOps, FileAttributes, AttributeChanges, dos_attributes};
 use moses_core::{Device, MosesError};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
@@ -225,6 +225,41 @@ impl FileSystemContext for MosesFileSystem {
         }
     }
     
+    // What attrib and Explorer's properties change; times are left as they are
+    fn set_basic_info(
+        &self,
+        context: &Self::FileContext,
+        file_attributes: u32,
+        _creation_time: u64,
+        _last_access_time: u64,
+        _last_write_time: u64,
+        _change_time: u64,
+        file_info: &mut FileInfo,
+    ) -> Result<(), FspError> {
+        // INVALID_FILE_ATTRIBUTES leaves the attributes alone
+        if file_attributes != u32::MAX {
+            if self.readonly {
+                return Err(FspError::from_win32_error(0x13)); // ERROR_WRITE_PROTECT
+            }
+            let editable = dos_attributes::READONLY | dos_attributes::HIDDEN | dos_attributes::SYSTEM | dos_attributes::ARCHIVE;
+            let changes = AttributeChanges {
+                set_dos: file_attributes & editable,
+                clear_dos: !file_attributes & editable,
+                ..Default::default()
+            };
+            let mut ops = self.ops.lock().unwrap();
+            if let Err(e) = ops.set_attributes(context, &changes) {
+                log::error!("Failed to set attributes of {}: {}", context.display(), e);
+                return Err(FspError::from_win32_error(match e {
+                    MosesError::NotSupported(_) => 0x32, // ERROR_NOT_SUPPORTED
+                    _ => 0x1D, // ERROR_WRITE_FAULT
+                }));
+            }
+        }
+        *file_info = self.get_file_info(context)?;
+        Ok(())
+    }
+    
     // Write operations - all return error for read-only filesystem
     fn write(
         &self,
@@ -268,10 +303,11 @@ impl MountProvider for WinFspMount {
     fn mount(
         &mut self,
         device: &Device,
-        mut ops: Box<dyn FilesystemOps>,
+        ops: Box<dyn FilesystemOps>,
         options: &MountOptions,
     ) -> Result<(), MosesError> {
-        // Initialize the filesystem ops
+        // Initialize the filesystem ops, behind the read-only guard if requested
+        let mut ops = super::enforce_options(ops, device, options);
         ops.init(device)?;
         
         // Create Moses filesystem
@@ -0,0 +1,182 @@
+// Detection of the OS driver that mounting relies on
+// Mounting goes through WinFsp on Windows, FUSE on Linux and macFUSE on macOS. These are
+// separate installs, so this checks at runtime whether one is present and which version,
+// independently of whether this build has mount support compiled in. Mounting checks it
+// first so a missing driver is reported with a download link instead of a cryptic failure.
+use moses_core::MosesError;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MountDriver {
+    /// "WinFsp", "FUSE" or "macFUSE"
+    pub name: &'static str,
+    pub installed: bool,
+    pub version: Option<String>,
+    /// Install directory or device node that was found
+    pub location: Option<PathBuf>,
+    /// Oldest version mounting is known to work with
+    pub required_version: &'static str,
+    /// Where to get the driver
+    pub download_url: &'static str,
+}
+
+impl MountDriver {
+    /// Whether this binary was built with mount support for the current platform
+    pub fn compiled_in() -> bool {
+        cfg!(any(
+            all(target_os = "windows", feature = "mount-windows"),
+            all(unix, feature = "mount-unix")
+        ))
+    }
+
+    /// Installed and recent enough; an unreadable version is given the benefit of the doubt
+    pub fn meets_requirement(&self) -> bool {
+        self.installed
+            && match self.version.as_deref() {
+                Some(version) => version_at_least(version, self.required_version),
+                None => true,
+            }
+    }
+
+    /// Error out with install instructions unless the driver is usable
+    pub fn ensure_available(&self) -> Result<(), MosesError> {
+        if self.meets_requirement() {
+            return Ok(());
+        }
+        Err(MosesError::MountDriverMissing {
+            driver: self.name.to_string(),
+            required_version: self.required_version.to_string(),
+            installed: match (&self.version, self.installed) {
+                (Some(version), true) => version.clone(),
+                _ => "none".to_string(),
+            },
+            download_url: self.download_url.to_string(),
+        })
+    }
+}
+
+/// Compare dotted version numbers numerically, so 2.10 is newer than 2.9
+fn version_at_least(version: &str, required: &str) -> bool {
+    let parse = |text: &str| -> Vec<u64> {
+        text.split('.').map(|part| part.parse().unwrap_or(0)).collect()
+    };
+    let (mut version, mut required) = (parse(version), parse(required));
+    let len = version.len().max(required.len());
+    version.resize(len, 0);
+    required.resize(len, 0);
+    version >= required
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // fusermount prints its version on stderr
+    let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+    Some(String::from_utf8_lossy(&text).trim().to_string())
+}
+
+/// Take the version number out of e.g. `fusermount3 version: 3.10.3`
+fn parse_version(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()) && word.contains('.'))
+        .map(|word| word.trim_end_matches(|c: char| !c.is_ascii_digit()).to_string())
+}
+
+/// Look for the platform's mount driver
+#[cfg(target_os = "linux")]
+pub fn detect() -> MountDriver {
+    let device = std::path::Path::new("/dev/fuse");
+    let version = command_output("fusermount3", &["-V"])
+        .or_else(|| command_output("fusermount", &["-V"]))
+        .and_then(|text| parse_version(&text));
+    MountDriver {
+        name: "FUSE",
+        installed: device.exists() && version.is_some(),
+        version,
+        location: device.exists().then(|| device.to_path_buf()),
+        required_version: "2.9",
+        download_url: "https://github.com/libfuse/libfuse",
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn detect() -> MountDriver {
+    let bundle = std::path::Path::new("/Library/Filesystems/macfuse.fs");
+    let version = bundle
+        .exists()
+        .then(|| {
+            let plist = bundle.join("Contents/Info");
+            command_output("defaults", &["read", &plist.to_string_lossy(), "CFBundleVersion"])
+        })
+        .flatten();
+    MountDriver {
+        name: "macFUSE",
+        installed: bundle.exists(),
+        version,
+        location: bundle.exists().then(|| bundle.to_path_buf()),
+        required_version: "4.0",
+        download_url: "https://osxfuse.github.io/",
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn detect() -> MountDriver {
+    // The installer records its directory under the 32-bit registry view
+    let install_dir = command_output("reg", &["query", r"HKLM\SOFTWARE\WOW6432Node\WinFsp", "/v", "InstallDir"])
+        .and_then(|text| {
+            text.lines()
+                .find(|line| line.contains("InstallDir"))
+                .and_then(|line| line.split("REG_SZ").nth(1))
+                .map(|dir| PathBuf::from(dir.trim()))
+        })
+        .filter(|dir| dir.join("bin").join("winfsp-x64.dll").exists());
+    let version = install_dir.as_ref().and_then(|dir| {
+        let dll = dir.join("bin").join("winfsp-x64.dll");
+        let script = format!("(Get-Item '{}').VersionInfo.ProductVersion", dll.display());
+        command_output("powershell", &["-NoProfile", "-Command", &script]).and_then(|text| parse_version(&text))
+    });
+    MountDriver {
+        name: "WinFsp",
+        installed: install_dir.is_some(),
+        version,
+        location: install_dir,
+        required_version: "2.0",
+        download_url: "https://winfsp.dev/rel/",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("fusermount3 version: 3.10.3"), Some("3.10.3".to_string()));
+        assert_eq!(parse_version("2.2 (1.0.0)"), Some("2.2".to_string()));
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[test]
+    fn test_version_requirement() {
+        assert!(version_at_least("2.10", "2.9"));
+        assert!(version_at_least("3", "2.9"));
+        assert!(!version_at_least("1.12.4", "2.0"));
+
+        let driver = MountDriver {
+            name: "FUSE",
+            installed: true,
+            version: Some("2.8".to_string()),
+            location: None,
+            required_version: "2.9",
+            download_url: "https://github.com/libfuse/libfuse",
+        };
+        assert!(matches!(
+            driver.ensure_available(),
+            Err(MosesError::MountDriverMissing { installed, .. }) if installed == "2.8"
+        ));
+    }
+}
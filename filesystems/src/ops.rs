@@ -3,7 +3,9 @@
 // enabling Moses to read, write, and mount any filesystem on any platform
 
 use moses_core::{Device, MosesError};
+use serde::{Serialize, Deserialize};
 use std::path::{Path, PathBuf};
+use crate::walker::{walk_tree, WalkControl, WalkOptions};
 
 /// File attributes returned by stat operations
 #[derive(Debug, Clone)]
@@ -27,6 +29,109 @@ pub struct DirectoryEntry {
     pub attributes: FileAttributes,
 }
 
+/// Counts and sizes of everything below a path, returned by [`FilesystemOps::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeStats {
+    pub files: u64,
+    /// Folders, counting the one asked about
+    pub directories: u64,
+    /// Links are counted, not followed
+    pub symlinks: u64,
+    /// Sum of the file sizes, not the space allocated for them
+    pub total_size: u64,
+    /// Levels of entries below the path: 0 for a file or an empty folder
+    pub depth: u32,
+}
+
+/// DOS/Windows attribute bits, as FAT, exFAT and NTFS store them
+pub mod dos_attributes {
+    pub const READONLY: u32 = 0x01;
+    pub const HIDDEN: u32 = 0x02;
+    pub const SYSTEM: u32 = 0x04;
+    pub const ARCHIVE: u32 = 0x20;
+}
+
+/// Changes for [`FilesystemOps::set_attributes`]; `None` and unset bits leave a value as it is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeChanges {
+    /// Unix permission bits (`0o7777` at most); on DOS filesystems only the owner's write
+    /// bit means anything, and clears or sets the read-only attribute
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    /// [`dos_attributes`] bits to set
+    pub set_dos: u32,
+    /// [`dos_attributes`] bits to clear
+    pub clear_dos: u32,
+}
+
+impl AttributeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.owner.is_none() && self.group.is_none() && self.set_dos == 0 && self.clear_dos == 0
+    }
+
+    /// Attribute bits from letters as `attrib` names them: r(ead-only), h(idden), s(ystem), a(rchive)
+    pub fn parse_dos(letters: &str) -> Result<u32, MosesError> {
+        letters.chars().filter(|c| !matches!(c, ',' | ' ' | '+' | '-')).try_fold(0, |bits, letter| {
+            Ok(bits | match letter.to_ascii_lowercase() {
+                'r' => dos_attributes::READONLY,
+                'h' => dos_attributes::HIDDEN,
+                's' => dos_attributes::SYSTEM,
+                'a' => dos_attributes::ARCHIVE,
+                _ => return Err(MosesError::InvalidInput(format!(
+                    "Unknown attribute '{}' (expected r, h, s or a)", letter
+                ))),
+            })
+        })
+    }
+
+    /// The attribute bits to set and clear on a DOS filesystem, with the mode's owner write
+    /// bit as the read-only attribute; owners and groups cannot be stored there
+    pub fn dos_bits(&self, filesystem: &str) -> Result<(u32, u32), MosesError> {
+        if self.owner.is_some() || self.group.is_some() {
+            return Err(MosesError::NotSupported(format!("{} does not store file owners", filesystem)));
+        }
+        let (mut set, mut clear) = (self.set_dos, self.clear_dos);
+        match self.mode {
+            Some(mode) if mode & 0o200 == 0 => set |= dos_attributes::READONLY,
+            Some(_) => clear |= dos_attributes::READONLY,
+            None => {}
+        }
+        if set & clear != 0 {
+            return Err(MosesError::InvalidInput("An attribute cannot be both set and cleared".to_string()));
+        }
+        Ok((set, clear))
+    }
+
+    /// What the change does, e.g. "mode 0644, +hidden"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(mode) = self.mode {
+            parts.push(format!("mode {:04o}", mode));
+        }
+        if let Some(owner) = self.owner {
+            parts.push(format!("owner {}", owner));
+        }
+        if let Some(group) = self.group {
+            parts.push(format!("group {}", group));
+        }
+        for (bit, name) in [
+            (dos_attributes::READONLY, "read-only"),
+            (dos_attributes::HIDDEN, "hidden"),
+            (dos_attributes::SYSTEM, "system"),
+            (dos_attributes::ARCHIVE, "archive"),
+        ] {
+            if self.set_dos & bit != 0 {
+                parts.push(format!("+{}", name));
+            }
+            if self.clear_dos & bit != 0 {
+                parts.push(format!("-{}", name));
+            }
+        }
+        parts.join(", ")
+    }
+}
+
 /// Core filesystem operations trait
 /// All operations are synchronous to match WinFsp/FUSE requirements
 pub trait FilesystemOps: Send + Sync {
@@ -45,6 +150,26 @@ pub trait FilesystemOps: Send + Sync {
     /// Read file contents
     fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError>;
     
+    /// File and folder counts, total size and depth of the tree at `path`
+    ///
+    /// The default walks `readdir`, whose entries already carry their attributes;
+    /// filesystems with a cheaper traversal override it.
+    fn stats(&mut self, path: &Path) -> Result<TreeStats, MosesError> {
+        tree_stats(self, path)
+    }
+    
+    /// A number naming the directory at `path` and no other one on the volume, such as its
+    /// inode number (optional). The tree walker uses it to notice a directory it has
+    /// already entered through a hard link or a loop.
+    fn directory_id(&mut self, _path: &Path) -> Option<u64> {
+        None
+    }
+    
+    /// Target of a symbolic link, junction or mount point (optional)
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        Err(MosesError::NotSupported(format!("Cannot read link {}: filesystem has no links", path.display())))
+    }
+    
     /// Write file contents (optional - not all filesystems support write)
     fn write(&mut self, _path: &Path, _offset: u64, _data: &[u8]) -> Result<u32, MosesError> {
         Err(MosesError::NotSupported("Filesystem is read-only".to_string()))
@@ -80,6 +205,11 @@ pub trait FilesystemOps: Send + Sync {
         Err(MosesError::NotSupported("Filesystem is read-only".to_string()))
     }
     
+    /// Change the mode, owner or DOS attributes of a file or directory (optional)
+    fn set_attributes(&mut self, _path: &Path, _changes: &AttributeChanges) -> Result<(), MosesError> {
+        Err(MosesError::NotSupported("Filesystem is read-only".to_string()))
+    }
+    
     /// Flush any pending writes
     fn sync(&mut self) -> Result<(), MosesError> {
         Ok(()) // No-op for read-only filesystems
@@ -90,10 +220,45 @@ pub trait FilesystemOps: Send + Sync {
         true // Default to read-only
     }
     
+    /// Problems noticed while opening the filesystem that make what is read unreliable,
+    /// such as an unclean shutdown (optional)
+    fn warnings(&self) -> Vec<String> {
+        Vec::new()
+    }
+    
     /// Get filesystem type name (e.g., "ext4", "ntfs", "fat32")
     fn filesystem_type(&self) -> &str;
 }
 
+/// [`FilesystemOps::stats`] by walking `readdir`
+pub fn tree_stats<F: FilesystemOps + ?Sized>(fs: &mut F, path: &Path) -> Result<TreeStats, MosesError> {
+    let mut stats = TreeStats::default();
+    let root = fs.stat(path)?;
+    if root.is_symlink {
+        stats.symlinks = 1;
+        return Ok(stats);
+    }
+    if !root.is_directory {
+        stats.files = 1;
+        stats.total_size = root.size;
+        return Ok(stats);
+    }
+    stats.directories = 1;
+    walk_tree(fs, path, &WalkOptions::default(), |_, entry| {
+        stats.depth = stats.depth.max(entry.depth as u32);
+        if entry.attributes.is_symlink {
+            stats.symlinks += 1;
+        } else if entry.attributes.is_directory {
+            stats.directories += 1;
+        } else {
+            stats.files += 1;
+            stats.total_size += entry.attributes.size;
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(stats)
+}
+
 /// Filesystem information
 #[derive(Debug, Clone)]
 pub struct FilesystemInfo {
@@ -127,6 +292,10 @@ pub trait FilesystemDetector: Send + Sync {
 pub struct FilesystemOpsRegistry {
     ops: std::collections::HashMap<String, Box<dyn Fn(&Device) -> Result<Box<dyn FilesystemOps>, MosesError>>>,
     detectors: Vec<Box<dyn FilesystemDetector>>,
+    /// Types whose registered operations can write
+    writable: std::collections::HashSet<String>,
+    /// Category of types that have no formatter to carry one, such as flash filesystems
+    categories: std::collections::HashMap<String, moses_core::FormatterCategory>,
 }
 
 impl FilesystemOpsRegistry {
@@ -134,6 +303,8 @@ impl FilesystemOpsRegistry {
         Self {
             ops: std::collections::HashMap::new(),
             detectors: Vec::new(),
+            writable: std::collections::HashSet::new(),
+            categories: std::collections::HashMap::new(),
         }
     }
     
@@ -142,9 +313,34 @@ impl FilesystemOpsRegistry {
     where
         F: Fn(&Device) -> Result<Box<dyn FilesystemOps>, MosesError> + 'static,
     {
+        self.writable.remove(filesystem_type);
         self.ops.insert(filesystem_type.to_string(), Box::new(factory));
     }
     
+    /// Register a factory whose operations can write as well as read
+    pub fn register_writable_ops<F>(&mut self, filesystem_type: &str, factory: F)
+    where
+        F: Fn(&Device) -> Result<Box<dyn FilesystemOps>, MosesError> + 'static,
+    {
+        self.register_ops(filesystem_type, factory);
+        self.writable.insert(filesystem_type.to_string());
+    }
+    
+    /// File a read-only type under a formatter category, so it is listed with the formatters
+    pub fn set_category(&mut self, filesystem_type: &str, category: moses_core::FormatterCategory) {
+        self.categories.insert(filesystem_type.to_string(), category);
+    }
+    
+    /// Types filed under `category`, sorted
+    pub fn list_by_category(&self, category: &moses_core::FormatterCategory) -> Vec<String> {
+        let mut types: Vec<String> = self.categories.iter()
+            .filter(|(_, filed)| *filed == category)
+            .map(|(filesystem_type, _)| filesystem_type.clone())
+            .collect();
+        types.sort();
+        types
+    }
+    
     /// Register a filesystem detector
     pub fn register_detector(&mut self, detector: Box<dyn FilesystemDetector>) {
         self.detectors.push(detector);
@@ -178,6 +374,11 @@ impl FilesystemOpsRegistry {
     pub fn supported_types(&self) -> Vec<String> {
         self.ops.keys().cloned().collect()
     }
+    
+    /// Whether the operations registered for `filesystem_type` can write
+    pub fn is_writable(&self, filesystem_type: &str) -> bool {
+        self.writable.contains(filesystem_type)
+    }
 }
 
 impl Default for FilesystemOpsRegistry {
@@ -200,6 +401,11 @@ pub enum MountSource {
     },
     /// Mount a folder from the host filesystem directly
     HostPath(PathBuf),
+    /// Mount an NTFS volume as it was when a shadow copy was taken (GUID or index)
+    NtfsSnapshot {
+        device: Device,
+        snapshot_id: String,
+    },
 }
 
 /// Wrapper that adds base path support to any FilesystemOps
@@ -267,6 +473,26 @@ impl FilesystemOps for SubfolderOps {
         self.inner.read(&internal_path, offset, size)
     }
     
+    fn stats(&mut self, path: &Path) -> Result<TreeStats, MosesError> {
+        let internal_path = self.to_internal_path(path);
+        self.inner.stats(&internal_path)
+    }
+    
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        let internal_path = self.to_internal_path(path);
+        self.inner.directory_id(&internal_path)
+    }
+    
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        let internal_path = self.to_internal_path(path);
+        self.inner.readlink(&internal_path)
+    }
+    
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let internal_path = self.to_internal_path(path);
+        self.inner.set_attributes(&internal_path, changes)
+    }
+    
     fn filesystem_type(&self) -> &str {
         self.inner.filesystem_type()
     }
@@ -274,6 +500,10 @@ impl FilesystemOps for SubfolderOps {
     fn is_readonly(&self) -> bool {
         self.inner.is_readonly()
     }
+    
+    fn warnings(&self) -> Vec<String> {
+        self.inner.warnings()
+    }
 }
 
 /// Host filesystem operations - mount any folder from the host OS as a drive
@@ -298,22 +528,24 @@ impl HostFolderOps {
             )));
         }
         
-        // Detect host filesystem type
-        let fs_type = if cfg!(windows) {
-            "NTFS"
-        } else if cfg!(target_os = "macos") {
-            "APFS"
-        } else {
-            "ext4"
-        }.to_string();
-        
         Ok(Self {
             base_path: path,
-            fs_type,
+            fs_type: host_filesystem_type().to_string(),
         })
     }
 }
 
+/// Filesystem the host OS usually keeps its folders on
+pub(crate) fn host_filesystem_type() -> &'static str {
+    if cfg!(windows) {
+        "NTFS"
+    } else if cfg!(target_os = "macos") {
+        "APFS"
+    } else {
+        "ext4"
+    }
+}
+
 impl FilesystemOps for HostFolderOps {
     fn init(&mut self, _device: &Device) -> Result<(), MosesError> {
         Ok(())
@@ -419,6 +651,49 @@ impl FilesystemOps for HostFolderOps {
         Ok(entries)
     }
     
+    fn stats(&mut self, path: &Path) -> Result<TreeStats, MosesError> {
+        // Directory entry types come from the listing itself; only files need a stat
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        let mut stats = TreeStats::default();
+        let root = std::fs::symlink_metadata(&full_path)?;
+        if root.file_type().is_symlink() {
+            stats.symlinks = 1;
+            return Ok(stats);
+        }
+        if !root.is_dir() {
+            stats.files = 1;
+            stats.total_size = root.len();
+            return Ok(stats);
+        }
+        stats.directories = 1;
+        let mut pending = vec![(full_path, 1)];
+        while let Some((dir, depth)) = pending.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                stats.depth = stats.depth.max(depth);
+                if file_type.is_symlink() {
+                    stats.symlinks += 1;
+                } else if file_type.is_dir() {
+                    stats.directories += 1;
+                    pending.push((entry.path(), depth + 1));
+                } else {
+                    stats.files += 1;
+                    stats.total_size += entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                }
+            }
+        }
+        Ok(stats)
+    }
+    
+    #[cfg(unix)]
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(self.base_path.join(path.strip_prefix("/").unwrap_or(path))).ok()?;
+        // The folder may span mounts, whose inode numbers overlap
+        Some(metadata.ino() ^ metadata.dev().rotate_left(40))
+    }
+    
     fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
         use std::fs::File;
         use std::io::{Read, Seek, SeekFrom};
@@ -439,6 +714,81 @@ impl FilesystemOps for HostFolderOps {
         Ok(buffer)
     }
     
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        std::fs::read_link(&full_path).map_err(MosesError::IoError)
+    }
+    
+    fn write(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<u32, MosesError> {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        let mut file = std::fs::OpenOptions::new().write(true).open(&full_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(data.len() as u32)
+    }
+
+    fn create(&mut self, path: &Path, _mode: u32) -> Result<(), MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        std::fs::File::create(&full_path).map_err(MosesError::IoError)?;
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &Path, _mode: u32) -> Result<(), MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        std::fs::create_dir(&full_path).map_err(MosesError::IoError)
+    }
+
+    fn unlink(&mut self, path: &Path) -> Result<(), MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        std::fs::remove_file(&full_path).map_err(MosesError::IoError)
+    }
+
+    fn rmdir(&mut self, path: &Path) -> Result<(), MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        std::fs::remove_dir(&full_path).map_err(MosesError::IoError)
+    }
+
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let full_path = self.base_path.join(path.strip_prefix("/").unwrap_or(path));
+        if (changes.set_dos | changes.clear_dos) & !dos_attributes::READONLY != 0 {
+            return Err(MosesError::NotSupported("Host folders only take the read-only attribute".to_string()));
+        }
+        let mut permissions = std::fs::metadata(&full_path)?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut mode = changes.mode.unwrap_or(permissions.mode()) & 0o7777;
+            if changes.set_dos & dos_attributes::READONLY != 0 {
+                mode &= !0o222;
+            } else if changes.clear_dos & dos_attributes::READONLY != 0 {
+                mode |= 0o200;
+            }
+            permissions.set_mode(mode);
+            if changes.owner.is_some() || changes.group.is_some() {
+                std::os::unix::fs::chown(&full_path, changes.owner, changes.group)?;
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            if changes.mode.is_some() || changes.owner.is_some() || changes.group.is_some() {
+                return Err(MosesError::NotSupported("Host folders here have no Unix modes or owners".to_string()));
+            }
+            if changes.set_dos & dos_attributes::READONLY != 0 {
+                permissions.set_readonly(true);
+            } else if changes.clear_dos & dos_attributes::READONLY != 0 {
+                permissions.set_readonly(false);
+            }
+        }
+        std::fs::set_permissions(&full_path, permissions)?;
+        Ok(())
+    }
+
+    fn is_readonly(&self) -> bool {
+        false
+    }
+
     fn filesystem_type(&self) -> &str {
         &self.fs_type
     }
@@ -471,4 +821,26 @@ pub fn register_builtin_ops(registry: &mut FilesystemOpsRegistry) {
     registry.register_detector(Box::new(ExtOpsDetector));
     
     // TODO: Add NTFS, FAT32, exFAT once their readers support the necessary operations
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("Photos/2019/Raw")).unwrap();
+        std::fs::create_dir_all(dir.path().join("Empty")).unwrap();
+        std::fs::write(dir.path().join("Photos/2019/a.jpg"), vec![0u8; 1000]).unwrap();
+        std::fs::write(dir.path().join("Photos/2019/Raw/a.raw"), vec![0u8; 5000]).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"notes").unwrap();
+        let mut fs = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+
+        let expected = TreeStats { files: 3, directories: 5, symlinks: 0, total_size: 6005, depth: 4 };
+        assert_eq!(fs.stats(Path::new("/")).unwrap(), expected);
+        assert_eq!(tree_stats(&mut fs, Path::new("/")).unwrap(), expected, "the walk over readdir agrees");
+        assert_eq!(fs.stats(Path::new("/Photos/2019")).unwrap(), TreeStats { files: 2, directories: 2, symlinks: 0, total_size: 6000, depth: 2 });
+        assert_eq!(fs.stats(Path::new("/notes.txt")).unwrap(), TreeStats { files: 1, total_size: 5, ..Default::default() });
+        assert_eq!(fs.stats(Path::new("/Empty")).unwrap(), TreeStats { directories: 1, ..Default::default() });
+    }
+}
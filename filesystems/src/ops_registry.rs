@@ -2,7 +2,7 @@
 // Includes read-write support for NTFS
 
 use crate::ops::{FilesystemOps, FilesystemOpsRegistry};
-use moses_core::{Device, MosesError};
+use moses_core::{Device, FormatterCategory, MosesError};
 
 /// Register all built-in filesystem operations
 pub fn register_all_filesystems(registry: &mut FilesystemOpsRegistry, enable_write: bool) {
@@ -11,6 +11,11 @@ pub fn register_all_filesystems(registry: &mut FilesystemOpsRegistry, enable_wri
     use crate::families::fat::fat32::Fat32Ops;
     use crate::families::fat::fat16::Fat16Ops;
     use crate::families::fat::exfat::ExFatOps;
+    use crate::families::btrfs::{BtrfsOps, BtrfsDetector};
+    use crate::families::xfs::{XfsOps, XfsDetector};
+    use crate::families::hfsplus::{HfsPlusOps, HfsPlusDetector};
+    use crate::families::flash::jffs2::{Jffs2Ops, Jffs2Detector};
+    use crate::families::optical::iso9660::{Iso9660Ops, Iso9660Detector};
     
     // Register ext4 operations (read-only for now)
     registry.register_ops("ext4", |device| {
@@ -34,7 +39,7 @@ pub fn register_all_filesystems(registry: &mut FilesystemOpsRegistry, enable_wri
     // Register NTFS operations
     if enable_write {
         // Use read-write version if writes are enabled
-        registry.register_ops("ntfs", |device| {
+        registry.register_writable_ops("ntfs", |device| {
             let mut ops = NtfsRwOps::new();
             ops.enable_writes(true);  // Enable write support
             ops.init(device)?;
@@ -70,12 +75,53 @@ pub fn register_all_filesystems(registry: &mut FilesystemOpsRegistry, enable_wri
         Ok(Box::new(ops))
     });
     
+    // Register btrfs operations (read-only)
+    registry.register_ops("btrfs", |device| {
+        let mut ops = BtrfsOps::new();
+        ops.init(device)?;
+        Ok(Box::new(ops))
+    });
+    
+    // Register XFS operations (read-only)
+    registry.register_ops("xfs", |device| {
+        let mut ops = XfsOps::new();
+        ops.init(device)?;
+        Ok(Box::new(ops))
+    });
+    
+    // Register HFS+ operations (read-only)
+    registry.register_ops("hfsplus", |device| {
+        let mut ops = HfsPlusOps::new();
+        ops.init(device)?;
+        Ok(Box::new(ops))
+    });
+    
+    // Register JFFS2 operations (read-only), for firmware dumps of embedded boards
+    registry.register_ops("jffs2", |device| {
+        let mut ops = Jffs2Ops::new();
+        ops.init(device)?;
+        Ok(Box::new(ops))
+    });
+    registry.set_category("jffs2", FormatterCategory::Embedded);
+    
+    // Register ISO9660 operations (read-only), for installer images and optical media
+    registry.register_ops("iso9660", |device| {
+        let mut ops = Iso9660Ops::new();
+        ops.init(device)?;
+        Ok(Box::new(ops))
+    });
+    
     // Register filesystem detectors
     registry.register_detector(Box::new(ExtOpsDetector));
     registry.register_detector(Box::new(NtfsDetector));
     registry.register_detector(Box::new(Fat32Detector));
     registry.register_detector(Box::new(Fat16Detector));
     registry.register_detector(Box::new(ExFatDetector));
+    registry.register_detector(Box::new(BtrfsDetector));
+    registry.register_detector(Box::new(XfsDetector));
+    registry.register_detector(Box::new(HfsPlusDetector));
+    registry.register_detector(Box::new(Jffs2Detector));
+    registry.register_detector(Box::new(Iso9660Detector));
 }
 
 // Filesystem detectors
@@ -3,20 +3,51 @@
 
 
 pub mod mbr_verifier;
-use moses_core::{Device, MosesError};
+use moses_core::{Device, FormatOptions, MosesError};
 
 #[cfg(test)]
 mod mbr_tests;
 use std::io::{Write, Seek, SeekFrom};
 use log::info;
 
+/// Format option choosing the table a formatter writes when it creates one: "mbr" (the default) or "gpt"
+pub const PARTITION_STYLE_OPTION: &str = "partition_style";
+
+/// Sectors an MBR can address; its start and size fields are 32-bit sector counts
+pub const MBR_MAX_SECTORS: u64 = 1 << 32;
+
 /// Type of partition table to create
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PartitionTableType {
     MBR,
     GPT,
 }
 
+impl PartitionTableType {
+    /// The table style asked for with [`PARTITION_STYLE_OPTION`]; MBR if none was
+    pub fn from_options(options: &FormatOptions) -> Result<Self, MosesError> {
+        match options.additional_options.get(PARTITION_STYLE_OPTION).map(|value| value.to_lowercase()).as_deref() {
+            None | Some("mbr") => Ok(Self::MBR),
+            Some("gpt") => Ok(Self::GPT),
+            Some(other) => Err(MosesError::InvalidInput(format!("Unknown partition style '{}'; use mbr or gpt", other))),
+        }
+    }
+}
+
+/// Bytes of a `disk_size` disk with `sector_size`-byte sectors that an MBR cannot reach
+pub fn mbr_unusable_bytes(disk_size: u64, sector_size: u32) -> u64 {
+    disk_size.saturating_sub(MBR_MAX_SECTORS * sector_size as u64)
+}
+
+/// Why an MBR on this disk would waste space, with the exact amount, or None if it fits
+pub fn mbr_capacity_warning(disk_size: u64, sector_size: u32) -> Option<String> {
+    let unusable = mbr_unusable_bytes(disk_size, sector_size);
+    (unusable > 0).then(|| format!(
+        "An MBR partition table can only address the first {} TiB of this disk; the remaining {} bytes ({:.2} GiB) would be unusable. Use GPT to reach the whole disk",
+        (MBR_MAX_SECTORS * sector_size as u64) >> 40, unusable, unusable as f64 / (1u64 << 30) as f64,
+    ))
+}
+
 /// Partition entry for creation
 #[derive(Debug, Clone)]
 pub struct PartitionEntry {
@@ -33,13 +64,40 @@ pub fn create_single_partition_table(
     filesystem_type: &str,
 ) -> Result<Vec<u8>, MosesError> {
     match table_type {
-        PartitionTableType::MBR => create_mbr_single_partition(device, filesystem_type),
+        PartitionTableType::MBR => create_mbr_single_partition(device, filesystem_type, 2048, (255, 63)),  // 1MB aligned
         PartitionTableType::GPT => create_gpt_single_partition(device, filesystem_type),
     }
 }
 
+/// Create an MBR whose single partition starts at `start_lba` instead of the usual 1MB,
+/// e.g. on an SD card boundary unit
+pub fn create_mbr_partition_table_at(
+    device: &Device,
+    filesystem_type: &str,
+    start_lba: u32,
+) -> Result<Vec<u8>, MosesError> {
+    create_mbr_single_partition(device, filesystem_type, start_lba, (255, 63))
+}
+
+/// As [`create_mbr_partition_table_at`], with CHS addresses for a disk of `heads` heads
+/// and `sectors_per_track` sectors per track rather than the usual 255 and 63
+pub fn create_mbr_partition_table_with_geometry(
+    device: &Device,
+    filesystem_type: &str,
+    start_lba: u32,
+    heads: u16,
+    sectors_per_track: u16,
+) -> Result<Vec<u8>, MosesError> {
+    create_mbr_single_partition(device, filesystem_type, start_lba, (heads as u32, sectors_per_track as u32))
+}
+
 /// Create an MBR with a single partition
-fn create_mbr_single_partition(device: &Device, filesystem_type: &str) -> Result<Vec<u8>, MosesError> {
+fn create_mbr_single_partition(
+    device: &Device,
+    filesystem_type: &str,
+    start_lba: u32,
+    (heads, sectors_per_track): (u32, u32),
+) -> Result<Vec<u8>, MosesError> {
     let mut mbr = vec![0u8; 512];
     
     // MBR boot code (minimal - just enough to be valid)
@@ -61,17 +119,14 @@ fn create_mbr_single_partition(device: &Device, filesystem_type: &str) -> Result
     };
     
     // Calculate partition parameters
-    let start_lba = 2048u32;  // Start at 1MB for alignment (standard for modern systems)
-    let total_sectors = (device.size / 512) as u32;
+    // Sectors past the 32-bit limit cannot be described; see mbr_capacity_warning
+    let total_sectors = (device.size / 512).min(u32::MAX as u64) as u32;
     let partition_size = total_sectors.saturating_sub(start_lba);
     
     // Calculate CHS values (for compatibility, though LBA is used)
-    // Standard geometry: 255 heads, 63 sectors per track
-    let heads = 255u32;
-    let sectors_per_track = 63u32;
     let cylinder_size = heads * sectors_per_track;
     
-    // Starting CHS (for LBA 2048)
+    // Starting CHS
     let start_cylinder = start_lba / cylinder_size;
     let start_temp = start_lba % cylinder_size;
     let start_head = start_temp / sectors_per_track;
@@ -126,7 +181,7 @@ fn create_mbr_single_partition(device: &Device, filesystem_type: &str) -> Result
     info!("  Start LBA: {} (offset {} bytes)", start_lba, start_lba * 512);
     info!("  Size: {} sectors ({} MB)", partition_size, partition_size * 512 / 1024 / 1024);
     info!("  Disk signature: 0x{:08X}", disk_sig);
-    info!("  CHS geometry: 255 heads, 63 sectors/track");
+    info!("  CHS geometry: {} heads, {} sectors/track", heads, sectors_per_track);
     
     Ok(mbr)
 }
@@ -161,7 +216,7 @@ fn create_gpt_single_partition(device: &Device, filesystem_type: &str) -> Result
     
     // LBA 1 to end of disk
     protective_mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
-    let protective_size = ((device.size / 512).saturating_sub(1) as u32).min(0xFFFFFFFF);
+    let protective_size = (device.size / 512).saturating_sub(1).min(u32::MAX as u64) as u32;
     protective_mbr[446 + 12..446 + 16].copy_from_slice(&protective_size.to_le_bytes());
     
     // MBR signature
@@ -228,11 +283,11 @@ fn create_gpt_single_partition(device: &Device, filesystem_type: &str) -> Result
     };
     
     // Partition type GUID
-    partition_entries[0..16].copy_from_slice(partition_type_guid.as_bytes());
+    partition_entries[0..16].copy_from_slice(&partition_type_guid.to_bytes_le());
     
     // Unique partition GUID
     let partition_guid = uuid::Uuid::new_v4();
-    partition_entries[16..32].copy_from_slice(partition_guid.as_bytes());
+    partition_entries[16..32].copy_from_slice(&partition_guid.to_bytes_le());
     
     // First LBA (align to 1MB = 2048 sectors)
     partition_entries[32..40].copy_from_slice(&2048u64.to_le_bytes());
@@ -268,6 +323,113 @@ fn create_gpt_single_partition(device: &Device, filesystem_type: &str) -> Result
     Ok(result)
 }
 
+/// Create a partition table holding `partitions` (sizes and starts in 512-byte sectors).
+/// MBR gives one sector; GPT gives the protective MBR, header and 32 sectors of entries,
+/// and the caller writes the backup copy at the end of the disk.
+pub fn create_partition_table(
+    disk_size: u64,
+    table_type: PartitionTableType,
+    partitions: &[PartitionEntry],
+) -> Result<Vec<u8>, MosesError> {
+    let disk_sectors = disk_size / 512;
+    for p in partitions {
+        if p.size_lba == 0 || p.start_lba + p.size_lba > disk_sectors {
+            return Err(MosesError::InvalidInput(format!(
+                "Partition at sector {} with {} sectors does not fit on the disk", p.start_lba, p.size_lba
+            )));
+        }
+    }
+    
+    match table_type {
+        PartitionTableType::MBR => {
+            if partitions.len() > 4 {
+                return Err(MosesError::InvalidInput("MBR holds at most 4 primary partitions".to_string()));
+            }
+            if partitions.iter().any(|p| p.start_lba + p.size_lba > u32::MAX as u64) {
+                return Err(MosesError::InvalidInput("Partitions beyond 2 TiB need GPT".to_string()));
+            }
+            let mut mbr = vec![0u8; 512];
+            for (i, p) in partitions.iter().enumerate() {
+                let entry = &mut mbr[446 + i * 16..446 + (i + 1) * 16];
+                entry[1..4].copy_from_slice(&chs(p.start_lba));
+                entry[4] = p.partition_type;
+                entry[5..8].copy_from_slice(&chs(p.start_lba + p.size_lba - 1));
+                entry[8..12].copy_from_slice(&(p.start_lba as u32).to_le_bytes());
+                entry[12..16].copy_from_slice(&(p.size_lba as u32).to_le_bytes());
+            }
+            let disk_sig = rand::random::<u32>().max(1);
+            mbr[440..444].copy_from_slice(&disk_sig.to_le_bytes());
+            mbr[510] = 0x55;
+            mbr[511] = 0xAA;
+            Ok(mbr)
+        }
+        PartitionTableType::GPT => {
+            if partitions.len() > 128 {
+                return Err(MosesError::InvalidInput("GPT holds at most 128 partitions".to_string()));
+            }
+            let backup_lba = disk_sectors - 1;
+            let last_usable = backup_lba - 33;
+            if partitions.iter().any(|p| p.start_lba < 34 || p.start_lba + p.size_lba - 1 > last_usable) {
+                return Err(MosesError::InvalidInput("Partitions overlap the GPT structures".to_string()));
+            }
+            let mut table = vec![0u8; 34 * 512];
+            
+            let mbr = &mut table[..512];
+            mbr[446 + 1..446 + 4].copy_from_slice(&[0x00, 0x01, 0x00]);
+            mbr[446 + 4] = 0xEE;
+            mbr[446 + 5..446 + 8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+            mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+            mbr[446 + 12..446 + 16].copy_from_slice(&(backup_lba.min(u32::MAX as u64) as u32).to_le_bytes());
+            mbr[510] = 0x55;
+            mbr[511] = 0xAA;
+            
+            let basic_data = uuid::Uuid::parse_str("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7").unwrap();
+            let linux = uuid::Uuid::parse_str("0FC63DAF-8483-4772-8E79-3D69D8477DE4").unwrap();
+            for (i, p) in partitions.iter().enumerate() {
+                let entry = &mut table[1024 + i * 128..1024 + (i + 1) * 128];
+                let type_guid = if p.partition_type == 0x83 { linux } else { basic_data };
+                entry[0..16].copy_from_slice(&type_guid.to_bytes_le());
+                entry[16..32].copy_from_slice(&uuid::Uuid::new_v4().to_bytes_le());
+                entry[32..40].copy_from_slice(&p.start_lba.to_le_bytes());
+                entry[40..48].copy_from_slice(&(p.start_lba + p.size_lba - 1).to_le_bytes());
+                for (j, ch) in p.name.encode_utf16().take(36).enumerate() {
+                    entry[56 + j * 2..58 + j * 2].copy_from_slice(&ch.to_le_bytes());
+                }
+            }
+            let entries_crc = crc32_of(&table[1024..]);
+            
+            let header = &mut table[512..1024];
+            header[0..8].copy_from_slice(b"EFI PART");
+            header[8..12].copy_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+            header[12..16].copy_from_slice(&92u32.to_le_bytes());
+            header[24..32].copy_from_slice(&1u64.to_le_bytes());
+            header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+            header[40..48].copy_from_slice(&34u64.to_le_bytes());
+            header[48..56].copy_from_slice(&last_usable.to_le_bytes());
+            header[56..72].copy_from_slice(&uuid::Uuid::new_v4().to_bytes_le());
+            header[72..80].copy_from_slice(&2u64.to_le_bytes());
+            header[80..84].copy_from_slice(&128u32.to_le_bytes());
+            header[84..88].copy_from_slice(&128u32.to_le_bytes());
+            header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+            let header_crc = crc32_of(&header[..92]);
+            header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+            Ok(table)
+        }
+    }
+}
+
+/// CHS address of `lba` for an MBR entry, with the usual 255 heads and 63 sectors per track
+fn chs(lba: u64) -> [u8; 3] {
+    let cylinder = lba / (255 * 63);
+    if cylinder > 1023 {
+        // Too far for CHS; readers use the LBA fields instead
+        return [0xFE, 0xFF, 0xFF];
+    }
+    let head = (lba / 63) % 255;
+    let sector = lba % 63 + 1;
+    [head as u8, (sector as u8 & 0x3F) | ((cylinder >> 2) as u8 & 0xC0), cylinder as u8]
+}
+
 /// Calculate CRC32 (using the CRC32C algorithm that GPT uses)
 fn crc32_of(data: &[u8]) -> u32 {
     // For now, use a simple CRC32 implementation
@@ -289,6 +451,33 @@ fn crc32_of(data: &[u8]) -> u32 {
     !crc
 }
 
+/// Write the backup copy of a GPT made by [`create_single_partition_table`] or
+/// [`create_partition_table`]: its entries, then its header in the disk's last sector
+pub fn write_gpt_backup<W: Write + Seek>(
+    writer: &mut W,
+    partition_table: &[u8],
+    disk_size: u64,
+) -> Result<(), MosesError> {
+    if partition_table.len() < 34 * 512 || &partition_table[512..520] != b"EFI PART" {
+        return Err(MosesError::InvalidInput("Only a GPT has a backup copy".to_string()));
+    }
+    let backup_lba = disk_size / 512 - 1;
+    let mut header = partition_table[512..1024].to_vec();
+    header[24..32].copy_from_slice(&backup_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&1u64.to_le_bytes());
+    header[72..80].copy_from_slice(&(backup_lba - 32).to_le_bytes());
+    header[16..20].fill(0);
+    let header_crc = crc32_of(&header[..92]);
+    header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+    writer.seek(SeekFrom::Start((backup_lba - 32) * 512))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to the backup GPT: {}", e)))?;
+    writer.write_all(&partition_table[1024..34 * 512])
+        .and_then(|_| writer.write_all(&header))
+        .map_err(|e| MosesError::Other(format!("Failed to write the backup GPT: {}", e)))?;
+    Ok(())
+}
+
 /// Write partition table to device
 pub fn write_partition_table<W: Write + Seek>(
     writer: &mut W,
@@ -0,0 +1,233 @@
+// Format postconditions - reopen a freshly formatted volume with our own readers
+// Debug and test builds wrap every built-in formatter so that a format reporting success is
+// immediately read back: the volume must detect as the requested filesystem, statfs must
+// describe a volume that fits the device, the root directory must list and the label must
+// be the one asked for. A formatter regression then fails at development time instead of
+// leaving an unreadable drive behind on a user's machine. Release builds skip the wrapper.
+
+use std::path::Path;
+use std::sync::Arc;
+use moses_core::{Device, FilesystemFormatter, FormatOptions, MosesError, Platform, SimulationReport};
+use crate::diagnostics::{read_at, read_partition_table, run_detectors};
+use crate::ops::FilesystemOpsRegistry;
+
+/// A formatter whose successful formats are checked with `check_postconditions`
+pub struct PostconditionFormatter {
+    filesystem: &'static str,
+    inner: Arc<dyn FilesystemFormatter>,
+}
+
+impl PostconditionFormatter {
+    pub fn new(filesystem: &'static str, inner: Arc<dyn FilesystemFormatter>) -> Self {
+        Self { filesystem, inner }
+    }
+}
+
+/// `formatter` wrapped in postcondition checks in debug builds, unchanged in release builds
+pub fn with_postconditions(filesystem: &'static str, formatter: Arc<dyn FilesystemFormatter>) -> Arc<dyn FilesystemFormatter> {
+    if cfg!(debug_assertions) {
+        Arc::new(PostconditionFormatter::new(filesystem, formatter))
+    } else {
+        formatter
+    }
+}
+
+#[async_trait::async_trait]
+impl FilesystemFormatter for PostconditionFormatter {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn supported_platforms(&self) -> Vec<Platform> {
+        self.inner.supported_platforms()
+    }
+
+    fn can_format(&self, device: &Device) -> bool {
+        self.inner.can_format(device)
+    }
+
+    fn requires_external_tools(&self) -> bool {
+        self.inner.requires_external_tools()
+    }
+
+    fn bundled_tools(&self) -> Vec<&'static str> {
+        self.inner.bundled_tools()
+    }
+
+    fn tools_available(&self) -> bool {
+        self.inner.tools_available()
+    }
+
+    async fn format(&self, device: &Device, options: &FormatOptions) -> Result<(), MosesError> {
+        self.inner.format(device, options).await?;
+        if options.dry_run {
+            return Ok(());
+        }
+        check_postconditions(device, self.filesystem, options).map_err(|e| {
+            log::error!("{} format of {} broke a postcondition: {}", self.filesystem, device.id, e);
+            MosesError::FormatError(format!("Postcondition failed after formatting as {}: {}", self.filesystem, e))
+        })
+    }
+
+    async fn validate_options(&self, options: &FormatOptions) -> Result<(), MosesError> {
+        self.inner.validate_options(options).await
+    }
+
+    async fn dry_run(&self, device: &Device, options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+        self.inner.dry_run(device, options).await
+    }
+}
+
+/// Read a freshly formatted `device` back and check it is the `filesystem` that was asked for
+pub fn check_postconditions(device: &Device, filesystem: &str, options: &FormatOptions) -> Result<(), MosesError> {
+    moses_core::FilesystemCache::global().invalidate(device);
+    let mut reader = crate::device_reader::AlignedDeviceReader::new(crate::utils::open_device_read(device)?);
+
+    let detected = detect_at(&mut reader, 0);
+    if detected.is_none() {
+        // Formatters that write a partition table put the volume in its first partition,
+        // which the readers cannot open; the boot sector there must still be right
+        let (style, partitions) = read_partition_table(&mut reader);
+        let Some(&(start, _)) = partitions.first() else {
+            return Err(MosesError::Other("no filesystem or partition table found on the device".to_string()));
+        };
+        let found = detect_at(&mut reader, start);
+        if found.as_deref() != Some(filesystem) {
+            return Err(MosesError::Other(format!(
+                "first {} partition holds {}", style.unwrap_or_default(), found.as_deref().unwrap_or("no filesystem")
+            )));
+        }
+        log::debug!("{} was formatted inside a partition at {}; skipping the reader checks", device.id, start);
+        return Ok(());
+    }
+    if detected.as_deref() != Some(filesystem) {
+        return Err(MosesError::Other(format!("volume detects as {}", detected.unwrap_or_default())));
+    }
+
+    let mut registry = FilesystemOpsRegistry::new();
+    crate::ops_registry::register_all_filesystems(&mut registry, false);
+    let mut ops = registry.create_ops(device, Some(filesystem))?;
+
+    let info = ops.statfs()?;
+    if info.total_space == 0 {
+        return Err(MosesError::Other("statfs reports no space".to_string()));
+    }
+    if info.free_space > info.total_space || info.available_space > info.total_space {
+        return Err(MosesError::Other(format!(
+            "statfs reports {} bytes free of {}", info.free_space, info.total_space
+        )));
+    }
+    if device.size > 0 && info.total_space > device.size {
+        return Err(MosesError::Other(format!(
+            "statfs reports {} bytes on a {} byte device", info.total_space, device.size
+        )));
+    }
+
+    ops.readdir(Path::new("/"))
+        .map_err(|e| MosesError::Other(format!("root directory cannot be listed: {}", e)))?;
+
+    if let Some(requested) = options.label.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+        let actual = info.volume_label.as_deref().map(str::trim).unwrap_or("");
+        if !label_matches(requested, actual) {
+            return Err(MosesError::Other(format!("label is {:?}, expected {:?}", actual, requested)));
+        }
+    }
+    Ok(())
+}
+
+fn detect_at<R: std::io::Read + std::io::Seek>(reader: &mut R, offset: u64) -> Option<String> {
+    let boot_sector = read_at(reader, offset, 512)?;
+    let superblock = read_at(reader, offset + 1024, 512);
+    run_detectors(&boot_sector, superblock.as_deref())
+}
+
+/// FAT labels are stored upper-case and every family truncates to its own length limit
+fn label_matches(requested: &str, actual: &str) -> bool {
+    if actual.is_empty() {
+        return false;
+    }
+    let requested = requested.to_uppercase();
+    let actual = actual.to_uppercase();
+    requested == actual || requested.starts_with(&actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moses_core::DeviceType;
+    use tempfile::NamedTempFile;
+
+    fn image(size: u64) -> (NamedTempFile, Device) {
+        let file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(size).unwrap();
+        let device = Device {
+            id: file.path().to_str().unwrap().to_string(),
+            name: "postconditions".to_string(),
+            size,
+            device_type: DeviceType::Unknown,
+            is_removable: true,
+            is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+            mount_points: vec![],
+            filesystem: None,
+        };
+        (file, device)
+    }
+
+    fn options(filesystem: &str, label: &str) -> FormatOptions {
+        FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            label: Some(label.to_string()),
+            quick_format: true,
+            ..Default::default()
+        }
+    }
+
+    /// Reports success without writing anything
+    struct NoopFormatter;
+
+    #[async_trait::async_trait]
+    impl FilesystemFormatter for NoopFormatter {
+        fn name(&self) -> &'static str { "noop" }
+        fn supported_platforms(&self) -> Vec<Platform> { vec![Platform::current()] }
+        fn can_format(&self, _device: &Device) -> bool { true }
+        fn requires_external_tools(&self) -> bool { false }
+        fn bundled_tools(&self) -> Vec<&'static str> { vec![] }
+        async fn format(&self, _device: &Device, _options: &FormatOptions) -> Result<(), MosesError> { Ok(()) }
+        async fn validate_options(&self, _options: &FormatOptions) -> Result<(), MosesError> { Ok(()) }
+        async fn dry_run(&self, _device: &Device, _options: &FormatOptions) -> Result<SimulationReport, MosesError> {
+            Err(MosesError::NotSupported("noop".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builtin_formats_meet_postconditions() {
+        let registry = crate::registration::builtin_registry();
+        for (filesystem, size) in [("fat32", 64 << 20), ("exfat", 64 << 20), ("ext4", 256 << 20), ("ntfs", 64 << 20)] {
+            let (_file, device) = image(size);
+            let formatter = registry.get_formatter(filesystem).unwrap();
+            formatter.format(&device, &options(filesystem, "Moses Test")).await
+                .unwrap_or_else(|e| panic!("{}: {}", filesystem, e));
+            check_postconditions(&device, filesystem, &options(filesystem, "Moses Test")).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broken_format_is_caught() {
+        let (_file, device) = image(8 << 20);
+        let formatter = PostconditionFormatter::new("fat32", Arc::new(NoopFormatter));
+        let result = formatter.format(&device, &options("fat32", "EMPTY")).await;
+        assert!(matches!(result, Err(MosesError::FormatError(msg)) if msg.contains("Postcondition")));
+
+        let mut dry = options("fat32", "EMPTY");
+        dry.dry_run = true;
+        assert!(formatter.format(&device, &dry).await.is_ok());
+
+        assert!(label_matches("Moses Test", "MOSES TEST"));
+        assert!(label_matches("A very long label", "A VERY LONG"));
+        assert!(!label_matches("DATA", "NO NAME"));
+    }
+}
@@ -0,0 +1,312 @@
+// Content previews over FilesystemOps
+// Lets the browser show the start of a text file or a thumbnail of a picture without
+// extracting it. Reads are capped: text previews fetch at most MAX_TEXT_BYTES and images
+// larger than MAX_IMAGE_BYTES are not decoded, so a preview never pulls a whole video
+// off a slow card. File types are sniffed from magic bytes, falling back to the name.
+use crate::ops::FilesystemOps;
+use moses_core::MosesError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Text bytes read when the caller does not say
+pub const DEFAULT_TEXT_BYTES: u32 = 64 * 1024;
+/// Upper bound on a text preview
+pub const MAX_TEXT_BYTES: u32 = 1024 * 1024;
+/// Largest image file that is decoded for a thumbnail
+pub const MAX_IMAGE_BYTES: u64 = 32 * 1024 * 1024;
+/// Longest edge of a thumbnail in pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// Largest file without a known extension that a listing reads to sniff its type
+pub const LISTING_SNIFF_BYTES: u64 = 64 * 1024;
+
+/// Bytes needed to recognise every signature in `sniff_mime`
+const SNIFF_BYTES: u32 = 512;
+
+/// What a preview shows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PreviewContent {
+    Text {
+        text: String,
+        /// The file continues past the previewed part
+        truncated: bool,
+    },
+    Image {
+        /// PNG-encoded thumbnail no larger than THUMBNAIL_SIZE on either edge
+        thumbnail_png: Vec<u8>,
+        /// Dimensions of the original picture
+        width: u32,
+        height: u32,
+    },
+    /// Binary or oversized content; `reason` says why there is no preview
+    Unavailable { reason: String },
+}
+
+/// Preview of one file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilePreview {
+    pub mime: String,
+    pub size: u64,
+    pub content: PreviewContent,
+}
+
+/// Preview the file at `path`, reading at most `max_text_bytes` for text (capped at
+/// MAX_TEXT_BYTES)
+pub fn preview_file(fs: &mut dyn FilesystemOps, path: &Path, max_text_bytes: u32) -> Result<FilePreview, MosesError> {
+    let attributes = fs.stat(path)?;
+    if attributes.is_directory {
+        return Err(MosesError::InvalidInput(format!("{} is a directory", path.display())));
+    }
+    let size = attributes.size;
+    let max_text_bytes = max_text_bytes.clamp(1, MAX_TEXT_BYTES);
+    let head = read_head(fs, path, size, SNIFF_BYTES.max(max_text_bytes))?;
+    let mime = sniff_mime(&head, &path.to_string_lossy()).to_string();
+
+    let content = if is_thumbnailable(&mime) {
+        if size > MAX_IMAGE_BYTES {
+            PreviewContent::Unavailable {
+                reason: format!("Image is larger than {} MB", MAX_IMAGE_BYTES / (1024 * 1024)),
+            }
+        } else {
+            let data = read_head(fs, path, size, size as u32)?;
+            thumbnail(&data).unwrap_or_else(|e| PreviewContent::Unavailable { reason: e })
+        }
+    } else if is_textual(&mime) {
+        let shown = &head[..head.len().min(max_text_bytes as usize)];
+        PreviewContent::Text {
+            text: decode_text(shown),
+            truncated: (shown.len() as u64) < size,
+        }
+    } else {
+        PreviewContent::Unavailable { reason: format!("No preview for {}", mime) }
+    };
+    Ok(FilePreview { mime, size, content })
+}
+
+/// Read up to `limit` bytes from the start of a file
+fn read_head(fs: &mut dyn FilesystemOps, path: &Path, size: u64, limit: u32) -> Result<Vec<u8>, MosesError> {
+    let want = (limit as u64).min(size);
+    let mut data = Vec::with_capacity(want as usize);
+    while (data.len() as u64) < want {
+        let chunk = fs.read(path, data.len() as u64, (want - data.len() as u64) as u32)?;
+        if chunk.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn is_thumbnailable(mime: &str) -> bool {
+    matches!(mime, "image/png" | "image/jpeg" | "image/gif" | "image/bmp" | "image/webp")
+}
+
+fn is_textual(mime: &str) -> bool {
+    mime.starts_with("text/") || matches!(mime, "application/json" | "application/xml" | "application/x-sh" | "image/svg+xml")
+}
+
+/// Decode and downscale an image into a PNG thumbnail
+fn thumbnail(data: &[u8]) -> Result<PreviewContent, String> {
+    let mut reader = image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    // A small file can still claim enormous dimensions; refuse rather than allocate them
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(16384);
+    limits.max_image_height = Some(16384);
+    limits.max_alloc = Some(256 * 1024 * 1024);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| format!("Cannot decode image: {}", e))?;
+
+    let mut png = Vec::new();
+    image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(PreviewContent::Image { thumbnail_png: png, width: image.width(), height: image.height() })
+}
+
+/// UTF-8 (or UTF-16 with a byte order mark) to a string, dropping a character cut off by
+/// the preview limit
+fn decode_text(data: &[u8]) -> String {
+    match data {
+        [0xFF, 0xFE, rest @ ..] => decode_utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => decode_utf16(rest, u16::from_be_bytes),
+        _ => {
+            let data = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+            let end = match std::str::from_utf8(data) {
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                _ => data.len(),
+            };
+            String::from_utf8_lossy(&data[..end]).into_owned()
+        }
+    }
+}
+
+fn decode_utf16(data: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = data.as_chunks::<2>().0.iter().map(|pair| unit(*pair)).collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// MIME type from the first bytes of a file, falling back to its extension
+pub fn sniff_mime(head: &[u8], name: &str) -> &'static str {
+    const SIGNATURES: &[(usize, &[u8], &str)] = &[
+        (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+        (0, b"\xFF\xD8\xFF", "image/jpeg"),
+        (0, b"GIF87a", "image/gif"),
+        (0, b"GIF89a", "image/gif"),
+        (0, b"BM", "image/bmp"),
+        (0, b"II*\0", "image/tiff"),
+        (0, b"MM\0*", "image/tiff"),
+        (0, b"%PDF-", "application/pdf"),
+        (0, b"PK\x03\x04", "application/zip"),
+        (0, b"\x1F\x8B", "application/gzip"),
+        (0, b"\x28\xB5\x2F\xFD", "application/zstd"),
+        (0, b"7z\xBC\xAF\x27\x1C", "application/x-7z-compressed"),
+        (0, b"Rar!\x1A\x07", "application/vnd.rar"),
+        (0, b"\x7FELF", "application/x-executable"),
+        (0, b"MZ", "application/x-msdownload"),
+        (0, b"ID3", "audio/mpeg"),
+        (0, b"OggS", "audio/ogg"),
+        (0, b"fLaC", "audio/flac"),
+        (4, b"ftyp", "video/mp4"),
+        (0, b"\x1A\x45\xDF\xA3", "video/x-matroska"),
+        (0, b"SQLite format 3\0", "application/vnd.sqlite3"),
+        (257, b"ustar", "application/x-tar"),
+    ];
+    for (offset, magic, mime) in SIGNATURES {
+        if head.get(*offset..offset + magic.len()) == Some(*magic) {
+            return mime;
+        }
+    }
+    if head.starts_with(b"RIFF") && head.len() >= 12 {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+
+    let by_extension = mime_from_extension(name);
+    if let Some(mime) = by_extension.filter(|mime| !is_textual(mime)) {
+        return mime;
+    }
+    let looks_textual = !head.contains(&0) || head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF]);
+    if !looks_textual {
+        return "application/octet-stream";
+    }
+    by_extension.unwrap_or("text/plain")
+}
+
+/// MIME type implied by a file name's extension
+pub fn mime_from_extension(name: &str) -> Option<&'static str> {
+    let extension = Path::new(name).extension()?.to_string_lossy().to_lowercase();
+    let mime = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "webp" => "image/webp",
+        "tif" | "tiff" => "image/tiff",
+        "ico" => "image/x-icon",
+        "heic" => "image/heic",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        "tar" => "application/x-tar",
+        "iso" => "application/x-iso9660-image",
+        "exe" | "dll" => "application/x-msdownload",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "sqlite" | "db" => "application/vnd.sqlite3",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "webm" => "video/webm",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "csv" => "text/csv",
+        "md" => "text/markdown",
+        "sh" => "application/x-sh",
+        "txt" | "log" | "ini" | "cfg" | "conf" => "text/plain",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// MIME type for a directory listing: by extension, falling back to the content of files
+/// small enough (LISTING_SNIFF_BYTES) that `read` is cheap
+pub fn listing_mime(name: &str, size: u64, read: impl FnOnce() -> Option<Vec<u8>>) -> &'static str {
+    if let Some(mime) = mime_from_extension(name) {
+        return mime;
+    }
+    match (size <= LISTING_SNIFF_BYTES).then(read).flatten() {
+        Some(data) => sniff_mime(&data[..data.len().min(SNIFF_BYTES as usize)], name),
+        None => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n....", "a.txt"), "image/png");
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 ", "x"), "image/webp");
+        assert_eq!(sniff_mime(b"{\"a\": 1}", "config.JSON"), "application/json");
+        assert_eq!(sniff_mime(b"plain words", "README"), "text/plain");
+        assert_eq!(sniff_mime(b"\0\x01\x02binary", "data.bin"), "application/octet-stream");
+        assert_eq!(sniff_mime(b"\0\x01\x02", "clip.MOV"), "video/quicktime");
+        assert_eq!(sniff_mime(b"\0\x01\x02", "notes.txt"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_listing_mime() {
+        assert_eq!(listing_mime("photo.JPG", 1 << 30, || panic!("read a file with a known extension")), "image/jpeg");
+        assert_eq!(listing_mime("IMG0001", 100, || Some(b"\xFF\xD8\xFF\xE0".to_vec())), "image/jpeg");
+        assert_eq!(listing_mime("huge", LISTING_SNIFF_BYTES + 1, || panic!("read a large file")), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_text_and_image_previews() {
+        let dir = tempfile::tempdir().unwrap();
+        // 'é' straddles the 10 byte limit and must not come out as a replacement character
+        std::fs::write(dir.path().join("notes.txt"), "123456789é and more").unwrap();
+        let picture = image::RgbImage::from_pixel(1024, 512, image::Rgb([200, 30, 30]));
+        picture.save(dir.path().join("photo.png")).unwrap();
+        std::fs::write(dir.path().join("broken.jpg"), b"\xFF\xD8\xFFnot really").unwrap();
+        let mut fs = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+
+        let text = preview_file(&mut fs, Path::new("/notes.txt"), 10).unwrap();
+        assert_eq!(text.content, PreviewContent::Text { text: "123456789".to_string(), truncated: true });
+
+        let photo = preview_file(&mut fs, Path::new("/photo.png"), DEFAULT_TEXT_BYTES).unwrap();
+        assert_eq!(photo.mime, "image/png");
+        let PreviewContent::Image { thumbnail_png, width, height } = photo.content else {
+            panic!("expected a thumbnail, got {:?}", photo.content);
+        };
+        assert_eq!((width, height), (1024, 512));
+        let thumb = image::load_from_memory(&thumbnail_png).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+
+        let broken = preview_file(&mut fs, Path::new("/broken.jpg"), DEFAULT_TEXT_BYTES).unwrap();
+        assert!(matches!(broken.content, PreviewContent::Unavailable { .. }));
+    }
+}
@@ -0,0 +1,324 @@
+// Read-only enforcement for FilesystemOps
+// Provider flags (FUSE `ro`, the WinFsp read-only attribute) only stop well-behaved
+// callers. ReadOnlyOps refuses every mutating call at the ops layer, and while it is
+// alive the device itself is marked read-only in the kernel where the platform allows
+// (BLKROSET on Linux, the disk read-only attribute on Windows), so a writer bug or a
+// journal replay during init cannot reach a drive that was mounted read-only.
+use crate::ops::{AttributeChanges, DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps, TreeStats};
+use moses_core::{Device, MosesError};
+use std::path::{Path, PathBuf};
+
+/// Wraps ops so nothing can be written through them
+pub struct ReadOnlyOps {
+    inner: Box<dyn FilesystemOps>,
+    guard: Option<DeviceReadOnlyGuard>,
+}
+
+impl ReadOnlyOps {
+    pub fn new(inner: Box<dyn FilesystemOps>) -> Self {
+        Self { inner, guard: None }
+    }
+
+    /// Whether the kernel-level read-only flag was set on the device
+    pub fn device_locked(&self) -> bool {
+        self.guard.is_some()
+    }
+
+    fn refuse(&self, operation: &str, path: &Path) -> MosesError {
+        log::warn!("Blocked {} of {} on a read-only {} mount", operation, path.display(), self.inner.filesystem_type());
+        MosesError::WriteProtected(format!("Cannot {} {}: mounted read-only", operation, path.display()))
+    }
+}
+
+impl FilesystemOps for ReadOnlyOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        // Lock the device before the inner ops touch it
+        self.guard = DeviceReadOnlyGuard::engage(device);
+        self.inner.init(device)
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        self.inner.statfs()
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.inner.stat(path)
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.inner.readdir(path)
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.inner.read(path, offset, size)
+    }
+
+    fn stats(&mut self, path: &Path) -> Result<TreeStats, MosesError> {
+        self.inner.stats(path)
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.inner.directory_id(path)
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.inner.readlink(path)
+    }
+
+    fn write(&mut self, path: &Path, _offset: u64, _data: &[u8]) -> Result<u32, MosesError> {
+        Err(self.refuse("write to", path))
+    }
+
+    fn create(&mut self, path: &Path, _mode: u32) -> Result<(), MosesError> {
+        Err(self.refuse("create", path))
+    }
+
+    fn mkdir(&mut self, path: &Path, _mode: u32) -> Result<(), MosesError> {
+        Err(self.refuse("create directory", path))
+    }
+
+    fn unlink(&mut self, path: &Path) -> Result<(), MosesError> {
+        Err(self.refuse("delete", path))
+    }
+
+    fn rmdir(&mut self, path: &Path) -> Result<(), MosesError> {
+        Err(self.refuse("remove directory", path))
+    }
+
+    fn rename(&mut self, from: &Path, _to: &Path) -> Result<(), MosesError> {
+        Err(self.refuse("rename", from))
+    }
+
+    fn truncate(&mut self, path: &Path, _size: u64) -> Result<(), MosesError> {
+        Err(self.refuse("truncate", path))
+    }
+
+    fn set_attributes(&mut self, path: &Path, _changes: &AttributeChanges) -> Result<(), MosesError> {
+        Err(self.refuse("change the attributes of", path))
+    }
+
+    fn sync(&mut self) -> Result<(), MosesError> {
+        // Nothing was written, so there is nothing to flush
+        Ok(())
+    }
+
+    fn is_readonly(&self) -> bool {
+        true
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        self.inner.warnings()
+    }
+
+    fn filesystem_type(&self) -> &str {
+        self.inner.filesystem_type()
+    }
+}
+
+/// Kernel read-only flag on a block device, cleared again on drop. Only a flag this guard
+/// set is cleared, so a device that was already read-only stays that way.
+pub struct DeviceReadOnlyGuard {
+    #[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+    file: std::fs::File,
+    path: String,
+}
+
+impl DeviceReadOnlyGuard {
+    /// Mark `device` read-only. Best effort: returns None (and logs why) for image files,
+    /// host folders, missing privileges or unsupported platforms.
+    pub fn engage(device: &Device) -> Option<Self> {
+        match Self::try_engage(device) {
+            Ok(guard) => guard,
+            Err(e) => {
+                log::warn!("Could not mark {} read-only at the OS level: {}", device.id, e);
+                None
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn try_engage(device: &Device) -> Result<Option<Self>, MosesError> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::io::AsRawFd;
+
+        if !std::fs::metadata(&device.id).is_ok_and(|meta| meta.file_type().is_block_device()) {
+            return Ok(None);
+        }
+        let file = std::fs::File::open(&device.id)?;
+        let mut read_only: nix::libc::c_int = 0;
+        unsafe { linux::blkroget(file.as_raw_fd(), &mut read_only) }.map_err(std::io::Error::from)?;
+        if read_only != 0 {
+            return Ok(None);
+        }
+        unsafe { linux::blkroset(file.as_raw_fd(), &1) }.map_err(std::io::Error::from)?;
+        log::info!("Marked {} read-only (BLKROSET)", device.id);
+        Ok(Some(Self { file, path: device.id.clone() }))
+    }
+
+    #[cfg(windows)]
+    fn try_engage(device: &Device) -> Result<Option<Self>, MosesError> {
+        if !device.id.to_ascii_uppercase().starts_with(r"\\.\PHYSICALDRIVE") {
+            return Ok(None);
+        }
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&device.id)?;
+        let attributes = windows::get_attributes(&file)?;
+        if attributes & windows::DISK_ATTRIBUTE_READ_ONLY != 0 {
+            return Ok(None);
+        }
+        windows::set_read_only(&file, true)?;
+        log::info!("Marked {} read-only (disk attributes)", device.id);
+        Ok(Some(Self { file, path: device.id.clone() }))
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    fn try_engage(_device: &Device) -> Result<Option<Self>, MosesError> {
+        Ok(None)
+    }
+}
+
+impl Drop for DeviceReadOnlyGuard {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        let result = {
+            use std::os::unix::io::AsRawFd;
+            unsafe { linux::blkroset(self.file.as_raw_fd(), &0) }.map(|_| ()).map_err(std::io::Error::from)
+        };
+        #[cfg(windows)]
+        let result = windows::set_read_only(&self.file, false);
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let result: Result<(), std::io::Error> = Ok(());
+
+        match result {
+            Ok(()) => log::info!("Cleared the read-only flag on {}", self.path),
+            Err(e) => log::warn!("Failed to clear the read-only flag on {}: {}", self.path, e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    // BLKROSET/BLKROGET: set and read a block device's read-only flag
+    nix::ioctl_write_ptr_bad!(blkroset, nix::request_code_none!(0x12, 93), nix::libc::c_int);
+    nix::ioctl_read_bad!(blkroget, nix::request_code_none!(0x12, 94), nix::libc::c_int);
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::ioapiset::DeviceIoControl;
+
+    // winioctl.h: CTL_CODE(IOCTL_DISK_BASE, 0x3c/0x3d, METHOD_BUFFERED, ...)
+    const IOCTL_DISK_GET_DISK_ATTRIBUTES: u32 = 0x0007_00F0;
+    const IOCTL_DISK_SET_DISK_ATTRIBUTES: u32 = 0x0007_C0F4;
+    pub const DISK_ATTRIBUTE_READ_ONLY: u64 = 0x2;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct GetDiskAttributes {
+        version: u32,
+        reserved1: u32,
+        attributes: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SetDiskAttributes {
+        version: u32,
+        persist: u8,
+        reserved1: [u8; 3],
+        attributes: u64,
+        attributes_mask: u64,
+        reserved2: [u32; 4],
+    }
+
+    pub fn get_attributes(file: &std::fs::File) -> std::io::Result<u64> {
+        let mut out = GetDiskAttributes::default();
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                IOCTL_DISK_GET_DISK_ATTRIBUTES,
+                std::ptr::null_mut(),
+                0,
+                &mut out as *mut _ as *mut _,
+                std::mem::size_of::<GetDiskAttributes>() as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(out.attributes)
+    }
+
+    /// Set or clear the read-only attribute until the next reboot (not persisted)
+    pub fn set_read_only(file: &std::fs::File, read_only: bool) -> std::io::Result<()> {
+        let mut input = SetDiskAttributes {
+            version: std::mem::size_of::<SetDiskAttributes>() as u32,
+            attributes: if read_only { DISK_ATTRIBUTE_READ_ONLY } else { 0 },
+            attributes_mask: DISK_ATTRIBUTE_READ_ONLY,
+            ..Default::default()
+        };
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                IOCTL_DISK_SET_DISK_ATTRIBUTES,
+                &mut input as *mut _ as *mut _,
+                std::mem::size_of::<SetDiskAttributes>() as u32,
+                std::ptr::null_mut(),
+                0,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+    use moses_core::DeviceType;
+
+    #[test]
+    fn test_mutations_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("keep.txt"), b"original").unwrap();
+        let mut ops = ReadOnlyOps::new(Box::new(HostFolderOps::new(dir.path().to_path_buf()).unwrap()));
+        let device = Device {
+            id: dir.path().to_string_lossy().to_string(),
+            name: "folder".to_string(),
+            size: 0,
+            device_type: DeviceType::Virtual,
+            mount_points: vec![],
+            is_removable: false,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        };
+        ops.init(&device).unwrap();
+        // A folder is not a block device, so only the ops layer guards it
+        assert!(!ops.device_locked());
+        assert!(ops.is_readonly());
+
+        let file = Path::new("/keep.txt");
+        assert_eq!(ops.read(file, 0, 64).unwrap(), b"original");
+        assert!(matches!(ops.write(file, 0, b"changed"), Err(MosesError::WriteProtected(_))));
+        assert!(ops.truncate(file, 0).is_err());
+        assert!(ops.unlink(file).is_err());
+        assert!(ops.rename(file, Path::new("/moved.txt")).is_err());
+        assert!(ops.create(Path::new("/new.txt"), 0o644).is_err());
+        assert!(ops.mkdir(Path::new("/dir"), 0o755).is_err());
+        assert_eq!(std::fs::read(dir.path().join("keep.txt")).unwrap(), b"original");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,26 +1,30 @@
 use moses_core::{
-    FormatterRegistry, FormatterMetadataBuilder, FormatterCategory, Platform,
-    FilesystemFormatter,
+    Device, FormatOptions, FormatStrategy, FormatterRegistry, FormatterMetadataBuilder, FormatterCategory,
+    MosesError, Platform, SelectedFormatter,
 };
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 // Import all our formatters
-// NTFS support is read-only for now (Phase 1)
+use crate::families::ntfs::ntfs::NtfsFormatter;
 use crate::families::fat::fat16::Fat16Formatter;
-use crate::families::fat::fat32::Fat32Formatter;
-use crate::families::fat::exfat::ExFatFormatter;
+use crate::families::fat::fat32::{Fat32Formatter, Fat32SystemFormatter};
+use crate::families::fat::exfat::{ExFatFormatter, ExFatSystemFormatter};
 
 // Use native EXT implementation for all platforms
 use crate::families::ext::ext4_native::Ext4NativeFormatter;
-use crate::families::ext::{Ext2Formatter, Ext3Formatter};
+use crate::families::ext::{Ext2Formatter, Ext3Formatter, Ext4SystemFormatter};
+
+// Debug builds read every successful format back before reporting it
+use crate::postconditions::with_postconditions;
 
 /// Register all built-in formatters with their metadata
+/// In debug builds each one is wrapped in `PostconditionFormatter`
 /// This serves as an example of how to properly register formatters
 pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(), moses_core::MosesError> {
     // EXT4 - Modern Linux filesystem (using native implementation for all platforms)
     registry.register(
         "ext4".to_string(),
-        Arc::new(Ext4NativeFormatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("ext4", Arc::new(Ext4NativeFormatter)),
         FormatterMetadataBuilder::new("ext4")
             .description("Fourth Extended Filesystem - Primary Linux filesystem")
             .aliases(vec!["ext", "linux"])
@@ -45,7 +49,7 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
     // EXT3 - Journaling filesystem
     registry.register(
         "ext3".to_string(),
-        Arc::new(Ext3Formatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("ext3", Arc::new(Ext3Formatter)),
         FormatterMetadataBuilder::new("ext3")
             .description("Third Extended Filesystem - Journaling Linux filesystem")
             .aliases(vec!["ext3fs"])
@@ -70,7 +74,7 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
     // EXT2 - Classic Linux filesystem
     registry.register(
         "ext2".to_string(),
-        Arc::new(Ext2Formatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("ext2", Arc::new(Ext2Formatter)),
         FormatterMetadataBuilder::new("ext2")
             .description("Second Extended Filesystem - Classic Linux filesystem")
             .aliases(vec!["ext2fs"])
@@ -92,13 +96,35 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
             .build()
     )?;
 
-    // NTFS - Read-only support for now (Phase 1)
-    // Formatter will be added in Phase 3-5 when write support is implemented
+    // NTFS - Windows filesystem, formatted natively on every platform
+    registry.register(
+        "ntfs".to_string(),
+        with_postconditions("ntfs", Arc::new(NtfsFormatter)),
+        FormatterMetadataBuilder::new("ntfs")
+            .description("New Technology File System - Primary Windows filesystem")
+            .aliases(vec!["ntfs3"])
+            .category(FormatterCategory::Modern)
+            .size_range(Some(10 * 1024 * 1024), None) // 10MB minimum
+            .version("1.0.0")
+            .author("Moses Team")
+            .capability(|c| {
+                c.supports_labels = true;
+                c.max_label_length = Some(32);
+                c.supports_uuid = true;
+                c.supports_encryption = false;
+                c.supports_compression = false;
+                c.supports_resize = false;
+                c.max_file_size = Some(16 * 1024_u64.pow(4)); // 16TB
+                c.case_sensitive = false;
+                c.preserves_permissions = false;
+            })
+            .build()
+    )?;
 
     // FAT16 - Classic DOS/Windows filesystem
     registry.register(
         "fat16".to_string(),
-        Arc::new(Fat16Formatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("fat16", Arc::new(Fat16Formatter)),
         FormatterMetadataBuilder::new("fat16")
             .description("File Allocation Table 16 - Classic DOS/Windows filesystem")
             .aliases(vec!["fat16fs"])
@@ -123,7 +149,7 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
     // FAT32 - Universal legacy filesystem
     registry.register(
         "fat32".to_string(),
-        Arc::new(Fat32Formatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("fat32", Arc::new(Fat32Formatter)),
         FormatterMetadataBuilder::new("fat32")
             .description("File Allocation Table 32 - Universal compatibility filesystem")
             .aliases(vec!["fat", "msdos", "vfat"])
@@ -148,7 +174,7 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
     // exFAT - Modern universal filesystem
     registry.register(
         "exfat".to_string(),
-        Arc::new(ExFatFormatter) as Arc<dyn FilesystemFormatter>,
+        with_postconditions("exfat", Arc::new(ExFatFormatter)),
         FormatterMetadataBuilder::new("exfat")
             .description("Extended FAT - Modern universal filesystem for large drives")
             .aliases(vec!["exf", "sdxc"])
@@ -170,6 +196,11 @@ pub fn register_builtin_formatters(registry: &mut FormatterRegistry) -> Result<(
             .build()
     )?;
 
+    // System-tool alternatives, chosen through the format strategy
+    registry.register_system_alternative("ext4", with_postconditions("ext4", Arc::new(Ext4SystemFormatter)))?;
+    registry.register_system_alternative("fat32", with_postconditions("fat32", Arc::new(Fat32SystemFormatter)))?;
+    registry.register_system_alternative("exfat", with_postconditions("exfat", Arc::new(ExFatSystemFormatter)))?;
+
     Ok(())
 }
 
@@ -195,6 +226,9 @@ pub fn get_formatter_info(registry: &FormatterRegistry, name: &str) -> Option<St
              Author: {}\n\
              Min Size: {}\n\
              Max Size: {}\n\
+             Platforms: {:?}\n\
+             Required Permission: {:?}\n\
+             Needs Elevated Worker: {}\n\
              Capabilities:\n\
              - Supports Labels: {}\n\
              - Max Label Length: {:?}\n\
@@ -212,6 +246,9 @@ pub fn get_formatter_info(registry: &FormatterRegistry, name: &str) -> Option<St
             meta.author,
             meta.min_size.map_or("None".to_string(), |s| format!("{} bytes", s)),
             meta.max_size.map_or("None".to_string(), |s| format!("{} bytes", s)),
+            meta.platform_support,
+            meta.required_permission,
+            meta.needs_elevated_worker,
             meta.capabilities.supports_labels,
             meta.capabilities.max_label_length,
             meta.capabilities.supports_uuid,
@@ -224,6 +261,46 @@ pub fn get_formatter_info(registry: &FormatterRegistry, name: &str) -> Option<St
     })
 }
 
+/// Registry of the built-in formatters, built once per process
+pub fn builtin_registry() -> &'static FormatterRegistry {
+    static GLOBAL: OnceLock<FormatterRegistry> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        let mut registry = FormatterRegistry::new();
+        if let Err(e) = register_builtin_formatters(&mut registry) {
+            log::error!("Failed to register built-in formatters: {}", e);
+        }
+        registry
+    })
+}
+
+/// Choose the formatter for `options.filesystem_type` (a name or alias), honouring the
+/// `strategy` option, so the CLI, GUI and worker all format the same way
+pub fn resolve_formatter(
+    registry: &FormatterRegistry,
+    device: &Device,
+    options: &FormatOptions,
+) -> Result<SelectedFormatter, MosesError> {
+    let strategy = FormatStrategy::from_options(options)?;
+    let selected = registry.select(&options.filesystem_type, device, strategy).ok_or_else(|| {
+        MosesError::NotSupported(format!("Unsupported filesystem type: {}", options.filesystem_type))
+    })?;
+
+    let platform = Platform::current();
+    if !selected.formatter.supported_platforms().contains(&platform) {
+        return Err(MosesError::PlatformNotSupported(format!(
+            "{} formatting is not supported on {:?}",
+            options.filesystem_type, platform
+        )));
+    }
+    if selected.system && crate::volume_serial::preserve_requested(options)? {
+        return Err(MosesError::NotSupported(format!(
+            "The system {} formatter picks its own volume serial; use the native formatter to keep it",
+            options.filesystem_type
+        )));
+    }
+    Ok(selected)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -248,6 +325,41 @@ mod tests {
         assert!(registry.is_supported("linux"));
     }
     
+    #[test]
+    fn test_resolve_formatter() {
+        let device = Device {
+            id: "mock://disk".to_string(),
+            name: "disk".to_string(),
+            size: 1024 * 1024 * 1024,
+            device_type: moses_core::DeviceType::USB,
+            mount_points: vec![],
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        };
+        let mut options = FormatOptions {
+            filesystem_type: "msdos".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(resolve_formatter(builtin_registry(), &device, &options).unwrap().formatter.name(), "fat32");
+
+        options.filesystem_type = "ntfs".to_string();
+        assert_eq!(resolve_formatter(builtin_registry(), &device, &options).unwrap().formatter.name(), "NTFS");
+        options.filesystem_type = "btrfs".to_string();
+        assert!(resolve_formatter(builtin_registry(), &device, &options).is_err());
+
+        options.filesystem_type = "fat32".to_string();
+        options.additional_options.insert(FormatStrategy::OPTION_KEY.to_string(), "fastest".to_string());
+        assert!(matches!(
+            resolve_formatter(builtin_registry(), &device, &options),
+            Err(MosesError::InvalidInput(_))
+        ));
+    }
+
     #[test]
     fn test_formatter_metadata() {
         let mut registry = FormatterRegistry::new();
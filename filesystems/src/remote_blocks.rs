@@ -0,0 +1,172 @@
+// Remote block reads - a device read through the process that holds it
+// The elevated worker can leave parsing of on-disk structures to an unprivileged child
+// process. The child never opens the device: each read becomes a request for a byte range
+// that the worker answers from its own read-only handle, so a parser bug in the child can
+// reach no more than the bytes it asked for. Parsers make many small reads of neighbouring
+// structures, so ranges are fetched a chunk at a time and the last few chunks are kept.
+use moses_core::MosesError;
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Bytes fetched for a read smaller than this; the rest of the chunk serves later reads
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// Largest range the worker answers in one request
+pub const MAX_REQUEST: usize = 4 * 1024 * 1024;
+/// Chunks kept for reads that come back to them
+const CACHED_CHUNKS: usize = 16;
+
+/// Asks the holding process for `length` bytes at `offset`
+pub type FetchBlocks = Box<dyn FnMut(u64, usize) -> Result<Vec<u8>, MosesError> + Send>;
+
+/// A device of `size` bytes whose reads are fetched from another process
+pub struct RemoteBlockSource {
+    fetch: FetchBlocks,
+    size: u64,
+    position: u64,
+    /// Recently fetched chunks by index, newest last
+    chunks: VecDeque<(u64, Vec<u8>)>,
+    requests: usize,
+}
+
+impl RemoteBlockSource {
+    pub fn new(size: u64, fetch: FetchBlocks) -> Self {
+        Self { fetch, size, position: 0, chunks: VecDeque::new(), requests: 0 }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Ranges requested so far
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    fn fetch(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        self.requests += 1;
+        let data = (self.fetch)(offset, length).map_err(io::Error::other)?;
+        if data.len() != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "asked for {} bytes at {:#x} and got {}", length, offset, data.len(),
+            )));
+        }
+        Ok(data)
+    }
+
+    /// The chunk holding `index * CHUNK_SIZE`, fetched if it is not kept
+    fn chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        let slot = match self.chunks.iter().position(|(kept, _)| *kept == index) {
+            Some(slot) => slot,
+            None => {
+                let offset = index * CHUNK_SIZE as u64;
+                let length = (self.size - offset).min(CHUNK_SIZE as u64) as usize;
+                let data = self.fetch(offset, length)?;
+                if self.chunks.len() == CACHED_CHUNKS {
+                    self.chunks.pop_front();
+                }
+                self.chunks.push_back((index, data));
+                self.chunks.len() - 1
+            }
+        };
+        Ok(&self.chunks[slot].1)
+    }
+}
+
+impl Read for RemoteBlockSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = (self.size - self.position).min(buf.len() as u64) as usize;
+        // Large reads (scans, file contents) go straight through without evicting chunks
+        if wanted >= CHUNK_SIZE {
+            let length = wanted.min(MAX_REQUEST);
+            let data = self.fetch(self.position, length)?;
+            buf[..length].copy_from_slice(&data);
+            self.position += length as u64;
+            return Ok(length);
+        }
+        let index = self.position / CHUNK_SIZE as u64;
+        let start = (self.position % CHUNK_SIZE as u64) as usize;
+        let chunk = self.chunk(index)?;
+        let length = wanted.min(chunk.len() - start);
+        buf[..length].copy_from_slice(&chunk[start..start + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Seek for RemoteBlockSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the device"))?;
+        Ok(self.position)
+    }
+}
+
+/// The holding process's side: read the requested range from `device` (`size` bytes),
+/// refusing a range larger than any read a RemoteBlockSource makes
+pub fn serve_blocks<R: Read + Seek>(device: &mut R, size: u64, offset: u64, length: usize) -> Result<Vec<u8>, MosesError> {
+    if length > MAX_REQUEST {
+        return Err(MosesError::InvalidInput(format!(
+            "Refused a read of {} bytes; at most {} are served at once", length, MAX_REQUEST,
+        )));
+    }
+    if offset >= size {
+        return Ok(Vec::new());
+    }
+    let length = (size - offset).min(length as u64) as usize;
+    device.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0u8; length];
+    device.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_remote_reads_match_the_device() {
+        let disk: Vec<u8> = (0..300 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        let size = disk.len() as u64;
+        let mut device = Cursor::new(disk.clone());
+        let mut remote = RemoteBlockSource::new(size, Box::new(move |offset, length| serve_blocks(&mut device, size, offset, length)));
+
+        // Neighbouring small reads share one request
+        let mut sector = [0u8; 512];
+        remote.read_exact(&mut sector).unwrap();
+        assert_eq!(&sector[..], &disk[..512]);
+        remote.seek(SeekFrom::Start(1024)).unwrap();
+        remote.read_exact(&mut sector).unwrap();
+        assert_eq!(&sector[..], &disk[1024..1536]);
+        assert_eq!(remote.requests(), 1);
+
+        // Reads across a chunk boundary, large reads and reads at the end
+        remote.seek(SeekFrom::Start(CHUNK_SIZE as u64 - 100)).unwrap();
+        remote.read_exact(&mut sector).unwrap();
+        assert_eq!(&sector[..], &disk[CHUNK_SIZE - 100..CHUNK_SIZE + 412]);
+        let mut large = vec![0u8; 200 * 1024];
+        remote.seek(SeekFrom::Start(10)).unwrap();
+        remote.read_exact(&mut large).unwrap();
+        assert_eq!(large, &disk[10..10 + 200 * 1024]);
+        remote.seek(SeekFrom::End(-100)).unwrap();
+        let mut tail = Vec::new();
+        remote.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &disk[disk.len() - 100..]);
+        assert!(remote.seek(SeekFrom::Current(-(size as i64) - 1)).is_err());
+
+        // Parsers run unchanged on top
+        remote.rewind().unwrap();
+        assert_eq!(crate::detection::detect_filesystem(&mut remote).unwrap(), "unknown");
+
+        let mut device = Cursor::new(disk);
+        assert!(serve_blocks(&mut device, size, 0, MAX_REQUEST + 1).is_err());
+        assert_eq!(serve_blocks(&mut device, size, size - 4, 512).unwrap().len(), 4);
+    }
+}
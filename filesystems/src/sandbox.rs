@@ -0,0 +1,133 @@
+// Sandbox formats - the user's options tried on a throwaway image first
+// Before pointing Moses at a real drive, the GUI can run the same formatter with the same
+// options against a small sparse image in a temporary directory, open the result with
+// the reader the way a later mount would, and show what came out: the formatter picked,
+// the label, the space, the root directory. Nothing outside the temporary directory is
+// touched, and the image is deleted when the test ends whatever happened.
+
+use moses_core::{FormatOptions, MosesError};
+use serde::Serialize;
+use std::time::Instant;
+use crate::ops::FilesystemOpsRegistry;
+
+/// What a sandbox format produced
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SandboxReport {
+    pub filesystem: String,
+    /// The formatter implementation used, as resolve_formatter describes it
+    pub formatter: Option<String>,
+    pub image_size: u64,
+    pub format_ms: u64,
+    /// What detection sees on the finished image
+    pub detected: Option<String>,
+    pub label: Option<String>,
+    pub total_space: u64,
+    pub free_space: u64,
+    pub block_size: u32,
+    /// Names in the root directory, such as a README the formatter writes
+    pub root_entries: Vec<String>,
+    /// Problems the reader noticed on opening the volume
+    pub warnings: Vec<String>,
+    /// The stage that failed and why; None when the image formatted and read back
+    pub error: Option<String>,
+}
+
+impl SandboxReport {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// An image size the formatter accepts for `filesystem`: FAT32 and ext need more
+/// clusters and groups than the others. The file is sparse, so this costs little disk.
+pub fn sandbox_size(filesystem: &str) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    match filesystem.to_lowercase().as_str() {
+        "fat32" | "ext2" | "ext3" | "ext4" => 300 * MIB,
+        _ => 64 * MIB,
+    }
+}
+
+/// Format a temporary image with `options` and read it back
+pub async fn sandbox_format(options: &FormatOptions) -> Result<SandboxReport, MosesError> {
+    let dir = tempfile::Builder::new().prefix("moses-sandbox").tempdir()
+        .map_err(|e| MosesError::Other(format!("Failed to create a sandbox directory: {}", e)))?;
+    let path = dir.path().join(format!("sandbox-{}.img", options.filesystem_type.to_lowercase()));
+    let mut report = SandboxReport {
+        filesystem: options.filesystem_type.clone(),
+        image_size: sandbox_size(&options.filesystem_type),
+        ..Default::default()
+    };
+
+    let device = crate::image_target::create_image(&path, report.image_size)?;
+    let selected = match crate::resolve_formatter(crate::builtin_registry(), &device, options) {
+        Ok(selected) => selected,
+        Err(e) => {
+            report.error = Some(format!("No formatter: {}", e));
+            return Ok(report);
+        }
+    };
+    report.formatter = Some(selected.describe());
+    let started = Instant::now();
+    let formatted = match selected.formatter.validate_options(options).await {
+        Ok(()) => selected.formatter.format(&device, options).await,
+        Err(e) => Err(e),
+    };
+    report.format_ms = started.elapsed().as_millis() as u64;
+    if let Err(e) = formatted {
+        report.error = Some(format!("Format failed: {}", e));
+        return Ok(report);
+    }
+
+    let mut registry = FilesystemOpsRegistry::new();
+    crate::ops_registry::register_all_filesystems(&mut registry, false);
+    let mut ops = match registry.create_ops(&device, None) {
+        Ok(ops) => ops,
+        Err(e) => {
+            report.error = Some(format!("The formatted image could not be read: {}", e));
+            return Ok(report);
+        }
+    };
+    report.detected = Some(ops.filesystem_type().to_string());
+    report.warnings = ops.warnings();
+    match ops.statfs() {
+        Ok(info) => {
+            report.label = info.volume_label;
+            report.total_space = info.total_space;
+            report.free_space = info.free_space;
+            report.block_size = info.block_size;
+        }
+        Err(e) => report.error = Some(format!("Reading the volume information failed: {}", e)),
+    }
+    match ops.readdir(std::path::Path::new("/")) {
+        Ok(entries) => report.root_entries = entries.into_iter().map(|entry| entry.name).collect(),
+        Err(e) => report.error = Some(format!("Reading the root directory failed: {}", e)),
+    }
+    let expected = options.filesystem_type.to_lowercase();
+    if report.error.is_none() && report.detected.as_deref() != Some(expected.as_str()) {
+        report.error = Some(format!(
+            "Formatted as {} but detected as {}", options.filesystem_type, report.detected.as_deref().unwrap_or("nothing"),
+        ));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sandbox_format() {
+        let options = FormatOptions { filesystem_type: "exfat".to_string(), label: Some("TRIAL".to_string()), ..Default::default() };
+        let report = sandbox_format(&options).await.unwrap();
+        assert!(report.succeeded(), "{:?}", report.error);
+        assert_eq!(report.detected.as_deref(), Some("exfat"));
+        assert_eq!(report.label.as_deref(), Some("TRIAL"));
+        assert!(report.total_space > 0 && report.total_space <= report.image_size);
+        assert!(!report.root_entries.is_empty(), "the exFAT formatter writes a README");
+
+        let unknown = FormatOptions { filesystem_type: "zfs".to_string(), ..Default::default() };
+        let report = sandbox_format(&unknown).await.unwrap();
+        assert!(report.error.unwrap().starts_with("No formatter"));
+    }
+}
@@ -0,0 +1,206 @@
+// Conformance sweep - format every cluster and block size and hold the result up to the
+// reference tools
+// FAT16 takes the cluster size as an option; FAT32 and exFAT pick it from the volume size,
+// so their sweep uses one image size per size tier. ext4 supports 4KiB blocks only and
+// must refuse the others. Where mkfs.fat, mkfs.exfat or mke2fs are installed the same
+// volume is made with them and the fields every implementation must agree on are compared,
+// and the matching fsck must pass our volume unchanged. Images are sparse, so the multi-gigabyte tiers cost only metadata.
+// Set MOSES_TEST_LOOP_MOUNT=1 on a runner that may mount loop devices to also mount each
+// volume read-only and list it with the kernel driver.
+
+use crate::families::ext::ext4_native::core::structures::Ext4Superblock;
+use crate::families::ext::ext4_native::reader::load_superblock;
+use crate::test_helpers::create_test_device;
+use crate::tools::ToolManager;
+use moses_core::{Device, FormatOptions};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const MB: u64 = 1024 * 1024;
+
+fn sparse_image(size: u64) -> (NamedTempFile, Device) {
+    let file = NamedTempFile::new().unwrap();
+    file.as_file().set_len(size).unwrap();
+    let device = create_test_device(file.path().to_str().unwrap(), size);
+    (file, device)
+}
+
+async fn format(device: &Device, filesystem: &str, cluster_size: Option<u32>) {
+    let options = FormatOptions {
+        filesystem_type: filesystem.to_string(),
+        label: Some("SWEEP".to_string()),
+        cluster_size,
+        quick_format: true,
+        ..Default::default()
+    };
+    let formatter = crate::registration::builtin_registry().get_formatter(filesystem).unwrap();
+    formatter.format(device, &options).await
+        .unwrap_or_else(|e| panic!("{} on {} bytes with {:?}: {}", filesystem, device.size, cluster_size, e));
+}
+
+fn boot_sector(path: &Path) -> Vec<u8> {
+    let mut boot = vec![0u8; 512];
+    File::open(path).unwrap().read_exact(&mut boot).unwrap();
+    boot
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u64 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u64
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+/// Run a reference tool if it is installed; None when it is not
+fn run_tool(binary: &str, args: &[&str], image: &Path) -> Option<std::process::Output> {
+    let tool = ToolManager::global().locate(binary)?;
+    Some(Command::new(tool.path).args(args).arg(image).output().unwrap())
+}
+
+/// The reference fsck must find nothing to fix
+fn assert_fsck_clean(binary: &str, args: &[&str], image: &Path, case: &str) {
+    if let Some(output) = run_tool(binary, args, image) {
+        assert!(output.status.success(), "{} rejected {}:\n{}{}", binary, case,
+                String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+/// Mount the image read-only through the kernel driver and list its root
+fn assert_mounts(image: &Path, fs_type: &str, case: &str) {
+    if std::env::var("MOSES_TEST_LOOP_MOUNT").as_deref() != Ok("1") {
+        return;
+    }
+    let drivers = std::fs::read_to_string("/proc/filesystems").unwrap_or_default();
+    if !drivers.split_whitespace().any(|name| name == fs_type) {
+        eprintln!("Skipping the mount of {}: this kernel has no {} driver", case, fs_type);
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let mounted = Command::new("mount")
+        .args(["-o", "loop,ro", "-t", fs_type])
+        .arg(image)
+        .arg(dir.path())
+        .status()
+        .unwrap();
+    assert!(mounted.success(), "the {} driver would not mount {}", fs_type, case);
+    let listed = std::fs::read_dir(dir.path()).map(|entries| entries.count());
+    let _ = Command::new("umount").arg(dir.path()).status();
+    assert!(listed.is_ok(), "the root of {} could not be listed once mounted", case);
+}
+
+/// BPB fields of a FAT volume that every formatter must agree on
+fn fat_geometry(boot: &[u8]) -> (u64, u64, u64, u64) {
+    let total_sectors = match u16_at(boot, 19) {
+        0 => u32_at(boot, 32),
+        sectors => sectors,
+    };
+    (u16_at(boot, 11), boot[13] as u64, boot[16] as u64, total_sectors)
+}
+
+/// Format each (size, cluster size) case and return the cluster size each one got
+async fn sweep_fat(filesystem: &str, fat_bits: &str, cases: &[(u64, Option<u32>)]) -> Vec<u64> {
+    let mut cluster_sizes = Vec::new();
+    for &(size, cluster_size) in cases {
+        let (image, device) = sparse_image(size);
+        format(&device, filesystem, cluster_size).await;
+        let case = format!("{} {} MiB", filesystem, size / MB);
+
+        let ours = boot_sector(image.path());
+        let (bytes_per_sector, sectors_per_cluster, _, _) = fat_geometry(&ours);
+        cluster_sizes.push(bytes_per_sector * sectors_per_cluster);
+        assert_fsck_clean("fsck.fat", &["-n", "-V"], image.path(), &case);
+        assert_mounts(image.path(), "vfat", &case);
+
+        let (reference, _) = sparse_image(size);
+        let spc = sectors_per_cluster.to_string();
+        if let Some(output) = run_tool("mkfs.fat", &["-F", fat_bits, "-S", "512", "-s", &spc, "-n", "SWEEP"], reference.path()) {
+            assert!(output.status.success(), "mkfs.fat failed for {}", case);
+            assert_eq!(fat_geometry(&ours), fat_geometry(&boot_sector(reference.path())), "{} geometry differs from mkfs.fat", case);
+        }
+    }
+    cluster_sizes
+}
+
+#[tokio::test]
+async fn test_fat16_cluster_size_sweep() {
+    // Every cluster size from 512B to 64KB, on a volume of 32768 clusters
+    let requested: Vec<u32> = (9..=16).map(|shift| 1 << shift).collect();
+    let cases: Vec<(u64, Option<u32>)> = requested.iter().map(|&size| (size as u64 * 32768, Some(size))).collect();
+    let chosen = sweep_fat("fat16", "16", &cases).await;
+    assert_eq!(chosen, requested.iter().map(|&size| size as u64).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_fat32_cluster_size_sweep() {
+    // The cluster size follows the volume size: 512B, 4KB, 8KB, 16KB and 32KB
+    let sizes = [64 * MB, 1024 * MB, 12 * 1024 * MB, 24 * 1024 * MB, 40 * 1024 * MB];
+    let cases: Vec<(u64, Option<u32>)> = sizes.iter().map(|&size| (size, None)).collect();
+    let chosen = sweep_fat("fat32", "32", &cases).await;
+    assert_eq!(chosen, vec![512, 4096, 8192, 16384, 32768]);
+}
+
+#[tokio::test]
+async fn test_exfat_cluster_size_sweep() {
+    let mut cluster_sizes = Vec::new();
+    // 4KB, 32KB, 128KB and 256KB clusters
+    for size in [128 * MB, 1024 * MB, 64 * 1024 * MB, 300 * 1024 * MB] {
+        let (image, device) = sparse_image(size);
+        format(&device, "exfat", None).await;
+        let case = format!("exfat {} MiB", size / MB);
+
+        let ours = boot_sector(image.path());
+        assert_eq!(u32_at(&ours, 72) << ours[108], size, "{} volume length", case);
+        cluster_sizes.push(1u64 << (ours[108] + ours[109]));
+        assert_fsck_clean("fsck.exfat", &["-n"], image.path(), &case);
+        assert_mounts(image.path(), "exfat", &case);
+
+        let (reference, _) = sparse_image(size);
+        let cluster = cluster_sizes.last().unwrap().to_string();
+        if let Some(output) = run_tool("mkfs.exfat", &["-c", &cluster, "-L", "SWEEP"], reference.path()) {
+            assert!(output.status.success(), "mkfs.exfat failed for {}", case);
+            let theirs = boot_sector(reference.path());
+            assert_eq!((ours[108], ours[109], ours[110]), (theirs[108], theirs[109], theirs[110]), "{} geometry", case);
+            assert_eq!(u32_at(&ours, 72), u32_at(&theirs, 72), "{} volume length", case);
+        }
+    }
+    cluster_sizes.dedup();
+    assert_eq!(cluster_sizes.len(), 4, "exfat cluster sizes {:?}", cluster_sizes);
+}
+
+#[tokio::test]
+async fn test_ext4_block_size_sweep() {
+    // Smaller blocks would need smaller groups starting at block 1; refuse rather than corrupt
+    for block_size in [1024u32, 2048] {
+        let (_image, device) = sparse_image(256 * MB);
+        let options = FormatOptions { cluster_size: Some(block_size), ..FormatOptions::default() };
+        let formatter = crate::registration::builtin_registry().get_formatter("ext4").unwrap();
+        assert!(formatter.validate_options(&options).await.is_err());
+        assert!(crate::families::ext::ext4_native::core::formatter_impl::format_device(&device, &options).await.is_err());
+    }
+
+    // 4KiB blocks across one, eight and sixty-four groups
+    for size in [256 * MB, 1024 * MB, 8 * 1024 * MB] {
+        let (image, device) = sparse_image(size);
+        format(&device, "ext4", Some(4096)).await;
+        let case = format!("ext4 {} MiB", size / MB);
+
+        let ours = load_superblock(&mut File::open(image.path()).unwrap()).unwrap();
+        assert_eq!(1024u32 << ours.s_log_block_size, 4096, "{}", case);
+        assert_fsck_clean("e2fsck", &["-fn"], image.path(), &case);
+        assert_mounts(image.path(), "ext4", &case);
+
+        let (reference, _) = sparse_image(size);
+        if let Some(output) = run_tool("mke2fs", &["-q", "-F", "-t", "ext4", "-b", "4096", "-L", "SWEEP"], reference.path()) {
+            assert!(output.status.success(), "mke2fs failed for {}", case);
+            let theirs = load_superblock(&mut File::open(reference.path()).unwrap()).unwrap();
+            let fields = |sb: &Ext4Superblock| (
+                sb.s_blocks_count_lo, sb.s_first_data_block, sb.s_log_block_size, sb.s_blocks_per_group,
+            );
+            assert_eq!(fields(&ours), fields(&theirs), "{} differs from mke2fs", case);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Large-device tests - format sparse images of real drive sizes
+// A sparse file only costs the blocks a formatter writes, so 2TB and 16TB volumes can be
+// formatted in CI without the hardware. These are the sizes where 32-bit sector counts,
+// FAT and bitmap lengths and ext4 group counts run out. Formats go through the registry,
+// so debug builds also get the postcondition checks. Hosts whose filesystem cannot hold
+// a file that large skip the test.
+
+use crate::families::ext::ext4_native::reader::{load_group_descriptors, load_superblock};
+use crate::test_helpers::create_test_device;
+use moses_core::{Device, FormatOptions, MosesError};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use tempfile::NamedTempFile;
+
+/// Drives are sold in decimal terabytes
+const TB: u64 = 1_000_000_000_000;
+
+fn sparse_image(size: u64) -> Option<(NamedTempFile, Device)> {
+    let file = NamedTempFile::new().unwrap();
+    if let Err(e) = file.as_file().set_len(size) {
+        eprintln!("Skipping: the host cannot hold a {} byte sparse file ({})", size, e);
+        return None;
+    }
+    let device = create_test_device(file.path().to_str().unwrap(), size);
+    Some((file, device))
+}
+
+async fn format(device: &Device, filesystem: &str) -> Result<(), MosesError> {
+    let options = FormatOptions {
+        filesystem_type: filesystem.to_string(),
+        label: Some("LARGE".to_string()),
+        quick_format: true,
+        ..Default::default()
+    };
+    let formatter = crate::registration::builtin_registry().get_formatter(filesystem).unwrap();
+    formatter.format(device, &options).await
+}
+
+fn read_bytes(device: &Device, offset: u64, len: usize) -> Vec<u8> {
+    let mut file = File::open(&device.id).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf).unwrap();
+    buf
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u64 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u64
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[tokio::test]
+async fn test_fat32_at_the_2tb_limit() {
+    // 2^32 sectors is one more than BPB_TotSec32 can count
+    for size in [2 * TB, 1u64 << 41] {
+        let Some((_image, device)) = sparse_image(size) else { return };
+        format(&device, "fat32").await.unwrap_or_else(|e| panic!("{} bytes: {}", size, e));
+
+        let boot = read_bytes(&device, 0, 512);
+        let total_sectors = u32_at(&boot, 32);
+        assert_eq!(total_sectors, (size / 512).min(u32::MAX as u64));
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(&boot, 14);
+        let sectors_per_fat = u32_at(&boot, 36);
+        let clusters = (total_sectors - reserved - boot[16] as u64 * sectors_per_fat) / sectors_per_cluster;
+        assert!((65525..=0x0FFF_FFF5).contains(&clusters), "{} clusters", clusters);
+        assert!(sectors_per_fat * 512 / 4 >= clusters + 2, "FAT of {} sectors for {} clusters", sectors_per_fat, clusters);
+    }
+
+    let Some((_image, device)) = sparse_image((1u64 << 41) + (1 << 20)) else { return };
+    assert!(format(&device, "fat32").await.is_err());
+}
+
+#[tokio::test]
+async fn test_exfat_16tb_cluster_count() {
+    let size = 16 * TB;
+    let Some((_image, device)) = sparse_image(size) else { return };
+    format(&device, "exfat").await.unwrap();
+
+    let boot = read_bytes(&device, 0, 512);
+    let sector_size = 1u64 << boot[108];
+    let cluster_size = sector_size << boot[109];
+    assert_eq!(u64_at(&boot, 72) * sector_size, size);
+    let (fat_offset, fat_length) = (u32_at(&boot, 80), u32_at(&boot, 84));
+    let heap_offset = u32_at(&boot, 88);
+    let cluster_count = u32_at(&boot, 92);
+    let root_cluster = u32_at(&boot, 96);
+
+    assert!(cluster_count <= 0xFFFF_FFF5, "{} clusters", cluster_count);
+    assert!(fat_offset + fat_length <= heap_offset);
+    assert!(fat_length * sector_size >= (cluster_count + 2) * 4);
+    assert!(heap_offset * sector_size + cluster_count * cluster_size <= size);
+    assert!((2..cluster_count + 2).contains(&root_cluster));
+
+    // The bitmap's directory entry must cover every cluster
+    let root = read_bytes(&device, heap_offset * sector_size + (root_cluster - 2) * cluster_size, cluster_size as usize);
+    let bitmap = root.as_chunks::<32>().0.iter().find(|entry| entry[0] == 0x81).expect("bitmap entry");
+    assert_eq!(u64_at(bitmap, 24), cluster_count.div_ceil(8));
+}
+
+#[tokio::test]
+async fn test_ext4_16tb_group_count() {
+    let size = 16 * TB;
+    let Some((_image, device)) = sparse_image(size) else { return };
+    format(&device, "ext4").await.unwrap();
+
+    let mut file = File::open(&device.id).unwrap();
+    let sb = load_superblock(&mut file).unwrap();
+    let block_size = 1024u64 << sb.s_log_block_size;
+    let blocks = sb.s_blocks_count_lo as u64 | ((sb.s_blocks_count_hi as u64) << 32);
+    assert_eq!(blocks, size / block_size);
+
+    // More groups than a u16 can count
+    let groups = (blocks - sb.s_first_data_block as u64).div_ceil(sb.s_blocks_per_group as u64);
+    assert!(groups > u16::MAX as u64, "{} groups", groups);
+    assert_eq!(sb.s_inodes_count as u64, groups * sb.s_inodes_per_group as u64);
+
+    let descriptors = load_group_descriptors(&mut file, &sb).unwrap();
+    assert_eq!(descriptors.len() as u64, groups);
+    let mut free_blocks = 0u64;
+    for (group, gd) in descriptors.iter().enumerate() {
+        let inode_table = gd.bg_inode_table_lo as u64 | ((gd.bg_inode_table_hi as u64) << 32);
+        assert!(inode_table > 0 && inode_table < blocks, "group {} inode table at {}", group, inode_table);
+        free_blocks += gd.bg_free_blocks_count_lo as u64 | ((gd.bg_free_blocks_count_hi as u64) << 16);
+    }
+    let sb_free = sb.s_free_blocks_count_lo as u64 | ((sb.s_free_blocks_count_hi as u64) << 32);
+    assert_eq!(free_blocks, sb_free);
+    assert!(sb_free < blocks);
+}
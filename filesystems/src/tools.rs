@@ -0,0 +1,364 @@
+// Management of the external tools some formatters shell out to
+// Tools are looked up in a managed directory first, then in a `tools` folder shipped next
+// to the executable, then on PATH. The managed directory can be filled from a manifest
+// listing download URLs and SHA-256 hashes per platform; every downloaded or managed
+// binary is verified against its hash before use, so a tampered file is never run.
+//
+// Manifest format (`<data dir>/moses/tools/manifest.json`):
+// { "tools": [ { "package": "exfatprogs", "binary": "mkfs.exfat",
+//                "platform": "linux-x86_64", "url": "https://...", "sha256": "..." } ] }
+use moses_core::{MosesConfig, MosesError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Binaries provided by the packages formatters list in `bundled_tools()`
+const PACKAGE_BINARIES: &[(&str, &[&str])] = &[
+    ("dosfstools", &["mkfs.fat"]),
+    ("exfatprogs", &["mkfs.exfat"]),
+    ("exfat-utils", &["mkexfatfs"]),
+    ("e2fsprogs", &["mkfs.ext4", "tune2fs"]),
+    ("ntfs-3g", &["mkfs.ntfs"]),
+];
+
+/// Binaries a package provides
+pub fn package_binaries(package: &str) -> &'static [&'static str] {
+    PACKAGE_BINARIES
+        .iter()
+        .find(|(name, _)| *name == package)
+        .map(|(_, binaries)| *binaries)
+        .unwrap_or(&[])
+}
+
+/// `<os>-<arch>` as used in manifests, e.g. `linux-x86_64`
+pub fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolEntry {
+    pub package: String,
+    pub binary: String,
+    pub platform: String,
+    pub url: String,
+    /// Lowercase hex SHA-256 of the binary
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolManifest {
+    #[serde(default)]
+    pub tools: Vec<ToolEntry>,
+}
+
+impl ToolManifest {
+    /// Entry for a binary on the current platform
+    pub fn entry(&self, binary: &str) -> Option<&ToolEntry> {
+        let platform = current_platform();
+        self.tools.iter().find(|entry| entry.binary == binary && entry.platform == platform)
+    }
+}
+
+/// Where a located tool came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ToolSource {
+    Managed,
+    Bundled,
+    System,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocatedTool {
+    pub path: PathBuf,
+    pub source: ToolSource,
+}
+
+pub struct ToolManager {
+    dir: PathBuf,
+    manifest: ToolManifest,
+    auto_download: bool,
+}
+
+impl ToolManager {
+    pub fn new(dir: PathBuf, manifest: ToolManifest, auto_download: bool) -> Self {
+        Self { dir, manifest, auto_download }
+    }
+
+    /// `<local data dir>/moses/tools`
+    pub fn default_dir() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("moses")
+            .join("tools")
+    }
+
+    /// Manager set up from the user's configuration, created once per process
+    pub fn global() -> &'static ToolManager {
+        static GLOBAL: OnceLock<ToolManager> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            let config = &MosesConfig::global().tools;
+            let dir = Self::default_dir();
+            let manifest = load_manifest(config.manifest.as_deref(), &dir).unwrap_or_else(|e| {
+                log::warn!("Ignoring tool manifest: {}", e);
+                ToolManifest::default()
+            });
+            Self::new(dir, manifest, config.auto_download)
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn manifest(&self) -> &ToolManifest {
+        &self.manifest
+    }
+
+    /// Find a binary without downloading anything
+    pub fn locate(&self, binary: &str) -> Option<LocatedTool> {
+        let file_name = executable_name(binary);
+
+        // A managed binary is only trusted with a manifest entry whose hash it matches
+        let managed = self.dir.join(&file_name);
+        if managed.is_file() {
+            match self.manifest.entry(binary) {
+                Some(entry) if verify_sha256(&managed, &entry.sha256).unwrap_or(false) => {
+                    return Some(LocatedTool { path: managed, source: ToolSource::Managed });
+                }
+                Some(_) => log::warn!("{} does not match its manifest hash; ignoring it", managed.display()),
+                None => log::warn!("{} has no manifest entry to verify it against; ignoring it", managed.display()),
+            }
+        }
+
+        let bundled = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join("tools").join(&file_name)))
+            .filter(|path| path.is_file());
+        if let Some(path) = bundled {
+            return Some(LocatedTool { path, source: ToolSource::Bundled });
+        }
+
+        which::which(binary)
+            .ok()
+            .map(|path| LocatedTool { path, source: ToolSource::System })
+    }
+
+    /// Find the first available binary of a package, downloading it if allowed
+    pub fn ensure(&self, package: &str, binaries: &[&str]) -> Result<PathBuf, MosesError> {
+        if let Some(tool) = binaries.iter().find_map(|binary| self.locate(binary)) {
+            return Ok(tool.path);
+        }
+
+        let downloadable = binaries.iter().find_map(|binary| self.manifest.entry(binary));
+        match downloadable {
+            Some(entry) if self.auto_download => self.install(entry),
+            Some(_) => Err(MosesError::ExternalToolMissing(format!(
+                "{} not found. Install the {} package, or enable automatic tool downloads in the settings",
+                binaries.join(" or "),
+                package
+            ))),
+            None => Err(MosesError::ExternalToolMissing(format!(
+                "{} not found. Install the {} package",
+                binaries.join(" or "),
+                package
+            ))),
+        }
+    }
+
+    /// Download every manifest binary of a package for this platform
+    pub fn install_package(&self, package: &str) -> Result<Vec<PathBuf>, MosesError> {
+        let platform = current_platform();
+        let entries: Vec<&ToolEntry> = self
+            .manifest
+            .tools
+            .iter()
+            .filter(|entry| entry.package == package && entry.platform == platform)
+            .collect();
+        if entries.is_empty() {
+            return Err(MosesError::NotSupported(format!(
+                "The tool manifest has no {} build for {}",
+                package, platform
+            )));
+        }
+        entries.into_iter().map(|entry| self.install(entry)).collect()
+    }
+
+    /// Download a binary, check its hash and move it into the managed directory
+    pub fn install(&self, entry: &ToolEntry) -> Result<PathBuf, MosesError> {
+        // The name comes from the manifest and must stay inside the managed directory
+        if entry.binary.is_empty() || entry.binary.contains(['/', '\\']) || entry.binary.contains("..") {
+            return Err(MosesError::InvalidInput(format!(
+                "Tool manifest names an invalid binary: {:?}",
+                entry.binary
+            )));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let target = self.dir.join(executable_name(&entry.binary));
+        let partial = target.with_extension("download");
+
+        log::info!("Downloading {} from {}", entry.binary, entry.url);
+        download(&entry.url, &partial)?;
+
+        if !verify_sha256(&partial, &entry.sha256)? {
+            let _ = std::fs::remove_file(&partial);
+            return Err(MosesError::SafetyViolation(format!(
+                "Downloaded {} does not match the expected SHA-256; it was discarded",
+                entry.binary
+            )));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+        }
+        std::fs::rename(&partial, &target)?;
+        log::info!("Installed {} to {}", entry.binary, target.display());
+        Ok(target)
+    }
+}
+
+fn executable_name(binary: &str) -> String {
+    if cfg!(windows) && !binary.ends_with(".exe") {
+        format!("{}.exe", binary)
+    } else {
+        binary.to_string()
+    }
+}
+
+fn load_manifest(location: Option<&str>, dir: &Path) -> Result<ToolManifest, MosesError> {
+    let text = match location {
+        Some(url) if url.starts_with("https://") => {
+            let cached = dir.join("manifest.json");
+            std::fs::create_dir_all(dir)?;
+            download(url, &cached)?;
+            std::fs::read_to_string(cached)?
+        }
+        // The manifest supplies the hashes every tool is checked against
+        Some(url) if url.starts_with("http://") => {
+            return Err(MosesError::InvalidInput(format!(
+                "Tool manifest {} must be fetched over https",
+                url
+            )))
+        }
+        Some(path) => std::fs::read_to_string(path)?,
+        None => match std::fs::read_to_string(dir.join("manifest.json")) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ToolManifest::default()),
+            Err(e) => return Err(e.into()),
+        },
+    };
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Fetch a URL with the platform's own downloader, so no HTTP stack is linked in
+pub(crate) fn download(url: &str, destination: &Path) -> Result<(), MosesError> {
+    #[cfg(target_os = "windows")]
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command"])
+        .arg(format!(
+            "Invoke-WebRequest -UseBasicParsing -Uri '{}' -OutFile '{}'",
+            url.replace('\'', "''"),
+            destination.display().to_string().replace('\'', "''")
+        ))
+        .output();
+    #[cfg(not(target_os = "windows"))]
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--output"])
+        .arg(destination)
+        .arg(url)
+        .output();
+
+    let output = output.map_err(|e| MosesError::External(format!("Could not start the downloader: {}", e)))?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(destination);
+        return Err(MosesError::External(format!(
+            "Download of {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Lowercase hex SHA-256 of a file
+pub fn sha256_file(path: &Path) -> Result<String, MosesError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<bool, MosesError> {
+    Ok(sha256_file(path)?.eq_ignore_ascii_case(expected.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRONG_SHA256: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    fn manifest_for(binary: &str, sha256: &str) -> ToolManifest {
+        ToolManifest {
+            tools: vec![ToolEntry {
+                package: "testpkg".to_string(),
+                binary: binary.to_string(),
+                platform: current_platform(),
+                url: "https://example.invalid/tool".to_string(),
+                sha256: sha256.to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_managed_tool_must_match_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = "moses-test-tool";
+        std::fs::write(dir.path().join(executable_name(binary)), b"moses").unwrap();
+        let actual = sha256_file(&dir.path().join(executable_name(binary))).unwrap();
+
+        let good = ToolManager::new(dir.path().to_path_buf(), manifest_for(binary, &actual), false);
+        assert_eq!(good.locate(binary).unwrap().source, ToolSource::Managed);
+
+        let bad = ToolManager::new(dir.path().to_path_buf(), manifest_for(binary, WRONG_SHA256), false);
+        assert!(bad.locate(binary).is_none());
+    }
+
+    #[test]
+    fn test_managed_tool_needs_manifest_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = "moses-test-unlisted-tool";
+        std::fs::write(dir.path().join(executable_name(binary)), b"moses").unwrap();
+
+        let manager = ToolManager::new(dir.path().to_path_buf(), ToolManifest::default(), false);
+        assert!(manager.locate(binary).is_none());
+
+        let mut escaping = manifest_for("../moses-escape", WRONG_SHA256);
+        let entry = escaping.tools.remove(0);
+        assert!(matches!(manager.install(&entry), Err(MosesError::InvalidInput(_))));
+        assert!(matches!(
+            load_manifest(Some("http://example.invalid/manifest.json"), dir.path()),
+            Err(MosesError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_tool_without_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = ToolManager::new(dir.path().to_path_buf(), ToolManifest::default(), false);
+        let err = manager.ensure("testpkg", &["moses-no-such-tool"]).unwrap_err();
+        assert!(matches!(err, MosesError::ExternalToolMissing(_)));
+        assert_eq!(package_binaries("dosfstools"), &["mkfs.fat"]);
+    }
+}
@@ -0,0 +1,915 @@
+// Transfer engine: walking, filtering and copying files out of a filesystem
+// Used by archive export and by the GUI copy command. A TransferFilter selects files by
+// glob, size and modification time; `preview` reports what it would select so the user
+// can check the numbers before anything is copied. Sources are only ever read.
+// Before a copy starts the selected tree is checked against what the destination
+// filesystem can store (file size, name and path length, Windows naming rules), and
+// every item that would not fit is reported at once instead of failing mid-copy.
+// As an opt-in, files over the destination's size limit (4 GiB on FAT32) are split into
+// `name.001`, `name.002`... with a `name.moses-split` manifest, and copying such a set
+// back to a filesystem that holds the whole file joins it again.
+use crate::ops::{FileAttributes, FilesystemOps};
+use crate::walker::{walk_tree, WalkControl, WalkOptions};
+use glob::{MatchOptions, Pattern};
+use moses_core::MosesError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Bytes requested from the filesystem per read
+pub(crate) const CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// FAT and exFAT store names in whatever case the writer chose, so globs ignore case
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Characters FAT, exFAT and NTFS do not allow in names, besides control characters
+const WINDOWS_INVALID_CHARACTERS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Extension of the manifest written next to the parts of a split file
+pub const SPLIT_MANIFEST_EXTENSION: &str = "moses-split";
+
+/// A split file has at most `name.001` to `name.999`
+const MAX_SPLIT_PARTS: u64 = 999;
+
+/// Manifests are never bigger than this; anything larger is not one
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024;
+
+/// Which files a transfer picks up
+///
+/// Patterns without a `/` match the file name anywhere in the tree (`*.jpg`); patterns with
+/// one match the path relative to the copied folder (`DCIM/**/*.jpg`). Excluded folders
+/// are not descended into.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferFilter {
+    /// Copy only files matching one of these (all files when empty)
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Skip files and folders matching any of these
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Skip files larger than this many bytes
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// Skip files last modified before this Unix timestamp
+    #[serde(default)]
+    pub newer_than: Option<u64>,
+    /// Copies only: split files too large for the destination into parts, and join
+    /// split files back together where the destination can hold them
+    #[serde(default)]
+    pub split_large_files: bool,
+    /// Bytes per read and write; None uses CHUNK_SIZE. Set from the source device's
+    /// tuned I/O parameters (see io_tuning).
+    #[serde(default)]
+    pub block_size: Option<u32>,
+}
+
+impl TransferFilter {
+    /// Bytes moved per read and write
+    pub fn chunk_size(&self) -> u32 {
+        self.block_size.filter(|&size| size > 0).unwrap_or(CHUNK_SIZE)
+    }
+
+    /// Compile the patterns, rejecting malformed globs
+    pub fn compile(&self) -> Result<Selection, MosesError> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>, MosesError> {
+            patterns.iter()
+                .map(|pattern| Pattern::new(pattern.trim_start_matches('/'))
+                    .map_err(|e| MosesError::InvalidInput(format!("Bad pattern '{}': {}", pattern, e))))
+                .collect()
+        };
+        Ok(Selection {
+            include: compile(&self.include)?,
+            exclude: compile(&self.exclude)?,
+            max_file_size: self.max_file_size,
+            newer_than: self.newer_than,
+        })
+    }
+}
+
+/// A compiled TransferFilter
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    max_file_size: Option<u64>,
+    newer_than: Option<u64>,
+}
+
+impl Selection {
+    /// Whether the file at `name` (relative, `/`-separated) is selected
+    pub fn accepts_file(&self, name: &str, attributes: &FileAttributes) -> bool {
+        if self.max_file_size.is_some_and(|max| attributes.size > max) {
+            return false;
+        }
+        if let Some(newer_than) = self.newer_than {
+            if attributes.modified.is_none_or(|modified| modified < newer_than) {
+                return false;
+            }
+        }
+        (self.include.is_empty() || matches_any(&self.include, name)) && !matches_any(&self.exclude, name)
+    }
+
+    /// Whether the folder at `name` is descended into
+    pub fn accepts_directory(&self, name: &str) -> bool {
+        !matches_any(&self.exclude, name)
+    }
+
+    /// With include patterns only folders holding a selected file end up in the output
+    fn keeps_empty_directories(&self) -> bool {
+        self.include.is_empty()
+    }
+}
+
+fn matches_any(patterns: &[Pattern], name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    patterns.iter().any(|pattern| {
+        let target = if pattern.as_str().contains('/') { name } else { file_name };
+        pattern.matches_with(target, MATCH_OPTIONS)
+    })
+}
+
+/// What a filter selects, and what it leaves out
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferPreview {
+    pub files: u64,
+    pub directories: u64,
+    pub bytes: u64,
+    pub excluded_files: u64,
+    pub excluded_bytes: u64,
+}
+
+/// Outcome of copying files out of a filesystem
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferReport {
+    pub files_copied: u64,
+    pub bytes_copied: u64,
+    /// One line per file or folder that could not be copied; the rest still are
+    pub errors: Vec<String>,
+    /// Files copied as parts because the destination could not hold them whole
+    #[serde(default)]
+    pub files_split: u64,
+    /// Split files put back together at the destination
+    #[serde(default)]
+    pub files_joined: u64,
+}
+
+/// Written as `name.moses-split` next to the parts of a split file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplitManifest {
+    /// Name of the whole file, without any folder
+    pub name: String,
+    pub size: u64,
+    /// Size of every part but the last
+    pub part_size: u64,
+    pub parts: u64,
+    #[serde(default)]
+    pub modified: Option<u64>,
+}
+
+impl SplitManifest {
+    fn part_name(&self, index: u64) -> String {
+        format!("{}.{:03}", self.name, index)
+    }
+}
+
+/// What a destination filesystem can store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilesystemLimits {
+    pub filesystem: String,
+    pub max_file_size: Option<u64>,
+    /// Longest file or folder name: UTF-16 units under Windows naming rules, bytes otherwise
+    pub max_name_length: Option<usize>,
+    /// Longest path from the volume root, in the same units
+    pub max_path_length: Option<usize>,
+    /// No `"*:<>?\|` or control characters, no trailing dot or space, no reserved device
+    /// names, and names that differ only in case are the same file
+    pub windows_names: bool,
+}
+
+impl FilesystemLimits {
+    /// Limits of `filesystem` (as in `FilesystemOps::filesystem_type`); unknown ones get none
+    pub fn for_filesystem(filesystem: &str) -> Self {
+        let filesystem = filesystem.trim_start_matches("host:").to_lowercase();
+        let max_file_size = crate::registration::builtin_registry()
+            .get_metadata(&filesystem)
+            .and_then(|metadata| metadata.capabilities.max_file_size);
+        let (max_file_size, max_name_length, max_path_length, windows_names) = match filesystem.as_str() {
+            "fat12" | "fat16" | "fat32" | "vfat" => (max_file_size.or(Some(4 * 1024_u64.pow(3) - 1)), Some(255), Some(260), true),
+            "exfat" | "ntfs" => (max_file_size, Some(255), Some(32767), true),
+            "ext2" | "ext3" | "ext4" => (max_file_size, Some(255), Some(4095), false),
+            "apfs" | "hfs+" | "hfsplus" => (max_file_size, Some(255), None, false),
+            _ => (max_file_size, None, None, false),
+        };
+        Self { filesystem, max_file_size, max_name_length, max_path_length, windows_names }
+    }
+
+    /// Limits of local folders, taken to be on the host OS's usual filesystem
+    pub fn for_host() -> Self {
+        Self::for_filesystem(crate::ops::host_filesystem_type())
+    }
+
+    fn length(&self, text: &str) -> usize {
+        if self.windows_names { text.encode_utf16().count() } else { text.len() }
+    }
+
+    fn units(&self) -> &'static str {
+        if self.windows_names { "characters" } else { "bytes" }
+    }
+
+    /// Why `name` (one path component) cannot be stored, if it cannot
+    fn name_problem(&self, name: &str) -> Option<String> {
+        if let Some(max) = self.max_name_length.filter(|&max| self.length(name) > max) {
+            return Some(format!("name is longer than the {} {} {} allows", max, self.units(), self.filesystem));
+        }
+        if !self.windows_names {
+            return None;
+        }
+        if let Some(c) = name.chars().find(|c| c.is_control() || WINDOWS_INVALID_CHARACTERS.contains(c)) {
+            return Some(format!("{} does not allow {:?} in names", self.filesystem, c));
+        }
+        if name.ends_with('.') || name.ends_with(' ') {
+            return Some(format!("{} names cannot end with a dot or space", self.filesystem));
+        }
+        let stem = name.split('.').next().unwrap_or(name).trim_end();
+        if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+            return Some(format!("{} is a reserved device name on {}", stem, self.filesystem));
+        }
+        None
+    }
+}
+
+/// One item of a copy the destination cannot store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LimitViolation {
+    /// Relative, `/`-separated name as in the copy
+    pub name: String,
+    pub problem: String,
+}
+
+impl std::fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.problem)
+    }
+}
+
+/// Called for each selected entry with its filesystem path, relative name and attributes
+pub(crate) type Visitor<'a> = dyn FnMut(&mut dyn FilesystemOps, &Path, String, &FileAttributes) -> Result<(), MosesError> + 'a;
+
+/// Visit every selected entry below `root` depth-first, parents before children. Names
+/// are relative to `root` and start with `prefix`. Returns the count and bytes of files
+/// the selection left out.
+pub(crate) fn walk(
+    fs: &mut dyn FilesystemOps,
+    root: &Path,
+    prefix: &str,
+    selection: &Selection,
+    visit: &mut Visitor<'_>,
+) -> Result<(u64, u64), MosesError> {
+    let mut excluded = (0, 0);
+    let options = WalkOptions { stat_entries: true, ..Default::default() };
+    let summary = walk_tree(fs, root, &options, |fs, entry| {
+        let name = format!("{}{}", prefix, entry.name);
+        if entry.is_directory() {
+            if !selection.accepts_directory(&name) {
+                return Ok(WalkControl::SkipChildren);
+            }
+            if selection.keeps_empty_directories() {
+                visit(fs, entry.path, name, entry.attributes)?;
+            }
+        } else if selection.accepts_file(&name, entry.attributes) {
+            visit(fs, entry.path, name, entry.attributes)?;
+        } else {
+            excluded.0 += 1;
+            excluded.1 += entry.attributes.size;
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    for problem in summary.problems() {
+        log::warn!("{}: {}", root.display(), problem);
+    }
+    Ok(excluded)
+}
+
+/// Count what `filter` selects under `sources` without reading any file data
+pub fn preview(fs: &mut dyn FilesystemOps, sources: &[PathBuf], filter: &TransferFilter) -> Result<TransferPreview, MosesError> {
+    let selection = filter.compile()?;
+    let mut preview = TransferPreview::default();
+    for source in sources {
+        let attributes = fs.stat(source)?;
+        let name = source_name(source);
+        if attributes.is_directory {
+            preview.directories += 1;
+            count_tree(fs, source, &format!("{}/", name), &selection, &mut preview)?;
+        } else if selection.accepts_file(&name, &attributes) {
+            preview.files += 1;
+            preview.bytes += attributes.size;
+        } else {
+            preview.excluded_files += 1;
+            preview.excluded_bytes += attributes.size;
+        }
+    }
+    Ok(preview)
+}
+
+/// As [`preview`] for the contents of one folder, with names relative to it as in
+/// archive export
+pub fn preview_tree(fs: &mut dyn FilesystemOps, root: &Path, filter: &TransferFilter) -> Result<TransferPreview, MosesError> {
+    let selection = filter.compile()?;
+    let mut preview = TransferPreview::default();
+    count_tree(fs, root, "", &selection, &mut preview)?;
+    Ok(preview)
+}
+
+fn count_tree(
+    fs: &mut dyn FilesystemOps,
+    root: &Path,
+    prefix: &str,
+    selection: &Selection,
+    preview: &mut TransferPreview,
+) -> Result<(), MosesError> {
+    let (files, bytes) = walk(fs, root, prefix, selection, &mut |_, _, _, attributes| {
+        if attributes.is_directory {
+            preview.directories += 1;
+        } else {
+            preview.files += 1;
+            preview.bytes += attributes.size;
+        }
+        Ok(())
+    })?;
+    preview.excluded_files += files;
+    preview.excluded_bytes += bytes;
+    Ok(())
+}
+
+/// Everything `filter` selects under `sources` that a copy into `destination` on a
+/// filesystem with `limits` could not store. Reads no file data.
+pub fn check_limits(
+    fs: &mut dyn FilesystemOps,
+    sources: &[PathBuf],
+    filter: &TransferFilter,
+    limits: &FilesystemLimits,
+    destination: &Path,
+) -> Result<Vec<LimitViolation>, MosesError> {
+    let selection = filter.compile()?;
+    let mut violations = Vec::new();
+    // Lower-cased name -> first name seen, for filesystems that ignore case
+    let mut seen: HashMap<String, String> = HashMap::new();
+    let destination = destination.to_string_lossy().trim_end_matches(['/', '\\']).to_string();
+    let mut check = |_: &mut dyn FilesystemOps, _: &Path, name: String, attributes: &FileAttributes| {
+        let mut problems = Vec::new();
+        let oversize = limits.max_file_size.filter(|&max| !attributes.is_directory && attributes.size > max);
+        // A split file is stored under its manifest's name, the longest of its set
+        let stored = match oversize {
+            Some(_) if filter.split_large_files => format!("{}.{}", name, SPLIT_MANIFEST_EXTENSION),
+            _ => name.clone(),
+        };
+        if let Some(problem) = stored.rsplit('/').next().and_then(|last| limits.name_problem(last)) {
+            problems.push(problem);
+        }
+        let full_path = format!("{}/{}", destination, stored);
+        if let Some(max) = limits.max_path_length.filter(|&max| limits.length(&full_path) > max) {
+            problems.push(format!("path is longer than the {} {} {} allows", max, limits.units(), limits.filesystem));
+        }
+        match oversize {
+            Some(max) if filter.split_large_files => {
+                if attributes.size.div_ceil(split_part_size(max)) > MAX_SPLIT_PARTS {
+                    problems.push(format!("would need more than {} parts to fit {}", MAX_SPLIT_PARTS, limits.filesystem));
+                }
+            }
+            Some(max) => problems.push(format!(
+                "{} MB is more than the {} MB {} allows in one file",
+                attributes.size.div_ceil(1024 * 1024), max / (1024 * 1024), limits.filesystem
+            )),
+            None => {}
+        }
+        if limits.windows_names {
+            if let Some(first) = seen.get(&name.to_lowercase()) {
+                problems.push(format!("{} ignores case, so this is the same name as {}", limits.filesystem, first));
+            } else {
+                seen.insert(name.to_lowercase(), name.clone());
+            }
+        }
+        violations.extend(problems.into_iter().map(|problem| LimitViolation { name: name.clone(), problem }));
+        Ok(())
+    };
+    for source in sources {
+        let attributes = fs.stat(source)?;
+        let name = source_name(source);
+        if attributes.is_directory {
+            check(fs, source, name.clone(), &attributes)?;
+            walk(fs, source, &format!("{}/", name), &selection, &mut check)?;
+        } else if selection.accepts_file(&name, &attributes) {
+            check(fs, source, name, &attributes)?;
+        }
+    }
+    Ok(violations)
+}
+
+/// Fail with every violation listed when there are any
+fn ensure_within_limits(
+    fs: &mut dyn FilesystemOps,
+    sources: &[PathBuf],
+    filter: &TransferFilter,
+    limits: &FilesystemLimits,
+    destination: &Path,
+) -> Result<(), MosesError> {
+    let violations = check_limits(fs, sources, filter, limits, destination)?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(MosesError::InvalidInput(format!(
+        "{} item(s) cannot be stored on {}, nothing was copied: {}",
+        violations.len(),
+        limits.filesystem,
+        violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )))
+}
+
+/// Copy `sources` (files or folders) into the local folder `destination`
+///
+/// A source folder is recreated inside `destination` under its own name. Nothing is
+/// copied when an item breaks the host filesystem's limits. Modification times are kept;
+/// other failures are collected per file and the copy carries on.
+pub fn extract_to_directory(
+    fs: &mut dyn FilesystemOps,
+    sources: &[PathBuf],
+    destination: &Path,
+    filter: &TransferFilter,
+) -> Result<TransferReport, MosesError> {
+    ensure_within_limits(fs, sources, filter, &FilesystemLimits::for_host(), destination)?;
+    std::fs::create_dir_all(destination)?;
+    copy(fs, sources, filter, &FilesystemLimits::for_host(), &mut LocalSink(destination.to_path_buf()))
+}
+
+/// Copy `sources` into the folder `destination` of another, writable filesystem
+///
+/// Nothing is copied when an item breaks the target filesystem's limits.
+pub fn copy_to_filesystem(
+    fs: &mut dyn FilesystemOps,
+    sources: &[PathBuf],
+    target: &mut dyn FilesystemOps,
+    destination: &Path,
+    filter: &TransferFilter,
+) -> Result<TransferReport, MosesError> {
+    if target.is_readonly() {
+        return Err(MosesError::InvalidInput(format!("{} destination is read-only", target.filesystem_type())));
+    }
+    let limits = FilesystemLimits::for_filesystem(target.filesystem_type());
+    ensure_within_limits(fs, sources, filter, &limits, destination)?;
+    copy(fs, sources, filter, &limits, &mut OpsSink { fs: target, root: destination.to_path_buf(), chunk_size: filter.chunk_size() })
+}
+
+/// How one selected entry reached the destination
+enum Copied {
+    Directory,
+    File,
+    Split,
+    /// A manifest whose parts were joined into a file of this size
+    Joined(u64),
+    /// A part of a split file, copied as part of the joined file instead
+    Skipped,
+}
+
+fn copy(
+    fs: &mut dyn FilesystemOps,
+    sources: &[PathBuf],
+    filter: &TransferFilter,
+    limits: &FilesystemLimits,
+    sink: &mut dyn Sink,
+) -> Result<TransferReport, MosesError> {
+    let selection = filter.compile()?;
+    let chunk_size = filter.chunk_size();
+    let mut report = TransferReport::default();
+    let mut copy_entry = |fs: &mut dyn FilesystemOps, path: &Path, name: String, attributes: &FileAttributes| {
+        let result = if attributes.is_symlink {
+            Err(MosesError::NotSupported("symlinks cannot be copied".to_string()))
+        } else if attributes.is_directory {
+            sink.create_directory(&name).map(|()| Copied::Directory)
+        } else if filter.split_large_files {
+            copy_splitting(fs, path, &name, attributes, limits, chunk_size, sink)
+        } else {
+            sink.create_file(&name, attributes, &mut FileReader::new(fs, path, attributes.size).with_chunk_size(chunk_size)).map(|()| Copied::File)
+        };
+        match result {
+            Ok(Copied::Directory | Copied::Skipped) => {}
+            Ok(copied) => {
+                report.files_copied += 1;
+                report.bytes_copied += match copied {
+                    Copied::Joined(size) => size,
+                    _ => attributes.size,
+                };
+                report.files_split += matches!(copied, Copied::Split) as u64;
+                report.files_joined += matches!(copied, Copied::Joined(_)) as u64;
+            }
+            Err(e) => report.errors.push(format!("{}: {}", name, e)),
+        }
+        Ok(())
+    };
+    for source in sources {
+        let attributes = fs.stat(source)?;
+        let name = source_name(source);
+        if attributes.is_directory {
+            copy_entry(fs, source, name.clone(), &attributes)?;
+            walk(fs, source, &format!("{}/", name), &selection, &mut copy_entry)?;
+        } else if selection.accepts_file(&name, &attributes) {
+            copy_entry(fs, source, name, &attributes)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Largest part a file is split into for a destination holding `max_file_size` per file
+fn split_part_size(max_file_size: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    if max_file_size >= MIB { max_file_size / MIB * MIB } else { max_file_size.max(1) }
+}
+
+/// Copy one file with splitting on: split it when the destination cannot hold it,
+/// join it when it is the manifest of a split file the destination can hold whole
+fn copy_splitting(
+    fs: &mut dyn FilesystemOps,
+    path: &Path,
+    name: &str,
+    attributes: &FileAttributes,
+    limits: &FilesystemLimits,
+    chunk_size: u32,
+    sink: &mut dyn Sink,
+) -> Result<Copied, MosesError> {
+    let fits = |size: u64| limits.max_file_size.is_none_or(|max| size <= max);
+    if let Some(manifest) = read_manifest(fs, path, attributes) {
+        if fits(manifest.size) {
+            let prefix = &name[..name.rfind('/').map_or(0, |slash| slash + 1)];
+            let parent = path.parent().unwrap_or(Path::new("/"));
+            let mut parts = Vec::new();
+            for index in 1..=manifest.parts {
+                let part = parent.join(manifest.part_name(index));
+                let size = fs.stat(&part)
+                    .map_err(|e| MosesError::Other(format!("part {} is missing: {}", manifest.part_name(index), e)))?
+                    .size;
+                parts.push((part, size));
+            }
+            let total: u64 = parts.iter().map(|(_, size)| size).sum();
+            if total != manifest.size {
+                return Err(MosesError::Other(format!("parts hold {} bytes, the manifest says {}", total, manifest.size)));
+            }
+            let joined = FileAttributes { size: manifest.size, modified: manifest.modified.or(attributes.modified), ..attributes.clone() };
+            let mut reader = JoinedReader { fs, parts, index: 0, offset: 0, chunk_size };
+            sink.create_file(&format!("{}{}", prefix, manifest.name), &joined, &mut reader)?;
+            return Ok(Copied::Joined(manifest.size));
+        }
+    } else if let Some(manifest) = manifest_of_part(fs, path) {
+        if fits(manifest.size) {
+            return Ok(Copied::Skipped);
+        }
+    } else if !fits(attributes.size) {
+        let part_size = split_part_size(limits.max_file_size.unwrap_or(u64::MAX));
+        let manifest = SplitManifest {
+            name: name.rsplit('/').next().unwrap_or(name).to_string(),
+            size: attributes.size,
+            part_size,
+            parts: attributes.size.div_ceil(part_size),
+            modified: attributes.modified,
+        };
+        let prefix = &name[..name.len() - manifest.name.len()];
+        let mut reader = FileReader::new(fs, path, attributes.size).with_chunk_size(chunk_size);
+        for index in 1..=manifest.parts {
+            let size = part_size.min(attributes.size - (index - 1) * part_size);
+            let part = FileAttributes { size, ..attributes.clone() };
+            sink.create_file(&format!("{}{}", prefix, manifest.part_name(index)), &part, &mut (&mut reader).take(size))?;
+        }
+        let json = serde_json::to_vec_pretty(&manifest).map_err(|e| MosesError::Other(e.to_string()))?;
+        let file = FileAttributes { size: json.len() as u64, ..attributes.clone() };
+        sink.create_file(&format!("{}.{}", name, SPLIT_MANIFEST_EXTENSION), &file, &mut json.as_slice())?;
+        return Ok(Copied::Split);
+    }
+    sink.create_file(name, attributes, &mut FileReader::new(fs, path, attributes.size).with_chunk_size(chunk_size))?;
+    Ok(Copied::File)
+}
+
+/// The manifest in the file at `path`, if it is one
+fn read_manifest(fs: &mut dyn FilesystemOps, path: &Path, attributes: &FileAttributes) -> Option<SplitManifest> {
+    let is_manifest = path.extension().is_some_and(|extension| extension == SPLIT_MANIFEST_EXTENSION);
+    if !is_manifest || attributes.size > MAX_MANIFEST_SIZE {
+        return None;
+    }
+    let data = fs.read(path, 0, attributes.size as u32).ok()?;
+    serde_json::from_slice::<SplitManifest>(&data).ok()
+        .filter(|manifest| manifest.parts <= MAX_SPLIT_PARTS && !manifest.name.contains(['/', '\\']))
+}
+
+/// The manifest of the split file that the file at `path` (`name.001`...) is a part of
+fn manifest_of_part(fs: &mut dyn FilesystemOps, path: &Path) -> Option<SplitManifest> {
+    let extension = path.extension()?.to_str()?;
+    if extension.len() != 3 || !extension.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let manifest_path = path.with_extension(SPLIT_MANIFEST_EXTENSION);
+    let attributes = fs.stat(&manifest_path).ok()?;
+    read_manifest(fs, &manifest_path, &attributes)
+        .filter(|manifest| Some(manifest.name.as_str()) == path.file_stem().and_then(|stem| stem.to_str()))
+}
+
+/// Last component of a source path, or "root" for `/`
+fn source_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".to_string())
+}
+
+/// Where copied entries go; names are relative and `/`-separated
+trait Sink {
+    fn create_directory(&mut self, name: &str) -> Result<(), MosesError>;
+    fn create_file(&mut self, name: &str, attributes: &FileAttributes, data: &mut dyn Read) -> Result<(), MosesError>;
+}
+
+struct LocalSink(PathBuf);
+
+impl LocalSink {
+    fn path(&self, name: &str) -> Result<PathBuf, MosesError> {
+        // Names come from the source filesystem; never let one escape the destination
+        if name.split('/').any(|part| part == ".." || part.is_empty()) || name.contains('\\') {
+            return Err(MosesError::InvalidInput(format!("unsafe file name '{}'", name)));
+        }
+        Ok(self.0.join(name))
+    }
+}
+
+impl Sink for LocalSink {
+    fn create_directory(&mut self, name: &str) -> Result<(), MosesError> {
+        std::fs::create_dir_all(self.path(name)?)?;
+        Ok(())
+    }
+
+    fn create_file(&mut self, name: &str, attributes: &FileAttributes, data: &mut dyn Read) -> Result<(), MosesError> {
+        let path = self.path(name)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::File::create(&path)?;
+        std::io::copy(data, &mut file)?;
+        if let Some(modified) = attributes.modified {
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified))?;
+        }
+        Ok(())
+    }
+}
+
+struct OpsSink<'a> {
+    fs: &'a mut dyn FilesystemOps,
+    root: PathBuf,
+    chunk_size: u32,
+}
+
+impl Sink for OpsSink<'_> {
+    fn create_directory(&mut self, name: &str) -> Result<(), MosesError> {
+        let path = self.root.join(name);
+        if self.fs.stat(&path).is_ok_and(|existing| existing.is_directory) {
+            return Ok(());
+        }
+        self.fs.mkdir(&path, 0o755)
+    }
+
+    fn create_file(&mut self, name: &str, _attributes: &FileAttributes, data: &mut dyn Read) -> Result<(), MosesError> {
+        let path = self.root.join(name);
+        self.fs.create(&path, 0o644)?;
+        let mut buffer = vec![0u8; self.chunk_size as usize];
+        let mut offset = 0;
+        loop {
+            let count = data.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(());
+            }
+            let mut written = 0;
+            while written < count {
+                written += self.fs.write(&path, offset + written as u64, &buffer[written..count])? as usize;
+            }
+            offset += count as u64;
+        }
+    }
+}
+
+/// `Read` over the parts of a split file, one after another
+struct JoinedReader<'a> {
+    fs: &'a mut dyn FilesystemOps,
+    parts: Vec<(PathBuf, u64)>,
+    index: usize,
+    offset: u64,
+    chunk_size: u32,
+}
+
+impl Read for JoinedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some((path, size)) = self.parts.get(self.index) {
+            if self.offset >= *size {
+                self.index += 1;
+                self.offset = 0;
+                continue;
+            }
+            let want = (buf.len() as u64).min(self.chunk_size as u64).min(size - self.offset) as u32;
+            let data = self.fs.read(path, self.offset, want)
+                .map_err(|e| std::io::Error::other(format!("{}: {}", path.display(), e)))?;
+            if data.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} ended after {} of {} bytes", path.display(), self.offset, size),
+                ));
+            }
+            let count = data.len().min(buf.len());
+            buf[..count].copy_from_slice(&data[..count]);
+            self.offset += count as u64;
+            return Ok(count);
+        }
+        Ok(0)
+    }
+}
+
+/// `Read` over a file inside a filesystem, fetched in CHUNK_SIZE pieces unless told otherwise
+pub(crate) struct FileReader<'a> {
+    fs: &'a mut dyn FilesystemOps,
+    path: &'a Path,
+    size: u64,
+    chunk_size: u32,
+    offset: u64,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<'a> FileReader<'a> {
+    pub(crate) fn new(fs: &'a mut dyn FilesystemOps, path: &'a Path, size: u64) -> Self {
+        Self { fs, path, size, chunk_size: CHUNK_SIZE, offset: 0, buffer: Vec::new(), position: 0 }
+    }
+
+    pub(crate) fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+impl Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.buffer.len() {
+            if self.offset >= self.size {
+                return Ok(0);
+            }
+            let want = (self.chunk_size as u64).min(self.size - self.offset) as u32;
+            self.buffer = self.fs.read(self.path, self.offset, want)
+                .map_err(|e| std::io::Error::other(format!("{}: {}", self.path.display(), e)))?;
+            self.position = 0;
+            // A short file would leave an archive header promising bytes that never come
+            if self.buffer.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} ended after {} of {} bytes", self.path.display(), self.offset, self.size),
+                ));
+            }
+            self.offset += self.buffer.len() as u64;
+        }
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+
+    fn sample_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("DCIM/100CANON")).unwrap();
+        std::fs::create_dir_all(dir.path().join("DCIM/.thumbnails")).unwrap();
+        std::fs::write(dir.path().join("DCIM/100CANON/IMG_0001.JPG"), vec![1u8; 4000]).unwrap();
+        std::fs::write(dir.path().join("DCIM/100CANON/MVI_0002.MOV"), vec![2u8; 90_000]).unwrap();
+        std::fs::write(dir.path().join("DCIM/.thumbnails/IMG_0001.jpg"), vec![3u8; 100]).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"notes").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_preview_and_extract_with_filter() {
+        let source = sample_tree();
+        let mut fs = HostFolderOps::new(source.path().to_path_buf()).unwrap();
+        let filter = TransferFilter {
+            include: vec!["*.jpg".to_string(), "*.mov".to_string()],
+            exclude: vec![".thumbnails".to_string()],
+            max_file_size: Some(50_000),
+            ..Default::default()
+        };
+        let sources = [PathBuf::from("/DCIM"), PathBuf::from("/notes.txt")];
+
+        let preview = preview(&mut fs, &sources, &filter).unwrap();
+        assert_eq!((preview.files, preview.bytes), (1, 4000));
+        assert_eq!((preview.excluded_files, preview.excluded_bytes), (2, 90_005));
+
+        let destination = tempfile::tempdir().unwrap();
+        let report = extract_to_directory(&mut fs, &sources, destination.path(), &filter).unwrap();
+        assert_eq!((report.files_copied, report.bytes_copied), (preview.files, preview.bytes));
+        assert!(report.errors.is_empty());
+        assert_eq!(std::fs::read(destination.path().join("DCIM/100CANON/IMG_0001.JPG")).unwrap().len(), 4000);
+        assert!(!destination.path().join("DCIM/.thumbnails").exists());
+        assert!(!destination.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_newer_than_and_path_patterns() {
+        let source = sample_tree();
+        let mut fs = HostFolderOps::new(source.path().to_path_buf()).unwrap();
+        let sources = [PathBuf::from("/")];
+
+        let future = TransferFilter { newer_than: Some(u64::MAX), ..Default::default() };
+        assert_eq!(preview(&mut fs, &sources, &future).unwrap().files, 0);
+
+        let by_path = TransferFilter { include: vec!["root/DCIM/100CANON/*".to_string()], ..Default::default() };
+        let preview = preview(&mut fs, &sources, &by_path).unwrap();
+        assert_eq!(preview.files, 2);
+
+        let relative = TransferFilter { include: vec!["DCIM/100CANON/*".to_string()], ..Default::default() };
+        assert_eq!(preview_tree(&mut fs, Path::new("/"), &relative).unwrap().files, 2);
+
+        let bad = TransferFilter { include: vec!["[".to_string()], ..Default::default() };
+        assert!(bad.compile().is_err());
+    }
+
+    #[test]
+    fn test_limits_report_every_violation() {
+        let source = sample_tree();
+        std::fs::File::create(source.path().join("DCIM/100CANON/MVI_0003.MOV")).unwrap()
+            .set_len(5 * 1024_u64.pow(3)).unwrap();
+        std::fs::write(source.path().join("12:30 notes.txt"), b"").unwrap();
+        std::fs::write(source.path().join("con.txt"), b"").unwrap();
+        std::fs::write(source.path().join("Notes.TXT"), b"").unwrap();
+        let deep = ["a".repeat(100), "b".repeat(100), "c".repeat(100)].join("/");
+        std::fs::create_dir_all(source.path().join(&deep)).unwrap();
+        let mut fs = HostFolderOps::new(source.path().to_path_buf()).unwrap();
+        let sources = [PathBuf::from("/")];
+        let filter = TransferFilter::default();
+
+        let fat32 = FilesystemLimits::for_filesystem("fat32");
+        assert_eq!(fat32.max_file_size, Some(4 * 1024_u64.pow(3) - 1));
+        let violations = check_limits(&mut fs, &sources, &filter, &fat32, Path::new("/")).unwrap();
+        let problem = |name: &str| violations.iter().find(|v| v.name == name).map(|v| v.problem.clone());
+        assert_eq!(violations.len(), 5, "{:?}", violations);
+        assert!(problem("root/DCIM/100CANON/MVI_0003.MOV").unwrap().contains("in one file"));
+        assert!(problem("root/12:30 notes.txt").unwrap().contains("':'"));
+        assert!(problem("root/con.txt").unwrap().contains("reserved"));
+        assert!(violations.iter().any(|v| v.problem.contains("ignores case")));
+        assert!(problem(&format!("root/{}", deep)).unwrap().contains("path is longer"));
+
+        let ext4 = FilesystemLimits::for_filesystem("ext4");
+        assert!(check_limits(&mut fs, &sources, &filter, &ext4, Path::new("/")).unwrap().is_empty());
+        assert!(FilesystemLimits::for_filesystem("exfat").max_file_size > Some(5 * 1024_u64.pow(3)));
+    }
+
+    #[test]
+    fn test_split_and_join_round_trip() {
+        let source = sample_tree();
+        let data: Vec<u8> = (0..7 * 1024 * 1024 + 123).map(|i| (i % 251) as u8).collect();
+        std::fs::write(source.path().join("DCIM/100CANON/MVI_0003.MOV"), &data).unwrap();
+        let mut fs = HostFolderOps::new(source.path().to_path_buf()).unwrap();
+        let sources = [PathBuf::from("/DCIM")];
+        let small = FilesystemLimits { max_file_size: Some(3 * 1024 * 1024), ..FilesystemLimits::for_filesystem("fat32") };
+        let split = TransferFilter { split_large_files: true, ..Default::default() };
+
+        let refused = check_limits(&mut fs, &sources, &TransferFilter::default(), &small, Path::new("/")).unwrap();
+        assert_eq!(refused.len(), 1);
+        assert!(check_limits(&mut fs, &sources, &split, &small, Path::new("/")).unwrap().is_empty());
+
+        let card = tempfile::tempdir().unwrap();
+        let mut target = HostFolderOps::new(card.path().to_path_buf()).unwrap();
+        let report = copy(&mut fs, &sources, &split, &small, &mut OpsSink { fs: &mut target, root: PathBuf::from("/"), chunk_size: CHUNK_SIZE }).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!((report.files_copied, report.files_split), (4, 1));
+        let folder = card.path().join("DCIM/100CANON");
+        assert_eq!(std::fs::metadata(folder.join("MVI_0003.MOV.001")).unwrap().len(), 3 * 1024 * 1024);
+        assert_eq!(std::fs::metadata(folder.join("MVI_0003.MOV.003")).unwrap().len(), 1024 * 1024 + 123);
+        assert!(folder.join("MVI_0003.MOV.moses-split").exists() && !folder.join("MVI_0003.MOV").exists());
+        assert_eq!(std::fs::read(folder.join("MVI_0002.MOV")).unwrap().len(), 90_000, "small files stay whole");
+
+        let back = tempfile::tempdir().unwrap();
+        let mut card_fs = HostFolderOps::new(card.path().to_path_buf()).unwrap();
+        let report = extract_to_directory(&mut card_fs, &sources, back.path(), &split).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!((report.files_copied, report.files_joined, report.bytes_copied), (4, 1, data.len() as u64 + 94_100));
+        let folder = back.path().join("DCIM/100CANON");
+        assert_eq!(std::fs::read(folder.join("MVI_0003.MOV")).unwrap(), data);
+        assert!(!folder.join("MVI_0003.MOV.001").exists());
+
+        let verbatim = tempfile::tempdir().unwrap();
+        extract_to_directory(&mut card_fs, &sources, verbatim.path(), &TransferFilter::default()).unwrap();
+        assert!(verbatim.path().join("DCIM/100CANON/MVI_0003.MOV.002").exists(), "without the option parts are copied as they are");
+    }
+}
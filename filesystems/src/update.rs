@@ -0,0 +1,535 @@
+// Self-update - release feed, signed downloads and replacement on the next start
+// The release feed lists each version with the channel it was published on and a build
+// per component (the `moses` CLI or the desktop app) and platform. Checking picks the
+// newest release newer than the running build that the followed channel offers. Staging
+// downloads that build, checks its SHA-256 and its Ed25519 signature against the release
+// key the build was made with, and records it; the executable is swapped for it the next
+// time it starts, since a running program cannot be overwritten on Windows. The signature
+// is checked again right before the swap, because the staged file and its record sit in a
+// user-writable directory.
+//
+// The feed itself is not signed. Each signature covers the component, platform, version
+// and SHA-256 of a build (see `signed_message`), so a feed cannot pass an older signed
+// build off as a newer one, and a build that is not newer than the running one is refused.
+//
+// Feed format (`update.feed` in the settings, or MOSES_UPDATE_FEED at build time):
+// { "releases": [ { "version": "0.2.0", "channel": "stable", "notes": "...",
+//     "assets": [ { "component": "cli", "platform": "windows-x86_64", "url": "https://...",
+//                   "sha256": "...", "signature": "<hex Ed25519 signature of signed_message>" } ] } ] }
+use crate::tools::{current_platform, sha256_file, verify_sha256};
+use ed25519_dalek::{Signature, VerifyingKey};
+use moses_core::{MosesConfig, MosesError, ReleaseChannel};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Feed the build was made with, used when the settings name none
+const BUILT_IN_FEED: Option<&str> = option_env!("MOSES_UPDATE_FEED");
+/// Hex Ed25519 public key that release builds are signed with
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("MOSES_RELEASE_PUBLIC_KEY");
+/// Record of the staged build inside the update directory
+const STAGED_FILE: &str = "staged.json";
+
+/// The program being updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    Cli,
+    Gui,
+}
+
+impl Component {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::Cli => "cli",
+            Component::Gui => "gui",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseAsset {
+    pub component: Component,
+    pub platform: String,
+    pub url: String,
+    /// Lowercase hex SHA-256 of the file
+    pub sha256: String,
+    /// Hex Ed25519 signature of `signed_message` for this build by the release key
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub version: String,
+    #[serde(default)]
+    pub channel: ReleaseChannel,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseFeed {
+    #[serde(default)]
+    pub releases: Vec<Release>,
+}
+
+/// A release the running build can move to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailableUpdate {
+    pub version: String,
+    pub channel: ReleaseChannel,
+    pub notes: String,
+    pub asset: ReleaseAsset,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    pub current_version: String,
+    pub channel: ReleaseChannel,
+    pub available: Option<AvailableUpdate>,
+}
+
+/// A verified build waiting to replace `target` on its next start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedUpdate {
+    pub version: String,
+    pub path: PathBuf,
+    pub target: PathBuf,
+    pub sha256: String,
+    /// Release signature, checked again before the build is swapped in
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl ReleaseFeed {
+    /// Newest release offered on `channel` for this component and platform that is newer
+    /// than `current`
+    pub fn latest(&self, channel: ReleaseChannel, component: Component, platform: &str, current: &Version) -> Option<AvailableUpdate> {
+        self.releases
+            .iter()
+            .filter(|release| channel.includes(release.channel))
+            .filter_map(|release| match Version::parse(release.version.trim_start_matches('v')) {
+                Ok(version) => Some((version, release)),
+                Err(e) => {
+                    log::warn!("Ignoring release {} in the feed: {}", release.version, e);
+                    None
+                }
+            })
+            .filter(|(version, _)| version > current)
+            .filter_map(|(version, release)| {
+                let asset = release.assets.iter()
+                    .find(|asset| asset.component == component && asset.platform == platform)?;
+                Some((version, release, asset))
+            })
+            .max_by(|(a, ..), (b, ..)| a.cmp(b))
+            .map(|(version, release, asset)| AvailableUpdate {
+                version: version.to_string(),
+                channel: release.channel,
+                notes: release.notes.clone(),
+                asset: asset.clone(),
+            })
+    }
+}
+
+pub struct Updater {
+    dir: PathBuf,
+    feed: Option<String>,
+    channel: ReleaseChannel,
+    public_key: Option<VerifyingKey>,
+    component: Component,
+    current: Version,
+}
+
+impl Updater {
+    pub fn new(
+        dir: PathBuf,
+        feed: Option<String>,
+        channel: ReleaseChannel,
+        public_key: Option<VerifyingKey>,
+        component: Component,
+        current: Version,
+    ) -> Self {
+        Self { dir, feed, channel, public_key, component, current }
+    }
+
+    /// Updater for the running build of `component`, set up from the user's configuration
+    pub fn from_config(component: Component, current_version: &str) -> Result<Self, MosesError> {
+        let config = &MosesConfig::global().update;
+        let current = Version::parse(current_version)
+            .map_err(|e| MosesError::Other(format!("Invalid build version {}: {}", current_version, e)))?;
+        let public_key = RELEASE_PUBLIC_KEY.map(parse_public_key).transpose()?;
+        let feed = config.feed.clone().or_else(|| BUILT_IN_FEED.map(str::to_string));
+        Ok(Self::new(Self::default_dir(), feed, config.channel, public_key, component, current))
+    }
+
+    /// `<local data dir>/moses/update`
+    pub fn default_dir() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("moses")
+            .join("update")
+    }
+
+    pub fn channel(&self) -> ReleaseChannel {
+        self.channel
+    }
+
+    /// Follow another channel for this updater only
+    pub fn with_channel(mut self, channel: ReleaseChannel) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    pub fn fetch_feed(&self) -> Result<ReleaseFeed, MosesError> {
+        let location = self.feed.as_deref().ok_or_else(|| MosesError::NotSupported(
+            "No release feed is configured; set update.feed in the settings".to_string()
+        ))?;
+        std::fs::create_dir_all(&self.dir)?;
+        let cached = self.dir.join("feed.json");
+        fetch(location, &cached)?;
+        let text = std::fs::read_to_string(&cached)?;
+        serde_json::from_str(&text)
+            .map_err(|e| MosesError::External(format!("Invalid release feed {}: {}", location, e)))
+    }
+
+    pub fn check(&self) -> Result<UpdateCheck, MosesError> {
+        let feed = self.fetch_feed()?;
+        Ok(UpdateCheck {
+            current_version: self.current.to_string(),
+            channel: self.channel,
+            available: feed.latest(self.channel, self.component, &current_platform(), &self.current),
+        })
+    }
+
+    /// Download `update`, verify it and stage it to replace `target` on its next start
+    pub fn stage(&self, update: &AvailableUpdate, target: &Path) -> Result<StagedUpdate, MosesError> {
+        let key = self.public_key.as_ref().ok_or_else(|| MosesError::NotSupported(
+            "This build has no release signing key, so downloaded updates cannot be verified".to_string()
+        ))?;
+        let version = parse_version(&update.version)?;
+        if version <= self.current {
+            return Err(MosesError::SafetyViolation(format!(
+                "Moses {} is not newer than the running {}; refusing to downgrade", version, self.current
+            )));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let staged = self.dir.join(format!("moses-{}{}", version, std::env::consts::EXE_SUFFIX));
+        let partial = staged.with_extension("download");
+
+        log::info!("Downloading Moses {} from {}", update.version, update.asset.url);
+        fetch(&update.asset.url, &partial)?;
+        let verified = if !verify_sha256(&partial, &update.asset.sha256)? {
+            Err(MosesError::SafetyViolation(format!(
+                "Downloaded Moses {} does not match the expected SHA-256; it was discarded", update.version
+            )))
+        } else {
+            let message = signed_message(self.component, &current_platform(), &version, &update.asset.sha256);
+            verify_signature(key, &message, &update.asset.signature)
+        };
+        if let Err(e) = verified {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+        }
+        std::fs::rename(&partial, &staged)?;
+        let record = StagedUpdate {
+            version: update.version.clone(),
+            path: staged,
+            target: target.to_path_buf(),
+            sha256: update.asset.sha256.to_ascii_lowercase(),
+            signature: update.asset.signature.clone(),
+        };
+        let text = serde_json::to_string_pretty(&record)
+            .map_err(|e| MosesError::Other(format!("Failed to record the staged update: {}", e)))?;
+        std::fs::write(self.dir.join(STAGED_FILE), text)?;
+        log::info!("Staged Moses {} to replace {} on the next start", record.version, target.display());
+        Ok(record)
+    }
+
+    /// The build waiting to be applied, if any
+    pub fn staged(&self) -> Option<StagedUpdate> {
+        read_staged(&self.dir)
+    }
+}
+
+fn parse_public_key(hex_key: &str) -> Result<VerifyingKey, MosesError> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim()).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| MosesError::Other("The release public key is not 32 bytes of hex".to_string()))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| MosesError::Other(format!("Invalid release public key: {}", e)))
+}
+
+fn parse_version(version: &str) -> Result<Version, MosesError> {
+    Version::parse(version.trim_start_matches('v'))
+        .map_err(|e| MosesError::Other(format!("Invalid release version {}: {}", version, e)))
+}
+
+/// What a release signature covers: which build this is and its SHA-256
+pub fn signed_message(component: Component, platform: &str, version: &Version, sha256: &str) -> Vec<u8> {
+    format!(
+        "moses-release\n{}\n{}\n{}\n{}\n",
+        component.name(), platform, version, sha256.trim().to_ascii_lowercase()
+    ).into_bytes()
+}
+
+/// Check that `message` was signed by the release key
+pub fn verify_signature(key: &VerifyingKey, message: &[u8], signature: &str) -> Result<(), MosesError> {
+    let signature: [u8; 64] = hex::decode(signature.trim()).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| MosesError::SafetyViolation("The update's signature is not 64 bytes of hex".to_string()))?;
+    key.verify_strict(message, &Signature::from_bytes(&signature)).map_err(|_| MosesError::SafetyViolation(
+        "The update is not signed by the Moses release key; it was discarded".to_string()
+    ))
+}
+
+/// Copy a feed or build from an https URL or a local path
+fn fetch(location: &str, destination: &Path) -> Result<(), MosesError> {
+    if location.starts_with("https://") {
+        crate::tools::download(location, destination)
+    } else if location.starts_with("http://") {
+        Err(MosesError::InvalidInput(format!("{} must be fetched over https", location)))
+    } else {
+        std::fs::copy(location, destination)?;
+        Ok(())
+    }
+}
+
+fn read_staged(dir: &Path) -> Option<StagedUpdate> {
+    let text = std::fs::read_to_string(dir.join(STAGED_FILE)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Swap `exe`, the running build `current` of `component`, for the build staged for it in
+/// `dir` once its release signature checks out. The old executable is kept beside it as
+/// `.old` until the next update. Returns the update applied, if there was one.
+pub fn apply_staged(
+    dir: &Path,
+    exe: &Path,
+    key: Option<&VerifyingKey>,
+    component: Component,
+    current: &Version,
+) -> Result<Option<StagedUpdate>, MosesError> {
+    let Some(staged) = read_staged(dir) else {
+        return Ok(None);
+    };
+    if staged.target != exe {
+        return Ok(None);
+    }
+    let discard = || {
+        let _ = std::fs::remove_file(&staged.path);
+        let _ = std::fs::remove_file(dir.join(STAGED_FILE));
+    };
+    // The file and its record sat in a user-writable directory since they were verified,
+    // so the signature is checked again against the file as it is now
+    let Some(key) = key else {
+        discard();
+        return Err(MosesError::NotSupported(
+            "This build has no release signing key, so the staged update cannot be verified".to_string()
+        ));
+    };
+    let version = match parse_version(&staged.version) {
+        Ok(version) if version > *current => version,
+        _ => {
+            discard();
+            return Err(MosesError::SafetyViolation(format!(
+                "The staged Moses {} is not newer than the running {}; it was discarded", staged.version, current
+            )));
+        }
+    };
+    let verified = staged.path.is_file() && sha256_file(&staged.path).is_ok_and(|sha256| {
+        let message = signed_message(component, &current_platform(), &version, &sha256);
+        verify_signature(key, &message, &staged.signature).is_ok()
+    });
+    if !verified {
+        discard();
+        return Err(MosesError::SafetyViolation(format!(
+            "The staged Moses {} changed after it was verified; it was discarded", staged.version
+        )));
+    }
+
+    // Copy next to the executable first so the swap is two renames on one volume
+    let incoming = exe.with_extension("new");
+    let previous = exe.with_extension("old");
+    std::fs::copy(&staged.path, &incoming)?;
+    let _ = std::fs::remove_file(&previous);
+    if let Err(e) = std::fs::rename(exe, &previous) {
+        let _ = std::fs::remove_file(&incoming);
+        return Err(e.into());
+    }
+    if let Err(e) = std::fs::rename(&incoming, exe) {
+        let _ = std::fs::rename(&previous, exe);
+        return Err(e.into());
+    }
+    discard();
+    log::info!("Updated {} to Moses {}", exe.display(), staged.version);
+    Ok(Some(staged))
+}
+
+/// Apply a build staged for the running executable, build `current_version` of
+/// `component`; problems are logged and the current build keeps running
+pub fn apply_staged_on_start(component: Component, current_version: &str) -> Option<StagedUpdate> {
+    let exe = std::env::current_exe().ok()?;
+    let current = Version::parse(current_version).ok()?;
+    let key = match RELEASE_PUBLIC_KEY.map(parse_public_key).transpose() {
+        Ok(key) => key,
+        Err(e) => {
+            log::warn!("Could not apply the staged update: {}", e);
+            return None;
+        }
+    };
+    match apply_staged(&Updater::default_dir(), &exe, key.as_ref(), component, &current) {
+        Ok(applied) => applied,
+        Err(e) => {
+            log::warn!("Could not apply the staged update: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn asset(component: Component, platform: &str) -> ReleaseAsset {
+        ReleaseAsset {
+            component,
+            platform: platform.to_string(),
+            url: String::new(),
+            sha256: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    fn release(version: &str, channel: ReleaseChannel, assets: Vec<ReleaseAsset>) -> Release {
+        Release { version: version.to_string(), channel, notes: String::new(), assets }
+    }
+
+    #[test]
+    fn test_latest_release_follows_channel() {
+        let here = "linux-x86_64";
+        let feed = ReleaseFeed {
+            releases: vec![
+                release("0.1.0", ReleaseChannel::Stable, vec![asset(Component::Cli, here)]),
+                release("0.2.0", ReleaseChannel::Stable, vec![asset(Component::Cli, here), asset(Component::Gui, here)]),
+                release("0.3.0-beta.1", ReleaseChannel::Beta, vec![asset(Component::Cli, here)]),
+                release("0.4.0", ReleaseChannel::Stable, vec![asset(Component::Cli, "windows-x86_64")]),
+                release("not-a-version", ReleaseChannel::Stable, vec![asset(Component::Cli, here)]),
+            ],
+        };
+        let current = Version::parse("0.1.0").unwrap();
+
+        let stable = feed.latest(ReleaseChannel::Stable, Component::Cli, here, &current).unwrap();
+        assert_eq!(stable.version, "0.2.0");
+        let beta = feed.latest(ReleaseChannel::Beta, Component::Cli, here, &current).unwrap();
+        assert_eq!((beta.version.as_str(), beta.channel), ("0.3.0-beta.1", ReleaseChannel::Beta));
+        assert_eq!(feed.latest(ReleaseChannel::Beta, Component::Gui, here, &current).unwrap().version, "0.2.0");
+        assert!(feed.latest(ReleaseChannel::Beta, Component::Cli, here, &Version::parse("0.3.0").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_stage_verifies_and_applies_on_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let signing = SigningKey::from_bytes(&[7u8; 32]);
+        let build = b"moses 0.2.0".to_vec();
+        let published = dir.path().join("published");
+        std::fs::write(&published, &build).unwrap();
+
+        let version = Version::parse("0.2.0").unwrap();
+        let sign = |key: &SigningKey, version: &Version| {
+            key.sign(&signed_message(Component::Cli, &current_platform(), version, &sha256_file(&published).unwrap())).to_bytes()
+        };
+        let update = |signature: &[u8]| AvailableUpdate {
+            version: "0.2.0".to_string(),
+            channel: ReleaseChannel::Stable,
+            notes: String::new(),
+            asset: ReleaseAsset {
+                component: Component::Cli,
+                platform: current_platform(),
+                url: published.to_string_lossy().into_owned(),
+                sha256: sha256_file(&published).unwrap(),
+                signature: hex::encode(signature),
+            },
+        };
+        let feed = ReleaseFeed { releases: vec![release("0.2.0", ReleaseChannel::Stable, vec![update(&[]).asset])] };
+        let feed_path = dir.path().join("feed.json");
+        std::fs::write(&feed_path, serde_json::to_string(&feed).unwrap()).unwrap();
+
+        let update_dir = dir.path().join("update");
+        let updater = Updater::new(
+            update_dir.clone(),
+            Some(feed_path.to_string_lossy().into_owned()),
+            ReleaseChannel::Stable,
+            Some(signing.verifying_key()),
+            Component::Cli,
+            Version::parse("0.1.0").unwrap(),
+        );
+        assert_eq!(updater.check().unwrap().available.unwrap().version, "0.2.0");
+
+        let exe = dir.path().join("moses");
+        std::fs::write(&exe, b"moses 0.1.0").unwrap();
+        let verifying = signing.verifying_key();
+        let key = Some(&verifying);
+        let current = Version::parse("0.1.0").unwrap();
+
+        // A build signed as 0.2.0 cannot be offered as 0.3.0
+        let mut relabelled = update(&sign(&signing, &version));
+        relabelled.version = "0.3.0".to_string();
+        assert!(matches!(updater.stage(&relabelled, &exe), Err(MosesError::SafetyViolation(_))));
+
+        // Signed by someone else: nothing is staged
+        let forged = sign(&SigningKey::from_bytes(&[8u8; 32]), &version);
+        let err = updater.stage(&update(&forged), &exe).unwrap_err();
+        assert!(matches!(err, MosesError::SafetyViolation(_)));
+        assert!(updater.staged().is_none());
+
+        let staged = updater.stage(&update(&sign(&signing, &version)), &exe).unwrap();
+        assert_eq!(updater.staged().unwrap(), staged);
+
+        // Another executable's start leaves it alone; the target's start swaps it in
+        assert!(apply_staged(&update_dir, &dir.path().join("other"), key, Component::Cli, &current).unwrap().is_none());
+        assert_eq!(apply_staged(&update_dir, &exe, key, Component::Cli, &current).unwrap().unwrap().version, "0.2.0");
+        assert_eq!(std::fs::read(&exe).unwrap(), build);
+        assert_eq!(std::fs::read(exe.with_extension("old")).unwrap(), b"moses 0.1.0");
+        assert!(updater.staged().is_none() && !staged.path.exists());
+
+        // A staged file swapped after verification is refused, even with its record updated
+        let staged = updater.stage(&update(&sign(&signing, &version)), &exe).unwrap();
+        std::fs::write(&staged.path, b"tampered").unwrap();
+        let mut record = staged.clone();
+        record.sha256 = sha256_file(&staged.path).unwrap();
+        std::fs::write(update_dir.join(STAGED_FILE), serde_json::to_string(&record).unwrap()).unwrap();
+        assert!(matches!(apply_staged(&update_dir, &exe, key, Component::Cli, &current), Err(MosesError::SafetyViolation(_))));
+        assert_eq!(std::fs::read(&exe).unwrap(), build);
+
+        // So is a genuine build that is not newer than the running one
+        let staged = updater.stage(&update(&sign(&signing, &version)), &exe).unwrap();
+        assert!(matches!(
+            apply_staged(&update_dir, &exe, key, Component::Cli, &version),
+            Err(MosesError::SafetyViolation(_))
+        ));
+        assert!(!staged.path.exists());
+        let newer = Updater::new(update_dir.clone(), None, ReleaseChannel::Stable, key.copied(), Component::Cli, version.clone());
+        assert!(matches!(newer.stage(&update(&sign(&signing, &version)), &exe), Err(MosesError::SafetyViolation(_))));
+    }
+
+    #[test]
+    fn test_no_feed_or_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let updater = Updater::new(dir.path().to_path_buf(), None, ReleaseChannel::Stable, None, Component::Gui, Version::new(0, 1, 0));
+        assert!(matches!(updater.check(), Err(MosesError::NotSupported(_))));
+        let plain = Updater::new(dir.path().to_path_buf(), Some("http://example.invalid/feed.json".to_string()),
+            ReleaseChannel::Stable, None, Component::Gui, Version::new(0, 1, 0));
+        assert!(matches!(plain.check(), Err(MosesError::InvalidInput(_))));
+        assert!(parse_public_key("abcd").is_err());
+        assert!(parse_public_key(&hex::encode(SigningKey::from_bytes(&[1u8; 32]).verifying_key().to_bytes())).is_ok());
+    }
+}
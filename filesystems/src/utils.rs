@@ -4,53 +4,11 @@ use moses_core::{Device, MosesError};
 use std::fs::File;
 use std::io::{Read, Write, Seek, SeekFrom};
 
-/// Get the best path to access a device on the current platform.
-/// On Windows, prefers drive letters (which don't require admin rights) over physical drive paths.
-/// On other platforms, returns the appropriate device path.
+/// The path to open a device at on the current platform, derived from its id.
+/// See [`moses_core::device_path`]; mount points are only tried by
+/// [`open_device_with_fallback`] when this path cannot be opened.
 pub fn get_device_path(device: &Device) -> String {
-    #[cfg(target_os = "windows")]
-    {
-        // On Windows, prefer drive letter access (doesn't require admin rights)
-        if !device.mount_points.is_empty() {
-            let mount = &device.mount_points[0];
-            let mount_str = mount.to_string_lossy();
-            
-            // Check if it's a drive letter like "E:" or "E:\"
-            if mount_str.len() >= 2 && mount_str.chars().nth(1) == Some(':') {
-                // Windows requires exactly "\\.\X:" format (no trailing backslash)
-                let clean_mount = mount_str.trim_end_matches('\\').trim_end_matches('/');
-                
-                // Ensure it's just the drive letter and colon
-                if clean_mount.len() == 2 {
-                    log::info!("Using drive letter access: \\\\.\\{}", clean_mount);
-                    return format!(r"\\.\{}", clean_mount);
-                } else if clean_mount.len() == 3 && clean_mount.ends_with(':') {
-                    // Handle "E:\\" -> "E:"
-                    let drive_letter = &clean_mount[0..2];
-                    log::info!("Using drive letter access: \\\\.\\{}", drive_letter);
-                    return format!(r"\\.\{}", drive_letter);
-                }
-            }
-        }
-        
-        // Fall back to physical drive path (requires admin rights)
-        log::info!("Using physical drive access: {}", device.id);
-        if device.id.starts_with(r"\\.\") {
-            device.id.clone()
-        } else {
-            format!(r"\\.\{}", device.id)
-        }
-    }
-    
-    #[cfg(not(target_os = "windows"))]
-    {
-        // On Unix-like systems, use /dev/ paths
-        if device.id.starts_with('/') {
-            device.id.clone()
-        } else {
-            format!("/dev/{}", device.id)
-        }
-    }
+    moses_core::device_path(device)
 }
 
 // Common filesystem constants
@@ -74,16 +32,11 @@ pub fn open_device_read(device: &Device) -> Result<File, MosesError> {
 /// Open a device for writing (formatting)
 /// For formatting, we always use the physical drive path, not drive letters
 pub fn open_device_write(device: &Device) -> Result<File, MosesError> {
-    // For formatting, always use physical drive path (device.id), not drive letters
-    // This is because after writing MBR, drive letters become invalid
-    let path = if device.id.starts_with(r"\\.\") {
-        device.id.clone()
-    } else {
-        format!(r"\\.\{}", device.id)
-    };
+    // Always the disk itself, never a drive letter: those become invalid once the MBR changes
+    let path = get_device_path(device);
     
     #[cfg(target_os = "windows")]
-    {
+    let mut file = {
         log::info!("Opening Windows device for writing: {}", path);
         
         // Just use regular file operations without special flags
@@ -96,17 +49,45 @@ pub fn open_device_write(device: &Device) -> Result<File, MosesError> {
                 log::error!("Failed to open device {} for writing: {} (OS error: {:?})", 
                           path, e, e.raw_os_error());
                 MosesError::Other(format!("Failed to open device {} for writing: {}", path, e))
-            })
-    }
+            })?
+    };
     
     #[cfg(not(target_os = "windows"))]
-    {
-        std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&path)
-            .map_err(|e| MosesError::Other(format!("Failed to open device {} for writing: {}", path, e)))
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|e| MosesError::Other(format!("Failed to open device {} for writing: {}", path, e)))?;
+    
+    ensure_same_size(device, &path, &mut file)?;
+    Ok(file)
+}
+
+/// Fail if the device now open at `path` is not the size it was enumerated with, which
+/// means a different drive was plugged in at the same path since it was selected.
+/// Disk images are not checked; they can legitimately grow.
+fn ensure_same_size(device: &Device, path: &str, file: &mut File) -> Result<(), MosesError> {
+    #[cfg(unix)]
+    let is_device = {
+        use std::os::unix::fs::FileTypeExt;
+        file.metadata().map(|m| m.file_type().is_block_device() || m.file_type().is_char_device()).unwrap_or(false)
+    };
+    #[cfg(not(unix))]
+    let is_device = path.starts_with(r"\\.\");
+    if !is_device || device.size == 0 {
+        return Ok(());
     }
+    
+    // Not every platform reports a size this way (Windows physical drives need an ioctl)
+    let size = file.seek(SeekFrom::End(0)).unwrap_or(0);
+    file.seek(SeekFrom::Start(0))?;
+    if size != 0 && size != device.size {
+        return Err(MosesError::DeviceChanged(format!(
+            "{} was {} bytes when selected but is now {} bytes. Select the drive again before continuing",
+            path, device.size, size
+        )));
+    }
+    Ok(())
 }
 
 /// Read a sector (512 bytes) from a specific offset
@@ -142,6 +123,28 @@ pub fn write_sector(file: &mut File, sector_number: u64, data: &[u8]) -> Result<
     Ok(())
 }
 
+/// Write barrier: returns once everything written through `file` is on stable media,
+/// including the drive's own write cache. `File::flush` does nothing for a device.
+///
+/// `sync_all` is `FlushFileBuffers` on Windows, `fsync` on Linux (which sends a cache
+/// flush to a block device) and `fcntl(F_FULLFSYNC)` on macOS, where a plain `fsync`
+/// leaves the data in the drive cache.
+pub fn flush_device(file: &File) -> Result<(), MosesError> {
+    file.sync_all()
+        .map_err(|e| MosesError::Other(format!("Failed to flush device to stable storage: {}", e)))
+}
+
+/// Write `data` at `offset` and wait until it is on stable media. Superblocks and boot
+/// sectors go through this, the portable equivalent of a FUA write, so that a volume is
+/// never reported ready while its first sectors are only in a cache.
+pub fn write_through(file: &mut File, offset: u64, data: &[u8]) -> Result<(), MosesError> {
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| MosesError::Other(format!("Failed to seek to offset {}: {}", offset, e)))?;
+    file.write_all(data)
+        .map_err(|e| MosesError::Other(format!("Failed to write at offset {}: {}", offset, e)))?;
+    flush_device(file)
+}
+
 /// Read a block of arbitrary size from a specific offset
 pub fn read_block(file: &mut File, offset: u64, size: usize) -> Result<Vec<u8>, MosesError> {
     file.seek(SeekFrom::Start(offset))
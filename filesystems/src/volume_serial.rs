@@ -0,0 +1,203 @@
+// Volume serial preservation - keep the old volume serial number across a reformat
+// Dashcams, consoles and some media players key their settings (or a pairing) to the
+// volume serial, so a fresh serial makes them treat the card as a new one. With the
+// preserve_serial option the FAT, exFAT and NTFS formatters read the serial of the volume
+// that is there now, before anything is written, and give it to the new filesystem.
+// FAT and exFAT serials are 32 bits and NTFS serials 64; Windows shows the low 32 bits
+// of an NTFS serial, so that half is what carries over between families. exFAT also has a
+// volume GUID in its root directory, which is kept when the old volume is exFAT too.
+use std::io::{Read, Seek};
+use moses_core::{Device, FormatOptions, MosesError};
+use serde::{Serialize, Deserialize};
+use crate::diagnostics::{read_at, read_partition_table};
+use crate::families::fat::exfat::structures::EXFAT_ENTRY_VOLUME_GUID;
+
+/// Entry type that ends an exFAT directory
+const EXFAT_END_OF_DIRECTORY: u8 = 0x00;
+
+/// Key in `FormatOptions::additional_options`: "true" keeps the current volume serial
+pub const PRESERVE_SERIAL_OPTION: &str = "preserve_serial";
+
+/// Filesystems whose formatters honour [`PRESERVE_SERIAL_OPTION`]
+pub const PRESERVING_FILESYSTEMS: &[&str] = &["fat16", "fat32", "exfat", "ntfs"];
+
+/// A volume serial read from an existing filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeSerial {
+    pub value: u64,
+    /// Read from NTFS, so all 64 bits are meaningful
+    pub wide: bool,
+    /// Volume GUID directory entry of an exFAT volume
+    #[serde(default)]
+    pub guid: Option<[u8; 16]>,
+}
+
+impl VolumeSerial {
+    /// The serial for FAT and exFAT
+    pub fn short(&self) -> u32 {
+        self.value as u32
+    }
+
+    /// The serial for NTFS; a 32-bit serial gets a random high half
+    pub fn long(&self) -> u64 {
+        if self.wide {
+            self.value
+        } else {
+            ((rand::random::<u32>() as u64) << 32) | self.value
+        }
+    }
+
+    /// As Windows shows it, e.g. `1A2B-3C4D`
+    pub fn display(&self) -> String {
+        let short = self.short();
+        format!("{:04X}-{:04X}", short >> 16, short & 0xFFFF)
+    }
+}
+
+/// Whether the options ask for the serial to be kept
+pub fn preserve_requested(options: &FormatOptions) -> Result<bool, MosesError> {
+    match options.additional_options.get(PRESERVE_SERIAL_OPTION) {
+        None => Ok(false),
+        Some(value) => value.parse::<bool>().map_err(|_| {
+            MosesError::InvalidInput(format!("{} must be true or false, not '{}'", PRESERVE_SERIAL_OPTION, value))
+        }),
+    }
+}
+
+/// The serial a formatter should write: the current one when the options ask to keep it
+///
+/// Call before the first write to the device. Fails when keeping the serial was asked for
+/// but there is none to keep, rather than quietly writing a new one.
+pub fn serial_for_format(device: &Device, options: &FormatOptions) -> Result<Option<VolumeSerial>, MosesError> {
+    if !preserve_requested(options)? {
+        return Ok(None);
+    }
+    let serial = read_volume_serial(device)?.ok_or_else(|| MosesError::InvalidInput(format!(
+        "{} has no FAT, exFAT or NTFS volume whose serial could be kept", device.name
+    )))?;
+    log::info!("Keeping volume serial {} of {}", serial.display(), device.name);
+    Ok(Some(serial))
+}
+
+/// Serial of the volume on the device, at its start or in its first partition that has one
+pub fn read_volume_serial(device: &Device) -> Result<Option<VolumeSerial>, MosesError> {
+    let file = crate::utils::open_device_with_fallback(device)?;
+    Ok(read_volume_serial_from(&mut crate::device_reader::AlignedDeviceReader::new(file)))
+}
+
+/// As [`read_volume_serial`] from any reader over a whole disk
+pub fn read_volume_serial_from<R: Read + Seek>(reader: &mut R) -> Option<VolumeSerial> {
+    let (table, layout) = read_partition_table(reader);
+    let offsets: Vec<u64> = match table {
+        Some(_) => layout.iter().map(|&(start, _)| start).collect(),
+        None => vec![0],
+    };
+    offsets.into_iter().find_map(|offset| {
+        let boot = read_at(reader, offset, 512)?;
+        let mut serial = serial_in_boot_sector(&boot)?;
+        if &boot[3..11] == b"EXFAT   " {
+            serial.guid = exfat_volume_guid(reader, offset, &boot);
+        }
+        Some(serial)
+    })
+}
+
+/// GUID in the Volume GUID entry of an exFAT root directory, looked for in its first cluster
+fn exfat_volume_guid<R: Read + Seek>(reader: &mut R, offset: u64, boot: &[u8]) -> Option<[u8; 16]> {
+    let (sector_shift, cluster_shift) = (boot[108] as u32, boot[109] as u32);
+    if !(9..=12).contains(&sector_shift) || sector_shift + cluster_shift > 25 {
+        return None;
+    }
+    let heap = u32::from_le_bytes(boot[88..92].try_into().unwrap()) as u64;
+    let root_cluster = u32::from_le_bytes(boot[96..100].try_into().unwrap()) as u64;
+    let root = offset + ((heap << sector_shift) + (root_cluster.checked_sub(2)? << (sector_shift + cluster_shift)));
+    let cluster = read_at(reader, root, 1 << (sector_shift + cluster_shift))?;
+    cluster.as_chunks::<32>().0.iter()
+        .take_while(|entry| entry[0] != EXFAT_END_OF_DIRECTORY)
+        .find(|entry| entry[0] == EXFAT_ENTRY_VOLUME_GUID)
+        .map(|entry| entry[6..22].try_into().unwrap())
+}
+
+/// Serial field of a FAT, exFAT or NTFS boot sector
+fn serial_in_boot_sector(boot: &[u8]) -> Option<VolumeSerial> {
+    let u32_at = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap()) as u64;
+    if boot.len() < 512 || boot[510] != 0x55 || boot[511] != 0xAA {
+        return None;
+    }
+    let value = match &boot[3..11] {
+        b"NTFS    " => return Some(VolumeSerial { value: u64::from_le_bytes(boot[72..80].try_into().unwrap()), wide: true, guid: None }),
+        b"EXFAT   " => u32_at(100),
+        // FAT32 extended BPB, with the 0x29 signature that says the serial is present
+        _ if &boot[82..87] == b"FAT32" && boot[66] == 0x29 => u32_at(67),
+        // FAT12/16 extended BPB; 0x28 has the serial but no label
+        _ if &boot[54..57] == b"FAT" && matches!(boot[38], 0x28 | 0x29) => u32_at(39),
+        _ => return None,
+    };
+    Some(VolumeSerial { value, wide: false, guid: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::registration::builtin_registry;
+    use crate::test_helpers::create_test_device;
+
+    fn options(filesystem: &str, preserve: bool, partition_table: bool) -> FormatOptions {
+        FormatOptions {
+            filesystem_type: filesystem.to_string(),
+            quick_format: true,
+            additional_options: HashMap::from([
+                (PRESERVE_SERIAL_OPTION.to_string(), preserve.to_string()),
+                ("create_partition_table".to_string(), partition_table.to_string()),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serial_kept_across_filesystems() {
+        let image = tempfile::NamedTempFile::new().unwrap();
+        image.as_file().set_len(128 * 1024 * 1024).unwrap();
+        let device = create_test_device(image.path().to_str().unwrap(), 128 * 1024 * 1024);
+        let registry = builtin_registry();
+
+        assert!(serial_for_format(&device, &options("fat32", true, false)).is_err(), "blank device has no serial");
+        registry.get_formatter("fat32").unwrap().format(&device, &options("fat32", false, true)).await.unwrap();
+        let original = read_volume_serial(&device).unwrap().unwrap();
+
+        let exfat = registry.get_formatter("exfat").unwrap();
+        exfat.format(&device, &options("exfat", true, false)).await.unwrap();
+        let guid = read_volume_serial(&device).unwrap().unwrap().guid;
+        assert!(guid.is_some());
+        exfat.format(&device, &options("exfat", true, false)).await.unwrap();
+        assert_eq!(read_volume_serial(&device).unwrap().unwrap().guid, guid, "exFAT keeps its volume GUID");
+
+        for filesystem in ["fat16", "fat32"] {
+            let formatter = registry.get_formatter(filesystem).unwrap();
+            formatter.format(&device, &options(filesystem, true, true)).await.unwrap();
+            let serial = read_volume_serial(&device).unwrap().unwrap();
+            assert_eq!(serial.short(), original.short(), "{} did not keep the serial", filesystem);
+        }
+
+        moses_core::FilesystemFormatter::format(&crate::NtfsFormatter, &device, &options("ntfs", true, false)).await.unwrap();
+        let ntfs = read_volume_serial(&device).unwrap().unwrap();
+        assert!(ntfs.wide);
+        assert_eq!(ntfs.short(), original.short());
+        moses_core::FilesystemFormatter::format(&crate::NtfsFormatter, &device, &options("ntfs", true, false)).await.unwrap();
+        assert_eq!(read_volume_serial(&device).unwrap().unwrap(), ntfs, "NTFS keeps all 64 bits");
+
+        registry.get_formatter("fat32").unwrap().format(&device, &options("fat32", false, false)).await.unwrap();
+        assert_ne!(read_volume_serial(&device).unwrap().unwrap().short(), original.short());
+    }
+
+    #[test]
+    fn test_option_parsing() {
+        assert!(!preserve_requested(&FormatOptions::default()).unwrap());
+        assert!(preserve_requested(&options("fat32", true, false)).unwrap());
+        let mut bad = options("fat32", true, false);
+        bad.additional_options.insert(PRESERVE_SERIAL_OPTION.to_string(), "yes".to_string());
+        assert!(preserve_requested(&bad).is_err());
+        assert_eq!(VolumeSerial { value: 0x1A2B3C4D, wide: false, guid: None }.display(), "1A2B-3C4D");
+    }
+}
@@ -0,0 +1,295 @@
+// Tree walker - one traversal of a volume for every feature that visits all its files
+// Hashing, export, copies, tree statistics and the contents and trash summaries all walk
+// every entry below a folder, and share this walk so they get the same guarantees: entries
+// come in name order, so two walks of the same tree agree; a directory reached a second
+// time (a hard-linked folder, a followed link back up the tree, a corrupted entry pointing
+// at an ancestor) is visited but not entered again; and depth and entry limits end the walk
+// of a damaged or very large tree instead of letting it run until memory runs out.
+// Directories are recognised through FilesystemOps::directory_id; where a filesystem has
+// no such id, the depth limit is what stops a loop.
+use crate::ops::{FileAttributes, FilesystemOps};
+use moses_core::MosesError;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Levels below the root a walk enters by default. Real trees stay far below this; a loop
+/// no id catches reaches it quickly.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Order in which directories are entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkOrder {
+    /// A directory's entries, then everything below its first subdirectory, and so on;
+    /// parents always come before their children
+    #[default]
+    DepthFirst,
+    /// Level by level, so a walk cut short by its entry limit has seen the top of the tree
+    BreadthFirst,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkOptions {
+    pub order: WalkOrder,
+    /// Directories at this depth are visited but not entered; the root's entries are at depth 1
+    pub max_depth: usize,
+    /// The walk ends after visiting this many entries
+    pub max_entries: Option<u64>,
+    /// A directory that cannot be listed is recorded and skipped instead of ending the walk
+    pub skip_unreadable: bool,
+    /// Take each entry's attributes from `stat` rather than the listing, for filesystems
+    /// whose listings leave fields such as times out
+    pub stat_entries: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            order: WalkOrder::DepthFirst,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_entries: None,
+            skip_unreadable: false,
+            stat_entries: false,
+        }
+    }
+}
+
+/// One entry as the visitor sees it
+#[derive(Debug, Clone, Copy)]
+pub struct WalkEntry<'a> {
+    /// Path on the filesystem
+    pub path: &'a Path,
+    /// `/`-separated name relative to the root of the walk
+    pub name: &'a str,
+    /// 1 for the root's entries
+    pub depth: usize,
+    pub attributes: &'a FileAttributes,
+}
+
+impl WalkEntry<'_> {
+    /// A directory the walk would enter; links are never followed by the walk itself
+    pub fn is_directory(&self) -> bool {
+        self.attributes.is_directory && !self.attributes.is_symlink
+    }
+
+    /// The first component of `name`: the root entry this one is under
+    pub fn top(&self) -> &str {
+        self.name.split('/').next().unwrap_or(self.name)
+    }
+}
+
+/// What the visitor wants done after an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+    Continue,
+    /// Do not enter this directory
+    SkipChildren,
+    /// End the walk here
+    Stop,
+}
+
+/// How a walk went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkSummary {
+    /// Entries visited
+    pub entries: u64,
+    /// The entry limit or the visitor ended the walk before it was done
+    pub stopped: bool,
+    /// Directories not entered because the walk had entered them already
+    pub cycles: Vec<PathBuf>,
+    /// Directories not entered because they were at the depth limit
+    pub too_deep: Vec<PathBuf>,
+    /// Directories that could not be listed, with why
+    pub unreadable: Vec<(PathBuf, String)>,
+}
+
+impl WalkSummary {
+    /// Every entry below the root was visited exactly once
+    pub fn is_complete(&self) -> bool {
+        !self.stopped && self.cycles.is_empty() && self.too_deep.is_empty() && self.unreadable.is_empty()
+    }
+
+    /// What kept the walk from being complete, for logs and reports
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.stopped {
+            problems.push(format!("stopped after {} entries", self.entries));
+        }
+        for path in &self.cycles {
+            problems.push(format!("{}: already visited (hard link or loop), not entered again", path.display()));
+        }
+        for path in &self.too_deep {
+            problems.push(format!("{}: deeper than the depth limit, not entered", path.display()));
+        }
+        for (path, reason) in &self.unreadable {
+            problems.push(format!("{}: could not be listed: {}", path.display(), reason));
+        }
+        problems
+    }
+}
+
+/// Visit every entry below `root` (not `root` itself), each directory's entries in name
+/// order. `.` and `..` are left out.
+pub fn walk_tree<F, V>(fs: &mut F, root: &Path, options: &WalkOptions, mut visit: V) -> Result<WalkSummary, MosesError>
+where
+    F: FilesystemOps + ?Sized,
+    V: FnMut(&mut F, &WalkEntry<'_>) -> Result<WalkControl, MosesError>,
+{
+    let mut summary = WalkSummary::default();
+    let mut entered = HashSet::new();
+    if let Some(id) = fs.directory_id(root) {
+        entered.insert(id);
+    }
+    let mut pending = VecDeque::from([(root.to_path_buf(), String::new(), 0)]);
+
+    while let Some((dir, prefix, depth)) = pending.pop_front() {
+        let mut entries = match fs.readdir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if options.skip_unreadable => {
+                log::debug!("Skipping {} in a walk: {}", dir.display(), e);
+                summary.unreadable.push((dir, e.to_string()));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        entries.retain(|entry| entry.name != "." && entry.name != "..");
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut children = Vec::new();
+        for entry in entries {
+            if options.max_entries.is_some_and(|max| summary.entries >= max) {
+                summary.stopped = true;
+                return Ok(summary);
+            }
+            summary.entries += 1;
+            let path = dir.join(&entry.name);
+            let name = format!("{}{}", prefix, entry.name);
+            let attributes = if options.stat_entries {
+                fs.stat(&path).unwrap_or(entry.attributes)
+            } else {
+                entry.attributes
+            };
+            let visited = WalkEntry { path: &path, name: &name, depth: depth + 1, attributes: &attributes };
+            let enter = visited.is_directory();
+            match visit(fs, &visited)? {
+                WalkControl::Stop => {
+                    summary.stopped = true;
+                    return Ok(summary);
+                }
+                WalkControl::SkipChildren => continue,
+                WalkControl::Continue if !enter => continue,
+                WalkControl::Continue => {}
+            }
+            if depth + 1 >= options.max_depth {
+                summary.too_deep.push(path);
+                continue;
+            }
+            if let Some(id) = fs.directory_id(&path) {
+                if !entered.insert(id) {
+                    log::debug!("Not entering {} again", path.display());
+                    summary.cycles.push(path);
+                    continue;
+                }
+            }
+            children.push((path, format!("{}/", name), depth + 1));
+        }
+
+        match options.order {
+            // In front and reversed, so the first subdirectory is entered next
+            WalkOrder::DepthFirst => children.into_iter().rev().for_each(|child| pending.push_front(child)),
+            WalkOrder::BreadthFirst => pending.extend(children),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{DirectoryEntry, FilesystemInfo, HostFolderOps};
+    use moses_core::Device;
+    use std::fs;
+
+    /// A folder whose /b/inner/up leads back to /b, as a corrupted directory entry would
+    struct Looped(HostFolderOps);
+
+    impl Looped {
+        fn target(path: &Path) -> PathBuf {
+            let mut path = path.to_path_buf();
+            while let Ok(rest) = path.strip_prefix("/b/inner/up") {
+                path = Path::new("/b").join(rest);
+            }
+            path
+        }
+    }
+
+    impl FilesystemOps for Looped {
+        fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+            self.0.init(device)
+        }
+
+        fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+            self.0.statfs()
+        }
+
+        fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+            self.0.stat(&Self::target(path))
+        }
+
+        fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+            self.0.readdir(&Self::target(path))
+        }
+
+        fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+            self.0.read(&Self::target(path), offset, size)
+        }
+
+        fn directory_id(&mut self, path: &Path) -> Option<u64> {
+            self.0.directory_id(&Self::target(path))
+        }
+
+        fn filesystem_type(&self) -> &str {
+            "test"
+        }
+    }
+
+    fn names(fs: &mut dyn FilesystemOps, options: &WalkOptions) -> (Vec<String>, WalkSummary) {
+        let mut names = Vec::new();
+        let summary = walk_tree(fs, Path::new("/"), options, |_, entry| {
+            names.push(entry.name.to_string());
+            Ok(WalkControl::Continue)
+        }).unwrap();
+        (names, summary)
+    }
+
+    #[test]
+    fn test_walk_order_limits_and_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("b/inner")).unwrap();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::write(dir.path().join("b/inner/deep.txt"), b"x").unwrap();
+        fs::write(dir.path().join("b/file.txt"), b"x").unwrap();
+        fs::write(dir.path().join("top.txt"), b"x").unwrap();
+        let mut ops = HostFolderOps::new(dir.path().to_path_buf()).unwrap();
+
+        let (depth_first, summary) = names(&mut ops, &WalkOptions::default());
+        assert_eq!(depth_first, ["a", "b", "top.txt", "b/file.txt", "b/inner", "b/inner/deep.txt"]);
+        assert!(summary.is_complete());
+        let breadth = WalkOptions { order: WalkOrder::BreadthFirst, max_entries: Some(4), ..Default::default() };
+        let (breadth_first, summary) = names(&mut ops, &breadth);
+        assert_eq!(breadth_first, ["a", "b", "top.txt", "b/file.txt"]);
+        assert!(summary.stopped);
+        let shallow = WalkOptions { max_depth: 2, ..Default::default() };
+        let (_, summary) = names(&mut ops, &shallow);
+        assert_eq!(summary.too_deep, [PathBuf::from("/b/inner")]);
+
+        // A directory leading back to its ancestor is visited once and not entered
+        fs::create_dir(dir.path().join("b/inner/up")).unwrap();
+        let mut looped = Looped(HostFolderOps::new(dir.path().to_path_buf()).unwrap());
+        if looped.directory_id(Path::new("/")).is_some() {
+            let (names, summary) = names(&mut looped, &WalkOptions::default());
+            assert!(names.contains(&"b/inner/up".to_string()));
+            assert!(!names.iter().any(|name| name.starts_with("b/inner/up/")));
+            assert_eq!(summary.cycles, [PathBuf::from("/b/inner/up")]);
+        }
+    }
+}
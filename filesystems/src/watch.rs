@@ -0,0 +1,310 @@
+// Directory watches - telling a file browser that the folder it shows has changed
+// Moses reads most volumes itself, so the OS change notifications (inotify,
+// ReadDirectoryChangesW) never fire for them. Writes made through Moses ops are announced
+// instead: NotifyingOps bumps the device's change generation in the filesystem cache,
+// which every Moses process shares, and a watch rescans as soon as it sees the bump.
+// Changes made any other way are caught by rescanning on an interval and diffing the
+// listing against the last one.
+use crate::ops::{AttributeChanges, DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps, TreeStats};
+use moses_core::{Device, FilesystemCache, MosesError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often a watch rescans when nothing announced a change
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Writes to file contents are announced at most this often; creates, deletes and renames
+/// always are
+pub const WRITE_NOTICE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Opens the watched volume afresh for each rescan
+pub type OpenOps = Box<dyn Fn() -> Result<Box<dyn FilesystemOps>, MosesError> + Send>;
+
+/// What a directory listing shows of one entry; a change in any of it is a modification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EntryState {
+    size: u64,
+    modified: Option<u64>,
+    is_directory: bool,
+}
+
+/// The entries of one directory at one moment, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectorySnapshot {
+    entries: BTreeMap<String, EntryState>,
+}
+
+impl DirectorySnapshot {
+    pub fn capture(ops: &mut dyn FilesystemOps, path: &Path) -> Result<Self, MosesError> {
+        Ok(Self::from_entries(&ops.readdir(path)?))
+    }
+
+    pub fn from_entries(entries: &[DirectoryEntry]) -> Self {
+        let entries = entries.iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| (entry.name.clone(), EntryState {
+                size: if entry.attributes.is_directory { 0 } else { entry.attributes.size },
+                modified: entry.attributes.modified,
+                is_directory: entry.attributes.is_directory,
+            }))
+            .collect();
+        Self { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// What changed between this listing and `newer`, as seen from `path`
+    pub fn diff(&self, newer: &DirectorySnapshot, path: &str) -> DirectoryChange {
+        let mut change = DirectoryChange { path: path.to_string(), ..Default::default() };
+        for (name, state) in &newer.entries {
+            match self.entries.get(name) {
+                None => change.added.push(name.clone()),
+                Some(before) if before != state => change.modified.push(name.clone()),
+                Some(_) => {}
+            }
+        }
+        change.removed = self.entries.keys().filter(|name| !newer.entries.contains_key(*name)).cloned().collect();
+        change
+    }
+}
+
+/// Names added, removed and modified in a watched directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryChange {
+    pub path: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl DirectoryChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// One directory on one device; call `check` regularly (a few times a second is cheap)
+pub struct DirectoryWatch {
+    device: Device,
+    path: String,
+    open: OpenOps,
+    snapshot: DirectorySnapshot,
+    generation: u64,
+    interval: Duration,
+    last_scan: Instant,
+}
+
+impl DirectoryWatch {
+    /// Start watching `path`; `open` gives fresh ops for each rescan, so nothing an
+    /// earlier read cached hides a change
+    pub fn new(device: &Device, path: &str, open: OpenOps) -> Result<Self, MosesError> {
+        let generation = FilesystemCache::global().generation(device);
+        let snapshot = DirectorySnapshot::capture(open()?.as_mut(), Path::new(path))?;
+        Ok(Self {
+            device: device.clone(),
+            path: path.to_string(),
+            open,
+            snapshot,
+            generation,
+            interval: POLL_INTERVAL,
+            last_scan: Instant::now(),
+        })
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Rescan if Moses announced a change to the device or the poll interval has passed,
+    /// and return what changed since the last scan
+    pub fn check(&mut self) -> Result<Option<DirectoryChange>, MosesError> {
+        let generation = FilesystemCache::global().generation(&self.device);
+        if generation == self.generation && self.last_scan.elapsed() < self.interval {
+            return Ok(None);
+        }
+        self.generation = generation;
+        self.last_scan = Instant::now();
+        let snapshot = DirectorySnapshot::capture((self.open)()?.as_mut(), Path::new(&self.path))?;
+        let change = self.snapshot.diff(&snapshot, &self.path);
+        self.snapshot = snapshot;
+        Ok((!change.is_empty()).then_some(change))
+    }
+}
+
+/// Wraps writable ops so every change made through them is announced to watches
+pub struct NotifyingOps {
+    inner: Box<dyn FilesystemOps>,
+    device: Device,
+    last_notice: Option<Instant>,
+    /// A write happened after the last announcement
+    pending: bool,
+}
+
+impl NotifyingOps {
+    pub fn new(inner: Box<dyn FilesystemOps>, device: &Device) -> Self {
+        Self { inner, device: device.clone(), last_notice: None, pending: false }
+    }
+
+    fn announce(&mut self) {
+        FilesystemCache::global().invalidate(&self.device);
+        self.last_notice = Some(Instant::now());
+        self.pending = false;
+    }
+
+    /// Announce a successful change
+    fn changed<T>(&mut self, result: Result<T, MosesError>) -> Result<T, MosesError> {
+        if result.is_ok() {
+            self.announce();
+        }
+        result
+    }
+}
+
+impl FilesystemOps for NotifyingOps {
+    fn init(&mut self, device: &Device) -> Result<(), MosesError> {
+        self.device = device.clone();
+        self.inner.init(device)
+    }
+
+    fn statfs(&self) -> Result<FilesystemInfo, MosesError> {
+        self.inner.statfs()
+    }
+
+    fn stat(&mut self, path: &Path) -> Result<FileAttributes, MosesError> {
+        self.inner.stat(path)
+    }
+
+    fn readdir(&mut self, path: &Path) -> Result<Vec<DirectoryEntry>, MosesError> {
+        self.inner.readdir(path)
+    }
+
+    fn read(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, MosesError> {
+        self.inner.read(path, offset, size)
+    }
+
+    fn stats(&mut self, path: &Path) -> Result<TreeStats, MosesError> {
+        self.inner.stats(path)
+    }
+
+    fn directory_id(&mut self, path: &Path) -> Option<u64> {
+        self.inner.directory_id(path)
+    }
+
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, MosesError> {
+        self.inner.readlink(path)
+    }
+
+    fn write(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<u32, MosesError> {
+        let written = self.inner.write(path, offset, data)?;
+        if self.last_notice.is_some_and(|at| at.elapsed() < WRITE_NOTICE_INTERVAL) {
+            self.pending = true;
+        } else {
+            self.announce();
+        }
+        Ok(written)
+    }
+
+    fn create(&mut self, path: &Path, mode: u32) -> Result<(), MosesError> {
+        let result = self.inner.create(path, mode);
+        self.changed(result)
+    }
+
+    fn mkdir(&mut self, path: &Path, mode: u32) -> Result<(), MosesError> {
+        let result = self.inner.mkdir(path, mode);
+        self.changed(result)
+    }
+
+    fn unlink(&mut self, path: &Path) -> Result<(), MosesError> {
+        let result = self.inner.unlink(path);
+        self.changed(result)
+    }
+
+    fn rmdir(&mut self, path: &Path) -> Result<(), MosesError> {
+        let result = self.inner.rmdir(path);
+        self.changed(result)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<(), MosesError> {
+        let result = self.inner.rename(from, to);
+        self.changed(result)
+    }
+
+    fn truncate(&mut self, path: &Path, size: u64) -> Result<(), MosesError> {
+        let result = self.inner.truncate(path, size);
+        self.changed(result)
+    }
+
+    fn set_attributes(&mut self, path: &Path, changes: &AttributeChanges) -> Result<(), MosesError> {
+        let result = self.inner.set_attributes(path, changes);
+        self.changed(result)
+    }
+
+    fn sync(&mut self) -> Result<(), MosesError> {
+        self.inner.sync()?;
+        // Writes held back by the interval are announced once they are on the device
+        if self.pending {
+            self.announce();
+        }
+        Ok(())
+    }
+
+    fn is_readonly(&self) -> bool {
+        self.inner.is_readonly()
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        self.inner.warnings()
+    }
+
+    fn filesystem_type(&self) -> &str {
+        self.inner.filesystem_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::HostFolderOps;
+
+    #[test]
+    fn test_directory_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("kept.txt"), b"one").unwrap();
+        std::fs::write(dir.path().join("gone.txt"), b"two").unwrap();
+        let root = dir.path().to_path_buf();
+        let device = crate::test_helpers::create_test_device(&format!("watch-test-{}", std::process::id()), 0);
+        let open: OpenOps = Box::new(move || Ok(Box::new(HostFolderOps::new(root.clone())?) as Box<dyn FilesystemOps>));
+        let mut watch = DirectoryWatch::new(&device, "/", open).unwrap().with_interval(Duration::from_secs(3600));
+        assert_eq!(watch.check().unwrap(), None, "nothing changed and the interval has not passed");
+
+        // A change made outside Moses waits for the poll interval
+        std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+        assert_eq!(watch.check().unwrap(), None);
+        watch.interval = Duration::ZERO;
+        let change = watch.check().unwrap().unwrap();
+        assert_eq!(change.removed, ["gone.txt"]);
+        watch.interval = Duration::from_secs(3600);
+
+        // One made through NotifyingOps is seen on the next check
+        let mut ops = NotifyingOps::new(Box::new(HostFolderOps::new(dir.path().to_path_buf()).unwrap()), &device);
+        ops.create(Path::new("/new.txt"), 0o644).unwrap();
+        ops.write(Path::new("/kept.txt"), 3, b" and more").unwrap();
+        let change = watch.check().unwrap().unwrap();
+        assert_eq!(change.added, ["new.txt"]);
+        assert_eq!(change.modified, ["kept.txt"]);
+        assert!(change.removed.is_empty());
+        assert_eq!(watch.check().unwrap(), None);
+    }
+}
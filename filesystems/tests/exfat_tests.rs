@@ -29,6 +29,10 @@ fn create_test_device(size: u64) -> (Device, NamedTempFile) {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     };
     
@@ -46,6 +50,10 @@ async fn test_exfat_formatter_safety() {
         mount_points: vec![std::path::PathBuf::from("/")],
         is_removable: false,
         is_system: true,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     };
     
@@ -17,6 +17,10 @@ fn create_test_device(file_path: &str, size: u64) -> Device {
         mount_points: vec![],
         is_removable: false,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -36,6 +36,10 @@ fn create_test_device(size: u64) -> (Device, NamedTempFile) {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     };
     
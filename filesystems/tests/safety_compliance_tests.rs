@@ -238,6 +238,10 @@ fn create_system_drive() -> Device {
         ],
         is_removable: false,
         is_system: true,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -255,6 +259,10 @@ fn create_safe_usb() -> Device {
         mount_points: vec![],
         is_removable: true,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -272,6 +280,10 @@ fn create_risky_device() -> Device {
         mount_points: vec![PathBuf::from("/data")],
         is_removable: false,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
@@ -285,6 +297,10 @@ fn create_device_with_mount(mount: PathBuf) -> Device {
         mount_points: vec![mount],
         is_removable: false,
         is_system: false,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
         filesystem: None,
     }
 }
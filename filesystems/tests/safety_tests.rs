@@ -17,6 +17,10 @@ mod safety_tests {
             mount_points: vec![PathBuf::from("C:\\")],
             is_removable: false,
             is_system: true,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         filesystem: None,
         }
     }
@@ -31,6 +35,10 @@ mod safety_tests {
             mount_points: vec![],
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         filesystem: None,
         }
     }
@@ -129,6 +137,10 @@ mod safety_tests {
                 mount_points: vec![mount.clone()],
                 is_removable: false,
                 is_system: false,
+                is_write_protected: false,
+                serial: None,
+                erase_block_size: None,
+                partition_offset: None,
         filesystem: None,
             };
             
@@ -229,6 +241,10 @@ mod safety_tests {
             mount_points: vec![],
             is_removable: true,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         filesystem: None,
         };
         
@@ -244,6 +260,10 @@ mod safety_tests {
             mount_points: vec![],
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         filesystem: None,
         };
         
@@ -259,6 +279,10 @@ mod safety_tests {
             mount_points: vec![],
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         filesystem: None,
         };
         
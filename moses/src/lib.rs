@@ -0,0 +1,201 @@
+//! Moses as a library: find devices, detect and read filesystems, mount them and format them.
+//!
+//! This crate is the supported way to embed Moses. Everything reachable from here follows
+//! semver; the crates behind it (`moses-core`, `moses-filesystems`, `moses-platform`) are
+//! internal and may be reorganised in any release.
+//!
+//! ```no_run
+//! # async fn run() -> moses::Result<()> {
+//! for device in moses::devices::list().await? {
+//!     println!("{} {}", device.id, moses::detect::filesystem(&device)?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub use moses_core::{Device, DeviceType, FormatOptions, MosesError, Platform, ProgressUpdate, SimulationReport};
+
+pub type Result<T> = std::result::Result<T, MosesError>;
+
+/// Enumerating the disks attached to this machine
+pub mod devices {
+    use crate::{Device, DeviceType, MosesError, Result};
+    use std::path::Path;
+
+    pub use moses_core::DeviceManager;
+    pub use moses_platform::PlatformDeviceManager;
+
+    /// Every disk the platform reports, including system disks (check `Device::is_system`)
+    pub async fn list() -> Result<Vec<Device>> {
+        PlatformDeviceManager.enumerate_devices().await
+    }
+
+    /// Look a device up by its id (e.g. `/dev/sdb` or `\\.\PHYSICALDRIVE1`)
+    pub async fn find(id: &str) -> Result<Option<Device>> {
+        Ok(list().await?.into_iter().find(|device| device.id == id))
+    }
+
+    /// Treat a disk image file as a device
+    pub fn from_image(path: &Path) -> Result<Device> {
+        let size = std::fs::metadata(path)?.len();
+        // Device helpers treat relative ids as names under /dev, so use an absolute path
+        let absolute = path.canonicalize()?;
+        Ok(Device {
+            id: absolute.to_string_lossy().to_string(),
+            name: path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| absolute.to_string_lossy().to_string()),
+            size,
+            device_type: DeviceType::Virtual,
+            mount_points: vec![],
+            is_removable: false,
+            is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+            filesystem: None,
+        })
+    }
+
+    /// A device id from `list()`, or else the path of an image file
+    pub async fn open(id_or_path: &str) -> Result<Device> {
+        let path = Path::new(id_or_path);
+        if path.is_file() {
+            return from_image(path);
+        }
+        find(id_or_path).await?
+            .ok_or_else(|| MosesError::DeviceNotFound(id_or_path.to_string()))
+    }
+}
+
+/// Recognising the filesystem on a device or image
+pub mod detect {
+    use crate::{Device, MosesError, Result};
+    use std::path::Path;
+
+    /// Filesystem at the start of the device, e.g. `"fat32"`, `"ext4"`, or `"unknown"`
+    pub fn filesystem(device: &Device) -> Result<String> {
+        file(Path::new(&device.id))
+    }
+
+    /// Filesystem at the start of an image file or device node
+    pub fn file(path: &Path) -> Result<String> {
+        let mut file = std::fs::File::open(path).map_err(MosesError::IoError)?;
+        moses_filesystems::detection::detect_filesystem(&mut file)
+    }
+}
+
+/// Browsing and reading files without mounting
+pub mod read {
+    use crate::{Device, MosesError, Result};
+    use std::io::Write;
+    use std::path::Path;
+
+    pub use moses_filesystems::{DirectoryEntry, FileAttributes, FilesystemInfo, FilesystemOps, TreeStats};
+    pub use moses_filesystems::export::{export_tree, ArchiveFormat, ExportSummary};
+    pub use moses_filesystems::readonly::ReadOnlyOps;
+    pub use moses_filesystems::preview::{listing_mime, mime_from_extension, preview_file, sniff_mime, FilePreview, PreviewContent};
+    pub use moses_filesystems::transfer::{check_limits, extract_to_directory, preview, preview_tree, FilesystemLimits, LimitViolation, SplitManifest, TransferFilter, TransferPreview, TransferReport};
+
+    /// Open the filesystem on `device` read-only; `filesystem` skips detection when given
+    pub fn open(device: &Device, filesystem: Option<&str>) -> Result<Box<dyn FilesystemOps>> {
+        open_with(device, filesystem, false)
+    }
+
+    /// Open the filesystem with write support where the implementation has it
+    pub fn open_writable(device: &Device, filesystem: Option<&str>) -> Result<Box<dyn FilesystemOps>> {
+        open_with(device, filesystem, true)
+    }
+
+    fn open_with(device: &Device, filesystem: Option<&str>, writable: bool) -> Result<Box<dyn FilesystemOps>> {
+        let mut registry = moses_filesystems::FilesystemOpsRegistry::new();
+        moses_filesystems::register_all_filesystems(&mut registry, writable);
+        registry.create_ops(device, filesystem)
+    }
+
+    /// Copy one file out of the filesystem into `out`, returning the bytes copied
+    pub fn extract(fs: &mut dyn FilesystemOps, path: &Path, out: &mut dyn Write) -> Result<u64> {
+        const CHUNK: u32 = 1024 * 1024;
+
+        let attributes = fs.stat(path)?;
+        if attributes.is_directory {
+            return Err(MosesError::InvalidInput(format!("{} is a directory", path.display())));
+        }
+        let mut offset = 0;
+        while offset < attributes.size {
+            let data = fs.read(path, offset, CHUNK.min((attributes.size - offset) as u32))?;
+            if data.is_empty() {
+                break;
+            }
+            out.write_all(&data)?;
+            offset += data.len() as u64;
+        }
+        Ok(offset)
+    }
+}
+
+/// Creating filesystems
+pub mod format {
+    use crate::{Device, FormatOptions, MosesError, Result, SimulationReport};
+
+    pub use moses_core::{
+        FilesystemFormatter, FormatStrategy, FormatterCategory, FormatterMetadata, FormatterRegistry,
+        SelectedFormatter,
+    };
+
+    /// Registry of every built-in formatter
+    pub fn registry() -> &'static FormatterRegistry {
+        moses_filesystems::builtin_registry()
+    }
+
+    /// The formatter that would handle `options.filesystem_type` on this device
+    pub fn resolve(device: &Device, options: &FormatOptions) -> Result<SelectedFormatter> {
+        moses_filesystems::resolve_formatter(registry(), device, options)
+    }
+
+    /// Describe what formatting would do without writing anything, including safety lints
+    /// warnings about operating systems found on the device and a summary of its current contents
+    pub async fn simulate(device: &Device, options: &FormatOptions) -> Result<SimulationReport> {
+        let selected = resolve(device, options)?;
+        let mut report = selected.formatter.dry_run(device, options).await?;
+        report.strategy = Some(selected.describe());
+        report.warnings.extend(moses_filesystems::disk_manager::installations::installation_warnings(device));
+        report.contents = moses_filesystems::disk_manager::contents::summarize_contents(device);
+        report.lints.extend(moses_filesystems::lints::lint(device, options));
+        Ok(report)
+    }
+
+    /// Format the device, destroying its contents. System disks are always refused.
+    pub async fn format(device: &Device, options: &FormatOptions) -> Result<()> {
+        if device.is_system {
+            return Err(MosesError::UnsafeDevice(format!("{} is a system disk", device.id)));
+        }
+        device.ensure_writable()?;
+
+        let selected = resolve(device, options)?;
+        selected.formatter.validate_options(options).await?;
+        if !selected.formatter.can_format(device) {
+            return Err(MosesError::UnsafeDevice(format!(
+                "{} cannot be formatted as {}",
+                device.id, options.filesystem_type
+            )));
+        }
+        moses_filesystems::disk_manager::history::record_before(device, format!("format as {}", options.filesystem_type));
+        selected.formatter.format(device, options).await
+    }
+}
+
+/// Mounting filesystems through FUSE/macFUSE/WinFsp (`mount-unix` or `mount-windows` feature)
+#[cfg(any(feature = "mount-unix", feature = "mount-windows"))]
+pub mod mount {
+    use crate::Result;
+
+    pub use moses_filesystems::mount::{MountOptions, MountProvider};
+    pub use moses_filesystems::mount_driver::MountDriver;
+
+    /// Mount provider for this platform, failing if the driver is missing or too old
+    pub fn provider() -> Result<Box<dyn MountProvider>> {
+        moses_filesystems::mount::get_mount_provider()
+    }
+}
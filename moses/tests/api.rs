@@ -0,0 +1,63 @@
+// Uses the facade the way a third-party program would: only through `moses::` paths
+
+use moses::{Device, DeviceType, FormatOptions};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+fn image_device(size: u64) -> (Device, NamedTempFile) {
+    let image = NamedTempFile::new().unwrap();
+    image.as_file().set_len(size).unwrap();
+    let device = Device {
+        id: image.path().to_string_lossy().to_string(),
+        name: "image".to_string(),
+        size,
+        device_type: DeviceType::Virtual,
+        mount_points: vec![],
+        is_removable: true,
+        is_system: false,
+        filesystem: None,
+        is_write_protected: false,
+        serial: None,
+        erase_block_size: None,
+        partition_offset: None,
+    };
+    (device, image)
+}
+
+fn options(filesystem: &str) -> FormatOptions {
+    FormatOptions {
+        filesystem_type: filesystem.to_string(),
+        label: Some("FACADE".to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_format_detect_and_read() {
+    let (device, _image) = image_device(1024 * 1024 * 1024);
+
+    let report = moses::format::simulate(&device, &options("ext4")).await.unwrap();
+    assert!(report.strategy.is_some());
+
+    moses::format::format(&device, &options("ext4")).await.unwrap();
+    assert_eq!(moses::detect::filesystem(&device).unwrap(), "ext4");
+
+    let mut fs = moses::read::open(&device, None).unwrap();
+    assert!(fs.readdir(Path::new("/")).is_ok());
+    assert_eq!(fs.statfs().unwrap().volume_label.as_deref(), Some("FACADE"));
+}
+
+#[tokio::test]
+async fn test_system_disks_are_refused() {
+    let (mut device, _image) = image_device(64 * 1024 * 1024);
+    device.is_system = true;
+    assert!(moses::format::format(&device, &options("fat32")).await.is_err());
+}
+
+#[test]
+fn test_registry_lists_builtin_formatters() {
+    let registry = moses::format::registry();
+    for name in ["ext4", "fat32", "exfat"] {
+        assert!(registry.is_supported(name), "{} missing", name);
+    }
+}
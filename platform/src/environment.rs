@@ -0,0 +1,90 @@
+// Checks of the environment Moses runs in
+// Shared by the GUI's requirement checks and `moses doctor`: whether we are elevated,
+// how elevation would be obtained, and which external tools a formatter still needs.
+
+/// Whether a command-line tool is available, either managed by Moses, bundled with it or on PATH
+pub fn tool_available(tool: &str) -> bool {
+    moses_filesystems::tools::ToolManager::global().locate(tool).is_some()
+}
+
+/// External tools a formatter needs on this platform that are not installed,
+/// as package names with the missing command in parentheses
+pub fn missing_tools(filesystem_type: &str) -> Vec<String> {
+    // (filesystem, command, package hint) per platform; Windows formats natively
+    #[cfg(target_os = "linux")]
+    const REQUIREMENTS: &[(&str, &str, &str)] = &[
+        ("ext4", "mkfs.ext4", "e2fsprogs (mkfs.ext4)"),
+        ("ntfs", "mkfs.ntfs", "ntfs-3g (mkfs.ntfs)"),
+        ("fat32", "mkfs.fat", "dosfstools (mkfs.fat)"),
+        ("exfat", "mkfs.exfat", "exfatprogs or exfat-utils (mkfs.exfat)"),
+    ];
+    #[cfg(target_os = "macos")]
+    const REQUIREMENTS: &[(&str, &str, &str)] = &[
+        ("ntfs", "mkfs.ntfs", "ntfs-3g-mac (install with: brew install ntfs-3g-mac)"),
+    ];
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    const REQUIREMENTS: &[(&str, &str, &str)] = &[];
+
+    REQUIREMENTS
+        .iter()
+        .filter(|(filesystem, tool, _)| *filesystem == filesystem_type && !tool_available(tool))
+        .map(|(_, _, package)| package.to_string())
+        .collect()
+}
+
+/// Whether this process runs as Administrator (Windows) or root
+pub fn is_elevated() -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        crate::windows::elevation::is_elevated()
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        nix::unistd::geteuid().is_root()
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("id")
+            .arg("-u")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "0")
+            .unwrap_or(false)
+    }
+}
+
+/// How the GUI would obtain elevation for the worker, if any mechanism is available
+pub fn elevation_method() -> Option<&'static str> {
+    #[cfg(target_os = "windows")]
+    {
+        Some("UAC prompt")
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        tool_available("osascript").then_some("administrator prompt (osascript)")
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if tool_available("pkexec") {
+            Some("pkexec")
+        } else if tool_available("sudo") {
+            Some("sudo")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_tools_are_missing() {
+        assert!(!tool_available("moses-no-such-tool"));
+        assert!(missing_tools("no-such-filesystem").is_empty());
+    }
+}
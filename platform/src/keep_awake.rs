@@ -0,0 +1,176 @@
+// Keep the machine awake while a long format or wipe runs
+// A laptop going to sleep halfway through leaves a half-written disk, so long operations
+// hold a KeepAwake guard. The inhibition is released when the guard is dropped, and also
+// when the process dies, since each backend is tied to our lifetime:
+// - Windows: SetThreadExecutionState on a dedicated thread
+// - Linux: a systemd-inhibit child that exits once its stdin closes
+// - macOS: caffeinate, which takes an IOPMAssertion and watches our pid
+
+/// Prevents system sleep (and idle sleep) until dropped
+pub struct KeepAwake {
+    inner: Option<imp::Inhibitor>,
+}
+
+impl KeepAwake {
+    /// Ask the OS not to sleep. Failing to inhibit never blocks the operation;
+    /// it is logged and the returned guard is simply inactive.
+    pub fn acquire(reason: &str) -> Self {
+        match imp::Inhibitor::acquire(reason) {
+            Ok(inner) => {
+                log::info!("Sleep inhibited: {}", reason);
+                Self { inner: Some(inner) }
+            }
+            Err(e) => {
+                log::warn!("Could not prevent system sleep ({}); keep the machine awake manually", e);
+                Self { inner: None }
+            }
+        }
+    }
+
+    /// Whether sleep is actually being inhibited
+    pub fn is_active(&self) -> bool {
+        self.inner.is_some()
+    }
+}
+
+impl Drop for KeepAwake {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.release();
+            log::info!("Sleep inhibition released");
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use std::sync::mpsc;
+    use std::thread::JoinHandle;
+    use windows::Win32::System::Power::{
+        SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED,
+    };
+
+    // The execution state belongs to the calling thread, and async tasks hop threads,
+    // so a dedicated thread holds it for the guard's lifetime
+    pub struct Inhibitor {
+        stop: mpsc::Sender<()>,
+        thread: JoinHandle<()>,
+    }
+
+    impl Inhibitor {
+        pub fn acquire(_reason: &str) -> Result<Self, String> {
+            let (stop, stopped) = mpsc::channel();
+            let (ready_tx, ready) = mpsc::channel();
+            let thread = std::thread::spawn(move || {
+                let previous = unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                let _ = ready_tx.send(previous.0 != 0);
+                let _ = stopped.recv();
+                unsafe {
+                    SetThreadExecutionState(ES_CONTINUOUS);
+                }
+            });
+
+            match ready.recv() {
+                Ok(true) => Ok(Self { stop, thread }),
+                _ => {
+                    let _ = stop.send(());
+                    let _ = thread.join();
+                    Err("SetThreadExecutionState failed".to_string())
+                }
+            }
+        }
+
+        pub fn release(self) {
+            let _ = self.stop.send(());
+            let _ = self.thread.join();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::process::{Child, Command, Stdio};
+
+    pub struct Inhibitor {
+        child: Child,
+    }
+
+    impl Inhibitor {
+        pub fn acquire(reason: &str) -> Result<Self, String> {
+            // `cat` keeps the inhibitor lock until its stdin (held by us) is closed
+            let child = Command::new("systemd-inhibit")
+                .args([
+                    "--what=sleep:idle",
+                    "--who=Moses",
+                    &format!("--why={}", reason),
+                    "--mode=block",
+                    "cat",
+                ])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("systemd-inhibit unavailable: {}", e))?;
+
+            let mut inhibitor = Self { child };
+            // systemd-inhibit exits straight away when logind refuses the lock
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            match inhibitor.child.try_wait() {
+                Ok(None) => Ok(inhibitor),
+                Ok(Some(status)) => Err(format!("systemd-inhibit exited with {}", status)),
+                Err(e) => {
+                    inhibitor.release();
+                    Err(format!("systemd-inhibit: {}", e))
+                }
+            }
+        }
+
+        pub fn release(mut self) {
+            drop(self.child.stdin.take());
+            if !matches!(self.child.try_wait(), Ok(Some(_))) {
+                let _ = self.child.kill();
+            }
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::process::{Child, Command, Stdio};
+
+    pub struct Inhibitor {
+        child: Child,
+    }
+
+    impl Inhibitor {
+        pub fn acquire(_reason: &str) -> Result<Self, String> {
+            // -i holds a PreventUserIdleSystemSleep assertion; -w drops it if we die
+            let child = Command::new("caffeinate")
+                .args(["-i", "-w", &std::process::id().to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("caffeinate unavailable: {}", e))?;
+            Ok(Self { child })
+        }
+
+        pub fn release(mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+mod imp {
+    pub struct Inhibitor;
+
+    impl Inhibitor {
+        pub fn acquire(_reason: &str) -> Result<Self, String> {
+            Err("not supported on this platform".to_string())
+        }
+
+        pub fn release(self) {}
+    }
+}
@@ -1,3 +1,8 @@
+pub mod environment;
+pub mod keep_awake;
+pub mod quiesce;
+pub mod remount;
+
 #[cfg(target_os = "linux")]
 pub mod linux;
 
@@ -14,4 +19,11 @@ pub use linux::LinuxDeviceManager as PlatformDeviceManager;
 pub use windows::WindowsDeviceManager as PlatformDeviceManager;
 
 #[cfg(target_os = "macos")]
-pub use macos::device::MacOSDeviceManager as PlatformDeviceManager;
\ No newline at end of file
+pub use macos::device::MacOSDeviceManager as PlatformDeviceManager;
+
+pub use keep_awake::KeepAwake;
+pub use quiesce::Quiesce;
+pub use remount::RemountTarget;
+/// Device id to OS path mapping; it lives in moses-core so the filesystem crate,
+/// which this crate depends on, opens devices the same way
+pub use moses_core::device_path::{device_path, physical_drive_number, resolve_device_path, PathStyle};
@@ -1,9 +1,15 @@
-use moses_core::{Device, DeviceInfo, DeviceManager, DeviceType, MosesError, Partition, PermissionLevel};
+use moses_core::{
+    Device, DeviceInfo, DeviceManager, DevicePowerState, DeviceType, LbaFormat, MosesError, Partition, PermissionLevel,
+};
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::os::unix::io::AsRawFd;
+
+// BLKROGET: read the block device's read-only flag
+nix::ioctl_read_bad!(blkroget, nix::request_code_none!(0x12, 94), nix::libc::c_int);
 
 pub struct LinuxDeviceManager;
 
@@ -37,6 +43,22 @@ impl LinuxDeviceManager {
             .unwrap_or(false)
     }
     
+    /// Write protection as the kernel sees it (SD lock switch, read-only media).
+    /// Uses BLKROGET when we can open the device, otherwise the sysfs flag.
+    fn is_write_protected(device_name: &str) -> bool {
+        if let Ok(file) = fs::File::open(format!("/dev/{}", device_name)) {
+            let mut read_only: nix::libc::c_int = 0;
+            if unsafe { blkroget(file.as_raw_fd(), &mut read_only) }.is_ok() {
+                return read_only != 0;
+            }
+        }
+        
+        let ro_path = format!("/sys/block/{}/ro", device_name);
+        fs::read_to_string(&ro_path)
+            .map(|content| content.trim() == "1")
+            .unwrap_or(false)
+    }
+    
     fn get_device_type(device_name: &str) -> DeviceType {
         // Check if it's removable first
         if Self::is_removable(device_name) {
@@ -116,10 +138,26 @@ impl LinuxDeviceManager {
         device_name.to_uppercase()
     }
     
+    fn get_device_serial(device_name: &str) -> Option<String> {
+        // NVMe and MMC devices expose the serial directly; USB/SATA go through lsblk
+        let serial_path = format!("/sys/block/{}/device/serial", device_name);
+        fs::read_to_string(serial_path)
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+    
+    fn get_erase_block_size(device_name: &str) -> Option<u64> {
+        // MMC/SD cards report their allocation unit; other flash only hints through discard
+        let read = |path: String| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok().filter(|&size| size > 0);
+        read(format!("/sys/block/{}/device/preferred_erase_size", device_name))
+            .or_else(|| read(format!("/sys/block/{}/queue/discard_granularity", device_name)).filter(|&size| size > 4096))
+    }
+    
     async fn parse_lsblk_output(&self) -> Result<Vec<Device>, MosesError> {
         // Run lsblk to get device information
         let output = Command::new("lsblk")
-            .args(["-b", "-P", "-o", "NAME,SIZE,TYPE,MOUNTPOINT,FSTYPE,MODEL,VENDOR,RM,RO"])
+            .args(["-b", "-P", "-o", "NAME,SIZE,TYPE,MOUNTPOINT,FSTYPE,MODEL,VENDOR,RM,RO,SERIAL"])
             .output()
             .map_err(|e| MosesError::Other(format!("Failed to run lsblk: {}", e)))?;
         
@@ -204,6 +242,11 @@ impl LinuxDeviceManager {
                 .map(|fs| fs.trim().to_string())
                 .filter(|fs| !fs.is_empty());
             
+            let serial = fields.get("SERIAL")
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .or_else(|| Self::get_device_serial(&name));
+            
             let device = Device {
                 id: device_path.clone(),
                 name: if !model.is_empty() { 
@@ -217,6 +260,10 @@ impl LinuxDeviceManager {
                 is_removable,
                 is_system,
                 filesystem,
+                is_write_protected: Self::is_write_protected(&name),
+                serial,
+                erase_block_size: Self::get_erase_block_size(&name),
+                partition_offset: None,
             };
             
             devices.push(device);
@@ -256,8 +303,14 @@ impl LinuxDeviceManager {
                     let filesystem = if parts.len() > 2 { Some(parts[2].to_string()) } else { None };
                     let mount_point = if parts.len() > 3 { Some(PathBuf::from(parts[3])) } else { None };
                     
+                    // sysfs gives the start in 512-byte sectors whatever the logical block size
+                    let start_offset = std::fs::read_to_string(format!("/sys/class/block/{}/start", name))
+                        .ok()
+                        .and_then(|start| start.trim().parse::<u64>().ok())
+                        .map_or(0, |sectors| sectors * 512);
                     partitions.push(Partition {
                         id: format!("/dev/{}", name),
+                        start_offset,
                         size,
                         filesystem,
                         mount_point,
@@ -325,6 +378,10 @@ impl DeviceManager for LinuxDeviceManager {
                 is_removable: Self::is_removable(&device_name),
                 is_system,
                 filesystem: None, // This is for fallback raw device detection
+                is_write_protected: Self::is_write_protected(&device_name),
+                serial: Self::get_device_serial(&device_name),
+                erase_block_size: Self::get_erase_block_size(&device_name),
+                partition_offset: None,
             });
         }
         
@@ -443,4 +500,50 @@ impl DeviceManager for LinuxDeviceManager {
         
         Ok(PermissionLevel::ReadOnly)
     }
-}
\ No newline at end of file
+    
+    async fn eject(&self, device: &Device, power_off: bool) -> Result<(), MosesError> {
+        if device.is_system {
+            return Err(MosesError::UnsafeDevice(format!("Refusing to eject system disk {}", device.name)));
+        }
+        
+        // Check the live mount table, not the snapshot taken at enumeration
+        let mounted = Self::get_mount_points(&device.id);
+        if !mounted.is_empty() {
+            return Err(MosesError::UnsafeDevice(format!(
+                "{} is still mounted at {}; unmount it before ejecting",
+                device.name,
+                mounted.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+            )));
+        }
+        
+        super::power::eject(&device.id, power_off)
+    }
+    
+    async fn set_power_state(&self, device: &Device, state: DevicePowerState) -> Result<(), MosesError> {
+        match state {
+            DevicePowerState::Standby => super::power::standby(&device.id),
+            DevicePowerState::Active => super::power::wake(&device.id),
+        }
+    }
+    
+    async fn lba_formats(&self, device: &Device) -> Result<Vec<LbaFormat>, MosesError> {
+        super::nvme::lba_formats(&device.id)
+    }
+    
+    async fn set_lba_format(&self, device: &Device, index: u8) -> Result<(), MosesError> {
+        if device.is_system {
+            return Err(MosesError::UnsafeDevice(format!("Refusing to reformat system disk {}", device.name)));
+        }
+        let mounted = Self::get_mount_points(&device.id);
+        if !mounted.is_empty() {
+            return Err(MosesError::UnsafeDevice(format!(
+                "{} is still mounted at {}; unmount it before changing its sector size",
+                device.name,
+                mounted.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+            )));
+        }
+        super::nvme::set_lba_format(&device.id, index)?;
+        moses_core::FilesystemCache::global().invalidate(device);
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
 pub mod device;
+pub mod nvme;
+pub mod power;
 
-pub use device::LinuxDeviceManager;
\ No newline at end of file
+pub use device::LinuxDeviceManager;
@@ -0,0 +1,131 @@
+// NVMe LBA formats on Linux - Identify Namespace and Format NVM through the admin passthrough
+// Both commands go to the namespace's block device (/dev/nvme0n1) with NVME_IOCTL_ADMIN_CMD,
+// which needs CAP_SYS_ADMIN. The kernel revalidates the namespace after a Format NVM, so the
+// block device reports the new sector size as soon as the command returns.
+use moses_core::nvme::{self, LbaFormat, IDENTIFY_NAMESPACE_SIZE};
+use moses_core::MosesError;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+const OPCODE_IDENTIFY: u8 = 0x06;
+const OPCODE_FORMAT_NVM: u8 = 0x80;
+/// Identify CNS value for the namespace data structure
+const CNS_NAMESPACE: u32 = 0x00;
+/// A Format NVM without secure erase is quick, but some drives take minutes
+const FORMAT_TIMEOUT_MS: u32 = 10 * 60 * 1000;
+
+/// struct nvme_passthru_cmd from linux/nvme_ioctl.h
+#[repr(C)]
+#[derive(Default)]
+struct PassthruCommand {
+    opcode: u8,
+    flags: u8,
+    rsvd1: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    addr: u64,
+    metadata_len: u32,
+    data_len: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_ms: u32,
+    result: u32,
+}
+
+nix::ioctl_none!(nvme_ioctl_id, b'N', 0x40);
+nix::ioctl_readwrite!(nvme_ioctl_admin_cmd, b'N', 0x41, PassthruCommand);
+
+/// Only NVMe namespaces (nvme0n1, not partitions or the controller) take these commands
+fn check_namespace(device_path: &str) -> Result<(), MosesError> {
+    let name = device_path.trim_start_matches("/dev/");
+    let is_namespace = name.strip_prefix("nvme")
+        .and_then(|rest| rest.split_once('n'))
+        .is_some_and(|(controller, namespace)| {
+            !controller.is_empty() && controller.bytes().all(|b| b.is_ascii_digit())
+                && !namespace.is_empty() && namespace.bytes().all(|b| b.is_ascii_digit())
+        });
+    if is_namespace {
+        Ok(())
+    } else {
+        Err(MosesError::NotSupported(format!("{} is not an NVMe namespace (such as /dev/nvme0n1)", device_path)))
+    }
+}
+
+fn admin_command(file: &File, command: &mut PassthruCommand, what: &str) -> Result<(), MosesError> {
+    match unsafe { nvme_ioctl_admin_cmd(file.as_raw_fd(), command) } {
+        Ok(0) => Ok(()),
+        Ok(status) => Err(MosesError::Other(format!("{} failed with NVMe status {:#x}", what, status))),
+        Err(nix::errno::Errno::EACCES | nix::errno::Errno::EPERM) => {
+            Err(MosesError::InsufficientPrivileges(format!("{} needs root", what)))
+        }
+        Err(e) => Err(MosesError::Other(format!("{} failed: {}", what, e))),
+    }
+}
+
+fn namespace_id(file: &File) -> Result<u32, MosesError> {
+    let id = unsafe { nvme_ioctl_id(file.as_raw_fd()) }
+        .map_err(|e| MosesError::Other(format!("Could not get the namespace id: {}", e)))?;
+    Ok(id as u32)
+}
+
+fn identify_namespace(file: &File, nsid: u32) -> Result<Vec<LbaFormat>, MosesError> {
+    let mut data = vec![0u8; IDENTIFY_NAMESPACE_SIZE];
+    let mut command = PassthruCommand {
+        opcode: OPCODE_IDENTIFY,
+        nsid,
+        addr: data.as_mut_ptr() as u64,
+        data_len: data.len() as u32,
+        cdw10: CNS_NAMESPACE,
+        ..Default::default()
+    };
+    admin_command(file, &mut command, "Identify Namespace")?;
+    nvme::parse_identify_namespace(&data)
+}
+
+/// The LBA formats the namespace at `device_path` supports
+pub fn lba_formats(device_path: &str) -> Result<Vec<LbaFormat>, MosesError> {
+    check_namespace(device_path)?;
+    let file = File::open(device_path)
+        .map_err(|e| MosesError::Other(format!("Failed to open {}: {}", device_path, e)))?;
+    identify_namespace(&file, namespace_id(&file)?)
+}
+
+/// Format the namespace at `device_path` with LBA format `index`. Everything on it is lost.
+pub fn set_lba_format(device_path: &str, index: u8) -> Result<(), MosesError> {
+    check_namespace(device_path)?;
+    // O_EXCL fails while anything (a mount, a partition in use) holds the device
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(nix::libc::O_EXCL)
+        .open(device_path)
+        .map_err(|e| MosesError::Other(format!("Failed to open {} exclusively (is it in use?): {}", device_path, e)))?;
+    let nsid = namespace_id(&file)?;
+    if !identify_namespace(&file, nsid)?.iter().any(|format| format.index == index) {
+        return Err(MosesError::InvalidInput(format!("{} has no LBA format #{}", device_path, index)));
+    }
+
+    log::warn!("Formatting NVMe namespace {} (nsid {}) with LBA format #{}", device_path, nsid, index);
+    let mut command = PassthruCommand {
+        opcode: OPCODE_FORMAT_NVM,
+        nsid,
+        cdw10: nvme::format_nvm_cdw10(index),
+        timeout_ms: FORMAT_TIMEOUT_MS,
+        ..Default::default()
+    };
+    admin_command(&file, &mut command, "Format NVM")?;
+
+    // Confirm the drive took the new format
+    let in_use = identify_namespace(&file, nsid)?.into_iter().find(|format| format.in_use);
+    match in_use {
+        Some(format) if format.index == index => Ok(()),
+        _ => Err(MosesError::Other(format!("{} accepted the format but does not report LBA format #{} in use", device_path, index))),
+    }
+}
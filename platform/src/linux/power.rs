@@ -0,0 +1,108 @@
+// Eject, power-off and standby control for Linux block devices
+// Flushing is done natively; the actual eject / spin-down goes through the usual system tools
+// (udisksctl, eject, hdparm, sdparm) since those already know how to talk to each bus.
+use moses_core::MosesError;
+use std::fs;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::process::Command;
+
+// BLKFLSBUF: write back and drop the block device's buffer cache
+nix::ioctl_none!(blkflsbuf, 0x12, 97);
+
+/// Write back everything cached for the device so nothing is lost when it goes away
+pub fn flush_device(device_path: &str) -> Result<(), MosesError> {
+    let file = fs::File::open(device_path)
+        .map_err(|e| MosesError::Other(format!("Failed to open {} for flushing: {}", device_path, e)))?;
+    file.sync_all()
+        .map_err(|e| MosesError::Other(format!("Failed to flush {}: {}", device_path, e)))?;
+
+    // Dropping the buffer cache needs CAP_SYS_ADMIN; the fsync above is what matters
+    if let Err(e) = unsafe { blkflsbuf(file.as_raw_fd()) } {
+        log::debug!("BLKFLSBUF on {} failed: {}", device_path, e);
+    }
+    Ok(())
+}
+
+/// Release the device for unplugging. `power_off` asks udisks to cut power to the
+/// port as well; otherwise the medium is ejected the way the desktop would do it.
+pub fn eject(device_path: &str, power_off: bool) -> Result<(), MosesError> {
+    flush_device(device_path)?;
+
+    let attempts: &[(&str, &[&str])] = if power_off {
+        &[("udisksctl", &["power-off", "--no-user-interaction", "-b"]), ("eject", &[])]
+    } else {
+        &[("eject", &[]), ("udisksctl", &["power-off", "--no-user-interaction", "-b"])]
+    };
+
+    let mut errors = Vec::new();
+    for (program, args) in attempts {
+        match run_tool(program, args, device_path) {
+            Ok(()) => {
+                log::info!("Released {} with {}", device_path, program);
+                return Ok(());
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // Last resort: detach a SCSI/USB disk from the kernel, which syncs its cache and stops it
+    let name = device_path.trim_start_matches("/dev/");
+    let delete_path = format!("/sys/block/{}/device/delete", name);
+    match fs::write(&delete_path, "1") {
+        Ok(()) => {
+            log::info!("Detached {} through {}", device_path, delete_path);
+            Ok(())
+        }
+        Err(e) => {
+            errors.push(format!("{}: {}", delete_path, e));
+            Err(MosesError::Other(format!(
+                "Could not eject {}: {}",
+                device_path,
+                errors.join("; ")
+            )))
+        }
+    }
+}
+
+/// Spin the drive down. ATA drives take `hdparm -y`; USB bridges usually only
+/// understand a SCSI STOP UNIT, which `sdparm` sends.
+pub fn standby(device_path: &str) -> Result<(), MosesError> {
+    flush_device(device_path)?;
+
+    let hdparm = run_tool("hdparm", &["-y"], device_path);
+    if hdparm.is_ok() {
+        return Ok(());
+    }
+    run_tool("sdparm", &["--readonly", "--command=stop"], device_path).map_err(|e| {
+        MosesError::Other(format!(
+            "Could not put {} into standby: {}; {}",
+            device_path,
+            hdparm.unwrap_err(),
+            e
+        ))
+    })
+}
+
+/// Wake the drive by reading its first sector; any access spins a disk back up
+pub fn wake(device_path: &str) -> Result<(), MosesError> {
+    let mut file = fs::File::open(device_path)
+        .map_err(|e| MosesError::Other(format!("Failed to open {}: {}", device_path, e)))?;
+    let mut sector = [0u8; 512];
+    file.read_exact(&mut sector)
+        .map_err(|e| MosesError::Other(format!("Failed to wake {}: {}", device_path, e)))
+}
+
+fn run_tool(program: &str, args: &[&str], device_path: &str) -> Result<(), String> {
+    let output = Command::new(program)
+        .args(args)
+        .arg(device_path)
+        .output()
+        .map_err(|e| format!("{} unavailable: {}", program, e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
@@ -94,4 +94,31 @@ impl DeviceManager for MacOSDeviceManager {
             Ok(PermissionLevel::User)
         }
     }
-}
\ No newline at end of file
+
+    async fn eject(&self, device: &Device, _power_off: bool) -> Result<(), MosesError> {
+        // diskutil eject unmounts every volume, flushes and releases the whole disk;
+        // macOS powers down the port itself once the disk is ejected
+        #[cfg(target_os = "macos")]
+        {
+            use std::process::Command;
+            
+            let output = Command::new("diskutil")
+                .args(&["eject", &device.id])
+                .output()
+                .map_err(|e| MosesError::Other(format!("Failed to run diskutil: {}", e)))?;
+            
+            if !output.status.success() {
+                return Err(MosesError::Other(format!(
+                    "diskutil eject failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            Ok(())
+        }
+        
+        #[cfg(not(target_os = "macos"))]
+        {
+            Err(MosesError::NotSupported(format!("Ejecting {} requires macOS", device.name)))
+        }
+    }
+}
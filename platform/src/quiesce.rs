@@ -0,0 +1,299 @@
+// Holding a mounted volume still while it is imaged
+// Copying a volume the OS keeps writing to gives an image that never existed at any one
+// moment. A Quiesce guard makes the copy consistent for as long as it is held:
+// - Linux: every mounted filesystem on the device is frozen with FIFREEZE, which writes
+//   everything back and blocks new writes, and thawed with FITHAW
+// - Windows: a Volume Shadow Copy of the volume is created and the snapshot device is read
+//   instead of the live volume; the shadow copy is deleted afterwards
+// Anything else fails with PlatformNotSupported rather than copying a moving volume.
+// The freeze or shadow copy is undone when the guard is dropped, so an error or a panic
+// halfway through an image never leaves a frozen filesystem behind.
+
+use moses_core::{Device, MosesError};
+use std::path::Path;
+
+pub struct Quiesce {
+    inner: Option<imp::Hold>,
+    source: Device,
+}
+
+impl Quiesce {
+    /// Quiesce `device` for imaging into `output`; the image must not be written to the
+    /// volume being held still
+    pub fn hold(device: &Device, output: &Path) -> Result<Self, MosesError> {
+        let (inner, source) = imp::Hold::acquire(device, output)?;
+        Ok(Self { inner: Some(inner), source })
+    }
+
+    /// The device to read while the guard is held: the device itself, or its snapshot
+    pub fn source(&self) -> &Device {
+        &self.source
+    }
+
+    /// Undo the freeze or snapshot, reporting a failure to do so
+    pub fn release(mut self) -> Result<(), MosesError> {
+        match self.inner.take() {
+            Some(inner) => inner.release(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Quiesce {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            if let Err(e) = inner.release() {
+                log::error!("Failed to release {}: {}", self.source.name, e);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use moses_core::{Device, MosesError};
+    use std::fs::File;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::raw::c_int;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+
+    nix::ioctl_readwrite!(fifreeze, b'X', 119, c_int);
+    nix::ioctl_readwrite!(fithaw, b'X', 120, c_int);
+
+    /// Frozen mount points, in the order they were frozen
+    pub struct Hold {
+        frozen: Vec<(PathBuf, File)>,
+    }
+
+    impl Hold {
+        pub fn acquire(device: &Device, output: &Path) -> Result<(Self, Device), MosesError> {
+            let mut mount_points = device.mount_points.clone();
+            mount_points.sort();
+            mount_points.dedup();
+            // The image is written while the volume is frozen, so it has to go elsewhere
+            let output_dir = match output.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let output_dev = std::fs::metadata(&output_dir)
+                .map_err(|e| MosesError::Other(format!("Failed to inspect {}: {}", output_dir.display(), e)))?
+                .dev();
+
+            let mut hold = Self { frozen: Vec::new() };
+            for mount_point in mount_points {
+                if mount_point == Path::new("/") {
+                    return Err(MosesError::UnsafeDevice(
+                        "the root filesystem cannot be frozen while the system runs".to_string(),
+                    ));
+                }
+                let dir = File::open(&mount_point)
+                    .map_err(|e| MosesError::Other(format!("Failed to open {}: {}", mount_point.display(), e)))?;
+                if dir.metadata()?.dev() == output_dev {
+                    return Err(MosesError::InvalidInput(format!(
+                        "{} is on {}, which would be frozen while the image is written; write the image to another drive",
+                        output.display(), mount_point.display()
+                    )));
+                }
+                // A failure here drops `hold`, which thaws what was already frozen
+                let mut unused: c_int = 0;
+                unsafe { fifreeze(dir.as_raw_fd(), &mut unused) }.map_err(|e| match e {
+                    nix::errno::Errno::EPERM => MosesError::InsufficientPrivileges(format!(
+                        "freezing {} needs root", mount_point.display()
+                    )),
+                    nix::errno::Errno::EOPNOTSUPP => MosesError::NotSupported(format!(
+                        "the filesystem on {} cannot be frozen; unmount it and image it without --consistent",
+                        mount_point.display()
+                    )),
+                    e => MosesError::Other(format!("Failed to freeze {}: {}", mount_point.display(), e)),
+                })?;
+                log::info!("Froze {}", mount_point.display());
+                hold.frozen.push((mount_point, dir));
+            }
+            Ok((hold, device.clone()))
+        }
+
+        pub fn release(mut self) -> Result<(), MosesError> {
+            self.thaw()
+        }
+
+        fn thaw(&mut self) -> Result<(), MosesError> {
+            let mut failed = Vec::new();
+            while let Some((mount_point, dir)) = self.frozen.pop() {
+                let mut unused: c_int = 0;
+                match unsafe { fithaw(dir.as_raw_fd(), &mut unused) } {
+                    Ok(_) => log::info!("Thawed {}", mount_point.display()),
+                    Err(e) => failed.push(format!("{} ({})", mount_point.display(), e)),
+                }
+            }
+            if failed.is_empty() {
+                Ok(())
+            } else {
+                Err(MosesError::Other(format!(
+                    "Failed to thaw {}; run `fsfreeze --unfreeze` on it", failed.join(", ")
+                )))
+            }
+        }
+    }
+
+    impl Drop for Hold {
+        fn drop(&mut self) {
+            if let Err(e) = self.thaw() {
+                log::error!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use moses_core::{physical_drive_number, Device, MosesError};
+    use std::os::windows::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    /// A shadow copy created for the image
+    pub struct Hold {
+        shadow_id: String,
+    }
+
+    fn powershell(script: &str) -> Result<String, MosesError> {
+        let output = Command::new("powershell.exe")
+            .creation_flags(CREATE_NO_WINDOW)
+            .args(["-NoProfile", "-Command", script])
+            .output()
+            .map_err(|e| MosesError::Other(format!("Failed to run PowerShell: {}", e)))?;
+        if !output.status.success() {
+            return Err(MosesError::External(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    impl Hold {
+        pub fn acquire(device: &Device, _output: &Path) -> Result<(Self, Device), MosesError> {
+            // Shadow copies are taken of one volume, not of a whole disk
+            let volume = match device.mount_points.as_slice() {
+                // Nothing mounted, nothing writing to it
+                [] => return Ok((Self { shadow_id: String::new() }, device.clone())),
+                [volume] if physical_drive_number(&device.id).is_none() => volume,
+                _ => {
+                    return Err(MosesError::NotSupported(format!(
+                        "{} is not a single mounted volume; name the partition (for example E:) to image it with --consistent",
+                        device.name
+                    )))
+                }
+            };
+            let mut volume = volume.display().to_string();
+            if !volume.ends_with('\\') {
+                volume.push('\\');
+            }
+
+            let created = powershell(&format!(
+                "$r = (Get-WmiObject -List Win32_ShadowCopy).Create('{}', 'ClientAccessible'); \"$($r.ReturnValue) $($r.ShadowID)\"",
+                volume.replace('\'', "''")
+            ))
+            .map_err(|e| MosesError::Other(format!("Failed to create a shadow copy of {}: {}", volume, e)))?;
+            let (code, shadow_id) = created.split_once(' ').unwrap_or((created.as_str(), ""));
+            if code != "0" || shadow_id.is_empty() {
+                return Err(match code {
+                    "1" => MosesError::InsufficientPrivileges("creating a shadow copy needs Administrator".to_string()),
+                    _ => MosesError::Other(format!("Failed to create a shadow copy of {} (Win32_ShadowCopy error {})", volume, code)),
+                });
+            }
+            let hold = Self { shadow_id: shadow_id.to_string() };
+
+            // Dropping `hold` on a failure here deletes the shadow copy again
+            let device_object = powershell(&format!(
+                "(Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq '{}' }}).DeviceObject",
+                hold.shadow_id
+            ))?;
+            if device_object.is_empty() {
+                return Err(MosesError::Other(format!("Shadow copy {} has no device", hold.shadow_id)));
+            }
+            log::info!("Created shadow copy {} of {} at {}", hold.shadow_id, volume, device_object);
+            let source = Device { id: device_object, mount_points: Vec::new(), ..device.clone() };
+            Ok((hold, source))
+        }
+
+        pub fn release(mut self) -> Result<(), MosesError> {
+            self.delete()
+        }
+
+        fn delete(&mut self) -> Result<(), MosesError> {
+            if self.shadow_id.is_empty() {
+                return Ok(());
+            }
+            let shadow_id = std::mem::take(&mut self.shadow_id);
+            powershell(&format!(
+                "Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq '{}' }} | ForEach-Object {{ $_.Delete() }}",
+                shadow_id
+            ))
+            .map(|_| log::info!("Deleted shadow copy {}", shadow_id))
+            .map_err(|e| MosesError::Other(format!(
+                "Failed to delete shadow copy {} ({}); remove it with `vssadmin delete shadows /shadow={}`",
+                shadow_id, e, shadow_id
+            )))
+        }
+    }
+
+    impl Drop for Hold {
+        fn drop(&mut self) {
+            if let Err(e) = self.delete() {
+                log::error!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+mod imp {
+    use moses_core::{Device, MosesError};
+    use std::path::Path;
+
+    pub struct Hold;
+
+    impl Hold {
+        pub fn acquire(_device: &Device, _output: &Path) -> Result<(Self, Device), MosesError> {
+            Err(MosesError::PlatformNotSupported(
+                "consistent images need FIFREEZE (Linux) or Volume Shadow Copy (Windows); unmount the volume and image it without --consistent".to_string(),
+            ))
+        }
+
+        pub fn release(self) -> Result<(), MosesError> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_hold_refuses_root_and_unmounted_needs_nothing() {
+        let dir = std::env::temp_dir();
+        let mut device = Device {
+            id: "/dev/quiesce-test".to_string(),
+            name: "Quiesce test".to_string(),
+            size: 0,
+            device_type: moses_core::DeviceType::USB,
+            mount_points: Vec::new(),
+            is_removable: true,
+            is_system: false,
+            filesystem: None,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        };
+        let held = Quiesce::hold(&device, &dir.join("image.img")).unwrap();
+        assert_eq!(held.source().id, device.id);
+        held.release().unwrap();
+
+        device.mount_points = vec!["/".into()];
+        assert!(matches!(Quiesce::hold(&device, &dir.join("image.img")), Err(MosesError::UnsafeDevice(_))));
+    }
+}
@@ -0,0 +1,57 @@
+// Reopen a device through the mount the OS gives it after a format
+// Windows and macOS mount a freshly formatted volume on their own, a moment after the
+// format returns. Jobs that write to the new volume, such as a selective format putting
+// the kept folders back, wait for that mount and write through the host; where nothing
+// mounts it in time they fall back to our own writers.
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use moses_core::{Device, DeviceManager, MosesError};
+use moses_filesystems::disk_manager::selective::{RestoreTarget, WritableVolume};
+use moses_filesystems::{FilesystemOps, HostFolderOps};
+
+/// How often the device list is re-read while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Waits up to `timeout` for the device to be mounted, then writes through the mount
+pub struct RemountTarget<M: DeviceManager> {
+    manager: M,
+    timeout: Duration,
+}
+
+impl<M: DeviceManager> RemountTarget<M> {
+    pub fn new(manager: M, timeout: Duration) -> Self {
+        Self { manager, timeout }
+    }
+
+    async fn wait_for_mount(&self, device: &Device) -> Option<PathBuf> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.manager.get_device_by_id(&device.id).await {
+                Ok(Some(current)) if !current.mount_points.is_empty() => return current.mount_points.into_iter().next(),
+                Ok(_) => {}
+                Err(e) => log::debug!("Re-reading {} failed: {}", device.id, e),
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<M: DeviceManager + Send> RestoreTarget for RemountTarget<M> {
+    async fn open(&mut self, device: &Device) -> Result<Box<dyn FilesystemOps>, MosesError> {
+        match self.wait_for_mount(device).await {
+            Some(mount) => {
+                log::info!("{} is mounted at {}", device.name, mount.display());
+                Ok(Box::new(HostFolderOps::new(mount)?))
+            }
+            None => {
+                log::info!("{} was not mounted within {:?}; using Moses' own writers", device.name, self.timeout);
+                WritableVolume.open(device).await
+            }
+        }
+    }
+}
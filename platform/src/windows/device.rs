@@ -1,4 +1,6 @@
-use moses_core::{Device, DeviceInfo, DeviceManager, DeviceType, MosesError, Partition, PermissionLevel};
+use moses_core::{
+    Device, DeviceInfo, DeviceManager, DevicePowerState, DeviceType, MosesError, Partition, PermissionLevel,
+};
 use std::fs::File;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -38,6 +40,8 @@ struct WindowsPartition {
     partition_number: u32,
     #[serde(rename = "DriveLetter")]
     drive_letter: Option<String>,
+    #[serde(rename = "Offset", default)]
+    offset: u64,
     #[serde(rename = "Size")]
     size: u64,
     #[serde(rename = "Type")]
@@ -61,6 +65,46 @@ struct WmiDiskDrive {
 pub struct WindowsDeviceManager;
 
 impl WindowsDeviceManager {
+    /// Ask the disk driver whether the media accepts writes (IOCTL_DISK_IS_WRITABLE).
+    /// Opening with no access rights works without elevation.
+    fn is_write_protected(device_path: &str) -> bool {
+        use windows::core::PCWSTR;
+        use windows::Win32::Foundation::{CloseHandle, ERROR_WRITE_PROTECT, HANDLE};
+        use windows::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        };
+        use windows::Win32::System::Ioctl::IOCTL_DISK_IS_WRITABLE;
+        use windows::Win32::System::IO::DeviceIoControl;
+        
+        let wide: Vec<u16> = device_path.encode_utf16().chain(std::iter::once(0)).collect();
+        unsafe {
+            let handle = match CreateFileW(
+                PCWSTR(wide.as_ptr()),
+                0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                None,
+                OPEN_EXISTING,
+                FILE_FLAGS_AND_ATTRIBUTES(0),
+                HANDLE::default(),
+            ) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    log::debug!("Could not open {} to check write protection: {}", device_path, e);
+                    return false;
+                }
+            };
+            
+            let mut returned = 0u32;
+            let result = DeviceIoControl(handle, IOCTL_DISK_IS_WRITABLE, None, 0, None, 0, Some(&mut returned), None);
+            let _ = CloseHandle(handle);
+            
+            match result {
+                Ok(()) => false,
+                Err(e) => e.code() == ERROR_WRITE_PROTECT.to_hresult(),
+            }
+        }
+    }
+    
     /// Detect filesystem type by reading boot sector signatures
     fn detect_filesystem(device_path: &str) -> Option<String> {
         log::debug!("Detecting filesystem for device: {}", device_path);
@@ -319,7 +363,7 @@ impl WindowsDeviceManager {
             .args(&[
                 "-NoProfile",
                 "-Command",
-                &format!("Get-Partition | Where-Object {{$_.DiskNumber -eq {}}} | Select-Object DiskNumber, PartitionNumber, DriveLetter, Size, Type | ConvertTo-Json", disk_number)
+                &format!("Get-Partition | Where-Object {{$_.DiskNumber -eq {}}} | Select-Object DiskNumber, PartitionNumber, DriveLetter, Offset, Size, Type | ConvertTo-Json", disk_number)
             ])
             .output();
         
@@ -343,6 +387,7 @@ impl WindowsDeviceManager {
                     
                     Partition {
                         id: format!("Partition{}", p.partition_number),
+                        start_offset: p.offset,
                         size: p.size,
                         filesystem: p.partition_type,
                         mount_point,
@@ -475,8 +520,13 @@ impl DeviceManager for WindowsDeviceManager {
                           format!("\\\\.\\PHYSICALDRIVE{}", disk.number), filesystem);
             }
             
+            let device_path = format!("\\\\.\\PHYSICALDRIVE{}", disk.number);
             devices.push(Device {
-                id: format!("\\\\.\\PHYSICALDRIVE{}", disk.number),
+                is_write_protected: Self::is_write_protected(&device_path),
+                serial: None,
+                erase_block_size: None,
+                partition_offset: None,
+                id: device_path,
                 name,
                 size: disk.size,
                 device_type,
@@ -502,11 +552,7 @@ impl DeviceManager for WindowsDeviceManager {
 
     async fn get_device_by_id(&self, device_id: &str) -> Result<Option<Device>, MosesError> {
         // Extract disk number from device ID
-        let disk_number = if let Some(num_str) = device_id.strip_prefix("\\\\.\\PHYSICALDRIVE") {
-            num_str.parse::<u32>().ok()
-        } else {
-            None
-        };
+        let disk_number = moses_core::physical_drive_number(device_id);
         
         if let Some(disk_num) = disk_number {
             log::debug!("Getting single device info for disk {}", disk_num);
@@ -580,6 +626,10 @@ impl DeviceManager for WindowsDeviceManager {
                 is_removable,
                 is_system: disk.is_system || disk.is_boot,
                 filesystem,
+                is_write_protected: Self::is_write_protected(device_id),
+                serial: None,
+                erase_block_size: None,
+                partition_offset: None,
             }))
         } else {
             Ok(None)
@@ -587,10 +637,8 @@ impl DeviceManager for WindowsDeviceManager {
     }
     async fn get_device_info(&self, device: &Device) -> Result<DeviceInfo, MosesError> {
         // Extract disk number from device ID
-        let disk_number = device.id
-            .trim_start_matches("\\\\.\\PHYSICALDRIVE")
-            .parse::<u32>()
-            .map_err(|_| MosesError::Other("Invalid device ID".to_string()))?;
+        let disk_number = moses_core::physical_drive_number(&device.id)
+            .ok_or_else(|| MosesError::Other("Invalid device ID".to_string()))?;
         
         let partitions = self.get_partitions(disk_number).await;
         
@@ -654,4 +702,22 @@ impl DeviceManager for WindowsDeviceManager {
             PermissionLevel::ReadOnly
         })
     }
-}
\ No newline at end of file
+    
+    async fn eject(&self, device: &Device, power_off: bool) -> Result<(), MosesError> {
+        if device.is_system {
+            return Err(MosesError::UnsafeDevice(format!("Refusing to eject system disk {}", device.name)));
+        }
+        super::power::eject(device, power_off)
+    }
+    
+    async fn set_power_state(&self, device: &Device, state: DevicePowerState) -> Result<(), MosesError> {
+        match state {
+            DevicePowerState::Active => super::power::wake(device),
+            // Spin-down timers belong to the power plan; there is no supported per-disk standby call
+            DevicePowerState::Standby => Err(MosesError::NotSupported(format!(
+                "Standby for {} is managed by the Windows power plan",
+                device.name
+            ))),
+        }
+    }
+}
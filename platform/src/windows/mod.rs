@@ -1,5 +1,6 @@
 pub mod device;
 pub mod elevation;
+pub mod power;
 
 pub use device::WindowsDeviceManager;
-pub use elevation::{is_elevated, request_elevation_for_operation, show_elevation_prompt};
\ No newline at end of file
+pub use elevation::{is_elevated, request_elevation_for_operation, show_elevation_prompt};
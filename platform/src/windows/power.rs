@@ -0,0 +1,114 @@
+// Eject control for Windows disks
+// Every mounted volume is flushed, locked and dismounted first so Windows doesn't
+// show "still in use" later, then the disk is unlocked for removal and ejected.
+use moses_core::{Device, MosesError};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, BOOLEAN, GENERIC_READ, GENERIC_WRITE, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FlushFileBuffers, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    OPEN_EXISTING,
+};
+use windows::Win32::System::Ioctl::{
+    FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, IOCTL_STORAGE_EJECT_MEDIA, IOCTL_STORAGE_MEDIA_REMOVAL,
+    PREVENT_MEDIA_REMOVAL,
+};
+use windows::Win32::System::IO::DeviceIoControl;
+
+/// Closes the handle when dropped, which also releases a volume lock
+struct OwnedHandle(HANDLE);
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+fn open(path: &str) -> Result<OwnedHandle, MosesError> {
+    let wide: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        CreateFileW(
+            PCWSTR(wide.as_ptr()),
+            (GENERIC_READ | GENERIC_WRITE).0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            HANDLE::default(),
+        )
+    }
+    .map(OwnedHandle)
+    .map_err(|e| MosesError::Other(format!("Failed to open {}: {}", path, e)))
+}
+
+fn ioctl(handle: &OwnedHandle, code: u32, input: Option<(*const std::ffi::c_void, u32)>, what: &str) -> Result<(), MosesError> {
+    let (in_ptr, in_len) = match input {
+        Some((ptr, len)) => (Some(ptr), len),
+        None => (None, 0),
+    };
+    let mut returned = 0u32;
+    unsafe { DeviceIoControl(handle.0, code, in_ptr, in_len, None, 0, Some(&mut returned), None) }
+        .map_err(|e| MosesError::Other(format!("{} failed: {}", what, e)))
+}
+
+/// Turn a mount point such as `E:\` into the volume path `\\.\E:`
+fn volume_path(mount_point: &std::path::Path) -> Option<String> {
+    let text = mount_point.to_string_lossy();
+    let letter = text.chars().next().filter(|c| c.is_ascii_alphabetic())?;
+    if text.chars().nth(1) != Some(':') {
+        return None;
+    }
+    Some(format!("\\\\.\\{}:", letter.to_ascii_uppercase()))
+}
+
+/// Flush, lock and dismount every volume, then eject the disk.
+/// Windows decides on its own when to cut port power after a removal, so `power_off`
+/// behaves like a plain eject here.
+pub fn eject(device: &Device, power_off: bool) -> Result<(), MosesError> {
+    if power_off {
+        log::info!("Power-off on Windows is handled by the eject; the port is powered down by the OS");
+    }
+
+    // Volume locks must stay held until the disk has been ejected
+    let mut locked_volumes = Vec::new();
+    for mount_point in &device.mount_points {
+        let Some(path) = volume_path(mount_point) else {
+            continue;
+        };
+        let volume = open(&path)?;
+        unsafe { FlushFileBuffers(volume.0) }
+            .map_err(|e| MosesError::Other(format!("Failed to flush {}: {}", path, e)))?;
+        ioctl(&volume, FSCTL_LOCK_VOLUME, None, &format!("Locking {} (files still open?)", path))
+            .map_err(|e| MosesError::UnsafeDevice(e.to_string()))?;
+        ioctl(&volume, FSCTL_DISMOUNT_VOLUME, None, &format!("Dismounting {}", path))?;
+        locked_volumes.push(volume);
+    }
+
+    let disk = open(&device.id)?;
+    unsafe { FlushFileBuffers(disk.0) }
+        .map_err(|e| MosesError::Other(format!("Failed to flush {}: {}", device.id, e)))?;
+
+    let allow_removal = PREVENT_MEDIA_REMOVAL { PreventMediaRemoval: BOOLEAN(0) };
+    ioctl(
+        &disk,
+        IOCTL_STORAGE_MEDIA_REMOVAL,
+        Some((&allow_removal as *const _ as *const std::ffi::c_void, std::mem::size_of::<PREVENT_MEDIA_REMOVAL>() as u32)),
+        "Allowing media removal",
+    )?;
+    ioctl(&disk, IOCTL_STORAGE_EJECT_MEDIA, None, &format!("Ejecting {}", device.name))?;
+
+    drop(locked_volumes);
+    Ok(())
+}
+
+/// Wake the drive by reading its first sector
+pub fn wake(device: &Device) -> Result<(), MosesError> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(&device.id)
+        .map_err(|e| MosesError::Other(format!("Failed to open {}: {}", device.id, e)))?;
+    let mut sector = [0u8; 512];
+    file.read_exact(&mut sector)
+        .map_err(|e| MosesError::Other(format!("Failed to wake {}: {}", device.name, e)))
+}
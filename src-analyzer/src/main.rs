@@ -1,7 +1,7 @@
 // Standalone analyzer binary that can be elevated
 use std::env;
 use moses_core::Device;
-// use moses_filesystems::diagnostics::analyze_unknown_filesystem;
+use moses_filesystems::diagnostics::analyze_unknown_filesystem;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -22,8 +22,14 @@ fn main() {
     };
     
     // Run analysis
-    // TODO: Re-implement analyze_unknown_filesystem after refactoring
-    println!("Analysis functionality temporarily disabled during refactoring");
-    println!("Device: {:?}", device);
-    std::process::exit(0);
+    match analyze_unknown_filesystem(&device) {
+        Ok(analysis) => {
+            print!("{}", analysis.to_report());
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Error: Analysis failed: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
\ No newline at end of file
@@ -5,29 +5,24 @@ use std::env;
 use std::fs;
 use std::path::Path;
 use std::io::Write;
-use moses_core::{Device, FormatOptions, FilesystemFormatter, MosesError};
-use moses_filesystems::{Fat16Formatter, Fat32Formatter, ExFatFormatter};
-// use moses_filesystems::diagnostics::analyze_unknown_filesystem;
+use moses_core::{
+    Device, FormatOptions, MosesError, FilesystemCache, CachedFilesystemInfo,
+    PostOperationAction, MosesConfig,
+};
+use moses_filesystems::diagnostics::{analyze_unknown_filesystem, analyze_unknown_filesystem_with_progress, AnalysisDepth};
+use moses_filesystems::watch::DirectoryChange;
 use serde_json;
 use moses_filesystems::disk_manager::{
     DiskManager, DiskCleaner, CleanOptions,
-    PartitionStyleConverter, PartitionStyle,
+    PartitionStyleConverter, PartitionStyle, ConvertOptions, BootCodeAction, SignatureWiper,
+    ConflictDetector,
 };
-#[cfg(target_os = "windows")]
-use moses_filesystems::{Ext2Formatter, Ext3Formatter};
 use serde::{Deserialize, Serialize};
 use log::{Record, Level, Metadata, LevelFilter};
 use std::net::TcpStream;
 use std::io::{BufReader, BufRead};
 use std::sync::Mutex;
 
-
-#[cfg(target_os = "windows")]
-use moses_filesystems::Ext4NativeFormatter;
-
-#[cfg(target_os = "linux")]
-use moses_filesystems::Ext4NativeFormatter;
-
 // Global log file path for this worker instance
 use std::sync::OnceLock;
 static LOG_FILE_PATH: OnceLock<std::path::PathBuf> = OnceLock::new();
@@ -35,6 +30,23 @@ static LOG_FILE_PATH: OnceLock<std::path::PathBuf> = OnceLock::new();
 // Global socket stream for log streaming
 static SOCKET_STREAM: OnceLock<Mutex<Option<TcpStream>>> = OnceLock::new();
 
+// Set when Moses sends Cancel for the running command; cleared as each command starts
+static CANCEL_REQUESTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// Stop flags of the running directory watches, by watch id
+static WATCHES: OnceLock<Mutex<std::collections::HashMap<String, std::sync::Arc<std::sync::atomic::AtomicBool>>>> = OnceLock::new();
+
+// How often a watch thread looks for changes; a rescan only happens when one is announced
+// or the watch's poll interval has passed
+const WATCH_TICK: std::time::Duration = std::time::Duration::from_millis(250);
+
+// How long a parser process gets to start and connect back
+const PARSER_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Account a parser process runs as when the worker cannot tell who launched it
+#[cfg(unix)]
+const NOBODY: u32 = 65534;
+
 // Simple file logging function
 fn log_to_file(msg: &str) {
     // Try to send over socket first
@@ -282,6 +294,18 @@ fn run_worker() {
         return;
     }
     
+    // Started by a worker to parse what it reads, without its privileges
+    if args.len() >= 4 && args[1] == "--parser" {
+        match args[2].parse::<u16>() {
+            Ok(port) => handle_parser_mode(port, &args[3]),
+            Err(_) => {
+                log_to_file(&format!("Invalid port number: {}", args[2]));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    
     // Check command type (legacy mode for backward compatibility)
     let command = &args[1];
     log_to_file(&format!("Command: {}", command));
@@ -331,7 +355,7 @@ fn run_worker() {
         "convert" => {
             // Convert command needs device file and target style
             if args.len() < 4 {
-                let error_msg = "Error: convert command requires <device-json-file> <target-style>";
+                let error_msg = "Error: convert command requires <device-json-file> <target-style> [boot-code]";
                 log_to_file(error_msg);
                 #[cfg(target_os = "windows")]
                 show_error_message("Invalid Arguments", error_msg);
@@ -340,7 +364,8 @@ fn run_worker() {
             
             let device_path = &args[2];
             let target_style = &args[3];
-            handle_convert(device_path, target_style);
+            let boot_code = args.get(4).map(|s| s.as_str()).unwrap_or("discard");
+            handle_convert(device_path, target_style, boot_code);
         }
         "prepare" => {
             // Prepare command needs device file, target style, and clean flag
@@ -474,7 +499,7 @@ fn handle_format(device_path: &str, options_path: &str) {
     log_to_file("========================================");
     
     // Use tokio runtime for async operations
-    let runtime = match tokio::runtime::Runtime::new() {
+    let runtime = match MosesConfig::global().concurrency.runtime() {
         Ok(rt) => rt,
         Err(e) => {
             let error_msg = format!("Failed to create tokio runtime: {}", e);
@@ -519,6 +544,7 @@ async fn execute_format(device: Device, options: FormatOptions) -> Result<String
     if device.is_system {
         return Err("Cannot format system drive".to_string());
     }
+    device.ensure_writable().map_err(|e| e.to_string())?;
     
     // Check critical mount points
     for mount in &device.mount_points {
@@ -532,6 +558,21 @@ async fn execute_format(device: Device, options: FormatOptions) -> Result<String
         }
     }
     
+    // The drive may have been swapped since the GUI enumerated it
+    {
+        use moses_core::DeviceManager;
+        moses_platform::PlatformDeviceManager.verify_unchanged(&device).await
+            .map_err(|e| e.to_string())?;
+    }
+    
+    // Never silently break the other members of a striped/spanned volume
+    {
+        use moses_core::DeviceManager;
+        let peers = moses_platform::PlatformDeviceManager.enumerate_devices().await.unwrap_or_default();
+        ConflictDetector::verify_multi_device_acknowledged(&device, &peers, &options)
+            .map_err(|e| e.to_string())?;
+    }
+    
     log_to_file(&format!("Executing format with filesystem type: {}", options.filesystem_type));
     
     // Clean disk first if there's an existing filesystem and we're creating a partition table
@@ -544,10 +585,12 @@ async fn execute_format(device: Device, options: FormatOptions) -> Result<String
         log_to_file(&format!("Existing filesystem detected ({}), cleaning disk first", 
                             device.filesystem.as_ref().unwrap()));
         
-        use moses_filesystems::disk_manager::{DiskCleaner, CleanOptions, WipeMethod};
+        use moses_filesystems::disk_manager::{DiskCleaner, CleanOptions, WipeMethod, BootCodeAction};
         let clean_options = CleanOptions {
             wipe_method: WipeMethod::Quick,
             zero_entire_disk: false,
+            boot_code: BootCodeAction::Discard,
+            throttle: Default::default(),
         };
         
         match DiskCleaner::clean(&device, &clean_options) {
@@ -563,209 +606,66 @@ async fn execute_format(device: Device, options: FormatOptions) -> Result<String
         }
     }
     
-    // Execute format based on filesystem type
-    match options.filesystem_type.as_str() {
-        "ext2" => {
-            #[cfg(target_os = "windows")]
-            {
-                log_to_file("Using Ext2Formatter");
-                let formatter = Ext2Formatter;
-                
-                log_to_file("Validating options...");
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                log_to_file("Checking if device can be formatted...");
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted".to_string());
-                }
-                
-                log_to_file("Starting format...");
-                match formatter.format(&device, &options).await {
-                    Ok(_) => {
-                        log_to_file("Format completed successfully");
-                        Ok(format!("Successfully formatted {} as ext2", device.name))
-                    }
-                    Err(e) => {
-                        let error_msg = format!("Format failed: {:?}", e);
-                        log_to_file(&error_msg);
-                        Err(error_msg)
-                    }
-                }
-            }
-            
-            #[cfg(not(target_os = "windows"))]
-            {
-                Err("ext2 formatting not yet implemented on this platform".to_string())
-            }
-        },
-        
-        "ext3" => {
-            #[cfg(target_os = "windows")]
-            {
-                log_to_file("Using Ext3Formatter");
-                let formatter = Ext3Formatter;
-                
-                log_to_file("Validating options...");
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                log_to_file("Checking if device can be formatted...");
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted".to_string());
-                }
-                
-                log_to_file("Starting format...");
-                match formatter.format(&device, &options).await {
-                    Ok(_) => {
-                        log_to_file("Format completed successfully");
-                        Ok(format!("Successfully formatted {} as ext3", device.name))
-                    }
-                    Err(e) => {
-                        let error_msg = format!("Format failed: {:?}", e);
-                        log_to_file(&error_msg);
-                        Err(error_msg)
-                    }
-                }
-            }
-            
-            #[cfg(not(target_os = "windows"))]
-            {
-                Err("ext3 formatting not yet implemented on this platform".to_string())
-            }
-        },
-        
-        "ext4" => {
-            #[cfg(target_os = "windows")]
-            {
-                log_to_file("Using Ext4NativeFormatter");
-                let formatter = Ext4NativeFormatter;
-                
-                log_to_file("Validating options...");
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                log_to_file("Checking if device can be formatted...");
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted".to_string());
-                }
-                
-                log_to_file("Starting format...");
-                match formatter.format(&device, &options).await {
-                    Ok(_) => {
-                        log_to_file("Format completed successfully");
-                        Ok(format!("Successfully formatted {} as EXT4", device.name))
-                    }
-                    Err(e) => {
-                        let error_msg = format!("Format failed: {:?}", e);
-                        log_to_file(&error_msg);
-                        Err(error_msg)
-                    }
-                }
-            }
-            
-            #[cfg(target_os = "linux")]
-            {
-                let formatter = Ext4NativeFormatter;
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted".to_string());
+    // Reject a bad post-format action before touching the disk
+    let post_action = PostOperationAction::from_options(&options).map_err(|e| e.to_string())?;
+    
+    // The cached filesystem for this device is no longer valid, whatever the outcome
+    FilesystemCache::global().invalidate(device);
+    
+    // Resolve the formatter through the registry, like the GUI and the CLI
+    let selected = moses_filesystems::resolve_formatter(moses_filesystems::builtin_registry(), &device, &options)
+        .map_err(|e| {
+            log_to_file(&format!("No formatter: {}", e));
+            e.to_string()
+        })?;
+    let formatter = &selected.formatter;
+    log_to_file(&format!("Using {} formatter, {}", formatter.name(), selected.describe()));
+    
+    log_to_file("Validating options...");
+    formatter.validate_options(&options)
+        .await
+        .map_err(|e| format!("Invalid options: {}", e))?;
+    
+    log_to_file("Checking if device can be formatted...");
+    if !formatter.can_format(&device) {
+        return Err(format!("Device cannot be formatted as {}", options.filesystem_type));
+    }
+    
+    moses_filesystems::disk_manager::history::record_before(&device, format!("format as {}", options.filesystem_type));
+    log_to_file("Starting format...");
+    if let Err(e) = formatter.format(&device, &options).await {
+        let error_msg = format!("Format failed: {:?}", e);
+        log_to_file(&error_msg);
+        return Err(error_msg);
+    }
+    log_to_file("Format completed successfully");
+    let message = format!("Successfully formatted {} as {}", device.name, formatter.name());
+    
+    // Remove leftovers of the previous filesystem so nothing detects two at once
+    let note = SignatureWiper::cleanup_after_format(&device, &options.filesystem_type);
+    FilesystemCache::global().insert(device, 0, CachedFilesystemInfo::detected_now(options.filesystem_type.clone()));
+    
+    let message = match note {
+        Some(note) => {
+            log_to_file(&note);
+            format!("{}\n{}", message, note)
+        }
+        None => message,
+    };
+    
+    // The format itself succeeded, so a failed eject/standby is only reported
+    match post_action {
+        Some(action) => {
+            log_to_file(&format!("Running post-format action: {}", action.as_str()));
+            match action.apply(&moses_platform::PlatformDeviceManager, &device).await {
+                Ok(()) => Ok(format!("{}\nDevice {}: done", message, action.as_str())),
+                Err(e) => {
+                    log_to_file(&format!("Post-format {} failed: {}", action.as_str(), e));
+                    Ok(format!("{}\nWarning: {} failed: {}", message, action.as_str(), e))
                 }
-                
-                formatter.format(&device, &options)
-                    .await
-                    .map_err(|e| format!("Format failed: {}", e))?;
-                
-                Ok(format!("Successfully formatted {} as EXT4", device.name))
             }
-            
-            #[cfg(target_os = "macos")]
-            {
-                Err("EXT4 formatting not yet implemented on macOS".to_string())
-            }
-        },
-        
-        "ntfs" => {
-            log_to_file("NTFS formatting not yet implemented");
-            return Err("NTFS formatting is not yet implemented. Only NTFS reading is currently supported.".to_string());
-        },
-        
-        "fat16" => {
-            log_to_file("Using Fat16Formatter");
-            let formatter = Fat16Formatter;
-            
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted".to_string());
-            }
-            
-            // Check size limit
-            if device.size > 4 * 1024 * 1024 * 1024 {
-                return Err("Device too large for FAT16. Maximum size is 4GB.".to_string());
-            }
-            
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as FAT16", device.name))
-        },
-        
-        "fat32" => {
-            log_to_file("Using Fat32Formatter");
-            let formatter = Fat32Formatter;
-            
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted".to_string());
-            }
-            
-            // Check size limit
-            if device.size > 2 * 1024_u64.pow(4) {
-                return Err("Device too large for FAT32. Maximum size is 2TB.".to_string());
-            }
-            
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as FAT32", device.name))
-        },
-        
-        "exfat" => {
-            log_to_file("Using ExFatFormatter");
-            let formatter = ExFatFormatter;
-            
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted".to_string());
-            }
-            
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as exFAT", device.name))
-        },
-        
-        _ => {
-            Err(format!("Unsupported filesystem type: {}", options.filesystem_type))
         }
+        None => Ok(message),
     }
 }
 
@@ -814,7 +714,9 @@ fn handle_analyze(device_path: &str) {
     log_to_file(&format!("Analyzing device: {} ({})", device.name, device.id));
     
     // Perform the analysis
-    let analysis_result: Result<String, MosesError> = Err(MosesError::Other("Analysis functionality temporarily disabled during refactoring".to_string()));
+    let analysis_result: Result<String, MosesError> = analyze_unknown_filesystem(&device)
+        .and_then(|analysis| serde_json::to_string_pretty(&analysis)
+            .map_err(|e| MosesError::Other(format!("Failed to serialize analysis: {}", e))));
     match analysis_result {
         Ok(report) => {
             log_to_file("Analysis completed successfully");
@@ -899,6 +801,13 @@ fn handle_clean(device_path: &str, options_path: &str) {
     
     log_to_file(&format!("Cleaning {} with method {:?}", device.name, options.wipe_method));
     
+    if let Err(error_msg) = revalidate_device(&device) {
+        log_to_file(&error_msg);
+        #[cfg(target_os = "windows")]
+        show_error_message("Device Changed", &error_msg);
+        std::process::exit(1);
+    }
+    
     // Perform the clean
     match DiskCleaner::clean(&device, &options) {
         Ok(_) => {
@@ -916,7 +825,7 @@ fn handle_clean(device_path: &str, options_path: &str) {
     }
 }
 
-fn handle_convert(device_path: &str, target_style: &str) {
+fn handle_convert(device_path: &str, target_style: &str, boot_code: &str) {
     log_to_file(&format!("Converting device from file: {} to {}", device_path, target_style));
     
     // Read device JSON
@@ -956,10 +865,29 @@ fn handle_convert(device_path: &str, target_style: &str) {
         }
     };
     
-    log_to_file(&format!("Converting {} to {:?}", device.name, style));
+    let boot_code = match BootCodeAction::parse(boot_code) {
+        Some(action) => action,
+        None => {
+            let error_msg = format!("Invalid boot code action: {}", boot_code);
+            log_to_file(&error_msg);
+            #[cfg(target_os = "windows")]
+            show_error_message("Invalid Boot Code Action", &error_msg);
+            std::process::exit(1);
+        }
+    };
+    
+    log_to_file(&format!("Converting {} to {:?} (boot code: {:?})", device.name, style, boot_code));
+    
+    if let Err(error_msg) = revalidate_device(&device) {
+        log_to_file(&error_msg);
+        #[cfg(target_os = "windows")]
+        show_error_message("Device Changed", &error_msg);
+        std::process::exit(1);
+    }
     
     // Perform the conversion
-    match PartitionStyleConverter::convert(&device, style) {
+    let options = ConvertOptions { boot_code };
+    match PartitionStyleConverter::convert_with_options(&device, style, &options) {
         Ok(_) => {
             log_to_file("Conversion completed successfully");
             println!("Conversion completed successfully");
@@ -1131,6 +1059,110 @@ fn handle_read_directory(device_path: &str, directory_path: &str) {
     }
 }
 
+/// Re-read the device from the platform right before a destructive write, so a different
+/// drive plugged in at the same path since the GUI enumerated it is never written to
+fn revalidate_device(device: &Device) -> Result<(), String> {
+    use moses_core::DeviceManager;
+    let runtime = MosesConfig::global().concurrency.runtime()
+        .map_err(|e| format!("Failed to create runtime: {}", e))?;
+    runtime.block_on(moses_platform::PlatformDeviceManager.verify_unchanged(device))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Watch `path` on `device` on its own thread, pushing a DirectoryChanged for every change
+/// until Unwatch; a watch that can no longer read the directory ends with WatchEnded
+fn start_watch(device: Device, path: String, watch_id: String) -> Result<(), String> {
+    use moses_filesystems::{register_all_filesystems, FilesystemOpsRegistry};
+    use moses_filesystems::watch::{DirectoryWatch, OpenOps};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Detect once; each rescan opens the volume afresh as that filesystem
+    let mut registry = FilesystemOpsRegistry::new();
+    register_all_filesystems(&mut registry, false);
+    let filesystem = registry.create_ops(&device, None)
+        .map_err(|e| format!("Failed to open {}: {}", device.id, e))?
+        .filesystem_type().to_string();
+    let open: OpenOps = {
+        let device = device.clone();
+        Box::new(move || {
+            let mut registry = FilesystemOpsRegistry::new();
+            register_all_filesystems(&mut registry, false);
+            registry.create_ops(&device, Some(filesystem.as_str()))
+        })
+    };
+    let mut watch = DirectoryWatch::new(&device, &path, open)
+        .map_err(|e| format!("Failed to watch {} on {}: {}", path, device.name, e))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let watches = WATCHES.get_or_init(Default::default);
+    if let Some(previous) = watches.lock().ok().and_then(|mut watches| watches.insert(watch_id.clone(), stop.clone())) {
+        previous.store(true, Ordering::SeqCst);
+    }
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(WATCH_TICK);
+            match watch.check() {
+                Ok(Some(change)) => {
+                    log_to_file(&format!("{} changed: {} added, {} removed, {} modified",
+                        change.path, change.added.len(), change.removed.len(), change.modified.len()));
+                    push_response(&WorkerResponse::DirectoryChanged { watch_id: watch_id.clone(), change });
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log_to_file(&format!("Watch of {} ended: {}", watch.path(), e));
+                    if let Ok(mut watches) = watches.lock() {
+                        if watches.get(&watch_id).is_some_and(|current| Arc::ptr_eq(current, &stop)) {
+                            watches.remove(&watch_id);
+                        }
+                    }
+                    push_response(&WorkerResponse::WatchEnded { watch_id, reason: e.to_string() });
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Stop a directory watch; false if there was none with that id
+fn stop_watch(watch_id: &str) -> bool {
+    let stopped = WATCHES.get()
+        .and_then(|watches| watches.lock().ok()?.remove(watch_id));
+    if let Some(stop) = &stopped {
+        stop.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+    stopped.is_some()
+}
+
+/// Send Moses a response it did not ask for, like a directory change, between the
+/// responses to its commands
+fn push_response(response: &WorkerResponse) {
+    let Ok(json) = serde_json::to_string(response) else {
+        return;
+    };
+    if let Some(stream_mutex) = SOCKET_STREAM.get() {
+        if let Ok(mut guard) = stream_mutex.lock() {
+            if let Some(ref mut stream) = *guard {
+                // One write per line, so it cannot land inside another response
+                let _ = stream.write_all(format!("{}\n", json).as_bytes());
+                let _ = stream.flush();
+            }
+        }
+    }
+}
+
+/// Why a command needs the machine kept awake, if it writes to the disk for a while
+fn long_running_reason(command: &WorkerCommand) -> Option<&'static str> {
+    match command {
+        WorkerCommand::Format { .. } => Some("Formatting a disk"),
+        WorkerCommand::Clean { .. } => Some("Wiping a disk"),
+        WorkerCommand::Convert { .. } | WorkerCommand::Prepare { .. } => Some("Rewriting a partition table"),
+        _ => None,
+    }
+}
+
 // Socket mode structures (must match worker_server.rs)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "command", content = "params")]
@@ -1145,10 +1177,21 @@ enum WorkerCommand {
     },
     Analyze {
         device: Device,
+        #[serde(default)]
+        depth: AnalysisDepth,
+    },
+    Detect {
+        device: Device,
+    },
+    Eject {
+        device: Device,
+        power_off: bool,
     },
     Convert {
         device: Device,
         target_style: String,
+        #[serde(default)]
+        boot_code: BootCodeAction,
     },
     Prepare {
         device: Device,
@@ -1159,8 +1202,27 @@ enum WorkerCommand {
         device: Device,
         path: String,
     },
+    WatchDirectory {
+        device: Device,
+        path: String,
+        watch_id: String,
+    },
+    Unwatch {
+        watch_id: String,
+    },
+    // The answer to a parser process's ReadBlocks, base64 encoded; only passes between a
+    // worker and its parser process
+    Blocks {
+        offset: u64,
+        data: String,
+    },
+    BlocksFailed {
+        offset: u64,
+        error: String,
+    },
     Ping,
     Shutdown,
+    Cancel,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1168,9 +1230,14 @@ enum WorkerCommand {
 enum WorkerResponse {
     Success(String),
     Error(String),
-    Progress { percent: u8, message: String },
+    Progress(moses_core::ProgressUpdate),
+    AnalysisProgress(String), // JSON serialized AnalysisProgress
     Log { level: String, message: String },
     DirectoryListing(String), // JSON serialized directory listing
+    DirectoryChanged { watch_id: String, change: DirectoryChange },
+    WatchEnded { watch_id: String, reason: String },
+    // A parser process asking its worker for part of the device
+    ReadBlocks { offset: u64, length: usize },
     Pong,
 }
 
@@ -1230,8 +1297,26 @@ fn handle_socket_mode(port: u16) {
     
     let reader = BufReader::new(stream.try_clone().expect("Failed to clone stream"));
     
+    // Lines are read on their own thread so a Cancel can arrive while a command runs
+    let (sender, lines) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            if let Ok(l) = &line {
+                if matches!(serde_json::from_str(l), Ok(WorkerCommand::Cancel)) {
+                    log_to_file("Cancel requested");
+                    CANCEL_REQUESTED.store(true, std::sync::atomic::Ordering::SeqCst);
+                    continue;
+                }
+            }
+            let failed = line.is_err();
+            if sender.send(line).is_err() || failed {
+                break;
+            }
+        }
+    });
+    
     // Main command loop
-    for line in reader.lines() {
+    for line in lines {
         let line = match line {
             Ok(l) => l,
             Err(e) => {
@@ -1251,11 +1336,18 @@ fn handle_socket_mode(port: u16) {
         };
         
         log_to_file(&format!("Received command: {:?}", command));
+        CANCEL_REQUESTED.store(false, std::sync::atomic::Ordering::SeqCst);
+        
+        // Don't let the machine sleep halfway through a disk write; released after the response
+        let _keep_awake = long_running_reason(&command).map(moses_platform::KeepAwake::acquire);
         
         // Execute command and send response
         let response = match command {
             WorkerCommand::Ping => WorkerResponse::Pong,
             
+            // Handled by the reader thread; one arriving here had nothing left to stop
+            WorkerCommand::Cancel => continue,
+            
             WorkerCommand::Shutdown => {
                 log_to_file("Received shutdown command");
                 send_response(&mut stream, WorkerResponse::Success("Shutting down".to_string()));
@@ -1266,7 +1358,7 @@ fn handle_socket_mode(port: u16) {
                 log_to_file(&format!("Executing format for {}", device.name));
                 
                 // Use tokio runtime for async format operation
-                let runtime = match tokio::runtime::Runtime::new() {
+                let runtime = match MosesConfig::global().concurrency.runtime() {
                     Ok(rt) => rt,
                     Err(e) => {
                         send_response(&mut stream, WorkerResponse::Error(format!("Failed to create runtime: {}", e)));
@@ -1286,22 +1378,85 @@ fn handle_socket_mode(port: u16) {
             
             WorkerCommand::Clean { device, options } => {
                 log_to_file(&format!("Executing clean for {}", device.name));
-                match DiskCleaner::clean(&device, &options) {
+                if let Err(e) = revalidate_device(&device) {
+                    send_response(&mut stream, WorkerResponse::Error(e));
+                    continue;
+                }
+                let result = DiskCleaner::clean_with_progress(&device, &options, &mut |update| {
+                    send_response(&mut stream, WorkerResponse::Progress(update.clone()));
+                });
+                match result {
                     Ok(_) => WorkerResponse::Success("Disk cleaned successfully".to_string()),
                     Err(e) => WorkerResponse::Error(format!("Clean failed: {:?}", e)),
                 }
             }
             
-            WorkerCommand::Analyze { device } => {
-                log_to_file(&format!("Analyzing {}", device.name));
-                match Err(MosesError::Other("Analysis functionality temporarily disabled during refactoring".to_string())) {
-                    Ok(report) => WorkerResponse::Success(report),
+            command @ (WorkerCommand::Analyze { .. } | WorkerCommand::Detect { .. })
+                if MosesConfig::global().worker.sandbox_parsing =>
+            {
+                parse_sandboxed(command, &mut stream)
+            }
+            
+            // The directory readers open the device by path themselves instead of reading
+            // through a block source, so they cannot be handed to a parser process; running
+            // them here would parse the volume with the worker's rights after all
+            WorkerCommand::ReadDirectory { .. } | WorkerCommand::WatchDirectory { .. }
+                if MosesConfig::global().worker.sandbox_parsing =>
+            {
+                WorkerResponse::Error(
+                    "Browsing a drive is not available while sandboxed parsing is on; turn off \
+                     worker.sandbox_parsing in the settings to browse it".to_string(),
+                )
+            }
+            
+            WorkerCommand::Analyze { device, depth } => {
+                log_to_file(&format!("Analyzing {} ({})", device.name, depth.as_str()));
+                let result = analyze_unknown_filesystem_with_progress(&device, depth, &mut |progress| {
+                    if let Ok(json) = serde_json::to_string(progress) {
+                        send_response(&mut stream, WorkerResponse::AnalysisProgress(json));
+                    }
+                    if CANCEL_REQUESTED.load(std::sync::atomic::Ordering::SeqCst) {
+                        std::ops::ControlFlow::Break(())
+                    } else {
+                        std::ops::ControlFlow::Continue(())
+                    }
+                });
+                match result {
+                    Ok(analysis) => match serde_json::to_string(&analysis) {
+                        Ok(report) => WorkerResponse::Success(report),
+                        Err(e) => WorkerResponse::Error(format!("Failed to serialize analysis: {}", e)),
+                    },
                     Err(e) => WorkerResponse::Error(format!("Analysis failed: {:?}", e)),
                 }
             }
             
-            WorkerCommand::Convert { device, target_style } => {
-                log_to_file(&format!("Converting {} to {}", device.name, target_style));
+            WorkerCommand::Detect { device } => {
+                log_to_file(&format!("Detecting filesystem on {}", device.name));
+                let detected = moses_filesystems::utils::open_device_read(&device)
+                    .and_then(|mut file| moses_filesystems::detection::detect_filesystem(&mut file));
+                match detected {
+                    Ok(fs_type) => WorkerResponse::Success(fs_type),
+                    Err(e) => WorkerResponse::Error(format!("Detection failed: {:?}", e)),
+                }
+            }
+            
+            WorkerCommand::Eject { device, power_off } => {
+                log_to_file(&format!("Ejecting {} (power off: {})", device.name, power_off));
+                let result = MosesConfig::global().concurrency.runtime()
+                    .map_err(|e| format!("Failed to create runtime: {}", e))
+                    .and_then(|runtime| runtime.block_on(async {
+                        use moses_core::DeviceManager;
+                        moses_platform::PlatformDeviceManager.eject(&device, power_off).await
+                            .map_err(|e| e.to_string())
+                    }));
+                match result {
+                    Ok(()) => WorkerResponse::Success(format!("{} can be safely removed", device.name)),
+                    Err(e) => WorkerResponse::Error(format!("Eject failed: {}", e)),
+                }
+            }
+            
+            WorkerCommand::Convert { device, target_style, boot_code } => {
+                log_to_file(&format!("Converting {} to {} (boot code: {:?})", device.name, target_style, boot_code));
                 let style = match target_style.as_str() {
                     "mbr" => PartitionStyle::MBR,
                     "gpt" => PartitionStyle::GPT,
@@ -1312,7 +1467,12 @@ fn handle_socket_mode(port: u16) {
                     }
                 };
                 
-                match PartitionStyleConverter::convert(&device, style) {
+                if let Err(e) = revalidate_device(&device) {
+                    send_response(&mut stream, WorkerResponse::Error(e));
+                    continue;
+                }
+                let options = ConvertOptions { boot_code };
+                match PartitionStyleConverter::convert_with_options(&device, style, &options) {
                     Ok(_) => WorkerResponse::Success(format!("Converted to {} successfully", target_style)),
                     Err(e) => WorkerResponse::Error(format!("Conversion failed: {:?}", e)),
                 }
@@ -1330,12 +1490,37 @@ fn handle_socket_mode(port: u16) {
                     }
                 };
                 
+                if let Err(e) = revalidate_device(&device) {
+                    send_response(&mut stream, WorkerResponse::Error(e));
+                    continue;
+                }
                 match DiskManager::prepare_disk(&device, style, clean_first) {
                     Ok(report) => WorkerResponse::Success(format!("Disk prepared: {:?}", report)),
                     Err(e) => WorkerResponse::Error(format!("Preparation failed: {:?}", e)),
                 }
             }
             
+            WorkerCommand::WatchDirectory { device, path, watch_id } => {
+                log_to_file(&format!("Watching {} on {} as {}", path, device.name, watch_id));
+                match start_watch(device, path.clone(), watch_id) {
+                    Ok(()) => WorkerResponse::Success(format!("Watching {}", path)),
+                    Err(e) => WorkerResponse::Error(e),
+                }
+            }
+            
+            WorkerCommand::Blocks { .. } | WorkerCommand::BlocksFailed { .. } => {
+                WorkerResponse::Error("Block data is only sent to a parser process".to_string())
+            }
+            
+            WorkerCommand::Unwatch { watch_id } => {
+                log_to_file(&format!("Stopping watch {}", watch_id));
+                if stop_watch(&watch_id) {
+                    WorkerResponse::Success(format!("Stopped watch {}", watch_id))
+                } else {
+                    WorkerResponse::Success(format!("Watch {} had already ended", watch_id))
+                }
+            }
+            
             WorkerCommand::ReadDirectory { device, path } => {
                 log_to_file(&format!("Reading directory {} on {}", path, device.name));
                 
@@ -1387,6 +1572,310 @@ fn handle_socket_mode(port: u16) {
     log_to_file("Worker shutting down");
 }
 
+/// The unprivileged process a worker hands parsing to, connected over a socket of its own.
+/// Each command gets a fresh one, so nothing a parser bug did outlives the command.
+struct ParserProcess {
+    stream: TcpStream,
+    lines: std::io::Lines<BufReader<TcpStream>>,
+    child: Option<std::process::Child>,
+}
+
+impl ParserProcess {
+    fn start() -> Result<Self, String> {
+        use std::net::TcpListener;
+        use std::time::Instant;
+        
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| format!("Failed to listen for a parser process: {}", e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        
+        // Anyone on this machine can connect to the port, and the worker reads raw blocks for
+        // whoever does, so the parser proves it was started here with a token from a file
+        // only it can read
+        let token = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+        let token_file = env::temp_dir().join(format!("moses-parser-{}-{}.token", std::process::id(), port));
+        let result = write_token(&token_file, &token)
+            .and_then(|_| Self::spawn(port, &token_file))
+            .and_then(|mut child| {
+                let deadline = Instant::now() + PARSER_CONNECT_TIMEOUT;
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let _ = stream.set_nonblocking(false);
+                            let mut lines = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?).lines();
+                            if lines.next().and_then(Result::ok).is_some_and(|line| line.trim() == token) {
+                                return Ok(Self { stream, lines, child });
+                            }
+                            log_to_file("Refused a parser connection without the token");
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                            std::thread::sleep(std::time::Duration::from_millis(50));
+                        }
+                        Err(e) => {
+                            if let Some(child) = &mut child {
+                                let _ = child.kill();
+                                let _ = child.wait();
+                            }
+                            return Err(format!("The parser process did not connect: {}", e));
+                        }
+                    }
+                }
+            });
+        let _ = fs::remove_file(&token_file);
+        result
+    }
+    
+    /// Start `moses-worker --parser` without the worker's rights: as the user who launched
+    /// Moses (nobody if unknown) on Unix, with a basic user token on Windows
+    fn spawn(port: u16, token_file: &Path) -> Result<Option<std::process::Child>, String> {
+        let exe = env::current_exe().map_err(|e| format!("Failed to find the worker executable: {}", e))?;
+        
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            
+            let id = |name: &str| env::var(name).ok().and_then(|id| id.parse::<u32>().ok()).filter(|&id| id != 0);
+            let uid = id("SUDO_UID").or_else(|| id("PKEXEC_UID")).unwrap_or(NOBODY);
+            let gid = id("SUDO_GID").unwrap_or(NOBODY);
+            let path = std::ffi::CString::new(token_file.as_os_str().as_encoded_bytes()).map_err(|e| e.to_string())?;
+            if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+                return Err(format!("Failed to hand the parser token to uid {}: {}", uid, std::io::Error::last_os_error()));
+            }
+            std::process::Command::new(&exe)
+                .arg("--parser").arg(port.to_string()).arg(token_file)
+                .uid(uid)
+                .gid(gid)
+                .spawn()
+                .map(Some)
+                .map_err(|e| format!("Failed to start a parser process as uid {}: {}", uid, e))
+        }
+        
+        // runas returns once the parser has started, so there is no child to wait for
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            let program = format!("\\\"{}\\\" --parser {} \\\"{}\\\"", exe.display(), port, token_file.display());
+            std::process::Command::new("runas")
+                .raw_arg("/trustlevel:0x20000")
+                .raw_arg(format!("\"{}\"", program))
+                .status()
+                .map(|_| None)
+                .map_err(|e| format!("Failed to start a parser process: {}", e))
+        }
+    }
+    
+    fn send(&mut self, command: &WorkerCommand) -> Result<(), String> {
+        let json = serde_json::to_string(command).map_err(|e| e.to_string())?;
+        self.stream.write_all(format!("{}\n", json).as_bytes())
+            .and_then(|_| self.stream.flush())
+            .map_err(|e| format!("Lost the parser process: {}", e))
+    }
+}
+
+impl Drop for ParserProcess {
+    fn drop(&mut self) {
+        let _ = self.send(&WorkerCommand::Shutdown);
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        if let Some(child) = &mut self.child {
+            for _ in 0..20 {
+                if matches!(child.try_wait(), Ok(Some(_))) {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Write the parser token where only this user can read it
+fn write_token(path: &Path, token: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .map_err(|e| format!("Failed to write the parser token: {}", e))
+}
+
+/// Run Analyze or Detect in a parser process, answering its block reads from the device
+/// and passing its progress and logs on to Moses
+fn parse_sandboxed(mut command: WorkerCommand, stream: &mut TcpStream) -> WorkerResponse {
+    use base64::Engine;
+    use moses_filesystems::device_reader::AlignedDeviceReader;
+    use moses_filesystems::remote_blocks::serve_blocks;
+    use std::io::{Seek, SeekFrom};
+    use std::sync::atomic::Ordering;
+    
+    let device = match &mut command {
+        WorkerCommand::Analyze { device, .. } | WorkerCommand::Detect { device } => device,
+        _ => return WorkerResponse::Error("Only analysis and detection run in a parser process".to_string()),
+    };
+    log_to_file(&format!("Parsing {} in a parser process", device.name));
+    let mut reader = match moses_filesystems::utils::open_device_with_fallback(device) {
+        Ok(file) => AlignedDeviceReader::new(file),
+        Err(e) => return WorkerResponse::Error(format!("Failed to open {}: {}", device.id, e)),
+    };
+    // The parser cannot look at the device, so it is told how large it is
+    if device.size == 0 {
+        device.size = reader.seek(SeekFrom::End(0)).unwrap_or(0);
+    }
+    let (size, name) = (device.size, device.name.clone());
+    
+    let mut parser = match ParserProcess::start() {
+        Ok(parser) => parser,
+        Err(e) => return WorkerResponse::Error(e),
+    };
+    if let Err(e) = parser.send(&command) {
+        return WorkerResponse::Error(e);
+    }
+    let mut cancel_sent = false;
+    while let Some(Ok(line)) = parser.lines.next() {
+        if !cancel_sent && CANCEL_REQUESTED.load(Ordering::SeqCst) {
+            cancel_sent = parser.send(&WorkerCommand::Cancel).is_ok();
+        }
+        let response: WorkerResponse = match serde_json::from_str(&line) {
+            Ok(response) => response,
+            Err(e) => return WorkerResponse::Error(format!("The parser process sent an invalid response: {}", e)),
+        };
+        match response {
+            WorkerResponse::ReadBlocks { offset, length } => {
+                let reply = match serve_blocks(&mut reader, size, offset, length) {
+                    Ok(data) => WorkerCommand::Blocks { offset, data: base64::engine::general_purpose::STANDARD.encode(data) },
+                    Err(e) => WorkerCommand::BlocksFailed { offset, error: e.to_string() },
+                };
+                if let Err(e) = parser.send(&reply) {
+                    return WorkerResponse::Error(e);
+                }
+            }
+            WorkerResponse::Log { .. } | WorkerResponse::Progress(_) | WorkerResponse::AnalysisProgress(_) => {
+                send_response(stream, response);
+            }
+            response => return response,
+        }
+    }
+    WorkerResponse::Error(format!(
+        "The parser process ended without an answer; it may have crashed on what it read from {}", name,
+    ))
+}
+
+/// Parse for a worker without its rights: connect back, prove it with the token, and run
+/// Analyze and Detect on blocks the worker reads and sends over
+fn handle_parser_mode(port: u16, token_file: &str) {
+    use base64::Engine;
+    use moses_filesystems::remote_blocks::RemoteBlockSource;
+    use std::sync::atomic::Ordering;
+    use std::sync::{mpsc, Arc};
+    
+    #[cfg(unix)]
+    let privileged = unsafe { libc::geteuid() } == 0;
+    #[cfg(target_os = "windows")]
+    let privileged = moses_platform::windows::elevation::is_elevated();
+    if privileged {
+        log_to_file("ERROR: The parser process must not run with administrator or root rights");
+        std::process::exit(1);
+    }
+    
+    let token = match fs::read_to_string(token_file) {
+        Ok(token) => token,
+        Err(e) => {
+            log_to_file(&format!("Failed to read the parser token: {}", e));
+            std::process::exit(1);
+        }
+    };
+    let mut stream = match TcpStream::connect(format!("127.0.0.1:{}", port)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            log_to_file(&format!("Failed to connect to the worker: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if stream.write_all(format!("{}\n", token.trim()).as_bytes()).is_err() {
+        std::process::exit(1);
+    }
+    if let Ok(log_stream) = stream.try_clone() {
+        let _ = SOCKET_STREAM.set(Mutex::new(Some(log_stream)));
+    }
+    log_to_file(&format!("Parser process {} connected", std::process::id()));
+    
+    // Block data goes to the read waiting for it, commands to the loop below
+    let (block_sender, blocks) = mpsc::channel::<(u64, Result<Vec<u8>, String>)>();
+    let (command_sender, commands) = mpsc::channel();
+    let reader = BufReader::new(stream.try_clone().expect("Failed to clone stream"));
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else { break };
+            let sent = match serde_json::from_str(&line) {
+                Ok(WorkerCommand::Cancel) => {
+                    CANCEL_REQUESTED.store(true, Ordering::SeqCst);
+                    true
+                }
+                Ok(WorkerCommand::Blocks { offset, data }) => {
+                    let data = base64::engine::general_purpose::STANDARD.decode(data).map_err(|e| e.to_string());
+                    block_sender.send((offset, data)).is_ok()
+                }
+                Ok(WorkerCommand::BlocksFailed { offset, error }) => block_sender.send((offset, Err(error))).is_ok(),
+                Ok(command) => command_sender.send(command).is_ok(),
+                Err(e) => {
+                    log_to_file(&format!("Failed to parse command: {}", e));
+                    true
+                }
+            };
+            if !sent {
+                break;
+            }
+        }
+    });
+    let blocks = Arc::new(Mutex::new(blocks));
+    let remote = |device: &Device| {
+        let blocks = blocks.clone();
+        RemoteBlockSource::new(device.size, Box::new(move |offset, length| {
+            push_response(&WorkerResponse::ReadBlocks { offset, length });
+            let blocks = blocks.lock().map_err(|_| MosesError::Other("Block channel poisoned".to_string()))?;
+            match blocks.recv() {
+                Ok((answered, data)) if answered == offset => data.map_err(MosesError::Other),
+                Ok((answered, _)) => Err(MosesError::Other(format!("Asked for {:#x}, got {:#x}", offset, answered))),
+                Err(_) => Err(MosesError::Other("The worker closed the connection".to_string())),
+            }
+        }))
+    };
+    
+    for command in commands {
+        CANCEL_REQUESTED.store(false, Ordering::SeqCst);
+        let response = match command {
+            WorkerCommand::Analyze { device, depth } => {
+                let result = moses_filesystems::diagnostics::analyze_unknown_with_progress(&mut remote(&device), device.size, depth, &mut |progress| {
+                    if let Ok(json) = serde_json::to_string(progress) {
+                        push_response(&WorkerResponse::AnalysisProgress(json));
+                    }
+                    if CANCEL_REQUESTED.load(Ordering::SeqCst) {
+                        std::ops::ControlFlow::Break(())
+                    } else {
+                        std::ops::ControlFlow::Continue(())
+                    }
+                });
+                match result.and_then(|analysis| serde_json::to_string(&analysis).map_err(|e| MosesError::Other(e.to_string()))) {
+                    Ok(report) => WorkerResponse::Success(report),
+                    Err(e) => WorkerResponse::Error(format!("Analysis failed: {:?}", e)),
+                }
+            }
+            WorkerCommand::Detect { device } => {
+                match moses_filesystems::detection::detect_filesystem(&mut remote(&device)) {
+                    Ok(fs_type) => WorkerResponse::Success(fs_type),
+                    Err(e) => WorkerResponse::Error(format!("Detection failed: {:?}", e)),
+                }
+            }
+            WorkerCommand::Shutdown => break,
+            _ => WorkerResponse::Error("A parser process only analyzes and detects".to_string()),
+        };
+        push_response(&response);
+    }
+    log_to_file("Parser process exiting");
+}
+
 fn send_response(stream: &mut TcpStream, response: WorkerResponse) {
     let json = match serde_json::to_string(&response) {
         Ok(j) => j,
@@ -1396,16 +1885,13 @@ fn send_response(stream: &mut TcpStream, response: WorkerResponse) {
         }
     };
     
-    if let Err(e) = stream.write_all(json.as_bytes()) {
+    // One write per line, so a directory change pushed from a watch thread cannot land
+    // between the response and its newline
+    if let Err(e) = stream.write_all(format!("{}\n", json).as_bytes()) {
         log_to_file(&format!("Failed to send response: {}", e));
         return;
     }
     
-    if let Err(e) = stream.write_all(b"\n") {
-        log_to_file(&format!("Failed to send newline: {}", e));
-        return;
-    }
-    
     if let Err(e) = stream.flush() {
         log_to_file(&format!("Failed to flush stream: {}", e));
     }
@@ -1455,11 +1941,21 @@ fn handle_prepare(device_path: &str, target_style: &str, clean_first: bool) {
     log_to_file(&format!("Preparing {} for {:?} (clean first: {})", 
                          device.name, style, clean_first));
     
+    if let Err(error_msg) = revalidate_device(&device) {
+        log_to_file(&error_msg);
+        #[cfg(target_os = "windows")]
+        show_error_message("Device Changed", &error_msg);
+        std::process::exit(1);
+    }
+    
     // Perform the preparation
     match DiskManager::prepare_disk(&device, style, clean_first) {
         Ok(report) => {
             log_to_file(&format!("Preparation completed successfully: {:?}", report));
             println!("Preparation completed successfully");
+            for warning in &report.warnings {
+                println!("Warning: {}", warning);
+            }
             std::process::exit(0);
         }
         Err(e) => {
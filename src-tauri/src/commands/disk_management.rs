@@ -1,18 +1,48 @@
 // Tauri commands for disk management operations
-use moses_core::{Device, DeviceManager};
+use moses_core::{Device, DeviceHistory, DeviceManager, HistoryEntry};
+use moses_filesystems::throttle::IoThrottle;
 use moses_filesystems::disk_manager::{
-    CleanOptions, WipeMethod,
-    ConflictDetector, ConflictReport
+    CleanOptions, WipeMethod, BootCodeAction,
+    ConflictDetector, ConflictReport,
+    DiskCleaner, PartitionStyleConverter, PartitionStyle, ConvertOptions, OperationPlan,
+    DeviceRisk, PurgeReport, TrashReport,
 };
 use moses_platform::PlatformDeviceManager;
 
 #[cfg(not(target_os = "windows"))]
-use moses_filesystems::disk_manager::{
-    DiskManager, DiskCleaner,
-    PartitionStyleConverter, PartitionStyle,
-};
+use moses_filesystems::disk_manager::DiskManager;
 use serde::{Deserialize, Serialize};
 
+// Helper function to parse an optional boot code action from the GUI
+fn parse_boot_code(value: Option<&str>) -> Result<BootCodeAction, String> {
+    match value {
+        None => Ok(BootCodeAction::Discard),
+        Some(s) => BootCodeAction::parse(s)
+            .ok_or_else(|| format!("Invalid boot code action: {}", s)),
+    }
+}
+
+// Helper function to parse the wipe method names used by the GUI
+fn parse_wipe_method(value: &str) -> Result<WipeMethod, String> {
+    match value {
+        "quick" => Ok(WipeMethod::Quick),
+        "zero" => Ok(WipeMethod::Zero),
+        "dod" => Ok(WipeMethod::DoD5220),
+        "random" => Ok(WipeMethod::Random),
+        _ => Err(format!("Invalid wipe method: {}", value)),
+    }
+}
+
+// Helper function to parse the partition style names used by the GUI
+fn parse_partition_style(value: &str) -> Result<PartitionStyle, String> {
+    match value {
+        "mbr" => Ok(PartitionStyle::MBR),
+        "gpt" => Ok(PartitionStyle::GPT),
+        "uninitialized" => Ok(PartitionStyle::Uninitialized),
+        _ => Err(format!("Invalid partition style: {}", value)),
+    }
+}
+
 // Helper function to get device by ID
 async fn get_device_by_id(device_id: &str) -> Option<Device> {
     let manager = PlatformDeviceManager;
@@ -34,12 +64,24 @@ async fn get_device_by_id(device_id: &str) -> Option<Device> {
 pub struct CleanDiskRequest {
     pub device_id: String,
     pub wipe_method: String, // "quick", "zero", "dod", "random"
+    /// "discard" (default), "preserve" or "standard"
+    #[serde(default)]
+    pub boot_code: Option<String>,
+    /// Run at idle I/O priority so the machine stays usable
+    #[serde(default)]
+    pub background: bool,
+    /// Throughput cap in MB/s
+    #[serde(default)]
+    pub max_mb_per_sec: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConvertPartitionStyleRequest {
     pub device_id: String,
     pub target_style: String, // "mbr", "gpt", "uninitialized"
+    /// "discard" (default), "preserve" or "standard"
+    #[serde(default)]
+    pub boot_code: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,18 +106,14 @@ pub async fn clean_disk(
         return Err("Cannot clean system disk".to_string());
     }
     
-    // Parse wipe method
-    let wipe_method = match request.wipe_method.as_str() {
-        "quick" => WipeMethod::Quick,
-        "zero" => WipeMethod::Zero,
-        "dod" => WipeMethod::DoD5220,
-        "random" => WipeMethod::Random,
-        _ => return Err(format!("Invalid wipe method: {}", request.wipe_method)),
-    };
+    let wipe_method = parse_wipe_method(&request.wipe_method)?;
+    let boot_code = parse_boot_code(request.boot_code.as_deref())?;
     
     let options = CleanOptions {
         wipe_method,
         zero_entire_disk: wipe_method != WipeMethod::Quick,
+        boot_code,
+        throttle: IoThrottle::new(request.max_mb_per_sec, request.background),
     };
     
     // Execute clean operation (needs elevation)
@@ -198,12 +236,68 @@ pub async fn clean_disk(
     #[cfg(not(target_os = "windows"))]
     {
         // On Unix systems, attempt direct clean (requires root)
-        DiskCleaner::clean(&device, &options)
+        let _keep_awake = moses_platform::KeepAwake::acquire("Wiping a disk");
+        DiskCleaner::clean_with_progress(&device, &options, &mut |update| crate::progress::emit(update))
             .map(|_| "Disk cleaned successfully".to_string())
             .map_err(|e| format!("Clean failed: {:?}", e))
     }
 }
 
+/// Show what `clean_disk` would overwrite without touching the disk
+#[tauri::command]
+pub async fn simulate_clean_disk(
+    request: CleanDiskRequest,
+) -> Result<OperationPlan, String> {
+    let device = get_device_by_id(&request.device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", request.device_id))?;
+    
+    let wipe_method = parse_wipe_method(&request.wipe_method)?;
+    let options = CleanOptions {
+        wipe_method,
+        zero_entire_disk: wipe_method != WipeMethod::Quick,
+        boot_code: parse_boot_code(request.boot_code.as_deref())?,
+        throttle: IoThrottle::new(request.max_mb_per_sec, request.background),
+    };
+    Ok(DiskCleaner::dry_run(&device, &options))
+}
+
+/// Show what `convert_partition_style` would overwrite without touching the disk
+#[tauri::command]
+pub async fn simulate_convert_partition_style(
+    request: ConvertPartitionStyleRequest,
+) -> Result<OperationPlan, String> {
+    let device = get_device_by_id(&request.device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", request.device_id))?;
+    
+    let target_style = parse_partition_style(&request.target_style)?;
+    let options = ConvertOptions { boot_code: parse_boot_code(request.boot_code.as_deref())? };
+    Ok(PartitionStyleConverter::dry_run(&device, target_style, &options))
+}
+
+/// What Moses recorded about a device before each operation, newest first
+#[tauri::command]
+pub async fn get_device_history(
+    device: Device,
+) -> Result<Vec<HistoryEntry>, String> {
+    Ok(DeviceHistory::global().entries(&device))
+}
+
+/// Write back the most recently saved partition table of a device
+#[tauri::command]
+pub async fn restore_device_layout(
+    device: Device,
+) -> Result<String, String> {
+    let entry = DeviceHistory::global().entries(&device)
+        .into_iter()
+        .find(|entry| entry.before.table_sectors.is_some())
+        .ok_or_else(|| "No saved partition table for this device".to_string())?;
+    moses_filesystems::disk_manager::history::restore_layout(&device, &entry.before)
+        .map(|_| format!("Restored the layout from before \"{}\"", entry.operation))
+        .map_err(|e| format!("Restore failed: {}", e))
+}
+
 /// Detect partition table conflicts
 #[tauri::command]
 pub async fn detect_conflicts(
@@ -215,10 +309,47 @@ pub async fn detect_conflicts(
         .ok_or_else(|| format!("Device not found: {}", device_id))?;
     
     // Run conflict detection
-    ConflictDetector::analyze(&device)
+    // Other devices are needed to list the members of striped/spanned volumes
+    let peers = PlatformDeviceManager.enumerate_devices().await.unwrap_or_default();
+    ConflictDetector::analyze_with_peers(&device, &peers)
         .map_err(|e| format!("Analysis failed: {:?}", e))
 }
 
+/// Score every enumerated device by how dangerous it is to format, with the reasons,
+/// so the device list can be sorted and high-risk targets confirmed twice
+#[tauri::command]
+pub async fn assess_device_risks() -> Result<Vec<DeviceRisk>, String> {
+    let devices = PlatformDeviceManager.enumerate_devices()
+        .await
+        .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
+    tokio::task::spawn_blocking(move || moses_filesystems::disk_manager::risk::assess_devices(&devices))
+        .await
+        .map_err(|e| format!("Risk assessment failed: {}", e))
+}
+
+/// Recycle bins and trash folders on each volume of the device, with their sizes
+#[tauri::command]
+pub async fn analyze_trash(device_id: String) -> Result<Vec<TrashReport>, String> {
+    let device = get_device_by_id(&device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", device_id))?;
+    tokio::task::spawn_blocking(move || moses_filesystems::disk_manager::trash::analyze_trash(&device))
+        .await
+        .map_err(|e| format!("Trash analysis failed: {}", e))
+}
+
+/// Permanently delete the contents of the device's recycle bins and trash folders
+#[tauri::command]
+pub async fn purge_trash(device_id: String) -> Result<PurgeReport, String> {
+    let device = get_device_by_id(&device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", device_id))?;
+    tokio::task::spawn_blocking(move || moses_filesystems::disk_manager::trash::purge_trash(&device))
+        .await
+        .map_err(|e| format!("Purge failed: {}", e))?
+        .map_err(|e| format!("Purge failed: {}", e))
+}
+
 /// Convert partition table style
 #[tauri::command]
 pub async fn convert_partition_style(
@@ -234,14 +365,13 @@ pub async fn convert_partition_style(
         return Err("Cannot convert system disk partition style".to_string());
     }
     
+    let boot_code = parse_boot_code(request.boot_code.as_deref())?;
+    
     // Execute conversion (needs elevation)
     #[cfg(target_os = "windows")]
     {
         // Validate target style
-        match request.target_style.as_str() {
-            "mbr" | "gpt" | "uninitialized" => {},
-            _ => return Err(format!("Invalid partition style: {}", request.target_style)),
-        }
+        parse_partition_style(&request.target_style)?;
         use std::process::Command;
         use std::env;
         
@@ -264,6 +394,7 @@ pub async fn convert_partition_style(
             .arg("convert")
             .arg(&device_file)
             .arg(&request.target_style)
+            .arg(boot_code.as_str())
             .output()
             .map_err(|e| format!("Failed to run elevated worker: {}", e))?;
         
@@ -279,14 +410,9 @@ pub async fn convert_partition_style(
     
     #[cfg(not(target_os = "windows"))]
     {
-        let target_style = match request.target_style.as_str() {
-            "mbr" => PartitionStyle::MBR,
-            "gpt" => PartitionStyle::GPT,
-            "uninitialized" => PartitionStyle::Uninitialized,
-            _ => return Err(format!("Invalid partition style: {}", request.target_style)),
-        };
-        
-        PartitionStyleConverter::convert(&device, target_style)
+        let target_style = parse_partition_style(&request.target_style)?;
+        let options = ConvertOptions { boot_code };
+        PartitionStyleConverter::convert_with_options(&device, target_style, &options)
             .map(|_| format!("Converted to {:?} successfully", target_style))
             .map_err(|e| format!("Conversion failed: {:?}", e))
     }
@@ -311,10 +437,7 @@ pub async fn prepare_disk(
     #[cfg(target_os = "windows")]
     {
         // Validate target style
-        match request.target_style.as_str() {
-            "mbr" | "gpt" | "uninitialized" => {},
-            _ => return Err(format!("Invalid partition style: {}", request.target_style)),
-        }
+        parse_partition_style(&request.target_style)?;
         use std::process::Command;
         use std::env;
         
@@ -344,7 +467,13 @@ pub async fn prepare_disk(
         let _ = std::fs::remove_file(device_file);
         
         if output.status.success() {
-            Ok("Disk prepared successfully".to_string())
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let mut message = "Disk prepared successfully".to_string();
+            for warning in stdout.lines().filter(|line| line.starts_with("Warning: ")) {
+                message.push('\n');
+                message.push_str(warning);
+            }
+            Ok(message)
         } else {
             let stderr = String::from_utf8_lossy(&output.stderr);
             Err(format!("Preparation failed: {}", stderr))
@@ -370,6 +499,9 @@ pub async fn prepare_disk(
         if let Some(style) = report.final_style {
             message.push_str(&format!("Final partition style: {:?}", style));
         }
+        for warning in &report.warnings {
+            message.push_str(&format!("\nWarning: {}", warning));
+        }
         
         Ok(message)
     }
@@ -384,6 +516,7 @@ pub async fn quick_clean(
         CleanDiskRequest {
             device_id,
             wipe_method: "quick".to_string(),
+            boot_code: None,
         },
     ).await
 }
@@ -1,16 +1,36 @@
 // Disk management commands using socket-based worker
 use moses_core::Device;
+use moses_filesystems::diagnostics::AnalysisDepth;
+use moses_filesystems::throttle::IoThrottle;
 use moses_filesystems::disk_manager::{
-    CleanOptions, WipeMethod,
+    CleanOptions, WipeMethod, BootCodeAction,
     ConflictDetector, ConflictReport
 };
 use serde::{Deserialize, Serialize};
-use crate::worker_server::{WorkerCommand, WorkerResponse, get_worker_server};
+use crate::worker_server::{WorkerCommand, WorkerResponse, ElevatedCommandError, run_on_worker};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CleanDiskRequest {
     pub device_id: String,
     pub wipe_method: String,
+    /// "discard" (default), "preserve" or "standard"
+    #[serde(default)]
+    pub boot_code: Option<String>,
+    /// Run at idle I/O priority so the machine stays usable
+    #[serde(default)]
+    pub background: bool,
+    /// Throughput cap in MB/s
+    #[serde(default)]
+    pub max_mb_per_sec: Option<u64>,
+}
+
+// Helper function to parse an optional boot code action from the GUI
+fn parse_boot_code(value: Option<&str>) -> Result<BootCodeAction, String> {
+    match value {
+        None => Ok(BootCodeAction::Discard),
+        Some(s) => BootCodeAction::parse(s)
+            .ok_or_else(|| format!("Invalid boot code action: {}", s)),
+    }
 }
 
 // Helper function to get device by ID
@@ -37,7 +57,7 @@ async fn get_device_by_id(device_id: &str) -> Option<Device> {
 #[tauri::command]
 pub async fn clean_disk_socket(
     request: CleanDiskRequest,
-) -> Result<String, String> {
+) -> Result<String, ElevatedCommandError> {
     // Get the device by ID
     let device = get_device_by_id(&request.device_id)
         .await
@@ -45,7 +65,7 @@ pub async fn clean_disk_socket(
     
     // Safety check
     if device.is_system {
-        return Err("Cannot clean system disk".to_string());
+        return Err("Cannot clean system disk".to_string().into());
     }
     
     // Parse wipe method
@@ -54,33 +74,27 @@ pub async fn clean_disk_socket(
         "zero" => WipeMethod::Zero,
         "dod" => WipeMethod::DoD5220,
         "random" => WipeMethod::Random,
-        _ => return Err(format!("Invalid wipe method: {}", request.wipe_method)),
+        _ => return Err(format!("Invalid wipe method: {}", request.wipe_method).into()),
     };
     
+    let boot_code = parse_boot_code(request.boot_code.as_deref())?;
+    
     let options = CleanOptions {
         wipe_method,
         zero_entire_disk: wipe_method != WipeMethod::Quick,
+        boot_code,
+        throttle: IoThrottle::new(request.max_mb_per_sec, request.background),
     };
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send clean command to worker
     let command = WorkerCommand::Clean {
         device,
         options,
     };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(msg)) => Ok(msg),
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+    match run_on_worker("Wiping a disk", command).await? {
+        WorkerResponse::Success(msg) => Ok(msg),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
 }
 
@@ -89,28 +103,20 @@ pub async fn clean_disk_socket(
 pub async fn format_disk_socket(
     device: Device,
     options: moses_core::FormatOptions,
-) -> Result<String, String> {
+) -> Result<String, ElevatedCommandError> {
     // Safety check
     if device.is_system {
-        return Err("Cannot format system disk".to_string());
+        return Err("Cannot format system disk".to_string().into());
     }
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send format command to worker
     let command = WorkerCommand::Format {
         device: device.clone(),
         options: options.clone(),
     };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(msg)) => {
+    match run_on_worker("Formatting a disk", command).await? {
+        WorkerResponse::Success(msg) => {
             // After successful format, update both caches
             // This ensures Moses immediately recognizes the new filesystem
             
@@ -121,22 +127,16 @@ pub async fn format_disk_socket(
                 log::info!("Updated in-memory cache for {} to {}", device.id, options.filesystem_type);
             }
             
-            // Update persistent cache
-            use crate::filesystem_cache::{self, CachedFilesystemInfo};
-            let cache_info = CachedFilesystemInfo {
-                filesystem: options.filesystem_type.clone(),
-                partition_table: Some("mbr".to_string()), // Assume MBR for now
-                partitions: vec![],
-                detected_at: std::time::SystemTime::now(),
-            };
-            filesystem_cache::cache_filesystem_info(&device.id, cache_info);
+            // Update persistent cache; the worker already bumped the device generation
+            use moses_core::fs_cache::{FilesystemCache, CachedFilesystemInfo};
+            let mut cache_info = CachedFilesystemInfo::detected_now(options.filesystem_type.clone());
+            cache_info.partition_table = Some("mbr".to_string()); // Assume MBR for now
+            FilesystemCache::global().insert(device, 0, cache_info);
             log::info!("Updated persistent cache for {} to {}", device.id, options.filesystem_type);
             
             Ok(msg)
         }
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
 }
 
@@ -151,7 +151,10 @@ pub async fn detect_conflicts_socket(
         .ok_or_else(|| format!("Device not found: {}", device_id))?;
     
     // Run conflict detection locally (doesn't need elevation)
-    ConflictDetector::analyze(&device)
+    // Other devices are needed to list the members of striped/spanned volumes
+    use moses_core::DeviceManager;
+    let peers = moses_platform::PlatformDeviceManager.enumerate_devices().await.unwrap_or_default();
+    ConflictDetector::analyze_with_peers(&device, &peers)
         .map_err(|e| format!("Analysis failed: {:?}", e))
 }
 
@@ -159,28 +162,19 @@ pub async fn detect_conflicts_socket(
 #[tauri::command]
 pub async fn analyze_filesystem_socket(
     device_id: String,
-) -> Result<String, String> {
+    depth: Option<AnalysisDepth>,
+) -> Result<String, ElevatedCommandError> {
     // Get the device by ID
     let device = get_device_by_id(&device_id)
         .await
         .ok_or_else(|| format!("Device not found: {}", device_id))?;
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send analyze command to worker
-    let command = WorkerCommand::Analyze { device };
+    let command = WorkerCommand::Analyze { device, depth: depth.unwrap_or_default() };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(analysis_json)) => Ok(analysis_json),
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+    match run_on_worker("Analyzing a filesystem", command).await? {
+        WorkerResponse::Success(analysis_json) => Ok(analysis_json),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
 }
 
@@ -188,7 +182,7 @@ pub async fn analyze_filesystem_socket(
 #[tauri::command]
 pub async fn detect_filesystem_socket(
     device_id: String,
-) -> Result<String, String> {
+) -> Result<String, ElevatedCommandError> {
     // Try cache first
     use crate::commands::filesystem::FILESYSTEM_CACHE;
     if let Ok(cache) = FILESYSTEM_CACHE.lock() {
@@ -203,22 +197,17 @@ pub async fn detect_filesystem_socket(
         .await
         .ok_or_else(|| format!("Device not found: {}", device_id))?;
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send detect command to worker (reuse Analyze command)
-    let command = WorkerCommand::Analyze { device };
+    let command = WorkerCommand::Analyze { device, depth: AnalysisDepth::Quick };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(result)) => {
+    match run_on_worker("Detecting a filesystem", command).await? {
+        WorkerResponse::Success(result) => {
             // Parse the analysis result to get filesystem type
             if let Ok(analysis) = serde_json::from_str::<serde_json::Value>(&result) {
-                if let Some(fs_type) = analysis.get("filesystem_type").and_then(|v| v.as_str()) {
+                let fs_type = analysis.get("filesystem_type")
+                    .or_else(|| analysis.get("filesystem"))
+                    .and_then(|v| v.as_str());
+                if let Some(fs_type) = fs_type {
                     // Cache the result
                     if let Ok(mut cache) = FILESYSTEM_CACHE.lock() {
                         cache.insert(device_id.clone(), fs_type.to_string());
@@ -228,18 +217,16 @@ pub async fn detect_filesystem_socket(
             }
             
             // If we can't parse, try direct filesystem detection
-            Err("Could not determine filesystem type".to_string())
+            Err("Could not determine filesystem type".to_string().into())
         }
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
 }/// Convert partition table style using the persistent worker
 #[tauri::command]
 pub async fn convert_partition_style_socket(
     device_id: String,
     target_style: String,
-) -> Result<String, String> {
+) -> Result<String, ElevatedCommandError> {
     // Get the device by ID
     let device = get_device_by_id(&device_id)
         .await
@@ -247,34 +234,24 @@ pub async fn convert_partition_style_socket(
     
     // Safety check
     if device.is_system {
-        return Err("Cannot convert system disk partition style".to_string());
+        return Err("Cannot convert system disk partition style".to_string().into());
     }
     
     // Validate target style
     match target_style.as_str() {
         "mbr" | "gpt" | "uninitialized" => {},
-        _ => return Err(format!("Invalid partition style: {}", target_style)),
+        _ => return Err(format!("Invalid partition style: {}", target_style).into()),
     }
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send convert command to worker
     let command = WorkerCommand::Convert {
         device,
         target_style: target_style.clone(),
     };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(msg)) => Ok(msg),
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+    match run_on_worker("Converting the partition table", command).await? {
+        WorkerResponse::Success(msg) => Ok(msg),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
 }
 
@@ -284,7 +261,7 @@ pub async fn prepare_disk_socket(
     device_id: String,
     target_style: String,
     clean_first: bool,
-) -> Result<String, String> {
+) -> Result<String, ElevatedCommandError> {
     // Get the device by ID
     let device = get_device_by_id(&device_id)
         .await
@@ -292,23 +269,15 @@ pub async fn prepare_disk_socket(
     
     // Safety check
     if device.is_system {
-        return Err("Cannot prepare system disk".to_string());
+        return Err("Cannot prepare system disk".to_string().into());
     }
     
     // Validate target style
     match target_style.as_str() {
         "mbr" | "gpt" | "uninitialized" => {},
-        _ => return Err(format!("Invalid partition style: {}", target_style)),
+        _ => return Err(format!("Invalid partition style: {}", target_style).into()),
     }
     
-    // Get the worker server
-    let server_arc = get_worker_server().await
-        .map_err(|e| format!("Failed to get worker server: {}", e))?;
-    
-    let mut server_guard = server_arc.lock().await;
-    let server = server_guard.as_mut()
-        .ok_or_else(|| "Worker server not initialized".to_string())?;
-    
     // Send prepare command to worker
     let command = WorkerCommand::Prepare {
         device,
@@ -316,10 +285,57 @@ pub async fn prepare_disk_socket(
         clean_first,
     };
     
-    match server.execute_command(command).await {
-        Ok(WorkerResponse::Success(msg)) => Ok(msg),
-        Ok(WorkerResponse::Error(err)) => Err(err),
-        Ok(_) => Err("Unexpected response from worker".to_string()),
-        Err(e) => Err(format!("Worker communication failed: {}", e)),
+    match run_on_worker("Preparing a disk", command).await? {
+        WorkerResponse::Success(msg) => Ok(msg),
+        _ => Err("Unexpected response from worker".to_string().into()),
     }
-}
\ No newline at end of file
+}
+/// Flush and release a removable device so it can be unplugged.
+/// Locking volumes needs admin rights on Windows, so it goes through the worker there.
+#[tauri::command]
+pub async fn eject_device_socket(
+    device_id: String,
+    power_off: bool,
+) -> Result<String, ElevatedCommandError> {
+    let device = get_device_by_id(&device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", device_id))?;
+    
+    if device.is_system {
+        return Err("Cannot eject the system drive".to_string().into());
+    }
+    
+    #[cfg(target_os = "windows")]
+    {
+        match run_on_worker("Ejecting a device", WorkerCommand::Eject { device, power_off }).await? {
+            WorkerResponse::Success(msg) => Ok(msg),
+            _ => Err("Unexpected response from worker".to_string().into()),
+        }
+    }
+    
+    #[cfg(not(target_os = "windows"))]
+    {
+        use moses_core::DeviceManager;
+        moses_platform::PlatformDeviceManager.eject(&device, power_off)
+            .await
+            .map(|_| format!("{} can be safely removed", device.name))
+            .map_err(|e| format!("Eject failed: {}", e).into())
+    }
+}
+
+/// Spin a drive down to standby or wake it up
+#[tauri::command]
+pub async fn set_device_power_state(
+    device_id: String,
+    state: moses_core::DevicePowerState,
+) -> Result<(), String> {
+    use moses_core::DeviceManager;
+    
+    let device = get_device_by_id(&device_id)
+        .await
+        .ok_or_else(|| format!("Device not found: {}", device_id))?;
+    
+    moses_platform::PlatformDeviceManager.set_power_state(&device, state)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -4,9 +4,13 @@ use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::sync::Mutex;
 use once_cell::sync::Lazy;
-use moses_filesystems::device_reader::FilesystemReader;
-// // use // moses_filesystems::diagnostics::analyze_unknown_filesystem;
-use crate::filesystem_cache;
+use moses_filesystems::device_reader::{FileEntry, FilesystemReader};
+use moses_filesystems::preview;
+use moses_filesystems::diagnostics::{analyze_unknown_filesystem_with_progress, AnalysisDepth, AnalysisEstimate, UnknownFilesystemAnalysis};
+use moses_core::fs_cache::{FilesystemCache, CachedFilesystemInfo, PartitionInfo};
+use moses_filesystems::transfer::{FilesystemLimits, LimitViolation, TransferFilter, TransferPreview, TransferReport};
+use moses_filesystems::{register_all_filesystems, FilesystemOps, FilesystemOpsRegistry, TreeStats};
+use std::path::{Path, PathBuf};
 
 // Cache for filesystem types to avoid repeated admin prompts
 pub(crate) static FILESYSTEM_CACHE: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| {
@@ -22,11 +26,14 @@ pub struct DirectoryEntry {
     pub modified: Option<DateTime<Utc>>,
     pub created: Option<DateTime<Utc>>,
     pub permissions: Option<String>,
+    /// Detected MIME type for files: by extension, or by content when that is cheap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<FilesystemMetadata>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct FilesystemMetadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compressed: Option<bool>,
@@ -35,9 +42,23 @@ pub struct FilesystemMetadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reparse_point: Option<String>, // Type of reparse point
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,   // Where a link or junction points
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub allocated_size: Option<u64>,   // Actual size on disk
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mft_record: Option<u64>,       // MFT record number for NTFS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readonly: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,         // Owner SID and name on NTFS
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub access: Vec<String>,           // Simplified ACL, one line per entry
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -82,6 +103,10 @@ pub async fn read_directory_elevated(
             filesystem: Some(filesystem.clone()),
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
         }
     } else {
         // Enumerate to find the device
@@ -121,40 +146,10 @@ pub async fn read_directory_elevated(
                 .map_err(|e| format!("Failed to parse directory listing: {}", e))?;
             
             if result["success"].as_bool().unwrap_or(false) {
-                let entries = result["entries"].as_array()
-                    .ok_or("Missing entries in result")?;
-                
-                // Convert to DirectoryListing
-                let mut total_size = 0u64;
-                let mut converted_entries = Vec::new();
+                let entries: Vec<FileEntry> = serde_json::from_value(result["entries"].clone())
+                    .map_err(|e| format!("Malformed entries in result: {}", e))?;
                 
-                for entry in entries {
-                    let name = entry["name"].as_str().unwrap_or("").to_string();
-                    let is_directory = entry["is_directory"].as_bool().unwrap_or(false);
-                    let size = entry["size"].as_u64().unwrap_or(0);
-                    
-                    if !is_directory {
-                        total_size += size;
-                    }
-                    
-                    converted_entries.push(DirectoryEntry {
-                        name: name.clone(),
-                        path: format!("{}/{}", path.trim_end_matches('/'), name),
-                        entry_type: if is_directory { EntryType::Directory } else { EntryType::File },
-                        size: if is_directory { None } else { Some(size) },
-                        modified: None,
-                        created: None,
-                        permissions: None,
-                        metadata: None,
-                    });
-                }
-                
-                Ok(DirectoryListing {
-                    path: path.clone(),
-                    entries: converted_entries,
-                    total_size,
-                    item_count: entries.len(),
-                })
+                Ok(convert_reader_entries(entries, &path, None))
             } else {
                 Err(result["error"].as_str().unwrap_or("Unknown error").to_string())
             }
@@ -164,6 +159,42 @@ pub async fn read_directory_elevated(
     }
 }
 
+/// Watch a directory through the worker so the file browser refreshes when it changes;
+/// returns the id carried by its `directory-changed` events
+#[tauri::command]
+pub async fn watch_directory(
+    device_id: String,
+    path: String,
+    filesystem: String,
+    mount_points: Option<Vec<String>>,
+) -> Result<String, crate::worker_server::ElevatedCommandError> {
+    let device = match mount_points {
+        Some(mounts) => Device {
+            id: device_id,
+            name: String::new(),
+            size: 0,
+            device_type: moses_core::DeviceType::HardDisk,
+            mount_points: mounts.into_iter().map(PathBuf::from).collect(),
+            filesystem: Some(filesystem),
+            is_removable: false,
+            is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
+        },
+        None => get_device(&device_id).ok_or_else(|| format!("Device {} not found", device_id))?,
+    };
+    log::info!("Watching {} on {}", path, device.id);
+    crate::worker_server::watch_directory(device, path).await
+}
+
+/// Stop a watch started with `watch_directory`
+#[tauri::command]
+pub async fn unwatch_directory(watch_id: String) -> Result<(), crate::worker_server::ElevatedCommandError> {
+    crate::worker_server::unwatch_directory(watch_id).await
+}
+
 /// Read directory contents from a filesystem
 #[tauri::command]
 pub async fn read_directory(
@@ -195,6 +226,10 @@ pub async fn read_directory(
             mount_points: mount_paths,
             is_removable: false,
             is_system: false,
+            is_write_protected: false,
+            serial: None,
+            erase_block_size: None,
+            partition_offset: None,
             filesystem: Some(filesystem.clone()),
         }
     } else {
@@ -222,6 +257,21 @@ pub async fn read_directory(
         "exfat" => {
             read_exfat_directory(&device, &path).await
         },
+        "btrfs" => {
+            read_btrfs_directory(&device, &path).await
+        },
+        "xfs" => {
+            read_xfs_directory(&device, &path).await
+        },
+        "hfsplus" => {
+            read_hfsplus_directory(&device, &path).await
+        },
+        "jffs2" => {
+            read_jffs2_directory(&device, &path).await
+        },
+        "iso9660" => {
+            read_iso9660_directory(&device, &path).await
+        },
         "unknown" => {
             // For unknown filesystems, we need admin rights to detect the type
             Err("Unable to detect filesystem type. Administrator privileges may be required to read unmounted drives.".to_string())
@@ -256,28 +306,172 @@ pub async fn read_file(
     }
 }
 
+/// Preview sent to the file browser; thumbnails travel as data URLs
+#[derive(Debug, Serialize)]
+pub struct FilePreviewResponse {
+    pub mime: String,
+    pub size: u64,
+    /// "text", "image" or "none"
+    pub kind: String,
+    pub text: Option<String>,
+    pub truncated: bool,
+    pub image_data_url: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Why there is no preview, when kind is "none"
+    pub reason: Option<String>,
+}
+
+/// Show the start of a text file or a thumbnail of an image without extracting it
+#[tauri::command]
+pub async fn preview_file(
+    device_id: String,
+    filesystem: String,
+    path: String,
+    max_bytes: Option<u32>,
+) -> Result<FilePreviewResponse, String> {
+    use base64::Engine;
+    use moses_filesystems::preview::PreviewContent;
+    
+    let mut fs = open_ops(&device_id, &filesystem, false)?;
+    let max_bytes = max_bytes.unwrap_or(preview::DEFAULT_TEXT_BYTES);
+    let file = preview::preview_file(fs.as_mut(), Path::new(&path), max_bytes)
+        .map_err(|e| format!("Failed to preview {}: {}", path, e))?;
+    
+    let mut response = FilePreviewResponse {
+        mime: file.mime,
+        size: file.size,
+        kind: "none".to_string(),
+        text: None,
+        truncated: false,
+        image_data_url: None,
+        width: None,
+        height: None,
+        reason: None,
+    };
+    match file.content {
+        PreviewContent::Text { text, truncated } => {
+            response.kind = "text".to_string();
+            response.text = Some(text);
+            response.truncated = truncated;
+        }
+        PreviewContent::Image { thumbnail_png, width, height } => {
+            response.kind = "image".to_string();
+            response.image_data_url = Some(format!(
+                "data:image/png;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(thumbnail_png)
+            ));
+            response.width = Some(width);
+            response.height = Some(height);
+        }
+        PreviewContent::Unavailable { reason } => response.reason = Some(reason),
+    }
+    Ok(response)
+}
+
+/// Files, folders and total size below a path, for folder sizes in the browser
+#[tauri::command]
+pub async fn folder_stats(
+    device_id: String,
+    filesystem: String,
+    path: String,
+) -> Result<TreeStats, String> {
+    let mut fs = open_ops(&device_id, &filesystem, false)?;
+    fs.stats(Path::new(&path))
+        .map_err(|e| format!("Failed to measure {}: {}", path, e))
+}
+
+/// Count the files and bytes a filtered copy would pick up, before starting it
+#[tauri::command]
+pub async fn preview_copy(
+    source_device: String,
+    source_fs: String,
+    source_paths: Vec<String>,
+    filter: Option<TransferFilter>,
+) -> Result<TransferPreview, String> {
+    let mut source = open_ops(&source_device, &source_fs, false)?;
+    let sources: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    moses_filesystems::transfer::preview(source.as_mut(), &sources, &filter.unwrap_or_default())
+        .map_err(|e| format!("Failed to scan files: {}", e))
+}
+
+/// Items of a copy that the destination filesystem cannot store, found before copying
+///
+/// Arguments as for [`copy_files`]; an empty list means the copy fits.
+#[tauri::command]
+pub async fn check_copy_limits(
+    source_device: String,
+    source_fs: String,
+    source_paths: Vec<String>,
+    dest_device: String,
+    dest_fs: String,
+    dest_path: String,
+    filter: Option<TransferFilter>,
+) -> Result<Vec<LimitViolation>, String> {
+    let limits = if dest_device.is_empty() {
+        FilesystemLimits::for_host()
+    } else if dest_fs.is_empty() || dest_fs == "unknown" {
+        FilesystemLimits::for_filesystem(open_ops(&dest_device, &dest_fs, false)?.filesystem_type())
+    } else {
+        FilesystemLimits::for_filesystem(&dest_fs)
+    };
+    let mut source = open_ops(&source_device, &source_fs, false)?;
+    let sources: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    moses_filesystems::transfer::check_limits(source.as_mut(), &sources, &filter.unwrap_or_default(), &limits, Path::new(&dest_path))
+        .map_err(|e| format!("Failed to scan files: {}", e))
+}
+
 /// Copy files from one filesystem to another
+///
+/// With an empty `dest_device` the files go to the local folder `dest_path`; otherwise
+/// they are written into `dest_path` on the destination device's filesystem.
 #[tauri::command]
 pub async fn copy_files(
-    _source_device: String,
-    _source_fs: String,
-    _source_paths: Vec<String>,
-    _dest_device: String,
-    _dest_fs: String,
-    _dest_path: String,
-) -> Result<CopyResult, String> {
-    log::info!("Copying {} files from {} to {}", 
-              _source_paths.len(), _source_fs, _dest_fs);
+    source_device: String,
+    source_fs: String,
+    source_paths: Vec<String>,
+    dest_device: String,
+    dest_fs: String,
+    dest_path: String,
+    filter: Option<TransferFilter>,
+) -> Result<TransferReport, String> {
+    log::info!("Copying {} item(s) from {} ({}) to {}",
+              source_paths.len(), source_device, source_fs,
+              if dest_device.is_empty() { dest_path.as_str() } else { dest_device.as_str() });
     
-    // This would orchestrate the cross-filesystem copy
-    todo!("Implement cross-filesystem copy")
+    let mut filter = filter.unwrap_or_default();
+    // Read the source in the block size it was tuned for unless the caller chose one
+    if filter.block_size.is_none() {
+        if let Some(device) = get_device(&source_device) {
+            filter.block_size = Some(moses_filesystems::io_tuning::for_device(&device).block_size as u32);
+        }
+    }
+    let mut source = open_ops(&source_device, &source_fs, false)?;
+    let sources: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    let report = if dest_device.is_empty() {
+        moses_filesystems::transfer::extract_to_directory(source.as_mut(), &sources, Path::new(&dest_path), &filter)
+    } else {
+        let mut destination = open_ops(&dest_device, &dest_fs, true)?;
+        let report = moses_filesystems::transfer::copy_to_filesystem(
+            source.as_mut(), &sources, destination.as_mut(), Path::new(&dest_path), &filter,
+        );
+        destination.sync().map_err(|e| format!("Failed to flush {}: {}", dest_device, e))?;
+        report
+    };
+    report.map_err(|e| format!("Copy failed: {}", e))
 }
 
-#[derive(Debug, Serialize)]
-pub struct CopyResult {
-    pub files_copied: usize,
-    pub bytes_copied: u64,
-    pub errors: Vec<String>,
+/// Open a device's filesystem through the ops registry; "unknown" or "" detects it
+fn open_ops(device_id: &str, filesystem: &str, writable: bool) -> Result<Box<dyn FilesystemOps>, String> {
+    let device = get_device(device_id)
+        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    let mut registry = FilesystemOpsRegistry::new();
+    register_all_filesystems(&mut registry, writable);
+    let filesystem = Some(filesystem).filter(|fs| !fs.is_empty() && *fs != "unknown");
+    let ops = registry.create_ops(&device, filesystem)
+        .map_err(|e| format!("Failed to open {}: {}", device_id, e))?;
+    // Copies into a volume refresh any file browser watching it
+    Ok(if writable { Box::new(moses_filesystems::watch::NotifyingOps::new(ops, &device)) } else { ops })
 }
 
 // Filesystem-specific implementations
@@ -329,11 +523,15 @@ async fn read_ext_directory(
                 moses_filesystems::families::ext::ext4_native::reader::FileType::BlockDevice => EntryType::Device,
                 _ => EntryType::Other,
             },
+            mime: size.map(|_| preview::mime_from_extension(&entry.name).unwrap_or("application/octet-stream").to_string()),
+            metadata: Some(FilesystemMetadata {
+                hidden: Some(entry.name.starts_with('.')),
+                ..Default::default()
+            }),
             size,
             modified: None, // TODO: Get from inode
             created: None,
             permissions: None,
-            metadata: None,
         }
     }).collect();
     
@@ -373,47 +571,9 @@ async fn read_fat16_directory(
     
     log::info!("Reading FAT16 directory: {} on device {}", path, device.id);
     
-    // Create reader
     let mut reader = Fat16Reader::new(device.clone())
         .map_err(|e| format!("Failed to open FAT16 filesystem: {:?}", e))?;
-    
-    // Read directory
-    let entries = reader.list_directory(path)
-        .map_err(|e| format!("Failed to read directory {}: {:?}", path, e))?;
-    
-    // Convert to our format
-    let mut total_size = 0u64;
-    let converted_entries: Vec<DirectoryEntry> = entries.into_iter().map(|entry| {
-        if !entry.is_directory {
-            total_size += entry.size;
-        }
-        DirectoryEntry {
-            name: entry.name.clone(),
-            path: if path == "/" || path.is_empty() {
-                format!("/{}", entry.name)
-            } else {
-                format!("{}/{}", path.trim_end_matches('/'), entry.name)
-            },
-            entry_type: if entry.is_directory {
-                EntryType::Directory
-            } else {
-                EntryType::File
-            },
-            size: if entry.is_directory { None } else { Some(entry.size) },
-            modified: None,
-            created: None,
-            permissions: None,
-            metadata: None,
-        }
-    }).collect();
-    
-    let item_count = converted_entries.len();
-    Ok(DirectoryListing {
-        path: path.to_string(),
-        entries: converted_entries,
-        total_size,
-        item_count,
-    })
+    list_reader_directory(&mut reader, path)
 }
 
 async fn read_fat32_directory(
@@ -424,47 +584,9 @@ async fn read_fat32_directory(
     
     log::info!("Reading FAT32 directory: {} on device {}", path, device.id);
     
-    // Create reader
     let mut reader = Fat32Reader::new(device.clone())
         .map_err(|e| format!("Failed to open FAT32 filesystem: {:?}", e))?;
-    
-    // Read directory
-    let entries = reader.list_directory(path)
-        .map_err(|e| format!("Failed to read directory {}: {:?}", path, e))?;
-    
-    // Convert to our format
-    let mut total_size = 0u64;
-    let converted_entries: Vec<DirectoryEntry> = entries.into_iter().map(|entry| {
-        if !entry.is_directory {
-            total_size += entry.size;
-        }
-        DirectoryEntry {
-            name: entry.name.clone(),
-            path: if path == "/" || path.is_empty() {
-                format!("/{}", entry.name)
-            } else {
-                format!("{}/{}", path.trim_end_matches('/'), entry.name)
-            },
-            entry_type: if entry.is_directory {
-                EntryType::Directory
-            } else {
-                EntryType::File
-            },
-            size: if entry.is_directory { None } else { Some(entry.size) },
-            modified: None, // TODO: Parse FAT32 timestamps
-            created: None,
-            permissions: None,
-            metadata: None,
-        }
-    }).collect();
-    
-    let item_count = converted_entries.len();
-    Ok(DirectoryListing {
-        path: path.to_string(),
-        entries: converted_entries,
-        total_size,
-        item_count,
-    })
+    list_reader_directory(&mut reader, path)
 }
 
 async fn read_ntfs_directory(
@@ -475,47 +597,9 @@ async fn read_ntfs_directory(
     
     log::info!("Reading NTFS directory: {} on device {}", path, device.id);
     
-    // Create NTFS reader
     let mut reader = NtfsReader::new(device.clone())
         .map_err(|e| format!("Failed to open NTFS filesystem: {:?}", e))?;
-    
-    // Read directory
-    let entries = reader.list_directory(path)
-        .map_err(|e| format!("Failed to read directory {}: {:?}", path, e))?;
-    
-    // Convert to our format
-    let mut total_size = 0u64;
-    let converted_entries: Vec<DirectoryEntry> = entries.into_iter().map(|entry| {
-        if !entry.is_directory {
-            total_size += entry.size;
-        }
-        DirectoryEntry {
-            name: entry.name.clone(),
-            path: if path == "/" || path.is_empty() {
-                format!("/{}", entry.name)
-            } else {
-                format!("{}/{}", path.trim_end_matches('/'), entry.name)
-            },
-            entry_type: if entry.is_directory {
-                EntryType::Directory
-            } else {
-                EntryType::File
-            },
-            size: if entry.is_directory { None } else { Some(entry.size) },
-            modified: None, // TODO: Parse NTFS timestamps
-            created: None,
-            permissions: None,
-            metadata: None,
-        }
-    }).collect();
-    
-    let item_count = converted_entries.len();
-    Ok(DirectoryListing {
-        path: path.to_string(),
-        entries: converted_entries,
-        total_size,
-        item_count,
-    })
+    list_reader_directory(&mut reader, path)
 }
 
 async fn read_exfat_directory(
@@ -524,47 +608,145 @@ async fn read_exfat_directory(
 ) -> Result<DirectoryListing, String> {
     use moses_filesystems::families::fat::exfat::ExFatReader;
     
-    // Create reader
     let mut reader = ExFatReader::new(device.clone())
         .map_err(|e| format!("Failed to open exFAT filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+async fn read_btrfs_directory(
+    device: &Device,
+    path: &str,
+) -> Result<DirectoryListing, String> {
+    use moses_filesystems::BtrfsReader;
+    
+    let mut reader = BtrfsReader::new(device.clone())
+        .map_err(|e| format!("Failed to open btrfs filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+async fn read_xfs_directory(
+    device: &Device,
+    path: &str,
+) -> Result<DirectoryListing, String> {
+    use moses_filesystems::XfsReader;
+    
+    let mut reader = XfsReader::new(device.clone())
+        .map_err(|e| format!("Failed to open XFS filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+async fn read_hfsplus_directory(
+    device: &Device,
+    path: &str,
+) -> Result<DirectoryListing, String> {
+    use moses_filesystems::HfsPlusReader;
+    
+    let mut reader = HfsPlusReader::new(device.clone())
+        .map_err(|e| format!("Failed to open HFS+ filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+async fn read_jffs2_directory(
+    device: &Device,
+    path: &str,
+) -> Result<DirectoryListing, String> {
+    use moses_filesystems::Jffs2Reader;
+    
+    let mut reader = Jffs2Reader::new(device.clone())
+        .map_err(|e| format!("Failed to open JFFS2 filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+async fn read_iso9660_directory(
+    device: &Device,
+    path: &str,
+) -> Result<DirectoryListing, String> {
+    use moses_filesystems::Iso9660Reader;
     
-    // Read directory using the common trait method
+    let mut reader = Iso9660Reader::new(device.clone())
+        .map_err(|e| format!("Failed to open ISO9660 filesystem: {:?}", e))?;
+    list_reader_directory(&mut reader, path)
+}
+
+/// Files with an unknown extension sniffed per listing; each sniff reads the whole file
+const MAX_SNIFFED_FILES: usize = 32;
+
+fn list_reader_directory(reader: &mut dyn FilesystemReader, path: &str) -> Result<DirectoryListing, String> {
     let entries = reader.list_directory(path)
         .map_err(|e| format!("Failed to read directory {}: {:?}", path, e))?;
-    
-    // Convert to our format
+    Ok(convert_reader_entries(entries, path, Some(reader)))
+}
+
+/// Convert a FilesystemReader listing. With a reader, small files whose extension says
+/// nothing are typed by content
+fn convert_reader_entries(
+    entries: Vec<FileEntry>,
+    path: &str,
+    mut reader: Option<&mut dyn FilesystemReader>,
+) -> DirectoryListing {
     let mut total_size = 0u64;
+    let mut sniffed = 0;
     let converted_entries: Vec<DirectoryEntry> = entries.into_iter().map(|entry| {
-        if !entry.is_directory {
+        let entry_path = child_path(path, &entry.name);
+        let mime = if entry.is_directory {
+            None
+        } else {
             total_size += entry.size;
-        }
+            let mime = preview::listing_mime(&entry.name, entry.size, || {
+                let reader = reader.as_deref_mut().filter(|_| sniffed < MAX_SNIFFED_FILES)?;
+                sniffed += 1;
+                reader.read_file(&entry_path).ok()
+            });
+            Some(mime.to_string())
+        };
+        let meta = &entry.metadata;
         DirectoryEntry {
             name: entry.name.clone(),
-            path: if path == "/" || path.is_empty() {
-                format!("/{}", entry.name)
-            } else {
-                format!("{}/{}", path.trim_end_matches('/'), entry.name)
-            },
-            entry_type: if entry.is_directory {
+            entry_type: if meta.link_target.is_some() {
+                EntryType::Symlink
+            } else if entry.is_directory {
                 EntryType::Directory
             } else {
                 EntryType::File
             },
             size: if entry.is_directory { None } else { Some(entry.size) },
-            modified: None, // TODO: Parse timestamps
-            created: None,
+            modified: meta.modified.and_then(|secs| DateTime::from_timestamp(secs as i64, 0)),
+            created: meta.created.and_then(|secs| DateTime::from_timestamp(secs as i64, 0)),
             permissions: None,
-            metadata: None,
+            mime,
+            metadata: Some(FilesystemMetadata {
+                compressed: Some(meta.compressed),
+                sparse: Some(meta.sparse),
+                reparse_point: meta.reparse_point.clone(),
+                link_target: meta.link_target.clone(),
+                allocated_size: meta.allocated_size,
+                mft_record: None,
+                hidden: Some(meta.hidden),
+                system: Some(meta.system),
+                readonly: Some(meta.readonly),
+                encrypted: Some(meta.encrypted),
+                owner: meta.owner.clone(),
+                access: meta.access.clone(),
+            }),
+            path: entry_path,
         }
     }).collect();
     
     let item_count = converted_entries.len();
-    Ok(DirectoryListing {
+    DirectoryListing {
         path: path.to_string(),
         entries: converted_entries,
         total_size,
         item_count,
-    })
+    }
+}
+
+fn child_path(path: &str, name: &str) -> String {
+    if path == "/" || path.is_empty() {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", path.trim_end_matches('/'), name)
+    }
 }
 
 /// Detect filesystem type for a device (may require elevation)
@@ -720,8 +902,10 @@ pub async fn get_filesystem_type(
 #[tauri::command]
 pub async fn analyze_filesystem(
     device_id: String,
+    depth: Option<AnalysisDepth>,
 ) -> Result<String, String> {
-    log::info!("Analyzing filesystem on device: {}", device_id);
+    let depth = depth.unwrap_or_default();
+    log::info!("Analyzing filesystem on device: {} ({})", device_id, depth.as_str());
     
     // Check if we're on Windows and need elevation
     #[cfg(target_os = "windows")]
@@ -733,8 +917,18 @@ pub async fn analyze_filesystem(
             .ok_or_else(|| format!("Device {} not found", device_id))?;
         
         // Try the analysis
-        return Err("Filesystem analysis not yet implemented".to_string());
-    
+        match analyze_with_progress(device.clone(), depth).await {
+            Ok(analysis) => {
+                let report = serde_json::to_string(&analysis)
+                    .map_err(|e| format!("Failed to serialize analysis: {}", e))?;
+                cache_analysis_result(&device, &report);
+                Ok(report)
+            }
+            Err(e) if !is_elevated() => {
+                Err(format!("Analysis requires administrator privileges: {}", e))
+            }
+            Err(e) => Err(format!("Analysis failed: {}", e)),
+        }
     }
     // Non-Windows platforms
     #[cfg(not(target_os = "windows"))]
@@ -742,14 +936,60 @@ pub async fn analyze_filesystem(
         let device = get_device(&device_id)
             .ok_or_else(|| format!("Device {} not found", device_id))?;
         
-        return Err("Filesystem analysis not yet implemented".to_string());
+        let analysis = analyze_with_progress(device.clone(), depth).await
+            .map_err(|e| format!("Analysis failed: {}", e))?;
+        let report = serde_json::to_string(&analysis)
+            .map_err(|e| format!("Failed to serialize analysis: {}", e))?;
+        cache_analysis_result(&device, &report);
+        Ok(report)
     }
 }
 
+/// Set by `cancel_analysis`; checked between the stages of an in-process analysis
+static ANALYSIS_CANCELLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Run the heuristic analysis off the async runtime, streaming progress and partial results
+async fn analyze_with_progress(device: Device, depth: AnalysisDepth) -> Result<UnknownFilesystemAnalysis, moses_core::MosesError> {
+    use std::sync::atomic::Ordering;
+    ANALYSIS_CANCELLED.store(false, Ordering::SeqCst);
+    tauri::async_runtime::spawn_blocking(move || {
+        analyze_unknown_filesystem_with_progress(&device, depth, &mut |progress| {
+            crate::progress::emit_analysis(progress);
+            if ANALYSIS_CANCELLED.load(Ordering::SeqCst) {
+                std::ops::ControlFlow::Break(())
+            } else {
+                std::ops::ControlFlow::Continue(())
+            }
+        })
+    })
+    .await
+    .map_err(|e| moses_core::MosesError::Other(format!("Analysis task failed: {}", e)))?
+}
+
+/// How much each analysis depth reads and how long it should take on this device
+#[tauri::command]
+pub async fn estimate_analysis(device_id: String) -> Result<Vec<AnalysisEstimate>, String> {
+    let device = get_device(&device_id)
+        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    tauri::async_runtime::spawn_blocking(move || moses_filesystems::diagnostics::estimate_filesystem_analysis(&device))
+        .await
+        .map_err(|e| format!("Estimate task failed: {}", e))?
+        .map_err(|e| format!("Failed to estimate analysis time: {}", e))
+}
+
+/// Stop the running filesystem analysis, in process or in the elevated worker
+#[tauri::command]
+pub async fn cancel_analysis() -> Result<(), String> {
+    ANALYSIS_CANCELLED.store(true, std::sync::atomic::Ordering::SeqCst);
+    crate::worker_server::request_cancel();
+    Ok(())
+}
+
 /// Analyze filesystem with elevation (Windows only)
 #[tauri::command]
 pub async fn analyze_filesystem_elevated(
     device_id: String,
+    depth: Option<AnalysisDepth>,
 ) -> Result<String, String> {
     log::info!("Requesting elevated analysis for device: {}", device_id);
     
@@ -767,12 +1007,12 @@ pub async fn analyze_filesystem_elevated(
         
         if let Some(worker) = server_guard.as_mut() {
             // Send analyze command through the socket
-            let command = WorkerCommand::Analyze { device: device.clone() };
+            let command = WorkerCommand::Analyze { device: device.clone(), depth: depth.unwrap_or_default() };
             
             match worker.execute_command(command).await {
                 Ok(WorkerResponse::Success(result)) => {
                     // Cache the result
-                    cache_analysis_result(&device_id, &result);
+                    cache_analysis_result(&device, &result);
                     Ok(result)
                 }
                 Ok(WorkerResponse::Error(e)) => {
@@ -793,12 +1033,58 @@ pub async fn analyze_filesystem_elevated(
     #[cfg(not(target_os = "windows"))]
     {
         // On non-Windows, just call the regular analyze
-        analyze_filesystem(device_id).await
+        analyze_filesystem(device_id, depth).await
     }
 }
 
+/// Read-only hex dump of a device region with structure annotations
+#[tauri::command]
+pub async fn hexdump_device(
+    device_id: String,
+    offset: u64,
+    length: usize,
+) -> Result<moses_filesystems::hexview::HexDump, String> {
+    log::info!("Hex dump of {} at {:#x} ({} bytes)", device_id, offset, length);
+    
+    let device = get_device(&device_id)
+        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    
+    moses_filesystems::hexview::hexdump_device(&device, offset, length)
+        .map_err(|e| format!("Hex dump failed: {}", e))
+}
+
+/// Identify and decode the on-disk structure at a byte offset (read-only)
+#[tauri::command]
+pub async fn explain_structure(
+    device_id: String,
+    offset: u64,
+) -> Result<Option<moses_filesystems::explain::StructureExplanation>, String> {
+    log::info!("Explaining structure on {} at {:#x}", device_id, offset);
+    
+    let device = get_device(&device_id)
+        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    
+    moses_filesystems::explain::explain_device_offset(&device, offset)
+        .map_err(|e| format!("Structure analysis failed: {}", e))
+}
+
+/// Downsampled allocation map of a volume for the block map view (read-only)
+#[tauri::command]
+pub async fn get_allocation_map(
+    device_id: String,
+    cells: usize,
+) -> Result<moses_filesystems::allocation::AllocationMap, String> {
+    log::info!("Reading allocation map of {} ({} cells)", device_id, cells);
+    
+    let device = get_device(&device_id)
+        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    
+    moses_filesystems::allocation::device_allocation_map(&device, cells)
+        .map_err(|e| format!("Failed to read allocation map: {}", e))
+}
+
 /// Cache the analysis result
-fn cache_analysis_result(device_id: &str, report_json: &str) {
+fn cache_analysis_result(device: &Device, report_json: &str) {
     // Try to parse the JSON report to extract filesystem info
     if let Ok(report) = serde_json::from_str::<serde_json::Value>(report_json) {
         let filesystem = report["filesystem"]
@@ -812,7 +1098,7 @@ fn cache_analysis_result(device_id: &str, report_json: &str) {
         
         let partitions = if let Some(parts) = report["partitions"].as_array() {
             parts.iter().map(|p| {
-                filesystem_cache::PartitionInfo {
+                PartitionInfo {
                     number: p["number"].as_u64().unwrap_or(0) as u32,
                     filesystem: p["filesystem"].as_str().map(|s| s.to_string()),
                     size: p["size"].as_u64().unwrap_or(0),
@@ -823,14 +1109,14 @@ fn cache_analysis_result(device_id: &str, report_json: &str) {
             vec![]
         };
         
-        let cached_info = filesystem_cache::CachedFilesystemInfo {
+        let cached_info = CachedFilesystemInfo {
             filesystem,
             partition_table,
             partitions,
             detected_at: std::time::SystemTime::now(),
         };
         
-        filesystem_cache::cache_filesystem_info(device_id, cached_info);
+        FilesystemCache::global().insert(device, 0, cached_info);
     }
 }
 
@@ -1,3 +1,6 @@
 pub mod filesystem;
 pub mod disk_management;
-pub mod disk_management_socket;
\ No newline at end of file
+pub mod disk_management_socket;
+pub mod mount_driver;
+pub mod update;
+pub mod stats;
@@ -0,0 +1,104 @@
+// Mount driver (WinFsp/FUSE/macFUSE) status and installation
+// The UI checks the driver before offering to mount. On Windows a WinFsp installer can be
+// shipped in the app's resources as installers/winfsp.msi; when present it is launched
+// directly, otherwise the download page is opened.
+use moses_filesystems::mount_driver::{self, MountDriver};
+use serde::Serialize;
+use tauri::AppHandle;
+
+/// Bundled installer location inside the app's resource directory
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+const BUNDLED_INSTALLER: &str = "installers/winfsp.msi";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MountDriverStatus {
+    #[serde(flatten)]
+    pub driver: MountDriver,
+    /// Installed and at least the required version
+    pub ready: bool,
+    /// Whether this build can mount at all
+    pub compiled_in: bool,
+    /// A bundled installer can be launched with `install_mount_driver`
+    pub installer_bundled: bool,
+}
+
+#[cfg(target_os = "windows")]
+fn bundled_installer(app: &AppHandle) -> Option<std::path::PathBuf> {
+    use tauri::Manager;
+
+    app.path()
+        .resolve(BUNDLED_INSTALLER, tauri::path::BaseDirectory::Resource)
+        .ok()
+        .filter(|path| path.exists())
+}
+
+#[tauri::command]
+pub async fn check_mount_driver(app: AppHandle) -> Result<MountDriverStatus, String> {
+    let driver = tokio::task::spawn_blocking(mount_driver::detect)
+        .await
+        .map_err(|e| format!("Driver detection failed: {}", e))?;
+
+    #[cfg(target_os = "windows")]
+    let installer_bundled = bundled_installer(&app).is_some();
+    #[cfg(not(target_os = "windows"))]
+    let installer_bundled = {
+        let _ = app;
+        false
+    };
+
+    Ok(MountDriverStatus {
+        ready: driver.meets_requirement(),
+        compiled_in: MountDriver::compiled_in(),
+        installer_bundled,
+        driver,
+    })
+}
+
+/// Run the bundled WinFsp installer (it asks for elevation itself), or open the download page
+#[tauri::command]
+pub async fn install_mount_driver(app: AppHandle) -> Result<String, String> {
+    let driver = mount_driver::detect();
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+        if let Some(installer) = bundled_installer(&app) {
+            log::info!("Launching bundled {} installer: {}", driver.name, installer.display());
+            let status = tokio::process::Command::new("msiexec")
+                .arg("/i")
+                .arg(&installer)
+                .arg("/passive")
+                .creation_flags(CREATE_NO_WINDOW)
+                .status()
+                .await
+                .map_err(|e| format!("Failed to start the installer: {}", e))?;
+
+            return match status.code() {
+                Some(0) => Ok(format!("{} installed", driver.name)),
+                // ERROR_SUCCESS_REBOOT_REQUIRED
+                Some(3010) => Ok(format!("{} installed; restart Windows to finish", driver.name)),
+                // ERROR_INSTALL_USEREXIT
+                Some(1602) => Err("Installation cancelled".to_string()),
+                code => Err(format!("The {} installer failed (exit code {:?})", driver.name, code)),
+            };
+        }
+
+        std::process::Command::new("cmd")
+            .args(["/C", "start", "", driver.download_url])
+            .creation_flags(CREATE_NO_WINDOW)
+            .spawn()
+            .map_err(|e| format!("Failed to open {}: {}", driver.download_url, e))?;
+        Ok(format!("Opened the {} download page", driver.name))
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = app;
+        Err(format!(
+            "Install {} {} or newer with your package manager or from {}",
+            driver.name, driver.required_version, driver.download_url
+        ))
+    }
+}
@@ -0,0 +1,18 @@
+// Local statistics for the app
+// The same records `moses stats` shows: operations run, failures by error code and the
+// average throughput per device type. They stay on this machine.
+use moses_core::{LocalMetrics, MetricsSummary};
+
+/// Summary of everything recorded so far
+#[tauri::command]
+pub async fn get_local_metrics() -> Result<MetricsSummary, String> {
+    tokio::task::spawn_blocking(|| LocalMetrics::global().summary())
+        .await
+        .map_err(|e| format!("Reading statistics failed: {}", e))
+}
+
+/// Delete the recorded statistics
+#[tauri::command]
+pub async fn reset_local_metrics() -> Result<(), String> {
+    LocalMetrics::global().clear().map_err(|e| e.to_string())
+}
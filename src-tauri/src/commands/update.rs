@@ -0,0 +1,60 @@
+// Update checks and the release channel for the app
+// The UI asks for a check, shows the notes and lets the user download; the staged build
+// replaces the app the next time it starts.
+use moses_core::{MosesConfig, ReleaseChannel};
+use moses_filesystems::update::{Component, StagedUpdate, UpdateCheck, Updater};
+
+fn saved_config() -> Result<(std::path::PathBuf, MosesConfig), String> {
+    let path = MosesConfig::default_path().ok_or("No config directory on this system")?;
+    let config = MosesConfig::load_from(&path).map_err(|e| e.to_string())?;
+    Ok((path, config))
+}
+
+/// Updater following the saved channel, so a change applies without a restart
+fn updater() -> Result<Updater, String> {
+    let (_, config) = saved_config()?;
+    Ok(Updater::from_config(Component::Gui, env!("CARGO_PKG_VERSION"))
+        .map_err(|e| e.to_string())?
+        .with_channel(config.update.channel))
+}
+
+/// Look for a release newer than this build on the configured channel
+#[tauri::command]
+pub async fn check_for_update() -> Result<UpdateCheck, String> {
+    tokio::task::spawn_blocking(|| updater()?.check().map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Update check failed: {}", e))?
+}
+
+/// Download and verify the newest release and stage it for the next start
+#[tauri::command]
+pub async fn stage_update() -> Result<StagedUpdate, String> {
+    tokio::task::spawn_blocking(|| {
+        let updater = updater()?;
+        let update = updater.check().map_err(|e| e.to_string())?
+            .available
+            .ok_or_else(|| "This is already the newest release".to_string())?;
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        updater.stage(&update, &exe).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("Update failed: {}", e))?
+}
+
+/// The staged build waiting for a restart, if any
+#[tauri::command]
+pub async fn get_staged_update() -> Result<Option<StagedUpdate>, String> {
+    Ok(updater()?.staged())
+}
+
+#[tauri::command]
+pub async fn get_update_channel() -> Result<ReleaseChannel, String> {
+    Ok(saved_config()?.1.update.channel)
+}
+
+#[tauri::command]
+pub async fn set_update_channel(channel: ReleaseChannel) -> Result<(), String> {
+    let (path, mut config) = saved_config()?;
+    config.update.channel = channel;
+    config.save_to(&path).map_err(|e| e.to_string())
+}
@@ -0,0 +1,218 @@
+// Background filesystem identification for devices the platform layer couldn't classify
+// Devices are queued after enumeration and probed one at a time. An unprivileged probe is
+// tried first; anything that needs admin rights goes through the pooled elevated worker, so
+// a whole batch costs at most one elevation prompt. Results are cached and emitted as events
+// so the drive list fills in progressively.
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+use moses_core::{CachedFilesystemInfo, Device, FilesystemCache};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc;
+use crate::worker_server::{get_worker_server, WorkerCommand, WorkerResponse};
+
+/// Emitted with an `IdentificationResult` when a device's filesystem is resolved
+pub const IDENTIFIED_EVENT: &str = "filesystem-identified";
+/// Emitted with an `IdentificationResult` when a device could not be identified
+pub const FAILED_EVENT: &str = "filesystem-identification-failed";
+
+/// How often the elevated worker is pinged so it stays connected, and relaunched if it died
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentificationResult {
+    pub device_id: String,
+    pub filesystem: Option<String>,
+    pub error: Option<String>,
+    /// Whether the elevated worker was used
+    pub elevated: bool,
+}
+
+struct Job {
+    device: Device,
+    allow_elevation: bool,
+}
+
+struct IdentificationQueue {
+    sender: mpsc::UnboundedSender<Job>,
+    pending: Mutex<HashSet<String>>,
+}
+
+static QUEUE: OnceCell<IdentificationQueue> = OnceCell::new();
+
+/// Start the identification queue and the worker keepalive
+pub fn init(app: AppHandle) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let queue = IdentificationQueue {
+        sender,
+        pending: Mutex::new(HashSet::new()),
+    };
+    if QUEUE.set(queue).is_err() {
+        return;
+    }
+
+    tauri::async_runtime::spawn(run_queue(app, receiver));
+    tauri::async_runtime::spawn(keep_worker_warm());
+}
+
+/// Whether a device still has no known filesystem
+pub fn needs_identification(device: &Device) -> bool {
+    matches!(device.filesystem.as_deref(), None | Some("unknown"))
+}
+
+/// Queue every unidentified device that isn't already queued; returns how many were added.
+/// Without `allow_elevation` the elevated worker is only used if it is already running.
+pub fn enqueue(devices: &[Device], allow_elevation: bool) -> usize {
+    let Some(queue) = QUEUE.get() else {
+        return 0;
+    };
+    let Ok(mut pending) = queue.pending.lock() else {
+        return 0;
+    };
+
+    let mut queued = 0;
+    for device in devices.iter().filter(|d| needs_identification(d)) {
+        if !pending.insert(device.id.clone()) {
+            continue;
+        }
+        let job = Job { device: device.clone(), allow_elevation };
+        if queue.sender.send(job).is_ok() {
+            queued += 1;
+        } else {
+            pending.remove(&device.id);
+        }
+    }
+
+    if queued > 0 {
+        log::info!("Queued {} device(s) for background filesystem identification", queued);
+    }
+    queued
+}
+
+async fn run_queue(app: AppHandle, mut receiver: mpsc::UnboundedReceiver<Job>) {
+    // Once the user declines elevation (or the worker can't start), don't prompt again
+    // for the rest of the batch
+    let mut elevation_refused = false;
+
+    while let Some(job) = receiver.recv().await {
+        let allow_elevation = job.allow_elevation && !elevation_refused;
+        let (result, worker_failed) = identify(&job.device, allow_elevation).await;
+        elevation_refused |= worker_failed;
+
+        if let Some(queue) = QUEUE.get() {
+            if let Ok(mut pending) = queue.pending.lock() {
+                pending.remove(&job.device.id);
+            }
+        }
+
+        let event = if result.filesystem.is_some() { IDENTIFIED_EVENT } else { FAILED_EVENT };
+        if let Err(e) = app.emit(event, &result) {
+            log::warn!("Failed to emit {} for {}: {}", event, result.device_id, e);
+        }
+
+        if receiver.is_empty() {
+            elevation_refused = false;
+        }
+    }
+}
+
+/// Identify one device; the flag reports whether the elevated worker couldn't be reached
+async fn identify(device: &Device, allow_elevation: bool) -> (IdentificationResult, bool) {
+    let mut result = IdentificationResult {
+        device_id: device.id.clone(),
+        filesystem: None,
+        error: None,
+        elevated: false,
+    };
+
+    if let Some(filesystem) = FilesystemCache::global().filesystem(device) {
+        result.filesystem = Some(filesystem);
+        return (result, false);
+    }
+
+    let probe_device = device.clone();
+    let direct = tauri::async_runtime::spawn_blocking(move || {
+        let _slot = moses_core::DeviceQueues::global().acquire(&probe_device.id);
+        moses_filesystems::utils::open_device_read(&probe_device)
+            .and_then(|mut file| moses_filesystems::detection::detect_filesystem(&mut file))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Detection task failed: {}", e)));
+
+    let mut worker_failed = false;
+    let outcome = match direct {
+        Ok(filesystem) => Ok(filesystem),
+        Err(e) if allow_elevation || worker_connected().await => {
+            log::info!("Unprivileged detection failed for {} ({}), using elevated worker", device.id, e);
+            result.elevated = true;
+            match identify_elevated(device).await {
+                Err(ElevatedError::Unavailable(e)) => {
+                    worker_failed = true;
+                    Err(e)
+                }
+                Err(ElevatedError::Detection(e)) => Err(e),
+                Ok(filesystem) => Ok(filesystem),
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    match outcome {
+        Ok(filesystem) if filesystem != "unknown" => {
+            log::info!("Identified {} as {}", device.id, filesystem);
+            FilesystemCache::global().insert(device, 0, CachedFilesystemInfo::detected_now(filesystem.clone()));
+            result.filesystem = Some(filesystem);
+        }
+        Ok(_) => result.error = Some("Filesystem not recognized".to_string()),
+        Err(e) => result.error = Some(e),
+    }
+
+    (result, worker_failed)
+}
+
+enum ElevatedError {
+    /// The worker couldn't be started or reached
+    Unavailable(String),
+    /// The worker ran but detection failed
+    Detection(String),
+}
+
+async fn identify_elevated(device: &Device) -> Result<String, ElevatedError> {
+    let server_arc = get_worker_server().await.map_err(ElevatedError::Unavailable)?;
+    let server_guard = server_arc.lock().await;
+    let server = server_guard.as_ref()
+        .ok_or_else(|| ElevatedError::Unavailable("Worker server not initialized".to_string()))?;
+
+    match server.execute_command(WorkerCommand::Detect { device: device.clone() }).await {
+        Ok(WorkerResponse::Success(filesystem)) => Ok(filesystem),
+        Ok(WorkerResponse::Error(e)) => Err(ElevatedError::Detection(e)),
+        Ok(_) => Err(ElevatedError::Detection("Unexpected response from worker".to_string())),
+        Err(e) => Err(ElevatedError::Unavailable(format!("Worker communication failed: {}", e))),
+    }
+}
+
+async fn worker_connected() -> bool {
+    let Ok(server_arc) = get_worker_server().await else {
+        return false;
+    };
+    let server_guard = server_arc.lock().await;
+    match server_guard.as_ref() {
+        Some(server) => server.is_connected().await,
+        None => false,
+    }
+}
+
+async fn keep_worker_warm() {
+    let mut interval = tokio::time::interval(KEEPALIVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Ok(server_arc) = get_worker_server().await {
+            if let Some(server) = server_arc.lock().await.as_ref() {
+                server.heartbeat().await;
+            }
+        }
+    }
+}
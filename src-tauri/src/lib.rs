@@ -1,21 +1,20 @@
-use moses_core::{Device, DeviceManager, FilesystemFormatter, FormatOptions, SimulationReport};
+use moses_core::{Device, DeviceManager, FormatOptions, SafetyLint, SimulationReport, FilesystemCache};
 
 use moses_platform::PlatformDeviceManager;
-use moses_filesystems::{Fat16Formatter, Fat32Formatter, ExFatFormatter};
+use moses_filesystems::disk_manager::{SelectiveFormatCheck, SelectiveFormatReport, SelectiveFormatRequest};
+#[cfg(not(target_os = "windows"))]
+use moses_filesystems::disk_manager::SignatureWiper;
+#[cfg(not(target_os = "windows"))]
+use moses_core::{CachedFilesystemInfo, PostOperationAction};
 
 #[cfg(target_os = "windows")]
 use moses_platform::windows::elevation::is_elevated;
 
 mod logging;
 pub mod commands;
-mod filesystem_cache;
 mod worker_server;
-
-#[cfg(target_os = "linux")]
-use moses_filesystems::Ext4NativeFormatter;
-
-#[cfg(target_os = "windows")]
-use moses_filesystems::Ext4NativeFormatter;
+mod identification;
+mod progress;
 
 #[tauri::command]
 async fn detect_drives() -> Result<Vec<Device>, String> {
@@ -33,6 +32,19 @@ async fn detect_drives() -> Result<Vec<Device>, String> {
         .map_err(|e| format!("Failed to detect drives: {}", e))
 }
 
+/// Connection state of the elevated worker, e.g. "worker: elevated, connected"
+#[tauri::command]
+async fn get_worker_status() -> Result<worker_server::WorkerStatus, String> {
+    Ok(worker_server::worker_status().await)
+}
+
+/// Ask for elevation again after the user declined it; commands that failed with
+/// NeedsElevation can then be run again
+#[tauri::command]
+async fn retry_elevation() -> Result<(), String> {
+    worker_server::retry_elevation().await
+}
+
 #[tauri::command]
 async fn check_elevation_status() -> Result<bool, String> {
     #[cfg(target_os = "windows")]
@@ -55,32 +67,24 @@ async fn execute_format_elevated(
 ) -> Result<String, String> {
     #[cfg(target_os = "windows")]
     {
-        use crate::worker_server::{get_worker_server, WorkerCommand, WorkerResponse};
+        use crate::worker_server::{run_on_worker, ElevatedCommandError, WorkerCommand, WorkerResponse};
         
         log::info!("Executing format with elevation - Device: name={}, id={}, size={}", 
                    device.name, device.id, device.size);
         log::info!("Options: filesystem={}, cluster_size={:?}", 
                    options.filesystem_type, options.cluster_size);
         
-        // Use the persistent socket-based worker
-        let server = get_worker_server().await?;
-        let mut server_guard = server.lock().await;
+        // Send format command through the persistent socket-based worker
+        let command = WorkerCommand::Format { 
+            device: device.clone(), 
+            options: options.clone() 
+        };
         
-        if let Some(worker) = server_guard.as_mut() {
-            // Send format command through the socket
-            let command = WorkerCommand::Format { 
-                device: device.clone(), 
-                options: options.clone() 
-            };
-            
-            match worker.execute_command(command).await {
-                Ok(WorkerResponse::Success(result)) => Ok(result),
-                Ok(WorkerResponse::Error(e)) => Err(format!("Format failed: {}", e)),
-                Ok(_) => Err("Unexpected response from worker".to_string()),
-                Err(e) => Err(format!("Worker communication failed: {}", e))
-            }
-        } else {
-            Err("Worker server not initialized".to_string())
+        match run_on_worker("Formatting a disk", command).await {
+            Ok(WorkerResponse::Success(result)) => Ok(result),
+            Ok(_) => Err("Unexpected response from worker".to_string()),
+            Err(ElevatedCommandError::Failed { message }) => Err(format!("Format failed: {}", message)),
+            Err(e) => Err(e.to_string()),
         }
     }
     
@@ -111,14 +115,10 @@ async fn enumerate_devices() -> Result<Vec<Device>, String> {
                 }
             }
             
-            // Then check the persistent filesystem cache
-            if let Some(cached_info) = filesystem_cache::get_cached_filesystem_info(&device.id) {
-                if filesystem_cache::is_cache_fresh(&cached_info) {
-                    log::info!("Using cached filesystem info for {}: {}", device.id, cached_info.filesystem);
-                    device.filesystem = Some(cached_info.filesystem);
-                } else {
-                    log::debug!("Cached filesystem info for {} is stale", device.id);
-                }
+            // Then check the persistent filesystem cache (only returns fresh entries)
+            if let Some(filesystem) = FilesystemCache::global().filesystem(device) {
+                log::info!("Using cached filesystem info for {}: {}", device.id, filesystem);
+                device.filesystem = Some(filesystem);
             }
         }
     }
@@ -129,69 +129,101 @@ async fn enumerate_devices() -> Result<Vec<Device>, String> {
                   device.name, device.id, device.size, device.filesystem);
     }
     
+    // Resolve the rest in the background; results arrive as filesystem-identified events
+    identification::enqueue(&devices, false);
+    
     Ok(devices)
 }
 
+/// Queue every unidentified device for background identification. With `allow_elevation`
+/// the elevated worker is started if needed, costing one prompt for the whole batch.
+#[tauri::command]
+async fn identify_filesystems(allow_elevation: bool) -> Result<usize, String> {
+    let manager = PlatformDeviceManager;
+    let devices = manager.enumerate_devices()
+        .await
+        .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
+    
+    Ok(identification::enqueue(&devices, allow_elevation))
+}
+
 #[tauri::command]
 async fn simulate_format(
     device: Device,
     options: FormatOptions,
 ) -> Result<SimulationReport, String> {
-    // Select the appropriate formatter based on filesystem type
-    match options.filesystem_type.as_str() {
-        "ext4" => {
-            #[cfg(target_os = "linux")]
-            {
-                let formatter = Ext4NativeFormatter;
-                formatter.dry_run(&device, &options)
-                    .await
-                    .map_err(|e| format!("Simulation failed: {}", e))
-            }
-            
-            #[cfg(target_os = "windows")]
-            {
-                let formatter = Ext4NativeFormatter;
-                formatter.dry_run(&device, &options)
-                    .await
-                    .map_err(|e| format!("Simulation failed: {}", e))
-            }
-            
-            #[cfg(target_os = "macos")]
-            {
-                Err("EXT4 formatting not yet implemented on macOS".to_string())
-            }
-        },
-        
-        "ntfs" => {
-            // NTFS formatting not yet implemented
-            return Err("NTFS formatting is not yet implemented. Only NTFS reading is currently supported.".to_string());
-        },
-        
-        "fat16" => {
-            let formatter = Fat16Formatter;
-            formatter.dry_run(&device, &options)
-                .await
-                .map_err(|e| format!("Simulation failed: {}", e))
-        },
-        
-        "fat32" => {
-            let formatter = Fat32Formatter;
-            formatter.dry_run(&device, &options)
-                .await
-                .map_err(|e| format!("Simulation failed: {}", e))
-        },
-        
-        "exfat" => {
-            let formatter = ExFatFormatter;
-            formatter.dry_run(&device, &options)
-                .await
-                .map_err(|e| format!("Simulation failed: {}", e))
-        },
-        
-        _ => {
-            Err(format!("Unsupported filesystem type: {}", options.filesystem_type))
-        }
+    let selected = moses_filesystems::resolve_formatter(moses_filesystems::builtin_registry(), &device, &options)
+        .map_err(|e| e.to_string())?;
+    let mut report = selected.formatter.dry_run(&device, &options)
+        .await
+        .map_err(|e| format!("Simulation failed: {}", e))?;
+    report.strategy = Some(selected.describe());
+    report.warnings.extend(moses_filesystems::disk_manager::installations::installation_warnings(&device));
+    report.contents = moses_filesystems::disk_manager::contents::summarize_contents(&device);
+    report.lints.extend(moses_filesystems::lints::lint(&device, &options));
+    // Windows formats run in the elevated worker, which prompts when it is not running yet
+    let requires_elevation = cfg!(target_os = "windows")
+        && worker_server::worker_status().await.state != worker_server::WorkerState::Connected;
+    moses_filesystems::disk_manager::plan_format(&mut report, requires_elevation);
+    Ok(report)
+}
+
+/// Run the real formatter with `options` on a throwaway image and read it back, so the
+/// options can be tried before any drive is touched
+#[tauri::command]
+async fn sandbox_test_format(options: FormatOptions) -> Result<moses_filesystems::sandbox::SandboxReport, String> {
+    moses_filesystems::sandbox::sandbox_format(&options)
+        .await
+        .map_err(|e| format!("Sandbox test failed: {}", e))
+}
+
+/// Space and path checks of a format that keeps some folders
+#[tauri::command]
+async fn check_selective_format(
+    device: Device,
+    request: SelectiveFormatRequest,
+) -> Result<SelectiveFormatCheck, String> {
+    let selected = moses_filesystems::resolve_formatter(moses_filesystems::builtin_registry(), &device, &request.options)
+        .map_err(|e| e.to_string())?;
+    Ok(moses_filesystems::disk_manager::selective::check(&device, selected.formatter.as_ref(), &request).await)
+}
+
+/// Copy the kept folders off, format, and copy them back onto the new volume
+#[tauri::command]
+async fn execute_selective_format(
+    device: Device,
+    request: SelectiveFormatRequest,
+) -> Result<SelectiveFormatReport, String> {
+    if device.is_system {
+        return Err("Cannot format system drive. This would make your system unbootable!".to_string());
     }
+    device.ensure_writable().map_err(|e| e.to_string())?;
+    let peers = PlatformDeviceManager.enumerate_devices().await.unwrap_or_default();
+    moses_filesystems::disk_manager::ConflictDetector::verify_multi_device_acknowledged(&device, &peers, &request.options)
+        .map_err(|e| e.to_string())?;
+
+    let selected = moses_filesystems::resolve_formatter(moses_filesystems::builtin_registry(), &device, &request.options)
+        .map_err(|e| e.to_string())?;
+    selected.formatter.validate_options(&request.options)
+        .await
+        .map_err(|e| format!("Invalid options: {}", e))?;
+
+    let _keep_awake = moses_platform::KeepAwake::acquire("Formatting a disk");
+    FilesystemCache::global().invalidate(device);
+    moses_filesystems::disk_manager::history::record_before(&device, format!("format as {}", request.options.filesystem_type));
+    let mut target = moses_platform::RemountTarget::new(PlatformDeviceManager, std::time::Duration::from_secs(30));
+    moses_filesystems::disk_manager::selective::selective_format(&device, selected.formatter.as_ref(), &request, &mut target)
+        .await
+        .map_err(|e| format!("Format failed: {}", e))
+}
+
+/// Resolve the auto-fixable entries of a simulation's pre-flight checklist
+#[tauri::command]
+fn apply_lint_fixes(
+    options: FormatOptions,
+    lints: Vec<SafetyLint>,
+) -> FormatOptions {
+    moses_filesystems::lints::apply_fixes(&options, &lints)
 }
 
 #[tauri::command]
@@ -213,6 +245,13 @@ async fn execute_format(
             return Err("Cannot format system drive. This would make your system unbootable!".to_string());
         }
         
+        device.ensure_writable().map_err(|e| e.to_string())?;
+        
+        // Never silently break the other members of a striped/spanned volume
+        let peers = PlatformDeviceManager.enumerate_devices().await.unwrap_or_default();
+        moses_filesystems::disk_manager::ConflictDetector::verify_multi_device_acknowledged(&device, &peers, &options)
+            .map_err(|e| e.to_string())?;
+        
         // Additional safety check for critical mount points
         for mount in &device.mount_points {
             let mount_str = mount.to_string_lossy().to_lowercase();
@@ -225,307 +264,149 @@ async fn execute_format(
             }
         }
     
-    // Select and execute the appropriate formatter
-    match options.filesystem_type.as_str() {
-        "ext2" => {
-            #[cfg(target_os = "windows")]
-            {
-                let formatter = Ext2Formatter;
-                
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted (mounted or system device)".to_string());
-                }
-                
-                formatter.format(&device, &options)
-                    .await
-                    .map_err(|e| format!("Format failed: {}", e))?;
-                
-                Ok(format!("Successfully formatted {} as ext2", device.name))
-            }
-            
-            #[cfg(not(target_os = "windows"))]
-            {
-                Err("ext2 formatting not yet implemented on this platform".to_string())
-            }
-        },
-        
-        "ext3" => {
-            #[cfg(target_os = "windows")]
-            {
-                let formatter = Ext3Formatter;
-                
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted (mounted or system device)".to_string());
-                }
-                
-                formatter.format(&device, &options)
-                    .await
-                    .map_err(|e| format!("Format failed: {}", e))?;
-                
-                Ok(format!("Successfully formatted {} as ext3", device.name))
-            }
-            
-            #[cfg(not(target_os = "windows"))]
-            {
-                Err("ext3 formatting not yet implemented on this platform".to_string())
-            }
-        },
-        
-        "ext4" => {
-            #[cfg(target_os = "linux")]
-            {
-                let formatter = Ext4NativeFormatter;
-                
-                // Validate options
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                // Check if device can be formatted
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted (mounted or system device)".to_string());
-                }
-                
-                // Execute the format
-                formatter.format(&device, &options)
-                    .await
-                    .map_err(|e| format!("Format failed: {}", e))?;
-                
-                Ok(format!("Successfully formatted {} as EXT4", device.name))
-            }
-            
-            #[cfg(target_os = "windows")]
-            {
-                let formatter = Ext4NativeFormatter;
-                
-                // Validate options
-                formatter.validate_options(&options)
-                    .await
-                    .map_err(|e| format!("Invalid options: {}", e))?;
-                
-                // Check if device can be formatted
-                if !formatter.can_format(&device) {
-                    return Err("Device cannot be formatted (mounted or system device)".to_string());
-                }
-                
-                // Execute the format
-                formatter.format(&device, &options)
-                    .await
-                    .map_err(|e| format!("Format failed: {}", e))?;
-                
-                Ok(format!("Successfully formatted {} as EXT4", device.name))
-            }
-            
-            #[cfg(target_os = "macos")]
-            {
-                Err("EXT4 formatting not yet implemented on macOS".to_string())
-            }
-        },
-        
-        "ntfs" => {
-            // NTFS formatting not yet implemented
-            return Err("NTFS formatting is not yet implemented. Only NTFS reading is currently supported.".to_string());
-        },
-        
-        "fat16" => {
-            let formatter = Fat16Formatter;
-            
-            // Validate options
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            // Check if device can be formatted
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted (system device or too large for FAT16)".to_string());
-            }
-            
-            // Check size limit
-            if device.size > 4 * 1024_u64.pow(3) {
-                return Err("Device too large for FAT16. Maximum size is 4GB. Consider using FAT32 or exFAT.".to_string());
-            }
-            
-            // Execute the format
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as FAT16", device.name))
-        },
-        
-        "fat32" => {
-            let formatter = Fat32Formatter;
-            
-            // Validate options
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            // Check if device can be formatted
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted (system device, critical mount points, or too large for FAT32)".to_string());
-            }
-            
-            // Check size limit
-            if device.size > 2 * 1024_u64.pow(4) {
-                return Err("Device too large for FAT32. Maximum size is 2TB. Consider using exFAT or NTFS.".to_string());
-            }
-            
-            // Warn about Windows 32GB limitation
-            #[cfg(target_os = "windows")]
-            {
-                if device.size > 32 * 1024_u64.pow(3) {
-                    eprintln!("Warning: Windows limits FAT32 formatting to 32GB. Format may fail for larger drives.");
-                }
-            }
-            
-            // Execute the format
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as FAT32", device.name))
-        },
-        
-        "exfat" => {
-            let formatter = ExFatFormatter;
-            
-            // Validate options
-            formatter.validate_options(&options)
-                .await
-                .map_err(|e| format!("Invalid options: {}", e))?;
-            
-            // Check if device can be formatted
-            if !formatter.can_format(&device) {
-                return Err("Device cannot be formatted (system device or critical mount points)".to_string());
-            }
-            
-            // Execute the format
-            formatter.format(&device, &options)
-                .await
-                .map_err(|e| format!("Format failed: {}", e))?;
-            
-            Ok(format!("Successfully formatted {} as exFAT", device.name))
+    // Reject a bad post-format action before touching the disk
+    let post_action = PostOperationAction::from_options(&options).map_err(|e| e.to_string())?;
+    
+    // Formats can take hours on large drives; don't let the machine sleep midway
+    let _keep_awake = moses_platform::KeepAwake::acquire("Formatting a disk");
+    
+    // Whatever happens next, the cached filesystem for this device is no longer valid
+    FilesystemCache::global().invalidate(device);
+    
+    // Resolve the formatter the same way the CLI and the worker do
+    let selected = moses_filesystems::resolve_formatter(moses_filesystems::builtin_registry(), &device, &options)
+        .map_err(|e| e.to_string())?;
+    let formatter = &selected.formatter;
+    log::info!("Formatting {} as {} using the {} implementation", device.name, options.filesystem_type, selected.describe());
+    
+    formatter.validate_options(&options)
+        .await
+        .map_err(|e| format!("Invalid options: {}", e))?;
+    
+    if !formatter.can_format(&device) {
+        return Err(format!(
+            "Device cannot be formatted as {} (system device, critical mount point, or unsupported size)",
+            options.filesystem_type
+        ));
+    }
+    
+    // Remember what was there so the user can see it later
+    moses_filesystems::disk_manager::history::record_before(&device, format!("format as {}", options.filesystem_type));
+    let started = std::time::Instant::now();
+    let result = formatter.format(&device, &options).await;
+    moses_core::LocalMetrics::global().record(moses_core::MetricRecord::new("format", &device, &result, started.elapsed()));
+    result.map_err(|e| format!("Format failed: {}", e))?;
+    
+    let message = format!("Successfully formatted {} as {}", device.name, formatter.name());
+    
+    // Remove leftovers of the previous filesystem so nothing detects two at once
+    let note = SignatureWiper::cleanup_after_format(&device, &options.filesystem_type);
+    FilesystemCache::global().insert(device, 0, CachedFilesystemInfo::detected_now(options.filesystem_type.clone()));
+    
+    let message = match note {
+        Some(note) => format!("{}\n{}", message, note),
+        None => message,
+    };
+    
+    // The format itself succeeded, so failed seeding and a failed eject/standby are only reported
+    let mut target = moses_platform::RemountTarget::new(PlatformDeviceManager, std::time::Duration::from_secs(30));
+    let message = match moses_filesystems::disk_manager::seeding::seed_volume(&device, &options, &mut target).await {
+        Ok(written) if written.is_empty() => message,
+        Ok(written) => format!("{}\nWrote {}", message, written.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")),
+        Err(e) => format!("{}\nWarning: post-format steps failed: {}", message, e),
+    };
+    match post_action {
+        Some(action) => match action.apply(&PlatformDeviceManager, &device).await {
+            Ok(()) => Ok(format!("{}\nDevice {}: done", message, action.as_str())),
+            Err(e) => Ok(format!("{}\nWarning: {} failed: {}", message, action.as_str(), e)),
         },
-        
-        _ => {
-            Err(format!("Unsupported filesystem type: {}", options.filesystem_type))
-        }
+        None => Ok(message),
     }
     } // End of cfg(not(target_os = "windows")) block
 }
 
 #[tauri::command]
 async fn check_formatter_requirements(filesystem_type: String) -> Result<Vec<String>, String> {
-    // Check what tools are required for each filesystem
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
-    let mut missing_tools = Vec::new();
-    
-    #[cfg(target_os = "windows")]
-    let missing_tools = Vec::new();
-    
-    match filesystem_type.as_str() {
-        "ext4" => {
-            #[cfg(target_os = "windows")]
-            {
-                // Native ext4 support - no external tools required
-            }
-            
-            #[cfg(target_os = "linux")]
-            {
-                // Check for mkfs.ext4
-                let output = std::process::Command::new("which")
-                    .arg("mkfs.ext4")
-                    .output();
-                
-                if output.is_err() || !output.unwrap().status.success() {
-                    missing_tools.push("e2fsprogs (mkfs.ext4)".to_string());
-                }
-            }
-        },
-        
-        "ntfs" => {
-            #[cfg(target_os = "linux")]
-            {
-                // Check for mkfs.ntfs
-                let output = std::process::Command::new("which")
-                    .arg("mkfs.ntfs")
-                    .output();
-                
-                if output.is_err() || !output.unwrap().status.success() {
-                    missing_tools.push("ntfs-3g (mkfs.ntfs)".to_string());
-                }
-            }
-            
-            #[cfg(target_os = "macos")]
-            {
-                // Check for ntfs-3g via Homebrew
-                let output = std::process::Command::new("which")
-                    .arg("mkfs.ntfs")
-                    .output();
-                
-                if output.is_err() || !output.unwrap().status.success() {
-                    missing_tools.push("ntfs-3g-mac (install with: brew install ntfs-3g-mac)".to_string());
-                }
-            }
-        },
-        
-        "fat32" => {
-            #[cfg(target_os = "linux")]
-            {
-                // Check for mkfs.fat
-                let output = std::process::Command::new("which")
-                    .arg("mkfs.fat")
-                    .output();
-                
-                if output.is_err() || !output.unwrap().status.success() {
-                    missing_tools.push("dosfstools (mkfs.fat)".to_string());
-                }
-            }
-        },
-        
-        "exfat" => {
-            #[cfg(target_os = "linux")]
-            {
-                // Check for mkfs.exfat
-                let output = std::process::Command::new("which")
-                    .arg("mkfs.exfat")
-                    .output();
-                
-                if output.is_err() || !output.unwrap().status.success() {
-                    missing_tools.push("exfatprogs or exfat-utils (mkfs.exfat)".to_string());
-                }
-            }
-        },
-        
-        _ => {}
+    Ok(moses_platform::environment::missing_tools(&filesystem_type))
+}
+
+/// What this process can offer: elevation, and an elevated worker next to the executable
+fn availability_context() -> moses_core::AvailabilityContext {
+    let worker_name = if cfg!(target_os = "windows") { "moses-worker.exe" } else { "moses-worker" };
+    let worker_available = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(worker_name)))
+        .is_some_and(|worker| worker.exists());
+
+    moses_core::AvailabilityContext {
+        platform: moses_core::Platform::current(),
+        elevated: moses_platform::environment::is_elevated(),
+        worker_available,
     }
-    
-    Ok(missing_tools)
+}
+
+/// Every registered formatter with its platform/permission needs, so the UI can
+/// grey out the ones that can't be used here and say why
+#[tauri::command]
+async fn get_formatter_registry() -> Result<Vec<moses_core::FormatterAvailability>, String> {
+    Ok(moses_filesystems::builtin_registry().availability(&availability_context()))
+}
+
+/// What can be done with each filesystem here, so the UI only offers what works
+#[tauri::command]
+async fn get_capability_matrix() -> Result<moses_filesystems::capabilities::CapabilityMatrix, String> {
+    Ok(moses_filesystems::capabilities::CapabilityMatrix::current(&availability_context()))
+}
+
+/// Recommend a filesystem for the device and what it will be used for, with the
+/// format options filled in
+#[tauri::command]
+async fn advise_filesystem(
+    device: Device,
+    goals: Vec<moses_filesystems::advisor::Goal>,
+) -> Result<moses_filesystems::advisor::Advice, String> {
+    if goals.is_empty() {
+        return Err("Pick at least one goal".to_string());
+    }
+    Ok(moses_filesystems::advisor::advise(moses_filesystems::builtin_registry(), &device, &goals))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Swap in a build staged by the updater and start that instead
+    if let Some(update) = moses_filesystems::update::apply_staged_on_start(moses_filesystems::update::Component::Gui, env!("CARGO_PKG_VERSION")) {
+        match std::process::Command::new(&update.target).args(std::env::args_os().skip(1)).spawn() {
+            Ok(_) => std::process::exit(0),
+            Err(e) => log::warn!("Updated to {} but could not restart: {}", update.version, e),
+        }
+    }
+    
+    // Run async work on a runtime sized from the user's concurrency settings
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    match moses_core::MosesConfig::global().concurrency.runtime() {
+        Ok(runtime) => tauri::async_runtime::set(RUNTIME.get_or_init(|| runtime).handle().clone()),
+        Err(e) => log::warn!("Using the default async runtime: {}", e),
+    }
+    
     tauri::Builder::default()
         .setup(|app| {
             // Initialize our custom logger that sends logs to the UI
             logging::init_logger(app.handle().clone());
             
             // Initialize the worker server for socket-based operations
+            worker_server::init_status_events(app.handle().clone());
             tauri::async_runtime::spawn(async move {
                 if let Err(e) = worker_server::init_worker_server().await {
                     log::error!("Failed to initialize worker server: {}", e);
                 }
             });
             
+            // Progress of long operations is forwarded to the UI as events
+            progress::init(app.handle().clone());
+            
+            // Start background filesystem identification and keep the elevated worker warm
+            identification::init(app.handle().clone());
+            
             // Note: We're not using tauri_plugin_log anymore since we have our own logger
             // that bridges the standard log crate to the UI console
             
@@ -533,20 +414,43 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             check_elevation_status,
+            get_worker_status,
+            retry_elevation,
             detect_drives,
             enumerate_devices,
+            identify_filesystems,
             simulate_format,
+            sandbox_test_format,
+            apply_lint_fixes,
             execute_format,
             execute_format_elevated,
             check_formatter_requirements,
+            get_formatter_registry,
+            get_capability_matrix,
+            advise_filesystem,
+            check_selective_format,
+            execute_selective_format,
             commands::filesystem::read_directory,
             commands::filesystem::read_directory_elevated,
+            commands::filesystem::watch_directory,
+            commands::filesystem::unwatch_directory,
             commands::filesystem::read_file,
             commands::filesystem::copy_files,
+            commands::filesystem::folder_stats,
+            commands::filesystem::preview_copy,
+            commands::filesystem::check_copy_limits,
+            commands::filesystem::preview_file,
             // Old disk management commands (to be deprecated)
             commands::disk_management::clean_disk,
             commands::disk_management::detect_conflicts,
+            commands::disk_management::assess_device_risks,
+            commands::disk_management::analyze_trash,
+            commands::disk_management::purge_trash,
             commands::disk_management::convert_partition_style,
+            commands::disk_management::simulate_clean_disk,
+            commands::disk_management::simulate_convert_partition_style,
+            commands::disk_management::get_device_history,
+            commands::disk_management::restore_device_layout,
             commands::disk_management::prepare_disk,
             commands::disk_management::quick_clean,
             commands::disk_management::needs_cleaning,
@@ -558,11 +462,27 @@ pub fn run() {
             commands::disk_management_socket::detect_filesystem_socket,
             commands::disk_management_socket::convert_partition_style_socket,
             commands::disk_management_socket::prepare_disk_socket,
+            commands::disk_management_socket::eject_device_socket,
+            commands::disk_management_socket::set_device_power_state,
+            commands::mount_driver::check_mount_driver,
+            commands::mount_driver::install_mount_driver,
+            commands::update::check_for_update,
+            commands::update::stage_update,
+            commands::update::get_staged_update,
+            commands::update::get_update_channel,
+            commands::update::set_update_channel,
+            commands::stats::get_local_metrics,
+            commands::stats::reset_local_metrics,
             commands::filesystem::detect_filesystem_elevated,
             commands::filesystem::request_elevated_filesystem_detection,
             commands::filesystem::get_filesystem_type,
             commands::filesystem::analyze_filesystem,
-            commands::filesystem::analyze_filesystem_elevated
+            commands::filesystem::analyze_filesystem_elevated,
+            commands::filesystem::cancel_analysis,
+            commands::filesystem::estimate_analysis,
+            commands::filesystem::hexdump_device,
+            commands::filesystem::explain_structure,
+            commands::filesystem::get_allocation_map
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,35 @@
+// Forwards progress of long operations to the GUI
+// Updates come from the elevated worker or from in-process operations and are emitted
+// as `operation-progress` events carrying throughput and the estimated time remaining.
+// Filesystem analyses also send what they have found so far as `analysis-progress` events.
+use moses_core::ProgressUpdate;
+use moses_filesystems::diagnostics::AnalysisProgress;
+use once_cell::sync::OnceCell;
+use tauri::{AppHandle, Emitter};
+
+/// Emitted with a `ProgressUpdate`
+pub const PROGRESS_EVENT: &str = "operation-progress";
+/// Emitted with an `AnalysisProgress`
+pub const ANALYSIS_EVENT: &str = "analysis-progress";
+
+static APP: OnceCell<AppHandle> = OnceCell::new();
+
+pub fn init(app: AppHandle) {
+    let _ = APP.set(app);
+}
+
+pub fn emit(update: &ProgressUpdate) {
+    if let Some(app) = APP.get() {
+        if let Err(e) = app.emit(PROGRESS_EVENT, update) {
+            log::warn!("Failed to emit progress for {}: {}", update.operation, e);
+        }
+    }
+}
+
+pub fn emit_analysis(progress: &AnalysisProgress) {
+    if let Some(app) = APP.get() {
+        if let Err(e) = app.emit(ANALYSIS_EVENT, progress) {
+            log::warn!("Failed to emit analysis progress: {}", e);
+        }
+    }
+}
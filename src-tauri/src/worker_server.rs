@@ -1,12 +1,48 @@
 // Socket-based communication with elevated worker process
+// The worker is pinged on a heartbeat; when it is lost it is relaunched (asking for elevation
+// again) and commands waiting for it run once it reconnects. The connection state is kept in
+// a WorkerStatus that the GUI shows and is told about through `worker-status` events.
+// When the user declines the elevation prompt Moses stays read-only: nothing asks again
+// until `retry_elevation`, and commands that need the worker fail with NeedsElevation.
+// Directory watches run in the worker, which pushes their changes between responses; they
+// are forwarded to the GUI as `directory-changed` events, and end when the worker is lost.
+use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use serde::{Deserialize, Serialize};
 use moses_core::{Device, FormatOptions};
-use moses_filesystems::disk_manager::CleanOptions;
-use std::time::Duration;
+use moses_filesystems::diagnostics::AnalysisDepth;
+use moses_filesystems::disk_manager::{CleanOptions, BootCodeAction};
+use moses_filesystems::watch::DirectoryChange;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use once_cell::sync::OnceCell;
+use tauri::{AppHandle, Emitter};
+
+/// Emitted with a `WorkerStatus` whenever the worker's connection state changes
+pub const STATUS_EVENT: &str = "worker-status";
+/// Emitted with a `DirectoryChangedEvent` when a watched directory changes
+pub const DIRECTORY_CHANGED_EVENT: &str = "directory-changed";
+/// Emitted with a `WatchEndedEvent` when a watch stops without being asked to
+pub const WATCH_ENDED_EVENT: &str = "directory-watch-ended";
+
+/// Times a command is sent before giving up, reconnecting in between
+const COMMAND_ATTEMPTS: usize = 3;
+/// How long a launched worker has to connect back
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often pushed directory changes are collected while a watch is running
+const WATCH_DELIVERY_INTERVAL: Duration = Duration::from_millis(500);
+/// pkexec exit codes for a dismissed or denied authentication dialog
+#[cfg(not(target_os = "windows"))]
+const PKEXEC_REFUSED: &[i32] = &[126, 127];
+/// Exit code of the launch script when UAC was declined (ERROR_CANCELLED from ShellExecute)
+#[cfg(target_os = "windows")]
+const UAC_DECLINED_EXIT: i32 = 2;
+
+static APP: OnceCell<AppHandle> = OnceCell::new();
+/// Wakes the command waiting for a response so it can send Cancel to the worker
+static CANCEL: Lazy<tokio::sync::Notify> = Lazy::new(tokio::sync::Notify::new);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "command", content = "params")]
@@ -21,10 +57,21 @@ pub enum WorkerCommand {
     },
     Analyze {
         device: Device,
+        #[serde(default)]
+        depth: AnalysisDepth,
+    },
+    Detect {
+        device: Device,
+    },
+    Eject {
+        device: Device,
+        power_off: bool,
     },
     Convert {
         device: Device,
         target_style: String,
+        #[serde(default)]
+        boot_code: BootCodeAction,
     },
     Prepare {
         device: Device,
@@ -35,8 +82,28 @@ pub enum WorkerCommand {
         device: Device,
         path: String,
     },
+    /// Keep reporting changes to `path` as DirectoryChanged until Unwatch
+    WatchDirectory {
+        device: Device,
+        path: String,
+        watch_id: String,
+    },
+    Unwatch {
+        watch_id: String,
+    },
     Ping, // Keepalive
     Shutdown, // Graceful shutdown
+    Cancel, // Stop the running command where it can stop; sent while waiting for its response
+}
+
+impl WorkerCommand {
+    /// Whether the command only reads, so running it twice is harmless. Commands that write
+    /// are not replayed when the worker is lost after receiving them, as they may have run.
+    fn is_read_only(&self) -> bool {
+        matches!(self, WorkerCommand::Analyze { .. } | WorkerCommand::Detect { .. }
+            | WorkerCommand::ReadDirectory { .. } | WorkerCommand::WatchDirectory { .. }
+            | WorkerCommand::Unwatch { .. } | WorkerCommand::Ping)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,18 +111,133 @@ pub enum WorkerCommand {
 pub enum WorkerResponse {
     Success(String),
     Error(String),
-    Progress { percent: u8, message: String },
+    Progress(moses_core::ProgressUpdate),
+    AnalysisProgress(String), // JSON serialized AnalysisProgress
     Log { level: String, message: String },
     DirectoryListing(String), // JSON serialized directory listing
+    /// Pushed by a watch, not a response to a command
+    DirectoryChanged { watch_id: String, change: DirectoryChange },
+    /// Pushed when a watch can no longer read its directory
+    WatchEnded { watch_id: String, reason: String },
     Pong,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryChangedEvent {
+    pub watch_id: String,
+    #[serde(flatten)]
+    pub change: DirectoryChange,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchEndedEvent {
+    pub watch_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    /// No worker has been needed yet
+    NotStarted,
+    /// Being launched, waiting for elevation or for it to connect
+    Starting,
+    Connected,
+    /// Stopped answering; relaunched on the next heartbeat or command
+    Lost,
+    /// Could not be launched or reached; the next command tries again
+    Failed,
+    /// The user declined the elevation prompt; only `retry_elevation` asks again
+    Refused,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatus {
+    pub state: WorkerState,
+    /// The worker runs with administrator (root) rights; it is only ever launched elevated
+    pub elevated: bool,
+    /// Times the worker was relaunched after being lost
+    pub restarts: u32,
+    /// Unix time of the last answered ping
+    pub last_heartbeat: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+impl Default for WorkerStatus {
+    fn default() -> Self {
+        Self { state: WorkerState::NotStarted, elevated: false, restarts: 0, last_heartbeat: None, last_error: None }
+    }
+}
+
+impl WorkerStatus {
+    /// e.g. `worker: elevated, connected`
+    pub fn describe(&self) -> String {
+        let state = match self.state {
+            WorkerState::NotStarted => return "worker: not started".to_string(),
+            WorkerState::Starting if self.restarts > 0 => "restarting",
+            WorkerState::Starting => "starting",
+            WorkerState::Connected => "connected",
+            WorkerState::Lost => "lost",
+            WorkerState::Failed => "unavailable",
+            WorkerState::Refused => return "worker: elevation refused, read-only".to_string(),
+        };
+        format!("worker: {}, {}", if self.elevated { "elevated" } else { "not elevated" }, state)
+    }
+}
+
+/// Why the worker could not be launched
+enum LaunchError {
+    /// The user declined the elevation prompt
+    Refused(String),
+    Failed(String),
+}
+
+/// Error returned to the GUI by commands that go through the elevated worker
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ElevatedCommandError {
+    /// Elevation was refused. Read-only features keep working; call `retry_elevation`
+    /// and, if that succeeds, run the command again.
+    NeedsElevation { operation: String, message: String },
+    Failed { message: String },
+}
+
+impl From<String> for ElevatedCommandError {
+    fn from(message: String) -> Self {
+        ElevatedCommandError::Failed { message }
+    }
+}
+
+impl std::fmt::Display for ElevatedCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ElevatedCommandError::NeedsElevation { operation, .. } => {
+                write!(f, "{} needs administrator rights, which were declined", operation)
+            }
+            ElevatedCommandError::Failed { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Why a command got no response
+enum CommandError {
+    /// It never fully reached the worker, so it can be sent again
+    Undelivered(String),
+    /// The worker got it but the connection broke before it answered
+    Interrupted(String),
+    Failed(String),
+}
+
 pub struct WorkerServer {
     listener: Option<TcpListener>,
-    connection: Arc<Mutex<Option<TcpStream>>>,
+    /// Buffered for reading, so a pushed line that arrives with a response is kept
+    connection: Arc<Mutex<Option<BufReader<TcpStream>>>>,
     port: u16,
     log_sender: Arc<Mutex<Option<mpsc::UnboundedSender<(String, String)>>>>,
     spawning: Arc<Mutex<bool>>,
+    status: std::sync::Mutex<WorkerStatus>,
+    /// Ids of the directory watches running in the worker
+    watches: std::sync::Mutex<HashSet<String>>,
 }
 
 impl WorkerServer {
@@ -77,8 +259,60 @@ impl WorkerServer {
             port,
             log_sender: Arc::new(Mutex::new(None)),
             spawning: Arc::new(Mutex::new(false)),
+            status: std::sync::Mutex::new(WorkerStatus::default()),
+            watches: std::sync::Mutex::new(HashSet::new()),
         })
     }
+
+    /// Current connection state of the worker
+    pub fn status(&self) -> WorkerStatus {
+        self.status.lock().map(|status| status.clone()).unwrap_or_default()
+    }
+
+    /// Change the status and tell the GUI when the state or error changed
+    fn update_status(&self, update: impl FnOnce(&mut WorkerStatus)) {
+        let Ok(mut status) = self.status.lock() else {
+            return;
+        };
+        let before = (status.state, status.last_error.clone());
+        update(&mut status);
+        if before != (status.state, status.last_error.clone()) {
+            log::info!("Worker status: {}", status.describe());
+            if let Some(app) = APP.get() {
+                if let Err(e) = app.emit(STATUS_EVENT, &*status) {
+                    log::warn!("Failed to emit worker status: {}", e);
+                }
+            }
+        }
+    }
+
+    fn mark_lost(&self, error: &str) {
+        self.update_status(|status| {
+            status.state = WorkerState::Lost;
+            status.last_error = Some(error.to_string());
+        });
+        // The watch threads went down with the worker
+        let watches: Vec<String> = self.watches.lock().map(|mut watches| watches.drain().collect()).unwrap_or_default();
+        for watch_id in watches {
+            emit(WATCH_ENDED_EVENT, &WatchEndedEvent { watch_id, reason: format!("The worker was lost: {}", error) });
+        }
+    }
+
+    /// Start tracking a watch the worker accepted
+    fn add_watch(&self, watch_id: &str) {
+        if let Ok(mut watches) = self.watches.lock() {
+            watches.insert(watch_id.to_string());
+        }
+    }
+
+    /// Stop tracking a watch; false if it was not tracked
+    fn remove_watch(&self, watch_id: &str) -> bool {
+        self.watches.lock().map(|mut watches| watches.remove(watch_id)).unwrap_or(false)
+    }
+
+    fn has_watches(&self) -> bool {
+        self.watches.lock().map(|watches| !watches.is_empty()).unwrap_or(false)
+    }
     
     #[allow(dead_code)]
     pub fn port(&self) -> u16 {
@@ -89,6 +323,10 @@ impl WorkerServer {
     pub async fn ensure_connected(&self) -> Result<(), String> {
         // Loop to handle waiting for another thread's spawn
         loop {
+            if self.status().state == WorkerState::Refused {
+                return Err("Elevation was refused; running read-only".to_string());
+            }
+            
             // First check if we have a working connection
             {
                 let mut conn = self.connection.lock().await;
@@ -96,7 +334,7 @@ impl WorkerServer {
                 // Check if we already have a connection
                 if let Some(ref mut stream) = *conn {
                     // Try to set TCP keepalive to detect broken connections
-                    let _ = stream.set_nodelay(true);
+                    let _ = stream.get_ref().set_nodelay(true);
                     
                     log::info!("Checking existing worker connection...");
                     // Send a ping to check if connection is alive
@@ -109,6 +347,7 @@ impl WorkerServer {
                             log::warn!("Worker ping failed: {}, will reconnect...", e);
                             // Connection is dead, remove it
                             *conn = None;
+                            self.mark_lost(&e);
                         }
                     }
                 } else {
@@ -141,8 +380,14 @@ impl WorkerServer {
                 *spawning = true;
             }
             
-            // Spawn the elevated worker
-            let spawn_result = self.spawn_elevated_worker().await;
+            // Spawn the elevated worker; a lost worker counts as a restart
+            self.update_status(|status| {
+                if status.state == WorkerState::Lost {
+                    status.restarts += 1;
+                }
+                status.state = WorkerState::Starting;
+            });
+            let result = self.spawn_and_accept().await;
             
             // Clear the spawning flag regardless of result
             {
@@ -150,115 +395,247 @@ impl WorkerServer {
                 *spawning = false;
             }
             
-            spawn_result?;
-            
-            // Store the new connection
-            let mut conn = self.connection.lock().await;
-            
-            // Accept the connection (with timeout)
-            if let Some(listener) = &self.listener {
-                let accept_future = listener.accept();
-                let timeout = tokio::time::timeout(Duration::from_secs(30), accept_future);
-                
-                match timeout.await {
-                    Ok(Ok((stream, addr))) => {
-                        log::info!("Worker connected from {}", addr);
-                        *conn = Some(stream);
-                        return Ok(());
+            self.update_status(|status| {
+                status.elevated = result.is_ok();
+                match &result {
+                    Ok(()) => {
+                        status.state = WorkerState::Connected;
+                        status.last_heartbeat = Some(unix_now());
+                        status.last_error = None;
+                    }
+                    Err(LaunchError::Refused(e)) => {
+                        status.state = WorkerState::Refused;
+                        status.last_error = Some(e.clone());
+                    }
+                    Err(LaunchError::Failed(e)) => {
+                        status.state = WorkerState::Failed;
+                        status.last_error = Some(e.clone());
                     }
-                    Ok(Err(e)) => return Err(format!("Failed to accept connection: {}", e)),
-                    Err(_) => return Err("Worker connection timeout".to_string()),
                 }
-            } else {
-                return Err("Listener not available".to_string());
+            });
+            return result.map_err(|(LaunchError::Refused(e) | LaunchError::Failed(e))| e);
+        }
+    }
+
+    /// Ask for elevation again after it was refused, e.g. from a retry button in the GUI
+    pub async fn retry_elevation(&self) -> Result<(), String> {
+        self.update_status(|status| {
+            if status.state == WorkerState::Refused {
+                status.state = WorkerState::NotStarted;
+            }
+        });
+        self.ensure_connected().await
+    }
+
+    async fn spawn_and_accept(&self) -> Result<(), LaunchError> {
+        let mut child = self.spawn_elevated_worker().await?;
+        
+        // Store the new connection
+        let mut conn = self.connection.lock().await;
+        
+        // Accept the connection, noticing a launcher that exits because elevation was refused
+        let listener = self.listener.as_ref().ok_or(LaunchError::Failed("Listener not available".to_string()))?;
+        let deadline = tokio::time::Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            match tokio::time::timeout(Duration::from_millis(250), listener.accept()).await {
+                Ok(Ok((stream, addr))) => {
+                    log::info!("Worker connected from {}", addr);
+                    *conn = Some(BufReader::new(stream));
+                    return Ok(());
+                }
+                Ok(Err(e)) => return Err(LaunchError::Failed(format!("Failed to accept connection: {}", e))),
+                Err(_) => {}
+            }
+            if let Some(Ok(Some(exit))) = child.as_mut().map(std::process::Child::try_wait) {
+                return Err(launcher_exited(exit));
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(LaunchError::Failed("Worker connection timeout".to_string()));
             }
         }
     }
     
     /// Send a command to the worker and get response
+    ///
+    /// When the worker is lost the command waits for it to be relaunched and is sent again,
+    /// unless the worker had already received a command that writes.
     pub async fn execute_command(&self, command: WorkerCommand) -> Result<WorkerResponse, String> {
-        // Try up to 2 times in case of connection issues
-        for attempt in 0..2 {
-            match self.execute_command_internal(&command).await {
+        let mut last_error = String::new();
+        for attempt in 1..=COMMAND_ATTEMPTS {
+            let error = match self.execute_command_internal(&command).await {
                 Ok(response) => return Ok(response),
-                Err(e) if e.contains("10054") || e.contains("broken pipe") || e.contains("connection") => {
-                    log::warn!("Connection error on attempt {}: {}", attempt + 1, e);
-                    // Reset connection and retry
-                    {
-                        let mut conn = self.connection.lock().await;
-                        *conn = None;
-                    }
-                    if attempt == 0 {
-                        log::info!("Reconnecting to worker...");
-                        continue;
-                    }
-                    return Err(format!("Worker connection failed after retry: {}", e));
+                Err(CommandError::Failed(e)) => return Err(e),
+                Err(CommandError::Interrupted(e)) if !command.is_read_only() => {
+                    *self.connection.lock().await = None;
+                    self.mark_lost(&e);
+                    return Err(format!(
+                        "The worker stopped while running the command ({}); it may not have finished, so check the device before trying again",
+                        e
+                    ));
                 }
-                Err(e) => return Err(e),
+                Err(CommandError::Undelivered(e) | CommandError::Interrupted(e)) => e,
+            };
+            log::warn!("Worker connection lost on attempt {}: {}", attempt, error);
+            *self.connection.lock().await = None;
+            self.mark_lost(&error);
+            last_error = error;
+            if attempt < COMMAND_ATTEMPTS {
+                log::info!("Reconnecting to worker to replay the command...");
             }
         }
-        Err("Failed to execute command after retries".to_string())
+        Err(format!("Worker connection failed after {} attempts: {}", COMMAND_ATTEMPTS, last_error))
     }
     
     /// Internal implementation of execute_command
-    async fn execute_command_internal(&self, command: &WorkerCommand) -> Result<WorkerResponse, String> {
-        self.ensure_connected().await?;
+    async fn execute_command_internal(&self, command: &WorkerCommand) -> Result<WorkerResponse, CommandError> {
+        self.ensure_connected().await.map_err(CommandError::Failed)?;
         
         let mut conn = self.connection.lock().await;
-        let stream = conn.as_mut().ok_or("No worker connection")?;
+        let reader = conn.as_mut().ok_or_else(|| CommandError::Undelivered("No worker connection".to_string()))?;
         
         // Send command
         let cmd_json = serde_json::to_string(command)
-            .map_err(|e| format!("Failed to serialize command: {}", e))?;
+            .map_err(|e| CommandError::Failed(format!("Failed to serialize command: {}", e)))?;
         
+        let stream = reader.get_mut();
         stream.write_all(cmd_json.as_bytes()).await
-            .map_err(|e| format!("Failed to send command: {}", e))?;
+            .map_err(|e| CommandError::Undelivered(format!("Failed to send command: {}", e)))?;
         stream.write_all(b"\n").await
-            .map_err(|e| format!("Failed to send newline: {}", e))?;
+            .map_err(|e| CommandError::Undelivered(format!("Failed to send newline: {}", e)))?;
         stream.flush().await
-            .map_err(|e| format!("Failed to flush: {}", e))?;
+            .map_err(|e| CommandError::Undelivered(format!("Failed to flush: {}", e)))?;
         
-        // Read response, filtering out log messages
-        let mut reader = BufReader::new(stream);
+        // Read response, filtering out log messages and pushed changes
+        let mut response_line = String::new();
         loop {
-            let mut response_line = String::new();
-            reader.read_line(&mut response_line).await
-                .map_err(|e| format!("Failed to read response: {}", e))?;
+            response_line.clear();
+            // read_line keeps what it read when cancelled, so it picks up where it left off
+            let read = loop {
+                tokio::select! {
+                    read = reader.read_line(&mut response_line) => break read,
+                    _ = CANCEL.notified() => {
+                        log::info!("Asking the worker to cancel the running command");
+                        let stream = reader.get_mut();
+                        let cancel = serde_json::to_string(&WorkerCommand::Cancel).unwrap_or_default();
+                        if let Err(e) = stream.write_all(format!("{}\n", cancel).as_bytes()).await {
+                            log::warn!("Failed to send cancel: {}", e);
+                        }
+                    }
+                }
+            }.map_err(|e| CommandError::Interrupted(format!("Failed to read response: {}", e)))?;
+            if read == 0 {
+                return Err(CommandError::Interrupted("The worker closed the connection".to_string()));
+            }
             
             let response: WorkerResponse = serde_json::from_str(&response_line)
-                .map_err(|e| format!("Failed to parse response: {}", e))?;
+                .map_err(|e| CommandError::Failed(format!("Failed to parse response: {}", e)))?;
             
-            match response {
-                WorkerResponse::Log { level, message } => {
-                    // Forward log to system logger
-                    log::log!(
-                        match level.as_str() {
-                            "ERROR" => log::Level::Error,
-                            "WARN" => log::Level::Warn,
-                            "INFO" => log::Level::Info,
-                            "DEBUG" => log::Level::Debug,
-                            _ => log::Level::Trace,
-                        },
-                        "[Worker] {}",
-                        message
-                    );
-                    
-                    // Store log for UI if we have a sender
-                    if let Some(ref sender) = *self.log_sender.lock().await {
-                        let _ = sender.send((level, message));
+            // This is the actual command response
+            if let Some(response) = self.forward_pushed(response).await {
+                return Ok(response);
+            }
+        }
+    }
+    
+    /// Pass on what the worker sends besides responses: logs, progress and directory
+    /// changes. Anything else is a response and is returned.
+    async fn forward_pushed(&self, response: WorkerResponse) -> Option<WorkerResponse> {
+        match response {
+            WorkerResponse::Log { level, message } => {
+                // Forward log to system logger
+                log::log!(
+                    match level.as_str() {
+                        "ERROR" => log::Level::Error,
+                        "WARN" => log::Level::Warn,
+                        "INFO" => log::Level::Info,
+                        "DEBUG" => log::Level::Debug,
+                        _ => log::Level::Trace,
+                    },
+                    "[Worker] {}",
+                    message
+                );
+                
+                // Store log for UI if we have a sender
+                if let Some(ref sender) = *self.log_sender.lock().await {
+                    let _ = sender.send((level, message));
+                }
+                // Continue reading for the actual response
+            }
+            WorkerResponse::Progress(update) => {
+                crate::progress::emit(&update);
+            }
+            WorkerResponse::AnalysisProgress(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(progress) => crate::progress::emit_analysis(&progress),
+                    Err(e) => log::warn!("Malformed analysis progress from worker: {}", e),
+                }
+            }
+            WorkerResponse::DirectoryChanged { watch_id, change } => {
+                emit(DIRECTORY_CHANGED_EVENT, &DirectoryChangedEvent { watch_id, change });
+            }
+            WorkerResponse::WatchEnded { watch_id, reason } => {
+                if self.remove_watch(&watch_id) {
+                    emit(WATCH_ENDED_EVENT, &WatchEndedEvent { watch_id, reason });
+                }
+            }
+            _ => return Some(response),
+        }
+        None
+    }
+    
+    /// Forward the changes watches pushed since the connection was last read. Does
+    /// nothing without a connection; the worker is not started for it.
+    pub async fn deliver_pushed(&self) {
+        let mut conn = self.connection.lock().await;
+        let Some(reader) = conn.as_mut() else {
+            return;
+        };
+        let mut line = String::new();
+        loop {
+            if reader.buffer().is_empty() {
+                match tokio::time::timeout(Duration::from_millis(20), reader.get_ref().readable()).await {
+                    Ok(Ok(())) => {}
+                    _ => return,
+                }
+            }
+            // The worker writes each line at once, so a started line finishes quickly; one
+            // that does not means the worker is stuck or gone
+            line.clear();
+            match tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut line)).await {
+                Ok(Ok(read)) if read > 0 => {}
+                Ok(Ok(_)) => {
+                    *conn = None;
+                    self.mark_lost("The worker closed the connection");
+                    return;
+                }
+                Ok(Err(e)) => {
+                    *conn = None;
+                    self.mark_lost(&format!("Failed to read from the worker: {}", e));
+                    return;
+                }
+                Err(_) => {
+                    *conn = None;
+                    self.mark_lost("The worker stopped in the middle of a message");
+                    return;
+                }
+            }
+            match serde_json::from_str::<WorkerResponse>(&line) {
+                Ok(response) => {
+                    if let Some(response) = self.forward_pushed(response).await {
+                        log::warn!("Unexpected response from the worker with no command running: {:?}", response);
                     }
-                    // Continue reading for the actual response
                 }
-                _ => return Ok(response), // This is the actual command response
+                Err(e) => log::warn!("Malformed message from the worker: {}", e),
             }
         }
     }
     
     /// Ping the worker to check if it's alive
-    async fn ping_worker(&self, stream: &mut TcpStream) -> Result<(), String> {
+    async fn ping_worker(&self, reader: &mut BufReader<TcpStream>) -> Result<(), String> {
         let ping = serde_json::to_string(&WorkerCommand::Ping)
             .map_err(|e| format!("Failed to serialize ping: {}", e))?;
         
+        let stream = reader.get_mut();
         stream.write_all(ping.as_bytes()).await
             .map_err(|e| format!("Failed to send ping: {}", e))?;
         stream.write_all(b"\n").await
@@ -266,29 +643,31 @@ impl WorkerServer {
         stream.flush().await
             .map_err(|e| format!("Failed to flush: {}", e))?;
         
-        // Try to read pong response with timeout
-        let mut reader = BufReader::new(stream);
+        // Read the pong with a timeout; logs and directory changes may come first
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
         let mut response_line = String::new();
-        
-        let read_future = reader.read_line(&mut response_line);
-        let timeout = tokio::time::timeout(Duration::from_secs(2), read_future);
-        
-        match timeout.await {
-            Ok(Ok(_)) => {
-                let response: WorkerResponse = serde_json::from_str(&response_line)
-                    .map_err(|e| format!("Invalid pong response: {}", e))?;
-                
-                match response {
-                    WorkerResponse::Pong => Ok(()),
-                    _ => Err("Unexpected response to ping".to_string()),
+        loop {
+            response_line.clear();
+            match tokio::time::timeout_at(deadline, reader.read_line(&mut response_line)).await {
+                Ok(Ok(read)) if read > 0 => {}
+                _ => return Err("Ping timeout".to_string()),
+            }
+            let response: WorkerResponse = serde_json::from_str(&response_line)
+                .map_err(|e| format!("Invalid pong response: {}", e))?;
+            match self.forward_pushed(response).await {
+                None => continue,
+                Some(WorkerResponse::Pong) => {
+                    self.update_status(|status| status.last_heartbeat = Some(unix_now()));
+                    return Ok(());
                 }
+                Some(_) => return Err("Unexpected response to ping".to_string()),
             }
-            _ => Err("Ping timeout".to_string()),
         }
     }
     
-    /// Spawn the elevated worker process
-    async fn spawn_elevated_worker(&self) -> Result<(), String> {
+    /// Spawn the elevated worker process; returns the launched process when it is the
+    /// worker itself or a launcher (pkexec, sudo) that lives as long as the worker
+    async fn spawn_elevated_worker(&self) -> Result<Option<std::process::Child>, LaunchError> {
         log::info!("Spawning elevated worker process...");
         #[cfg(target_os = "windows")]
         {
@@ -298,18 +677,19 @@ impl WorkerServer {
             use moses_platform::windows::elevation::is_elevated;
             
             let worker_exe = env::current_exe()
-                .map_err(|e| format!("Failed to get executable path: {}", e))?
+                .map_err(|e| LaunchError::Failed(format!("Failed to get executable path: {}", e)))?
                 .parent()
-                .ok_or_else(|| "Failed to get executable directory".to_string())?
+                .ok_or_else(|| LaunchError::Failed("Failed to get executable directory".to_string()))?
                 .join("moses-worker.exe");
             
             if is_elevated() {
                 // Already elevated, spawn directly
-                Command::new(&worker_exe)
+                return Command::new(&worker_exe)
                     .arg("--socket")
                     .arg(self.port.to_string())
                     .spawn()
-                    .map_err(|e| format!("Failed to spawn worker: {}", e))?;
+                    .map(Some)
+                    .map_err(|e| LaunchError::Failed(format!("Failed to spawn worker: {}", e)));
             } else {
                 // Request elevation via PowerShell
                 let ps_script = format!(
@@ -328,12 +708,16 @@ impl WorkerServer {
                         Write-Output "Worker started"
                         exit 0
                     }} catch {{
+                        if ($_.Exception.InnerException.NativeErrorCode -eq 1223) {{
+                            exit {}
+                        }}
                         Write-Error "Failed to start elevated worker: $_"
                         exit 1
                     }}
                     "#,
                     worker_exe.display(),
-                    self.port
+                    self.port,
+                    UAC_DECLINED_EXIT
                 );
                 
                 const CREATE_NO_WINDOW: u32 = 0x08000000;
@@ -345,15 +729,18 @@ impl WorkerServer {
                     ])
                     .creation_flags(CREATE_NO_WINDOW)
                     .output()
-                    .map_err(|e| format!("Failed to run PowerShell: {}", e))?;
+                    .map_err(|e| LaunchError::Failed(format!("Failed to run PowerShell: {}", e)))?;
                 
+                if output.status.code() == Some(UAC_DECLINED_EXIT) {
+                    return Err(LaunchError::Refused("The administrator prompt was declined".to_string()));
+                }
                 if !output.status.success() {
                     let stderr = String::from_utf8_lossy(&output.stderr);
-                    return Err(format!("Failed to spawn elevated worker: {}", stderr));
+                    return Err(LaunchError::Failed(format!("Failed to spawn elevated worker: {}", stderr)));
                 }
             }
             
-            Ok(())
+            Ok(None)
         }
         
         #[cfg(not(target_os = "windows"))]
@@ -363,9 +750,9 @@ impl WorkerServer {
             use std::env;
             
             let worker_exe = env::current_exe()
-                .map_err(|e| format!("Failed to get executable path: {}", e))?
+                .map_err(|e| LaunchError::Failed(format!("Failed to get executable path: {}", e)))?
                 .parent()
-                .ok_or_else(|| "Failed to get executable directory".to_string())?
+                .ok_or_else(|| LaunchError::Failed("Failed to get executable directory".to_string()))?
                 .join("moses-worker");
             
             // Try pkexec first, then sudo
@@ -375,19 +762,54 @@ impl WorkerServer {
                 .arg(self.port.to_string())
                 .spawn();
             
-            if result.is_err() {
-                Command::new("sudo")
+            match result {
+                Ok(child) => Ok(Some(child)),
+                Err(_) => Command::new("sudo")
                     .arg(&worker_exe)
                     .arg("--socket")
                     .arg(self.port.to_string())
                     .spawn()
-                    .map_err(|e| format!("Failed to spawn worker with sudo: {}", e))?;
+                    .map(Some)
+                    .map_err(|e| LaunchError::Failed(format!("Failed to spawn worker with sudo: {}", e))),
             }
-            
-            Ok(())
         }
     }
     
+    /// Whether an elevated worker is currently connected (no elevation prompt needed)
+    pub async fn is_connected(&self) -> bool {
+        self.connection.lock().await.is_some()
+    }
+
+    /// Ping the worker so it stays warm, relaunching it once if it stopped answering.
+    /// A worker that was never started, or whose relaunch failed, is left alone.
+    pub async fn heartbeat(&self) -> bool {
+        {
+            let mut conn = self.connection.lock().await;
+            if let Some(ref mut stream) = *conn {
+                match self.ping_worker(stream).await {
+                    Ok(()) => return true,
+                    Err(e) => {
+                        log::warn!("Worker heartbeat failed: {}, dropping connection", e);
+                        *conn = None;
+                        self.mark_lost(&e);
+                    }
+                }
+            }
+        }
+
+        if self.status().state != WorkerState::Lost {
+            return false;
+        }
+        log::info!("Relaunching the lost worker...");
+        match self.ensure_connected().await {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Failed to relaunch the worker: {}", e);
+                false
+            }
+        }
+    }
+
     /// Shutdown the worker gracefully
     #[allow(dead_code)]
     pub async fn shutdown(&self) -> Result<(), String> {
@@ -402,6 +824,7 @@ impl WorkerServer {
         }
         
         *conn = None;
+        self.update_status(|status| *status = WorkerStatus::default());
         Ok(())
     }
     
@@ -420,12 +843,46 @@ impl WorkerServer {
     }
 }
 
+/// Why the worker (or its launcher) exited before connecting
+fn launcher_exited(exit: std::process::ExitStatus) -> LaunchError {
+    #[cfg(not(target_os = "windows"))]
+    {
+        if exit.code().is_some_and(|code| PKEXEC_REFUSED.contains(&code)) {
+            return LaunchError::Refused("Authentication for the worker was dismissed or denied".to_string());
+        }
+    }
+    LaunchError::Failed(format!("The worker exited before connecting ({})", exit))
+}
+
+/// Ask the worker to stop the command it is running, if it can stop it (an analysis)
+pub fn request_cancel() {
+    CANCEL.notify_waiters();
+}
+
+/// Send an event to the GUI, if it is listening
+fn emit(event: &str, payload: &impl Serialize) {
+    if let Some(app) = APP.get() {
+        if let Err(e) = app.emit(event, payload) {
+            log::warn!("Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
 // Global instance of the worker server
 use once_cell::sync::Lazy;
 
 pub static WORKER_SERVER: Lazy<Arc<Mutex<Option<WorkerServer>>>> = 
     Lazy::new(|| Arc::new(Mutex::new(None)));
 
+/// Send worker status changes to the GUI
+pub fn init_status_events(app: AppHandle) {
+    let _ = APP.set(app);
+}
+
 /// Initialize the worker server
 pub async fn init_worker_server() -> Result<(), String> {
     let server = WorkerServer::new().await?;
@@ -442,4 +899,91 @@ pub async fn get_worker_server() -> Result<Arc<Mutex<Option<WorkerServer>>>, Str
         init_worker_server().await?;
     }
     Ok(WORKER_SERVER.clone())
-}
\ No newline at end of file
+}
+
+/// Connection state of the worker, without starting it
+pub async fn worker_status() -> WorkerStatus {
+    match get_worker_server().await {
+        Ok(server_arc) => server_arc.lock().await.as_ref().map(WorkerServer::status).unwrap_or_default(),
+        Err(_) => WorkerStatus::default(),
+    }
+}
+
+/// Run a command on the elevated worker for the GUI. Once elevation was refused the command
+/// fails with `NeedsElevation` instead of prompting again.
+pub async fn run_on_worker(operation: &str, command: WorkerCommand) -> Result<WorkerResponse, ElevatedCommandError> {
+    let server_arc = get_worker_server().await
+        .map_err(|e| format!("Failed to get worker server: {}", e))?;
+    let server_guard = server_arc.lock().await;
+    let server = server_guard.as_ref()
+        .ok_or_else(|| "Worker server not initialized".to_string())?;
+
+    match server.execute_command(command).await {
+        Ok(WorkerResponse::Error(message)) => Err(ElevatedCommandError::Failed { message }),
+        Ok(response) => Ok(response),
+        Err(message) if server.status().state == WorkerState::Refused => {
+            Err(ElevatedCommandError::NeedsElevation { operation: operation.to_string(), message })
+        }
+        Err(e) => Err(format!("Worker communication failed: {}", e).into()),
+    }
+}
+
+/// Watch a directory on a device through the worker; its changes arrive as
+/// `directory-changed` events carrying the returned watch id
+pub async fn watch_directory(device: Device, path: String) -> Result<String, ElevatedCommandError> {
+    static NEXT_WATCH: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+    let watch_id = format!("watch-{}", NEXT_WATCH.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+    let command = WorkerCommand::WatchDirectory { device, path, watch_id: watch_id.clone() };
+    run_on_worker("Watching a directory", command).await?;
+
+    let server_arc = get_worker_server().await?;
+    if let Some(server) = server_arc.lock().await.as_ref() {
+        server.add_watch(&watch_id);
+    }
+    start_delivery();
+    Ok(watch_id)
+}
+
+/// Stop a watch started with `watch_directory`
+pub async fn unwatch_directory(watch_id: String) -> Result<(), ElevatedCommandError> {
+    let server_arc = get_worker_server().await?;
+    let tracked = server_arc.lock().await.as_ref().is_some_and(|server| server.remove_watch(&watch_id));
+    if !tracked {
+        // Already ended, e.g. with the worker
+        return Ok(());
+    }
+    run_on_worker("Stopping a directory watch", WorkerCommand::Unwatch { watch_id }).await.map(|_| ())
+}
+
+/// Collect pushed changes while any watch runs; nothing else reads an idle connection
+fn start_delivery() {
+    static DELIVERING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    if DELIVERING.swap(true, std::sync::atomic::Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(WATCH_DELIVERY_INTERVAL).await;
+            let Ok(server_arc) = get_worker_server().await else {
+                continue;
+            };
+            let server_guard = server_arc.lock().await;
+            match server_guard.as_ref() {
+                Some(server) if server.has_watches() => server.deliver_pushed().await,
+                _ => {
+                    // Cleared under the server lock, so a watch added next starts a new loop
+                    DELIVERING.store(false, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Ask for elevation again after it was refused
+pub async fn retry_elevation() -> Result<(), String> {
+    let server_arc = get_worker_server().await?;
+    let server_guard = server_arc.lock().await;
+    let server = server_guard.as_ref().ok_or("Worker server not initialized")?;
+    server.retry_elevation().await
+}